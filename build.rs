@@ -1,45 +1,725 @@
 extern crate cc;
 
+use std::env;
 use std::path::PathBuf;
 
-fn dir(build: &mut cc::Build, dir: &str) {
+fn dir(build: &mut cc::Build, dir: &str, ported: &[&str]) {
     let mut path = PathBuf::from("libwebp-1.0.0");
     path.push(dir);
     for entry in path.read_dir().unwrap() {
         let entry = entry.unwrap();
         let path = entry.path();
-        if path.extension() == Some("c".as_ref()) {
+        if path.extension() == Some("c".as_ref())
+            && !ported
+                .iter()
+                .any(|&name| path.file_name() == Some(name.as_ref()))
+        {
             build.file(path);
         }
     }
 }
 
+const REFERENCE: &[(&str, &[&str])] = &[
+    (
+        "libwebp-1.0.0/src/dec/alpha_dec.c",
+        &["WebPDeallocateAlphaMemory", "VP8DecompressAlphaRows"],
+    ),
+    (
+        "libwebp-1.0.0/src/dec/buffer_dec.c",
+        &[
+            "WebPFlipBuffer",
+            "WebPAllocateDecBuffer",
+            "WebPInitDecBufferInternal",
+            "WebPFreeDecBuffer",
+            "WebPCopyDecBuffer",
+            "WebPGrabDecBuffer",
+            "WebPCopyDecBufferPixels",
+            "WebPAvoidSlowMemory",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dec/frame_dec.c",
+        &[
+            "VP8InitDithering",
+            "VP8ProcessRow",
+            "VP8EnterCritical",
+            "VP8ExitCritical",
+            "VP8GetThreadMethod",
+            "VP8InitFrame",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dec/idec_dec.c",
+        &[
+            "WebPINewDecoder",
+            "WebPIDecode",
+            "WebPIDelete",
+            "WebPINewRGB",
+            "WebPINewYUVA",
+            "WebPINewYUV",
+            "WebPIAppend",
+            "WebPIUpdate",
+            "WebPIDecodedArea",
+            "WebPIDecGetRGB",
+            "WebPIDecGetYUVA",
+            "WebPISetIOHooks",
+        ],
+    ),
+    ("libwebp-1.0.0/src/dec/io_dec.c", &["WebPInitCustomIo"]),
+    ("libwebp-1.0.0/src/dec/quant_dec.c", &["VP8ParseQuant"]),
+    (
+        "libwebp-1.0.0/src/dec/tree_dec.c",
+        &["VP8ResetProba", "VP8ParseProba", "VP8ParseIntraModeRow"],
+    ),
+    (
+        "libwebp-1.0.0/src/dec/vp8_dec.c",
+        &[
+            "WebPGetDecoderVersion",
+            "VP8InitIoInternal",
+            "VP8New",
+            "VP8Status",
+            "VP8StatusMessage",
+            "VP8Delete",
+            "VP8SetError",
+            "VP8CheckSignature",
+            "VP8GetInfo",
+            "VP8GetHeaders",
+            "VP8DecodeMB",
+            "VP8InitScanline",
+            "VP8Decode",
+            "VP8Clear",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dec/vp8l_dec.c",
+        &[
+            "VP8LCheckSignature",
+            "VP8LGetInfo",
+            "VP8LDecodeAlphaHeader",
+            "VP8LDecodeAlphaImageStream",
+            "VP8LNew",
+            "VP8LClear",
+            "VP8LDelete",
+            "VP8LDecodeHeader",
+            "VP8LDecodeImage",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dec/webp_dec.c",
+        &[
+            "WebPParseHeaders",
+            "WebPResetDecParams",
+            "WebPDecodeRGBInto",
+            "WebPDecodeRGBAInto",
+            "WebPDecodeARGBInto",
+            "WebPDecodeBGRInto",
+            "WebPDecodeBGRAInto",
+            "WebPDecodeYUVInto",
+            "WebPDecodeRGB",
+            "WebPDecodeRGBA",
+            "WebPDecodeARGB",
+            "WebPDecodeBGR",
+            "WebPDecodeBGRA",
+            "WebPDecodeYUV",
+            "WebPGetInfo",
+            "WebPInitDecoderConfigInternal",
+            "WebPGetFeaturesInternal",
+            "WebPDecode",
+            "WebPIoInitFromOptions",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/demux/anim_decode.c",
+        &[
+            "WebPAnimDecoderOptionsInitInternal",
+            "WebPAnimDecoderNewInternal",
+            "WebPAnimDecoderGetInfo",
+            "WebPAnimDecoderGetNext",
+            "WebPAnimDecoderHasMoreFrames",
+            "WebPAnimDecoderReset",
+            "WebPAnimDecoderGetDemuxer",
+            "WebPAnimDecoderDelete",
+        ],
+    ),
+    // The reference animation decoder needs its own demuxer.
+    (
+        "libwebp-1.0.0/src/demux/demux.c",
+        &[
+            "WebPGetDemuxVersion",
+            "WebPDemuxInternal",
+            "WebPDemuxDelete",
+            "WebPDemuxGetI",
+            "WebPDemuxGetFrame",
+            "WebPDemuxNextFrame",
+            "WebPDemuxPrevFrame",
+            "WebPDemuxReleaseIterator",
+            "WebPDemuxGetChunk",
+            "WebPDemuxNextChunk",
+            "WebPDemuxPrevChunk",
+            "WebPDemuxReleaseChunkIterator",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/dec.c",
+        &[
+            "VP8TransformWHT",
+            "VP8PredLuma16",
+            "VP8PredLuma4",
+            "VP8PredChroma8",
+            "VP8Transform",
+            "VP8TransformAC3",
+            "VP8TransformUV",
+            "VP8TransformDC",
+            "VP8TransformDCUV",
+            "VP8VFilter16",
+            "VP8HFilter16",
+            "VP8VFilter8",
+            "VP8HFilter8",
+            "VP8VFilter16i",
+            "VP8HFilter16i",
+            "VP8VFilter8i",
+            "VP8HFilter8i",
+            "VP8SimpleVFilter16",
+            "VP8SimpleHFilter16",
+            "VP8SimpleVFilter16i",
+            "VP8SimpleHFilter16i",
+            "VP8DitherCombine8x8",
+            "VP8DspInit",
+        ],
+    ),
+    // The SIMD variants of the ported DSP files are part of the reference too,
+    // so that it takes the same code paths as the port.
+    ("libwebp-1.0.0/src/dsp/dec_neon.c", &["VP8DspInitNEON"]),
+    ("libwebp-1.0.0/src/dsp/dec_sse2.c", &["VP8DspInitSSE2"]),
+    ("libwebp-1.0.0/src/dsp/dec_sse41.c", &["VP8DspInitSSE41"]),
+    (
+        "libwebp-1.0.0/src/dsp/filters.c",
+        &["WebPFilters", "WebPUnfilters", "VP8FiltersInit"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/filters_neon.c",
+        &["VP8FiltersInitNEON"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/filters_sse2.c",
+        &["VP8FiltersInitSSE2"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/lossless.c",
+        &[
+            "VP8LPredictors",
+            "VP8LPredictors_C",
+            "VP8LPredictorsAdd",
+            "VP8LPredictorsAdd_C",
+            "VP8LAddGreenToBlueAndRed",
+            "VP8LAddGreenToBlueAndRed_C",
+            "VP8LTransformColorInverse",
+            "VP8LTransformColorInverse_C",
+            "VP8LColorIndexInverseTransformAlpha",
+            "VP8LInverseTransform",
+            "VP8LConvertBGRAToRGB",
+            "VP8LConvertBGRAToRGB_C",
+            "VP8LConvertBGRAToRGBA",
+            "VP8LConvertBGRAToRGBA_C",
+            "VP8LConvertBGRAToRGBA4444",
+            "VP8LConvertBGRAToRGBA4444_C",
+            "VP8LConvertBGRAToRGB565",
+            "VP8LConvertBGRAToRGB565_C",
+            "VP8LConvertBGRAToBGR",
+            "VP8LConvertBGRAToBGR_C",
+            "VP8LConvertFromBGRA",
+            "VP8LMapColor32b",
+            "VP8LMapColor8b",
+            "VP8LDspInit",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/lossless_neon.c",
+        &["VP8LDspInitNEON"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/lossless_sse2.c",
+        &["VP8LDspInitSSE2"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/rescaler.c",
+        &[
+            "WebPRescalerImportRowExpand_C",
+            "WebPRescalerImportRowShrink_C",
+            "WebPRescalerExportRowExpand_C",
+            "WebPRescalerExportRowShrink_C",
+            "WebPRescalerImportRow",
+            "WebPRescalerExportRow",
+            "WebPRescalerImportRowExpand",
+            "WebPRescalerImportRowShrink",
+            "WebPRescalerExportRowExpand",
+            "WebPRescalerExportRowShrink",
+            "WebPRescalerDspInit",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/rescaler_neon.c",
+        &["WebPRescalerDspInitNEON"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/rescaler_sse2.c",
+        &["WebPRescalerDspInitSSE2"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/upsampling.c",
+        &[
+            "WebPUpsamplers",
+            "WebPGetLinePairConverter",
+            "WebPYuv444ToRgba_C",
+            "WebPYuv444ToBgra_C",
+            "WebPYuv444ToRgb_C",
+            "WebPYuv444ToBgr_C",
+            "WebPYuv444ToArgb_C",
+            "WebPYuv444ToRgba4444_C",
+            "WebPYuv444ToRgb565_C",
+            "WebPYUV444Converters",
+            "WebPInitYUV444Converters",
+            "WebPInitUpsamplers",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/upsampling_neon.c",
+        &["WebPInitUpsamplersNEON"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/upsampling_sse2.c",
+        &["WebPInitUpsamplersSSE2", "WebPInitYUV444ConvertersSSE2"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/upsampling_sse41.c",
+        &["WebPInitUpsamplersSSE41", "WebPInitYUV444ConvertersSSE41"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/yuv.c",
+        &[
+            "WebPSamplerProcessPlane",
+            "WebPSamplers",
+            "WebPInitSamplers",
+            "WebPConvertARGBToUV_C",
+            "WebPConvertRGBA32ToUV_C",
+            "WebPConvertRGB24ToY",
+            "WebPConvertBGR24ToY",
+            "WebPConvertRGBA32ToUV",
+            "WebPConvertARGBToY",
+            "WebPConvertARGBToUV",
+            "WebPSharpYUVUpdateY",
+            "WebPSharpYUVUpdateRGB",
+            "WebPSharpYUVFilterRow",
+            "WebPInitConvertARGBToYUV",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/yuv_neon.c",
+        &["WebPInitConvertARGBToYUVNEON", "WebPInitSharpYUVNEON"],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/yuv_sse2.c",
+        &[
+            "VP8YuvToRgba32_SSE2",
+            "VP8YuvToBgra32_SSE2",
+            "VP8YuvToArgb32_SSE2",
+            "VP8YuvToRgba444432_SSE2",
+            "VP8YuvToRgb56532_SSE2",
+            "VP8YuvToRgb32_SSE2",
+            "VP8YuvToBgr32_SSE2",
+            "WebPInitSamplersSSE2",
+            "WebPInitConvertARGBToYUVSSE2",
+            "WebPInitSharpYUVSSE2",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/dsp/yuv_sse41.c",
+        &[
+            "VP8YuvToRgb32_SSE41",
+            "VP8YuvToBgr32_SSE41",
+            "WebPInitSamplersSSE41",
+            "WebPInitConvertARGBToYUVSSE41",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/utils/bit_reader_utils.c",
+        &[
+            "VP8BitReaderSetBuffer",
+            "VP8InitBitReader",
+            "VP8RemapBitReader",
+            "kVP8Log2Range",
+            "kVP8NewRange",
+            "VP8LoadFinalBytes",
+            "VP8GetValue",
+            "VP8GetSignedValue",
+            "VP8LInitBitReader",
+            "VP8LBitReaderSetBuffer",
+            "VP8LDoFillBitWindow",
+            "VP8LReadBits",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/utils/color_cache_utils.c",
+        &[
+            "VP8LColorCacheInit",
+            "VP8LColorCacheClear",
+            "VP8LColorCacheCopy",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/utils/filters_utils.c",
+        &["WebPEstimateBestFilter"],
+    ),
+    (
+        "libwebp-1.0.0/src/utils/huffman_utils.c",
+        &[
+            "VP8LHtreeGroupsNew",
+            "VP8LHtreeGroupsFree",
+            "VP8LBuildHuffmanTable",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/utils/quant_levels_dec_utils.c",
+        &["WebPDequantizeLevels"],
+    ),
+    ("libwebp-1.0.0/src/utils/random_utils.c", &["VP8InitRandom"]),
+    (
+        "libwebp-1.0.0/src/utils/rescaler_utils.c",
+        &[
+            "WebPRescalerInit",
+            "WebPRescalerGetScaledDimensions",
+            "WebPRescaleNeededLines",
+            "WebPRescalerImport",
+            "WebPRescalerExport",
+        ],
+    ),
+];
+
+// The lossless encoder, when the rust-vp8l-encoder feature is enabled.
+const VP8L_ENCODER_REFERENCE: &[(&str, &[&str])] = &[
+    (
+        "libwebp-1.0.0/src/enc/backward_references_cost_enc.c",
+        &["VP8LBackwardReferencesTraceBackwards"],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/backward_references_enc.c",
+        &[
+            "VP8LDistanceToPlaneCode",
+            "VP8LClearBackwardRefs",
+            "VP8LBackwardRefsClear",
+            "VP8LBackwardRefsInit",
+            "VP8LRefsCursorInit",
+            "VP8LRefsCursorNextBlock",
+            "VP8LBackwardRefsCursorAdd",
+            "VP8LHashChainInit",
+            "VP8LHashChainClear",
+            "VP8LHashChainFill",
+            "VP8LGetBackwardReferences",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/histogram_enc.c",
+        &[
+            "VP8LGetHistogramSize",
+            "VP8LFreeHistogram",
+            "VP8LFreeHistogramSet",
+            "VP8LHistogramStoreRefs",
+            "VP8LHistogramCreate",
+            "VP8LHistogramInit",
+            "VP8LAllocateHistogram",
+            "VP8LAllocateHistogramSet",
+            "VP8LHistogramAddSinglePixOrCopy",
+            "VP8LBitsEntropy",
+            "VP8LHistogramEstimateBits",
+            "VP8LGetHistoImageSymbols",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/predictor_enc.c",
+        &["VP8LResidualImage", "VP8LColorSpaceTransform"],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/vp8l_enc.c",
+        &["VP8LEncodeStream", "VP8LEncodeImage"],
+    ),
+];
+
+// The lossy encoder, when the rust-vp8-encoder feature is enabled.
+const VP8_ENCODER_REFERENCE: &[(&str, &[&str])] = &[
+    ("libwebp-1.0.0/src/enc/analysis_enc.c", &["VP8EncAnalyze"]),
+    (
+        "libwebp-1.0.0/src/enc/cost_enc.c",
+        &[
+            "VP8LevelCodes",
+            "VP8CalculateLevelCosts",
+            "VP8FixedCostsUV",
+            "VP8FixedCostsI16",
+            "VP8FixedCostsI4",
+            "VP8InitResidual",
+            "VP8GetCostLuma4",
+            "VP8GetCostLuma16",
+            "VP8GetCostUV",
+            "VP8RecordCoeffs",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/filter_enc.c",
+        &[
+            "VP8FilterStrengthFromDelta",
+            "VP8InitFilter",
+            "VP8StoreFilterStats",
+            "VP8AdjustFilterStrength",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/frame_enc.c",
+        &[
+            "VP8Cat3",
+            "VP8Cat4",
+            "VP8Cat5",
+            "VP8Cat6",
+            "VP8EncLoop",
+            "VP8EncTokenLoop",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/iterator_enc.c",
+        &[
+            "VP8IteratorSetRow",
+            "VP8IteratorReset",
+            "VP8IteratorSetCountDown",
+            "VP8IteratorIsDone",
+            "VP8IteratorInit",
+            "VP8IteratorProgress",
+            "VP8IteratorImport",
+            "VP8IteratorExport",
+            "VP8IteratorNzToBytes",
+            "VP8IteratorBytesToNz",
+            "VP8IteratorSaveBoundary",
+            "VP8IteratorNext",
+            "VP8SetIntra16Mode",
+            "VP8SetIntra4Mode",
+            "VP8SetIntraUVMode",
+            "VP8SetSkip",
+            "VP8SetSegment",
+            "VP8IteratorStartI4",
+            "VP8IteratorRotateI4",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/quant_enc.c",
+        &[
+            "VP8SetSegmentParams",
+            "VP8Scan",
+            "VP8I16ModeOffsets",
+            "VP8UVModeOffsets",
+            "VP8I4ModeOffsets",
+            "VP8MakeLuma16Preds",
+            "VP8MakeChroma8Preds",
+            "VP8MakeIntra4Preds",
+            "VP8Decimate",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/syntax_enc.c",
+        &["VP8EncFreeBitWriters", "VP8EncWrite"],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/token_enc.c",
+        &[
+            "VP8TBufferInit",
+            "VP8TBufferClear",
+            "VP8RecordCoeffTokens",
+            "VP8EmitTokens",
+            "VP8EstimateTokenSize",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/tree_enc.c",
+        &[
+            "VP8CoeffsProba0",
+            "VP8DefaultProbas",
+            "VP8CodeIntraModes",
+            "VP8CoeffsUpdateProba",
+            "VP8WriteProbas",
+        ],
+    ),
+    (
+        "libwebp-1.0.0/src/enc/webp_enc.c",
+        &[
+            "WebPGetEncoderVersion",
+            "WebPEncodingSetError",
+            "WebPReportProgress",
+            "WebPEncode",
+        ],
+    ),
+];
+
+// The bit writers, when either encoder feature is enabled.
+const BIT_WRITER_REFERENCE: &[(&str, &[&str])] = &[(
+    "libwebp-1.0.0/src/utils/bit_writer_utils.c",
+    &[
+        "VP8PutBit",
+        "VP8PutBitUniform",
+        "VP8PutBits",
+        "VP8PutSignedBits",
+        "VP8BitWriterInit",
+        "VP8BitWriterFinish",
+        "VP8BitWriterAppend",
+        "VP8BitWriterWipeOut",
+        "VP8LBitWriterInit",
+        "VP8LBitWriterClone",
+        "VP8LBitWriterWipeOut",
+        "VP8LBitWriterReset",
+        "VP8LBitWriterSwap",
+        "VP8LPutBitsFlushBits",
+        "VP8LPutBitsInternal",
+        "VP8LBitWriterFinish",
+    ],
+)];
+
 fn main() {
+    let pure_rust_decoder = env::var_os("CARGO_FEATURE_PURE_RUST_DECODER").is_some();
+    let c_reference = env::var_os("CARGO_FEATURE_C_REFERENCE").is_some();
+
     let mut build = cc::Build::new();
     build.include("libwebp-1.0.0");
+    // The thread worker interface is implemented in Rust (src/utils/thread_utils.rs).
+    build.define("WEBP_USE_THREAD", None);
     // build.file("libwebp-1.0.0/src/dec/alpha_dec.c");
-    build.file("libwebp-1.0.0/src/dec/buffer_dec.c");
-    build.file("libwebp-1.0.0/src/dec/frame_dec.c");
-    build.file("libwebp-1.0.0/src/dec/idec_dec.c");
-    build.file("libwebp-1.0.0/src/dec/io_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/buffer_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/frame_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/idec_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/io_dec.c");
     // build.file("libwebp-1.0.0/src/dec/quant_dec.c");
-    build.file("libwebp-1.0.0/src/dec/tree_dec.c");
-    build.file("libwebp-1.0.0/src/dec/vp8_dec.c");
-    build.file("libwebp-1.0.0/src/dec/vp8l_dec.c");
-    build.file("libwebp-1.0.0/src/dec/webp_dec.c");
-    dir(&mut build, "src/enc");
-    dir(&mut build, "src/dsp");
-    build.file("libwebp-1.0.0/src/utils/bit_reader_utils.c");
-    build.file("libwebp-1.0.0/src/utils/bit_writer_utils.c");
-    build.file("libwebp-1.0.0/src/utils/color_cache_utils.c");
-    build.file("libwebp-1.0.0/src/utils/filters_utils.c");
+    // build.file("libwebp-1.0.0/src/dec/tree_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/vp8_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/vp8l_dec.c");
+    // build.file("libwebp-1.0.0/src/dec/webp_dec.c");
+    let rust_vp8_encoder = env::var_os("CARGO_FEATURE_RUST_VP8_ENCODER").is_some();
+    let rust_vp8l_encoder = env::var_os("CARGO_FEATURE_RUST_VP8L_ENCODER").is_some();
+    let mut ported_enc = Vec::new();
+    if rust_vp8_encoder {
+        ported_enc.extend_from_slice(&[
+            "analysis_enc.c",
+            "cost_enc.c",
+            "filter_enc.c",
+            "frame_enc.c",
+            "iterator_enc.c",
+            "quant_enc.c",
+            "syntax_enc.c",
+            "token_enc.c",
+            "tree_enc.c",
+            "webp_enc.c",
+        ]);
+    }
+    if rust_vp8l_encoder {
+        ported_enc.extend_from_slice(&[
+            "backward_references_cost_enc.c",
+            "backward_references_enc.c",
+            "histogram_enc.c",
+            "predictor_enc.c",
+            "vp8l_enc.c",
+        ]);
+    }
+    dir(&mut build, "src/enc", &ported_enc);
+    dir(&mut build, "src/mux", &[]);
+    dir(&mut build, "src/demux", &["anim_decode.c"]);
+    let mut ported_dsp = vec![
+        "alpha_processing.c",
+        "cpu.c",
+        "dec.c",
+        "dec_clip_tables.c",
+        "dec_sse2.c",
+        "dec_sse41.c",
+        "filters.c",
+        "lossless.c",
+        "lossless_sse2.c",
+        "rescaler.c",
+        "upsampling.c",
+        "upsampling_sse2.c",
+        "upsampling_sse41.c",
+        "yuv.c",
+    ];
+    if pure_rust_decoder {
+        // The Rust decoder doesn't call into the platform-specific C decoding
+        // functions either then.
+        ported_dsp.extend_from_slice(&[
+            "alpha_processing_mips_dsp_r2.c",
+            "alpha_processing_neon.c",
+            "alpha_processing_sse2.c",
+            "alpha_processing_sse41.c",
+            "dec_mips32.c",
+            "dec_mips_dsp_r2.c",
+            "dec_msa.c",
+            "dec_neon.c",
+            "filters_mips_dsp_r2.c",
+            "filters_msa.c",
+            "filters_neon.c",
+            "filters_sse2.c",
+            "lossless_mips_dsp_r2.c",
+            "lossless_msa.c",
+            "lossless_neon.c",
+            "rescaler_mips32.c",
+            "rescaler_mips_dsp_r2.c",
+            "rescaler_msa.c",
+            "rescaler_neon.c",
+            "rescaler_sse2.c",
+            "upsampling_mips_dsp_r2.c",
+            "upsampling_msa.c",
+            "upsampling_neon.c",
+        ]);
+    }
+    dir(&mut build, "src/dsp", &ported_dsp);
+    // build.file("libwebp-1.0.0/src/utils/bit_reader_utils.c");
+    if !rust_vp8_encoder && !rust_vp8l_encoder {
+        build.file("libwebp-1.0.0/src/utils/bit_writer_utils.c");
+    }
+    // build.file("libwebp-1.0.0/src/utils/color_cache_utils.c");
+    // build.file("libwebp-1.0.0/src/utils/filters_utils.c");
     build.file("libwebp-1.0.0/src/utils/huffman_encode_utils.c");
-    build.file("libwebp-1.0.0/src/utils/huffman_utils.c");
-    build.file("libwebp-1.0.0/src/utils/quant_levels_dec_utils.c");
+    // build.file("libwebp-1.0.0/src/utils/huffman_utils.c");
+    // build.file("libwebp-1.0.0/src/utils/quant_levels_dec_utils.c");
     build.file("libwebp-1.0.0/src/utils/quant_levels_utils.c");
     // build.file("libwebp-1.0.0/src/utils/random_utils.c");
-    build.file("libwebp-1.0.0/src/utils/rescaler_utils.c");
-    build.file("libwebp-1.0.0/src/utils/thread_utils.c");
-    build.file("libwebp-1.0.0/src/utils/utils.c");
+    // build.file("libwebp-1.0.0/src/utils/rescaler_utils.c");
+    // build.file("libwebp-1.0.0/src/utils/thread_utils.c");
+    // build.file("libwebp-1.0.0/src/utils/utils.c");
     build.compile("webp");
+
+    // The original C implementation of the ported parts, with their symbols
+    // renamed, to check the ports against.
+    if !c_reference {
+        return;
+    }
+    let mut build = cc::Build::new();
+    build.include("libwebp-1.0.0");
+    let vp8_encoder_reference = if rust_vp8_encoder {
+        VP8_ENCODER_REFERENCE
+    } else {
+        &[]
+    };
+    let vp8l_encoder_reference = if rust_vp8l_encoder {
+        VP8L_ENCODER_REFERENCE
+    } else {
+        &[]
+    };
+    let bit_writer_reference = if rust_vp8_encoder || rust_vp8l_encoder {
+        BIT_WRITER_REFERENCE
+    } else {
+        &[]
+    };
+    for &(file, symbols) in REFERENCE
+        .iter()
+        .chain(vp8_encoder_reference)
+        .chain(vp8l_encoder_reference)
+        .chain(bit_writer_reference)
+    {
+        build.file(file);
+        for symbol in symbols {
+            build.define(symbol, Some(&format!("WebPRef_{}", symbol)[..]));
+        }
+    }
+    build.file("reference/bit_reader_ref.c");
+    build.compile("webpref");
 }
@@ -0,0 +1,218 @@
+//! Bodies of the fuzz targets, shared with the regression tests of the main
+//! crate (`tests/fuzz_regressions.rs`), which replay the seed corpus in
+//! `fuzz/seeds/<target>/` and the crash inputs in `fuzz/regressions/<target>/`.
+//!
+//! The seeds are the libwebp example images and the test fixtures, along with
+//! ICC and EXIF tagged variants of them, minimized with `-merge=1`, plus a few
+//! handcrafted edge cases. The targets taking an options or step size prefix
+//! get the files under several prefixes. Fuzz from a copy of them, e.g.:
+//!
+//! ```text
+//! cargo fuzz run decode fuzz/corpus/decode fuzz/seeds/decode
+//! ```
+//!
+//! libFuzzer only adds new inputs to the first directory. Once a crash is
+//! fixed, minimize the input with `cargo fuzz tmin` and add it to
+//! `fuzz/regressions/<target>/`.
+
+use std::os::raw::c_int;
+use std::ptr;
+
+use webp::demux::Demuxer;
+use webp::sys;
+use webp::{
+    decode_argb, decode_bgr, decode_bgra, decode_rgb, decode_rgba, decode_rgba_with_options,
+    get_exif, get_features, get_icc_profile, get_info, get_orientation, resize_rgba, AnimDecoder,
+    DecoderOptions, WebpBox,
+};
+#[cfg(feature = "icc")]
+use webp::{decode_rgba_srgb, IccProfile};
+
+// Inputs declaring more pixels than this are only parsed, not decoded, to
+// keep the fuzzers fast and within their memory limit.
+const MAX_PIXELS: u64 = 1 << 22;
+
+// MODE_RGBA
+const COLORSPACE: c_int = 1;
+
+type Decoder = fn(&[u8]) -> Option<(u32, u32, WebpBox<[u8]>)>;
+
+fn small_enough(data: &[u8]) -> bool {
+    match get_info(data) {
+        Some((width, height)) => width as u64 * height as u64 <= MAX_PIXELS,
+        None => true,
+    }
+}
+
+/// Header parsing: `get_info`, `get_features` and the metadata getters.
+pub fn fuzz_get_info(data: &[u8]) {
+    let info = get_info(data);
+    if let Some(features) = get_features(data) {
+        assert_eq!(info, Some((features.width, features.height)));
+    }
+    let _ = get_icc_profile(data);
+    let _ = get_exif(data);
+    let _ = get_orientation(data);
+}
+
+/// Every `decode_*` function, checked against `get_info`.
+pub fn fuzz_decode(data: &[u8]) {
+    if !small_enough(data) {
+        return;
+    }
+    let decoders: [(Decoder, usize); 5] = [
+        (decode_rgba, 4),
+        (decode_argb, 4),
+        (decode_bgra, 4),
+        (decode_rgb, 3),
+        (decode_bgr, 3),
+    ];
+    for &(decode, bytes_per_pixel) in &decoders {
+        if let Some((width, height, buf)) = decode(data) {
+            assert_eq!(get_info(data), Some((width, height)));
+            assert_eq!(
+                buf.len(),
+                width as usize * height as usize * bytes_per_pixel
+            );
+        }
+    }
+}
+
+/// The ICC profile parser, on the bare input, and `decode_rgba_srgb`, which
+/// converts the decoded pixels with the profile of the file.
+#[cfg(feature = "icc")]
+pub fn fuzz_decode_rgba_srgb(data: &[u8]) {
+    if let Some(profile) = IccProfile::parse(data) {
+        let mut rgba: Vec<u8> = (0..=255).collect();
+        profile.transform_rgba_to_srgb(&mut rgba);
+    }
+    if !small_enough(data) {
+        return;
+    }
+    if let Some((width, height, buf)) = decode_rgba_srgb(data) {
+        assert_eq!(get_info(data), Some((width, height)));
+        assert_eq!(buf.len(), width as usize * height as usize * 4);
+    }
+}
+
+/// `decode_rgba_with_options` and `resize_rgba`. The first 8 bytes of the
+/// input select the options, the rest is the file.
+pub fn fuzz_decode_with_options(data: &[u8]) {
+    if data.len() < 8 {
+        return;
+    }
+    let (params, data) = data.split_at(8);
+    let (width, height) = match get_info(data) {
+        Some(dimensions) => dimensions,
+        None => return,
+    };
+    if width as u64 * height as u64 > MAX_PIXELS {
+        return;
+    }
+    let flags = params[0];
+    let scaled = |param: u8, dim: u32| param as u32 * (dim + 1) / 128;
+    let options = DecoderOptions {
+        bypass_filtering: flags & 1 != 0,
+        no_fancy_upsampling: flags & 2 != 0,
+        use_cropping: flags & 4 != 0,
+        crop_left: scaled(params[1], width),
+        crop_top: scaled(params[2], height),
+        crop_width: scaled(params[3], width),
+        crop_height: scaled(params[4], height),
+        use_scaling: flags & 8 != 0,
+        scaled_width: scaled(params[5], width),
+        scaled_height: scaled(params[6], height),
+        use_threads: flags & 16 != 0,
+        dithering_strength: (params[7] % 128) as i32,
+        flip: flags & 32 != 0,
+        alpha_dithering_strength: (params[7] / 2) as i32,
+        apply_orientation: flags & 64 != 0,
+    };
+    if let Some((width, height, buf)) = decode_rgba_with_options(data, &options) {
+        assert_eq!(buf.len(), width as usize * height as usize * 4);
+        let _ = resize_rgba(
+            &buf,
+            width,
+            height,
+            params[5] as u32 + 1,
+            params[6] as u32 + 1,
+        );
+    }
+}
+
+/// The incremental decoder, fed in pieces. The first byte of the input
+/// selects the size of the pieces, the rest is the file.
+pub fn fuzz_incremental_decode(data: &[u8]) {
+    let (step, data) = match data.split_first() {
+        Some((&step, data)) => (step as usize + 1, data),
+        None => return,
+    };
+    if !small_enough(data) {
+        return;
+    }
+    unsafe {
+        // WebPIAppend copies the data, WebPIUpdate sees the whole prefix.
+        for &append in &[true, false] {
+            let idec = sys::WebPINewRGB(COLORSPACE, ptr::null_mut(), 0, 0);
+            assert!(!idec.is_null());
+            let mut end = 0;
+            while end < data.len() {
+                let start = end;
+                end = (end + step).min(data.len());
+                let status = if append {
+                    sys::WebPIAppend(idec, data[start..].as_ptr(), end - start)
+                } else {
+                    sys::WebPIUpdate(idec, data.as_ptr(), end)
+                };
+                let (mut last_y, mut width, mut height, mut stride) = (0, 0, 0, 0);
+                let rgba =
+                    sys::WebPIDecGetRGB(idec, &mut last_y, &mut width, &mut height, &mut stride);
+                if !rgba.is_null() {
+                    assert!(0 <= last_y && last_y <= height);
+                    assert!(stride >= width * 4);
+                    // Read the rows decoded so far.
+                    let len = last_y as usize * stride as usize;
+                    let rows = std::slice::from_raw_parts(rgba, len);
+                    let _ = rows.iter().fold(0u8, |acc, &b| acc ^ b);
+                }
+                // VP8_STATUS_OK or VP8_STATUS_SUSPENDED
+                if status != 0 && status != 5 {
+                    break;
+                }
+            }
+            sys::WebPIDelete(idec);
+        }
+    }
+}
+
+/// The container parser, and the animation decoder on top of it.
+pub fn fuzz_demux(data: &[u8]) {
+    let demux = match Demuxer::new(data) {
+        Some(demux) => demux,
+        None => return,
+    };
+    for frame in &demux.frames {
+        assert_eq!(demux.frame(frame.frame_num), Some(frame));
+        assert!(frame.x_offset as u64 + frame.width as u64 <= demux.canvas_width as u64);
+        assert!(frame.y_offset as u64 + frame.height as u64 <= demux.canvas_height as u64);
+    }
+    for chunk in &demux.chunks {
+        let _ = demux.chunk(&chunk.fourcc);
+    }
+    if demux.canvas_width as u64 * demux.canvas_height as u64 > MAX_PIXELS {
+        return;
+    }
+    if let Some(mut dec) = AnimDecoder::new(data) {
+        let info = dec.info();
+        let canvas_len = info.canvas_width as usize * info.canvas_height as usize * 4;
+        while dec.has_more_frames() {
+            match dec.next_frame() {
+                Some((canvas, _)) => assert_eq!(canvas.len(), canvas_len),
+                None => break,
+            }
+        }
+        if info.frame_count > 0 {
+            let _ = dec.render_frame(info.frame_count);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+// Exposes the inlined bit reader methods of the original C implementation
+// so that the Rust port can be checked against them. Compiled together with
+// bit_reader_utils.c, with every global symbol prefixed with "WebPRef_".
+
+#include "src/utils/bit_reader_inl_utils.h"
+
+int WebPRef_VP8GetBit(VP8BitReader* const br, int prob) {
+  return VP8GetBit(br, prob);
+}
+
+int WebPRef_VP8GetSigned(VP8BitReader* const br, int v) {
+  return VP8GetSigned(br, v);
+}
+
+int WebPRef_VP8GetBitAlt(VP8BitReader* const br, int prob) {
+  return VP8GetBitAlt(br, prob);
+}
+
+uint32_t WebPRef_VP8LPrefetchBits(VP8LBitReader* const br) {
+  return VP8LPrefetchBits(br);
+}
+
+void WebPRef_VP8LFillBitWindow(VP8LBitReader* const br) {
+  VP8LFillBitWindow(br);
+}
@@ -0,0 +1,1085 @@
+// Copyright 2015 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+//  AnimDecoder implementation.
+//
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+use std::ptr;
+use std::slice;
+
+use dec::vp8_dec::WEBP_ABI_IS_INCOMPATIBLE;
+use decode::{decode_into, MODE_bgrA, MODE_rgbA, MODE_BGRA, MODE_RGBA, WEBP_CSP_MODE};
+use demux::{Demuxer, Frame, WebPMuxAnimBlend, WebPMuxAnimDispose};
+use sys;
+
+pub const WEBP_DEMUX_ABI_VERSION: u16 = 0x0107;
+
+const NUM_CHANNELS: usize = 4;
+
+type BlendRowFunc = fn(&mut [u8], &[u8], usize, usize);
+
+/// Global information about an animation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimInfo {
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    pub loop_count: u32,
+    pub bgcolor: u32,
+    pub frame_count: u32,
+}
+
+/// Pure-Rust counterpart of `WebPAnimDecoder`: decodes the frames of an
+/// animation and composes them onto an RGBA canvas, following the dispose
+/// and blend methods of each frame.
+///
+/// Key-frames (frames which don't depend on the previous canvas) are
+/// determined up front, so that `render_frame` can start decoding from the
+/// nearest key-frame.
+pub struct AnimDecoder<'a> {
+    demux: Demuxer<'a>,
+    color_mode: WEBP_CSP_MODE, // One of MODE_RGBA, MODE_BGRA, MODE_rgbA, MODE_bgrA.
+    use_threads: bool,
+    blend_func: BlendRowFunc,
+    info: AnimInfo,
+    key_frames: Vec<bool>,        // Whether each frame is a key-frame.
+    curr_frame: Vec<u8>,          // Current canvas (not disposed).
+    prev_frame_disposed: Vec<u8>, // Previous canvas (properly disposed).
+    prev_frame_timestamp: i32,    // Previous frame timestamp (milliseconds).
+    next_frame: u32,              // Index of the next frame to be decoded
+                                  // (starting from 1).
+}
+
+// Returns true if the frame covers the full canvas.
+fn is_full_frame(width: u32, height: u32, canvas_width: u32, canvas_height: u32) -> bool {
+    width == canvas_width && height == canvas_height
+}
+
+// Returns true if the current frame is a key-frame.
+fn is_key_frame(
+    curr: &Frame,
+    prev: &Frame,
+    prev_frame_was_key_frame: bool,
+    canvas_width: u32,
+    canvas_height: u32,
+) -> bool {
+    if curr.frame_num == 1
+        || ((!curr.has_alpha || curr.blend_method == WebPMuxAnimBlend::WEBP_MUX_NO_BLEND)
+            && is_full_frame(curr.width, curr.height, canvas_width, canvas_height))
+    {
+        true
+    } else {
+        prev.dispose_method == WebPMuxAnimDispose::WEBP_MUX_DISPOSE_BACKGROUND
+            && (is_full_frame(prev.width, prev.height, canvas_width, canvas_height)
+                || prev_frame_was_key_frame)
+    }
+}
+
+// Clear given frame rectangle to transparent.
+fn zero_fill_frame_rect(
+    buf: &mut [u8],
+    buf_stride: usize,
+    x_offset: usize,
+    y_offset: usize,
+    width: usize,
+    height: usize,
+) {
+    c_assert!(width * NUM_CHANNELS <= buf_stride);
+    for j in 0..height {
+        let start = (y_offset + j) * buf_stride + x_offset * NUM_CHANNELS;
+        for b in &mut buf[start..start + width * NUM_CHANNELS] {
+            *b = 0;
+        }
+    }
+}
+
+fn get_pixel(buf: &[u8], i: usize) -> u32 {
+    let p = &buf[i * NUM_CHANNELS..];
+    p[0] as u32 | (p[1] as u32) << 8 | (p[2] as u32) << 16 | (p[3] as u32) << 24
+}
+
+fn set_pixel(buf: &mut [u8], i: usize, pixel: u32) {
+    let p = &mut buf[i * NUM_CHANNELS..];
+    p[0] = pixel as u8;
+    p[1] = (pixel >> 8) as u8;
+    p[2] = (pixel >> 16) as u8;
+    p[3] = (pixel >> 24) as u8;
+}
+
+// Blend a single channel of 'src' over 'dst', given their alpha channel values.
+// 'src' and 'dst' are assumed to be NOT pre-multiplied by alpha.
+fn blend_channel_non_premult(
+    src: u32,
+    src_a: u8,
+    dst: u32,
+    dst_a: u8,
+    scale: u32,
+    shift: u32,
+) -> u8 {
+    let src_channel = ((src >> shift) & 0xff) as u8;
+    let dst_channel = ((dst >> shift) & 0xff) as u8;
+    let blend_unscaled = src_channel as u32 * src_a as u32 + dst_channel as u32 * dst_a as u32;
+    c_assert!((blend_unscaled as u64) < (1u64 << 32) / scale as u64);
+    ((blend_unscaled * scale) >> 24) as u8
+}
+
+// Blend 'src' over 'dst' assuming they are NOT pre-multiplied by alpha.
+fn blend_pixel_non_premult(src: u32, dst: u32) -> u32 {
+    let src_a = ((src >> 24) & 0xff) as u8;
+
+    if src_a == 0 {
+        dst
+    } else {
+        let dst_a = ((dst >> 24) & 0xff) as u8;
+        // This is the approximate integer arithmetic for the actual formula:
+        // dst_factor_a = (dst_a * (255 - src_a)) / 255.
+        let dst_factor_a = ((dst_a as u32 * (256 - src_a as u32)) >> 8) as u8;
+        let blend_a = src_a + dst_factor_a;
+        let scale = (1u32 << 24) / blend_a as u32;
+
+        let blend_r = blend_channel_non_premult(src, src_a, dst, dst_factor_a, scale, 0);
+        let blend_g = blend_channel_non_premult(src, src_a, dst, dst_factor_a, scale, 8);
+        let blend_b = blend_channel_non_premult(src, src_a, dst, dst_factor_a, scale, 16);
+        c_assert!((src_a as u32 + dst_factor_a as u32) < 256);
+
+        (blend_r as u32) | (blend_g as u32) << 8 | (blend_b as u32) << 16 | (blend_a as u32) << 24
+    }
+}
+
+// Blend 'num_pixels' in 'src' over 'dst' assuming they are NOT pre-multiplied
+// by alpha.
+fn blend_pixel_row_non_premult(src: &mut [u8], dst: &[u8], offset: usize, num_pixels: usize) {
+    for i in offset..offset + num_pixels {
+        let src_pixel = get_pixel(src, i);
+        let src_alpha = ((src_pixel >> 24) & 0xff) as u8;
+        if src_alpha != 0xff {
+            set_pixel(
+                src,
+                i,
+                blend_pixel_non_premult(src_pixel, get_pixel(dst, i)),
+            );
+        }
+    }
+}
+
+// Multiply each channel in 'pix' by 'scale'.
+fn channelwise_multiply(pix: u32, scale: u32) -> u32 {
+    let mask = 0x00ff00ff;
+    let rb = ((pix & mask) * scale) >> 8;
+    let ag = ((pix >> 8) & mask) * scale;
+    (rb & mask) | (ag & !mask)
+}
+
+// Blend 'src' over 'dst' assuming they are pre-multiplied by alpha.
+fn blend_pixel_premult(src: u32, dst: u32) -> u32 {
+    let src_a = ((src >> 24) & 0xff) as u8;
+    src.wrapping_add(channelwise_multiply(dst, 256 - src_a as u32))
+}
+
+// Blend 'num_pixels' in 'src' over 'dst' assuming they are pre-multiplied by
+// alpha.
+fn blend_pixel_row_premult(src: &mut [u8], dst: &[u8], offset: usize, num_pixels: usize) {
+    for i in offset..offset + num_pixels {
+        let src_pixel = get_pixel(src, i);
+        let src_alpha = ((src_pixel >> 24) & 0xff) as u8;
+        if src_alpha != 0xff {
+            set_pixel(src, i, blend_pixel_premult(src_pixel, get_pixel(dst, i)));
+        }
+    }
+}
+
+// Returns two ranges (<left, width> pairs) at row 'canvas_y', that belong to
+// 'src' but not 'dst'. A point range is empty if the corresponding width is 0.
+fn find_blend_range_at_row(src: &Frame, dst: &Frame, canvas_y: u32) -> ((u32, u32), (u32, u32)) {
+    let src_max_x = src.x_offset + src.width;
+    let dst_max_x = dst.x_offset + dst.width;
+    let dst_max_y = dst.y_offset + dst.height;
+    c_assert!(canvas_y >= src.y_offset && canvas_y < (src.y_offset + src.height));
+    let mut range1 = (0, 0);
+    let mut range2 = (0, 0);
+
+    if canvas_y < dst.y_offset
+        || canvas_y >= dst_max_y
+        || src.x_offset >= dst_max_x
+        || src_max_x <= dst.x_offset
+    {
+        range1 = (src.x_offset, src.width);
+        return (range1, range2);
+    }
+
+    if src.x_offset < dst.x_offset {
+        range1 = (src.x_offset, dst.x_offset - src.x_offset);
+    }
+
+    if src_max_x > dst_max_x {
+        range2 = (dst_max_x, src_max_x - dst_max_x);
+    }
+    (range1, range2)
+}
+
+// Allocates a zeroed canvas, or returns None where WebPSafeCalloc() would
+// return NULL.
+fn new_canvas(size: usize) -> Option<Vec<u8>> {
+    let mut buf = Vec::new();
+    if buf.try_reserve_exact(size).is_err() {
+        return None;
+    }
+    buf.resize(size, 0);
+    Some(buf)
+}
+
+impl<'a> AnimDecoder<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        Self::with_options(data, MODE_RGBA, false)
+    }
+
+    // Same as new(), with the options of WebPAnimDecoderOptions. Returns None
+    // if 'color_mode' is not one of the 32-bit RGB(A) modes.
+    pub(crate) fn with_options(
+        data: &'a [u8],
+        color_mode: WEBP_CSP_MODE,
+        use_threads: bool,
+    ) -> Option<Self> {
+        let blend_func: BlendRowFunc = if color_mode == MODE_RGBA || color_mode == MODE_BGRA {
+            blend_pixel_row_non_premult
+        } else if color_mode == MODE_rgbA || color_mode == MODE_bgrA {
+            blend_pixel_row_premult
+        } else {
+            return None;
+        };
+
+        let demux = Demuxer::new(data)?;
+        let info = AnimInfo {
+            canvas_width: demux.canvas_width,
+            canvas_height: demux.canvas_height,
+            loop_count: demux.loop_count,
+            bgcolor: demux.bgcolor,
+            frame_count: demux.frames.len() as u32,
+        };
+
+        let mut key_frames: Vec<bool> = Vec::with_capacity(demux.frames.len());
+        for (i, frame) in demux.frames.iter().enumerate() {
+            let key_frame = if i == 0 {
+                true
+            } else {
+                is_key_frame(
+                    frame,
+                    &demux.frames[i - 1],
+                    key_frames[i - 1],
+                    info.canvas_width,
+                    info.canvas_height,
+                )
+            };
+            key_frames.push(key_frame);
+        }
+
+        let size = (info.canvas_width as usize)
+            .checked_mul(info.canvas_height as usize)?
+            .checked_mul(NUM_CHANNELS)?;
+        let curr_frame = new_canvas(size)?;
+        let prev_frame_disposed = new_canvas(size)?;
+        Some(Self {
+            demux,
+            color_mode,
+            use_threads,
+            blend_func,
+            info,
+            key_frames,
+            curr_frame,
+            prev_frame_disposed,
+            prev_frame_timestamp: 0,
+            next_frame: 1,
+        })
+    }
+
+    pub fn info(&self) -> AnimInfo {
+        self.info
+    }
+
+    pub fn demuxer(&self) -> &Demuxer<'a> {
+        &self.demux
+    }
+
+    /// Returns true if the 1-based `frame_num`-th frame can be rendered
+    /// without the canvas of the previous frames.
+    pub fn is_key_frame(&self, frame_num: u32) -> bool {
+        frame_num >= 1 && self.key_frames.get(frame_num as usize - 1) == Some(&true)
+    }
+
+    pub fn has_more_frames(&self) -> bool {
+        self.next_frame <= self.info.frame_count
+    }
+
+    pub fn reset(&mut self) {
+        self.prev_frame_timestamp = 0;
+        self.next_frame = 1;
+    }
+
+    /// Decodes the next frame and returns the fully reconstructed canvas
+    /// (in RGBA order, unless another mode was requested through the C API)
+    /// together with the timestamp at which the frame ends.
+    pub fn next_frame(&mut self) -> Option<(&[u8], i32)> {
+        if !self.has_more_frames() {
+            return None;
+        }
+
+        let width = self.info.canvas_width as usize;
+        let height = self.info.canvas_height as usize;
+        let idx = self.next_frame as usize - 1;
+        let iter = self.demux.frames[idx];
+        let timestamp = self.prev_frame_timestamp.wrapping_add(iter.duration as i32);
+
+        // Initialize.
+        let is_key_frame = self.key_frames[idx];
+        if is_key_frame {
+            for b in &mut self.curr_frame {
+                *b = 0;
+            }
+        } else {
+            self.curr_frame.copy_from_slice(&self.prev_frame_disposed);
+        }
+
+        // Decode.
+        {
+            let stride = NUM_CHANNELS * width;
+            let out_offset =
+                iter.y_offset as usize * stride + iter.x_offset as usize * NUM_CHANNELS;
+            let (frame_width, frame_height) = decode_into(
+                iter.payload,
+                self.color_mode,
+                self.use_threads,
+                &mut self.curr_frame[out_offset..],
+                stride,
+            )?;
+            if frame_width != iter.width || frame_height != iter.height {
+                return None;
+            }
+        }
+
+        // During the decoding of current frame, we may have set some pixels to be
+        // transparent (i.e. alpha < 255). However, the value of each of these
+        // pixels should have been determined by blending it against the value of
+        // that pixel in the previous frame if blending method of is WEBP_MUX_BLEND.
+        if iter.frame_num > 1
+            && iter.blend_method == WebPMuxAnimBlend::WEBP_MUX_BLEND
+            && !is_key_frame
+        {
+            let prev_iter = self.demux.frames[idx - 1];
+            if prev_iter.dispose_method == WebPMuxAnimDispose::WEBP_MUX_DISPOSE_NONE {
+                // Blend transparent pixels with pixels in previous canvas.
+                for y in 0..iter.height as usize {
+                    let offset = (iter.y_offset as usize + y) * width + iter.x_offset as usize;
+                    (self.blend_func)(
+                        &mut self.curr_frame,
+                        &self.prev_frame_disposed,
+                        offset,
+                        iter.width as usize,
+                    );
+                }
+            } else {
+                c_assert!(
+                    prev_iter.dispose_method == WebPMuxAnimDispose::WEBP_MUX_DISPOSE_BACKGROUND
+                );
+                // We need to blend a transparent pixel with its value just after
+                // initialization. That is, blend it with:
+                // * Fully transparent pixel if it belongs to prevRect <-- No-op.
+                // * The pixel in the previous canvas otherwise <-- Need alpha-blending.
+                for y in 0..iter.height {
+                    let canvas_y = iter.y_offset + y;
+                    let ((left1, width1), (left2, width2)) =
+                        find_blend_range_at_row(&iter, &prev_iter, canvas_y);
+                    if width1 > 0 {
+                        let offset1 = canvas_y as usize * width + left1 as usize;
+                        (self.blend_func)(
+                            &mut self.curr_frame,
+                            &self.prev_frame_disposed,
+                            offset1,
+                            width1 as usize,
+                        );
+                    }
+                    if width2 > 0 {
+                        let offset2 = canvas_y as usize * width + left2 as usize;
+                        (self.blend_func)(
+                            &mut self.curr_frame,
+                            &self.prev_frame_disposed,
+                            offset2,
+                            width2 as usize,
+                        );
+                    }
+                }
+            }
+        }
+
+        // Update info of the previous frame and dispose it for the next iteration.
+        self.prev_frame_timestamp = timestamp;
+        self.prev_frame_disposed.copy_from_slice(&self.curr_frame);
+        if iter.dispose_method == WebPMuxAnimDispose::WEBP_MUX_DISPOSE_BACKGROUND {
+            zero_fill_frame_rect(
+                &mut self.prev_frame_disposed,
+                width * NUM_CHANNELS,
+                iter.x_offset as usize,
+                iter.y_offset as usize,
+                iter.width as usize,
+                iter.height as usize,
+            );
+        }
+        self.next_frame += 1;
+        c_assert!(self.curr_frame.len() == width * height * NUM_CHANNELS);
+
+        Some((&self.curr_frame, timestamp))
+    }
+
+    /// Renders the 1-based `frame_num`-th frame, decoding only the frames
+    /// from the nearest preceding key-frame. Subsequent `next_frame` calls
+    /// continue from there.
+    pub fn render_frame(&mut self, frame_num: u32) -> Option<(&[u8], i32)> {
+        if frame_num == 0 || frame_num > self.info.frame_count {
+            return None;
+        }
+        let key_frame = (1..frame_num + 1)
+            .rev()
+            .find(|&n| self.is_key_frame(n))
+            .unwrap();
+        self.prev_frame_timestamp = self.demux.frames[..key_frame as usize - 1]
+            .iter()
+            .fold(0i32, |sum, f| sum.wrapping_add(f.duration as i32));
+        self.next_frame = key_frame;
+        while self.next_frame < frame_num {
+            self.next_frame()?;
+        }
+        self.next_frame()
+    }
+}
+
+//------------------------------------------------------------------------------
+// C API (WebPAnimDecoder), on top of AnimDecoder.
+// As with libwebp, the WebPData must outlive the decoder.
+
+#[allow(non_snake_case)]
+fn DefaultDecoderOptions(dec_options: &mut sys::WebPAnimDecoderOptions) {
+    dec_options.color_mode = MODE_RGBA;
+    dec_options.use_threads = 0;
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderOptionsInitInternal(
+    dec_options: *mut sys::WebPAnimDecoderOptions,
+    abi_version: c_int,
+) -> c_int {
+    let dec_options = match dec_options.as_mut() {
+        Some(dec_options) => dec_options,
+        None => return 0,
+    };
+    if WEBP_ABI_IS_INCOMPATIBLE(abi_version, WEBP_DEMUX_ABI_VERSION as c_int) {
+        return 0;
+    }
+    DefaultDecoderOptions(dec_options);
+    1
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderNewInternal(
+    webp_data: *const sys::WebPData,
+    dec_options: *const sys::WebPAnimDecoderOptions,
+    abi_version: c_int,
+) -> *mut sys::WebPAnimDecoder {
+    let webp_data = match webp_data.as_ref() {
+        Some(webp_data) => webp_data,
+        None => return ptr::null_mut(),
+    };
+    if WEBP_ABI_IS_INCOMPATIBLE(abi_version, WEBP_DEMUX_ABI_VERSION as c_int) {
+        return ptr::null_mut();
+    }
+    let (color_mode, use_threads) = match dec_options.as_ref() {
+        Some(dec_options) => (dec_options.color_mode, dec_options.use_threads != 0),
+        None => (MODE_RGBA, false),
+    };
+    if webp_data.bytes.is_null() {
+        return ptr::null_mut();
+    }
+
+    let data: &'static [u8] = slice::from_raw_parts(webp_data.bytes, webp_data.size);
+    match AnimDecoder::with_options(data, color_mode, use_threads) {
+        Some(dec) => Box::into_raw(Box::new(dec)) as *mut sys::WebPAnimDecoder,
+        None => ptr::null_mut(),
+    }
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderGetInfo(
+    dec: *const sys::WebPAnimDecoder,
+    info: *mut sys::WebPAnimInfo,
+) -> c_int {
+    let dec = match (dec as *const AnimDecoder<'static>).as_ref() {
+        Some(dec) => dec,
+        None => return 0,
+    };
+    let info = match info.as_mut() {
+        Some(info) => info,
+        None => return 0,
+    };
+    *info = sys::WebPAnimInfo {
+        canvas_width: dec.info.canvas_width,
+        canvas_height: dec.info.canvas_height,
+        loop_count: dec.info.loop_count,
+        bgcolor: dec.info.bgcolor,
+        frame_count: dec.info.frame_count,
+        pad: [0; 4],
+    };
+    1
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderGetNext(
+    dec: *mut sys::WebPAnimDecoder,
+    buf_ptr: *mut *mut u8,
+    timestamp_ptr: *mut c_int,
+) -> c_int {
+    let dec = match (dec as *mut AnimDecoder<'static>).as_mut() {
+        Some(dec) => dec,
+        None => return 0,
+    };
+    if buf_ptr.is_null() || timestamp_ptr.is_null() {
+        return 0;
+    }
+    let timestamp = match dec.next_frame() {
+        Some((_, timestamp)) => timestamp,
+        None => return 0,
+    };
+    *buf_ptr = dec.curr_frame.as_mut_ptr();
+    *timestamp_ptr = timestamp as c_int;
+    1
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderHasMoreFrames(dec: *const sys::WebPAnimDecoder) -> c_int {
+    match (dec as *const AnimDecoder<'static>).as_ref() {
+        Some(dec) => dec.has_more_frames() as c_int,
+        None => 0,
+    }
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderReset(dec: *mut sys::WebPAnimDecoder) {
+    if let Some(dec) = (dec as *mut AnimDecoder<'static>).as_mut() {
+        dec.reset();
+    }
+}
+
+#[allow(non_snake_case)]
+#[doc(hidden)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPAnimDecoderDelete(dec: *mut sys::WebPAnimDecoder) {
+    if !dec.is_null() {
+        drop(Box::from_raw(dec as *mut AnimDecoder<'static>));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem;
+
+    use anim_encode::{AnimEncoder, AnimEncoderOptions};
+    use demux::WebPMuxAnimBlend::*;
+    use demux::WebPMuxAnimDispose::*;
+    use encode::EncoderConfig;
+    use format_constants::MAX_DURATION;
+    use sys;
+    use test_util::check_fixtures;
+    use test_util::{fixtures, Digest, Fixture};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(improper_ctypes)]
+    extern "C" {
+        fn WebPRef_WebPAnimDecoderOptionsInitInternal(
+            dec_options: *mut sys::WebPAnimDecoderOptions,
+            abi_version: c_int,
+        ) -> c_int;
+        fn WebPRef_WebPAnimDecoderNewInternal(
+            webp_data: *const sys::WebPData,
+            dec_options: *const sys::WebPAnimDecoderOptions,
+            abi_version: c_int,
+        ) -> *mut sys::WebPAnimDecoder;
+        fn WebPRef_WebPAnimDecoderGetInfo(
+            dec: *const sys::WebPAnimDecoder,
+            info: *mut sys::WebPAnimInfo,
+        ) -> c_int;
+        fn WebPRef_WebPAnimDecoderGetNext(
+            dec: *mut sys::WebPAnimDecoder,
+            buf_ptr: *mut *mut u8,
+            timestamp_ptr: *mut c_int,
+        ) -> c_int;
+        fn WebPRef_WebPAnimDecoderHasMoreFrames(dec: *const sys::WebPAnimDecoder) -> c_int;
+        fn WebPRef_WebPAnimDecoderReset(dec: *mut sys::WebPAnimDecoder);
+        fn WebPRef_WebPAnimDecoderDelete(dec: *mut sys::WebPAnimDecoder);
+    }
+
+    // The info (canvas width and height, loop count, background color and
+    // frame count) and the frames (canvas and timestamp) of an animation.
+    type Decoded = ((u32, u32, u32, u32, u32), Vec<(Vec<u8>, i32)>);
+
+    fn digest(decoded: &Decoded) -> Digest {
+        let mut digest = Digest::new();
+        let (width, height, loop_count, bgcolor, frame_count) = decoded.0;
+        for value in &[width, height, loop_count, bgcolor, frame_count] {
+            digest.update(&value.to_le_bytes());
+        }
+        for (buf, timestamp) in &decoded.1 {
+            digest.update(buf);
+            digest.update(&timestamp.to_le_bytes());
+        }
+        digest
+    }
+
+    const COLOR_MODES: [WEBP_CSP_MODE; 4] = [MODE_RGBA, MODE_BGRA, MODE_rgbA, MODE_bgrA];
+
+    // The WebPAnimDecoder functions, either libwebp's or the Rust ones.
+    struct AnimDecoderApi {
+        options_init: unsafe extern "C" fn(*mut sys::WebPAnimDecoderOptions, c_int) -> c_int,
+        new: unsafe extern "C" fn(
+            *const sys::WebPData,
+            *const sys::WebPAnimDecoderOptions,
+            c_int,
+        ) -> *mut sys::WebPAnimDecoder,
+        get_info:
+            unsafe extern "C" fn(*const sys::WebPAnimDecoder, *mut sys::WebPAnimInfo) -> c_int,
+        get_next:
+            unsafe extern "C" fn(*mut sys::WebPAnimDecoder, *mut *mut u8, *mut c_int) -> c_int,
+        has_more_frames: unsafe extern "C" fn(*const sys::WebPAnimDecoder) -> c_int,
+        reset: unsafe extern "C" fn(*mut sys::WebPAnimDecoder),
+        delete: unsafe extern "C" fn(*mut sys::WebPAnimDecoder),
+    }
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE_API: AnimDecoderApi = AnimDecoderApi {
+        options_init: WebPRef_WebPAnimDecoderOptionsInitInternal,
+        new: WebPRef_WebPAnimDecoderNewInternal,
+        get_info: WebPRef_WebPAnimDecoderGetInfo,
+        get_next: WebPRef_WebPAnimDecoderGetNext,
+        has_more_frames: WebPRef_WebPAnimDecoderHasMoreFrames,
+        reset: WebPRef_WebPAnimDecoderReset,
+        delete: WebPRef_WebPAnimDecoderDelete,
+    };
+
+    const RUST_API: AnimDecoderApi = AnimDecoderApi {
+        options_init: WebPAnimDecoderOptionsInitInternal,
+        new: WebPAnimDecoderNewInternal,
+        get_info: WebPAnimDecoderGetInfo,
+        get_next: WebPAnimDecoderGetNext,
+        has_more_frames: WebPAnimDecoderHasMoreFrames,
+        reset: WebPAnimDecoderReset,
+        delete: WebPAnimDecoderDelete,
+    };
+
+    // Decodes all the frames through 'api' in the given color mode, twice
+    // (the second time after a reset), and returns the info and the frames.
+    fn api_anim_decode(api: &AnimDecoderApi, data: &[u8], color_mode: WEBP_CSP_MODE) -> Decoded {
+        let version = WEBP_DEMUX_ABI_VERSION as c_int;
+        unsafe {
+            let mut options: sys::WebPAnimDecoderOptions = mem::zeroed();
+            assert!((api.options_init)(&mut options, version) != 0);
+            options.color_mode = color_mode;
+            let webp_data = sys::WebPData {
+                bytes: data.as_ptr(),
+                size: data.len(),
+            };
+            let dec = (api.new)(&webp_data, &options, version);
+            assert!(!dec.is_null());
+            let mut info: sys::WebPAnimInfo = mem::zeroed();
+            assert!((api.get_info)(dec, &mut info) != 0);
+            let size = info.canvas_width as usize * info.canvas_height as usize * 4;
+            let mut passes = Vec::new();
+            for _ in 0..2 {
+                let mut frames = Vec::new();
+                let mut buf: *mut u8 = ptr::null_mut();
+                let mut timestamp: c_int = 0;
+                while (api.has_more_frames)(dec) != 0 {
+                    assert!((api.get_next)(dec, &mut buf, &mut timestamp) != 0);
+                    frames.push((slice::from_raw_parts(buf, size).to_vec(), timestamp as i32));
+                }
+                assert_eq!((api.get_next)(dec, &mut buf, &mut timestamp), 0);
+                passes.push(frames);
+                (api.reset)(dec);
+            }
+            (api.delete)(dec);
+            let frames = passes.pop().unwrap();
+            assert!(passes[0] == frames);
+            let info = (
+                info.canvas_width,
+                info.canvas_height,
+                info.loop_count,
+                info.bgcolor,
+                info.frame_count,
+            );
+            (info, frames)
+        }
+    }
+
+    // The digests in tests/fixtures/anim.txt come from libwebp's output.
+    #[cfg(feature = "c-reference")]
+    fn reference_anim_decode(data: &[u8], mode: WEBP_CSP_MODE) -> Decoded {
+        api_anim_decode(&REFERENCE_API, data, mode)
+    }
+
+    #[cfg(not(feature = "c-reference"))]
+    fn reference_anim_decode(_data: &[u8], _mode: WEBP_CSP_MODE) -> Decoded {
+        panic!("regenerating the fixtures needs libwebp, build with the c-reference feature")
+    }
+
+    // Decodes all the frames with libwebp's WebPAnimDecoder.
+    #[cfg(feature = "c-reference")]
+    fn c_anim_decode(data: &[u8]) -> Vec<(Vec<u8>, i32)> {
+        api_anim_decode(&REFERENCE_API, data, MODE_RGBA).1
+    }
+
+    // A moving, partially transparent square over a gradient, so that the
+    // encoder produces sub-frames with various dispose and blend methods.
+    fn canvas(width: u32, height: u32, t: u32) -> Vec<u8> {
+        let mut buf = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let in_square = x >= t * 3 && x < t * 3 + 10 && y >= t * 2 && y < t * 2 + 12;
+                let in_hole = (20..28).contains(&x) && (4..10).contains(&y) && t % 3 != 0;
+                if in_hole {
+                    buf.extend_from_slice(&[0, 0, 0, 0]);
+                } else if in_square {
+                    buf.extend_from_slice(&[
+                        (t * 40) as u8,
+                        200,
+                        (x * 7) as u8,
+                        (100 + t * 20) as u8,
+                    ]);
+                } else {
+                    buf.extend_from_slice(&[(x * 8) as u8, (y * 8) as u8, 128, 255]);
+                }
+            }
+        }
+        buf
+    }
+
+    fn encode_animation(options: &AnimEncoderOptions, configs: &[EncoderConfig]) -> Vec<u8> {
+        let (width, height) = (40, 30);
+        let mut enc = AnimEncoder::new(width, height, options).unwrap();
+        for t in 0..8 {
+            let config = &configs[t as usize % configs.len()];
+            enc.add_rgba(&canvas(width, height, t), width * 4, t as i32 * 70, config)
+                .unwrap();
+        }
+        enc.assemble(8 * 70).unwrap().to_vec()
+    }
+
+    // Animations encoded with various options, so that they use every dispose
+    // and blend method, as (path, contents) pairs.
+    fn generate_animations() -> Vec<(String, Vec<u8>)> {
+        let lossy = EncoderConfig::default();
+        let lossless = EncoderConfig::lossless_preset(6).unwrap();
+        let default_options = AnimEncoderOptions::default();
+        let animations = vec![
+            ("lossless", encode_animation(&default_options, &[lossless])),
+            ("lossy", encode_animation(&default_options, &[lossy])),
+            (
+                "lossy-lossless",
+                encode_animation(&default_options, &[lossy, lossless]),
+            ),
+            (
+                "allow-mixed",
+                encode_animation(
+                    &AnimEncoderOptions {
+                        allow_mixed: true,
+                        ..default_options
+                    },
+                    &[lossy],
+                ),
+            ),
+            (
+                "minimize-size",
+                encode_animation(
+                    &AnimEncoderOptions {
+                        minimize_size: true,
+                        ..default_options
+                    },
+                    &[lossless],
+                ),
+            ),
+            (
+                "kmin-kmax",
+                encode_animation(
+                    &AnimEncoderOptions {
+                        kmin: 2,
+                        kmax: 3,
+                        ..default_options
+                    },
+                    &[lossless, lossy],
+                ),
+            ),
+        ];
+        animations
+            .into_iter()
+            .map(|(name, data)| (format!("tests/fixtures/anim/{}.webp", name), data))
+            .collect()
+    }
+
+    // The checked-in animations, with the digests of the libwebp output in
+    // each of COLOR_MODES.
+    fn test_animations() -> Vec<Fixture> {
+        fixtures("anim")
+    }
+
+    #[test]
+    fn test_anim_fixtures() {
+        let header = "\
+# Animations generated by anim_decode::tests::generate_animations(): path,
+# then the digests of the libwebp output in MODE_RGBA, MODE_BGRA, MODE_rgbA
+# and MODE_bgrA.
+# Regenerate with WEBP_UPDATE_FIXTURES=1 cargo test --features c-reference fixtures
+";
+        check_fixtures("anim", header, &generate_animations(), |files| {
+            files
+                .iter()
+                .map(|(_, data)| {
+                    COLOR_MODES
+                        .iter()
+                        .map(|&mode| digest(&reference_anim_decode(data, mode)))
+                        .collect()
+                })
+                .collect()
+        });
+    }
+
+    // Decodes all the frames with AnimDecoder.
+    fn anim_decode(data: &[u8]) -> Decoded {
+        let mut dec = AnimDecoder::new(data).unwrap();
+        let info = dec.info();
+        let mut frames = Vec::new();
+        while let Some((buf, timestamp)) = dec.next_frame() {
+            frames.push((buf.to_vec(), timestamp));
+        }
+        assert!(!dec.has_more_frames());
+        assert_eq!(frames.len(), info.frame_count as usize);
+        let info = (
+            info.canvas_width,
+            info.canvas_height,
+            info.loop_count,
+            info.bgcolor,
+            info.frame_count,
+        );
+        (info, frames)
+    }
+
+    #[test]
+    fn test_anim_decode_matches_libwebp() {
+        let (mut num_dispose_bg, mut num_no_blend, mut num_non_key) = (0, 0, 0);
+        for fixture in test_animations() {
+            let dec = AnimDecoder::new(&fixture.data).unwrap();
+            for frame in &dec.demuxer().frames {
+                if frame.dispose_method == WEBP_MUX_DISPOSE_BACKGROUND {
+                    num_dispose_bg += 1;
+                }
+                if frame.blend_method == WEBP_MUX_NO_BLEND {
+                    num_no_blend += 1;
+                }
+                if !dec.is_key_frame(frame.frame_num) {
+                    num_non_key += 1;
+                }
+            }
+            assert_eq!(
+                (dec.info().canvas_width, dec.info().canvas_height),
+                (40, 30)
+            );
+            let decoded = anim_decode(&fixture.data);
+            #[cfg(feature = "c-reference")]
+            {
+                let expected = c_anim_decode(&fixture.data);
+                assert_eq!(decoded.1.len(), expected.len());
+                for (frame, expected) in decoded.1.iter().zip(&expected) {
+                    assert_eq!(frame.1, expected.1);
+                    assert!(frame.0 == expected.0);
+                }
+            }
+            assert_eq!(digest(&decoded), fixture.digests[0], "{}", fixture.path);
+        }
+        // Make sure the corpus exercises every composition path.
+        assert!(num_dispose_bg > 0);
+        assert!(num_no_blend > 0);
+        assert!(num_non_key > 0);
+    }
+
+    // An animation repeating the frame of test.webp, each frame lasting
+    // 'duration' milliseconds.
+    fn repeated_frame_animation(num_frames: usize, duration: u32) -> Vec<u8> {
+        use format_constants::*;
+
+        let data = include_bytes!("../libwebp-1.0.0/examples/test.webp");
+        let image = Demuxer::new(data).unwrap().frames[0].payload;
+        let mut body = Vec::new();
+        body.extend_from_slice(b"ANIM");
+        body.extend_from_slice(&(ANIM_CHUNK_SIZE as u32).to_le_bytes());
+        body.extend_from_slice(&[0; ANIM_CHUNK_SIZE]);
+        for _ in 0..num_frames {
+            body.extend_from_slice(b"ANMF");
+            body.extend_from_slice(&((ANMF_CHUNK_SIZE + image.len()) as u32).to_le_bytes());
+            body.extend_from_slice(&[0; 6]);
+            body.extend_from_slice(&127u32.to_le_bytes()[..3]);
+            body.extend_from_slice(&127u32.to_le_bytes()[..3]);
+            body.extend_from_slice(&duration.to_le_bytes()[..3]);
+            body.push(0);
+            body.extend_from_slice(image);
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        let riff_size = 4 + CHUNK_HEADER_SIZE + VP8X_CHUNK_SIZE + body.len();
+        out.extend_from_slice(&(riff_size as u32).to_le_bytes());
+        out.extend_from_slice(b"WEBPVP8X");
+        out.extend_from_slice(&(VP8X_CHUNK_SIZE as u32).to_le_bytes());
+        out.extend_from_slice(&ANIMATION_FLAG.to_le_bytes());
+        out.extend_from_slice(&127u32.to_le_bytes()[..3]);
+        out.extend_from_slice(&127u32.to_le_bytes()[..3]);
+        out.extend_from_slice(&body);
+        out
+    }
+
+    #[test]
+    fn test_anim_timestamp_wraps() {
+        // The timestamps overflow 32 bits after 128 frames of the maximum
+        // duration, and wrap around as in libwebp.
+        let duration = MAX_DURATION - 1;
+        let data = repeated_frame_animation(130, duration);
+        let mut dec = AnimDecoder::new(&data).unwrap();
+        assert_eq!(dec.info().frame_count, 130);
+        let expected = |frame_num: u32| duration.wrapping_mul(frame_num) as i32;
+        let (_, timestamp) = dec.render_frame(129).unwrap();
+        assert_eq!(timestamp, expected(129));
+        assert!(timestamp < 0);
+        let (_, timestamp) = dec.next_frame().unwrap();
+        assert_eq!(timestamp, expected(130));
+    }
+
+    #[test]
+    fn test_anim_render_frame() {
+        for fixture in test_animations() {
+            let data = fixture.data;
+            let expected = anim_decode(&data).1;
+            let mut dec = AnimDecoder::new(&data).unwrap();
+            assert!(dec.is_key_frame(1));
+            for frame_num in (1..expected.len() as u32 + 1).rev() {
+                let (buf, timestamp) = dec.render_frame(frame_num).unwrap();
+                let (ref expected_buf, expected_timestamp) = expected[frame_num as usize - 1];
+                assert_eq!(timestamp, expected_timestamp);
+                assert!(buf == &expected_buf[..]);
+            }
+            assert!(dec.render_frame(0).is_none());
+            assert!(dec.render_frame(expected.len() as u32 + 1).is_none());
+
+            // Sequential decoding resumes after the rendered frame.
+            dec.render_frame(1).unwrap();
+            if expected.len() > 1 {
+                let (buf, _) = dec.next_frame().unwrap();
+                assert!(buf == &expected[1].0[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_anim_decoder_api_matches_libwebp() {
+        for fixture in test_animations() {
+            let decoded: Vec<Decoded> = COLOR_MODES
+                .iter()
+                .map(|&mode| api_anim_decode(&RUST_API, &fixture.data, mode))
+                .collect();
+            assert!(decoded[0] == anim_decode(&fixture.data));
+            assert!(decoded[0].1 != decoded[2].1);
+            for (i, (&mode, actual)) in COLOR_MODES.iter().zip(&decoded).enumerate() {
+                #[cfg(feature = "c-reference")]
+                {
+                    let expected = api_anim_decode(&REFERENCE_API, &fixture.data, mode);
+                    assert_eq!(actual.0, expected.0);
+                    assert_eq!(actual.1.len(), expected.1.len());
+                    for (actual, expected) in actual.1.iter().zip(&expected.1) {
+                        assert_eq!(actual.1, expected.1);
+                        assert!(actual.0 == expected.0);
+                    }
+                }
+                assert_eq!(
+                    digest(actual),
+                    fixture.digests[i],
+                    "{}, mode {}",
+                    fixture.path,
+                    mode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_anim_decoder_api_invalid_params() {
+        let data = test_animations().swap_remove(0).data;
+        let apis = [
+            &RUST_API,
+            #[cfg(feature = "c-reference")]
+            &REFERENCE_API,
+        ];
+        for api in &apis {
+            unsafe {
+                let version = WEBP_DEMUX_ABI_VERSION as c_int;
+                let mut options: sys::WebPAnimDecoderOptions = mem::zeroed();
+                assert_eq!((api.options_init)(ptr::null_mut(), version), 0);
+                assert_eq!((api.options_init)(&mut options, version + 0x100), 0);
+                assert!((api.options_init)(&mut options, version) != 0);
+                assert_eq!((options.color_mode, options.use_threads), (MODE_RGBA, 0));
+
+                let webp_data = sys::WebPData {
+                    bytes: data.as_ptr(),
+                    size: data.len(),
+                };
+                let empty = sys::WebPData {
+                    bytes: ptr::null(),
+                    size: 0,
+                };
+                assert!((api.new)(ptr::null(), &options, version).is_null());
+                assert!((api.new)(&empty, &options, version).is_null());
+                assert!((api.new)(&webp_data, &options, version + 0x100).is_null());
+                options.color_mode = 0; // MODE_RGB
+                assert!((api.new)(&webp_data, &options, version).is_null());
+
+                // Default options.
+                let dec = (api.new)(&webp_data, ptr::null(), version);
+                assert!(!dec.is_null());
+                let mut info: sys::WebPAnimInfo = mem::zeroed();
+                assert_eq!((api.get_info)(dec, ptr::null_mut()), 0);
+                assert_eq!((api.get_info)(ptr::null(), &mut info), 0);
+                let mut buf: *mut u8 = ptr::null_mut();
+                let mut timestamp: c_int = 0;
+                assert_eq!((api.get_next)(dec, ptr::null_mut(), &mut timestamp), 0);
+                assert_eq!((api.get_next)(dec, &mut buf, ptr::null_mut()), 0);
+                assert_eq!((api.get_next)(ptr::null_mut(), &mut buf, &mut timestamp), 0);
+                assert!((api.get_next)(dec, &mut buf, &mut timestamp) != 0);
+                assert_eq!((api.has_more_frames)(ptr::null()), 0);
+                (api.reset)(ptr::null_mut());
+                (api.delete)(dec);
+                (api.delete)(ptr::null_mut());
+            }
+        }
+    }
+}
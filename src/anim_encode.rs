@@ -0,0 +1,257 @@
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::*;
+use std::ptr::{self, NonNull};
+
+use encode::{EncoderConfig, WEBP_ENCODER_ABI_VERSION};
+use sys;
+use WebpBox;
+
+pub const WEBP_MUX_ABI_VERSION: u16 = 0x0108;
+
+/// Global options of an `AnimEncoder`, mirroring `WebPAnimEncoderOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimEncoderOptions {
+    // Background color of the canvas, in MSB order: [31..24] alpha,
+    // [23..16] red, [15..8] green, [7..0] blue.
+    pub bgcolor: u32,
+    // Number of times to repeat the animation [0 = infinite].
+    pub loop_count: i32,
+    // If true, minimize the output size (slow). Implicitly disables key-frame
+    // insertion.
+    pub minimize_size: bool,
+    // Minimum and maximum distance between consecutive key frames in the
+    // output. kmax <= 0 disables key-frame insertion, and kmax == 1 makes all
+    // frames key-frames.
+    pub kmin: i32,
+    pub kmax: i32,
+    // If true, each frame may be encoded either lossy or lossless, whichever
+    // is smaller.
+    pub allow_mixed: bool,
+}
+
+impl AnimEncoderOptions {
+    fn to_raw(self) -> sys::WebPAnimEncoderOptions {
+        let mut raw: sys::WebPAnimEncoderOptions = unsafe { mem::zeroed() };
+        let ok = unsafe {
+            sys::WebPAnimEncoderOptionsInitInternal(&mut raw, WEBP_MUX_ABI_VERSION as c_int)
+        };
+        assert!(ok != 0);
+        raw.anim_params.bgcolor = self.bgcolor;
+        raw.anim_params.loop_count = self.loop_count as c_int;
+        raw.minimize_size = self.minimize_size as c_int;
+        raw.kmin = self.kmin as c_int;
+        raw.kmax = self.kmax as c_int;
+        raw.allow_mixed = self.allow_mixed as c_int;
+        raw
+    }
+}
+
+impl Default for AnimEncoderOptions {
+    // Same as WebPAnimEncoderOptionsInit().
+    fn default() -> Self {
+        let mut raw: sys::WebPAnimEncoderOptions = unsafe { mem::zeroed() };
+        let ok = unsafe {
+            sys::WebPAnimEncoderOptionsInitInternal(&mut raw, WEBP_MUX_ABI_VERSION as c_int)
+        };
+        assert!(ok != 0);
+        Self {
+            bgcolor: raw.anim_params.bgcolor,
+            loop_count: raw.anim_params.loop_count,
+            minimize_size: raw.minimize_size != 0,
+            kmin: raw.kmin,
+            kmax: raw.kmax,
+            allow_mixed: raw.allow_mixed != 0,
+        }
+    }
+}
+
+/// Animated WebP encoder (`WebPAnimEncoder`).
+///
+/// Frames are added as full-canvas RGBA images together with their timestamp;
+/// the encoder takes care of sub-frame rectangles, blending and disposal.
+pub struct AnimEncoder {
+    ptr: NonNull<sys::WebPAnimEncoder>,
+    width: u32,
+    height: u32,
+}
+
+impl AnimEncoder {
+    pub fn new(width: u32, height: u32, options: &AnimEncoderOptions) -> Option<Self> {
+        assert_eq!(width as c_int as u32, width);
+        assert_eq!(height as c_int as u32, height);
+
+        let raw_options = options.to_raw();
+        let ptr = unsafe {
+            sys::WebPAnimEncoderNewInternal(
+                width as c_int,
+                height as c_int,
+                &raw_options,
+                WEBP_MUX_ABI_VERSION as c_int,
+            )
+        };
+        NonNull::new(ptr).map(|ptr| Self { ptr, width, height })
+    }
+
+    /// Encodes an RGBA frame of the canvas size, shown from `timestamp_ms`
+    /// until the timestamp of the next frame. Timestamps must be
+    /// non-decreasing.
+    pub fn add_rgba(
+        &mut self,
+        buf: &[u8],
+        stride: u32,
+        timestamp_ms: i32,
+        config: &EncoderConfig,
+    ) -> Option<()> {
+        let rgba_len = (stride as usize).checked_mul(self.height as usize).unwrap();
+        assert_eq!(rgba_len, buf.len());
+        assert!(self.width <= stride / 4);
+        assert_eq!(stride as c_int as u32, stride);
+
+        let mut picture: sys::WebPPicture = unsafe { mem::zeroed() };
+        if unsafe { sys::WebPPictureInitInternal(&mut picture, WEBP_ENCODER_ABI_VERSION as c_int) }
+            == 0
+        {
+            return None;
+        }
+        picture.use_argb = 1;
+        picture.width = self.width as c_int;
+        picture.height = self.height as c_int;
+        let raw_config = config.to_raw();
+        let result = unsafe {
+            sys::WebPPictureImportRGBA(&mut picture, buf.as_ptr(), stride as c_int) != 0
+                && sys::WebPAnimEncoderAdd(
+                    self.ptr.as_ptr(),
+                    &mut picture,
+                    timestamp_ms as c_int,
+                    &raw_config,
+                ) != 0
+        };
+        unsafe {
+            sys::WebPPictureFree(&mut picture);
+        }
+        if result {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    /// Finishes the animation, giving the last frame a duration up to
+    /// `end_timestamp_ms`, and returns the assembled WebP file.
+    pub fn assemble(self, end_timestamp_ms: i32) -> Option<WebpBox<[u8]>> {
+        let mut data = sys::WebPData {
+            bytes: ptr::null(),
+            size: 0,
+        };
+        let result = unsafe {
+            sys::WebPAnimEncoderAdd(
+                self.ptr.as_ptr(),
+                ptr::null_mut(),
+                end_timestamp_ms as c_int,
+                ptr::null(),
+            ) != 0
+                && sys::WebPAnimEncoderAssemble(self.ptr.as_ptr(), &mut data) != 0
+        };
+        if result && !data.bytes.is_null() {
+            Some(unsafe { WebpBox::from_raw_parts(data.bytes as *mut u8, data.size) })
+        } else {
+            None
+        }
+    }
+
+    /// Error message of the last failed call, or an empty string.
+    pub fn error_message(&self) -> String {
+        let msg = unsafe { sys::WebPAnimEncoderGetError(self.ptr.as_ptr()) };
+        if msg.is_null() {
+            String::new()
+        } else {
+            unsafe { CStr::from_ptr(msg) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
+impl Drop for AnimEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            sys::WebPAnimEncoderDelete(self.ptr.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, color: [u8; 4]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if (x / 4 + y / 4) % 2 == 0 {
+                    buf.extend_from_slice(&color);
+                } else {
+                    buf.extend_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+        }
+        buf
+    }
+
+    fn find_chunk<'a>(data: &'a [u8], tag: &[u8]) -> Option<&'a [u8]> {
+        data.windows(4)
+            .position(|w| w == tag)
+            .map(|pos| &data[pos + 8..])
+    }
+
+    #[test]
+    fn test_anim_encoder_options() {
+        let options = AnimEncoderOptions::default();
+        assert_eq!(options.bgcolor, 0xffffffff);
+        assert_eq!(options.loop_count, 0);
+        assert!(!options.allow_mixed);
+    }
+
+    #[test]
+    fn test_anim_encode() {
+        let options = AnimEncoderOptions {
+            loop_count: 3,
+            bgcolor: 0xff336699,
+            allow_mixed: true,
+            kmin: 2,
+            kmax: 3,
+            ..AnimEncoderOptions::default()
+        };
+        let mut enc = AnimEncoder::new(16, 16, &options).unwrap();
+        let lossy = EncoderConfig::default();
+        let lossless = EncoderConfig::lossless_preset(6).unwrap();
+        enc.add_rgba(&frame(16, 16, [255, 0, 0, 255]), 64, 0, &lossy)
+            .unwrap();
+        enc.add_rgba(&frame(16, 16, [0, 255, 0, 255]), 64, 100, &lossless)
+            .unwrap();
+        enc.add_rgba(&frame(16, 16, [0, 0, 255, 128]), 64, 250, &lossy)
+            .unwrap();
+        let data = enc.assemble(400).unwrap();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[8..12], b"WEBP");
+        let anim = find_chunk(&data, b"ANIM").unwrap();
+        assert_eq!(&anim[0..4], &[0x99, 0x66, 0x33, 0xff]);
+        assert_eq!(&anim[4..6], &[3, 0]);
+        let num_frames = data.windows(4).filter(|w| w == b"ANMF").count();
+        assert_eq!(num_frames, 3);
+    }
+
+    #[test]
+    fn test_anim_encode_bad_timestamp() {
+        let mut enc = AnimEncoder::new(8, 8, &AnimEncoderOptions::default()).unwrap();
+        let config = EncoderConfig::default();
+        enc.add_rgba(&frame(8, 8, [255, 0, 0, 255]), 32, 100, &config)
+            .unwrap();
+        assert!(enc
+            .add_rgba(&frame(8, 8, [255, 0, 0, 255]), 32, 50, &config)
+            .is_none());
+        assert!(!enc.error_message().is_empty());
+    }
+}
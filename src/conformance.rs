@@ -0,0 +1,724 @@
+// Differential conformance tests between the Rust ports and the original C
+// implementation of libwebp.
+//
+// build.rs compiles the C sources of every ported file a second time, with
+// their exported symbols renamed to WebPRef_*. The tests here run both
+// versions on generated inputs and on a corpus of images encoded with many
+// encoder configurations, and report the first byte where they disagree.
+//
+// The corpus is checked in under tests/fixtures/, together with digests of
+// the libwebp output, so that the ports are checked against them when the C
+// reference isn't built (that is, without the c-reference feature). The
+// parity tests of the individual functions fall back the same way on
+// tests/fixtures/parity.txt.
+
+use std::mem;
+use std::os::raw::*;
+use std::slice;
+
+use dec::alpha_dec::{VP8DecompressAlphaRows, WebPDeallocateAlphaMemory};
+use dec::frame_dec::VP8InitDithering;
+use dec::quant_dec::VP8ParseQuant;
+use dec::vp8_dec::{VP8Delete, VP8Io, VP8New};
+use dec::vp8i_dec::VP8Decoder;
+use dec::webp_dec::{WebPDecode, WebPGetFeaturesInternal, WebPGetInfo};
+use decode::{
+    VP8StatusCode, MODE_LAST, MODE_RGBA, MODE_YUV, WEBP_CSP_MODE, WEBP_DECODER_ABI_VERSION,
+};
+use encode::{encode_rgba_with_config, EncoderConfig};
+use sys::{WebPBitstreamFeatures, WebPDecBuffer, WebPDecoderConfig, WebPDecoderOptions};
+#[cfg(feature = "c-reference")]
+use test_util::assert_same_state;
+use test_util::bytes_per_pixel;
+use test_util::{check_fixtures, check_parity};
+use test_util::{chunk, new_io};
+use test_util::{fixtures, Digest, Fixture, Lcg};
+use utils::bit_reader_utils::VP8InitBitReader;
+use utils::random_utils::{VP8InitRandom, VP8Random};
+use WebpBox;
+
+#[cfg(feature = "c-reference")]
+#[allow(improper_ctypes)]
+extern "C" {
+    fn WebPRef_VP8New() -> *mut VP8Decoder;
+    fn WebPRef_VP8Delete(dec: *mut VP8Decoder);
+    fn WebPRef_VP8InitDithering(options: *const WebPDecoderOptions, dec: *mut VP8Decoder);
+    fn WebPRef_VP8DecompressAlphaRows(
+        dec: *mut VP8Decoder,
+        io: *const VP8Io,
+        row: c_int,
+        num_rows: c_int,
+    ) -> *const u8;
+    fn WebPRef_WebPDeallocateAlphaMemory(dec: *mut VP8Decoder);
+    fn WebPRef_VP8ParseQuant(dec: *mut VP8Decoder);
+    fn WebPRef_VP8InitRandom(rg: *mut VP8Random, dithering: c_float);
+    fn WebPRef_WebPGetInfo(
+        data: *const u8,
+        data_size: usize,
+        width: *mut c_int,
+        height: *mut c_int,
+    ) -> c_int;
+    fn WebPRef_WebPGetFeaturesInternal(
+        data: *const u8,
+        data_size: usize,
+        features: *mut WebPBitstreamFeatures,
+        version: c_int,
+    ) -> VP8StatusCode;
+    fn WebPRef_WebPInitDecoderConfigInternal(
+        config: *mut WebPDecoderConfig,
+        version: c_int,
+    ) -> c_int;
+    fn WebPRef_WebPDecode(
+        data: *const u8,
+        data_size: usize,
+        config: *mut WebPDecoderConfig,
+    ) -> VP8StatusCode;
+    fn WebPRef_WebPFreeDecBuffer(buffer: *mut WebPDecBuffer);
+}
+
+// Index of the first byte where 'a' and 'b' differ (which is the length of the
+// shorter one if it is a prefix of the other), or None if they are equal.
+pub(crate) fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    match a.iter().zip(b).position(|(x, y)| x != y) {
+        Some(pos) => Some(pos),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
+
+// Like assert_eq!, but points at the first differing byte instead of dumping
+// both buffers.
+#[cfg(feature = "c-reference")]
+pub(crate) fn assert_bit_exact(what: &str, port: &[u8], reference: &[u8]) {
+    if let Some(pos) = first_difference(port, reference) {
+        let byte = |data: &[u8]| match data.get(pos) {
+            Some(b) => format!("{:#04x}", b),
+            None => "end of data".to_string(),
+        };
+        panic!(
+            "{}: first difference at byte {} of {}/{} (port: {}, reference: {})",
+            what,
+            pos,
+            port.len(),
+            reference.len(),
+            byte(port),
+            byte(reference)
+        );
+    }
+}
+
+// The raw bytes of a #[repr(C)] struct made of integers only.
+#[cfg(feature = "c-reference")]
+unsafe fn raw_bytes<T>(value: &T) -> &[u8] {
+    slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>())
+}
+
+//------------------------------------------------------------------------------
+// Corpus
+
+// Smooth gradients with noisy patches and an alpha channel of the given kind
+// (0: opaque, 1: binary, 2: gradient).
+fn image(width: usize, height: usize, alpha: u32, seed: u32) -> Vec<u8> {
+    let mut rng = Lcg(seed);
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let noisy = (x / 8 + y / 8) % 3 == 0;
+            let noise = |rng: &mut Lcg| if noisy { (rng.next() % 64) as usize } else { 0 };
+            rgba.push((x * 255 / width + noise(&mut rng)) as u8);
+            rgba.push((y * 255 / height + noise(&mut rng)) as u8);
+            rgba.push(((x + y) * 127 / (width + height) + noise(&mut rng)) as u8);
+            rgba.push(match alpha {
+                0 => 255,
+                1 => (((x / 5 + y / 3) % 2) * 255) as u8,
+                _ => ((x * 7 + y * 3) % 256) as u8,
+            });
+        }
+    }
+    rgba
+}
+
+fn configs() -> Vec<(String, EncoderConfig)> {
+    let mut configs = Vec::new();
+    {
+        let mut add = |name: String, base: EncoderConfig, set: &dyn Fn(&mut EncoderConfig)| {
+            let mut config = base;
+            set(&mut config);
+            configs.push((name, config));
+        };
+        let lossy = EncoderConfig::default;
+        for &quality in &[0.0, 30.0, 75.0, 100.0] {
+            add(format!("quality {}", quality), lossy(), &|c| {
+                c.quality = quality
+            });
+        }
+        for method in 0..=6 {
+            add(format!("method {}", method), lossy(), &|c| {
+                c.method = method
+            });
+        }
+        add("one segment".into(), lossy(), &|c| c.segments = 1);
+        add("no sns".into(), lossy(), &|c| c.sns_strength = 0);
+        add("simple filter".into(), lossy(), &|c| {
+            c.filter_type = 0;
+            c.filter_strength = 60;
+        });
+        add("sharp filter".into(), lossy(), &|c| {
+            c.filter_strength = 100;
+            c.filter_sharpness = 7;
+        });
+        add("no filter".into(), lossy(), &|c| c.filter_strength = 0);
+        add("partitions".into(), lossy(), &|c| c.partitions = 3);
+        add("preprocessing".into(), lossy(), &|c| c.preprocessing = 4);
+        add("sharp yuv".into(), lossy(), &|c| c.use_sharp_yuv = true);
+        add("raw alpha".into(), lossy(), &|c| c.alpha_compression = 0);
+        add("quantized alpha".into(), lossy(), &|c| c.alpha_quality = 20);
+        for filtering in 0..=2 {
+            add(format!("alpha filtering {}", filtering), lossy(), &|c| {
+                c.alpha_filtering = filtering
+            });
+        }
+        let lossless = || EncoderConfig::lossless_preset(6).unwrap();
+        for &level in &[0, 5, 9] {
+            let preset = EncoderConfig::lossless_preset(level).unwrap();
+            add(format!("lossless level {}", level), preset, &|_| ());
+        }
+        add("near lossless".into(), lossless(), &|c| {
+            c.near_lossless = 40
+        });
+        add("exact".into(), lossless(), &|c| c.exact = true);
+    }
+    configs
+}
+
+// WebP files produced by the encoder with many configurations, plus the
+// sample files shipped with the crate, as (path, contents) pairs.
+fn generate_corpus() -> Vec<(String, Vec<u8>)> {
+    let mut corpus = vec![
+        (
+            "libwebp-1.0.0/examples/test.webp".to_string(),
+            include_bytes!("../libwebp-1.0.0/examples/test.webp").to_vec(),
+        ),
+        (
+            "examples/rust-logo-256x256.webp".to_string(),
+            include_bytes!("../examples/rust-logo-256x256.webp").to_vec(),
+        ),
+    ];
+    let sizes = [(1, 1), (13, 7), (67, 45)];
+    for (name, config) in configs() {
+        for (i, &(width, height)) in sizes.iter().enumerate() {
+            let alpha = i as u32 % 3;
+            let rgba = image(width, height, alpha, i as u32);
+            let webp: WebpBox<[u8]> = encode_rgba_with_config(
+                &rgba,
+                width as u32,
+                height as u32,
+                width as u32 * 4,
+                &config,
+            )
+            .unwrap();
+            corpus.push((
+                format!(
+                    "tests/fixtures/corpus/{}-{}x{}.webp",
+                    name.replace(' ', "-"),
+                    width,
+                    height
+                ),
+                webp.to_vec(),
+            ));
+        }
+    }
+    corpus
+}
+
+// The checked-in corpus, with the digests of the libwebp output of
+// test_decode_corpus and test_decode_mutated_corpus for each file.
+fn corpus() -> Vec<Fixture> {
+    fixtures("corpus")
+}
+
+//------------------------------------------------------------------------------
+// Whole-file decoding
+
+// The pixels of a decoded buffer, without the stride padding.
+unsafe fn pixels(buffer: &WebPDecBuffer) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut plane = |data: *const u8, stride: c_int, width: usize, height: usize| {
+        for y in 0..height {
+            let row = data.offset(y as isize * stride as isize);
+            out.extend_from_slice(slice::from_raw_parts(row, width));
+        }
+    };
+    let width = buffer.width as usize;
+    let height = buffer.height as usize;
+    if buffer.colorspace < MODE_YUV {
+        let rgba = &buffer.u.RGBA;
+        plane(
+            rgba.rgba,
+            rgba.stride,
+            width * bytes_per_pixel(buffer.colorspace),
+            height,
+        );
+    } else {
+        let yuva = &buffer.u.YUVA;
+        let (uv_width, uv_height) = (width.div_ceil(2), height.div_ceil(2));
+        plane(yuva.y, yuva.y_stride, width, height);
+        plane(yuva.u, yuva.u_stride, uv_width, uv_height);
+        plane(yuva.v, yuva.v_stride, uv_width, uv_height);
+        if !yuva.a.is_null() {
+            plane(yuva.a, yuva.a_stride, width, height);
+        }
+    }
+    out
+}
+
+#[derive(Clone, Copy, Default)]
+struct Options {
+    colorspace: WEBP_CSP_MODE,
+    bypass_filtering: bool,
+    no_fancy_upsampling: bool,
+    crop: Option<(c_int, c_int, c_int, c_int)>, // left, top, width, height
+    scale: Option<(c_int, c_int)>,
+    flip: bool,
+    use_threads: bool,
+    dithering: c_int,
+}
+
+fn variants(width: c_int, height: c_int) -> Vec<Options> {
+    let base = Options {
+        colorspace: MODE_RGBA,
+        ..Options::default()
+    };
+    let mut variants: Vec<Options> = (0..MODE_LAST)
+        .map(|colorspace| Options { colorspace, ..base })
+        .collect();
+    variants.extend(vec![
+        Options {
+            bypass_filtering: true,
+            ..base
+        },
+        Options {
+            no_fancy_upsampling: true,
+            ..base
+        },
+        Options {
+            crop: Some((1, 1, (width - 1).max(1), (height - 1).max(1))),
+            ..base
+        },
+        Options {
+            scale: Some((width / 2 + 1, height * 2)),
+            ..base
+        },
+        Options {
+            crop: Some((
+                width / 3,
+                height / 4,
+                (width / 2).max(1),
+                (height / 2).max(1),
+            )),
+            scale: Some((width + 5, (height / 3).max(1))),
+            ..base
+        },
+        Options { flip: true, ..base },
+        Options {
+            use_threads: true,
+            ..base
+        },
+        Options {
+            dithering: 100,
+            ..base
+        },
+    ]);
+    variants
+}
+
+// The whole-file decoding functions, either libwebp's or the Rust ones.
+struct DecodeApi {
+    init_config: unsafe extern "C" fn(*mut WebPDecoderConfig, c_int) -> c_int,
+    decode: unsafe extern "C" fn(*const u8, usize, *mut WebPDecoderConfig) -> VP8StatusCode,
+    free: unsafe extern "C" fn(*mut WebPDecBuffer),
+}
+
+const PORT: DecodeApi = DecodeApi {
+    init_config: ::dec::webp_dec::WebPInitDecoderConfigInternal,
+    decode: WebPDecode,
+    free: ::dec::buffer_dec::WebPFreeDecBuffer,
+};
+
+#[cfg(feature = "c-reference")]
+const REFERENCE: DecodeApi = DecodeApi {
+    init_config: WebPRef_WebPInitDecoderConfigInternal,
+    decode: WebPRef_WebPDecode,
+    free: WebPRef_WebPFreeDecBuffer,
+};
+
+// Status and pixels of WebPDecode() with the given options.
+fn decode(data: &[u8], options: &Options, api: &DecodeApi) -> (VP8StatusCode, Vec<u8>) {
+    unsafe {
+        let mut config: WebPDecoderConfig = mem::zeroed();
+        let version = WEBP_DECODER_ABI_VERSION as c_int;
+        assert_eq!((api.init_config)(&mut config, version), 1);
+        config.output.colorspace = options.colorspace;
+        let raw = &mut config.options;
+        raw.bypass_filtering = options.bypass_filtering as c_int;
+        raw.no_fancy_upsampling = options.no_fancy_upsampling as c_int;
+        if let Some((left, top, width, height)) = options.crop {
+            raw.use_cropping = 1;
+            raw.crop_left = left;
+            raw.crop_top = top;
+            raw.crop_width = width;
+            raw.crop_height = height;
+        }
+        if let Some((width, height)) = options.scale {
+            raw.use_scaling = 1;
+            raw.scaled_width = width;
+            raw.scaled_height = height;
+        }
+        raw.flip = options.flip as c_int;
+        raw.use_threads = options.use_threads as c_int;
+        raw.dithering_strength = options.dithering;
+        raw.alpha_dithering_strength = options.dithering;
+        let status = (api.decode)(data.as_ptr(), data.len(), &mut config);
+        let pixels = if status == VP8StatusCode::VP8_STATUS_OK {
+            pixels(&config.output)
+        } else {
+            Vec::new()
+        };
+        (api.free)(&mut config.output);
+        (status, pixels)
+    }
+}
+
+// Decodes with the port, checking that libwebp produces the same status and
+// pixels, and adds them to 'digest'. 'what' labels the comparisons.
+#[cfg_attr(not(feature = "c-reference"), allow(unused_variables))]
+fn check_decode(what: &str, data: &[u8], options: &Options, digest: &mut Digest) -> VP8StatusCode {
+    let (status, port) = decode(data, options, &PORT);
+    #[cfg(feature = "c-reference")]
+    {
+        let (ref_status, reference) = decode(data, options, &REFERENCE);
+        assert_eq!(status, ref_status, "{}: status", what);
+        assert_bit_exact(what, &port, &reference);
+    }
+    digest.update(&[status as u8]);
+    digest.update(&port);
+    status
+}
+
+// Width and height from WebPGetInfo(), checking that both versions agree.
+// The result of WebPGetInfo() and WebPGetFeatures() is added to 'digest'.
+#[cfg_attr(not(feature = "c-reference"), allow(unused_variables))]
+fn check_info(what: &str, data: &[u8], digest: &mut Digest) -> Option<(c_int, c_int)> {
+    unsafe {
+        let (mut width, mut height) = (0, 0);
+        let ok = WebPGetInfo(data.as_ptr(), data.len(), &mut width, &mut height);
+        let mut features: WebPBitstreamFeatures = mem::zeroed();
+        let version = WEBP_DECODER_ABI_VERSION as c_int;
+        let status = WebPGetFeaturesInternal(data.as_ptr(), data.len(), &mut features, version);
+
+        #[cfg(feature = "c-reference")]
+        {
+            let (mut ref_width, mut ref_height) = (0, 0);
+            let ref_ok =
+                WebPRef_WebPGetInfo(data.as_ptr(), data.len(), &mut ref_width, &mut ref_height);
+            assert_eq!(ok, ref_ok, "{}: WebPGetInfo", what);
+            assert_eq!(
+                (width, height),
+                (ref_width, ref_height),
+                "{}: WebPGetInfo",
+                what
+            );
+
+            let mut ref_features: WebPBitstreamFeatures = mem::zeroed();
+            let ref_status = WebPRef_WebPGetFeaturesInternal(
+                data.as_ptr(),
+                data.len(),
+                &mut ref_features,
+                version,
+            );
+            assert_eq!(status, ref_status, "{}: WebPGetFeatures", what);
+            assert_bit_exact(
+                &format!("{}: WebPGetFeatures", what),
+                raw_bytes(&features),
+                raw_bytes(&ref_features),
+            );
+        }
+
+        let values = [
+            ok,
+            width,
+            height,
+            status as c_int,
+            features.width,
+            features.height,
+            features.has_alpha,
+            features.has_animation,
+            features.format,
+        ];
+        for value in &values {
+            digest.update(&value.to_le_bytes());
+        }
+        if ok != 0 {
+            Some((width, height))
+        } else {
+            None
+        }
+    }
+}
+
+// Digest of the decoding of 'data' with every variant of the options.
+fn decode_digest(name: &str, data: &[u8]) -> Digest {
+    let mut digest = Digest::new();
+    let (width, height) = check_info(name, data, &mut digest).unwrap();
+    for (i, options) in variants(width, height).iter().enumerate() {
+        let what = format!("{}, variant {}", name, i);
+        let status = check_decode(&what, data, options, &mut digest);
+        // Cropping can fall outside of the tiniest images.
+        if options.crop.is_none() {
+            assert_eq!(status, VP8StatusCode::VP8_STATUS_OK, "{}", what);
+        }
+    }
+    digest
+}
+
+// Digest of the decoding of truncated and corrupted versions of 'data'.
+fn mutated_digest(name: &str, data: &[u8], rng: &mut Lcg) -> Digest {
+    let mut digest = Digest::new();
+    let options = Options {
+        colorspace: MODE_RGBA,
+        ..Options::default()
+    };
+    for _ in 0..4 {
+        let len = rng.next() as usize % data.len();
+        let what = format!("{}, truncated to {}", name, len);
+        check_info(&what, &data[..len], &mut digest);
+        check_decode(&what, &data[..len], &options, &mut digest);
+    }
+    for _ in 0..8 {
+        let mut mutated = data.to_vec();
+        // Leave the RIFF header alone most of the time, to get past it.
+        let pos = 12 + rng.next() as usize % (data.len() - 12);
+        mutated[pos] ^= 1 << (rng.next() % 8);
+        let what = format!("{}, bit flipped at {}", name, pos);
+        check_info(&what, &mutated, &mut digest);
+        check_decode(&what, &mutated, &options, &mut digest);
+    }
+    digest
+}
+
+#[test]
+fn test_decode_corpus() {
+    for fixture in corpus() {
+        let digest = decode_digest(&fixture.path, &fixture.data);
+        assert_eq!(digest, fixture.digests[0], "{}", fixture.path);
+    }
+}
+
+// Truncated and corrupted files must fail (or succeed) the same way.
+#[test]
+fn test_decode_mutated_corpus() {
+    let mut rng = Lcg(45);
+    for fixture in corpus() {
+        let digest = mutated_digest(&fixture.path, &fixture.data, &mut rng);
+        assert_eq!(digest, fixture.digests[1], "{}", fixture.path);
+    }
+}
+
+#[test]
+fn test_corpus_fixtures() {
+    let header = "\
+# The conformance corpus, generated by conformance::generate_corpus(): path,
+# then the digests of test_decode_corpus and test_decode_mutated_corpus.
+# Regenerate with WEBP_UPDATE_FIXTURES=1 cargo test --features c-reference fixtures
+";
+    check_fixtures("corpus", header, &generate_corpus(), |files| {
+        let mut rng = Lcg(45);
+        files
+            .iter()
+            .map(|(path, data)| {
+                vec![
+                    decode_digest(path, data),
+                    mutated_digest(path, data, &mut rng),
+                ]
+            })
+            .collect()
+    });
+}
+
+//------------------------------------------------------------------------------
+// Individual functions
+
+// The functions to decode the alpha plane of a VP8 frame, either libwebp's
+// or the Rust ones.
+type AlphaApi = (
+    unsafe extern "C" fn() -> *mut VP8Decoder,
+    unsafe extern "C" fn(*const WebPDecoderOptions, *mut VP8Decoder),
+    unsafe extern "C" fn(*mut VP8Decoder, *const VP8Io, c_int, c_int) -> *const u8,
+    unsafe extern "C" fn(*mut VP8Decoder),
+    unsafe extern "C" fn(*mut VP8Decoder),
+);
+
+#[test]
+fn test_decompress_alpha_rows_parity() {
+    // The reference keeps the alpha plane in the decoder, the port in its
+    // alpha decoder: each needs its own.
+    unsafe fn rows(
+        alpha: &[u8],
+        width: c_int,
+        height: c_int,
+        step: c_int,
+        dithering: c_int,
+        api: AlphaApi,
+    ) -> Vec<u8> {
+        let (new, init_dithering, decompress_rows, deallocate, delete) = api;
+        let dec = new();
+        (*dec).alpha_data_ = alpha.as_ptr();
+        (*dec).alpha_data_size_ = alpha.len();
+        let mut options: WebPDecoderOptions = mem::zeroed();
+        options.alpha_dithering_strength = dithering;
+        init_dithering(&options, dec);
+        let mut io = new_io(&[]);
+        io.width = width;
+        io.height = height;
+        io.crop_right = width;
+        io.crop_bottom = height;
+        let mut out = Vec::new();
+        let mut row = 0;
+        while row < height {
+            let num_rows = step.min(height - row);
+            let rows = decompress_rows(dec, &io, row, num_rows);
+            if rows.is_null() {
+                out.extend_from_slice(b"failed");
+                break;
+            }
+            out.extend_from_slice(slice::from_raw_parts(rows, (num_rows * width) as usize));
+            row += num_rows;
+        }
+        deallocate(dec);
+        delete(dec);
+        out
+    }
+
+    const PORT: AlphaApi = (
+        VP8New,
+        VP8InitDithering,
+        VP8DecompressAlphaRows,
+        WebPDeallocateAlphaMemory,
+        VP8Delete,
+    );
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: AlphaApi = (
+        WebPRef_VP8New,
+        WebPRef_VP8InitDithering,
+        WebPRef_VP8DecompressAlphaRows,
+        WebPRef_WebPDeallocateAlphaMemory,
+        WebPRef_VP8Delete,
+    );
+
+    let mut digest = Digest::new();
+    let mut rng = Lcg(47);
+    for fixture in corpus() {
+        let (name, data) = (fixture.path, fixture.data);
+        let alpha = match chunk(&data, b"ALPH") {
+            Some(alpha) => alpha.to_vec(),
+            None => continue,
+        };
+        let (width, height) = check_info(&name, &data, &mut Digest::new()).unwrap();
+        let mut corrupted = alpha.clone();
+        let pos = rng.next() as usize % alpha.len();
+        corrupted[pos] ^= 1 << (rng.next() % 8);
+        for (alpha, _kind) in [(alpha, "alpha"), (corrupted, "corrupted alpha")] {
+            for &step in &[1, 3, 16, height] {
+                for &dithering in &[0, 100] {
+                    let port = unsafe { rows(&alpha, width, height, step, dithering, PORT) };
+                    digest.update(&port);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let what = format!(
+                            "{}, {}, step {}, dithering {}",
+                            name, _kind, step, dithering
+                        );
+                        let reference =
+                            unsafe { rows(&alpha, width, height, step, dithering, REFERENCE) };
+                        assert_bit_exact(&what, &port, &reference);
+                    }
+                }
+            }
+        }
+    }
+    check_parity("conformance::test_decompress_alpha_rows_parity", digest);
+}
+
+#[test]
+fn test_parse_quant_parity() {
+    let mut digest = Digest::new();
+    let mut rng = Lcg(48);
+    for _i in 0..200 {
+        let data = rng.bytes(16);
+        let use_segment = rng.next() % 2;
+        let absolute_delta = rng.next() % 2;
+        let quantizer: Vec<i8> = (0..4).map(|_| (rng.next() % 256) as i8).collect();
+        let parse = |parse_quant: unsafe extern "C" fn(*mut VP8Decoder)| unsafe {
+            let dec = VP8New();
+            VP8InitBitReader(&mut (*dec).br_, data.as_ptr(), data.len());
+            let hdr = &mut (*dec).segment_hdr_;
+            hdr.use_segment_ = use_segment as c_int;
+            hdr.absolute_delta_ = absolute_delta as c_int;
+            hdr.quantizer_.copy_from_slice(&quantizer);
+            parse_quant(dec);
+            dec
+        };
+        let dec = parse(VP8ParseQuant);
+        unsafe {
+            for q in &(*dec).dqm_ {
+                digest.update_i32(&q.y1_mat_);
+                digest.update_i32(&q.y2_mat_);
+                digest.update_i32(&q.uv_mat_);
+                digest.update_i32(&[q.uv_quant_, q.dither_]);
+            }
+            #[cfg(feature = "c-reference")]
+            {
+                let ref_dec = parse(WebPRef_VP8ParseQuant);
+                assert_bit_exact(
+                    &format!("VP8ParseQuant, input {}", _i),
+                    raw_bytes(&(*dec).dqm_),
+                    raw_bytes(&(*ref_dec).dqm_),
+                );
+                assert_same_state(&(*dec).br_, &(*ref_dec).br_);
+                VP8Delete(ref_dec);
+            }
+            VP8Delete(dec);
+        }
+    }
+    check_parity("conformance::test_parse_quant_parity", digest);
+}
+
+#[test]
+fn test_init_random_parity() {
+    let mut digest = Digest::new();
+    for &dithering in &[0.0, 0.01, 0.25, 0.5, 0.99, 1.0, 1.5, -1.0] {
+        let mut port: VP8Random = unsafe { mem::zeroed() };
+        unsafe { VP8InitRandom(&mut port, dithering) };
+        digest.update_i32(&[port.index1_, port.index2_, port.amp_]);
+        digest.update_u32(&port.tab_);
+        #[cfg(feature = "c-reference")]
+        unsafe {
+            let mut reference: VP8Random = mem::zeroed();
+            WebPRef_VP8InitRandom(&mut reference, dithering);
+            assert_bit_exact(
+                &format!("VP8InitRandom({})", dithering),
+                raw_bytes(&port),
+                raw_bytes(&reference),
+            );
+        }
+    }
+    check_parity("conformance::test_init_random_parity", digest);
+}
+
+#[test]
+fn test_first_difference() {
+    assert_eq!(first_difference(b"abc", b"abc"), None);
+    assert_eq!(first_difference(b"abc", b"abd"), Some(2));
+    assert_eq!(first_difference(b"ab", b"abc"), Some(2));
+    assert_eq!(first_difference(b"", b""), None);
+}
@@ -17,21 +17,22 @@ use std::os::raw::*;
 use std::ptr;
 use std::slice;
 
-use dec::io_dec::WebPInitCustomIo;
+use dec::io_dec::InitCustomIo;
 use dec::vp8_dec::{VP8InitIo, VP8Io};
 use dec::vp8i_dec::VP8Decoder;
-use dec::vp8l_dec::{VP8LDecodeAlphaHeader, VP8LDecodeAlphaImageStream};
-use dec::vp8li_dec::{VP8LDecoder, VP8LDelete};
+use dec::vp8l_dec::{VP8LDecodeAlphaHeader, VP8LDecodeAlphaImageStream, VP8LDelete};
+use dec::vp8li_dec::VP8LDecoder;
 use dsp::dsp::{WEBP_FILTER_LAST, WEBP_FILTER_NONE, WEBP_FILTER_TYPE};
-use dsp::filters::{VP8FiltersInit, WebPUnfilters};
+use dsp::filters::{unfilter_row, VP8FiltersInit};
 use format_constants::{
     ALPHA_HEADER_LEN, ALPHA_LOSSLESS_COMPRESSION, ALPHA_NO_COMPRESSION, ALPHA_PREPROCESSED_LEVELS,
 };
-use utils::quant_levels_dec_utils::WebPDequantizeLevels;
-use utils::utils::{WebPSafeCalloc, WebPSafeFree, WebPSafeMalloc};
+use utils::quant_levels_dec_utils::DequantizeLevels;
 
+// The fields up to vp8l_dec_ keep the C layout: the incremental decoder reads
+// them to re-point the lossless bit reader when its input buffer moves.
 #[repr(C)]
-pub(crate) struct ALPHDecoder {
+pub struct ALPHDecoder {
     pub(crate) width_: c_int,
     pub(crate) height_: c_int,
     pub(crate) method_: c_int,
@@ -42,29 +43,67 @@ pub(crate) struct ALPHDecoder {
     pub(crate) use_8b_decode_: c_int, // Although alpha channel requires only 1 byte per
     // pixel, sometimes VP8LDecoder may need to allocate
     // 4 bytes per pixel internally during decode.
-    pub(crate) output_: *mut u8,
-    pub(crate) prev_line_: *const u8, // last output row (or NULL)
+    pub(crate) output_: Vec<u8>, // output. Persistent, contains the whole data.
+    pub(crate) prev_line_: Option<usize>, // last output row (or None)
+}
+
+impl ALPHDecoder {
+    // Unfilters row 'row' of the output from 'deltas', or in place if 'deltas'
+    // is None, using the last output row as predictor.
+    pub(crate) fn unfilter_row(&mut self, row: usize, deltas: Option<&[u8]>) {
+        let width = self.width_ as usize;
+        let (above, rest) = self.output_.split_at_mut(row * width);
+        let prev_line = self
+            .prev_line_
+            .map(|prev_row| &above[prev_row * width..][..width]);
+        unfilter_row(self.filter_, prev_line, deltas, &mut rest[..width]);
+        self.prev_line_ = Some(row);
+    }
+}
+
+impl Drop for ALPHDecoder {
+    fn drop(&mut self) {
+        ALPHClear(self);
+    }
 }
 
 //------------------------------------------------------------------------------
 // ALPHDecoder object.
 
-// Allocates a new alpha decoder instance.
+// Allocates a new alpha decoder instance, along with its output plane of
+// io.width x io.crop_bottom pixels.
 #[allow(non_snake_case)]
-fn ALPHNew() -> *mut ALPHDecoder {
-    let dec: *mut ALPHDecoder =
-        (unsafe { WebPSafeCalloc(1, mem::size_of::<ALPHDecoder>()) }) as *mut ALPHDecoder;
-    return dec;
+pub(crate) fn ALPHNew(io: &VP8Io) -> Option<Box<ALPHDecoder>> {
+    let alpha_size = io.width as usize * io.crop_bottom as usize;
+    let mut output = Vec::new();
+    if output.try_reserve_exact(alpha_size).is_err() {
+        return None;
+    }
+    output.resize(alpha_size, 0);
+    Some(Box::new(ALPHDecoder {
+        width_: 0,
+        height_: 0,
+        method_: 0,
+        filter_: WEBP_FILTER_NONE,
+        pre_processing_: 0,
+        vp8l_dec_: ptr::null_mut(),
+        // Plain C struct of integers and pointers, for which all-zero is valid.
+        // Set up by ALPHInit().
+        io_: unsafe { mem::zeroed() },
+        use_8b_decode_: 0,
+        output_: output,
+        prev_line_: None,
+    }))
 }
 
-// Clears and deallocates an alpha decoder instance.
+// Releases the lossless decoder, once all the rows are decoded. The output
+// plane is kept.
 #[allow(non_snake_case)]
-unsafe fn ALPHDelete(dec: *mut ALPHDecoder) {
-    if !dec.is_null() {
-        VP8LDelete((*dec).vp8l_dec_);
-        (*dec).vp8l_dec_ = ptr::null_mut();
-        WebPSafeFree(dec as *mut c_void);
-    }
+fn ALPHClear(dec: &mut ALPHDecoder) {
+    // vp8l_dec_ is either null or the decoder created for this instance by
+    // VP8LDecodeAlphaHeader(), which nothing else frees.
+    unsafe { VP8LDelete(dec.vp8l_dec_) };
+    dec.vp8l_dec_ = ptr::null_mut();
 }
 
 //------------------------------------------------------------------------------
@@ -75,34 +114,19 @@ unsafe fn ALPHDelete(dec: *mut ALPHDecoder) {
 // Returns false in case of error in alpha header (data too short, invalid
 // compression method or filter, error in lossless header data etc).
 #[allow(non_snake_case)]
-unsafe fn ALPHInit(
-    dec: *mut ALPHDecoder,
-    data: *const u8,
-    data_size: usize,
-    src_io: *const VP8Io,
-    output: *mut u8,
-) -> c_int {
-    let copy_of_dec = dec;
-    let dec: &mut ALPHDecoder = &mut *dec;
-
-    let alpha_data: *const u8 = data.offset(ALPHA_HEADER_LEN as isize);
-    let alpha_data_size: usize = data_size - ALPHA_HEADER_LEN;
-
-    c_assert!(!data.is_null() && !output.is_null() && !src_io.is_null());
-    let src_io: &VP8Io = &*src_io;
-    let data: &[u8] = slice::from_raw_parts(data, data_size);
-
-    VP8FiltersInit();
-    dec.output_ = output;
+fn ALPHInit(dec: &mut ALPHDecoder, data: &[u8], src_io: &VP8Io) -> c_int {
+    // Only fills in the filter tables, under the init lock.
+    unsafe { VP8FiltersInit() };
     dec.width_ = src_io.width;
     dec.height_ = src_io.height;
     c_assert!(dec.width_ > 0 && dec.height_ > 0);
 
-    if data_size <= ALPHA_HEADER_LEN {
+    if data.len() <= ALPHA_HEADER_LEN {
         return 0;
     }
+    let alpha_data: &[u8] = &data[ALPHA_HEADER_LEN..];
 
-    dec.method_ = ((data[0] >> 0) & 0x03) as i32;
+    dec.method_ = (data[0] & 0x03) as i32;
     dec.filter_ = WEBP_FILTER_TYPE::new(((data[0] >> 2) & 0x03) as i32);
     dec.pre_processing_ = ((data[0] >> 4) & 0x03) as i32;
     let rsrv: i32 = ((data[0] >> 6) & 0x03) as i32;
@@ -117,10 +141,11 @@ unsafe fn ALPHInit(
 
     // Copy the necessary parameters from src_io to io
     {
+        let opaque = dec as *mut ALPHDecoder as *mut c_void;
         let io: &mut VP8Io = &mut dec.io_;
         VP8InitIo(io);
-        WebPInitCustomIo(ptr::null_mut(), io);
-        io.opaque = copy_of_dec as *mut c_void;
+        InitCustomIo(ptr::null_mut(), io);
+        io.opaque = opaque;
         io.width = src_io.width;
         io.height = src_io.height;
 
@@ -132,16 +157,16 @@ unsafe fn ALPHInit(
         // No need to copy the scaling parameters.
     }
 
-    let ok: c_int;
     if dec.method_ == ALPHA_NO_COMPRESSION {
         let alpha_decoded_size = dec.width_ as usize * dec.height_ as usize;
-        ok = (alpha_data_size >= alpha_decoded_size) as c_int;
+        (alpha_data.len() >= alpha_decoded_size) as c_int
     } else {
         c_assert!(dec.method_ == ALPHA_LOSSLESS_COMPRESSION);
-        ok = VP8LDecodeAlphaHeader(dec, alpha_data, alpha_data_size);
+        // 'alpha_data' points into the input being decoded, which outlives the
+        // lossless decoder (the incremental decoder re-points its bit reader
+        // whenever it moves its buffer).
+        unsafe { VP8LDecodeAlphaHeader(dec, alpha_data) }
     }
-
-    return ok;
 }
 
 // Decodes, unfilters and dequantizes *at least* 'num_rows' rows of alpha
@@ -149,39 +174,23 @@ unsafe fn ALPHInit(
 // already been decoded.
 // Returns false in case of bitstream error.
 #[allow(non_snake_case)]
-unsafe fn ALPHDecode(dec: *mut VP8Decoder, row: c_int, num_rows: c_int) -> c_int {
-    let dec: &mut VP8Decoder = &mut *dec;
-
-    let alph_dec: &mut ALPHDecoder = &mut *dec.alph_dec_;
-    let width: c_int = alph_dec.width_;
+fn ALPHDecode(dec: &mut VP8Decoder, data: &[u8], row: c_int, num_rows: c_int) -> c_int {
+    let alph_dec: &mut ALPHDecoder = match dec.alph_dec_ {
+        Some(ref mut alph_dec) => alph_dec,
+        None => return 0,
+    };
+    let width = alph_dec.width_ as usize;
     let height: c_int = alph_dec.io_.crop_bottom;
     if alph_dec.method_ == ALPHA_NO_COMPRESSION {
-        let mut prev_line: *const u8 = dec.alpha_prev_line_;
-        let mut deltas: *const u8 = dec.alpha_data_
-            .offset(ALPHA_HEADER_LEN as isize + row as isize * width as isize);
-        let mut dst: *mut u8 = dec.alpha_plane_.offset(row as isize * width as isize);
-        c_assert!(deltas <= dec.alpha_data_.offset(dec.alpha_data_size_ as isize));
-        if alph_dec.filter_ != WEBP_FILTER_NONE {
-            c_assert!(WebPUnfilters[alph_dec.filter_ as usize].is_some());
-            for _ in 0..num_rows {
-                WebPUnfilters[alph_dec.filter_ as usize].unwrap()(prev_line, deltas, dst, width);
-                prev_line = dst;
-                dst = dst.offset(width as isize);
-                deltas = deltas.offset(width as isize);
-            }
-        } else {
-            for _ in 0..num_rows {
-                ptr::copy_nonoverlapping(deltas, dst, width as usize);
-                prev_line = dst;
-                dst = dst.offset(width as isize);
-                deltas = deltas.offset(width as isize);
-            }
+        let deltas: &[u8] = &data[ALPHA_HEADER_LEN..];
+        for y in row as usize..(row + num_rows) as usize {
+            alph_dec.unfilter_row(y, Some(&deltas[y * width..]));
         }
-        dec.alpha_prev_line_ = prev_line;
     } else {
         // alph_dec->method_ == ALPHA_LOSSLESS_COMPRESSION
         c_assert!(!alph_dec.vp8l_dec_.is_null());
-        if !(VP8LDecodeAlphaImageStream(alph_dec, row + num_rows) != 0) {
+        // The lossless decoder was set up by ALPHInit() on the same input.
+        if unsafe { VP8LDecodeAlphaImageStream(alph_dec, row + num_rows) } == 0 {
             return 0;
         }
     }
@@ -189,36 +198,13 @@ unsafe fn ALPHDecode(dec: *mut VP8Decoder, row: c_int, num_rows: c_int) -> c_int
     if row + num_rows >= height {
         dec.is_alpha_decoded_ = 1;
     }
-    return 1;
-}
-
-unsafe fn AllocateAlphaPlane(dec: *mut VP8Decoder, io: *const VP8Io) -> c_int {
-    let dec: &mut VP8Decoder = &mut *dec;
-    let io: &VP8Io = &*io;
-
-    let stride: c_int = io.width;
-    let height: c_int = io.crop_bottom;
-    let alpha_size = stride as u64 * height as u64;
-    c_assert!(dec.alpha_plane_mem_.is_null());
-    dec.alpha_plane_mem_ = WebPSafeMalloc(alpha_size, mem::size_of::<u8>()) as *mut u8;
-    if dec.alpha_plane_mem_.is_null() {
-        return 0;
-    }
-    dec.alpha_plane_ = dec.alpha_plane_mem_;
-    dec.alpha_prev_line_ = ptr::null_mut();
-    return 1;
+    1
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn WebPDeallocateAlphaMemory(dec: *mut VP8Decoder) {
     c_assert!(!dec.is_null());
-    let dec: &mut VP8Decoder = &mut *dec;
-
-    WebPSafeFree(dec.alpha_plane_mem_ as *mut c_void);
-    dec.alpha_plane_mem_ = ptr::null_mut();
-    dec.alpha_plane_ = ptr::null_mut();
-    ALPHDelete(dec.alph_dec_);
-    dec.alph_dec_ = ptr::null_mut();
+    (*dec).alph_dec_ = None;
 }
 
 //------------------------------------------------------------------------------
@@ -229,78 +215,88 @@ pub unsafe extern "C" fn VP8DecompressAlphaRows(
     dec: *mut VP8Decoder,
     io: *const VP8Io,
     row: c_int,
-    mut num_rows: c_int,
+    num_rows: c_int,
 ) -> *const u8 {
     c_assert!(!dec.is_null() && !io.is_null());
     let dec: &mut VP8Decoder = &mut *dec;
     let io: &VP8Io = &*io;
-
-    let width: c_int = io.width;
-    let height: c_int = io.crop_bottom;
-
-    if row < 0 || num_rows <= 0 || row + num_rows > height {
+    if row < 0 || num_rows <= 0 || row + num_rows > io.crop_bottom {
         return ptr::null(); // sanity check.
     }
-
-    if !(dec.is_alpha_decoded_ != 0) {
-        if dec.alph_dec_.is_null() {
-            // Initialize decoder.
-            dec.alph_dec_ = ALPHNew();
-            if dec.alph_dec_.is_null() {
-                return ptr::null();
-            }
-            if !(AllocateAlphaPlane(dec, io) != 0) {
-                WebPDeallocateAlphaMemory(dec);
-                return ptr::null();
-            }
-            if !(ALPHInit(
-                dec.alph_dec_,
-                dec.alpha_data_,
-                dec.alpha_data_size_,
-                io,
-                dec.alpha_plane_,
-            ) != 0)
-            {
-                WebPDeallocateAlphaMemory(dec);
-                return ptr::null();
-            }
-            // if we allowed use of alpha dithering, check whether it's needed at all
-            if (*dec.alph_dec_).pre_processing_ != ALPHA_PREPROCESSED_LEVELS {
-                dec.alpha_dithering_ = 0; // disable dithering
-            } else {
-                num_rows = height - row; // decode everything in one pass
-            }
-        }
-
-        c_assert!(!dec.alph_dec_.is_null());
-        c_assert!(row + num_rows <= height);
-        if !(ALPHDecode(dec, row, num_rows) != 0) {
+    if dec.is_alpha_decoded_ == 0 {
+        c_assert!(!dec.alpha_data_.is_null());
+        let data: &[u8] = slice::from_raw_parts(dec.alpha_data_, dec.alpha_data_size_);
+        if DecompressAlphaRows(dec, data, io, row, num_rows) == 0 {
             WebPDeallocateAlphaMemory(dec);
             return ptr::null();
         }
+    }
 
-        if dec.is_alpha_decoded_ != 0 {
-            // finished?
-            ALPHDelete(dec.alph_dec_);
-            dec.alph_dec_ = ptr::null_mut();
-            if dec.alpha_dithering_ > 0 {
-                let alpha: *mut u8 = dec.alpha_plane_
-                    .offset(io.crop_top as isize * width as isize + io.crop_left as isize);
-                if !(WebPDequantizeLevels(
-                    alpha,
-                    io.crop_right - io.crop_left,
-                    io.crop_bottom - io.crop_top,
-                    width,
-                    dec.alpha_dithering_,
-                ) != 0)
-                {
-                    WebPDeallocateAlphaMemory(dec);
-                    return ptr::null();
-                }
-            }
+    // Return a pointer to the current decoded row.
+    match dec.alph_dec_ {
+        Some(ref alph_dec) => alph_dec.output_[row as usize * io.width as usize..].as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+// Decodes the alpha rows up to row + num_rows (or all of them), from the
+// compressed alpha data 'data'. Returns false in case of error.
+#[allow(non_snake_case)]
+fn DecompressAlphaRows(
+    dec: &mut VP8Decoder,
+    data: &[u8],
+    io: &VP8Io,
+    row: c_int,
+    mut num_rows: c_int,
+) -> c_int {
+    let width = io.width as usize;
+    let height: c_int = io.crop_bottom;
+
+    if dec.alph_dec_.is_none() {
+        // Initialize decoder.
+        let mut alph_dec = match ALPHNew(io) {
+            Some(alph_dec) => alph_dec,
+            None => return 0,
+        };
+        if ALPHInit(&mut alph_dec, data, io) == 0 {
+            return 0;
+        }
+        // if we allowed use of alpha dithering, check whether it's needed at all
+        if alph_dec.pre_processing_ != ALPHA_PREPROCESSED_LEVELS {
+            dec.alpha_dithering_ = 0; // disable dithering
+        } else {
+            num_rows = height - row; // decode everything in one pass
         }
+        dec.alph_dec_ = Some(alph_dec);
     }
 
-    // Return a pointer to the current decoded row.
-    return dec.alpha_plane_.offset(row as isize * width as isize);
+    c_assert!(row + num_rows <= height);
+    if ALPHDecode(dec, data, row, num_rows) == 0 {
+        return 0;
+    }
+
+    if dec.is_alpha_decoded_ != 0 {
+        // finished?
+        let alph_dec: &mut ALPHDecoder = match dec.alph_dec_ {
+            Some(ref mut alph_dec) => alph_dec,
+            None => return 0,
+        };
+        ALPHClear(alph_dec);
+        if dec.alpha_dithering_ > 0 {
+            let start = io.crop_top as usize * width + io.crop_left as usize;
+            let end = (io.crop_bottom as usize - 1) * width + io.crop_right as usize;
+            let alpha: &mut [u8] = &mut alph_dec.output_[start..end];
+            let ok = DequantizeLevels(
+                alpha,
+                io.crop_right - io.crop_left,
+                io.crop_bottom - io.crop_top,
+                width as c_int,
+                dec.alpha_dithering_,
+            );
+            if ok == 0 {
+                return 0;
+            }
+        }
+    }
+    1
 }
@@ -0,0 +1,625 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Everything about WebPDecBuffer
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+use std::ptr;
+
+use dec::vp8_dec::WEBP_ABI_IS_INCOMPATIBLE;
+use decode::VP8StatusCode::{self, *};
+use decode::{
+    WebPIsAlphaMode, WebPIsPremultipliedMode, WebPIsRGBMode, MODE_LAST, MODE_RGB, MODE_YUVA,
+    WEBP_CSP_MODE, WEBP_DECODER_ABI_VERSION,
+};
+use sys::{WebPBitstreamFeatures, WebPDecBuffer, WebPDecoderOptions};
+use utils::rescaler_utils::WebPRescalerGetScaledDimensions;
+use utils::utils::{WebPCopyPlane, WebPSafeFree, WebPSafeMalloc};
+
+//------------------------------------------------------------------------------
+// WebPDecBuffer
+
+// Number of bytes per pixel for the different color-spaces.
+#[allow(non_upper_case_globals)]
+#[rustfmt::skip]
+const kModeBpp: [u8; MODE_LAST as usize] = [
+    3, 4, 3, 4, 4, 2, 2,
+    4, 4, 4, 2, // pre-multiplied modes
+    1, 1,
+];
+
+// Check that webp_csp_mode is within the bounds of WEBP_CSP_MODE.
+#[allow(non_snake_case)]
+fn IsValidColorspace(webp_csp_mode: WEBP_CSP_MODE) -> bool {
+    (MODE_RGB..MODE_LAST).contains(&webp_csp_mode)
+}
+
+// strictly speaking, the very last (or first, if flipped) row
+// doesn't require padding.
+#[allow(non_snake_case)]
+fn MIN_BUFFER_SIZE(width: c_int, height: c_int, stride: c_int) -> u64 {
+    (stride as u64)
+        .wrapping_mul((height - 1) as u64)
+        .wrapping_add(width as u64)
+}
+
+#[allow(non_snake_case)]
+unsafe fn CheckDecBuffer(buffer: &WebPDecBuffer) -> VP8StatusCode {
+    let mut ok = true;
+    let mode = buffer.colorspace;
+    let width = buffer.width;
+    let height = buffer.height;
+    if !IsValidColorspace(mode) {
+        ok = false;
+    } else if WebPIsRGBMode(mode) == 0 {
+        // YUV checks
+        let buf = &buffer.u.YUVA;
+        let uv_width = (width + 1) / 2;
+        let uv_height = (height + 1) / 2;
+        let y_stride = buf.y_stride.abs();
+        let u_stride = buf.u_stride.abs();
+        let v_stride = buf.v_stride.abs();
+        let a_stride = buf.a_stride.abs();
+        let y_size = MIN_BUFFER_SIZE(width, height, y_stride);
+        let u_size = MIN_BUFFER_SIZE(uv_width, uv_height, u_stride);
+        let v_size = MIN_BUFFER_SIZE(uv_width, uv_height, v_stride);
+        let a_size = MIN_BUFFER_SIZE(width, height, a_stride);
+        ok &= y_size <= buf.y_size as u64;
+        ok &= u_size <= buf.u_size as u64;
+        ok &= v_size <= buf.v_size as u64;
+        ok &= y_stride >= width;
+        ok &= u_stride >= uv_width;
+        ok &= v_stride >= uv_width;
+        ok &= !buf.y.is_null();
+        ok &= !buf.u.is_null();
+        ok &= !buf.v.is_null();
+        if mode == MODE_YUVA {
+            ok &= a_stride >= width;
+            ok &= a_size <= buf.a_size as u64;
+            ok &= !buf.a.is_null();
+        }
+    } else {
+        // RGB checks
+        let buf = &buffer.u.RGBA;
+        let stride = buf.stride.abs();
+        let size = MIN_BUFFER_SIZE(width, height, stride);
+        ok &= size <= buf.size as u64;
+        ok &= stride >= width * kModeBpp[mode as usize] as c_int;
+        ok &= !buf.rgba.is_null();
+    }
+    if ok {
+        VP8_STATUS_OK
+    } else {
+        VP8_STATUS_INVALID_PARAM
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn AllocateBuffer(buffer: &mut WebPDecBuffer) -> VP8StatusCode {
+    let w = buffer.width;
+    let h = buffer.height;
+    let mode = buffer.colorspace;
+
+    if w <= 0 || h <= 0 || !IsValidColorspace(mode) {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+
+    if buffer.is_external_memory <= 0 && buffer.private_memory.is_null() {
+        let mut uv_stride = 0;
+        let mut a_stride = 0;
+        let mut uv_size: u64 = 0;
+        let mut a_size: u64 = 0;
+        // We need memory and it hasn't been allocated yet.
+        // => initialize output buffer, now that dimensions are known.
+        if w as u64 * kModeBpp[mode as usize] as u64 >= (1 << 32) {
+            return VP8_STATUS_INVALID_PARAM;
+        }
+        let stride = w * kModeBpp[mode as usize] as c_int;
+        let size = stride as u64 * h as u64;
+        if WebPIsRGBMode(mode) == 0 {
+            uv_stride = (w + 1) / 2;
+            uv_size = uv_stride as u64 * ((h + 1) / 2) as u64;
+            if mode == MODE_YUVA {
+                a_stride = w;
+                a_size = a_stride as u64 * h as u64;
+            }
+        }
+        let total_size = size + 2 * uv_size + a_size;
+
+        // Security/sanity checks
+        let output = WebPSafeMalloc(total_size, 1) as *mut u8;
+        if output.is_null() {
+            return VP8_STATUS_OUT_OF_MEMORY;
+        }
+        buffer.private_memory = output;
+
+        if WebPIsRGBMode(mode) == 0 {
+            // YUVA initialization
+            let buf = &mut buffer.u.YUVA;
+            buf.y = output;
+            buf.y_stride = stride;
+            buf.y_size = size as usize;
+            buf.u = output.offset(size as isize);
+            buf.u_stride = uv_stride;
+            buf.u_size = uv_size as usize;
+            buf.v = output.offset((size + uv_size) as isize);
+            buf.v_stride = uv_stride;
+            buf.v_size = uv_size as usize;
+            if mode == MODE_YUVA {
+                buf.a = output.offset((size + 2 * uv_size) as isize);
+            }
+            buf.a_size = a_size as usize;
+            buf.a_stride = a_stride;
+        } else {
+            // RGBA initialization
+            let buf = &mut buffer.u.RGBA;
+            buf.rgba = output;
+            buf.stride = stride;
+            buf.size = size as usize;
+        }
+    }
+    CheckDecBuffer(buffer)
+}
+
+// Flip buffer vertically by negating the various strides.
+#[no_mangle]
+pub unsafe extern "C" fn WebPFlipBuffer(buffer: *mut WebPDecBuffer) -> VP8StatusCode {
+    if buffer.is_null() {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    let buffer = &mut *buffer;
+    if WebPIsRGBMode(buffer.colorspace) != 0 {
+        let buf = &mut buffer.u.RGBA;
+        buf.rgba = buf
+            .rgba
+            .offset((buffer.height - 1) as isize * buf.stride as isize);
+        buf.stride = -buf.stride;
+    } else {
+        let buf = &mut buffer.u.YUVA;
+        let h = buffer.height;
+        buf.y = buf.y.offset((h - 1) as isize * buf.y_stride as isize);
+        buf.y_stride = -buf.y_stride;
+        buf.u = buf
+            .u
+            .offset(((h - 1) >> 1) as isize * buf.u_stride as isize);
+        buf.u_stride = -buf.u_stride;
+        buf.v = buf
+            .v
+            .offset(((h - 1) >> 1) as isize * buf.v_stride as isize);
+        buf.v_stride = -buf.v_stride;
+        if !buf.a.is_null() {
+            buf.a = buf.a.offset((h - 1) as isize * buf.a_stride as isize);
+            buf.a_stride = -buf.a_stride;
+        }
+    }
+    VP8_STATUS_OK
+}
+
+// Prepare 'buffer' with the requested initial dimensions width/height.
+// If no external storage is supplied, initializes buffer by allocating output
+// memory and setting up the stride information. Validate cropping and rescaling
+// parameters. Returns an error code in case of problem (no memory, or invalid
+// stride / size / dimension / etc.). If *options is not NULL, also verify that
+// the options' parameters are valid and apply them to the width/height
+// dimensions of the output buffer. This takes cropping / scaling / rotation
+// into account. Also incorporates the options->flip flag to flip the buffer
+// parameters if needed.
+#[no_mangle]
+pub unsafe extern "C" fn WebPAllocateDecBuffer(
+    mut width: c_int,
+    mut height: c_int,
+    options: *const WebPDecoderOptions,
+    buffer: *mut WebPDecBuffer,
+) -> VP8StatusCode {
+    if buffer.is_null() || width <= 0 || height <= 0 {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    if let Some(options) = options.as_ref() {
+        // First, apply options if there is any.
+        if options.use_cropping != 0 {
+            let cw = options.crop_width;
+            let ch = options.crop_height;
+            let x = options.crop_left & !1;
+            let y = options.crop_top & !1;
+            if x < 0 || y < 0 || cw <= 0 || ch <= 0 || x + cw > width || y + ch > height {
+                return VP8_STATUS_INVALID_PARAM; // out of frame boundary.
+            }
+            width = cw;
+            height = ch;
+        }
+
+        if options.use_scaling != 0 {
+            let mut scaled_width = options.scaled_width;
+            let mut scaled_height = options.scaled_height;
+            if WebPRescalerGetScaledDimensions(width, height, &mut scaled_width, &mut scaled_height)
+                == 0
+            {
+                return VP8_STATUS_INVALID_PARAM;
+            }
+            width = scaled_width;
+            height = scaled_height;
+        }
+    }
+    (*buffer).width = width;
+    (*buffer).height = height;
+
+    // Then, allocate buffer for real.
+    let mut status = AllocateBuffer(&mut *buffer);
+    if status != VP8_STATUS_OK {
+        return status;
+    }
+
+    // Use the stride trick if vertical flip is needed.
+    if !options.is_null() && (*options).flip != 0 {
+        status = WebPFlipBuffer(buffer);
+    }
+    status
+}
+
+//------------------------------------------------------------------------------
+// constructors / destructors
+
+// Internal, version-checked, entry point
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitDecBufferInternal(
+    buffer: *mut WebPDecBuffer,
+    version: c_int,
+) -> c_int {
+    if WEBP_ABI_IS_INCOMPATIBLE(version, WEBP_DECODER_ABI_VERSION as c_int) {
+        return 0; // version mismatch
+    }
+    if buffer.is_null() {
+        return 0;
+    }
+    ptr::write_bytes(buffer, 0, 1);
+    1
+}
+
+// Free any memory associated with the buffer. Must always be called last.
+// Note: doesn't free the 'buffer' structure itself.
+#[no_mangle]
+pub unsafe extern "C" fn WebPFreeDecBuffer(buffer: *mut WebPDecBuffer) {
+    if let Some(buffer) = buffer.as_mut() {
+        if buffer.is_external_memory <= 0 {
+            WebPSafeFree(buffer.private_memory as *mut c_void);
+        }
+        buffer.private_memory = ptr::null_mut();
+    }
+}
+
+// Copy 'src' into 'dst' buffer, making sure 'dst' is not marked as owner of the
+// memory (still held by 'src'). No pixels are copied.
+#[no_mangle]
+pub unsafe extern "C" fn WebPCopyDecBuffer(src: *const WebPDecBuffer, dst: *mut WebPDecBuffer) {
+    if !src.is_null() && !dst.is_null() {
+        ptr::copy(src, dst, 1);
+        if !(*src).private_memory.is_null() {
+            (*dst).is_external_memory = 1; // dst buffer doesn't own the memory.
+            (*dst).private_memory = ptr::null_mut();
+        }
+    }
+}
+
+// Copy and transfer ownership from src to dst (beware of parameter order!)
+#[no_mangle]
+pub unsafe extern "C" fn WebPGrabDecBuffer(src: *mut WebPDecBuffer, dst: *mut WebPDecBuffer) {
+    if !src.is_null() && !dst.is_null() {
+        ptr::copy(src, dst, 1);
+        if !(*src).private_memory.is_null() {
+            (*src).is_external_memory = 1; // src relinquishes ownership
+            (*src).private_memory = ptr::null_mut();
+        }
+    }
+}
+
+// Copy pixels from 'src' into a *preallocated* 'dst' buffer. Returns
+// VP8_STATUS_INVALID_PARAM if the 'dst' is not set up correctly for the copy.
+#[no_mangle]
+pub unsafe extern "C" fn WebPCopyDecBufferPixels(
+    src_buf: *const WebPDecBuffer,
+    dst_buf: *mut WebPDecBuffer,
+) -> VP8StatusCode {
+    c_assert!(!src_buf.is_null() && !dst_buf.is_null());
+    let src_buf = &*src_buf;
+    let dst_buf = &mut *dst_buf;
+    c_assert!(src_buf.colorspace == dst_buf.colorspace);
+
+    dst_buf.width = src_buf.width;
+    dst_buf.height = src_buf.height;
+    if CheckDecBuffer(dst_buf) != VP8_STATUS_OK {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    if WebPIsRGBMode(src_buf.colorspace) != 0 {
+        let src = &src_buf.u.RGBA;
+        let dst = &dst_buf.u.RGBA;
+        WebPCopyPlane(
+            src.rgba,
+            src.stride,
+            dst.rgba,
+            dst.stride,
+            src_buf.width * kModeBpp[src_buf.colorspace as usize] as c_int,
+            src_buf.height,
+        );
+    } else {
+        let src = &src_buf.u.YUVA;
+        let dst = &dst_buf.u.YUVA;
+        let uv_width = (src_buf.width + 1) / 2;
+        let uv_height = (src_buf.height + 1) / 2;
+        WebPCopyPlane(
+            src.y,
+            src.y_stride,
+            dst.y,
+            dst.y_stride,
+            src_buf.width,
+            src_buf.height,
+        );
+        WebPCopyPlane(
+            src.u,
+            src.u_stride,
+            dst.u,
+            dst.u_stride,
+            uv_width,
+            uv_height,
+        );
+        WebPCopyPlane(
+            src.v,
+            src.v_stride,
+            dst.v,
+            dst.v_stride,
+            uv_width,
+            uv_height,
+        );
+        if WebPIsAlphaMode(src_buf.colorspace) != 0 {
+            WebPCopyPlane(
+                src.a,
+                src.a_stride,
+                dst.a,
+                dst.a_stride,
+                src_buf.width,
+                src_buf.height,
+            );
+        }
+    }
+    VP8_STATUS_OK
+}
+
+// Returns true if decoding will be slow with the current configuration
+// and bitstream features.
+#[no_mangle]
+pub unsafe extern "C" fn WebPAvoidSlowMemory(
+    output: *const WebPDecBuffer,
+    features: *const WebPBitstreamFeatures,
+) -> c_int {
+    c_assert!(!output.is_null());
+    ((*output).is_external_memory >= 2
+        && WebPIsPremultipliedMode((*output).colorspace) != 0
+        && (!features.is_null() && (*features).has_alpha != 0)) as c_int
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem;
+    use std::slice;
+
+    use decode::{MODE_rgbA, MODE_RGBA_4444, MODE_RGB_565, MODE_YUV};
+    use test_util::{check_parity, Digest};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_WebPAllocateDecBuffer(
+            width: c_int,
+            height: c_int,
+            options: *const WebPDecoderOptions,
+            buffer: *mut WebPDecBuffer,
+        ) -> VP8StatusCode;
+        fn WebPRef_WebPFreeDecBuffer(buffer: *mut WebPDecBuffer);
+        fn WebPRef_WebPCopyDecBufferPixels(
+            src_buf: *const WebPDecBuffer,
+            dst_buf: *mut WebPDecBuffer,
+        ) -> VP8StatusCode;
+    }
+
+    fn options(
+        crop: Option<(c_int, c_int, c_int, c_int)>,
+        scale: Option<(c_int, c_int)>,
+        flip: bool,
+    ) -> WebPDecoderOptions {
+        let mut options: WebPDecoderOptions = unsafe { mem::zeroed() };
+        if let Some((left, top, width, height)) = crop {
+            options.use_cropping = 1;
+            options.crop_left = left;
+            options.crop_top = top;
+            options.crop_width = width;
+            options.crop_height = height;
+        }
+        if let Some((width, height)) = scale {
+            options.use_scaling = 1;
+            options.scaled_width = width;
+            options.scaled_height = height;
+        }
+        options.flip = flip as c_int;
+        options
+    }
+
+    fn new_buffer(colorspace: WEBP_CSP_MODE) -> WebPDecBuffer {
+        let mut buffer: WebPDecBuffer = unsafe { mem::zeroed() };
+        buffer.colorspace = colorspace as c_int;
+        buffer
+    }
+
+    // Strides, sizes and plane offsets (relative to the allocated memory) of
+    // a buffer.
+    unsafe fn layout(buffer: &WebPDecBuffer) -> Vec<isize> {
+        let base = buffer.private_memory;
+        let mut layout = vec![buffer.width as isize, buffer.height as isize];
+        if WebPIsRGBMode(buffer.colorspace) != 0 {
+            let buf = &buffer.u.RGBA;
+            layout.extend(&[
+                buf.rgba.offset_from(base),
+                buf.stride as isize,
+                buf.size as isize,
+            ]);
+        } else {
+            let buf = &buffer.u.YUVA;
+            for &(plane, stride, size) in &[
+                (buf.y, buf.y_stride, buf.y_size),
+                (buf.u, buf.u_stride, buf.u_size),
+                (buf.v, buf.v_stride, buf.v_size),
+                (buf.a, buf.a_stride, buf.a_size),
+            ] {
+                let offset = if plane.is_null() {
+                    -1
+                } else {
+                    plane.offset_from(base)
+                };
+                layout.extend(&[offset, stride as isize, size as isize]);
+            }
+        }
+        layout
+    }
+
+    // Size of the memory allocated for a buffer.
+    unsafe fn memory_size(buffer: &WebPDecBuffer) -> usize {
+        if WebPIsRGBMode(buffer.colorspace) != 0 {
+            buffer.u.RGBA.size
+        } else {
+            let buf = &buffer.u.YUVA;
+            buf.y_size + buf.u_size + buf.v_size + buf.a_size
+        }
+    }
+
+    #[test]
+    fn test_allocate_dec_buffer_parity() {
+        let colorspaces = [
+            MODE_RGB,
+            MODE_rgbA,
+            MODE_RGBA_4444,
+            MODE_RGB_565,
+            MODE_YUV,
+            MODE_YUVA,
+        ];
+        let cases = [
+            (37, 21, options(None, None, false)),
+            (37, 21, options(None, None, true)),
+            (37, 21, options(Some((3, 5, 20, 11)), None, false)),
+            (37, 21, options(Some((3, 5, 20, 11)), Some((9, 0)), true)),
+            (37, 21, options(None, Some((64, 64)), false)),
+            (37, 21, options(Some((30, 0, 10, 10)), None, false)),
+            (37, 21, options(None, Some((0, 0)), false)),
+            (0, 21, options(None, None, false)),
+        ];
+        let mut digest = Digest::new();
+        for &colorspace in &colorspaces {
+            for &(width, height, ref options) in &cases {
+                unsafe {
+                    let mut buffer = new_buffer(colorspace);
+                    let status = WebPAllocateDecBuffer(width, height, options, &mut buffer);
+                    digest.update(&[status as u8]);
+                    if status == VP8_STATUS_OK {
+                        for value in layout(&buffer) {
+                            digest.update(&(value as i64).to_le_bytes());
+                        }
+                    }
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let mut buffer_ref = new_buffer(colorspace);
+                        let status_ref =
+                            WebPRef_WebPAllocateDecBuffer(width, height, options, &mut buffer_ref);
+                        assert_eq!(status, status_ref);
+                        if status == VP8_STATUS_OK {
+                            assert_eq!(layout(&buffer), layout(&buffer_ref));
+                        }
+                        WebPRef_WebPFreeDecBuffer(&mut buffer_ref);
+                    }
+                    WebPFreeDecBuffer(&mut buffer);
+                }
+            }
+        }
+        check_parity("dec::buffer_dec::test_allocate_dec_buffer_parity", digest);
+    }
+
+    #[test]
+    fn test_copy_dec_buffer_pixels_parity() {
+        let mut digest = Digest::new();
+        for &colorspace in &[MODE_RGB, MODE_RGBA_4444, MODE_YUV, MODE_YUVA] {
+            unsafe {
+                let mut src = new_buffer(colorspace);
+                assert_eq!(
+                    WebPAllocateDecBuffer(13, 7, ptr::null(), &mut src),
+                    VP8_STATUS_OK
+                );
+                let memory = src.private_memory;
+                for i in 0..memory_size(&src) {
+                    *memory.add(i) = (i * 7 + 3) as u8;
+                }
+
+                let mut dst = new_buffer(colorspace);
+                assert_eq!(
+                    WebPAllocateDecBuffer(13, 7, ptr::null(), &mut dst),
+                    VP8_STATUS_OK
+                );
+                let size = memory_size(&dst);
+                ptr::write_bytes(dst.private_memory, 0, size);
+                assert_eq!(WebPCopyDecBufferPixels(&src, &mut dst), VP8_STATUS_OK);
+                digest.update(slice::from_raw_parts(dst.private_memory, size));
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut dst_ref = new_buffer(colorspace);
+                    assert_eq!(
+                        WebPAllocateDecBuffer(13, 7, ptr::null(), &mut dst_ref),
+                        VP8_STATUS_OK
+                    );
+                    ptr::write_bytes(dst_ref.private_memory, 0, size);
+                    assert_eq!(
+                        WebPRef_WebPCopyDecBufferPixels(&src, &mut dst_ref),
+                        VP8_STATUS_OK
+                    );
+                    assert_eq!(
+                        slice::from_raw_parts(dst.private_memory, size),
+                        slice::from_raw_parts(dst_ref.private_memory, size)
+                    );
+                    WebPFreeDecBuffer(&mut dst_ref);
+                }
+
+                // A destination that is too small is rejected.
+                let mut small = new_buffer(colorspace);
+                assert_eq!(
+                    WebPAllocateDecBuffer(5, 7, ptr::null(), &mut small),
+                    VP8_STATUS_OK
+                );
+                assert_eq!(
+                    WebPCopyDecBufferPixels(&src, &mut small),
+                    VP8_STATUS_INVALID_PARAM
+                );
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut small_ref = new_buffer(colorspace);
+                    WebPCopyDecBuffer(&small, &mut small_ref);
+                    assert_eq!(
+                        WebPRef_WebPCopyDecBufferPixels(&src, &mut small_ref),
+                        VP8_STATUS_INVALID_PARAM
+                    );
+                }
+
+                WebPFreeDecBuffer(&mut src);
+                WebPFreeDecBuffer(&mut dst);
+                WebPFreeDecBuffer(&mut small);
+            }
+        }
+        check_parity(
+            "dec::buffer_dec::test_copy_dec_buffer_pixels_parity",
+            digest,
+        );
+    }
+}
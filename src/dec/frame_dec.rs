@@ -0,0 +1,1218 @@
+// Copyright 2010 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Frame-reconstruction function. Memory allocation.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dec::alpha_dec::VP8DecompressAlphaRows;
+use dec::common_dec::*;
+use dec::vp8_dec::{VP8InitScanline, VP8Io, VP8SetError};
+use dec::vp8i_dec::*;
+use dec::webpi_dec::WebPHeaderStructure;
+use decode::VP8StatusCode;
+use dsp::dec::*;
+use dsp::dsp::*;
+use sys::WebPDecoderOptions;
+use utils::random_utils::{VP8InitRandom, VP8Random, VP8RandomBits2, VP8_RANDOM_DITHER_FIX};
+use utils::thread_utils::{WebPGetWorkerInterface, WebPWorkerStatus};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc, WEBP_ALIGN, WEBP_ALIGN_CST};
+
+const WEBP_USE_THREAD: bool = true;
+
+//------------------------------------------------------------------------------
+// Main reconstruction function.
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+const kScan: [u16; 16] = [
+    0,                     4,                     8,                     12,
+    (4 * BPS) as u16,      (4 + 4 * BPS) as u16,  (8 + 4 * BPS) as u16,  (12 + 4 * BPS) as u16,
+    (8 * BPS) as u16,      (4 + 8 * BPS) as u16,  (8 + 8 * BPS) as u16,  (12 + 8 * BPS) as u16,
+    (12 * BPS) as u16,     (4 + 12 * BPS) as u16, (8 + 12 * BPS) as u16, (12 + 12 * BPS) as u16,
+];
+
+#[allow(non_snake_case)]
+fn CheckMode(mb_x: c_int, mb_y: c_int, mode: c_int) -> c_int {
+    if mode == B_DC_PRED {
+        if mb_x == 0 {
+            return if mb_y == 0 {
+                B_DC_PRED_NOTOPLEFT
+            } else {
+                B_DC_PRED_NOLEFT
+            };
+        } else {
+            return if mb_y == 0 {
+                B_DC_PRED_NOTOP
+            } else {
+                B_DC_PRED
+            };
+        }
+    }
+    mode
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn Copy32b(dst: *mut u8, src: *const u8) {
+    ptr::copy_nonoverlapping(src, dst, 4);
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoTransform(bits: u32, src: *const i16, dst: *mut u8) {
+    match bits >> 30 {
+        3 => VP8Transform.unwrap()(src, dst, 0),
+        2 => VP8TransformAC3.unwrap()(src, dst),
+        1 => VP8TransformDC.unwrap()(src, dst),
+        _ => {}
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn DoUVTransform(bits: u32, src: *const i16, dst: *mut u8) {
+    if bits & 0xff != 0 {
+        // any non-zero coeff at all?
+        if bits & 0xaa != 0 {
+            // any non-zero AC coefficient?
+            VP8TransformUV.unwrap()(src, dst); // note we don't use the AC3 variant for U/V
+        } else {
+            VP8TransformDCUV.unwrap()(src, dst);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ReconstructRow(dec: &VP8Decoder, ctx: &VP8ThreadContext) {
+    let mb_y = ctx.mb_y_;
+    let cache_id = ctx.id_;
+    let y_dst: *mut u8 = dec.yuv_b_.offset(Y_OFF as isize);
+    let u_dst: *mut u8 = dec.yuv_b_.offset(U_OFF as isize);
+    let v_dst: *mut u8 = dec.yuv_b_.offset(V_OFF as isize);
+
+    // Initialize left-most block.
+    for j in 0..16 {
+        *y_dst.offset((j * BPS - 1) as isize) = 129;
+    }
+    for j in 0..8 {
+        *u_dst.offset((j * BPS - 1) as isize) = 129;
+        *v_dst.offset((j * BPS - 1) as isize) = 129;
+    }
+
+    // Init top-left sample on left column too.
+    if mb_y > 0 {
+        *y_dst.offset((-1 - BPS) as isize) = 129;
+        *u_dst.offset((-1 - BPS) as isize) = 129;
+        *v_dst.offset((-1 - BPS) as isize) = 129;
+    } else {
+        // we only need to do this init once at block (0,0).
+        // Afterward, it remains valid for the whole topmost row.
+        ptr::write_bytes(y_dst.offset((-BPS - 1) as isize), 127, 16 + 4 + 1);
+        ptr::write_bytes(u_dst.offset((-BPS - 1) as isize), 127, 8 + 1);
+        ptr::write_bytes(v_dst.offset((-BPS - 1) as isize), 127, 8 + 1);
+    }
+
+    // Reconstruct one row.
+    for mb_x in 0..dec.mb_w_ {
+        let block: &VP8MBData = &*ctx.mb_data_.offset(mb_x as isize);
+
+        // Rotate in the left samples from previously decoded block. We move four
+        // pixels at a time for alignment reason, and because of in-loop filter.
+        if mb_x > 0 {
+            for j in -1..16 {
+                Copy32b(
+                    y_dst.offset((j * BPS - 4) as isize),
+                    y_dst.offset((j * BPS + 12) as isize),
+                );
+            }
+            for j in -1..8 {
+                Copy32b(
+                    u_dst.offset((j * BPS - 4) as isize),
+                    u_dst.offset((j * BPS + 4) as isize),
+                );
+                Copy32b(
+                    v_dst.offset((j * BPS - 4) as isize),
+                    v_dst.offset((j * BPS + 4) as isize),
+                );
+            }
+        }
+        {
+            // bring top samples into the cache
+            let top_yuv: *mut VP8TopSamples = dec.yuv_t_.offset(mb_x as isize);
+            let coeffs: *const i16 = block.coeffs_.as_ptr();
+            let mut bits: u32 = block.non_zero_y_;
+
+            if mb_y > 0 {
+                ptr::copy_nonoverlapping((*top_yuv).y.as_ptr(), y_dst.offset(-BPS as isize), 16);
+                ptr::copy_nonoverlapping((*top_yuv).u.as_ptr(), u_dst.offset(-BPS as isize), 8);
+                ptr::copy_nonoverlapping((*top_yuv).v.as_ptr(), v_dst.offset(-BPS as isize), 8);
+            }
+
+            // predict and add residuals
+            if block.is_i4x4_ != 0 {
+                // 4x4
+                let top_right: *mut u32 = y_dst.offset((-BPS + 16) as isize) as *mut u32;
+
+                if mb_y > 0 {
+                    if mb_x >= dec.mb_w_ - 1 {
+                        // on rightmost border
+                        ptr::write_bytes(
+                            top_right as *mut u8,
+                            (*top_yuv).y[15],
+                            mem::size_of::<u32>(),
+                        );
+                    } else {
+                        ptr::copy_nonoverlapping(
+                            (*top_yuv.offset(1)).y.as_ptr(),
+                            top_right as *mut u8,
+                            mem::size_of::<u32>(),
+                        );
+                    }
+                }
+                // replicate the top-right pixels below
+                let top_right_value = ptr::read_unaligned(top_right);
+                ptr::write_unaligned(top_right.offset(BPS as isize), top_right_value);
+                ptr::write_unaligned(top_right.offset(2 * BPS as isize), top_right_value);
+                ptr::write_unaligned(top_right.offset(3 * BPS as isize), top_right_value);
+
+                // predict and add residuals for all 4x4 blocks in turn.
+                for (n, &scan) in kScan.iter().enumerate() {
+                    let dst: *mut u8 = y_dst.offset(scan as isize);
+                    VP8PredLuma4[block.imodes_[n] as usize].unwrap()(dst);
+                    DoTransform(bits, coeffs.add(n * 16), dst);
+                    bits <<= 2;
+                }
+            } else {
+                // 16x16
+                let pred_func = CheckMode(mb_x, mb_y, block.imodes_[0] as c_int);
+                VP8PredLuma16[pred_func as usize].unwrap()(y_dst);
+                if bits != 0 {
+                    for (n, &scan) in kScan.iter().enumerate() {
+                        DoTransform(bits, coeffs.add(n * 16), y_dst.offset(scan as isize));
+                        bits <<= 2;
+                    }
+                }
+            }
+            {
+                // Chroma
+                let bits_uv: u32 = block.non_zero_uv_;
+                let pred_func = CheckMode(mb_x, mb_y, block.uvmode_ as c_int);
+                VP8PredChroma8[pred_func as usize].unwrap()(u_dst);
+                VP8PredChroma8[pred_func as usize].unwrap()(v_dst);
+                DoUVTransform(bits_uv, coeffs.add(16 * 16), u_dst);
+                DoUVTransform(bits_uv >> 8, coeffs.add(20 * 16), v_dst);
+            }
+
+            // stash away top samples for next block
+            if mb_y < dec.mb_h_ - 1 {
+                let top_yuv: &mut VP8TopSamples = &mut *top_yuv;
+                ptr::copy_nonoverlapping(
+                    y_dst.offset((15 * BPS) as isize),
+                    top_yuv.y.as_mut_ptr(),
+                    16,
+                );
+                ptr::copy_nonoverlapping(
+                    u_dst.offset((7 * BPS) as isize),
+                    top_yuv.u.as_mut_ptr(),
+                    8,
+                );
+                ptr::copy_nonoverlapping(
+                    v_dst.offset((7 * BPS) as isize),
+                    top_yuv.v.as_mut_ptr(),
+                    8,
+                );
+            }
+        }
+        // Transfer reconstructed samples from yuv_b_ cache to final destination.
+        {
+            let y_offset = cache_id * 16 * dec.cache_y_stride_;
+            let uv_offset = cache_id * 8 * dec.cache_uv_stride_;
+            let y_out: *mut u8 = dec.cache_y_.offset((mb_x * 16 + y_offset) as isize);
+            let u_out: *mut u8 = dec.cache_u_.offset((mb_x * 8 + uv_offset) as isize);
+            let v_out: *mut u8 = dec.cache_v_.offset((mb_x * 8 + uv_offset) as isize);
+            for j in 0..16 {
+                ptr::copy_nonoverlapping(
+                    y_dst.offset((j * BPS) as isize),
+                    y_out.offset((j * dec.cache_y_stride_) as isize),
+                    16,
+                );
+            }
+            for j in 0..8 {
+                ptr::copy_nonoverlapping(
+                    u_dst.offset((j * BPS) as isize),
+                    u_out.offset((j * dec.cache_uv_stride_) as isize),
+                    8,
+                );
+                ptr::copy_nonoverlapping(
+                    v_dst.offset((j * BPS) as isize),
+                    v_out.offset((j * dec.cache_uv_stride_) as isize),
+                    8,
+                );
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Filtering
+
+// kFilterExtraRows[] = How many extra lines are needed on the MB boundary
+// for caching, given a filtering level.
+// Simple filter:  up to 2 luma samples are read and 1 is written.
+// Complex filter: up to 4 luma samples are read and 3 are written. Same for
+//                 U/V, so it's 8 samples total (because of the 2x upsampling).
+#[allow(non_upper_case_globals)]
+const kFilterExtraRows: [u8; 3] = [0, 2, 8];
+
+#[allow(non_snake_case)]
+unsafe fn DoFilter(dec: &VP8Decoder, mb_x: c_int, mb_y: c_int) {
+    let ctx: &VP8ThreadContext = &dec.thread_ctx_;
+    let cache_id = ctx.id_;
+    let y_bps = dec.cache_y_stride_;
+    let f_info: &VP8FInfo = &*ctx.f_info_.offset(mb_x as isize);
+    let y_dst: *mut u8 = dec
+        .cache_y_
+        .offset((cache_id * 16 * y_bps + mb_x * 16) as isize);
+    let ilevel = f_info.f_ilevel_ as c_int;
+    let limit = f_info.f_limit_ as c_int;
+    if limit == 0 {
+        return;
+    }
+    c_assert!(limit >= 3);
+    if dec.filter_type_ == 1 {
+        // simple
+        if mb_x > 0 {
+            VP8SimpleHFilter16.unwrap()(y_dst, y_bps, limit + 4);
+        }
+        if f_info.f_inner_ != 0 {
+            VP8SimpleHFilter16i.unwrap()(y_dst, y_bps, limit);
+        }
+        if mb_y > 0 {
+            VP8SimpleVFilter16.unwrap()(y_dst, y_bps, limit + 4);
+        }
+        if f_info.f_inner_ != 0 {
+            VP8SimpleVFilter16i.unwrap()(y_dst, y_bps, limit);
+        }
+    } else {
+        // complex
+        let uv_bps = dec.cache_uv_stride_;
+        let u_dst: *mut u8 = dec
+            .cache_u_
+            .offset((cache_id * 8 * uv_bps + mb_x * 8) as isize);
+        let v_dst: *mut u8 = dec
+            .cache_v_
+            .offset((cache_id * 8 * uv_bps + mb_x * 8) as isize);
+        let hev_thresh = f_info.hev_thresh_ as c_int;
+        if mb_x > 0 {
+            VP8HFilter16.unwrap()(y_dst, y_bps, limit + 4, ilevel, hev_thresh);
+            VP8HFilter8.unwrap()(u_dst, v_dst, uv_bps, limit + 4, ilevel, hev_thresh);
+        }
+        if f_info.f_inner_ != 0 {
+            VP8HFilter16i.unwrap()(y_dst, y_bps, limit, ilevel, hev_thresh);
+            VP8HFilter8i.unwrap()(u_dst, v_dst, uv_bps, limit, ilevel, hev_thresh);
+        }
+        if mb_y > 0 {
+            VP8VFilter16.unwrap()(y_dst, y_bps, limit + 4, ilevel, hev_thresh);
+            VP8VFilter8.unwrap()(u_dst, v_dst, uv_bps, limit + 4, ilevel, hev_thresh);
+        }
+        if f_info.f_inner_ != 0 {
+            VP8VFilter16i.unwrap()(y_dst, y_bps, limit, ilevel, hev_thresh);
+            VP8VFilter8i.unwrap()(u_dst, v_dst, uv_bps, limit, ilevel, hev_thresh);
+        }
+    }
+}
+
+// Filter the decoded macroblock row (if needed)
+#[allow(non_snake_case)]
+unsafe fn FilterRow(dec: &VP8Decoder) {
+    let mb_y = dec.thread_ctx_.mb_y_;
+    c_assert!(dec.thread_ctx_.filter_row_ != 0);
+    for mb_x in dec.tl_mb_x_..dec.br_mb_x_ {
+        DoFilter(dec, mb_x, mb_y);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Precompute the filtering strength for each segment and each i4x4/i16x16 mode.
+
+#[allow(non_snake_case)]
+fn PrecomputeFilterStrengths(dec: &mut VP8Decoder) {
+    if dec.filter_type_ > 0 {
+        let hdr: &VP8FilterHeader = &dec.filter_hdr_;
+        for s in 0..NUM_MB_SEGMENTS as usize {
+            // First, compute the initial level
+            let mut base_level: c_int;
+            if dec.segment_hdr_.use_segment_ != 0 {
+                base_level = dec.segment_hdr_.filter_strength_[s] as c_int;
+                if dec.segment_hdr_.absolute_delta_ == 0 {
+                    base_level += hdr.level_;
+                }
+            } else {
+                base_level = hdr.level_;
+            }
+            for i4x4 in 0..=1 {
+                let info: &mut VP8FInfo = &mut dec.fstrengths_[s][i4x4];
+                let mut level = base_level;
+                if hdr.use_lf_delta_ != 0 {
+                    level += hdr.ref_lf_delta_[0];
+                    if i4x4 != 0 {
+                        level += hdr.mode_lf_delta_[0];
+                    }
+                }
+                level = level.clamp(0, 63);
+                if level > 0 {
+                    let mut ilevel = level;
+                    if hdr.sharpness_ > 0 {
+                        if hdr.sharpness_ > 4 {
+                            ilevel >>= 2;
+                        } else {
+                            ilevel >>= 1;
+                        }
+                        if ilevel > 9 - hdr.sharpness_ {
+                            ilevel = 9 - hdr.sharpness_;
+                        }
+                    }
+                    if ilevel < 1 {
+                        ilevel = 1;
+                    }
+                    info.f_ilevel_ = ilevel as u8;
+                    info.f_limit_ = (2 * level + ilevel) as u8;
+                    info.hev_thresh_ = if level >= 40 {
+                        2
+                    } else if level >= 15 {
+                        1
+                    } else {
+                        0
+                    };
+                } else {
+                    info.f_limit_ = 0; // no filtering
+                }
+                info.f_inner_ = i4x4 as u8;
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Dithering
+
+// minimal amp that will provide a non-zero dithering effect
+const MIN_DITHER_AMP: u8 = 4;
+
+const DITHER_AMP_TAB_SIZE: c_int = 12;
+#[allow(non_upper_case_globals)]
+const kQuantToDitherAmp: [u8; DITHER_AMP_TAB_SIZE as usize] = [
+    // roughly, it's dqm->uv_mat_[1]
+    8, 7, 6, 4, 4, 2, 2, 2, 1, 1, 1, 1,
+];
+
+// Initialize dithering post-process if needed.
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitDithering(
+    options: *const WebPDecoderOptions,
+    dec: *mut VP8Decoder,
+) {
+    c_assert!(!dec.is_null());
+    let dec: &mut VP8Decoder = &mut *dec;
+    if !options.is_null() {
+        let options: &WebPDecoderOptions = &*options;
+        let d = options.dithering_strength;
+        let max_amp = (1 << VP8_RANDOM_DITHER_FIX) - 1;
+        let f = if d < 0 {
+            0
+        } else if d > 100 {
+            max_amp
+        } else {
+            d * max_amp / 100
+        };
+        if f > 0 {
+            let mut all_amp = 0;
+            for dqm in dec.dqm_.iter_mut() {
+                if dqm.uv_quant_ < DITHER_AMP_TAB_SIZE {
+                    // TODO(skal): should we specially dither more for uv_quant_ < 0?
+                    let idx = if dqm.uv_quant_ < 0 { 0 } else { dqm.uv_quant_ };
+                    dqm.dither_ = (f * kQuantToDitherAmp[idx as usize] as c_int) >> 3;
+                }
+                all_amp |= dqm.dither_;
+            }
+            if all_amp != 0 {
+                VP8InitRandom(&mut dec.dithering_rg_, 1.0);
+                dec.dither_ = 1;
+            }
+        }
+        // potentially allow alpha dithering
+        dec.alpha_dithering_ = options.alpha_dithering_strength.clamp(0, 100);
+    }
+}
+
+// Convert to range: [-2,2] for dither=50, [-4,4] for dither=100
+#[allow(non_snake_case)]
+unsafe fn Dither8x8(rg: &mut VP8Random, dst: *mut u8, bps: c_int, amp: c_int) {
+    let mut dither = [0u8; 64];
+    for d in dither.iter_mut() {
+        *d = VP8RandomBits2(rg, VP8_DITHER_AMP_BITS + 1, amp) as u8;
+    }
+    VP8DitherCombine8x8.unwrap()(dither.as_ptr(), dst, bps);
+}
+
+#[allow(non_snake_case)]
+unsafe fn DitherRow(dec: &mut VP8Decoder) {
+    c_assert!(dec.dither_ != 0);
+    for mb_x in dec.tl_mb_x_..dec.br_mb_x_ {
+        let ctx: &VP8ThreadContext = &dec.thread_ctx_;
+        let data: &VP8MBData = &*ctx.mb_data_.offset(mb_x as isize);
+        let cache_id = ctx.id_;
+        let uv_bps = dec.cache_uv_stride_;
+        if data.dither_ >= MIN_DITHER_AMP {
+            let amp = data.dither_ as c_int;
+            let u_dst: *mut u8 = dec
+                .cache_u_
+                .offset((cache_id * 8 * uv_bps + mb_x * 8) as isize);
+            let v_dst: *mut u8 = dec
+                .cache_v_
+                .offset((cache_id * 8 * uv_bps + mb_x * 8) as isize);
+            Dither8x8(&mut dec.dithering_rg_, u_dst, uv_bps, amp);
+            Dither8x8(&mut dec.dithering_rg_, v_dst, uv_bps, amp);
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// This function is called after a row of macroblocks is finished decoding.
+// It also takes into account the following restrictions:
+//  * In case of in-loop filtering, we must hold off sending some of the bottom
+//    pixels as they are yet unfiltered. They will be when the next macroblock
+//    row is decoded. Meanwhile, we must preserve them by rotating them in the
+//    cache area. This doesn't hold for the very bottom row of the uncropped
+//    picture of course.
+//  * we must clip the remaining pixels against the cropping area. The VP8Io
+//    struct must have the following fields set correctly before calling put():
+
+// vertical position of a MB
+#[allow(non_snake_case)]
+fn MACROBLOCK_VPOS(mb_y: c_int) -> c_int {
+    mb_y * 16
+}
+
+// Finalize and transmit a complete row. Return false in case of user-abort.
+#[allow(non_snake_case)]
+extern "C-unwind" fn FinishRow(arg1: *mut c_void, arg2: *mut c_void) -> c_int {
+    unsafe {
+        let dec: &mut VP8Decoder = &mut *(arg1 as *mut VP8Decoder);
+        let io: &mut VP8Io = &mut *(arg2 as *mut VP8Io);
+        let mut ok = 1;
+        let cache_id = dec.thread_ctx_.id_;
+        let extra_y_rows = kFilterExtraRows[dec.filter_type_ as usize] as c_int;
+        let ysize = extra_y_rows * dec.cache_y_stride_;
+        let uvsize = (extra_y_rows / 2) * dec.cache_uv_stride_;
+        let y_offset = cache_id * 16 * dec.cache_y_stride_;
+        let uv_offset = cache_id * 8 * dec.cache_uv_stride_;
+        let ydst: *mut u8 = dec.cache_y_.offset((-ysize + y_offset) as isize);
+        let udst: *mut u8 = dec.cache_u_.offset((-uvsize + uv_offset) as isize);
+        let vdst: *mut u8 = dec.cache_v_.offset((-uvsize + uv_offset) as isize);
+        let mb_y = dec.thread_ctx_.mb_y_;
+        let is_first_row = mb_y == 0;
+        let is_last_row = mb_y >= dec.br_mb_y_ - 1;
+
+        if dec.mt_method_ == 2 {
+            ReconstructRow(dec, &dec.thread_ctx_);
+        }
+
+        if dec.thread_ctx_.filter_row_ != 0 {
+            FilterRow(dec);
+        }
+
+        if dec.dither_ != 0 {
+            DitherRow(dec);
+        }
+
+        if let Some(put) = io.put {
+            let mut y_start = MACROBLOCK_VPOS(mb_y);
+            let mut y_end = MACROBLOCK_VPOS(mb_y + 1);
+            if !is_first_row {
+                y_start -= extra_y_rows;
+                io.y = ydst;
+                io.u = udst;
+                io.v = vdst;
+            } else {
+                io.y = dec.cache_y_.offset(y_offset as isize);
+                io.u = dec.cache_u_.offset(uv_offset as isize);
+                io.v = dec.cache_v_.offset(uv_offset as isize);
+            }
+
+            if !is_last_row {
+                y_end -= extra_y_rows;
+            }
+            if y_end > io.crop_bottom {
+                y_end = io.crop_bottom; // make sure we don't overflow on last row.
+            }
+            // If dec->alpha_data_ is not NULL, we have some alpha plane present.
+            io.a = ptr::null();
+            if !dec.alpha_data_.is_null() && y_start < y_end {
+                io.a = VP8DecompressAlphaRows(dec, io, y_start, y_end - y_start);
+                if io.a.is_null() {
+                    return VP8SetError(
+                        dec,
+                        VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR,
+                        c_str!("Could not decode alpha data."),
+                    );
+                }
+            }
+            if y_start < io.crop_top {
+                let delta_y = io.crop_top - y_start;
+                y_start = io.crop_top;
+                c_assert!(delta_y & 1 == 0);
+                io.y = io.y.offset((dec.cache_y_stride_ * delta_y) as isize);
+                io.u =
+                    io.u.offset((dec.cache_uv_stride_ * (delta_y >> 1)) as isize);
+                io.v =
+                    io.v.offset((dec.cache_uv_stride_ * (delta_y >> 1)) as isize);
+                if !io.a.is_null() {
+                    io.a = io.a.offset((io.width * delta_y) as isize);
+                }
+            }
+            if y_start < y_end {
+                io.y = io.y.offset(io.crop_left as isize);
+                io.u = io.u.offset((io.crop_left >> 1) as isize);
+                io.v = io.v.offset((io.crop_left >> 1) as isize);
+                if !io.a.is_null() {
+                    io.a = io.a.offset(io.crop_left as isize);
+                }
+                io.mb_y = y_start - io.crop_top;
+                io.mb_w = io.crop_right - io.crop_left;
+                io.mb_h = y_end - y_start;
+                ok = put(io);
+            }
+        }
+        // rotate top samples if needed
+        if cache_id + 1 == dec.num_caches_ && !is_last_row {
+            ptr::copy_nonoverlapping(
+                ydst.offset((16 * dec.cache_y_stride_) as isize),
+                dec.cache_y_.offset(-ysize as isize),
+                ysize as usize,
+            );
+            ptr::copy_nonoverlapping(
+                udst.offset((8 * dec.cache_uv_stride_) as isize),
+                dec.cache_u_.offset(-uvsize as isize),
+                uvsize as usize,
+            );
+            ptr::copy_nonoverlapping(
+                vdst.offset((8 * dec.cache_uv_stride_) as isize),
+                dec.cache_v_.offset(-uvsize as isize),
+                uvsize as usize,
+            );
+        }
+
+        ok
+    }
+}
+
+//------------------------------------------------------------------------------
+
+// Process the last decoded row (filtering + output).
+#[no_mangle]
+pub unsafe extern "C" fn VP8ProcessRow(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int {
+    let mut ok = 1;
+    let filter_row = ((*dec).filter_type_ > 0
+        && (*dec).mb_y_ >= (*dec).tl_mb_y_
+        && (*dec).mb_y_ <= (*dec).br_mb_y_) as c_int;
+    if (*dec).mt_method_ == 0 {
+        let dec: &mut VP8Decoder = &mut *dec;
+        // ctx->id_ and ctx->f_info_ are already set
+        dec.thread_ctx_.mb_y_ = dec.mb_y_;
+        dec.thread_ctx_.filter_row_ = filter_row;
+        ReconstructRow(dec, &dec.thread_ctx_);
+        ok = FinishRow(dec as *mut VP8Decoder as *mut c_void, io as *mut c_void);
+    } else {
+        let worker = &mut (*dec).worker_ as *mut _;
+        // Finish previous job *before* updating context
+        ok &= (*WebPGetWorkerInterface()).Sync.unwrap()(worker);
+        c_assert!((*worker).status_ == WebPWorkerStatus::OK);
+        if ok != 0 {
+            // spawn a new deblocking/output job
+            let dec: &mut VP8Decoder = &mut *dec;
+            dec.thread_ctx_.io_ = ptr::read(io);
+            dec.thread_ctx_.id_ = dec.cache_id_;
+            dec.thread_ctx_.mb_y_ = dec.mb_y_;
+            dec.thread_ctx_.filter_row_ = filter_row;
+            if dec.mt_method_ == 2 {
+                // swap macroblock data
+                mem::swap(&mut dec.thread_ctx_.mb_data_, &mut dec.mb_data_);
+            } else {
+                // perform reconstruction directly in main thread
+                ReconstructRow(dec, &dec.thread_ctx_);
+            }
+            if filter_row != 0 {
+                // swap filter info
+                mem::swap(&mut dec.thread_ctx_.f_info_, &mut dec.f_info_);
+            }
+            // (reconstruct)+filter in parallel
+            (*WebPGetWorkerInterface()).Launch.unwrap()(worker);
+            dec.cache_id_ += 1;
+            if dec.cache_id_ == dec.num_caches_ {
+                dec.cache_id_ = 0;
+            }
+        }
+    }
+    ok
+}
+
+//------------------------------------------------------------------------------
+// Finish setting up the decoding parameter once user's setup() is called.
+
+// Must be called after VP8GetHeaders(), before VP8InitFrame().
+#[no_mangle]
+pub unsafe extern "C" fn VP8EnterCritical(dec: *mut VP8Decoder, io: *mut VP8Io) -> VP8StatusCode {
+    // Call setup() first. This may trigger additional decoding features on 'io'.
+    // Note: Afterward, we must call teardown() no matter what.
+    if let Some(setup) = (*io).setup {
+        if setup(io) == 0 {
+            VP8SetError(
+                dec,
+                VP8StatusCode::VP8_STATUS_USER_ABORT,
+                c_str!("Frame setup failed"),
+            );
+            return (*dec).status_;
+        }
+    }
+    let dec: &mut VP8Decoder = &mut *dec;
+    let io: &VP8Io = &*io;
+
+    // Disable filtering per user request
+    if io.bypass_filtering != 0 {
+        dec.filter_type_ = 0;
+    }
+
+    // Define the area where we can skip in-loop filtering, in case of cropping.
+    //
+    // 'Simple' filter reads two luma samples outside of the macroblock
+    // and filters one. It doesn't filter the chroma samples. Hence, we can
+    // avoid doing the in-loop filtering before crop_top/crop_left position.
+    // For the 'Complex' filter, 3 samples are read and up to 3 are filtered.
+    // Means: there's a dependency chain that goes all the way up to the
+    // top-left corner of the picture (MB #0). We must filter all the previous
+    // macroblocks.
+    {
+        let extra_pixels = kFilterExtraRows[dec.filter_type_ as usize] as c_int;
+        if dec.filter_type_ == 2 {
+            // For complex filter, we need to preserve the dependency chain.
+            dec.tl_mb_x_ = 0;
+            dec.tl_mb_y_ = 0;
+        } else {
+            // For simple filter, we can filter only the cropped region.
+            // We include 'extra_pixels' on the other side of the boundary, since
+            // vertical or horizontal filtering of the previous macroblock can
+            // modify some abutting pixels.
+            dec.tl_mb_x_ = (io.crop_left - extra_pixels) >> 4;
+            dec.tl_mb_y_ = (io.crop_top - extra_pixels) >> 4;
+            if dec.tl_mb_x_ < 0 {
+                dec.tl_mb_x_ = 0;
+            }
+            if dec.tl_mb_y_ < 0 {
+                dec.tl_mb_y_ = 0;
+            }
+        }
+        // We need some 'extra' pixels on the right/bottom.
+        dec.br_mb_y_ = (io.crop_bottom + 15 + extra_pixels) >> 4;
+        dec.br_mb_x_ = (io.crop_right + 15 + extra_pixels) >> 4;
+        if dec.br_mb_x_ > dec.mb_w_ {
+            dec.br_mb_x_ = dec.mb_w_;
+        }
+        if dec.br_mb_y_ > dec.mb_h_ {
+            dec.br_mb_y_ = dec.mb_h_;
+        }
+    }
+    PrecomputeFilterStrengths(dec);
+    VP8StatusCode::VP8_STATUS_OK
+}
+
+// Must always be called in pair with VP8EnterCritical().
+// Returns false in case of error.
+#[no_mangle]
+pub unsafe extern "C" fn VP8ExitCritical(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int {
+    let mut ok = 1;
+    if (*dec).mt_method_ > 0 {
+        ok = (*WebPGetWorkerInterface()).Sync.unwrap()(&mut (*dec).worker_);
+    }
+
+    if let Some(teardown) = (*io).teardown {
+        teardown(io);
+    }
+    ok
+}
+
+//------------------------------------------------------------------------------
+// For multi-threaded decoding we need to use 3 rows of 16 pixels as delay line.
+//
+// Reason is: the deblocking filter cannot deblock the bottom horizontal edges
+// immediately, and needs to wait for first few rows of the next macroblock to
+// be decoded. Hence, deblocking is lagging behind by 4 or 8 pixels (depending
+// on strength).
+// With two threads, the vertical positions of the rows being decoded are:
+// Decode:  [ 0..15][16..31][32..47][48..63][64..79][...
+// Deblock:         [ 0..11][12..27][28..43][44..59][...
+// If we use two threads and two caches of 16 pixels, the sequence would be:
+// Decode:  [ 0..15][16..31][ 0..15!!][16..31][ 0..15][...
+// Deblock:         [ 0..11][12..27!!][-4..11][12..27][...
+// The problem occurs during row [12..15!!] that both the decoding and
+// deblocking threads are writing simultaneously.
+// With 3 cache lines, one get a safe write pattern:
+// Decode:  [ 0..15][16..31][32..47][ 0..15][16..31][32..47][0..
+// Deblock:         [ 0..11][12..27][28..43][-4..11][12..27][28...
+// Note that multi-threaded output _without_ deblocking can make use of two
+// cache lines of 16 pixels only, since there's no lagging behind. The decoding
+// and output process have non-concurrent writing:
+// Decode:  [ 0..15][16..31][ 0..15][16..31][...
+// io->put:         [ 0..15][16..31][ 0..15][...
+
+const MT_CACHE_LINES: c_int = 3;
+const ST_CACHE_LINES: c_int = 1; // 1 cache row only for single-threaded case
+
+// Initialize multi/single-thread worker
+#[allow(non_snake_case)]
+unsafe fn InitThreadContext(dec: *mut VP8Decoder) -> c_int {
+    let dec_ptr = dec;
+    let dec: &mut VP8Decoder = &mut *dec;
+    dec.cache_id_ = 0;
+    if dec.mt_method_ > 0 {
+        let worker = &mut dec.worker_;
+        if (*WebPGetWorkerInterface()).Reset.unwrap()(worker) == 0 {
+            return VP8SetError(
+                dec,
+                VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY,
+                c_str!("thread initialization failed."),
+            );
+        }
+        worker.data1 = dec_ptr as *mut c_void;
+        worker.data2 = &mut dec.thread_ctx_.io_ as *mut VP8Io as *mut c_void;
+        worker.hook = Some(FinishRow);
+        dec.num_caches_ = if dec.filter_type_ > 0 {
+            MT_CACHE_LINES
+        } else {
+            MT_CACHE_LINES - 1
+        };
+    } else {
+        dec.num_caches_ = ST_CACHE_LINES;
+    }
+    1
+}
+
+// Return the multi-threading method to use (0=off), depending
+// on options and bitstream size. Only for lossy decoding.
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetThreadMethod(
+    options: *const WebPDecoderOptions,
+    headers: *const WebPHeaderStructure,
+    width: c_int,
+    _height: c_int,
+) -> c_int {
+    if options.is_null() || (*options).use_threads == 0 {
+        return 0;
+    }
+    c_assert!(headers.is_null() || (*headers).is_lossless == 0);
+    if WEBP_USE_THREAD {
+        if width < MIN_WIDTH_FOR_THREADS {
+            return 0;
+        }
+        // TODO(skal): tune the heuristic further
+        2
+    } else {
+        0
+    }
+}
+
+//------------------------------------------------------------------------------
+// Memory setup
+
+#[allow(non_snake_case)]
+unsafe fn AllocateMemory(dec: &mut VP8Decoder) -> c_int {
+    let num_caches = dec.num_caches_;
+    let mb_w = dec.mb_w_ as usize;
+    // Note: we use 'size_t' when there's no overflow risk, uint64_t otherwise.
+    let intra_pred_mode_size: usize = 4 * mb_w * mem::size_of::<u8>();
+    let top_size: usize = mem::size_of::<VP8TopSamples>() * mb_w;
+    let mb_info_size: usize = (mb_w + 1) * mem::size_of::<VP8MB>();
+    let f_info_size: usize = if dec.filter_type_ > 0 {
+        mb_w * (if dec.mt_method_ > 0 { 2 } else { 1 }) * mem::size_of::<VP8FInfo>()
+    } else {
+        0
+    };
+    let yuv_size: usize = YUV_SIZE as usize * mem::size_of::<u8>();
+    let mb_data_size: usize =
+        (if dec.mt_method_ == 2 { 2 } else { 1 }) * mb_w * mem::size_of::<VP8MBData>();
+    let cache_height: usize =
+        (16 * num_caches as usize + kFilterExtraRows[dec.filter_type_ as usize] as usize) * 3 / 2;
+    let cache_size: usize = top_size * cache_height;
+    let needed: u64 = intra_pred_mode_size as u64
+        + top_size as u64
+        + mb_info_size as u64
+        + f_info_size as u64
+        + yuv_size as u64
+        + mb_data_size as u64
+        + cache_size as u64
+        + WEBP_ALIGN_CST as u64;
+
+    if needed != needed as usize as u64 {
+        return 0; // check for overflow
+    }
+    if needed > dec.mem_size_ as u64 {
+        WebPSafeFree(dec.mem_);
+        dec.mem_size_ = 0;
+        dec.mem_ = WebPSafeMalloc(needed, mem::size_of::<u8>());
+        if dec.mem_.is_null() {
+            return VP8SetError(
+                dec,
+                VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY,
+                c_str!("no memory during frame initialization."),
+            );
+        }
+        // down-cast is ok, thanks to WebPSafeMalloc() above.
+        dec.mem_size_ = needed as usize;
+    }
+
+    let mut mem: *mut u8 = dec.mem_ as *mut u8;
+    dec.intra_t_ = mem;
+    mem = mem.add(intra_pred_mode_size);
+
+    dec.yuv_t_ = mem as *mut VP8TopSamples;
+    mem = mem.add(top_size);
+
+    dec.mb_info_ = (mem as *mut VP8MB).offset(1);
+    mem = mem.add(mb_info_size);
+
+    dec.f_info_ = if f_info_size != 0 {
+        mem as *mut VP8FInfo
+    } else {
+        ptr::null_mut()
+    };
+    mem = mem.add(f_info_size);
+    dec.thread_ctx_.id_ = 0;
+    dec.thread_ctx_.f_info_ = dec.f_info_;
+    if dec.mt_method_ > 0 {
+        // secondary cache line. The deblocking process need to make use of the
+        // filtering strength from previous macroblock row, while the new ones
+        // are being decoded in parallel. We'll just swap the pointers.
+        dec.thread_ctx_.f_info_ = dec.thread_ctx_.f_info_.add(mb_w);
+    }
+
+    mem = WEBP_ALIGN(mem);
+    c_assert!(yuv_size & WEBP_ALIGN_CST == 0);
+    dec.yuv_b_ = mem;
+    mem = mem.add(yuv_size);
+
+    dec.mb_data_ = mem as *mut VP8MBData;
+    dec.thread_ctx_.mb_data_ = mem as *mut VP8MBData;
+    if dec.mt_method_ == 2 {
+        dec.thread_ctx_.mb_data_ = dec.thread_ctx_.mb_data_.add(mb_w);
+    }
+    mem = mem.add(mb_data_size);
+
+    dec.cache_y_stride_ = 16 * mb_w as c_int;
+    dec.cache_uv_stride_ = 8 * mb_w as c_int;
+    {
+        let extra_rows = kFilterExtraRows[dec.filter_type_ as usize] as c_int;
+        let extra_y = extra_rows * dec.cache_y_stride_;
+        let extra_uv = (extra_rows / 2) * dec.cache_uv_stride_;
+        dec.cache_y_ = mem.offset(extra_y as isize);
+        dec.cache_u_ = dec
+            .cache_y_
+            .offset((16 * num_caches * dec.cache_y_stride_ + extra_uv) as isize);
+        dec.cache_v_ = dec
+            .cache_u_
+            .offset((8 * num_caches * dec.cache_uv_stride_ + extra_uv) as isize);
+        dec.cache_id_ = 0;
+    }
+    mem = mem.add(cache_size);
+
+    c_assert!(mem <= (dec.mem_ as *mut u8).add(dec.mem_size_));
+
+    // note: left/top-info is initialized once for all.
+    ptr::write_bytes(dec.mb_info_.offset(-1) as *mut u8, 0, mb_info_size);
+    VP8InitScanline(dec); // initialize left too.
+
+    // initialize top
+    ptr::write_bytes(dec.intra_t_, B_DC_PRED as u8, intra_pred_mode_size);
+
+    1
+}
+
+#[allow(non_snake_case)]
+fn InitIo(dec: &VP8Decoder, io: &mut VP8Io) {
+    // prepare 'io'
+    io.mb_y = 0;
+    io.y = dec.cache_y_;
+    io.u = dec.cache_u_;
+    io.v = dec.cache_v_;
+    io.y_stride = dec.cache_y_stride_;
+    io.uv_stride = dec.cache_uv_stride_;
+    io.a = ptr::null();
+}
+
+// Must be called after VP8EnterCritical() and before any row processing.
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitFrame(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int {
+    if InitThreadContext(dec) == 0 {
+        return 0; // call first. Sets dec->num_caches_.
+    }
+    let dec: &mut VP8Decoder = &mut *dec;
+    if AllocateMemory(dec) == 0 {
+        return 0;
+    }
+    InitIo(dec, &mut *io);
+    VP8DspInit(); // Init critical function pointers and look-up tables.
+    1
+}
+
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dec::vp8_dec::{VP8Decode, VP8Delete, VP8GetHeaders, VP8New};
+    use encode::{encode_rgba_with_config, EncoderConfig};
+    use test_util::Lcg;
+    use test_util::{check_parity, chunk, corpus, new_io, put, Digest};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(improper_ctypes)]
+    extern "C" {
+        fn WebPRef_VP8New() -> *mut VP8Decoder;
+        fn WebPRef_VP8Delete(dec: *mut VP8Decoder);
+        fn WebPRef_VP8GetHeaders(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int;
+        fn WebPRef_VP8Decode(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int;
+        fn WebPRef_VP8InitDithering(options: *const WebPDecoderOptions, dec: *mut VP8Decoder);
+    }
+
+    #[derive(Clone, Copy, Default)]
+    struct Options {
+        bypass_filtering: bool,
+        crop: Option<(c_int, c_int, c_int, c_int)>, // left, top, right, bottom
+        mt_method: c_int,
+        dithering: c_int,
+    }
+
+    fn variants(width: c_int, height: c_int) -> Vec<Options> {
+        let base = Options::default();
+        vec![
+            base,
+            Options {
+                bypass_filtering: true,
+                ..base
+            },
+            Options {
+                crop: Some((6, 10, width - 5, height - 3)),
+                ..base
+            },
+            Options {
+                crop: Some((34, 20, width / 2 + 1, height / 2 + 4)),
+                ..base
+            },
+            Options {
+                mt_method: 1,
+                ..base
+            },
+            Options {
+                mt_method: 2,
+                ..base
+            },
+            Options {
+                dithering: 50,
+                ..base
+            },
+            Options {
+                dithering: 100,
+                mt_method: 2,
+                ..base
+            },
+        ]
+    }
+
+    type Api = (
+        unsafe extern "C" fn() -> *mut VP8Decoder,
+        unsafe extern "C" fn(*mut VP8Decoder),
+        unsafe extern "C" fn(*mut VP8Decoder, *mut VP8Io) -> c_int,
+        unsafe extern "C" fn(*mut VP8Decoder, *mut VP8Io) -> c_int,
+        unsafe extern "C" fn(*const WebPDecoderOptions, *mut VP8Decoder),
+    );
+
+    const PORT: Api = (
+        VP8New,
+        VP8Delete,
+        VP8GetHeaders,
+        VP8Decode,
+        VP8InitDithering,
+    );
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = (
+        WebPRef_VP8New,
+        WebPRef_VP8Delete,
+        WebPRef_VP8GetHeaders,
+        WebPRef_VP8Decode,
+        WebPRef_VP8InitDithering,
+    );
+
+    fn decode(
+        data: &[u8],
+        alpha: Option<&[u8]>,
+        options: &Options,
+        api: Api,
+    ) -> (c_int, VP8StatusCode, Vec<u8>) {
+        let (new, delete, get_headers, decode, init_dithering) = api;
+        let mut out = Vec::new();
+        let mut io = new_io(data);
+        io.put = Some(put);
+        io.opaque = &mut out as *mut Vec<u8> as *mut c_void;
+        unsafe {
+            let dec = new();
+            if let Some(alpha) = alpha {
+                (*dec).alpha_data_ = alpha.as_ptr();
+                (*dec).alpha_data_size_ = alpha.len();
+            }
+            if get_headers(dec, &mut io) != 0 {
+                if options.bypass_filtering {
+                    io.bypass_filtering = 1;
+                }
+                if let Some((left, top, right, bottom)) = options.crop {
+                    io.use_cropping = 1;
+                    io.crop_left = left;
+                    io.crop_top = top;
+                    io.crop_right = right;
+                    io.crop_bottom = bottom;
+                }
+                (*dec).mt_method_ = options.mt_method;
+                let mut dec_options: WebPDecoderOptions = mem::zeroed();
+                dec_options.dithering_strength = options.dithering;
+                dec_options.alpha_dithering_strength = options.dithering;
+                init_dithering(&dec_options, dec);
+            }
+            let ok = decode(dec, &mut io);
+            let status = (*dec).status_;
+            delete(dec);
+            (ok, status, out)
+        }
+    }
+
+    fn check(data: &[u8], alpha: Option<&[u8]>, width: c_int, height: c_int, digest: &mut Digest) {
+        for options in variants(width, height) {
+            let (ok, status, out) = decode(data, alpha, &options, PORT);
+            assert_eq!(ok, 1);
+            assert_eq!(status, VP8StatusCode::VP8_STATUS_OK);
+            assert!(!out.is_empty());
+            digest.update(&out);
+            #[cfg(feature = "c-reference")]
+            assert_eq!((ok, status, out), decode(data, alpha, &options, REFERENCE));
+        }
+    }
+
+    // Smooth gradients with noisy patches, so that the encoder makes use of
+    // all prediction modes and of several segments.
+    fn image(width: usize, height: usize, seed: u32) -> Vec<u8> {
+        let mut rng = Lcg(seed);
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let noisy = (x / 16 + y / 16) % 3 == 0;
+                let noise = if noisy { rng.next() % 64 } else { 0 } as usize;
+                rgba.push(((x * 255 / width + noise) % 256) as u8);
+                rgba.push(((y * 255 / height + noise) % 256) as u8);
+                rgba.push((((x + y) * 2 + noise) % 256) as u8);
+                rgba.push(((x * y + noise) % 256) as u8);
+            }
+        }
+        rgba
+    }
+
+    fn dimensions(data: &[u8]) -> (c_int, c_int) {
+        let mut io = new_io(data);
+        unsafe {
+            let dec = VP8New();
+            assert_eq!(VP8GetHeaders(dec, &mut io), 1);
+            VP8Delete(dec);
+        }
+        (io.width, io.height)
+    }
+
+    #[test]
+    fn test_frame_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            let (width, height) = dimensions(&data);
+            check(&data, None, width, height, &mut digest);
+        }
+        check_parity("dec::frame_dec::test_frame_parity", digest);
+    }
+
+    #[test]
+    fn test_generated_frame_parity() {
+        let (width, height) = (83, 61);
+        let mut digest = Digest::new();
+        let mut filter_types = [false; 3];
+        for segments in 1..=4 {
+            for &(filter_type, filter_strength, filter_sharpness) in &[
+                (0, 0, 0),
+                (0, 40, 0),
+                (0, 100, 6),
+                (1, 20, 2),
+                (1, 60, 7),
+                (1, 100, 0),
+            ] {
+                let config = EncoderConfig {
+                    quality: 40.0 + 15.0 * segments as f32,
+                    segments,
+                    filter_type,
+                    filter_strength,
+                    filter_sharpness,
+                    ..EncoderConfig::default()
+                };
+                let rgba = image(width, height, segments as u32);
+                let webp = encode_rgba_with_config(
+                    &rgba,
+                    width as u32,
+                    height as u32,
+                    width as u32 * 4,
+                    &config,
+                )
+                .unwrap();
+                let data = chunk(&webp, b"VP8 ").unwrap();
+                let alpha = chunk(&webp, b"ALPH");
+                assert!(alpha.is_some());
+                unsafe {
+                    let mut io = new_io(data);
+                    let dec = VP8New();
+                    assert_eq!(VP8GetHeaders(dec, &mut io), 1);
+                    filter_types[(*dec).filter_type_ as usize] = true;
+                    VP8Delete(dec);
+                }
+                check(data, None, width as c_int, height as c_int, &mut digest);
+                check(data, alpha, width as c_int, height as c_int, &mut digest);
+            }
+        }
+        assert_eq!(filter_types, [true; 3]);
+        check_parity("dec::frame_dec::test_generated_frame_parity", digest);
+    }
+
+    #[test]
+    fn test_dithering_applies() {
+        let data = &corpus()[0];
+        let options = Options {
+            dithering: 100,
+            ..Options::default()
+        };
+        assert_ne!(
+            decode(data, None, &Options::default(), PORT).2,
+            decode(data, None, &options, PORT).2
+        );
+    }
+}
@@ -0,0 +1,1696 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Incremental decoding
+//
+// Author: somnath@google.com (Somnath Banerjee)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(clippy::too_many_arguments)]
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dec::buffer_dec::{
+    WebPAllocateDecBuffer, WebPAvoidSlowMemory, WebPCopyDecBufferPixels, WebPFlipBuffer,
+    WebPFreeDecBuffer,
+};
+use dec::frame_dec::{
+    VP8EnterCritical, VP8ExitCritical, VP8GetThreadMethod, VP8InitDithering, VP8InitFrame,
+    VP8ProcessRow,
+};
+use dec::io_dec::WebPInitCustomIo;
+use dec::tree_dec::VP8ParseIntraModeRow;
+use dec::vp8_dec::{
+    VP8DecodeMB, VP8Delete, VP8GetHeaders, VP8GetInfo, VP8InitIo, VP8InitScanline, VP8Io,
+    VP8IoPutHook, VP8IoSetupHook, VP8IoTeardownHook, VP8New,
+};
+use dec::vp8i_dec::{VP8Decoder, VP8MB};
+use dec::vp8l_dec::{VP8LDecodeHeader, VP8LDecodeImage, VP8LDelete, VP8LNew};
+use dec::vp8li_dec::VP8LDecoder;
+use dec::webp_dec::{
+    WebPGetFeaturesInternal, WebPInitDecBuffer, WebPParseHeaders, WebPResetDecParams,
+};
+use dec::webpi_dec::{WebPDecParams, WebPHeaderStructure};
+use decode::VP8StatusCode::{self, *};
+use decode::{MODE_YUV, MODE_YUVA, WEBP_CSP_MODE, WEBP_DECODER_ABI_VERSION};
+use format_constants::{
+    ALPHA_HEADER_LEN, ALPHA_LOSSLESS_COMPRESSION, MAX_CHUNK_PAYLOAD, VP8_FRAME_HEADER_SIZE,
+};
+use sys::{WebPBitstreamFeatures, WebPDecBuffer, WebPDecoderConfig};
+use utils::bit_reader_utils::{
+    VP8BitReader, VP8BitReaderSetBuffer, VP8LBitReaderSetBuffer, VP8RemapBitReader,
+};
+use utils::utils::{WebPSafeCalloc, WebPSafeFree, WebPSafeMalloc};
+
+// In append mode, buffer allocations increase as multiples of this value.
+// Needs to be a power of 2.
+const CHUNK_SIZE: u64 = 4096;
+const MAX_MB_SIZE: usize = 4096;
+
+//------------------------------------------------------------------------------
+// Data structures for memory and states
+
+// Decoding states. State normally flows as:
+// WEBP_HEADER->VP8_HEADER->VP8_PARTS0->VP8_DATA->DONE for a lossy image, and
+// WEBP_HEADER->VP8L_HEADER->VP8L_DATA->DONE for a lossless image.
+// If there is any error the decoder goes into state ERROR.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(C)]
+enum DecState {
+    STATE_WEBP_HEADER, // All the data before that of the VP8/VP8L chunk.
+    STATE_VP8_HEADER,  // The VP8 Frame header (within the VP8 chunk).
+    STATE_VP8_PARTS0,
+    STATE_VP8_DATA,
+    STATE_VP8L_HEADER,
+    STATE_VP8L_DATA,
+    STATE_DONE,
+    STATE_ERROR,
+}
+use self::DecState::*;
+
+// Operating state for the MemBuffer
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+enum MemBufferMode {
+    MEM_MODE_NONE = 0,
+    MEM_MODE_APPEND,
+    MEM_MODE_MAP,
+}
+use self::MemBufferMode::*;
+
+// storage for partition #0 and partial data (in a rolling fashion)
+#[repr(C)]
+struct MemBuffer {
+    mode_: MemBufferMode, // Operation mode
+    start_: usize,        // start location of the data to be decoded
+    end_: usize,          // end location
+    buf_size_: usize,     // size of the allocated buffer
+    buf_: *mut u8,        // We don't own this buffer in case WebPIUpdate()
+
+    part0_size_: usize,    // size of partition #0
+    part0_buf_: *const u8, // buffer to store partition #0
+}
+
+#[repr(C)]
+pub struct WebPIDecoder {
+    state_: DecState,       // current decoding state
+    params_: WebPDecParams, // Params to store output info
+    is_lossless_: c_int,    // for down-casting 'dec_'.
+    dec_: *mut c_void,      // either a VP8Decoder or a VP8LDecoder instance
+    io_: VP8Io,
+
+    mem_: MemBuffer,        // input memory buffer.
+    output_: WebPDecBuffer, // output buffer (when no external one is supplied,
+    // or if the external one has slow-memory)
+    final_output_: *mut WebPDecBuffer, // Slow-memory output to copy to eventually.
+    chunk_size_: usize,                // Compressed VP8/VP8L size extracted from Header.
+
+    last_mb_y_: c_int, // last row reached for intra-mode decoding
+}
+
+// MB context to restore in case VP8DecodeMB() fails
+struct MBContext {
+    left_: VP8MB,
+    info_: VP8MB,
+    token_br_: VP8BitReader,
+}
+
+//------------------------------------------------------------------------------
+// MemBuffer: incoming data handling
+
+#[allow(non_snake_case)]
+#[inline]
+fn MemDataSize(mem: &MemBuffer) -> usize {
+    mem.end_ - mem.start_
+}
+
+// Check if we need to preserve the compressed alpha data, as it may not have
+// been decoded yet.
+#[allow(non_snake_case)]
+unsafe fn NeedCompressedAlpha(idec: &WebPIDecoder) -> bool {
+    if idec.state_ == STATE_WEBP_HEADER {
+        // We haven't parsed the headers yet, so we don't know whether the image is
+        // lossy or lossless. This also means that we haven't parsed the ALPH chunk.
+        return false;
+    }
+    if idec.is_lossless_ != 0 {
+        false // ALPH chunk is not present for lossless images.
+    } else {
+        let dec = idec.dec_ as *const VP8Decoder;
+        c_assert!(!dec.is_null()); // Must be true as idec->state_ != STATE_WEBP_HEADER.
+        !(*dec).alpha_data_.is_null() && (*dec).is_alpha_decoded_ == 0
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn DoRemap(idec: &mut WebPIDecoder, offset: isize) {
+    let new_base: *const u8 = idec.mem_.buf_.add(idec.mem_.start_);
+    // note: for VP8, setting up idec->io_ is only really needed at the beginning
+    // of the decoding, till partition #0 is complete.
+    idec.io_.data = new_base;
+    idec.io_.data_size = MemDataSize(&idec.mem_);
+
+    if !idec.dec_.is_null() {
+        if idec.is_lossless_ == 0 {
+            let need_compressed_alpha = NeedCompressedAlpha(idec);
+            let mem = &idec.mem_;
+            let dec: &mut VP8Decoder = &mut *(idec.dec_ as *mut VP8Decoder);
+            let last_part = dec.num_parts_minus_one_ as usize;
+            if offset != 0 {
+                for part in &mut dec.parts_[..=last_part] {
+                    VP8RemapBitReader(part, offset);
+                }
+                // Remap partition #0 data pointer to new offset, but only in MAP
+                // mode (in APPEND mode, partition #0 is copied into a fixed memory).
+                if mem.mode_ == MEM_MODE_MAP {
+                    VP8RemapBitReader(&mut dec.br_, offset);
+                }
+            }
+            {
+                let last_start: *const u8 = dec.parts_[last_part].buf_;
+                VP8BitReaderSetBuffer(
+                    &mut dec.parts_[last_part],
+                    last_start,
+                    (mem.buf_.add(mem.end_) as usize).wrapping_sub(last_start as usize),
+                );
+            }
+            if need_compressed_alpha {
+                dec.alpha_data_ = dec.alpha_data_.wrapping_offset(offset);
+                if let Some(ref mut alph_dec) = dec.alph_dec_ {
+                    if alph_dec.method_ == ALPHA_LOSSLESS_COMPRESSION {
+                        let alph_vp8l_dec: *mut VP8LDecoder = alph_dec.vp8l_dec_;
+                        c_assert!(!alph_vp8l_dec.is_null());
+                        c_assert!(dec.alpha_data_size_ >= ALPHA_HEADER_LEN);
+                        VP8LBitReaderSetBuffer(
+                            &mut (*alph_vp8l_dec).br_,
+                            dec.alpha_data_.add(ALPHA_HEADER_LEN),
+                            dec.alpha_data_size_ - ALPHA_HEADER_LEN,
+                        );
+                    } else {
+                        // alph_dec->method_ == ALPHA_NO_COMPRESSION
+                        // Nothing special to do in this case.
+                    }
+                }
+            }
+        } else {
+            // Resize lossless bitreader
+            let dec: &mut VP8LDecoder = &mut *(idec.dec_ as *mut VP8LDecoder);
+            VP8LBitReaderSetBuffer(&mut dec.br_, new_base, MemDataSize(&idec.mem_));
+        }
+    }
+}
+
+// Appends data to the end of MemBuffer->buf_. It expands the allocated memory
+// size if required and also updates VP8BitReader's if new memory is allocated.
+#[allow(non_snake_case)]
+unsafe fn AppendToMemBuffer(idec: &mut WebPIDecoder, data: *const u8, data_size: usize) -> c_int {
+    let dec = idec.dec_ as *const VP8Decoder;
+    let need_compressed_alpha = NeedCompressedAlpha(idec);
+    let mem = &mut idec.mem_;
+    let old_start: *const u8 = mem.buf_.wrapping_add(mem.start_);
+    let old_base: *const u8 = if need_compressed_alpha {
+        (*dec).alpha_data_
+    } else {
+        old_start
+    };
+    c_assert!(mem.mode_ == MEM_MODE_APPEND);
+    if data_size > MAX_CHUNK_PAYLOAD as usize {
+        // security safeguard: trying to allocate more than what the format
+        // allows for a chunk should be considered a smoke smell.
+        return 0;
+    }
+
+    if mem.end_ + data_size > mem.buf_size_ {
+        // Need some free memory
+        let new_mem_start = old_start as usize - old_base as usize;
+        let current_size = MemDataSize(mem) + new_mem_start;
+        let new_size = current_size as u64 + data_size as u64;
+        let extra_size = (new_size + CHUNK_SIZE - 1) & !(CHUNK_SIZE - 1);
+        let new_buf = WebPSafeMalloc(extra_size, mem::size_of::<u8>()) as *mut u8;
+        if new_buf.is_null() {
+            return 0;
+        }
+        if current_size > 0 {
+            ptr::copy_nonoverlapping(old_base, new_buf, current_size);
+        }
+        WebPSafeFree(mem.buf_ as *mut c_void);
+        mem.buf_ = new_buf;
+        mem.buf_size_ = extra_size as usize;
+        mem.start_ = new_mem_start;
+        mem.end_ = current_size;
+    }
+
+    ptr::copy_nonoverlapping(data, mem.buf_.add(mem.end_), data_size);
+    mem.end_ += data_size;
+    c_assert!(mem.end_ <= mem.buf_size_);
+
+    let offset = (mem.buf_.add(mem.start_) as isize).wrapping_sub(old_start as isize);
+    DoRemap(idec, offset);
+    1
+}
+
+#[allow(non_snake_case)]
+unsafe fn RemapMemBuffer(idec: &mut WebPIDecoder, data: *const u8, data_size: usize) -> c_int {
+    let mem = &mut idec.mem_;
+    let old_buf: *const u8 = mem.buf_;
+    let old_start: *const u8 = old_buf.wrapping_add(mem.start_);
+    c_assert!(mem.mode_ == MEM_MODE_MAP);
+
+    if data_size < mem.buf_size_ {
+        return 0; // can't remap to a shorter buffer!
+    }
+
+    mem.buf_ = data as *mut u8;
+    mem.buf_size_ = data_size;
+    mem.end_ = data_size;
+
+    let offset = (mem.buf_.add(mem.start_) as isize).wrapping_sub(old_start as isize);
+    DoRemap(idec, offset);
+    1
+}
+
+#[allow(non_snake_case)]
+fn InitMemBuffer(mem: &mut MemBuffer) {
+    mem.mode_ = MEM_MODE_NONE;
+    mem.buf_ = ptr::null_mut();
+    mem.buf_size_ = 0;
+    mem.part0_buf_ = ptr::null();
+    mem.part0_size_ = 0;
+}
+
+#[allow(non_snake_case)]
+unsafe fn ClearMemBuffer(mem: &mut MemBuffer) {
+    if mem.mode_ == MEM_MODE_APPEND {
+        WebPSafeFree(mem.buf_ as *mut c_void);
+        WebPSafeFree(mem.part0_buf_ as *mut c_void);
+    }
+}
+
+#[allow(non_snake_case)]
+fn CheckMemBufferMode(mem: &mut MemBuffer, expected: MemBufferMode) -> c_int {
+    if mem.mode_ == MEM_MODE_NONE {
+        mem.mode_ = expected; // switch to the expected mode
+    } else if mem.mode_ != expected {
+        return 0; // we mixed the modes => error
+    }
+    c_assert!(mem.mode_ == expected); // mode is ok
+    1
+}
+
+// To be called last.
+#[allow(non_snake_case)]
+unsafe fn FinishDecoding(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let options = idec.params_.options;
+    let output: *mut WebPDecBuffer = idec.params_.output;
+
+    idec.state_ = STATE_DONE;
+    if !options.is_null() && (*options).flip != 0 {
+        let status = WebPFlipBuffer(output);
+        if status != VP8_STATUS_OK {
+            return status;
+        }
+    }
+    if !idec.final_output_.is_null() {
+        WebPCopyDecBufferPixels(output, idec.final_output_); // do the slow-copy
+        WebPFreeDecBuffer(&mut idec.output_);
+        ptr::copy_nonoverlapping(idec.final_output_, output, 1);
+        idec.final_output_ = ptr::null_mut();
+    }
+    VP8_STATUS_OK
+}
+
+//------------------------------------------------------------------------------
+// Macroblock-decoding contexts
+
+#[allow(non_snake_case)]
+unsafe fn SaveContext(dec: &VP8Decoder, token_br: *const VP8BitReader) -> MBContext {
+    MBContext {
+        left_: ptr::read(dec.mb_info_.offset(-1)),
+        info_: ptr::read(dec.mb_info_.offset(dec.mb_x_ as isize)),
+        token_br_: ptr::read(token_br),
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn RestoreContext(context: MBContext, dec: &mut VP8Decoder, token_br: *mut VP8BitReader) {
+    ptr::write(dec.mb_info_.offset(-1), context.left_);
+    ptr::write(dec.mb_info_.offset(dec.mb_x_ as isize), context.info_);
+    ptr::write(token_br, context.token_br_);
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+fn IDecError(idec: &mut WebPIDecoder, error: VP8StatusCode) -> VP8StatusCode {
+    if idec.state_ == STATE_VP8_DATA {
+        let io: &VP8Io = &idec.io_;
+        if let Some(teardown) = io.teardown {
+            teardown(io);
+        }
+    }
+    idec.state_ = STATE_ERROR;
+    error
+}
+
+#[allow(non_snake_case)]
+unsafe fn ChangeState(idec: &mut WebPIDecoder, new_state: DecState, consumed_bytes: usize) {
+    let mem = &mut idec.mem_;
+    idec.state_ = new_state;
+    mem.start_ += consumed_bytes;
+    c_assert!(mem.start_ <= mem.end_);
+    idec.io_.data = mem.buf_.add(mem.start_);
+    idec.io_.data_size = MemDataSize(mem);
+}
+
+// Headers
+#[allow(non_snake_case)]
+unsafe fn DecodeWebPHeaders(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let mem = &idec.mem_;
+    let data: *const u8 = mem.buf_.add(mem.start_);
+    let curr_size = MemDataSize(mem);
+    let mut headers: WebPHeaderStructure = mem::zeroed();
+
+    headers.data = data;
+    headers.data_size = curr_size;
+    headers.have_all_data = 0;
+    let status = WebPParseHeaders(&mut headers);
+    if status == VP8_STATUS_NOT_ENOUGH_DATA {
+        return VP8_STATUS_SUSPENDED; // We haven't found a VP8 chunk yet.
+    } else if status != VP8_STATUS_OK {
+        return IDecError(idec, status);
+    }
+
+    idec.chunk_size_ = headers.compressed_size;
+    idec.is_lossless_ = headers.is_lossless;
+    if idec.is_lossless_ == 0 {
+        let dec = VP8New();
+        if dec.is_null() {
+            return VP8_STATUS_OUT_OF_MEMORY;
+        }
+        idec.dec_ = dec as *mut c_void;
+        (*dec).alpha_data_ = headers.alpha_data;
+        (*dec).alpha_data_size_ = headers.alpha_data_size;
+        ChangeState(idec, STATE_VP8_HEADER, headers.offset);
+    } else {
+        let dec = VP8LNew();
+        if dec.is_null() {
+            return VP8_STATUS_OUT_OF_MEMORY;
+        }
+        idec.dec_ = dec as *mut c_void;
+        ChangeState(idec, STATE_VP8L_HEADER, headers.offset);
+    }
+    VP8_STATUS_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn DecodeVP8FrameHeader(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let data: *const u8 = idec.mem_.buf_.add(idec.mem_.start_);
+    let curr_size = MemDataSize(&idec.mem_);
+    let mut width: c_int = 0;
+    let mut height: c_int = 0;
+
+    if curr_size < VP8_FRAME_HEADER_SIZE {
+        // Not enough data bytes to extract VP8 Frame Header.
+        return VP8_STATUS_SUSPENDED;
+    }
+    if VP8GetInfo(data, curr_size, idec.chunk_size_, &mut width, &mut height) == 0 {
+        return IDecError(idec, VP8_STATUS_BITSTREAM_ERROR);
+    }
+
+    let bits = *data as u32 | (*data.add(1) as u32) << 8 | (*data.add(2) as u32) << 16;
+    idec.mem_.part0_size_ = (bits >> 5) as usize + VP8_FRAME_HEADER_SIZE;
+
+    idec.io_.data = data;
+    idec.io_.data_size = curr_size;
+    idec.state_ = STATE_VP8_PARTS0;
+    VP8_STATUS_OK
+}
+
+// Partition #0
+#[allow(non_snake_case)]
+unsafe fn CopyParts0Data(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let dec: &mut VP8Decoder = &mut *(idec.dec_ as *mut VP8Decoder);
+    let br: &mut VP8BitReader = &mut dec.br_;
+    let part_size = br.buf_end_.offset_from(br.buf_) as usize;
+    let mem = &mut idec.mem_;
+    c_assert!(idec.is_lossless_ == 0);
+    c_assert!(mem.part0_buf_.is_null());
+    // the following is a format limitation, no need for runtime check:
+    c_assert!(part_size <= mem.part0_size_);
+    if part_size == 0 {
+        // can't have zero-size partition #0
+        return VP8_STATUS_BITSTREAM_ERROR;
+    }
+    if mem.mode_ == MEM_MODE_APPEND {
+        // We copy and grab ownership of the partition #0 data.
+        let part0_buf = WebPSafeMalloc(1, part_size) as *mut u8;
+        if part0_buf.is_null() {
+            return VP8_STATUS_OUT_OF_MEMORY;
+        }
+        ptr::copy_nonoverlapping(br.buf_, part0_buf, part_size);
+        mem.part0_buf_ = part0_buf;
+        VP8BitReaderSetBuffer(br, part0_buf, part_size);
+    } else {
+        // Else: just keep pointers to the partition #0's data in dec_->br_.
+    }
+    mem.start_ += part_size;
+    VP8_STATUS_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn DecodePartition0(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let dec = idec.dec_ as *mut VP8Decoder;
+    let io: *mut VP8Io = &mut idec.io_;
+    let options = idec.params_.options;
+    let output: *mut WebPDecBuffer = idec.params_.output;
+
+    // Wait till we have enough data for the whole partition #0
+    if MemDataSize(&idec.mem_) < idec.mem_.part0_size_ {
+        return VP8_STATUS_SUSPENDED;
+    }
+
+    if VP8GetHeaders(dec, io) == 0 {
+        let status = (*dec).status_;
+        if status == VP8_STATUS_SUSPENDED || status == VP8_STATUS_NOT_ENOUGH_DATA {
+            // treating NOT_ENOUGH_DATA as SUSPENDED state
+            return VP8_STATUS_SUSPENDED;
+        }
+        return IDecError(idec, status);
+    }
+
+    // Allocate/Verify output buffer now
+    (*dec).status_ = WebPAllocateDecBuffer((*io).width, (*io).height, options, output);
+    if (*dec).status_ != VP8_STATUS_OK {
+        return IDecError(idec, (*dec).status_);
+    }
+    // This change must be done before calling VP8InitFrame()
+    (*dec).mt_method_ = VP8GetThreadMethod(options, ptr::null(), (*io).width, (*io).height);
+    VP8InitDithering(options, dec);
+
+    (*dec).status_ = CopyParts0Data(idec);
+    if (*dec).status_ != VP8_STATUS_OK {
+        return IDecError(idec, (*dec).status_);
+    }
+
+    // Finish setting up the decoding parameters. Will call io->setup().
+    if VP8EnterCritical(dec, io) != VP8_STATUS_OK {
+        return IDecError(idec, (*dec).status_);
+    }
+
+    // Note: past this point, teardown() must always be called
+    // in case of error.
+    idec.state_ = STATE_VP8_DATA;
+    // Allocate memory and prepare everything.
+    if VP8InitFrame(dec, io) == 0 {
+        return IDecError(idec, (*dec).status_);
+    }
+    VP8_STATUS_OK
+}
+
+// Remaining partitions
+#[allow(non_snake_case)]
+unsafe fn DecodeRemaining(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let dec = idec.dec_ as *mut VP8Decoder;
+    let io: *mut VP8Io = &mut idec.io_;
+
+    c_assert!((*dec).ready_ != 0);
+    while (*dec).mb_y_ < (*dec).mb_h_ {
+        if idec.last_mb_y_ != (*dec).mb_y_ {
+            if VP8ParseIntraModeRow(&mut (*dec).br_, dec) == 0 {
+                // note: normally, error shouldn't occur since we already have the whole
+                // partition0 available here in DecodeRemaining(). Reaching EOF while
+                // reading intra modes really means a BITSTREAM_ERROR.
+                return IDecError(idec, VP8_STATUS_BITSTREAM_ERROR);
+            }
+            idec.last_mb_y_ = (*dec).mb_y_;
+        }
+        while (*dec).mb_x_ < (*dec).mb_w_ {
+            let token_br: *mut VP8BitReader =
+                &mut (*dec).parts_[((*dec).mb_y_ as u32 & (*dec).num_parts_minus_one_) as usize];
+            let context = SaveContext(&*dec, token_br);
+            if VP8DecodeMB(dec, token_br) == 0 {
+                // We shouldn't fail when MAX_MB data was available
+                if (*dec).num_parts_minus_one_ == 0 && MemDataSize(&idec.mem_) > MAX_MB_SIZE {
+                    return IDecError(idec, VP8_STATUS_BITSTREAM_ERROR);
+                }
+                RestoreContext(context, &mut *dec, token_br);
+                return VP8_STATUS_SUSPENDED;
+            }
+            // Release buffer only if there is only one partition
+            if (*dec).num_parts_minus_one_ == 0 {
+                idec.mem_.start_ = (*token_br).buf_.offset_from(idec.mem_.buf_) as usize;
+                c_assert!(idec.mem_.start_ <= idec.mem_.end_);
+            }
+            (*dec).mb_x_ += 1;
+        }
+        VP8InitScanline(dec); // Prepare for next scanline
+
+        // Reconstruct, filter and emit the row.
+        if VP8ProcessRow(dec, io) == 0 {
+            return IDecError(idec, VP8_STATUS_USER_ABORT);
+        }
+        (*dec).mb_y_ += 1;
+    }
+    // Synchronize the thread and check for errors.
+    if VP8ExitCritical(dec, io) == 0 {
+        return IDecError(idec, VP8_STATUS_USER_ABORT);
+    }
+    (*dec).ready_ = 0;
+    FinishDecoding(idec)
+}
+
+#[allow(non_snake_case)]
+fn ErrorStatusLossless(idec: &mut WebPIDecoder, status: VP8StatusCode) -> VP8StatusCode {
+    if status == VP8_STATUS_SUSPENDED || status == VP8_STATUS_NOT_ENOUGH_DATA {
+        return VP8_STATUS_SUSPENDED;
+    }
+    IDecError(idec, status)
+}
+
+#[allow(non_snake_case)]
+unsafe fn DecodeVP8LHeader(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let io: *mut VP8Io = &mut idec.io_;
+    let dec = idec.dec_ as *mut VP8LDecoder;
+    let options = idec.params_.options;
+    let output: *mut WebPDecBuffer = idec.params_.output;
+    let curr_size = MemDataSize(&idec.mem_);
+    c_assert!(idec.is_lossless_ != 0);
+
+    // Wait until there's enough data for decoding header.
+    if curr_size < (idec.chunk_size_ >> 3) {
+        (*dec).status_ = VP8_STATUS_SUSPENDED;
+        return ErrorStatusLossless(idec, (*dec).status_);
+    }
+
+    if VP8LDecodeHeader(dec, io) == 0 {
+        if (*dec).status_ == VP8_STATUS_BITSTREAM_ERROR && curr_size < idec.chunk_size_ {
+            (*dec).status_ = VP8_STATUS_SUSPENDED;
+        }
+        return ErrorStatusLossless(idec, (*dec).status_);
+    }
+    // Allocate/verify output buffer now.
+    (*dec).status_ = WebPAllocateDecBuffer((*io).width, (*io).height, options, output);
+    if (*dec).status_ != VP8_STATUS_OK {
+        return IDecError(idec, (*dec).status_);
+    }
+
+    idec.state_ = STATE_VP8L_DATA;
+    VP8_STATUS_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn DecodeVP8LData(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let dec = idec.dec_ as *mut VP8LDecoder;
+    let curr_size = MemDataSize(&idec.mem_);
+    c_assert!(idec.is_lossless_ != 0);
+
+    // Switch to incremental decoding if we don't have all the bytes available.
+    (*dec).incremental_ = (curr_size < idec.chunk_size_) as c_int;
+
+    if VP8LDecodeImage(dec) == 0 {
+        return ErrorStatusLossless(idec, (*dec).status_);
+    }
+    c_assert!((*dec).status_ == VP8_STATUS_OK || (*dec).status_ == VP8_STATUS_SUSPENDED);
+    if (*dec).status_ == VP8_STATUS_SUSPENDED {
+        (*dec).status_
+    } else {
+        FinishDecoding(idec)
+    }
+}
+
+// Main decoding loop
+#[allow(non_snake_case)]
+unsafe fn IDecode(idec: &mut WebPIDecoder) -> VP8StatusCode {
+    let mut status = VP8_STATUS_SUSPENDED;
+
+    if idec.state_ == STATE_WEBP_HEADER {
+        status = DecodeWebPHeaders(idec);
+    } else if idec.dec_.is_null() {
+        return VP8_STATUS_SUSPENDED; // can't continue if we have no decoder.
+    }
+    if idec.state_ == STATE_VP8_HEADER {
+        status = DecodeVP8FrameHeader(idec);
+    }
+    if idec.state_ == STATE_VP8_PARTS0 {
+        status = DecodePartition0(idec);
+    }
+    if idec.state_ == STATE_VP8_DATA {
+        status = DecodeRemaining(idec);
+    }
+    if idec.state_ == STATE_VP8L_HEADER {
+        status = DecodeVP8LHeader(idec);
+    }
+    if idec.state_ == STATE_VP8L_DATA {
+        status = DecodeVP8LData(idec);
+    }
+    status
+}
+
+//------------------------------------------------------------------------------
+// Internal constructor
+
+#[allow(non_snake_case)]
+unsafe fn NewDecoder(
+    output_buffer: *mut WebPDecBuffer,
+    features: *const WebPBitstreamFeatures,
+) -> *mut WebPIDecoder {
+    let idec = WebPSafeCalloc(1, mem::size_of::<WebPIDecoder>()) as *mut WebPIDecoder;
+    if idec.is_null() {
+        return ptr::null_mut();
+    }
+    let idec_ptr = idec;
+    let idec: &mut WebPIDecoder = &mut *idec;
+
+    idec.state_ = STATE_WEBP_HEADER;
+    idec.chunk_size_ = 0;
+
+    idec.last_mb_y_ = -1;
+
+    InitMemBuffer(&mut idec.mem_);
+    WebPInitDecBuffer(&mut idec.output_);
+    VP8InitIo(&mut idec.io_);
+
+    WebPResetDecParams(&mut idec.params_);
+    if output_buffer.is_null() || WebPAvoidSlowMemory(output_buffer, features) != 0 {
+        idec.params_.output = &mut idec.output_;
+        idec.final_output_ = output_buffer;
+        if !output_buffer.is_null() {
+            (*idec.params_.output).colorspace = (*output_buffer).colorspace;
+        }
+    } else {
+        idec.params_.output = output_buffer;
+        idec.final_output_ = ptr::null_mut();
+    }
+    WebPInitCustomIo(&mut idec.params_, &mut idec.io_); // Plug the I/O functions.
+
+    idec_ptr
+}
+
+//------------------------------------------------------------------------------
+// Public functions
+
+// Creates a new incremental decoder with the supplied buffer parameter.
+// This output_buffer can be passed NULL, in which case a default output buffer
+// is used (with MODE_RGB). Otherwise, an internal reference to 'output_buffer'
+// is kept, which means that the lifespan of 'output_buffer' must be larger than
+// that of the returned WebPIDecoder object.
+// Returns NULL if the allocation failed.
+#[no_mangle]
+pub unsafe extern "C" fn WebPINewDecoder(output_buffer: *mut WebPDecBuffer) -> *mut WebPIDecoder {
+    NewDecoder(output_buffer, ptr::null())
+}
+
+// Akin to WebPDecode(), this function will create an incremental decoder, but
+// with the features and options of 'config'.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIDecode(
+    data: *const u8,
+    data_size: usize,
+    config: *mut WebPDecoderConfig,
+) -> *mut WebPIDecoder {
+    let mut tmp_features: WebPBitstreamFeatures = mem::zeroed();
+    let features: *mut WebPBitstreamFeatures = if config.is_null() {
+        &mut tmp_features
+    } else {
+        &mut (*config).input
+    };
+
+    // Parse the bitstream's features, if requested:
+    if !data.is_null()
+        && data_size > 0
+        && WebPGetFeaturesInternal(data, data_size, features, WEBP_DECODER_ABI_VERSION as c_int)
+            != VP8_STATUS_OK
+    {
+        return ptr::null_mut();
+    }
+
+    // Create an instance of the incremental decoder
+    let idec = if !config.is_null() {
+        NewDecoder(&mut (*config).output, features)
+    } else {
+        NewDecoder(ptr::null_mut(), features)
+    };
+    if idec.is_null() {
+        return ptr::null_mut();
+    }
+    // Finish initialization
+    if !config.is_null() {
+        (*idec).params_.options = &(*config).options;
+    }
+    idec
+}
+
+// Deletes the WebPIDecoder object and associated memory. Must always be called
+// if WebPINewDecoder, WebPINewRGB or WebPINewYUV succeeded.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIDelete(idec: *mut WebPIDecoder) {
+    let idec = match idec.as_mut() {
+        Some(idec) => idec,
+        None => return,
+    };
+    if !idec.dec_.is_null() {
+        if idec.is_lossless_ == 0 {
+            if idec.state_ == STATE_VP8_DATA {
+                // Synchronize the thread, clean-up and check for errors.
+                VP8ExitCritical(idec.dec_ as *mut VP8Decoder, &mut idec.io_);
+            }
+            VP8Delete(idec.dec_ as *mut VP8Decoder);
+        } else {
+            VP8LDelete(idec.dec_ as *mut VP8LDecoder);
+        }
+    }
+    ClearMemBuffer(&mut idec.mem_);
+    WebPFreeDecBuffer(&mut idec.output_);
+    WebPSafeFree(idec as *mut WebPIDecoder as *mut c_void);
+}
+
+//------------------------------------------------------------------------------
+// Wrapper toward WebPINewDecoder
+
+// This function allocates and initializes an incremental-decoder object, which
+// will output the RGB/A samples specified by 'csp' into a preallocated
+// buffer 'output_buffer'. The size of this buffer is at least
+// 'output_buffer_size' and the stride (distance in bytes between two scanlines)
+// is specified by 'output_stride'.
+// Additionally, output_buffer can be passed NULL in which case the output
+// buffer will be allocated automatically when the decoding starts. The
+// colorspace 'csp' is taken into account for allocating this buffer. All other
+// parameters are ignored.
+// Returns NULL if the allocation failed, or if some parameters are invalid.
+#[no_mangle]
+pub unsafe extern "C" fn WebPINewRGB(
+    csp: WEBP_CSP_MODE,
+    output_buffer: *mut u8,
+    mut output_buffer_size: usize,
+    mut output_stride: c_int,
+) -> *mut WebPIDecoder {
+    let is_external_memory = !output_buffer.is_null() as c_int;
+
+    if csp >= MODE_YUV {
+        return ptr::null_mut();
+    }
+    if is_external_memory == 0 {
+        // Overwrite parameters to sane values.
+        output_buffer_size = 0;
+        output_stride = 0;
+    } else if output_stride == 0 || output_buffer_size == 0 {
+        // A buffer was passed. Validate the other params.
+        return ptr::null_mut(); // invalid parameter.
+    }
+    let idec = WebPINewDecoder(ptr::null_mut());
+    if idec.is_null() {
+        return ptr::null_mut();
+    }
+    let output = &mut (*idec).output_;
+    output.colorspace = csp;
+    output.is_external_memory = is_external_memory;
+    output.u.RGBA.rgba = output_buffer;
+    output.u.RGBA.stride = output_stride;
+    output.u.RGBA.size = output_buffer_size;
+    idec
+}
+
+// This function allocates and initializes an incremental-decoder object, which
+// will output the raw luma/chroma samples into a preallocated planes if
+// supplied. The luma plane is specified by its pointer 'luma', its size
+// 'luma_size' and its stride 'luma_stride'. Similarly, the chroma-u plane
+// is specified by the 'u', 'u_size' and 'u_stride' parameters, and the chroma-v
+// plane by 'v' and 'v_size'. And same for the alpha-plane. The 'a' pointer
+// can be pass NULL in case one is not interested in the transparency plane.
+// Conversely, 'luma' can be passed NULL if no preallocated planes are supplied.
+// In this case, the output buffer will be automatically allocated (using
+// MODE_YUVA) when decoding starts. All parameters are then ignored.
+// Returns NULL if the allocation failed or if a parameter is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn WebPINewYUVA(
+    luma: *mut u8,
+    mut luma_size: usize,
+    mut luma_stride: c_int,
+    mut u: *mut u8,
+    mut u_size: usize,
+    mut u_stride: c_int,
+    mut v: *mut u8,
+    mut v_size: usize,
+    mut v_stride: c_int,
+    mut a: *mut u8,
+    mut a_size: usize,
+    mut a_stride: c_int,
+) -> *mut WebPIDecoder {
+    let is_external_memory = !luma.is_null() as c_int;
+    let colorspace;
+
+    if is_external_memory == 0 {
+        // Overwrite parameters to sane values.
+        luma_size = 0;
+        u_size = 0;
+        v_size = 0;
+        a_size = 0;
+        luma_stride = 0;
+        u_stride = 0;
+        v_stride = 0;
+        a_stride = 0;
+        u = ptr::null_mut();
+        v = ptr::null_mut();
+        a = ptr::null_mut();
+        colorspace = MODE_YUVA;
+    } else {
+        // A luma buffer was passed. Validate the other parameters.
+        if u.is_null() || v.is_null() {
+            return ptr::null_mut();
+        }
+        if luma_size == 0 || u_size == 0 || v_size == 0 {
+            return ptr::null_mut();
+        }
+        if luma_stride == 0 || u_stride == 0 || v_stride == 0 {
+            return ptr::null_mut();
+        }
+        if !a.is_null() && (a_size == 0 || a_stride == 0) {
+            return ptr::null_mut();
+        }
+        colorspace = if a.is_null() { MODE_YUV } else { MODE_YUVA };
+    }
+
+    let idec = WebPINewDecoder(ptr::null_mut());
+    if idec.is_null() {
+        return ptr::null_mut();
+    }
+
+    let output = &mut (*idec).output_;
+    output.colorspace = colorspace;
+    output.is_external_memory = is_external_memory;
+    output.u.YUVA.y = luma;
+    output.u.YUVA.y_stride = luma_stride;
+    output.u.YUVA.y_size = luma_size;
+    output.u.YUVA.u = u;
+    output.u.YUVA.u_stride = u_stride;
+    output.u.YUVA.u_size = u_size;
+    output.u.YUVA.v = v;
+    output.u.YUVA.v_stride = v_stride;
+    output.u.YUVA.v_size = v_size;
+    output.u.YUVA.a = a;
+    output.u.YUVA.a_stride = a_stride;
+    output.u.YUVA.a_size = a_size;
+    idec
+}
+
+// Deprecated version of the above, without the alpha plane.
+// Kept for backward compatibility.
+#[no_mangle]
+pub unsafe extern "C" fn WebPINewYUV(
+    luma: *mut u8,
+    luma_size: usize,
+    luma_stride: c_int,
+    u: *mut u8,
+    u_size: usize,
+    u_stride: c_int,
+    v: *mut u8,
+    v_size: usize,
+    v_stride: c_int,
+) -> *mut WebPIDecoder {
+    WebPINewYUVA(
+        luma,
+        luma_size,
+        luma_stride,
+        u,
+        u_size,
+        u_stride,
+        v,
+        v_size,
+        v_stride,
+        ptr::null_mut(),
+        0,
+        0,
+    )
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+fn IDecCheckStatus(idec: &WebPIDecoder) -> VP8StatusCode {
+    if idec.state_ == STATE_ERROR {
+        return VP8_STATUS_BITSTREAM_ERROR;
+    }
+    if idec.state_ == STATE_DONE {
+        return VP8_STATUS_OK;
+    }
+    VP8_STATUS_SUSPENDED
+}
+
+// Copies and decodes the next available data. Returns VP8_STATUS_OK when
+// the image is successfully decoded. Returns VP8_STATUS_SUSPENDED when more
+// data is expected. Returns error in other cases.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIAppend(
+    idec: *mut WebPIDecoder,
+    data: *const u8,
+    data_size: usize,
+) -> VP8StatusCode {
+    let idec = match idec.as_mut() {
+        Some(idec) if !data.is_null() => idec,
+        _ => return VP8_STATUS_INVALID_PARAM,
+    };
+    let status = IDecCheckStatus(idec);
+    if status != VP8_STATUS_SUSPENDED {
+        return status;
+    }
+    // Check mixed calls between RemapMemBuffer and AppendToMemBuffer.
+    if CheckMemBufferMode(&mut idec.mem_, MEM_MODE_APPEND) == 0 {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    // Append data to memory buffer
+    if AppendToMemBuffer(idec, data, data_size) == 0 {
+        return VP8_STATUS_OUT_OF_MEMORY;
+    }
+    IDecode(idec)
+}
+
+// A variant of the above function to be used when data buffer contains
+// partial data from the beginning. In this case data buffer is not copied
+// to the internal memory.
+// Note that the value of the 'data' pointer can change between calls to
+// WebPIUpdate, for instance when the data buffer is resized to fit larger data.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIUpdate(
+    idec: *mut WebPIDecoder,
+    data: *const u8,
+    data_size: usize,
+) -> VP8StatusCode {
+    let idec = match idec.as_mut() {
+        Some(idec) if !data.is_null() => idec,
+        _ => return VP8_STATUS_INVALID_PARAM,
+    };
+    let status = IDecCheckStatus(idec);
+    if status != VP8_STATUS_SUSPENDED {
+        return status;
+    }
+    // Check mixed calls between RemapMemBuffer and AppendToMemBuffer.
+    if CheckMemBufferMode(&mut idec.mem_, MEM_MODE_MAP) == 0 {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    // Make the memory buffer point to the new buffer
+    if RemapMemBuffer(idec, data, data_size) == 0 {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    IDecode(idec)
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn GetOutputBuffer(idec: *const WebPIDecoder) -> *const WebPDecBuffer {
+    let idec = match idec.as_ref() {
+        Some(idec) if !idec.dec_.is_null() => idec,
+        _ => return ptr::null(),
+    };
+    if idec.state_ <= STATE_VP8_PARTS0 {
+        return ptr::null();
+    }
+    if !idec.final_output_.is_null() {
+        return ptr::null(); // not yet slow-copied
+    }
+    idec.params_.output
+}
+
+// Generic call to retrieve information about the displayable area.
+// If non NULL, the left/right/width/height pointers are filled with the visible
+// rectangular area so far.
+// Returns NULL in case the incremental decoder object is in an invalid state.
+// Otherwise returns the pointer to the internal representation. This structure
+// is read-only, tied to WebPIDecoder's lifespan and should not be modified.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIDecodedArea(
+    idec: *const WebPIDecoder,
+    left: *mut c_int,
+    top: *mut c_int,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> *const WebPDecBuffer {
+    let src = GetOutputBuffer(idec);
+    if !left.is_null() {
+        *left = 0;
+    }
+    if !top.is_null() {
+        *top = 0;
+    }
+    if !src.is_null() {
+        if !width.is_null() {
+            *width = (*src).width;
+        }
+        if !height.is_null() {
+            *height = (*idec).params_.last_y;
+        }
+    } else {
+        if !width.is_null() {
+            *width = 0;
+        }
+        if !height.is_null() {
+            *height = 0;
+        }
+    }
+    src
+}
+
+// Returns the RGB/A image decoded so far. Returns NULL if output params
+// are not initialized yet. The RGB/A output type corresponds to the colorspace
+// specified during call to WebPINewDecoder() or WebPINewRGB().
+// *last_y is the index of last decoded row in raster scan order. Some pointers
+// (*last_y, *width etc.) can be NULL if corresponding information is not
+// needed. The values in these pointers are only valid on successful (non-NULL)
+// return.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIDecGetRGB(
+    idec: *const WebPIDecoder,
+    last_y: *mut c_int,
+    width: *mut c_int,
+    height: *mut c_int,
+    stride: *mut c_int,
+) -> *mut u8 {
+    let src = match GetOutputBuffer(idec).as_ref() {
+        Some(src) => src,
+        None => return ptr::null_mut(),
+    };
+    if src.colorspace >= MODE_YUV {
+        return ptr::null_mut();
+    }
+
+    if !last_y.is_null() {
+        *last_y = (*idec).params_.last_y;
+    }
+    if !width.is_null() {
+        *width = src.width;
+    }
+    if !height.is_null() {
+        *height = src.height;
+    }
+    if !stride.is_null() {
+        *stride = src.u.RGBA.stride;
+    }
+
+    src.u.RGBA.rgba
+}
+
+// Same as above function to get a YUVA image. Returns pointer to the luma
+// plane or NULL in case of error. If there is no alpha information
+// the alpha pointer '*a' will be returned NULL.
+#[no_mangle]
+pub unsafe extern "C" fn WebPIDecGetYUVA(
+    idec: *const WebPIDecoder,
+    last_y: *mut c_int,
+    u: *mut *mut u8,
+    v: *mut *mut u8,
+    a: *mut *mut u8,
+    width: *mut c_int,
+    height: *mut c_int,
+    stride: *mut c_int,
+    uv_stride: *mut c_int,
+    a_stride: *mut c_int,
+) -> *mut u8 {
+    let src = match GetOutputBuffer(idec).as_ref() {
+        Some(src) => src,
+        None => return ptr::null_mut(),
+    };
+    if src.colorspace < MODE_YUV {
+        return ptr::null_mut();
+    }
+
+    if !last_y.is_null() {
+        *last_y = (*idec).params_.last_y;
+    }
+    if !u.is_null() {
+        *u = src.u.YUVA.u;
+    }
+    if !v.is_null() {
+        *v = src.u.YUVA.v;
+    }
+    if !a.is_null() {
+        *a = src.u.YUVA.a;
+    }
+    if !width.is_null() {
+        *width = src.width;
+    }
+    if !height.is_null() {
+        *height = src.height;
+    }
+    if !stride.is_null() {
+        *stride = src.u.YUVA.y_stride;
+    }
+    if !uv_stride.is_null() {
+        *uv_stride = src.u.YUVA.u_stride;
+    }
+    if !a_stride.is_null() {
+        *a_stride = src.u.YUVA.a_stride;
+    }
+
+    src.u.YUVA.y
+}
+
+// Set the custom IO function pointers and user-data. The setter for IO hooks
+// should be called before initiating incremental decoding. Returns true if
+// WebPIDecoder object is successfully modified, false otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn WebPISetIOHooks(
+    idec: *mut WebPIDecoder,
+    put: VP8IoPutHook,
+    setup: VP8IoSetupHook,
+    teardown: VP8IoTeardownHook,
+    user_data: *mut c_void,
+) -> c_int {
+    let idec = match idec.as_mut() {
+        Some(idec) if idec.state_ <= STATE_WEBP_HEADER => idec,
+        _ => return 0,
+    };
+
+    idec.io_.put = put;
+    idec.io_.setup = setup;
+    idec.io_.teardown = teardown;
+    idec.io_.opaque = user_data;
+
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use dec::webp_dec::{WebPGetInfo, WebPInitDecoderConfigInternal};
+    use decode::{decode_rgba, MODE_BGRA, MODE_RGBA, MODE_RGB_565};
+    use encode::{encode_lossless_rgba, encode_rgba, encode_rgba_with_config, EncoderConfig};
+    use sys::WebPDecoderOptions;
+    use test_util::{check_parity, rgba_images, Digest};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(improper_ctypes)]
+    extern "C" {
+        fn WebPRef_WebPINewRGB(
+            csp: WEBP_CSP_MODE,
+            output_buffer: *mut u8,
+            output_buffer_size: usize,
+            output_stride: c_int,
+        ) -> *mut WebPIDecoder;
+        fn WebPRef_WebPINewYUVA(
+            luma: *mut u8,
+            luma_size: usize,
+            luma_stride: c_int,
+            u: *mut u8,
+            u_size: usize,
+            u_stride: c_int,
+            v: *mut u8,
+            v_size: usize,
+            v_stride: c_int,
+            a: *mut u8,
+            a_size: usize,
+            a_stride: c_int,
+        ) -> *mut WebPIDecoder;
+        fn WebPRef_WebPIDecode(
+            data: *const u8,
+            data_size: usize,
+            config: *mut WebPDecoderConfig,
+        ) -> *mut WebPIDecoder;
+        fn WebPRef_WebPIDelete(idec: *mut WebPIDecoder);
+        fn WebPRef_WebPIAppend(
+            idec: *mut WebPIDecoder,
+            data: *const u8,
+            data_size: usize,
+        ) -> VP8StatusCode;
+        fn WebPRef_WebPIUpdate(
+            idec: *mut WebPIDecoder,
+            data: *const u8,
+            data_size: usize,
+        ) -> VP8StatusCode;
+        fn WebPRef_WebPIDecodedArea(
+            idec: *const WebPIDecoder,
+            left: *mut c_int,
+            top: *mut c_int,
+            width: *mut c_int,
+            height: *mut c_int,
+        ) -> *const WebPDecBuffer;
+        fn WebPRef_WebPIDecGetRGB(
+            idec: *const WebPIDecoder,
+            last_y: *mut c_int,
+            width: *mut c_int,
+            height: *mut c_int,
+            stride: *mut c_int,
+        ) -> *mut u8;
+        fn WebPRef_WebPIDecGetYUVA(
+            idec: *const WebPIDecoder,
+            last_y: *mut c_int,
+            u: *mut *mut u8,
+            v: *mut *mut u8,
+            a: *mut *mut u8,
+            width: *mut c_int,
+            height: *mut c_int,
+            stride: *mut c_int,
+            uv_stride: *mut c_int,
+            a_stride: *mut c_int,
+        ) -> *mut u8;
+    }
+
+    // The incremental decoding functions of either version.
+    struct Api {
+        new_rgb: unsafe extern "C" fn(WEBP_CSP_MODE, *mut u8, usize, c_int) -> *mut WebPIDecoder,
+        new_yuva: unsafe extern "C" fn(
+            *mut u8,
+            usize,
+            c_int,
+            *mut u8,
+            usize,
+            c_int,
+            *mut u8,
+            usize,
+            c_int,
+            *mut u8,
+            usize,
+            c_int,
+        ) -> *mut WebPIDecoder,
+        decode: unsafe extern "C" fn(*const u8, usize, *mut WebPDecoderConfig) -> *mut WebPIDecoder,
+        delete: unsafe extern "C" fn(*mut WebPIDecoder),
+        append: unsafe extern "C" fn(*mut WebPIDecoder, *const u8, usize) -> VP8StatusCode,
+        update: unsafe extern "C" fn(*mut WebPIDecoder, *const u8, usize) -> VP8StatusCode,
+        decoded_area: unsafe extern "C" fn(
+            *const WebPIDecoder,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+        ) -> *const WebPDecBuffer,
+        get_rgb: unsafe extern "C" fn(
+            *const WebPIDecoder,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+        ) -> *mut u8,
+        get_yuva: unsafe extern "C" fn(
+            *const WebPIDecoder,
+            *mut c_int,
+            *mut *mut u8,
+            *mut *mut u8,
+            *mut *mut u8,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+            *mut c_int,
+        ) -> *mut u8,
+    }
+
+    const PORT: Api = Api {
+        new_rgb: WebPINewRGB,
+        new_yuva: WebPINewYUVA,
+        decode: WebPIDecode,
+        delete: WebPIDelete,
+        append: WebPIAppend,
+        update: WebPIUpdate,
+        decoded_area: WebPIDecodedArea,
+        get_rgb: WebPIDecGetRGB,
+        get_yuva: WebPIDecGetYUVA,
+    };
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = Api {
+        new_rgb: WebPRef_WebPINewRGB,
+        new_yuva: WebPRef_WebPINewYUVA,
+        decode: WebPRef_WebPIDecode,
+        delete: WebPRef_WebPIDelete,
+        append: WebPRef_WebPIAppend,
+        update: WebPRef_WebPIUpdate,
+        decoded_area: WebPRef_WebPIDecodedArea,
+        get_rgb: WebPRef_WebPIDecGetRGB,
+        get_yuva: WebPRef_WebPIDecGetYUVA,
+    };
+
+    // Lossy (with and without alpha, with several partitions) and lossless
+    // files.
+    fn corpus() -> Vec<Vec<u8>> {
+        let mut corpus = vec![
+            include_bytes!("../../libwebp-1.0.0/examples/test.webp").to_vec(),
+            include_bytes!("../../examples/rust-logo-256x256.webp").to_vec(),
+        ];
+        let images = rgba_images(0x5678, 0x1f, &[(67, 45, 0, true), (40, 33, 5, true)]);
+        for (width, height, rgba) in images {
+            corpus.push(
+                encode_lossless_rgba(&rgba, width, height, width * 4)
+                    .unwrap()
+                    .to_vec(),
+            );
+            corpus.push(
+                encode_rgba(&rgba, width, height, width * 4, 80.0)
+                    .unwrap()
+                    .to_vec(),
+            );
+            let config = EncoderConfig {
+                partitions: 3,
+                alpha_compression: 0,
+                ..EncoderConfig::default()
+            };
+            let webp = encode_rgba_with_config(&rgba, width, height, width * 4, &config).unwrap();
+            corpus.push(webp.to_vec());
+        }
+        corpus
+    }
+
+    // What the caller of the incremental decoder gets to see: the status and
+    // the decoded area after each call, and the final RGBA samples.
+    #[derive(Debug, PartialEq)]
+    struct Progress {
+        steps: Vec<(VP8StatusCode, c_int, c_int, c_int)>,
+        rgba: Option<Vec<u8>>,
+    }
+
+    impl Progress {
+        fn update(&self, digest: &mut Digest) {
+            for &(status, last_y, width, height) in &self.steps {
+                digest.update_i32(&[status as i32, last_y, width, height]);
+            }
+            if let Some(ref rgba) = self.rgba {
+                digest.update(rgba);
+            }
+        }
+    }
+
+    // Decodes with the port, adding the progress to 'digest', and compares
+    // with the reference.
+    fn check_rgb(
+        data: &[u8],
+        csp: WEBP_CSP_MODE,
+        step: usize,
+        append: bool,
+        digest: &mut Digest,
+    ) -> Progress {
+        let progress = decode_rgb(data, csp, step, append, &PORT);
+        progress.update(digest);
+        #[cfg(feature = "c-reference")]
+        assert_eq!(progress, decode_rgb(data, csp, step, append, &REFERENCE));
+        progress
+    }
+
+    // Feeds 'data' by pieces of 'step' bytes, with WebPIAppend() (which copies
+    // the data) or WebPIUpdate() (which maps the growing prefix).
+    fn decode_rgb(
+        data: &[u8],
+        csp: WEBP_CSP_MODE,
+        step: usize,
+        append: bool,
+        api: &Api,
+    ) -> Progress {
+        let mut steps = Vec::new();
+        let mut rgba = None;
+        unsafe {
+            let idec = (api.new_rgb)(csp, ptr::null_mut(), 0, 0);
+            assert!(!idec.is_null());
+            let mut end = 0;
+            while end < data.len() {
+                let start = end;
+                end = (end + step).min(data.len());
+                let status = if append {
+                    (api.append)(idec, data[start..].as_ptr(), end - start)
+                } else {
+                    (api.update)(idec, data.as_ptr(), end)
+                };
+                let (mut last_y, mut width, mut height, mut stride) = (-1, -1, -1, -1);
+                let out = (api.get_rgb)(idec, &mut last_y, &mut width, &mut height, &mut stride);
+                steps.push((status, last_y, width, height));
+                if status == VP8_STATUS_OK {
+                    let len = (stride * height) as usize;
+                    rgba = Some(slice::from_raw_parts(out, len).to_vec());
+                }
+                if status != VP8_STATUS_SUSPENDED {
+                    break;
+                }
+            }
+            (api.delete)(idec);
+        }
+        Progress { steps, rgba }
+    }
+
+    #[test]
+    fn test_incremental_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            let full = decode_rgba(&data).unwrap();
+            for &step in &[1, 7, 100, 1000, data.len()] {
+                for &append in &[true, false] {
+                    let progress = check_rgb(&data, MODE_RGBA, step, append, &mut digest);
+                    assert_eq!(progress.rgba.as_ref().unwrap()[..], full.2[..]);
+                }
+            }
+            for &csp in &[MODE_BGRA, MODE_RGB_565] {
+                check_rgb(&data, csp, 37, true, &mut digest);
+            }
+        }
+        check_parity("dec::idec_dec::test_incremental_parity", digest);
+    }
+
+    #[test]
+    fn test_incremental_truncated_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            for len in (0..data.len()).step_by(53) {
+                for &append in &[true, false] {
+                    check_rgb(&data[..len], MODE_RGBA, 29, append, &mut digest);
+                }
+            }
+            // Corrupted data.
+            for pos in (0..data.len()).step_by(31) {
+                let mut corrupted = data.clone();
+                corrupted[pos] ^= 0x42;
+                check_rgb(&corrupted, MODE_RGBA, 61, true, &mut digest);
+            }
+        }
+        check_parity("dec::idec_dec::test_incremental_truncated_parity", digest);
+    }
+
+    // Decodes 'data' in two pieces with WebPIDecode() and the given options,
+    // into YUVA, and returns the decoded area after each piece along with the
+    // final planes.
+    fn decode_yuva(
+        data: &[u8],
+        options: &WebPDecoderOptions,
+        api: &Api,
+    ) -> (Vec<(VP8StatusCode, [c_int; 4])>, Vec<u8>) {
+        let mut steps = Vec::new();
+        let mut planes = Vec::new();
+        unsafe {
+            let mut config: WebPDecoderConfig = mem::zeroed();
+            assert_eq!(
+                WebPInitDecoderConfigInternal(&mut config, WEBP_DECODER_ABI_VERSION as c_int),
+                1
+            );
+            config.options = ptr::read(options);
+            config.output.colorspace = MODE_YUVA;
+            let idec = (api.decode)(data.as_ptr(), data.len(), &mut config);
+            assert!(!idec.is_null());
+            for piece in data.chunks(data.len() / 2 + 1) {
+                let mut area = [-1; 4];
+                let [left, top, width, height] = &mut area;
+                let status = (api.append)(idec, piece.as_ptr(), piece.len());
+                (api.decoded_area)(idec, left, top, width, height);
+                steps.push((status, area));
+                if status != VP8_STATUS_SUSPENDED {
+                    break;
+                }
+            }
+            let (mut u, mut v, mut a) = (ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            let (mut last_y, mut width, mut height) = (0, 0, 0);
+            let (mut stride, mut uv_stride, mut a_stride) = (0, 0, 0);
+            let y = (api.get_yuva)(
+                idec,
+                &mut last_y,
+                &mut u,
+                &mut v,
+                &mut a,
+                &mut width,
+                &mut height,
+                &mut stride,
+                &mut uv_stride,
+                &mut a_stride,
+            );
+            if !y.is_null() {
+                let uv_height = (height + 1) / 2;
+                planes.extend_from_slice(slice::from_raw_parts(y, (stride * height) as usize));
+                planes
+                    .extend_from_slice(slice::from_raw_parts(u, (uv_stride * uv_height) as usize));
+                planes
+                    .extend_from_slice(slice::from_raw_parts(v, (uv_stride * uv_height) as usize));
+                if !a.is_null() {
+                    planes
+                        .extend_from_slice(slice::from_raw_parts(a, (a_stride * height) as usize));
+                }
+            }
+            (api.delete)(idec);
+            WebPFreeDecBuffer(&mut config.output);
+        }
+        (steps, planes)
+    }
+
+    // Decodes into YUVA with the port, adding the result to 'digest', and
+    // compares with the reference.
+    fn check_yuva(data: &[u8], options: &WebPDecoderOptions, digest: &mut Digest) {
+        let (steps, planes) = decode_yuva(data, options, &PORT);
+        for &(status, area) in &steps {
+            digest.update_i32(&[status as i32]);
+            digest.update_i32(&area);
+        }
+        digest.update(&planes);
+        #[cfg(feature = "c-reference")]
+        assert_eq!((steps, planes), decode_yuva(data, options, &REFERENCE));
+    }
+
+    #[test]
+    fn test_incremental_options_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            let (mut width, mut height) = (0, 0);
+            assert_eq!(
+                unsafe { WebPGetInfo(data.as_ptr(), data.len(), &mut width, &mut height) },
+                1
+            );
+            let mut options: WebPDecoderOptions = unsafe { mem::zeroed() };
+            check_yuva(&data, &options, &mut digest);
+            options.use_cropping = 1;
+            options.crop_left = width / 4;
+            options.crop_top = height / 3;
+            options.crop_width = width / 2;
+            options.crop_height = height / 2;
+            check_yuva(&data, &options, &mut digest);
+            options.use_scaling = 1;
+            options.scaled_width = width / 3 + 1;
+            options.scaled_height = height / 2 + 1;
+            options.flip = 1;
+            check_yuva(&data, &options, &mut digest);
+        }
+        check_parity("dec::idec_dec::test_incremental_options_parity", digest);
+    }
+
+    // Decodes 'data' into caller-supplied planes, 'step' bytes at a time.
+    fn decode_external_yuva(data: &[u8], step: usize, api: &Api) -> (Vec<VP8StatusCode>, Vec<u8>) {
+        let (mut width, mut height) = (0, 0);
+        assert_eq!(
+            unsafe { WebPGetInfo(data.as_ptr(), data.len(), &mut width, &mut height) },
+            1
+        );
+        let (stride, uv_stride) = (width + 3, (width + 1) / 2 + 5);
+        let uv_height = (height + 1) / 2;
+        let mut y = vec![0u8; (stride * height) as usize];
+        let mut u = vec![0u8; (uv_stride * uv_height) as usize];
+        let mut v = vec![0u8; (uv_stride * uv_height) as usize];
+        let mut a = vec![0u8; (stride * height) as usize];
+        let mut statuses = Vec::new();
+        unsafe {
+            let idec = (api.new_yuva)(
+                y.as_mut_ptr(),
+                y.len(),
+                stride,
+                u.as_mut_ptr(),
+                u.len(),
+                uv_stride,
+                v.as_mut_ptr(),
+                v.len(),
+                uv_stride,
+                a.as_mut_ptr(),
+                a.len(),
+                stride,
+            );
+            assert!(!idec.is_null());
+            for piece in data.chunks(step) {
+                let status = (api.append)(idec, piece.as_ptr(), piece.len());
+                statuses.push(status);
+                if status != VP8_STATUS_SUSPENDED {
+                    break;
+                }
+            }
+            (api.delete)(idec);
+        }
+        (statuses, [y, u, v, a].concat())
+    }
+
+    #[test]
+    fn test_incremental_external_memory_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            let (statuses, planes) = decode_external_yuva(&data, 97, &PORT);
+            assert_eq!(statuses.last(), Some(&VP8_STATUS_OK));
+            for &status in &statuses {
+                digest.update_i32(&[status as i32]);
+            }
+            digest.update(&planes);
+            #[cfg(feature = "c-reference")]
+            assert_eq!(
+                (statuses, planes),
+                decode_external_yuva(&data, 97, &REFERENCE)
+            );
+        }
+        check_parity(
+            "dec::idec_dec::test_incremental_external_memory_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let data = include_bytes!("../../libwebp-1.0.0/examples/test.webp");
+        unsafe {
+            assert!(WebPINewRGB(MODE_YUV, ptr::null_mut(), 0, 0).is_null());
+            let mut buf = [0u8; 16];
+            assert!(WebPINewRGB(MODE_RGBA, buf.as_mut_ptr(), 0, 4).is_null());
+            assert!(WebPINewYUVA(
+                buf.as_mut_ptr(),
+                16,
+                4,
+                ptr::null_mut(),
+                0,
+                0,
+                ptr::null_mut(),
+                0,
+                0,
+                ptr::null_mut(),
+                0,
+                0
+            )
+            .is_null());
+            assert_eq!(
+                WebPIAppend(ptr::null_mut(), data.as_ptr(), data.len()),
+                VP8_STATUS_INVALID_PARAM
+            );
+            assert!(WebPIDecGetRGB(
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut()
+            )
+            .is_null());
+
+            // Mixing WebPIAppend() and WebPIUpdate() is an error.
+            let idec = WebPINewRGB(MODE_RGBA, ptr::null_mut(), 0, 0);
+            assert_eq!(WebPIAppend(idec, data.as_ptr(), 100), VP8_STATUS_SUSPENDED);
+            assert_eq!(
+                WebPIUpdate(idec, data.as_ptr(), 200),
+                VP8_STATUS_INVALID_PARAM
+            );
+            assert_eq!(WebPISetIOHooks(idec, None, None, None, ptr::null_mut()), 0);
+            WebPIDelete(idec);
+
+            // So is remapping to a shorter buffer.
+            let idec = WebPINewRGB(MODE_RGBA, ptr::null_mut(), 0, 0);
+            assert_eq!(WebPIUpdate(idec, data.as_ptr(), 200), VP8_STATUS_SUSPENDED);
+            assert_eq!(
+                WebPIUpdate(idec, data.as_ptr(), 100),
+                VP8_STATUS_INVALID_PARAM
+            );
+            WebPIDelete(idec);
+        }
+    }
+}
@@ -1,6 +1,1084 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// functions for sample output.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
 use dec::vp8_dec::VP8Io;
+use dec::webp_dec::WebPIoInitFromOptions;
 use dec::webpi_dec::WebPDecParams;
+use decode::{
+    MODE_Argb, MODE_rgbA_4444, WebPIsAlphaMode, WebPIsPremultipliedMode, WebPIsRGBMode, MODE_ARGB,
+    MODE_RGBA_4444, MODE_YUV, MODE_YUVA,
+};
+use dsp::alpha_processing::{
+    WebPApplyAlphaMultiply, WebPApplyAlphaMultiply4444, WebPDispatchAlpha, WebPInitAlphaProcessing,
+    WebPMultRows,
+};
+use dsp::dsp::WEBP_SWAP_16BIT_CSP;
+use dsp::rescaler::WebPRescalerExportRow;
+use dsp::upsampling::{
+    WebPInitUpsamplers, WebPInitYUV444Converters, WebPUpsamplers, WebPYUV444Converters,
+};
+use dsp::yuv::{WebPInitSamplers, WebPSamplerProcessPlane, WebPSamplers};
+use utils::rescaler_utils::{
+    rescaler_t, WebPRescaleNeededLines, WebPRescaler, WebPRescalerExport,
+    WebPRescalerHasPendingOutput, WebPRescalerImport, WebPRescalerInit,
+};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc, WEBP_ALIGN, WEBP_ALIGN_CST};
+
+//------------------------------------------------------------------------------
+// Main YUV<->RGB conversion functions
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitYUV(io: *const VP8Io, p: *mut WebPDecParams) -> c_int {
+    let io = &*io;
+    let output = &mut *(*p).output;
+    let buf = &output.u.YUVA;
+    let y_dst = buf.y.offset(io.mb_y as isize * buf.y_stride as isize);
+    let u_dst = buf
+        .u
+        .offset((io.mb_y >> 1) as isize * buf.u_stride as isize);
+    let v_dst = buf
+        .v
+        .offset((io.mb_y >> 1) as isize * buf.v_stride as isize);
+    let mb_w = io.mb_w;
+    let mb_h = io.mb_h;
+    let uv_w = (mb_w + 1) / 2;
+    let uv_h = (mb_h + 1) / 2;
+    for j in 0..mb_h as isize {
+        ptr::copy_nonoverlapping(
+            io.y.offset(j * io.y_stride as isize),
+            y_dst.offset(j * buf.y_stride as isize),
+            mb_w as usize,
+        );
+    }
+    for j in 0..uv_h as isize {
+        ptr::copy_nonoverlapping(
+            io.u.offset(j * io.uv_stride as isize),
+            u_dst.offset(j * buf.u_stride as isize),
+            uv_w as usize,
+        );
+        ptr::copy_nonoverlapping(
+            io.v.offset(j * io.uv_stride as isize),
+            v_dst.offset(j * buf.v_stride as isize),
+            uv_w as usize,
+        );
+    }
+    io.mb_h
+}
+
+// Point-sampling U/V sampler.
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitSampledRGB(io: *const VP8Io, p: *mut WebPDecParams) -> c_int {
+    let io = &*io;
+    let output = &mut *(*p).output;
+    let buf = &output.u.RGBA;
+    let dst = buf.rgba.offset(io.mb_y as isize * buf.stride as isize);
+    WebPSamplerProcessPlane(
+        io.y,
+        io.y_stride,
+        io.u,
+        io.v,
+        io.uv_stride,
+        dst,
+        buf.stride,
+        io.mb_w,
+        io.mb_h,
+        WebPSamplers[output.colorspace as usize],
+    );
+    io.mb_h
+}
+
+//------------------------------------------------------------------------------
+// Fancy upsampling
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitFancyRGB(io: *const VP8Io, p: *mut WebPDecParams) -> c_int {
+    let io = &*io;
+    let p = &mut *p;
+    let mut num_lines_out = io.mb_h; // a priori guess
+    let buf = &(*p.output).u.RGBA;
+    let stride = buf.stride as isize;
+    let mut dst = buf.rgba.offset(io.mb_y as isize * stride);
+    let upsample = WebPUpsamplers[(*p.output).colorspace as usize].unwrap();
+    let mut cur_y = io.y;
+    let mut cur_u = io.u;
+    let mut cur_v = io.v;
+    let mut top_u: *const u8 = p.tmp_u;
+    let mut top_v: *const u8 = p.tmp_v;
+    let mut y = io.mb_y;
+    let y_end = io.mb_y + io.mb_h;
+    let mb_w = io.mb_w;
+    let uv_w = (mb_w + 1) / 2;
+
+    if y == 0 {
+        // First line is special cased. We mirror the u/v samples at boundary.
+        upsample(
+            cur_y,
+            ptr::null(),
+            cur_u,
+            cur_v,
+            cur_u,
+            cur_v,
+            dst,
+            ptr::null_mut(),
+            mb_w,
+        );
+    } else {
+        // We can finish the left-over line from previous call.
+        upsample(
+            p.tmp_y,
+            cur_y,
+            top_u,
+            top_v,
+            cur_u,
+            cur_v,
+            dst.offset(-stride),
+            dst,
+            mb_w,
+        );
+        num_lines_out += 1;
+    }
+    // Loop over each output pairs of row.
+    while y + 2 < y_end {
+        top_u = cur_u;
+        top_v = cur_v;
+        cur_u = cur_u.offset(io.uv_stride as isize);
+        cur_v = cur_v.offset(io.uv_stride as isize);
+        dst = dst.offset(2 * stride);
+        cur_y = cur_y.offset(2 * io.y_stride as isize);
+        upsample(
+            cur_y.offset(-io.y_stride as isize),
+            cur_y,
+            top_u,
+            top_v,
+            cur_u,
+            cur_v,
+            dst.offset(-stride),
+            dst,
+            mb_w,
+        );
+        y += 2;
+    }
+    // move to last row
+    cur_y = cur_y.offset(io.y_stride as isize);
+    if io.crop_top + y_end < io.crop_bottom {
+        // Save the unfinished samples for next call (as we're not done yet).
+        ptr::copy_nonoverlapping(cur_y, p.tmp_y, mb_w as usize);
+        ptr::copy_nonoverlapping(cur_u, p.tmp_u, uv_w as usize);
+        ptr::copy_nonoverlapping(cur_v, p.tmp_v, uv_w as usize);
+        // The fancy upsampler leaves a row unfinished behind
+        // (except for the very last row)
+        num_lines_out -= 1;
+    } else {
+        // Process the very last row of even-sized picture
+        if (y_end & 1) == 0 {
+            upsample(
+                cur_y,
+                ptr::null(),
+                cur_u,
+                cur_v,
+                cur_u,
+                cur_v,
+                dst.offset(stride),
+                ptr::null_mut(),
+                mb_w,
+            );
+        }
+    }
+    num_lines_out
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn FillAlphaPlane(mut dst: *mut u8, w: c_int, h: c_int, stride: c_int) {
+    for _ in 0..h {
+        ptr::write_bytes(dst, 0xff, w as usize);
+        dst = dst.offset(stride as isize);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitAlphaYUV(
+    io: *const VP8Io,
+    p: *mut WebPDecParams,
+    expected_num_lines_out: c_int,
+) -> c_int {
+    let io = &*io;
+    let mut alpha = io.a;
+    let buf = &(*(*p).output).u.YUVA;
+    let mb_w = io.mb_w;
+    let mb_h = io.mb_h;
+    let mut dst = buf.a.offset(io.mb_y as isize * buf.a_stride as isize);
+    let _ = expected_num_lines_out;
+    c_assert!(expected_num_lines_out == mb_h);
+    if !alpha.is_null() {
+        for _ in 0..mb_h {
+            ptr::copy_nonoverlapping(alpha, dst, mb_w as usize);
+            alpha = alpha.offset(io.width as isize);
+            dst = dst.offset(buf.a_stride as isize);
+        }
+    } else if !buf.a.is_null() {
+        // the user requested alpha, but there is none, set it to opaque.
+        FillAlphaPlane(dst, mb_w, mb_h, buf.a_stride);
+    }
+    0
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetAlphaSourceRow(io: &VP8Io, alpha: &mut *const u8, num_rows: &mut c_int) -> c_int {
+    let mut start_y = io.mb_y;
+    *num_rows = io.mb_h;
+
+    // Compensate for the 1-line delay of the fancy upscaler.
+    // This is similar to EmitFancyRGB().
+    if io.fancy_upsampling != 0 {
+        if start_y == 0 {
+            // We don't process the last row yet. It'll be done during the next call.
+            *num_rows -= 1;
+        } else {
+            start_y -= 1;
+            // Fortunately, *alpha data is persistent, so we can go back
+            // one row and finish alpha blending, now that the fancy upscaler
+            // completed the YUV->RGB interpolation.
+            *alpha = alpha.offset(-io.width as isize);
+        }
+        if io.crop_top + io.mb_y + io.mb_h == io.crop_bottom {
+            // If it's the very last call, we process all the remaining rows!
+            *num_rows = io.crop_bottom - io.crop_top - start_y;
+        }
+    }
+    start_y
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitAlphaRGB(
+    io: *const VP8Io,
+    p: *mut WebPDecParams,
+    expected_num_lines_out: c_int,
+) -> c_int {
+    let io = &*io;
+    let mut alpha = io.a;
+    if !alpha.is_null() {
+        let mb_w = io.mb_w;
+        let colorspace = (*(*p).output).colorspace;
+        let alpha_first = (colorspace == MODE_ARGB || colorspace == MODE_Argb) as c_int;
+        let buf = &(*(*p).output).u.RGBA;
+        let mut num_rows = 0;
+        let start_y = GetAlphaSourceRow(io, &mut alpha, &mut num_rows);
+        let base_rgba = buf.rgba.offset(start_y as isize * buf.stride as isize);
+        let dst = base_rgba.offset(if alpha_first != 0 { 0 } else { 3 });
+        let has_alpha =
+            WebPDispatchAlpha.unwrap()(alpha, io.width, mb_w, num_rows, dst, buf.stride);
+        let _ = expected_num_lines_out;
+        c_assert!(expected_num_lines_out == num_rows);
+        // has_alpha is true if there's non-trivial alpha to premultiply with.
+        if has_alpha != 0 && WebPIsPremultipliedMode(colorspace) != 0 {
+            WebPApplyAlphaMultiply.unwrap()(base_rgba, alpha_first, mb_w, num_rows, buf.stride);
+        }
+    }
+    0
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitAlphaRGBA4444(
+    io: *const VP8Io,
+    p: *mut WebPDecParams,
+    expected_num_lines_out: c_int,
+) -> c_int {
+    let io = &*io;
+    let mut alpha = io.a;
+    if !alpha.is_null() {
+        let mb_w = io.mb_w;
+        let colorspace = (*(*p).output).colorspace;
+        let buf = &(*(*p).output).u.RGBA;
+        let mut num_rows = 0;
+        let start_y = GetAlphaSourceRow(io, &mut alpha, &mut num_rows);
+        let base_rgba = buf.rgba.offset(start_y as isize * buf.stride as isize);
+        let mut alpha_dst = if WEBP_SWAP_16BIT_CSP == 1 {
+            base_rgba
+        } else {
+            base_rgba.offset(1)
+        };
+        let mut alpha_mask: u32 = 0x0f;
+        for _ in 0..num_rows {
+            for i in 0..mb_w as isize {
+                // Fill in the alpha value (converted to 4 bits).
+                let alpha_value = (*alpha.offset(i) >> 4) as u32;
+                let d = alpha_dst.offset(2 * i);
+                *d = ((*d as u32 & 0xf0) | alpha_value) as u8;
+                alpha_mask &= alpha_value;
+            }
+            alpha = alpha.offset(io.width as isize);
+            alpha_dst = alpha_dst.offset(buf.stride as isize);
+        }
+        let _ = expected_num_lines_out;
+        c_assert!(expected_num_lines_out == num_rows);
+        if alpha_mask != 0x0f && WebPIsPremultipliedMode(colorspace) != 0 {
+            WebPApplyAlphaMultiply4444.unwrap()(base_rgba, mb_w, num_rows, buf.stride);
+        }
+    }
+    0
+}
+
+//------------------------------------------------------------------------------
+// YUV rescaling (no final RGB conversion needed)
+
+#[allow(non_snake_case)]
+unsafe fn Rescale(
+    mut src: *const u8,
+    src_stride: c_int,
+    mut new_lines: c_int,
+    wrk: *mut WebPRescaler,
+) -> c_int {
+    let mut num_lines_out = 0;
+    while new_lines > 0 {
+        // import new contributions of source rows.
+        let lines_in = WebPRescalerImport(wrk, new_lines, src, src_stride);
+        src = src.offset(lines_in as isize * src_stride as isize);
+        new_lines -= lines_in;
+        num_lines_out += WebPRescalerExport(wrk); // emit output row(s)
+    }
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitRescaledYUV(io: *const VP8Io, p: *mut WebPDecParams) -> c_int {
+    let io = &*io;
+    let p = &mut *p;
+    let mb_h = io.mb_h;
+    let uv_mb_h = (mb_h + 1) >> 1;
+    let scaler = p.scaler_y;
+    if WebPIsAlphaMode((*p.output).colorspace) != 0 && !io.a.is_null() {
+        // Before rescaling, we premultiply the luma directly into the io->y
+        // internal buffer. This is OK since these samples are not used for
+        // intra-prediction (the top samples are saved in cache_y_/u_/v_).
+        // But we need to cast the const away, though.
+        WebPMultRows(
+            io.y as *mut u8,
+            io.y_stride,
+            io.a,
+            io.width,
+            io.mb_w,
+            mb_h,
+            0,
+        );
+    }
+    let num_lines_out = Rescale(io.y, io.y_stride, mb_h, scaler);
+    Rescale(io.u, io.uv_stride, uv_mb_h, p.scaler_u);
+    Rescale(io.v, io.uv_stride, uv_mb_h, p.scaler_v);
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitRescaledAlphaYUV(
+    io: *const VP8Io,
+    p: *mut WebPDecParams,
+    expected_num_lines_out: c_int,
+) -> c_int {
+    let io = &*io;
+    let p = &mut *p;
+    let buf = &(*p.output).u.YUVA;
+    let dst_a = buf.a.offset(p.last_y as isize * buf.a_stride as isize);
+    if !io.a.is_null() {
+        let dst_y = buf.y.offset(p.last_y as isize * buf.y_stride as isize);
+        let num_lines_out = Rescale(io.a, io.width, io.mb_h, p.scaler_a);
+        c_assert!(expected_num_lines_out == num_lines_out);
+        if num_lines_out > 0 {
+            // unmultiply the Y
+            WebPMultRows(
+                dst_y,
+                buf.y_stride,
+                dst_a,
+                buf.a_stride,
+                (*p.scaler_a).dst_width,
+                num_lines_out,
+                1,
+            );
+        }
+    } else if !buf.a.is_null() {
+        // the user requested alpha, but there is none, set it to opaque.
+        c_assert!(p.last_y + expected_num_lines_out <= io.scaled_height);
+        FillAlphaPlane(dst_a, io.scaled_width, expected_num_lines_out, buf.a_stride);
+    }
+    0
+}
+
+#[allow(non_snake_case)]
+unsafe fn InitYUVRescaler(io: &VP8Io, p: &mut WebPDecParams) -> c_int {
+    let has_alpha = WebPIsAlphaMode((*p.output).colorspace) != 0;
+    let buf = &(*p.output).u.YUVA;
+    let out_width = io.scaled_width;
+    let out_height = io.scaled_height;
+    let uv_out_width = (out_width + 1) >> 1;
+    let uv_out_height = (out_height + 1) >> 1;
+    let uv_in_width = (io.mb_w + 1) >> 1;
+    let uv_in_height = (io.mb_h + 1) >> 1;
+    let work_size = 2 * out_width as usize; // scratch memory for luma rescaler
+    let uv_work_size = 2 * uv_out_width as usize; // and for each u/v ones
+    let num_rescalers = if has_alpha { 4 } else { 3 };
+
+    let mut tmp_size = (work_size + 2 * uv_work_size) * mem::size_of::<rescaler_t>();
+    if has_alpha {
+        tmp_size += work_size * mem::size_of::<rescaler_t>();
+    }
+    let rescaler_size = num_rescalers * mem::size_of::<WebPRescaler>() + WEBP_ALIGN_CST;
+
+    p.memory = WebPSafeMalloc(1, tmp_size + rescaler_size);
+    if p.memory.is_null() {
+        return 0; // memory error
+    }
+    let work = p.memory as *mut rescaler_t;
+
+    let scalers = WEBP_ALIGN((work as *mut u8).add(tmp_size)) as *mut WebPRescaler;
+    p.scaler_y = scalers.offset(0);
+    p.scaler_u = scalers.offset(1);
+    p.scaler_v = scalers.offset(2);
+    p.scaler_a = if has_alpha {
+        scalers.offset(3)
+    } else {
+        ptr::null_mut()
+    };
+
+    WebPRescalerInit(
+        p.scaler_y,
+        io.mb_w,
+        io.mb_h,
+        buf.y,
+        out_width,
+        out_height,
+        buf.y_stride,
+        1,
+        work,
+    );
+    WebPRescalerInit(
+        p.scaler_u,
+        uv_in_width,
+        uv_in_height,
+        buf.u,
+        uv_out_width,
+        uv_out_height,
+        buf.u_stride,
+        1,
+        work.add(work_size),
+    );
+    WebPRescalerInit(
+        p.scaler_v,
+        uv_in_width,
+        uv_in_height,
+        buf.v,
+        uv_out_width,
+        uv_out_height,
+        buf.v_stride,
+        1,
+        work.add(work_size + uv_work_size),
+    );
+    p.emit = Some(EmitRescaledYUV);
+
+    if has_alpha {
+        WebPRescalerInit(
+            p.scaler_a,
+            io.mb_w,
+            io.mb_h,
+            buf.a,
+            out_width,
+            out_height,
+            buf.a_stride,
+            1,
+            work.add(work_size + 2 * uv_work_size),
+        );
+        p.emit_alpha = Some(EmitRescaledAlphaYUV);
+        WebPInitAlphaProcessing();
+    }
+    1
+}
+
+//------------------------------------------------------------------------------
+// RGBA rescaling
+
+#[allow(non_snake_case)]
+unsafe fn ExportRGB(p: &mut WebPDecParams, y_pos: c_int) -> c_int {
+    let convert = WebPYUV444Converters[(*p.output).colorspace as usize].unwrap();
+    let buf = &(*p.output).u.RGBA;
+    let mut dst = buf.rgba.offset(y_pos as isize * buf.stride as isize);
+    let mut num_lines_out = 0;
+    // For RGB rescaling, because of the YUV420, current scan position
+    // U/V can be +1/-1 line from the Y one.  Hence the double test.
+    while WebPRescalerHasPendingOutput(&*p.scaler_y) != 0
+        && WebPRescalerHasPendingOutput(&*p.scaler_u) != 0
+    {
+        c_assert!(y_pos + num_lines_out < (*p.output).height);
+        c_assert!((*p.scaler_u).y_accum == (*p.scaler_v).y_accum);
+        WebPRescalerExportRow(p.scaler_y);
+        WebPRescalerExportRow(p.scaler_u);
+        WebPRescalerExportRow(p.scaler_v);
+        convert(
+            (*p.scaler_y).dst,
+            (*p.scaler_u).dst,
+            (*p.scaler_v).dst,
+            dst,
+            (*p.scaler_y).dst_width,
+        );
+        dst = dst.offset(buf.stride as isize);
+        num_lines_out += 1;
+    }
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitRescaledRGB(io: *const VP8Io, p: *mut WebPDecParams) -> c_int {
+    let io = &*io;
+    let p = &mut *p;
+    let mb_h = io.mb_h;
+    let uv_mb_h = (mb_h + 1) >> 1;
+    let mut j = 0;
+    let mut uv_j = 0;
+    let mut num_lines_out = 0;
+    while j < mb_h {
+        let y_lines_in = WebPRescalerImport(
+            p.scaler_y,
+            mb_h - j,
+            io.y.offset(j as isize * io.y_stride as isize),
+            io.y_stride,
+        );
+        j += y_lines_in;
+        if WebPRescaleNeededLines(p.scaler_u, uv_mb_h - uv_j) != 0 {
+            let u_lines_in = WebPRescalerImport(
+                p.scaler_u,
+                uv_mb_h - uv_j,
+                io.u.offset(uv_j as isize * io.uv_stride as isize),
+                io.uv_stride,
+            );
+            let v_lines_in = WebPRescalerImport(
+                p.scaler_v,
+                uv_mb_h - uv_j,
+                io.v.offset(uv_j as isize * io.uv_stride as isize),
+                io.uv_stride,
+            );
+            let _ = v_lines_in;
+            c_assert!(u_lines_in == v_lines_in);
+            uv_j += u_lines_in;
+        }
+        let y_pos = p.last_y + num_lines_out;
+        num_lines_out += ExportRGB(p, y_pos);
+    }
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ExportAlpha(
+    p: *mut WebPDecParams,
+    y_pos: c_int,
+    max_lines_out: c_int,
+) -> c_int {
+    let p = &mut *p;
+    let buf = &(*p.output).u.RGBA;
+    let base_rgba = buf.rgba.offset(y_pos as isize * buf.stride as isize);
+    let colorspace = (*p.output).colorspace;
+    let alpha_first = (colorspace == MODE_ARGB || colorspace == MODE_Argb) as c_int;
+    let mut dst = base_rgba.offset(if alpha_first != 0 { 0 } else { 3 });
+    let mut num_lines_out = 0;
+    let is_premult_alpha = WebPIsPremultipliedMode(colorspace);
+    let mut non_opaque = 0;
+    let width = (*p.scaler_a).dst_width;
+
+    while WebPRescalerHasPendingOutput(&*p.scaler_a) != 0 && num_lines_out < max_lines_out {
+        c_assert!(y_pos + num_lines_out < (*p.output).height);
+        WebPRescalerExportRow(p.scaler_a);
+        non_opaque |= WebPDispatchAlpha.unwrap()((*p.scaler_a).dst, 0, width, 1, dst, 0);
+        dst = dst.offset(buf.stride as isize);
+        num_lines_out += 1;
+    }
+    if is_premult_alpha != 0 && non_opaque != 0 {
+        WebPApplyAlphaMultiply.unwrap()(base_rgba, alpha_first, width, num_lines_out, buf.stride);
+    }
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ExportAlphaRGBA4444(
+    p: *mut WebPDecParams,
+    y_pos: c_int,
+    max_lines_out: c_int,
+) -> c_int {
+    let p = &mut *p;
+    let buf = &(*p.output).u.RGBA;
+    let base_rgba = buf.rgba.offset(y_pos as isize * buf.stride as isize);
+    let mut alpha_dst = if WEBP_SWAP_16BIT_CSP == 1 {
+        base_rgba
+    } else {
+        base_rgba.offset(1)
+    };
+    let mut num_lines_out = 0;
+    let colorspace = (*p.output).colorspace;
+    let width = (*p.scaler_a).dst_width;
+    let is_premult_alpha = WebPIsPremultipliedMode(colorspace);
+    let mut alpha_mask: u32 = 0x0f;
+
+    while WebPRescalerHasPendingOutput(&*p.scaler_a) != 0 && num_lines_out < max_lines_out {
+        c_assert!(y_pos + num_lines_out < (*p.output).height);
+        WebPRescalerExportRow(p.scaler_a);
+        for i in 0..width as isize {
+            // Fill in the alpha value (converted to 4 bits).
+            let alpha_value = (*(*p.scaler_a).dst.offset(i) >> 4) as u32;
+            let d = alpha_dst.offset(2 * i);
+            *d = ((*d as u32 & 0xf0) | alpha_value) as u8;
+            alpha_mask &= alpha_value;
+        }
+        alpha_dst = alpha_dst.offset(buf.stride as isize);
+        num_lines_out += 1;
+    }
+    if is_premult_alpha != 0 && alpha_mask != 0x0f {
+        WebPApplyAlphaMultiply4444.unwrap()(base_rgba, width, num_lines_out, buf.stride);
+    }
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn EmitRescaledAlphaRGB(
+    io: *const VP8Io,
+    p: *mut WebPDecParams,
+    expected_num_out_lines: c_int,
+) -> c_int {
+    let io = &*io;
+    if !io.a.is_null() {
+        let scaler = (*p).scaler_a;
+        let mut lines_left = expected_num_out_lines;
+        let y_end = (*p).last_y + lines_left;
+        while lines_left > 0 {
+            let row_offset = (*scaler).src_y - io.mb_y;
+            WebPRescalerImport(
+                scaler,
+                io.mb_h + io.mb_y - (*scaler).src_y,
+                io.a.offset(row_offset as isize * io.width as isize),
+                io.width,
+            );
+            lines_left -= (*p).emit_alpha_row.unwrap()(p, y_end - lines_left, lines_left);
+        }
+    }
+    0
+}
+
+#[allow(non_snake_case)]
+unsafe fn InitRGBRescaler(io: &VP8Io, p: &mut WebPDecParams) -> c_int {
+    let has_alpha = WebPIsAlphaMode((*p.output).colorspace) != 0;
+    let out_width = io.scaled_width;
+    let out_height = io.scaled_height;
+    let uv_in_width = (io.mb_w + 1) >> 1;
+    let uv_in_height = (io.mb_h + 1) >> 1;
+    let work_size = 2 * out_width as usize; // scratch memory for one rescaler
+    let num_rescalers = if has_alpha { 4 } else { 3 };
+
+    let mut tmp_size1 = 3 * work_size;
+    let mut tmp_size2 = 3 * out_width as usize;
+    if has_alpha {
+        tmp_size1 += work_size;
+        tmp_size2 += out_width as usize;
+    }
+    let total_size = tmp_size1 * mem::size_of::<rescaler_t>() + tmp_size2;
+    let rescaler_size = num_rescalers * mem::size_of::<WebPRescaler>() + WEBP_ALIGN_CST;
+
+    p.memory = WebPSafeMalloc(1, total_size + rescaler_size);
+    if p.memory.is_null() {
+        return 0; // memory error
+    }
+    let work = p.memory as *mut rescaler_t; // rescalers work area
+                                            // tmp storage for scaled YUV444 samples before RGB conversion
+    let tmp = work.add(tmp_size1) as *mut u8;
+
+    let scalers = WEBP_ALIGN((work as *mut u8).add(total_size)) as *mut WebPRescaler;
+    p.scaler_y = scalers.offset(0);
+    p.scaler_u = scalers.offset(1);
+    p.scaler_v = scalers.offset(2);
+    p.scaler_a = if has_alpha {
+        scalers.offset(3)
+    } else {
+        ptr::null_mut()
+    };
+
+    let out_width_ = out_width as usize;
+    WebPRescalerInit(
+        p.scaler_y, io.mb_w, io.mb_h, tmp, out_width, out_height, 0, 1, work,
+    );
+    WebPRescalerInit(
+        p.scaler_u,
+        uv_in_width,
+        uv_in_height,
+        tmp.add(out_width_),
+        out_width,
+        out_height,
+        0,
+        1,
+        work.add(work_size),
+    );
+    WebPRescalerInit(
+        p.scaler_v,
+        uv_in_width,
+        uv_in_height,
+        tmp.add(2 * out_width_),
+        out_width,
+        out_height,
+        0,
+        1,
+        work.add(2 * work_size),
+    );
+    p.emit = Some(EmitRescaledRGB);
+    WebPInitYUV444Converters();
+
+    if has_alpha {
+        WebPRescalerInit(
+            p.scaler_a,
+            io.mb_w,
+            io.mb_h,
+            tmp.add(3 * out_width_),
+            out_width,
+            out_height,
+            0,
+            1,
+            work.add(3 * work_size),
+        );
+        p.emit_alpha = Some(EmitRescaledAlphaRGB);
+        if (*p.output).colorspace == MODE_RGBA_4444 || (*p.output).colorspace == MODE_rgbA_4444 {
+            p.emit_alpha_row = Some(ExportAlphaRGBA4444);
+        } else {
+            p.emit_alpha_row = Some(ExportAlpha);
+        }
+        WebPInitAlphaProcessing();
+    }
+    1
+}
+
+//------------------------------------------------------------------------------
+// Default custom functions
+
+#[allow(non_snake_case)]
+extern "C" fn CustomSetup(io: *mut VP8Io) -> c_int {
+    unsafe {
+        let io = &mut *io;
+        let p = &mut *(io.opaque as *mut WebPDecParams);
+        let colorspace = (*p.output).colorspace;
+        let is_rgb = WebPIsRGBMode(colorspace) != 0;
+        let is_alpha = WebPIsAlphaMode(colorspace) != 0;
+
+        p.memory = ptr::null_mut();
+        p.emit = None;
+        p.emit_alpha = None;
+        p.emit_alpha_row = None;
+        if WebPIoInitFromOptions(p.options, io, if is_alpha { MODE_YUV } else { MODE_YUVA }) == 0 {
+            return 0;
+        }
+        if is_alpha && WebPIsPremultipliedMode(colorspace) != 0 {
+            WebPInitUpsamplers();
+        }
+        if io.use_scaling != 0 {
+            let ok = if is_rgb {
+                InitRGBRescaler(io, p)
+            } else {
+                InitYUVRescaler(io, p)
+            };
+            if ok == 0 {
+                return 0; // memory error
+            }
+        } else {
+            if is_rgb {
+                WebPInitSamplers();
+                p.emit = Some(EmitSampledRGB); // default
+                if io.fancy_upsampling != 0 {
+                    let uv_width = (io.mb_w + 1) >> 1;
+                    p.memory = WebPSafeMalloc(1, (io.mb_w + 2 * uv_width) as usize);
+                    if p.memory.is_null() {
+                        return 0; // memory error.
+                    }
+                    p.tmp_y = p.memory as *mut u8;
+                    p.tmp_u = p.tmp_y.offset(io.mb_w as isize);
+                    p.tmp_v = p.tmp_u.offset(uv_width as isize);
+                    p.emit = Some(EmitFancyRGB);
+                    WebPInitUpsamplers();
+                }
+            } else {
+                p.emit = Some(EmitYUV);
+            }
+            if is_alpha {
+                // need transparency output
+                p.emit_alpha = if colorspace == MODE_RGBA_4444 || colorspace == MODE_rgbA_4444 {
+                    Some(EmitAlphaRGBA4444)
+                } else if is_rgb {
+                    Some(EmitAlphaRGB)
+                } else {
+                    Some(EmitAlphaYUV)
+                };
+                if is_rgb {
+                    WebPInitAlphaProcessing();
+                }
+            }
+        }
+
+        1
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+extern "C" fn CustomPut(io: *const VP8Io) -> c_int {
+    unsafe {
+        let p = (*io).opaque as *mut WebPDecParams;
+        let mb_w = (*io).mb_w;
+        let mb_h = (*io).mb_h;
+        c_assert!(((*io).mb_y & 1) == 0);
+
+        if mb_w <= 0 || mb_h <= 0 {
+            return 0;
+        }
+        let num_lines_out = (*p).emit.unwrap()(io, p);
+        if let Some(emit_alpha) = (*p).emit_alpha {
+            emit_alpha(io, p, num_lines_out);
+        }
+        (*p).last_y += num_lines_out;
+        1
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+extern "C" fn CustomTeardown(io: *const VP8Io) {
+    unsafe {
+        let p = (*io).opaque as *mut WebPDecParams;
+        WebPSafeFree((*p).memory);
+        (*p).memory = ptr::null_mut();
+    }
+}
+
+//------------------------------------------------------------------------------
+// Main entry point
+
+// Initializes VP8Io with custom setup, io and teardown functions. The default
+// hooks will use the supplied 'params' as io->opaque handle.
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitCustomIo(params: *mut WebPDecParams, io: *mut VP8Io) {
+    InitCustomIo(params, &mut *io);
+}
+
+// Same as WebPInitCustomIo(), on a borrowed 'io'.
+#[allow(non_snake_case)]
+pub(crate) fn InitCustomIo(params: *mut WebPDecParams, io: &mut VP8Io) {
+    io.put = Some(CustomPut);
+    io.setup = Some(CustomSetup);
+    io.teardown = Some(CustomTeardown);
+    io.opaque = params as *mut c_void;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use dec::buffer_dec::{WebPAllocateDecBuffer, WebPFreeDecBuffer};
+    use decode::{MODE_bgrA, MODE_rgbA, VP8StatusCode, MODE_BGRA, MODE_RGBA, WEBP_CSP_MODE};
+    use decode::{MODE_LAST, MODE_RGB};
+    use sys;
+    use test_util::new_io;
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_WebPInitCustomIo(params: *mut WebPDecParams, io: *mut VP8Io);
+    }
+
+    struct Picture {
+        width: c_int,
+        height: c_int,
+        y: Vec<u8>,
+        u: Vec<u8>,
+        v: Vec<u8>,
+        a: Vec<u8>,
+    }
+
+    impl Picture {
+        fn new(width: c_int, height: c_int, seed: u32) -> Picture {
+            let mut rng = Lcg(seed);
+            let uv_size = (((width + 1) >> 1) * ((height + 1) >> 1)) as usize;
+            Picture {
+                width,
+                height,
+                y: rng.bytes((width * height) as usize),
+                u: rng.bytes(uv_size),
+                v: rng.bytes(uv_size),
+                a: rng.bytes((width * height) as usize),
+            }
+        }
+    }
+
+    // Feeds the picture through the setup/put/teardown hooks installed by
+    // 'init_custom_io', 16 rows at a time as the VP8 decoder does, and
+    // returns the output memory and the last output row.
+    unsafe fn emit(
+        init_custom_io: unsafe extern "C" fn(*mut WebPDecParams, *mut VP8Io),
+        pic: &Picture,
+        colorspace: WEBP_CSP_MODE,
+        options: &sys::WebPDecoderOptions,
+        with_alpha: bool,
+    ) -> (Vec<u8>, c_int) {
+        let mut output: sys::WebPDecBuffer = mem::zeroed();
+        output.colorspace = colorspace as c_int;
+        assert_eq!(
+            WebPAllocateDecBuffer(pic.width, pic.height, options, &mut output),
+            VP8StatusCode::VP8_STATUS_OK
+        );
+        let mut params: WebPDecParams = mem::zeroed();
+        params.output = &mut output;
+        params.options = options;
+        // The rescaling emitters premultiply the luma in place.
+        let mut pic_y = pic.y.clone();
+        let mut io = new_io(&[]);
+        io.width = pic.width;
+        io.height = pic.height;
+        init_custom_io(&mut params, &mut io);
+        assert_eq!(io.setup.unwrap()(&mut io), 1);
+
+        let uv_stride = (pic.width + 1) >> 1;
+        let mut y_start = 0;
+        while y_start < pic.height {
+            let y_end = (y_start + 16).min(io.crop_bottom);
+            let y = y_start.max(io.crop_top);
+            if y < y_end {
+                io.mb_y = y - io.crop_top;
+                io.mb_w = io.crop_right - io.crop_left;
+                io.mb_h = y_end - y;
+                io.y_stride = pic.width;
+                io.uv_stride = uv_stride;
+                io.y = pic_y
+                    .as_mut_ptr()
+                    .offset((y * pic.width + io.crop_left) as isize);
+                let uv_offset = ((y >> 1) * uv_stride + (io.crop_left >> 1)) as isize;
+                io.u = pic.u.as_ptr().offset(uv_offset);
+                io.v = pic.v.as_ptr().offset(uv_offset);
+                io.a = if with_alpha {
+                    pic.a
+                        .as_ptr()
+                        .offset((y * pic.width + io.crop_left) as isize)
+                } else {
+                    ptr::null()
+                };
+                assert_eq!(io.put.unwrap()(&io), 1);
+            }
+            y_start += 16;
+        }
+        io.teardown.unwrap()(&io);
+
+        let buffer = &output.u.YUVA;
+        let size = if WebPIsRGBMode(colorspace) != 0 {
+            output.u.RGBA.size
+        } else {
+            buffer.y_size + buffer.u_size + buffer.v_size + buffer.a_size
+        };
+        let pixels = slice::from_raw_parts(output.private_memory, size).to_vec();
+        WebPFreeDecBuffer(&mut output);
+        (pixels, params.last_y)
+    }
+
+    #[test]
+    fn test_custom_io_parity() {
+        let pic = Picture::new(45, 37, 0x10de);
+        let mut cases = Vec::new();
+        for &no_fancy_upsampling in &[0, 1] {
+            for &crop in &[None, Some((5, 3, 31, 27)), Some((0, 20, 45, 17))] {
+                for &scale in &[None, Some((23, 0)), Some((70, 51))] {
+                    let mut options: sys::WebPDecoderOptions = unsafe { mem::zeroed() };
+                    options.no_fancy_upsampling = no_fancy_upsampling;
+                    if let Some((left, top, width, height)) = crop {
+                        options.use_cropping = 1;
+                        options.crop_left = left;
+                        options.crop_top = top;
+                        options.crop_width = width;
+                        options.crop_height = height;
+                    }
+                    if let Some((width, height)) = scale {
+                        options.use_scaling = 1;
+                        options.scaled_width = width;
+                        options.scaled_height = height;
+                    }
+                    cases.push(options);
+                }
+            }
+        }
+        let mut digest = Digest::new();
+        for colorspace in MODE_RGB..MODE_LAST {
+            for options in &cases {
+                for &with_alpha in &[false, true] {
+                    unsafe {
+                        let (pixels, last_y) =
+                            emit(WebPInitCustomIo, &pic, colorspace, options, with_alpha);
+                        digest.update(&pixels);
+                        digest.update(&last_y.to_le_bytes());
+                        #[cfg(feature = "c-reference")]
+                        {
+                            let (pixels_ref, last_y_ref) = emit(
+                                WebPRef_WebPInitCustomIo,
+                                &pic,
+                                colorspace,
+                                options,
+                                with_alpha,
+                            );
+                            assert!(
+                                pixels == pixels_ref,
+                                "mode {} no_fancy {} crop {} scale {} alpha {}",
+                                colorspace,
+                                options.no_fancy_upsampling,
+                                options.use_cropping,
+                                options.use_scaling,
+                                with_alpha
+                            );
+                            assert_eq!(last_y, last_y_ref);
+                        }
+                    }
+                }
+            }
+        }
+        check_parity("dec::io_dec::test_custom_io_parity", digest);
+    }
 
-extern "C" {
-    pub(crate) fn WebPInitCustomIo(params: *mut WebPDecParams, io: *mut VP8Io);
+    #[test]
+    fn test_custom_io_premultiplied() {
+        // Fully transparent pixels come out black in the premultiplied modes.
+        let mut pic = Picture::new(16, 16, 0xa1fa);
+        for (i, a) in pic.a.iter_mut().enumerate() {
+            *a = if i % 3 == 0 { 0 } else { 0xff };
+        }
+        let options: sys::WebPDecoderOptions = unsafe { mem::zeroed() };
+        for &(mode, premultiplied, alpha_offset) in &[
+            (MODE_RGBA, MODE_rgbA, 3),
+            (MODE_BGRA, MODE_bgrA, 3),
+            (MODE_ARGB, MODE_Argb, 0),
+        ] {
+            let (straight, _) = unsafe { emit(WebPInitCustomIo, &pic, mode, &options, true) };
+            let (pixels, _) =
+                unsafe { emit(WebPInitCustomIo, &pic, premultiplied, &options, true) };
+            for (i, (s, p)) in straight.chunks(4).zip(pixels.chunks(4)).enumerate() {
+                assert_eq!(s[alpha_offset], pic.a[i]);
+                assert_eq!(p[alpha_offset], pic.a[i]);
+                if pic.a[i] == 0 {
+                    assert!(p.iter().all(|&c| c == 0));
+                } else {
+                    assert_eq!(s, p);
+                }
+            }
+        }
+    }
 }
@@ -1,12 +1,20 @@
 #[doc(hidden)]
 pub mod alpha_dec;
 #[doc(hidden)]
+pub mod buffer_dec;
+#[doc(hidden)]
 pub mod common_dec;
 #[doc(hidden)]
+pub mod frame_dec;
+#[doc(hidden)]
+pub mod idec_dec;
+#[doc(hidden)]
 pub mod io_dec;
 #[doc(hidden)]
 pub mod quant_dec;
 #[doc(hidden)]
+pub mod tree_dec;
+#[doc(hidden)]
 pub mod vp8_dec;
 #[doc(hidden)]
 pub mod vp8i_dec;
@@ -15,4 +23,6 @@ pub mod vp8l_dec;
 #[doc(hidden)]
 pub mod vp8li_dec;
 #[doc(hidden)]
+pub mod webp_dec;
+#[doc(hidden)]
 pub mod webpi_dec;
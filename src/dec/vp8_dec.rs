@@ -1,6 +1,40 @@
+// Copyright 2010 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// main entry for the decoder
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
 use std::os::raw::*;
+use std::ptr;
+use std::sync::Once;
 
+use dec::alpha_dec::WebPDeallocateAlphaMemory;
+use dec::common_dec::*;
+use dec::frame_dec::{VP8EnterCritical, VP8ExitCritical, VP8InitFrame, VP8ProcessRow};
+use dec::quant_dec::VP8ParseQuant;
+use dec::tree_dec::{VP8ParseIntraModeRow, VP8ParseProba, VP8ResetProba};
+use dec::vp8i_dec::*;
+use decode::VP8StatusCode;
 use decode::WEBP_DECODER_ABI_VERSION;
+use dsp::cpu::VP8GetCPUInfo;
+use dsp::dec::VP8TransformWHT;
+use dsp::dsp::kSlowSSSE3;
+use format_constants::VP8_FRAME_HEADER_SIZE;
+use utils::bit_reader_inl_utils::{VP8GetBit, VP8GetBitAlt, VP8GetSigned};
+use utils::bit_reader_utils::{
+    VP8BitReader, VP8Get, VP8GetSignedValue, VP8GetValue, VP8InitBitReader,
+};
+use utils::thread_utils::WebPGetWorkerInterface;
+use utils::utils::{WebPSafeCalloc, WebPSafeFree};
 
 pub(crate) type VP8IoPutHook = Option<extern "C" fn(*const VP8Io) -> c_int>;
 pub(crate) type VP8IoSetupHook = Option<extern "C" fn(*mut VP8Io) -> c_int>;
@@ -12,8 +46,8 @@ pub struct VP8Io {
     pub(crate) width: c_int,
     pub(crate) height: c_int, // picture dimensions, in pixels (invariable).
     // These are the original, uncropped dimensions.
-                              // The actual area passed to put() is stored
-                              // in mb_w / mb_h fields.
+    // The actual area passed to put() is stored
+    // in mb_w / mb_h fields.
 
     // set before calling put()
     pub(crate) mb_y: c_int,      // position of the current rows (in pixels)
@@ -77,11 +111,1149 @@ pub struct VP8Io {
     pub(crate) a: *const u8,
 }
 
-extern "C" {
-    pub(crate) fn VP8InitIoInternal(io: *mut VP8Io, version: c_int) -> c_int;
+#[inline]
+pub(crate) fn VP8InitIo(io: &mut VP8Io) -> c_int {
+    // Only zeroes 'io': all its fields are integers, raw pointers or optional
+    // function pointers, for which all-zero is a valid value.
+    unsafe { VP8InitIoInternal(io, WEBP_DECODER_ABI_VERSION as c_int) }
+}
+
+//------------------------------------------------------------------------------
+
+// Returns the version number of the decoder library, packed in hexadecimal
+// using 8bits for each of major/minor/revision. E.g: v2.5.7 is 0x020507.
+#[no_mangle]
+pub extern "C" fn WebPGetDecoderVersion() -> c_int {
+    (DEC_MAJ_VERSION << 16) | (DEC_MIN_VERSION << 8) | DEC_REV_VERSION
+}
+
+//------------------------------------------------------------------------------
+// Signature and pointer-to-function for GetCoeffs() variants below.
+
+type GetCoeffsFunc = unsafe fn(
+    br: *mut VP8BitReader,
+    prob: *const *const VP8BandProbas,
+    ctx: c_int,
+    dq: &quant_t,
+    n: c_int,
+    out: *mut i16,
+) -> c_int;
+#[allow(non_upper_case_globals)]
+static mut GetCoeffs: Option<GetCoeffsFunc> = None;
+#[allow(non_upper_case_globals)]
+static GetCoeffsOnce: Once = Once::new();
+
+//------------------------------------------------------------------------------
+// VP8Decoder
+
+#[allow(non_snake_case)]
+fn SetOk(dec: &mut VP8Decoder) {
+    dec.status_ = VP8StatusCode::VP8_STATUS_OK;
+    dec.error_msg_ = c_str!("OK");
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitIoInternal(io: *mut VP8Io, version: c_int) -> c_int {
+    if WEBP_ABI_IS_INCOMPATIBLE(version, WEBP_DECODER_ABI_VERSION as c_int) {
+        return 0; // mismatch error
+    }
+    if !io.is_null() {
+        ptr::write_bytes(io, 0, 1);
+    }
+    1
+}
+
+#[allow(non_snake_case)]
+pub(crate) fn WEBP_ABI_IS_INCOMPATIBLE(a: c_int, b: c_int) -> bool {
+    (a >> 8) != (b >> 8)
+}
+
+// Create a new decoder object.
+#[no_mangle]
+pub unsafe extern "C" fn VP8New() -> *mut VP8Decoder {
+    let dec = WebPSafeCalloc(1, mem::size_of::<VP8Decoder>()) as *mut VP8Decoder;
+    if !dec.is_null() {
+        let dec: &mut VP8Decoder = &mut *dec;
+        SetOk(dec);
+        ((*WebPGetWorkerInterface()).Init.unwrap())(&mut dec.worker_);
+        dec.ready_ = 0;
+        dec.num_parts_minus_one_ = 0;
+        InitGetCoeffs();
+    }
+    dec
+}
+
+// Return current status of the decoder:
+#[no_mangle]
+pub unsafe extern "C" fn VP8Status(dec: *mut VP8Decoder) -> VP8StatusCode {
+    if dec.is_null() {
+        return VP8StatusCode::VP8_STATUS_INVALID_PARAM;
+    }
+    (*dec).status_
+}
+
+// return readable string corresponding to the last status.
+#[no_mangle]
+pub unsafe extern "C" fn VP8StatusMessage(dec: *mut VP8Decoder) -> *const c_char {
+    if dec.is_null() {
+        return c_str!("no object");
+    }
+    if (*dec).error_msg_.is_null() {
+        return c_str!("OK");
+    }
+    (*dec).error_msg_
+}
+
+// Destroy the decoder object.
+#[no_mangle]
+pub unsafe extern "C" fn VP8Delete(dec: *mut VP8Decoder) {
+    if !dec.is_null() {
+        VP8Clear(dec);
+        WebPSafeFree(dec as *mut c_void);
+    }
+}
+
+// in vp8.c
+#[no_mangle]
+pub unsafe extern "C" fn VP8SetError(
+    dec: *mut VP8Decoder,
+    error: VP8StatusCode,
+    msg: *const c_char,
+) -> c_int {
+    let dec: &mut VP8Decoder = &mut *dec;
+
+    // The oldest error reported takes precedence over the new one.
+    if dec.status_ == VP8StatusCode::VP8_STATUS_OK {
+        dec.status_ = error;
+        dec.error_msg_ = msg;
+        dec.ready_ = 0;
+    }
+    0
+}
+
+//------------------------------------------------------------------------------
+
+// Returns true if the next 3 bytes in data contain the VP8 signature.
+#[no_mangle]
+pub unsafe extern "C" fn VP8CheckSignature(data: *const u8, data_size: usize) -> c_int {
+    (data_size >= 3 && *data == 0x9d && *data.add(1) == 0x01 && *data.add(2) == 0x2a) as c_int
+}
+
+// Validates the VP8 data-header and retrieves basic header information viz
+// width and height. Returns 0 in case of formatting error. *width/*height
+// can be passed NULL.
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetInfo(
+    data: *const u8,
+    data_size: usize,
+    chunk_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> c_int {
+    if data.is_null() || data_size < VP8_FRAME_HEADER_SIZE {
+        return 0; // not enough data
+    }
+    let data = std::slice::from_raw_parts(data, VP8_FRAME_HEADER_SIZE);
+    // check signature
+    if VP8CheckSignature(data[3..].as_ptr(), data_size - 3) == 0 {
+        return 0; // Wrong signature.
+    }
+    let bits: u32 = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16;
+    let key_frame = bits & 1 == 0;
+    let w: c_int = ((data[7] as c_int) << 8 | data[6] as c_int) & 0x3fff;
+    let h: c_int = ((data[9] as c_int) << 8 | data[8] as c_int) & 0x3fff;
+
+    if !key_frame {
+        // Not a keyframe.
+        return 0;
+    }
+
+    if ((bits >> 1) & 7) > 3 {
+        return 0; // unknown profile
+    }
+    if (bits >> 4) & 1 == 0 {
+        return 0; // first frame is invisible!
+    }
+    if (bits >> 5) as usize >= chunk_size {
+        // partition_length
+        return 0; // inconsistent size information.
+    }
+    if w == 0 || h == 0 {
+        return 0; // We don't support both width and height to be zero.
+    }
+
+    if !width.is_null() {
+        *width = w;
+    }
+    if !height.is_null() {
+        *height = h;
+    }
+
+    1
+}
+
+//------------------------------------------------------------------------------
+// Header parsing
+
+#[allow(non_snake_case)]
+fn ResetSegmentHeader(hdr: &mut VP8SegmentHeader) {
+    hdr.use_segment_ = 0;
+    hdr.update_map_ = 0;
+    hdr.absolute_delta_ = 1;
+    hdr.quantizer_ = [0; NUM_MB_SEGMENTS as usize];
+    hdr.filter_strength_ = [0; NUM_MB_SEGMENTS as usize];
+}
+
+// Paragraph 9.3
+#[allow(non_snake_case)]
+unsafe fn ParseSegmentHeader(
+    br: *mut VP8BitReader,
+    hdr: &mut VP8SegmentHeader,
+    proba: &mut VP8Proba,
+) -> c_int {
+    c_assert!(!br.is_null());
+    hdr.use_segment_ = VP8Get(br) as c_int;
+    if hdr.use_segment_ != 0 {
+        hdr.update_map_ = VP8Get(br) as c_int;
+        if VP8Get(br) != 0 {
+            // update data
+            hdr.absolute_delta_ = VP8Get(br) as c_int;
+            for s in 0..NUM_MB_SEGMENTS as usize {
+                hdr.quantizer_[s] = if VP8Get(br) != 0 {
+                    VP8GetSignedValue(br, 7) as i8
+                } else {
+                    0
+                };
+            }
+            for s in 0..NUM_MB_SEGMENTS as usize {
+                hdr.filter_strength_[s] = if VP8Get(br) != 0 {
+                    VP8GetSignedValue(br, 6) as i8
+                } else {
+                    0
+                };
+            }
+        }
+        if hdr.update_map_ != 0 {
+            for s in 0..MB_FEATURE_TREE_PROBS as usize {
+                proba.segments_[s] = if VP8Get(br) != 0 {
+                    VP8GetValue(br, 8) as u8
+                } else {
+                    255
+                };
+            }
+        }
+    } else {
+        hdr.update_map_ = 0;
+    }
+    ((*br).eof_ == 0) as c_int
+}
+
+// Paragraph 9.5
+// This function returns VP8_STATUS_SUSPENDED if we don't have all the
+// necessary data in 'buf'.
+// This case is not necessarily an error (for incremental decoding).
+// Still, no bitreader is ever initialized to make it possible to read
+// unavailable memory.
+// If we don't even have the partitions' sizes, than VP8_STATUS_NOT_ENOUGH_DATA
+// is returned, and this is an unrecoverable error.
+// If the partitions were positioned ok, VP8_STATUS_OK is returned.
+#[allow(non_snake_case)]
+unsafe fn ParsePartitions(dec: &mut VP8Decoder, buf: *const u8, size: usize) -> VP8StatusCode {
+    let br: *mut VP8BitReader = &mut dec.br_;
+    let mut sz: *const u8 = buf;
+    let buf_end: *const u8 = buf.add(size);
+    let mut size_left: usize = size;
+
+    dec.num_parts_minus_one_ = (1 << VP8GetValue(br, 2)) - 1;
+    let last_part = dec.num_parts_minus_one_ as usize;
+    if size < 3 * last_part {
+        // we can't even read the sizes with sz[]! That's a failure.
+        return VP8StatusCode::VP8_STATUS_NOT_ENOUGH_DATA;
+    }
+    let mut part_start: *const u8 = buf.add(last_part * 3);
+    size_left -= last_part * 3;
+    for p in 0..last_part {
+        let mut psize: usize =
+            *sz as usize | (*sz.add(1) as usize) << 8 | (*sz.add(2) as usize) << 16;
+        if psize > size_left {
+            psize = size_left;
+        }
+        VP8InitBitReader(&mut dec.parts_[p], part_start, psize);
+        part_start = part_start.add(psize);
+        size_left -= psize;
+        sz = sz.add(3);
+    }
+    VP8InitBitReader(&mut dec.parts_[last_part], part_start, size_left);
+    if part_start < buf_end {
+        VP8StatusCode::VP8_STATUS_OK
+    } else {
+        VP8StatusCode::VP8_STATUS_SUSPENDED // Init is ok, but there's not enough data
+    }
+}
+
+// Paragraph 9.4
+#[allow(non_snake_case)]
+unsafe fn ParseFilterHeader(br: *mut VP8BitReader, dec: &mut VP8Decoder) -> c_int {
+    let hdr: &mut VP8FilterHeader = &mut dec.filter_hdr_;
+    hdr.simple_ = VP8Get(br) as c_int;
+    hdr.level_ = VP8GetValue(br, 6) as c_int;
+    hdr.sharpness_ = VP8GetValue(br, 3) as c_int;
+    hdr.use_lf_delta_ = VP8Get(br) as c_int;
+    if hdr.use_lf_delta_ != 0 && VP8Get(br) != 0 {
+        // update lf-delta?
+        for i in 0..NUM_REF_LF_DELTAS as usize {
+            if VP8Get(br) != 0 {
+                hdr.ref_lf_delta_[i] = VP8GetSignedValue(br, 6);
+            }
+        }
+        for i in 0..NUM_MODE_LF_DELTAS as usize {
+            if VP8Get(br) != 0 {
+                hdr.mode_lf_delta_[i] = VP8GetSignedValue(br, 6);
+            }
+        }
+    }
+    dec.filter_type_ = if hdr.level_ == 0 {
+        0
+    } else if hdr.simple_ != 0 {
+        1
+    } else {
+        2
+    };
+    ((*br).eof_ == 0) as c_int
+}
+
+// Decode the VP8 frame header. Returns true if ok.
+// Note: 'io->data' must be pointing to the start of the VP8 frame header.
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetHeaders(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int {
+    if dec.is_null() {
+        return 0;
+    }
+    let decp = dec;
+    let dec: &mut VP8Decoder = &mut *dec;
+    SetOk(dec);
+    if io.is_null() {
+        return VP8SetError(
+            decp,
+            VP8StatusCode::VP8_STATUS_INVALID_PARAM,
+            c_str!("null VP8Io passed to VP8GetHeaders()"),
+        );
+    }
+    let io: &mut VP8Io = &mut *io;
+    let mut buf: *const u8 = io.data;
+    let mut buf_size: usize = io.data_size;
+    if buf_size < 4 {
+        return VP8SetError(
+            decp,
+            VP8StatusCode::VP8_STATUS_NOT_ENOUGH_DATA,
+            c_str!("Truncated header."),
+        );
+    }
+
+    // Paragraph 9.1
+    {
+        let bits: u32 = *buf as u32 | (*buf.add(1) as u32) << 8 | (*buf.add(2) as u32) << 16;
+        let frm_hdr: &mut VP8FrameHeader = &mut dec.frm_hdr_;
+        frm_hdr.key_frame_ = (bits & 1 == 0) as u8;
+        frm_hdr.profile_ = ((bits >> 1) & 7) as u8;
+        frm_hdr.show_ = ((bits >> 4) & 1) as u8;
+        frm_hdr.partition_length_ = bits >> 5;
+        if frm_hdr.profile_ > 3 {
+            return VP8SetError(
+                decp,
+                VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR,
+                c_str!("Incorrect keyframe parameters."),
+            );
+        }
+        if frm_hdr.show_ == 0 {
+            return VP8SetError(
+                decp,
+                VP8StatusCode::VP8_STATUS_UNSUPPORTED_FEATURE,
+                c_str!("Frame not displayable."),
+            );
+        }
+        buf = buf.add(3);
+        buf_size -= 3;
+    }
+
+    if dec.frm_hdr_.key_frame_ != 0 {
+        // Paragraph 9.2
+        if buf_size < 7 {
+            return VP8SetError(
+                decp,
+                VP8StatusCode::VP8_STATUS_NOT_ENOUGH_DATA,
+                c_str!("cannot parse picture header"),
+            );
+        }
+        if VP8CheckSignature(buf, buf_size) == 0 {
+            return VP8SetError(
+                decp,
+                VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR,
+                c_str!("Bad code word"),
+            );
+        }
+        {
+            let pic_hdr: &mut VP8PictureHeader = &mut dec.pic_hdr_;
+            pic_hdr.width_ = (((*buf.add(4) as u16) << 8) | *buf.add(3) as u16) & 0x3fff;
+            pic_hdr.xscale_ = *buf.add(4) >> 6; // ratio: 1, 5/4 5/3 or 2
+            pic_hdr.height_ = (((*buf.add(6) as u16) << 8) | *buf.add(5) as u16) & 0x3fff;
+            pic_hdr.yscale_ = *buf.add(6) >> 6;
+        }
+        buf = buf.add(7);
+        buf_size -= 7;
+
+        dec.mb_w_ = (dec.pic_hdr_.width_ as c_int + 15) >> 4;
+        dec.mb_h_ = (dec.pic_hdr_.height_ as c_int + 15) >> 4;
+
+        // Setup default output area (can be later modified during io->setup())
+        io.width = dec.pic_hdr_.width_ as c_int;
+        io.height = dec.pic_hdr_.height_ as c_int;
+        // IMPORTANT! use some sane dimensions in crop_* and scaled_* fields.
+        // So they can be used interchangeably without always testing for
+        // 'use_cropping'.
+        io.use_cropping = 0;
+        io.crop_top = 0;
+        io.crop_left = 0;
+        io.crop_right = io.width;
+        io.crop_bottom = io.height;
+        io.use_scaling = 0;
+        io.scaled_width = io.width;
+        io.scaled_height = io.height;
+
+        io.mb_w = io.width; // sanity check
+        io.mb_h = io.height; // ditto
+
+        VP8ResetProba(&mut dec.proba_);
+        ResetSegmentHeader(&mut dec.segment_hdr_);
+    }
+
+    // Check if we have all the partition #0 available, and initialize dec->br_
+    // to read this partition (and this partition only).
+    let partition_length = dec.frm_hdr_.partition_length_ as usize;
+    if partition_length > buf_size {
+        return VP8SetError(
+            decp,
+            VP8StatusCode::VP8_STATUS_NOT_ENOUGH_DATA,
+            c_str!("bad partition length"),
+        );
+    }
+
+    let br: *mut VP8BitReader = &mut dec.br_;
+    VP8InitBitReader(br, buf, partition_length);
+    buf = buf.add(partition_length);
+    buf_size -= partition_length;
+
+    if dec.frm_hdr_.key_frame_ != 0 {
+        dec.pic_hdr_.colorspace_ = VP8Get(br) as u8;
+        dec.pic_hdr_.clamp_type_ = VP8Get(br) as u8;
+    }
+    if ParseSegmentHeader(br, &mut dec.segment_hdr_, &mut dec.proba_) == 0 {
+        return VP8SetError(
+            decp,
+            VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR,
+            c_str!("cannot parse segment header"),
+        );
+    }
+    // Filter specs
+    if ParseFilterHeader(br, dec) == 0 {
+        return VP8SetError(
+            decp,
+            VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR,
+            c_str!("cannot parse filter header"),
+        );
+    }
+    let status = ParsePartitions(dec, buf, buf_size);
+    if status != VP8StatusCode::VP8_STATUS_OK {
+        return VP8SetError(decp, status, c_str!("cannot parse partitions"));
+    }
+
+    // quantizer change
+    VP8ParseQuant(dec);
+
+    // Frame buffer marking
+    if dec.frm_hdr_.key_frame_ == 0 {
+        return VP8SetError(
+            decp,
+            VP8StatusCode::VP8_STATUS_UNSUPPORTED_FEATURE,
+            c_str!("Not a key frame."),
+        );
+    }
+
+    VP8Get(br); // ignore the value of update_proba_
+
+    VP8ParseProba(br, dec);
+
+    // sanitized state
+    dec.ready_ = 1;
+    1
+}
+
+//------------------------------------------------------------------------------
+// Residual decoding (Paragraph 13.2 / 13.3)
+
+#[allow(non_upper_case_globals)]
+const kCat3: [u8; 4] = [173, 148, 140, 0];
+#[allow(non_upper_case_globals)]
+const kCat4: [u8; 5] = [176, 155, 140, 135, 0];
+#[allow(non_upper_case_globals)]
+const kCat5: [u8; 6] = [180, 157, 141, 134, 130, 0];
+#[allow(non_upper_case_globals)]
+const kCat6: [u8; 12] = [254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129, 0];
+#[allow(non_upper_case_globals)]
+const kCat3456: [&[u8]; 4] = [&kCat3, &kCat4, &kCat5, &kCat6];
+#[allow(non_upper_case_globals)]
+const kZigzag: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+// See section 13-2: http://tools.ietf.org/html/rfc6386#section-13.2
+#[allow(non_snake_case)]
+unsafe fn GetLargeValue(br: *mut VP8BitReader, p: *const u8) -> c_int {
+    let mut v: c_int;
+    if VP8GetBit(br, *p.add(3) as c_int) == 0 {
+        if VP8GetBit(br, *p.add(4) as c_int) == 0 {
+            v = 2;
+        } else {
+            v = 3 + VP8GetBit(br, *p.add(5) as c_int);
+        }
+    } else if VP8GetBit(br, *p.add(6) as c_int) == 0 {
+        if VP8GetBit(br, *p.add(7) as c_int) == 0 {
+            v = 5 + VP8GetBit(br, 159);
+        } else {
+            v = 7 + 2 * VP8GetBit(br, 165);
+            v += VP8GetBit(br, 145);
+        }
+    } else {
+        let bit1 = VP8GetBit(br, *p.add(8) as c_int);
+        let bit0 = VP8GetBit(br, *p.add(9 + bit1 as usize) as c_int);
+        let cat = 2 * bit1 + bit0;
+        v = 0;
+        for &tab in kCat3456[cat as usize].iter().take_while(|&&tab| tab != 0) {
+            v = 2 * v + VP8GetBit(br, tab as c_int);
+        }
+        v += 3 + (8 << cat);
+    }
+    v
+}
+
+// Returns the position of the last non-zero coeff plus one
+#[allow(non_snake_case)]
+unsafe fn GetCoeffsFast(
+    br: *mut VP8BitReader,
+    prob: *const *const VP8BandProbas,
+    ctx: c_int,
+    dq: &quant_t,
+    mut n: c_int,
+    out: *mut i16,
+) -> c_int {
+    let mut p: *const u8 = (**prob.offset(n as isize)).probas_[ctx as usize].as_ptr();
+    while n < 16 {
+        if VP8GetBit(br, *p as c_int) == 0 {
+            return n; // previous coeff was last non-zero coeff
+        }
+        while VP8GetBit(br, *p.add(1) as c_int) == 0 {
+            // sequence of zero coeffs
+            n += 1;
+            p = (**prob.offset(n as isize)).probas_[0].as_ptr();
+            if n == 16 {
+                return 16;
+            }
+        }
+        {
+            // non zero coeff
+            let p_ctx: &[VP8ProbaArray; NUM_CTX as usize] =
+                &(**prob.offset(n as isize + 1)).probas_;
+            let v: c_int;
+            if VP8GetBit(br, *p.add(2) as c_int) == 0 {
+                v = 1;
+                p = p_ctx[1].as_ptr();
+            } else {
+                v = GetLargeValue(br, p);
+                p = p_ctx[2].as_ptr();
+            }
+            *out.add(kZigzag[n as usize] as usize) =
+                (VP8GetSigned(br, v) * dq[(n > 0) as usize]) as i16;
+        }
+        n += 1;
+    }
+    16
 }
 
+// This version of GetCoeffs() uses VP8GetBitAlt() which is an alternate version
+// of VP8GetBitAlt() targeting specific platforms.
+#[allow(non_snake_case)]
+unsafe fn GetCoeffsAlt(
+    br: *mut VP8BitReader,
+    prob: *const *const VP8BandProbas,
+    ctx: c_int,
+    dq: &quant_t,
+    mut n: c_int,
+    out: *mut i16,
+) -> c_int {
+    let mut p: *const u8 = (**prob.offset(n as isize)).probas_[ctx as usize].as_ptr();
+    while n < 16 {
+        if VP8GetBitAlt(br, *p as c_int) == 0 {
+            return n; // previous coeff was last non-zero coeff
+        }
+        while VP8GetBitAlt(br, *p.add(1) as c_int) == 0 {
+            // sequence of zero coeffs
+            n += 1;
+            p = (**prob.offset(n as isize)).probas_[0].as_ptr();
+            if n == 16 {
+                return 16;
+            }
+        }
+        {
+            // non zero coeff
+            let p_ctx: &[VP8ProbaArray; NUM_CTX as usize] =
+                &(**prob.offset(n as isize + 1)).probas_;
+            let v: c_int;
+            if VP8GetBitAlt(br, *p.add(2) as c_int) == 0 {
+                v = 1;
+                p = p_ctx[1].as_ptr();
+            } else {
+                v = GetLargeValue(br, p);
+                p = p_ctx[2].as_ptr();
+            }
+            *out.add(kZigzag[n as usize] as usize) =
+                (VP8GetSigned(br, v) * dq[(n > 0) as usize]) as i16;
+        }
+        n += 1;
+    }
+    16
+}
+
+#[allow(non_snake_case)]
+fn InitGetCoeffs() {
+    GetCoeffsOnce.call_once(|| unsafe {
+        GetCoeffs = match VP8GetCPUInfo {
+            Some(cpu_info) if cpu_info(kSlowSSSE3) != 0 => Some(GetCoeffsAlt),
+            _ => Some(GetCoeffsFast),
+        };
+    });
+}
+
+#[allow(non_snake_case)]
 #[inline]
-pub(crate) unsafe fn VP8InitIo(io: *mut VP8Io) -> c_int {
-    VP8InitIoInternal(io, WEBP_DECODER_ABI_VERSION as c_int)
+fn NzCodeBits(mut nz_coeffs: u32, nz: c_int, dc_nz: c_int) -> u32 {
+    nz_coeffs <<= 2;
+    nz_coeffs |= if nz > 3 {
+        3
+    } else if nz > 1 {
+        2
+    } else {
+        dc_nz as u32
+    };
+    nz_coeffs
+}
+
+#[allow(non_snake_case)]
+unsafe fn ParseResiduals(
+    dec: &mut VP8Decoder,
+    mb: *mut VP8MB,
+    token_br: *mut VP8BitReader,
+) -> c_int {
+    let get_coeffs: GetCoeffsFunc = GetCoeffs.unwrap();
+    let bands: &[[*const VP8BandProbas; 16 + 1]; NUM_TYPES as usize] = &dec.proba_.bands_ptr_;
+    let ac_proba: *const *const VP8BandProbas;
+    let block: &mut VP8MBData = &mut *dec.mb_data_.offset(dec.mb_x_ as isize);
+    let q: &VP8QuantMatrix = &dec.dqm_[block.segment_ as usize];
+    let mut dst: *mut i16 = block.coeffs_.as_mut_ptr();
+    let mb: &mut VP8MB = &mut *mb;
+    let left_mb: &mut VP8MB = &mut *dec.mb_info_.offset(-1);
+    let mut non_zero_y: u32 = 0;
+    let mut non_zero_uv: u32 = 0;
+    let first: c_int;
+
+    block.coeffs_ = [0; 384];
+    if block.is_i4x4_ == 0 {
+        // parse DC
+        let mut dc: [i16; 16] = [0; 16];
+        let ctx = mb.nz_dc_ as c_int + left_mb.nz_dc_ as c_int;
+        let nz = get_coeffs(
+            token_br,
+            bands[1].as_ptr(),
+            ctx,
+            &q.y2_mat_,
+            0,
+            dc.as_mut_ptr(),
+        );
+        left_mb.nz_dc_ = (nz > 0) as u8;
+        mb.nz_dc_ = left_mb.nz_dc_;
+        if nz > 1 {
+            // more than just the DC -> perform the full transform
+            (VP8TransformWHT.unwrap())(dc.as_ptr(), dst);
+        } else {
+            // only DC is non-zero -> inlined simplified transform
+            let dc0 = (dc[0] as c_int + 3) >> 3;
+            for i in 0..16 {
+                *dst.add(i * 16) = dc0 as i16;
+            }
+        }
+        first = 1;
+        ac_proba = bands[0].as_ptr();
+    } else {
+        first = 0;
+        ac_proba = bands[3].as_ptr();
+    }
+
+    let mut tnz: u8 = mb.nz_ & 0x0f;
+    let mut lnz: u8 = left_mb.nz_ & 0x0f;
+    for _ in 0..4 {
+        let mut l = lnz & 1;
+        let mut nz_coeffs: u32 = 0;
+        for _ in 0..4 {
+            let ctx = l as c_int + (tnz & 1) as c_int;
+            let nz = get_coeffs(token_br, ac_proba, ctx, &q.y1_mat_, first, dst);
+            l = (nz > first) as u8;
+            tnz = (tnz >> 1) | (l << 7);
+            nz_coeffs = NzCodeBits(nz_coeffs, nz, (*dst != 0) as c_int);
+            dst = dst.add(16);
+        }
+        tnz >>= 4;
+        lnz = (lnz >> 1) | (l << 7);
+        non_zero_y = (non_zero_y << 8) | nz_coeffs;
+    }
+    let mut out_t_nz: u32 = tnz as u32;
+    let mut out_l_nz: u32 = (lnz >> 4) as u32;
+
+    for ch in (0..4).step_by(2) {
+        let mut nz_coeffs: u32 = 0;
+        tnz = mb.nz_ >> (4 + ch);
+        lnz = left_mb.nz_ >> (4 + ch);
+        for _ in 0..2 {
+            let mut l = lnz & 1;
+            for _ in 0..2 {
+                let ctx = l as c_int + (tnz & 1) as c_int;
+                let nz = get_coeffs(token_br, bands[2].as_ptr(), ctx, &q.uv_mat_, 0, dst);
+                l = (nz > 0) as u8;
+                tnz = (tnz >> 1) | (l << 3);
+                nz_coeffs = NzCodeBits(nz_coeffs, nz, (*dst != 0) as c_int);
+                dst = dst.add(16);
+            }
+            tnz >>= 2;
+            lnz = (lnz >> 1) | (l << 5);
+        }
+        // Note: we don't really need the per-4x4 details for U/V blocks.
+        non_zero_uv |= nz_coeffs << (4 * ch);
+        out_t_nz |= ((tnz as u32) << 4) << ch;
+        out_l_nz |= ((lnz & 0xf0) as u32) << ch;
+    }
+    mb.nz_ = out_t_nz as u8;
+    left_mb.nz_ = out_l_nz as u8;
+
+    block.non_zero_y_ = non_zero_y;
+    block.non_zero_uv_ = non_zero_uv;
+
+    // We look at the mode-code of each block and check if some blocks have less
+    // than three non-zero coeffs (code < 2). This is to avoid dithering flat and
+    // empty blocks.
+    block.dither_ = if non_zero_uv & 0xaaaa != 0 {
+        0
+    } else {
+        q.dither_ as u8
+    };
+
+    (non_zero_y | non_zero_uv == 0) as c_int // will be used for further optimization
+}
+
+//------------------------------------------------------------------------------
+// Main loop
+
+// Decode one macroblock. Returns false if there is not enough data.
+#[no_mangle]
+pub unsafe extern "C" fn VP8DecodeMB(dec: *mut VP8Decoder, token_br: *mut VP8BitReader) -> c_int {
+    let dec: &mut VP8Decoder = &mut *dec;
+
+    let left: *mut VP8MB = dec.mb_info_.offset(-1);
+    let mb: *mut VP8MB = dec.mb_info_.offset(dec.mb_x_ as isize);
+    let block: *mut VP8MBData = dec.mb_data_.offset(dec.mb_x_ as isize);
+    let mut skip: c_int = if dec.use_skip_proba_ != 0 {
+        (*block).skip_ as c_int
+    } else {
+        0
+    };
+
+    if skip == 0 {
+        skip = ParseResiduals(dec, mb, token_br);
+    } else {
+        (*mb).nz_ = 0;
+        (*left).nz_ = 0;
+        if (*block).is_i4x4_ == 0 {
+            (*mb).nz_dc_ = 0;
+            (*left).nz_dc_ = 0;
+        }
+        (*block).non_zero_y_ = 0;
+        (*block).non_zero_uv_ = 0;
+        (*block).dither_ = 0;
+    }
+
+    if dec.filter_type_ > 0 {
+        // store filter info
+        let finfo: &mut VP8FInfo = &mut *dec.f_info_.offset(dec.mb_x_ as isize);
+        *finfo = dec.fstrengths_[(*block).segment_ as usize][(*block).is_i4x4_ as usize];
+        finfo.f_inner_ |= (skip == 0) as u8;
+    }
+
+    ((*token_br).eof_ == 0) as c_int
+}
+
+// To be called at the start of a new scanline, to initialize predictors.
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitScanline(dec: *mut VP8Decoder) {
+    let dec: &mut VP8Decoder = &mut *dec;
+
+    let left: &mut VP8MB = &mut *dec.mb_info_.offset(-1);
+    left.nz_ = 0;
+    left.nz_dc_ = 0;
+    dec.intra_l_ = [B_DC_PRED as u8; 4];
+    dec.mb_x_ = 0;
+}
+
+#[allow(non_snake_case)]
+unsafe fn ParseFrame(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int {
+    (*dec).mb_y_ = 0;
+    while (*dec).mb_y_ < (*dec).br_mb_y_ {
+        // Parse bitstream for this row.
+        let token_br: *mut VP8BitReader =
+            &mut (*dec).parts_[((*dec).mb_y_ as u32 & (*dec).num_parts_minus_one_) as usize];
+        if VP8ParseIntraModeRow(&mut (*dec).br_, dec) == 0 {
+            return VP8SetError(
+                dec,
+                VP8StatusCode::VP8_STATUS_NOT_ENOUGH_DATA,
+                c_str!("Premature end-of-partition0 encountered."),
+            );
+        }
+        while (*dec).mb_x_ < (*dec).mb_w_ {
+            if VP8DecodeMB(dec, token_br) == 0 {
+                return VP8SetError(
+                    dec,
+                    VP8StatusCode::VP8_STATUS_NOT_ENOUGH_DATA,
+                    c_str!("Premature end-of-file encountered."),
+                );
+            }
+            (*dec).mb_x_ += 1;
+        }
+        VP8InitScanline(dec); // Prepare for next scanline
+
+        // Reconstruct, filter and emit the row.
+        if VP8ProcessRow(dec, io) == 0 {
+            return VP8SetError(
+                dec,
+                VP8StatusCode::VP8_STATUS_USER_ABORT,
+                c_str!("Output aborted."),
+            );
+        }
+        (*dec).mb_y_ += 1;
+    }
+    if (*dec).mt_method_ > 0
+        && ((*WebPGetWorkerInterface()).Sync.unwrap())(&mut (*dec).worker_) == 0
+    {
+        return 0;
+    }
+
+    1
+}
+
+// Decode a picture. Will call VP8GetHeaders() if it wasn't done already.
+// Returns false in case of error.
+#[no_mangle]
+pub unsafe extern "C" fn VP8Decode(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int {
+    if dec.is_null() {
+        return 0;
+    }
+    if io.is_null() {
+        return VP8SetError(
+            dec,
+            VP8StatusCode::VP8_STATUS_INVALID_PARAM,
+            c_str!("NULL VP8Io parameter in VP8Decode()."),
+        );
+    }
+
+    if (*dec).ready_ == 0 && VP8GetHeaders(dec, io) == 0 {
+        return 0;
+    }
+    c_assert!((*dec).ready_ != 0);
+
+    // Finish setting up the decoding parameter. Will call io->setup().
+    let mut ok = (VP8EnterCritical(dec, io) == VP8StatusCode::VP8_STATUS_OK) as c_int;
+    if ok != 0 {
+        // good to go.
+        // Will allocate memory and prepare everything.
+        ok = VP8InitFrame(dec, io);
+
+        // Main decoding loop
+        if ok != 0 {
+            ok = ParseFrame(dec, io);
+        }
+
+        // Exit.
+        ok &= VP8ExitCritical(dec, io);
+    }
+
+    if ok == 0 {
+        VP8Clear(dec);
+        return 0;
+    }
+
+    (*dec).ready_ = 0;
+    ok
+}
+
+// Resets the decoder in its initial state, reclaiming memory.
+// Not a mandatory call between calls to VP8Decode().
+#[no_mangle]
+pub unsafe extern "C" fn VP8Clear(dec: *mut VP8Decoder) {
+    if dec.is_null() {
+        return;
+    }
+    ((*WebPGetWorkerInterface()).End.unwrap())(&mut (*dec).worker_);
+    WebPDeallocateAlphaMemory(dec);
+    WebPSafeFree((*dec).mem_);
+    (*dec).mem_ = ptr::null_mut();
+    (*dec).mem_size_ = 0;
+    ptr::write_bytes(&mut (*dec).br_, 0, 1);
+    (*dec).ready_ = 0;
+}
+
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ffi::CStr;
+
+    #[cfg(feature = "c-reference")]
+    use test_util::assert_same_state;
+    use test_util::{check_parity, corpus, new_io, put, Digest};
+
+    // The reference decoder has its own alpha decoder, in C, behind alph_dec_.
+    #[cfg(feature = "c-reference")]
+    #[allow(improper_ctypes)]
+    extern "C" {
+        fn WebPRef_VP8New() -> *mut VP8Decoder;
+        fn WebPRef_VP8Delete(dec: *mut VP8Decoder);
+        fn WebPRef_VP8GetHeaders(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int;
+        fn WebPRef_VP8Decode(dec: *mut VP8Decoder, io: *mut VP8Io) -> c_int;
+        fn WebPRef_VP8GetInfo(
+            data: *const u8,
+            data_size: usize,
+            chunk_size: usize,
+            width: *mut c_int,
+            height: *mut c_int,
+        ) -> c_int;
+        fn WebPRef_VP8StatusMessage(dec: *mut VP8Decoder) -> *const c_char;
+    }
+
+    unsafe fn error_msg(msg: *const c_char) -> String {
+        CStr::from_ptr(msg).to_string_lossy().into_owned()
+    }
+
+    // What VP8GetHeaders() sets up in the decoder and in the io, but the bit
+    // readers.
+    fn headers(dec: &VP8Decoder, io: &VP8Io) -> (Vec<i64>, String) {
+        let (f, p) = (&dec.frm_hdr_, &dec.pic_hdr_);
+        let (s, h) = (&dec.segment_hdr_, &dec.filter_hdr_);
+        let mut values = vec![
+            dec.status_ as i64,
+            dec.ready_ as i64,
+            f.key_frame_ as i64,
+            f.profile_ as i64,
+            f.show_ as i64,
+            f.partition_length_ as i64,
+            p.width_ as i64,
+            p.height_ as i64,
+            p.xscale_ as i64,
+            p.yscale_ as i64,
+            p.colorspace_ as i64,
+            p.clamp_type_ as i64,
+            s.use_segment_ as i64,
+            s.update_map_ as i64,
+            s.absolute_delta_ as i64,
+            h.simple_ as i64,
+            h.level_ as i64,
+            h.sharpness_ as i64,
+            h.use_lf_delta_ as i64,
+            dec.filter_type_ as i64,
+            dec.mb_w_ as i64,
+            dec.mb_h_ as i64,
+            dec.num_parts_minus_one_ as i64,
+            dec.use_skip_proba_ as i64,
+            dec.skip_p_ as i64,
+            io.width as i64,
+            io.height as i64,
+            io.mb_w as i64,
+            io.mb_h as i64,
+            io.crop_left as i64,
+            io.crop_right as i64,
+            io.crop_top as i64,
+            io.crop_bottom as i64,
+            io.use_scaling as i64,
+            io.scaled_width as i64,
+            io.scaled_height as i64,
+        ];
+        values.extend(s.quantizer_.iter().map(|&v| v as i64));
+        values.extend(s.filter_strength_.iter().map(|&v| v as i64));
+        values.extend(h.ref_lf_delta_.iter().map(|&v| v as i64));
+        values.extend(h.mode_lf_delta_.iter().map(|&v| v as i64));
+        values.extend(dec.proba_.segments_.iter().map(|&v| v as i64));
+        for bands in &dec.proba_.bands_ {
+            for band in bands {
+                for probas in &band.probas_ {
+                    values.extend(probas.iter().map(|&v| v as i64));
+                }
+            }
+        }
+        for q in &dec.dqm_ {
+            for mat in &[q.y1_mat_, q.y2_mat_, q.uv_mat_] {
+                values.extend(mat.iter().map(|&v| v as i64));
+            }
+            values.push(q.uv_quant_ as i64);
+        }
+        (values, unsafe { error_msg(dec.error_msg_) })
+    }
+
+    // Runs VP8GetHeaders() on 'data', adding the resulting state to 'digest',
+    // and compares it with the reference's.
+    fn check_headers(data: &[u8], digest: &mut Digest) {
+        let mut io = new_io(data);
+        unsafe {
+            let dec = VP8New();
+            let ok = VP8GetHeaders(dec, &mut io);
+            let (values, msg) = headers(&*dec, &io);
+            let status_msg = error_msg(VP8StatusMessage(dec));
+            digest.update_i32(&[ok]);
+            digest.update_i64(&values);
+            digest.update(msg.as_bytes());
+            digest.update(status_msg.as_bytes());
+            #[cfg(feature = "c-reference")]
+            {
+                let mut ref_io = new_io(data);
+                let ref_dec = WebPRef_VP8New();
+                assert_eq!(ok, WebPRef_VP8GetHeaders(ref_dec, &mut ref_io));
+                assert_eq!((values, msg), headers(&*ref_dec, &ref_io));
+                assert_same_state(&(*dec).br_, &(*ref_dec).br_);
+                for (pa, pb) in (*dec).parts_.iter().zip(&(*ref_dec).parts_) {
+                    assert_same_state(pa, pb);
+                }
+                assert_eq!(status_msg, error_msg(WebPRef_VP8StatusMessage(ref_dec)));
+                WebPRef_VP8Delete(ref_dec);
+            }
+            VP8Delete(dec);
+        }
+    }
+
+    #[test]
+    fn test_get_headers_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            for len in (0..64).chain((64..data.len()).step_by(97)) {
+                check_headers(&data[..len], &mut digest);
+            }
+            check_headers(&data, &mut digest);
+            // Corrupted headers.
+            for pos in 0..32 {
+                for &bits in &[0x01, 0x10, 0x80, 0xff] {
+                    let mut corrupted = data.clone();
+                    corrupted[pos] ^= bits;
+                    check_headers(&corrupted, &mut digest);
+                }
+            }
+        }
+        unsafe {
+            let dec = VP8New();
+            assert_eq!(VP8GetHeaders(dec, ptr::null_mut()), 0);
+            assert_eq!(VP8Status(dec), VP8StatusCode::VP8_STATUS_INVALID_PARAM);
+            assert_eq!(
+                error_msg(VP8StatusMessage(dec)),
+                "null VP8Io passed to VP8GetHeaders()"
+            );
+            VP8Delete(dec);
+            assert_eq!(error_msg(VP8StatusMessage(ptr::null_mut())), "no object");
+        }
+        check_parity("dec::vp8_dec::test_get_headers_parity", digest);
+    }
+
+    #[test]
+    fn test_get_info_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            for pos in 0..10 {
+                for &bits in &[0x00, 0x01, 0x02, 0x10, 0x20, 0x80] {
+                    let mut header = data[..10].to_vec();
+                    header[pos] ^= bits;
+                    for &chunk_size in &[0, 10, data.len()] {
+                        let (mut w, mut h) = (0, 0);
+                        let ok =
+                            unsafe { VP8GetInfo(header.as_ptr(), 10, chunk_size, &mut w, &mut h) };
+                        digest.update_i32(&[ok, w, h]);
+                        #[cfg(feature = "c-reference")]
+                        {
+                            let (mut ref_w, mut ref_h) = (0, 0);
+                            let ref_ok = unsafe {
+                                WebPRef_VP8GetInfo(
+                                    header.as_ptr(),
+                                    10,
+                                    chunk_size,
+                                    &mut ref_w,
+                                    &mut ref_h,
+                                )
+                            };
+                            assert_eq!((ok, w, h), (ref_ok, ref_w, ref_h));
+                        }
+                    }
+                }
+            }
+        }
+        check_parity("dec::vp8_dec::test_get_info_parity", digest);
+    }
+
+    type Decoded = (c_int, VP8StatusCode, Vec<u8>);
+
+    unsafe fn decode_with(
+        new: unsafe extern "C" fn() -> *mut VP8Decoder,
+        decode: unsafe extern "C" fn(*mut VP8Decoder, *mut VP8Io) -> c_int,
+        delete: unsafe extern "C" fn(*mut VP8Decoder),
+        data: &[u8],
+    ) -> Decoded {
+        let mut out = Vec::new();
+        let mut io = new_io(data);
+        io.put = Some(put);
+        io.opaque = &mut out as *mut Vec<u8> as *mut c_void;
+        let dec = new();
+        let ok = decode(dec, &mut io);
+        let status = (*dec).status_;
+        delete(dec);
+        (ok, status, out)
+    }
+
+    fn decode(data: &[u8]) -> Decoded {
+        unsafe { decode_with(VP8New, VP8Decode, VP8Delete, data) }
+    }
+
+    #[cfg(feature = "c-reference")]
+    fn decode_ref(data: &[u8]) -> Decoded {
+        unsafe { decode_with(WebPRef_VP8New, WebPRef_VP8Decode, WebPRef_VP8Delete, data) }
+    }
+
+    #[test]
+    fn test_decode_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            let (ok, status, out) = decode(&data);
+            assert_eq!(ok, 1);
+            assert_eq!(status, VP8StatusCode::VP8_STATUS_OK);
+            assert!(!out.is_empty());
+            digest.update(&out);
+            #[cfg(feature = "c-reference")]
+            assert_eq!((ok, status, out), decode_ref(&data));
+            for len in (0..data.len()).step_by(311) {
+                let (ok, status, out) = decode(&data[..len]);
+                digest.update(&[ok as u8, status as u8]);
+                digest.update(&out);
+                #[cfg(feature = "c-reference")]
+                assert_eq!((ok, status, out), decode_ref(&data[..len]));
+            }
+        }
+        check_parity("dec::vp8_dec::test_decode_parity", digest);
+    }
 }
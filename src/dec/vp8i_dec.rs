@@ -4,10 +4,46 @@ use dec::alpha_dec::ALPHDecoder;
 use dec::common_dec::*;
 use dec::vp8_dec::VP8Io;
 use decode::VP8StatusCode;
+use dsp::dsp::BPS;
 use utils::bit_reader_utils::VP8BitReader;
 use utils::random_utils::VP8Random;
 use utils::thread_utils::WebPWorker;
 
+// version numbers
+pub(crate) const DEC_MAJ_VERSION: c_int = 1;
+pub(crate) const DEC_MIN_VERSION: c_int = 0;
+pub(crate) const DEC_REV_VERSION: c_int = 0;
+
+// YUV-cache parameters. Cache is 32-bytes wide (= one cacheline).
+// Constraints are: We need to store one 16x16 block of luma samples (y),
+// and two 8x8 chroma blocks (u/v). These are better be 16-bytes aligned,
+// in order to be SIMD-friendly. We also need to store the top, left and
+// top-left samples (from previously decoded blocks), along with four
+// extra top-right samples for luma (intra4x4 prediction only).
+// One possible layout is, using 32 * (17 + 9) bytes:
+//
+//   .+------   <- only 1 pixel high
+//   .|yyyyt.
+//   .|yyyyt.
+//   .|yyyyt.
+//   .|yyyy..
+//   .+--.+--   <- only 1 pixel high
+//   .|uu.|vv
+//   .|uu.|vv
+//
+// Every character is a 4x4 block, with legend:
+//  '.' = unused
+//  'y' = y-samples   'u' = u-samples     'v' = u-samples
+//  '|' = left sample,   '-' = top sample,    '+' = top-left sample
+//  't' = extra top-right sample for 4x4 modes
+pub(crate) const YUV_SIZE: c_int = BPS * 17 + BPS * 9;
+pub(crate) const Y_OFF: c_int = BPS + 8;
+pub(crate) const U_OFF: c_int = Y_OFF + BPS * 16 + BPS;
+pub(crate) const V_OFF: c_int = U_OFF + 16;
+
+// minimal width under which lossy multi-threading is always disabled
+pub(crate) const MIN_WIDTH_FOR_THREADS: c_int = 512;
+
 #[repr(C)]
 pub(crate) struct VP8FrameHeader {
     pub(crate) key_frame_: u8,
@@ -46,7 +82,7 @@ pub(crate) struct VP8BandProbas {
 
 // Struct collecting all frame-persistent probabilities.
 #[repr(C)]
-pub(crate) struct VP8Proba {
+pub struct VP8Proba {
     pub(crate) segments_: [u8; MB_FEATURE_TREE_PROBS as usize],
     // Type: 0:Intra16-AC  1:Intra16-DC   2:Chroma   3:Intra4
     pub(crate) bands_: [[VP8BandProbas; NUM_BANDS as usize]; NUM_TYPES as usize],
@@ -63,6 +99,7 @@ pub(crate) struct VP8FilterHeader {
     pub(crate) mode_lf_delta_: [c_int; NUM_MODE_LF_DELTAS as usize],
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub(crate) struct VP8FInfo {
     // filter specs
@@ -213,12 +250,9 @@ pub struct VP8Decoder {
     pub(crate) fstrengths_: [[VP8FInfo; 2]; NUM_MB_SEGMENTS as usize], // precalculated per-segment/type
 
     // Alpha
-    pub(crate) alph_dec_: *mut ALPHDecoder, // alpha-plane decoder object
-    pub(crate) alpha_data_: *const u8,      // compressed alpha data (if present)
+    pub(crate) alph_dec_: Option<Box<ALPHDecoder>>, // alpha-plane decoder object, owns the plane
+    pub(crate) alpha_data_: *const u8,              // compressed alpha data (if present)
     pub(crate) alpha_data_size_: usize,
-    pub(crate) is_alpha_decoded_: c_int, // true if alpha_data_ is decoded in alpha_plane_
-    pub(crate) alpha_plane_mem_: *mut u8, // memory allocated for alpha_plane_
-    pub(crate) alpha_plane_: *mut u8,    // output. Persistent, contains the whole data.
-    pub(crate) alpha_prev_line_: *const u8, // last decoded alpha row (or NULL)
+    pub(crate) is_alpha_decoded_: c_int, // true if alpha_data_ is decoded in the alpha plane
     pub(crate) alpha_dithering_: c_int,  // derived from decoding options (0=off, 100=full)
 }
@@ -1,13 +1,2487 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// main entry for the decoder
+//
+// Authors: Vikas Arora (vikaas.arora@gmail.com)
+//          Jyrki Alakuijala (jyrki@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
 use std::os::raw::*;
+use std::ptr;
 
 use dec::alpha_dec::ALPHDecoder;
+use dec::vp8_dec::VP8Io;
+use dec::vp8li_dec::*;
+use dec::webp_dec::WebPIoInitFromOptions;
+use dec::webpi_dec::WebPDecParams;
+use decode::{VP8StatusCode, WebPIsPremultipliedMode, WebPIsRGBMode, MODE_BGRA, WEBP_CSP_MODE};
+use dsp::alpha_processing::{
+    WebPExtractAlpha, WebPExtractGreen, WebPInitAlphaProcessing, WebPMultARGBRow, WebPMultARGBRows,
+};
+use dsp::dsp::{WEBP_FILTER_HORIZONTAL, WEBP_FILTER_NONE};
+use dsp::lossless::{
+    VP8LColorIndexInverseTransformAlpha, VP8LConvertFromBGRA, VP8LDspInit, VP8LInverseTransform,
+};
+use dsp::lossless_common::VP8LSubSampleSize;
+use dsp::rescaler::WebPRescalerExportRow;
+use dsp::yuv::{WebPConvertARGBToUV, WebPConvertARGBToY, WebPInitConvertARGBToYUV};
+use format_constants::VP8LImageTransformType::*;
+use format_constants::{
+    VP8LImageTransformType, DEFAULT_CODE_LENGTH, HUFFMAN_CODES_PER_META_CODE, MAX_CACHE_BITS,
+    NUM_DISTANCE_CODES, NUM_LENGTH_CODES, NUM_LITERAL_CODES, NUM_TRANSFORMS,
+    VP8L_FRAME_HEADER_SIZE, VP8L_IMAGE_SIZE_BITS, VP8L_MAGIC_BYTE, VP8L_VERSION_BITS,
+};
+use sys::{WebPDecBuffer, WebPYUVABuffer};
+use utils::bit_reader_utils::{
+    VP8LBitReader, VP8LFillBitWindow, VP8LInitBitReader, VP8LIsEndOfStream, VP8LPrefetchBits,
+    VP8LReadBits, VP8LSetBitPos,
+};
+use utils::color_cache_utils::{
+    VP8LColorCache, VP8LColorCacheClear, VP8LColorCacheCopy, VP8LColorCacheInit,
+    VP8LColorCacheInsert, VP8LColorCacheLookup,
+};
+use utils::huffman_utils::{
+    HTreeGroup, HuffmanCode, HuffmanCode32, VP8LBuildHuffmanTable, VP8LHtreeGroupsFree,
+    VP8LHtreeGroupsNew, HUFFMAN_PACKED_BITS, HUFFMAN_PACKED_TABLE_SIZE, HUFFMAN_TABLE_BITS,
+    HUFFMAN_TABLE_MASK, LENGTHS_TABLE_BITS, LENGTHS_TABLE_MASK,
+};
+use utils::rescaler_utils::{
+    rescaler_t, WebPRescaleNeededLines, WebPRescaler, WebPRescalerHasPendingOutput,
+    WebPRescalerImport, WebPRescalerInit,
+};
+use utils::utils::{WebPSafeCalloc, WebPSafeFree, WebPSafeMalloc};
+
+const NUM_ARGB_CACHE_ROWS: c_int = 16;
+
+#[allow(non_upper_case_globals)]
+const kCodeLengthLiterals: c_int = 16;
+#[allow(non_upper_case_globals)]
+const kCodeLengthRepeatCode: c_int = 16;
+#[allow(non_upper_case_globals)]
+const kCodeLengthExtraBits: [u8; 3] = [2, 3, 7];
+#[allow(non_upper_case_globals)]
+const kCodeLengthRepeatOffsets: [u8; 3] = [3, 3, 11];
+
+// -----------------------------------------------------------------------------
+//  Five Huffman codes are used at each meta code:
+//  1. green + length prefix codes + color cache codes,
+//  2. alpha,
+//  3. red,
+//  4. blue, and,
+//  5. distance prefix codes.
+const GREEN: usize = 0;
+const RED: usize = 1;
+const BLUE: usize = 2;
+const ALPHA: usize = 3;
+const DIST: usize = 4;
+
+#[allow(non_upper_case_globals)]
+const kAlphabetSize: [u16; HUFFMAN_CODES_PER_META_CODE] = [
+    (NUM_LITERAL_CODES + NUM_LENGTH_CODES) as u16,
+    NUM_LITERAL_CODES as u16,
+    NUM_LITERAL_CODES as u16,
+    NUM_LITERAL_CODES as u16,
+    NUM_DISTANCE_CODES as u16,
+];
+
+#[allow(non_upper_case_globals)]
+const kLiteralMap: [u8; HUFFMAN_CODES_PER_META_CODE] = [0, 1, 1, 1, 0];
+
+const NUM_CODE_LENGTH_CODES: usize = 19;
+#[allow(non_upper_case_globals)]
+const kCodeLengthCodeOrder: [u8; NUM_CODE_LENGTH_CODES] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+const CODE_TO_PLANE_CODES: c_int = 120;
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+const kCodeToPlane: [u8; CODE_TO_PLANE_CODES as usize] = [
+    0x18, 0x07, 0x17, 0x19, 0x28, 0x06, 0x27, 0x29, 0x16, 0x1a,
+    0x26, 0x2a, 0x38, 0x05, 0x37, 0x39, 0x15, 0x1b, 0x36, 0x3a,
+    0x25, 0x2b, 0x48, 0x04, 0x47, 0x49, 0x14, 0x1c, 0x35, 0x3b,
+    0x46, 0x4a, 0x24, 0x2c, 0x58, 0x45, 0x4b, 0x34, 0x3c, 0x03,
+    0x57, 0x59, 0x13, 0x1d, 0x56, 0x5a, 0x23, 0x2d, 0x44, 0x4c,
+    0x55, 0x5b, 0x33, 0x3d, 0x68, 0x02, 0x67, 0x69, 0x12, 0x1e,
+    0x66, 0x6a, 0x22, 0x2e, 0x54, 0x5c, 0x43, 0x4d, 0x65, 0x6b,
+    0x32, 0x3e, 0x78, 0x01, 0x77, 0x79, 0x53, 0x5d, 0x11, 0x1f,
+    0x64, 0x6c, 0x42, 0x4e, 0x76, 0x7a, 0x21, 0x2f, 0x75, 0x7b,
+    0x31, 0x3f, 0x63, 0x6d, 0x52, 0x5e, 0x00, 0x74, 0x7c, 0x41,
+    0x4f, 0x10, 0x20, 0x62, 0x6e, 0x30, 0x73, 0x7d, 0x51, 0x5f,
+    0x40, 0x72, 0x7e, 0x61, 0x6f, 0x50, 0x71, 0x7f, 0x60, 0x70,
+];
+
+// Memory needed for lookup tables of one Huffman tree group. Red, blue, alpha
+// and distance alphabets are constant (256 for red, blue and alpha, 40 for
+// distance) and lookup table sizes for them in worst case are 630 and 410
+// respectively. Size of green alphabet depends on color cache size and is equal
+// to 256 (green component values) + 24 (length prefix values)
+// + color_cache_size (between 0 and 2048).
+// All values computed for 8-bit first level lookup with Mark Adler's tool:
+// http://www.hdfgroup.org/ftp/lib-external/zlib/zlib-1.2.5/examples/enough.c
+const FIXED_TABLE_SIZE: u16 = 630 * 3 + 410;
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+const kTableSize: [u16; 12] = [
+    FIXED_TABLE_SIZE + 654,
+    FIXED_TABLE_SIZE + 656,
+    FIXED_TABLE_SIZE + 658,
+    FIXED_TABLE_SIZE + 662,
+    FIXED_TABLE_SIZE + 670,
+    FIXED_TABLE_SIZE + 686,
+    FIXED_TABLE_SIZE + 718,
+    FIXED_TABLE_SIZE + 782,
+    FIXED_TABLE_SIZE + 912,
+    FIXED_TABLE_SIZE + 1168,
+    FIXED_TABLE_SIZE + 1680,
+    FIXED_TABLE_SIZE + 2704,
+];
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LCheckSignature(data: *const u8, size: usize) -> c_int {
+    (size >= VP8L_FRAME_HEADER_SIZE && *data == VP8L_MAGIC_BYTE && (*data.add(4) >> 5) == 0)
+        as c_int // version
+}
+
+#[allow(non_snake_case)]
+unsafe fn ReadImageInfo(
+    br: &mut VP8LBitReader,
+    width: &mut c_int,
+    height: &mut c_int,
+    has_alpha: &mut c_int,
+) -> c_int {
+    if VP8LReadBits(br, 8) != VP8L_MAGIC_BYTE as u32 {
+        return 0;
+    }
+    *width = VP8LReadBits(br, VP8L_IMAGE_SIZE_BITS) as c_int + 1;
+    *height = VP8LReadBits(br, VP8L_IMAGE_SIZE_BITS) as c_int + 1;
+    *has_alpha = VP8LReadBits(br, 1) as c_int;
+    if VP8LReadBits(br, VP8L_VERSION_BITS) != 0 {
+        return 0;
+    }
+    (br.eos_ == 0) as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LGetInfo(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+    has_alpha: *mut c_int,
+) -> c_int {
+    if data.is_null()
+        || data_size < VP8L_FRAME_HEADER_SIZE // not enough data
+        || VP8LCheckSignature(data, data_size) == 0
+    {
+        0 // bad signature
+    } else {
+        let mut w: c_int = 0;
+        let mut h: c_int = 0;
+        let mut a: c_int = 0;
+        let mut br: VP8LBitReader = mem::zeroed();
+        VP8LInitBitReader(&mut br, data, data_size);
+        if ReadImageInfo(&mut br, &mut w, &mut h, &mut a) == 0 {
+            return 0;
+        }
+        if !width.is_null() {
+            *width = w;
+        }
+        if !height.is_null() {
+            *height = h;
+        }
+        if !has_alpha.is_null() {
+            *has_alpha = a;
+        }
+        1
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetCopyDistance(distance_symbol: c_int, br: &mut VP8LBitReader) -> c_int {
+    if distance_symbol < 4 {
+        return distance_symbol + 1;
+    }
+    let extra_bits = (distance_symbol - 2) >> 1;
+    let offset = (2 + (distance_symbol & 1)) << extra_bits;
+    offset + VP8LReadBits(br, extra_bits) as c_int + 1
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetCopyLength(length_symbol: c_int, br: &mut VP8LBitReader) -> c_int {
+    // Length and distance prefixes are encoded the same way.
+    GetCopyDistance(length_symbol, br)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn PlaneCodeToDistance(xsize: c_int, plane_code: c_int) -> c_int {
+    if plane_code > CODE_TO_PLANE_CODES {
+        plane_code - CODE_TO_PLANE_CODES
+    } else {
+        let dist_code = kCodeToPlane[(plane_code - 1) as usize] as c_int;
+        let yoffset = dist_code >> 4;
+        let xoffset = 8 - (dist_code & 0xf);
+        let dist = yoffset * xsize + xoffset;
+        if dist >= 1 {
+            dist
+        } else {
+            1 // dist<1 can happen if xsize is very small
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Decodes the next Huffman code from bit-stream.
+// FillBitWindow(br) needs to be called at minimum every second call
+// to ReadSymbol, in order to pre-fetch enough bits.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn ReadSymbol(mut table: *const HuffmanCode, br: &mut VP8LBitReader) -> c_int {
+    let mut val: u32 = VP8LPrefetchBits(br);
+    table = table.add((val & HUFFMAN_TABLE_MASK) as usize);
+    let nbits = (*table).bits as c_int - HUFFMAN_TABLE_BITS;
+    if nbits > 0 {
+        let bit_pos = br.bit_pos_ + HUFFMAN_TABLE_BITS;
+        VP8LSetBitPos(br, bit_pos);
+        val = VP8LPrefetchBits(br);
+        table = table.add((*table).value as usize);
+        table = table.add((val & ((1 << nbits) - 1)) as usize);
+    }
+    let bit_pos = br.bit_pos_ + (*table).bits as c_int;
+    VP8LSetBitPos(br, bit_pos);
+    (*table).value as c_int
+}
+
+// Reads packed symbol depending on GREEN channel
+const BITS_SPECIAL_MARKER: c_int = 0x100; // something large enough (and a bit-mask)
+const PACKED_NON_LITERAL_CODE: c_int = 0; // must be < NUM_LITERAL_CODES
+#[allow(non_snake_case)]
+#[inline]
+fn ReadPackedSymbols(group: &HTreeGroup, br: &mut VP8LBitReader, dst: &mut u32) -> c_int {
+    let val: u32 = VP8LPrefetchBits(br) & (HUFFMAN_PACKED_TABLE_SIZE - 1);
+    let code: HuffmanCode32 = group.packed_table[val as usize];
+    c_assert!(group.use_packed_table != 0);
+    if code.bits < BITS_SPECIAL_MARKER {
+        let bit_pos = br.bit_pos_ + code.bits;
+        VP8LSetBitPos(br, bit_pos);
+        *dst = code.value;
+        PACKED_NON_LITERAL_CODE
+    } else {
+        let bit_pos = br.bit_pos_ + code.bits - BITS_SPECIAL_MARKER;
+        VP8LSetBitPos(br, bit_pos);
+        c_assert!(code.value >= NUM_LITERAL_CODES as u32);
+        code.value as c_int
+    }
+}
+
+#[allow(non_snake_case)]
+fn AccumulateHCode(hcode: HuffmanCode, shift: c_int, huff: &mut HuffmanCode32) -> c_int {
+    huff.bits += hcode.bits as c_int;
+    huff.value |= (hcode.value as u32) << shift;
+    c_assert!(huff.bits <= HUFFMAN_TABLE_BITS);
+    hcode.bits as c_int
+}
+
+#[allow(non_snake_case)]
+unsafe fn BuildPackedTable(htree_group: &mut HTreeGroup) {
+    for code in 0..HUFFMAN_PACKED_TABLE_SIZE {
+        let mut bits: u32 = code;
+        let mut huff = HuffmanCode32::default();
+        let hcode: HuffmanCode = *htree_group.htrees[GREEN].add(bits as usize);
+        if hcode.value as c_int >= NUM_LITERAL_CODES {
+            huff.bits = hcode.bits as c_int + BITS_SPECIAL_MARKER;
+            huff.value = hcode.value as u32;
+        } else {
+            huff.bits = 0;
+            huff.value = 0;
+            bits >>= AccumulateHCode(hcode, 8, &mut huff);
+            bits >>= AccumulateHCode(*htree_group.htrees[RED].add(bits as usize), 16, &mut huff);
+            bits >>= AccumulateHCode(*htree_group.htrees[BLUE].add(bits as usize), 0, &mut huff);
+            AccumulateHCode(*htree_group.htrees[ALPHA].add(bits as usize), 24, &mut huff);
+        }
+        htree_group.packed_table[code as usize] = huff;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ReadHuffmanCodeLengths(
+    dec: &mut VP8LDecoder,
+    code_length_code_lengths: &[c_int; NUM_CODE_LENGTH_CODES],
+    num_symbols: c_int,
+    code_lengths: *mut c_int,
+) -> c_int {
+    let br: &mut VP8LBitReader = &mut dec.br_;
+    let mut prev_code_len = DEFAULT_CODE_LENGTH;
+    let mut table = [HuffmanCode::default(); 1 << LENGTHS_TABLE_BITS];
+
+    if VP8LBuildHuffmanTable(
+        table.as_mut_ptr(),
+        LENGTHS_TABLE_BITS,
+        code_length_code_lengths.as_ptr(),
+        NUM_CODE_LENGTH_CODES as c_int,
+    ) == 0
+    {
+        dec.status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+        return 0;
+    }
+
+    let mut max_symbol = if VP8LReadBits(br, 1) != 0 {
+        // use length
+        let length_nbits = 2 + 2 * VP8LReadBits(br, 3) as c_int;
+        let max_symbol = 2 + VP8LReadBits(br, length_nbits) as c_int;
+        if max_symbol > num_symbols {
+            dec.status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+            return 0;
+        }
+        max_symbol
+    } else {
+        num_symbols
+    };
+
+    let mut symbol = 0;
+    while symbol < num_symbols {
+        if max_symbol == 0 {
+            break;
+        }
+        max_symbol -= 1;
+        VP8LFillBitWindow(br);
+        let p: &HuffmanCode = &table[(VP8LPrefetchBits(br) & LENGTHS_TABLE_MASK) as usize];
+        let bit_pos = br.bit_pos_ + p.bits as c_int;
+        VP8LSetBitPos(br, bit_pos);
+        let code_len = p.value as c_int;
+        if code_len < kCodeLengthLiterals {
+            *code_lengths.offset(symbol as isize) = code_len;
+            symbol += 1;
+            if code_len != 0 {
+                prev_code_len = code_len;
+            }
+        } else {
+            let use_prev = code_len == kCodeLengthRepeatCode;
+            let slot = (code_len - kCodeLengthLiterals) as usize;
+            let extra_bits = kCodeLengthExtraBits[slot] as c_int;
+            let repeat_offset = kCodeLengthRepeatOffsets[slot] as c_int;
+            let repeat = VP8LReadBits(br, extra_bits) as c_int + repeat_offset;
+            if symbol + repeat > num_symbols {
+                dec.status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+                return 0;
+            } else {
+                let length = if use_prev { prev_code_len } else { 0 };
+                for _ in 0..repeat {
+                    *code_lengths.offset(symbol as isize) = length;
+                    symbol += 1;
+                }
+            }
+        }
+    }
+    1
+}
+
+// 'code_lengths' is pre-allocated temporary buffer, used for creating Huffman
+// tree.
+#[allow(non_snake_case)]
+unsafe fn ReadHuffmanCode(
+    alphabet_size: c_int,
+    dec: &mut VP8LDecoder,
+    code_lengths: *mut c_int,
+    table: *mut HuffmanCode,
+) -> c_int {
+    let mut ok;
+    let mut size = 0;
+    let simple_code = VP8LReadBits(&mut dec.br_, 1);
+
+    ptr::write_bytes(code_lengths, 0, alphabet_size as usize);
+
+    if simple_code != 0 {
+        // Read symbols, codes & code lengths directly.
+        let br: &mut VP8LBitReader = &mut dec.br_;
+        let num_symbols = VP8LReadBits(br, 1) + 1;
+        let first_symbol_len_code = VP8LReadBits(br, 1);
+        // The first code is either 1 bit or 8 bit code.
+        let mut symbol = VP8LReadBits(br, if first_symbol_len_code == 0 { 1 } else { 8 });
+        *code_lengths.add(symbol as usize) = 1;
+        // The second code (if present), is always 8 bit long.
+        if num_symbols == 2 {
+            symbol = VP8LReadBits(br, 8);
+            *code_lengths.add(symbol as usize) = 1;
+        }
+        ok = 1;
+    } else {
+        // Decode Huffman-coded code lengths.
+        let mut code_length_code_lengths: [c_int; NUM_CODE_LENGTH_CODES] =
+            [0; NUM_CODE_LENGTH_CODES];
+        let num_codes = VP8LReadBits(&mut dec.br_, 4) as usize + 4;
+        if num_codes > NUM_CODE_LENGTH_CODES {
+            dec.status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+            return 0;
+        }
+
+        for &order in &kCodeLengthCodeOrder[..num_codes] {
+            code_length_code_lengths[order as usize] = VP8LReadBits(&mut dec.br_, 3) as c_int;
+        }
+        ok = ReadHuffmanCodeLengths(dec, &code_length_code_lengths, alphabet_size, code_lengths);
+    }
+
+    ok = (ok != 0 && dec.br_.eos_ == 0) as c_int;
+    if ok != 0 {
+        size = VP8LBuildHuffmanTable(table, HUFFMAN_TABLE_BITS, code_lengths, alphabet_size);
+    }
+    if ok == 0 || size == 0 {
+        dec.status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+        return 0;
+    }
+    size
+}
+
+#[allow(non_snake_case)]
+unsafe fn ReadHuffmanCodes(
+    dec: *mut VP8LDecoder,
+    xsize: c_int,
+    ysize: c_int,
+    color_cache_bits: c_int,
+    allow_recursion: c_int,
+) -> c_int {
+    let mut ok = 1;
+    let br: *mut VP8LBitReader = &mut (*dec).br_;
+    let hdr: *mut VP8LMetadata = &mut (*dec).hdr_;
+    let mut huffman_image: *mut u32 = ptr::null_mut();
+    let mut htree_groups: *mut HTreeGroup = ptr::null_mut();
+    let mut huffman_tables: *mut HuffmanCode = ptr::null_mut();
+    let mut num_htree_groups: c_int = 1;
+    let mut max_alphabet_size: c_int = 0;
+    let mut code_lengths: *mut c_int = ptr::null_mut();
+    let table_size = kTableSize[color_cache_bits as usize] as c_int;
+
+    if allow_recursion != 0 && VP8LReadBits(br, 1) != 0 {
+        // use meta Huffman codes.
+        let huffman_precision = VP8LReadBits(br, 3) as c_int + 2;
+        let huffman_xsize = VP8LSubSampleSize(xsize as u32, huffman_precision as u32) as c_int;
+        let huffman_ysize = VP8LSubSampleSize(ysize as u32, huffman_precision as u32) as c_int;
+        let huffman_pixs = huffman_xsize * huffman_ysize;
+        if DecodeImageStream(huffman_xsize, huffman_ysize, 0, dec, &mut huffman_image) == 0 {
+            ok = 0;
+        } else {
+            (*hdr).huffman_subsample_bits_ = huffman_precision;
+            for i in 0..huffman_pixs as usize {
+                // The huffman data is stored in red and green bytes.
+                let group = ((*huffman_image.add(i) >> 8) & 0xffff) as c_int;
+                *huffman_image.add(i) = group as u32;
+                if group >= num_htree_groups {
+                    num_htree_groups = group + 1;
+                }
+            }
+        }
+    }
+
+    if ok != 0 && (*br).eos_ != 0 {
+        ok = 0;
+    }
+
+    if ok != 0 {
+        // Find maximum alphabet size for the htree group.
+        for (j, &size) in kAlphabetSize.iter().enumerate() {
+            let mut alphabet_size = size as c_int;
+            if j == 0 && color_cache_bits > 0 {
+                alphabet_size += 1 << color_cache_bits;
+            }
+            if max_alphabet_size < alphabet_size {
+                max_alphabet_size = alphabet_size;
+            }
+        }
+
+        huffman_tables = WebPSafeMalloc(
+            (num_htree_groups * table_size) as u64,
+            mem::size_of::<HuffmanCode>(),
+        ) as *mut HuffmanCode;
+        htree_groups = VP8LHtreeGroupsNew(num_htree_groups);
+        code_lengths =
+            WebPSafeCalloc(max_alphabet_size as u64, mem::size_of::<c_int>()) as *mut c_int;
+
+        if htree_groups.is_null() || code_lengths.is_null() || huffman_tables.is_null() {
+            (*dec).status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+            ok = 0;
+        }
+    }
+
+    if ok != 0 {
+        let mut next: *mut HuffmanCode = huffman_tables;
+        'groups: for i in 0..num_htree_groups as usize {
+            let htree_group: &mut HTreeGroup = &mut *htree_groups.add(i);
+            let mut total_size: c_int = 0;
+            let mut is_trivial_literal: c_int = 1;
+            let mut max_bits: c_int = 0;
+            for j in 0..HUFFMAN_CODES_PER_META_CODE {
+                let mut alphabet_size = kAlphabetSize[j] as c_int;
+                htree_group.htrees[j] = next;
+                if j == 0 && color_cache_bits > 0 {
+                    alphabet_size += 1 << color_cache_bits;
+                }
+                let size = ReadHuffmanCode(alphabet_size, &mut *dec, code_lengths, next);
+                if size == 0 {
+                    ok = 0;
+                    break 'groups;
+                }
+                if is_trivial_literal != 0 && kLiteralMap[j] == 1 {
+                    is_trivial_literal = ((*next).bits == 0) as c_int;
+                }
+                total_size += (*next).bits as c_int;
+                next = next.offset(size as isize);
+                if j <= ALPHA {
+                    let code_lengths: &[c_int] =
+                        ::std::slice::from_raw_parts(code_lengths, alphabet_size as usize);
+                    max_bits += *code_lengths.iter().max().unwrap();
+                }
+            }
+            htree_group.is_trivial_literal = is_trivial_literal;
+            htree_group.is_trivial_code = 0;
+            if is_trivial_literal != 0 {
+                let red = (*htree_group.htrees[RED]).value as u32;
+                let blue = (*htree_group.htrees[BLUE]).value as u32;
+                let alpha = (*htree_group.htrees[ALPHA]).value as u32;
+                htree_group.literal_arb = (alpha << 24) | (red << 16) | blue;
+                if total_size == 0
+                    && ((*htree_group.htrees[GREEN]).value as c_int) < NUM_LITERAL_CODES
+                {
+                    htree_group.is_trivial_code = 1;
+                    htree_group.literal_arb |= ((*htree_group.htrees[GREEN]).value as u32) << 8;
+                }
+            }
+            htree_group.use_packed_table =
+                (htree_group.is_trivial_code == 0 && max_bits < HUFFMAN_PACKED_BITS) as c_int;
+            if htree_group.use_packed_table != 0 {
+                BuildPackedTable(htree_group);
+            }
+        }
+    }
+    WebPSafeFree(code_lengths as *mut c_void);
+
+    if ok == 0 {
+        WebPSafeFree(huffman_image as *mut c_void);
+        WebPSafeFree(huffman_tables as *mut c_void);
+        VP8LHtreeGroupsFree(htree_groups);
+        return 0;
+    }
+
+    // All OK. Finalize pointers and return.
+    (*hdr).huffman_image_ = huffman_image;
+    (*hdr).num_htree_groups_ = num_htree_groups;
+    (*hdr).htree_groups_ = htree_groups;
+    (*hdr).huffman_tables_ = huffman_tables;
+    1
+}
+
+//------------------------------------------------------------------------------
+// Scaling.
+
+#[allow(non_snake_case)]
+unsafe fn AllocateAndInitRescaler(dec: &mut VP8LDecoder, io: &VP8Io) -> c_int {
+    let num_channels: c_int = 4;
+    let in_width = io.mb_w;
+    let out_width = io.scaled_width;
+    let in_height = io.mb_h;
+    let out_height = io.scaled_height;
+    let work_size: u64 = 2 * num_channels as u64 * out_width as u64;
+    let scaled_data_size: u64 = out_width as u64;
+    let memory_size: u64 = mem::size_of::<WebPRescaler>() as u64
+        + work_size * mem::size_of::<rescaler_t>() as u64
+        + scaled_data_size * mem::size_of::<u32>() as u64;
+    let mut memory: *mut u8 = WebPSafeMalloc(memory_size, mem::size_of::<u8>()) as *mut u8;
+    if memory.is_null() {
+        dec.status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+        return 0;
+    }
+    c_assert!(dec.rescaler_memory.is_null());
+    dec.rescaler_memory = memory;
+
+    dec.rescaler = memory as *mut WebPRescaler;
+    memory = memory.add(mem::size_of::<WebPRescaler>());
+    let work: *mut rescaler_t = memory as *mut rescaler_t; // Rescaler work area.
+    memory = memory.add(work_size as usize * mem::size_of::<rescaler_t>());
+    let scaled_data: *mut u32 = memory as *mut u32; // Temporary storage for scaled BGRA data.
+
+    WebPRescalerInit(
+        dec.rescaler,
+        in_width,
+        in_height,
+        scaled_data as *mut u8,
+        out_width,
+        out_height,
+        0,
+        num_channels,
+        work,
+    );
+    1
+}
+
+//------------------------------------------------------------------------------
+// Export to ARGB
+
+// We have special "export" function since we need to convert from BGRA
+#[allow(non_snake_case)]
+unsafe fn Export(
+    rescaler: *mut WebPRescaler,
+    colorspace: WEBP_CSP_MODE,
+    rgba_stride: c_int,
+    rgba: *mut u8,
+) -> c_int {
+    let src: *mut u32 = (*rescaler).dst as *mut u32;
+    let dst_width = (*rescaler).dst_width;
+    let mut num_lines_out: c_int = 0;
+    while WebPRescalerHasPendingOutput(&*rescaler) != 0 {
+        let dst: *mut u8 = rgba.offset(num_lines_out as isize * rgba_stride as isize);
+        WebPRescalerExportRow(rescaler);
+        WebPMultARGBRow.unwrap()(src, dst_width, 1);
+        VP8LConvertFromBGRA(src, dst_width, colorspace, dst);
+        num_lines_out += 1;
+    }
+    num_lines_out
+}
+
+// Emit scaled rows.
+#[allow(non_snake_case)]
+unsafe fn EmitRescaledRowsRGBA(
+    dec: &VP8LDecoder,
+    in_: *mut u8,
+    in_stride: c_int,
+    mb_h: c_int,
+    out: *mut u8,
+    out_stride: c_int,
+) -> c_int {
+    let colorspace: WEBP_CSP_MODE = (*dec.output_).colorspace;
+    let mut num_lines_in: c_int = 0;
+    let mut num_lines_out: c_int = 0;
+    while num_lines_in < mb_h {
+        let row_in: *mut u8 = in_.offset(num_lines_in as isize * in_stride as isize);
+        let row_out: *mut u8 = out.offset(num_lines_out as isize * out_stride as isize);
+        let lines_left = mb_h - num_lines_in;
+        let needed_lines = WebPRescaleNeededLines(dec.rescaler, lines_left);
+        c_assert!(needed_lines > 0 && needed_lines <= lines_left);
+        WebPMultARGBRows(
+            row_in,
+            in_stride,
+            (*dec.rescaler).src_width,
+            needed_lines,
+            0,
+        );
+        let lines_imported = WebPRescalerImport(dec.rescaler, lines_left, row_in, in_stride);
+        c_assert!(lines_imported == needed_lines);
+        num_lines_in += lines_imported;
+        num_lines_out += Export(dec.rescaler, colorspace, out_stride, row_out);
+    }
+    num_lines_out
+}
+
+// Emit rows without any scaling.
+#[allow(non_snake_case)]
+unsafe fn EmitRows(
+    colorspace: WEBP_CSP_MODE,
+    mut row_in: *const u8,
+    in_stride: c_int,
+    mb_w: c_int,
+    mb_h: c_int,
+    out: *mut u8,
+    out_stride: c_int,
+) -> c_int {
+    let mut row_out: *mut u8 = out;
+    for _ in 0..mb_h {
+        VP8LConvertFromBGRA(row_in as *const u32, mb_w, colorspace, row_out);
+        row_in = row_in.offset(in_stride as isize);
+        row_out = row_out.offset(out_stride as isize);
+    }
+    mb_h // Num rows out == num rows in.
+}
+
+//------------------------------------------------------------------------------
+// Export to YUVA
+
+#[allow(non_snake_case)]
+unsafe fn ConvertToYUVA(src: *const u32, width: c_int, y_pos: c_int, output: &WebPDecBuffer) {
+    let buf: &WebPYUVABuffer = &output.u.YUVA;
+
+    // first, the luma plane
+    WebPConvertARGBToY.unwrap()(
+        src,
+        buf.y.offset(y_pos as isize * buf.y_stride as isize),
+        width,
+    );
+
+    // then U/V planes
+    {
+        let u: *mut u8 = buf.u.offset((y_pos >> 1) as isize * buf.u_stride as isize);
+        let v: *mut u8 = buf.v.offset((y_pos >> 1) as isize * buf.v_stride as isize);
+        // even lines: store values
+        // odd lines: average with previous values
+        WebPConvertARGBToUV.unwrap()(src, u, v, width, ((y_pos & 1) == 0) as c_int);
+    }
+    // Lastly, store alpha if needed.
+    if !buf.a.is_null() {
+        let a: *mut u8 = buf.a.offset(y_pos as isize * buf.a_stride as isize);
+        let alpha_offset = if cfg!(target_endian = "big") { 0 } else { 3 };
+        WebPExtractAlpha.unwrap()((src as *const u8).add(alpha_offset), 0, width, 1, a, 0);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ExportYUVA(dec: &VP8LDecoder, mut y_pos: c_int) -> c_int {
+    let rescaler: *mut WebPRescaler = dec.rescaler;
+    let src: *mut u32 = (*rescaler).dst as *mut u32;
+    let dst_width = (*rescaler).dst_width;
+    let mut num_lines_out: c_int = 0;
+    while WebPRescalerHasPendingOutput(&*rescaler) != 0 {
+        WebPRescalerExportRow(rescaler);
+        WebPMultARGBRow.unwrap()(src, dst_width, 1);
+        ConvertToYUVA(src, dst_width, y_pos, &*dec.output_);
+        y_pos += 1;
+        num_lines_out += 1;
+    }
+    num_lines_out
+}
+
+#[allow(non_snake_case)]
+unsafe fn EmitRescaledRowsYUVA(
+    dec: &VP8LDecoder,
+    mut in_: *mut u8,
+    in_stride: c_int,
+    mb_h: c_int,
+) -> c_int {
+    let mut num_lines_in: c_int = 0;
+    let mut y_pos = dec.last_out_row_;
+    while num_lines_in < mb_h {
+        let lines_left = mb_h - num_lines_in;
+        let needed_lines = WebPRescaleNeededLines(dec.rescaler, lines_left);
+        WebPMultARGBRows(in_, in_stride, (*dec.rescaler).src_width, needed_lines, 0);
+        let lines_imported = WebPRescalerImport(dec.rescaler, lines_left, in_, in_stride);
+        c_assert!(lines_imported == needed_lines);
+        num_lines_in += lines_imported;
+        in_ = in_.offset(needed_lines as isize * in_stride as isize);
+        y_pos += ExportYUVA(dec, y_pos);
+    }
+    y_pos
+}
+
+#[allow(non_snake_case)]
+unsafe fn EmitRowsYUVA(
+    dec: &VP8LDecoder,
+    mut in_: *const u8,
+    in_stride: c_int,
+    mb_w: c_int,
+    num_rows: c_int,
+) -> c_int {
+    let mut y_pos = dec.last_out_row_;
+    for _ in 0..num_rows {
+        ConvertToYUVA(in_ as *const u32, mb_w, y_pos, &*dec.output_);
+        in_ = in_.offset(in_stride as isize);
+        y_pos += 1;
+    }
+    y_pos
+}
+
+//------------------------------------------------------------------------------
+// Cropping.
+
+// Sets io->mb_y, io->mb_h & io->mb_w according to start row, end row and
+// crop options. Also updates the input data pointer, so that it points to the
+// start of the cropped window. Note that pixels are in ARGB format even if
+// 'in_data' is uint8_t*.
+// Returns true if the crop window is not empty.
+#[allow(non_snake_case)]
+unsafe fn SetCropWindow(
+    io: &mut VP8Io,
+    mut y_start: c_int,
+    mut y_end: c_int,
+    in_data: &mut *mut u8,
+    pixel_stride: c_int,
+) -> c_int {
+    c_assert!(y_start < y_end);
+    c_assert!(io.crop_left < io.crop_right);
+    if y_end > io.crop_bottom {
+        y_end = io.crop_bottom; // make sure we don't overflow on last row.
+    }
+    if y_start < io.crop_top {
+        let delta = io.crop_top - y_start;
+        y_start = io.crop_top;
+        *in_data = in_data.offset(delta as isize * pixel_stride as isize);
+    }
+    if y_start >= y_end {
+        return 0; // Crop window is empty.
+    }
+
+    *in_data = in_data.add(io.crop_left as usize * mem::size_of::<u32>());
+
+    io.mb_y = y_start - io.crop_top;
+    io.mb_w = io.crop_right - io.crop_left;
+    io.mb_h = y_end - y_start;
+    1 // Non-empty crop window.
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetMetaIndex(image: *const u32, xsize: c_int, bits: c_int, x: c_int, y: c_int) -> c_int {
+    if bits == 0 {
+        return 0;
+    }
+    *image.offset((xsize * (y >> bits) + (x >> bits)) as isize) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetHtreeGroupForPos(hdr: &VP8LMetadata, x: c_int, y: c_int) -> *mut HTreeGroup {
+    let meta_index = GetMetaIndex(
+        hdr.huffman_image_,
+        hdr.huffman_xsize_,
+        hdr.huffman_subsample_bits_,
+        x,
+        y,
+    );
+    c_assert!(meta_index < hdr.num_htree_groups_);
+    hdr.htree_groups_.offset(meta_index as isize)
+}
+
+//------------------------------------------------------------------------------
+// Main loop, with custom row-processing function
+
+type ProcessRowsFunc = Option<unsafe fn(dec: *mut VP8LDecoder, row: c_int)>;
+
+#[allow(non_snake_case)]
+unsafe fn ApplyInverseTransforms(dec: &mut VP8LDecoder, num_rows: c_int, rows: *const u32) {
+    let cache_pixs = dec.width_ * num_rows;
+    let start_row = dec.last_row_;
+    let end_row = start_row + num_rows;
+    let mut rows_in: *const u32 = rows;
+    let rows_out: *mut u32 = dec.argb_cache_;
+
+    // Inverse transforms.
+    for transform in dec.transforms_[..dec.next_transform_ as usize].iter().rev() {
+        VP8LInverseTransform(transform, start_row, end_row, rows_in, rows_out);
+        rows_in = rows_out;
+    }
+    if !ptr::eq(rows_in, rows_out) {
+        // No transform called, hence just copy.
+        ptr::copy_nonoverlapping(rows_in, rows_out, cache_pixs as usize);
+    }
+}
+
+// Processes (transforms, scales & color-converts) the rows decoded after the
+// last call.
+#[allow(non_snake_case)]
+unsafe fn ProcessRows(dec: *mut VP8LDecoder, row: c_int) {
+    let dec: &mut VP8LDecoder = &mut *dec;
+    let rows: *const u32 = dec
+        .pixels_
+        .offset(dec.width_ as isize * dec.last_row_ as isize);
+    let num_rows = row - dec.last_row_;
+
+    c_assert!(row <= (*dec.io_).crop_bottom);
+    // We can't process more than NUM_ARGB_CACHE_ROWS at a time (that's the size
+    // of argb_cache_), but we currently don't need more than that.
+    c_assert!(num_rows <= NUM_ARGB_CACHE_ROWS);
+    if num_rows > 0 {
+        // Emit output.
+        let io: &mut VP8Io = &mut *dec.io_;
+        let mut rows_data: *mut u8 = dec.argb_cache_ as *mut u8;
+        let in_stride = io.width * mem::size_of::<u32>() as c_int; // in unit of RGBA
+
+        ApplyInverseTransforms(dec, num_rows, rows);
+        // Nothing to output (this time) if the crop window is empty.
+        if SetCropWindow(io, dec.last_row_, row, &mut rows_data, in_stride) != 0 {
+            let output: &WebPDecBuffer = &*dec.output_;
+            if WebPIsRGBMode(output.colorspace) != 0 {
+                // convert to RGBA
+                let buf = &output.u.RGBA;
+                let rgba: *mut u8 = buf
+                    .rgba
+                    .offset(dec.last_out_row_ as isize * buf.stride as isize);
+                let num_rows_out = if io.use_scaling != 0 {
+                    EmitRescaledRowsRGBA(dec, rows_data, in_stride, io.mb_h, rgba, buf.stride)
+                } else {
+                    EmitRows(
+                        output.colorspace,
+                        rows_data,
+                        in_stride,
+                        io.mb_w,
+                        io.mb_h,
+                        rgba,
+                        buf.stride,
+                    )
+                };
+                // Update 'last_out_row_'.
+                dec.last_out_row_ += num_rows_out;
+            } else {
+                // convert to YUVA
+                dec.last_out_row_ = if io.use_scaling != 0 {
+                    EmitRescaledRowsYUVA(dec, rows_data, in_stride, io.mb_h)
+                } else {
+                    EmitRowsYUVA(dec, rows_data, in_stride, io.mb_w, io.mb_h)
+                };
+            }
+            c_assert!(dec.last_out_row_ <= output.height);
+        }
+    }
+
+    // Update 'last_row_'.
+    dec.last_row_ = row;
+    c_assert!(dec.last_row_ <= dec.height_);
+}
+
+// Row-processing for the special case when alpha data contains only one
+// transform (color indexing), and trivial non-green literals.
+#[allow(non_snake_case)]
+unsafe fn Is8bOptimizable(hdr: &VP8LMetadata) -> c_int {
+    if hdr.color_cache_size_ > 0 {
+        return 0;
+    }
+    // When the Huffman tree contains only one symbol, we can skip the
+    // call to ReadSymbol() for red/blue/alpha channels.
+    for i in 0..hdr.num_htree_groups_ as usize {
+        let htrees = &(*hdr.htree_groups_.add(i)).htrees;
+        if (*htrees[RED]).bits > 0 {
+            return 0;
+        }
+        if (*htrees[BLUE]).bits > 0 {
+            return 0;
+        }
+        if (*htrees[ALPHA]).bits > 0 {
+            return 0;
+        }
+    }
+    1
+}
+
+#[allow(non_snake_case)]
+fn AlphaApplyFilter(alph_dec: &mut ALPHDecoder, first_row: c_int, last_row: c_int) {
+    if alph_dec.filter_ != WEBP_FILTER_NONE {
+        for row in first_row..last_row {
+            alph_dec.unfilter_row(row as usize, None);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ExtractPalettedAlphaRows(dec: &mut VP8LDecoder, last_row: c_int) {
+    // For vertical and gradient filtering, we need to decode the part above the
+    // crop_top row, in order to have the correct spatial predictors.
+    let io: &VP8Io = &*dec.io_;
+    let alph_dec: &mut ALPHDecoder = &mut *(io.opaque as *mut ALPHDecoder);
+    let top_row =
+        if alph_dec.filter_ == WEBP_FILTER_NONE || alph_dec.filter_ == WEBP_FILTER_HORIZONTAL {
+            io.crop_top
+        } else {
+            dec.last_row_
+        };
+    let first_row = if dec.last_row_ < top_row {
+        top_row
+    } else {
+        dec.last_row_
+    };
+    c_assert!(last_row <= io.crop_bottom);
+    if last_row > first_row {
+        // Special method for paletted alpha data. We only process the cropped area.
+        let width = io.width as usize;
+        let out: &mut [u8] =
+            &mut alph_dec.output_[width * first_row as usize..width * last_row as usize];
+        let in_: *const u8 =
+            (dec.pixels_ as *const u8).offset(dec.width_ as isize * first_row as isize);
+        let transform: &VP8LTransform = &dec.transforms_[0];
+        c_assert!(dec.next_transform_ == 1);
+        c_assert!(transform.type_ == COLOR_INDEXING_TRANSFORM);
+        VP8LColorIndexInverseTransformAlpha(transform, first_row, last_row, in_, out.as_mut_ptr());
+        AlphaApplyFilter(alph_dec, first_row, last_row);
+    }
+    dec.last_row_ = last_row;
+    dec.last_out_row_ = last_row;
+}
+
+//------------------------------------------------------------------------------
+// Helper functions for fast pattern copy (8b and 32b)
+
+// cyclic rotation of pattern word
+#[allow(non_snake_case)]
+#[inline]
+fn Rotate8b(v: u32) -> u32 {
+    if cfg!(target_endian = "big") {
+        ((v & 0xff000000) >> 24) | (v << 8)
+    } else {
+        ((v & 0xff) << 24) | (v >> 8)
+    }
+}
+
+// copy 1, 2 or 4-bytes pattern
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn CopySmallPattern8b(
+    mut src: *const u8,
+    mut dst: *mut u8,
+    mut length: c_int,
+    mut pattern: u32,
+) {
+    // align 'dst' to 4-bytes boundary. Adjust the pattern along the way.
+    while (dst as usize) & 3 != 0 {
+        *dst = *src;
+        dst = dst.add(1);
+        src = src.add(1);
+        pattern = Rotate8b(pattern);
+        length -= 1;
+    }
+    // Copy the pattern 4 bytes at a time.
+    let length = length as usize;
+    for i in 0..(length >> 2) {
+        *(dst as *mut u32).add(i) = pattern;
+    }
+    // Finish with left-overs. 'pattern' is still correctly positioned,
+    // so no Rotate8b() call is needed.
+    for i in (length & !3)..length {
+        *dst.add(i) = *src.add(i);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn CopyBlock8b(dst: *mut u8, dist: c_int, length: c_int) {
+    let src: *const u8 = dst.offset(-(dist as isize));
+    if length >= 8 {
+        let pattern: Option<u32> = match dist {
+            1 => Some(0x01010101 * *src as u32),
+            2 => Some(0x00010001 * ptr::read_unaligned(src as *const u16) as u32),
+            4 => Some(ptr::read_unaligned(src as *const u32)),
+            _ => None,
+        };
+        if let Some(pattern) = pattern {
+            CopySmallPattern8b(src, dst, length, pattern);
+            return;
+        }
+    }
+    if dist >= length {
+        // no overlap -> use memcpy()
+        ptr::copy_nonoverlapping(src, dst, length as usize);
+    } else {
+        for i in 0..length as usize {
+            *dst.add(i) = *src.add(i);
+        }
+    }
+}
+
+// copy pattern of 1 or 2 uint32_t's
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn CopySmallPattern32b(
+    mut src: *const u32,
+    mut dst: *mut u32,
+    mut length: c_int,
+    mut pattern: u64,
+) {
+    if (dst as usize) & 4 != 0 {
+        // Align 'dst' to 8-bytes boundary.
+        *dst = *src;
+        dst = dst.add(1);
+        src = src.add(1);
+        pattern = pattern.rotate_left(32);
+        length -= 1;
+    }
+    c_assert!(0 == ((dst as usize) & 7));
+    let half = (length >> 1) as usize;
+    for i in 0..half {
+        *(dst as *mut u64).add(i) = pattern; // Copy the pattern 8 bytes at a time.
+    }
+    if length & 1 != 0 {
+        // Finish with left-over.
+        *dst.add(half << 1) = *src.add(half << 1);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn CopyBlock32b(dst: *mut u32, dist: c_int, length: c_int) {
+    let src: *const u32 = dst.offset(-(dist as isize));
+    if dist <= 2 && length >= 4 && ((dst as usize) & 3) == 0 {
+        let pattern: u64 = if dist == 1 {
+            let pattern = *src as u64;
+            pattern | (pattern << 32)
+        } else {
+            ptr::read_unaligned(src as *const u64)
+        };
+        CopySmallPattern32b(src, dst, length, pattern);
+    } else if dist >= length {
+        // no overlap
+        ptr::copy_nonoverlapping(src, dst, length as usize);
+    } else {
+        for i in 0..length as usize {
+            *dst.add(i) = *src.add(i);
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn DecodeAlphaData(
+    dec: &mut VP8LDecoder,
+    data: *mut u8,
+    width: c_int,
+    height: c_int,
+    last_row: c_int,
+) -> c_int {
+    let mut ok = 1;
+    let mut row = dec.last_pixel_ / width;
+    let mut col = dec.last_pixel_ % width;
+    let br: *mut VP8LBitReader = &mut dec.br_;
+    let hdr: *const VP8LMetadata = &dec.hdr_;
+    let mut pos = dec.last_pixel_; // current position
+    let end = width * height; // End of data
+    let last = width * last_row; // Last pixel to decode
+    let len_code_limit = NUM_LITERAL_CODES + NUM_LENGTH_CODES;
+    let mask = (*hdr).huffman_mask_;
+    let mut htree_group: *const HTreeGroup = if pos < last {
+        GetHtreeGroupForPos(&*hdr, col, row)
+    } else {
+        ptr::null()
+    };
+    c_assert!(pos <= end);
+    c_assert!(last_row <= height);
+    c_assert!(Is8bOptimizable(&*hdr) != 0);
+
+    while (*br).eos_ == 0 && pos < last {
+        // Only update when changing tile.
+        if (col & mask) == 0 {
+            htree_group = GetHtreeGroupForPos(&*hdr, col, row);
+        }
+        c_assert!(!htree_group.is_null());
+        VP8LFillBitWindow(br);
+        let code = ReadSymbol((*htree_group).htrees[GREEN], &mut *br);
+        if code < NUM_LITERAL_CODES {
+            // Literal
+            *data.offset(pos as isize) = code as u8;
+            pos += 1;
+            col += 1;
+            if col >= width {
+                col = 0;
+                row += 1;
+                if row <= last_row && (row % NUM_ARGB_CACHE_ROWS == 0) {
+                    ExtractPalettedAlphaRows(dec, row);
+                }
+            }
+        } else if code < len_code_limit {
+            // Backward reference
+            let length_sym = code - NUM_LITERAL_CODES;
+            let length = GetCopyLength(length_sym, &mut *br);
+            let dist_symbol = ReadSymbol((*htree_group).htrees[DIST], &mut *br);
+            VP8LFillBitWindow(br);
+            let dist_code = GetCopyDistance(dist_symbol, &mut *br);
+            let dist = PlaneCodeToDistance(width, dist_code);
+            if pos >= dist && end - pos >= length {
+                CopyBlock8b(data.offset(pos as isize), dist, length);
+            } else {
+                ok = 0;
+                break;
+            }
+            pos += length;
+            col += length;
+            while col >= width {
+                col -= width;
+                row += 1;
+                if row <= last_row && (row % NUM_ARGB_CACHE_ROWS == 0) {
+                    ExtractPalettedAlphaRows(dec, row);
+                }
+            }
+            if pos < last && (col & mask) != 0 {
+                htree_group = GetHtreeGroupForPos(&*hdr, col, row);
+            }
+        } else {
+            // Not reached
+            ok = 0;
+            break;
+        }
+        (*br).eos_ = VP8LIsEndOfStream(&*br);
+    }
+    if ok != 0 {
+        // Process the remaining rows corresponding to last row-block.
+        ExtractPalettedAlphaRows(dec, if row > last_row { last_row } else { row });
+    }
+
+    (*br).eos_ = VP8LIsEndOfStream(&*br);
+    if ok == 0 || ((*br).eos_ != 0 && pos < end) {
+        ok = 0;
+        dec.status_ = if (*br).eos_ != 0 {
+            VP8StatusCode::VP8_STATUS_SUSPENDED
+        } else {
+            VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR
+        };
+    } else {
+        dec.last_pixel_ = pos;
+    }
+    ok
+}
+
+#[allow(non_snake_case)]
+unsafe fn SaveState(dec: &mut VP8LDecoder, last_pixel: c_int) {
+    c_assert!(dec.incremental_ != 0);
+    dec.saved_br_ = dec.br_;
+    dec.saved_last_pixel_ = last_pixel;
+    if dec.hdr_.color_cache_size_ > 0 {
+        VP8LColorCacheCopy(&dec.hdr_.color_cache_, &mut dec.hdr_.saved_color_cache_);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn RestoreState(dec: &mut VP8LDecoder) {
+    c_assert!(dec.br_.eos_ != 0);
+    dec.status_ = VP8StatusCode::VP8_STATUS_SUSPENDED;
+    dec.br_ = dec.saved_br_;
+    dec.last_pixel_ = dec.saved_last_pixel_;
+    if dec.hdr_.color_cache_size_ > 0 {
+        VP8LColorCacheCopy(&dec.hdr_.saved_color_cache_, &mut dec.hdr_.color_cache_);
+    }
+}
+
+const SYNC_EVERY_N_ROWS: c_int = 8; // minimum number of rows between check-points
+#[allow(non_snake_case)]
+unsafe fn DecodeImageData(
+    dec: *mut VP8LDecoder,
+    data: *mut u32,
+    width: c_int,
+    height: c_int,
+    last_row: c_int,
+    process_func: ProcessRowsFunc,
+) -> c_int {
+    let mut row = (*dec).last_pixel_ / width;
+    let mut col = (*dec).last_pixel_ % width;
+    let br: *mut VP8LBitReader = &mut (*dec).br_;
+    let hdr: *mut VP8LMetadata = &mut (*dec).hdr_;
+    let mut src: *mut u32 = data.offset((*dec).last_pixel_ as isize);
+    let mut last_cached: *mut u32 = src;
+    let src_end: *mut u32 = data.offset(width as isize * height as isize); // End of data
+                                                                           // Last pixel to decode
+    let src_last: *mut u32 = data.offset(width as isize * last_row as isize);
+    let len_code_limit = NUM_LITERAL_CODES + NUM_LENGTH_CODES;
+    let color_cache_limit = len_code_limit + (*hdr).color_cache_size_;
+    let mut next_sync_row = if (*dec).incremental_ != 0 {
+        row
+    } else {
+        1 << 24
+    };
+    let color_cache: *const VP8LColorCache = if (*hdr).color_cache_size_ > 0 {
+        &(*hdr).color_cache_
+    } else {
+        ptr::null()
+    };
+    let mask = (*hdr).huffman_mask_;
+    let mut htree_group: *const HTreeGroup = if src < src_last {
+        GetHtreeGroupForPos(&*hdr, col, row)
+    } else {
+        ptr::null()
+    };
+    c_assert!((*dec).last_row_ < last_row);
+    c_assert!(src_last <= src_end);
+
+    while src < src_last {
+        if row >= next_sync_row {
+            SaveState(&mut *dec, src.offset_from(data) as c_int);
+            next_sync_row = row + SYNC_EVERY_N_ROWS;
+        }
+        // Only update when changing tile. Note we could use this test:
+        // if "((((prev_col ^ col) | prev_row ^ row)) > mask)" -> tile changed
+        // but that's actually slower and needs storing the previous col/row.
+        if (col & mask) == 0 {
+            htree_group = GetHtreeGroupForPos(&*hdr, col, row);
+        }
+        c_assert!(!htree_group.is_null());
+        let group: &HTreeGroup = &*htree_group;
+        let mut advance_by_one = false;
+        if group.is_trivial_code != 0 {
+            *src = group.literal_arb;
+            advance_by_one = true;
+        } else {
+            VP8LFillBitWindow(br);
+            let code = if group.use_packed_table != 0 {
+                let code = ReadPackedSymbols(group, &mut *br, &mut *src);
+                if VP8LIsEndOfStream(&*br) != 0 {
+                    break;
+                }
+                code
+            } else {
+                ReadSymbol(group.htrees[GREEN], &mut *br)
+            };
+            if group.use_packed_table != 0 && code == PACKED_NON_LITERAL_CODE {
+                advance_by_one = true;
+            } else if VP8LIsEndOfStream(&*br) != 0 {
+                break;
+            } else if code < NUM_LITERAL_CODES {
+                // Literal
+                if group.is_trivial_literal != 0 {
+                    *src = group.literal_arb | ((code as u32) << 8);
+                } else {
+                    let red = ReadSymbol(group.htrees[RED], &mut *br) as u32;
+                    VP8LFillBitWindow(br);
+                    let blue = ReadSymbol(group.htrees[BLUE], &mut *br) as u32;
+                    let alpha = ReadSymbol(group.htrees[ALPHA], &mut *br) as u32;
+                    if VP8LIsEndOfStream(&*br) != 0 {
+                        break;
+                    }
+                    *src = (alpha << 24) | (red << 16) | ((code as u32) << 8) | blue;
+                }
+                advance_by_one = true;
+            } else if code < len_code_limit {
+                // Backward reference
+                let length_sym = code - NUM_LITERAL_CODES;
+                let length = GetCopyLength(length_sym, &mut *br);
+                let dist_symbol = ReadSymbol(group.htrees[DIST], &mut *br);
+                VP8LFillBitWindow(br);
+                let dist_code = GetCopyDistance(dist_symbol, &mut *br);
+                let dist = PlaneCodeToDistance(width, dist_code);
+                if VP8LIsEndOfStream(&*br) != 0 {
+                    break;
+                }
+                if src.offset_from(data) < dist as isize
+                    || src_end.offset_from(src) < length as isize
+                {
+                    (*dec).status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+                    return 0;
+                } else {
+                    CopyBlock32b(src, dist, length);
+                }
+                src = src.offset(length as isize);
+                col += length;
+                while col >= width {
+                    col -= width;
+                    row += 1;
+                    if let Some(process_func) = process_func {
+                        if row <= last_row && (row % NUM_ARGB_CACHE_ROWS == 0) {
+                            process_func(dec, row);
+                        }
+                    }
+                }
+                // Because of the check done above (before 'src' was incremented by
+                // 'length'), the following holds true.
+                c_assert!(src <= src_end);
+                if (col & mask) != 0 {
+                    htree_group = GetHtreeGroupForPos(&*hdr, col, row);
+                }
+                if !color_cache.is_null() {
+                    while last_cached < src {
+                        VP8LColorCacheInsert(&*color_cache, *last_cached);
+                        last_cached = last_cached.add(1);
+                    }
+                }
+            } else if code < color_cache_limit {
+                // Color cache
+                let key = code - len_code_limit;
+                c_assert!(!color_cache.is_null());
+                while last_cached < src {
+                    VP8LColorCacheInsert(&*color_cache, *last_cached);
+                    last_cached = last_cached.add(1);
+                }
+                *src = VP8LColorCacheLookup(&*color_cache, key as u32);
+                advance_by_one = true;
+            } else {
+                // Not reached
+                (*dec).status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+                return 0;
+            }
+        }
+        if advance_by_one {
+            src = src.add(1);
+            col += 1;
+            if col >= width {
+                col = 0;
+                row += 1;
+                if let Some(process_func) = process_func {
+                    if row <= last_row && (row % NUM_ARGB_CACHE_ROWS == 0) {
+                        process_func(dec, row);
+                    }
+                }
+                if !color_cache.is_null() {
+                    while last_cached < src {
+                        VP8LColorCacheInsert(&*color_cache, *last_cached);
+                        last_cached = last_cached.add(1);
+                    }
+                }
+            }
+        }
+    }
+
+    (*br).eos_ = VP8LIsEndOfStream(&*br);
+    if (*dec).incremental_ != 0 && (*br).eos_ != 0 && src < src_end {
+        RestoreState(&mut *dec);
+    } else if (*br).eos_ == 0 {
+        // Process the remaining rows corresponding to last row-block.
+        if let Some(process_func) = process_func {
+            process_func(dec, if row > last_row { last_row } else { row });
+        }
+        (*dec).status_ = VP8StatusCode::VP8_STATUS_OK;
+        (*dec).last_pixel_ = src.offset_from(data) as c_int; // end-of-scan marker
+    } else {
+        // if not incremental, and we are past the end of buffer (eos_=1), then this
+        // is a real bitstream error.
+        (*dec).status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+        return 0;
+    }
+    1
+}
+
+// -----------------------------------------------------------------------------
+// VP8LTransform
+
+#[allow(non_snake_case)]
+unsafe fn ClearTransform(transform: &mut VP8LTransform) {
+    WebPSafeFree(transform.data_ as *mut c_void);
+    transform.data_ = ptr::null_mut();
+}
+
+// For security reason, we need to remap the color map to span
+// the total possible bundled values, and not just the num_colors.
+#[allow(non_snake_case)]
+unsafe fn ExpandColorMap(num_colors: c_int, transform: &mut VP8LTransform) -> c_int {
+    let final_num_colors = 1 << (8 >> transform.bits_);
+    let new_color_map: *mut u32 =
+        WebPSafeMalloc(final_num_colors as u64, mem::size_of::<u32>()) as *mut u32;
+    if new_color_map.is_null() {
+        0
+    } else {
+        let data: *const u8 = transform.data_ as *const u8;
+        let new_data: *mut u8 = new_color_map as *mut u8;
+        *new_color_map = *transform.data_;
+        for i in 4..4 * num_colors as usize {
+            // Equivalent to AddPixelEq(), on a byte-basis.
+            *new_data.add(i) = (*data.add(i)).wrapping_add(*new_data.add(i - 4));
+        }
+        for i in 4 * num_colors as usize..4 * final_num_colors as usize {
+            *new_data.add(i) = 0; // black tail.
+        }
+        WebPSafeFree(transform.data_ as *mut c_void);
+        transform.data_ = new_color_map;
+        1
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ReadTransform(xsize: &mut c_int, ysize: c_int, dec: *mut VP8LDecoder) -> c_int {
+    let mut ok = 1;
+    let br: *mut VP8LBitReader = &mut (*dec).br_;
+    let transform: &mut VP8LTransform = &mut (*dec).transforms_[(*dec).next_transform_ as usize];
+    let type_: VP8LImageTransformType = match VP8LReadBits(br, 2) {
+        0 => PREDICTOR_TRANSFORM,
+        1 => CROSS_COLOR_TRANSFORM,
+        2 => SUBTRACT_GREEN,
+        _ => COLOR_INDEXING_TRANSFORM,
+    };
+
+    // Each transform type can only be present once in the stream.
+    if (*dec).transforms_seen_ & (1 << type_ as u32) != 0 {
+        return 0; // Already there, let's not accept the second same transform.
+    }
+    (*dec).transforms_seen_ |= 1 << type_ as u32;
+
+    transform.type_ = type_;
+    transform.xsize_ = *xsize;
+    transform.ysize_ = ysize;
+    transform.data_ = ptr::null_mut();
+    (*dec).next_transform_ += 1;
+    c_assert!((*dec).next_transform_ as usize <= NUM_TRANSFORMS);
+
+    match type_ {
+        PREDICTOR_TRANSFORM | CROSS_COLOR_TRANSFORM => {
+            transform.bits_ = VP8LReadBits(br, 3) as c_int + 2;
+            ok = DecodeImageStream(
+                VP8LSubSampleSize(transform.xsize_ as u32, transform.bits_ as u32) as c_int,
+                VP8LSubSampleSize(transform.ysize_ as u32, transform.bits_ as u32) as c_int,
+                0,
+                dec,
+                &mut transform.data_,
+            );
+        }
+        COLOR_INDEXING_TRANSFORM => {
+            let num_colors = VP8LReadBits(br, 8) as c_int + 1;
+            let bits = if num_colors > 16 {
+                0
+            } else if num_colors > 4 {
+                1
+            } else if num_colors > 2 {
+                2
+            } else {
+                3
+            };
+            *xsize = VP8LSubSampleSize(transform.xsize_ as u32, bits as u32) as c_int;
+            transform.bits_ = bits;
+            ok = DecodeImageStream(num_colors, 1, 0, dec, &mut transform.data_);
+            ok = (ok != 0 && ExpandColorMap(num_colors, transform) != 0) as c_int;
+        }
+        SUBTRACT_GREEN => {}
+    }
+
+    ok
+}
+
+// -----------------------------------------------------------------------------
+// VP8LMetadata
+
+#[allow(non_snake_case)]
+unsafe fn InitMetadata(hdr: *mut VP8LMetadata) {
+    c_assert!(!hdr.is_null());
+    ptr::write_bytes(hdr, 0, 1);
+}
+
+#[allow(non_snake_case)]
+unsafe fn ClearMetadata(hdr: *mut VP8LMetadata) {
+    c_assert!(!hdr.is_null());
+
+    WebPSafeFree((*hdr).huffman_image_ as *mut c_void);
+    WebPSafeFree((*hdr).huffman_tables_ as *mut c_void);
+    VP8LHtreeGroupsFree((*hdr).htree_groups_);
+    VP8LColorCacheClear(&mut (*hdr).color_cache_);
+    VP8LColorCacheClear(&mut (*hdr).saved_color_cache_);
+    InitMetadata(hdr);
+}
+
+// -----------------------------------------------------------------------------
+// VP8LDecoder
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LNew() -> *mut VP8LDecoder {
+    let dec: *mut VP8LDecoder =
+        WebPSafeCalloc(1, mem::size_of::<VP8LDecoder>()) as *mut VP8LDecoder;
+    if dec.is_null() {
+        return ptr::null_mut();
+    }
+    (*dec).status_ = VP8StatusCode::VP8_STATUS_OK;
+    (*dec).state_ = READ_DIM;
+
+    VP8LDspInit(); // Init critical function pointers.
+
+    dec
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LClear(dec: *mut VP8LDecoder) {
+    if dec.is_null() {
+        return;
+    }
+    let dec: &mut VP8LDecoder = &mut *dec;
+    ClearMetadata(&mut dec.hdr_);
+
+    WebPSafeFree(dec.pixels_ as *mut c_void);
+    dec.pixels_ = ptr::null_mut();
+    for transform in dec.transforms_[..dec.next_transform_ as usize].iter_mut() {
+        ClearTransform(transform);
+    }
+    dec.next_transform_ = 0;
+    dec.transforms_seen_ = 0;
+
+    WebPSafeFree(dec.rescaler_memory as *mut c_void);
+    dec.rescaler_memory = ptr::null_mut();
+
+    dec.output_ = ptr::null(); // leave no trace behind
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LDelete(dec: *mut VP8LDecoder) {
+    if !dec.is_null() {
+        VP8LClear(dec);
+        WebPSafeFree(dec as *mut c_void);
+    }
+}
+
+#[allow(non_snake_case)]
+fn UpdateDecoder(dec: &mut VP8LDecoder, width: c_int, height: c_int) {
+    let hdr: &mut VP8LMetadata = &mut dec.hdr_;
+    let num_bits = hdr.huffman_subsample_bits_;
+    dec.width_ = width;
+    dec.height_ = height;
+
+    hdr.huffman_xsize_ = VP8LSubSampleSize(width as u32, num_bits as u32) as c_int;
+    hdr.huffman_mask_ = if num_bits == 0 {
+        !0
+    } else {
+        (1 << num_bits) - 1
+    };
+}
+
+#[allow(non_snake_case)]
+unsafe fn DecodeImageStream(
+    xsize: c_int,
+    ysize: c_int,
+    is_level0: c_int,
+    dec: *mut VP8LDecoder,
+    decoded_data: *mut *mut u32,
+) -> c_int {
+    let mut ok = 1;
+    let mut transform_xsize = xsize;
+    let transform_ysize = ysize;
+    let br: *mut VP8LBitReader = &mut (*dec).br_;
+    let hdr: *mut VP8LMetadata = &mut (*dec).hdr_;
+    let mut data: *mut u32 = ptr::null_mut();
+    let mut color_cache_bits: c_int = 0;
+
+    // Read the transforms (may recurse).
+    if is_level0 != 0 {
+        while ok != 0 && VP8LReadBits(br, 1) != 0 {
+            ok = ReadTransform(&mut transform_xsize, transform_ysize, dec);
+        }
+    }
+
+    // Color cache
+    if ok != 0 && VP8LReadBits(br, 1) != 0 {
+        color_cache_bits = VP8LReadBits(br, 4) as c_int;
+        ok = (1..=MAX_CACHE_BITS).contains(&color_cache_bits) as c_int;
+    }
+
+    // Read the Huffman codes (may recurse).
+    if ok != 0 {
+        ok = ReadHuffmanCodes(
+            dec,
+            transform_xsize,
+            transform_ysize,
+            color_cache_bits,
+            is_level0,
+        );
+    }
+    if ok == 0 {
+        (*dec).status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+    }
+
+    // Finish setting up the color-cache
+    if ok != 0 {
+        if color_cache_bits > 0 {
+            (*hdr).color_cache_size_ = 1 << color_cache_bits;
+            if VP8LColorCacheInit(&mut (*hdr).color_cache_, color_cache_bits) == 0 {
+                (*dec).status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+                ok = 0;
+            }
+        } else {
+            (*hdr).color_cache_size_ = 0;
+        }
+    }
+    if ok != 0 {
+        UpdateDecoder(&mut *dec, transform_xsize, transform_ysize);
+
+        if is_level0 != 0 {
+            // level 0 complete
+            (*dec).state_ = READ_HDR;
+        } else {
+            let total_size: u64 = transform_xsize as u64 * transform_ysize as u64;
+            data = WebPSafeMalloc(total_size, mem::size_of::<u32>()) as *mut u32;
+            if data.is_null() {
+                (*dec).status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+                ok = 0;
+            } else {
+                // Use the Huffman trees to decode the LZ77 encoded data.
+                ok = DecodeImageData(
+                    dec,
+                    data,
+                    transform_xsize,
+                    transform_ysize,
+                    transform_ysize,
+                    None,
+                );
+                ok = (ok != 0 && (*br).eos_ == 0) as c_int;
+            }
+        }
+    }
+
+    if ok == 0 {
+        WebPSafeFree(data as *mut c_void);
+        ClearMetadata(hdr);
+    } else {
+        if !decoded_data.is_null() {
+            *decoded_data = data;
+        } else {
+            // We allocate image data in this function only for transforms. At level 0
+            // (that is: not the transforms), we shouldn't have allocated anything.
+            c_assert!(data.is_null());
+            c_assert!(is_level0 != 0);
+        }
+        (*dec).last_pixel_ = 0; // Reset for future DECODE_DATA_FUNC() calls.
+        if is_level0 == 0 {
+            ClearMetadata(hdr); // Clean up temporary data behind.
+        }
+    }
+    ok
+}
+
+//------------------------------------------------------------------------------
+// Allocate internal buffers dec->pixels_ and dec->argb_cache_.
+#[allow(non_snake_case)]
+unsafe fn AllocateInternalBuffers32b(dec: &mut VP8LDecoder, final_width: c_int) -> c_int {
+    let num_pixels: u64 = dec.width_ as u64 * dec.height_ as u64;
+    // Scratch buffer corresponding to top-prediction row for transforming the
+    // first row in the row-blocks. Not needed for paletted alpha.
+    let cache_top_pixels: u64 = final_width as u16 as u64;
+    // Scratch buffer for temporary BGRA storage. Not needed for paletted alpha.
+    let cache_pixels: u64 = final_width as u64 * NUM_ARGB_CACHE_ROWS as u64;
+    let total_num_pixels: u64 = num_pixels + cache_top_pixels + cache_pixels;
+
+    c_assert!(dec.width_ <= final_width);
+    dec.pixels_ = WebPSafeMalloc(total_num_pixels, mem::size_of::<u32>()) as *mut u32;
+    if dec.pixels_.is_null() {
+        dec.argb_cache_ = ptr::null_mut(); // for sanity check
+        dec.status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+        return 0;
+    }
+    dec.argb_cache_ = dec.pixels_.add((num_pixels + cache_top_pixels) as usize);
+    1
+}
+
+#[allow(non_snake_case)]
+unsafe fn AllocateInternalBuffers8b(dec: &mut VP8LDecoder) -> c_int {
+    let total_num_pixels: u64 = dec.width_ as u64 * dec.height_ as u64;
+    dec.argb_cache_ = ptr::null_mut(); // for sanity check
+    dec.pixels_ = WebPSafeMalloc(total_num_pixels, mem::size_of::<u8>()) as *mut u32;
+    if dec.pixels_.is_null() {
+        dec.status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+        return 0;
+    }
+    1
+}
+
+//------------------------------------------------------------------------------
+
+// Special row-processing that only stores the alpha data.
+#[allow(non_snake_case)]
+unsafe fn ExtractAlphaRows(dec: *mut VP8LDecoder, last_row: c_int) {
+    let dec: &mut VP8LDecoder = &mut *dec;
+    let mut cur_row = dec.last_row_;
+    let mut num_rows = last_row - cur_row;
+    let mut in_: *const u32 = dec.pixels_.offset(dec.width_ as isize * cur_row as isize);
+
+    c_assert!(last_row <= (*dec.io_).crop_bottom);
+    while num_rows > 0 {
+        let num_rows_to_process = if num_rows > NUM_ARGB_CACHE_ROWS {
+            NUM_ARGB_CACHE_ROWS
+        } else {
+            num_rows
+        };
+        // Extract alpha (which is stored in the green plane).
+        let alph_dec: &mut ALPHDecoder = &mut *((*dec.io_).opaque as *mut ALPHDecoder);
+        let width = (*dec.io_).width; // the final width (!= dec->width_)
+        let cache_pixs = width * num_rows_to_process;
+        let dst: &mut [u8] =
+            &mut alph_dec.output_[(width * cur_row) as usize..][..cache_pixs as usize];
+        let src: *const u32 = dec.argb_cache_;
+        ApplyInverseTransforms(dec, num_rows_to_process, in_);
+        WebPExtractGreen.unwrap()(src, dst.as_mut_ptr(), cache_pixs);
+        AlphaApplyFilter(alph_dec, cur_row, cur_row + num_rows_to_process);
+        num_rows -= num_rows_to_process;
+        in_ = in_.offset(num_rows_to_process as isize * dec.width_ as isize);
+        cur_row += num_rows_to_process;
+    }
+    c_assert!(cur_row == last_row);
+    dec.last_row_ = last_row;
+    dec.last_out_row_ = last_row;
+}
+
+// The lossless decoder keeps reading from 'data' in later calls to
+// VP8LDecodeAlphaImageStream(), so 'data' must outlive it (or the bit reader
+// must be re-pointed with VP8LBitReaderSetBuffer() when it moves).
+#[allow(non_snake_case)]
+pub(crate) unsafe fn VP8LDecodeAlphaHeader(alph_dec: &mut ALPHDecoder, data: &[u8]) -> c_int {
+    let dec: *mut VP8LDecoder = VP8LNew();
+
+    if dec.is_null() {
+        return 0;
+    }
+
+    alph_dec.vp8l_dec_ = dec;
+
+    {
+        let dec: &mut VP8LDecoder = &mut *dec;
+        dec.width_ = alph_dec.width_;
+        dec.height_ = alph_dec.height_;
+        dec.io_ = &mut alph_dec.io_;
+        alph_dec.io_.width = alph_dec.width_;
+        alph_dec.io_.height = alph_dec.height_;
+
+        dec.status_ = VP8StatusCode::VP8_STATUS_OK;
+        VP8LInitBitReader(&mut dec.br_, data.as_ptr(), data.len());
+    }
+    alph_dec.io_.opaque = alph_dec as *mut ALPHDecoder as *mut c_void;
+
+    let ok = if DecodeImageStream(alph_dec.width_, alph_dec.height_, 1, dec, ptr::null_mut()) == 0 {
+        0
+    } else {
+        let dec: &mut VP8LDecoder = &mut *dec;
+        // Special case: if alpha data uses only the color indexing transform and
+        // doesn't use color cache (a frequent case), we will use DecodeAlphaData()
+        // method that only needs allocation of 1 byte per pixel (alpha channel).
+        if dec.next_transform_ == 1
+            && dec.transforms_[0].type_ == COLOR_INDEXING_TRANSFORM
+            && Is8bOptimizable(&dec.hdr_) != 0
+        {
+            alph_dec.use_8b_decode_ = 1;
+            AllocateInternalBuffers8b(dec)
+        } else {
+            // Allocate internal buffers (note that dec->width_ may have changed here).
+            alph_dec.use_8b_decode_ = 0;
+            AllocateInternalBuffers32b(dec, alph_dec.width_)
+        }
+    };
+
+    if ok == 0 {
+        VP8LDelete(alph_dec.vp8l_dec_);
+        alph_dec.vp8l_dec_ = ptr::null_mut();
+        return 0;
+    }
+
+    1
+}
+
+#[allow(non_snake_case)]
+pub(crate) unsafe fn VP8LDecodeAlphaImageStream(
+    alph_dec: &mut ALPHDecoder,
+    last_row: c_int,
+) -> c_int {
+    let dec: *mut VP8LDecoder = alph_dec.vp8l_dec_;
+    c_assert!(!dec.is_null());
+    c_assert!(last_row <= (*dec).height_);
+
+    if (*dec).last_row_ >= last_row {
+        return 1; // done
+    }
+
+    if alph_dec.use_8b_decode_ == 0 {
+        WebPInitAlphaProcessing();
+    }
+
+    // Decode (with special row processing).
+    // The row callbacks reach the alpha decoder through io.opaque.
+    if alph_dec.use_8b_decode_ != 0 {
+        let dec: &mut VP8LDecoder = &mut *dec;
+        let (pixels, width, height) = (dec.pixels_ as *mut u8, dec.width_, dec.height_);
+        DecodeAlphaData(dec, pixels, width, height, last_row)
+    } else {
+        DecodeImageData(
+            dec,
+            (*dec).pixels_,
+            (*dec).width_,
+            (*dec).height_,
+            last_row,
+            Some(ExtractAlphaRows),
+        )
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LDecodeHeader(dec: *mut VP8LDecoder, io: *mut VP8Io) -> c_int {
+    let mut width: c_int = 0;
+    let mut height: c_int = 0;
+    let mut has_alpha: c_int = 0;
+
+    if dec.is_null() {
+        return 0;
+    }
+    if io.is_null() {
+        (*dec).status_ = VP8StatusCode::VP8_STATUS_INVALID_PARAM;
+        return 0;
+    }
+
+    (*dec).io_ = io;
+    (*dec).status_ = VP8StatusCode::VP8_STATUS_OK;
+    VP8LInitBitReader(&mut (*dec).br_, (*io).data, (*io).data_size);
+    if ReadImageInfo(&mut (*dec).br_, &mut width, &mut height, &mut has_alpha) == 0 {
+        (*dec).status_ = VP8StatusCode::VP8_STATUS_BITSTREAM_ERROR;
+    } else {
+        (*dec).state_ = READ_DIM;
+        (*io).width = width;
+        (*io).height = height;
+
+        if DecodeImageStream(width, height, 1, dec, ptr::null_mut()) != 0 {
+            return 1;
+        }
+    }
+
+    VP8LClear(dec);
+    c_assert!((*dec).status_ != VP8StatusCode::VP8_STATUS_OK);
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LDecodeImage(dec: *mut VP8LDecoder) -> c_int {
+    // Sanity checks.
+    if dec.is_null() {
+        return 0;
+    }
+
+    c_assert!(!(*dec).hdr_.huffman_tables_.is_null());
+    c_assert!(!(*dec).hdr_.htree_groups_.is_null());
+    c_assert!((*dec).hdr_.num_htree_groups_ > 0);
+
+    let io: *mut VP8Io = (*dec).io_;
+    c_assert!(!io.is_null());
+    let params: *mut WebPDecParams = (*io).opaque as *mut WebPDecParams;
+    c_assert!(!params.is_null());
+
+    // Initialization.
+    if (*dec).state_ != READ_DATA && DecodeImageInit(&mut *dec, &mut *io, &*params) == 0 {
+        VP8LClear(dec);
+        c_assert!((*dec).status_ != VP8StatusCode::VP8_STATUS_OK);
+        return 0;
+    }
+
+    // Decode.
+    if DecodeImageData(
+        dec,
+        (*dec).pixels_,
+        (*dec).width_,
+        (*dec).height_,
+        (*io).crop_bottom,
+        Some(ProcessRows),
+    ) == 0
+    {
+        VP8LClear(dec);
+        c_assert!((*dec).status_ != VP8StatusCode::VP8_STATUS_OK);
+        return 0;
+    }
+
+    (*params).last_y = (*dec).last_out_row_;
+    1
+}
+
+// Initialization part of VP8LDecodeImage(), run once before the first call
+// to DecodeImageData().
+#[allow(non_snake_case)]
+unsafe fn DecodeImageInit(dec: &mut VP8LDecoder, io: &mut VP8Io, params: &WebPDecParams) -> c_int {
+    dec.output_ = params.output;
+    c_assert!(!dec.output_.is_null());
+
+    if WebPIoInitFromOptions(params.options, io, MODE_BGRA) == 0 {
+        dec.status_ = VP8StatusCode::VP8_STATUS_INVALID_PARAM;
+        return 0;
+    }
+
+    if AllocateInternalBuffers32b(dec, io.width) == 0 {
+        return 0;
+    }
+
+    if io.use_scaling != 0 && AllocateAndInitRescaler(dec, io) == 0 {
+        return 0;
+    }
+    if io.use_scaling != 0 || WebPIsPremultipliedMode((*dec.output_).colorspace) != 0 {
+        // need the alpha-multiply functions for premultiplied output or rescaling
+        WebPInitAlphaProcessing();
+    }
+
+    if WebPIsRGBMode((*dec.output_).colorspace) == 0 {
+        WebPInitConvertARGBToYUV();
+        if !(*dec.output_).u.YUVA.a.is_null() {
+            WebPInitAlphaProcessing();
+        }
+    }
+    if dec.incremental_ != 0
+        && dec.hdr_.color_cache_size_ > 0
+        && dec.hdr_.saved_color_cache_.colors_.is_null()
+        && VP8LColorCacheInit(
+            &mut dec.hdr_.saved_color_cache_,
+            dec.hdr_.color_cache_.hash_bits_,
+        ) == 0
+    {
+        dec.status_ = VP8StatusCode::VP8_STATUS_OUT_OF_MEMORY;
+        return 0;
+    }
+    dec.state_ = READ_DATA;
+    1
+}
+
+//------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use dec::alpha_dec::ALPHNew;
+    use dec::buffer_dec::{WebPAllocateDecBuffer, WebPFreeDecBuffer};
+    use dec::vp8_dec::VP8InitIo;
+    use decode::{MODE_rgbA, MODE_RGBA, MODE_RGB_565, MODE_YUV, MODE_YUVA};
+    use dsp::dsp::WEBP_FILTER_TYPE;
+    use dsp::filters::VP8FiltersInit;
+    use encode::{encode_lossless_rgba, encode_rgba_with_config, EncoderConfig};
+    use format_constants::ALPHA_HEADER_LEN;
+    use sys::WebPDecoderOptions;
+    use test_util::{check_parity, chunk, new_io, rgba_images, Digest};
+    use utils::bit_reader_utils::VP8LBitReaderSetBuffer;
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LNew() -> *mut VP8LDecoder;
+        fn WebPRef_VP8LDelete(dec: *mut VP8LDecoder);
+        fn WebPRef_VP8LDecodeHeader(dec: *mut VP8LDecoder, io: *mut VP8Io) -> c_int;
+        fn WebPRef_VP8LDecodeImage(dec: *mut VP8LDecoder) -> c_int;
+        fn WebPRef_VP8LGetInfo(
+            data: *const u8,
+            data_size: usize,
+            width: *mut c_int,
+            height: *mut c_int,
+            has_alpha: *mut c_int,
+        ) -> c_int;
+        fn WebPRef_VP8LDecodeAlphaHeader(
+            alph_dec: *mut RefALPHDecoder,
+            data: *const u8,
+            data_size: usize,
+        ) -> c_int;
+        fn WebPRef_VP8LDecodeAlphaImageStream(
+            alph_dec: *mut RefALPHDecoder,
+            last_row: c_int,
+        ) -> c_int;
+        fn WebPRef_VP8FiltersInit();
+    }
+
+    fn images() -> Vec<(u32, u32, Vec<u8>)> {
+        rgba_images(
+            0x1234,
+            0x0f,
+            &[
+                (1, 1, 0, false),
+                (1, 19, 0, true),
+                (37, 23, 0, false),
+                (64, 40, 0, true),
+                (33, 17, 2, false),
+                (40, 21, 3, true),
+                (29, 30, 11, false),
+                (120, 9, 200, true),
+            ],
+        )
+    }
+
+    // Raw VP8L bitstreams of the test images, at every method level.
+    fn corpus() -> Vec<Vec<u8>> {
+        let mut corpus = Vec::new();
+        for (width, height, rgba) in images() {
+            let webp = encode_lossless_rgba(&rgba, width, height, width * 4).unwrap();
+            corpus.push(chunk(&webp, b"VP8L").unwrap().to_vec());
+            for level in 0..=9 {
+                let config = EncoderConfig {
+                    exact: level % 2 == 0,
+                    ..EncoderConfig::lossless_preset(level).unwrap()
+                };
+                let webp =
+                    encode_rgba_with_config(&rgba, width, height, width * 4, &config).unwrap();
+                corpus.push(chunk(&webp, b"VP8L").unwrap().to_vec());
+            }
+        }
+        corpus
+    }
+
+    // Location and size of all the planes of an allocated output buffer.
+    unsafe fn planes(output: &WebPDecBuffer) -> Vec<(*mut u8, usize)> {
+        if WebPIsRGBMode(output.colorspace) != 0 {
+            let buf = &output.u.RGBA;
+            vec![(buf.rgba, buf.size)]
+        } else {
+            let buf = &output.u.YUVA;
+            vec![
+                (buf.y, buf.y_size),
+                (buf.u, buf.u_size),
+                (buf.v, buf.v_size),
+                (buf.a, buf.a_size),
+            ]
+        }
+    }
+
+    struct Decoded {
+        header_ok: c_int,
+        ok: c_int,
+        status: VP8StatusCode,
+        last_y: c_int,
+        pixels: Vec<u8>,
+    }
+
+    type Api = (
+        unsafe extern "C" fn() -> *mut VP8LDecoder,
+        unsafe extern "C" fn(*mut VP8LDecoder),
+        unsafe extern "C" fn(*mut VP8LDecoder, *mut VP8Io) -> c_int,
+        unsafe extern "C" fn(*mut VP8LDecoder) -> c_int,
+    );
+
+    const PORT: Api = (VP8LNew, VP8LDelete, VP8LDecodeHeader, VP8LDecodeImage);
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = (
+        WebPRef_VP8LNew,
+        WebPRef_VP8LDelete,
+        WebPRef_VP8LDecodeHeader,
+        WebPRef_VP8LDecodeImage,
+    );
+
+    // Decodes 'data' with either version. With 'split' set, the decoder runs
+    // in incremental mode and first only sees 'split' bytes of the input.
+    fn decode(
+        data: &[u8],
+        colorspace: WEBP_CSP_MODE,
+        options: Option<&WebPDecoderOptions>,
+        split: Option<usize>,
+        api: Api,
+    ) -> Decoded {
+        let options: *const WebPDecoderOptions = options.map_or(ptr::null(), |o| o);
+        let mut io = new_io(&data[..split.unwrap_or(data.len())]);
+        let mut output: WebPDecBuffer = unsafe { mem::zeroed() };
+        output.colorspace = colorspace;
+        let mut params: WebPDecParams = unsafe { mem::zeroed() };
+        params.output = &mut output;
+        params.options = options;
+        io.opaque = &mut params as *mut WebPDecParams as *mut c_void;
+        let mut pixels = Vec::new();
+        unsafe {
+            let (new, delete, decode_header, decode_image) = api;
+            let dec = new();
+            (*dec).incremental_ = split.is_some() as c_int;
+            let header_ok = decode_header(dec, &mut io);
+            let mut ok = 0;
+            if header_ok != 0 {
+                // Fails if the crop window doesn't fit in the image.
+                let status = WebPAllocateDecBuffer(io.width, io.height, options, &mut output);
+                if status != VP8StatusCode::VP8_STATUS_OK {
+                    delete(dec);
+                    return Decoded {
+                        header_ok,
+                        ok,
+                        status,
+                        last_y: 0,
+                        pixels,
+                    };
+                }
+                for &(plane, size) in planes(&output).iter().filter(|p| !p.0.is_null()) {
+                    ptr::write_bytes(plane, 0, size);
+                }
+                ok = decode_image(dec);
+                if ok != 0 && (*dec).status_ == VP8StatusCode::VP8_STATUS_SUSPENDED {
+                    // Feed the rest of the data and resume.
+                    VP8LBitReaderSetBuffer(&mut (*dec).br_, data.as_ptr(), data.len());
+                    ok = decode_image(dec);
+                }
+                for &(plane, size) in planes(&output).iter().filter(|p| !p.0.is_null()) {
+                    pixels.extend_from_slice(slice::from_raw_parts(plane, size));
+                }
+            }
+            let status = (*dec).status_;
+            delete(dec);
+            WebPFreeDecBuffer(&mut output);
+            Decoded {
+                header_ok,
+                ok,
+                status,
+                last_y: params.last_y,
+                pixels,
+            }
+        }
+    }
+
+    fn check_decode(
+        data: &[u8],
+        colorspace: WEBP_CSP_MODE,
+        options: Option<&WebPDecoderOptions>,
+        split: Option<usize>,
+        digest: &mut Digest,
+    ) -> Decoded {
+        let a = decode(data, colorspace, options, split, PORT);
+        digest.update_i32(&[a.header_ok, a.ok, a.status as i32, a.last_y]);
+        digest.update(&a.pixels);
+        #[cfg(feature = "c-reference")]
+        {
+            let b = decode(data, colorspace, options, split, REFERENCE);
+            assert_eq!((a.header_ok, a.ok, a.status), (b.header_ok, b.ok, b.status));
+            assert_eq!(a.last_y, b.last_y);
+            assert!(a.pixels == b.pixels);
+        }
+        a
+    }
+
+    fn new_options() -> WebPDecoderOptions {
+        unsafe { mem::zeroed() }
+    }
+
+    #[test]
+    fn test_get_info_parity() {
+        let mut digest = Digest::new();
+        for data in corpus() {
+            for len in 0..8 {
+                for pos in 0..len {
+                    let mut header = data[..len].to_vec();
+                    header[pos] ^= 0x81;
+                    let mut a = (0, 0, 0);
+                    let ok =
+                        unsafe { VP8LGetInfo(header.as_ptr(), len, &mut a.0, &mut a.1, &mut a.2) };
+                    digest.update_i32(&[ok, a.0, a.1, a.2]);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let mut b = (0, 0, 0);
+                        let ref_ok = unsafe {
+                            WebPRef_VP8LGetInfo(header.as_ptr(), len, &mut b.0, &mut b.1, &mut b.2)
+                        };
+                        assert_eq!((ok, a), (ref_ok, b));
+                    }
+                }
+            }
+        }
+        check_parity("dec::vp8l_dec::test_get_info_parity", digest);
+    }
+
+    #[test]
+    fn test_decode_parity() {
+        let mut digest = Digest::new();
+        let corpus = corpus();
+        assert_eq!(corpus.len(), images().len() * 11);
+        for data in &corpus {
+            for &colorspace in &[
+                MODE_RGBA,
+                MODE_BGRA,
+                MODE_rgbA,
+                MODE_RGB_565,
+                MODE_YUV,
+                MODE_YUVA,
+            ] {
+                let decoded = check_decode(data, colorspace, None, None, &mut digest);
+                assert_eq!(
+                    (decoded.ok, decoded.status),
+                    (1, VP8StatusCode::VP8_STATUS_OK)
+                );
+            }
+        }
+        check_parity("dec::vp8l_dec::test_decode_parity", digest);
+    }
+
+    #[test]
+    fn test_decode_lossless() {
+        for (width, height, rgba) in images() {
+            let config = EncoderConfig {
+                exact: true,
+                ..EncoderConfig::lossless_preset(6).unwrap()
+            };
+            let webp = encode_rgba_with_config(&rgba, width, height, width * 4, &config).unwrap();
+            let data = chunk(&webp, b"VP8L").unwrap();
+            let decoded = decode(data, MODE_RGBA, None, None, PORT);
+            assert_eq!(decoded.ok, 1);
+            assert_eq!(decoded.last_y, height as c_int);
+            assert!(decoded.pixels == rgba);
+        }
+    }
+
+    #[test]
+    fn test_decode_crop_and_scale_parity() {
+        let mut cropped = new_options();
+        cropped.use_cropping = 1;
+        cropped.crop_left = 3;
+        cropped.crop_top = 5;
+        cropped.crop_width = 20;
+        cropped.crop_height = 11;
+        let mut scaled = new_options();
+        scaled.use_scaling = 1;
+        scaled.scaled_width = 17;
+        scaled.scaled_height = 31;
+        let mut both = new_options();
+        both.use_cropping = 1;
+        both.crop_left = 1;
+        both.crop_top = 2;
+        both.crop_width = 25;
+        both.crop_height = 14;
+        both.use_scaling = 1;
+        both.scaled_width = 50;
+        both.scaled_height = 7;
+        let mut digest = Digest::new();
+        for data in corpus().iter().step_by(3) {
+            for &options in &[&cropped, &scaled, &both] {
+                for &colorspace in &[MODE_RGBA, MODE_rgbA, MODE_YUVA] {
+                    check_decode(data, colorspace, Some(options), None, &mut digest);
+                }
+            }
+        }
+        check_parity("dec::vp8l_dec::test_decode_crop_and_scale_parity", digest);
+    }
+
+    #[test]
+    fn test_decode_truncated_parity() {
+        let mut digest = Digest::new();
+        for data in corpus().iter().step_by(5) {
+            for len in (0..data.len()).step_by(13) {
+                check_decode(&data[..len], MODE_RGBA, None, None, &mut digest);
+            }
+            // Corrupted data.
+            for pos in (5..data.len()).step_by(7) {
+                let mut corrupted = data.clone();
+                corrupted[pos] ^= 0x24;
+                check_decode(&corrupted, MODE_RGBA, None, None, &mut digest);
+            }
+        }
+        check_parity("dec::vp8l_dec::test_decode_truncated_parity", digest);
+    }
+
+    #[test]
+    fn test_decode_incremental_parity() {
+        let mut digest = Digest::new();
+        for data in corpus().iter().step_by(4) {
+            let full = check_decode(data, MODE_RGBA, None, None, &mut digest);
+            for split in (data.len() / 2..data.len()).step_by(17) {
+                let decoded = check_decode(data, MODE_RGBA, None, Some(split), &mut digest);
+                if decoded.ok != 0 {
+                    assert!(decoded.pixels == full.pixels);
+                }
+            }
+        }
+        check_parity("dec::vp8l_dec::test_decode_incremental_parity", digest);
+    }
+
+    // Layout of the C alpha decoder, for the reference.
+    #[cfg(feature = "c-reference")]
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct RefALPHDecoder {
+        width_: c_int,
+        height_: c_int,
+        method_: c_int,
+        filter_: WEBP_FILTER_TYPE,
+        pre_processing_: c_int,
+        vp8l_dec_: *mut VP8LDecoder,
+        io_: VP8Io,
+        use_8b_decode_: c_int,
+        output_: *mut u8,
+        prev_line_: *const u8,
+    }
+
+    // Rows up to which the alpha plane of height 'height' is decoded, with
+    // 'step' rows at a time.
+    fn last_rows(height: c_int, step: c_int) -> Vec<c_int> {
+        let mut last_rows = Vec::new();
+        let mut row = 0;
+        while row < height {
+            row = if row + step > height {
+                height
+            } else {
+                row + step
+            };
+            last_rows.push(row);
+        }
+        last_rows
+    }
+
+    fn new_alpha_io(width: c_int, height: c_int) -> VP8Io {
+        unsafe {
+            let mut io: VP8Io = mem::zeroed();
+            VP8InitIo(&mut io);
+            io.width = width;
+            io.crop_right = width;
+            io.crop_bottom = height;
+            io
+        }
+    }
+
+    // Decodes the alpha plane stored in the 'ALPH' chunk payload 'data', with
+    // 'step' rows at a time.
+    fn decode_alpha(
+        data: &[u8],
+        width: c_int,
+        height: c_int,
+        step: c_int,
+    ) -> (Vec<c_int>, Vec<u8>) {
+        let filter = WEBP_FILTER_TYPE::new(((data[0] >> 2) & 0x03) as c_int);
+        let alpha_data = &data[ALPHA_HEADER_LEN..];
+        let mut results = Vec::new();
+        unsafe {
+            VP8FiltersInit();
+            let io = new_alpha_io(width, height);
+            let mut alph_dec = ALPHNew(&io).unwrap();
+            alph_dec.width_ = width;
+            alph_dec.height_ = height;
+            alph_dec.filter_ = filter;
+            alph_dec.io_ = io;
+            let header_ok = VP8LDecodeAlphaHeader(&mut alph_dec, alpha_data);
+            results.push(header_ok);
+            if header_ok != 0 {
+                results.push(alph_dec.use_8b_decode_);
+                for last_row in last_rows(height, step) {
+                    results.push(VP8LDecodeAlphaImageStream(&mut alph_dec, last_row));
+                }
+            }
+            (results, alph_dec.output_.clone())
+        }
+    }
 
-extern "C" {
-    pub(crate) fn VP8LDecodeAlphaHeader(
-        alph_dec: *mut ALPHDecoder,
-        data: *const u8,
-        data_size: usize,
-    ) -> c_int;
+    #[cfg(feature = "c-reference")]
+    fn decode_alpha_ref(
+        data: &[u8],
+        width: c_int,
+        height: c_int,
+        step: c_int,
+    ) -> (Vec<c_int>, Vec<u8>) {
+        let filter = WEBP_FILTER_TYPE::new(((data[0] >> 2) & 0x03) as c_int);
+        let alpha_data = &data[ALPHA_HEADER_LEN..];
+        let mut results = Vec::new();
+        unsafe {
+            WebPRef_VP8FiltersInit();
+            let mut output = vec![0u8; (width * height) as usize];
+            let mut alph_dec: RefALPHDecoder = mem::zeroed();
+            alph_dec.width_ = width;
+            alph_dec.height_ = height;
+            alph_dec.filter_ = filter;
+            alph_dec.output_ = output.as_mut_ptr();
+            alph_dec.io_ = new_alpha_io(width, height);
+            let header_ok =
+                WebPRef_VP8LDecodeAlphaHeader(&mut alph_dec, alpha_data.as_ptr(), alpha_data.len());
+            results.push(header_ok);
+            if header_ok != 0 {
+                results.push(alph_dec.use_8b_decode_);
+                for last_row in last_rows(height, step) {
+                    results.push(WebPRef_VP8LDecodeAlphaImageStream(&mut alph_dec, last_row));
+                }
+                WebPRef_VP8LDelete(alph_dec.vp8l_dec_);
+            }
+            (results, output)
+        }
+    }
 
-    pub(crate) fn VP8LDecodeAlphaImageStream(alph_dec: *mut ALPHDecoder, last_row: c_int) -> c_int;
+    #[test]
+    fn test_decode_alpha_parity() {
+        let mut digest = Digest::new();
+        let mut use_8b_decode = [false; 2];
+        for (width, height, mut rgba) in images() {
+            if width * height < 64 {
+                continue;
+            }
+            // Few alpha levels give a paletted alpha plane (8b decoding path).
+            let mut few_levels = rgba.clone();
+            for (i, pixel) in few_levels.chunks_mut(4).enumerate() {
+                pixel[3] = [0, 0x80, 0xff][i / 5 % 3];
+            }
+            for pixel in rgba.chunks_mut(4) {
+                pixel[3] = pixel[0] / 2 + 0x40;
+            }
+            for rgba in &[rgba, few_levels] {
+                for alpha_filtering in 0..3 {
+                    let config = EncoderConfig {
+                        alpha_filtering,
+                        ..EncoderConfig::default()
+                    };
+                    let webp =
+                        encode_rgba_with_config(rgba, width, height, width * 4, &config).unwrap();
+                    let data = chunk(&webp, b"ALPH").unwrap();
+                    let (w, h) = (width as c_int, height as c_int);
+                    for &step in &[1, 16, h] {
+                        let (results, alpha) = decode_alpha(data, w, h, step);
+                        assert_eq!(results[0], 1);
+                        assert!(results[2..].iter().all(|&r| r == 1));
+                        use_8b_decode[results[1] as usize] = true;
+                        digest.update_i32(&results);
+                        digest.update(&alpha);
+                        #[cfg(feature = "c-reference")]
+                        assert_eq!((results, alpha), decode_alpha_ref(data, w, h, step));
+                    }
+                }
+            }
+        }
+        assert_eq!(use_8b_decode, [true, true]);
+        check_parity("dec::vp8l_dec::test_decode_alpha_parity", digest);
+    }
 }
@@ -1,7 +1,82 @@
 use std::os::raw::*;
 
-pub(crate) type VP8LDecoder = c_void; // TODO
+use dec::vp8_dec::VP8Io;
+use decode::VP8StatusCode;
+use format_constants::{VP8LImageTransformType, NUM_TRANSFORMS};
+use sys;
+use utils::bit_reader_utils::VP8LBitReader;
+use utils::color_cache_utils::VP8LColorCache;
+use utils::huffman_utils::{HTreeGroup, HuffmanCode};
+use utils::rescaler_utils::WebPRescaler;
 
-extern "C" {
-    pub(crate) fn VP8LDelete(dec: *mut VP8LDecoder);
+pub(crate) use self::VP8LDecodeState::*;
+
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub(crate) enum VP8LDecodeState {
+    READ_DATA = 0,
+    READ_HDR = 1,
+    READ_DIM = 2,
+}
+
+#[repr(C)]
+pub struct VP8LTransform {
+    pub(crate) type_: VP8LImageTransformType, // transform type.
+    pub(crate) bits_: c_int,                  // subsampling bits defining transform window.
+    pub(crate) xsize_: c_int,                 // transform window X index.
+    pub(crate) ysize_: c_int,                 // transform window Y index.
+    pub(crate) data_: *mut u32,               // transform data.
+}
+
+#[repr(C)]
+pub(crate) struct VP8LMetadata {
+    pub(crate) color_cache_size_: c_int,
+    pub(crate) color_cache_: VP8LColorCache,
+    pub(crate) saved_color_cache_: VP8LColorCache, // for incremental
+
+    pub(crate) huffman_mask_: c_int,
+    pub(crate) huffman_subsample_bits_: c_int,
+    pub(crate) huffman_xsize_: c_int,
+    pub(crate) huffman_image_: *mut u32,
+    pub(crate) num_htree_groups_: c_int,
+    pub(crate) htree_groups_: *mut HTreeGroup,
+    pub(crate) huffman_tables_: *mut HuffmanCode,
+}
+
+#[repr(C)]
+pub struct VP8LDecoder {
+    pub(crate) status_: VP8StatusCode,
+    pub(crate) state_: VP8LDecodeState,
+    pub(crate) io_: *mut VP8Io,
+
+    pub(crate) output_: *const sys::WebPDecBuffer, // shortcut to io->opaque->output
+
+    pub(crate) pixels_: *mut u32, // Internal data: either uint8_t* for alpha
+    // or uint32_t* for BGRA.
+    pub(crate) argb_cache_: *mut u32, // Scratch buffer for temporary BGRA storage.
+
+    pub(crate) br_: VP8LBitReader,
+    pub(crate) incremental_: c_int, // if true, incremental decoding is expected
+    pub(crate) saved_br_: VP8LBitReader, // note: could be local variables too
+    pub(crate) saved_last_pixel_: c_int,
+
+    pub(crate) width_: c_int,
+    pub(crate) height_: c_int,
+    pub(crate) last_row_: c_int,   // last input row decoded so far.
+    pub(crate) last_pixel_: c_int, // last pixel decoded so far. However, it may
+    // not be transformed, scaled and
+    // color-converted yet.
+    pub(crate) last_out_row_: c_int, // last row output so far.
+
+    pub(crate) hdr_: VP8LMetadata,
+
+    pub(crate) next_transform_: c_int,
+    pub(crate) transforms_: [VP8LTransform; NUM_TRANSFORMS],
+    // or'd bitset storing the transforms types.
+    pub(crate) transforms_seen_: u32,
+
+    pub(crate) rescaler_memory: *mut u8, // Working memory for rescaling work.
+    pub(crate) rescaler: *mut WebPRescaler, // Common rescaler for all channels.
 }
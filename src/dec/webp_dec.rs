@@ -0,0 +1,1073 @@
+// Copyright 2010 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Main decoding functions for WEBP images.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(clippy::too_many_arguments)]
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+use std::slice;
+
+use dec::buffer_dec::{
+    WebPAllocateDecBuffer, WebPAvoidSlowMemory, WebPCopyDecBuffer, WebPCopyDecBufferPixels,
+    WebPFlipBuffer, WebPFreeDecBuffer, WebPInitDecBufferInternal,
+};
+use dec::frame_dec::{VP8GetThreadMethod, VP8InitDithering};
+use dec::io_dec::WebPInitCustomIo;
+use dec::vp8_dec::{
+    VP8Decode, VP8Delete, VP8GetHeaders, VP8GetInfo, VP8InitIo, VP8Io, VP8New,
+    WEBP_ABI_IS_INCOMPATIBLE,
+};
+use dec::vp8l_dec::{
+    VP8LCheckSignature, VP8LDecodeHeader, VP8LDecodeImage, VP8LDelete, VP8LGetInfo, VP8LNew,
+};
+use dec::webpi_dec::{WebPDecParams, WebPHeaderStructure};
+use decode::VP8StatusCode::{self, *};
+use decode::{
+    WebPIsRGBMode, MODE_ARGB, MODE_BGR, MODE_BGRA, MODE_RGB, MODE_RGBA, MODE_YUV, WEBP_CSP_MODE,
+    WEBP_DECODER_ABI_VERSION,
+};
+use format_constants::*;
+use sys::{WebPBitstreamFeatures, WebPDecBuffer, WebPDecoderConfig, WebPDecoderOptions};
+use utils::rescaler_utils::WebPRescalerGetScaledDimensions;
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetLE24(data: *const u8) -> u32 {
+    *data as u32 | (*data.offset(1) as u32) << 8 | (*data.offset(2) as u32) << 16
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetLE32(data: *const u8) -> u32 {
+    GetLE24(data) | (*data.offset(3) as u32) << 24
+}
+
+// Compares the 4-byte tag at 'data' with 'tag'.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn IsTag(data: *const u8, tag: &[u8; TAG_SIZE]) -> bool {
+    slice::from_raw_parts(data, TAG_SIZE) == tag
+}
+
+//------------------------------------------------------------------------------
+// RIFF layout is:
+//   Offset  tag
+//   0...3   "RIFF" 4-byte tag
+//   4...7   size of image data (including metadata) starting at offset 8
+//   8...11  "WEBP"   our form-type signature
+// The RIFF container (12 bytes) is followed by appropriate chunks:
+//   12..15  "VP8 ": 4-bytes tags, signaling the use of VP8 video format
+//   16..19  size of the raw VP8 image data, starting at offset 20
+//   20....  the VP8 bytes
+// Or,
+//   12..15  "VP8L": 4-bytes tags, signaling the use of VP8L lossless format
+//   16..19  size of the raw VP8L image data, starting at offset 20
+//   20....  the VP8L bytes
+// Or,
+//   12..15  "VP8X": 4-bytes tags, describing the extended-VP8 chunk.
+//   16..19  size of the VP8X chunk starting at offset 20.
+//   20..23  VP8X flags bit-map corresponding to the chunk-types present.
+//   24..26  Width of the Canvas Image.
+//   27..29  Height of the Canvas Image.
+// There can be extra chunks after the "VP8X" chunk (ICCP, ANMF, VP8, VP8L,
+// XMP, EXIF  ...)
+// All sizes are in little-endian order.
+// Note: chunk data size must be padded to multiple of 2 when written.
+
+// Validates the RIFF container (if detected) and skips over it.
+// If a RIFF container is detected, returns:
+//     VP8_STATUS_BITSTREAM_ERROR for invalid header,
+//     VP8_STATUS_NOT_ENOUGH_DATA for truncated data if have_all_data is true,
+// and VP8_STATUS_OK otherwise.
+// In case there are not enough bytes (partial RIFF container), return 0 for
+// *riff_size. Else return the RIFF size extracted from the header.
+#[allow(non_snake_case)]
+unsafe fn ParseRIFF(
+    data: &mut *const u8,
+    data_size: &mut usize,
+    have_all_data: c_int,
+    riff_size: &mut usize,
+) -> VP8StatusCode {
+    *riff_size = 0; // Default: no RIFF present.
+    if *data_size >= RIFF_HEADER_SIZE && IsTag(*data, b"RIFF") {
+        if !IsTag(data.offset(8), b"WEBP") {
+            return VP8_STATUS_BITSTREAM_ERROR; // Wrong image file signature.
+        } else {
+            let size = GetLE32(data.add(TAG_SIZE));
+            // Check that we have at least one chunk (i.e "WEBP" + "VP8?nnnn").
+            if (size as usize) < TAG_SIZE + CHUNK_HEADER_SIZE {
+                return VP8_STATUS_BITSTREAM_ERROR;
+            }
+            if size > MAX_CHUNK_PAYLOAD {
+                return VP8_STATUS_BITSTREAM_ERROR;
+            }
+            if have_all_data != 0 && size as usize > *data_size - CHUNK_HEADER_SIZE {
+                return VP8_STATUS_NOT_ENOUGH_DATA; // Truncated bitstream.
+            }
+            // We have a RIFF container. Skip it.
+            *riff_size = size as usize;
+            *data = data.add(RIFF_HEADER_SIZE);
+            *data_size -= RIFF_HEADER_SIZE;
+        }
+    }
+    VP8_STATUS_OK
+}
+
+// Validates the VP8X header and skips over it.
+// Returns VP8_STATUS_BITSTREAM_ERROR for invalid VP8X header,
+//         VP8_STATUS_NOT_ENOUGH_DATA in case of insufficient data, and
+//         VP8_STATUS_OK otherwise.
+// If a VP8X chunk is found, found_vp8x is set to true and *width_ptr,
+// *height_ptr and *flags_ptr are set to the corresponding values extracted
+// from the VP8X chunk.
+#[allow(non_snake_case)]
+unsafe fn ParseVP8X(
+    data: &mut *const u8,
+    data_size: &mut usize,
+    found_vp8x: &mut c_int,
+    width_ptr: &mut c_int,
+    height_ptr: &mut c_int,
+    flags_ptr: &mut u32,
+) -> VP8StatusCode {
+    let vp8x_size = CHUNK_HEADER_SIZE + VP8X_CHUNK_SIZE;
+
+    *found_vp8x = 0;
+
+    if *data_size < CHUNK_HEADER_SIZE {
+        return VP8_STATUS_NOT_ENOUGH_DATA; // Insufficient data.
+    }
+
+    if IsTag(*data, b"VP8X") {
+        let chunk_size = GetLE32(data.add(TAG_SIZE));
+        if chunk_size as usize != VP8X_CHUNK_SIZE {
+            return VP8_STATUS_BITSTREAM_ERROR; // Wrong chunk size.
+        }
+
+        // Verify if enough data is available to validate the VP8X chunk.
+        if *data_size < vp8x_size {
+            return VP8_STATUS_NOT_ENOUGH_DATA; // Insufficient data.
+        }
+        let flags = GetLE32(data.offset(8));
+        let width = 1 + GetLE24(data.offset(12)) as c_int;
+        let height = 1 + GetLE24(data.offset(15)) as c_int;
+        if width as u64 * height as u64 >= MAX_IMAGE_AREA {
+            return VP8_STATUS_BITSTREAM_ERROR; // image is too large
+        }
+
+        *flags_ptr = flags;
+        *width_ptr = width;
+        *height_ptr = height;
+        // Skip over VP8X header bytes.
+        *data = data.add(vp8x_size);
+        *data_size -= vp8x_size;
+        *found_vp8x = 1;
+    }
+    VP8_STATUS_OK
+}
+
+// Skips to the next VP8/VP8L chunk header in the data given the size of the
+// RIFF chunk 'riff_size'.
+// Returns VP8_STATUS_BITSTREAM_ERROR if any invalid chunk size is encountered,
+//         VP8_STATUS_NOT_ENOUGH_DATA in case of insufficient data, and
+//         VP8_STATUS_OK otherwise.
+// If an alpha chunk is found, *alpha_data and *alpha_size are set
+// appropriately.
+#[allow(non_snake_case)]
+unsafe fn ParseOptionalChunks(
+    data: &mut *const u8,
+    data_size: &mut usize,
+    riff_size: usize,
+    alpha_data: &mut *const u8,
+    alpha_size: &mut usize,
+) -> VP8StatusCode {
+    let mut total_size: u32 = (TAG_SIZE + // "WEBP".
+        CHUNK_HEADER_SIZE + // "VP8Xnnnn".
+        VP8X_CHUNK_SIZE) as u32; // data.
+    let mut buf = *data;
+    let mut buf_size = *data_size;
+
+    *alpha_data = ptr::null();
+    *alpha_size = 0;
+
+    loop {
+        *data = buf;
+        *data_size = buf_size;
+
+        if buf_size < CHUNK_HEADER_SIZE {
+            // Insufficient data.
+            return VP8_STATUS_NOT_ENOUGH_DATA;
+        }
+
+        let chunk_size = GetLE32(buf.add(TAG_SIZE));
+        if chunk_size > MAX_CHUNK_PAYLOAD {
+            return VP8_STATUS_BITSTREAM_ERROR; // Not a valid chunk size.
+        }
+        // For odd-sized chunk-payload, there's one byte padding at the end.
+        let disk_chunk_size = (CHUNK_HEADER_SIZE as u32 + chunk_size + 1) & !1;
+        total_size = total_size.wrapping_add(disk_chunk_size);
+
+        // Check that total bytes skipped so far does not exceed riff_size.
+        if riff_size > 0 && total_size as usize > riff_size {
+            return VP8_STATUS_BITSTREAM_ERROR; // Not a valid chunk size.
+        }
+
+        // Start of a (possibly incomplete) VP8/VP8L chunk implies that we have
+        // parsed all the optional chunks.
+        // Note: This check must occur before the check 'buf_size < disk_chunk_size'
+        // below to allow incomplete VP8/VP8L chunks.
+        if IsTag(buf, b"VP8 ") || IsTag(buf, b"VP8L") {
+            return VP8_STATUS_OK;
+        }
+
+        if buf_size < disk_chunk_size as usize {
+            // Insufficient data.
+            return VP8_STATUS_NOT_ENOUGH_DATA;
+        }
+
+        if IsTag(buf, b"ALPH") {
+            // A valid ALPH header.
+            *alpha_data = buf.add(CHUNK_HEADER_SIZE);
+            *alpha_size = chunk_size as usize;
+        }
+
+        // We have a full and valid chunk; skip it.
+        buf = buf.offset(disk_chunk_size as isize);
+        buf_size -= disk_chunk_size as usize;
+    }
+}
+
+// Validates the VP8/VP8L Header ("VP8 nnnn" or "VP8L nnnn") and skips over it.
+// Returns VP8_STATUS_BITSTREAM_ERROR for invalid (chunk larger than
+//         riff_size) VP8/VP8L header,
+//         VP8_STATUS_NOT_ENOUGH_DATA in case of insufficient data, and
+//         VP8_STATUS_OK otherwise.
+// If a VP8/VP8L chunk is found, *chunk_size is set to the total number of bytes
+// extracted from the VP8/VP8L chunk header.
+// The flag '*is_lossless' is set to 1 in case of VP8L chunk / raw VP8L data.
+#[allow(non_snake_case)]
+unsafe fn ParseVP8Header(
+    data_ptr: &mut *const u8,
+    data_size: &mut usize,
+    have_all_data: c_int,
+    riff_size: usize,
+    chunk_size: &mut usize,
+    is_lossless: &mut c_int,
+) -> VP8StatusCode {
+    let data = *data_ptr;
+    let minimal_size = TAG_SIZE + CHUNK_HEADER_SIZE; // "WEBP" + "VP8 nnnn" OR
+                                                     // "WEBP" + "VP8Lnnnn"
+
+    if *data_size < CHUNK_HEADER_SIZE {
+        return VP8_STATUS_NOT_ENOUGH_DATA; // Insufficient data.
+    }
+    let is_vp8 = IsTag(data, b"VP8 ");
+    let is_vp8l = IsTag(data, b"VP8L");
+
+    if is_vp8 || is_vp8l {
+        // Bitstream contains VP8/VP8L header.
+        let size = GetLE32(data.add(TAG_SIZE)) as usize;
+        if riff_size >= minimal_size && size > riff_size - minimal_size {
+            return VP8_STATUS_BITSTREAM_ERROR; // Inconsistent size information.
+        }
+        if have_all_data != 0 && size > *data_size - CHUNK_HEADER_SIZE {
+            return VP8_STATUS_NOT_ENOUGH_DATA; // Truncated bitstream.
+        }
+        // Skip over CHUNK_HEADER_SIZE bytes from VP8/VP8L Header.
+        *chunk_size = size;
+        *data_ptr = data_ptr.add(CHUNK_HEADER_SIZE);
+        *data_size -= CHUNK_HEADER_SIZE;
+        *is_lossless = is_vp8l as c_int;
+    } else {
+        // Raw VP8/VP8L bitstream (no header).
+        *is_lossless = VP8LCheckSignature(data, *data_size);
+        *chunk_size = *data_size;
+    }
+
+    VP8_STATUS_OK
+}
+
+//------------------------------------------------------------------------------
+
+// Fetch '*width', '*height', '*has_alpha' and fill out 'headers' based on
+// 'data'. All the output parameters may be NULL. If 'headers' is NULL only the
+// minimal amount will be read to fetch the remaining parameters.
+// If 'headers' is non-NULL this function will attempt to locate both alpha
+// data (with or without a VP8X chunk) and the bitstream chunk (VP8/VP8L).
+// Note: The following chunk sequences (before the raw VP8/VP8L data) are
+// considered valid by this function:
+// RIFF + VP8(L)
+// RIFF + VP8X + (optional chunks) + VP8(L)
+// ALPH + VP8 <-- Not a valid WebP format: only allowed for internal purpose.
+// VP8(L)     <-- Not a valid WebP format: only allowed for internal purpose.
+#[allow(non_snake_case)]
+unsafe fn ParseHeadersInternal(
+    mut data: *const u8,
+    mut data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+    has_alpha: *mut c_int,
+    has_animation: *mut c_int,
+    format: *mut c_int,
+    headers: *mut WebPHeaderStructure,
+) -> VP8StatusCode {
+    let mut canvas_width = 0;
+    let mut canvas_height = 0;
+    let mut image_width;
+    let mut image_height;
+    let mut found_vp8x = 0;
+    let animation_present;
+    let have_all_data = headers.as_ref().map_or(0, |headers| headers.have_all_data);
+
+    if data.is_null() || data_size < RIFF_HEADER_SIZE {
+        return VP8_STATUS_NOT_ENOUGH_DATA;
+    }
+    let mut hdrs: WebPHeaderStructure = mem::zeroed();
+    hdrs.data = data;
+    hdrs.data_size = data_size;
+
+    // Skip over RIFF header.
+    let mut status = ParseRIFF(
+        &mut data,
+        &mut data_size,
+        have_all_data,
+        &mut hdrs.riff_size,
+    );
+    if status != VP8_STATUS_OK {
+        return status; // Wrong RIFF header / insufficient data.
+    }
+    let found_riff = hdrs.riff_size > 0;
+
+    // The parsing below breaks out to 'ReturnWidthHeight' with its status,
+    // unless an error must be returned as-is.
+    status = 'parse: {
+        // Skip over VP8X.
+        {
+            let mut flags = 0;
+            status = ParseVP8X(
+                &mut data,
+                &mut data_size,
+                &mut found_vp8x,
+                &mut canvas_width,
+                &mut canvas_height,
+                &mut flags,
+            );
+            if status != VP8_STATUS_OK {
+                return status; // Wrong VP8X / insufficient data.
+            }
+            animation_present = (flags & ANIMATION_FLAG != 0) as c_int;
+            if !found_riff && found_vp8x != 0 {
+                // Note: This restriction may be removed in the future, if it becomes
+                // necessary to send VP8X chunk to the decoder.
+                return VP8_STATUS_BITSTREAM_ERROR;
+            }
+            if !has_alpha.is_null() {
+                *has_alpha = (flags & ALPHA_FLAG != 0) as c_int;
+            }
+            if !has_animation.is_null() {
+                *has_animation = animation_present;
+            }
+            if !format.is_null() {
+                *format = 0; // default = undefined
+            }
+
+            image_width = canvas_width;
+            image_height = canvas_height;
+            if found_vp8x != 0 && animation_present != 0 && headers.is_null() {
+                break 'parse VP8_STATUS_OK; // Just return features from VP8X header.
+            }
+        }
+
+        if data_size < TAG_SIZE {
+            break 'parse VP8_STATUS_NOT_ENOUGH_DATA;
+        }
+
+        // Skip over optional chunks if data started with "RIFF + VP8X" or "ALPH".
+        if (found_riff && found_vp8x != 0)
+            || (!found_riff && found_vp8x == 0 && IsTag(data, b"ALPH"))
+        {
+            status = ParseOptionalChunks(
+                &mut data,
+                &mut data_size,
+                hdrs.riff_size,
+                &mut hdrs.alpha_data,
+                &mut hdrs.alpha_data_size,
+            );
+            if status != VP8_STATUS_OK {
+                break 'parse status; // Invalid chunk size / insufficient data.
+            }
+        }
+
+        // Skip over VP8/VP8L header.
+        status = ParseVP8Header(
+            &mut data,
+            &mut data_size,
+            have_all_data,
+            hdrs.riff_size,
+            &mut hdrs.compressed_size,
+            &mut hdrs.is_lossless,
+        );
+        if status != VP8_STATUS_OK {
+            break 'parse status; // Wrong VP8/VP8L chunk-header / insufficient data.
+        }
+        if hdrs.compressed_size > MAX_CHUNK_PAYLOAD as usize {
+            return VP8_STATUS_BITSTREAM_ERROR;
+        }
+
+        if !format.is_null() && animation_present == 0 {
+            *format = if hdrs.is_lossless != 0 { 2 } else { 1 };
+        }
+
+        if hdrs.is_lossless == 0 {
+            if data_size < VP8_FRAME_HEADER_SIZE {
+                break 'parse VP8_STATUS_NOT_ENOUGH_DATA;
+            }
+            // Validates raw VP8 data.
+            if VP8GetInfo(
+                data,
+                data_size,
+                hdrs.compressed_size,
+                &mut image_width,
+                &mut image_height,
+            ) == 0
+            {
+                return VP8_STATUS_BITSTREAM_ERROR;
+            }
+        } else {
+            if data_size < VP8L_FRAME_HEADER_SIZE {
+                break 'parse VP8_STATUS_NOT_ENOUGH_DATA;
+            }
+            // Validates raw VP8L data.
+            if VP8LGetInfo(
+                data,
+                data_size,
+                &mut image_width,
+                &mut image_height,
+                has_alpha,
+            ) == 0
+            {
+                return VP8_STATUS_BITSTREAM_ERROR;
+            }
+        }
+        // Validates image size coherency.
+        if found_vp8x != 0 && (canvas_width != image_width || canvas_height != image_height) {
+            return VP8_STATUS_BITSTREAM_ERROR;
+        }
+        if let Some(headers) = headers.as_mut() {
+            *headers = hdrs;
+            headers.offset = data as usize - headers.data as usize;
+            c_assert!(((data as usize - headers.data as usize) as u64) < MAX_CHUNK_PAYLOAD as u64);
+            c_assert!(headers.offset == headers.data_size - data_size);
+        }
+        VP8_STATUS_OK
+    };
+
+    // ReturnWidthHeight:
+    if status == VP8_STATUS_OK
+        || (status == VP8_STATUS_NOT_ENOUGH_DATA && found_vp8x != 0 && headers.is_null())
+    {
+        if !has_alpha.is_null() {
+            // If the data did not contain a VP8X/VP8L chunk the only definitive way
+            // to set this is by looking for alpha data (from an ALPH chunk).
+            *has_alpha |= !hdrs.alpha_data.is_null() as c_int;
+        }
+        if !width.is_null() {
+            *width = image_width;
+        }
+        if !height.is_null() {
+            *height = image_height;
+        }
+        VP8_STATUS_OK
+    } else {
+        status
+    }
+}
+
+// Skips over all the headers that may precede the VP8/VP8L data, and fills
+// 'headers' with the RIFF size, the VP8/VP8L data offset and size and the
+// ALPH chunk, if any. Returns VP8_STATUS_UNSUPPORTED_FEATURE for animations.
+#[no_mangle]
+pub unsafe extern "C" fn WebPParseHeaders(headers: *mut WebPHeaderStructure) -> VP8StatusCode {
+    let mut has_animation = 0;
+    c_assert!(!headers.is_null());
+    // fill out headers, ignore width/height/has_alpha.
+    let mut status = ParseHeadersInternal(
+        (*headers).data,
+        (*headers).data_size,
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        &mut has_animation,
+        ptr::null_mut(),
+        headers,
+    );
+    if status == VP8_STATUS_OK || status == VP8_STATUS_NOT_ENOUGH_DATA {
+        // The WebPDemux API + libwebp can be used to decode individual
+        // uncomposited frames or the WebPAnimDecoder can be used to fully
+        // reconstruct them (see webp/demux.h).
+        if has_animation != 0 {
+            status = VP8_STATUS_UNSUPPORTED_FEATURE;
+        }
+    }
+    status
+}
+
+//------------------------------------------------------------------------------
+// WebPDecParams
+
+// Should be called first, before any use of the WebPDecParams object.
+#[no_mangle]
+pub unsafe extern "C" fn WebPResetDecParams(params: *mut WebPDecParams) {
+    if !params.is_null() {
+        ptr::write_bytes(params, 0, 1);
+    }
+}
+
+//------------------------------------------------------------------------------
+// "Into" decoding variants
+
+// Main flow
+#[allow(non_snake_case)]
+unsafe fn DecodeInto(
+    data: *const u8,
+    data_size: usize,
+    params: &mut WebPDecParams,
+) -> VP8StatusCode {
+    let mut io: VP8Io = mem::zeroed();
+    let mut headers: WebPHeaderStructure = mem::zeroed();
+
+    headers.data = data;
+    headers.data_size = data_size;
+    headers.have_all_data = 1;
+    let mut status = WebPParseHeaders(&mut headers); // Process Pre-VP8 chunks.
+    if status != VP8_STATUS_OK {
+        return status;
+    }
+
+    VP8InitIo(&mut io);
+    io.data = headers.data.add(headers.offset);
+    io.data_size = headers.data_size - headers.offset;
+    WebPInitCustomIo(params, &mut io); // Plug the I/O functions.
+
+    if headers.is_lossless == 0 {
+        let dec = VP8New();
+        if dec.is_null() {
+            return VP8_STATUS_OUT_OF_MEMORY;
+        }
+        (*dec).alpha_data_ = headers.alpha_data;
+        (*dec).alpha_data_size_ = headers.alpha_data_size;
+
+        // Decode bitstream header, update io->width/io->height.
+        if VP8GetHeaders(dec, &mut io) == 0 {
+            status = (*dec).status_; // An error occurred. Grab error status.
+        } else {
+            // Allocate/check output buffers.
+            status = WebPAllocateDecBuffer(io.width, io.height, params.options, params.output);
+            if status == VP8_STATUS_OK {
+                // Decode
+                // This change must be done before calling VP8Decode()
+                (*dec).mt_method_ =
+                    VP8GetThreadMethod(params.options, &headers, io.width, io.height);
+                VP8InitDithering(params.options, dec);
+                if VP8Decode(dec, &mut io) == 0 {
+                    status = (*dec).status_;
+                }
+            }
+        }
+        VP8Delete(dec);
+    } else {
+        let dec = VP8LNew();
+        if dec.is_null() {
+            return VP8_STATUS_OUT_OF_MEMORY;
+        }
+        if VP8LDecodeHeader(dec, &mut io) == 0 {
+            status = (*dec).status_; // An error occurred. Grab error status.
+        } else {
+            // Allocate/check output buffers.
+            status = WebPAllocateDecBuffer(io.width, io.height, params.options, params.output);
+            if status == VP8_STATUS_OK {
+                // Decode
+                if VP8LDecodeImage(dec) == 0 {
+                    status = (*dec).status_;
+                }
+            }
+        }
+        VP8LDelete(dec);
+    }
+
+    if status != VP8_STATUS_OK {
+        WebPFreeDecBuffer(params.output);
+    } else if !params.options.is_null() && (*params.options).flip != 0 {
+        // This restores the original stride values if options->flip was used
+        // during the call to WebPAllocateDecBuffer above.
+        status = WebPFlipBuffer(params.output);
+    }
+    status
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn WebPInitDecBuffer(buffer: *mut WebPDecBuffer) -> c_int {
+    WebPInitDecBufferInternal(buffer, WEBP_DECODER_ABI_VERSION as c_int)
+}
+
+// Helpers
+#[allow(non_snake_case)]
+unsafe fn DecodeIntoRGBABuffer(
+    colorspace: WEBP_CSP_MODE,
+    data: *const u8,
+    data_size: usize,
+    rgba: *mut u8,
+    stride: c_int,
+    size: usize,
+) -> *mut u8 {
+    let mut params: WebPDecParams = mem::zeroed();
+    let mut buf: WebPDecBuffer = mem::zeroed();
+    if rgba.is_null() {
+        return ptr::null_mut();
+    }
+    WebPInitDecBuffer(&mut buf);
+    WebPResetDecParams(&mut params);
+    buf.colorspace = colorspace;
+    buf.u.RGBA.rgba = rgba;
+    buf.u.RGBA.stride = stride;
+    buf.u.RGBA.size = size;
+    buf.is_external_memory = 1;
+    params.output = &mut buf;
+    if DecodeInto(data, data_size, &mut params) != VP8_STATUS_OK {
+        return ptr::null_mut();
+    }
+    rgba
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeRGBInto(
+    data: *const u8,
+    data_size: usize,
+    output: *mut u8,
+    size: usize,
+    stride: c_int,
+) -> *mut u8 {
+    DecodeIntoRGBABuffer(MODE_RGB, data, data_size, output, stride, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeRGBAInto(
+    data: *const u8,
+    data_size: usize,
+    output: *mut u8,
+    size: usize,
+    stride: c_int,
+) -> *mut u8 {
+    DecodeIntoRGBABuffer(MODE_RGBA, data, data_size, output, stride, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeARGBInto(
+    data: *const u8,
+    data_size: usize,
+    output: *mut u8,
+    size: usize,
+    stride: c_int,
+) -> *mut u8 {
+    DecodeIntoRGBABuffer(MODE_ARGB, data, data_size, output, stride, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeBGRInto(
+    data: *const u8,
+    data_size: usize,
+    output: *mut u8,
+    size: usize,
+    stride: c_int,
+) -> *mut u8 {
+    DecodeIntoRGBABuffer(MODE_BGR, data, data_size, output, stride, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeBGRAInto(
+    data: *const u8,
+    data_size: usize,
+    output: *mut u8,
+    size: usize,
+    stride: c_int,
+) -> *mut u8 {
+    DecodeIntoRGBABuffer(MODE_BGRA, data, data_size, output, stride, size)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeYUVInto(
+    data: *const u8,
+    data_size: usize,
+    luma: *mut u8,
+    luma_size: usize,
+    luma_stride: c_int,
+    u: *mut u8,
+    u_size: usize,
+    u_stride: c_int,
+    v: *mut u8,
+    v_size: usize,
+    v_stride: c_int,
+) -> *mut u8 {
+    let mut params: WebPDecParams = mem::zeroed();
+    let mut output: WebPDecBuffer = mem::zeroed();
+    if luma.is_null() {
+        return ptr::null_mut();
+    }
+    WebPInitDecBuffer(&mut output);
+    WebPResetDecParams(&mut params);
+    output.colorspace = MODE_YUV;
+    output.u.YUVA.y = luma;
+    output.u.YUVA.y_stride = luma_stride;
+    output.u.YUVA.y_size = luma_size;
+    output.u.YUVA.u = u;
+    output.u.YUVA.u_stride = u_stride;
+    output.u.YUVA.u_size = u_size;
+    output.u.YUVA.v = v;
+    output.u.YUVA.v_stride = v_stride;
+    output.u.YUVA.v_size = v_size;
+    output.is_external_memory = 1;
+    params.output = &mut output;
+    if DecodeInto(data, data_size, &mut params) != VP8_STATUS_OK {
+        return ptr::null_mut();
+    }
+    luma
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn Decode(
+    mode: WEBP_CSP_MODE,
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+    keep_info: *mut WebPDecBuffer,
+) -> *mut u8 {
+    let mut params: WebPDecParams = mem::zeroed();
+    let mut output: WebPDecBuffer = mem::zeroed();
+
+    WebPInitDecBuffer(&mut output);
+    WebPResetDecParams(&mut params);
+    output.colorspace = mode;
+
+    // Retrieve (and report back) the required dimensions from bitstream.
+    if WebPGetInfo(data, data_size, &mut output.width, &mut output.height) == 0 {
+        return ptr::null_mut();
+    }
+    if !width.is_null() {
+        *width = output.width;
+    }
+    if !height.is_null() {
+        *height = output.height;
+    }
+
+    // Decode
+    params.output = &mut output;
+    if DecodeInto(data, data_size, &mut params) != VP8_STATUS_OK {
+        return ptr::null_mut();
+    }
+    if !keep_info.is_null() {
+        // keep track of the side-info
+        WebPCopyDecBuffer(&output, keep_info);
+    }
+    // return decoded samples (don't clear 'output'!)
+    if WebPIsRGBMode(mode) != 0 {
+        output.u.RGBA.rgba
+    } else {
+        output.u.YUVA.y
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeRGB(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> *mut u8 {
+    Decode(MODE_RGB, data, data_size, width, height, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeRGBA(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> *mut u8 {
+    Decode(MODE_RGBA, data, data_size, width, height, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeARGB(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> *mut u8 {
+    Decode(MODE_ARGB, data, data_size, width, height, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeBGR(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> *mut u8 {
+    Decode(MODE_BGR, data, data_size, width, height, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeBGRA(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> *mut u8 {
+    Decode(MODE_BGRA, data, data_size, width, height, ptr::null_mut())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecodeYUV(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+    u: *mut *mut u8,
+    v: *mut *mut u8,
+    stride: *mut c_int,
+    uv_stride: *mut c_int,
+) -> *mut u8 {
+    let mut output: WebPDecBuffer = mem::zeroed(); // only to preserve the side-infos
+    let out = Decode(MODE_YUV, data, data_size, width, height, &mut output);
+
+    if !out.is_null() {
+        let buf = &output.u.YUVA;
+        *u = buf.u;
+        *v = buf.v;
+        *stride = buf.y_stride;
+        *uv_stride = buf.u_stride;
+        c_assert!(buf.u_stride == buf.v_stride);
+    }
+    out
+}
+
+#[allow(non_snake_case)]
+unsafe fn DefaultFeatures(features: *mut WebPBitstreamFeatures) {
+    c_assert!(!features.is_null());
+    ptr::write_bytes(features, 0, 1);
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetFeatures(
+    data: *const u8,
+    data_size: usize,
+    features: *mut WebPBitstreamFeatures,
+) -> VP8StatusCode {
+    if features.is_null() || data.is_null() {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    DefaultFeatures(features);
+
+    // Only parse enough of the data to retrieve the features.
+    let features = &mut *features;
+    ParseHeadersInternal(
+        data,
+        data_size,
+        &mut features.width,
+        &mut features.height,
+        &mut features.has_alpha,
+        &mut features.has_animation,
+        &mut features.format,
+        ptr::null_mut(),
+    )
+}
+
+//------------------------------------------------------------------------------
+// WebPGetInfo()
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPGetInfo(
+    data: *const u8,
+    data_size: usize,
+    width: *mut c_int,
+    height: *mut c_int,
+) -> c_int {
+    let mut features: WebPBitstreamFeatures = mem::zeroed();
+
+    if GetFeatures(data, data_size, &mut features) != VP8_STATUS_OK {
+        return 0;
+    }
+
+    if !width.is_null() {
+        *width = features.width;
+    }
+    if !height.is_null() {
+        *height = features.height;
+    }
+
+    1
+}
+
+//------------------------------------------------------------------------------
+// Advance decoding API
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitDecoderConfigInternal(
+    config: *mut WebPDecoderConfig,
+    version: c_int,
+) -> c_int {
+    if WEBP_ABI_IS_INCOMPATIBLE(version, WEBP_DECODER_ABI_VERSION as c_int) {
+        return 0; // version mismatch
+    }
+    if config.is_null() {
+        return 0;
+    }
+    ptr::write_bytes(config, 0, 1);
+    DefaultFeatures(&mut (*config).input);
+    WebPInitDecBuffer(&mut (*config).output);
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPGetFeaturesInternal(
+    data: *const u8,
+    data_size: usize,
+    features: *mut WebPBitstreamFeatures,
+    version: c_int,
+) -> VP8StatusCode {
+    if WEBP_ABI_IS_INCOMPATIBLE(version, WEBP_DECODER_ABI_VERSION as c_int) {
+        return VP8_STATUS_INVALID_PARAM; // version mismatch
+    }
+    if features.is_null() {
+        return VP8_STATUS_INVALID_PARAM;
+    }
+    GetFeatures(data, data_size, features)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPDecode(
+    data: *const u8,
+    data_size: usize,
+    config: *mut WebPDecoderConfig,
+) -> VP8StatusCode {
+    let mut params: WebPDecParams = mem::zeroed();
+
+    let config = match config.as_mut() {
+        Some(config) => config,
+        None => return VP8_STATUS_INVALID_PARAM,
+    };
+
+    let mut status = GetFeatures(data, data_size, &mut config.input);
+    if status != VP8_STATUS_OK {
+        if status == VP8_STATUS_NOT_ENOUGH_DATA {
+            return VP8_STATUS_BITSTREAM_ERROR; // Not-enough-data treated as error.
+        }
+        return status;
+    }
+
+    WebPResetDecParams(&mut params);
+    params.options = &config.options;
+    params.output = &mut config.output;
+    if WebPAvoidSlowMemory(params.output, &config.input) != 0 {
+        // decoding to slow memory: use a temporary in-mem buffer to decode into.
+        let mut in_mem_buffer: WebPDecBuffer = mem::zeroed();
+        WebPInitDecBuffer(&mut in_mem_buffer);
+        in_mem_buffer.colorspace = config.output.colorspace;
+        in_mem_buffer.width = config.input.width;
+        in_mem_buffer.height = config.input.height;
+        params.output = &mut in_mem_buffer;
+        status = DecodeInto(data, data_size, &mut params);
+        if status == VP8_STATUS_OK {
+            // do the slow-copy
+            status = WebPCopyDecBufferPixels(&in_mem_buffer, &mut config.output);
+        }
+        WebPFreeDecBuffer(&mut in_mem_buffer);
+    } else {
+        status = DecodeInto(data, data_size, &mut params);
+    }
+
+    status
+}
+
+//------------------------------------------------------------------------------
+// Cropping and rescaling.
+
+// Setup crop_xxx fields, mb_w and mb_h in io. 'src_colorspace' refers
+// to the *compressed* format, not the output one.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPIoInitFromOptions(
+    options: *const WebPDecoderOptions,
+    io: *mut VP8Io,
+    src_colorspace: WEBP_CSP_MODE,
+) -> c_int {
+    let options = options.as_ref();
+    let io = &mut *io;
+    let W = io.width;
+    let H = io.height;
+    let (mut x, mut y, mut w, mut h) = (0, 0, W, H);
+
+    // Cropping
+    io.use_cropping = options.is_some_and(|options| options.use_cropping > 0) as c_int;
+    if let Some(options) = options.filter(|_| io.use_cropping != 0) {
+        w = options.crop_width;
+        h = options.crop_height;
+        x = options.crop_left;
+        y = options.crop_top;
+        if WebPIsRGBMode(src_colorspace) == 0 {
+            // only snap for YUV420
+            x &= !1;
+            y &= !1;
+        }
+        if x < 0 || y < 0 || w <= 0 || h <= 0 || x + w > W || y + h > H {
+            return 0; // out of frame boundary error
+        }
+    }
+    io.crop_left = x;
+    io.crop_top = y;
+    io.crop_right = x + w;
+    io.crop_bottom = y + h;
+    io.mb_w = w;
+    io.mb_h = h;
+
+    // Scaling
+    io.use_scaling = options.is_some_and(|options| options.use_scaling > 0) as c_int;
+    if let Some(options) = options.filter(|_| io.use_scaling != 0) {
+        let mut scaled_width = options.scaled_width;
+        let mut scaled_height = options.scaled_height;
+        if WebPRescalerGetScaledDimensions(w, h, &mut scaled_width, &mut scaled_height) == 0 {
+            return 0;
+        }
+        io.scaled_width = scaled_width;
+        io.scaled_height = scaled_height;
+    }
+
+    // Filter
+    io.bypass_filtering = options.is_some_and(|options| options.bypass_filtering != 0) as c_int;
+
+    // Fancy upsampler
+    io.fancy_upsampling = options.is_none_or(|options| options.no_fancy_upsampling == 0) as c_int;
+
+    if io.use_scaling != 0 {
+        // disable filter (only for large downscaling ratio).
+        io.bypass_filtering =
+            (io.scaled_width < W * 3 / 4 && io.scaled_height < H * 3 / 4) as c_int;
+        io.fancy_upsampling = 0;
+    }
+    1
+}
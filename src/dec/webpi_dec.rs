@@ -1,35 +1,57 @@
 use std::os::raw::*;
 
 use dec::vp8_dec::VP8Io;
+use sys;
+use utils::rescaler_utils::WebPRescaler;
 
-pub(crate) type OutputFunc = Option<extern "C" fn(*const VP8Io, *mut WebPDecParams) -> c_int>;
-pub(crate) type OutputAlphaFunc =
-    Option<extern "C" fn(*const VP8Io, *mut WebPDecParams, c_int) -> c_int>;
-pub(crate) type OutputRowFunc = Option<extern "C" fn(*mut WebPDecParams, c_int, c_int)>;
+pub(crate) type OutputFunc =
+    Option<unsafe extern "C" fn(io: *const VP8Io, p: *mut WebPDecParams) -> c_int>;
+pub(crate) type OutputAlphaFunc = Option<
+    unsafe extern "C" fn(
+        io: *const VP8Io,
+        p: *mut WebPDecParams,
+        expected_num_out_lines: c_int,
+    ) -> c_int,
+>;
+pub(crate) type OutputRowFunc = Option<
+    unsafe extern "C" fn(p: *mut WebPDecParams, y_pos: c_int, max_out_lines: c_int) -> c_int,
+>;
 
 #[repr(C)]
-pub(crate) struct WebPDecParams {
-    // pub(crate) output: *mut WebPDecBuffer, // output buffer.
-    _output: *mut c_void,      // TODO
-    pub(crate) tmp_y: *mut u8, // cache for the fancy upsampler
-    pub(crate) tmp_u: *mut u8, // or used for tmp rescaling
+pub struct WebPDecParams {
+    pub(crate) output: *mut sys::WebPDecBuffer, // output buffer.
+    pub(crate) tmp_y: *mut u8,                  // cache for the fancy upsampler
+    pub(crate) tmp_u: *mut u8,                  // or used for tmp rescaling
     pub(crate) tmp_v: *mut u8,
 
     pub(crate) last_y: c_int, // coordinate of the line that was last output
-    // pub(crate) options: *const WebPDecoderOptions, // if not NULL, use alt decoding features
-    _options: *const c_void, // TODO
-
-    // pub(crate) scaler_y: *mut WebPRescaler, // rescalers
-    // pub(crate) scaler_u: *mut WebPRescaler,
-    // pub(crate) scaler_v: *mut WebPRescaler,
-    // pub(crate) scaler_a: *mut WebPRescaler,
-    _scaler_y: *mut c_void,         // TODO
-    _scaler_u: *mut c_void,         // TODO
-    _scaler_v: *mut c_void,         // TODO
-    _scaler_a: *mut c_void,         // TODO
+    pub(crate) options: *const sys::WebPDecoderOptions, // if not NULL, use alt decoding features
+
+    pub(crate) scaler_y: *mut WebPRescaler, // rescalers
+    pub(crate) scaler_u: *mut WebPRescaler,
+    pub(crate) scaler_v: *mut WebPRescaler,
+    pub(crate) scaler_a: *mut WebPRescaler,
     pub(crate) memory: *mut c_void, // overall scratch memory for the output work.
 
     pub(crate) emit: OutputFunc,              // output RGB or YUV samples
     pub(crate) emit_alpha: OutputAlphaFunc,   // output alpha channel
     pub(crate) emit_alpha_row: OutputRowFunc, // output one line of rescaled alpha values
 }
+
+//------------------------------------------------------------------------------
+// Header parsing helpers
+
+// Structure storing a description of the RIFF headers.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct WebPHeaderStructure {
+    pub(crate) data: *const u8,        // input buffer
+    pub(crate) data_size: usize,       // input buffer size
+    pub(crate) have_all_data: c_int,   // true if all data is known to be available
+    pub(crate) offset: usize,          // offset to main data chunk (VP8 or VP8L)
+    pub(crate) alpha_data: *const u8,  // points to alpha chunk (if present)
+    pub(crate) alpha_data_size: usize, // alpha chunk size
+    pub(crate) compressed_size: usize, // VP8/VP8L compressed data size
+    pub(crate) riff_size: usize,       // size of the riff payload (or 0 if absent)
+    pub(crate) is_lossless: c_int,     // true if a VP8L chunk is present
+}
@@ -1,9 +1,15 @@
+use std::mem;
 use std::os::raw::*;
 
+use demux::Demuxer;
+use exif::Orientation;
 use sys;
+use utils::rescaler_utils::WebPRescalerGetScaledDimensions;
+use utils::utils::WebPSafeMalloc;
 use WebpBox;
 
 #[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub enum VP8StatusCode {
     VP8_STATUS_OK = 0,
@@ -18,6 +24,60 @@ pub enum VP8StatusCode {
 
 pub const WEBP_DECODER_ABI_VERSION: u16 = 0x0208;
 
+// Colorspaces
+// Note: the naming describes the byte-ordering of packed samples in memory.
+// For instance, MODE_BGRA relates to samples ordered as B,G,R,A,B,G,R,A,...
+// Non-capital names (e.g.:MODE_Argb) relates to pre-multiplied RGB channels.
+#[allow(non_camel_case_types)]
+pub(crate) type WEBP_CSP_MODE = c_int;
+
+#[allow(dead_code)]
+#[allow(non_upper_case_globals)]
+mod csp_mode {
+    use super::WEBP_CSP_MODE;
+
+    pub(crate) const MODE_RGB: WEBP_CSP_MODE = 0;
+    pub(crate) const MODE_RGBA: WEBP_CSP_MODE = 1;
+    pub(crate) const MODE_BGR: WEBP_CSP_MODE = 2;
+    pub(crate) const MODE_BGRA: WEBP_CSP_MODE = 3;
+    pub(crate) const MODE_ARGB: WEBP_CSP_MODE = 4;
+    pub(crate) const MODE_RGBA_4444: WEBP_CSP_MODE = 5;
+    pub(crate) const MODE_RGB_565: WEBP_CSP_MODE = 6;
+    // RGB-premultiplied transparent modes (alpha value is preserved)
+    pub(crate) const MODE_rgbA: WEBP_CSP_MODE = 7;
+    pub(crate) const MODE_bgrA: WEBP_CSP_MODE = 8;
+    pub(crate) const MODE_Argb: WEBP_CSP_MODE = 9;
+    pub(crate) const MODE_rgbA_4444: WEBP_CSP_MODE = 10;
+    // YUV modes must come after RGB ones.
+    pub(crate) const MODE_YUV: WEBP_CSP_MODE = 11;
+    pub(crate) const MODE_YUVA: WEBP_CSP_MODE = 12; // yuv 4:2:0
+    pub(crate) const MODE_LAST: WEBP_CSP_MODE = 13;
+}
+pub(crate) use self::csp_mode::*;
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WebPIsPremultipliedMode(mode: WEBP_CSP_MODE) -> c_int {
+    (mode == MODE_rgbA || mode == MODE_bgrA || mode == MODE_Argb || mode == MODE_rgbA_4444) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WebPIsAlphaMode(mode: WEBP_CSP_MODE) -> c_int {
+    (mode == MODE_RGBA
+        || mode == MODE_BGRA
+        || mode == MODE_ARGB
+        || mode == MODE_RGBA_4444
+        || mode == MODE_YUVA
+        || WebPIsPremultipliedMode(mode) != 0) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WebPIsRGBMode(mode: WEBP_CSP_MODE) -> c_int {
+    (mode < MODE_YUV) as c_int
+}
+
 pub fn decoder_version() -> i32 {
     (unsafe { sys::WebPGetDecoderVersion() }) as i32
 }
@@ -33,6 +93,232 @@ pub fn get_info(data: &[u8]) -> Option<(u32, u32)> {
     }
 }
 
+// Features gathered from the bitstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitstreamFeatures {
+    pub width: u32,          // Width in pixels, as read from the bitstream.
+    pub height: u32,         // Height in pixels, as read from the bitstream.
+    pub has_alpha: bool,     // True if the bitstream contains an alpha channel.
+    pub has_animation: bool, // True if the bitstream is an animation.
+    pub format: u32,         // 0 = undefined (/mixed), 1 = lossy, 2 = lossless
+}
+
+pub fn get_features(data: &[u8]) -> Option<BitstreamFeatures> {
+    let mut features: sys::WebPBitstreamFeatures = unsafe { mem::zeroed() };
+    let status = unsafe {
+        sys::WebPGetFeaturesInternal(
+            data.as_ptr(),
+            data.len(),
+            &mut features,
+            WEBP_DECODER_ABI_VERSION as c_int,
+        )
+    };
+    if status == VP8StatusCode::VP8_STATUS_OK as c_int {
+        Some(BitstreamFeatures {
+            width: features.width as u32,
+            height: features.height as u32,
+            has_alpha: features.has_alpha != 0,
+            has_animation: features.has_animation != 0,
+            format: features.format as u32,
+        })
+    } else {
+        None
+    }
+}
+
+/// Returns the embedded ICC color profile (the `ICCP` chunk), if any.
+pub fn get_icc_profile(data: &[u8]) -> Option<&[u8]> {
+    Demuxer::new(data)?.chunk(b"ICCP")
+}
+
+/// Returns the raw EXIF metadata (the `EXIF` chunk), if any.
+pub fn get_exif(data: &[u8]) -> Option<&[u8]> {
+    Demuxer::new(data)?.chunk(b"EXIF")
+}
+
+/// Returns the orientation recorded in the EXIF metadata, if any.
+pub fn get_orientation(data: &[u8]) -> Option<Orientation> {
+    get_exif(data).and_then(Orientation::from_exif)
+}
+
+/// Decoding options, mirroring `WebPDecoderOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecoderOptions {
+    pub bypass_filtering: bool,    // if true, skip the in-loop filtering
+    pub no_fancy_upsampling: bool, // if true, use faster pointwise upsampler
+    pub use_cropping: bool,        // if true, cropping is applied _first_
+    pub crop_left: u32,            // top-left position for cropping.
+    pub crop_top: u32,             // Will be snapped to even values.
+    pub crop_width: u32,           // dimension of the cropping area
+    pub crop_height: u32,
+    pub use_scaling: bool, // if true, scaling is applied _afterward_
+    pub scaled_width: u32, // final resolution
+    pub scaled_height: u32,
+    pub use_threads: bool,             // if true, use multi-threaded decoding
+    pub dithering_strength: i32,       // dithering strength (0=Off, 100=full)
+    pub flip: bool,                    // flip output vertically
+    pub alpha_dithering_strength: i32, // alpha dithering strength in [0..100]
+    // If true, rotate/flip the output according to the EXIF orientation, after
+    // all of the above.
+    pub apply_orientation: bool,
+}
+
+fn init_decoder_config() -> sys::WebPDecoderConfig {
+    let mut config: sys::WebPDecoderConfig = unsafe { mem::zeroed() };
+    let ok = unsafe {
+        sys::WebPInitDecoderConfigInternal(&mut config, WEBP_DECODER_ABI_VERSION as c_int)
+    };
+    assert!(ok != 0);
+    config
+}
+
+impl DecoderOptions {
+    fn to_raw(self) -> sys::WebPDecoderOptions {
+        let mut raw = init_decoder_config().options;
+        raw.bypass_filtering = self.bypass_filtering as c_int;
+        raw.no_fancy_upsampling = self.no_fancy_upsampling as c_int;
+        raw.use_cropping = self.use_cropping as c_int;
+        raw.crop_left = self.crop_left as c_int;
+        raw.crop_top = self.crop_top as c_int;
+        raw.crop_width = self.crop_width as c_int;
+        raw.crop_height = self.crop_height as c_int;
+        raw.use_scaling = self.use_scaling as c_int;
+        raw.scaled_width = self.scaled_width as c_int;
+        raw.scaled_height = self.scaled_height as c_int;
+        raw.use_threads = self.use_threads as c_int;
+        raw.dithering_strength = self.dithering_strength as c_int;
+        raw.flip = self.flip as c_int;
+        raw.alpha_dithering_strength = self.alpha_dithering_strength as c_int;
+        raw
+    }
+}
+
+impl Default for DecoderOptions {
+    // Same as WebPInitDecoderConfig(), with no orientation applied.
+    fn default() -> Self {
+        let raw = init_decoder_config().options;
+        Self {
+            bypass_filtering: raw.bypass_filtering != 0,
+            no_fancy_upsampling: raw.no_fancy_upsampling != 0,
+            use_cropping: raw.use_cropping != 0,
+            crop_left: raw.crop_left as u32,
+            crop_top: raw.crop_top as u32,
+            crop_width: raw.crop_width as u32,
+            crop_height: raw.crop_height as u32,
+            use_scaling: raw.use_scaling != 0,
+            scaled_width: raw.scaled_width as u32,
+            scaled_height: raw.scaled_height as u32,
+            use_threads: raw.use_threads != 0,
+            dithering_strength: raw.dithering_strength,
+            flip: raw.flip != 0,
+            alpha_dithering_strength: raw.alpha_dithering_strength,
+            apply_orientation: false,
+        }
+    }
+}
+
+/// Same as `decode_rgba`, with advanced decoding options.
+pub fn decode_rgba_with_options(
+    data: &[u8],
+    options: &DecoderOptions,
+) -> Option<(u32, u32, WebpBox<[u8]>)> {
+    // MODE_RGBA
+    const COLORSPACE: c_int = 1;
+
+    let mut config = init_decoder_config();
+    config.options = options.to_raw();
+    config.output.colorspace = COLORSPACE;
+    let (width, height) = get_info(data)?;
+    let (width, height) = output_dimensions(width, height, &config.options)?;
+    let stride = width as usize * 4;
+    let len = stride * height as usize;
+    let mut buf = unsafe {
+        let ptr = WebPSafeMalloc(height as u64, stride) as *mut u8;
+        if ptr.is_null() {
+            return None;
+        }
+        WebpBox::from_raw_parts(ptr, len)
+    };
+    if decode_into_config(data, &mut config, &mut buf, stride)? != (width, height) {
+        return None;
+    }
+    let (width, height) = match get_orientation(data) {
+        Some(orientation) if options.apply_orientation => {
+            orientation.apply_rgba(width, height, &mut buf)
+        }
+        _ => (width, height),
+    };
+    Some((width, height, buf))
+}
+
+// Dimensions of the image decoded from a 'width' x 'height' file with
+// 'options', as computed by WebPAllocateDecBuffer().
+fn output_dimensions(
+    width: u32,
+    height: u32,
+    options: &sys::WebPDecoderOptions,
+) -> Option<(u32, u32)> {
+    let (mut width, mut height) = (width as c_int, height as c_int);
+    if options.use_cropping != 0 {
+        let (cw, ch) = (options.crop_width, options.crop_height);
+        let x = options.crop_left & !1;
+        let y = options.crop_top & !1;
+        if x < 0 || y < 0 || cw <= 0 || ch <= 0 || x > width - cw || y > height - ch {
+            return None;
+        }
+        width = cw;
+        height = ch;
+    }
+    if options.use_scaling != 0 {
+        let mut scaled_width = options.scaled_width;
+        let mut scaled_height = options.scaled_height;
+        let ok = unsafe {
+            WebPRescalerGetScaledDimensions(width, height, &mut scaled_width, &mut scaled_height)
+        };
+        if ok == 0 {
+            return None;
+        }
+        width = scaled_width;
+        height = scaled_height;
+    }
+    Some((width as u32, height as u32))
+}
+
+// Decodes 'data' in the RGB(A) 'colorspace' directly into 'buf', whose rows
+// are 'stride' bytes apart, and returns the dimensions of the image.
+pub(crate) fn decode_into(
+    data: &[u8],
+    colorspace: WEBP_CSP_MODE,
+    use_threads: bool,
+    buf: &mut [u8],
+    stride: usize,
+) -> Option<(u32, u32)> {
+    let mut config = init_decoder_config();
+    config.options.use_threads = use_threads as c_int;
+    config.output.colorspace = colorspace;
+    decode_into_config(data, &mut config, buf, stride)
+}
+
+// Same as decode_into(), with the options and colorspace of 'config'.
+fn decode_into_config(
+    data: &[u8],
+    config: &mut sys::WebPDecoderConfig,
+    buf: &mut [u8],
+    stride: usize,
+) -> Option<(u32, u32)> {
+    config.output.is_external_memory = 1;
+    config.output.u.RGBA = sys::WebPRGBABuffer {
+        rgba: buf.as_mut_ptr(),
+        stride: stride as c_int,
+        size: buf.len(),
+    };
+    let status = unsafe { sys::WebPDecode(data.as_ptr(), data.len(), config) };
+    if status != VP8StatusCode::VP8_STATUS_OK as c_int {
+        return None;
+    }
+    Some((config.output.width as u32, config.output.height as u32))
+}
+
 macro_rules! wrap_decoder {
     ($decoder:ident, $c_decoder:ident, $elems:expr) => {
         pub fn $decoder(data: &[u8]) -> Option<(u32, u32, WebpBox<[u8]>)> {
@@ -65,6 +351,8 @@ mod tests {
 
     use std::iter;
 
+    use test_util::{exif_with_orientation, with_chunks};
+
     fn example_webp() -> Vec<u8> {
         include_bytes!("../libwebp-1.0.0/examples/test.webp").to_vec()
     }
@@ -141,6 +429,131 @@ mod tests {
         assert_eq!(get_info(b""), None);
     }
 
+    #[test]
+    fn test_get_features() {
+        let features = get_features(&example_webp()).unwrap();
+        assert_eq!((features.width, features.height), (128, 128));
+        assert!(!features.has_alpha);
+        assert!(!features.has_animation);
+        assert_eq!(features.format, 1);
+
+        let img = include_bytes!("../examples/rust-logo-256x256.webp");
+        let features = get_features(img).unwrap();
+        assert_eq!((features.width, features.height), (256, 256));
+        assert!(features.has_alpha);
+
+        assert_eq!(get_features(b""), None);
+    }
+
+    #[test]
+    fn test_get_icc_profile() {
+        assert_eq!(get_icc_profile(&example_webp()), None);
+
+        let profile = b"fake icc profile";
+        let img = with_chunks(&example_webp(), &[(b"ICCP", profile)]);
+        assert_eq!(get_icc_profile(&img), Some(&profile[..]));
+        let (width, height, _) = decode_rgba(&img).unwrap();
+        assert_eq!((width, height), (128, 128));
+
+        assert_eq!(get_icc_profile(b""), None);
+    }
+
+    #[test]
+    fn test_get_orientation() {
+        assert_eq!(get_orientation(&example_webp()), None);
+
+        let exif = exif_with_orientation(6, false);
+        let img = with_chunks(&example_webp(), &[(b"EXIF", &exif)]);
+        assert_eq!(get_exif(&img), Some(&exif[..]));
+        assert_eq!(get_orientation(&img), Some(Orientation::RightTop));
+    }
+
+    #[test]
+    fn test_decode_rgba_with_options() {
+        let (_, _, reference) = decode_rgba(&example_webp()).unwrap();
+        let options = DecoderOptions::default();
+        let (width, height, buf) = decode_rgba_with_options(&example_webp(), &options).unwrap();
+        assert_eq!((width, height), (128, 128));
+        assert_eq!(*buf, *reference);
+
+        let options = DecoderOptions {
+            flip: true,
+            ..DecoderOptions::default()
+        };
+        let (_, _, buf) = decode_rgba_with_options(&example_webp(), &options).unwrap();
+        assert_eq!(buf[..128 * 4], reference[127 * 128 * 4..]);
+
+        let options = DecoderOptions {
+            use_cropping: true,
+            crop_left: 10,
+            crop_top: 20,
+            crop_width: 30,
+            crop_height: 40,
+            ..DecoderOptions::default()
+        };
+        let (width, height, _) = decode_rgba_with_options(&example_webp(), &options).unwrap();
+        assert_eq!((width, height), (30, 40));
+
+        // Rotated by 90 degrees clockwise: the first row of the output is
+        // the first column of the stored image, bottom to top.
+        let exif = exif_with_orientation(6, true);
+        let img = with_chunks(&example_webp(), &[(b"EXIF", &exif)]);
+        let options = DecoderOptions {
+            apply_orientation: true,
+            ..DecoderOptions::default()
+        };
+        let (width, height, buf) = decode_rgba_with_options(&img, &options).unwrap();
+        assert_eq!((width, height), (128, 128));
+        for x in 0..128 {
+            let src = (127 - x) * 128 * 4;
+            assert_eq!(buf[x * 4..x * 4 + 4], reference[src..src + 4]);
+        }
+
+        // Non-square: the dimensions are swapped, 6 rotates clockwise and 8
+        // counterclockwise.
+        let crop = DecoderOptions {
+            use_cropping: true,
+            crop_left: 10,
+            crop_top: 20,
+            crop_width: 30,
+            crop_height: 40,
+            ..DecoderOptions::default()
+        };
+        let (_, _, cropped) = decode_rgba_with_options(&example_webp(), &crop).unwrap();
+        let pixel = |x: usize, y: usize| &cropped[(y * 30 + x) * 4..(y * 30 + x) * 4 + 4];
+        for &orientation in &[6, 8] {
+            let exif = exif_with_orientation(orientation, false);
+            let img = with_chunks(&example_webp(), &[(b"EXIF", &exif)]);
+            let options = DecoderOptions {
+                apply_orientation: true,
+                ..crop
+            };
+            let (width, height, buf) = decode_rgba_with_options(&img, &options).unwrap();
+            assert_eq!((width, height), (40, 30));
+            assert_eq!(buf.len(), 40 * 30 * 4);
+            for y in 0..30 {
+                for x in 0..40 {
+                    let expected = if orientation == 6 {
+                        pixel(y, 39 - x)
+                    } else {
+                        pixel(29 - y, x)
+                    };
+                    let pos = (y * 40 + x) * 4;
+                    assert_eq!(
+                        buf[pos..pos + 4],
+                        *expected,
+                        "{} ({}, {})",
+                        orientation,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+
+        assert!(decode_rgba_with_options(b"", &DecoderOptions::default()).is_none());
+    }
+
     #[test]
     fn test_decode_rgba() {
         let (width, height, buf) = decode_rgba(&example_webp()).unwrap();
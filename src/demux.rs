@@ -0,0 +1,826 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+//  WebP container demux.
+//
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use decode::get_features;
+use format_constants::*;
+
+// Dispose method (animation only). Indicates how the area used by the current
+// frame is to be treated before rendering the next frame on the canvas.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebPMuxAnimDispose {
+    WEBP_MUX_DISPOSE_NONE,       // Do not dispose.
+    WEBP_MUX_DISPOSE_BACKGROUND, // Dispose to background color.
+}
+
+// Blend operation (animation only). Indicates how transparent pixels of the
+// current frame are blended with those of the previous canvas.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebPMuxAnimBlend {
+    WEBP_MUX_BLEND,    // Blend.
+    WEBP_MUX_NO_BLEND, // Do not blend.
+}
+
+/// A frame of a WebP file: the whole image of a still file, or the contents
+/// of an `ANMF` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub frame_num: u32, // 1-based index of the frame.
+    pub x_offset: u32,
+    pub y_offset: u32,
+    pub width: u32,
+    pub height: u32,
+    pub duration: u32, // in milliseconds.
+    pub dispose_method: WebPMuxAnimDispose,
+    pub blend_method: WebPMuxAnimBlend,
+    pub has_alpha: bool,
+    // The image bearing chunks ('ALPH' + 'VP8 ', or 'VP8L'), which can be
+    // passed as-is to the decode_* functions.
+    pub payload: &'a [u8],
+}
+
+/// A non-image chunk (`ICCP`, `EXIF`, `XMP ` or an unknown one).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub fourcc: [u8; 4],
+    pub payload: &'a [u8], // unpadded payload.
+}
+
+/// Parsed WebP container, borrowing from the input bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Demuxer<'a> {
+    pub is_ext_format: bool, // true if the file has a 'VP8X' chunk.
+    pub feature_flags: u32,
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    pub loop_count: u32,
+    pub bgcolor: u32,
+    pub frames: Vec<Frame<'a>>,
+    pub chunks: Vec<Chunk<'a>>, // non-image chunks, in file order.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseStatus {
+    Ok,
+    NeedMoreData,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChunkData {
+    offset: usize,
+    size: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FrameData {
+    x_offset: i32,
+    y_offset: i32,
+    width: i32,
+    height: i32,
+    has_alpha: bool,
+    duration: i32,
+    dispose_method: WebPMuxAnimDispose,
+    blend_method: WebPMuxAnimBlend,
+    frame_num: i32,
+    img_components: [ChunkData; 2], // 0=VP8{,L} 1=ALPH
+}
+
+impl Default for FrameData {
+    fn default() -> Self {
+        Self {
+            x_offset: 0,
+            y_offset: 0,
+            width: 0,
+            height: 0,
+            has_alpha: false,
+            duration: 0,
+            dispose_method: WebPMuxAnimDispose::WEBP_MUX_DISPOSE_NONE,
+            blend_method: WebPMuxAnimBlend::WEBP_MUX_BLEND,
+            frame_num: 0,
+            img_components: [ChunkData::default(); 2],
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// MemBuffer
+
+struct MemBuffer<'a> {
+    start: usize,    // start location of the data
+    riff_end: usize, // riff chunk end location
+    buf: &'a [u8],
+}
+
+impl<'a> MemBuffer<'a> {
+    // Return the remaining data size available in 'mem'.
+    fn data_size(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    // Return true if 'size' exceeds the end of the RIFF chunk.
+    fn size_is_invalid(&self, size: usize) -> bool {
+        size > self.riff_end - self.start
+    }
+
+    fn skip(&mut self, size: usize) {
+        self.start += size;
+    }
+
+    fn rewind(&mut self, size: usize) {
+        self.start -= size;
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let byte = self.buf[self.start];
+        self.skip(1);
+        byte
+    }
+
+    fn read_le16s(&mut self) -> i32 {
+        let val = get_le16(&self.buf[self.start..]) as i32;
+        self.skip(2);
+        val
+    }
+
+    fn read_le24s(&mut self) -> i32 {
+        let val = get_le24(&self.buf[self.start..]) as i32;
+        self.skip(3);
+        val
+    }
+
+    fn read_le32(&mut self) -> u32 {
+        let val = get_le32(&self.buf[self.start..]);
+        self.skip(4);
+        val
+    }
+}
+
+fn get_le16(data: &[u8]) -> u32 {
+    data[0] as u32 | (data[1] as u32) << 8
+}
+
+fn get_le24(data: &[u8]) -> u32 {
+    get_le16(data) | (data[2] as u32) << 16
+}
+
+fn get_le32(data: &[u8]) -> u32 {
+    get_le16(data) | get_le16(&data[2..]) << 16
+}
+
+fn fourcc(data: &[u8]) -> [u8; 4] {
+    [data[0], data[1], data[2], data[3]]
+}
+
+// -----------------------------------------------------------------------------
+// Secondary chunk parsing
+
+struct DemuxState<'a> {
+    mem: MemBuffer<'a>,
+    is_ext_format: bool,
+    feature_flags: u32,
+    canvas_width: i32,
+    canvas_height: i32,
+    loop_count: i32,
+    bgcolor: u32,
+    frames: Vec<FrameData>,
+    chunks: Vec<ChunkData>,
+}
+
+fn set_frame_info(
+    start_offset: usize,
+    size: usize,
+    frame_num: i32,
+    data: &[u8],
+    frame: &mut FrameData,
+) -> ParseStatus {
+    // Extract the bitstream features.
+    let features = match get_features(data) {
+        Some(features) => features,
+        None => return ParseStatus::Error,
+    };
+    frame.img_components[0].offset = start_offset;
+    frame.img_components[0].size = size;
+    frame.width = features.width as i32;
+    frame.height = features.height as i32;
+    frame.has_alpha |= features.has_alpha;
+    frame.frame_num = frame_num;
+    ParseStatus::Ok
+}
+
+// Store image bearing chunks to 'frame'. 'min_size' is an optional size
+// requirement, it may be zero.
+fn store_frame(
+    frame_num: i32,
+    min_size: usize,
+    mem: &mut MemBuffer,
+    frame: &mut FrameData,
+) -> ParseStatus {
+    let mut alpha_chunks = 0;
+    let mut image_chunks = 0;
+
+    if mem.data_size() < CHUNK_HEADER_SIZE || mem.data_size() < min_size {
+        return ParseStatus::Error;
+    }
+
+    loop {
+        let chunk_start_offset = mem.start;
+        let fourcc = fourcc(&mem.buf[mem.start..]);
+        mem.skip(TAG_SIZE);
+        let payload_size = mem.read_le32();
+        let payload_size_padded = payload_size as usize + (payload_size & 1) as usize;
+        let chunk_size = CHUNK_HEADER_SIZE + payload_size_padded;
+
+        if payload_size > MAX_CHUNK_PAYLOAD {
+            return ParseStatus::Error;
+        }
+        if mem.size_is_invalid(payload_size_padded) {
+            return ParseStatus::Error;
+        }
+
+        let done = match &fourcc {
+            b"ALPH" if alpha_chunks == 0 => {
+                alpha_chunks += 1;
+                frame.img_components[1].offset = chunk_start_offset;
+                frame.img_components[1].size = chunk_size;
+                frame.has_alpha = true;
+                frame.frame_num = frame_num;
+                mem.skip(payload_size_padded);
+                false
+            }
+            b"VP8L" if alpha_chunks > 0 => {
+                return ParseStatus::Error; // VP8L has its own alpha
+            }
+            b"VP8L" | b"VP8 " if image_chunks == 0 => {
+                image_chunks += 1;
+                let data = &mem.buf[chunk_start_offset..chunk_start_offset + chunk_size];
+                if set_frame_info(chunk_start_offset, chunk_size, frame_num, data, frame)
+                    != ParseStatus::Ok
+                {
+                    return ParseStatus::Error;
+                }
+                mem.skip(payload_size_padded);
+                false
+            }
+            _ => {
+                // Restore fourcc/size when moving up one level in parsing.
+                mem.rewind(CHUNK_HEADER_SIZE);
+                true
+            }
+        };
+
+        if done || mem.start == mem.riff_end {
+            break;
+        } else if mem.data_size() < CHUNK_HEADER_SIZE {
+            return ParseStatus::Error;
+        }
+    }
+
+    ParseStatus::Ok
+}
+
+impl<'a> DemuxState<'a> {
+    // Parse a 'ANMF' chunk and any image bearing chunks that immediately follow.
+    // 'frame_chunk_size' is the previously validated, padded chunk size.
+    fn parse_animation_frame(&mut self, frame_chunk_size: usize) -> ParseStatus {
+        let is_animation = (self.feature_flags & ANIMATION_FLAG) != 0;
+        if self.mem.size_is_invalid(ANMF_CHUNK_SIZE) || frame_chunk_size < ANMF_CHUNK_SIZE {
+            return ParseStatus::Error;
+        }
+        if self.mem.data_size() < ANMF_CHUNK_SIZE {
+            return ParseStatus::Error;
+        }
+        let anmf_payload_size = frame_chunk_size - ANMF_CHUNK_SIZE;
+
+        let mut frame = FrameData::default();
+        let mem = &mut self.mem;
+        frame.x_offset = 2 * mem.read_le24s();
+        frame.y_offset = 2 * mem.read_le24s();
+        frame.width = 1 + mem.read_le24s();
+        frame.height = 1 + mem.read_le24s();
+        frame.duration = mem.read_le24s();
+        let bits = mem.read_byte();
+        frame.dispose_method = if (bits & 1) != 0 {
+            WebPMuxAnimDispose::WEBP_MUX_DISPOSE_BACKGROUND
+        } else {
+            WebPMuxAnimDispose::WEBP_MUX_DISPOSE_NONE
+        };
+        frame.blend_method = if (bits & 2) != 0 {
+            WebPMuxAnimBlend::WEBP_MUX_NO_BLEND
+        } else {
+            WebPMuxAnimBlend::WEBP_MUX_BLEND
+        };
+        if frame.width as u64 * frame.height as u64 >= MAX_IMAGE_AREA {
+            return ParseStatus::Error;
+        }
+
+        // Store a frame only if the animation flag is set there is some data for
+        // this frame is available.
+        let status = store_frame(
+            self.frames.len() as i32 + 1,
+            anmf_payload_size,
+            mem,
+            &mut frame,
+        );
+        if status != ParseStatus::Error && is_animation && frame.frame_num > 0 {
+            self.frames.push(frame);
+        }
+        status
+    }
+
+    fn parse_single_image(&mut self) -> ParseStatus {
+        if !self.frames.is_empty() {
+            return ParseStatus::Error;
+        }
+        if self.mem.size_is_invalid(CHUNK_HEADER_SIZE) {
+            return ParseStatus::Error;
+        }
+
+        let mut frame = FrameData::default();
+        let status = store_frame(1, 0, &mut self.mem, &mut frame);
+        if status != ParseStatus::Error {
+            let has_alpha = (self.feature_flags & ALPHA_FLAG) != 0;
+            // Clear any alpha when the alpha flag is missing.
+            if !has_alpha && frame.img_components[1].size > 0 {
+                frame.img_components[1] = ChunkData::default();
+                frame.has_alpha = false;
+            }
+
+            // Use the frame width/height as the canvas values for non-vp8x files.
+            // Also, set ALPHA_FLAG if this is a lossless image with alpha.
+            if !self.is_ext_format && frame.width > 0 && frame.height > 0 {
+                self.canvas_width = frame.width;
+                self.canvas_height = frame.height;
+                self.feature_flags |= if frame.has_alpha { ALPHA_FLAG } else { 0 };
+            }
+            self.frames.push(frame);
+        }
+        status
+    }
+
+    fn parse_vp8x_chunks(&mut self) -> ParseStatus {
+        let is_animation = (self.feature_flags & ANIMATION_FLAG) != 0;
+        let mut anim_chunks = 0;
+        let mut status = ParseStatus::Ok;
+
+        loop {
+            let mut store_chunk = true;
+            let chunk_start_offset = self.mem.start;
+            let fourcc = fourcc(&self.mem.buf[self.mem.start..]);
+            self.mem.skip(TAG_SIZE);
+            let chunk_size = self.mem.read_le32();
+            let chunk_size_padded = chunk_size as usize + (chunk_size & 1) as usize;
+
+            if chunk_size > MAX_CHUNK_PAYLOAD {
+                return ParseStatus::Error;
+            }
+            if self.mem.size_is_invalid(chunk_size_padded) {
+                return ParseStatus::Error;
+            }
+
+            let skip = match &fourcc {
+                b"VP8X" => {
+                    return ParseStatus::Error;
+                }
+                b"ALPH" | b"VP8 " | b"VP8L" => {
+                    // check that this isn't an animation (all frames should be in an ANMF).
+                    if anim_chunks > 0 || is_animation {
+                        return ParseStatus::Error;
+                    }
+
+                    self.mem.rewind(CHUNK_HEADER_SIZE);
+                    status = self.parse_single_image();
+                    false
+                }
+                b"ANIM" => {
+                    if chunk_size_padded < ANIM_CHUNK_SIZE {
+                        return ParseStatus::Error;
+                    }
+
+                    if anim_chunks == 0 {
+                        anim_chunks += 1;
+                        self.bgcolor = self.mem.read_le32();
+                        self.loop_count = self.mem.read_le16s();
+                        self.mem.skip(chunk_size_padded - ANIM_CHUNK_SIZE);
+                        status = ParseStatus::Ok;
+                        false
+                    } else {
+                        store_chunk = false;
+                        true
+                    }
+                }
+                b"ANMF" => {
+                    if anim_chunks == 0 {
+                        return ParseStatus::Error; // 'ANIM' precedes frames.
+                    }
+                    status = self.parse_animation_frame(chunk_size_padded);
+                    false
+                }
+                b"ICCP" => {
+                    store_chunk = (self.feature_flags & ICCP_FLAG) != 0;
+                    true
+                }
+                b"EXIF" => {
+                    store_chunk = (self.feature_flags & EXIF_FLAG) != 0;
+                    true
+                }
+                b"XMP " => {
+                    store_chunk = (self.feature_flags & XMP_FLAG) != 0;
+                    true
+                }
+                _ => true,
+            };
+            if skip {
+                if store_chunk {
+                    // Store only the chunk header and unpadded size as only the payload
+                    // will be returned to the user.
+                    self.chunks.push(ChunkData {
+                        offset: chunk_start_offset,
+                        size: CHUNK_HEADER_SIZE + chunk_size as usize,
+                    });
+                }
+                self.mem.skip(chunk_size_padded);
+                status = ParseStatus::Ok;
+            }
+
+            if status != ParseStatus::Ok || self.mem.start == self.mem.riff_end {
+                break;
+            } else if self.mem.data_size() < CHUNK_HEADER_SIZE {
+                status = ParseStatus::Error;
+                break;
+            }
+        }
+
+        status
+    }
+
+    fn parse_vp8x(&mut self) -> ParseStatus {
+        if self.mem.data_size() < CHUNK_HEADER_SIZE {
+            return ParseStatus::Error;
+        }
+
+        self.is_ext_format = true;
+        self.mem.skip(TAG_SIZE); // VP8X
+        let vp8x_size = self.mem.read_le32();
+        if vp8x_size > MAX_CHUNK_PAYLOAD {
+            return ParseStatus::Error;
+        }
+        if (vp8x_size as usize) < VP8X_CHUNK_SIZE {
+            return ParseStatus::Error;
+        }
+        let vp8x_size = vp8x_size as usize + (vp8x_size & 1) as usize;
+        if self.mem.size_is_invalid(vp8x_size) {
+            return ParseStatus::Error;
+        }
+        if self.mem.data_size() < vp8x_size {
+            return ParseStatus::Error;
+        }
+
+        self.feature_flags = self.mem.read_byte() as u32;
+        self.mem.skip(3); // Reserved.
+        self.canvas_width = 1 + self.mem.read_le24s();
+        self.canvas_height = 1 + self.mem.read_le24s();
+        if self.canvas_width as u64 * self.canvas_height as u64 >= MAX_IMAGE_AREA {
+            return ParseStatus::Error; // image final dimension is too large
+        }
+        self.mem.skip(vp8x_size - VP8X_CHUNK_SIZE); // skip any trailing data.
+
+        if self.mem.size_is_invalid(CHUNK_HEADER_SIZE) {
+            return ParseStatus::Error;
+        }
+        if self.mem.data_size() < CHUNK_HEADER_SIZE {
+            return ParseStatus::Error;
+        }
+
+        self.parse_vp8x_chunks()
+    }
+
+    // -------------------------------------------------------------------------
+    // Format validation
+
+    fn is_valid_simple_format(&self) -> bool {
+        if self.canvas_width <= 0 || self.canvas_height <= 0 {
+            return false;
+        }
+        match self.frames.first() {
+            Some(frame) => frame.width > 0 && frame.height > 0,
+            None => false,
+        }
+    }
+
+    fn is_valid_extended_format(&self) -> bool {
+        let is_animation = (self.feature_flags & ANIMATION_FLAG) != 0;
+
+        if self.canvas_width <= 0 || self.canvas_height <= 0 {
+            return false;
+        }
+        if self.loop_count < 0 {
+            return false;
+        }
+        if self.frames.is_empty() {
+            return false;
+        }
+        if (self.feature_flags & !ALL_VALID_FLAGS) != 0 {
+            return false; // invalid bitstream
+        }
+
+        for f in &self.frames {
+            let image = &f.img_components[0];
+            let alpha = &f.img_components[1];
+
+            if !is_animation && f.frame_num > 1 {
+                return false;
+            }
+
+            // A frame without image data is incomplete, and there shouldn't be
+            // a partial frame in a complete file.
+            if image.size == 0 {
+                return false;
+            }
+            // Ensure alpha precedes image bitstream.
+            if alpha.size > 0 && alpha.offset > image.offset {
+                return false;
+            }
+            if f.width <= 0 || f.height <= 0 {
+                return false;
+            }
+
+            if !check_frame_bounds(f, !is_animation, self.canvas_width, self.canvas_height) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn into_demuxer(self) -> Demuxer<'a> {
+        let buf = self.mem.buf;
+        let frames = self
+            .frames
+            .iter()
+            .map(|f| Frame {
+                frame_num: f.frame_num as u32,
+                x_offset: f.x_offset as u32,
+                y_offset: f.y_offset as u32,
+                width: f.width as u32,
+                height: f.height as u32,
+                duration: f.duration as u32,
+                dispose_method: f.dispose_method,
+                blend_method: f.blend_method,
+                has_alpha: f.has_alpha,
+                payload: get_frame_payload(buf, f),
+            })
+            .collect();
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|c| Chunk {
+                fourcc: fourcc(&buf[c.offset..]),
+                payload: &buf[c.offset + CHUNK_HEADER_SIZE..c.offset + c.size],
+            })
+            .collect();
+        Demuxer {
+            is_ext_format: self.is_ext_format,
+            feature_flags: self.feature_flags,
+            canvas_width: self.canvas_width as u32,
+            canvas_height: self.canvas_height as u32,
+            loop_count: self.loop_count as u32,
+            bgcolor: self.bgcolor,
+            frames,
+            chunks,
+        }
+    }
+}
+
+// If 'exact' is true, check that the image resolution matches the canvas.
+// If 'exact' is false, check that the x/y offsets do not exceed the canvas.
+fn check_frame_bounds(
+    frame: &FrameData,
+    exact: bool,
+    canvas_width: i32,
+    canvas_height: i32,
+) -> bool {
+    if exact {
+        if frame.x_offset != 0 || frame.y_offset != 0 {
+            return false;
+        }
+        if frame.width != canvas_width || frame.height != canvas_height {
+            return false;
+        }
+    } else {
+        if frame.x_offset < 0 || frame.y_offset < 0 {
+            return false;
+        }
+        if frame.width + frame.x_offset > canvas_width {
+            return false;
+        }
+        if frame.height + frame.y_offset > canvas_height {
+            return false;
+        }
+    }
+    true
+}
+
+fn get_frame_payload<'a>(buf: &'a [u8], frame: &FrameData) -> &'a [u8] {
+    let image = &frame.img_components[0];
+    let alpha = &frame.img_components[1];
+    let mut start_offset = image.offset;
+    let mut data_size = image.size;
+
+    // if alpha exists it precedes image, update the size allowing for
+    // intervening chunks.
+    if alpha.size > 0 {
+        let inter_size = if image.offset > 0 {
+            image.offset - (alpha.offset + alpha.size)
+        } else {
+            0
+        };
+        start_offset = alpha.offset;
+        data_size += alpha.size + inter_size;
+    }
+    &buf[start_offset..start_offset + data_size]
+}
+
+// -----------------------------------------------------------------------------
+// Demuxer object
+
+impl<'a> Demuxer<'a> {
+    /// Parses a complete WebP file, or a raw VP8/VP8L bitstream.
+    /// Returns `None` if the data is truncated or invalid.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let mut state = DemuxState {
+            mem: MemBuffer {
+                start: 0,
+                riff_end: 0,
+                buf: data,
+            },
+            is_ext_format: false,
+            feature_flags: 0,
+            canvas_width: -1,
+            canvas_height: -1,
+            loop_count: 1,
+            bgcolor: 0xFFFFFFFF, // White background by default.
+            frames: Vec::new(),
+            chunks: Vec::new(),
+        };
+
+        match state.read_header() {
+            ParseStatus::Ok => {}
+            ParseStatus::NeedMoreData => return None,
+            ParseStatus::Error => {
+                // If parsing of the webp file header fails attempt to handle a raw
+                // VP8/VP8L frame.
+                return Self::new_raw_image(data);
+            }
+        }
+        // Partial files are not supported.
+        if state.mem.buf.len() < state.mem.riff_end {
+            return None;
+        }
+        state.mem.buf = &data[..state.mem.riff_end];
+
+        let status = match &fourcc(&state.mem.buf[state.mem.start..]) {
+            b"VP8 " | b"VP8L" => {
+                if state.parse_single_image() == ParseStatus::Ok && state.is_valid_simple_format() {
+                    ParseStatus::Ok
+                } else {
+                    ParseStatus::Error
+                }
+            }
+            b"VP8X" => {
+                if state.parse_vp8x() == ParseStatus::Ok && state.is_valid_extended_format() {
+                    ParseStatus::Ok
+                } else {
+                    ParseStatus::Error
+                }
+            }
+            _ => ParseStatus::Error,
+        };
+        if status != ParseStatus::Ok {
+            return None;
+        }
+        Some(state.into_demuxer())
+    }
+
+    fn new_raw_image(data: &'a [u8]) -> Option<Self> {
+        let features = get_features(data)?;
+        let frame = Frame {
+            frame_num: 1,
+            x_offset: 0,
+            y_offset: 0,
+            width: features.width,
+            height: features.height,
+            duration: 0,
+            dispose_method: WebPMuxAnimDispose::WEBP_MUX_DISPOSE_NONE,
+            blend_method: WebPMuxAnimBlend::WEBP_MUX_BLEND,
+            has_alpha: features.has_alpha,
+            payload: data,
+        };
+        Some(Demuxer {
+            is_ext_format: false,
+            feature_flags: if features.has_alpha { ALPHA_FLAG } else { 0 },
+            canvas_width: features.width,
+            canvas_height: features.height,
+            loop_count: 1,
+            bgcolor: 0xFFFFFFFF,
+            frames: vec![frame],
+            chunks: Vec::new(),
+        })
+    }
+
+    /// Returns the 1-based `frame_num`-th frame.
+    pub fn frame(&self, frame_num: u32) -> Option<&Frame<'a>> {
+        if frame_num == 0 {
+            return None;
+        }
+        self.frames.get(frame_num as usize - 1)
+    }
+
+    /// Returns the payload of the first chunk with the given tag.
+    pub fn chunk(&self, fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+        self.chunks
+            .iter()
+            .find(|c| &c.fourcc == fourcc)
+            .map(|c| c.payload)
+    }
+}
+
+impl<'a> DemuxState<'a> {
+    fn read_header(&mut self) -> ParseStatus {
+        let mem = &mut self.mem;
+        let min_size = RIFF_HEADER_SIZE + CHUNK_HEADER_SIZE;
+
+        // Basic file level validation.
+        if mem.data_size() < min_size {
+            return ParseStatus::NeedMoreData;
+        }
+        if &mem.buf[0..4] != b"RIFF"
+            || &mem.buf[CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + 4] != b"WEBP"
+        {
+            return ParseStatus::Error;
+        }
+
+        let riff_size = get_le32(&mem.buf[TAG_SIZE..]);
+        if (riff_size as usize) < CHUNK_HEADER_SIZE {
+            return ParseStatus::Error;
+        }
+        if riff_size > MAX_CHUNK_PAYLOAD {
+            return ParseStatus::Error;
+        }
+
+        // There's no point in reading past the end of the RIFF chunk
+        mem.riff_end = riff_size as usize + CHUNK_HEADER_SIZE;
+
+        mem.skip(RIFF_HEADER_SIZE);
+        ParseStatus::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demux_simple() {
+        let data = include_bytes!("../libwebp-1.0.0/examples/test.webp");
+        let demux = Demuxer::new(data).unwrap();
+        assert!(!demux.is_ext_format);
+        assert_eq!((demux.canvas_width, demux.canvas_height), (128, 128));
+        assert_eq!(demux.frames.len(), 1);
+        let frame = demux.frame(1).unwrap();
+        assert_eq!((frame.width, frame.height), (128, 128));
+        assert!(!frame.has_alpha);
+        assert_eq!(&frame.payload[0..4], b"VP8 ");
+        assert_eq!(frame.payload.len(), data.len() - RIFF_HEADER_SIZE);
+
+        // Raw VP8 bitstream.
+        let demux = Demuxer::new(frame.payload).unwrap();
+        assert_eq!(demux.frames.len(), 1);
+
+        assert_eq!(Demuxer::new(b""), None);
+        assert_eq!(Demuxer::new(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_demux_extended() {
+        let data = include_bytes!("../examples/rust-logo-256x256.webp");
+        let demux = Demuxer::new(data).unwrap();
+        assert!(demux.is_ext_format);
+        assert_eq!(demux.feature_flags & ALPHA_FLAG, ALPHA_FLAG);
+        assert_eq!((demux.canvas_width, demux.canvas_height), (256, 256));
+        let frame = demux.frame(1).unwrap();
+        assert!(frame.has_alpha);
+        assert_eq!(&frame.payload[0..4], b"ALPH");
+        assert!(demux.frame(2).is_none());
+    }
+}
@@ -0,0 +1,632 @@
+// Copyright 2013 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Utilities for processing transparent channel.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(clippy::identity_op)]
+
+use std::os::raw::*;
+
+#[cfg(not(any(target_arch = "aarch64", feature = "pure-rust-decoder")))]
+use dsp::cpu::VP8GetCPUInfo;
+#[cfg(not(any(target_arch = "aarch64", feature = "pure-rust-decoder")))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::WEBP_SWAP_16BIT_CSP;
+
+// -----------------------------------------------------------------------------
+
+const MFIX: u32 = 24; // 24bit fixed-point arithmetic
+const HALF: u32 = (1u32 << MFIX) >> 1;
+const KINV_255: u32 = (1u32 << MFIX) / 255u32;
+
+#[allow(non_snake_case)]
+#[inline]
+fn Mult(x: u8, mult: u32) -> u32 {
+    let v = (x as u32).wrapping_mul(mult).wrapping_add(HALF) >> MFIX;
+    c_assert!(v <= 255); // <- 24bit precision is enough to ensure that.
+    v
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn GetScale(a: u32, inverse: c_int) -> u32 {
+    if inverse != 0 {
+        (255u32 << MFIX) / a
+    } else {
+        a * KINV_255
+    }
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPMultARGBRow_C(ptr: *mut u32, width: c_int, inverse: c_int) {
+    for x in 0..width as isize {
+        let argb = *ptr.offset(x);
+        if argb < 0xff000000u32 {
+            // alpha < 255
+            if argb <= 0x00ffffffu32 {
+                // alpha == 0
+                *ptr.offset(x) = 0;
+            } else {
+                let alpha = (argb >> 24) & 0xff;
+                let scale = GetScale(alpha, inverse);
+                let mut out = argb & 0xff000000u32;
+                out |= Mult((argb >> 0) as u8, scale) << 0;
+                out |= Mult((argb >> 8) as u8, scale) << 8;
+                out |= Mult((argb >> 16) as u8, scale) << 16;
+                *ptr.offset(x) = out;
+            }
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPMultRow_C(
+    ptr: *mut u8,
+    alpha: *const u8,
+    width: c_int,
+    inverse: c_int,
+) {
+    for x in 0..width as isize {
+        let a = *alpha.offset(x) as u32;
+        if a != 255 {
+            if a == 0 {
+                *ptr.offset(x) = 0;
+            } else {
+                let scale = GetScale(a, inverse);
+                *ptr.offset(x) = Mult(*ptr.offset(x), scale) as u8;
+            }
+        }
+    }
+}
+
+// Pre-Multiply or Un-Multiply (if 'inverse' is true) argb values in a row.
+#[no_mangle]
+pub static mut WebPMultARGBRow: Option<
+    unsafe extern "C" fn(ptr: *mut u32, width: c_int, inverse: c_int),
+> = None;
+// Same for a row of single values, with side alpha values.
+#[no_mangle]
+pub static mut WebPMultRow: Option<
+    unsafe extern "C" fn(ptr: *mut u8, alpha: *const u8, width: c_int, inverse: c_int),
+> = None;
+
+//------------------------------------------------------------------------------
+// Generic per-plane calls
+
+// Same a WebPMultARGBRow(), but for several rows.
+#[no_mangle]
+pub unsafe extern "C" fn WebPMultARGBRows(
+    mut ptr: *mut u8,
+    stride: c_int,
+    width: c_int,
+    num_rows: c_int,
+    inverse: c_int,
+) {
+    for _ in 0..num_rows {
+        WebPMultARGBRow.unwrap()(ptr as *mut u32, width, inverse);
+        ptr = ptr.offset(stride as isize);
+    }
+}
+
+// Same a WebPMultRow(), but for several 'num_rows' rows.
+#[no_mangle]
+pub unsafe extern "C" fn WebPMultRows(
+    mut ptr: *mut u8,
+    stride: c_int,
+    mut alpha: *const u8,
+    alpha_stride: c_int,
+    width: c_int,
+    num_rows: c_int,
+    inverse: c_int,
+) {
+    for _ in 0..num_rows {
+        WebPMultRow.unwrap()(ptr, alpha, width, inverse);
+        ptr = ptr.offset(stride as isize);
+        alpha = alpha.offset(alpha_stride as isize);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Premultiplied modes
+
+// non dithered-modes
+
+// (x * a * 32897) >> 23 is bit-wise equivalent to (int)(x * a / 255.)
+// for all 8bit x or a. For bit-wise equivalence to (int)(x * a / 255. + .5),
+// one can use instead: (x * a * 65793 + (1 << 23)) >> 24
+#[inline]
+fn premultiply(x: u8, a: u32) -> u8 {
+    ((x as u32 * (a * 32897u32)) >> 23) as u8
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ApplyAlphaMultiply_C(
+    mut rgba: *mut u8,
+    alpha_first: c_int,
+    w: c_int,
+    h: c_int,
+    stride: c_int,
+) {
+    for _ in 0..h {
+        let rgb = rgba.offset(if alpha_first != 0 { 1 } else { 0 });
+        let alpha: *const u8 = rgba.offset(if alpha_first != 0 { 0 } else { 3 });
+        for i in 0..w as isize {
+            let a = *alpha.offset(4 * i) as u32;
+            if a != 0xff {
+                for c in 0..3 {
+                    *rgb.offset(4 * i + c) = premultiply(*rgb.offset(4 * i + c), a);
+                }
+            }
+        }
+        rgba = rgba.offset(stride as isize);
+    }
+}
+
+// rgbA4444
+
+#[inline]
+fn dither_hi(x: u8) -> u8 {
+    (x & 0xf0) | (x >> 4)
+}
+
+#[inline]
+fn dither_lo(x: u8) -> u8 {
+    (x & 0x0f) | (x << 4)
+}
+
+#[inline]
+fn multiply(x: u8, m: u32) -> u8 {
+    ((x as u32 * m) >> 16) as u8
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn ApplyAlphaMultiply4444_C(
+    mut rgba4444: *mut u8,
+    w: c_int,
+    h: c_int,
+    stride: c_int,
+    rg_byte_pos: isize, /* 0 or 1 */
+) {
+    for _ in 0..h {
+        for i in 0..w as isize {
+            let rg = *rgba4444.offset(2 * i + rg_byte_pos);
+            let ba = *rgba4444.offset(2 * i + (rg_byte_pos ^ 1));
+            let a = ba & 0x0f;
+            let mult = a as u32 * 0x1111; // 0x1111 ~= (1 << 16) / 15
+            let r = multiply(dither_hi(rg), mult);
+            let g = multiply(dither_lo(rg), mult);
+            let b = multiply(dither_hi(ba), mult);
+            *rgba4444.offset(2 * i + rg_byte_pos) = (r & 0xf0) | ((g >> 4) & 0x0f);
+            *rgba4444.offset(2 * i + (rg_byte_pos ^ 1)) = (b & 0xf0) | a;
+        }
+        rgba4444 = rgba4444.offset(stride as isize);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ApplyAlphaMultiply_16b_C(
+    rgba4444: *mut u8,
+    w: c_int,
+    h: c_int,
+    stride: c_int,
+) {
+    if WEBP_SWAP_16BIT_CSP == 1 {
+        ApplyAlphaMultiply4444_C(rgba4444, w, h, stride, 1);
+    } else {
+        ApplyAlphaMultiply4444_C(rgba4444, w, h, stride, 0);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DispatchAlpha_C(
+    mut alpha: *const u8,
+    alpha_stride: c_int,
+    width: c_int,
+    height: c_int,
+    mut dst: *mut u8,
+    dst_stride: c_int,
+) -> c_int {
+    let mut alpha_mask: u8 = 0xff;
+
+    for _ in 0..height {
+        for i in 0..width as isize {
+            let alpha_value = *alpha.offset(i);
+            *dst.offset(4 * i) = alpha_value;
+            alpha_mask &= alpha_value;
+        }
+        alpha = alpha.offset(alpha_stride as isize);
+        dst = dst.offset(dst_stride as isize);
+    }
+
+    (alpha_mask != 0xff) as c_int
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DispatchAlphaToGreen_C(
+    mut alpha: *const u8,
+    alpha_stride: c_int,
+    width: c_int,
+    height: c_int,
+    mut dst: *mut u32,
+    dst_stride: c_int,
+) {
+    for _ in 0..height {
+        for i in 0..width as isize {
+            *dst.offset(i) = (*alpha.offset(i) as u32) << 8; // leave A/R/B channels zero'd.
+        }
+        alpha = alpha.offset(alpha_stride as isize);
+        dst = dst.offset(dst_stride as isize);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ExtractAlpha_C(
+    mut argb: *const u8,
+    argb_stride: c_int,
+    width: c_int,
+    height: c_int,
+    mut alpha: *mut u8,
+    alpha_stride: c_int,
+) -> c_int {
+    let mut alpha_mask: u8 = 0xff;
+
+    for _ in 0..height {
+        for i in 0..width as isize {
+            let alpha_value = *argb.offset(4 * i);
+            *alpha.offset(i) = alpha_value;
+            alpha_mask &= alpha_value;
+        }
+        argb = argb.offset(argb_stride as isize);
+        alpha = alpha.offset(alpha_stride as isize);
+    }
+    (alpha_mask == 0xff) as c_int
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ExtractGreen_C(argb: *const u32, alpha: *mut u8, size: c_int) {
+    for i in 0..size as isize {
+        *alpha.offset(i) = (*argb.offset(i) >> 8) as u8;
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HasAlpha8b_C(src: *const u8, length: c_int) -> c_int {
+    for i in 0..length as isize {
+        if *src.offset(i) != 0xff {
+            return 1;
+        }
+    }
+    0
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HasAlpha32b_C(src: *const u8, length: c_int) -> c_int {
+    for i in 0..length as isize {
+        if *src.offset(4 * i) != 0xff {
+            return 1;
+        }
+    }
+    0
+}
+
+//------------------------------------------------------------------------------
+// Simple channel manipulations.
+
+#[allow(non_snake_case)]
+#[inline]
+fn MakeARGB32(a: u8, r: u8, g: u8, b: u8) -> u32 {
+    (a as u32) << 24 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+#[cfg(target_endian = "big")]
+#[allow(non_snake_case)]
+unsafe extern "C" fn PackARGB_C(
+    a: *const u8,
+    r: *const u8,
+    g: *const u8,
+    b: *const u8,
+    len: c_int,
+    out: *mut u32,
+) {
+    for i in 0..len as isize {
+        *out.offset(i) = MakeARGB32(
+            *a.offset(4 * i),
+            *r.offset(4 * i),
+            *g.offset(4 * i),
+            *b.offset(4 * i),
+        );
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn PackRGB_C(
+    r: *const u8,
+    g: *const u8,
+    b: *const u8,
+    len: c_int,
+    step: c_int,
+    out: *mut u32,
+) {
+    let mut offset = 0;
+    for i in 0..len as isize {
+        *out.offset(i) = MakeARGB32(
+            0xff,
+            *r.offset(offset),
+            *g.offset(offset),
+            *b.offset(offset),
+        );
+        offset += step as isize;
+    }
+}
+
+// Apply alpha pre-multiply on an rgba, bgra or argb plane of size w * h.
+// alpha_first should be 0 for argb, 1 for rgba or bgra (where alpha is last).
+#[no_mangle]
+pub static mut WebPApplyAlphaMultiply: Option<
+    unsafe extern "C" fn(rgba: *mut u8, alpha_first: c_int, w: c_int, h: c_int, stride: c_int),
+> = None;
+// Same, buf specifically for RGBA4444 format
+#[no_mangle]
+pub static mut WebPApplyAlphaMultiply4444: Option<
+    unsafe extern "C" fn(rgba4444: *mut u8, w: c_int, h: c_int, stride: c_int),
+> = None;
+// Dispatch the values from alpha[] plane to the ARGB destination 'dst'.
+// Returns true if alpha[] plane has non-trivial values different from 0xff.
+#[no_mangle]
+pub static mut WebPDispatchAlpha: Option<
+    unsafe extern "C" fn(
+        alpha: *const u8,
+        alpha_stride: c_int,
+        width: c_int,
+        height: c_int,
+        dst: *mut u8,
+        dst_stride: c_int,
+    ) -> c_int,
+> = None;
+// Transfer packed 8b alpha[] values to green channel in dst[], zero'ing the
+// A/R/B values. 'dst_stride' is the stride for dst[] in uint32_t units.
+#[no_mangle]
+pub static mut WebPDispatchAlphaToGreen: Option<
+    unsafe extern "C" fn(
+        alpha: *const u8,
+        alpha_stride: c_int,
+        width: c_int,
+        height: c_int,
+        dst: *mut u32,
+        dst_stride: c_int,
+    ),
+> = None;
+// Extract the alpha values from 32b values in argb[] and pack them into alpha[]
+// (this is the opposite of WebPDispatchAlpha).
+// Returns true if there's only trivial 0xff alpha values.
+#[no_mangle]
+pub static mut WebPExtractAlpha: Option<
+    unsafe extern "C" fn(
+        argb: *const u8,
+        argb_stride: c_int,
+        width: c_int,
+        height: c_int,
+        alpha: *mut u8,
+        alpha_stride: c_int,
+    ) -> c_int,
+> = None;
+// Extract the green values from 32b values in argb[] and pack them into alpha[]
+// (this is the opposite of WebPDispatchAlphaToGreen).
+#[no_mangle]
+pub static mut WebPExtractGreen: Option<
+    unsafe extern "C" fn(argb: *const u32, alpha: *mut u8, size: c_int),
+> = None;
+// Pack a[], r[], g[], b[] (each taken with a stride of 4) into argb values.
+#[cfg(target_endian = "big")]
+#[no_mangle]
+pub static mut WebPPackARGB: Option<
+    unsafe extern "C" fn(
+        a: *const u8,
+        r: *const u8,
+        g: *const u8,
+        b: *const u8,
+        len: c_int,
+        out: *mut u32,
+    ),
+> = None;
+// RGB packing function. 'step' can be 3 or 4. r/g/b input is rgb or bgr order.
+#[no_mangle]
+pub static mut WebPPackRGB: Option<
+    unsafe extern "C" fn(
+        r: *const u8,
+        g: *const u8,
+        b: *const u8,
+        len: c_int,
+        step: c_int,
+        out: *mut u32,
+    ),
+> = None;
+
+// This function returns true if src[i] contains a value different from 0xff.
+#[no_mangle]
+pub static mut WebPHasAlpha8b: Option<
+    unsafe extern "C" fn(src: *const u8, length: c_int) -> c_int,
+> = None;
+// This function returns true if src[4*i] contains a value different from 0xff.
+#[no_mangle]
+pub static mut WebPHasAlpha32b: Option<
+    unsafe extern "C" fn(src: *const u8, length: c_int) -> c_int,
+> = None;
+
+//------------------------------------------------------------------------------
+// Init function
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPInitAlphaProcessingMIPSdspR2();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitAlphaProcessingSSE2();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitAlphaProcessingSSE41();
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn WebPInitAlphaProcessingNEON();
+}
+
+webp_dsp_init_func! {
+    // To be called first before using the above.
+    #[no_mangle]
+    pub fn WebPInitAlphaProcessing() {
+        WebPMultARGBRow = Some(WebPMultARGBRow_C);
+        WebPMultRow = Some(WebPMultRow_C);
+        WebPApplyAlphaMultiply4444 = Some(ApplyAlphaMultiply_16b_C);
+
+        #[cfg(target_endian = "big")]
+        {
+            WebPPackARGB = Some(PackARGB_C);
+        }
+        WebPPackRGB = Some(PackRGB_C);
+        WebPApplyAlphaMultiply = Some(ApplyAlphaMultiply_C);
+        WebPDispatchAlpha = Some(DispatchAlpha_C);
+        WebPDispatchAlphaToGreen = Some(DispatchAlphaToGreen_C);
+        WebPExtractAlpha = Some(ExtractAlpha_C);
+        WebPExtractGreen = Some(ExtractGreen_C);
+
+        WebPHasAlpha8b = Some(HasAlpha8b_C);
+        WebPHasAlpha32b = Some(HasAlpha32b_C);
+
+        // If defined, use CPUInfo() to overwrite some pointers with faster versions.
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    WebPInitAlphaProcessingSSE2();
+                    if get_cpu_info(kSSE4_1) != 0 {
+                        WebPInitAlphaProcessingSSE41();
+                    }
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    WebPInitAlphaProcessingMIPSdspR2();
+                }
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "pure-rust-decoder")))]
+        {
+            WebPInitAlphaProcessingNEON();
+        }
+        #[cfg(all(target_arch = "arm", not(feature = "pure-rust-decoder")))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    WebPInitAlphaProcessingNEON();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::Lcg;
+
+    #[test]
+    fn test_premultiply_matches_float() {
+        for x in 0..256u32 {
+            for a in 0..256u32 {
+                let expected = (x as f64 * a as f64 / 255.) as u8;
+                assert_eq!(premultiply(x as u8, a), expected, "{} * {}", x, a);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dispatch_extract_alpha_roundtrip() {
+        unsafe {
+            WebPInitAlphaProcessing();
+        }
+        let mut rng = Lcg(3);
+        for &(width, height) in &[(1, 1), (5, 3), (17, 9)] {
+            let mut alpha = rng.bytes(width * height);
+            for &opaque in &[false, true] {
+                if opaque {
+                    alpha = vec![0xff; width * height];
+                }
+                let mut argb = rng.bytes(4 * width * height);
+                let mut extracted = vec![0; width * height];
+                unsafe {
+                    let non_trivial = WebPDispatchAlpha.unwrap()(
+                        alpha.as_ptr(),
+                        width as c_int,
+                        width as c_int,
+                        height as c_int,
+                        argb.as_mut_ptr(),
+                        4 * width as c_int,
+                    );
+                    assert_eq!(non_trivial != 0, !opaque);
+                    let trivial = WebPExtractAlpha.unwrap()(
+                        argb.as_ptr(),
+                        4 * width as c_int,
+                        width as c_int,
+                        height as c_int,
+                        extracted.as_mut_ptr(),
+                        width as c_int,
+                    );
+                    assert_eq!(trivial != 0, opaque);
+                    assert_eq!(
+                        WebPHasAlpha32b.unwrap()(argb.as_ptr(), (width * height) as c_int) != 0,
+                        !opaque
+                    );
+                }
+                assert!(extracted == alpha);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mult_argb_row_roundtrip() {
+        unsafe {
+            WebPInitAlphaProcessing();
+        }
+        let mut rng = Lcg(4);
+        // Premultiplied values, so that unmultiplying and multiplying back is lossless.
+        let mut row: Vec<u32> = (0..1000)
+            .map(|_| {
+                let a = rng.next() & 0xff;
+                let c = |rng: &mut Lcg| (rng.next() & 0xff) * a / 255;
+                a << 24 | c(&mut rng) << 16 | c(&mut rng) << 8 | c(&mut rng)
+            })
+            .collect();
+        row[0] = 0x00123456;
+        row[1] = 0xff123456;
+        let mut expected = row.clone();
+        expected[0] = 0;
+        unsafe {
+            WebPMultARGBRow.unwrap()(row.as_mut_ptr(), row.len() as c_int, 1);
+            WebPMultARGBRow.unwrap()(row.as_mut_ptr(), row.len() as c_int, 0);
+        }
+        assert!(row == expected);
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2016 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE2 code common to several files.
+//
+// Author: Vincent Rabaud (vrabaud@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(clippy::too_many_arguments)]
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+//------------------------------------------------------------------------------
+// Math functions.
+
+// Transpose two 4x4 16b matrices horizontally stored in registers.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn VP8Transpose_2_4x4_16b(
+    in0: &__m128i,
+    in1: &__m128i,
+    in2: &__m128i,
+    in3: &__m128i,
+    out0: &mut __m128i,
+    out1: &mut __m128i,
+    out2: &mut __m128i,
+    out3: &mut __m128i,
+) {
+    // Transpose the two 4x4.
+    // a00 a01 a02 a03   b00 b01 b02 b03
+    // a10 a11 a12 a13   b10 b11 b12 b13
+    // a20 a21 a22 a23   b20 b21 b22 b23
+    // a30 a31 a32 a33   b30 b31 b32 b33
+    let transpose0_0 = _mm_unpacklo_epi16(*in0, *in1);
+    let transpose0_1 = _mm_unpacklo_epi16(*in2, *in3);
+    let transpose0_2 = _mm_unpackhi_epi16(*in0, *in1);
+    let transpose0_3 = _mm_unpackhi_epi16(*in2, *in3);
+    // a00 a10 a01 a11   a02 a12 a03 a13
+    // a20 a30 a21 a31   a22 a32 a23 a33
+    // b00 b10 b01 b11   b02 b12 b03 b13
+    // b20 b30 b21 b31   b22 b32 b23 b33
+    let transpose1_0 = _mm_unpacklo_epi32(transpose0_0, transpose0_1);
+    let transpose1_1 = _mm_unpacklo_epi32(transpose0_2, transpose0_3);
+    let transpose1_2 = _mm_unpackhi_epi32(transpose0_0, transpose0_1);
+    let transpose1_3 = _mm_unpackhi_epi32(transpose0_2, transpose0_3);
+    // a00 a10 a20 a30 a01 a11 a21 a31
+    // b00 b10 b20 b30 b01 b11 b21 b31
+    // a02 a12 a22 a32 a03 a13 a23 a33
+    // b02 b12 a22 b32 b03 b13 b23 b33
+    *out0 = _mm_unpacklo_epi64(transpose1_0, transpose1_1);
+    *out1 = _mm_unpackhi_epi64(transpose1_0, transpose1_1);
+    *out2 = _mm_unpacklo_epi64(transpose1_2, transpose1_3);
+    *out3 = _mm_unpackhi_epi64(transpose1_2, transpose1_3);
+    // a00 a10 a20 a30   b00 b10 b20 b30
+    // a01 a11 a21 a31   b01 b11 b21 b31
+    // a02 a12 a22 a32   b02 b12 b22 b32
+    // a03 a13 a23 a33   b03 b13 b23 b33
+}
+
+//------------------------------------------------------------------------------
+// Channel mixing.
+
+// Function used several times in VP8PlanarTo24b.
+// It samples the in buffer as follows: one every two unsigned char is stored
+// at the beginning of the buffer, while the other half is stored at the end.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn VP8PlanarTo24bHelper(input: &[__m128i; 6], output: &mut [__m128i; 6]) {
+    let v_mask = _mm_set1_epi16(0x00ff);
+    // Take one every two upper 8b values.
+    output[0] = _mm_packus_epi16(
+        _mm_and_si128(input[0], v_mask),
+        _mm_and_si128(input[1], v_mask),
+    );
+    output[1] = _mm_packus_epi16(
+        _mm_and_si128(input[2], v_mask),
+        _mm_and_si128(input[3], v_mask),
+    );
+    output[2] = _mm_packus_epi16(
+        _mm_and_si128(input[4], v_mask),
+        _mm_and_si128(input[5], v_mask),
+    );
+    // Take one every two lower 8b values.
+    output[3] = _mm_packus_epi16(_mm_srli_epi16(input[0], 8), _mm_srli_epi16(input[1], 8));
+    output[4] = _mm_packus_epi16(_mm_srli_epi16(input[2], 8), _mm_srli_epi16(input[3], 8));
+    output[5] = _mm_packus_epi16(_mm_srli_epi16(input[4], 8), _mm_srli_epi16(input[5], 8));
+}
+
+// Pack the planar buffers
+// rrrr... rrrr... gggg... gggg... bbbb... bbbb....
+// triplet by triplet in the output buffer rgb as rgbrgbrgbrgb ...
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn VP8PlanarTo24b_SSE2(input: &mut [__m128i; 6]) {
+    // The input is 6 registers of sixteen 8b but for the sake of explanation,
+    // let's take 6 registers of four 8b values.
+    // To pack, we will keep taking one every two 8b integer and move it
+    // around as follows:
+    // Input:
+    //   r0r1r2r3 | r4r5r6r7 | g0g1g2g3 | g4g5g6g7 | b0b1b2b3 | b4b5b6b7
+    // Split the 6 registers in two sets of 3 registers: the first set as the even
+    // 8b bytes, the second the odd ones:
+    //   r0r2r4r6 | g0g2g4g6 | b0b2b4b6 | r1r3r5r7 | g1g3g5g7 | b1b3b5b7
+    // Repeat the same permutations twice more:
+    //   r0r4g0g4 | b0b4r1r5 | g1g5b1b5 | r2r6g2g6 | b2b6r3r7 | g3g7b3b7
+    //   r0g0b0r1 | g1b1r2g2 | b2r3g3b3 | r4g4b4r5 | g5b5r6g6 | b6r7g7b7
+    let mut tmp = [_mm_setzero_si128(); 6];
+    VP8PlanarTo24bHelper(input, &mut tmp);
+    VP8PlanarTo24bHelper(&tmp, input);
+    VP8PlanarTo24bHelper(input, &mut tmp);
+    // We need to do it two more times than the example as we have sixteen bytes.
+    {
+        let mut out = [_mm_setzero_si128(); 6];
+        VP8PlanarTo24bHelper(&tmp, &mut out);
+        VP8PlanarTo24bHelper(&out, input);
+    }
+}
+
+// Convert four packed four-channel buffers like argbargbargbargb... into the
+// split channels aaaaa ... rrrr ... gggg .... bbbbb ......
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn VP8L32bToPlanar_SSE2(
+    in0: &mut __m128i,
+    in1: &mut __m128i,
+    in2: &mut __m128i,
+    in3: &mut __m128i,
+) {
+    // Column-wise transpose.
+    let A0 = _mm_unpacklo_epi8(*in0, *in1);
+    let A1 = _mm_unpackhi_epi8(*in0, *in1);
+    let A2 = _mm_unpacklo_epi8(*in2, *in3);
+    let A3 = _mm_unpackhi_epi8(*in2, *in3);
+    let B0 = _mm_unpacklo_epi8(A0, A1);
+    let B1 = _mm_unpackhi_epi8(A0, A1);
+    let B2 = _mm_unpacklo_epi8(A2, A3);
+    let B3 = _mm_unpackhi_epi8(A2, A3);
+    // C0 = g7 g6 ... g1 g0 | b7 b6 ... b1 b0
+    // C1 = a7 a6 ... a1 a0 | r7 r6 ... r1 r0
+    let C0 = _mm_unpacklo_epi8(B0, B1);
+    let C1 = _mm_unpackhi_epi8(B0, B1);
+    let C2 = _mm_unpacklo_epi8(B2, B3);
+    let C3 = _mm_unpackhi_epi8(B2, B3);
+    // Gather the channels.
+    *in0 = _mm_unpackhi_epi64(C1, C3);
+    *in1 = _mm_unpacklo_epi64(C1, C3);
+    *in2 = _mm_unpackhi_epi64(C0, C2);
+    *in3 = _mm_unpacklo_epi64(C0, C2);
+}
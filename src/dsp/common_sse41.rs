@@ -0,0 +1,86 @@
+// Copyright 2016 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE4 code common to several files.
+//
+// Author: Vincent Rabaud (vrabaud@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+//------------------------------------------------------------------------------
+// Channel mixing.
+// Shuffles the input buffer as A0 0 0 A1 0 0 A2 ...
+macro_rules! webp_sse41_shuff {
+    ($in0:expr, $in1:expr, $shuff0:expr, $shuff1:expr, $shuff2:expr) => {
+        [
+            _mm_shuffle_epi8($in0, $shuff0),
+            _mm_shuffle_epi8($in0, $shuff1),
+            _mm_shuffle_epi8($in0, $shuff2),
+            _mm_shuffle_epi8($in1, $shuff0),
+            _mm_shuffle_epi8($in1, $shuff1),
+            _mm_shuffle_epi8($in1, $shuff2),
+        ]
+    };
+}
+
+// Pack the planar buffers
+// rrrr... rrrr... gggg... gggg... bbbb... bbbb....
+// triplet by triplet in the output buffer rgb as rgbrgbrgbrgb ...
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn VP8PlanarTo24b_SSE41(input: &mut [__m128i; 6]) {
+    // Process R.
+    #[rustfmt::skip]
+    let R = {
+        let shuff0 = _mm_set_epi8(
+            5, -1, -1, 4, -1, -1, 3, -1, -1, 2, -1, -1, 1, -1, -1, 0);
+        let shuff1 = _mm_set_epi8(
+            -1, 10, -1, -1, 9, -1, -1, 8, -1, -1, 7, -1, -1, 6, -1, -1);
+        let shuff2 = _mm_set_epi8(
+            -1, -1, 15, -1, -1, 14, -1, -1, 13, -1, -1, 12, -1, -1, 11, -1);
+        webp_sse41_shuff!(input[0], input[1], shuff0, shuff1, shuff2)
+    };
+
+    // Process G.
+    #[rustfmt::skip]
+    let G = {
+        // Same as before, just shifted to the left by one and including the right
+        // padding.
+        let shuff0 = _mm_set_epi8(
+            -1, -1, 4, -1, -1, 3, -1, -1, 2, -1, -1, 1, -1, -1, 0, -1);
+        let shuff1 = _mm_set_epi8(
+            10, -1, -1, 9, -1, -1, 8, -1, -1, 7, -1, -1, 6, -1, -1, 5);
+        let shuff2 = _mm_set_epi8(
+            -1, 15, -1, -1, 14, -1, -1, 13, -1, -1, 12, -1, -1, 11, -1, -1);
+        webp_sse41_shuff!(input[2], input[3], shuff0, shuff1, shuff2)
+    };
+
+    // Process B.
+    #[rustfmt::skip]
+    let B = {
+        let shuff0 = _mm_set_epi8(
+            -1, 4, -1, -1, 3, -1, -1, 2, -1, -1, 1, -1, -1, 0, -1, -1);
+        let shuff1 = _mm_set_epi8(
+            -1, -1, 9, -1, -1, 8, -1, -1, 7, -1, -1, 6, -1, -1, 5, -1);
+        let shuff2 = _mm_set_epi8(
+            15, -1, -1, 14, -1, -1, 13, -1, -1, 12, -1, -1, 11, -1, -1, 10);
+        webp_sse41_shuff!(input[4], input[5], shuff0, shuff1, shuff2)
+    };
+
+    // OR the different channels.
+    for i in 0..6 {
+        let RG = _mm_or_si128(R[i], G[i]);
+        input[i] = _mm_or_si128(RG, B[i]);
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// CPU detection
+//
+// Author: Christian Duvivier (cduvivier@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+
+#[allow(unused_imports)]
+use dsp::dsp::{kAVX, kAVX2, kMIPS32, kMIPSdspR2, kMSA, kNEON, kSSE2, kSSE3, kSSE4_1, kSlowSSSE3};
+use dsp::dsp::{CPUFeature, VP8CPUInfo};
+
+//------------------------------------------------------------------------------
+// SSE2 detection.
+//
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[allow(non_snake_case)]
+#[inline]
+fn GetCPUInfo(cpu_info: &mut [c_int; 4], info_type: c_int) {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::__cpuid_count;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::__cpuid_count;
+
+    // set ecx=0
+    let result = __cpuid_count(info_type as u32, 0);
+    *cpu_info = [
+        result.eax as c_int,
+        result.ebx as c_int,
+        result.ecx as c_int,
+        result.edx as c_int,
+    ];
+}
+
+// helper function for run-time detection of slow SSSE3 platforms
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[allow(non_snake_case)]
+fn CheckSlowModel(info: c_int) -> c_int {
+    // Table listing display models with longer latencies for the bsr instruction
+    // (ie 2 cycles vs 10/16 cycles) and some SSSE3 instructions like pshufb.
+    // Refer to Intel 64 and IA-32 Architectures Optimization Reference Manual.
+    #[allow(non_upper_case_globals)]
+    #[rustfmt::skip]
+    const kSlowModels: [u8; 6] = [
+        0x37, 0x4a, 0x4d, // Silvermont Microarchitecture
+        0x1c, 0x26, 0x27, // Atom Microarchitecture
+    ];
+    let model = ((info as u32 & 0xf0000) >> 12) | ((info as u32 >> 4) & 0xf);
+    let family = (info as u32 >> 8) & 0xf;
+    if family == 0x06
+        && kSlowModels
+            .iter()
+            .any(|&slow_model| model == slow_model as u32)
+    {
+        return 1;
+    }
+    0
+}
+
+// The instruction set extensions are queried through std, which also checks
+// that the OS saves the extended registers; cpuid is only used for the model
+// based kSlowSSSE3 check.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[allow(non_snake_case)]
+unsafe extern "C" fn x86CPUInfo(feature: CPUFeature) -> c_int {
+    match feature {
+        kSSE2 => is_x86_feature_detected!("sse2") as c_int,
+        kSSE3 => is_x86_feature_detected!("sse3") as c_int,
+        kSlowSSSE3 => {
+            let mut cpu_info = [0; 4];
+
+            // get the highest feature value cpuid supports
+            GetCPUInfo(&mut cpu_info, 0);
+            if cpu_info[0] < 1 {
+                return 0;
+            }
+            const VENDOR_ID_INTEL_EBX: c_int = 0x756e6547; // uneG
+            const VENDOR_ID_INTEL_EDX: c_int = 0x49656e69; // Ieni
+            const VENDOR_ID_INTEL_ECX: c_int = 0x6c65746e; // letn
+            let is_intel = cpu_info[1] == VENDOR_ID_INTEL_EBX
+                && cpu_info[2] == VENDOR_ID_INTEL_ECX
+                && cpu_info[3] == VENDOR_ID_INTEL_EDX; // genuine Intel?
+
+            GetCPUInfo(&mut cpu_info, 1);
+            if is_intel && is_x86_feature_detected!("ssse3") {
+                return CheckSlowModel(cpu_info[0]);
+            }
+            0
+        }
+        kSSE4_1 => is_x86_feature_detected!("sse4.1") as c_int,
+        kAVX => is_x86_feature_detected!("avx") as c_int,
+        kAVX2 => is_x86_feature_detected!("avx2") as c_int,
+        _ => 0,
+    }
+}
+
+// define a dummy function to enable turning off NEON at runtime by setting
+// VP8DecGetCPUInfo = NULL
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+#[allow(non_snake_case)]
+unsafe extern "C" fn armCPUInfo(feature: CPUFeature) -> c_int {
+    (feature == kNEON) as c_int
+}
+
+#[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+#[allow(non_snake_case)]
+unsafe extern "C" fn mipsCPUInfo(feature: CPUFeature) -> c_int {
+    (feature == kMIPS32 || feature == kMIPSdspR2 || feature == kMSA) as c_int
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[no_mangle]
+pub static mut VP8GetCPUInfo: VP8CPUInfo = Some(x86CPUInfo);
+#[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+#[no_mangle]
+pub static mut VP8GetCPUInfo: VP8CPUInfo = Some(armCPUInfo);
+#[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+#[no_mangle]
+pub static mut VP8GetCPUInfo: VP8CPUInfo = Some(mipsCPUInfo);
+#[cfg(not(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "mips",
+    target_arch = "mips64"
+)))]
+#[no_mangle]
+pub static mut VP8GetCPUInfo: VP8CPUInfo = None;
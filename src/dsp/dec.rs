@@ -0,0 +1,1506 @@
+// Copyright 2010 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Speed-critical decoding functions, default plain-C implementations.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(clippy::identity_op, clippy::erasing_op)]
+
+use std::os::raw::*;
+use std::ptr;
+
+use dec::common_dec::{NUM_BMODES, NUM_B_DC_MODES};
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    not(any(target_arch = "aarch64", feature = "pure-rust-decoder"))
+))]
+use dsp::cpu::VP8GetCPUInfo;
+use dsp::dec_clip_tables::{VP8InitClipTables, VP8kabs0, VP8kclip1, VP8ksclip1, VP8ksclip2};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use dsp::dec_sse2::VP8DspInitSSE2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use dsp::dec_sse41::VP8DspInitSSE41;
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    not(any(target_arch = "aarch64", feature = "pure-rust-decoder"))
+))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::{
+    VP8ChromaFilterFunc, VP8DecIdct, VP8DecIdct2, VP8LumaFilterFunc, VP8PredFunc,
+    VP8SimpleFilterFunc, BPS, VP8WHT, VP8_DITHER_AMP_CENTER, VP8_DITHER_DESCALE,
+    VP8_DITHER_DESCALE_ROUNDER,
+};
+
+const BPS_: isize = BPS as isize;
+
+//------------------------------------------------------------------------------
+
+#[inline]
+fn clip_8b(v: c_int) -> u8 {
+    if (v & !0xff) == 0 {
+        v as u8
+    } else if v < 0 {
+        0
+    } else {
+        255
+    }
+}
+
+//------------------------------------------------------------------------------
+// Transforms (Paragraph 14.4)
+
+macro_rules! store {
+    ($dst:expr, $x:expr, $y:expr, $v:expr) => {{
+        let d = $dst.offset($x + $y * BPS_);
+        *d = clip_8b(*d as c_int + (($v) >> 3));
+    }};
+}
+
+macro_rules! store2 {
+    ($dst:expr, $y:expr, $dc:expr, $d:expr, $c:expr) => {{
+        let dc = $dc;
+        store!($dst, 0, $y, dc + ($d));
+        store!($dst, 1, $y, dc + ($c));
+        store!($dst, 2, $y, dc - ($c));
+        store!($dst, 3, $y, dc - ($d));
+    }};
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn MUL1(a: c_int) -> c_int {
+    ((a * 20091) >> 16) + a
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn MUL2(a: c_int) -> c_int {
+    (a * 35468) >> 16
+}
+
+#[allow(non_snake_case)]
+unsafe fn TransformOne_C(mut in_: *const i16, mut dst: *mut u8) {
+    let mut C = [0 as c_int; 4 * 4];
+    for i in 0..4 {
+        // vertical pass
+        let in0 = *in_.offset(0) as c_int;
+        let in4 = *in_.offset(4) as c_int;
+        let in8 = *in_.offset(8) as c_int;
+        let in12 = *in_.offset(12) as c_int;
+        let a = in0 + in8; // [-4096, 4094]
+        let b = in0 - in8; // [-4095, 4095]
+        let c = MUL2(in4) - MUL1(in12); // [-3783, 3783]
+        let d = MUL1(in4) + MUL2(in12); // [-3785, 3781]
+        C[4 * i + 0] = a + d; // [-7881, 7875]
+        C[4 * i + 1] = b + c; // [-7878, 7878]
+        C[4 * i + 2] = b - c; // [-7878, 7878]
+        C[4 * i + 3] = a - d; // [-7877, 7879]
+        in_ = in_.offset(1);
+    }
+    // Each pass is expanding the dynamic range by ~3.85 (upper bound).
+    // The exact value is (2. + (20091 + 35468) / 65536).
+    // After the second pass, maximum interval is [-3794, 3794], assuming
+    // an input in [-2048, 2047] interval. We then need to add a dst value
+    // in the [0, 255] range.
+    // In the worst case scenario, the input to clip_8b() can be as large as
+    // [-60713, 60968].
+    for i in 0..4 {
+        // horizontal pass
+        let dc = C[i] + 4;
+        let a = dc + C[i + 8];
+        let b = dc - C[i + 8];
+        let c = MUL2(C[i + 4]) - MUL1(C[i + 12]);
+        let d = MUL1(C[i + 4]) + MUL2(C[i + 12]);
+        store!(dst, 0, 0, a + d);
+        store!(dst, 1, 0, b + c);
+        store!(dst, 2, 0, b - c);
+        store!(dst, 3, 0, a - d);
+        dst = dst.offset(BPS_);
+    }
+}
+
+// Simplified transform when only in[0], in[1] and in[4] are non-zero
+#[allow(non_snake_case)]
+unsafe extern "C" fn TransformAC3_C(in_: *const i16, dst: *mut u8) {
+    let a = *in_.offset(0) as c_int + 4;
+    let c4 = MUL2(*in_.offset(4) as c_int);
+    let d4 = MUL1(*in_.offset(4) as c_int);
+    let c1 = MUL2(*in_.offset(1) as c_int);
+    let d1 = MUL1(*in_.offset(1) as c_int);
+    store2!(dst, 0, a + d4, d1, c1);
+    store2!(dst, 1, a + c4, d1, c1);
+    store2!(dst, 2, a - c4, d1, c1);
+    store2!(dst, 3, a - d4, d1, c1);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn TransformTwo_C(in_: *const i16, dst: *mut u8, do_two: c_int) {
+    TransformOne_C(in_, dst);
+    if do_two != 0 {
+        TransformOne_C(in_.offset(16), dst.offset(4));
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn TransformUV_C(in_: *const i16, dst: *mut u8) {
+    VP8Transform.unwrap()(in_.offset(0 * 16), dst, 1);
+    VP8Transform.unwrap()(in_.offset(2 * 16), dst.offset(4 * BPS_), 1);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn TransformDC_C(in_: *const i16, dst: *mut u8) {
+    let DC = *in_.offset(0) as c_int + 4;
+    for j in 0..4 {
+        for i in 0..4 {
+            store!(dst, i, j, DC);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn TransformDCUV_C(in_: *const i16, dst: *mut u8) {
+    if *in_.offset(0 * 16) != 0 {
+        VP8TransformDC.unwrap()(in_.offset(0 * 16), dst);
+    }
+    if *in_.offset(1 * 16) != 0 {
+        VP8TransformDC.unwrap()(in_.offset(1 * 16), dst.offset(4));
+    }
+    if *in_.offset(2 * 16) != 0 {
+        VP8TransformDC.unwrap()(in_.offset(2 * 16), dst.offset(4 * BPS_));
+    }
+    if *in_.offset(3 * 16) != 0 {
+        VP8TransformDC.unwrap()(in_.offset(3 * 16), dst.offset(4 * BPS_ + 4));
+    }
+}
+
+//------------------------------------------------------------------------------
+// Paragraph 14.3
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn TransformWHT_C(in_: *const i16, mut out: *mut i16) {
+    let mut tmp = [0 as c_int; 16];
+    let in_ = |i: usize| *in_.add(i) as c_int;
+    for i in 0..4 {
+        let a0 = in_(0 + i) + in_(12 + i);
+        let a1 = in_(4 + i) + in_(8 + i);
+        let a2 = in_(4 + i) - in_(8 + i);
+        let a3 = in_(0 + i) - in_(12 + i);
+        tmp[0 + i] = a0 + a1;
+        tmp[8 + i] = a0 - a1;
+        tmp[4 + i] = a3 + a2;
+        tmp[12 + i] = a3 - a2;
+    }
+    for i in 0..4 {
+        let dc = tmp[0 + i * 4] + 3; // w/ rounder
+        let a0 = dc + tmp[3 + i * 4];
+        let a1 = tmp[1 + i * 4] + tmp[2 + i * 4];
+        let a2 = tmp[1 + i * 4] - tmp[2 + i * 4];
+        let a3 = dc - tmp[3 + i * 4];
+        *out.offset(0) = ((a0 + a1) >> 3) as i16;
+        *out.offset(16) = ((a3 + a2) >> 3) as i16;
+        *out.offset(32) = ((a0 - a1) >> 3) as i16;
+        *out.offset(48) = ((a3 - a2) >> 3) as i16;
+        out = out.offset(64);
+    }
+}
+
+#[no_mangle]
+pub static mut VP8TransformWHT: VP8WHT = None;
+
+//------------------------------------------------------------------------------
+// Intra predictions
+
+// Assigns the same value to several DST(x, y) positions.
+macro_rules! dst {
+    ($dst:expr, $(($x:expr, $y:expr)),+ = $v:expr) => {{
+        let v = $v;
+        $(*$dst.offset($x + $y * BPS_) = v;)+
+    }};
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn TrueMotion(mut dst: *mut u8, size: isize) {
+    let top: *const u8 = dst.offset(-BPS_);
+    let clip0: isize = -(*top.offset(-1) as isize);
+    for _ in 0..size {
+        let clip = clip0 + *dst.offset(-1) as isize;
+        for x in 0..size {
+            *dst.offset(x) = *VP8kclip1.offset(clip + *top.offset(x) as isize);
+        }
+        dst = dst.offset(BPS_);
+    }
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn TM4_C(dst: *mut u8) {
+    TrueMotion(dst, 4);
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn TM8uv_C(dst: *mut u8) {
+    TrueMotion(dst, 8);
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn TM16_C(dst: *mut u8) {
+    TrueMotion(dst, 16);
+}
+
+//------------------------------------------------------------------------------
+// 16x16
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VE16_C(dst: *mut u8) {
+    // vertical
+    for j in 0..16 {
+        ptr::copy_nonoverlapping(dst.offset(-BPS_), dst.offset(j * BPS_), 16);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HE16_C(mut dst: *mut u8) {
+    // horizontal
+    for _ in 0..16 {
+        ptr::write_bytes(dst, *dst.offset(-1), 16);
+        dst = dst.offset(BPS_);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn Put16(v: c_int, dst: *mut u8) {
+    for j in 0..16 {
+        ptr::write_bytes(dst.offset(j * BPS_), v as u8, 16);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC16_C(dst: *mut u8) {
+    // DC
+    let mut DC: c_int = 16;
+    for j in 0..16 {
+        DC += *dst.offset(-1 + j * BPS_) as c_int + *dst.offset(j - BPS_) as c_int;
+    }
+    Put16(DC >> 5, dst);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC16NoTop_C(dst: *mut u8) {
+    // DC with top samples not available
+    let mut DC: c_int = 8;
+    for j in 0..16 {
+        DC += *dst.offset(-1 + j * BPS_) as c_int;
+    }
+    Put16(DC >> 4, dst);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC16NoLeft_C(dst: *mut u8) {
+    // DC with left samples not available
+    let mut DC: c_int = 8;
+    for i in 0..16 {
+        DC += *dst.offset(i - BPS_) as c_int;
+    }
+    Put16(DC >> 4, dst);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC16NoTopLeft_C(dst: *mut u8) {
+    // DC with no top and left samples
+    Put16(0x80, dst);
+}
+
+#[no_mangle]
+pub static mut VP8PredLuma16: [VP8PredFunc; NUM_B_DC_MODES as usize] =
+    [None; NUM_B_DC_MODES as usize];
+
+//------------------------------------------------------------------------------
+// 4x4
+
+#[allow(non_snake_case)]
+#[inline]
+fn AVG3(a: c_int, b: c_int, c: c_int) -> u8 {
+    ((a + 2 * b + c + 2) >> 2) as u8
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn AVG2(a: c_int, b: c_int) -> u8 {
+    ((a + b + 1) >> 1) as u8
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VE4_C(dst: *mut u8) {
+    // vertical
+    let top: *const u8 = dst.offset(-BPS_);
+    let top = |i: isize| *top.offset(i) as c_int;
+    let vals: [u8; 4] = [
+        AVG3(top(-1), top(0), top(1)),
+        AVG3(top(0), top(1), top(2)),
+        AVG3(top(1), top(2), top(3)),
+        AVG3(top(2), top(3), top(4)),
+    ];
+    for i in 0..4 {
+        ptr::copy_nonoverlapping(vals.as_ptr(), dst.offset(i * BPS_), vals.len());
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HE4_C(dst: *mut u8) {
+    // horizontal
+    let A = *dst.offset(-1 - BPS_) as c_int;
+    let B = *dst.offset(-1) as c_int;
+    let C = *dst.offset(-1 + BPS_) as c_int;
+    let D = *dst.offset(-1 + 2 * BPS_) as c_int;
+    let E = *dst.offset(-1 + 3 * BPS_) as c_int;
+    ptr::write_bytes(dst.offset(0 * BPS_), AVG3(A, B, C), 4);
+    ptr::write_bytes(dst.offset(1 * BPS_), AVG3(B, C, D), 4);
+    ptr::write_bytes(dst.offset(2 * BPS_), AVG3(C, D, E), 4);
+    ptr::write_bytes(dst.offset(3 * BPS_), AVG3(D, E, E), 4);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC4_C(dst: *mut u8) {
+    // DC
+    let mut dc: u32 = 4;
+    for i in 0..4 {
+        dc += *dst.offset(i - BPS_) as u32 + *dst.offset(-1 + i * BPS_) as u32;
+    }
+    dc >>= 3;
+    for i in 0..4 {
+        ptr::write_bytes(dst.offset(i * BPS_), dc as u8, 4);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn RD4_C(dst: *mut u8) {
+    // Down-right
+    let I = *dst.offset(-1 + 0 * BPS_) as c_int;
+    let J = *dst.offset(-1 + 1 * BPS_) as c_int;
+    let K = *dst.offset(-1 + 2 * BPS_) as c_int;
+    let L = *dst.offset(-1 + 3 * BPS_) as c_int;
+    let X = *dst.offset(-1 - BPS_) as c_int;
+    let A = *dst.offset(0 - BPS_) as c_int;
+    let B = *dst.offset(1 - BPS_) as c_int;
+    let C = *dst.offset(2 - BPS_) as c_int;
+    let D = *dst.offset(3 - BPS_) as c_int;
+    dst!(dst, (0, 3) = AVG3(J, K, L));
+    dst!(dst, (1, 3), (0, 2) = AVG3(I, J, K));
+    dst!(dst, (2, 3), (1, 2), (0, 1) = AVG3(X, I, J));
+    dst!(dst, (3, 3), (2, 2), (1, 1), (0, 0) = AVG3(A, X, I));
+    dst!(dst, (3, 2), (2, 1), (1, 0) = AVG3(B, A, X));
+    dst!(dst, (3, 1), (2, 0) = AVG3(C, B, A));
+    dst!(dst, (3, 0) = AVG3(D, C, B));
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn LD4_C(dst: *mut u8) {
+    // Down-Left
+    let A = *dst.offset(0 - BPS_) as c_int;
+    let B = *dst.offset(1 - BPS_) as c_int;
+    let C = *dst.offset(2 - BPS_) as c_int;
+    let D = *dst.offset(3 - BPS_) as c_int;
+    let E = *dst.offset(4 - BPS_) as c_int;
+    let F = *dst.offset(5 - BPS_) as c_int;
+    let G = *dst.offset(6 - BPS_) as c_int;
+    let H = *dst.offset(7 - BPS_) as c_int;
+    dst!(dst, (0, 0) = AVG3(A, B, C));
+    dst!(dst, (1, 0), (0, 1) = AVG3(B, C, D));
+    dst!(dst, (2, 0), (1, 1), (0, 2) = AVG3(C, D, E));
+    dst!(dst, (3, 0), (2, 1), (1, 2), (0, 3) = AVG3(D, E, F));
+    dst!(dst, (3, 1), (2, 2), (1, 3) = AVG3(E, F, G));
+    dst!(dst, (3, 2), (2, 3) = AVG3(F, G, H));
+    dst!(dst, (3, 3) = AVG3(G, H, H));
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VR4_C(dst: *mut u8) {
+    // Vertical-Right
+    let I = *dst.offset(-1 + 0 * BPS_) as c_int;
+    let J = *dst.offset(-1 + 1 * BPS_) as c_int;
+    let K = *dst.offset(-1 + 2 * BPS_) as c_int;
+    let X = *dst.offset(-1 - BPS_) as c_int;
+    let A = *dst.offset(0 - BPS_) as c_int;
+    let B = *dst.offset(1 - BPS_) as c_int;
+    let C = *dst.offset(2 - BPS_) as c_int;
+    let D = *dst.offset(3 - BPS_) as c_int;
+    dst!(dst, (0, 0), (1, 2) = AVG2(X, A));
+    dst!(dst, (1, 0), (2, 2) = AVG2(A, B));
+    dst!(dst, (2, 0), (3, 2) = AVG2(B, C));
+    dst!(dst, (3, 0) = AVG2(C, D));
+
+    dst!(dst, (0, 3) = AVG3(K, J, I));
+    dst!(dst, (0, 2) = AVG3(J, I, X));
+    dst!(dst, (0, 1), (1, 3) = AVG3(I, X, A));
+    dst!(dst, (1, 1), (2, 3) = AVG3(X, A, B));
+    dst!(dst, (2, 1), (3, 3) = AVG3(A, B, C));
+    dst!(dst, (3, 1) = AVG3(B, C, D));
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VL4_C(dst: *mut u8) {
+    // Vertical-Left
+    let A = *dst.offset(0 - BPS_) as c_int;
+    let B = *dst.offset(1 - BPS_) as c_int;
+    let C = *dst.offset(2 - BPS_) as c_int;
+    let D = *dst.offset(3 - BPS_) as c_int;
+    let E = *dst.offset(4 - BPS_) as c_int;
+    let F = *dst.offset(5 - BPS_) as c_int;
+    let G = *dst.offset(6 - BPS_) as c_int;
+    let H = *dst.offset(7 - BPS_) as c_int;
+    dst!(dst, (0, 0) = AVG2(A, B));
+    dst!(dst, (1, 0), (0, 2) = AVG2(B, C));
+    dst!(dst, (2, 0), (1, 2) = AVG2(C, D));
+    dst!(dst, (3, 0), (2, 2) = AVG2(D, E));
+
+    dst!(dst, (0, 1) = AVG3(A, B, C));
+    dst!(dst, (1, 1), (0, 3) = AVG3(B, C, D));
+    dst!(dst, (2, 1), (1, 3) = AVG3(C, D, E));
+    dst!(dst, (3, 1), (2, 3) = AVG3(D, E, F));
+    dst!(dst, (3, 2) = AVG3(E, F, G));
+    dst!(dst, (3, 3) = AVG3(F, G, H));
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HU4_C(dst: *mut u8) {
+    // Horizontal-Up
+    let I = *dst.offset(-1 + 0 * BPS_) as c_int;
+    let J = *dst.offset(-1 + 1 * BPS_) as c_int;
+    let K = *dst.offset(-1 + 2 * BPS_) as c_int;
+    let L = *dst.offset(-1 + 3 * BPS_) as c_int;
+    dst!(dst, (0, 0) = AVG2(I, J));
+    dst!(dst, (2, 0), (0, 1) = AVG2(J, K));
+    dst!(dst, (2, 1), (0, 2) = AVG2(K, L));
+    dst!(dst, (1, 0) = AVG3(I, J, K));
+    dst!(dst, (3, 0), (1, 1) = AVG3(J, K, L));
+    dst!(dst, (3, 1), (1, 2) = AVG3(K, L, L));
+    dst!(
+        dst,
+        (3, 2),
+        (2, 2),
+        (0, 3),
+        (1, 3),
+        (2, 3),
+        (3, 3) = L as u8
+    );
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HD4_C(dst: *mut u8) {
+    // Horizontal-Down
+    let I = *dst.offset(-1 + 0 * BPS_) as c_int;
+    let J = *dst.offset(-1 + 1 * BPS_) as c_int;
+    let K = *dst.offset(-1 + 2 * BPS_) as c_int;
+    let L = *dst.offset(-1 + 3 * BPS_) as c_int;
+    let X = *dst.offset(-1 - BPS_) as c_int;
+    let A = *dst.offset(0 - BPS_) as c_int;
+    let B = *dst.offset(1 - BPS_) as c_int;
+    let C = *dst.offset(2 - BPS_) as c_int;
+
+    dst!(dst, (0, 0), (2, 1) = AVG2(I, X));
+    dst!(dst, (0, 1), (2, 2) = AVG2(J, I));
+    dst!(dst, (0, 2), (2, 3) = AVG2(K, J));
+    dst!(dst, (0, 3) = AVG2(L, K));
+
+    dst!(dst, (3, 0) = AVG3(A, B, C));
+    dst!(dst, (2, 0) = AVG3(X, A, B));
+    dst!(dst, (1, 0), (3, 1) = AVG3(I, X, A));
+    dst!(dst, (1, 1), (3, 2) = AVG3(J, I, X));
+    dst!(dst, (1, 2), (3, 3) = AVG3(K, J, I));
+    dst!(dst, (1, 3) = AVG3(L, K, J));
+}
+
+#[no_mangle]
+pub static mut VP8PredLuma4: [VP8PredFunc; NUM_BMODES as usize] = [None; NUM_BMODES as usize];
+
+//------------------------------------------------------------------------------
+// Chroma
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VE8uv_C(dst: *mut u8) {
+    // vertical
+    for j in 0..8 {
+        ptr::copy_nonoverlapping(dst.offset(-BPS_), dst.offset(j * BPS_), 8);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HE8uv_C(mut dst: *mut u8) {
+    // horizontal
+    for _ in 0..8 {
+        ptr::write_bytes(dst, *dst.offset(-1), 8);
+        dst = dst.offset(BPS_);
+    }
+}
+
+// helper for chroma-DC predictions
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn Put8x8uv(value: u8, dst: *mut u8) {
+    for j in 0..8 {
+        ptr::write_bytes(dst.offset(j * BPS_), value, 8);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC8uv_C(dst: *mut u8) {
+    // DC
+    let mut dc0: c_int = 8;
+    for i in 0..8 {
+        dc0 += *dst.offset(i - BPS_) as c_int + *dst.offset(-1 + i * BPS_) as c_int;
+    }
+    Put8x8uv((dc0 >> 4) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC8uvNoLeft_C(dst: *mut u8) {
+    // DC with no left samples
+    let mut dc0: c_int = 4;
+    for i in 0..8 {
+        dc0 += *dst.offset(i - BPS_) as c_int;
+    }
+    Put8x8uv((dc0 >> 3) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC8uvNoTop_C(dst: *mut u8) {
+    // DC with no top samples
+    let mut dc0: c_int = 4;
+    for i in 0..8 {
+        dc0 += *dst.offset(-1 + i * BPS_) as c_int;
+    }
+    Put8x8uv((dc0 >> 3) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DC8uvNoTopLeft_C(dst: *mut u8) {
+    // DC with nothing
+    Put8x8uv(0x80, dst);
+}
+
+#[no_mangle]
+pub static mut VP8PredChroma8: [VP8PredFunc; NUM_B_DC_MODES as usize] =
+    [None; NUM_B_DC_MODES as usize];
+
+//------------------------------------------------------------------------------
+// Edge filtering functions
+
+#[inline]
+unsafe fn sclip1(v: c_int) -> c_int {
+    *VP8ksclip1.offset(v as isize) as c_int
+}
+
+#[inline]
+unsafe fn sclip2(v: c_int) -> c_int {
+    *VP8ksclip2.offset(v as isize) as c_int
+}
+
+#[inline]
+unsafe fn clip1(v: c_int) -> u8 {
+    *VP8kclip1.offset(v as isize)
+}
+
+#[inline]
+unsafe fn abs0(v: c_int) -> c_int {
+    *VP8kabs0.offset(v as isize) as c_int
+}
+
+// 4 pixels in, 2 pixels out
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoFilter2_C(p: *mut u8, step: isize) {
+    let p1 = *p.offset(-2 * step) as c_int;
+    let p0 = *p.offset(-step) as c_int;
+    let q0 = *p.offset(0) as c_int;
+    let q1 = *p.offset(step) as c_int;
+    let a = 3 * (q0 - p0) + sclip1(p1 - q1); // in [-893,892]
+    let a1 = sclip2((a + 4) >> 3); // in [-16,15]
+    let a2 = sclip2((a + 3) >> 3);
+    *p.offset(-step) = clip1(p0 + a2);
+    *p.offset(0) = clip1(q0 - a1);
+}
+
+// 4 pixels in, 4 pixels out
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoFilter4_C(p: *mut u8, step: isize) {
+    let p1 = *p.offset(-2 * step) as c_int;
+    let p0 = *p.offset(-step) as c_int;
+    let q0 = *p.offset(0) as c_int;
+    let q1 = *p.offset(step) as c_int;
+    let a = 3 * (q0 - p0);
+    let a1 = sclip2((a + 4) >> 3);
+    let a2 = sclip2((a + 3) >> 3);
+    let a3 = (a1 + 1) >> 1;
+    *p.offset(-2 * step) = clip1(p1 + a3);
+    *p.offset(-step) = clip1(p0 + a2);
+    *p.offset(0) = clip1(q0 - a1);
+    *p.offset(step) = clip1(q1 - a3);
+}
+
+// 6 pixels in, 6 pixels out
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoFilter6_C(p: *mut u8, step: isize) {
+    let p2 = *p.offset(-3 * step) as c_int;
+    let p1 = *p.offset(-2 * step) as c_int;
+    let p0 = *p.offset(-step) as c_int;
+    let q0 = *p.offset(0) as c_int;
+    let q1 = *p.offset(step) as c_int;
+    let q2 = *p.offset(2 * step) as c_int;
+    let a = sclip1(3 * (q0 - p0) + sclip1(p1 - q1));
+    // a is in [-128,127], a1 in [-27,27], a2 in [-18,18] and a3 in [-9,9]
+    let a1 = (27 * a + 63) >> 7; // eq. to ((3 * a + 7) * 9) >> 7
+    let a2 = (18 * a + 63) >> 7; // eq. to ((2 * a + 7) * 9) >> 7
+    let a3 = (9 * a + 63) >> 7; // eq. to ((1 * a + 7) * 9) >> 7
+    *p.offset(-3 * step) = clip1(p2 + a3);
+    *p.offset(-2 * step) = clip1(p1 + a2);
+    *p.offset(-step) = clip1(p0 + a1);
+    *p.offset(0) = clip1(q0 - a1);
+    *p.offset(step) = clip1(q1 - a2);
+    *p.offset(2 * step) = clip1(q2 - a3);
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn Hev(p: *const u8, step: isize, thresh: c_int) -> bool {
+    let p1 = *p.offset(-2 * step) as c_int;
+    let p0 = *p.offset(-step) as c_int;
+    let q0 = *p.offset(0) as c_int;
+    let q1 = *p.offset(step) as c_int;
+    (abs0(p1 - p0) > thresh) || (abs0(q1 - q0) > thresh)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn NeedsFilter_C(p: *const u8, step: isize, t: c_int) -> bool {
+    let p1 = *p.offset(-2 * step) as c_int;
+    let p0 = *p.offset(-step) as c_int;
+    let q0 = *p.offset(0) as c_int;
+    let q1 = *p.offset(step) as c_int;
+    (4 * abs0(p0 - q0) + abs0(p1 - q1)) <= t
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn NeedsFilter2_C(p: *const u8, step: isize, t: c_int, it: c_int) -> bool {
+    let p3 = *p.offset(-4 * step) as c_int;
+    let p2 = *p.offset(-3 * step) as c_int;
+    let p1 = *p.offset(-2 * step) as c_int;
+    let p0 = *p.offset(-step) as c_int;
+    let q0 = *p.offset(0) as c_int;
+    let q1 = *p.offset(step) as c_int;
+    let q2 = *p.offset(2 * step) as c_int;
+    let q3 = *p.offset(3 * step) as c_int;
+    if (4 * abs0(p0 - q0) + abs0(p1 - q1)) > t {
+        return false;
+    }
+    abs0(p3 - p2) <= it
+        && abs0(p2 - p1) <= it
+        && abs0(p1 - p0) <= it
+        && abs0(q3 - q2) <= it
+        && abs0(q2 - q1) <= it
+        && abs0(q1 - q0) <= it
+}
+
+//------------------------------------------------------------------------------
+// Simple In-loop filtering (Paragraph 15.2)
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SimpleVFilter16_C(p: *mut u8, stride: c_int, thresh: c_int) {
+    let stride = stride as isize;
+    let thresh2 = 2 * thresh + 1;
+    for i in 0..16 {
+        if NeedsFilter_C(p.offset(i), stride, thresh2) {
+            DoFilter2_C(p.offset(i), stride);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SimpleHFilter16_C(p: *mut u8, stride: c_int, thresh: c_int) {
+    let stride = stride as isize;
+    let thresh2 = 2 * thresh + 1;
+    for i in 0..16 {
+        if NeedsFilter_C(p.offset(i * stride), 1, thresh2) {
+            DoFilter2_C(p.offset(i * stride), 1);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SimpleVFilter16i_C(mut p: *mut u8, stride: c_int, thresh: c_int) {
+    for _ in 0..3 {
+        p = p.offset(4 * stride as isize);
+        SimpleVFilter16_C(p, stride, thresh);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SimpleHFilter16i_C(mut p: *mut u8, stride: c_int, thresh: c_int) {
+    for _ in 0..3 {
+        p = p.offset(4);
+        SimpleHFilter16_C(p, stride, thresh);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Complex In-loop filtering (Paragraph 15.3)
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn FilterLoop26_C(
+    mut p: *mut u8,
+    hstride: isize,
+    vstride: isize,
+    size: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let thresh2 = 2 * thresh + 1;
+    for _ in 0..size {
+        if NeedsFilter2_C(p, hstride, thresh2, ithresh) {
+            if Hev(p, hstride, hev_thresh) {
+                DoFilter2_C(p, hstride);
+            } else {
+                DoFilter6_C(p, hstride);
+            }
+        }
+        p = p.offset(vstride);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn FilterLoop24_C(
+    mut p: *mut u8,
+    hstride: isize,
+    vstride: isize,
+    size: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let thresh2 = 2 * thresh + 1;
+    for _ in 0..size {
+        if NeedsFilter2_C(p, hstride, thresh2, ithresh) {
+            if Hev(p, hstride, hev_thresh) {
+                DoFilter2_C(p, hstride);
+            } else {
+                DoFilter4_C(p, hstride);
+            }
+        }
+        p = p.offset(vstride);
+    }
+}
+
+// on macroblock edges
+#[allow(non_snake_case)]
+unsafe extern "C" fn VFilter16_C(
+    p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    FilterLoop26_C(p, stride as isize, 1, 16, thresh, ithresh, hev_thresh);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HFilter16_C(
+    p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    FilterLoop26_C(p, 1, stride as isize, 16, thresh, ithresh, hev_thresh);
+}
+
+// on three inner edges
+#[allow(non_snake_case)]
+unsafe extern "C" fn VFilter16i_C(
+    mut p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    for _ in 0..3 {
+        p = p.offset(4 * stride as isize);
+        FilterLoop24_C(p, stride as isize, 1, 16, thresh, ithresh, hev_thresh);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HFilter16i_C(
+    mut p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    for _ in 0..3 {
+        p = p.offset(4);
+        FilterLoop24_C(p, 1, stride as isize, 16, thresh, ithresh, hev_thresh);
+    }
+}
+
+// 8-pixels wide variant, for chroma filtering
+#[allow(non_snake_case)]
+unsafe extern "C" fn VFilter8_C(
+    u: *mut u8,
+    v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    FilterLoop26_C(u, stride as isize, 1, 8, thresh, ithresh, hev_thresh);
+    FilterLoop26_C(v, stride as isize, 1, 8, thresh, ithresh, hev_thresh);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HFilter8_C(
+    u: *mut u8,
+    v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    FilterLoop26_C(u, 1, stride as isize, 8, thresh, ithresh, hev_thresh);
+    FilterLoop26_C(v, 1, stride as isize, 8, thresh, ithresh, hev_thresh);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VFilter8i_C(
+    u: *mut u8,
+    v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    FilterLoop24_C(
+        u.offset(4 * stride),
+        stride,
+        1,
+        8,
+        thresh,
+        ithresh,
+        hev_thresh,
+    );
+    FilterLoop24_C(
+        v.offset(4 * stride),
+        stride,
+        1,
+        8,
+        thresh,
+        ithresh,
+        hev_thresh,
+    );
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HFilter8i_C(
+    u: *mut u8,
+    v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    FilterLoop24_C(u.offset(4), 1, stride, 8, thresh, ithresh, hev_thresh);
+    FilterLoop24_C(v.offset(4), 1, stride, 8, thresh, ithresh, hev_thresh);
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn DitherCombine8x8_C(
+    mut dither: *const u8,
+    mut dst: *mut u8,
+    dst_stride: c_int,
+) {
+    for _ in 0..8 {
+        for i in 0..8 {
+            let delta0 = *dither.offset(i) as c_int - VP8_DITHER_AMP_CENTER;
+            let delta1 = (delta0 + VP8_DITHER_DESCALE_ROUNDER) >> VP8_DITHER_DESCALE;
+            *dst.offset(i) = clip_8b(*dst.offset(i) as c_int + delta1);
+        }
+        dst = dst.offset(dst_stride as isize);
+        dither = dither.offset(8);
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub static mut VP8Transform: VP8DecIdct2 = None;
+#[no_mangle]
+pub static mut VP8TransformAC3: VP8DecIdct = None;
+#[no_mangle]
+pub static mut VP8TransformUV: VP8DecIdct = None;
+#[no_mangle]
+pub static mut VP8TransformDC: VP8DecIdct = None;
+#[no_mangle]
+pub static mut VP8TransformDCUV: VP8DecIdct = None;
+
+// on outer edge
+#[no_mangle]
+pub static mut VP8VFilter16: VP8LumaFilterFunc = None;
+#[no_mangle]
+pub static mut VP8HFilter16: VP8LumaFilterFunc = None;
+#[no_mangle]
+pub static mut VP8VFilter8: VP8ChromaFilterFunc = None;
+#[no_mangle]
+pub static mut VP8HFilter8: VP8ChromaFilterFunc = None;
+// on inner edge
+#[no_mangle]
+pub static mut VP8VFilter16i: VP8LumaFilterFunc = None; // filtering 3 inner edges altogether
+#[no_mangle]
+pub static mut VP8HFilter16i: VP8LumaFilterFunc = None;
+#[no_mangle]
+pub static mut VP8VFilter8i: VP8ChromaFilterFunc = None; // filtering u and v altogether
+#[no_mangle]
+pub static mut VP8HFilter8i: VP8ChromaFilterFunc = None;
+// simple filter (only for luma)
+#[no_mangle]
+pub static mut VP8SimpleVFilter16: VP8SimpleFilterFunc = None;
+#[no_mangle]
+pub static mut VP8SimpleHFilter16: VP8SimpleFilterFunc = None;
+#[no_mangle]
+pub static mut VP8SimpleVFilter16i: VP8SimpleFilterFunc = None; // filter 3 inner edges
+#[no_mangle]
+pub static mut VP8SimpleHFilter16i: VP8SimpleFilterFunc = None;
+
+// Dithering. Combines dithering values (centered around 128) with dst[],
+// according to: dst[] = clip(dst[] + (((dither[]-128) + 8) >> 4)
+#[no_mangle]
+pub static mut VP8DitherCombine8x8: Option<
+    unsafe extern "C" fn(dither: *const u8, dst: *mut u8, dst_stride: c_int),
+> = None;
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn VP8DspInitNEON();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8DspInitMIPS32();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8DspInitMIPSdspR2();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8DspInitMSA();
+}
+
+webp_dsp_init_func! {
+    // Init critical function pointers and look-up tables.
+    #[no_mangle]
+    pub fn VP8DspInit() {
+        VP8InitClipTables();
+
+        VP8TransformWHT = Some(TransformWHT_C);
+        VP8Transform = Some(TransformTwo_C);
+        VP8TransformDC = Some(TransformDC_C);
+        VP8TransformAC3 = Some(TransformAC3_C);
+        VP8TransformUV = Some(TransformUV_C);
+        VP8TransformDCUV = Some(TransformDCUV_C);
+
+        VP8VFilter16 = Some(VFilter16_C);
+        VP8VFilter16i = Some(VFilter16i_C);
+        VP8HFilter16 = Some(HFilter16_C);
+        VP8VFilter8 = Some(VFilter8_C);
+        VP8VFilter8i = Some(VFilter8i_C);
+        VP8SimpleVFilter16 = Some(SimpleVFilter16_C);
+        VP8SimpleHFilter16 = Some(SimpleHFilter16_C);
+        VP8SimpleVFilter16i = Some(SimpleVFilter16i_C);
+        VP8SimpleHFilter16i = Some(SimpleHFilter16i_C);
+
+        VP8HFilter16i = Some(HFilter16i_C);
+        VP8HFilter8 = Some(HFilter8_C);
+        VP8HFilter8i = Some(HFilter8i_C);
+
+        VP8PredLuma4[0] = Some(DC4_C);
+        VP8PredLuma4[1] = Some(TM4_C);
+        VP8PredLuma4[2] = Some(VE4_C);
+        VP8PredLuma4[4] = Some(RD4_C);
+        VP8PredLuma4[6] = Some(LD4_C);
+
+        VP8PredLuma4[3] = Some(HE4_C);
+        VP8PredLuma4[5] = Some(VR4_C);
+        VP8PredLuma4[7] = Some(VL4_C);
+        VP8PredLuma4[8] = Some(HD4_C);
+        VP8PredLuma4[9] = Some(HU4_C);
+
+        VP8PredLuma16[0] = Some(DC16_C);
+        VP8PredLuma16[1] = Some(TM16_C);
+        VP8PredLuma16[2] = Some(VE16_C);
+        VP8PredLuma16[3] = Some(HE16_C);
+        VP8PredLuma16[4] = Some(DC16NoTop_C);
+        VP8PredLuma16[5] = Some(DC16NoLeft_C);
+        VP8PredLuma16[6] = Some(DC16NoTopLeft_C);
+
+        VP8PredChroma8[0] = Some(DC8uv_C);
+        VP8PredChroma8[1] = Some(TM8uv_C);
+        VP8PredChroma8[2] = Some(VE8uv_C);
+        VP8PredChroma8[3] = Some(HE8uv_C);
+        VP8PredChroma8[4] = Some(DC8uvNoTop_C);
+        VP8PredChroma8[5] = Some(DC8uvNoLeft_C);
+        VP8PredChroma8[6] = Some(DC8uvNoTopLeft_C);
+
+        VP8DitherCombine8x8 = Some(DitherCombine8x8_C);
+
+        // If defined, use CPUInfo() to overwrite some pointers with faster versions.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    VP8DspInitSSE2();
+                    if get_cpu_info(kSSE4_1) != 0 {
+                        VP8DspInitSSE41();
+                    }
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPS32) != 0 {
+                    VP8DspInitMIPS32();
+                }
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    VP8DspInitMIPSdspR2();
+                }
+                if get_cpu_info(kMSA) != 0 {
+                    VP8DspInitMSA();
+                }
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "pure-rust-decoder")))]
+        {
+            VP8DspInitNEON();
+        }
+        #[cfg(all(target_arch = "arm", not(feature = "pure-rust-decoder")))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    VP8DspInitNEON();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(non_upper_case_globals)]
+    extern "C" {
+        static WebPRef_VP8Transform: VP8DecIdct2;
+        static WebPRef_VP8TransformAC3: VP8DecIdct;
+        static WebPRef_VP8TransformUV: VP8DecIdct;
+        static WebPRef_VP8TransformDC: VP8DecIdct;
+        static WebPRef_VP8TransformDCUV: VP8DecIdct;
+        static WebPRef_VP8TransformWHT: VP8WHT;
+        static WebPRef_VP8PredLuma16: [VP8PredFunc; NUM_B_DC_MODES as usize];
+        static WebPRef_VP8PredChroma8: [VP8PredFunc; NUM_B_DC_MODES as usize];
+        static WebPRef_VP8PredLuma4: [VP8PredFunc; NUM_BMODES as usize];
+        static WebPRef_VP8SimpleVFilter16: VP8SimpleFilterFunc;
+        static WebPRef_VP8SimpleHFilter16: VP8SimpleFilterFunc;
+        static WebPRef_VP8SimpleVFilter16i: VP8SimpleFilterFunc;
+        static WebPRef_VP8SimpleHFilter16i: VP8SimpleFilterFunc;
+        static WebPRef_VP8VFilter16: VP8LumaFilterFunc;
+        static WebPRef_VP8HFilter16: VP8LumaFilterFunc;
+        static WebPRef_VP8VFilter16i: VP8LumaFilterFunc;
+        static WebPRef_VP8HFilter16i: VP8LumaFilterFunc;
+        static WebPRef_VP8VFilter8: VP8ChromaFilterFunc;
+        static WebPRef_VP8HFilter8: VP8ChromaFilterFunc;
+        static WebPRef_VP8VFilter8i: VP8ChromaFilterFunc;
+        static WebPRef_VP8HFilter8i: VP8ChromaFilterFunc;
+        static WebPRef_VP8DitherCombine8x8:
+            Option<unsafe extern "C" fn(dither: *const u8, dst: *mut u8, dst_stride: c_int)>;
+        fn WebPRef_VP8DspInit();
+    }
+
+    // A work area of 20 rows of BPS pixels, with the block starting at
+    // (4, 4) so that the top, left and top-right samples are available.
+    const WORK_SIZE: usize = 20 * BPS as usize;
+    const WORK_OFFSET: usize = 4 * BPS as usize + 4;
+
+    // Mostly smooth samples, with the odd outlier to trigger the filters' cut-offs.
+    fn samples(rng: &mut Lcg, len: usize) -> Vec<u8> {
+        let base = rng.next() as u8;
+        (0..len)
+            .map(|_| match rng.next() % 8 {
+                0 => rng.next() as u8,
+                _ => base.wrapping_add((rng.next() % 16) as u8),
+            })
+            .collect()
+    }
+
+    // The work area after running 'func' on the block of a copy of 'work'.
+    fn run(work: &[u8], func: &dyn Fn(*mut u8)) -> Vec<u8> {
+        let mut out = work.to_vec();
+        func(unsafe { out.as_mut_ptr().add(WORK_OFFSET) });
+        out
+    }
+
+    fn check_block(rng: &mut Lcg, ours: &dyn Fn(*mut u8), theirs: &dyn Fn(*mut u8), what: &str) {
+        let work = samples(rng, WORK_SIZE);
+        assert!(run(&work, ours) == run(&work, theirs), "{}", what);
+    }
+
+    // The indices only pick the reference function to compare with.
+    #[test]
+    #[cfg_attr(not(feature = "c-reference"), allow(clippy::unused_enumerate_index))]
+    fn test_dec_dsp_parity() {
+        #[cfg(feature = "c-reference")]
+        unsafe {
+            WebPRef_VP8DspInit();
+        }
+        let luma16: [unsafe extern "C" fn(*mut u8); NUM_B_DC_MODES as usize] = [
+            DC16_C,
+            TM16_C,
+            VE16_C,
+            HE16_C,
+            DC16NoTop_C,
+            DC16NoLeft_C,
+            DC16NoTopLeft_C,
+        ];
+        let chroma8: [unsafe extern "C" fn(*mut u8); NUM_B_DC_MODES as usize] = [
+            DC8uv_C,
+            TM8uv_C,
+            VE8uv_C,
+            HE8uv_C,
+            DC8uvNoTop_C,
+            DC8uvNoLeft_C,
+            DC8uvNoTopLeft_C,
+        ];
+        let luma4: [unsafe extern "C" fn(*mut u8); NUM_BMODES as usize] = [
+            DC4_C, TM4_C, VE4_C, HE4_C, RD4_C, VR4_C, LD4_C, VL4_C, HD4_C, HU4_C,
+        ];
+        let preds: [(&str, &[unsafe extern "C" fn(*mut u8)]); 3] = [
+            ("luma16", &luma16),
+            ("chroma8", &chroma8),
+            ("luma4", &luma4),
+        ];
+        #[cfg(feature = "c-reference")]
+        let ref_preds: [&[VP8PredFunc]; 3] = unsafe {
+            [
+                &WebPRef_VP8PredLuma16,
+                &WebPRef_VP8PredChroma8,
+                &WebPRef_VP8PredLuma4,
+            ]
+        };
+        let simple_filters: [unsafe extern "C" fn(*mut u8, c_int, c_int); 4] = [
+            SimpleVFilter16_C,
+            SimpleHFilter16_C,
+            SimpleVFilter16i_C,
+            SimpleHFilter16i_C,
+        ];
+        #[cfg(feature = "c-reference")]
+        let ref_simple_filters: [VP8SimpleFilterFunc; 4] = unsafe {
+            [
+                WebPRef_VP8SimpleVFilter16,
+                WebPRef_VP8SimpleHFilter16,
+                WebPRef_VP8SimpleVFilter16i,
+                WebPRef_VP8SimpleHFilter16i,
+            ]
+        };
+        let luma_filters: [unsafe extern "C" fn(*mut u8, c_int, c_int, c_int, c_int); 4] =
+            [VFilter16_C, HFilter16_C, VFilter16i_C, HFilter16i_C];
+        #[cfg(feature = "c-reference")]
+        let ref_luma_filters: [VP8LumaFilterFunc; 4] = unsafe {
+            [
+                WebPRef_VP8VFilter16,
+                WebPRef_VP8HFilter16,
+                WebPRef_VP8VFilter16i,
+                WebPRef_VP8HFilter16i,
+            ]
+        };
+        let chroma_filters: [unsafe extern "C" fn(*mut u8, *mut u8, c_int, c_int, c_int, c_int);
+            4] = [VFilter8_C, HFilter8_C, VFilter8i_C, HFilter8i_C];
+        #[cfg(feature = "c-reference")]
+        let ref_chroma_filters: [VP8ChromaFilterFunc; 4] = unsafe {
+            [
+                WebPRef_VP8VFilter8,
+                WebPRef_VP8HFilter8,
+                WebPRef_VP8VFilter8i,
+                WebPRef_VP8HFilter8i,
+            ]
+        };
+        let transforms: [unsafe extern "C" fn(*const i16, *mut u8); 4] = [
+            TransformAC3_C,
+            TransformDC_C,
+            TransformUV_C,
+            TransformDCUV_C,
+        ];
+        #[cfg(feature = "c-reference")]
+        let ref_transforms: [VP8DecIdct; 4] = unsafe {
+            [
+                WebPRef_VP8TransformAC3,
+                WebPRef_VP8TransformDC,
+                WebPRef_VP8TransformUV,
+                WebPRef_VP8TransformDCUV,
+            ]
+        };
+
+        let mut digest = Digest::new();
+        let mut rng = Lcg(5);
+        for _ in 0..200 {
+            for (_i, &(_what, preds)) in preds.iter().enumerate() {
+                for (_mode, &pred) in preds.iter().enumerate() {
+                    let work = samples(&mut rng, WORK_SIZE);
+                    let out = run(&work, &|dst| unsafe { pred(dst) });
+                    digest.update(&out);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let ref_pred = ref_preds[_i][_mode].unwrap();
+                        let ref_out = run(&work, &|dst| unsafe { ref_pred(dst) });
+                        assert!(out == ref_out, "{}", _what);
+                    }
+                }
+            }
+
+            let coeffs: Vec<i16> = (0..4 * 16)
+                .map(|_| (rng.next() % 4096) as i16 - 2048)
+                .collect();
+            let coeffs = coeffs.as_ptr();
+            unsafe {
+                // TransformUV_C and TransformDCUV_C dispatch through the pointers.
+                VP8DspInit();
+                for (_i, &transform) in transforms.iter().enumerate() {
+                    let work = samples(&mut rng, WORK_SIZE);
+                    let out = run(&work, &|dst| transform(coeffs, dst));
+                    digest.update(&out);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let ref_transform = ref_transforms[_i].unwrap();
+                        let ref_out = run(&work, &|dst| ref_transform(coeffs, dst));
+                        assert!(out == ref_out, "transform");
+                    }
+                }
+                for do_two in 0..2 {
+                    let work = samples(&mut rng, WORK_SIZE);
+                    let out = run(&work, &|dst| TransformTwo_C(coeffs, dst, do_two));
+                    digest.update(&out);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let ref_transform = WebPRef_VP8Transform.unwrap();
+                        let ref_out = run(&work, &|dst| ref_transform(coeffs, dst, do_two));
+                        assert!(out == ref_out, "transform");
+                    }
+                }
+                let mut out = [0i16; 16 * 16];
+                TransformWHT_C(coeffs, out.as_mut_ptr());
+                for coeff in out.iter() {
+                    digest.update(&coeff.to_le_bytes());
+                }
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut ref_out = [0i16; 16 * 16];
+                    WebPRef_VP8TransformWHT.unwrap()(coeffs, ref_out.as_mut_ptr());
+                    assert!(out[..] == ref_out[..]);
+                }
+
+                let dither: Vec<u8> = rng.bytes(64);
+                let work = samples(&mut rng, WORK_SIZE);
+                let out = run(&work, &|dst| DitherCombine8x8_C(dither.as_ptr(), dst, BPS));
+                digest.update(&out);
+                #[cfg(feature = "c-reference")]
+                {
+                    let ref_dither = WebPRef_VP8DitherCombine8x8.unwrap();
+                    let ref_out = run(&work, &|dst| ref_dither(dither.as_ptr(), dst, BPS));
+                    assert!(out == ref_out, "dither");
+                }
+            }
+
+            let thresh = (rng.next() % 64) as c_int;
+            let ithresh = (rng.next() % 64) as c_int;
+            let hev_thresh = (rng.next() % 64) as c_int;
+            for (_i, &filter) in simple_filters.iter().enumerate() {
+                let work = samples(&mut rng, WORK_SIZE);
+                let out = run(&work, &|p| unsafe { filter(p, BPS, thresh) });
+                digest.update(&out);
+                #[cfg(feature = "c-reference")]
+                {
+                    let ref_filter = ref_simple_filters[_i].unwrap();
+                    let ref_out = run(&work, &|p| unsafe { ref_filter(p, BPS, thresh) });
+                    assert!(out == ref_out, "simple filter");
+                }
+            }
+            for (_i, &filter) in luma_filters.iter().enumerate() {
+                let work = samples(&mut rng, WORK_SIZE);
+                let out = run(&work, &|p| unsafe {
+                    filter(p, BPS, thresh, ithresh, hev_thresh)
+                });
+                digest.update(&out);
+                #[cfg(feature = "c-reference")]
+                {
+                    let ref_filter = ref_luma_filters[_i].unwrap();
+                    let ref_out = run(&work, &|p| unsafe {
+                        ref_filter(p, BPS, thresh, ithresh, hev_thresh)
+                    });
+                    assert!(out == ref_out, "luma filter");
+                }
+            }
+            let v = 8 * BPS as usize;
+            for (_i, &filter) in chroma_filters.iter().enumerate() {
+                let work = samples(&mut rng, WORK_SIZE);
+                let out = run(&work, &|p| unsafe {
+                    filter(p, p.add(v), BPS, thresh, ithresh, hev_thresh)
+                });
+                digest.update(&out);
+                #[cfg(feature = "c-reference")]
+                {
+                    let ref_filter = ref_chroma_filters[_i].unwrap();
+                    let ref_out = run(&work, &|p| unsafe {
+                        ref_filter(p, p.add(v), BPS, thresh, ithresh, hev_thresh)
+                    });
+                    assert!(out == ref_out, "chroma filter");
+                }
+            }
+        }
+        check_parity("dsp::dec::test_dec_dsp_parity", digest);
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_dec_dsp_simd() {
+        use dsp::dec_sse2::*;
+        use dsp::dec_sse41::*;
+
+        type Pred = unsafe extern "C" fn(*mut u8);
+        type SimpleFilter = unsafe extern "C" fn(*mut u8, c_int, c_int);
+        type LumaFilter = unsafe extern "C" fn(*mut u8, c_int, c_int, c_int, c_int);
+        type ChromaFilter = unsafe extern "C" fn(*mut u8, *mut u8, c_int, c_int, c_int, c_int);
+
+        let mut preds: Vec<(Pred, Pred)> = Vec::new();
+        let mut simple_filters: Vec<(SimpleFilter, SimpleFilter)> = Vec::new();
+        let mut luma_filters: Vec<(LumaFilter, LumaFilter)> = Vec::new();
+        let mut chroma_filters: Vec<(ChromaFilter, ChromaFilter)> = Vec::new();
+        let mut transforms: Vec<(VP8DecIdct2, VP8DecIdct2)> = Vec::new();
+        if is_x86_feature_detected!("sse2") {
+            preds.extend_from_slice(&[
+                (TM4_SSE2, TM4_C),
+                (VE4_SSE2, VE4_C),
+                (RD4_SSE2, RD4_C),
+                (VR4_SSE2, VR4_C),
+                (LD4_SSE2, LD4_C),
+                (VL4_SSE2, VL4_C),
+                (DC16_SSE2, DC16_C),
+                (TM16_SSE2, TM16_C),
+                (VE16_SSE2, VE16_C),
+                (HE16_SSE2, HE16_C),
+                (DC16NoTop_SSE2, DC16NoTop_C),
+                (DC16NoLeft_SSE2, DC16NoLeft_C),
+                (DC16NoTopLeft_SSE2, DC16NoTopLeft_C),
+                (DC8uv_SSE2, DC8uv_C),
+                (TM8uv_SSE2, TM8uv_C),
+                (VE8uv_SSE2, VE8uv_C),
+                (DC8uvNoTop_SSE2, DC8uvNoTop_C),
+                (DC8uvNoLeft_SSE2, DC8uvNoLeft_C),
+                (DC8uvNoTopLeft_SSE2, DC8uvNoTopLeft_C),
+            ]);
+            simple_filters.extend_from_slice(&[
+                (SimpleVFilter16_SSE2, SimpleVFilter16_C),
+                (SimpleHFilter16_SSE2, SimpleHFilter16_C),
+                (SimpleVFilter16i_SSE2, SimpleVFilter16i_C),
+                (SimpleHFilter16i_SSE2, SimpleHFilter16i_C),
+            ]);
+            luma_filters.extend_from_slice(&[
+                (VFilter16_SSE2, VFilter16_C),
+                (HFilter16_SSE2, HFilter16_C),
+                (VFilter16i_SSE2, VFilter16i_C),
+                (HFilter16i_SSE2, HFilter16i_C),
+            ]);
+            chroma_filters.extend_from_slice(&[
+                (VFilter8_SSE2, VFilter8_C),
+                (HFilter8_SSE2, HFilter8_C),
+                (VFilter8i_SSE2, VFilter8i_C),
+                (HFilter8i_SSE2, HFilter8i_C),
+            ]);
+            transforms.push((Some(Transform_SSE2), Some(TransformTwo_C)));
+        }
+        if is_x86_feature_detected!("sse4.1") {
+            preds.push((HE16_SSE41, HE16_C));
+        }
+
+        let mut rng = Lcg(7);
+        for _ in 0..200 {
+            for &(pred, pred_c) in &preds {
+                check_block(
+                    &mut rng,
+                    &|dst| unsafe { pred(dst) },
+                    &|dst| unsafe { pred_c(dst) },
+                    "pred",
+                );
+            }
+
+            let coeffs: Vec<i16> = (0..4 * 16)
+                .map(|_| (rng.next() % 4096) as i16 - 2048)
+                .collect();
+            let coeffs = coeffs.as_ptr();
+            for &(transform, transform_c) in &transforms {
+                let (transform, transform_c) = (transform.unwrap(), transform_c.unwrap());
+                for do_two in 0..2 {
+                    check_block(
+                        &mut rng,
+                        &|dst| unsafe { transform(coeffs, dst, do_two) },
+                        &|dst| unsafe { transform_c(coeffs, dst, do_two) },
+                        "transform",
+                    );
+                }
+            }
+
+            let thresh = (rng.next() % 64) as c_int;
+            let ithresh = (rng.next() % 64) as c_int;
+            let hev_thresh = (rng.next() % 64) as c_int;
+            for &(filter, filter_c) in &simple_filters {
+                check_block(
+                    &mut rng,
+                    &|p| unsafe { filter(p, BPS, thresh) },
+                    &|p| unsafe { filter_c(p, BPS, thresh) },
+                    "simple filter",
+                );
+            }
+            for &(filter, filter_c) in &luma_filters {
+                check_block(
+                    &mut rng,
+                    &|p| unsafe { filter(p, BPS, thresh, ithresh, hev_thresh) },
+                    &|p| unsafe { filter_c(p, BPS, thresh, ithresh, hev_thresh) },
+                    "luma filter",
+                );
+            }
+            for &(filter, filter_c) in &chroma_filters {
+                let v = 8 * BPS as usize;
+                check_block(
+                    &mut rng,
+                    &|p| unsafe { filter(p, p.add(v), BPS, thresh, ithresh, hev_thresh) },
+                    &|p| unsafe { filter_c(p, p.add(v), BPS, thresh, ithresh, hev_thresh) },
+                    "chroma filter",
+                );
+            }
+        }
+    }
+}
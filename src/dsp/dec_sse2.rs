@@ -0,0 +1,1540 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE2 version of some decoding functions (idct, loop filtering).
+//
+// Author: somnath@google.com (Somnath Banerjee)
+//         cduvivier@google.com (Christian Duvivier)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(
+    clippy::identity_op,
+    clippy::erasing_op,
+    clippy::neg_multiply,
+    clippy::too_many_arguments
+)]
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::os::raw::*;
+
+use dsp::common_sse2::VP8Transpose_2_4x4_16b;
+use dsp::dec::{
+    VP8HFilter16, VP8HFilter16i, VP8HFilter8, VP8HFilter8i, VP8PredChroma8, VP8PredLuma16,
+    VP8PredLuma4, VP8SimpleHFilter16, VP8SimpleHFilter16i, VP8SimpleVFilter16, VP8SimpleVFilter16i,
+    VP8Transform, VP8VFilter16, VP8VFilter16i, VP8VFilter8, VP8VFilter8i,
+};
+use dsp::dsp::BPS;
+use utils::utils::{WebPMemToUint32, WebPUint32ToMem};
+
+const BPS_: isize = BPS as isize;
+
+//------------------------------------------------------------------------------
+// Transforms (Paragraph 14.4)
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Transform_SSE2(in_: *const i16, dst: *mut u8, do_two: c_int) {
+    // This implementation makes use of 16-bit fixed point versions of two
+    // multiply constants:
+    //    K1 = sqrt(2) * cos (pi/8) ~= 85627 / 2^16
+    //    K2 = sqrt(2) * sin (pi/8) ~= 35468 / 2^16
+    //
+    // To be able to use signed 16-bit integers, we use the following trick to
+    // have constants within range:
+    // - Associated constants are obtained by subtracting the 16-bit fixed point
+    //   version of one:
+    //      k = K - (1 << 16)  =>  K = k + (1 << 16)
+    //      K1 = 85267  =>  k1 =  20091
+    //      K2 = 35468  =>  k2 = -30068
+    // - The multiplication of a variable by a constant become the sum of the
+    //   variable and the multiplication of that variable by the associated
+    //   constant:
+    //      (x * K) >> 16 = (x * (k + (1 << 16))) >> 16 = ((x * k ) >> 16) + x
+    let k1 = _mm_set1_epi16(20091);
+    let k2 = _mm_set1_epi16(-30068);
+    let mut T0 = _mm_setzero_si128();
+    let mut T1 = _mm_setzero_si128();
+    let mut T2 = _mm_setzero_si128();
+    let mut T3 = _mm_setzero_si128();
+
+    // Load and concatenate the transform coefficients (we'll do two transforms
+    // in parallel). In the case of only one transform, the second half of the
+    // vectors will just contain random value we'll never use nor store.
+    let mut in0 = _mm_loadl_epi64(in_.offset(0) as *const __m128i);
+    let mut in1 = _mm_loadl_epi64(in_.offset(4) as *const __m128i);
+    let mut in2 = _mm_loadl_epi64(in_.offset(8) as *const __m128i);
+    let mut in3 = _mm_loadl_epi64(in_.offset(12) as *const __m128i);
+    // a00 a10 a20 a30   x x x x
+    // a01 a11 a21 a31   x x x x
+    // a02 a12 a22 a32   x x x x
+    // a03 a13 a23 a33   x x x x
+    if do_two != 0 {
+        let inB0 = _mm_loadl_epi64(in_.offset(16) as *const __m128i);
+        let inB1 = _mm_loadl_epi64(in_.offset(20) as *const __m128i);
+        let inB2 = _mm_loadl_epi64(in_.offset(24) as *const __m128i);
+        let inB3 = _mm_loadl_epi64(in_.offset(28) as *const __m128i);
+        in0 = _mm_unpacklo_epi64(in0, inB0);
+        in1 = _mm_unpacklo_epi64(in1, inB1);
+        in2 = _mm_unpacklo_epi64(in2, inB2);
+        in3 = _mm_unpacklo_epi64(in3, inB3);
+        // a00 a10 a20 a30   b00 b10 b20 b30
+        // a01 a11 a21 a31   b01 b11 b21 b31
+        // a02 a12 a22 a32   b02 b12 b22 b32
+        // a03 a13 a23 a33   b03 b13 b23 b33
+    }
+
+    // Vertical pass and subsequent transpose.
+    {
+        // First pass, c and d calculations are longer because of the "trick"
+        // multiplications.
+        let a = _mm_add_epi16(in0, in2);
+        let b = _mm_sub_epi16(in0, in2);
+        // c = MUL(in1, K2) - MUL(in3, K1) = MUL(in1, k2) - MUL(in3, k1) + in1 - in3
+        let c1 = _mm_mulhi_epi16(in1, k2);
+        let c2 = _mm_mulhi_epi16(in3, k1);
+        let c3 = _mm_sub_epi16(in1, in3);
+        let c4 = _mm_sub_epi16(c1, c2);
+        let c = _mm_add_epi16(c3, c4);
+        // d = MUL(in1, K1) + MUL(in3, K2) = MUL(in1, k1) + MUL(in3, k2) + in1 + in3
+        let d1 = _mm_mulhi_epi16(in1, k1);
+        let d2 = _mm_mulhi_epi16(in3, k2);
+        let d3 = _mm_add_epi16(in1, in3);
+        let d4 = _mm_add_epi16(d1, d2);
+        let d = _mm_add_epi16(d3, d4);
+
+        // Second pass.
+        let tmp0 = _mm_add_epi16(a, d);
+        let tmp1 = _mm_add_epi16(b, c);
+        let tmp2 = _mm_sub_epi16(b, c);
+        let tmp3 = _mm_sub_epi16(a, d);
+
+        // Transpose the two 4x4.
+        VP8Transpose_2_4x4_16b(
+            &tmp0, &tmp1, &tmp2, &tmp3, &mut T0, &mut T1, &mut T2, &mut T3,
+        );
+    }
+
+    // Horizontal pass and subsequent transpose.
+    {
+        // First pass, c and d calculations are longer because of the "trick"
+        // multiplications.
+        let four = _mm_set1_epi16(4);
+        let dc = _mm_add_epi16(T0, four);
+        let a = _mm_add_epi16(dc, T2);
+        let b = _mm_sub_epi16(dc, T2);
+        // c = MUL(T1, K2) - MUL(T3, K1) = MUL(T1, k2) - MUL(T3, k1) + T1 - T3
+        let c1 = _mm_mulhi_epi16(T1, k2);
+        let c2 = _mm_mulhi_epi16(T3, k1);
+        let c3 = _mm_sub_epi16(T1, T3);
+        let c4 = _mm_sub_epi16(c1, c2);
+        let c = _mm_add_epi16(c3, c4);
+        // d = MUL(T1, K1) + MUL(T3, K2) = MUL(T1, k1) + MUL(T3, k2) + T1 + T3
+        let d1 = _mm_mulhi_epi16(T1, k1);
+        let d2 = _mm_mulhi_epi16(T3, k2);
+        let d3 = _mm_add_epi16(T1, T3);
+        let d4 = _mm_add_epi16(d1, d2);
+        let d = _mm_add_epi16(d3, d4);
+
+        // Second pass.
+        let tmp0 = _mm_add_epi16(a, d);
+        let tmp1 = _mm_add_epi16(b, c);
+        let tmp2 = _mm_sub_epi16(b, c);
+        let tmp3 = _mm_sub_epi16(a, d);
+        let shifted0 = _mm_srai_epi16(tmp0, 3);
+        let shifted1 = _mm_srai_epi16(tmp1, 3);
+        let shifted2 = _mm_srai_epi16(tmp2, 3);
+        let shifted3 = _mm_srai_epi16(tmp3, 3);
+
+        // Transpose the two 4x4.
+        VP8Transpose_2_4x4_16b(
+            &shifted0, &shifted1, &shifted2, &shifted3, &mut T0, &mut T1, &mut T2, &mut T3,
+        );
+    }
+
+    // Add inverse transform to 'dst' and store.
+    {
+        let zero = _mm_setzero_si128();
+        // Load the reference(s).
+        let (mut dst0, mut dst1, mut dst2, mut dst3);
+        if do_two != 0 {
+            // Load eight bytes/pixels per line.
+            dst0 = _mm_loadl_epi64(dst.offset(0 * BPS_) as *const __m128i);
+            dst1 = _mm_loadl_epi64(dst.offset(1 * BPS_) as *const __m128i);
+            dst2 = _mm_loadl_epi64(dst.offset(2 * BPS_) as *const __m128i);
+            dst3 = _mm_loadl_epi64(dst.offset(3 * BPS_) as *const __m128i);
+        } else {
+            // Load four bytes/pixels per line.
+            dst0 = _mm_cvtsi32_si128(WebPMemToUint32(dst.offset(0 * BPS_)) as i32);
+            dst1 = _mm_cvtsi32_si128(WebPMemToUint32(dst.offset(1 * BPS_)) as i32);
+            dst2 = _mm_cvtsi32_si128(WebPMemToUint32(dst.offset(2 * BPS_)) as i32);
+            dst3 = _mm_cvtsi32_si128(WebPMemToUint32(dst.offset(3 * BPS_)) as i32);
+        }
+        // Convert to 16b.
+        dst0 = _mm_unpacklo_epi8(dst0, zero);
+        dst1 = _mm_unpacklo_epi8(dst1, zero);
+        dst2 = _mm_unpacklo_epi8(dst2, zero);
+        dst3 = _mm_unpacklo_epi8(dst3, zero);
+        // Add the inverse transform(s).
+        dst0 = _mm_add_epi16(dst0, T0);
+        dst1 = _mm_add_epi16(dst1, T1);
+        dst2 = _mm_add_epi16(dst2, T2);
+        dst3 = _mm_add_epi16(dst3, T3);
+        // Unsigned saturate to 8b.
+        dst0 = _mm_packus_epi16(dst0, dst0);
+        dst1 = _mm_packus_epi16(dst1, dst1);
+        dst2 = _mm_packus_epi16(dst2, dst2);
+        dst3 = _mm_packus_epi16(dst3, dst3);
+        // Store the results.
+        if do_two != 0 {
+            // Store eight bytes/pixels per line.
+            _mm_storel_epi64(dst.offset(0 * BPS_) as *mut __m128i, dst0);
+            _mm_storel_epi64(dst.offset(1 * BPS_) as *mut __m128i, dst1);
+            _mm_storel_epi64(dst.offset(2 * BPS_) as *mut __m128i, dst2);
+            _mm_storel_epi64(dst.offset(3 * BPS_) as *mut __m128i, dst3);
+        } else {
+            // Store four bytes/pixels per line.
+            WebPUint32ToMem(dst.offset(0 * BPS_), _mm_cvtsi128_si32(dst0) as u32);
+            WebPUint32ToMem(dst.offset(1 * BPS_), _mm_cvtsi128_si32(dst1) as u32);
+            WebPUint32ToMem(dst.offset(2 * BPS_), _mm_cvtsi128_si32(dst2) as u32);
+            WebPUint32ToMem(dst.offset(3 * BPS_), _mm_cvtsi128_si32(dst3) as u32);
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Loop Filter (Paragraph 15)
+
+// Compute abs(p - q) = subs(p - q) OR subs(q - p)
+macro_rules! mm_abs {
+    ($p:expr, $q:expr) => {
+        _mm_or_si128(_mm_subs_epu8($q, $p), _mm_subs_epu8($p, $q))
+    };
+}
+
+// Shift each byte of "x" by 3 bits while preserving by the sign bit.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn SignedShift8b_SSE2(x: &mut __m128i) {
+    let zero = _mm_setzero_si128();
+    let lo_0 = _mm_unpacklo_epi8(zero, *x);
+    let hi_0 = _mm_unpackhi_epi8(zero, *x);
+    let lo_1 = _mm_srai_epi16(lo_0, 3 + 8);
+    let hi_1 = _mm_srai_epi16(hi_0, 3 + 8);
+    *x = _mm_packs_epi16(lo_1, hi_1);
+}
+
+macro_rules! flip_sign_bit2 {
+    ($sign_bit:expr, $a:expr, $b:expr) => {{
+        $a = _mm_xor_si128($a, $sign_bit);
+        $b = _mm_xor_si128($b, $sign_bit);
+    }};
+}
+
+macro_rules! flip_sign_bit4 {
+    ($sign_bit:expr, $a:expr, $b:expr, $c:expr, $d:expr) => {{
+        flip_sign_bit2!($sign_bit, $a, $b);
+        flip_sign_bit2!($sign_bit, $c, $d);
+    }};
+}
+
+// input/output is uint8_t
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn GetNotHEV_SSE2(
+    p1: &__m128i,
+    p0: &__m128i,
+    q0: &__m128i,
+    q1: &__m128i,
+    hev_thresh: c_int,
+    not_hev: &mut __m128i,
+) {
+    let zero = _mm_setzero_si128();
+    let t_1 = mm_abs!(*p1, *p0);
+    let t_2 = mm_abs!(*q1, *q0);
+
+    let h = _mm_set1_epi8(hev_thresh as i8);
+    let t_max = _mm_max_epu8(t_1, t_2);
+
+    let t_max_h = _mm_subs_epu8(t_max, h);
+    *not_hev = _mm_cmpeq_epi8(t_max_h, zero); // not_hev <= t1 && not_hev <= t2
+}
+
+// input pixels are int8_t
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn GetBaseDelta_SSE2(
+    p1: &__m128i,
+    p0: &__m128i,
+    q0: &__m128i,
+    q1: &__m128i,
+    delta: &mut __m128i,
+) {
+    // beware of addition order, for saturation!
+    let p1_q1 = _mm_subs_epi8(*p1, *q1); // p1 - q1
+    let q0_p0 = _mm_subs_epi8(*q0, *p0); // q0 - p0
+    let s1 = _mm_adds_epi8(p1_q1, q0_p0); // p1 - q1 + 1 * (q0 - p0)
+    let s2 = _mm_adds_epi8(q0_p0, s1); // p1 - q1 + 2 * (q0 - p0)
+    let s3 = _mm_adds_epi8(q0_p0, s2); // p1 - q1 + 3 * (q0 - p0)
+    *delta = s3;
+}
+
+// input and output are int8_t
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn DoSimpleFilter_SSE2(p0: &mut __m128i, q0: &mut __m128i, fl: &__m128i) {
+    let k3 = _mm_set1_epi8(3);
+    let k4 = _mm_set1_epi8(4);
+    let mut v3 = _mm_adds_epi8(*fl, k3);
+    let mut v4 = _mm_adds_epi8(*fl, k4);
+
+    SignedShift8b_SSE2(&mut v4); // v4 >> 3
+    SignedShift8b_SSE2(&mut v3); // v3 >> 3
+    *q0 = _mm_subs_epi8(*q0, v4); // q0 -= v4
+    *p0 = _mm_adds_epi8(*p0, v3); // p0 += v3
+}
+
+// Updates values of 2 pixels at MB edge during complex filtering.
+// Update operations:
+// q = q - delta and p = p + delta; where delta = [(a_hi >> 7), (a_lo >> 7)]
+// Pixels 'pi' and 'qi' are int8_t on input, uint8_t on output (sign flip).
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Update2Pixels_SSE2(pi: &mut __m128i, qi: &mut __m128i, a0_lo: &__m128i, a0_hi: &__m128i) {
+    let a1_lo = _mm_srai_epi16(*a0_lo, 7);
+    let a1_hi = _mm_srai_epi16(*a0_hi, 7);
+    let delta = _mm_packs_epi16(a1_lo, a1_hi);
+    let sign_bit = _mm_set1_epi8(0x80u8 as i8);
+    *pi = _mm_adds_epi8(*pi, delta);
+    *qi = _mm_subs_epi8(*qi, delta);
+    flip_sign_bit2!(sign_bit, *pi, *qi);
+}
+
+// input pixels are uint8_t
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn NeedsFilter_SSE2(
+    p1: &__m128i,
+    p0: &__m128i,
+    q0: &__m128i,
+    q1: &__m128i,
+    thresh: c_int,
+    mask: &mut __m128i,
+) {
+    let m_thresh = _mm_set1_epi8(thresh as i8);
+    let t1 = mm_abs!(*p1, *q1); // abs(p1 - q1)
+    let kFE = _mm_set1_epi8(0xFEu8 as i8);
+    let t2 = _mm_and_si128(t1, kFE); // set lsb of each byte to zero
+    let t3 = _mm_srli_epi16(t2, 1); // abs(p1 - q1) / 2
+
+    let t4 = mm_abs!(*p0, *q0); // abs(p0 - q0)
+    let t5 = _mm_adds_epu8(t4, t4); // abs(p0 - q0) * 2
+    let t6 = _mm_adds_epu8(t5, t3); // abs(p0-q0)*2 + abs(p1-q1)/2
+
+    let t7 = _mm_subs_epu8(t6, m_thresh); // mask <= m_thresh
+    *mask = _mm_cmpeq_epi8(t7, _mm_setzero_si128());
+}
+
+//------------------------------------------------------------------------------
+// Edge filtering functions
+
+// Applies filter on 2 pixels (p0 and q0)
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn DoFilter2_SSE2(
+    p1: &mut __m128i,
+    p0: &mut __m128i,
+    q0: &mut __m128i,
+    q1: &mut __m128i,
+    thresh: c_int,
+) {
+    let mut a = _mm_setzero_si128();
+    let mut mask = _mm_setzero_si128();
+    let sign_bit = _mm_set1_epi8(0x80u8 as i8);
+    // convert p1/q1 to int8_t (for GetBaseDelta_SSE2)
+    let p1s = _mm_xor_si128(*p1, sign_bit);
+    let q1s = _mm_xor_si128(*q1, sign_bit);
+
+    NeedsFilter_SSE2(p1, p0, q0, q1, thresh, &mut mask);
+
+    flip_sign_bit2!(sign_bit, *p0, *q0);
+    GetBaseDelta_SSE2(&p1s, p0, q0, &q1s, &mut a);
+    a = _mm_and_si128(a, mask); // mask filter values we don't care about
+    DoSimpleFilter_SSE2(p0, q0, &a);
+    flip_sign_bit2!(sign_bit, *p0, *q0);
+}
+
+// Applies filter on 4 pixels (p1, p0, q0 and q1)
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn DoFilter4_SSE2(
+    p1: &mut __m128i,
+    p0: &mut __m128i,
+    q0: &mut __m128i,
+    q1: &mut __m128i,
+    mask: &__m128i,
+    hev_thresh: c_int,
+) {
+    let zero = _mm_setzero_si128();
+    let sign_bit = _mm_set1_epi8(0x80u8 as i8);
+    let k64 = _mm_set1_epi8(64);
+    let k3 = _mm_set1_epi8(3);
+    let k4 = _mm_set1_epi8(4);
+    let mut not_hev = _mm_setzero_si128();
+
+    // compute hev mask
+    GetNotHEV_SSE2(p1, p0, q0, q1, hev_thresh, &mut not_hev);
+
+    // convert to signed values
+    flip_sign_bit4!(sign_bit, *p1, *p0, *q0, *q1);
+
+    let mut t1 = _mm_subs_epi8(*p1, *q1); // p1 - q1
+    t1 = _mm_andnot_si128(not_hev, t1); // hev(p1 - q1)
+    let mut t2 = _mm_subs_epi8(*q0, *p0); // q0 - p0
+    t1 = _mm_adds_epi8(t1, t2); // hev(p1 - q1) + 1 * (q0 - p0)
+    t1 = _mm_adds_epi8(t1, t2); // hev(p1 - q1) + 2 * (q0 - p0)
+    t1 = _mm_adds_epi8(t1, t2); // hev(p1 - q1) + 3 * (q0 - p0)
+    t1 = _mm_and_si128(t1, *mask); // mask filter values we don't care about
+
+    t2 = _mm_adds_epi8(t1, k3); // 3 * (q0 - p0) + hev(p1 - q1) + 3
+    let mut t3 = _mm_adds_epi8(t1, k4); // 3 * (q0 - p0) + hev(p1 - q1) + 4
+    SignedShift8b_SSE2(&mut t2); // (3 * (q0 - p0) + hev(p1 - q1) + 3) >> 3
+    SignedShift8b_SSE2(&mut t3); // (3 * (q0 - p0) + hev(p1 - q1) + 4) >> 3
+    *p0 = _mm_adds_epi8(*p0, t2); // p0 += t2
+    *q0 = _mm_subs_epi8(*q0, t3); // q0 -= t3
+    flip_sign_bit2!(sign_bit, *p0, *q0);
+
+    // this is equivalent to signed (a + 1) >> 1 calculation
+    t2 = _mm_add_epi8(t3, sign_bit);
+    t3 = _mm_avg_epu8(t2, zero);
+    t3 = _mm_sub_epi8(t3, k64);
+
+    t3 = _mm_and_si128(not_hev, t3); // if !hev
+    *q1 = _mm_subs_epi8(*q1, t3); // q1 -= t3
+    *p1 = _mm_adds_epi8(*p1, t3); // p1 += t3
+    flip_sign_bit2!(sign_bit, *p1, *q1);
+}
+
+// Applies filter on 6 pixels (p2, p1, p0, q0, q1 and q2)
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn DoFilter6_SSE2(
+    p2: &mut __m128i,
+    p1: &mut __m128i,
+    p0: &mut __m128i,
+    q0: &mut __m128i,
+    q1: &mut __m128i,
+    q2: &mut __m128i,
+    mask: &__m128i,
+    hev_thresh: c_int,
+) {
+    let zero = _mm_setzero_si128();
+    let sign_bit = _mm_set1_epi8(0x80u8 as i8);
+    let mut a = _mm_setzero_si128();
+    let mut not_hev = _mm_setzero_si128();
+
+    // compute hev mask
+    GetNotHEV_SSE2(p1, p0, q0, q1, hev_thresh, &mut not_hev);
+
+    flip_sign_bit4!(sign_bit, *p1, *p0, *q0, *q1);
+    flip_sign_bit2!(sign_bit, *p2, *q2);
+    GetBaseDelta_SSE2(p1, p0, q0, q1, &mut a);
+
+    {
+        // do simple filter on pixels with hev
+        let m = _mm_andnot_si128(not_hev, *mask);
+        let f = _mm_and_si128(a, m);
+        DoSimpleFilter_SSE2(p0, q0, &f);
+    }
+
+    {
+        // do strong filter on pixels with not hev
+        let k9 = _mm_set1_epi16(0x0900);
+        let k63 = _mm_set1_epi16(63);
+
+        let m = _mm_and_si128(not_hev, *mask);
+        let f = _mm_and_si128(a, m);
+
+        let f_lo = _mm_unpacklo_epi8(zero, f);
+        let f_hi = _mm_unpackhi_epi8(zero, f);
+
+        let f9_lo = _mm_mulhi_epi16(f_lo, k9); // Filter (lo) * 9
+        let f9_hi = _mm_mulhi_epi16(f_hi, k9); // Filter (hi) * 9
+
+        let a2_lo = _mm_add_epi16(f9_lo, k63); // Filter * 9 + 63
+        let a2_hi = _mm_add_epi16(f9_hi, k63); // Filter * 9 + 63
+
+        let a1_lo = _mm_add_epi16(a2_lo, f9_lo); // Filter * 18 + 63
+        let a1_hi = _mm_add_epi16(a2_hi, f9_hi); // Filter * 18 + 63
+
+        let a0_lo = _mm_add_epi16(a1_lo, f9_lo); // Filter * 27 + 63
+        let a0_hi = _mm_add_epi16(a1_hi, f9_hi); // Filter * 27 + 63
+
+        Update2Pixels_SSE2(p2, q2, &a2_lo, &a2_hi);
+        Update2Pixels_SSE2(p1, q1, &a1_lo, &a1_hi);
+        Update2Pixels_SSE2(p0, q0, &a0_lo, &a0_hi);
+    }
+}
+
+// reads 8 rows across a vertical edge.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Load8x4_SSE2(b: *const u8, stride: isize, p: &mut __m128i, q: &mut __m128i) {
+    // A0 = 63 62 61 60 23 22 21 20 43 42 41 40 03 02 01 00
+    // A1 = 73 72 71 70 33 32 31 30 53 52 51 50 13 12 11 10
+    let A0 = _mm_set_epi32(
+        WebPMemToUint32(b.offset(6 * stride)) as i32,
+        WebPMemToUint32(b.offset(2 * stride)) as i32,
+        WebPMemToUint32(b.offset(4 * stride)) as i32,
+        WebPMemToUint32(b.offset(0 * stride)) as i32,
+    );
+    let A1 = _mm_set_epi32(
+        WebPMemToUint32(b.offset(7 * stride)) as i32,
+        WebPMemToUint32(b.offset(3 * stride)) as i32,
+        WebPMemToUint32(b.offset(5 * stride)) as i32,
+        WebPMemToUint32(b.offset(1 * stride)) as i32,
+    );
+
+    // B0 = 53 43 52 42 51 41 50 40 13 03 12 02 11 01 10 00
+    // B1 = 73 63 72 62 71 61 70 60 33 23 32 22 31 21 30 20
+    let B0 = _mm_unpacklo_epi8(A0, A1);
+    let B1 = _mm_unpackhi_epi8(A0, A1);
+
+    // C0 = 33 23 13 03 32 22 12 02 31 21 11 01 30 20 10 00
+    // C1 = 73 63 53 43 72 62 52 42 71 61 51 41 70 60 50 40
+    let C0 = _mm_unpacklo_epi16(B0, B1);
+    let C1 = _mm_unpackhi_epi16(B0, B1);
+
+    // *p = 71 61 51 41 31 21 11 01 70 60 50 40 30 20 10 00
+    // *q = 73 63 53 43 33 23 13 03 72 62 52 42 32 22 12 02
+    *p = _mm_unpacklo_epi32(C0, C1);
+    *q = _mm_unpackhi_epi32(C0, C1);
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Load16x4_SSE2(
+    r0: *const u8,
+    r8: *const u8,
+    stride: isize,
+    p1: &mut __m128i,
+    p0: &mut __m128i,
+    q0: &mut __m128i,
+    q1: &mut __m128i,
+) {
+    // Assume the pixels around the edge (|) are numbered as follows
+    //                00 01 | 02 03
+    //                10 11 | 12 13
+    //                 ...  |  ...
+    //                e0 e1 | e2 e3
+    //                f0 f1 | f2 f3
+    //
+    // r0 is pointing to the 0th row (00)
+    // r8 is pointing to the 8th row (80)
+
+    // Load
+    // p1 = 71 61 51 41 31 21 11 01 70 60 50 40 30 20 10 00
+    // q0 = 73 63 53 43 33 23 13 03 72 62 52 42 32 22 12 02
+    // p0 = f1 e1 d1 c1 b1 a1 91 81 f0 e0 d0 c0 b0 a0 90 80
+    // q1 = f3 e3 d3 c3 b3 a3 93 83 f2 e2 d2 c2 b2 a2 92 82
+    Load8x4_SSE2(r0, stride, p1, q0);
+    Load8x4_SSE2(r8, stride, p0, q1);
+
+    {
+        // p1 = f0 e0 d0 c0 b0 a0 90 80 70 60 50 40 30 20 10 00
+        // p0 = f1 e1 d1 c1 b1 a1 91 81 71 61 51 41 31 21 11 01
+        // q0 = f2 e2 d2 c2 b2 a2 92 82 72 62 52 42 32 22 12 02
+        // q1 = f3 e3 d3 c3 b3 a3 93 83 73 63 53 43 33 23 13 03
+        let t1 = *p1;
+        let t2 = *q0;
+        *p1 = _mm_unpacklo_epi64(t1, *p0);
+        *p0 = _mm_unpackhi_epi64(t1, *p0);
+        *q0 = _mm_unpacklo_epi64(t2, *q1);
+        *q1 = _mm_unpackhi_epi64(t2, *q1);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Store4x4_SSE2(x: &mut __m128i, mut dst: *mut u8, stride: isize) {
+    for _ in 0..4 {
+        WebPUint32ToMem(dst, _mm_cvtsi128_si32(*x) as u32);
+        *x = _mm_srli_si128(*x, 4);
+        dst = dst.offset(stride);
+    }
+}
+
+// Transpose back and store
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Store16x4_SSE2(
+    p1: &__m128i,
+    p0: &__m128i,
+    q0: &__m128i,
+    q1: &__m128i,
+    mut r0: *mut u8,
+    mut r8: *mut u8,
+    stride: isize,
+) {
+    // p0 = 71 70 61 60 51 50 41 40 31 30 21 20 11 10 01 00
+    // p1 = f1 f0 e1 e0 d1 d0 c1 c0 b1 b0 a1 a0 91 90 81 80
+    let mut t1 = *p0;
+    let mut p0_s = _mm_unpacklo_epi8(*p1, t1);
+    let mut p1_s = _mm_unpackhi_epi8(*p1, t1);
+
+    // q0 = 73 72 63 62 53 52 43 42 33 32 23 22 13 12 03 02
+    // q1 = f3 f2 e3 e2 d3 d2 c3 c2 b3 b2 a3 a2 93 92 83 82
+    t1 = *q0;
+    let mut q0_s = _mm_unpacklo_epi8(t1, *q1);
+    let mut q1_s = _mm_unpackhi_epi8(t1, *q1);
+
+    // p0 = 33 32 31 30 23 22 21 20 13 12 11 10 03 02 01 00
+    // q0 = 73 72 71 70 63 62 61 60 53 52 51 50 43 42 41 40
+    t1 = p0_s;
+    p0_s = _mm_unpacklo_epi16(t1, q0_s);
+    q0_s = _mm_unpackhi_epi16(t1, q0_s);
+
+    // p1 = b3 b2 b1 b0 a3 a2 a1 a0 93 92 91 90 83 82 81 80
+    // q1 = f3 f2 f1 f0 e3 e2 e1 e0 d3 d2 d1 d0 c3 c2 c1 c0
+    t1 = p1_s;
+    p1_s = _mm_unpacklo_epi16(t1, q1_s);
+    q1_s = _mm_unpackhi_epi16(t1, q1_s);
+
+    Store4x4_SSE2(&mut p0_s, r0, stride);
+    r0 = r0.offset(4 * stride);
+    Store4x4_SSE2(&mut q0_s, r0, stride);
+
+    Store4x4_SSE2(&mut p1_s, r8, stride);
+    r8 = r8.offset(4 * stride);
+    Store4x4_SSE2(&mut q1_s, r8, stride);
+}
+
+//------------------------------------------------------------------------------
+// Simple In-loop filtering (Paragraph 15.2)
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn SimpleVFilter16_SSE2(p: *mut u8, stride: c_int, thresh: c_int) {
+    let stride = stride as isize;
+    // Load
+    let mut p1 = _mm_loadu_si128(p.offset(-2 * stride) as *const __m128i);
+    let mut p0 = _mm_loadu_si128(p.offset(-stride) as *const __m128i);
+    let mut q0 = _mm_loadu_si128(p.offset(0) as *const __m128i);
+    let mut q1 = _mm_loadu_si128(p.offset(stride) as *const __m128i);
+
+    DoFilter2_SSE2(&mut p1, &mut p0, &mut q0, &mut q1, thresh);
+
+    // Store
+    _mm_storeu_si128(p.offset(-stride) as *mut __m128i, p0);
+    _mm_storeu_si128(p.offset(0) as *mut __m128i, q0);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn SimpleHFilter16_SSE2(p: *mut u8, stride: c_int, thresh: c_int) {
+    let stride = stride as isize;
+    let mut p1 = _mm_setzero_si128();
+    let mut p0 = _mm_setzero_si128();
+    let mut q0 = _mm_setzero_si128();
+    let mut q1 = _mm_setzero_si128();
+
+    let p = p.offset(-2); // beginning of p1
+
+    Load16x4_SSE2(
+        p,
+        p.offset(8 * stride),
+        stride,
+        &mut p1,
+        &mut p0,
+        &mut q0,
+        &mut q1,
+    );
+    DoFilter2_SSE2(&mut p1, &mut p0, &mut q0, &mut q1, thresh);
+    Store16x4_SSE2(&p1, &p0, &q0, &q1, p, p.offset(8 * stride), stride);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn SimpleVFilter16i_SSE2(
+    mut p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+) {
+    for _ in 0..3 {
+        p = p.offset(4 * stride as isize);
+        SimpleVFilter16_SSE2(p, stride, thresh);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn SimpleHFilter16i_SSE2(
+    mut p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+) {
+    for _ in 0..3 {
+        p = p.offset(4);
+        SimpleHFilter16_SSE2(p, stride, thresh);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Complex In-loop filtering (Paragraph 15.3)
+
+macro_rules! max_diff1 {
+    ($p3:expr, $p2:expr, $p1:expr, $p0:expr, $m:expr) => {{
+        $m = mm_abs!($p1, $p0);
+        $m = _mm_max_epu8($m, mm_abs!($p3, $p2));
+        $m = _mm_max_epu8($m, mm_abs!($p2, $p1));
+    }};
+}
+
+macro_rules! max_diff2 {
+    ($p3:expr, $p2:expr, $p1:expr, $p0:expr, $m:expr) => {{
+        $m = _mm_max_epu8($m, mm_abs!($p1, $p0));
+        $m = _mm_max_epu8($m, mm_abs!($p3, $p2));
+        $m = _mm_max_epu8($m, mm_abs!($p2, $p1));
+    }};
+}
+
+macro_rules! load_h_edges4 {
+    ($p:expr, $stride:expr, $e1:expr, $e2:expr, $e3:expr, $e4:expr) => {{
+        $e1 = _mm_loadu_si128($p.offset(0 * $stride) as *const __m128i);
+        $e2 = _mm_loadu_si128($p.offset(1 * $stride) as *const __m128i);
+        $e3 = _mm_loadu_si128($p.offset(2 * $stride) as *const __m128i);
+        $e4 = _mm_loadu_si128($p.offset(3 * $stride) as *const __m128i);
+    }};
+}
+
+macro_rules! loaduv_h_edge {
+    ($p:expr, $u:expr, $v:expr, $stride:expr) => {{
+        let U = _mm_loadl_epi64($u.offset($stride) as *const __m128i);
+        let V = _mm_loadl_epi64($v.offset($stride) as *const __m128i);
+        $p = _mm_unpacklo_epi64(U, V);
+    }};
+}
+
+macro_rules! loaduv_h_edges4 {
+    ($u:expr, $v:expr, $stride:expr, $e1:expr, $e2:expr, $e3:expr, $e4:expr) => {{
+        loaduv_h_edge!($e1, $u, $v, 0 * $stride);
+        loaduv_h_edge!($e2, $u, $v, 1 * $stride);
+        loaduv_h_edge!($e3, $u, $v, 2 * $stride);
+        loaduv_h_edge!($e4, $u, $v, 3 * $stride);
+    }};
+}
+
+macro_rules! storeuv {
+    ($p:expr, $u:expr, $v:expr, $stride:expr) => {{
+        _mm_storel_epi64($u.offset($stride) as *mut __m128i, $p);
+        $p = _mm_srli_si128($p, 8);
+        _mm_storel_epi64($v.offset($stride) as *mut __m128i, $p);
+    }};
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn ComplexMask_SSE2(
+    p1: &__m128i,
+    p0: &__m128i,
+    q0: &__m128i,
+    q1: &__m128i,
+    thresh: c_int,
+    ithresh: c_int,
+    mask: &mut __m128i,
+) {
+    let it = _mm_set1_epi8(ithresh as i8);
+    let diff = _mm_subs_epu8(*mask, it);
+    let thresh_mask = _mm_cmpeq_epi8(diff, _mm_setzero_si128());
+    let mut filter_mask = _mm_setzero_si128();
+    NeedsFilter_SSE2(p1, p0, q0, q1, thresh, &mut filter_mask);
+    *mask = _mm_and_si128(thresh_mask, filter_mask);
+}
+
+// on macroblock edges
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VFilter16_SSE2(
+    p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let mut t1;
+    let mut mask;
+    let (mut p2, mut p1, mut p0, mut q0, mut q1, mut q2);
+
+    // Load p3, p2, p1, p0
+    load_h_edges4!(p.offset(-4 * stride), stride, t1, p2, p1, p0);
+    max_diff1!(t1, p2, p1, p0, mask);
+
+    // Load q0, q1, q2, q3
+    load_h_edges4!(p, stride, q0, q1, q2, t1);
+    max_diff2!(t1, q2, q1, q0, mask);
+
+    ComplexMask_SSE2(&p1, &p0, &q0, &q1, thresh, ithresh, &mut mask);
+    DoFilter6_SSE2(
+        &mut p2, &mut p1, &mut p0, &mut q0, &mut q1, &mut q2, &mask, hev_thresh,
+    );
+
+    // Store
+    _mm_storeu_si128(p.offset(-3 * stride) as *mut __m128i, p2);
+    _mm_storeu_si128(p.offset(-2 * stride) as *mut __m128i, p1);
+    _mm_storeu_si128(p.offset(-1 * stride) as *mut __m128i, p0);
+    _mm_storeu_si128(p.offset(0 * stride) as *mut __m128i, q0);
+    _mm_storeu_si128(p.offset(1 * stride) as *mut __m128i, q1);
+    _mm_storeu_si128(p.offset(2 * stride) as *mut __m128i, q2);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn HFilter16_SSE2(
+    p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let mut mask;
+    let mut p3 = _mm_setzero_si128();
+    let mut p2 = _mm_setzero_si128();
+    let mut p1 = _mm_setzero_si128();
+    let mut p0 = _mm_setzero_si128();
+    let mut q0 = _mm_setzero_si128();
+    let mut q1 = _mm_setzero_si128();
+    let mut q2 = _mm_setzero_si128();
+    let mut q3 = _mm_setzero_si128();
+
+    let b = p.offset(-4);
+    Load16x4_SSE2(
+        b,
+        b.offset(8 * stride),
+        stride,
+        &mut p3,
+        &mut p2,
+        &mut p1,
+        &mut p0,
+    );
+    max_diff1!(p3, p2, p1, p0, mask);
+
+    Load16x4_SSE2(
+        p,
+        p.offset(8 * stride),
+        stride,
+        &mut q0,
+        &mut q1,
+        &mut q2,
+        &mut q3,
+    );
+    max_diff2!(q3, q2, q1, q0, mask);
+
+    ComplexMask_SSE2(&p1, &p0, &q0, &q1, thresh, ithresh, &mut mask);
+    DoFilter6_SSE2(
+        &mut p2, &mut p1, &mut p0, &mut q0, &mut q1, &mut q2, &mask, hev_thresh,
+    );
+
+    Store16x4_SSE2(&p3, &p2, &p1, &p0, b, b.offset(8 * stride), stride);
+    Store16x4_SSE2(&q0, &q1, &q2, &q3, p, p.offset(8 * stride), stride);
+}
+
+// on three inner edges
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VFilter16i_SSE2(
+    mut p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let (mut p3, mut p2, mut p1, mut p0); // loop invariants
+
+    load_h_edges4!(p, stride, p3, p2, p1, p0); // prologue
+
+    for _ in 0..3 {
+        let mut mask;
+        let (tmp1, tmp2);
+        let b = p.offset(2 * stride); // beginning of p1
+        p = p.offset(4 * stride);
+
+        max_diff1!(p3, p2, p1, p0, mask); // compute partial mask
+        load_h_edges4!(p, stride, p3, p2, tmp1, tmp2);
+        max_diff2!(p3, p2, tmp1, tmp2, mask);
+
+        // p3 and p2 are not just temporary variables here: they will be
+        // re-used for next span. And q2/q3 will become p1/p0 accordingly.
+        ComplexMask_SSE2(&p1, &p0, &p3, &p2, thresh, ithresh, &mut mask);
+        DoFilter4_SSE2(&mut p1, &mut p0, &mut p3, &mut p2, &mask, hev_thresh);
+
+        // Store
+        _mm_storeu_si128(b.offset(0 * stride) as *mut __m128i, p1);
+        _mm_storeu_si128(b.offset(1 * stride) as *mut __m128i, p0);
+        _mm_storeu_si128(b.offset(2 * stride) as *mut __m128i, p3);
+        _mm_storeu_si128(b.offset(3 * stride) as *mut __m128i, p2);
+
+        // rotate samples
+        p1 = tmp1;
+        p0 = tmp2;
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn HFilter16i_SSE2(
+    mut p: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    // loop invariants
+    let mut p3 = _mm_setzero_si128();
+    let mut p2 = _mm_setzero_si128();
+    let mut p1 = _mm_setzero_si128();
+    let mut p0 = _mm_setzero_si128();
+
+    Load16x4_SSE2(
+        p,
+        p.offset(8 * stride),
+        stride,
+        &mut p3,
+        &mut p2,
+        &mut p1,
+        &mut p0,
+    ); // prologue
+
+    for _ in 0..3 {
+        let mut mask;
+        let mut tmp1 = _mm_setzero_si128();
+        let mut tmp2 = _mm_setzero_si128();
+        let b = p.offset(2); // beginning of p1
+
+        p = p.offset(4); // beginning of q0 (and next span)
+
+        max_diff1!(p3, p2, p1, p0, mask); // compute partial mask
+        Load16x4_SSE2(
+            p,
+            p.offset(8 * stride),
+            stride,
+            &mut p3,
+            &mut p2,
+            &mut tmp1,
+            &mut tmp2,
+        );
+        max_diff2!(p3, p2, tmp1, tmp2, mask);
+
+        ComplexMask_SSE2(&p1, &p0, &p3, &p2, thresh, ithresh, &mut mask);
+        DoFilter4_SSE2(&mut p1, &mut p0, &mut p3, &mut p2, &mask, hev_thresh);
+
+        Store16x4_SSE2(&p1, &p0, &p3, &p2, b, b.offset(8 * stride), stride);
+
+        // rotate samples
+        p1 = tmp1;
+        p0 = tmp2;
+    }
+}
+
+// 8-pixels wide variant, for chroma filtering
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VFilter8_SSE2(
+    u: *mut u8,
+    v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let mut mask;
+    let (mut t1, mut p2, mut p1, mut p0, mut q0, mut q1, mut q2);
+
+    // Load p3, p2, p1, p0
+    loaduv_h_edges4!(
+        u.offset(-4 * stride),
+        v.offset(-4 * stride),
+        stride,
+        t1,
+        p2,
+        p1,
+        p0
+    );
+    max_diff1!(t1, p2, p1, p0, mask);
+
+    // Load q0, q1, q2, q3
+    loaduv_h_edges4!(u, v, stride, q0, q1, q2, t1);
+    max_diff2!(t1, q2, q1, q0, mask);
+
+    ComplexMask_SSE2(&p1, &p0, &q0, &q1, thresh, ithresh, &mut mask);
+    DoFilter6_SSE2(
+        &mut p2, &mut p1, &mut p0, &mut q0, &mut q1, &mut q2, &mask, hev_thresh,
+    );
+
+    // Store
+    storeuv!(p2, u, v, -3 * stride);
+    storeuv!(p1, u, v, -2 * stride);
+    storeuv!(p0, u, v, -1 * stride);
+    storeuv!(q0, u, v, 0 * stride);
+    storeuv!(q1, u, v, 1 * stride);
+    storeuv!(q2, u, v, 2 * stride);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn HFilter8_SSE2(
+    u: *mut u8,
+    v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let mut mask;
+    let mut p3 = _mm_setzero_si128();
+    let mut p2 = _mm_setzero_si128();
+    let mut p1 = _mm_setzero_si128();
+    let mut p0 = _mm_setzero_si128();
+    let mut q0 = _mm_setzero_si128();
+    let mut q1 = _mm_setzero_si128();
+    let mut q2 = _mm_setzero_si128();
+    let mut q3 = _mm_setzero_si128();
+
+    let tu = u.offset(-4);
+    let tv = v.offset(-4);
+    Load16x4_SSE2(tu, tv, stride, &mut p3, &mut p2, &mut p1, &mut p0);
+    max_diff1!(p3, p2, p1, p0, mask);
+
+    Load16x4_SSE2(u, v, stride, &mut q0, &mut q1, &mut q2, &mut q3);
+    max_diff2!(q3, q2, q1, q0, mask);
+
+    ComplexMask_SSE2(&p1, &p0, &q0, &q1, thresh, ithresh, &mut mask);
+    DoFilter6_SSE2(
+        &mut p2, &mut p1, &mut p0, &mut q0, &mut q1, &mut q2, &mask, hev_thresh,
+    );
+
+    Store16x4_SSE2(&p3, &p2, &p1, &p0, tu, tv, stride);
+    Store16x4_SSE2(&q0, &q1, &q2, &q3, u, v, stride);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VFilter8i_SSE2(
+    mut u: *mut u8,
+    mut v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let mut mask;
+    let (mut t1, mut t2, mut p1, mut p0, mut q0, mut q1);
+
+    // Load p3, p2, p1, p0
+    loaduv_h_edges4!(u, v, stride, t2, t1, p1, p0);
+    max_diff1!(t2, t1, p1, p0, mask);
+
+    u = u.offset(4 * stride);
+    v = v.offset(4 * stride);
+
+    // Load q0, q1, q2, q3
+    loaduv_h_edges4!(u, v, stride, q0, q1, t1, t2);
+    max_diff2!(t2, t1, q1, q0, mask);
+
+    ComplexMask_SSE2(&p1, &p0, &q0, &q1, thresh, ithresh, &mut mask);
+    DoFilter4_SSE2(&mut p1, &mut p0, &mut q0, &mut q1, &mask, hev_thresh);
+
+    // Store
+    storeuv!(p1, u, v, -2 * stride);
+    storeuv!(p0, u, v, -1 * stride);
+    storeuv!(q0, u, v, 0 * stride);
+    storeuv!(q1, u, v, 1 * stride);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn HFilter8i_SSE2(
+    mut u: *mut u8,
+    mut v: *mut u8,
+    stride: c_int,
+    thresh: c_int,
+    ithresh: c_int,
+    hev_thresh: c_int,
+) {
+    let stride = stride as isize;
+    let mut mask;
+    let mut t1 = _mm_setzero_si128();
+    let mut t2 = _mm_setzero_si128();
+    let mut p1 = _mm_setzero_si128();
+    let mut p0 = _mm_setzero_si128();
+    let mut q0 = _mm_setzero_si128();
+    let mut q1 = _mm_setzero_si128();
+    Load16x4_SSE2(u, v, stride, &mut t2, &mut t1, &mut p1, &mut p0); // p3, p2, p1, p0
+    max_diff1!(t2, t1, p1, p0, mask);
+
+    u = u.offset(4); // beginning of q0
+    v = v.offset(4);
+    Load16x4_SSE2(u, v, stride, &mut q0, &mut q1, &mut t1, &mut t2); // q0, q1, q2, q3
+    max_diff2!(t2, t1, q1, q0, mask);
+
+    ComplexMask_SSE2(&p1, &p0, &q0, &q1, thresh, ithresh, &mut mask);
+    DoFilter4_SSE2(&mut p1, &mut p0, &mut q0, &mut q1, &mask, hev_thresh);
+
+    u = u.offset(-2); // beginning of p1
+    v = v.offset(-2);
+    Store16x4_SSE2(&p1, &p0, &q0, &q1, u, v, stride);
+}
+
+//------------------------------------------------------------------------------
+// 4x4 predictions
+
+macro_rules! dst {
+    ($dst:expr, $x:expr, $y:expr) => {
+        *$dst.offset($x + $y * BPS_)
+    };
+}
+
+macro_rules! avg3 {
+    ($a:expr, $b:expr, $c:expr) => {
+        ((($a) + 2 * ($b) + ($c) + 2) >> 2) as u8
+    };
+}
+
+// We use the following 8b-arithmetic tricks:
+//     (a + 2 * b + c + 2) >> 2 = (AC + b + 1) >> 1
+//   where: AC = (a + c) >> 1 = [(a + c + 1) >> 1] - [(a^c) & 1]
+// and:
+//     (a + 2 * b + c + 2) >> 2 = (AB + BC + 1) >> 1 - (ab|bc)&lsb
+//   where: AC = (a + b + 1) >> 1,   BC = (b + c + 1) >> 1
+//   and ab = a ^ b, bc = b ^ c, lsb = (AC^BC)&1
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VE4_SSE2(dst: *mut u8) {
+    // vertical
+    let one = _mm_set1_epi8(1);
+    let ABCDEFGH = _mm_loadl_epi64(dst.offset(-BPS_ - 1) as *const __m128i);
+    let BCDEFGH0 = _mm_srli_si128(ABCDEFGH, 1);
+    let CDEFGH00 = _mm_srli_si128(ABCDEFGH, 2);
+    let a = _mm_avg_epu8(ABCDEFGH, CDEFGH00);
+    let lsb = _mm_and_si128(_mm_xor_si128(ABCDEFGH, CDEFGH00), one);
+    let b = _mm_subs_epu8(a, lsb);
+    let avg = _mm_avg_epu8(b, BCDEFGH0);
+    let vals = _mm_cvtsi128_si32(avg) as u32;
+    for i in 0..4 {
+        WebPUint32ToMem(dst.offset(i * BPS_), vals);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn LD4_SSE2(dst: *mut u8) {
+    // Down-Left
+    let one = _mm_set1_epi8(1);
+    let ABCDEFGH = _mm_loadl_epi64(dst.offset(-BPS_) as *const __m128i);
+    let BCDEFGH0 = _mm_srli_si128(ABCDEFGH, 1);
+    let CDEFGH00 = _mm_srli_si128(ABCDEFGH, 2);
+    let CDEFGHH0 = _mm_insert_epi16(CDEFGH00, *dst.offset(-BPS_ + 7) as i32, 3);
+    let avg1 = _mm_avg_epu8(ABCDEFGH, CDEFGHH0);
+    let lsb = _mm_and_si128(_mm_xor_si128(ABCDEFGH, CDEFGHH0), one);
+    let avg2 = _mm_subs_epu8(avg1, lsb);
+    let abcdefg = _mm_avg_epu8(avg2, BCDEFGH0);
+    WebPUint32ToMem(dst.offset(0 * BPS_), _mm_cvtsi128_si32(abcdefg) as u32);
+    WebPUint32ToMem(
+        dst.offset(1 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(abcdefg, 1)) as u32,
+    );
+    WebPUint32ToMem(
+        dst.offset(2 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(abcdefg, 2)) as u32,
+    );
+    WebPUint32ToMem(
+        dst.offset(3 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(abcdefg, 3)) as u32,
+    );
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VR4_SSE2(dst: *mut u8) {
+    // Vertical-Right
+    let one = _mm_set1_epi8(1);
+    let I = dst!(dst, -1, 0) as c_int;
+    let J = dst!(dst, -1, 1) as c_int;
+    let K = dst!(dst, -1, 2) as c_int;
+    let X = dst!(dst, -1, -1) as c_int;
+    let XABCD = _mm_loadl_epi64(dst.offset(-BPS_ - 1) as *const __m128i);
+    let ABCD0 = _mm_srli_si128(XABCD, 1);
+    let abcd = _mm_avg_epu8(XABCD, ABCD0);
+    let _XABCD = _mm_slli_si128(XABCD, 1);
+    let IXABCD = _mm_insert_epi16(_XABCD, I | (X << 8), 0);
+    let avg1 = _mm_avg_epu8(IXABCD, ABCD0);
+    let lsb = _mm_and_si128(_mm_xor_si128(IXABCD, ABCD0), one);
+    let avg2 = _mm_subs_epu8(avg1, lsb);
+    let efgh = _mm_avg_epu8(avg2, XABCD);
+    WebPUint32ToMem(dst.offset(0 * BPS_), _mm_cvtsi128_si32(abcd) as u32);
+    WebPUint32ToMem(dst.offset(1 * BPS_), _mm_cvtsi128_si32(efgh) as u32);
+    WebPUint32ToMem(
+        dst.offset(2 * BPS_),
+        _mm_cvtsi128_si32(_mm_slli_si128(abcd, 1)) as u32,
+    );
+    WebPUint32ToMem(
+        dst.offset(3 * BPS_),
+        _mm_cvtsi128_si32(_mm_slli_si128(efgh, 1)) as u32,
+    );
+
+    // these two are hard to implement in SSE2, so we keep the C-version:
+    dst!(dst, 0, 2) = avg3!(J, I, X);
+    dst!(dst, 0, 3) = avg3!(K, J, I);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VL4_SSE2(dst: *mut u8) {
+    // Vertical-Left
+    let one = _mm_set1_epi8(1);
+    let ABCDEFGH = _mm_loadl_epi64(dst.offset(-BPS_) as *const __m128i);
+    let BCDEFGH_ = _mm_srli_si128(ABCDEFGH, 1);
+    let CDEFGH__ = _mm_srli_si128(ABCDEFGH, 2);
+    let avg1 = _mm_avg_epu8(ABCDEFGH, BCDEFGH_);
+    let avg2 = _mm_avg_epu8(CDEFGH__, BCDEFGH_);
+    let avg3 = _mm_avg_epu8(avg1, avg2);
+    let lsb1 = _mm_and_si128(_mm_xor_si128(avg1, avg2), one);
+    let ab = _mm_xor_si128(ABCDEFGH, BCDEFGH_);
+    let bc = _mm_xor_si128(CDEFGH__, BCDEFGH_);
+    let abbc = _mm_or_si128(ab, bc);
+    let lsb2 = _mm_and_si128(abbc, lsb1);
+    let avg4 = _mm_subs_epu8(avg3, lsb2);
+    let extra_out = _mm_cvtsi128_si32(_mm_srli_si128(avg4, 4)) as u32;
+    WebPUint32ToMem(dst.offset(0 * BPS_), _mm_cvtsi128_si32(avg1) as u32);
+    WebPUint32ToMem(dst.offset(1 * BPS_), _mm_cvtsi128_si32(avg4) as u32);
+    WebPUint32ToMem(
+        dst.offset(2 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(avg1, 1)) as u32,
+    );
+    WebPUint32ToMem(
+        dst.offset(3 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(avg4, 1)) as u32,
+    );
+
+    // these two are hard to get and irregular
+    dst!(dst, 3, 2) = ((extra_out >> 0) & 0xff) as u8;
+    dst!(dst, 3, 3) = ((extra_out >> 8) & 0xff) as u8;
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn RD4_SSE2(dst: *mut u8) {
+    // Down-right
+    let one = _mm_set1_epi8(1);
+    let XABCD = _mm_loadl_epi64(dst.offset(-BPS_ - 1) as *const __m128i);
+    let ____XABCD = _mm_slli_si128(XABCD, 4);
+    let I = dst!(dst, -1, 0) as u32;
+    let J = dst!(dst, -1, 1) as u32;
+    let K = dst!(dst, -1, 2) as u32;
+    let L = dst!(dst, -1, 3) as u32;
+    let LKJI_____ = _mm_cvtsi32_si128((L | (K << 8) | (J << 16) | (I << 24)) as i32);
+    let LKJIXABCD = _mm_or_si128(LKJI_____, ____XABCD);
+    let KJIXABCD_ = _mm_srli_si128(LKJIXABCD, 1);
+    let JIXABCD__ = _mm_srli_si128(LKJIXABCD, 2);
+    let avg1 = _mm_avg_epu8(JIXABCD__, LKJIXABCD);
+    let lsb = _mm_and_si128(_mm_xor_si128(JIXABCD__, LKJIXABCD), one);
+    let avg2 = _mm_subs_epu8(avg1, lsb);
+    let abcdefg = _mm_avg_epu8(avg2, KJIXABCD_);
+    WebPUint32ToMem(dst.offset(3 * BPS_), _mm_cvtsi128_si32(abcdefg) as u32);
+    WebPUint32ToMem(
+        dst.offset(2 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(abcdefg, 1)) as u32,
+    );
+    WebPUint32ToMem(
+        dst.offset(1 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(abcdefg, 2)) as u32,
+    );
+    WebPUint32ToMem(
+        dst.offset(0 * BPS_),
+        _mm_cvtsi128_si32(_mm_srli_si128(abcdefg, 3)) as u32,
+    );
+}
+
+//------------------------------------------------------------------------------
+// Luma 16x16
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn TrueMotion_SSE2(mut dst: *mut u8, size: c_int) {
+    let top: *const u8 = dst.offset(-BPS_);
+    let zero = _mm_setzero_si128();
+    if size == 4 {
+        let top_values = _mm_cvtsi32_si128(WebPMemToUint32(top) as i32);
+        let top_base = _mm_unpacklo_epi8(top_values, zero);
+        for _ in 0..4 {
+            let val = *dst.offset(-1) as c_int - *top.offset(-1) as c_int;
+            let base = _mm_set1_epi16(val as i16);
+            let out = _mm_packus_epi16(_mm_add_epi16(base, top_base), zero);
+            WebPUint32ToMem(dst, _mm_cvtsi128_si32(out) as u32);
+            dst = dst.offset(BPS_);
+        }
+    } else if size == 8 {
+        let top_values = _mm_loadl_epi64(top as *const __m128i);
+        let top_base = _mm_unpacklo_epi8(top_values, zero);
+        for _ in 0..8 {
+            let val = *dst.offset(-1) as c_int - *top.offset(-1) as c_int;
+            let base = _mm_set1_epi16(val as i16);
+            let out = _mm_packus_epi16(_mm_add_epi16(base, top_base), zero);
+            _mm_storel_epi64(dst as *mut __m128i, out);
+            dst = dst.offset(BPS_);
+        }
+    } else {
+        let top_values = _mm_loadu_si128(top as *const __m128i);
+        let top_base_0 = _mm_unpacklo_epi8(top_values, zero);
+        let top_base_1 = _mm_unpackhi_epi8(top_values, zero);
+        for _ in 0..16 {
+            let val = *dst.offset(-1) as c_int - *top.offset(-1) as c_int;
+            let base = _mm_set1_epi16(val as i16);
+            let out_0 = _mm_add_epi16(base, top_base_0);
+            let out_1 = _mm_add_epi16(base, top_base_1);
+            let out = _mm_packus_epi16(out_0, out_1);
+            _mm_storeu_si128(dst as *mut __m128i, out);
+            dst = dst.offset(BPS_);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn TM4_SSE2(dst: *mut u8) {
+    TrueMotion_SSE2(dst, 4);
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn TM8uv_SSE2(dst: *mut u8) {
+    TrueMotion_SSE2(dst, 8);
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn TM16_SSE2(dst: *mut u8) {
+    TrueMotion_SSE2(dst, 16);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VE16_SSE2(dst: *mut u8) {
+    let top = _mm_loadu_si128(dst.offset(-BPS_) as *const __m128i);
+    for j in 0..16 {
+        _mm_storeu_si128(dst.offset(j * BPS_) as *mut __m128i, top);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn HE16_SSE2(mut dst: *mut u8) {
+    // horizontal
+    for _ in 0..16 {
+        let values = _mm_set1_epi8(*dst.offset(-1) as i8);
+        _mm_storeu_si128(dst as *mut __m128i, values);
+        dst = dst.offset(BPS_);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Put16_SSE2(v: u8, dst: *mut u8) {
+    let values = _mm_set1_epi8(v as i8);
+    for j in 0..16 {
+        _mm_storeu_si128(dst.offset(j * BPS_) as *mut __m128i, values);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC16_SSE2(dst: *mut u8) {
+    // DC
+    let zero = _mm_setzero_si128();
+    let top = _mm_loadu_si128(dst.offset(-BPS_) as *const __m128i);
+    let sad8x2 = _mm_sad_epu8(top, zero);
+    // sum the two sads: sad8x2[0:1] + sad8x2[8:9]
+    let sum = _mm_add_epi16(sad8x2, _mm_shuffle_epi32(sad8x2, 2));
+    let mut left: c_int = 0;
+    for j in 0..16 {
+        left += dst!(dst, -1, j) as c_int;
+    }
+    {
+        let DC = _mm_cvtsi128_si32(sum) + left + 16;
+        Put16_SSE2((DC >> 5) as u8, dst);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC16NoTop_SSE2(dst: *mut u8) {
+    // DC with top samples unavailable
+    let mut DC: c_int = 8;
+    for j in 0..16 {
+        DC += dst!(dst, -1, j) as c_int;
+    }
+    Put16_SSE2((DC >> 4) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC16NoLeft_SSE2(dst: *mut u8) {
+    // DC with left samples unavailable
+    let zero = _mm_setzero_si128();
+    let top = _mm_loadu_si128(dst.offset(-BPS_) as *const __m128i);
+    let sad8x2 = _mm_sad_epu8(top, zero);
+    // sum the two sads: sad8x2[0:1] + sad8x2[8:9]
+    let sum = _mm_add_epi16(sad8x2, _mm_shuffle_epi32(sad8x2, 2));
+    let DC = _mm_cvtsi128_si32(sum) + 8;
+    Put16_SSE2((DC >> 4) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC16NoTopLeft_SSE2(dst: *mut u8) {
+    // DC with no top & left samples
+    Put16_SSE2(0x80, dst);
+}
+
+//------------------------------------------------------------------------------
+// Chroma
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn VE8uv_SSE2(dst: *mut u8) {
+    // vertical
+    let top = _mm_loadl_epi64(dst.offset(-BPS_) as *const __m128i);
+    for j in 0..8 {
+        _mm_storel_epi64(dst.offset(j * BPS_) as *mut __m128i, top);
+    }
+}
+
+// helper for chroma-DC predictions
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Put8x8uv_SSE2(v: u8, dst: *mut u8) {
+    let values = _mm_set1_epi8(v as i8);
+    for j in 0..8 {
+        _mm_storel_epi64(dst.offset(j * BPS_) as *mut __m128i, values);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC8uv_SSE2(dst: *mut u8) {
+    // DC
+    let zero = _mm_setzero_si128();
+    let top = _mm_loadl_epi64(dst.offset(-BPS_) as *const __m128i);
+    let sum = _mm_sad_epu8(top, zero);
+    let mut left: c_int = 0;
+    for j in 0..8 {
+        left += dst!(dst, -1, j) as c_int;
+    }
+    {
+        let DC = _mm_cvtsi128_si32(sum) + left + 8;
+        Put8x8uv_SSE2((DC >> 4) as u8, dst);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC8uvNoLeft_SSE2(dst: *mut u8) {
+    // DC with no left samples
+    let zero = _mm_setzero_si128();
+    let top = _mm_loadl_epi64(dst.offset(-BPS_) as *const __m128i);
+    let sum = _mm_sad_epu8(top, zero);
+    let DC = _mm_cvtsi128_si32(sum) + 4;
+    Put8x8uv_SSE2((DC >> 3) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC8uvNoTop_SSE2(dst: *mut u8) {
+    // DC with no top samples
+    let mut dc0: c_int = 4;
+    for i in 0..8 {
+        dc0 += dst!(dst, -1, i) as c_int;
+    }
+    Put8x8uv_SSE2((dc0 >> 3) as u8, dst);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn DC8uvNoTopLeft_SSE2(dst: *mut u8) {
+    // DC with nothing
+    Put8x8uv_SSE2(0x80, dst);
+}
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8DspInitSSE2() {
+    VP8Transform = Some(Transform_SSE2);
+
+    VP8VFilter16 = Some(VFilter16_SSE2);
+    VP8HFilter16 = Some(HFilter16_SSE2);
+    VP8VFilter8 = Some(VFilter8_SSE2);
+    VP8HFilter8 = Some(HFilter8_SSE2);
+    VP8VFilter16i = Some(VFilter16i_SSE2);
+    VP8HFilter16i = Some(HFilter16i_SSE2);
+    VP8VFilter8i = Some(VFilter8i_SSE2);
+    VP8HFilter8i = Some(HFilter8i_SSE2);
+
+    VP8SimpleVFilter16 = Some(SimpleVFilter16_SSE2);
+    VP8SimpleHFilter16 = Some(SimpleHFilter16_SSE2);
+    VP8SimpleVFilter16i = Some(SimpleVFilter16i_SSE2);
+    VP8SimpleHFilter16i = Some(SimpleHFilter16i_SSE2);
+
+    VP8PredLuma4[1] = Some(TM4_SSE2);
+    VP8PredLuma4[2] = Some(VE4_SSE2);
+    VP8PredLuma4[4] = Some(RD4_SSE2);
+    VP8PredLuma4[5] = Some(VR4_SSE2);
+    VP8PredLuma4[6] = Some(LD4_SSE2);
+    VP8PredLuma4[7] = Some(VL4_SSE2);
+
+    VP8PredLuma16[0] = Some(DC16_SSE2);
+    VP8PredLuma16[1] = Some(TM16_SSE2);
+    VP8PredLuma16[2] = Some(VE16_SSE2);
+    VP8PredLuma16[3] = Some(HE16_SSE2);
+    VP8PredLuma16[4] = Some(DC16NoTop_SSE2);
+    VP8PredLuma16[5] = Some(DC16NoLeft_SSE2);
+    VP8PredLuma16[6] = Some(DC16NoTopLeft_SSE2);
+
+    VP8PredChroma8[0] = Some(DC8uv_SSE2);
+    VP8PredChroma8[1] = Some(TM8uv_SSE2);
+    VP8PredChroma8[2] = Some(VE8uv_SSE2);
+    VP8PredChroma8[4] = Some(DC8uvNoTop_SSE2);
+    VP8PredChroma8[5] = Some(DC8uvNoLeft_SSE2);
+    VP8PredChroma8[6] = Some(DC8uvNoTopLeft_SSE2);
+}
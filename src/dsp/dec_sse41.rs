@@ -0,0 +1,43 @@
+// Copyright 2015 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE4 version of some decoding functions.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use dsp::dec::VP8PredLuma16;
+use dsp::dsp::BPS;
+use utils::utils::WebPMemToUint32;
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe extern "C" fn HE16_SSE41(mut dst: *mut u8) {
+    // horizontal
+    let kShuffle3 = _mm_set1_epi8(3);
+    for _ in 0..16 {
+        let in_ = _mm_cvtsi32_si128(WebPMemToUint32(dst.offset(-4)) as i32);
+        let values = _mm_shuffle_epi8(in_, kShuffle3);
+        _mm_storeu_si128(dst as *mut __m128i, values);
+        dst = dst.offset(BPS as isize);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8DspInitSSE41() {
+    VP8PredLuma16[3] = Some(HE16_SSE41);
+}
@@ -1,12 +1,151 @@
 use std::os::raw::*;
 
+use utils::rescaler_utils::WebPRescaler;
+
+pub(crate) use self::CPUFeature::*;
 pub(crate) use self::WEBP_FILTER_TYPE::*;
 
+//------------------------------------------------------------------------------
+// CPU detection
+
+#[allow(dead_code)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum CPUFeature {
+    kSSE2,
+    kSSE3,
+    kSlowSSSE3, // special feature for slow SSSE3 architectures
+    kSSE4_1,
+    kAVX,
+    kAVX2,
+    kNEON,
+    kMIPS32,
+    kMIPSdspR2,
+    kMSA,
+}
+
+// returns true if the CPU supports the feature.
+pub type VP8CPUInfo = Option<unsafe extern "C" fn(feature: CPUFeature) -> c_int>;
+
+// Runs the body of a dsp init function again only when VP8GetCPUInfo has
+// changed since the last call (see WEBP_DSP_INIT_FUNC in dsp.h).
+macro_rules! webp_dsp_init_func {
+    ($(#[$attr:meta])* $vis:vis fn $name:ident() $body:block) => {
+        $(#[$attr])*
+        $vis unsafe extern "C" fn $name() {
+            static LAST_CPUINFO_USED: ::std::sync::Mutex<Option<Option<usize>>> =
+                ::std::sync::Mutex::new(None);
+            let cpuinfo = Some(::dsp::cpu::VP8GetCPUInfo.map(|f| f as usize));
+            if let Ok(mut last_cpuinfo_used) = LAST_CPUINFO_USED.lock() {
+                if *last_cpuinfo_used != cpuinfo {
+                    $body
+                }
+                *last_cpuinfo_used = cpuinfo;
+            }
+        }
+    };
+}
+
+pub(crate) const BPS: c_int = 32; // this is the common stride for enc/dec
+
+//------------------------------------------------------------------------------
+// Decoding
+
+pub type VP8DecIdct = Option<unsafe extern "C" fn(coeffs: *const i16, dst: *mut u8)>;
+// when doing two transforms, coeffs is actually int16_t[2][16].
+pub type VP8DecIdct2 =
+    Option<unsafe extern "C" fn(coeffs: *const i16, dst: *mut u8, do_two: c_int)>;
+
+// Transforms
+pub type VP8WHT = Option<unsafe extern "C" fn(in_: *const i16, out: *mut i16)>;
+
+// *dst is the destination block, with stride BPS. Boundary samples are
+// assumed accessible when needed.
+pub type VP8PredFunc = Option<unsafe extern "C" fn(dst: *mut u8)>;
+
+// simple filter (only for luma)
+pub type VP8SimpleFilterFunc =
+    Option<unsafe extern "C" fn(p: *mut u8, stride: c_int, thresh: c_int)>;
+// regular filter (on both macroblock edges and inner edges)
+pub type VP8LumaFilterFunc = Option<
+    unsafe extern "C" fn(luma: *mut u8, stride: c_int, thresh: c_int, ithresh: c_int, hev_t: c_int),
+>;
+pub type VP8ChromaFilterFunc = Option<
+    unsafe extern "C" fn(
+        u: *mut u8,
+        v: *mut u8,
+        stride: c_int,
+        thresh: c_int,
+        ithresh: c_int,
+        hev_t: c_int,
+    ),
+>;
+
+// Dithering. Combines dithering values (centered around 128) with dst[],
+// according to: dst[] = clip(dst[] + (((dither[]-128) + 8) >> 4)
+pub(crate) const VP8_DITHER_DESCALE: c_int = 4;
+pub(crate) const VP8_DITHER_DESCALE_ROUNDER: c_int = 1 << (VP8_DITHER_DESCALE - 1);
+pub(crate) const VP8_DITHER_AMP_BITS: c_int = 7;
+pub(crate) const VP8_DITHER_AMP_CENTER: c_int = 1 << VP8_DITHER_AMP_BITS;
+
+//------------------------------------------------------------------------------
+// WebP I/O
+
+// Convert a pair of y/u/v lines together to the output rgb/a colorspace.
+// bottom_y can be NULL if only one line of output is needed (at top/bottom).
+pub(crate) type WebPUpsampleLinePairFunc = Option<
+    unsafe extern "C" fn(
+        top_y: *const u8,
+        bottom_y: *const u8,
+        top_u: *const u8,
+        top_v: *const u8,
+        cur_u: *const u8,
+        cur_v: *const u8,
+        top_dst: *mut u8,
+        bottom_dst: *mut u8,
+        len: c_int,
+    ),
+>;
+
+// Per-row point-sampling methods.
+pub(crate) type WebPSamplerRowFunc = Option<
+    unsafe extern "C" fn(y: *const u8, u: *const u8, v: *const u8, dst: *mut u8, len: c_int),
+>;
+
+// YUV444->RGB converters
+pub(crate) type WebPYUV444Converter = Option<
+    unsafe extern "C" fn(y: *const u8, u: *const u8, v: *const u8, dst: *mut u8, len: c_int),
+>;
+
+//------------------------------------------------------------------------------
+// Rescaler
+
+// Import a row of data and save its contribution in the rescaler.
+// 'channel' denotes the channel number to be imported. 'Expand' corresponds to
+// the wrk->x_expand case. Otherwise, 'Shrink' is to be used.
+pub(crate) type WebPRescalerImportRowFunc =
+    Option<unsafe extern "C" fn(wrk: *mut WebPRescaler, src: *const u8)>;
+
+// Export one row (starting at x_out position) from rescaler.
+// 'Expand' corresponds to the wrk->y_expand case.
+// Otherwise 'Shrink' is to be used
+pub(crate) type WebPRescalerExportRowFunc = Option<unsafe extern "C" fn(wrk: *mut WebPRescaler)>;
+
+//------------------------------------------------------------------------------
+// Utilities for processing transparent channel.
+
+// Regularize the definition of WEBP_SWAP_16BIT_CSP (backward compatibility)
+pub(crate) const WEBP_SWAP_16BIT_CSP: c_int = 0;
+
+//------------------------------------------------------------------------------
+// Filter functions
+
 #[allow(dead_code)]
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
-pub(crate) enum WEBP_FILTER_TYPE {
+pub enum WEBP_FILTER_TYPE {
     // Filter types.
     WEBP_FILTER_NONE = 0,
     WEBP_FILTER_HORIZONTAL,
@@ -41,8 +180,12 @@ impl WEBP_FILTER_TYPE {
     }
 }
 
-pub(crate) type WebPFilterFunc = Option<extern "C" fn(*const u8, c_int, c_int, c_int, *mut u8)>;
+pub(crate) type WebPFilterFunc = Option<
+    unsafe extern "C" fn(in_: *const u8, width: c_int, height: c_int, stride: c_int, out: *mut u8),
+>;
 
 // In-place un-filtering.
 // Warning! 'prev_line' pointer can be equal to 'cur_line' or 'preds'.
-pub(crate) type WebPUnfilterFunc = Option<extern "C" fn(*const u8, *const u8, *mut u8, c_int)>;
+pub(crate) type WebPUnfilterFunc = Option<
+    unsafe extern "C" fn(prev_line: *const u8, preds: *const u8, cur_line: *mut u8, width: c_int),
+>;
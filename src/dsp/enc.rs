@@ -0,0 +1,157 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+//   Speed-critical functions.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+// The encoding half of dsp.h. The functions themselves are still the C ones
+// (enc.c, cost.c, ssim.c and their SIMD variants).
+
+use std::os::raw::*;
+
+use dsp::dsp::VP8WHT;
+use enc::cost_enc::VP8Residual;
+use enc::vp8i_enc::VP8Matrix;
+
+//------------------------------------------------------------------------------
+// Encoding
+
+// Transforms
+// VP8Idct: Does one of two inverse transforms. If do_two is set, the transforms
+//          will be done for (ref, in, dst) and (ref + 4, in + 16, dst + 4).
+pub(crate) type VP8Idct =
+    Option<unsafe extern "C" fn(ref_: *const u8, in_: *const i16, dst: *mut u8, do_two: c_int)>;
+pub(crate) type VP8Fdct =
+    Option<unsafe extern "C" fn(src: *const u8, ref_: *const u8, out: *mut i16)>;
+// Predictions
+// *dst is the destination block. *top and *left can be NULL.
+pub(crate) type VP8IntraPreds =
+    Option<unsafe extern "C" fn(dst: *mut u8, left: *const u8, top: *const u8)>;
+pub(crate) type VP8Intra4Preds = Option<unsafe extern "C" fn(dst: *mut u8, top: *const u8)>;
+
+pub(crate) type VP8Metric = Option<unsafe extern "C" fn(pix: *const u8, ref_: *const u8) -> c_int>;
+// The weights for VP8TDisto4x4 and VP8TDisto16x16 contain a row-major
+// 4 by 4 symmetric matrix.
+pub(crate) type VP8WMetric =
+    Option<unsafe extern "C" fn(pix: *const u8, ref_: *const u8, weights: *const u16) -> c_int>;
+
+// Compute the average (DC) of four 4x4 blocks.
+// Each sub-4x4 block #i sum is stored in dc[i].
+pub(crate) type VP8MeanMetric = Option<unsafe extern "C" fn(ref_: *const u8, dc: *mut u32)>;
+
+pub(crate) type VP8BlockCopy = Option<unsafe extern "C" fn(src: *const u8, dst: *mut u8)>;
+// Quantization
+pub(crate) type VP8QuantizeBlock =
+    Option<unsafe extern "C" fn(in_: *mut i16, out: *mut i16, mtx: *const VP8Matrix) -> c_int>;
+// Same as VP8QuantizeBlock, but quantizes two consecutive blocks.
+pub(crate) type VP8Quantize2Blocks =
+    Option<unsafe extern "C" fn(in_: *mut i16, out: *mut i16, mtx: *const VP8Matrix) -> c_int>;
+
+// specific to 2nd transform:
+pub(crate) type VP8QuantizeBlockWHT =
+    Option<unsafe extern "C" fn(in_: *mut i16, out: *mut i16, mtx: *const VP8Matrix) -> c_int>;
+
+// Collect histogram for susceptibility calculation.
+#[repr(C)]
+pub(crate) struct VP8Histogram {
+    // We only need to store max_value and last_non_zero, not the distribution.
+    pub(crate) max_value: c_int,
+    pub(crate) last_non_zero: c_int,
+}
+pub(crate) type VP8CHisto = Option<
+    unsafe extern "C" fn(
+        ref_: *const u8,
+        pred: *const u8,
+        start_block: c_int,
+        end_block: c_int,
+        histo: *mut VP8Histogram,
+    ),
+>;
+
+extern "C" {
+    pub(crate) static mut VP8ITransform: VP8Idct;
+    pub(crate) static mut VP8FTransform: VP8Fdct;
+    pub(crate) static mut VP8FTransform2: VP8Fdct; // performs two transforms at a time
+    pub(crate) static mut VP8FTransformWHT: VP8WHT;
+
+    pub(crate) static mut VP8EncPredLuma4: VP8Intra4Preds;
+    pub(crate) static mut VP8EncPredLuma16: VP8IntraPreds;
+    pub(crate) static mut VP8EncPredChroma8: VP8IntraPreds;
+
+    pub(crate) static mut VP8SSE16x16: VP8Metric;
+    pub(crate) static mut VP8SSE16x8: VP8Metric;
+    pub(crate) static mut VP8SSE8x8: VP8Metric;
+    pub(crate) static mut VP8SSE4x4: VP8Metric;
+    pub(crate) static mut VP8TDisto4x4: VP8WMetric;
+    pub(crate) static mut VP8TDisto16x16: VP8WMetric;
+
+    pub(crate) static mut VP8Mean16x4: VP8MeanMetric;
+
+    pub(crate) static mut VP8Copy4x4: VP8BlockCopy;
+    pub(crate) static mut VP8Copy16x8: VP8BlockCopy;
+
+    pub(crate) static mut VP8EncQuantizeBlock: VP8QuantizeBlock;
+    pub(crate) static mut VP8EncQuantize2Blocks: VP8Quantize2Blocks;
+    pub(crate) static mut VP8EncQuantizeBlockWHT: VP8QuantizeBlockWHT;
+
+    pub(crate) static mut VP8CollectHistogram: VP8CHisto;
+
+    // must be called before using any of the above
+    pub(crate) fn VP8EncDspInit();
+}
+
+//------------------------------------------------------------------------------
+// cost functions (encoding)
+
+pub(crate) type VP8SetResidualCoeffsFunc =
+    Option<unsafe extern "C" fn(coeffs: *const i16, res: *mut VP8Residual)>;
+
+// Cost calculation function.
+pub(crate) type VP8GetResidualCostFunc =
+    Option<unsafe extern "C" fn(ctx0: c_int, res: *const VP8Residual) -> c_int>;
+
+extern "C" {
+    pub(crate) static VP8EntropyCost: [u16; 256]; // 8bit fixed-point log(p)
+                                                  // approximate cost per level:
+    pub(crate) static VP8LevelFixedCosts: [u16; 2047 /*MAX_LEVEL*/ + 1];
+    pub(crate) static VP8EncBands: [u8; 16 + 1];
+
+    pub(crate) static mut VP8SetResidualCoeffs: VP8SetResidualCoeffsFunc;
+    pub(crate) static mut VP8GetResidualCost: VP8GetResidualCostFunc;
+
+    // must be called before anything using the above
+    pub(crate) fn VP8EncDspCostInit();
+}
+
+//------------------------------------------------------------------------------
+// SSIM / PSNR utils
+
+pub(crate) const VP8_SSIM_KERNEL: c_int = 3; // total size of the kernel: 2 * VP8_SSIM_KERNEL + 1
+#[allow(non_snake_case)]
+pub(crate) type VP8SSIMGetClippedFunc = Option<
+    unsafe extern "C" fn(
+        src1: *const u8,
+        stride1: c_int,
+        src2: *const u8,
+        stride2: c_int,
+        xo: c_int, // center position
+        yo: c_int,
+        W: c_int, // plane dimension
+        H: c_int,
+    ) -> c_double,
+>;
+
+extern "C" {
+    pub(crate) static mut VP8SSIMGetClipped: VP8SSIMGetClippedFunc; // with clipping
+
+    // must be called before using any of the above directly
+    pub(crate) fn VP8SSIMDspInit();
+}
@@ -1,6 +1,585 @@
-use dsp::dsp::{WebPUnfilterFunc, WEBP_FILTER_LAST};
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Spatial prediction using various filters
+//
+// Author: Urvang (urvang@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
 
+use std::os::raw::*;
+use std::ptr;
+
+#[cfg(not(any(target_arch = "aarch64", feature = "pure-rust-decoder")))]
+use dsp::cpu::VP8GetCPUInfo;
+#[cfg(not(any(target_arch = "aarch64", feature = "pure-rust-decoder")))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::WEBP_FILTER_TYPE::*;
+use dsp::dsp::{WebPFilterFunc, WebPUnfilterFunc, WEBP_FILTER_TYPE};
+
+//------------------------------------------------------------------------------
+// Helpful macro.
+
+// The height of the picture is not needed by the Do*Filter_C() functions
+// themselves, so it is checked by their callers instead.
+macro_rules! sanity_check {
+    ($in_:expr, $out:expr, $width:expr, $stride:expr, $row:expr, $num_rows:expr) => {
+        c_assert!(!$in_.is_null());
+        c_assert!(!$out.is_null());
+        c_assert!($width > 0);
+        c_assert!($stride >= $width);
+        c_assert!($row >= 0 && $num_rows > 0);
+    };
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn PredictLine_C(
+    src: *const u8,
+    pred: *const u8,
+    dst: *mut u8,
+    length: c_int,
+    inverse: c_int,
+) {
+    if inverse != 0 {
+        for i in 0..length as isize {
+            *dst.offset(i) = (*src.offset(i)).wrapping_add(*pred.offset(i));
+        }
+    } else {
+        for i in 0..length as isize {
+            *dst.offset(i) = (*src.offset(i)).wrapping_sub(*pred.offset(i));
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Horizontal filter.
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoHorizontalFilter_C(
+    mut in_: *const u8,
+    width: c_int,
+    stride: c_int,
+    mut row: c_int,
+    num_rows: c_int,
+    inverse: c_int,
+    mut out: *mut u8,
+) {
+    let start_offset = row as isize * stride as isize;
+    let last_row = row + num_rows;
+    sanity_check!(in_, out, width, stride, row, num_rows);
+    in_ = in_.offset(start_offset);
+    out = out.offset(start_offset);
+    let mut preds: *const u8 = if inverse != 0 { out } else { in_ };
+
+    if row == 0 {
+        // Leftmost pixel is the same as input for topmost scanline.
+        *out = *in_;
+        PredictLine_C(in_.offset(1), preds, out.offset(1), width - 1, inverse);
+        row = 1;
+        preds = preds.offset(stride as isize);
+        in_ = in_.offset(stride as isize);
+        out = out.offset(stride as isize);
+    }
+
+    // Filter line-by-line.
+    while row < last_row {
+        // Leftmost pixel is predicted from above.
+        PredictLine_C(in_, preds.offset(-stride as isize), out, 1, inverse);
+        PredictLine_C(in_.offset(1), preds, out.offset(1), width - 1, inverse);
+        row += 1;
+        preds = preds.offset(stride as isize);
+        in_ = in_.offset(stride as isize);
+        out = out.offset(stride as isize);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Vertical filter.
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoVerticalFilter_C(
+    mut in_: *const u8,
+    width: c_int,
+    stride: c_int,
+    mut row: c_int,
+    num_rows: c_int,
+    inverse: c_int,
+    mut out: *mut u8,
+) {
+    let start_offset = row as isize * stride as isize;
+    let last_row = row + num_rows;
+    sanity_check!(in_, out, width, stride, row, num_rows);
+    in_ = in_.offset(start_offset);
+    out = out.offset(start_offset);
+    let mut preds: *const u8 = if inverse != 0 { out } else { in_ };
+
+    if row == 0 {
+        // Very first top-left pixel is copied.
+        *out = *in_;
+        // Rest of top scan-line is left-predicted.
+        PredictLine_C(in_.offset(1), preds, out.offset(1), width - 1, inverse);
+        row = 1;
+        in_ = in_.offset(stride as isize);
+        out = out.offset(stride as isize);
+    } else {
+        // We are starting from in-between. Make sure 'preds' points to prev row.
+        preds = preds.offset(-stride as isize);
+    }
+
+    // Filter line-by-line.
+    while row < last_row {
+        PredictLine_C(in_, preds, out, width, inverse);
+        row += 1;
+        preds = preds.offset(stride as isize);
+        in_ = in_.offset(stride as isize);
+        out = out.offset(stride as isize);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Gradient filter.
+
+#[allow(non_snake_case)]
+#[inline]
+fn GradientPredictor_C(a: u8, b: u8, c: u8) -> c_int {
+    let g = a as c_int + b as c_int - c as c_int;
+    if (g & !0xff) == 0 {
+        g
+    } else if g < 0 {
+        0
+    } else {
+        255
+    } // clip to 8bit
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn DoGradientFilter_C(
+    mut in_: *const u8,
+    width: c_int,
+    stride: c_int,
+    mut row: c_int,
+    num_rows: c_int,
+    inverse: c_int,
+    mut out: *mut u8,
+) {
+    let start_offset = row as isize * stride as isize;
+    let last_row = row + num_rows;
+    sanity_check!(in_, out, width, stride, row, num_rows);
+    in_ = in_.offset(start_offset);
+    out = out.offset(start_offset);
+    let mut preds: *const u8 = if inverse != 0 { out } else { in_ };
+
+    // left prediction for top scan-line
+    if row == 0 {
+        *out = *in_;
+        PredictLine_C(in_.offset(1), preds, out.offset(1), width - 1, inverse);
+        row = 1;
+        preds = preds.offset(stride as isize);
+        in_ = in_.offset(stride as isize);
+        out = out.offset(stride as isize);
+    }
+
+    // Filter line-by-line.
+    while row < last_row {
+        // leftmost pixel: predict from above.
+        PredictLine_C(in_, preds.offset(-stride as isize), out, 1, inverse);
+        for w in 1..width as isize {
+            let pred = GradientPredictor_C(
+                *preds.offset(w - 1),
+                *preds.offset(w - stride as isize),
+                *preds.offset(w - stride as isize - 1),
+            ) as u8;
+            *out.offset(w) = if inverse != 0 {
+                (*in_.offset(w)).wrapping_add(pred)
+            } else {
+                (*in_.offset(w)).wrapping_sub(pred)
+            };
+        }
+        row += 1;
+        preds = preds.offset(stride as isize);
+        in_ = in_.offset(stride as isize);
+        out = out.offset(stride as isize);
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HorizontalFilter_C(
+    data: *const u8,
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+    filtered_data: *mut u8,
+) {
+    c_assert!(height > 0);
+    DoHorizontalFilter_C(data, width, stride, 0, height, 0, filtered_data);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VerticalFilter_C(
+    data: *const u8,
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+    filtered_data: *mut u8,
+) {
+    c_assert!(height > 0);
+    DoVerticalFilter_C(data, width, stride, 0, height, 0, filtered_data);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn GradientFilter_C(
+    data: *const u8,
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+    filtered_data: *mut u8,
+) {
+    c_assert!(height > 0);
+    DoGradientFilter_C(data, width, stride, 0, height, 0, filtered_data);
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn HorizontalUnfilter_C(
+    prev: *const u8,
+    in_: *const u8,
+    out: *mut u8,
+    width: c_int,
+) {
+    let mut pred: u8 = if prev.is_null() { 0 } else { *prev };
+    for i in 0..width as isize {
+        *out.offset(i) = pred.wrapping_add(*in_.offset(i));
+        pred = *out.offset(i);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn VerticalUnfilter_C(
+    prev: *const u8,
+    in_: *const u8,
+    out: *mut u8,
+    width: c_int,
+) {
+    if prev.is_null() {
+        HorizontalUnfilter_C(ptr::null(), in_, out, width);
+    } else {
+        for i in 0..width as isize {
+            *out.offset(i) = (*prev.offset(i)).wrapping_add(*in_.offset(i));
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn GradientUnfilter_C(
+    prev: *const u8,
+    in_: *const u8,
+    out: *mut u8,
+    width: c_int,
+) {
+    if prev.is_null() {
+        HorizontalUnfilter_C(ptr::null(), in_, out, width);
+    } else {
+        let mut top: u8 = *prev;
+        let mut top_left: u8 = top;
+        let mut left: u8 = top;
+        for i in 0..width as isize {
+            top = *prev.offset(i); // need to read this first, in case prev==out
+            left = (*in_.offset(i)).wrapping_add(GradientPredictor_C(left, top, top_left) as u8);
+            top_left = top;
+            *out.offset(i) = left;
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Init function
+
+#[no_mangle]
+pub static mut WebPFilters: [WebPFilterFunc; WEBP_FILTER_LAST as usize] =
+    [None; WEBP_FILTER_LAST as usize];
+#[no_mangle]
+pub static mut WebPUnfilters: [WebPUnfilterFunc; WEBP_FILTER_LAST as usize] =
+    [None; WEBP_FILTER_LAST as usize];
+
+#[cfg(not(feature = "pure-rust-decoder"))]
 extern "C" {
-    pub(crate) static WebPUnfilters: [WebPUnfilterFunc; WEBP_FILTER_LAST as usize];
-    pub(crate) fn VP8FiltersInit();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8FiltersInitMIPSdspR2();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8FiltersInitMSA();
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn VP8FiltersInitNEON();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn VP8FiltersInitSSE2();
+}
+
+webp_dsp_init_func! {
+    // To be called first before using the above.
+    #[no_mangle]
+    pub fn VP8FiltersInit() {
+        WebPUnfilters[WEBP_FILTER_NONE as usize] = None;
+        WebPUnfilters[WEBP_FILTER_HORIZONTAL as usize] = Some(HorizontalUnfilter_C);
+        WebPUnfilters[WEBP_FILTER_VERTICAL as usize] = Some(VerticalUnfilter_C);
+        WebPUnfilters[WEBP_FILTER_GRADIENT as usize] = Some(GradientUnfilter_C);
+
+        WebPFilters[WEBP_FILTER_NONE as usize] = None;
+        WebPFilters[WEBP_FILTER_HORIZONTAL as usize] = Some(HorizontalFilter_C);
+        WebPFilters[WEBP_FILTER_VERTICAL as usize] = Some(VerticalFilter_C);
+        WebPFilters[WEBP_FILTER_GRADIENT as usize] = Some(GradientFilter_C);
+
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    VP8FiltersInitSSE2();
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    VP8FiltersInitMIPSdspR2();
+                }
+                if get_cpu_info(kMSA) != 0 {
+                    VP8FiltersInitMSA();
+                }
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "pure-rust-decoder")))]
+        {
+            VP8FiltersInitNEON();
+        }
+        #[cfg(all(target_arch = "arm", not(feature = "pure-rust-decoder")))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    VP8FiltersInitNEON();
+                }
+            }
+        }
+
+        c_assert!(WebPUnfilters[WEBP_FILTER_HORIZONTAL as usize].is_some());
+        c_assert!(WebPUnfilters[WEBP_FILTER_VERTICAL as usize].is_some());
+        c_assert!(WebPUnfilters[WEBP_FILTER_GRADIENT as usize].is_some());
+        c_assert!(WebPFilters[WEBP_FILTER_HORIZONTAL as usize].is_some());
+        c_assert!(WebPFilters[WEBP_FILTER_VERTICAL as usize].is_some());
+        c_assert!(WebPFilters[WEBP_FILTER_GRADIENT as usize].is_some());
+    }
+}
+
+//------------------------------------------------------------------------------
+// Slice-based entry point
+
+// Un-filters one row of 'out.len()' pixels with 'filter', using 'prev_line'
+// (the previous output row, if any) as predictor. The filtered row is read
+// from 'deltas', or from 'out' itself (in place) if 'deltas' is None.
+// VP8FiltersInit() must have been called.
+pub(crate) fn unfilter_row(
+    filter: WEBP_FILTER_TYPE,
+    prev_line: Option<&[u8]>,
+    deltas: Option<&[u8]>,
+    out: &mut [u8],
+) {
+    let width = out.len();
+    // The table only ever holds the C or SIMD unfilters, which all read and
+    // write 'width' pixels per row.
+    match unsafe { WebPUnfilters[filter as usize] } {
+        Some(unfilter) => {
+            let prev_line = match prev_line {
+                Some(prev_line) => prev_line[..width].as_ptr(),
+                None => ptr::null(),
+            };
+            let cur_line = out.as_mut_ptr();
+            let preds = match deltas {
+                Some(deltas) => deltas[..width].as_ptr(),
+                None => cur_line as *const u8,
+            };
+            // The slicing above checked that every row holds 'width' pixels,
+            // and the unfilters allow 'preds' to alias 'cur_line'.
+            unsafe { unfilter(prev_line, preds, cur_line, width as c_int) };
+        }
+        None => {
+            if let Some(deltas) = deltas {
+                out.copy_from_slice(&deltas[..width]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dsp::dsp::WEBP_FILTER_TYPE;
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(non_upper_case_globals)]
+    extern "C" {
+        static WebPRef_WebPFilters: [WebPFilterFunc; WEBP_FILTER_LAST as usize];
+        static WebPRef_WebPUnfilters: [WebPUnfilterFunc; WEBP_FILTER_LAST as usize];
+        fn WebPRef_VP8FiltersInit();
+    }
+
+    const FILTERS: [WEBP_FILTER_TYPE; 3] = [
+        WEBP_FILTER_HORIZONTAL,
+        WEBP_FILTER_VERTICAL,
+        WEBP_FILTER_GRADIENT,
+    ];
+
+    #[allow(non_snake_case)]
+    fn filters_C(filter: WEBP_FILTER_TYPE) -> (WebPFilterFunc, WebPUnfilterFunc) {
+        match filter {
+            WEBP_FILTER_HORIZONTAL => (Some(HorizontalFilter_C), Some(HorizontalUnfilter_C)),
+            WEBP_FILTER_VERTICAL => (Some(VerticalFilter_C), Some(VerticalUnfilter_C)),
+            WEBP_FILTER_GRADIENT => (Some(GradientFilter_C), Some(GradientUnfilter_C)),
+            _ => unreachable!(),
+        }
+    }
+
+    // A smooth-ish alpha plane with some noise and hard edges.
+    fn alpha_plane(rng: &mut Lcg, width: usize, height: usize) -> Vec<u8> {
+        let mut plane = vec![0; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let noise = (rng.next() % 8) as usize;
+                plane[y * width + x] = match rng.next() % 16 {
+                    0 => 0,
+                    1 => 255,
+                    _ => ((x * 7 + y * 3 + noise) & 0xff) as u8,
+                };
+            }
+        }
+        plane
+    }
+
+    // Unfilters the rows one by one, as the alpha decoder does.
+    fn unfilter(
+        unfilter: WebPUnfilterFunc,
+        filtered: &[u8],
+        width: usize,
+        in_place: bool,
+    ) -> Vec<u8> {
+        let mut out = if in_place {
+            filtered.to_vec()
+        } else {
+            vec![0; filtered.len()]
+        };
+        let mut prev_line: *const u8 = ptr::null();
+        for (y, row) in filtered.chunks(width).enumerate() {
+            unsafe {
+                let cur_line = out.as_mut_ptr().add(y * width);
+                let preds = if in_place { cur_line } else { row.as_ptr() };
+                unfilter.unwrap()(prev_line, preds, cur_line, width as c_int);
+                prev_line = cur_line;
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_filter_unfilter_roundtrip() {
+        unsafe {
+            VP8FiltersInit();
+        }
+        let mut rng = Lcg(1);
+        for &(width, height) in &[(1, 1), (1, 7), (7, 1), (2, 2), (17, 13), (64, 33)] {
+            let plane = alpha_plane(&mut rng, width, height);
+            for &filter in &FILTERS {
+                let dispatched =
+                    unsafe { (WebPFilters[filter as usize], WebPUnfilters[filter as usize]) };
+                for &(filter_func, unfilter_func) in &[filters_C(filter), dispatched] {
+                    let mut filtered = vec![0; plane.len()];
+                    unsafe {
+                        filter_func.unwrap()(
+                            plane.as_ptr(),
+                            width as c_int,
+                            height as c_int,
+                            width as c_int,
+                            filtered.as_mut_ptr(),
+                        );
+                    }
+                    for &in_place in &[false, true] {
+                        let out = unfilter(unfilter_func, &filtered, width, in_place);
+                        assert!(out == plane, "{:?}, {}x{}", filter, width, height);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_filters_parity() {
+        #[cfg(feature = "c-reference")]
+        unsafe {
+            WebPRef_VP8FiltersInit();
+        }
+        let mut digest = Digest::new();
+        let mut rng = Lcg(2);
+        for _ in 0..50 {
+            let width = 1 + rng.next() as usize % 50;
+            let height = 1 + rng.next() as usize % 50;
+            let plane = rng.bytes(width * height);
+            for &filter in &FILTERS {
+                let (filter_func, unfilter_func) = filters_C(filter);
+                let mut filtered = vec![0; plane.len()];
+                unsafe {
+                    filter_func.unwrap()(
+                        plane.as_ptr(),
+                        width as c_int,
+                        height as c_int,
+                        width as c_int,
+                        filtered.as_mut_ptr(),
+                    );
+                }
+                // Unfiltering random data exercises the clipping of the
+                // gradient predictor.
+                let unfiltered = unfilter(unfilter_func, &plane, width, true);
+                digest.update(&filtered);
+                digest.update(&unfiltered);
+                #[cfg(feature = "c-reference")]
+                {
+                    let ref_filter_func = unsafe { WebPRef_WebPFilters[filter as usize] };
+                    let ref_unfilter_func = unsafe { WebPRef_WebPUnfilters[filter as usize] };
+                    let mut ref_filtered = vec![0; plane.len()];
+                    unsafe {
+                        ref_filter_func.unwrap()(
+                            plane.as_ptr(),
+                            width as c_int,
+                            height as c_int,
+                            width as c_int,
+                            ref_filtered.as_mut_ptr(),
+                        );
+                    }
+                    assert!(filtered == ref_filtered, "{:?}", filter);
+                    assert!(
+                        unfiltered == unfilter(ref_unfilter_func, &plane, width, true),
+                        "{:?}",
+                        filter
+                    );
+                }
+            }
+        }
+        check_parity("dsp::filters::test_filters_parity", digest);
+    }
 }
@@ -0,0 +1,1305 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Image transforms and color space conversion methods for lossless decoder.
+//
+// Authors: Vikas Arora (vikaas.arora@gmail.com)
+//          Jyrki Alakuijala (jyrki@google.com)
+//          Urvang Joshi (urvang@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+use std::ptr;
+
+use dec::vp8li_dec::VP8LTransform;
+use decode::{
+    MODE_Argb, MODE_bgrA, MODE_rgbA, MODE_rgbA_4444, MODE_ARGB, MODE_BGR, MODE_BGRA, MODE_RGB,
+    MODE_RGBA, MODE_RGBA_4444, MODE_RGB_565, WEBP_CSP_MODE,
+};
+use dsp::alpha_processing::{WebPApplyAlphaMultiply, WebPApplyAlphaMultiply4444};
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    not(any(target_arch = "aarch64", feature = "pure-rust-decoder"))
+))]
+use dsp::cpu::VP8GetCPUInfo;
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    not(any(target_arch = "aarch64", feature = "pure-rust-decoder"))
+))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::WEBP_SWAP_16BIT_CSP;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use dsp::lossless_avx2::VP8LDspInitAVX2;
+use dsp::lossless_common::{
+    VP8GetARGBIndex, VP8GetARGBValue, VP8GetAlphaIndex, VP8GetAlphaValue, VP8LAddPixels,
+    VP8LSubSampleSize,
+};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use dsp::lossless_sse2::VP8LDspInitSSE2;
+use format_constants::VP8LImageTransformType::*;
+use format_constants::ARGB_BLACK;
+
+//------------------------------------------------------------------------------
+// Decoding
+
+// color mapping related functions.
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LPredictorFunc = Option<unsafe extern "C" fn(left: u32, top: *const u32) -> u32>;
+// These Add/Sub function expects upper[-1] and out[-1] to be readable.
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LPredictorAddSubFunc = Option<
+    unsafe extern "C" fn(in_: *const u32, upper: *const u32, num_pixels: c_int, out: *mut u32),
+>;
+
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LProcessDecBlueAndRedFunc =
+    Option<unsafe extern "C" fn(src: *const u32, num_pixels: c_int, dst: *mut u32)>;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VP8LMultipliers {
+    // Note: the members are uint8_t, so that any negative values are
+    // automatically converted to "mod 256" values.
+    pub(crate) green_to_red_: u8,
+    pub(crate) green_to_blue_: u8,
+    pub(crate) red_to_blue_: u8,
+}
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LTransformColorInverseFunc = Option<
+    unsafe extern "C" fn(
+        m: *const VP8LMultipliers,
+        src: *const u32,
+        num_pixels: c_int,
+        dst: *mut u32,
+    ),
+>;
+
+// Color space conversion.
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LConvertFunc =
+    Option<unsafe extern "C" fn(src: *const u32, num_pixels: c_int, dst: *mut u8)>;
+
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LMapARGBFunc = Option<
+    unsafe extern "C" fn(
+        src: *const u32,
+        color_map: *const u32,
+        dst: *mut u32,
+        y_start: c_int,
+        y_end: c_int,
+        width: c_int,
+    ),
+>;
+#[allow(non_camel_case_types)]
+pub(crate) type VP8LMapAlphaFunc = Option<
+    unsafe extern "C" fn(
+        src: *const u8,
+        color_map: *const u32,
+        dst: *mut u8,
+        y_start: c_int,
+        y_end: c_int,
+        width: c_int,
+    ),
+>;
+
+//------------------------------------------------------------------------------
+// Image transforms.
+
+#[allow(non_snake_case)]
+#[inline]
+fn Average2(a0: u32, a1: u32) -> u32 {
+    (((a0 ^ a1) & 0xfefefefe) >> 1) + (a0 & a1)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn Average3(a0: u32, a1: u32, a2: u32) -> u32 {
+    Average2(Average2(a0, a2), a1)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn Average4(a0: u32, a1: u32, a2: u32, a3: u32) -> u32 {
+    Average2(Average2(a0, a1), Average2(a2, a3))
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn Clip255(a: u32) -> u32 {
+    if a < 256 {
+        return a;
+    }
+    // return 0, when a is a negative integer.
+    // return 255, when a is positive.
+    !a >> 24
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn AddSubtractComponentFull(a: c_int, b: c_int, c: c_int) -> c_int {
+    Clip255((a + b - c) as u32) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ClampedAddSubtractFull(c0: u32, c1: u32, c2: u32) -> u32 {
+    let a = AddSubtractComponentFull(
+        (c0 >> 24) as c_int,
+        (c1 >> 24) as c_int,
+        (c2 >> 24) as c_int,
+    );
+    let r = AddSubtractComponentFull(
+        ((c0 >> 16) & 0xff) as c_int,
+        ((c1 >> 16) & 0xff) as c_int,
+        ((c2 >> 16) & 0xff) as c_int,
+    );
+    let g = AddSubtractComponentFull(
+        ((c0 >> 8) & 0xff) as c_int,
+        ((c1 >> 8) & 0xff) as c_int,
+        ((c2 >> 8) & 0xff) as c_int,
+    );
+    let b = AddSubtractComponentFull(
+        (c0 & 0xff) as c_int,
+        (c1 & 0xff) as c_int,
+        (c2 & 0xff) as c_int,
+    );
+    ((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn AddSubtractComponentHalf(a: c_int, b: c_int) -> c_int {
+    Clip255((a + (a - b) / 2) as u32) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ClampedAddSubtractHalf(c0: u32, c1: u32, c2: u32) -> u32 {
+    let ave = Average2(c0, c1);
+    let a = AddSubtractComponentHalf((ave >> 24) as c_int, (c2 >> 24) as c_int);
+    let r = AddSubtractComponentHalf(((ave >> 16) & 0xff) as c_int, ((c2 >> 16) & 0xff) as c_int);
+    let g = AddSubtractComponentHalf(((ave >> 8) & 0xff) as c_int, ((c2 >> 8) & 0xff) as c_int);
+    let b = AddSubtractComponentHalf((ave & 0xff) as c_int, (c2 & 0xff) as c_int);
+    ((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn Sub3(a: c_int, b: c_int, c: c_int) -> c_int {
+    let pb = b - c;
+    let pa = a - c;
+    pb.abs() - pa.abs()
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn Select(a: u32, b: u32, c: u32) -> u32 {
+    let pa_minus_pb = Sub3((a >> 24) as c_int, (b >> 24) as c_int, (c >> 24) as c_int)
+        + Sub3(
+            ((a >> 16) & 0xff) as c_int,
+            ((b >> 16) & 0xff) as c_int,
+            ((c >> 16) & 0xff) as c_int,
+        )
+        + Sub3(
+            ((a >> 8) & 0xff) as c_int,
+            ((b >> 8) & 0xff) as c_int,
+            ((c >> 8) & 0xff) as c_int,
+        )
+        + Sub3(
+            (a & 0xff) as c_int,
+            (b & 0xff) as c_int,
+            (c & 0xff) as c_int,
+        );
+    if pa_minus_pb <= 0 {
+        a
+    } else {
+        b
+    }
+}
+
+//------------------------------------------------------------------------------
+// Predictors
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor0_C(_left: u32, _top: *const u32) -> u32 {
+    ARGB_BLACK
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor1_C(left: u32, _top: *const u32) -> u32 {
+    left
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor2_C(_left: u32, top: *const u32) -> u32 {
+    *top
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor3_C(_left: u32, top: *const u32) -> u32 {
+    *top.offset(1)
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor4_C(_left: u32, top: *const u32) -> u32 {
+    *top.offset(-1)
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor5_C(left: u32, top: *const u32) -> u32 {
+    Average3(left, *top, *top.offset(1))
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor6_C(left: u32, top: *const u32) -> u32 {
+    Average2(left, *top.offset(-1))
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor7_C(left: u32, top: *const u32) -> u32 {
+    Average2(left, *top)
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor8_C(_left: u32, top: *const u32) -> u32 {
+    Average2(*top.offset(-1), *top)
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor9_C(_left: u32, top: *const u32) -> u32 {
+    Average2(*top, *top.offset(1))
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor10_C(left: u32, top: *const u32) -> u32 {
+    Average4(left, *top.offset(-1), *top, *top.offset(1))
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor11_C(left: u32, top: *const u32) -> u32 {
+    Select(*top, left, *top.offset(-1))
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor12_C(left: u32, top: *const u32) -> u32 {
+    ClampedAddSubtractFull(left, *top, *top.offset(-1))
+}
+#[allow(non_snake_case)]
+unsafe extern "C" fn Predictor13_C(left: u32, top: *const u32) -> u32 {
+    ClampedAddSubtractHalf(left, *top, *top.offset(-1))
+}
+
+generate_predictor_add!(Predictor0_C, PredictorAdd0_C);
+#[allow(non_snake_case)]
+unsafe extern "C" fn PredictorAdd1_C(
+    in_: *const u32,
+    _upper: *const u32,
+    num_pixels: c_int,
+    out: *mut u32,
+) {
+    let mut left = *out.offset(-1);
+    for i in 0..num_pixels as isize {
+        left = VP8LAddPixels(*in_.offset(i), left);
+        *out.offset(i) = left;
+    }
+}
+generate_predictor_add!(Predictor2_C, PredictorAdd2_C);
+generate_predictor_add!(Predictor3_C, PredictorAdd3_C);
+generate_predictor_add!(Predictor4_C, PredictorAdd4_C);
+generate_predictor_add!(Predictor5_C, PredictorAdd5_C);
+generate_predictor_add!(Predictor6_C, PredictorAdd6_C);
+generate_predictor_add!(Predictor7_C, PredictorAdd7_C);
+generate_predictor_add!(Predictor8_C, PredictorAdd8_C);
+generate_predictor_add!(Predictor9_C, PredictorAdd9_C);
+generate_predictor_add!(Predictor10_C, PredictorAdd10_C);
+generate_predictor_add!(Predictor11_C, PredictorAdd11_C);
+generate_predictor_add!(Predictor12_C, PredictorAdd12_C);
+generate_predictor_add!(Predictor13_C, PredictorAdd13_C);
+
+//------------------------------------------------------------------------------
+
+// Inverse prediction.
+#[allow(non_snake_case)]
+unsafe fn PredictorInverseTransform_C(
+    transform: *const VP8LTransform,
+    mut y_start: c_int,
+    y_end: c_int,
+    mut in_: *const u32,
+    mut out: *mut u32,
+) {
+    let transform = &*transform;
+    let width = transform.xsize_;
+    if y_start == 0 {
+        // First Row follows the L (mode=1) mode.
+        PredictorAdd0_C(in_, ptr::null(), 1, out);
+        PredictorAdd1_C(in_.offset(1), ptr::null(), width - 1, out.offset(1));
+        in_ = in_.offset(width as isize);
+        out = out.offset(width as isize);
+        y_start += 1;
+    }
+
+    {
+        let mut y = y_start;
+        let tile_width = 1 << transform.bits_;
+        let mask = tile_width - 1;
+        let tiles_per_row = VP8LSubSampleSize(width as u32, transform.bits_ as u32) as c_int;
+        let mut pred_mode_base = transform
+            .data_
+            .offset(((y >> transform.bits_) * tiles_per_row) as isize);
+
+        while y < y_end {
+            let mut pred_mode_src = pred_mode_base;
+            let mut x = 1;
+            // First pixel follows the T (mode=2) mode.
+            PredictorAdd2_C(in_, out.offset(-width as isize), 1, out);
+            // .. the rest:
+            while x < width {
+                let pred_func = VP8LPredictorsAdd[((*pred_mode_src >> 8) & 0xf) as usize].unwrap();
+                pred_mode_src = pred_mode_src.offset(1);
+                let mut x_end = (x & !mask) + tile_width;
+                if x_end > width {
+                    x_end = width;
+                }
+                pred_func(
+                    in_.offset(x as isize),
+                    out.offset((x - width) as isize),
+                    x_end - x,
+                    out.offset(x as isize),
+                );
+                x = x_end;
+            }
+            in_ = in_.offset(width as isize);
+            out = out.offset(width as isize);
+            y += 1;
+            if (y & mask) == 0 {
+                // Use the same mask, since tiles are squares.
+                pred_mode_base = pred_mode_base.offset(tiles_per_row as isize);
+            }
+        }
+    }
+}
+
+// Add green to blue and red channels (i.e. perform the inverse transform of
+// 'subtract green').
+#[no_mangle]
+pub unsafe extern "C" fn VP8LAddGreenToBlueAndRed_C(
+    src: *const u32,
+    num_pixels: c_int,
+    dst: *mut u32,
+) {
+    for i in 0..num_pixels as isize {
+        let argb = *src.offset(i);
+        let green = (argb >> 8) & 0xff;
+        let mut red_blue = argb & 0x00ff00ff;
+        red_blue = red_blue.wrapping_add((green << 16) | green);
+        red_blue &= 0x00ff00ff;
+        *dst.offset(i) = (argb & 0xff00ff00) | red_blue;
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ColorTransformDelta(color_pred: i8, color: i8) -> c_int {
+    (color_pred as c_int * color as c_int) >> 5
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ColorCodeToMultipliers(color_code: u32, m: &mut VP8LMultipliers) {
+    m.green_to_red_ = (color_code & 0xff) as u8;
+    m.green_to_blue_ = ((color_code >> 8) & 0xff) as u8;
+    m.red_to_blue_ = ((color_code >> 16) & 0xff) as u8;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LTransformColorInverse_C(
+    m: *const VP8LMultipliers,
+    src: *const u32,
+    num_pixels: c_int,
+    dst: *mut u32,
+) {
+    let m = &*m;
+    for i in 0..num_pixels as isize {
+        let argb = *src.offset(i);
+        let green = (argb >> 8) as i8;
+        let red = argb >> 16;
+        let mut new_red = (red & 0xff) as c_int;
+        let mut new_blue = (argb & 0xff) as c_int;
+        new_red += ColorTransformDelta(m.green_to_red_ as i8, green);
+        new_red &= 0xff;
+        new_blue += ColorTransformDelta(m.green_to_blue_ as i8, green);
+        new_blue += ColorTransformDelta(m.red_to_blue_ as i8, new_red as i8);
+        new_blue &= 0xff;
+        *dst.offset(i) = (argb & 0xff00ff00) | ((new_red as u32) << 16) | new_blue as u32;
+    }
+}
+
+// Color space inverse transform.
+#[allow(non_snake_case)]
+unsafe fn ColorSpaceInverseTransform_C(
+    transform: *const VP8LTransform,
+    y_start: c_int,
+    y_end: c_int,
+    mut src: *const u32,
+    mut dst: *mut u32,
+) {
+    let transform = &*transform;
+    let width = transform.xsize_;
+    let tile_width = 1 << transform.bits_;
+    let mask = tile_width - 1;
+    let safe_width = width & !mask;
+    let remaining_width = width - safe_width;
+    let tiles_per_row = VP8LSubSampleSize(width as u32, transform.bits_ as u32) as c_int;
+    let mut y = y_start;
+    let mut pred_row = transform
+        .data_
+        .offset(((y >> transform.bits_) * tiles_per_row) as isize);
+
+    while y < y_end {
+        let mut pred = pred_row;
+        let mut m = VP8LMultipliers::default();
+        let src_safe_end = src.offset(safe_width as isize);
+        let src_end = src.offset(width as isize);
+        while src < src_safe_end {
+            ColorCodeToMultipliers(*pred, &mut m);
+            pred = pred.offset(1);
+            VP8LTransformColorInverse.unwrap()(&m, src, tile_width, dst);
+            src = src.offset(tile_width as isize);
+            dst = dst.offset(tile_width as isize);
+        }
+        if src < src_end {
+            // Left-overs using C-version.
+            ColorCodeToMultipliers(*pred, &mut m);
+            VP8LTransformColorInverse.unwrap()(&m, src, remaining_width, dst);
+            src = src.offset(remaining_width as isize);
+            dst = dst.offset(remaining_width as isize);
+        }
+        y += 1;
+        if (y & mask) == 0 {
+            pred_row = pred_row.offset(tiles_per_row as isize);
+        }
+    }
+}
+
+// Separate out pixels packed together using pixel-bundling.
+// We define two methods for ARGB data (uint32_t) and alpha-only data (uint8_t).
+macro_rules! color_index_inverse {
+    (
+        $(#[$attr:meta])*
+        $vis:vis fn $func_name:ident,
+        $f_name:ident,
+        $type:ty,
+        $map_color:ident,
+        $get_index:ident,
+        $get_value:ident
+    ) => {
+        #[allow(non_snake_case)]
+        unsafe extern "C" fn $f_name(
+            mut src: *const $type,
+            color_map: *const u32,
+            mut dst: *mut $type,
+            y_start: c_int,
+            y_end: c_int,
+            width: c_int,
+        ) {
+            for _ in y_start..y_end {
+                for _ in 0..width {
+                    *dst = $get_value(*color_map.offset($get_index(*src) as isize));
+                    dst = dst.offset(1);
+                    src = src.offset(1);
+                }
+            }
+        }
+
+        $(#[$attr])*
+        #[allow(non_snake_case)]
+        $vis unsafe extern "C" fn $func_name(
+            transform: *const VP8LTransform,
+            y_start: c_int,
+            y_end: c_int,
+            mut src: *const $type,
+            mut dst: *mut $type,
+        ) {
+            let transform = &*transform;
+            let bits_per_pixel = 8 >> transform.bits_;
+            let width = transform.xsize_;
+            let color_map = transform.data_;
+            if bits_per_pixel < 8 {
+                let pixels_per_byte = 1 << transform.bits_;
+                let count_mask = pixels_per_byte - 1;
+                let bit_mask: u32 = (1 << bits_per_pixel) - 1;
+                for _ in y_start..y_end {
+                    let mut packed_pixels: u32 = 0;
+                    for x in 0..width {
+                        // We need to load fresh 'packed_pixels' once every
+                        // 'pixels_per_byte' increments of x. Fortunately, pixels_per_byte
+                        // is a power of 2, so can just use a mask for that, instead of
+                        // decrementing a counter.
+                        if (x & count_mask) == 0 {
+                            packed_pixels = $get_index(*src) as u32;
+                            src = src.offset(1);
+                        }
+                        *dst = $get_value(*color_map.offset((packed_pixels & bit_mask) as isize));
+                        dst = dst.offset(1);
+                        packed_pixels >>= bits_per_pixel;
+                    }
+                }
+            } else {
+                $map_color.unwrap()(src, color_map, dst, y_start, y_end, width);
+            }
+        }
+    };
+}
+
+color_index_inverse!(
+    fn ColorIndexInverseTransform_C,
+    MapARGB_C,
+    u32,
+    VP8LMapColor32b,
+    VP8GetARGBIndex,
+    VP8GetARGBValue
+);
+color_index_inverse!(
+    // Similar to the static method ColorIndexInverseTransform() that is part of
+    // lossless.c, but used only for alpha decoding. It takes uint8_t (rather than
+    // uint32_t) arguments for 'src' and 'dst'.
+    #[no_mangle]
+    pub fn VP8LColorIndexInverseTransformAlpha,
+    MapAlpha_C,
+    u8,
+    VP8LMapColor8b,
+    VP8GetAlphaIndex,
+    VP8GetAlphaValue
+);
+
+// Performs inverse transform of data given transform information, start and end
+// rows. Transform will be applied to rows [row_start, row_end[.
+// The *in and *out pointers refer to source and destination data respectively
+// corresponding to the intermediate row (row_start).
+#[no_mangle]
+pub unsafe extern "C" fn VP8LInverseTransform(
+    transform: *const VP8LTransform,
+    row_start: c_int,
+    row_end: c_int,
+    in_: *const u32,
+    out: *mut u32,
+) {
+    let width = (*transform).xsize_;
+    c_assert!(row_start < row_end);
+    c_assert!(row_end <= (*transform).ysize_);
+    match (*transform).type_ {
+        SUBTRACT_GREEN => {
+            VP8LAddGreenToBlueAndRed.unwrap()(in_, (row_end - row_start) * width, out);
+        }
+        PREDICTOR_TRANSFORM => {
+            PredictorInverseTransform_C(transform, row_start, row_end, in_, out);
+            if row_end != (*transform).ysize_ {
+                // The last predicted row in this iteration will be the top-pred row
+                // for the first row in next iteration.
+                ptr::copy_nonoverlapping(
+                    out.offset(((row_end - row_start - 1) * width) as isize),
+                    out.offset(-width as isize),
+                    width as usize,
+                );
+            }
+        }
+        CROSS_COLOR_TRANSFORM => {
+            ColorSpaceInverseTransform_C(transform, row_start, row_end, in_, out);
+        }
+        COLOR_INDEXING_TRANSFORM => {
+            if ptr::eq(in_, out) && (*transform).bits_ > 0 {
+                // Move packed pixels to the end of unpacked region, so that unpacking
+                // can occur seamlessly.
+                // Also, note that this is the only transform that applies on
+                // the effective width of VP8LSubSampleSize(xsize_, bits_). All other
+                // transforms work on effective width of xsize_.
+                let out_stride = (row_end - row_start) * width;
+                let in_stride = (row_end - row_start)
+                    * VP8LSubSampleSize((*transform).xsize_ as u32, (*transform).bits_ as u32)
+                        as c_int;
+                let src = out.offset((out_stride - in_stride) as isize);
+                ptr::copy(out, src, in_stride as usize);
+                ColorIndexInverseTransform_C(transform, row_start, row_end, src, out);
+            } else {
+                ColorIndexInverseTransform_C(transform, row_start, row_end, in_, out);
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Color space conversion.
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LConvertBGRAToRGB_C(
+    mut src: *const u32,
+    num_pixels: c_int,
+    mut dst: *mut u8,
+) {
+    let src_end = src.offset(num_pixels as isize);
+    while src < src_end {
+        let argb = *src;
+        src = src.offset(1);
+        *dst = (argb >> 16) as u8;
+        *dst.offset(1) = (argb >> 8) as u8;
+        *dst.offset(2) = argb as u8;
+        dst = dst.offset(3);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LConvertBGRAToRGBA_C(
+    mut src: *const u32,
+    num_pixels: c_int,
+    mut dst: *mut u8,
+) {
+    let src_end = src.offset(num_pixels as isize);
+    while src < src_end {
+        let argb = *src;
+        src = src.offset(1);
+        *dst = (argb >> 16) as u8;
+        *dst.offset(1) = (argb >> 8) as u8;
+        *dst.offset(2) = argb as u8;
+        *dst.offset(3) = (argb >> 24) as u8;
+        dst = dst.offset(4);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LConvertBGRAToRGBA4444_C(
+    mut src: *const u32,
+    num_pixels: c_int,
+    mut dst: *mut u8,
+) {
+    let src_end = src.offset(num_pixels as isize);
+    while src < src_end {
+        let argb = *src;
+        src = src.offset(1);
+        let rg = (((argb >> 16) & 0xf0) | ((argb >> 12) & 0xf)) as u8;
+        let ba = ((argb & 0xf0) | ((argb >> 28) & 0xf)) as u8;
+        if WEBP_SWAP_16BIT_CSP == 1 {
+            *dst = ba;
+            *dst.offset(1) = rg;
+        } else {
+            *dst = rg;
+            *dst.offset(1) = ba;
+        }
+        dst = dst.offset(2);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LConvertBGRAToRGB565_C(
+    mut src: *const u32,
+    num_pixels: c_int,
+    mut dst: *mut u8,
+) {
+    let src_end = src.offset(num_pixels as isize);
+    while src < src_end {
+        let argb = *src;
+        src = src.offset(1);
+        let rg = (((argb >> 16) & 0xf8) | ((argb >> 13) & 0x7)) as u8;
+        let gb = (((argb >> 5) & 0xe0) | ((argb >> 3) & 0x1f)) as u8;
+        if WEBP_SWAP_16BIT_CSP == 1 {
+            *dst = gb;
+            *dst.offset(1) = rg;
+        } else {
+            *dst = rg;
+            *dst.offset(1) = gb;
+        }
+        dst = dst.offset(2);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LConvertBGRAToBGR_C(
+    mut src: *const u32,
+    num_pixels: c_int,
+    mut dst: *mut u8,
+) {
+    let src_end = src.offset(num_pixels as isize);
+    while src < src_end {
+        let argb = *src;
+        src = src.offset(1);
+        *dst = argb as u8;
+        *dst.offset(1) = (argb >> 8) as u8;
+        *dst.offset(2) = (argb >> 16) as u8;
+        dst = dst.offset(3);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn CopyOrSwap(
+    mut src: *const u32,
+    num_pixels: c_int,
+    mut dst: *mut u8,
+    swap_on_big_endian: bool,
+) {
+    if cfg!(target_endian = "big") == swap_on_big_endian {
+        let src_end = src.offset(num_pixels as isize);
+        while src < src_end {
+            let argb = *src;
+            src = src.offset(1);
+            ptr::write_unaligned(dst as *mut u32, argb.swap_bytes());
+            dst = dst.offset(4);
+        }
+    } else {
+        ptr::copy_nonoverlapping(src as *const u8, dst, num_pixels as usize * 4);
+    }
+}
+
+// Converts from BGRA to other color spaces.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8LConvertFromBGRA(
+    in_data: *const u32,
+    num_pixels: c_int,
+    out_colorspace: WEBP_CSP_MODE,
+    rgba: *mut u8,
+) {
+    match out_colorspace {
+        MODE_RGB => {
+            VP8LConvertBGRAToRGB.unwrap()(in_data, num_pixels, rgba);
+        }
+        MODE_RGBA => {
+            VP8LConvertBGRAToRGBA.unwrap()(in_data, num_pixels, rgba);
+        }
+        MODE_rgbA => {
+            VP8LConvertBGRAToRGBA.unwrap()(in_data, num_pixels, rgba);
+            WebPApplyAlphaMultiply.unwrap()(rgba, 0, num_pixels, 1, 0);
+        }
+        MODE_BGR => {
+            VP8LConvertBGRAToBGR.unwrap()(in_data, num_pixels, rgba);
+        }
+        MODE_BGRA => {
+            CopyOrSwap(in_data, num_pixels, rgba, true);
+        }
+        MODE_bgrA => {
+            CopyOrSwap(in_data, num_pixels, rgba, true);
+            WebPApplyAlphaMultiply.unwrap()(rgba, 0, num_pixels, 1, 0);
+        }
+        MODE_ARGB => {
+            CopyOrSwap(in_data, num_pixels, rgba, false);
+        }
+        MODE_Argb => {
+            CopyOrSwap(in_data, num_pixels, rgba, false);
+            WebPApplyAlphaMultiply.unwrap()(rgba, 1, num_pixels, 1, 0);
+        }
+        MODE_RGBA_4444 => {
+            VP8LConvertBGRAToRGBA4444.unwrap()(in_data, num_pixels, rgba);
+        }
+        MODE_rgbA_4444 => {
+            VP8LConvertBGRAToRGBA4444.unwrap()(in_data, num_pixels, rgba);
+            WebPApplyAlphaMultiply4444.unwrap()(rgba, num_pixels, 1, 0);
+        }
+        MODE_RGB_565 => {
+            VP8LConvertBGRAToRGB565.unwrap()(in_data, num_pixels, rgba);
+        }
+        _ => {
+            c_assert!(false); // Code flow should not reach here.
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub static mut VP8LAddGreenToBlueAndRed: VP8LProcessDecBlueAndRedFunc = None;
+#[no_mangle]
+pub static mut VP8LPredictorsAdd: [VP8LPredictorAddSubFunc; 16] = [None; 16];
+#[no_mangle]
+pub static mut VP8LPredictors: [VP8LPredictorFunc; 16] = [None; 16];
+
+// exposed plain-C implementations
+#[no_mangle]
+pub static mut VP8LPredictorsAdd_C: [VP8LPredictorAddSubFunc; 16] = [None; 16];
+#[no_mangle]
+pub static mut VP8LPredictors_C: [VP8LPredictorFunc; 16] = [None; 16];
+
+#[no_mangle]
+pub static mut VP8LTransformColorInverse: VP8LTransformColorInverseFunc = None;
+
+#[no_mangle]
+pub static mut VP8LConvertBGRAToRGB: VP8LConvertFunc = None;
+#[no_mangle]
+pub static mut VP8LConvertBGRAToRGBA: VP8LConvertFunc = None;
+#[no_mangle]
+pub static mut VP8LConvertBGRAToRGBA4444: VP8LConvertFunc = None;
+#[no_mangle]
+pub static mut VP8LConvertBGRAToRGB565: VP8LConvertFunc = None;
+#[no_mangle]
+pub static mut VP8LConvertBGRAToBGR: VP8LConvertFunc = None;
+
+#[no_mangle]
+pub static mut VP8LMapColor32b: VP8LMapARGBFunc = None;
+#[no_mangle]
+pub static mut VP8LMapColor8b: VP8LMapAlphaFunc = None;
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn VP8LDspInitNEON();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8LDspInitMIPSdspR2();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn VP8LDspInitMSA();
+}
+
+#[allow(non_upper_case_globals)]
+#[rustfmt::skip]
+const kPredictors_C: [VP8LPredictorFunc; 16] = [
+    Some(Predictor0_C), Some(Predictor1_C), Some(Predictor2_C), Some(Predictor3_C),
+    Some(Predictor4_C), Some(Predictor5_C), Some(Predictor6_C), Some(Predictor7_C),
+    Some(Predictor8_C), Some(Predictor9_C), Some(Predictor10_C), Some(Predictor11_C),
+    Some(Predictor12_C), Some(Predictor13_C),
+    Some(Predictor0_C), Some(Predictor0_C), // <- padding security sentinels
+];
+
+#[allow(non_upper_case_globals)]
+#[rustfmt::skip]
+const kPredictorsAdd_C: [VP8LPredictorAddSubFunc; 16] = [
+    Some(PredictorAdd0_C), Some(PredictorAdd1_C), Some(PredictorAdd2_C), Some(PredictorAdd3_C),
+    Some(PredictorAdd4_C), Some(PredictorAdd5_C), Some(PredictorAdd6_C), Some(PredictorAdd7_C),
+    Some(PredictorAdd8_C), Some(PredictorAdd9_C), Some(PredictorAdd10_C), Some(PredictorAdd11_C),
+    Some(PredictorAdd12_C), Some(PredictorAdd13_C),
+    Some(PredictorAdd0_C), Some(PredictorAdd0_C), // <- padding security sentinels
+];
+
+webp_dsp_init_func! {
+    // Must be called before calling any of the above methods.
+    #[no_mangle]
+    pub fn VP8LDspInit() {
+        VP8LPredictors = kPredictors_C;
+        VP8LPredictors_C = kPredictors_C;
+        VP8LPredictorsAdd = kPredictorsAdd_C;
+        VP8LPredictorsAdd_C = kPredictorsAdd_C;
+
+        VP8LAddGreenToBlueAndRed = Some(VP8LAddGreenToBlueAndRed_C);
+
+        VP8LTransformColorInverse = Some(VP8LTransformColorInverse_C);
+
+        VP8LConvertBGRAToRGBA = Some(VP8LConvertBGRAToRGBA_C);
+        VP8LConvertBGRAToRGB = Some(VP8LConvertBGRAToRGB_C);
+        VP8LConvertBGRAToBGR = Some(VP8LConvertBGRAToBGR_C);
+
+        VP8LConvertBGRAToRGBA4444 = Some(VP8LConvertBGRAToRGBA4444_C);
+        VP8LConvertBGRAToRGB565 = Some(VP8LConvertBGRAToRGB565_C);
+
+        VP8LMapColor32b = Some(MapARGB_C);
+        VP8LMapColor8b = Some(MapAlpha_C);
+
+        // If defined, use CPUInfo() to overwrite some pointers with faster versions.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    VP8LDspInitSSE2();
+                    if get_cpu_info(kAVX2) != 0 {
+                        VP8LDspInitAVX2();
+                    }
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    VP8LDspInitMIPSdspR2();
+                }
+                if get_cpu_info(kMSA) != 0 {
+                    VP8LDspInitMSA();
+                }
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "pure-rust-decoder")))]
+        {
+            VP8LDspInitNEON();
+        }
+        #[cfg(all(target_arch = "arm", not(feature = "pure-rust-decoder")))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    VP8LDspInitNEON();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dsp::alpha_processing::WebPInitAlphaProcessing;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(non_upper_case_globals)]
+    extern "C" {
+        static WebPRef_VP8LPredictors_C: [VP8LPredictorFunc; 16];
+        static WebPRef_VP8LPredictorsAdd_C: [VP8LPredictorAddSubFunc; 16];
+        fn WebPRef_VP8LAddGreenToBlueAndRed_C(src: *const u32, num_pixels: c_int, dst: *mut u32);
+        fn WebPRef_VP8LTransformColorInverse_C(
+            m: *const VP8LMultipliers,
+            src: *const u32,
+            num_pixels: c_int,
+            dst: *mut u32,
+        );
+        fn WebPRef_VP8LInverseTransform(
+            transform: *const VP8LTransform,
+            row_start: c_int,
+            row_end: c_int,
+            in_: *const u32,
+            out: *mut u32,
+        );
+        fn WebPRef_VP8LColorIndexInverseTransformAlpha(
+            transform: *const VP8LTransform,
+            y_start: c_int,
+            y_end: c_int,
+            src: *const u8,
+            dst: *mut u8,
+        );
+        fn WebPRef_VP8LConvertFromBGRA(
+            in_data: *const u32,
+            num_pixels: c_int,
+            out_colorspace: WEBP_CSP_MODE,
+            rgba: *mut u8,
+        );
+        fn WebPRef_VP8LDspInit();
+    }
+
+    fn init() {
+        unsafe {
+            // VP8LConvertFromBGRA() premultiplies through WebPApplyAlphaMultiply.
+            WebPInitAlphaProcessing();
+            VP8LDspInit();
+            #[cfg(feature = "c-reference")]
+            WebPRef_VP8LDspInit();
+        }
+    }
+
+    // Random pixels, biased towards extreme channel values to exercise the
+    // clamping predictors.
+    fn pixels(rng: &mut Lcg, len: usize) -> Vec<u32> {
+        (0..len)
+            .map(|_| {
+                let argb = (rng.next() << 8) ^ rng.next();
+                match rng.next() % 4 {
+                    0 => argb | 0xf0f0f0f0,
+                    1 => argb & 0x0f0f0f0f,
+                    _ => argb,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "c-reference"), allow(clippy::needless_range_loop))]
+    fn test_predictors_parity() {
+        init();
+        let mut digest = Digest::new();
+        let mut rng = Lcg(1);
+        for _ in 0..1000 {
+            let top = pixels(&mut rng, 3);
+            let left = pixels(&mut rng, 1)[0];
+            for mode in 0..16 {
+                unsafe {
+                    let pred = VP8LPredictors_C[mode].unwrap()(left, top.as_ptr().offset(1));
+                    digest.update_u32(&[pred]);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let ref_pred =
+                            WebPRef_VP8LPredictors_C[mode].unwrap()(left, top.as_ptr().offset(1));
+                        assert_eq!(pred, ref_pred, "mode = {}", mode);
+                    }
+                }
+            }
+        }
+        check_parity("dsp::lossless::test_predictors_parity", digest);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "c-reference"), allow(clippy::needless_range_loop))]
+    fn test_predictors_add_parity() {
+        init();
+        let mut digest = Digest::new();
+        let mut rng = Lcg(2);
+        for num_pixels in 1..40 {
+            let in_ = pixels(&mut rng, num_pixels);
+            let upper = pixels(&mut rng, num_pixels + 2);
+            let out_init = pixels(&mut rng, 1)[0];
+            for mode in 0..16 {
+                let mut out = vec![out_init; num_pixels + 1];
+                unsafe {
+                    VP8LPredictorsAdd_C[mode].unwrap()(
+                        in_.as_ptr(),
+                        upper.as_ptr().offset(1),
+                        num_pixels as c_int,
+                        out.as_mut_ptr().offset(1),
+                    );
+                }
+                digest.update_u32(&out);
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut ref_out = vec![out_init; num_pixels + 1];
+                    unsafe {
+                        WebPRef_VP8LPredictorsAdd_C[mode].unwrap()(
+                            in_.as_ptr(),
+                            upper.as_ptr().offset(1),
+                            num_pixels as c_int,
+                            ref_out.as_mut_ptr().offset(1),
+                        );
+                    }
+                    assert_eq!(out, ref_out, "mode = {}", mode);
+                }
+            }
+        }
+        check_parity("dsp::lossless::test_predictors_add_parity", digest);
+    }
+
+    #[test]
+    fn test_add_green_and_color_inverse_parity() {
+        init();
+        let mut digest = Digest::new();
+        let mut rng = Lcg(3);
+        let src = pixels(&mut rng, 1000);
+        let mut dst = vec![0; src.len()];
+        #[cfg(feature = "c-reference")]
+        let mut ref_dst = vec![0; src.len()];
+        unsafe {
+            VP8LAddGreenToBlueAndRed_C(src.as_ptr(), src.len() as c_int, dst.as_mut_ptr());
+        }
+        digest.update_u32(&dst);
+        #[cfg(feature = "c-reference")]
+        {
+            unsafe {
+                WebPRef_VP8LAddGreenToBlueAndRed_C(
+                    src.as_ptr(),
+                    src.len() as c_int,
+                    ref_dst.as_mut_ptr(),
+                );
+            }
+            assert_eq!(dst, ref_dst);
+        }
+        for _ in 0..100 {
+            let mut m = VP8LMultipliers::default();
+            ColorCodeToMultipliers(rng.next(), &mut m);
+            unsafe {
+                VP8LTransformColorInverse_C(&m, src.as_ptr(), src.len() as c_int, dst.as_mut_ptr());
+            }
+            digest.update_u32(&dst);
+            #[cfg(feature = "c-reference")]
+            {
+                unsafe {
+                    WebPRef_VP8LTransformColorInverse_C(
+                        &m,
+                        src.as_ptr(),
+                        src.len() as c_int,
+                        ref_dst.as_mut_ptr(),
+                    );
+                }
+                assert_eq!(dst, ref_dst);
+            }
+        }
+        check_parity(
+            "dsp::lossless::test_add_green_and_color_inverse_parity",
+            digest,
+        );
+    }
+
+    // Runs the inverse transform over the whole image in batches of rows, the
+    // way the decoder does: 'out' always points just below a cached top row.
+    fn inverse_transform(
+        transform: &VP8LTransform,
+        in_: &[u32],
+        in_place: bool,
+        num_rows: c_int,
+        inverse: unsafe extern "C" fn(*const VP8LTransform, c_int, c_int, *const u32, *mut u32),
+    ) -> Vec<u32> {
+        let width = transform.xsize_ as usize;
+        let in_width = if transform.type_ == COLOR_INDEXING_TRANSFORM {
+            VP8LSubSampleSize(transform.xsize_ as u32, transform.bits_ as u32) as usize
+        } else {
+            width
+        };
+        let mut result = Vec::new();
+        let mut cache = vec![0; width * (num_rows as usize + 1)];
+        let mut row_start = 0;
+        while row_start < transform.ysize_ {
+            let row_end = (row_start + num_rows).min(transform.ysize_);
+            let rows = (row_end - row_start) as usize;
+            let in_rows = &in_[row_start as usize * in_width..][..rows * in_width];
+            unsafe {
+                let out = cache.as_mut_ptr().add(width);
+                if in_place {
+                    ptr::copy_nonoverlapping(in_rows.as_ptr(), out, in_rows.len());
+                    inverse(transform, row_start, row_end, out, out);
+                } else {
+                    inverse(transform, row_start, row_end, in_rows.as_ptr(), out);
+                }
+            }
+            result.extend_from_slice(&cache[width..][..rows * width]);
+            row_start = row_end;
+        }
+        result
+    }
+
+    #[test]
+    fn test_inverse_transform_parity() {
+        init();
+        let mut digest = Digest::new();
+        let mut rng = Lcg(4);
+        let types = [
+            PREDICTOR_TRANSFORM,
+            CROSS_COLOR_TRANSFORM,
+            SUBTRACT_GREEN,
+            COLOR_INDEXING_TRANSFORM,
+        ];
+        for &type_ in &types {
+            for _ in 0..20 {
+                let width = 1 + rng.next() as c_int % 70;
+                let height = 1 + rng.next() as c_int % 40;
+                let bits = match type_ {
+                    PREDICTOR_TRANSFORM | CROSS_COLOR_TRANSFORM => 2 + rng.next() as c_int % 4,
+                    COLOR_INDEXING_TRANSFORM => rng.next() as c_int % 4,
+                    SUBTRACT_GREEN => 0,
+                };
+                let (in_width, mut data) = if type_ == COLOR_INDEXING_TRANSFORM {
+                    let in_width = VP8LSubSampleSize(width as u32, bits as u32);
+                    (in_width as usize, pixels(&mut rng, 256))
+                } else {
+                    let tiles_per_row = VP8LSubSampleSize(width as u32, bits as u32);
+                    let tiles_per_col = VP8LSubSampleSize(height as u32, bits as u32);
+                    let len = (tiles_per_row * tiles_per_col) as usize;
+                    (width as usize, pixels(&mut rng, len))
+                };
+                // Any green value is a valid (packed) index into the 256-entry
+                // color map.
+                let in_ = pixels(&mut rng, in_width * height as usize);
+                let transform = VP8LTransform {
+                    type_,
+                    bits_: bits,
+                    xsize_: width,
+                    ysize_: height,
+                    data_: data.as_mut_ptr(),
+                };
+                for &num_rows in &[1, 3, 16, height] {
+                    for &in_place in &[false, true] {
+                        let out = inverse_transform(
+                            &transform,
+                            &in_,
+                            in_place,
+                            num_rows,
+                            VP8LInverseTransform,
+                        );
+                        digest.update_u32(&out);
+                        #[cfg(feature = "c-reference")]
+                        {
+                            let ref_out = inverse_transform(
+                                &transform,
+                                &in_,
+                                in_place,
+                                num_rows,
+                                WebPRef_VP8LInverseTransform,
+                            );
+                            assert!(
+                                out == ref_out,
+                                "type = {:?}, {}x{}, bits = {}",
+                                type_,
+                                width,
+                                height,
+                                bits
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        check_parity("dsp::lossless::test_inverse_transform_parity", digest);
+    }
+
+    #[test]
+    fn test_color_index_inverse_alpha_parity() {
+        init();
+        let mut digest = Digest::new();
+        let mut rng = Lcg(5);
+        for bits in 0..4 {
+            for _ in 0..20 {
+                let width = 1 + rng.next() as c_int % 70;
+                let height = 1 + rng.next() as c_int % 10;
+                let in_width = VP8LSubSampleSize(width as u32, bits as u32) as usize;
+                let num_colors = if bits == 0 { 256 } else { 1 << (8 >> bits) };
+                let mut color_map = pixels(&mut rng, num_colors);
+                let src = rng.bytes(in_width * height as usize);
+                let transform = VP8LTransform {
+                    type_: COLOR_INDEXING_TRANSFORM,
+                    bits_: bits,
+                    xsize_: width,
+                    ysize_: height,
+                    data_: color_map.as_mut_ptr(),
+                };
+                let mut dst = vec![0; (width * height) as usize];
+                unsafe {
+                    VP8LColorIndexInverseTransformAlpha(
+                        &transform,
+                        0,
+                        height,
+                        src.as_ptr(),
+                        dst.as_mut_ptr(),
+                    );
+                }
+                digest.update(&dst);
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut ref_dst = vec![0; (width * height) as usize];
+                    unsafe {
+                        WebPRef_VP8LColorIndexInverseTransformAlpha(
+                            &transform,
+                            0,
+                            height,
+                            src.as_ptr(),
+                            ref_dst.as_mut_ptr(),
+                        );
+                    }
+                    assert_eq!(dst, ref_dst);
+                }
+            }
+        }
+        check_parity(
+            "dsp::lossless::test_color_index_inverse_alpha_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_convert_from_bgra_parity() {
+        init();
+        let mut digest = Digest::new();
+        let mut rng = Lcg(6);
+        for num_pixels in 1..50 {
+            let src = pixels(&mut rng, num_pixels);
+            for mode in MODE_RGB..=MODE_rgbA_4444 {
+                let mut dst = vec![0; num_pixels * 4];
+                unsafe {
+                    VP8LConvertFromBGRA(src.as_ptr(), num_pixels as c_int, mode, dst.as_mut_ptr());
+                }
+                digest.update(&dst);
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut ref_dst = vec![0; num_pixels * 4];
+                    unsafe {
+                        WebPRef_VP8LConvertFromBGRA(
+                            src.as_ptr(),
+                            num_pixels as c_int,
+                            mode,
+                            ref_dst.as_mut_ptr(),
+                        );
+                    }
+                    assert_eq!(dst, ref_dst, "mode = {}", mode);
+                }
+            }
+        }
+        check_parity("dsp::lossless::test_convert_from_bgra_parity", digest);
+    }
+}
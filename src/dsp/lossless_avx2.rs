@@ -0,0 +1,115 @@
+// Copyright 2014 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// AVX2 variant of methods for lossless decoder
+//
+// libwebp 1.0.0 has no AVX2 decoding functions; these widen the ones of
+// lossless_sse2.c to 256-bit registers.
+//
+// Author: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::os::raw::*;
+
+use dsp::lossless::{VP8LAddGreenToBlueAndRed, VP8LMultipliers, VP8LTransformColorInverse};
+use dsp::lossless_sse2::{AddGreenToBlueAndRed_SSE2, TransformColorInverse_SSE2};
+
+//------------------------------------------------------------------------------
+// Subtract-Green Transform
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe extern "C" fn AddGreenToBlueAndRed_AVX2(
+    src: *const u32,
+    num_pixels: c_int,
+    dst: *mut u32,
+) {
+    #[rustfmt::skip]
+    let kCstShuffle = _mm256_set_epi8(
+        -1, 29, -1, 29, -1, 25, -1, 25, -1, 21, -1, 21, -1, 17, -1, 17,
+        -1, 13, -1, 13, -1, 9, -1, 9, -1, 5, -1, 5, -1, 1, -1, 1);
+    let mut i = 0;
+    while i + 8 <= num_pixels as isize {
+        let in_ = _mm256_loadu_si256(src.offset(i) as *const __m256i); // argb
+        let in_0g0g = _mm256_shuffle_epi8(in_, kCstShuffle); // 0g0g
+        let out = _mm256_add_epi8(in_, in_0g0g);
+        _mm256_storeu_si256(dst.offset(i) as *mut __m256i, out);
+        i += 8;
+    }
+    // fallthrough and finish off with SSE.
+    if i != num_pixels as isize {
+        AddGreenToBlueAndRed_SSE2(src.offset(i), num_pixels - i as c_int, dst.offset(i));
+    }
+}
+
+//------------------------------------------------------------------------------
+// Color Transform
+
+// sign-extended multiplying constants, pre-shifted by 5.
+macro_rules! cst {
+    ($m:expr, $x:ident) => {
+        ((($m.$x as u16) << 8) as i16) >> 5 // sign-extend
+    };
+}
+
+macro_rules! mk_cst_16 {
+    ($hi:expr, $lo:expr) => {
+        _mm256_set1_epi32(((($hi as i32 as u32) << 16) | (($lo as i32 as u32) & 0xffff)) as i32)
+    };
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "avx2")]
+pub(crate) unsafe extern "C" fn TransformColorInverse_AVX2(
+    m: *const VP8LMultipliers,
+    src: *const u32,
+    num_pixels: c_int,
+    dst: *mut u32,
+) {
+    let mults_rb = mk_cst_16!(cst!(*m, green_to_red_), cst!(*m, green_to_blue_));
+    let mults_b2 = mk_cst_16!(0, cst!(*m, red_to_blue_));
+    let mask_ag = _mm256_set1_epi32(0xff00ff00u32 as i32); // alpha-green masks
+    #[rustfmt::skip]
+    let perm1 = _mm256_setr_epi8(
+        -1, 1, -1, 1, -1, 5, -1, 5, -1, 9, -1, 9, -1, 13, -1, 13,
+        -1, 17, -1, 17, -1, 21, -1, 21, -1, 25, -1, 25, -1, 29, -1, 29);
+    #[rustfmt::skip]
+    let perm2 = _mm256_setr_epi8(
+        -1, 2, -1, -1, -1, 6, -1, -1, -1, 10, -1, -1, -1, 14, -1, -1,
+        -1, 18, -1, -1, -1, 22, -1, -1, -1, 26, -1, -1, -1, 30, -1, -1);
+    let mut i = 0;
+    while i + 8 <= num_pixels as isize {
+        let A = _mm256_loadu_si256(src.offset(i) as *const __m256i);
+        let B = _mm256_shuffle_epi8(A, perm1); // argb -> g0g0
+        let C = _mm256_mulhi_epi16(B, mults_rb);
+        let D = _mm256_add_epi8(A, C);
+        let E = _mm256_shuffle_epi8(D, perm2);
+        let F = _mm256_mulhi_epi16(E, mults_b2);
+        let G = _mm256_add_epi8(D, F);
+        let out = _mm256_blendv_epi8(G, A, mask_ag);
+        _mm256_storeu_si256(dst.offset(i) as *mut __m256i, out);
+        i += 8;
+    }
+    // Fall-back to SSE-version for left-overs.
+    if i != num_pixels as isize {
+        TransformColorInverse_SSE2(m, src.offset(i), num_pixels - i as c_int, dst.offset(i));
+    }
+}
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[allow(non_snake_case)]
+pub(crate) unsafe fn VP8LDspInitAVX2() {
+    VP8LAddGreenToBlueAndRed = Some(AddGreenToBlueAndRed_AVX2);
+    VP8LTransformColorInverse = Some(TransformColorInverse_AVX2);
+}
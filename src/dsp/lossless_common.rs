@@ -0,0 +1,76 @@
+//------------------------------------------------------------------------------
+// Misc methods.
+
+// Computes sampled size of 'size' when sampling using 'sampling bits'.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LSubSampleSize(size: u32, sampling_bits: u32) -> u32 {
+    (size + (1 << sampling_bits) - 1) >> sampling_bits
+}
+
+//------------------------------------------------------------------------------
+// Decoding
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8GetARGBIndex(idx: u32) -> u32 {
+    (idx >> 8) & 0xff
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8GetAlphaIndex(idx: u8) -> u8 {
+    idx
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8GetARGBValue(val: u32) -> u32 {
+    val
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8GetAlphaValue(val: u32) -> u8 {
+    ((val >> 8) & 0xff) as u8
+}
+
+//------------------------------------------------------------------------------
+// Pixel operations.
+
+// Sum of each component, mod 256.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LAddPixels(a: u32, b: u32) -> u32 {
+    let alpha_and_green = (a & 0xff00ff00).wrapping_add(b & 0xff00ff00);
+    let red_and_blue = (a & 0x00ff00ff).wrapping_add(b & 0x00ff00ff);
+    (alpha_and_green & 0xff00ff00) | (red_and_blue & 0x00ff00ff)
+}
+
+//------------------------------------------------------------------------------
+// Transform-related functions use din both encoding and decoding.
+
+// Macros used to create a batch predictor that iteratively uses a
+// one-pixel predictor.
+
+// The predictor is added to the output pixel (which
+// is therefore considered as a residual) to get the final prediction.
+macro_rules! generate_predictor_add {
+    ($(#[$attr:meta])* $predictor:ident, $predictor_add:ident) => {
+        #[allow(non_snake_case)]
+        $(#[$attr])*
+        unsafe extern "C" fn $predictor_add(
+            in_: *const u32,
+            upper: *const u32,
+            num_pixels: ::std::os::raw::c_int,
+            out: *mut u32,
+        ) {
+            for x in 0..num_pixels as isize {
+                // 'upper' is NULL for the first row; it is never read then.
+                let pred = $predictor(*out.offset(x - 1), upper.wrapping_offset(x));
+                *out.offset(x) =
+                    ::dsp::lossless_common::VP8LAddPixels(*in_.offset(x), pred);
+            }
+        }
+    };
+}
@@ -0,0 +1,270 @@
+// Copyright 2015 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Image transform methods for lossless encoder.
+//
+// Authors: Vikas Arora (vikaas.arora@gmail.com)
+//          Jyrki Alakuijala (jyrki@google.com)
+//          Urvang Joshi (urvang@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+// The encoding half of lossless.h and lossless_common.h. The functions
+// themselves are still the C ones (lossless_enc.c and its SIMD variants).
+
+use std::os::raw::*;
+
+use dsp::lossless::{VP8LMultipliers, VP8LPredictorAddSubFunc};
+use enc::histogram_enc::VP8LHistogram;
+use utils::utils::BitsLog2Floor;
+
+//------------------------------------------------------------------------------
+// Encoding
+
+pub(crate) type VP8LProcessEncBlueAndRedFunc =
+    Option<unsafe extern "C" fn(dst: *mut u32, num_pixels: c_int)>;
+pub(crate) type VP8LTransformColorFunc =
+    Option<unsafe extern "C" fn(m: *const VP8LMultipliers, dst: *mut u32, num_pixels: c_int)>;
+pub(crate) type VP8LCollectColorBlueTransformsFunc = Option<
+    unsafe extern "C" fn(
+        argb: *const u32,
+        stride: c_int,
+        tile_width: c_int,
+        tile_height: c_int,
+        green_to_blue: c_int,
+        red_to_blue: c_int,
+        histo: *mut c_int,
+    ),
+>;
+pub(crate) type VP8LCollectColorRedTransformsFunc = Option<
+    unsafe extern "C" fn(
+        argb: *const u32,
+        stride: c_int,
+        tile_width: c_int,
+        tile_height: c_int,
+        green_to_red: c_int,
+        histo: *mut c_int,
+    ),
+>;
+
+//------------------------------------------------------------------------------
+// Image transforms.
+
+pub(crate) type VP8LCostFunc =
+    Option<unsafe extern "C" fn(population: *const u32, length: c_int) -> c_double>;
+pub(crate) type VP8LCostCombinedFunc =
+    Option<unsafe extern "C" fn(x: *const u32, y: *const u32, length: c_int) -> c_double>;
+pub(crate) type VP8LCombinedShannonEntropyFunc =
+    Option<unsafe extern "C" fn(x: *const c_int, y: *const c_int) -> c_float>;
+
+// small struct to hold counters
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct VP8LStreaks {
+    pub(crate) counts: [c_int; 2], // index: 0=zero steak, 1=non-zero streak
+    pub(crate) streaks: [[c_int; 2]; 2], // [zero/non-zero][streak<3 / streak>=3]
+}
+
+// small struct to hold bit entropy results
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub(crate) struct VP8LBitEntropy {
+    pub(crate) entropy: c_double, // entropy
+    pub(crate) sum: u32,          // sum of the population
+    pub(crate) nonzeros: c_int,   // number of non-zero elements in the population
+    pub(crate) max_val: u32,      // maximum value in the population
+    pub(crate) nonzero_code: u32, // index of the last non-zero in the population
+}
+
+// Get the combined symbol bit entropy and Huffman cost stats for the
+// distributions 'X' and 'Y'. Those results can then be refined according to
+// codec specific heuristics.
+pub(crate) type VP8LGetCombinedEntropyUnrefinedFunc = Option<
+    unsafe extern "C" fn(
+        x: *const u32,
+        y: *const u32,
+        length: c_int,
+        bit_entropy: *mut VP8LBitEntropy,
+        stats: *mut VP8LStreaks,
+    ),
+>;
+
+// Get the entropy for the distribution 'X'.
+pub(crate) type VP8LGetEntropyUnrefinedFunc = Option<
+    unsafe extern "C" fn(
+        x: *const u32,
+        length: c_int,
+        bit_entropy: *mut VP8LBitEntropy,
+        stats: *mut VP8LStreaks,
+    ),
+>;
+
+pub(crate) type VP8LHistogramAddFunc = Option<
+    unsafe extern "C" fn(a: *const VP8LHistogram, b: *const VP8LHistogram, out: *mut VP8LHistogram),
+>;
+
+// Returns the first index where array1 and array2 are different.
+pub(crate) type VP8LVectorMismatchFunc =
+    Option<unsafe extern "C" fn(array1: *const u32, array2: *const u32, length: c_int) -> c_int>;
+
+pub(crate) type VP8LBundleColorMapFunc =
+    Option<unsafe extern "C" fn(row: *const u8, width: c_int, xbits: c_int, dst: *mut u32)>;
+
+pub(crate) type VP8LFastLog2SlowFunc = Option<unsafe extern "C" fn(v: u32) -> c_float>;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct VP8LPrefixCode {
+    pub(crate) code_: i8,
+    pub(crate) extra_bits_: i8,
+}
+
+pub(crate) const LOG_LOOKUP_IDX_MAX: usize = 256;
+pub(crate) const PREFIX_LOOKUP_IDX_MAX: usize = 512;
+
+extern "C" {
+    pub(crate) static mut VP8LSubtractGreenFromBlueAndRed: VP8LProcessEncBlueAndRedFunc;
+    pub(crate) static mut VP8LTransformColor: VP8LTransformColorFunc;
+    pub(crate) static mut VP8LCollectColorBlueTransforms: VP8LCollectColorBlueTransformsFunc;
+    pub(crate) static mut VP8LCollectColorRedTransforms: VP8LCollectColorRedTransformsFunc;
+
+    pub(crate) static mut VP8LPredictorsSub: [VP8LPredictorAddSubFunc; 16];
+
+    pub(crate) static mut VP8LExtraCost: VP8LCostFunc;
+    pub(crate) static mut VP8LExtraCostCombined: VP8LCostCombinedFunc;
+    pub(crate) static mut VP8LCombinedShannonEntropy: VP8LCombinedShannonEntropyFunc;
+
+    pub(crate) static mut VP8LGetCombinedEntropyUnrefined: VP8LGetCombinedEntropyUnrefinedFunc;
+    pub(crate) static mut VP8LGetEntropyUnrefined: VP8LGetEntropyUnrefinedFunc;
+
+    pub(crate) fn VP8LBitsEntropyUnrefined(
+        array: *const u32,
+        n: c_int,
+        entropy: *mut VP8LBitEntropy,
+    );
+
+    pub(crate) static mut VP8LHistogramAdd: VP8LHistogramAddFunc;
+
+    pub(crate) static mut VP8LVectorMismatch: VP8LVectorMismatchFunc;
+
+    pub(crate) static mut VP8LBundleColorMap: VP8LBundleColorMapFunc;
+
+    // Must be called before calling any of the above methods.
+    pub(crate) fn VP8LEncDspInit();
+
+    static kLog2Table: [c_float; LOG_LOOKUP_IDX_MAX];
+    static mut VP8LFastLog2Slow: VP8LFastLog2SlowFunc;
+
+    // These tables are derived using VP8LPrefixEncodeNoLUT.
+    static kPrefixEncodeCode: [VP8LPrefixCode; PREFIX_LOOKUP_IDX_MAX];
+    static kPrefixEncodeExtraBitsValue: [u8; PREFIX_LOOKUP_IDX_MAX];
+}
+
+//------------------------------------------------------------------------------
+// Misc methods.
+
+// Converts near lossless quality into max number of bits shaved off.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LNearLosslessBits(near_lossless_quality: c_int) -> c_int {
+    //    100 -> 0
+    // 80..99 -> 1
+    // 60..79 -> 2
+    // 40..59 -> 3
+    // 20..39 -> 4
+    //  0..19 -> 5
+    5 - near_lossless_quality / 20
+}
+
+//------------------------------------------------------------------------------
+// Faster logarithm for integers. Small values use a look-up table.
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LFastLog2(v: u32) -> c_float {
+    if (v as usize) < LOG_LOOKUP_IDX_MAX {
+        kLog2Table[v as usize]
+    } else {
+        VP8LFastLog2Slow.unwrap()(v)
+    }
+}
+
+//------------------------------------------------------------------------------
+// PrefixEncode()
+
+// Splitting of distance and length codes into prefixes and
+// extra bits. The prefixes are encoded with an entropy code
+// while the extra bits are stored just as normal bits.
+#[allow(non_snake_case)]
+#[inline]
+fn VP8LPrefixEncodeBitsNoLUT(mut distance: c_int, code: &mut c_int, extra_bits: &mut c_int) {
+    distance -= 1;
+    let highest_bit = BitsLog2Floor(distance as u32);
+    let second_highest_bit = (distance >> (highest_bit - 1)) & 1;
+    *extra_bits = highest_bit - 1;
+    *code = 2 * highest_bit + second_highest_bit;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn VP8LPrefixEncodeNoLUT(
+    mut distance: c_int,
+    code: &mut c_int,
+    extra_bits: &mut c_int,
+    extra_bits_value: &mut c_int,
+) {
+    distance -= 1;
+    let highest_bit = BitsLog2Floor(distance as u32);
+    let second_highest_bit = (distance >> (highest_bit - 1)) & 1;
+    *extra_bits = highest_bit - 1;
+    *extra_bits_value = distance & ((1 << *extra_bits) - 1);
+    *code = 2 * highest_bit + second_highest_bit;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LPrefixEncodeBits(distance: c_int, code: &mut c_int, extra_bits: &mut c_int) {
+    if (distance as usize) < PREFIX_LOOKUP_IDX_MAX {
+        let prefix_code = unsafe { kPrefixEncodeCode[distance as usize] };
+        *code = prefix_code.code_ as c_int;
+        *extra_bits = prefix_code.extra_bits_ as c_int;
+    } else {
+        VP8LPrefixEncodeBitsNoLUT(distance, code, extra_bits);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LPrefixEncode(
+    distance: c_int,
+    code: &mut c_int,
+    extra_bits: &mut c_int,
+    extra_bits_value: &mut c_int,
+) {
+    if (distance as usize) < PREFIX_LOOKUP_IDX_MAX {
+        let prefix_code = unsafe { kPrefixEncodeCode[distance as usize] };
+        *code = prefix_code.code_ as c_int;
+        *extra_bits = prefix_code.extra_bits_ as c_int;
+        *extra_bits_value = unsafe { kPrefixEncodeExtraBitsValue[distance as usize] } as c_int;
+    } else {
+        VP8LPrefixEncodeNoLUT(distance, code, extra_bits, extra_bits_value);
+    }
+}
+
+// Difference of each component, mod 256.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LSubPixels(a: u32, b: u32) -> u32 {
+    let alpha_and_green = 0x00ff00ffu32
+        .wrapping_add(a & 0xff00ff00)
+        .wrapping_sub(b & 0xff00ff00);
+    let red_and_blue = 0xff00ff00u32
+        .wrapping_add(a & 0x00ff00ff)
+        .wrapping_sub(b & 0x00ff00ff);
+    (alpha_and_green & 0xff00ff00) | (red_and_blue & 0x00ff00ff)
+}
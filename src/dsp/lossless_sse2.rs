@@ -0,0 +1,1013 @@
+// Copyright 2014 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE2 variant of methods for lossless decoder
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::os::raw::*;
+
+use dsp::common_sse2::{VP8L32bToPlanar_SSE2, VP8PlanarTo24b_SSE2};
+use dsp::dsp::WEBP_SWAP_16BIT_CSP;
+use dsp::lossless::{
+    VP8LAddGreenToBlueAndRed, VP8LAddGreenToBlueAndRed_C, VP8LConvertBGRAToBGR,
+    VP8LConvertBGRAToBGR_C, VP8LConvertBGRAToRGB, VP8LConvertBGRAToRGB565,
+    VP8LConvertBGRAToRGB565_C, VP8LConvertBGRAToRGBA, VP8LConvertBGRAToRGBA4444,
+    VP8LConvertBGRAToRGBA4444_C, VP8LConvertBGRAToRGBA_C, VP8LConvertBGRAToRGB_C, VP8LMultipliers,
+    VP8LPredictors, VP8LPredictorsAdd, VP8LPredictorsAdd_C, VP8LTransformColorInverse,
+    VP8LTransformColorInverse_C,
+};
+use format_constants::ARGB_BLACK;
+
+//------------------------------------------------------------------------------
+// Predictor Transform
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn ClampedAddSubtractFull_SSE2(c0: u32, c1: u32, c2: u32) -> u32 {
+    let zero = _mm_setzero_si128();
+    let C0 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(c0 as i32), zero);
+    let C1 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(c1 as i32), zero);
+    let C2 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(c2 as i32), zero);
+    let V1 = _mm_add_epi16(C0, C1);
+    let V2 = _mm_sub_epi16(V1, C2);
+    let b = _mm_packus_epi16(V2, V2);
+    _mm_cvtsi128_si32(b) as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn ClampedAddSubtractHalf_SSE2(c0: u32, c1: u32, c2: u32) -> u32 {
+    let zero = _mm_setzero_si128();
+    let C0 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(c0 as i32), zero);
+    let C1 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(c1 as i32), zero);
+    let B0 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(c2 as i32), zero);
+    let avg = _mm_add_epi16(C1, C0);
+    let A0 = _mm_srli_epi16(avg, 1);
+    let A1 = _mm_sub_epi16(A0, B0);
+    let BgtA = _mm_cmpgt_epi16(B0, A0);
+    let A2 = _mm_sub_epi16(A1, BgtA);
+    let A3 = _mm_srai_epi16(A2, 1);
+    let A4 = _mm_add_epi16(A0, A3);
+    let A5 = _mm_packus_epi16(A4, A4);
+    _mm_cvtsi128_si32(A5) as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Select_SSE2(a: u32, b: u32, c: u32) -> u32 {
+    let zero = _mm_setzero_si128();
+    let A0 = _mm_cvtsi32_si128(a as i32);
+    let B0 = _mm_cvtsi32_si128(b as i32);
+    let C0 = _mm_cvtsi32_si128(c as i32);
+    let AC0 = _mm_subs_epu8(A0, C0);
+    let CA0 = _mm_subs_epu8(C0, A0);
+    let BC0 = _mm_subs_epu8(B0, C0);
+    let CB0 = _mm_subs_epu8(C0, B0);
+    let AC = _mm_or_si128(AC0, CA0);
+    let BC = _mm_or_si128(BC0, CB0);
+    let pa = _mm_unpacklo_epi8(AC, zero); // |a - c|
+    let pb = _mm_unpacklo_epi8(BC, zero); // |b - c|
+    let diff = _mm_sub_epi16(pb, pa);
+    let pa_minus_pb = {
+        let mut out = [0i16; 8];
+        _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, diff);
+        out[0] as c_int + out[1] as c_int + out[2] as c_int + out[3] as c_int
+    };
+    if pa_minus_pb <= 0 {
+        a
+    } else {
+        b
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Average2_m128i(a0: &__m128i, a1: &__m128i, avg: &mut __m128i) {
+    // (a + b) >> 1 = ((a + b + 1) >> 1) - ((a ^ b) & 1)
+    let ones = _mm_set1_epi8(1);
+    let avg1 = _mm_avg_epu8(*a0, *a1);
+    let one = _mm_and_si128(_mm_xor_si128(*a0, *a1), ones);
+    *avg = _mm_sub_epi8(avg1, one);
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Average2_uint32_SSE2(a0: u32, a1: u32, avg: &mut __m128i) {
+    // (a + b) >> 1 = ((a + b + 1) >> 1) - ((a ^ b) & 1)
+    let ones = _mm_set1_epi8(1);
+    let A0 = _mm_cvtsi32_si128(a0 as i32);
+    let A1 = _mm_cvtsi32_si128(a1 as i32);
+    let avg1 = _mm_avg_epu8(A0, A1);
+    let one = _mm_and_si128(_mm_xor_si128(A0, A1), ones);
+    *avg = _mm_sub_epi8(avg1, one);
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Average2_uint32_16_SSE2(a0: u32, a1: u32) -> __m128i {
+    let zero = _mm_setzero_si128();
+    let A0 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(a0 as i32), zero);
+    let A1 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(a1 as i32), zero);
+    let sum = _mm_add_epi16(A1, A0);
+    _mm_srli_epi16(sum, 1)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Average2_SSE2(a0: u32, a1: u32) -> u32 {
+    let mut output = _mm_setzero_si128();
+    Average2_uint32_SSE2(a0, a1, &mut output);
+    _mm_cvtsi128_si32(output) as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Average3_SSE2(a0: u32, a1: u32, a2: u32) -> u32 {
+    let zero = _mm_setzero_si128();
+    let avg1 = Average2_uint32_16_SSE2(a0, a2);
+    let A1 = _mm_unpacklo_epi8(_mm_cvtsi32_si128(a1 as i32), zero);
+    let sum = _mm_add_epi16(avg1, A1);
+    let avg2 = _mm_srli_epi16(sum, 1);
+    let A2 = _mm_packus_epi16(avg2, avg2);
+    _mm_cvtsi128_si32(A2) as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn Average4_SSE2(a0: u32, a1: u32, a2: u32, a3: u32) -> u32 {
+    let avg1 = Average2_uint32_16_SSE2(a0, a1);
+    let avg2 = Average2_uint32_16_SSE2(a2, a3);
+    let sum = _mm_add_epi16(avg2, avg1);
+    let avg3 = _mm_srli_epi16(sum, 1);
+    let A0 = _mm_packus_epi16(avg3, avg3);
+    _mm_cvtsi128_si32(A0) as u32
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor5_SSE2(left: u32, top: *const u32) -> u32 {
+    Average3_SSE2(left, *top, *top.offset(1))
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor6_SSE2(left: u32, top: *const u32) -> u32 {
+    Average2_SSE2(left, *top.offset(-1))
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor7_SSE2(left: u32, top: *const u32) -> u32 {
+    Average2_SSE2(left, *top)
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor8_SSE2(_left: u32, top: *const u32) -> u32 {
+    Average2_SSE2(*top.offset(-1), *top)
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor9_SSE2(_left: u32, top: *const u32) -> u32 {
+    Average2_SSE2(*top, *top.offset(1))
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor10_SSE2(left: u32, top: *const u32) -> u32 {
+    Average4_SSE2(left, *top.offset(-1), *top, *top.offset(1))
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor11_SSE2(left: u32, top: *const u32) -> u32 {
+    Select_SSE2(*top, left, *top.offset(-1))
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor12_SSE2(left: u32, top: *const u32) -> u32 {
+    ClampedAddSubtractFull_SSE2(left, *top, *top.offset(-1))
+}
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn Predictor13_SSE2(left: u32, top: *const u32) -> u32 {
+    ClampedAddSubtractHalf_SSE2(left, *top, *top.offset(-1))
+}
+
+// Batch versions of those functions.
+
+// Predictor0: ARGB_BLACK.
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn PredictorAdd0_SSE2(
+    in_: *const u32,
+    upper: *const u32,
+    num_pixels: c_int,
+    out: *mut u32,
+) {
+    let mut i = 0;
+    let black = _mm_set1_epi32(ARGB_BLACK as i32);
+    while i + 4 <= num_pixels as isize {
+        let src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+        let res = _mm_add_epi8(src, black);
+        _mm_storeu_si128(out.offset(i) as *mut __m128i, res);
+        i += 4;
+    }
+    if i != num_pixels as isize {
+        VP8LPredictorsAdd_C[0].unwrap()(
+            in_.offset(i),
+            upper.wrapping_offset(i),
+            num_pixels - i as c_int,
+            out.offset(i),
+        );
+    }
+}
+
+// Predictor1: left.
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn PredictorAdd1_SSE2(
+    in_: *const u32,
+    upper: *const u32,
+    num_pixels: c_int,
+    out: *mut u32,
+) {
+    let mut i = 0;
+    let mut prev = _mm_set1_epi32(*out.offset(-1) as i32);
+    while i + 4 <= num_pixels as isize {
+        // a | b | c | d
+        let src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+        // 0 | a | b | c
+        let shift0 = _mm_slli_si128(src, 4);
+        // a | a + b | b + c | c + d
+        let sum0 = _mm_add_epi8(src, shift0);
+        // 0 | 0 | a | a + b
+        let shift1 = _mm_slli_si128(sum0, 8);
+        // a | a + b | a + b + c | a + b + c + d
+        let sum1 = _mm_add_epi8(sum0, shift1);
+        let res = _mm_add_epi8(sum1, prev);
+        _mm_storeu_si128(out.offset(i) as *mut __m128i, res);
+        // replicate prev output on the four lanes
+        prev = _mm_shuffle_epi32(res, 0xff /* _MM_SHUFFLE(3, 3, 3, 3) */);
+        i += 4;
+    }
+    if i != num_pixels as isize {
+        VP8LPredictorsAdd_C[1].unwrap()(
+            in_.offset(i),
+            upper.wrapping_offset(i),
+            num_pixels - i as c_int,
+            out.offset(i),
+        );
+    }
+}
+
+// Macro that adds 32-bit integers from IN using mod 256 arithmetic
+// per 8 bit channel.
+macro_rules! generate_predictor_1 {
+    ($x:expr, $predictor_add:ident, $offset:expr) => {
+        #[allow(non_snake_case)]
+        #[target_feature(enable = "sse2")]
+        pub(crate) unsafe extern "C" fn $predictor_add(
+            in_: *const u32,
+            upper: *const u32,
+            num_pixels: c_int,
+            out: *mut u32,
+        ) {
+            let mut i = 0;
+            while i + 4 <= num_pixels as isize {
+                let src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+                let other = _mm_loadu_si128(upper.offset(i + $offset) as *const __m128i);
+                let res = _mm_add_epi8(src, other);
+                _mm_storeu_si128(out.offset(i) as *mut __m128i, res);
+                i += 4;
+            }
+            if i != num_pixels as isize {
+                VP8LPredictorsAdd_C[$x].unwrap()(
+                    in_.offset(i),
+                    upper.offset(i),
+                    num_pixels - i as c_int,
+                    out.offset(i),
+                );
+            }
+        }
+    };
+}
+
+// Predictor2: Top.
+generate_predictor_1!(2, PredictorAdd2_SSE2, 0);
+// Predictor3: Top-right.
+generate_predictor_1!(3, PredictorAdd3_SSE2, 1);
+// Predictor4: Top-left.
+generate_predictor_1!(4, PredictorAdd4_SSE2, -1);
+
+// Due to averages with integers, values cannot be accumulated in parallel for
+// predictors 5 to 7.
+generate_predictor_add!(
+    #[target_feature(enable = "sse2")]
+    Predictor5_SSE2,
+    PredictorAdd5_SSE2
+);
+generate_predictor_add!(
+    #[target_feature(enable = "sse2")]
+    Predictor6_SSE2,
+    PredictorAdd6_SSE2
+);
+generate_predictor_add!(
+    #[target_feature(enable = "sse2")]
+    Predictor7_SSE2,
+    PredictorAdd7_SSE2
+);
+
+macro_rules! generate_predictor_2 {
+    ($x:expr, $predictor_add:ident, $offset:expr) => {
+        #[allow(non_snake_case)]
+        #[target_feature(enable = "sse2")]
+        pub(crate) unsafe extern "C" fn $predictor_add(
+            in_: *const u32,
+            upper: *const u32,
+            num_pixels: c_int,
+            out: *mut u32,
+        ) {
+            let mut i = 0;
+            while i + 4 <= num_pixels as isize {
+                let Tother = _mm_loadu_si128(upper.offset(i + $offset) as *const __m128i);
+                let T = _mm_loadu_si128(upper.offset(i) as *const __m128i);
+                let src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+                let mut avg = _mm_setzero_si128();
+                Average2_m128i(&T, &Tother, &mut avg);
+                let res = _mm_add_epi8(avg, src);
+                _mm_storeu_si128(out.offset(i) as *mut __m128i, res);
+                i += 4;
+            }
+            if i != num_pixels as isize {
+                VP8LPredictorsAdd_C[$x].unwrap()(
+                    in_.offset(i),
+                    upper.offset(i),
+                    num_pixels - i as c_int,
+                    out.offset(i),
+                );
+            }
+        }
+    };
+}
+// Predictor8: average TL T.
+generate_predictor_2!(8, PredictorAdd8_SSE2, -1);
+// Predictor9: average T TR.
+generate_predictor_2!(9, PredictorAdd9_SSE2, 1);
+
+// Predictor10: average of (average of (L,TL), average of (T, TR)).
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn PredictorAdd10_SSE2(
+    in_: *const u32,
+    upper: *const u32,
+    num_pixels: c_int,
+    out: *mut u32,
+) {
+    let mut i = 0;
+    let mut L = _mm_cvtsi32_si128(*out.offset(-1) as i32);
+    while i + 4 <= num_pixels as isize {
+        let mut src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+        let mut TL = _mm_loadu_si128(upper.offset(i - 1) as *const __m128i);
+        let T = _mm_loadu_si128(upper.offset(i) as *const __m128i);
+        let TR = _mm_loadu_si128(upper.offset(i + 1) as *const __m128i);
+        let mut avgTTR = _mm_setzero_si128();
+        Average2_m128i(&T, &TR, &mut avgTTR);
+        for out_ in 0..4 {
+            if out_ > 0 {
+                // Rotate the pre-computed values for the next iteration.
+                avgTTR = _mm_srli_si128(avgTTR, 4);
+                TL = _mm_srli_si128(TL, 4);
+                src = _mm_srli_si128(src, 4);
+            }
+            let mut avgLTL = _mm_setzero_si128();
+            let mut avg = _mm_setzero_si128();
+            Average2_m128i(&L, &TL, &mut avgLTL);
+            Average2_m128i(&avgTTR, &avgLTL, &mut avg);
+            L = _mm_add_epi8(avg, src);
+            *out.offset(i + out_) = _mm_cvtsi128_si32(L) as u32;
+        }
+        i += 4;
+    }
+    if i != num_pixels as isize {
+        VP8LPredictorsAdd_C[10].unwrap()(
+            in_.offset(i),
+            upper.offset(i),
+            num_pixels - i as c_int,
+            out.offset(i),
+        );
+    }
+}
+
+// Predictor11: select.
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn PredictorAdd11_SSE2(
+    in_: *const u32,
+    upper: *const u32,
+    num_pixels: c_int,
+    out: *mut u32,
+) {
+    let mut i = 0;
+    let mut L = _mm_cvtsi32_si128(*out.offset(-1) as i32);
+    while i + 4 <= num_pixels as isize {
+        let mut T = _mm_loadu_si128(upper.offset(i) as *const __m128i);
+        let mut TL = _mm_loadu_si128(upper.offset(i - 1) as *const __m128i);
+        let mut src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+        let mut pa = {
+            // We can unpack with any value on the upper 32 bits, provided it's the
+            // same on both operands (so that their sum of abs diff is zero). Here we
+            // use T.
+            let T_lo = _mm_unpacklo_epi32(T, T);
+            let TL_lo = _mm_unpacklo_epi32(TL, T);
+            let T_hi = _mm_unpackhi_epi32(T, T);
+            let TL_hi = _mm_unpackhi_epi32(TL, T);
+            let s_lo = _mm_sad_epu8(T_lo, TL_lo);
+            let s_hi = _mm_sad_epu8(T_hi, TL_hi);
+            _mm_packs_epi32(s_lo, s_hi) // pa = sum |T-TL|
+        };
+        for out_ in 0..4 {
+            if out_ > 0 {
+                // Shift the pre-computed value for the next iteration.
+                T = _mm_srli_si128(T, 4);
+                TL = _mm_srli_si128(TL, 4);
+                src = _mm_srli_si128(src, 4);
+                pa = _mm_srli_si128(pa, 4);
+            }
+            let L_lo = _mm_unpacklo_epi32(L, T);
+            let TL_lo = _mm_unpacklo_epi32(TL, T);
+            let pb = _mm_sad_epu8(L_lo, TL_lo); // pb = sum |L-TL|
+            let mask = _mm_cmpgt_epi32(pb, pa);
+            let A = _mm_and_si128(mask, L);
+            let B = _mm_andnot_si128(mask, T);
+            let pred = _mm_or_si128(A, B); // pred = (pa > b)? L : T
+            L = _mm_add_epi8(src, pred);
+            *out.offset(i + out_) = _mm_cvtsi128_si32(L) as u32;
+        }
+        i += 4;
+    }
+    if i != num_pixels as isize {
+        VP8LPredictorsAdd_C[11].unwrap()(
+            in_.offset(i),
+            upper.offset(i),
+            num_pixels - i as c_int,
+            out.offset(i),
+        );
+    }
+}
+
+// Predictor12: ClampedAddSubtractFull.
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn PredictorAdd12_SSE2(
+    in_: *const u32,
+    upper: *const u32,
+    num_pixels: c_int,
+    out: *mut u32,
+) {
+    let mut i = 0;
+    let zero = _mm_setzero_si128();
+    let L8 = _mm_cvtsi32_si128(*out.offset(-1) as i32);
+    let mut L = _mm_unpacklo_epi8(L8, zero);
+    while i + 4 <= num_pixels as isize {
+        // Load 4 pixels at a time.
+        let mut src = _mm_loadu_si128(in_.offset(i) as *const __m128i);
+        let T = _mm_loadu_si128(upper.offset(i) as *const __m128i);
+        let T_lo = _mm_unpacklo_epi8(T, zero);
+        let T_hi = _mm_unpackhi_epi8(T, zero);
+        let TL = _mm_loadu_si128(upper.offset(i - 1) as *const __m128i);
+        let TL_lo = _mm_unpacklo_epi8(TL, zero);
+        let TL_hi = _mm_unpackhi_epi8(TL, zero);
+        let mut diff_lo = _mm_sub_epi16(T_lo, TL_lo);
+        let mut diff_hi = _mm_sub_epi16(T_hi, TL_hi);
+        for out_ in 0..4 {
+            let diff = if out_ < 2 { &mut diff_lo } else { &mut diff_hi };
+            let all = _mm_add_epi16(L, *diff);
+            let alls = _mm_packus_epi16(all, all);
+            let res = _mm_add_epi8(src, alls);
+            *out.offset(i + out_) = _mm_cvtsi128_si32(res) as u32;
+            L = _mm_unpacklo_epi8(res, zero);
+            // Shift the pre-computed value for the next iteration.
+            if out_ % 2 == 0 {
+                *diff = _mm_srli_si128(*diff, 8);
+            }
+            src = _mm_srli_si128(src, 4);
+        }
+        i += 4;
+    }
+    if i != num_pixels as isize {
+        VP8LPredictorsAdd_C[12].unwrap()(
+            in_.offset(i),
+            upper.offset(i),
+            num_pixels - i as c_int,
+            out.offset(i),
+        );
+    }
+}
+
+// Due to averages with integers, values cannot be accumulated in parallel for
+// predictors 13.
+generate_predictor_add!(
+    #[target_feature(enable = "sse2")]
+    Predictor13_SSE2,
+    PredictorAdd13_SSE2
+);
+
+//------------------------------------------------------------------------------
+// Subtract-Green Transform
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn AddGreenToBlueAndRed_SSE2(
+    src: *const u32,
+    num_pixels: c_int,
+    dst: *mut u32,
+) {
+    let mut i = 0;
+    while i + 4 <= num_pixels as isize {
+        let in_ = _mm_loadu_si128(src.offset(i) as *const __m128i); // argb
+        let A = _mm_srli_epi16(in_, 8); // 0 a 0 g
+        let B = _mm_shufflelo_epi16(A, 0xa0 /* _MM_SHUFFLE(2, 2, 0, 0) */);
+        let C = _mm_shufflehi_epi16(B, 0xa0 /* _MM_SHUFFLE(2, 2, 0, 0) */); // 0g0g
+        let out = _mm_add_epi8(in_, C);
+        _mm_storeu_si128(dst.offset(i) as *mut __m128i, out);
+        i += 4;
+    }
+    // fallthrough and finish off with plain-C
+    if i != num_pixels as isize {
+        VP8LAddGreenToBlueAndRed_C(src.offset(i), num_pixels - i as c_int, dst.offset(i));
+    }
+}
+
+//------------------------------------------------------------------------------
+// Color Transform
+
+// sign-extended multiplying constants, pre-shifted by 5.
+macro_rules! cst {
+    ($m:expr, $x:ident) => {
+        ((($m.$x as u16) << 8) as i16) >> 5 // sign-extend
+    };
+}
+
+macro_rules! mk_cst_16 {
+    ($hi:expr, $lo:expr) => {
+        _mm_set1_epi32(((($hi as i32 as u32) << 16) | (($lo as i32 as u32) & 0xffff)) as i32)
+    };
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn TransformColorInverse_SSE2(
+    m: *const VP8LMultipliers,
+    src: *const u32,
+    num_pixels: c_int,
+    dst: *mut u32,
+) {
+    let mults_rb = mk_cst_16!(cst!(*m, green_to_red_), cst!(*m, green_to_blue_));
+    let mults_b2 = mk_cst_16!(cst!(*m, red_to_blue_), 0);
+    let mask_ag = _mm_set1_epi32(0xff00ff00u32 as i32); // alpha-green masks
+    let mut i = 0;
+    while i + 4 <= num_pixels as isize {
+        let in_ = _mm_loadu_si128(src.offset(i) as *const __m128i); // argb
+        let A = _mm_and_si128(in_, mask_ag); // a   0   g   0
+        let B = _mm_shufflelo_epi16(A, 0xa0 /* _MM_SHUFFLE(2, 2, 0, 0) */);
+        let C = _mm_shufflehi_epi16(B, 0xa0 /* _MM_SHUFFLE(2, 2, 0, 0) */); // g0g0
+        let D = _mm_mulhi_epi16(C, mults_rb); // x dr  x db1
+        let E = _mm_add_epi8(in_, D); // x r'  x   b'
+        let F = _mm_slli_epi16(E, 8); // r' 0   b' 0
+        let G = _mm_mulhi_epi16(F, mults_b2); // x db2  0  0
+        let H = _mm_srli_epi32(G, 8); // 0  x db2  0
+        let I = _mm_add_epi8(H, F); // r' x  b'' 0
+        let J = _mm_srli_epi16(I, 8); // 0  r'  0  b''
+        let out = _mm_or_si128(J, A);
+        _mm_storeu_si128(dst.offset(i) as *mut __m128i, out);
+        i += 4;
+    }
+    // Fall-back to C-version for left-overs.
+    if i != num_pixels as isize {
+        VP8LTransformColorInverse_C(m, src.offset(i), num_pixels - i as c_int, dst.offset(i));
+    }
+}
+
+//------------------------------------------------------------------------------
+// Color-space conversion functions
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn ConvertBGRAToRGB_SSE2(
+    src: *const u32,
+    mut num_pixels: c_int,
+    dst: *mut u8,
+) {
+    let mut in_ = src as *const __m128i;
+    let mut out = dst as *mut __m128i;
+
+    while num_pixels >= 32 {
+        // Load the BGRA buffers.
+        let mut in0 = _mm_loadu_si128(in_.offset(0));
+        let mut in1 = _mm_loadu_si128(in_.offset(1));
+        let mut in2 = _mm_loadu_si128(in_.offset(2));
+        let mut in3 = _mm_loadu_si128(in_.offset(3));
+        let mut in4 = _mm_loadu_si128(in_.offset(4));
+        let mut in5 = _mm_loadu_si128(in_.offset(5));
+        let mut in6 = _mm_loadu_si128(in_.offset(6));
+        let mut in7 = _mm_loadu_si128(in_.offset(7));
+        VP8L32bToPlanar_SSE2(&mut in0, &mut in1, &mut in2, &mut in3);
+        VP8L32bToPlanar_SSE2(&mut in4, &mut in5, &mut in6, &mut in7);
+        // At this points, in1/in5 contains red only, in2/in6 green only ...
+        // Pack the colors in 24b RGB.
+        let mut rgb = [in1, in5, in2, in6, in3, in7];
+        VP8PlanarTo24b_SSE2(&mut rgb);
+        for (k, &v) in rgb.iter().enumerate() {
+            _mm_storeu_si128(out.add(k), v);
+        }
+        in_ = in_.offset(8);
+        out = out.offset(6);
+        num_pixels -= 32;
+    }
+    // left-overs
+    if num_pixels > 0 {
+        VP8LConvertBGRAToRGB_C(in_ as *const u32, num_pixels, out as *mut u8);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn ConvertBGRAToRGBA_SSE2(
+    src: *const u32,
+    mut num_pixels: c_int,
+    dst: *mut u8,
+) {
+    let red_blue_mask = _mm_set1_epi32(0x00ff00ff);
+    let mut in_ = src as *const __m128i;
+    let mut out = dst as *mut __m128i;
+    while num_pixels >= 8 {
+        let A1 = _mm_loadu_si128(in_);
+        let A2 = _mm_loadu_si128(in_.offset(1));
+        in_ = in_.offset(2);
+        let B1 = _mm_and_si128(A1, red_blue_mask); // R 0 B 0
+        let B2 = _mm_and_si128(A2, red_blue_mask); // R 0 B 0
+        let C1 = _mm_andnot_si128(red_blue_mask, A1); // 0 G 0 A
+        let C2 = _mm_andnot_si128(red_blue_mask, A2); // 0 G 0 A
+        let D1 = _mm_shufflelo_epi16(B1, 0xb1 /* _MM_SHUFFLE(2, 3, 0, 1) */);
+        let D2 = _mm_shufflelo_epi16(B2, 0xb1 /* _MM_SHUFFLE(2, 3, 0, 1) */);
+        let E1 = _mm_shufflehi_epi16(D1, 0xb1 /* _MM_SHUFFLE(2, 3, 0, 1) */);
+        let E2 = _mm_shufflehi_epi16(D2, 0xb1 /* _MM_SHUFFLE(2, 3, 0, 1) */);
+        let F1 = _mm_or_si128(E1, C1);
+        let F2 = _mm_or_si128(E2, C2);
+        _mm_storeu_si128(out, F1);
+        _mm_storeu_si128(out.offset(1), F2);
+        out = out.offset(2);
+        num_pixels -= 8;
+    }
+    // left-overs
+    if num_pixels > 0 {
+        VP8LConvertBGRAToRGBA_C(in_ as *const u32, num_pixels, out as *mut u8);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn ConvertBGRAToRGBA4444_SSE2(
+    src: *const u32,
+    mut num_pixels: c_int,
+    dst: *mut u8,
+) {
+    let mask_0x0f = _mm_set1_epi8(0x0f);
+    let mask_0xf0 = _mm_set1_epi8(0xf0u8 as i8);
+    let mut in_ = src as *const __m128i;
+    let mut out = dst as *mut __m128i;
+    while num_pixels >= 8 {
+        let bgra0 = _mm_loadu_si128(in_); // bgra0|bgra1|bgra2|bgra3
+        let bgra4 = _mm_loadu_si128(in_.offset(1)); // bgra4|bgra5|bgra6|bgra7
+        in_ = in_.offset(2);
+        let v0l = _mm_unpacklo_epi8(bgra0, bgra4); // b0b4g0g4r0r4a0a4...
+        let v0h = _mm_unpackhi_epi8(bgra0, bgra4); // b2b6g2g6r2r6a2a6...
+        let v1l = _mm_unpacklo_epi8(v0l, v0h); // b0b2b4b6g0g2g4g6...
+        let v1h = _mm_unpackhi_epi8(v0l, v0h); // b1b3b5b7g1g3g5g7...
+        let v2l = _mm_unpacklo_epi8(v1l, v1h); // b0...b7 | g0...g7
+        let v2h = _mm_unpackhi_epi8(v1l, v1h); // r0...r7 | a0...a7
+        let ga0 = _mm_unpackhi_epi64(v2l, v2h); // g0...g7 | a0...a7
+        let rb0 = _mm_unpacklo_epi64(v2h, v2l); // r0...r7 | b0...b7
+        let ga1 = _mm_srli_epi16(ga0, 4); // g0-|g1-|...|a6-|a7-
+        let rb1 = _mm_and_si128(rb0, mask_0xf0); // -r0|-r1|...|-b6|-a7
+        let ga2 = _mm_and_si128(ga1, mask_0x0f); // g0-|g1-|...|a6-|a7-
+        let rgba0 = _mm_or_si128(ga2, rb1); // rg0..rg7 | ba0..ba7
+        let rgba1 = _mm_srli_si128(rgba0, 8); // ba0..ba7 | 0
+        let rgba = if WEBP_SWAP_16BIT_CSP == 1 {
+            _mm_unpacklo_epi8(rgba1, rgba0) // barg0...barg7
+        } else {
+            _mm_unpacklo_epi8(rgba0, rgba1) // rgba0...rgba7
+        };
+        _mm_storeu_si128(out, rgba);
+        out = out.offset(1);
+        num_pixels -= 8;
+    }
+    // left-overs
+    if num_pixels > 0 {
+        VP8LConvertBGRAToRGBA4444_C(in_ as *const u32, num_pixels, out as *mut u8);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn ConvertBGRAToRGB565_SSE2(
+    src: *const u32,
+    mut num_pixels: c_int,
+    dst: *mut u8,
+) {
+    let mask_0xe0 = _mm_set1_epi8(0xe0u8 as i8);
+    let mask_0xf8 = _mm_set1_epi8(0xf8u8 as i8);
+    let mask_0x07 = _mm_set1_epi8(0x07);
+    let mut in_ = src as *const __m128i;
+    let mut out = dst as *mut __m128i;
+    while num_pixels >= 8 {
+        let bgra0 = _mm_loadu_si128(in_); // bgra0|bgra1|bgra2|bgra3
+        let bgra4 = _mm_loadu_si128(in_.offset(1)); // bgra4|bgra5|bgra6|bgra7
+        in_ = in_.offset(2);
+        let v0l = _mm_unpacklo_epi8(bgra0, bgra4); // b0b4g0g4r0r4a0a4...
+        let v0h = _mm_unpackhi_epi8(bgra0, bgra4); // b2b6g2g6r2r6a2a6...
+        let v1l = _mm_unpacklo_epi8(v0l, v0h); // b0b2b4b6g0g2g4g6...
+        let v1h = _mm_unpackhi_epi8(v0l, v0h); // b1b3b5b7g1g3g5g7...
+        let v2l = _mm_unpacklo_epi8(v1l, v1h); // b0...b7 | g0...g7
+        let v2h = _mm_unpackhi_epi8(v1l, v1h); // r0...r7 | a0...a7
+        let ga0 = _mm_unpackhi_epi64(v2l, v2h); // g0...g7 | a0...a7
+        let rb0 = _mm_unpacklo_epi64(v2h, v2l); // r0...r7 | b0...b7
+        let rb1 = _mm_and_si128(rb0, mask_0xf8); // -r0..-r7|-b0..-b7
+        let g_lo1 = _mm_srli_epi16(ga0, 5);
+        let g_lo2 = _mm_and_si128(g_lo1, mask_0x07); // g0-...g7-|xx (3b)
+        let g_hi1 = _mm_slli_epi16(ga0, 3);
+        let g_hi2 = _mm_and_si128(g_hi1, mask_0xe0); // -g0...-g7|xx (3b)
+        let b0 = _mm_srli_si128(rb1, 8); // -b0...-b7|0
+        let rg1 = _mm_or_si128(rb1, g_lo2); // gr0...gr7|xx
+        let b1 = _mm_srli_epi16(b0, 3);
+        let gb1 = _mm_or_si128(b1, g_hi2); // bg0...bg7|xx
+        let rgba = if WEBP_SWAP_16BIT_CSP == 1 {
+            _mm_unpacklo_epi8(gb1, rg1) // rggb0...rggb7
+        } else {
+            _mm_unpacklo_epi8(rg1, gb1) // bgrb0...bgrb7
+        };
+        _mm_storeu_si128(out, rgba);
+        out = out.offset(1);
+        num_pixels -= 8;
+    }
+    // left-overs
+    if num_pixels > 0 {
+        VP8LConvertBGRAToRGB565_C(in_ as *const u32, num_pixels, out as *mut u8);
+    }
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe extern "C" fn ConvertBGRAToBGR_SSE2(
+    src: *const u32,
+    mut num_pixels: c_int,
+    mut dst: *mut u8,
+) {
+    let mask_l = _mm_set_epi32(0, 0x00ffffff, 0, 0x00ffffff);
+    let mask_h = _mm_set_epi32(0x00ffffff, 0, 0x00ffffff, 0);
+    let mut in_ = src as *const __m128i;
+    let end: *const u8 = dst.offset(num_pixels as isize * 3);
+    // the last storel_epi64 below writes 8 bytes starting at offset 18
+    while dst.offset(26) as *const u8 <= end {
+        let bgra0 = _mm_loadu_si128(in_); // bgra0|bgra1|bgra2|bgra3
+        let bgra4 = _mm_loadu_si128(in_.offset(1)); // bgra4|bgra5|bgra6|bgra7
+        in_ = in_.offset(2);
+        let a0l = _mm_and_si128(bgra0, mask_l); // bgr0|0|bgr0|0
+        let a4l = _mm_and_si128(bgra4, mask_l); // bgr0|0|bgr0|0
+        let a0h = _mm_and_si128(bgra0, mask_h); // 0|bgr0|0|bgr0
+        let a4h = _mm_and_si128(bgra4, mask_h); // 0|bgr0|0|bgr0
+        let b0h = _mm_srli_epi64(a0h, 8); // 000b|gr00|000b|gr00
+        let b4h = _mm_srli_epi64(a4h, 8); // 000b|gr00|000b|gr00
+        let c0 = _mm_or_si128(a0l, b0h); // rgbrgb00|rgbrgb00
+        let c4 = _mm_or_si128(a4l, b4h); // rgbrgb00|rgbrgb00
+        let c2 = _mm_srli_si128(c0, 8);
+        let c6 = _mm_srli_si128(c4, 8);
+        _mm_storel_epi64(dst.offset(0) as *mut __m128i, c0);
+        _mm_storel_epi64(dst.offset(6) as *mut __m128i, c2);
+        _mm_storel_epi64(dst.offset(12) as *mut __m128i, c4);
+        _mm_storel_epi64(dst.offset(18) as *mut __m128i, c6);
+        dst = dst.offset(24);
+        num_pixels -= 8;
+    }
+    // left-overs
+    if num_pixels > 0 {
+        VP8LConvertBGRAToBGR_C(in_ as *const u32, num_pixels, dst);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LDspInitSSE2() {
+    VP8LPredictors[5] = Some(Predictor5_SSE2);
+    VP8LPredictors[6] = Some(Predictor6_SSE2);
+    VP8LPredictors[7] = Some(Predictor7_SSE2);
+    VP8LPredictors[8] = Some(Predictor8_SSE2);
+    VP8LPredictors[9] = Some(Predictor9_SSE2);
+    VP8LPredictors[10] = Some(Predictor10_SSE2);
+    VP8LPredictors[11] = Some(Predictor11_SSE2);
+    VP8LPredictors[12] = Some(Predictor12_SSE2);
+    VP8LPredictors[13] = Some(Predictor13_SSE2);
+
+    VP8LPredictorsAdd[0] = Some(PredictorAdd0_SSE2);
+    VP8LPredictorsAdd[1] = Some(PredictorAdd1_SSE2);
+    VP8LPredictorsAdd[2] = Some(PredictorAdd2_SSE2);
+    VP8LPredictorsAdd[3] = Some(PredictorAdd3_SSE2);
+    VP8LPredictorsAdd[4] = Some(PredictorAdd4_SSE2);
+    VP8LPredictorsAdd[5] = Some(PredictorAdd5_SSE2);
+    VP8LPredictorsAdd[6] = Some(PredictorAdd6_SSE2);
+    VP8LPredictorsAdd[7] = Some(PredictorAdd7_SSE2);
+    VP8LPredictorsAdd[8] = Some(PredictorAdd8_SSE2);
+    VP8LPredictorsAdd[9] = Some(PredictorAdd9_SSE2);
+    VP8LPredictorsAdd[10] = Some(PredictorAdd10_SSE2);
+    VP8LPredictorsAdd[11] = Some(PredictorAdd11_SSE2);
+    VP8LPredictorsAdd[12] = Some(PredictorAdd12_SSE2);
+    VP8LPredictorsAdd[13] = Some(PredictorAdd13_SSE2);
+
+    VP8LAddGreenToBlueAndRed = Some(AddGreenToBlueAndRed_SSE2);
+    VP8LTransformColorInverse = Some(TransformColorInverse_SSE2);
+
+    VP8LConvertBGRAToRGB = Some(ConvertBGRAToRGB_SSE2);
+    VP8LConvertBGRAToRGBA = Some(ConvertBGRAToRGBA_SSE2);
+    VP8LConvertBGRAToRGBA4444 = Some(ConvertBGRAToRGBA4444_SSE2);
+    VP8LConvertBGRAToRGB565 = Some(ConvertBGRAToRGB565_SSE2);
+    VP8LConvertBGRAToBGR = Some(ConvertBGRAToBGR_SSE2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dsp::lossless::{
+        VP8LDspInit, VP8LPredictorAddSubFunc, VP8LPredictorFunc, VP8LPredictors_C,
+    };
+    use dsp::lossless_avx2::{AddGreenToBlueAndRed_AVX2, TransformColorInverse_AVX2};
+    use test_util::Lcg;
+
+    type Transform = unsafe extern "C" fn(*const u32, c_int, *mut u32);
+    type ColorTransform = unsafe extern "C" fn(*const VP8LMultipliers, *const u32, c_int, *mut u32);
+    type Convert = unsafe extern "C" fn(*const u32, c_int, *mut u8);
+
+    // Random pixels, biased towards extreme channel values.
+    fn pixels(rng: &mut Lcg, len: usize) -> Vec<u32> {
+        (0..len)
+            .map(|_| {
+                let argb = (rng.next() << 8) ^ rng.next();
+                match rng.next() % 4 {
+                    0 => argb | 0xf0f0f0f0,
+                    1 => argb & 0x0f0f0f0f,
+                    _ => argb,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lossless_dsp_simd() {
+        if !is_x86_feature_detected!("sse2") {
+            return;
+        }
+        // Fills in the scalar tables.
+        unsafe {
+            VP8LDspInit();
+        }
+        let predictors: [(usize, VP8LPredictorFunc); 9] = [
+            (5, Some(Predictor5_SSE2)),
+            (6, Some(Predictor6_SSE2)),
+            (7, Some(Predictor7_SSE2)),
+            (8, Some(Predictor8_SSE2)),
+            (9, Some(Predictor9_SSE2)),
+            (10, Some(Predictor10_SSE2)),
+            (11, Some(Predictor11_SSE2)),
+            (12, Some(Predictor12_SSE2)),
+            (13, Some(Predictor13_SSE2)),
+        ];
+        let predictors_add: [VP8LPredictorAddSubFunc; 14] = [
+            Some(PredictorAdd0_SSE2),
+            Some(PredictorAdd1_SSE2),
+            Some(PredictorAdd2_SSE2),
+            Some(PredictorAdd3_SSE2),
+            Some(PredictorAdd4_SSE2),
+            Some(PredictorAdd5_SSE2),
+            Some(PredictorAdd6_SSE2),
+            Some(PredictorAdd7_SSE2),
+            Some(PredictorAdd8_SSE2),
+            Some(PredictorAdd9_SSE2),
+            Some(PredictorAdd10_SSE2),
+            Some(PredictorAdd11_SSE2),
+            Some(PredictorAdd12_SSE2),
+            Some(PredictorAdd13_SSE2),
+        ];
+        let mut transforms: Vec<(Transform, Transform)> =
+            vec![(AddGreenToBlueAndRed_SSE2, VP8LAddGreenToBlueAndRed_C)];
+        let mut color_transforms: Vec<(ColorTransform, ColorTransform)> =
+            vec![(TransformColorInverse_SSE2, VP8LTransformColorInverse_C)];
+        if is_x86_feature_detected!("avx2") {
+            transforms.push((AddGreenToBlueAndRed_AVX2, VP8LAddGreenToBlueAndRed_C));
+            color_transforms.push((TransformColorInverse_AVX2, VP8LTransformColorInverse_C));
+        }
+        let converters: [(Convert, Convert, usize); 5] = [
+            (ConvertBGRAToRGB_SSE2, VP8LConvertBGRAToRGB_C, 3),
+            (ConvertBGRAToRGBA_SSE2, VP8LConvertBGRAToRGBA_C, 4),
+            (ConvertBGRAToRGBA4444_SSE2, VP8LConvertBGRAToRGBA4444_C, 2),
+            (ConvertBGRAToRGB565_SSE2, VP8LConvertBGRAToRGB565_C, 2),
+            (ConvertBGRAToBGR_SSE2, VP8LConvertBGRAToBGR_C, 3),
+        ];
+
+        let mut rng = Lcg(11);
+        for _ in 0..1000 {
+            let top = pixels(&mut rng, 3);
+            let left = pixels(&mut rng, 1)[0];
+            for &(mode, pred) in &predictors {
+                unsafe {
+                    assert_eq!(
+                        pred.unwrap()(left, top.as_ptr().offset(1)),
+                        VP8LPredictors_C[mode].unwrap()(left, top.as_ptr().offset(1)),
+                        "mode = {}",
+                        mode
+                    );
+                }
+            }
+        }
+        // Lengths around the 4- and 8-pixel blocks, so that the leftovers
+        // are covered as well.
+        for num_pixels in 1..40 {
+            let in_ = pixels(&mut rng, num_pixels);
+            let upper = pixels(&mut rng, num_pixels + 2);
+            let out_init = pixels(&mut rng, 1)[0];
+            for (mode, &pred_add) in predictors_add.iter().enumerate() {
+                let mut out = vec![out_init; num_pixels + 1];
+                let mut out_c = out.clone();
+                unsafe {
+                    pred_add.unwrap()(
+                        in_.as_ptr(),
+                        upper.as_ptr().offset(1),
+                        num_pixels as c_int,
+                        out.as_mut_ptr().offset(1),
+                    );
+                    VP8LPredictorsAdd_C[mode].unwrap()(
+                        in_.as_ptr(),
+                        upper.as_ptr().offset(1),
+                        num_pixels as c_int,
+                        out_c.as_mut_ptr().offset(1),
+                    );
+                }
+                assert_eq!(out, out_c, "mode = {}", mode);
+            }
+
+            for &(transform, transform_c) in &transforms {
+                let mut dst = vec![0; num_pixels];
+                let mut dst_c = vec![0; num_pixels];
+                unsafe {
+                    transform(in_.as_ptr(), num_pixels as c_int, dst.as_mut_ptr());
+                    transform_c(in_.as_ptr(), num_pixels as c_int, dst_c.as_mut_ptr());
+                }
+                assert_eq!(dst, dst_c);
+            }
+            for _ in 0..10 {
+                let m = VP8LMultipliers {
+                    green_to_red_: rng.next() as u8,
+                    green_to_blue_: rng.next() as u8,
+                    red_to_blue_: rng.next() as u8,
+                };
+                for &(transform, transform_c) in &color_transforms {
+                    let mut dst = vec![0; num_pixels];
+                    let mut dst_c = vec![0; num_pixels];
+                    unsafe {
+                        transform(&m, in_.as_ptr(), num_pixels as c_int, dst.as_mut_ptr());
+                        transform_c(&m, in_.as_ptr(), num_pixels as c_int, dst_c.as_mut_ptr());
+                    }
+                    assert_eq!(dst, dst_c);
+                }
+            }
+
+            for &(convert, convert_c, bpp) in &converters {
+                let mut dst = vec![0; num_pixels * bpp];
+                let mut dst_c = vec![0; num_pixels * bpp];
+                unsafe {
+                    convert(in_.as_ptr(), num_pixels as c_int, dst.as_mut_ptr());
+                    convert_c(in_.as_ptr(), num_pixels as c_int, dst_c.as_mut_ptr());
+                }
+                assert_eq!(dst, dst_c);
+            }
+        }
+    }
+}
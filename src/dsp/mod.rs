@@ -1,4 +1,60 @@
+// Declared first, so that its macros are visible in the other modules.
 #[doc(hidden)]
+#[macro_use]
 pub mod dsp;
+
+#[doc(hidden)]
+pub mod alpha_processing;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod common_sse2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod common_sse41;
+#[doc(hidden)]
+pub mod cpu;
+#[doc(hidden)]
+pub mod dec;
+#[doc(hidden)]
+pub mod dec_clip_tables;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[doc(hidden)]
+pub mod dec_sse2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[doc(hidden)]
+pub mod dec_sse41;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod enc;
 #[doc(hidden)]
 pub mod filters;
+// Declared before lossless, so that its macros are visible there.
+#[doc(hidden)]
+#[macro_use]
+pub mod lossless_common;
+#[doc(hidden)]
+pub mod lossless;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod lossless_avx2;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod lossless_enc;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[doc(hidden)]
+pub mod lossless_sse2;
+#[doc(hidden)]
+pub mod rescaler;
+#[doc(hidden)]
+pub mod upsampling;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+// Declared before upsampling_sse41, so that its macros are visible there.
+#[doc(hidden)]
+#[macro_use]
+pub mod upsampling_sse2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[doc(hidden)]
+pub mod upsampling_sse41;
+#[doc(hidden)]
+pub mod yuv;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod yuv_sse2;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod yuv_sse41;
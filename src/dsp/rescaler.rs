@@ -0,0 +1,430 @@
+// Copyright 2014 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Rescaling functions
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+
+#[cfg(not(any(target_arch = "aarch64", feature = "pure-rust-decoder")))]
+use dsp::cpu::VP8GetCPUInfo;
+#[cfg(not(any(target_arch = "aarch64", feature = "pure-rust-decoder")))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::{WebPRescalerExportRowFunc, WebPRescalerImportRowFunc};
+use utils::rescaler_utils::{
+    WebPRescaler, WebPRescalerInputDone, WebPRescalerOutputDone, WEBP_RESCALER_FRAC,
+    WEBP_RESCALER_ONE, WEBP_RESCALER_RFIX,
+};
+
+//------------------------------------------------------------------------------
+// Implementations of critical functions ImportRow / ExportRow
+
+const ROUNDER: u64 = WEBP_RESCALER_ONE >> 1;
+
+#[allow(non_snake_case)]
+#[inline]
+fn MULT_FIX(x: u32, y: u32) -> u32 {
+    ((x as u64 * y as u64 + ROUNDER) >> WEBP_RESCALER_RFIX) as u32
+}
+
+//------------------------------------------------------------------------------
+// Row import
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerImportRowExpand_C(wrk: *mut WebPRescaler, src: *const u8) {
+    let wrk = &mut *wrk;
+    let x_stride = wrk.num_channels;
+    let x_out_max = wrk.dst_width * wrk.num_channels;
+    c_assert!(WebPRescalerInputDone(wrk) == 0);
+    c_assert!(wrk.x_expand != 0);
+    for channel in 0..x_stride {
+        let mut x_in = channel;
+        let mut x_out = channel;
+        // simple bilinear interpolation
+        let mut accum = wrk.x_add;
+        let mut left = *src.offset(x_in as isize) as c_int;
+        let mut right = if wrk.src_width > 1 {
+            *src.offset((x_in + x_stride) as isize) as c_int
+        } else {
+            left
+        };
+        x_in += x_stride;
+        loop {
+            *wrk.frow.offset(x_out as isize) = (right * wrk.x_add + (left - right) * accum) as u32;
+            x_out += x_stride;
+            if x_out >= x_out_max {
+                break;
+            }
+            accum -= wrk.x_sub;
+            if accum < 0 {
+                left = right;
+                x_in += x_stride;
+                c_assert!(x_in < wrk.src_width * x_stride);
+                right = *src.offset(x_in as isize) as c_int;
+                accum += wrk.x_add;
+            }
+        }
+        c_assert!(wrk.x_sub == 0 /* <- special case for src_width=1 */ || accum == 0);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerImportRowShrink_C(wrk: *mut WebPRescaler, src: *const u8) {
+    let wrk = &mut *wrk;
+    let x_stride = wrk.num_channels;
+    let x_out_max = wrk.dst_width * wrk.num_channels;
+    c_assert!(WebPRescalerInputDone(wrk) == 0);
+    c_assert!(wrk.x_expand == 0);
+    for channel in 0..x_stride {
+        let mut x_in = channel;
+        let mut x_out = channel;
+        let mut sum: u32 = 0;
+        let mut accum: c_int = 0;
+        while x_out < x_out_max {
+            let mut base: u32 = 0;
+            accum += wrk.x_add;
+            while accum > 0 {
+                accum -= wrk.x_sub;
+                c_assert!(x_in < wrk.src_width * x_stride);
+                base = *src.offset(x_in as isize) as u32;
+                sum += base;
+                x_in += x_stride;
+            }
+            {
+                // Emit next horizontal pixel.
+                let frac = base.wrapping_mul(accum.wrapping_neg() as u32);
+                *wrk.frow.offset(x_out as isize) =
+                    sum.wrapping_mul(wrk.x_sub as u32).wrapping_sub(frac);
+                // fresh fractional start for next pixel
+                sum = MULT_FIX(frac, wrk.fx_scale);
+            }
+            x_out += x_stride;
+        }
+        c_assert!(accum == 0);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Row export
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerExportRowExpand_C(wrk: *mut WebPRescaler) {
+    let wrk = &mut *wrk;
+    let dst = wrk.dst;
+    let irow = wrk.irow;
+    let x_out_max = wrk.dst_width * wrk.num_channels;
+    let frow = wrk.frow;
+    c_assert!(WebPRescalerOutputDone(wrk) == 0);
+    c_assert!(wrk.y_accum <= 0);
+    c_assert!(wrk.y_expand != 0);
+    c_assert!(wrk.y_sub != 0);
+    if wrk.y_accum == 0 {
+        for x_out in 0..x_out_max as isize {
+            let J = *frow.offset(x_out);
+            let v = MULT_FIX(J, wrk.fy_scale);
+            c_assert!(v <= 255);
+            *dst.offset(x_out) = v as u8;
+        }
+    } else {
+        let B = WEBP_RESCALER_FRAC(-wrk.y_accum as u64, wrk.y_sub as u64);
+        let A = (WEBP_RESCALER_ONE - B as u64) as u32;
+        for x_out in 0..x_out_max as isize {
+            let I = A as u64 * *frow.offset(x_out) as u64 + B as u64 * *irow.offset(x_out) as u64;
+            let J = ((I + ROUNDER) >> WEBP_RESCALER_RFIX) as u32;
+            let v = MULT_FIX(J, wrk.fy_scale);
+            c_assert!(v <= 255);
+            *dst.offset(x_out) = v as u8;
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerExportRowShrink_C(wrk: *mut WebPRescaler) {
+    let wrk = &mut *wrk;
+    let dst = wrk.dst;
+    let irow = wrk.irow;
+    let x_out_max = wrk.dst_width * wrk.num_channels;
+    let frow = wrk.frow;
+    let yscale = wrk.fy_scale.wrapping_mul(wrk.y_accum.wrapping_neg() as u32);
+    c_assert!(WebPRescalerOutputDone(wrk) == 0);
+    c_assert!(wrk.y_accum <= 0);
+    c_assert!(wrk.y_expand == 0);
+    if yscale != 0 {
+        for x_out in 0..x_out_max as isize {
+            let frac = MULT_FIX(*frow.offset(x_out), yscale);
+            let v = MULT_FIX((*irow.offset(x_out)).wrapping_sub(frac), wrk.fxy_scale);
+            c_assert!(v <= 255);
+            *dst.offset(x_out) = v as u8;
+            *irow.offset(x_out) = frac; // new fractional start
+        }
+    } else {
+        for x_out in 0..x_out_max as isize {
+            let v = MULT_FIX(*irow.offset(x_out), wrk.fxy_scale);
+            c_assert!(v <= 255);
+            *dst.offset(x_out) = v as u8;
+            *irow.offset(x_out) = 0;
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Main entry calls
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerImportRow(wrk: *mut WebPRescaler, src: *const u8) {
+    c_assert!(WebPRescalerInputDone(&*wrk) == 0);
+    if (*wrk).x_expand == 0 {
+        WebPRescalerImportRowShrink.unwrap()(wrk, src);
+    } else {
+        WebPRescalerImportRowExpand.unwrap()(wrk, src);
+    }
+}
+
+// Export one row (starting at x_out position) from rescaler.
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerExportRow(wrk: *mut WebPRescaler) {
+    let wrk = &mut *wrk;
+    if wrk.y_accum <= 0 {
+        c_assert!(WebPRescalerOutputDone(wrk) == 0);
+        if wrk.y_expand != 0 {
+            WebPRescalerExportRowExpand.unwrap()(wrk);
+        } else if wrk.fxy_scale != 0 {
+            WebPRescalerExportRowShrink.unwrap()(wrk);
+        } else {
+            // special case
+            c_assert!(wrk.src_height == wrk.dst_height && wrk.x_add == 1);
+            c_assert!(wrk.src_width == 1 && wrk.dst_width <= 2);
+            for i in 0..(wrk.num_channels * wrk.dst_width) as isize {
+                *wrk.dst.offset(i) = *wrk.irow.offset(i) as u8;
+                *wrk.irow.offset(i) = 0;
+            }
+        }
+        wrk.y_accum += wrk.y_add;
+        wrk.dst = wrk.dst.offset(wrk.dst_stride as isize);
+        wrk.dst_y += 1;
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub static mut WebPRescalerImportRowExpand: WebPRescalerImportRowFunc = None;
+#[no_mangle]
+pub static mut WebPRescalerImportRowShrink: WebPRescalerImportRowFunc = None;
+
+#[no_mangle]
+pub static mut WebPRescalerExportRowExpand: WebPRescalerExportRowFunc = None;
+#[no_mangle]
+pub static mut WebPRescalerExportRowShrink: WebPRescalerExportRowFunc = None;
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPRescalerDspInitSSE2();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPRescalerDspInitMIPS32();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPRescalerDspInitMIPSdspR2();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPRescalerDspInitMSA();
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn WebPRescalerDspInitNEON();
+}
+
+webp_dsp_init_func! {
+    // Must be called first before using the above.
+    #[no_mangle]
+    pub fn WebPRescalerDspInit() {
+        WebPRescalerExportRowExpand = Some(WebPRescalerExportRowExpand_C);
+        WebPRescalerExportRowShrink = Some(WebPRescalerExportRowShrink_C);
+
+        WebPRescalerImportRowExpand = Some(WebPRescalerImportRowExpand_C);
+        WebPRescalerImportRowShrink = Some(WebPRescalerImportRowShrink_C);
+
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    WebPRescalerDspInitSSE2();
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPS32) != 0 {
+                    WebPRescalerDspInitMIPS32();
+                }
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    WebPRescalerDspInitMIPSdspR2();
+                }
+                if get_cpu_info(kMSA) != 0 {
+                    WebPRescalerDspInitMSA();
+                }
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "pure-rust-decoder")))]
+        {
+            WebPRescalerDspInitNEON();
+        }
+        #[cfg(all(target_arch = "arm", not(feature = "pure-rust-decoder")))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    WebPRescalerDspInitNEON();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem;
+
+    use test_util::{check_parity, Digest, Lcg};
+    use utils::rescaler_utils::{rescaler_t, WebPRescalerHasPendingOutput, WebPRescalerInit};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_WebPRescalerImportRowExpand_C(wrk: *mut WebPRescaler, src: *const u8);
+        fn WebPRef_WebPRescalerImportRowShrink_C(wrk: *mut WebPRescaler, src: *const u8);
+        fn WebPRef_WebPRescalerExportRowExpand_C(wrk: *mut WebPRescaler);
+        fn WebPRef_WebPRescalerExportRowShrink_C(wrk: *mut WebPRescaler);
+    }
+
+    type Kernels = (
+        unsafe extern "C" fn(*mut WebPRescaler, *const u8),
+        unsafe extern "C" fn(*mut WebPRescaler, *const u8),
+        unsafe extern "C" fn(*mut WebPRescaler),
+        unsafe extern "C" fn(*mut WebPRescaler),
+    );
+
+    // Rescales a plane with the given import/export row kernels, the way
+    // WebPRescalerImport() and WebPRescalerExport() drive them.
+    unsafe fn rescale(
+        kernels: Kernels,
+        src: &[u8],
+        src_size: (c_int, c_int),
+        dst_size: (c_int, c_int),
+        num_channels: c_int,
+    ) -> Vec<u8> {
+        let (import_expand, import_shrink, export_expand, export_shrink) = kernels;
+        let (src_width, src_height) = src_size;
+        let (dst_width, dst_height) = dst_size;
+        let stride = src_width * num_channels;
+        let dst_stride = dst_width * num_channels;
+        let mut dst = vec![0; (dst_stride * dst_height) as usize];
+        let mut work: Vec<rescaler_t> = vec![0; 2 * dst_stride as usize];
+        let mut wrk: WebPRescaler = mem::zeroed();
+        WebPRescalerInit(
+            &mut wrk,
+            src_width,
+            src_height,
+            dst.as_mut_ptr(),
+            dst_width,
+            dst_height,
+            dst_stride,
+            num_channels,
+            work.as_mut_ptr(),
+        );
+        for y in 0..src_height {
+            if wrk.y_expand != 0 {
+                mem::swap(&mut wrk.irow, &mut wrk.frow);
+            }
+            let row = src.as_ptr().offset((y * stride) as isize);
+            if wrk.x_expand != 0 {
+                import_expand(&mut wrk, row);
+            } else {
+                import_shrink(&mut wrk, row);
+            }
+            if wrk.y_expand == 0 {
+                for x in 0..dst_stride as isize {
+                    *wrk.irow.offset(x) = (*wrk.irow.offset(x)).wrapping_add(*wrk.frow.offset(x));
+                }
+            }
+            wrk.src_y += 1;
+            wrk.y_accum -= wrk.y_sub;
+            while WebPRescalerHasPendingOutput(&wrk) != 0 {
+                if wrk.y_expand != 0 {
+                    export_expand(&mut wrk);
+                } else if wrk.fxy_scale != 0 {
+                    export_shrink(&mut wrk);
+                } else {
+                    for x in 0..dst_stride as isize {
+                        *wrk.dst.offset(x) = *wrk.irow.offset(x) as u8;
+                        *wrk.irow.offset(x) = 0;
+                    }
+                }
+                wrk.y_accum += wrk.y_add;
+                wrk.dst = wrk.dst.offset(wrk.dst_stride as isize);
+                wrk.dst_y += 1;
+            }
+        }
+        assert_eq!(wrk.dst_y, dst_height);
+        dst
+    }
+
+    #[test]
+    fn test_rescaler_rows_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(0x7e5c);
+        let sizes = [(1, 1), (1, 9), (2, 3), (7, 7), (16, 5), (33, 21)];
+        for &num_channels in &[1, 3, 4] {
+            for &src_size in &sizes {
+                let src = rng.bytes((src_size.0 * src_size.1 * num_channels) as usize);
+                for &dst_size in &sizes {
+                    unsafe {
+                        let out = rescale(
+                            (
+                                WebPRescalerImportRowExpand_C,
+                                WebPRescalerImportRowShrink_C,
+                                WebPRescalerExportRowExpand_C,
+                                WebPRescalerExportRowShrink_C,
+                            ),
+                            &src,
+                            src_size,
+                            dst_size,
+                            num_channels,
+                        );
+                        digest.update(&out);
+                        #[cfg(feature = "c-reference")]
+                        let out_ref = rescale(
+                            (
+                                WebPRef_WebPRescalerImportRowExpand_C,
+                                WebPRef_WebPRescalerImportRowShrink_C,
+                                WebPRef_WebPRescalerExportRowExpand_C,
+                                WebPRef_WebPRescalerExportRowShrink_C,
+                            ),
+                            &src,
+                            src_size,
+                            dst_size,
+                            num_channels,
+                        );
+                        #[cfg(feature = "c-reference")]
+                        assert_eq!(out, out_ref, "{:?} -> {:?}", src_size, dst_size);
+                    }
+                }
+            }
+        }
+        check_parity("dsp::rescaler::test_rescaler_rows_parity", digest);
+    }
+}
@@ -0,0 +1,677 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// YUV to RGB upsampling functions.
+//
+// Author: somnath@google.com (Somnath Banerjee)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+
+use decode::{
+    MODE_Argb, MODE_bgrA, MODE_rgbA, MODE_rgbA_4444, MODE_ARGB, MODE_BGR, MODE_BGRA, MODE_LAST,
+    MODE_RGB, MODE_RGBA, MODE_RGBA_4444, MODE_RGB_565,
+};
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    not(any(target_arch = "aarch64", feature = "pure-rust-decoder"))
+))]
+use dsp::cpu::VP8GetCPUInfo;
+#[cfg(any(
+    target_arch = "x86",
+    target_arch = "x86_64",
+    not(any(target_arch = "aarch64", feature = "pure-rust-decoder"))
+))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::{WebPUpsampleLinePairFunc, WebPYUV444Converter};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use dsp::upsampling_sse2::{WebPInitUpsamplersSSE2, WebPInitYUV444ConvertersSSE2};
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+use dsp::upsampling_sse41::{WebPInitUpsamplersSSE41, WebPInitYUV444ConvertersSSE41};
+use dsp::yuv::{
+    VP8YuvToArgb, VP8YuvToBgr, VP8YuvToBgra, VP8YuvToRgb, VP8YuvToRgb565, VP8YuvToRgba,
+    VP8YuvToRgba4444,
+};
+
+//------------------------------------------------------------------------------
+// Fancy upsampler
+
+// Fancy upsampling functions to convert YUV to RGB
+#[no_mangle]
+pub static mut WebPUpsamplers: [WebPUpsampleLinePairFunc; MODE_LAST as usize] =
+    [None; MODE_LAST as usize];
+
+// Given samples laid out in a square as:
+//  [a b]
+//  [c d]
+// we interpolate u/v as:
+//  ([9*a + 3*b + 3*c +   d    3*a + 9*b + 3*c +   d] + [8 8]) / 16
+//  ([3*a +   b + 9*c + 3*d      a + 3*b + 3*c + 9*d]   [8 8]) / 16
+
+// We process u and v together stashed into 32bit (16bit each).
+macro_rules! load_uv {
+    ($u:expr, $v:expr) => {
+        ($u as u32) | (($v as u32) << 16)
+    };
+}
+
+macro_rules! upsample_func {
+    ($func_name:ident, $func:ident, $xstep:expr) => {
+        #[allow(non_snake_case)]
+        unsafe extern "C" fn $func_name(
+            top_y: *const u8,
+            bottom_y: *const u8,
+            top_u: *const u8,
+            top_v: *const u8,
+            cur_u: *const u8,
+            cur_v: *const u8,
+            top_dst: *mut u8,
+            bottom_dst: *mut u8,
+            len: c_int,
+        ) {
+            let last_pixel_pair = ((len - 1) >> 1) as isize;
+            let mut tl_uv: u32 = load_uv!(*top_u, *top_v); // top-left sample
+            let mut l_uv: u32 = load_uv!(*cur_u, *cur_v); // left-sample
+            c_assert!(!top_y.is_null());
+            {
+                let uv0 = (3 * tl_uv + l_uv + 0x00020002) >> 2;
+                $func(
+                    *top_y as c_int,
+                    (uv0 & 0xff) as c_int,
+                    (uv0 >> 16) as c_int,
+                    top_dst,
+                );
+            }
+            if !bottom_y.is_null() {
+                let uv0 = (3 * l_uv + tl_uv + 0x00020002) >> 2;
+                $func(
+                    *bottom_y as c_int,
+                    (uv0 & 0xff) as c_int,
+                    (uv0 >> 16) as c_int,
+                    bottom_dst,
+                );
+            }
+            for x in 1..=last_pixel_pair {
+                let t_uv: u32 = load_uv!(*top_u.offset(x), *top_v.offset(x)); // top sample
+                let uv: u32 = load_uv!(*cur_u.offset(x), *cur_v.offset(x)); // sample
+
+                // precompute invariant values associated with first and second diagonals
+                let avg = tl_uv + t_uv + l_uv + uv + 0x00080008;
+                let diag_12 = (avg + 2 * (t_uv + l_uv)) >> 3;
+                let diag_03 = (avg + 2 * (tl_uv + uv)) >> 3;
+                {
+                    let uv0 = (diag_12 + tl_uv) >> 1;
+                    let uv1 = (diag_03 + t_uv) >> 1;
+                    $func(
+                        *top_y.offset(2 * x - 1) as c_int,
+                        (uv0 & 0xff) as c_int,
+                        (uv0 >> 16) as c_int,
+                        top_dst.offset((2 * x - 1) * $xstep),
+                    );
+                    $func(
+                        *top_y.offset(2 * x) as c_int,
+                        (uv1 & 0xff) as c_int,
+                        (uv1 >> 16) as c_int,
+                        top_dst.offset((2 * x) * $xstep),
+                    );
+                }
+                if !bottom_y.is_null() {
+                    let uv0 = (diag_03 + l_uv) >> 1;
+                    let uv1 = (diag_12 + uv) >> 1;
+                    $func(
+                        *bottom_y.offset(2 * x - 1) as c_int,
+                        (uv0 & 0xff) as c_int,
+                        (uv0 >> 16) as c_int,
+                        bottom_dst.offset((2 * x - 1) * $xstep),
+                    );
+                    $func(
+                        *bottom_y.offset(2 * x) as c_int,
+                        (uv1 & 0xff) as c_int,
+                        (uv1 >> 16) as c_int,
+                        bottom_dst.offset((2 * x) * $xstep),
+                    );
+                }
+                tl_uv = t_uv;
+                l_uv = uv;
+            }
+            if (len & 1) == 0 {
+                let last = (len - 1) as isize;
+                {
+                    let uv0 = (3 * tl_uv + l_uv + 0x00020002) >> 2;
+                    $func(
+                        *top_y.offset(last) as c_int,
+                        (uv0 & 0xff) as c_int,
+                        (uv0 >> 16) as c_int,
+                        top_dst.offset(last * $xstep),
+                    );
+                }
+                if !bottom_y.is_null() {
+                    let uv0 = (3 * l_uv + tl_uv + 0x00020002) >> 2;
+                    $func(
+                        *bottom_y.offset(last) as c_int,
+                        (uv0 & 0xff) as c_int,
+                        (uv0 >> 16) as c_int,
+                        bottom_dst.offset(last * $xstep),
+                    );
+                }
+            }
+        }
+    };
+}
+
+// All variants implemented.
+upsample_func!(UpsampleRgbaLinePair_C, VP8YuvToRgba, 4);
+upsample_func!(UpsampleBgraLinePair_C, VP8YuvToBgra, 4);
+upsample_func!(UpsampleArgbLinePair_C, VP8YuvToArgb, 4);
+upsample_func!(UpsampleRgbLinePair_C, VP8YuvToRgb, 3);
+upsample_func!(UpsampleBgrLinePair_C, VP8YuvToBgr, 3);
+upsample_func!(UpsampleRgba4444LinePair_C, VP8YuvToRgba4444, 2);
+upsample_func!(UpsampleRgb565LinePair_C, VP8YuvToRgb565, 2);
+
+//------------------------------------------------------------------------------
+
+// Return a fancy upsampler for the given ordering of the output samples:
+// BGRA if alpha_is_last is true, ARGB otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn WebPGetLinePairConverter(
+    alpha_is_last: c_int,
+) -> WebPUpsampleLinePairFunc {
+    WebPInitUpsamplers();
+    WebPUpsamplers[if alpha_is_last != 0 {
+        MODE_BGRA
+    } else {
+        MODE_ARGB
+    } as usize]
+}
+
+//------------------------------------------------------------------------------
+// YUV444 converter
+
+macro_rules! yuv444_func {
+    ($func_name:ident, $func:ident, $xstep:expr) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn $func_name(
+            y: *const u8,
+            u: *const u8,
+            v: *const u8,
+            dst: *mut u8,
+            len: c_int,
+        ) {
+            for i in 0..len as isize {
+                $func(
+                    *y.offset(i) as c_int,
+                    *u.offset(i) as c_int,
+                    *v.offset(i) as c_int,
+                    dst.offset(i * $xstep),
+                );
+            }
+        }
+    };
+}
+
+yuv444_func!(WebPYuv444ToRgba_C, VP8YuvToRgba, 4);
+yuv444_func!(WebPYuv444ToBgra_C, VP8YuvToBgra, 4);
+yuv444_func!(WebPYuv444ToRgb_C, VP8YuvToRgb, 3);
+yuv444_func!(WebPYuv444ToBgr_C, VP8YuvToBgr, 3);
+yuv444_func!(WebPYuv444ToArgb_C, VP8YuvToArgb, 4);
+yuv444_func!(WebPYuv444ToRgba4444_C, VP8YuvToRgba4444, 2);
+yuv444_func!(WebPYuv444ToRgb565_C, VP8YuvToRgb565, 2);
+
+#[no_mangle]
+pub static mut WebPYUV444Converters: [WebPYUV444Converter; MODE_LAST as usize] =
+    [None; MODE_LAST as usize];
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPInitYUV444ConvertersMIPSdspR2();
+}
+
+webp_dsp_init_func! {
+    // Must be called before using WebPYUV444Converters[]
+    #[no_mangle]
+    pub fn WebPInitYUV444Converters() {
+        WebPYUV444Converters[MODE_RGBA as usize] = Some(WebPYuv444ToRgba_C);
+        WebPYUV444Converters[MODE_BGRA as usize] = Some(WebPYuv444ToBgra_C);
+        WebPYUV444Converters[MODE_RGB as usize] = Some(WebPYuv444ToRgb_C);
+        WebPYUV444Converters[MODE_BGR as usize] = Some(WebPYuv444ToBgr_C);
+        WebPYUV444Converters[MODE_ARGB as usize] = Some(WebPYuv444ToArgb_C);
+        WebPYUV444Converters[MODE_RGBA_4444 as usize] = Some(WebPYuv444ToRgba4444_C);
+        WebPYUV444Converters[MODE_RGB_565 as usize] = Some(WebPYuv444ToRgb565_C);
+        WebPYUV444Converters[MODE_rgbA as usize] = Some(WebPYuv444ToRgba_C);
+        WebPYUV444Converters[MODE_bgrA as usize] = Some(WebPYuv444ToBgra_C);
+        WebPYUV444Converters[MODE_Argb as usize] = Some(WebPYuv444ToArgb_C);
+        WebPYUV444Converters[MODE_rgbA_4444 as usize] = Some(WebPYuv444ToRgba4444_C);
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    WebPInitYUV444ConvertersSSE2();
+                }
+                if get_cpu_info(kSSE4_1) != 0 {
+                    WebPInitYUV444ConvertersSSE41();
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    WebPInitYUV444ConvertersMIPSdspR2();
+                }
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Main calls
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn WebPInitUpsamplersNEON();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPInitUpsamplersMIPSdspR2();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPInitUpsamplersMSA();
+}
+
+webp_dsp_init_func! {
+    // Must be called before using the WebPUpsamplers[] (and for premultiplied
+    // colorspaces like rgbA, rgbA4444, etc)
+    #[no_mangle]
+    pub fn WebPInitUpsamplers() {
+        WebPUpsamplers[MODE_RGBA as usize] = Some(UpsampleRgbaLinePair_C);
+        WebPUpsamplers[MODE_BGRA as usize] = Some(UpsampleBgraLinePair_C);
+        WebPUpsamplers[MODE_rgbA as usize] = Some(UpsampleRgbaLinePair_C);
+        WebPUpsamplers[MODE_bgrA as usize] = Some(UpsampleBgraLinePair_C);
+        WebPUpsamplers[MODE_RGB as usize] = Some(UpsampleRgbLinePair_C);
+        WebPUpsamplers[MODE_BGR as usize] = Some(UpsampleBgrLinePair_C);
+        WebPUpsamplers[MODE_ARGB as usize] = Some(UpsampleArgbLinePair_C);
+        WebPUpsamplers[MODE_RGBA_4444 as usize] = Some(UpsampleRgba4444LinePair_C);
+        WebPUpsamplers[MODE_RGB_565 as usize] = Some(UpsampleRgb565LinePair_C);
+        WebPUpsamplers[MODE_Argb as usize] = Some(UpsampleArgbLinePair_C);
+        WebPUpsamplers[MODE_rgbA_4444 as usize] = Some(UpsampleRgba4444LinePair_C);
+
+        // If defined, use CPUInfo() to overwrite some pointers with faster versions.
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    WebPInitUpsamplersSSE2();
+                }
+                if get_cpu_info(kSSE4_1) != 0 {
+                    WebPInitUpsamplersSSE41();
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    WebPInitUpsamplersMIPSdspR2();
+                }
+                if get_cpu_info(kMSA) != 0 {
+                    WebPInitUpsamplersMSA();
+                }
+            }
+        }
+
+        #[cfg(all(target_arch = "aarch64", not(feature = "pure-rust-decoder")))]
+        {
+            WebPInitUpsamplersNEON();
+        }
+        #[cfg(all(target_arch = "arm", not(feature = "pure-rust-decoder")))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    WebPInitUpsamplersNEON();
+                }
+            }
+        }
+
+        c_assert!(WebPUpsamplers[MODE_RGBA as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_BGRA as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_rgbA as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_bgrA as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_RGB as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_BGR as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_ARGB as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_RGBA_4444 as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_RGB_565 as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_Argb as usize].is_some());
+        c_assert!(WebPUpsamplers[MODE_rgbA_4444 as usize].is_some());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    use decode::WEBP_CSP_MODE;
+    use test_util::{bytes_per_pixel, check_parity, grid, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(non_upper_case_globals)]
+    extern "C" {
+        static WebPRef_WebPUpsamplers: [WebPUpsampleLinePairFunc; MODE_LAST as usize];
+        fn WebPRef_WebPInitUpsamplers();
+        static WebPRef_WebPYUV444Converters: [WebPYUV444Converter; MODE_LAST as usize];
+        fn WebPRef_WebPInitYUV444Converters();
+    }
+
+    #[allow(non_snake_case, non_upper_case_globals)]
+    fn upsampler_C(mode: WEBP_CSP_MODE) -> WebPUpsampleLinePairFunc {
+        match mode {
+            MODE_RGBA | MODE_rgbA => Some(UpsampleRgbaLinePair_C),
+            MODE_BGRA | MODE_bgrA => Some(UpsampleBgraLinePair_C),
+            MODE_RGB => Some(UpsampleRgbLinePair_C),
+            MODE_BGR => Some(UpsampleBgrLinePair_C),
+            MODE_ARGB | MODE_Argb => Some(UpsampleArgbLinePair_C),
+            MODE_RGBA_4444 | MODE_rgbA_4444 => Some(UpsampleRgba4444LinePair_C),
+            MODE_RGB_565 => Some(UpsampleRgb565LinePair_C),
+            _ => unreachable!(),
+        }
+    }
+
+    // Runs an upsampler on two lines of 'len' pixels, or on the top line
+    // only if 'bottom_y' is None.
+    fn upsample(
+        func: WebPUpsampleLinePairFunc,
+        mode: WEBP_CSP_MODE,
+        top_y: &[u8],
+        bottom_y: Option<&[u8]>,
+        uv: [&[u8]; 4],
+        len: usize,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let bpp = bytes_per_pixel(mode);
+        let mut top_dst = vec![0; len * bpp];
+        let mut bottom_dst = vec![0; len * bpp];
+        unsafe {
+            func.unwrap()(
+                top_y.as_ptr(),
+                bottom_y.map_or(ptr::null(), |y| y.as_ptr()),
+                uv[0].as_ptr(),
+                uv[1].as_ptr(),
+                uv[2].as_ptr(),
+                uv[3].as_ptr(),
+                top_dst.as_mut_ptr(),
+                if bottom_y.is_some() {
+                    bottom_dst.as_mut_ptr()
+                } else {
+                    ptr::null_mut()
+                },
+                len as c_int,
+            );
+        }
+        (top_dst, bottom_dst)
+    }
+
+    #[test]
+    fn test_upsamplers_parity() {
+        unsafe {
+            WebPInitUpsamplers();
+            #[cfg(feature = "c-reference")]
+            WebPRef_WebPInitUpsamplers();
+        }
+        let grid = grid();
+        // The chroma rows walk over every (u, v) pair of the grid, with the
+        // top and current rows taking opposite corners of the range.
+        let mut top_u = Vec::new();
+        let mut top_v = Vec::new();
+        for &u in &grid {
+            for &v in &grid {
+                top_u.push(u);
+                top_v.push(v);
+            }
+        }
+        let cur_u = top_u.iter().map(|&u| 255 - u).collect::<Vec<u8>>();
+        let cur_v = top_v.iter().rev().cloned().collect::<Vec<u8>>();
+        let uv = [&top_u[..], &top_v[..], &cur_u[..], &cur_v[..]];
+        let max_len = 2 * top_u.len();
+        let top_y = (0..max_len)
+            .map(|i| grid[i % grid.len()])
+            .collect::<Vec<u8>>();
+        let bottom_y = top_y.iter().rev().cloned().collect::<Vec<u8>>();
+        let mut digest = Digest::new();
+        for mode in MODE_RGB..=MODE_rgbA_4444 {
+            for &len in &[1, 2, 3, max_len - 1, max_len] {
+                for &bottom in &[Some(&bottom_y[..]), None] {
+                    let out = upsample(upsampler_C(mode), mode, &top_y, bottom, uv, len);
+                    digest.update(&out.0);
+                    digest.update(&out.1);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let ref_func = unsafe { WebPRef_WebPUpsamplers[mode as usize] };
+                        assert!(
+                            out == upsample(ref_func, mode, &top_y, bottom, uv, len),
+                            "mode = {}, len = {}",
+                            mode,
+                            len
+                        );
+                    }
+                }
+            }
+        }
+        check_parity("dsp::upsampling::test_upsamplers_parity", digest);
+    }
+
+    #[test]
+    fn test_line_pair_converter() {
+        let mut rng = Lcg(1);
+        let len = 37;
+        let top_y = rng.bytes(len);
+        let bottom_y = rng.bytes(len);
+        let chroma = (0..4)
+            .map(|_| rng.bytes((len + 1) >> 1))
+            .collect::<Vec<_>>();
+        let uv = [
+            &chroma[0][..],
+            &chroma[1][..],
+            &chroma[2][..],
+            &chroma[3][..],
+        ];
+        for &(alpha_is_last, mode) in &[(1, MODE_BGRA), (0, MODE_ARGB)] {
+            let func = unsafe { WebPGetLinePairConverter(alpha_is_last) };
+            assert!(
+                upsample(func, mode, &top_y, Some(&bottom_y), uv, len)
+                    == upsample(upsampler_C(mode), mode, &top_y, Some(&bottom_y), uv, len)
+            );
+        }
+    }
+
+    #[test]
+    fn test_yuv444_converters_parity() {
+        unsafe {
+            WebPInitYUV444Converters();
+            #[cfg(feature = "c-reference")]
+            WebPRef_WebPInitYUV444Converters();
+        }
+        let grid = grid();
+        let (mut y, mut u, mut v) = (Vec::new(), Vec::new(), Vec::new());
+        for &yy in &grid {
+            for &uu in &grid {
+                for &vv in &grid {
+                    y.push(yy);
+                    u.push(uu);
+                    v.push(vv);
+                }
+            }
+        }
+        let mut digest = Digest::new();
+        for mode in MODE_RGB..=MODE_rgbA_4444 {
+            let bpp = bytes_per_pixel(mode);
+            let mut dst = vec![0; y.len() * bpp];
+            unsafe {
+                WebPYUV444Converters[mode as usize].unwrap()(
+                    y.as_ptr(),
+                    u.as_ptr(),
+                    v.as_ptr(),
+                    dst.as_mut_ptr(),
+                    y.len() as c_int,
+                );
+            }
+            digest.update(&dst);
+            #[cfg(feature = "c-reference")]
+            {
+                let mut ref_dst = vec![0; y.len() * bpp];
+                unsafe {
+                    WebPRef_WebPYUV444Converters[mode as usize].unwrap()(
+                        y.as_ptr(),
+                        u.as_ptr(),
+                        v.as_ptr(),
+                        ref_dst.as_mut_ptr(),
+                        y.len() as c_int,
+                    );
+                }
+                assert!(dst == ref_dst, "mode = {}", mode);
+            }
+        }
+        check_parity("dsp::upsampling::test_yuv444_converters_parity", digest);
+    }
+
+    #[allow(non_snake_case, non_upper_case_globals)]
+    fn yuv444_converter_C(mode: WEBP_CSP_MODE) -> WebPYUV444Converter {
+        match mode {
+            MODE_RGBA | MODE_rgbA => Some(WebPYuv444ToRgba_C),
+            MODE_BGRA | MODE_bgrA => Some(WebPYuv444ToBgra_C),
+            MODE_RGB => Some(WebPYuv444ToRgb_C),
+            MODE_BGR => Some(WebPYuv444ToBgr_C),
+            MODE_ARGB | MODE_Argb => Some(WebPYuv444ToArgb_C),
+            MODE_RGBA_4444 | MODE_rgbA_4444 => Some(WebPYuv444ToRgba4444_C),
+            MODE_RGB_565 => Some(WebPYuv444ToRgb565_C),
+            _ => unreachable!(),
+        }
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    #[test]
+    fn test_upsamplers_simd() {
+        use dsp::upsampling_sse2::*;
+        use dsp::upsampling_sse41::*;
+
+        let mut impls: Vec<(WEBP_CSP_MODE, WebPUpsampleLinePairFunc, WebPYUV444Converter)> =
+            Vec::new();
+        if is_x86_feature_detected!("sse2") {
+            impls.extend_from_slice(&[
+                (
+                    MODE_RGBA,
+                    Some(UpsampleRgbaLinePair_SSE2),
+                    Some(Yuv444ToRgba_SSE2),
+                ),
+                (
+                    MODE_BGRA,
+                    Some(UpsampleBgraLinePair_SSE2),
+                    Some(Yuv444ToBgra_SSE2),
+                ),
+                (
+                    MODE_RGB,
+                    Some(UpsampleRgbLinePair_SSE2),
+                    Some(Yuv444ToRgb_SSE2),
+                ),
+                (
+                    MODE_BGR,
+                    Some(UpsampleBgrLinePair_SSE2),
+                    Some(Yuv444ToBgr_SSE2),
+                ),
+                (
+                    MODE_ARGB,
+                    Some(UpsampleArgbLinePair_SSE2),
+                    Some(Yuv444ToArgb_SSE2),
+                ),
+                (
+                    MODE_RGBA_4444,
+                    Some(UpsampleRgba4444LinePair_SSE2),
+                    Some(Yuv444ToRgba4444_SSE2),
+                ),
+                (
+                    MODE_RGB_565,
+                    Some(UpsampleRgb565LinePair_SSE2),
+                    Some(Yuv444ToRgb565_SSE2),
+                ),
+            ]);
+        }
+        if is_x86_feature_detected!("sse4.1") {
+            impls.extend_from_slice(&[
+                (
+                    MODE_RGB,
+                    Some(UpsampleRgbLinePair_SSE41),
+                    Some(Yuv444ToRgb_SSE41),
+                ),
+                (
+                    MODE_BGR,
+                    Some(UpsampleBgrLinePair_SSE41),
+                    Some(Yuv444ToBgr_SSE41),
+                ),
+            ]);
+        }
+
+        let mut rng = Lcg(3);
+        // Lengths around the 32- and 64-pixel blocks, so that the leftovers
+        // are covered as well.
+        for len in 1..80 {
+            let top_y = rng.bytes(len);
+            let bottom_y = rng.bytes(len);
+            let chroma = (0..4)
+                .map(|_| rng.bytes((len + 1) >> 1))
+                .collect::<Vec<_>>();
+            let uv = [
+                &chroma[0][..],
+                &chroma[1][..],
+                &chroma[2][..],
+                &chroma[3][..],
+            ];
+            for &(mode, upsampler, converter) in &impls {
+                for &bottom in &[Some(&bottom_y[..]), None] {
+                    assert!(
+                        upsample(upsampler, mode, &top_y, bottom, uv, len)
+                            == upsample(upsampler_C(mode), mode, &top_y, bottom, uv, len),
+                        "mode = {}, len = {}",
+                        mode,
+                        len
+                    );
+                }
+
+                // Full-resolution planes for the YUV444 converters.
+                let v = top_y.iter().rev().cloned().collect::<Vec<u8>>();
+                let bpp = bytes_per_pixel(mode);
+                let mut dst = vec![0; len * bpp];
+                let mut dst_c = vec![0; len * bpp];
+                unsafe {
+                    converter.unwrap()(
+                        top_y.as_ptr(),
+                        bottom_y.as_ptr(),
+                        v.as_ptr(),
+                        dst.as_mut_ptr(),
+                        len as c_int,
+                    );
+                    yuv444_converter_C(mode).unwrap()(
+                        top_y.as_ptr(),
+                        bottom_y.as_ptr(),
+                        v.as_ptr(),
+                        dst_c.as_mut_ptr(),
+                        len as c_int,
+                    );
+                }
+                assert!(dst == dst_c, "mode = {}, len = {}", mode, len);
+            }
+        }
+    }
+}
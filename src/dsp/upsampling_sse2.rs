@@ -0,0 +1,427 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE2 version of YUV to RGB upsampling functions.
+//
+// Author: somnath@google.com (Somnath Banerjee)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::os::raw::*;
+use std::ptr;
+
+use decode::{
+    MODE_Argb, MODE_bgrA, MODE_rgbA, MODE_rgbA_4444, MODE_ARGB, MODE_BGR, MODE_BGRA, MODE_RGB,
+    MODE_RGBA, MODE_RGBA_4444, MODE_RGB_565,
+};
+use dsp::upsampling::{
+    WebPUpsamplers, WebPYUV444Converters, WebPYuv444ToArgb_C, WebPYuv444ToBgr_C,
+    WebPYuv444ToBgra_C, WebPYuv444ToRgb565_C, WebPYuv444ToRgb_C, WebPYuv444ToRgba4444_C,
+    WebPYuv444ToRgba_C,
+};
+use dsp::yuv::{
+    VP8YuvToArgb, VP8YuvToBgr, VP8YuvToBgra, VP8YuvToRgb, VP8YuvToRgb565, VP8YuvToRgba,
+    VP8YuvToRgba4444,
+};
+use dsp::yuv_sse2::{
+    VP8YuvToArgb32_SSE2, VP8YuvToBgr32_SSE2, VP8YuvToBgra32_SSE2, VP8YuvToRgb32_SSE2,
+    VP8YuvToRgb56532_SSE2, VP8YuvToRgba32_SSE2, VP8YuvToRgba444432_SSE2,
+};
+
+// We compute (9*a + 3*b + 3*c + d + 8) / 16 as follows
+// u = (9*a + 3*b + 3*c + d + 8) / 16
+//   = (a + (a + 3*b + 3*c + d) / 8 + 1) / 2
+//   = (a + m + 1) / 2
+// where m = (a + 3*b + 3*c + d) / 8
+//         = ((a + b + c + d) / 2 + b + c) / 4
+//
+// Let's say  k = (a + b + c + d) / 4.
+// We can compute k as
+// k = (s + t + 1) / 2 - ((a^d) | (b^c) | (s^t)) & 1
+// where s = (a + d + 1) / 2 and t = (b + c + 1) / 2
+//
+// Then m can be written as
+// m = (k + t + 1) / 2 - (((b^c) & (s^t)) | (k^t)) & 1
+
+// Computes out = (k + in + 1) / 2 - ((ij & (s^t)) | (k^in)) & 1
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn GetM_SSE2(k: __m128i, st: __m128i, one: __m128i, ij: __m128i, in_: __m128i) -> __m128i {
+    let tmp0 = _mm_avg_epu8(k, in_); // (k + in + 1) / 2
+    let tmp1 = _mm_and_si128(ij, st); // (ij) & (s^t)
+    let tmp2 = _mm_xor_si128(k, in_); // (k^in)
+    let tmp3 = _mm_or_si128(tmp1, tmp2); // ((ij) & (s^t)) | (k^in)
+    let tmp4 = _mm_and_si128(tmp3, one); // & 1 -> lsb_correction
+    _mm_sub_epi8(tmp0, tmp4) // (k + in + 1) / 2 - lsb_correction
+}
+
+// pack and store two alternating pixel rows
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+unsafe fn PackAndStore_SSE2(a: __m128i, b: __m128i, da: __m128i, db: __m128i, out: *mut u8) {
+    let t_a = _mm_avg_epu8(a, da); // (9a + 3b + 3c +  d + 8) / 16
+    let t_b = _mm_avg_epu8(b, db); // (3a + 9b +  c + 3d + 8) / 16
+    let t_1 = _mm_unpacklo_epi8(t_a, t_b);
+    let t_2 = _mm_unpackhi_epi8(t_a, t_b);
+    _mm_store_si128((out as *mut __m128i).offset(0), t_1);
+    _mm_store_si128((out as *mut __m128i).offset(1), t_2);
+}
+
+// Loads 17 pixels each from rows r1 and r2 and generates 32 pixels.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn Upsample32Pixels_SSE2(r1: *const u8, r2: *const u8, out: *mut u8) {
+    let one = _mm_set1_epi8(1);
+    let a = _mm_loadu_si128(r1.offset(0) as *const __m128i);
+    let b = _mm_loadu_si128(r1.offset(1) as *const __m128i);
+    let c = _mm_loadu_si128(r2.offset(0) as *const __m128i);
+    let d = _mm_loadu_si128(r2.offset(1) as *const __m128i);
+
+    let s = _mm_avg_epu8(a, d); // s = (a + d + 1) / 2
+    let t = _mm_avg_epu8(b, c); // t = (b + c + 1) / 2
+    let st = _mm_xor_si128(s, t); // st = s^t
+
+    let ad = _mm_xor_si128(a, d); // ad = a^d
+    let bc = _mm_xor_si128(b, c); // bc = b^c
+
+    let t1 = _mm_or_si128(ad, bc); // (a^d) | (b^c)
+    let t2 = _mm_or_si128(t1, st); // (a^d) | (b^c) | (s^t)
+    let t3 = _mm_and_si128(t2, one); // (a^d) | (b^c) | (s^t) & 1
+    let t4 = _mm_avg_epu8(s, t);
+    let k = _mm_sub_epi8(t4, t3); // k = (a + b + c + d) / 4
+
+    let diag1 = GetM_SSE2(k, st, one, bc, t); // diag1 = (a + 3b + 3c + d) / 8
+    let diag2 = GetM_SSE2(k, st, one, ad, s); // diag2 = (3a + b + c + 3d) / 8
+
+    // pack the alternate pixels
+    PackAndStore_SSE2(a, b, diag1, diag2, out.offset(0)); // store top
+    PackAndStore_SSE2(c, d, diag2, diag1, out.offset(2 * 32)); // store bottom
+}
+
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse2")]
+pub(crate) unsafe fn UpsampleLastBlock_SSE2(
+    tb: *const u8,
+    bb: *const u8,
+    num_pixels: usize,
+    out: *mut u8,
+) {
+    let mut r1 = [0u8; 17];
+    let mut r2 = [0u8; 17];
+    ptr::copy_nonoverlapping(tb, r1.as_mut_ptr(), num_pixels);
+    ptr::copy_nonoverlapping(bb, r2.as_mut_ptr(), num_pixels);
+    // replicate last byte
+    let (last1, last2) = (r1[num_pixels - 1], r2[num_pixels - 1]);
+    for x in &mut r1[num_pixels..] {
+        *x = last1;
+    }
+    for x in &mut r2[num_pixels..] {
+        *x = last2;
+    }
+    // using the shared function instead of the macro saves ~3k code size
+    Upsample32Pixels_SSE2(r1.as_ptr(), r2.as_ptr(), out);
+}
+
+macro_rules! convert2rgb_32 {
+    (
+        $func32:ident, $xstep:expr, $top_y:expr, $bottom_y:expr,
+        $top_dst:expr, $bottom_dst:expr, $cur_x:expr, $r_u:expr, $r_v:expr
+    ) => {
+        $func32(
+            $top_y.offset($cur_x),
+            $r_u,
+            $r_v,
+            $top_dst.offset($cur_x * $xstep),
+        );
+        if !$bottom_y.is_null() {
+            $func32(
+                $bottom_y.offset($cur_x),
+                $r_u.offset(64),
+                $r_v.offset(64),
+                $bottom_dst.offset($cur_x * $xstep),
+            );
+        }
+    };
+}
+
+// Shared with the SSE4.1 variants, which only differ by the converters.
+macro_rules! sse2_upsample_func {
+    ($(#[$attr:meta])* $func_name:ident, $func:ident, $func32:ident, $xstep:expr) => {
+        $(#[$attr])*
+        #[allow(non_snake_case)]
+        pub(crate) unsafe extern "C" fn $func_name(
+            top_y: *const u8,
+            bottom_y: *const u8,
+            top_u: *const u8,
+            top_v: *const u8,
+            cur_u: *const u8,
+            cur_v: *const u8,
+            top_dst: *mut u8,
+            bottom_dst: *mut u8,
+            len: c_int,
+        ) {
+            use dsp::upsampling_sse2::{UpsampleLastBlock_SSE2, Upsample32Pixels_SSE2};
+            let len = len as isize;
+            // 16byte-aligned array to cache reconstructed u and v
+            let mut uv_buf = [0u8; 14 * 32 + 15];
+            let r_u = ((uv_buf.as_mut_ptr() as usize + 15) & !15) as *mut u8;
+            let r_v = r_u.offset(32);
+
+            c_assert!(!top_y.is_null());
+            {
+                // Treat the first pixel in regular way
+                let u_diag = ((*top_u as c_int + *cur_u as c_int) >> 1) + 1;
+                let v_diag = ((*top_v as c_int + *cur_v as c_int) >> 1) + 1;
+                let u0_t = (*top_u as c_int + u_diag) >> 1;
+                let v0_t = (*top_v as c_int + v_diag) >> 1;
+                $func(*top_y as c_int, u0_t, v0_t, top_dst);
+                if !bottom_y.is_null() {
+                    let u0_b = (*cur_u as c_int + u_diag) >> 1;
+                    let v0_b = (*cur_v as c_int + v_diag) >> 1;
+                    $func(*bottom_y as c_int, u0_b, v0_b, bottom_dst);
+                }
+            }
+            // For UPSAMPLE_32PIXELS, 17 u/v values must be read-able for each block
+            let mut pos = 1;
+            let mut uv_pos = 0;
+            while pos + 32 < len {
+                Upsample32Pixels_SSE2(top_u.offset(uv_pos), cur_u.offset(uv_pos), r_u);
+                Upsample32Pixels_SSE2(top_v.offset(uv_pos), cur_v.offset(uv_pos), r_v);
+                convert2rgb_32!(
+                    $func32, $xstep, top_y, bottom_y, top_dst, bottom_dst, pos, r_u, r_v
+                );
+                pos += 32;
+                uv_pos += 16;
+            }
+            if len > 1 {
+                let left_over = ((len + 1) >> 1) - (pos >> 1);
+                let tmp_top_dst = r_u.offset(4 * 32);
+                let tmp_bottom_dst = tmp_top_dst.offset(4 * 32);
+                let tmp_top = tmp_bottom_dst.offset(4 * 32);
+                let tmp_bottom = if bottom_y.is_null() {
+                    ptr::null_mut()
+                } else {
+                    tmp_top.offset(32)
+                };
+                c_assert!(left_over > 0);
+                UpsampleLastBlock_SSE2(
+                    top_u.offset(uv_pos),
+                    cur_u.offset(uv_pos),
+                    left_over as usize,
+                    r_u,
+                );
+                UpsampleLastBlock_SSE2(
+                    top_v.offset(uv_pos),
+                    cur_v.offset(uv_pos),
+                    left_over as usize,
+                    r_v,
+                );
+                ptr::copy_nonoverlapping(top_y.offset(pos), tmp_top, (len - pos) as usize);
+                if !bottom_y.is_null() {
+                    ptr::copy_nonoverlapping(
+                        bottom_y.offset(pos),
+                        tmp_bottom,
+                        (len - pos) as usize,
+                    );
+                }
+                convert2rgb_32!(
+                    $func32, $xstep, tmp_top, tmp_bottom, tmp_top_dst, tmp_bottom_dst, 0,
+                    r_u, r_v
+                );
+                ptr::copy_nonoverlapping(
+                    tmp_top_dst,
+                    top_dst.offset(pos * $xstep),
+                    ((len - pos) * $xstep) as usize,
+                );
+                if !bottom_y.is_null() {
+                    ptr::copy_nonoverlapping(
+                        tmp_bottom_dst,
+                        bottom_dst.offset(pos * $xstep),
+                        ((len - pos) * $xstep) as usize,
+                    );
+                }
+            }
+        }
+    };
+}
+
+// SSE2 variants of the fancy upsampler.
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleRgbaLinePair_SSE2,
+    VP8YuvToRgba,
+    VP8YuvToRgba32_SSE2,
+    4
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleBgraLinePair_SSE2,
+    VP8YuvToBgra,
+    VP8YuvToBgra32_SSE2,
+    4
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleRgbLinePair_SSE2,
+    VP8YuvToRgb,
+    VP8YuvToRgb32_SSE2,
+    3
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleBgrLinePair_SSE2,
+    VP8YuvToBgr,
+    VP8YuvToBgr32_SSE2,
+    3
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleArgbLinePair_SSE2,
+    VP8YuvToArgb,
+    VP8YuvToArgb32_SSE2,
+    4
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleRgba4444LinePair_SSE2,
+    VP8YuvToRgba4444,
+    VP8YuvToRgba444432_SSE2,
+    2
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse2")]
+    UpsampleRgb565LinePair_SSE2,
+    VP8YuvToRgb565,
+    VP8YuvToRgb56532_SSE2,
+    2
+);
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitUpsamplersSSE2() {
+    WebPUpsamplers[MODE_RGBA as usize] = Some(UpsampleRgbaLinePair_SSE2);
+    WebPUpsamplers[MODE_BGRA as usize] = Some(UpsampleBgraLinePair_SSE2);
+    WebPUpsamplers[MODE_rgbA as usize] = Some(UpsampleRgbaLinePair_SSE2);
+    WebPUpsamplers[MODE_bgrA as usize] = Some(UpsampleBgraLinePair_SSE2);
+    WebPUpsamplers[MODE_RGB as usize] = Some(UpsampleRgbLinePair_SSE2);
+    WebPUpsamplers[MODE_BGR as usize] = Some(UpsampleBgrLinePair_SSE2);
+    WebPUpsamplers[MODE_ARGB as usize] = Some(UpsampleArgbLinePair_SSE2);
+    WebPUpsamplers[MODE_Argb as usize] = Some(UpsampleArgbLinePair_SSE2);
+    WebPUpsamplers[MODE_RGB_565 as usize] = Some(UpsampleRgb565LinePair_SSE2);
+    WebPUpsamplers[MODE_RGBA_4444 as usize] = Some(UpsampleRgba4444LinePair_SSE2);
+    WebPUpsamplers[MODE_rgbA_4444 as usize] = Some(UpsampleRgba4444LinePair_SSE2);
+}
+
+//------------------------------------------------------------------------------
+
+// Shared with the SSE4.1 variants, which only differ by the converters.
+macro_rules! yuv444_func {
+    ($(#[$attr:meta])* $func_name:ident, $call:ident, $call_c:ident, $xstep:expr) => {
+        $(#[$attr])*
+        #[allow(non_snake_case)]
+        pub(crate) unsafe extern "C" fn $func_name(
+            y: *const u8,
+            u: *const u8,
+            v: *const u8,
+            dst: *mut u8,
+            len: c_int,
+        ) {
+            let max_len = (len & !31) as isize;
+            let mut i = 0;
+            while i < max_len {
+                $call(y.offset(i), u.offset(i), v.offset(i), dst.offset(i * $xstep));
+                i += 32;
+            }
+            if i < len as isize {
+                // C-fallback
+                $call_c(
+                    y.offset(i),
+                    u.offset(i),
+                    v.offset(i),
+                    dst.offset(i * $xstep),
+                    len - i as c_int,
+                );
+            }
+        }
+    };
+}
+
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToRgba_SSE2,
+    VP8YuvToRgba32_SSE2,
+    WebPYuv444ToRgba_C,
+    4
+);
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToBgra_SSE2,
+    VP8YuvToBgra32_SSE2,
+    WebPYuv444ToBgra_C,
+    4
+);
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToRgb_SSE2,
+    VP8YuvToRgb32_SSE2,
+    WebPYuv444ToRgb_C,
+    3
+);
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToBgr_SSE2,
+    VP8YuvToBgr32_SSE2,
+    WebPYuv444ToBgr_C,
+    3
+);
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToArgb_SSE2,
+    VP8YuvToArgb32_SSE2,
+    WebPYuv444ToArgb_C,
+    4
+);
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToRgba4444_SSE2,
+    VP8YuvToRgba444432_SSE2,
+    WebPYuv444ToRgba4444_C,
+    2
+);
+yuv444_func!(
+    #[target_feature(enable = "sse2")]
+    Yuv444ToRgb565_SSE2,
+    VP8YuvToRgb56532_SSE2,
+    WebPYuv444ToRgb565_C,
+    2
+);
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitYUV444ConvertersSSE2() {
+    WebPYUV444Converters[MODE_RGBA as usize] = Some(Yuv444ToRgba_SSE2);
+    WebPYUV444Converters[MODE_BGRA as usize] = Some(Yuv444ToBgra_SSE2);
+    WebPYUV444Converters[MODE_rgbA as usize] = Some(Yuv444ToRgba_SSE2);
+    WebPYUV444Converters[MODE_bgrA as usize] = Some(Yuv444ToBgra_SSE2);
+    WebPYUV444Converters[MODE_RGB as usize] = Some(Yuv444ToRgb_SSE2);
+    WebPYUV444Converters[MODE_BGR as usize] = Some(Yuv444ToBgr_SSE2);
+    WebPYUV444Converters[MODE_ARGB as usize] = Some(Yuv444ToArgb_SSE2);
+    WebPYUV444Converters[MODE_RGBA_4444 as usize] = Some(Yuv444ToRgba4444_SSE2);
+    WebPYUV444Converters[MODE_RGB_565 as usize] = Some(Yuv444ToRgb565_SSE2);
+    WebPYUV444Converters[MODE_Argb as usize] = Some(Yuv444ToArgb_SSE2);
+    WebPYUV444Converters[MODE_rgbA_4444 as usize] = Some(Yuv444ToRgba4444_SSE2);
+}
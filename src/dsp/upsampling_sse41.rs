@@ -0,0 +1,69 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// SSE41 version of YUV to RGB upsampling functions.
+//
+// Author: somnath@google.com (Somnath Banerjee)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+use std::ptr;
+
+use decode::{MODE_BGR, MODE_RGB};
+use dsp::upsampling::{WebPUpsamplers, WebPYUV444Converters, WebPYuv444ToBgr_C, WebPYuv444ToRgb_C};
+use dsp::yuv::{VP8YuvToBgr, VP8YuvToRgb};
+use dsp::yuv_sse41::{VP8YuvToBgr32_SSE41, VP8YuvToRgb32_SSE41};
+
+// SSE4 variants of the fancy upsampler.
+sse2_upsample_func!(
+    #[target_feature(enable = "sse4.1")]
+    UpsampleRgbLinePair_SSE41,
+    VP8YuvToRgb,
+    VP8YuvToRgb32_SSE41,
+    3
+);
+sse2_upsample_func!(
+    #[target_feature(enable = "sse4.1")]
+    UpsampleBgrLinePair_SSE41,
+    VP8YuvToBgr,
+    VP8YuvToBgr32_SSE41,
+    3
+);
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitUpsamplersSSE41() {
+    WebPUpsamplers[MODE_RGB as usize] = Some(UpsampleRgbLinePair_SSE41);
+    WebPUpsamplers[MODE_BGR as usize] = Some(UpsampleBgrLinePair_SSE41);
+}
+
+//------------------------------------------------------------------------------
+
+yuv444_func!(
+    #[target_feature(enable = "sse4.1")]
+    Yuv444ToRgb_SSE41,
+    VP8YuvToRgb32_SSE41,
+    WebPYuv444ToRgb_C,
+    3
+);
+yuv444_func!(
+    #[target_feature(enable = "sse4.1")]
+    Yuv444ToBgr_SSE41,
+    VP8YuvToBgr32_SSE41,
+    WebPYuv444ToBgr_C,
+    3
+);
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPInitYUV444ConvertersSSE41() {
+    WebPYUV444Converters[MODE_RGB as usize] = Some(Yuv444ToRgb_SSE41);
+    WebPYUV444Converters[MODE_BGR as usize] = Some(Yuv444ToBgr_SSE41);
+}
@@ -0,0 +1,1035 @@
+// Copyright 2010 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// YUV->RGB conversion functions
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+// The inline functions below used to live in yuv.h:
+//
+// The exact naming is Y'CbCr, following the ITU-R BT.601 standard.
+// More information at: http://en.wikipedia.org/wiki/YCbCr
+// Y = 0.2569 * R + 0.5044 * G + 0.0979 * B + 16
+// U = -0.1483 * R - 0.2911 * G + 0.4394 * B + 128
+// V = 0.4394 * R - 0.3679 * G - 0.0715 * B + 128
+// We use 16bit fixed point operations for RGB->YUV conversion (YUV_FIX).
+//
+// For the Y'CbCr to RGB conversion, the BT.601 specification reads:
+//   R = 1.164 * (Y-16) + 1.596 * (V-128)
+//   G = 1.164 * (Y-16) - 0.813 * (V-128) - 0.391 * (U-128)
+//   B = 1.164 * (Y-16)                   + 2.018 * (U-128)
+// where Y is in the [16,235] range, and U/V in the [16,240] range.
+//
+// The fixed-point implementation used here is:
+//  R = (19077 . y             + 26149 . v - 14234) >> 6
+//  G = (19077 . y -  6419 . u - 13320 . v +  8708) >> 6
+//  B = (19077 . y + 33050 . u             - 17685) >> 6
+// where the '.' operator is the mulhi_epu16 variant:
+//   a . b = ((a << 8) * b) >> 16
+// that preserves 8 bits of fractional precision before final descaling.
+
+use std::os::raw::*;
+
+use decode::{
+    MODE_Argb, MODE_bgrA, MODE_rgbA, MODE_rgbA_4444, MODE_ARGB, MODE_BGR, MODE_BGRA, MODE_LAST,
+    MODE_RGB, MODE_RGBA, MODE_RGBA_4444, MODE_RGB_565,
+};
+#[cfg(not(target_arch = "aarch64"))]
+use dsp::cpu::VP8GetCPUInfo;
+#[cfg(not(target_arch = "aarch64"))]
+use dsp::dsp::CPUFeature::*;
+use dsp::dsp::{WebPSamplerRowFunc, WEBP_SWAP_16BIT_CSP};
+
+//------------------------------------------------------------------------------
+// YUV -> RGB conversion
+
+pub(crate) const YUV_FIX: c_int = 16; // fixed-point precision for RGB->YUV
+pub(crate) const YUV_HALF: c_int = 1 << (YUV_FIX - 1);
+pub(crate) const YUV_FIX2: c_int = 6; // fixed-point precision for YUV->RGB
+pub(crate) const YUV_MASK2: c_int = (256 << YUV_FIX2) - 1;
+
+//------------------------------------------------------------------------------
+// slower on x86 by ~7-8%, but bit-exact with the SSE2/NEON version
+
+// _mm_mulhi_epu16 emulation
+#[allow(non_snake_case)]
+#[inline]
+fn MultHi(v: c_int, coeff: c_int) -> c_int {
+    (v * coeff) >> 8
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn VP8Clip8(v: c_int) -> c_int {
+    if (v & !YUV_MASK2) == 0 {
+        v >> YUV_FIX2
+    } else if v < 0 {
+        0
+    } else {
+        255
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8YUVToR(y: c_int, v: c_int) -> c_int {
+    VP8Clip8(MultHi(y, 19077) + MultHi(v, 26149) - 14234)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8YUVToG(y: c_int, u: c_int, v: c_int) -> c_int {
+    VP8Clip8(MultHi(y, 19077) - MultHi(u, 6419) - MultHi(v, 13320) + 8708)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8YUVToB(y: c_int, u: c_int) -> c_int {
+    VP8Clip8(MultHi(y, 19077) + MultHi(u, 33050) - 17685)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToRgb(y: c_int, u: c_int, v: c_int, rgb: *mut u8) {
+    *rgb.offset(0) = VP8YUVToR(y, v) as u8;
+    *rgb.offset(1) = VP8YUVToG(y, u, v) as u8;
+    *rgb.offset(2) = VP8YUVToB(y, u) as u8;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToBgr(y: c_int, u: c_int, v: c_int, bgr: *mut u8) {
+    *bgr.offset(0) = VP8YUVToB(y, u) as u8;
+    *bgr.offset(1) = VP8YUVToG(y, u, v) as u8;
+    *bgr.offset(2) = VP8YUVToR(y, v) as u8;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToRgb565(y: c_int, u: c_int, v: c_int, rgb: *mut u8) {
+    let r = VP8YUVToR(y, v); // 5 usable bits
+    let g = VP8YUVToG(y, u, v); // 6 usable bits
+    let b = VP8YUVToB(y, u); // 5 usable bits
+    let rg = (r & 0xf8) | (g >> 5);
+    let gb = ((g << 3) & 0xe0) | (b >> 3);
+    if WEBP_SWAP_16BIT_CSP == 1 {
+        *rgb.offset(0) = gb as u8;
+        *rgb.offset(1) = rg as u8;
+    } else {
+        *rgb.offset(0) = rg as u8;
+        *rgb.offset(1) = gb as u8;
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToRgba4444(y: c_int, u: c_int, v: c_int, argb: *mut u8) {
+    let r = VP8YUVToR(y, v); // 4 usable bits
+    let g = VP8YUVToG(y, u, v); // 4 usable bits
+    let b = VP8YUVToB(y, u); // 4 usable bits
+    let rg = (r & 0xf0) | (g >> 4);
+    let ba = (b & 0xf0) | 0x0f; // overwrite the lower 4 bits
+    if WEBP_SWAP_16BIT_CSP == 1 {
+        *argb.offset(0) = ba as u8;
+        *argb.offset(1) = rg as u8;
+    } else {
+        *argb.offset(0) = rg as u8;
+        *argb.offset(1) = ba as u8;
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Alpha handling variants
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToArgb(y: c_int, u: c_int, v: c_int, argb: *mut u8) {
+    *argb.offset(0) = 0xff;
+    VP8YuvToRgb(y, u, v, argb.offset(1));
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToBgra(y: c_int, u: c_int, v: c_int, bgra: *mut u8) {
+    VP8YuvToBgr(y, u, v, bgra);
+    *bgra.offset(3) = 0xff;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8YuvToRgba(y: c_int, u: c_int, v: c_int, rgba: *mut u8) {
+    VP8YuvToRgb(y, u, v, rgba);
+    *rgba.offset(3) = 0xff;
+}
+
+//------------------------------------------------------------------------------
+// RGB -> YUV conversion
+
+// Stub functions that can be called with various rounding values:
+#[allow(non_snake_case)]
+#[inline]
+fn VP8ClipUV(mut uv: c_int, rounding: c_int) -> c_int {
+    uv = (uv + rounding + (128 << (YUV_FIX + 2))) >> (YUV_FIX + 2);
+    if (uv & !0xff) == 0 {
+        uv
+    } else if uv < 0 {
+        0
+    } else {
+        255
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8RGBToY(r: c_int, g: c_int, b: c_int, rounding: c_int) -> c_int {
+    let luma = 16839 * r + 33059 * g + 6420 * b;
+    (luma + rounding + (16 << YUV_FIX)) >> YUV_FIX // no need to clip
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8RGBToU(r: c_int, g: c_int, b: c_int, rounding: c_int) -> c_int {
+    let u = -9719 * r - 19081 * g + 28800 * b;
+    VP8ClipUV(u, rounding)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8RGBToV(r: c_int, g: c_int, b: c_int, rounding: c_int) -> c_int {
+    let v = 28800 * r - 24116 * g - 4684 * b;
+    VP8ClipUV(v, rounding)
+}
+
+//-----------------------------------------------------------------------------
+// Plain-C version
+
+macro_rules! row_func {
+    ($func_name:ident, $func:ident, $xstep:expr) => {
+        #[allow(non_snake_case)]
+        unsafe extern "C" fn $func_name(
+            mut y: *const u8,
+            mut u: *const u8,
+            mut v: *const u8,
+            mut dst: *mut u8,
+            len: c_int,
+        ) {
+            let end: *const u8 = dst.offset((len & !1) as isize * $xstep);
+            while dst as *const u8 != end {
+                $func(*y.offset(0) as c_int, *u as c_int, *v as c_int, dst);
+                $func(
+                    *y.offset(1) as c_int,
+                    *u as c_int,
+                    *v as c_int,
+                    dst.offset($xstep),
+                );
+                y = y.offset(2);
+                u = u.offset(1);
+                v = v.offset(1);
+                dst = dst.offset(2 * $xstep);
+            }
+            if (len & 1) != 0 {
+                $func(*y as c_int, *u as c_int, *v as c_int, dst);
+            }
+        }
+    };
+}
+
+// All variants implemented.
+row_func!(YuvToRgbRow, VP8YuvToRgb, 3);
+row_func!(YuvToBgrRow, VP8YuvToBgr, 3);
+row_func!(YuvToRgbaRow, VP8YuvToRgba, 4);
+row_func!(YuvToBgraRow, VP8YuvToBgra, 4);
+row_func!(YuvToArgbRow, VP8YuvToArgb, 4);
+row_func!(YuvToRgba4444Row, VP8YuvToRgba4444, 2);
+row_func!(YuvToRgb565Row, VP8YuvToRgb565, 2);
+
+// Main call for processing a plane with a WebPSamplerRowFunc function:
+#[no_mangle]
+pub unsafe extern "C" fn WebPSamplerProcessPlane(
+    mut y: *const u8,
+    y_stride: c_int,
+    mut u: *const u8,
+    mut v: *const u8,
+    uv_stride: c_int,
+    mut dst: *mut u8,
+    dst_stride: c_int,
+    width: c_int,
+    height: c_int,
+    func: WebPSamplerRowFunc,
+) {
+    for j in 0..height {
+        func.unwrap()(y, u, v, dst, width);
+        y = y.offset(y_stride as isize);
+        if (j & 1) != 0 {
+            u = u.offset(uv_stride as isize);
+            v = v.offset(uv_stride as isize);
+        }
+        dst = dst.offset(dst_stride as isize);
+    }
+}
+
+//-----------------------------------------------------------------------------
+// Main call
+
+#[no_mangle]
+pub static mut WebPSamplers: [WebPSamplerRowFunc; MODE_LAST as usize] = [None; MODE_LAST as usize];
+
+#[cfg(not(feature = "pure-rust-decoder"))]
+extern "C" {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitSamplersSSE2();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitSamplersSSE41();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPInitSamplersMIPS32();
+    #[cfg(any(target_arch = "mips", target_arch = "mips64"))]
+    fn WebPInitSamplersMIPSdspR2();
+}
+
+webp_dsp_init_func! {
+    // Must be called before using the WebPSamplers[] (and for premultiplied
+    // colorspaces like rgbA, rgbA4444, etc)
+    #[no_mangle]
+    pub fn WebPInitSamplers() {
+        WebPSamplers[MODE_RGB as usize] = Some(YuvToRgbRow);
+        WebPSamplers[MODE_RGBA as usize] = Some(YuvToRgbaRow);
+        WebPSamplers[MODE_BGR as usize] = Some(YuvToBgrRow);
+        WebPSamplers[MODE_BGRA as usize] = Some(YuvToBgraRow);
+        WebPSamplers[MODE_ARGB as usize] = Some(YuvToArgbRow);
+        WebPSamplers[MODE_RGBA_4444 as usize] = Some(YuvToRgba4444Row);
+        WebPSamplers[MODE_RGB_565 as usize] = Some(YuvToRgb565Row);
+        WebPSamplers[MODE_rgbA as usize] = Some(YuvToRgbaRow);
+        WebPSamplers[MODE_bgrA as usize] = Some(YuvToBgraRow);
+        WebPSamplers[MODE_Argb as usize] = Some(YuvToArgbRow);
+        WebPSamplers[MODE_rgbA_4444 as usize] = Some(YuvToRgba4444Row);
+
+        // If defined, use CPUInfo() to overwrite some pointers with faster versions.
+        #[cfg(all(
+            any(target_arch = "x86", target_arch = "x86_64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    WebPInitSamplersSSE2();
+                }
+                if get_cpu_info(kSSE4_1) != 0 {
+                    WebPInitSamplersSSE41();
+                }
+            }
+        }
+        #[cfg(all(
+            any(target_arch = "mips", target_arch = "mips64"),
+            not(feature = "pure-rust-decoder")
+        ))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kMIPS32) != 0 {
+                    WebPInitSamplersMIPS32();
+                }
+                if get_cpu_info(kMIPSdspR2) != 0 {
+                    WebPInitSamplersMIPSdspR2();
+                }
+            }
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+// ARGB -> YUV converters
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ConvertARGBToY_C(argb: *const u32, y: *mut u8, width: c_int) {
+    for i in 0..width as isize {
+        let p = *argb.offset(i);
+        *y.offset(i) = VP8RGBToY(
+            ((p >> 16) & 0xff) as c_int,
+            ((p >> 8) & 0xff) as c_int,
+            (p & 0xff) as c_int,
+            YUV_HALF,
+        ) as u8;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPConvertARGBToUV_C(
+    argb: *const u32,
+    u: *mut u8,
+    v: *mut u8,
+    src_width: c_int,
+    do_store: c_int,
+) {
+    // No rounding. Last pixel is dealt with separately.
+    let uv_width = (src_width >> 1) as isize;
+    for i in 0..uv_width {
+        let v0 = *argb.offset(2 * i);
+        let v1 = *argb.offset(2 * i + 1);
+        // VP8RGBToU/V expects four accumulated pixels. Hence we need to
+        // scale r/g/b value by a factor 2. We just shift v0/v1 one bit less.
+        let r = (((v0 >> 15) & 0x1fe) + ((v1 >> 15) & 0x1fe)) as c_int;
+        let g = (((v0 >> 7) & 0x1fe) + ((v1 >> 7) & 0x1fe)) as c_int;
+        let b = (((v0 << 1) & 0x1fe) + ((v1 << 1) & 0x1fe)) as c_int;
+        let tmp_u = VP8RGBToU(r, g, b, YUV_HALF << 2);
+        let tmp_v = VP8RGBToV(r, g, b, YUV_HALF << 2);
+        if do_store != 0 {
+            *u.offset(i) = tmp_u as u8;
+            *v.offset(i) = tmp_v as u8;
+        } else {
+            // Approximated average-of-four. But it's an acceptable diff.
+            *u.offset(i) = ((*u.offset(i) as c_int + tmp_u + 1) >> 1) as u8;
+            *v.offset(i) = ((*v.offset(i) as c_int + tmp_v + 1) >> 1) as u8;
+        }
+    }
+    if (src_width & 1) != 0 {
+        // last pixel
+        let i = uv_width;
+        let v0 = *argb.offset(2 * i);
+        let r = ((v0 >> 14) & 0x3fc) as c_int;
+        let g = ((v0 >> 6) & 0x3fc) as c_int;
+        let b = ((v0 << 2) & 0x3fc) as c_int;
+        let tmp_u = VP8RGBToU(r, g, b, YUV_HALF << 2);
+        let tmp_v = VP8RGBToV(r, g, b, YUV_HALF << 2);
+        if do_store != 0 {
+            *u.offset(i) = tmp_u as u8;
+            *v.offset(i) = tmp_v as u8;
+        } else {
+            *u.offset(i) = ((*u.offset(i) as c_int + tmp_u + 1) >> 1) as u8;
+            *v.offset(i) = ((*v.offset(i) as c_int + tmp_v + 1) >> 1) as u8;
+        }
+    }
+}
+
+//-----------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ConvertRGB24ToY_C(mut rgb: *const u8, y: *mut u8, width: c_int) {
+    for i in 0..width as isize {
+        *y.offset(i) = VP8RGBToY(
+            *rgb.offset(0) as c_int,
+            *rgb.offset(1) as c_int,
+            *rgb.offset(2) as c_int,
+            YUV_HALF,
+        ) as u8;
+        rgb = rgb.offset(3);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn ConvertBGR24ToY_C(mut bgr: *const u8, y: *mut u8, width: c_int) {
+    for i in 0..width as isize {
+        *y.offset(i) = VP8RGBToY(
+            *bgr.offset(2) as c_int,
+            *bgr.offset(1) as c_int,
+            *bgr.offset(0) as c_int,
+            YUV_HALF,
+        ) as u8;
+        bgr = bgr.offset(3);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPConvertRGBA32ToUV_C(
+    mut rgb: *const u16,
+    u: *mut u8,
+    v: *mut u8,
+    width: c_int,
+) {
+    for i in 0..width as isize {
+        let r = *rgb.offset(0) as c_int;
+        let g = *rgb.offset(1) as c_int;
+        let b = *rgb.offset(2) as c_int;
+        *u.offset(i) = VP8RGBToU(r, g, b, YUV_HALF << 2) as u8;
+        *v.offset(i) = VP8RGBToV(r, g, b, YUV_HALF << 2) as u8;
+        rgb = rgb.offset(4);
+    }
+}
+
+//-----------------------------------------------------------------------------
+
+const MAX_Y: c_int = (1 << 10) - 1; // 10b precision over 16b-arithmetic
+
+fn clip_y(v: c_int) -> u16 {
+    if v < 0 {
+        0
+    } else if v > MAX_Y {
+        MAX_Y as u16
+    } else {
+        v as u16
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SharpYUVUpdateY_C(
+    ref_: *const u16,
+    src: *const u16,
+    dst: *mut u16,
+    len: c_int,
+) -> u64 {
+    let mut diff: u64 = 0;
+    for i in 0..len as isize {
+        let diff_y = *ref_.offset(i) as c_int - *src.offset(i) as c_int;
+        let new_y = *dst.offset(i) as c_int + diff_y;
+        *dst.offset(i) = clip_y(new_y);
+        diff += diff_y.unsigned_abs() as u64;
+    }
+    diff
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SharpYUVUpdateRGB_C(
+    ref_: *const i16,
+    src: *const i16,
+    dst: *mut i16,
+    len: c_int,
+) {
+    for i in 0..len as isize {
+        let diff_uv = *ref_.offset(i) as c_int - *src.offset(i) as c_int;
+        *dst.offset(i) = (*dst.offset(i) as c_int + diff_uv) as i16;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn SharpYUVFilterRow_C(
+    mut A: *const i16,
+    mut B: *const i16,
+    len: c_int,
+    best_y: *const u16,
+    out: *mut u16,
+) {
+    for i in 0..len as isize {
+        let a0 = *A.offset(0) as c_int;
+        let a1 = *A.offset(1) as c_int;
+        let b0 = *B.offset(0) as c_int;
+        let b1 = *B.offset(1) as c_int;
+        let v0 = (a0 * 9 + a1 * 3 + b0 * 3 + b1 + 8) >> 4;
+        let v1 = (a1 * 9 + a0 * 3 + b1 * 3 + b0 + 8) >> 4;
+        *out.offset(2 * i) = clip_y(*best_y.offset(2 * i) as c_int + v0);
+        *out.offset(2 * i + 1) = clip_y(*best_y.offset(2 * i + 1) as c_int + v1);
+        A = A.offset(1);
+        B = B.offset(1);
+    }
+}
+
+//-----------------------------------------------------------------------------
+
+#[no_mangle]
+pub static mut WebPConvertRGB24ToY: Option<
+    unsafe extern "C" fn(rgb: *const u8, y: *mut u8, width: c_int),
+> = None;
+#[no_mangle]
+pub static mut WebPConvertBGR24ToY: Option<
+    unsafe extern "C" fn(bgr: *const u8, y: *mut u8, width: c_int),
+> = None;
+#[no_mangle]
+pub static mut WebPConvertRGBA32ToUV: Option<
+    unsafe extern "C" fn(rgb: *const u16, u: *mut u8, v: *mut u8, width: c_int),
+> = None;
+
+#[no_mangle]
+pub static mut WebPConvertARGBToY: Option<
+    unsafe extern "C" fn(argb: *const u32, y: *mut u8, width: c_int),
+> = None;
+#[no_mangle]
+pub static mut WebPConvertARGBToUV: Option<
+    unsafe extern "C" fn(
+        argb: *const u32,
+        u: *mut u8,
+        v: *mut u8,
+        src_width: c_int,
+        do_store: c_int,
+    ),
+> = None;
+
+#[no_mangle]
+pub static mut WebPSharpYUVUpdateY: Option<
+    unsafe extern "C" fn(ref_: *const u16, src: *const u16, dst: *mut u16, len: c_int) -> u64,
+> = None;
+#[no_mangle]
+pub static mut WebPSharpYUVUpdateRGB: Option<
+    unsafe extern "C" fn(ref_: *const i16, src: *const i16, dst: *mut i16, len: c_int),
+> = None;
+#[no_mangle]
+pub static mut WebPSharpYUVFilterRow: Option<
+    unsafe extern "C" fn(
+        a: *const i16,
+        b: *const i16,
+        len: c_int,
+        best_y: *const u16,
+        out: *mut u16,
+    ),
+> = None;
+
+extern "C" {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitConvertARGBToYUVSSE2();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitConvertARGBToYUVSSE41();
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn WebPInitConvertARGBToYUVNEON();
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn WebPInitSharpYUVSSE2();
+    #[cfg(any(target_arch = "arm", target_arch = "aarch64"))]
+    fn WebPInitSharpYUVNEON();
+}
+
+webp_dsp_init_func! {
+    // Must be called before using the above.
+    #[no_mangle]
+    pub fn WebPInitConvertARGBToYUV() {
+        WebPConvertARGBToY = Some(ConvertARGBToY_C);
+        WebPConvertARGBToUV = Some(WebPConvertARGBToUV_C);
+
+        WebPConvertRGB24ToY = Some(ConvertRGB24ToY_C);
+        WebPConvertBGR24ToY = Some(ConvertBGR24ToY_C);
+
+        WebPConvertRGBA32ToUV = Some(WebPConvertRGBA32ToUV_C);
+
+        WebPSharpYUVUpdateY = Some(SharpYUVUpdateY_C);
+        WebPSharpYUVUpdateRGB = Some(SharpYUVUpdateRGB_C);
+        WebPSharpYUVFilterRow = Some(SharpYUVFilterRow_C);
+
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kSSE2) != 0 {
+                    WebPInitConvertARGBToYUVSSE2();
+                    WebPInitSharpYUVSSE2();
+                }
+                if get_cpu_info(kSSE4_1) != 0 {
+                    WebPInitConvertARGBToYUVSSE41();
+                }
+            }
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        {
+            WebPInitConvertARGBToYUVNEON();
+            WebPInitSharpYUVNEON();
+        }
+        #[cfg(target_arch = "arm")]
+        {
+            if let Some(get_cpu_info) = VP8GetCPUInfo {
+                if get_cpu_info(kNEON) != 0 {
+                    WebPInitConvertARGBToYUVNEON();
+                    WebPInitSharpYUVNEON();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use decode::WEBP_CSP_MODE;
+    use test_util::{bytes_per_pixel, check_parity, grid, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    #[allow(non_upper_case_globals)]
+    extern "C" {
+        static WebPRef_WebPSamplers: [WebPSamplerRowFunc; MODE_LAST as usize];
+        fn WebPRef_WebPInitSamplers();
+        fn WebPRef_WebPSamplerProcessPlane(
+            y: *const u8,
+            y_stride: c_int,
+            u: *const u8,
+            v: *const u8,
+            uv_stride: c_int,
+            dst: *mut u8,
+            dst_stride: c_int,
+            width: c_int,
+            height: c_int,
+            func: WebPSamplerRowFunc,
+        );
+        static WebPRef_WebPConvertARGBToY:
+            Option<unsafe extern "C" fn(argb: *const u32, y: *mut u8, width: c_int)>;
+        static WebPRef_WebPConvertRGB24ToY:
+            Option<unsafe extern "C" fn(rgb: *const u8, y: *mut u8, width: c_int)>;
+        static WebPRef_WebPConvertBGR24ToY:
+            Option<unsafe extern "C" fn(bgr: *const u8, y: *mut u8, width: c_int)>;
+        fn WebPRef_WebPConvertARGBToUV_C(
+            argb: *const u32,
+            u: *mut u8,
+            v: *mut u8,
+            src_width: c_int,
+            do_store: c_int,
+        );
+        fn WebPRef_WebPConvertRGBA32ToUV_C(rgb: *const u16, u: *mut u8, v: *mut u8, width: c_int);
+        static WebPRef_WebPSharpYUVUpdateY: Option<
+            unsafe extern "C" fn(
+                ref_: *const u16,
+                src: *const u16,
+                dst: *mut u16,
+                len: c_int,
+            ) -> u64,
+        >;
+        static WebPRef_WebPSharpYUVUpdateRGB: Option<
+            unsafe extern "C" fn(ref_: *const i16, src: *const i16, dst: *mut i16, len: c_int),
+        >;
+        static WebPRef_WebPSharpYUVFilterRow: Option<
+            unsafe extern "C" fn(
+                a: *const i16,
+                b: *const i16,
+                len: c_int,
+                best_y: *const u16,
+                out: *mut u16,
+            ),
+        >;
+        fn WebPRef_WebPInitConvertARGBToYUV();
+    }
+
+    #[allow(non_snake_case, non_upper_case_globals)]
+    fn sampler_C(mode: WEBP_CSP_MODE) -> WebPSamplerRowFunc {
+        match mode {
+            MODE_RGB => Some(YuvToRgbRow),
+            MODE_RGBA | MODE_rgbA => Some(YuvToRgbaRow),
+            MODE_BGR => Some(YuvToBgrRow),
+            MODE_BGRA | MODE_bgrA => Some(YuvToBgraRow),
+            MODE_ARGB | MODE_Argb => Some(YuvToArgbRow),
+            MODE_RGBA_4444 | MODE_rgbA_4444 => Some(YuvToRgba4444Row),
+            MODE_RGB_565 => Some(YuvToRgb565Row),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_samplers_parity() {
+        unsafe {
+            WebPInitSamplers();
+            #[cfg(feature = "c-reference")]
+            WebPRef_WebPInitSamplers();
+        }
+        let grid = grid();
+        // Each row holds every (y, v) pair of the grid for a fixed u, with
+        // the second pixel of each pair using the mirrored luma.
+        let mut y = Vec::new();
+        let mut v = Vec::new();
+        for &vv in &grid {
+            for &yy in &grid {
+                y.push(yy);
+                y.push(255 - yy);
+                v.push(vv);
+            }
+        }
+        let mut digest = Digest::new();
+        for mode in MODE_RGB..=MODE_rgbA_4444 {
+            let bpp = bytes_per_pixel(mode);
+            for &uu in &grid {
+                let u = vec![uu; v.len()];
+                // Also check an odd length, which handles the last pixel alone.
+                for &len in &[y.len(), y.len() - 1] {
+                    let mut dst = vec![0; len * bpp];
+                    unsafe {
+                        sampler_C(mode).unwrap()(
+                            y.as_ptr(),
+                            u.as_ptr(),
+                            v.as_ptr(),
+                            dst.as_mut_ptr(),
+                            len as c_int,
+                        );
+                    }
+                    digest.update(&dst);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let mut ref_dst = vec![0; len * bpp];
+                        unsafe {
+                            WebPRef_WebPSamplers[mode as usize].unwrap()(
+                                y.as_ptr(),
+                                u.as_ptr(),
+                                v.as_ptr(),
+                                ref_dst.as_mut_ptr(),
+                                len as c_int,
+                            );
+                        }
+                        assert!(dst == ref_dst, "mode = {}, u = {}", mode, uu);
+                    }
+                }
+            }
+        }
+        check_parity("dsp::yuv::test_samplers_parity", digest);
+    }
+
+    #[test]
+    fn test_sampler_process_plane_parity() {
+        unsafe {
+            WebPInitSamplers();
+            #[cfg(feature = "c-reference")]
+            WebPRef_WebPInitSamplers();
+        }
+        let mut digest = Digest::new();
+        let mut rng = Lcg(1);
+        for &(width, height) in &[(1, 1), (2, 3), (7, 5), (16, 16), (33, 9)] {
+            let uv_width = (width + 1) >> 1;
+            let uv_height = (height + 1) >> 1;
+            let y = rng.bytes(width * height);
+            let u = rng.bytes(uv_width * uv_height);
+            let v = rng.bytes(uv_width * uv_height);
+            for mode in MODE_RGB..=MODE_rgbA_4444 {
+                let stride = width * bytes_per_pixel(mode);
+                let mut dst = vec![0; stride * height];
+                unsafe {
+                    WebPSamplerProcessPlane(
+                        y.as_ptr(),
+                        width as c_int,
+                        u.as_ptr(),
+                        v.as_ptr(),
+                        uv_width as c_int,
+                        dst.as_mut_ptr(),
+                        stride as c_int,
+                        width as c_int,
+                        height as c_int,
+                        WebPSamplers[mode as usize],
+                    );
+                }
+                digest.update(&dst);
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut ref_dst = vec![0; stride * height];
+                    unsafe {
+                        WebPRef_WebPSamplerProcessPlane(
+                            y.as_ptr(),
+                            width as c_int,
+                            u.as_ptr(),
+                            v.as_ptr(),
+                            uv_width as c_int,
+                            ref_dst.as_mut_ptr(),
+                            stride as c_int,
+                            width as c_int,
+                            height as c_int,
+                            WebPRef_WebPSamplers[mode as usize],
+                        );
+                    }
+                    assert!(dst == ref_dst, "mode = {}, {}x{}", mode, width, height);
+                }
+            }
+        }
+        check_parity("dsp::yuv::test_sampler_process_plane_parity", digest);
+    }
+
+    #[test]
+    fn test_rgb_to_yuv_parity() {
+        #[cfg(feature = "c-reference")]
+        unsafe {
+            WebPRef_WebPInitConvertARGBToYUV();
+        }
+        let mut digest = Digest::new();
+        let mut rng = Lcg(2);
+        for width in 1..40 {
+            let argb = (0..width)
+                .map(|_| rng.next() << 8 | rng.next() & 0xff)
+                .collect::<Vec<u32>>();
+            let rgb = rng.bytes(width * 3);
+            let rgba = (0..width * 4)
+                .map(|_| (rng.next() % (4 * 256)) as u16)
+                .collect::<Vec<u16>>();
+            #[cfg(feature = "c-reference")]
+            let mut ref_y = vec![0; width];
+            let mut y = vec![0; width];
+            unsafe {
+                ConvertARGBToY_C(argb.as_ptr(), y.as_mut_ptr(), width as c_int);
+            }
+            digest.update(&y);
+            #[cfg(feature = "c-reference")]
+            {
+                unsafe {
+                    WebPRef_WebPConvertARGBToY.unwrap()(
+                        argb.as_ptr(),
+                        ref_y.as_mut_ptr(),
+                        width as c_int,
+                    );
+                }
+                assert_eq!(y, ref_y);
+            }
+            unsafe {
+                ConvertRGB24ToY_C(rgb.as_ptr(), y.as_mut_ptr(), width as c_int);
+            }
+            digest.update(&y);
+            #[cfg(feature = "c-reference")]
+            {
+                unsafe {
+                    WebPRef_WebPConvertRGB24ToY.unwrap()(
+                        rgb.as_ptr(),
+                        ref_y.as_mut_ptr(),
+                        width as c_int,
+                    );
+                }
+                assert_eq!(y, ref_y);
+            }
+            unsafe {
+                ConvertBGR24ToY_C(rgb.as_ptr(), y.as_mut_ptr(), width as c_int);
+            }
+            digest.update(&y);
+            #[cfg(feature = "c-reference")]
+            {
+                unsafe {
+                    WebPRef_WebPConvertBGR24ToY.unwrap()(
+                        rgb.as_ptr(),
+                        ref_y.as_mut_ptr(),
+                        width as c_int,
+                    );
+                }
+                assert_eq!(y, ref_y);
+            }
+
+            let uv_width = (width + 1) >> 1;
+            let init = rng.bytes(2 * uv_width);
+            for &do_store in &[1, 0] {
+                let (mut u, mut v) = (init[..uv_width].to_vec(), init[uv_width..].to_vec());
+                unsafe {
+                    WebPConvertARGBToUV_C(
+                        argb.as_ptr(),
+                        u.as_mut_ptr(),
+                        v.as_mut_ptr(),
+                        width as c_int,
+                        do_store,
+                    );
+                }
+                digest.update(&u);
+                digest.update(&v);
+                #[cfg(feature = "c-reference")]
+                {
+                    let (mut ref_u, mut ref_v) =
+                        (init[..uv_width].to_vec(), init[uv_width..].to_vec());
+                    unsafe {
+                        WebPRef_WebPConvertARGBToUV_C(
+                            argb.as_ptr(),
+                            ref_u.as_mut_ptr(),
+                            ref_v.as_mut_ptr(),
+                            width as c_int,
+                            do_store,
+                        );
+                    }
+                    assert_eq!((u, v), (ref_u, ref_v));
+                }
+            }
+
+            let (mut u, mut v) = (vec![0; width], vec![0; width]);
+            unsafe {
+                WebPConvertRGBA32ToUV_C(
+                    rgba.as_ptr(),
+                    u.as_mut_ptr(),
+                    v.as_mut_ptr(),
+                    width as c_int,
+                );
+            }
+            digest.update(&u);
+            digest.update(&v);
+            #[cfg(feature = "c-reference")]
+            {
+                let (mut ref_u, mut ref_v) = (vec![0; width], vec![0; width]);
+                unsafe {
+                    WebPRef_WebPConvertRGBA32ToUV_C(
+                        rgba.as_ptr(),
+                        ref_u.as_mut_ptr(),
+                        ref_v.as_mut_ptr(),
+                        width as c_int,
+                    );
+                }
+                assert_eq!((u, v), (ref_u, ref_v));
+            }
+        }
+        check_parity("dsp::yuv::test_rgb_to_yuv_parity", digest);
+    }
+
+    #[test]
+    fn test_sharp_yuv_parity() {
+        #[cfg(feature = "c-reference")]
+        unsafe {
+            WebPRef_WebPInitConvertARGBToYUV();
+        }
+        let mut digest = Digest::new();
+        let mut rng = Lcg(3);
+        for len in 1..40 {
+            // 10-bit values, slightly out of range to exercise clip_y().
+            let mut sample = |n: usize| {
+                (0..n)
+                    .map(|_| (rng.next() % 1200) as i16 - 80)
+                    .collect::<Vec<i16>>()
+            };
+            let ref_ = sample(2 * len + 2);
+            let src = sample(2 * len + 2);
+            let dst0 = sample(2 * len + 2);
+            let as_u16 = |v: &[i16]| v.iter().map(|&x| x.max(0) as u16).collect::<Vec<u16>>();
+
+            let mut dst = as_u16(&dst0);
+            let diff = unsafe {
+                SharpYUVUpdateY_C(
+                    as_u16(&ref_).as_ptr(),
+                    as_u16(&src).as_ptr(),
+                    dst.as_mut_ptr(),
+                    len as c_int,
+                )
+            };
+            digest.update(&diff.to_le_bytes());
+            digest.update_u16(&dst);
+            #[cfg(feature = "c-reference")]
+            {
+                let mut ref_dst = as_u16(&dst0);
+                let ref_diff = unsafe {
+                    WebPRef_WebPSharpYUVUpdateY.unwrap()(
+                        as_u16(&ref_).as_ptr(),
+                        as_u16(&src).as_ptr(),
+                        ref_dst.as_mut_ptr(),
+                        len as c_int,
+                    )
+                };
+                assert_eq!((diff, dst), (ref_diff, ref_dst));
+            }
+
+            let mut dst = dst0.clone();
+            unsafe {
+                SharpYUVUpdateRGB_C(ref_.as_ptr(), src.as_ptr(), dst.as_mut_ptr(), len as c_int);
+            }
+            digest.update_i16(&dst);
+            #[cfg(feature = "c-reference")]
+            {
+                let mut ref_dst = dst0.clone();
+                unsafe {
+                    WebPRef_WebPSharpYUVUpdateRGB.unwrap()(
+                        ref_.as_ptr(),
+                        src.as_ptr(),
+                        ref_dst.as_mut_ptr(),
+                        len as c_int,
+                    );
+                }
+                assert_eq!(dst, ref_dst);
+            }
+
+            let best_y = as_u16(&dst0);
+            let mut out = vec![0; 2 * len];
+            unsafe {
+                SharpYUVFilterRow_C(
+                    ref_.as_ptr(),
+                    src.as_ptr(),
+                    len as c_int,
+                    best_y.as_ptr(),
+                    out.as_mut_ptr(),
+                );
+            }
+            digest.update_u16(&out);
+            #[cfg(feature = "c-reference")]
+            {
+                let mut ref_out = vec![0; 2 * len];
+                unsafe {
+                    WebPRef_WebPSharpYUVFilterRow.unwrap()(
+                        ref_.as_ptr(),
+                        src.as_ptr(),
+                        len as c_int,
+                        best_y.as_ptr(),
+                        ref_out.as_mut_ptr(),
+                    );
+                }
+                assert_eq!(out, ref_out);
+            }
+        }
+        check_parity("dsp::yuv::test_sharp_yuv_parity", digest);
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2014 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// YUV->RGB conversion functions
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+// Only the 32-pixel converters used by the fancy upsampler are ported here;
+// the samplers and the RGB->YUV converters of yuv_sse41.c are still C.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use dsp::common_sse41::VP8PlanarTo24b_SSE41;
+
+//-----------------------------------------------------------------------------
+// Convert spans of 32 pixels to various RGB formats for the fancy upsampler.
+
+// These constants are 14b fixed-point version of ITU-R BT.601 constants.
+// R = (19077 * y             + 26149 * v - 14234) >> 6
+// G = (19077 * y -  6419 * u - 13320 * v +  8708) >> 6
+// B = (19077 * y + 33050 * u             - 17685) >> 6
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn ConvertYUV444ToRGB_SSE41(
+    Y0: &__m128i,
+    U0: &__m128i,
+    V0: &__m128i,
+    R: &mut __m128i,
+    G: &mut __m128i,
+    B: &mut __m128i,
+) {
+    let k19077 = _mm_set1_epi16(19077);
+    let k26149 = _mm_set1_epi16(26149);
+    let k14234 = _mm_set1_epi16(14234);
+    // 33050 doesn't fit in a signed short: only use this with unsigned arithmetic
+    let k33050 = _mm_set1_epi16(33050u16 as i16);
+    let k17685 = _mm_set1_epi16(17685);
+    let k6419 = _mm_set1_epi16(6419);
+    let k13320 = _mm_set1_epi16(13320);
+    let k8708 = _mm_set1_epi16(8708);
+
+    let Y1 = _mm_mulhi_epu16(*Y0, k19077);
+
+    let R0 = _mm_mulhi_epu16(*V0, k26149);
+    let R1 = _mm_sub_epi16(Y1, k14234);
+    let R2 = _mm_add_epi16(R1, R0);
+
+    let G0 = _mm_mulhi_epu16(*U0, k6419);
+    let G1 = _mm_mulhi_epu16(*V0, k13320);
+    let G2 = _mm_add_epi16(Y1, k8708);
+    let G3 = _mm_add_epi16(G0, G1);
+    let G4 = _mm_sub_epi16(G2, G3);
+
+    // be careful with the saturated *unsigned* arithmetic here!
+    let B0 = _mm_mulhi_epu16(*U0, k33050);
+    let B1 = _mm_adds_epu16(B0, Y1);
+    let B2 = _mm_subs_epu16(B1, k17685);
+
+    // use logical shift for B2, which can be larger than 32767
+    *R = _mm_srai_epi16(R2, 6); // range: [-14234, 30815]
+    *G = _mm_srai_epi16(G4, 6); // range: [-10953, 27710]
+    *B = _mm_srli_epi16(B2, 6); // range: [0, 34238]
+}
+
+// Load the bytes into the *upper* part of 16b words. That's "<< 8", basically.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn Load_HI_16_SSE41(src: *const u8) -> __m128i {
+    let zero = _mm_setzero_si128();
+    _mm_unpacklo_epi8(zero, _mm_loadl_epi64(src as *const __m128i))
+}
+
+// Convert 32 samples of YUV444 to R/G/B
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn YUV444ToRGB_SSE41(
+    y: *const u8,
+    u: *const u8,
+    v: *const u8,
+    R: &mut __m128i,
+    G: &mut __m128i,
+    B: &mut __m128i,
+) {
+    let Y0 = Load_HI_16_SSE41(y);
+    let U0 = Load_HI_16_SSE41(u);
+    let V0 = Load_HI_16_SSE41(v);
+    ConvertYUV444ToRGB_SSE41(&Y0, &U0, &V0, R, G, B);
+}
+
+// Pack the planar buffers
+// rrrr... rrrr... gggg... gggg... bbbb... bbbb....
+// triplet by triplet in the output buffer rgb as rgbrgbrgbrgb ...
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn PlanarTo24b_SSE41(input: &mut [__m128i; 6], rgb: *mut u8) {
+    // The input is 6 registers of sixteen 8b but for the sake of explanation,
+    // let's take 6 registers of four 8b values.
+    // To pack, we will keep taking one every two 8b integer and move it
+    // around as follows:
+    // Input:
+    //   r0r1r2r3 | r4r5r6r7 | g0g1g2g3 | g4g5g6g7 | b0b1b2b3 | b4b5b6b7
+    // Split the 6 registers in two sets of 3 registers: the first set as the even
+    // 8b bytes, the second the odd ones:
+    //   r0r2r4r6 | g0g2g4g6 | b0b2b4b6 | r1r3r5r7 | g1g3g5g7 | b1b3b5b7
+    // Repeat the same permutations twice more:
+    //   r0r4g0g4 | b0b4r1r5 | g1g5b1b5 | r2r6g2g6 | b2b6r3r7 | g3g7b3b7
+    //   r0g0b0r1 | g1b1r2g2 | b2r3g3b3 | r4g4b4r5 | g5b5r6g6 | b6r7g7b7
+    VP8PlanarTo24b_SSE41(input);
+
+    for (k, &v) in input.iter().enumerate() {
+        _mm_storeu_si128(rgb.add(16 * k) as *mut __m128i, v);
+    }
+}
+
+// Converts 32 samples to R/G/B, as four groups of 8 samples.
+#[allow(non_snake_case)]
+#[inline]
+#[target_feature(enable = "sse4.1")]
+unsafe fn YUV444ToRGB32_SSE41(
+    y: *const u8,
+    u: *const u8,
+    v: *const u8,
+) -> ([__m128i; 4], [__m128i; 4], [__m128i; 4]) {
+    let mut R = [_mm_setzero_si128(); 4];
+    let mut G = [_mm_setzero_si128(); 4];
+    let mut B = [_mm_setzero_si128(); 4];
+    for i in 0..4 {
+        let n = 8 * i as isize;
+        YUV444ToRGB_SSE41(
+            y.offset(n),
+            u.offset(n),
+            v.offset(n),
+            &mut R[i],
+            &mut G[i],
+            &mut B[i],
+        );
+    }
+    (R, G, B)
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn VP8YuvToRgb32_SSE41(y: *const u8, u: *const u8, v: *const u8, dst: *mut u8) {
+    let (R, G, B) = YUV444ToRGB32_SSE41(y, u, v);
+
+    // Cast to 8b and store as RRRRGGGGBBBB.
+    let mut rgb = [
+        _mm_packus_epi16(R[0], R[1]),
+        _mm_packus_epi16(R[2], R[3]),
+        _mm_packus_epi16(G[0], G[1]),
+        _mm_packus_epi16(G[2], G[3]),
+        _mm_packus_epi16(B[0], B[1]),
+        _mm_packus_epi16(B[2], B[3]),
+    ];
+
+    // Pack as RGBRGBRGBRGB.
+    PlanarTo24b_SSE41(&mut rgb, dst);
+}
+
+#[allow(non_snake_case)]
+#[target_feature(enable = "sse4.1")]
+pub(crate) unsafe fn VP8YuvToBgr32_SSE41(y: *const u8, u: *const u8, v: *const u8, dst: *mut u8) {
+    let (R, G, B) = YUV444ToRGB32_SSE41(y, u, v);
+
+    // Cast to 8b and store as BBBBGGGGRRRR.
+    let mut bgr = [
+        _mm_packus_epi16(B[0], B[1]),
+        _mm_packus_epi16(B[2], B[3]),
+        _mm_packus_epi16(G[0], G[1]),
+        _mm_packus_epi16(G[2], G[3]),
+        _mm_packus_epi16(R[0], R[1]),
+        _mm_packus_epi16(R[2], R[3]),
+    ];
+
+    // Pack as BGRBGRBGRBGR.
+    PlanarTo24b_SSE41(&mut bgr, dst);
+}
@@ -0,0 +1,731 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Macroblock analysis
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+
+use dec::common_dec::*;
+use dsp::dsp::BPS;
+use dsp::enc::{VP8CollectHistogram, VP8Histogram, VP8Mean16x4};
+use enc::iterator_enc::{
+    VP8IteratorImport, VP8IteratorInit, VP8IteratorIsDone, VP8IteratorNext, VP8IteratorProgress,
+    VP8IteratorRotateI4, VP8IteratorSetCountDown, VP8IteratorSetRow, VP8IteratorStartI4,
+    VP8SetIntra16Mode, VP8SetIntra4Mode, VP8SetIntraUVMode, VP8SetSegment, VP8SetSkip,
+};
+use enc::quant_enc::{
+    VP8I16ModeOffsets, VP8I4ModeOffsets, VP8MakeChroma8Preds, VP8MakeIntra4Preds,
+    VP8MakeLuma16Preds, VP8Scan, VP8UVModeOffsets,
+};
+use enc::vp8i_enc::*;
+use enc::webp_enc::WebPReportProgress;
+use utils::thread_utils::{WebPGetWorkerInterface, WebPWorker};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc, WEBP_ALIGN, WEBP_ALIGN_CST};
+
+// The C library is always built with WEBP_USE_THREAD.
+const WEBP_USE_THREAD: bool = true;
+
+const MAX_ITERS_K_MEANS: c_int = 6;
+
+//------------------------------------------------------------------------------
+// Smooth the segment map by replacing isolated block by the majority of its
+// neighbours.
+
+#[allow(non_snake_case)]
+unsafe fn SmoothSegmentMap(enc: *mut VP8Encoder) {
+    let w = (*enc).mb_w_;
+    let h = (*enc).mb_h_;
+    let majority_cnt_3_x_3_grid = 5;
+    let tmp = WebPSafeMalloc((w * h) as u64, mem::size_of::<u8>()) as *mut u8;
+    c_assert!((w * h) as u64 == w as u64 * h as u64); // no overflow, as per spec
+
+    if tmp.is_null() {
+        return;
+    }
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let mut cnt = [0; NUM_MB_SEGMENTS as usize];
+            let mb = (*enc).mb_info_.offset((x + w * y) as isize);
+            let mut majority_seg = (*mb).segment_ as c_int;
+            let w = w as isize;
+            // Check the 8 neighbouring segment values.
+            cnt[(*mb.offset(-w - 1)).segment_ as usize] += 1; // top-left
+            cnt[(*mb.offset(-w + 0)).segment_ as usize] += 1; // top
+            cnt[(*mb.offset(-w + 1)).segment_ as usize] += 1; // top-right
+            cnt[(*mb.offset(-1)).segment_ as usize] += 1; // left
+            cnt[(*mb.offset(1)).segment_ as usize] += 1; // right
+            cnt[(*mb.offset(w - 1)).segment_ as usize] += 1; // bottom-left
+            cnt[(*mb.offset(w + 0)).segment_ as usize] += 1; // bottom
+            cnt[(*mb.offset(w + 1)).segment_ as usize] += 1; // bottom-right
+            for n in 0..NUM_MB_SEGMENTS {
+                if cnt[n as usize] >= majority_cnt_3_x_3_grid {
+                    majority_seg = n;
+                    break;
+                }
+            }
+            *tmp.offset((x + y * w as c_int) as isize) = majority_seg as u8;
+        }
+    }
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let mb = (*enc).mb_info_.offset((x + w * y) as isize);
+            (*mb).segment_ = *tmp.offset((x + y * w) as isize);
+        }
+    }
+    WebPSafeFree(tmp as *mut c_void);
+}
+
+//------------------------------------------------------------------------------
+// set segment susceptibility alpha_ / beta_
+
+#[allow(non_snake_case)]
+#[inline]
+fn clip(v: c_int, m: c_int, M: c_int) -> c_int {
+    if v < m {
+        m
+    } else if v > M {
+        M
+    } else {
+        v
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn SetSegmentAlphas(
+    enc: *mut VP8Encoder,
+    centers: &[c_int; NUM_MB_SEGMENTS as usize],
+    mid: c_int,
+) {
+    let nb = (*enc).segment_hdr_.num_segments_ as usize;
+    let mut min = centers[0];
+    let mut max = centers[0];
+
+    if nb > 1 {
+        for n in 0..nb {
+            if min > centers[n] {
+                min = centers[n];
+            }
+            if max < centers[n] {
+                max = centers[n];
+            }
+        }
+    }
+    if max == min {
+        max = min + 1;
+    }
+    c_assert!(mid <= max && mid >= min);
+    for n in 0..nb {
+        let alpha = 255 * (centers[n] - mid) / (max - min);
+        let beta = 255 * (centers[n] - min) / (max - min);
+        (*enc).dqm_[n].alpha_ = clip(alpha, -127, 127);
+        (*enc).dqm_[n].beta_ = clip(beta, 0, 255);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Compute susceptibility based on DCT-coeff histograms:
+// the higher, the "easier" the macroblock is to compress.
+
+const MAX_ALPHA: c_int = 255; // 8b of precision for susceptibilities.
+const ALPHA_SCALE: c_int = 2 * MAX_ALPHA; // scaling factor for alpha.
+const DEFAULT_ALPHA: c_int = -1;
+#[allow(non_snake_case)]
+fn IS_BETTER_ALPHA(alpha: c_int, best_alpha: c_int) -> bool {
+    alpha > best_alpha
+}
+
+#[allow(non_snake_case)]
+fn FinalAlphaValue(mut alpha: c_int) -> c_int {
+    alpha = MAX_ALPHA - alpha;
+    clip(alpha, 0, MAX_ALPHA)
+}
+
+#[allow(non_snake_case)]
+fn GetAlpha(histo: &VP8Histogram) -> c_int {
+    // 'alpha' will later be clipped to [0..MAX_ALPHA] range, clamping outer
+    // values which happen to be mostly noise. This leaves the maximum precision
+    // for handling the useful small values which contribute most.
+    let max_value = histo.max_value;
+    let last_non_zero = histo.last_non_zero;
+    if max_value > 1 {
+        ALPHA_SCALE * last_non_zero / max_value
+    } else {
+        0
+    }
+}
+
+#[allow(non_snake_case)]
+fn InitHistogram(histo: &mut VP8Histogram) {
+    histo.max_value = 0;
+    histo.last_non_zero = 1;
+}
+
+#[allow(non_snake_case)]
+fn MergeHistograms(in_: &VP8Histogram, out: &mut VP8Histogram) {
+    if in_.max_value > out.max_value {
+        out.max_value = in_.max_value;
+    }
+    if in_.last_non_zero > out.last_non_zero {
+        out.last_non_zero = in_.last_non_zero;
+    }
+}
+
+//------------------------------------------------------------------------------
+// Simplified k-Means, to assign Nb segments based on alpha-histogram
+
+#[allow(non_snake_case)]
+unsafe fn AssignSegments(enc: *mut VP8Encoder, alphas: &[c_int; MAX_ALPHA as usize + 1]) {
+    // 'num_segments_' is previously validated and <= NUM_MB_SEGMENTS, but an
+    // explicit check is needed to avoid spurious warning about 'n + 1' exceeding
+    // array bounds of 'centers' with some compilers (noticed with gcc-4.9).
+    let nb = if (*enc).segment_hdr_.num_segments_ < NUM_MB_SEGMENTS {
+        (*enc).segment_hdr_.num_segments_
+    } else {
+        NUM_MB_SEGMENTS
+    };
+    let mut centers = [0; NUM_MB_SEGMENTS as usize];
+    let mut weighted_average = 0;
+    let mut map = [0; MAX_ALPHA as usize + 1];
+    // 'int' type is ok for histo, and won't overflow
+    let mut accum = [0; NUM_MB_SEGMENTS as usize];
+    let mut dist_accum = [0; NUM_MB_SEGMENTS as usize];
+
+    c_assert!(nb >= 1);
+    c_assert!(nb <= NUM_MB_SEGMENTS);
+
+    // bracket the input
+    let mut n = 0;
+    while n <= MAX_ALPHA && alphas[n as usize] == 0 {
+        n += 1;
+    }
+    let min_a = n;
+    n = MAX_ALPHA;
+    while n > min_a && alphas[n as usize] == 0 {
+        n -= 1;
+    }
+    let max_a = n;
+    let range_a = max_a - min_a;
+
+    // Spread initial centers evenly
+    n = 1;
+    for k in 0..nb {
+        c_assert!(n < 2 * nb);
+        centers[k as usize] = min_a + (n * range_a) / (2 * nb);
+        n += 2;
+    }
+
+    for _ in 0..MAX_ITERS_K_MEANS {
+        // few iters are enough
+        // Reset stats
+        for n in 0..nb as usize {
+            accum[n] = 0;
+            dist_accum[n] = 0;
+        }
+        // Assign nearest center for each 'a'
+        let mut n = 0; // track the nearest center for current 'a'
+        for a in min_a..=max_a {
+            if alphas[a as usize] != 0 {
+                while n + 1 < nb as usize && (a - centers[n + 1]).abs() < (a - centers[n]).abs() {
+                    n += 1;
+                }
+                map[a as usize] = n as c_int;
+                // accumulate contribution into best centroid
+                dist_accum[n] += a * alphas[a as usize];
+                accum[n] += alphas[a as usize];
+            }
+        }
+        // All point are classified. Move the centroids to the
+        // center of their respective cloud.
+        let mut displaced = 0;
+        weighted_average = 0;
+        let mut total_weight = 0;
+        for n in 0..nb as usize {
+            if accum[n] != 0 {
+                let new_center = (dist_accum[n] + accum[n] / 2) / accum[n];
+                displaced += (centers[n] - new_center).abs();
+                centers[n] = new_center;
+                weighted_average += new_center * accum[n];
+                total_weight += accum[n];
+            }
+        }
+        weighted_average = (weighted_average + total_weight / 2) / total_weight;
+        if displaced < 5 {
+            break; // no need to keep on looping...
+        }
+    }
+
+    // Map each original value to the closest centroid
+    for n in 0..((*enc).mb_w_ * (*enc).mb_h_) as isize {
+        let mb = &mut *(*enc).mb_info_.offset(n);
+        let alpha = mb.alpha_ as usize;
+        mb.segment_ = map[alpha] as u8;
+        mb.alpha_ = centers[map[alpha] as usize] as u8; // for the record.
+    }
+
+    if nb > 1 {
+        let smooth = (*(*enc).config_).preprocessing & 1;
+        if smooth != 0 {
+            SmoothSegmentMap(enc);
+        }
+    }
+
+    SetSegmentAlphas(enc, &centers, weighted_average); // pick some alphas.
+}
+
+//------------------------------------------------------------------------------
+// Macroblock analysis: collect histogram for each mode, deduce the maximal
+// susceptibility and set best modes for this macroblock.
+// Segment assignment is done later.
+
+// Number of modes to inspect for alpha_ evaluation. We don't need to test all
+// the possible modes during the analysis phase: we risk falling into a local
+// optimum, or be subject to boundary effect
+const MAX_INTRA16_MODE: c_int = 2;
+const MAX_INTRA4_MODE: c_int = 2;
+const MAX_UV_MODE: c_int = 2;
+
+#[allow(non_snake_case)]
+unsafe fn MBAnalyzeBestIntra16Mode(it: *mut VP8EncIterator) -> c_int {
+    let max_mode = MAX_INTRA16_MODE;
+    let mut best_alpha = DEFAULT_ALPHA;
+    let mut best_mode = 0;
+
+    VP8MakeLuma16Preds(it);
+    for mode in 0..max_mode {
+        let mut histo: VP8Histogram = mem::zeroed();
+
+        InitHistogram(&mut histo);
+        VP8CollectHistogram.unwrap()(
+            (*it).yuv_in_.offset(Y_OFF_ENC as isize),
+            (*it)
+                .yuv_p_
+                .offset(VP8I16ModeOffsets[mode as usize] as isize),
+            0,
+            16,
+            &mut histo,
+        );
+        let alpha = GetAlpha(&histo);
+        if IS_BETTER_ALPHA(alpha, best_alpha) {
+            best_alpha = alpha;
+            best_mode = mode;
+        }
+    }
+    VP8SetIntra16Mode(it, best_mode);
+    best_alpha
+}
+
+#[allow(non_snake_case)]
+unsafe fn FastMBAnalyze(it: *mut VP8EncIterator) -> c_int {
+    // Empirical cut-off value, should be around 16 (~=block size). We use the
+    // [8-17] range and favor intra4 at high quality, intra16 for low quality.
+    let q = (*(*(*it).enc_).config_).quality as c_int;
+    let kThreshold = (8 + (17 - 8) * q / 100) as u32;
+    let mut dc = [0u32; 16];
+    for k in (0..16).step_by(4) {
+        VP8Mean16x4.unwrap()(
+            (*it)
+                .yuv_in_
+                .offset((Y_OFF_ENC + k as c_int * BPS) as isize),
+            dc.as_mut_ptr().offset(k as isize),
+        );
+    }
+    let mut m: u32 = 0;
+    let mut m2: u32 = 0;
+    for k in 0..16 {
+        m = m.wrapping_add(dc[k]);
+        m2 = m2.wrapping_add(dc[k].wrapping_mul(dc[k]));
+    }
+    if kThreshold.wrapping_mul(m2) < m.wrapping_mul(m) {
+        VP8SetIntra16Mode(it, 0); // DC16
+    } else {
+        let modes = [0u8; 16]; // DC4
+        VP8SetIntra4Mode(it, modes.as_ptr());
+    }
+    0
+}
+
+#[allow(non_snake_case)]
+unsafe fn MBAnalyzeBestIntra4Mode(it: *mut VP8EncIterator, mut best_alpha: c_int) -> c_int {
+    let mut modes = [0u8; 16];
+    let max_mode = MAX_INTRA4_MODE;
+    let mut total_histo: VP8Histogram = mem::zeroed();
+    let mut cur_histo = 0;
+    InitHistogram(&mut total_histo);
+
+    VP8IteratorStartI4(it);
+    loop {
+        let mut best_mode_alpha = DEFAULT_ALPHA;
+        let mut histos: [VP8Histogram; 2] = mem::zeroed();
+        let src = (*it)
+            .yuv_in_
+            .offset((Y_OFF_ENC + VP8Scan[(*it).i4_ as usize] as c_int) as isize);
+
+        VP8MakeIntra4Preds(it);
+        for mode in 0..max_mode {
+            InitHistogram(&mut histos[cur_histo]);
+            VP8CollectHistogram.unwrap()(
+                src,
+                (*it)
+                    .yuv_p_
+                    .offset(VP8I4ModeOffsets[mode as usize] as isize),
+                0,
+                1,
+                &mut histos[cur_histo],
+            );
+            let alpha = GetAlpha(&histos[cur_histo]);
+            if IS_BETTER_ALPHA(alpha, best_mode_alpha) {
+                best_mode_alpha = alpha;
+                modes[(*it).i4_ as usize] = mode as u8;
+                cur_histo ^= 1; // keep track of best histo so far.
+            }
+        }
+        // accumulate best histogram
+        MergeHistograms(&histos[cur_histo ^ 1], &mut total_histo);
+        // Note: we reuse the original samples for predictors
+        if VP8IteratorRotateI4(it, (*it).yuv_in_.offset(Y_OFF_ENC as isize)) == 0 {
+            break;
+        }
+    }
+
+    let i4_alpha = GetAlpha(&total_histo);
+    if IS_BETTER_ALPHA(i4_alpha, best_alpha) {
+        VP8SetIntra4Mode(it, modes.as_ptr());
+        best_alpha = i4_alpha;
+    }
+    best_alpha
+}
+
+#[allow(non_snake_case)]
+unsafe fn MBAnalyzeBestUVMode(it: *mut VP8EncIterator) -> c_int {
+    let mut best_alpha = DEFAULT_ALPHA;
+    let mut smallest_alpha = 0;
+    let mut best_mode = 0;
+    let max_mode = MAX_UV_MODE;
+
+    VP8MakeChroma8Preds(it);
+    for mode in 0..max_mode {
+        let mut histo: VP8Histogram = mem::zeroed();
+        InitHistogram(&mut histo);
+        VP8CollectHistogram.unwrap()(
+            (*it).yuv_in_.offset(U_OFF_ENC as isize),
+            (*it)
+                .yuv_p_
+                .offset(VP8UVModeOffsets[mode as usize] as isize),
+            16,
+            16 + 4 + 4,
+            &mut histo,
+        );
+        let alpha = GetAlpha(&histo);
+        if IS_BETTER_ALPHA(alpha, best_alpha) {
+            best_alpha = alpha;
+        }
+        // The best prediction mode tends to be the one with the smallest alpha.
+        if mode == 0 || alpha < smallest_alpha {
+            smallest_alpha = alpha;
+            best_mode = mode;
+        }
+    }
+    VP8SetIntraUVMode(it, best_mode);
+    best_alpha
+}
+
+#[allow(non_snake_case)]
+unsafe fn MBAnalyze(
+    it: *mut VP8EncIterator,
+    alphas: &mut [c_int; MAX_ALPHA as usize + 1],
+    alpha: &mut c_int,
+    uv_alpha: &mut c_int,
+) {
+    let enc = (*it).enc_;
+    let mut best_alpha;
+
+    VP8SetIntra16Mode(it, 0); // default: Intra16, DC_PRED
+    VP8SetSkip(it, 0); // not skipped
+    VP8SetSegment(it, 0); // default segment, spec-wise.
+
+    if (*enc).method_ <= 1 {
+        best_alpha = FastMBAnalyze(it);
+    } else {
+        best_alpha = MBAnalyzeBestIntra16Mode(it);
+        if (*enc).method_ >= 5 {
+            // We go and make a fast decision for intra4/intra16.
+            // It's usually not a good and definitive pick, but helps seeding the
+            // stats about level bit-cost.
+            // TODO(skal): improve criterion.
+            best_alpha = MBAnalyzeBestIntra4Mode(it, best_alpha);
+        }
+    }
+    let best_uv_alpha = MBAnalyzeBestUVMode(it);
+
+    // Final susceptibility mix
+    best_alpha = (3 * best_alpha + best_uv_alpha + 2) >> 2;
+    best_alpha = FinalAlphaValue(best_alpha);
+    alphas[best_alpha as usize] += 1;
+    (*(*it).mb_).alpha_ = best_alpha as u8; // for later remapping.
+
+    // Accumulate for later complexity analysis.
+    *alpha += best_alpha; // mixed susceptibility (not just luma)
+    *uv_alpha += best_uv_alpha;
+}
+
+#[allow(non_snake_case)]
+fn DefaultMBInfo(mb: &mut VP8MBInfo) {
+    mb.type_ = 1; // I16x16
+    mb.uv_mode_ = 0;
+    mb.skip_ = 0; // not skipped
+    mb.segment_ = 0; // default segment
+    mb.alpha_ = 0;
+}
+
+//------------------------------------------------------------------------------
+// Main analysis loop:
+// Collect all susceptibilities for each macroblock and record their
+// distribution in alphas[]. Segments is assigned a-posteriori, based on
+// this histogram.
+// We also pick an intra16 prediction mode, which shouldn't be considered
+// final except for fast-encode settings. We can also pick some intra4 modes
+// and decide intra4/intra16, but that's usually almost always a bad choice at
+// this stage.
+
+#[allow(non_snake_case)]
+unsafe fn ResetAllMBInfo(enc: *mut VP8Encoder) {
+    for n in 0..((*enc).mb_w_ * (*enc).mb_h_) as isize {
+        DefaultMBInfo(&mut *(*enc).mb_info_.offset(n));
+    }
+    // Default susceptibilities.
+    (*enc).dqm_[0].alpha_ = 0;
+    (*enc).dqm_[0].beta_ = 0;
+    // Note: we can't compute this alpha_ / uv_alpha_ -> set to default value.
+    (*enc).alpha_ = 0;
+    (*enc).uv_alpha_ = 0;
+    WebPReportProgress((*enc).pic_, (*enc).percent_ + 20, &mut (*enc).percent_);
+}
+
+// struct used to collect job result
+#[repr(C)]
+struct SegmentJob {
+    worker: WebPWorker,
+    alphas: [c_int; MAX_ALPHA as usize + 1],
+    alpha: c_int,
+    uv_alpha: c_int,
+    it: VP8EncIterator,
+    delta_progress: c_int,
+}
+
+// main work call
+#[allow(non_snake_case)]
+extern "C-unwind" fn DoSegmentsJob(arg1: *mut c_void, arg2: *mut c_void) -> c_int {
+    unsafe {
+        let job = &mut *(arg1 as *mut SegmentJob);
+        let it = arg2 as *mut VP8EncIterator;
+        let mut ok = 1;
+        if VP8IteratorIsDone(it) == 0 {
+            let mut tmp = [0u8; 32 + WEBP_ALIGN_CST];
+            let scratch = WEBP_ALIGN(tmp.as_mut_ptr());
+            loop {
+                // Let's pretend we have perfect lossless reconstruction.
+                VP8IteratorImport(it, scratch);
+                MBAnalyze(it, &mut job.alphas, &mut job.alpha, &mut job.uv_alpha);
+                ok = VP8IteratorProgress(it, job.delta_progress);
+                if !(ok != 0 && VP8IteratorNext(it) != 0) {
+                    break;
+                }
+            }
+        }
+        ok
+    }
+}
+
+#[allow(non_snake_case)]
+fn MergeJobs(src: &SegmentJob, dst: &mut SegmentJob) {
+    for i in 0..=MAX_ALPHA as usize {
+        dst.alphas[i] += src.alphas[i];
+    }
+    dst.alpha += src.alpha;
+    dst.uv_alpha += src.uv_alpha;
+}
+
+// initialize the job struct with some TODOs
+#[allow(non_snake_case)]
+unsafe fn InitSegmentJob(
+    enc: *mut VP8Encoder,
+    job: *mut SegmentJob,
+    start_row: c_int,
+    end_row: c_int,
+) {
+    (*WebPGetWorkerInterface()).Init.unwrap()(&mut (*job).worker);
+    (*job).worker.data1 = job as *mut c_void;
+    (*job).worker.data2 = &mut (*job).it as *mut VP8EncIterator as *mut c_void;
+    (*job).worker.hook = Some(DoSegmentsJob);
+    VP8IteratorInit(enc, &mut (*job).it);
+    VP8IteratorSetRow(&mut (*job).it, start_row);
+    VP8IteratorSetCountDown(&mut (*job).it, (end_row - start_row) * (*enc).mb_w_);
+    (*job).alphas = [0; MAX_ALPHA as usize + 1];
+    (*job).alpha = 0;
+    (*job).uv_alpha = 0;
+    // only one of both jobs can record the progress, since we don't
+    // expect the user's hook to be multi-thread safe
+    (*job).delta_progress = if start_row == 0 { 20 } else { 0 };
+}
+
+// main entry point
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8EncAnalyze(enc: *mut VP8Encoder) -> c_int {
+    let mut ok = 1;
+    let do_segments = (*(*enc).config_).emulate_jpeg_size != 0 // We need the complexity evaluation.
+        || (*enc).segment_hdr_.num_segments_ > 1
+        || (*enc).method_ <= 1; // for method 0 - 1, we need preds_[] to be filled.
+    if do_segments {
+        let last_row = (*enc).mb_h_;
+        // We give a little more than a half work to the main thread.
+        let split_row = (9 * last_row + 15) >> 4;
+        let total_mb = last_row * (*enc).mb_w_;
+        let do_mt = if WEBP_USE_THREAD {
+            let kMinSplitRow = 2; // minimal rows needed for mt to be worth it
+            (*enc).thread_level_ > 0 && split_row >= kMinSplitRow
+        } else {
+            false
+        };
+        let worker_interface = &*WebPGetWorkerInterface();
+        let mut main_job: SegmentJob = mem::zeroed();
+        if do_mt {
+            let mut side_job: SegmentJob = mem::zeroed();
+            // Note the use of '&' instead of '&&' because we must call the functions
+            // no matter what.
+            InitSegmentJob(enc, &mut main_job, 0, split_row);
+            InitSegmentJob(enc, &mut side_job, split_row, last_row);
+            // we don't need to call Reset() on main_job.worker, since we're calling
+            // WebPWorkerExecute() on it
+            ok &= worker_interface.Reset.unwrap()(&mut side_job.worker);
+            // launch the two jobs in parallel
+            if ok != 0 {
+                worker_interface.Launch.unwrap()(&mut side_job.worker);
+                worker_interface.Execute.unwrap()(&mut main_job.worker);
+                ok &= worker_interface.Sync.unwrap()(&mut side_job.worker);
+                ok &= worker_interface.Sync.unwrap()(&mut main_job.worker);
+            }
+            worker_interface.End.unwrap()(&mut side_job.worker);
+            if ok != 0 {
+                MergeJobs(&side_job, &mut main_job); // merge results together
+            }
+        } else {
+            // Even for single-thread case, we use the generic Worker tools.
+            InitSegmentJob(enc, &mut main_job, 0, last_row);
+            worker_interface.Execute.unwrap()(&mut main_job.worker);
+            ok &= worker_interface.Sync.unwrap()(&mut main_job.worker);
+        }
+        worker_interface.End.unwrap()(&mut main_job.worker);
+        if ok != 0 {
+            (*enc).alpha_ = main_job.alpha / total_mb;
+            (*enc).uv_alpha_ = main_job.uv_alpha / total_mb;
+            AssignSegments(enc, &main_job.alphas);
+        }
+    } else {
+        // Use only one default segment.
+        ResetAllMBInfo(enc);
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use encode::EncoderConfig;
+    use test_util::{check_parity, lossy_images, stage_encoders, Digest, StageEncoder};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8EncAnalyze(enc: *mut VP8Encoder) -> c_int;
+    }
+
+    // The settings VP8EncAnalyze() depends on.
+    fn configs() -> Vec<EncoderConfig> {
+        let base = EncoderConfig::default();
+        let mut configs: Vec<_> = (0..=6)
+            .map(|method| EncoderConfig { method, ..base })
+            .collect();
+        configs.extend_from_slice(&[
+            EncoderConfig {
+                segments: 1,
+                ..base
+            },
+            EncoderConfig {
+                segments: 2,
+                sns_strength: 100,
+                ..base
+            },
+            EncoderConfig {
+                segments: 3,
+                sns_strength: 0,
+                method: 1,
+                ..base
+            },
+            EncoderConfig {
+                segments: 1,
+                emulate_jpeg_size: true,
+                ..base
+            },
+            EncoderConfig {
+                thread_level: 1,
+                ..base
+            },
+        ]);
+        configs
+    }
+
+    // What VP8EncAnalyze() sets: the susceptibilities, the segment header,
+    // the macroblock infos and the intra4 modes.
+    fn analysis(encoder: &StageEncoder) -> (Vec<c_int>, Vec<[u8; 5]>, Vec<u8>) {
+        let enc = unsafe { &*encoder.enc };
+        let mut values = vec![
+            enc.alpha_,
+            enc.uv_alpha_,
+            enc.segment_hdr_.num_segments_,
+            enc.segment_hdr_.update_map_,
+            enc.segment_hdr_.size_,
+        ];
+        for dqm in &enc.dqm_ {
+            values.extend_from_slice(&[dqm.alpha_, dqm.beta_]);
+        }
+        (values, encoder.mb_info(), encoder.preds())
+    }
+
+    #[test]
+    fn test_analyze_parity() {
+        let mut digest = Digest::new();
+        for (width, height, rgba) in lossy_images() {
+            for config in &configs() {
+                let encs = stage_encoders(&rgba, width, height, config, |_| ());
+                assert_ne!(unsafe { VP8EncAnalyze(encs[0].enc) }, 0);
+                let (values, mb_info, preds) = analysis(&encs[0]);
+                digest.update_i32(&values);
+                digest.update(&mb_info.concat());
+                digest.update(&preds);
+                #[cfg(feature = "c-reference")]
+                {
+                    assert_ne!(unsafe { WebPRef_VP8EncAnalyze(encs[1].enc) }, 0);
+                    assert!(
+                        analysis(&encs[1]) == (values, mb_info, preds),
+                        "{}x{} image, {:?}",
+                        width,
+                        height,
+                        config
+                    );
+                }
+            }
+        }
+        check_parity("enc::analysis_enc::tests::test_analyze_parity", digest);
+    }
+}
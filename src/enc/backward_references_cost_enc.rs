@@ -0,0 +1,969 @@
+// Copyright 2017 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Improves a given set of backward references by analyzing its bit cost.
+// The algorithm is similar to the Zopfli compression algorithm but tailored to
+// images.
+//
+// Author: Vincent Rabaud (vrabaud@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::lossless_enc::{VP8LFastLog2, VP8LPrefixEncodeBits};
+use enc::backward_references_enc::{
+    PixOrCopyCreateCacheIdx, PixOrCopyCreateCopy, PixOrCopyCreateLiteral, VP8LBackwardRefs,
+    VP8LBackwardRefsCursorAdd, VP8LClearBackwardRefs, VP8LDistanceToPlaneCode, VP8LHashChain,
+    VP8LHashChainFindCopy, VP8LHashChainFindOffset, VP8LRefsCursorInit, VP8LRefsCursorNext,
+    VP8LRefsCursorOk, MAX_LENGTH,
+};
+use enc::histogram_enc::{
+    VP8LAllocateHistogram, VP8LFreeHistogram, VP8LHistogramAddSinglePixOrCopy, VP8LHistogramInit,
+    VP8LHistogramNumCodes,
+};
+use format_constants::{NUM_DISTANCE_CODES, NUM_LENGTH_CODES, NUM_LITERAL_CODES};
+use utils::color_cache_utils::{
+    VP8LColorCache, VP8LColorCacheClear, VP8LColorCacheContains, VP8LColorCacheInit,
+    VP8LColorCacheInsert,
+};
+use utils::utils::{WebPSafeCalloc, WebPSafeFree, WebPSafeMalloc};
+
+const VALUES_IN_BYTE: usize = 256;
+
+#[repr(C)]
+struct CostModel {
+    alpha_: [c_double; VALUES_IN_BYTE],
+    red_: [c_double; VALUES_IN_BYTE],
+    blue_: [c_double; VALUES_IN_BYTE],
+    distance_: [c_double; NUM_DISTANCE_CODES as usize],
+    literal_: *mut c_double,
+}
+
+#[allow(non_snake_case)]
+unsafe fn ConvertPopulationCountTableToBitEstimates(
+    num_symbols: c_int,
+    population_counts: *const u32,
+    output: *mut c_double,
+) {
+    let population_counts = ::std::slice::from_raw_parts(population_counts, num_symbols as usize);
+    let output = ::std::slice::from_raw_parts_mut(output, num_symbols as usize);
+    let mut sum: u32 = 0;
+    let mut nonzeros = 0;
+    for &count in population_counts {
+        sum = sum.wrapping_add(count);
+        if count > 0 {
+            nonzeros += 1;
+        }
+    }
+    if nonzeros <= 1 {
+        for out in output.iter_mut() {
+            *out = 0.0;
+        }
+    } else {
+        let logsum = VP8LFastLog2(sum) as c_double;
+        for (out, &count) in output.iter_mut().zip(population_counts) {
+            *out = logsum - VP8LFastLog2(count) as c_double;
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn CostModelBuild(
+    m: &mut CostModel,
+    xsize: c_int,
+    cache_bits: c_int,
+    refs: *const VP8LBackwardRefs,
+) -> c_int {
+    let mut c = VP8LRefsCursorInit(refs);
+    let histo = VP8LAllocateHistogram(cache_bits);
+    if histo.is_null() {
+        return 0;
+    }
+
+    // The following code is similar to VP8LHistogramCreate but converts the
+    // distance to plane code.
+    VP8LHistogramInit(histo, cache_bits);
+    while VP8LRefsCursorOk(&c) {
+        VP8LHistogramAddSinglePixOrCopy(histo, c.cur_pos, Some(VP8LDistanceToPlaneCode), xsize);
+        VP8LRefsCursorNext(&mut c);
+    }
+
+    {
+        let histo = &*histo;
+        ConvertPopulationCountTableToBitEstimates(
+            VP8LHistogramNumCodes(histo.palette_code_bits_),
+            histo.literal_,
+            m.literal_,
+        );
+        ConvertPopulationCountTableToBitEstimates(
+            VALUES_IN_BYTE as c_int,
+            histo.red_.as_ptr(),
+            m.red_.as_mut_ptr(),
+        );
+        ConvertPopulationCountTableToBitEstimates(
+            VALUES_IN_BYTE as c_int,
+            histo.blue_.as_ptr(),
+            m.blue_.as_mut_ptr(),
+        );
+        ConvertPopulationCountTableToBitEstimates(
+            VALUES_IN_BYTE as c_int,
+            histo.alpha_.as_ptr(),
+            m.alpha_.as_mut_ptr(),
+        );
+        ConvertPopulationCountTableToBitEstimates(
+            NUM_DISTANCE_CODES,
+            histo.distance_.as_ptr(),
+            m.distance_.as_mut_ptr(),
+        );
+    }
+
+    VP8LFreeHistogram(histo);
+    1
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetLiteralCost(m: &CostModel, v: u32) -> c_double {
+    m.alpha_[(v >> 24) as usize]
+        + m.red_[((v >> 16) & 0xff) as usize]
+        + *m.literal_.add(((v >> 8) & 0xff) as usize)
+        + m.blue_[(v & 0xff) as usize]
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetCacheCost(m: &CostModel, idx: u32) -> c_double {
+    let literal_idx = VALUES_IN_BYTE + NUM_LENGTH_CODES as usize + idx as usize;
+    *m.literal_.add(literal_idx)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetLengthCost(m: &CostModel, length: u32) -> c_double {
+    let mut code = 0;
+    let mut extra_bits = 0;
+    VP8LPrefixEncodeBits(length as c_int, &mut code, &mut extra_bits);
+    *m.literal_.add(VALUES_IN_BYTE + code as usize) + extra_bits as c_double
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn GetDistanceCost(m: &CostModel, distance: u32) -> c_double {
+    let mut code = 0;
+    let mut extra_bits = 0;
+    VP8LPrefixEncodeBits(distance as c_int, &mut code, &mut extra_bits);
+    m.distance_[code as usize] + extra_bits as c_double
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn AddSingleLiteralWithCostModel(
+    argb: *const u32,
+    hashers: &VP8LColorCache,
+    cost_model: &CostModel,
+    idx: c_int,
+    use_color_cache: bool,
+    prev_cost: c_float,
+    cost: *mut c_float,
+    dist_array: *mut u16,
+) {
+    let mut cost_val = prev_cost as c_double;
+    let color = *argb.offset(idx as isize);
+    let ix = if use_color_cache {
+        VP8LColorCacheContains(hashers, color)
+    } else {
+        -1
+    };
+    if ix >= 0 {
+        // use_color_cache is true and hashers contains color
+        let mul0 = 0.68;
+        cost_val += GetCacheCost(cost_model, ix as u32) * mul0;
+    } else {
+        let mul1 = 0.82;
+        if use_color_cache {
+            VP8LColorCacheInsert(hashers, color);
+        }
+        cost_val += GetLiteralCost(cost_model, color) * mul1;
+    }
+    if *cost.offset(idx as isize) as c_double > cost_val {
+        *cost.offset(idx as isize) = cost_val as c_float;
+        *dist_array.offset(idx as isize) = 1; // only one is inserted.
+    }
+}
+
+// -----------------------------------------------------------------------------
+// CostManager and interval handling
+
+// Empirical value to avoid high memory consumption but good for performance.
+const COST_CACHE_INTERVAL_SIZE_MAX: c_int = 500;
+
+// To perform backward reference every pixel at index index_ is considered and
+// the cost for the MAX_LENGTH following pixels computed. Those following pixels
+// at index index_ + k (k from 0 to MAX_LENGTH) have a cost of:
+//     cost_ = distance cost at index + GetLengthCost(cost_model, k)
+// and the minimum value is kept. GetLengthCost(cost_model, k) is cached in an
+// array of size MAX_LENGTH.
+// Instead of performing MAX_LENGTH comparisons per pixel, we keep track of the
+// minimal values using intervals of constant cost.
+// An interval is defined by the index_ of the pixel that generated it and
+// is only useful in a range of indices from start_ to end_ (exclusive), i.e.
+// it contains the minimum value for pixels between start_ and end_.
+// Intervals are stored in a linked list and ordered by start_. When a new
+// interval has a better value, old intervals are split or removed. There are
+// therefore no overlapping intervals.
+#[repr(C)]
+struct CostInterval {
+    cost_: c_float,
+    start_: c_int,
+    end_: c_int,
+    index_: c_int,
+    previous_: *mut CostInterval,
+    next_: *mut CostInterval,
+}
+
+// The GetLengthCost(cost_model, k) are cached in a CostCacheInterval.
+#[repr(C)]
+struct CostCacheInterval {
+    cost_: c_double,
+    start_: c_int,
+    end_: c_int, // Exclusive.
+}
+
+// This structure is in charge of managing intervals and costs.
+// It caches the different CostCacheInterval, caches the different
+// GetLengthCost(cost_model, k) in cost_cache_ and the CostInterval's (whose
+// count_ is limited by COST_CACHE_INTERVAL_SIZE_MAX).
+const COST_MANAGER_MAX_FREE_LIST: usize = 10;
+#[repr(C)]
+struct CostManager {
+    head_: *mut CostInterval,
+    count_: c_int, // The number of stored intervals.
+    cache_intervals_: *mut CostCacheInterval,
+    cache_intervals_size_: usize,
+    cost_cache_: [c_double; MAX_LENGTH as usize], // Contains the GetLengthCost(cost_model, k).
+    costs_: *mut c_float,
+    dist_array_: *mut u16,
+    // Most of the time, we only need few intervals -> use a free-list, to avoid
+    // fragmentation with small allocs in most common cases.
+    intervals_: [CostInterval; COST_MANAGER_MAX_FREE_LIST],
+    free_intervals_: *mut CostInterval,
+    // These are regularly malloc'd remains. This list can't grow larger than than
+    // size COST_CACHE_INTERVAL_SIZE_MAX - COST_MANAGER_MAX_FREE_LIST, note.
+    recycled_intervals_: *mut CostInterval,
+}
+
+#[allow(non_snake_case)]
+unsafe fn CostIntervalAddToFreeList(manager: &mut CostManager, interval: *mut CostInterval) {
+    (*interval).next_ = manager.free_intervals_;
+    manager.free_intervals_ = interval;
+}
+
+#[allow(non_snake_case)]
+fn CostIntervalIsInFreeList(manager: &CostManager, interval: *const CostInterval) -> bool {
+    interval >= &manager.intervals_[0] as *const _
+        && interval <= &manager.intervals_[COST_MANAGER_MAX_FREE_LIST - 1] as *const _
+}
+
+#[allow(non_snake_case)]
+unsafe fn CostManagerInitFreeList(manager: &mut CostManager) {
+    manager.free_intervals_ = ptr::null_mut();
+    for i in 0..COST_MANAGER_MAX_FREE_LIST {
+        let interval: *mut CostInterval = &mut manager.intervals_[i];
+        CostIntervalAddToFreeList(manager, interval);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn DeleteIntervalList(manager: &CostManager, mut interval: *const CostInterval) {
+    while !interval.is_null() {
+        let next = (*interval).next_;
+        if !CostIntervalIsInFreeList(manager, interval) {
+            WebPSafeFree(interval as *mut c_void);
+        } // else: do nothing
+        interval = next;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn CostManagerClear(manager: *mut CostManager) {
+    if manager.is_null() {
+        return;
+    }
+    let manager = &mut *manager;
+
+    WebPSafeFree(manager.costs_ as *mut c_void);
+    WebPSafeFree(manager.cache_intervals_ as *mut c_void);
+
+    // Clear the interval lists.
+    DeleteIntervalList(manager, manager.head_);
+    manager.head_ = ptr::null_mut();
+    DeleteIntervalList(manager, manager.recycled_intervals_);
+    manager.recycled_intervals_ = ptr::null_mut();
+
+    // Reset pointers, count_ and cache_intervals_size_.
+    ptr::write_bytes(manager as *mut CostManager, 0, 1);
+    CostManagerInitFreeList(manager);
+}
+
+#[allow(non_snake_case)]
+unsafe fn CostManagerInit(
+    manager: &mut CostManager,
+    dist_array: *mut u16,
+    pix_count: c_int,
+    cost_model: &CostModel,
+) -> c_int {
+    let cost_cache_size = if pix_count > MAX_LENGTH {
+        MAX_LENGTH
+    } else {
+        pix_count
+    } as usize;
+
+    manager.costs_ = ptr::null_mut();
+    manager.cache_intervals_ = ptr::null_mut();
+    manager.head_ = ptr::null_mut();
+    manager.recycled_intervals_ = ptr::null_mut();
+    manager.count_ = 0;
+    manager.dist_array_ = dist_array;
+    CostManagerInitFreeList(manager);
+
+    // Fill in the cost_cache_.
+    manager.cache_intervals_size_ = 1;
+    manager.cost_cache_[0] = GetLengthCost(cost_model, 0);
+    for i in 1..cost_cache_size {
+        manager.cost_cache_[i] = GetLengthCost(cost_model, i as u32);
+        // Get the number of bound intervals.
+        if manager.cost_cache_[i] != manager.cost_cache_[i - 1] {
+            manager.cache_intervals_size_ += 1;
+        }
+    }
+
+    // With the current cost model, we usually have below 20 intervals.
+    // The worst case scenario with a cost model would be if every length has a
+    // different cost, hence MAX_LENGTH but that is impossible with the current
+    // implementation that spirals around a pixel.
+    c_assert!(manager.cache_intervals_size_ <= MAX_LENGTH as usize);
+    manager.cache_intervals_ = WebPSafeMalloc(
+        manager.cache_intervals_size_ as u64,
+        mem::size_of::<CostCacheInterval>(),
+    ) as *mut CostCacheInterval;
+    if manager.cache_intervals_.is_null() {
+        CostManagerClear(manager);
+        return 0;
+    }
+
+    // Fill in the cache_intervals_.
+    {
+        let mut cur = manager.cache_intervals_;
+
+        // Consecutive values in cost_cache_ are compared and if a big enough
+        // difference is found, a new interval is created and bounded.
+        (*cur).start_ = 0;
+        (*cur).end_ = 1;
+        (*cur).cost_ = manager.cost_cache_[0];
+        for i in 1..cost_cache_size {
+            let cost_val = manager.cost_cache_[i];
+            if cost_val != (*cur).cost_ {
+                cur = cur.add(1);
+                // Initialize an interval.
+                (*cur).start_ = i as c_int;
+                (*cur).cost_ = cost_val;
+            }
+            (*cur).end_ = i as c_int + 1;
+        }
+    }
+
+    manager.costs_ = WebPSafeMalloc(pix_count as u64, mem::size_of::<c_float>()) as *mut c_float;
+    if manager.costs_.is_null() {
+        CostManagerClear(manager);
+        return 0;
+    }
+    // Set the initial costs_ high for every pixel as we will keep the minimum.
+    for i in 0..pix_count as usize {
+        *manager.costs_.add(i) = 1e38;
+    }
+
+    1
+}
+
+// Given the cost and the position that define an interval, update the cost at
+// pixel 'i' if it is smaller than the previously computed value.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn UpdateCost(manager: &mut CostManager, i: c_int, position: c_int, cost: c_float) {
+    let k = i - position;
+    c_assert!(k >= 0 && k < MAX_LENGTH);
+
+    if *manager.costs_.offset(i as isize) > cost {
+        *manager.costs_.offset(i as isize) = cost;
+        *manager.dist_array_.offset(i as isize) = (k + 1) as u16;
+    }
+}
+
+// Given the cost and the position that define an interval, update the cost for
+// all the pixels between 'start' and 'end' excluded.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn UpdateCostPerInterval(
+    manager: &mut CostManager,
+    start: c_int,
+    end: c_int,
+    position: c_int,
+    cost: c_float,
+) {
+    for i in start..end {
+        UpdateCost(manager, i, position, cost);
+    }
+}
+
+// Given two intervals, make 'prev' be the previous one of 'next' in 'manager'.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn ConnectIntervals(
+    manager: &mut CostManager,
+    prev: *mut CostInterval,
+    next: *mut CostInterval,
+) {
+    if !prev.is_null() {
+        (*prev).next_ = next;
+    } else {
+        manager.head_ = next;
+    }
+
+    if !next.is_null() {
+        (*next).previous_ = prev;
+    }
+}
+
+// Pop an interval in the manager.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn PopInterval(manager: &mut CostManager, interval: *mut CostInterval) {
+    if interval.is_null() {
+        return;
+    }
+
+    ConnectIntervals(manager, (*interval).previous_, (*interval).next_);
+    if CostIntervalIsInFreeList(manager, interval) {
+        CostIntervalAddToFreeList(manager, interval);
+    } else {
+        // recycle regularly malloc'd intervals too
+        (*interval).next_ = manager.recycled_intervals_;
+        manager.recycled_intervals_ = interval;
+    }
+    manager.count_ -= 1;
+    c_assert!(manager.count_ >= 0);
+}
+
+// Update the cost at index i by going over all the stored intervals that
+// overlap with i.
+// If 'do_clean_intervals' is set to something different than 0, intervals that
+// end before 'i' will be popped.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn UpdateCostAtIndex(manager: &mut CostManager, i: c_int, do_clean_intervals: bool) {
+    let mut current = manager.head_;
+
+    while !current.is_null() && (*current).start_ <= i {
+        let next = (*current).next_;
+        if (*current).end_ <= i {
+            if do_clean_intervals {
+                // We have an outdated interval, remove it.
+                PopInterval(manager, current);
+            }
+        } else {
+            UpdateCost(manager, i, (*current).index_, (*current).cost_);
+        }
+        current = next;
+    }
+}
+
+// Given a current orphan interval and its previous interval, before
+// it was orphaned (which can be NULL), set it at the right place in the list
+// of intervals using the start_ ordering and the previous interval as a hint.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn PositionOrphanInterval(
+    manager: &mut CostManager,
+    current: *mut CostInterval,
+    mut previous: *mut CostInterval,
+) {
+    c_assert!(!current.is_null());
+
+    if previous.is_null() {
+        previous = manager.head_;
+    }
+    while !previous.is_null() && (*current).start_ < (*previous).start_ {
+        previous = (*previous).previous_;
+    }
+    while !previous.is_null()
+        && !(*previous).next_.is_null()
+        && (*(*previous).next_).start_ < (*current).start_
+    {
+        previous = (*previous).next_;
+    }
+
+    if !previous.is_null() {
+        ConnectIntervals(manager, current, (*previous).next_);
+    } else {
+        let head = manager.head_;
+        ConnectIntervals(manager, current, head);
+    }
+    ConnectIntervals(manager, previous, current);
+}
+
+// Insert an interval in the list contained in the manager by starting at
+// interval_in as a hint. The intervals are sorted by start_ value.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn InsertInterval(
+    manager: &mut CostManager,
+    interval_in: *mut CostInterval,
+    cost: c_float,
+    position: c_int,
+    start: c_int,
+    end: c_int,
+) {
+    if start >= end {
+        return;
+    }
+    if manager.count_ >= COST_CACHE_INTERVAL_SIZE_MAX {
+        // Serialize the interval if we cannot store it.
+        UpdateCostPerInterval(manager, start, end, position, cost);
+        return;
+    }
+    let interval_new;
+    if !manager.free_intervals_.is_null() {
+        interval_new = manager.free_intervals_;
+        manager.free_intervals_ = (*interval_new).next_;
+    } else if !manager.recycled_intervals_.is_null() {
+        interval_new = manager.recycled_intervals_;
+        manager.recycled_intervals_ = (*interval_new).next_;
+    } else {
+        // malloc for good
+        interval_new = WebPSafeMalloc(1, mem::size_of::<CostInterval>()) as *mut CostInterval;
+        if interval_new.is_null() {
+            // Write down the interval if we cannot create it.
+            UpdateCostPerInterval(manager, start, end, position, cost);
+            return;
+        }
+    }
+
+    (*interval_new).cost_ = cost;
+    (*interval_new).index_ = position;
+    (*interval_new).start_ = start;
+    (*interval_new).end_ = end;
+    PositionOrphanInterval(manager, interval_new, interval_in);
+
+    manager.count_ += 1;
+}
+
+// Given a new cost interval defined by its start at position, its length value
+// and distance_cost, add its contributions to the previous intervals and costs.
+// If handling the interval or one of its subintervals becomes to heavy, its
+// contribution is added to the costs right away.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn PushInterval(
+    manager: &mut CostManager,
+    distance_cost: c_double,
+    position: c_int,
+    len: c_int,
+) {
+    let mut interval = manager.head_;
+    let cost_cache_intervals = manager.cache_intervals_;
+    // If the interval is small enough, no need to deal with the heavy
+    // interval logic, just serialize it right away. This constant is empirical.
+    #[allow(non_upper_case_globals)]
+    const kSkipDistance: c_int = 10;
+
+    if len < kSkipDistance {
+        for j in position..position + len {
+            let k = j - position;
+            c_assert!(k >= 0 && k < MAX_LENGTH);
+            let cost_tmp = (distance_cost + manager.cost_cache_[k as usize]) as c_float;
+
+            if *manager.costs_.offset(j as isize) > cost_tmp {
+                *manager.costs_.offset(j as isize) = cost_tmp;
+                *manager.dist_array_.offset(j as isize) = (k + 1) as u16;
+            }
+        }
+        return;
+    }
+
+    let mut i = 0;
+    while i < manager.cache_intervals_size_ && (*cost_cache_intervals.add(i)).start_ < len {
+        let cache_interval = &*cost_cache_intervals.add(i);
+        // Define the intersection of the ith interval with the new one.
+        let mut start = position + cache_interval.start_;
+        let end = position
+            + if cache_interval.end_ > len {
+                len
+            } else {
+                cache_interval.end_
+            };
+        let cost = (distance_cost + cache_interval.cost_) as c_float;
+
+        while !interval.is_null() && (*interval).start_ < end {
+            let interval_next = (*interval).next_;
+
+            // Make sure we have some overlap
+            if start >= (*interval).end_ {
+                interval = interval_next;
+                continue;
+            }
+
+            if cost >= (*interval).cost_ {
+                // When intervals are represented, the lower, the better.
+                // [**********************************************************[
+                // start                                                    end
+                //                   [----------------------------------[
+                //                   interval->start_       interval->end_
+                // If we are worse than what we already have, add whatever we have so
+                // far up to interval.
+                let start_new = (*interval).end_;
+                let interval_start = (*interval).start_;
+                InsertInterval(manager, interval, cost, position, start, interval_start);
+                start = start_new;
+                if start >= end {
+                    break;
+                }
+                interval = interval_next;
+                continue;
+            }
+
+            if start <= (*interval).start_ {
+                if (*interval).end_ <= end {
+                    //                   [----------------------------------[
+                    //                   interval->start_       interval->end_
+                    // [**************************************************************[
+                    // start                                                        end
+                    // We can safely remove the old interval as it is fully included.
+                    PopInterval(manager, interval);
+                } else {
+                    //              [------------------------------------[
+                    //              interval->start_        interval->end_
+                    // [*****************************[
+                    // start                       end
+                    (*interval).start_ = end;
+                    break;
+                }
+            } else if end < (*interval).end_ {
+                // [--------------------------------------------------------------[
+                // interval->start_                                  interval->end_
+                //                     [*****************************[
+                //                     start                       end
+                // We have to split the old interval as it fully contains the new one.
+                let end_original = (*interval).end_;
+                (*interval).end_ = start;
+                let (interval_cost, interval_index) = ((*interval).cost_, (*interval).index_);
+                InsertInterval(
+                    manager,
+                    interval,
+                    interval_cost,
+                    interval_index,
+                    end,
+                    end_original,
+                );
+                interval = (*interval).next_;
+                break;
+            } else {
+                // [------------------------------------[
+                // interval->start_        interval->end_
+                //                     [*****************************[
+                //                     start                       end
+                (*interval).end_ = start;
+            }
+            interval = interval_next;
+        }
+        // Insert the remaining interval from start to end.
+        InsertInterval(manager, interval, cost, position, start, end);
+        i += 1;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn BackwardReferencesHashChainDistanceOnly(
+    xsize: c_int,
+    ysize: c_int,
+    argb: *const u32,
+    cache_bits: c_int,
+    hash_chain: &VP8LHashChain,
+    refs: *const VP8LBackwardRefs,
+    dist_array: *mut u16,
+) -> c_int {
+    let mut ok = 0;
+    let mut cc_init = 0;
+    let pix_count = xsize * ysize;
+    let use_color_cache = cache_bits > 0;
+    let literal_array_size = mem::size_of::<c_double>()
+        * (NUM_LITERAL_CODES + NUM_LENGTH_CODES + if cache_bits > 0 { 1 << cache_bits } else { 0 })
+            as usize;
+    let cost_model_size = mem::size_of::<CostModel>() + literal_array_size;
+    let cost_model = WebPSafeCalloc(1, cost_model_size) as *mut CostModel;
+    let mut hashers: VP8LColorCache = mem::zeroed();
+    let cost_manager = WebPSafeCalloc(1, mem::size_of::<CostManager>()) as *mut CostManager;
+    let mut offset_prev: c_int = -1;
+    let mut len_prev: c_int = -1;
+    let mut offset_cost: c_double = -1.0;
+    let mut first_offset_is_constant: c_int = -1; // initialized with 'impossible' value
+    let mut reach: c_int = 0;
+
+    'error: {
+        if cost_model.is_null() || cost_manager.is_null() {
+            break 'error;
+        }
+        let cost_model = &mut *cost_model;
+        let cost_manager = &mut *cost_manager;
+
+        cost_model.literal_ = (cost_model as *mut CostModel).add(1) as *mut c_double;
+        if use_color_cache {
+            cc_init = VP8LColorCacheInit(&mut hashers, cache_bits);
+            if cc_init == 0 {
+                break 'error;
+            }
+        }
+
+        if CostModelBuild(cost_model, xsize, cache_bits, refs) == 0 {
+            break 'error;
+        }
+
+        if CostManagerInit(cost_manager, dist_array, pix_count, cost_model) == 0 {
+            break 'error;
+        }
+
+        // We loop one pixel at a time, but store all currently best points to
+        // non-processed locations from this point.
+        *dist_array = 0;
+        // Add first pixel as literal.
+        AddSingleLiteralWithCostModel(
+            argb,
+            &hashers,
+            cost_model,
+            0,
+            use_color_cache,
+            0.0,
+            cost_manager.costs_,
+            dist_array,
+        );
+
+        for i in 1..pix_count {
+            let prev_cost = *cost_manager.costs_.offset(i as isize - 1);
+            let mut offset = 0;
+            let mut len = 0;
+            VP8LHashChainFindCopy(hash_chain, i, &mut offset, &mut len);
+
+            // Try adding the pixel as a literal.
+            AddSingleLiteralWithCostModel(
+                argb,
+                &hashers,
+                cost_model,
+                i,
+                use_color_cache,
+                prev_cost,
+                cost_manager.costs_,
+                dist_array,
+            );
+
+            // If we are dealing with a non-literal.
+            if len >= 2 {
+                if offset != offset_prev {
+                    let code = VP8LDistanceToPlaneCode(xsize, offset);
+                    offset_cost = GetDistanceCost(cost_model, code as u32);
+                    first_offset_is_constant = 1;
+                    PushInterval(cost_manager, prev_cost as c_double + offset_cost, i, len);
+                } else {
+                    c_assert!(offset_cost >= 0.0);
+                    c_assert!(len_prev >= 0);
+                    c_assert!(first_offset_is_constant == 0 || first_offset_is_constant == 1);
+                    // Instead of considering all contributions from a pixel i by calling:
+                    //         PushInterval(cost_manager, prev_cost + offset_cost, i, len);
+                    // we optimize these contributions in case offset_cost stays the same
+                    // for consecutive pixels. This describes a set of pixels similar to a
+                    // previous set (e.g. constant color regions).
+                    if first_offset_is_constant != 0 {
+                        reach = i - 1 + len_prev - 1;
+                        first_offset_is_constant = 0;
+                    }
+
+                    if i + len - 1 > reach {
+                        // We can only be go further with the same offset if the previous
+                        // length was maxed, hence len_prev == len == MAX_LENGTH.
+                        // TODO(vrabaud), bump i to the end right away (insert cache and
+                        // update cost).
+                        // TODO(vrabaud), check if one of the points in between does not have
+                        // a lower cost.
+                        // Already consider the pixel at "reach" to add intervals that are
+                        // better than whatever we add.
+                        let mut offset_j = 0;
+                        let mut len_j = 0;
+                        c_assert!(len == MAX_LENGTH || len == pix_count - i);
+                        // Figure out the last consecutive pixel within [i, reach + 1] with
+                        // the same offset.
+                        let mut j = i;
+                        while j <= reach {
+                            VP8LHashChainFindCopy(hash_chain, j + 1, &mut offset_j, &mut len_j);
+                            if offset_j != offset {
+                                VP8LHashChainFindCopy(hash_chain, j, &mut offset_j, &mut len_j);
+                                break;
+                            }
+                            j += 1;
+                        }
+                        // Update the cost at j - 1 and j.
+                        UpdateCostAtIndex(cost_manager, j - 1, false);
+                        UpdateCostAtIndex(cost_manager, j, false);
+
+                        let cost_j = *cost_manager.costs_.offset(j as isize - 1) as c_double;
+                        PushInterval(cost_manager, cost_j + offset_cost, j, len_j);
+                        reach = j + len_j - 1;
+                    }
+                }
+            }
+
+            UpdateCostAtIndex(cost_manager, i, true);
+            offset_prev = offset;
+            len_prev = len;
+        }
+
+        ok = ((*refs).error_ == 0) as c_int;
+    }
+    if cc_init != 0 {
+        VP8LColorCacheClear(&mut hashers);
+    }
+    CostManagerClear(cost_manager);
+    WebPSafeFree(cost_model as *mut c_void);
+    WebPSafeFree(cost_manager as *mut c_void);
+    ok
+}
+
+// We pack the path at the end of *dist_array and return
+// a pointer to this part of the array. Example:
+// dist_array = [1x2xx3x2] => packed [1x2x1232], chosen_path = [1232]
+#[allow(non_snake_case)]
+unsafe fn TraceBackwards(dist_array: *mut u16, dist_array_size: c_int) -> (*mut u16, c_int) {
+    let mut path = dist_array.offset(dist_array_size as isize);
+    let mut cur = dist_array_size as isize - 1;
+    while cur >= 0 {
+        let k = *dist_array.offset(cur);
+        path = path.offset(-1);
+        *path = k;
+        cur -= k as isize;
+    }
+    let chosen_path_size = (dist_array.offset(dist_array_size as isize) as usize - path as usize)
+        / mem::size_of::<u16>();
+    (path, chosen_path_size as c_int)
+}
+
+#[allow(non_snake_case)]
+unsafe fn BackwardReferencesHashChainFollowChosenPath(
+    argb: *const u32,
+    cache_bits: c_int,
+    chosen_path: *const u16,
+    chosen_path_size: c_int,
+    hash_chain: &VP8LHashChain,
+    refs: *mut VP8LBackwardRefs,
+) -> c_int {
+    let use_color_cache = cache_bits > 0;
+    let mut i: c_int = 0;
+    let mut hashers: VP8LColorCache = mem::zeroed();
+
+    if use_color_cache && VP8LColorCacheInit(&mut hashers, cache_bits) == 0 {
+        return 0;
+    }
+
+    VP8LClearBackwardRefs(refs);
+    for ix in 0..chosen_path_size as usize {
+        let len = *chosen_path.add(ix) as c_int;
+        if len != 1 {
+            let offset = VP8LHashChainFindOffset(hash_chain, i);
+            VP8LBackwardRefsCursorAdd(refs, PixOrCopyCreateCopy(offset as u32, len as u16));
+            if use_color_cache {
+                for k in 0..len {
+                    VP8LColorCacheInsert(&hashers, *argb.offset((i + k) as isize));
+                }
+            }
+            i += len;
+        } else {
+            let argb_i = *argb.offset(i as isize);
+            let idx = if use_color_cache {
+                VP8LColorCacheContains(&hashers, argb_i)
+            } else {
+                -1
+            };
+            let v = if idx >= 0 {
+                // use_color_cache is true and hashers contains argb[i]
+                // push pixel as a color cache index
+                PixOrCopyCreateCacheIdx(idx)
+            } else {
+                if use_color_cache {
+                    VP8LColorCacheInsert(&hashers, argb_i);
+                }
+                PixOrCopyCreateLiteral(argb_i)
+            };
+            VP8LBackwardRefsCursorAdd(refs, v);
+            i += 1;
+        }
+    }
+    let ok = ((*refs).error_ == 0) as c_int;
+    if use_color_cache {
+        VP8LColorCacheClear(&mut hashers);
+    }
+    ok
+}
+
+// Returns 1 on success.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBackwardReferencesTraceBackwards(
+    xsize: c_int,
+    ysize: c_int,
+    argb: *const u32,
+    cache_bits: c_int,
+    hash_chain: *const VP8LHashChain,
+    refs_src: *const VP8LBackwardRefs,
+    refs_dst: *mut VP8LBackwardRefs,
+) -> c_int {
+    let mut ok = 0;
+    let dist_array_size = xsize * ysize;
+    let dist_array = WebPSafeMalloc(dist_array_size as u64, mem::size_of::<u16>()) as *mut u16;
+
+    'error: {
+        if dist_array.is_null() {
+            break 'error;
+        }
+
+        if BackwardReferencesHashChainDistanceOnly(
+            xsize,
+            ysize,
+            argb,
+            cache_bits,
+            &*hash_chain,
+            refs_src,
+            dist_array,
+        ) == 0
+        {
+            break 'error;
+        }
+        let (chosen_path, chosen_path_size) = TraceBackwards(dist_array, dist_array_size);
+        if BackwardReferencesHashChainFollowChosenPath(
+            argb,
+            cache_bits,
+            chosen_path,
+            chosen_path_size,
+            &*hash_chain,
+            refs_dst,
+        ) == 0
+        {
+            break 'error;
+        }
+        ok = 1;
+    }
+    WebPSafeFree(dist_array as *mut c_void);
+    ok
+}
@@ -0,0 +1,1717 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Author: Jyrki Alakuijala (jyrki@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::lossless_enc::VP8LVectorMismatch;
+use enc::backward_references_cost_enc::VP8LBackwardReferencesTraceBackwards;
+use enc::histogram_enc::{
+    VP8LAllocateHistogram, VP8LFreeHistogram, VP8LHistogram, VP8LHistogramCreate,
+    VP8LHistogramEstimateBits,
+};
+use format_constants::{NUM_LENGTH_CODES, NUM_LITERAL_CODES};
+use utils::color_cache_utils::{
+    VP8LColorCache, VP8LColorCacheClear, VP8LColorCacheContains, VP8LColorCacheGetIndex,
+    VP8LColorCacheInit, VP8LColorCacheInsert, VP8LColorCacheLookup, VP8LColorCacheSet, VP8LHashPix,
+};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc};
+
+// The maximum allowed limit is 11.
+pub(crate) const MAX_COLOR_CACHE_BITS: c_int = 10;
+
+// -----------------------------------------------------------------------------
+// PixOrCopy
+
+#[allow(non_upper_case_globals)]
+pub(crate) const kLiteral: u8 = 0;
+#[allow(non_upper_case_globals)]
+pub(crate) const kCacheIdx: u8 = 1;
+#[allow(non_upper_case_globals)]
+pub(crate) const kCopy: u8 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PixOrCopy {
+    pub(crate) mode: u8,
+    pub(crate) len: u16,
+    pub(crate) argb_or_distance: u32,
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyCreateCopy(distance: u32, len: u16) -> PixOrCopy {
+    PixOrCopy {
+        mode: kCopy,
+        argb_or_distance: distance,
+        len,
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyCreateCacheIdx(idx: c_int) -> PixOrCopy {
+    c_assert!(idx >= 0);
+    c_assert!(idx < (1 << MAX_COLOR_CACHE_BITS));
+    PixOrCopy {
+        mode: kCacheIdx,
+        argb_or_distance: idx as u32,
+        len: 1,
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyCreateLiteral(argb: u32) -> PixOrCopy {
+    PixOrCopy {
+        mode: kLiteral,
+        argb_or_distance: argb,
+        len: 1,
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyIsLiteral(p: &PixOrCopy) -> bool {
+    p.mode == kLiteral
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyIsCacheIdx(p: &PixOrCopy) -> bool {
+    p.mode == kCacheIdx
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyIsCopy(p: &PixOrCopy) -> bool {
+    p.mode == kCopy
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyLiteral(p: &PixOrCopy, component: c_int) -> u32 {
+    c_assert!(p.mode == kLiteral);
+    (p.argb_or_distance >> (component * 8)) & 0xff
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyLength(p: &PixOrCopy) -> u32 {
+    p.len as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyCacheIdx(p: &PixOrCopy) -> u32 {
+    c_assert!(p.mode == kCacheIdx);
+    c_assert!(p.argb_or_distance < (1 << MAX_COLOR_CACHE_BITS));
+    p.argb_or_distance
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn PixOrCopyDistance(p: &PixOrCopy) -> u32 {
+    c_assert!(p.mode == kCopy);
+    p.argb_or_distance
+}
+
+// -----------------------------------------------------------------------------
+// VP8LHashChain
+
+pub(crate) const HASH_BITS: c_int = 18;
+pub(crate) const HASH_SIZE: c_int = 1 << HASH_BITS;
+
+// If you change this, you need MAX_LENGTH_BITS + WINDOW_SIZE_BITS <= 32 as it
+// is used in VP8LHashChain.
+pub(crate) const MAX_LENGTH_BITS: c_int = 12;
+pub(crate) const WINDOW_SIZE_BITS: c_int = 20;
+// We want the max value to be attainable and stored in MAX_LENGTH_BITS bits.
+pub(crate) const MAX_LENGTH: c_int = (1 << MAX_LENGTH_BITS) - 1;
+
+#[repr(C)]
+pub struct VP8LHashChain {
+    // The 20 most significant bits contain the offset at which the best match
+    // is found. These 20 bits are the limit defined by GetWindowSizeForHashChain
+    // (through WINDOW_SIZE = 1<<20).
+    // The lower 12 bits contain the length of the match. The 12 bit limit is
+    // defined in MaxFindCopyLength with MAX_LENGTH=4096.
+    pub(crate) offset_length_: *mut u32,
+    // This is the maximum size of the hash_chain that can be constructed.
+    // Typically this is the pixel count (width x height) for a given image.
+    pub(crate) size_: c_int,
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LHashChainFindOffset(p: &VP8LHashChain, base_position: c_int) -> c_int {
+    (*p.offset_length_.offset(base_position as isize) >> MAX_LENGTH_BITS) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LHashChainFindLength(p: &VP8LHashChain, base_position: c_int) -> c_int {
+    (*p.offset_length_.offset(base_position as isize) & ((1u32 << MAX_LENGTH_BITS) - 1)) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LHashChainFindCopy(
+    p: &VP8LHashChain,
+    base_position: c_int,
+    offset_ptr: &mut c_int,
+    length_ptr: &mut c_int,
+) {
+    *offset_ptr = VP8LHashChainFindOffset(p, base_position);
+    *length_ptr = VP8LHashChainFindLength(p, base_position);
+}
+
+// -----------------------------------------------------------------------------
+// VP8LBackwardRefs (block-based backward-references storage)
+
+// maximum number of reference blocks the image will be segmented into
+pub(crate) const MAX_REFS_BLOCK_PER_IMAGE: c_int = 16;
+
+#[repr(C)]
+pub struct PixOrCopyBlock {
+    next_: *mut PixOrCopyBlock, // next block (or NULL)
+    start_: *mut PixOrCopy,     // data start
+    size_: c_int,               // currently used size
+}
+
+#[repr(C)]
+pub struct VP8LBackwardRefs {
+    pub(crate) block_size_: c_int,                // common block-size
+    pub(crate) error_: c_int,                     // set to true if some memory error occurred
+    pub(crate) refs_: *mut PixOrCopyBlock,        // list of currently used blocks
+    pub(crate) tail_: *mut *mut PixOrCopyBlock,   // for list recycling
+    pub(crate) free_blocks_: *mut PixOrCopyBlock, // free-list
+    pub(crate) last_block_: *mut PixOrCopyBlock,  // used for adding new refs (internal)
+}
+
+// Cursor for iterating on references content
+#[repr(C)]
+pub struct VP8LRefsCursor {
+    // public:
+    pub(crate) cur_pos: *mut PixOrCopy, // current position
+    // private:
+    cur_block_: *mut PixOrCopyBlock, // current block in the refs list
+    last_pos_: *const PixOrCopy,     // sentinel for switching to next block
+}
+
+// Returns true if cursor is pointing at a valid position.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LRefsCursorOk(c: &VP8LRefsCursor) -> bool {
+    !c.cur_pos.is_null()
+}
+
+// Move to next position, or NULL. Should not be called if !VP8LRefsCursorOk().
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LRefsCursorNext(c: &mut VP8LRefsCursor) {
+    c_assert!(VP8LRefsCursorOk(c));
+    c.cur_pos = c.cur_pos.add(1);
+    if ptr::eq(c.cur_pos, c.last_pos_) {
+        VP8LRefsCursorNextBlock(c);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Main entry points
+
+#[allow(non_upper_case_globals)]
+pub(crate) const kLZ77Standard: c_int = 1;
+#[allow(non_upper_case_globals)]
+pub(crate) const kLZ77RLE: c_int = 2;
+#[allow(non_upper_case_globals)]
+pub(crate) const kLZ77Box: c_int = 4;
+
+// -----------------------------------------------------------------------------
+
+const MIN_BLOCK_SIZE: c_int = 256; // minimum block size for backward references
+
+const MAX_ENTROPY: c_double = 1e30f32 as c_double;
+
+// 1M window (4M bytes) minus 120 special codes for short distances.
+const WINDOW_SIZE: c_int = (1 << WINDOW_SIZE_BITS) - 120;
+
+// Minimum number of pixels for which it is cheaper to encode a
+// distance + length instead of each pixel as a literal.
+const MIN_LENGTH: c_int = 4;
+
+// -----------------------------------------------------------------------------
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+const plane_to_code_lut: [u8; 128] = [
+    96,   73,  55,  39,  23,  13,   5,  1,  255, 255, 255, 255, 255, 255, 255, 255,
+    101,  78,  58,  42,  26,  16,   8,  2,    0,   3,  9,   17,  27,  43,  59,  79,
+    102,  86,  62,  46,  32,  20,  10,  6,    4,   7,  11,  21,  33,  47,  63,  87,
+    105,  90,  70,  52,  37,  28,  18,  14,  12,  15,  19,  29,  38,  53,  71,  91,
+    110,  99,  82,  66,  48,  35,  30,  24,  22,  25,  31,  36,  49,  67,  83, 100,
+    115, 108,  94,  76,  64,  50,  44,  40,  34,  41,  45,  51,  65,  77,  95, 109,
+    118, 113, 103,  92,  80,  68,  60,  56,  54,  57,  61,  69,  81,  93, 104, 114,
+    119, 116, 111, 106,  97,  88,  84,  74,  72,  75,  85,  89,  98, 107, 112, 117,
+];
+
+#[no_mangle]
+pub extern "C" fn VP8LDistanceToPlaneCode(xsize: c_int, dist: c_int) -> c_int {
+    let yoffset = dist / xsize;
+    let xoffset = dist - yoffset * xsize;
+    if xoffset <= 8 && yoffset < 8 {
+        plane_to_code_lut[(yoffset * 16 + 8 - xoffset) as usize] as c_int + 1
+    } else if xoffset > xsize - 8 && yoffset < 7 {
+        plane_to_code_lut[((yoffset + 1) * 16 + 8 + (xsize - xoffset)) as usize] as c_int + 1
+    } else {
+        dist + 120
+    }
+}
+
+// Returns the exact index where array1 and array2 are different. For an index
+// inferior or equal to best_len_match, the return value just has to be strictly
+// inferior to best_len_match. The current behavior is to return 0 if this index
+// is best_len_match, and the index itself otherwise.
+// If no two elements are the same, it returns max_limit.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn FindMatchLength(
+    array1: *const u32,
+    array2: *const u32,
+    best_len_match: c_int,
+    max_limit: c_int,
+) -> c_int {
+    // Before 'expensive' linear match, check if the two arrays match at the
+    // current best length index.
+    if *array1.offset(best_len_match as isize) != *array2.offset(best_len_match as isize) {
+        return 0;
+    }
+
+    VP8LVectorMismatch.unwrap()(array1, array2, max_limit)
+}
+
+// -----------------------------------------------------------------------------
+//  VP8LBackwardRefs
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LClearBackwardRefs(refs: *mut VP8LBackwardRefs) {
+    c_assert!(!refs.is_null());
+    let refs = &mut *refs;
+    if !refs.tail_.is_null() {
+        *refs.tail_ = refs.free_blocks_; // recycle all blocks at once
+    }
+    refs.free_blocks_ = refs.refs_;
+    refs.tail_ = &mut refs.refs_;
+    refs.last_block_ = ptr::null_mut();
+    refs.refs_ = ptr::null_mut();
+}
+
+// Release memory for backward references.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBackwardRefsClear(refs: *mut VP8LBackwardRefs) {
+    c_assert!(!refs.is_null());
+    VP8LClearBackwardRefs(refs);
+    let refs = &mut *refs;
+    while !refs.free_blocks_.is_null() {
+        let next = (*refs.free_blocks_).next_;
+        WebPSafeFree(refs.free_blocks_ as *mut c_void);
+        refs.free_blocks_ = next;
+    }
+}
+
+// Initialize the object. 'block_size' is the common block size to store
+// references (typically, width * height / MAX_REFS_BLOCK_PER_IMAGE).
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBackwardRefsInit(refs: *mut VP8LBackwardRefs, block_size: c_int) {
+    c_assert!(!refs.is_null());
+    ptr::write_bytes(refs, 0, 1);
+    let refs = &mut *refs;
+    refs.tail_ = &mut refs.refs_;
+    refs.block_size_ = if block_size < MIN_BLOCK_SIZE {
+        MIN_BLOCK_SIZE
+    } else {
+        block_size
+    };
+}
+
+// Returns a cursor positioned at the beginning of the references list.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LRefsCursorInit(refs: *const VP8LBackwardRefs) -> VP8LRefsCursor {
+    let cur_block_ = (*refs).refs_;
+    if !cur_block_.is_null() {
+        let cur_pos = (*cur_block_).start_;
+        VP8LRefsCursor {
+            cur_pos,
+            cur_block_,
+            last_pos_: cur_pos.offset((*cur_block_).size_ as isize),
+        }
+    } else {
+        VP8LRefsCursor {
+            cur_pos: ptr::null_mut(),
+            cur_block_,
+            last_pos_: ptr::null(),
+        }
+    }
+}
+
+// Internal use only.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LRefsCursorNextBlock(c: *mut VP8LRefsCursor) {
+    let c = &mut *c;
+    let b = (*c.cur_block_).next_;
+    c.cur_pos = if b.is_null() {
+        ptr::null_mut()
+    } else {
+        (*b).start_
+    };
+    c.last_pos_ = if b.is_null() {
+        ptr::null()
+    } else {
+        (*b).start_.offset((*b).size_ as isize)
+    };
+    c.cur_block_ = b;
+}
+
+// Create a new block, either from the free list or allocated
+#[allow(non_snake_case)]
+unsafe fn BackwardRefsNewBlock(refs: &mut VP8LBackwardRefs) -> *mut PixOrCopyBlock {
+    let mut b = refs.free_blocks_;
+    if b.is_null() {
+        // allocate new memory chunk
+        let total_size = mem::size_of::<PixOrCopyBlock>()
+            + refs.block_size_ as usize * mem::size_of::<PixOrCopy>();
+        b = WebPSafeMalloc(1, total_size) as *mut PixOrCopyBlock;
+        if b.is_null() {
+            refs.error_ |= 1;
+            return ptr::null_mut();
+        }
+        // not always aligned
+        (*b).start_ = (b as *mut u8).add(mem::size_of::<PixOrCopyBlock>()) as *mut PixOrCopy;
+    } else {
+        // recycle from free-list
+        refs.free_blocks_ = (*b).next_;
+    }
+    *refs.tail_ = b;
+    refs.tail_ = &mut (*b).next_;
+    refs.last_block_ = b;
+    (*b).next_ = ptr::null_mut();
+    (*b).size_ = 0;
+    b
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBackwardRefsCursorAdd(refs: *mut VP8LBackwardRefs, v: PixOrCopy) {
+    let refs = &mut *refs;
+    let mut b = refs.last_block_;
+    if b.is_null() || (*b).size_ == refs.block_size_ {
+        b = BackwardRefsNewBlock(refs);
+        if b.is_null() {
+            return; // refs->error_ is set
+        }
+    }
+    *(*b).start_.offset((*b).size_ as isize) = v;
+    (*b).size_ += 1;
+}
+
+// -----------------------------------------------------------------------------
+// Hash chains
+
+// Must be called first, to set size.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHashChainInit(p: *mut VP8LHashChain, size: c_int) -> c_int {
+    let p = &mut *p;
+    c_assert!(p.size_ == 0);
+    c_assert!(p.offset_length_.is_null());
+    c_assert!(size > 0);
+    p.offset_length_ = WebPSafeMalloc(size as u64, mem::size_of::<u32>()) as *mut u32;
+    if p.offset_length_.is_null() {
+        return 0;
+    }
+    p.size_ = size;
+
+    1
+}
+
+// Release memory.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHashChainClear(p: *mut VP8LHashChain) {
+    c_assert!(!p.is_null());
+    let p = &mut *p;
+    WebPSafeFree(p.offset_length_ as *mut c_void);
+
+    p.size_ = 0;
+    p.offset_length_ = ptr::null_mut();
+}
+
+// -----------------------------------------------------------------------------
+
+const HASH_MULTIPLIER_HI: u64 = 0xc6a4a793;
+const HASH_MULTIPLIER_LO: u64 = 0x5bd1e996;
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetPixPairHash64(argb: *const u32) -> u32 {
+    let mut key = ((*argb.add(1) as u64 * HASH_MULTIPLIER_HI) & 0xffffffff) as u32;
+    key = key.wrapping_add(((*argb as u64 * HASH_MULTIPLIER_LO) & 0xffffffff) as u32);
+    key >> (32 - HASH_BITS)
+}
+
+// Returns the maximum number of hash chain lookups to do for a
+// given compression quality. Return value in range [8, 86].
+#[allow(non_snake_case)]
+fn GetMaxItersForQuality(quality: c_int) -> c_int {
+    8 + (quality * quality) / 128
+}
+
+#[allow(non_snake_case)]
+fn GetWindowSizeForHashChain(quality: c_int, xsize: c_int) -> c_int {
+    let max_window_size = if quality > 75 {
+        WINDOW_SIZE
+    } else if quality > 50 {
+        xsize << 8
+    } else if quality > 25 {
+        xsize << 6
+    } else {
+        xsize << 4
+    };
+    c_assert!(xsize > 0);
+    if max_window_size > WINDOW_SIZE {
+        WINDOW_SIZE
+    } else {
+        max_window_size
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn MaxFindCopyLength(len: c_int) -> c_int {
+    if len < MAX_LENGTH {
+        len
+    } else {
+        MAX_LENGTH
+    }
+}
+
+// Pre-compute the best matches for argb.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHashChainFill(
+    p: *mut VP8LHashChain,
+    quality: c_int,
+    argb: *const u32,
+    xsize: c_int,
+    ysize: c_int,
+    low_effort: c_int,
+) -> c_int {
+    let p = &mut *p;
+    let size = xsize * ysize;
+    let iter_max = GetMaxItersForQuality(quality);
+    let window_size = GetWindowSizeForHashChain(quality, xsize) as u32;
+    // Temporarily use the p->offset_length_ as a hash chain.
+    let chain = p.offset_length_ as *mut i32;
+    c_assert!(size > 0);
+    c_assert!(p.size_ != 0);
+    c_assert!(!p.offset_length_.is_null());
+
+    if size <= 2 {
+        *p.offset_length_ = 0;
+        *p.offset_length_.offset(size as isize - 1) = 0;
+        return 1;
+    }
+
+    let hash_to_first_index = WebPSafeMalloc(HASH_SIZE as u64, mem::size_of::<i32>()) as *mut i32;
+    if hash_to_first_index.is_null() {
+        return 0;
+    }
+
+    // Set the int32_t array to -1.
+    ptr::write_bytes(hash_to_first_index, 0xff, HASH_SIZE as usize);
+    // Fill the chain linking pixels with the same hash.
+    let argb_at = |i: c_int| *argb.offset(i as isize);
+    let mut argb_comp = argb_at(0) == argb_at(1);
+    let mut pos: c_int = 0;
+    while pos < size - 2 {
+        let argb_comp_next = argb_at(pos + 1) == argb_at(pos + 2);
+        if argb_comp && argb_comp_next {
+            // Consecutive pixels with the same color will share the same hash.
+            // We therefore use a different hash: the color and its repetition
+            // length.
+            let mut tmp = [0u32; 2];
+            let mut len: u32 = 1;
+            tmp[0] = argb_at(pos);
+            // Figure out how far the pixels are the same.
+            // The last pixel has a different 64 bit hash, as its next pixel does
+            // not have the same color, so we just need to get to the last pixel equal
+            // to its follower.
+            while pos + len as c_int + 2 < size && argb_at(pos + len as c_int + 2) == argb_at(pos) {
+                len += 1;
+            }
+            if len > MAX_LENGTH as u32 {
+                // Skip the pixels that match for distance=1 and length>MAX_LENGTH
+                // because they are linked to their predecessor and we automatically
+                // check that in the main for loop below. Skipping means setting no
+                // predecessor in the chain, hence -1.
+                ptr::write_bytes(
+                    chain.offset(pos as isize),
+                    0xff,
+                    (len - MAX_LENGTH as u32) as usize,
+                );
+                pos += (len - MAX_LENGTH as u32) as c_int;
+                len = MAX_LENGTH as u32;
+            }
+            // Process the rest of the hash chain.
+            while len != 0 {
+                tmp[1] = len;
+                len -= 1;
+                let hash_code = GetPixPairHash64(tmp.as_ptr());
+                *chain.offset(pos as isize) = *hash_to_first_index.add(hash_code as usize);
+                *hash_to_first_index.add(hash_code as usize) = pos;
+                pos += 1;
+            }
+            argb_comp = false;
+        } else {
+            // Just move one pixel forward.
+            let hash_code = GetPixPairHash64(argb.offset(pos as isize));
+            *chain.offset(pos as isize) = *hash_to_first_index.add(hash_code as usize);
+            *hash_to_first_index.add(hash_code as usize) = pos;
+            pos += 1;
+            argb_comp = argb_comp_next;
+        }
+    }
+    // Process the penultimate pixel.
+    *chain.offset(pos as isize) =
+        *hash_to_first_index.add(GetPixPairHash64(argb.offset(pos as isize)) as usize);
+
+    WebPSafeFree(hash_to_first_index as *mut c_void);
+
+    // Find the best match interval at each pixel, defined by an offset to the
+    // pixel and a length. The right-most pixel cannot match anything to the right
+    // (hence a best length of 0) and the left-most pixel nothing to the left
+    // (hence an offset of 0).
+    c_assert!(size > 2);
+    *p.offset_length_ = 0;
+    *p.offset_length_.offset(size as isize - 1) = 0;
+    let mut base_position: u32 = size as u32 - 2;
+    while base_position > 0 {
+        let max_len = MaxFindCopyLength(size - 1 - base_position as c_int);
+        let argb_start = argb.add(base_position as usize);
+        let mut iter = iter_max;
+        let mut best_length: c_int = 0;
+        let mut best_distance: u32 = 0;
+        let min_pos: c_int = if base_position > window_size {
+            (base_position - window_size) as c_int
+        } else {
+            0
+        };
+        let length_max = if max_len < 256 { max_len } else { 256 };
+
+        pos = *chain.add(base_position as usize);
+        if low_effort == 0 {
+            // Heuristic: use the comparison with the above line as an initialization.
+            if base_position >= xsize as u32 {
+                let curr_length = FindMatchLength(
+                    argb_start.offset(-(xsize as isize)),
+                    argb_start,
+                    best_length,
+                    max_len,
+                );
+                if curr_length > best_length {
+                    best_length = curr_length;
+                    best_distance = xsize as u32;
+                }
+                iter -= 1;
+            }
+            // Heuristic: compare to the previous pixel.
+            let curr_length =
+                FindMatchLength(argb_start.offset(-1), argb_start, best_length, max_len);
+            if curr_length > best_length {
+                best_length = curr_length;
+                best_distance = 1;
+            }
+            iter -= 1;
+            // Skip the for loop if we already have the maximum.
+            if best_length == MAX_LENGTH {
+                pos = min_pos - 1;
+            }
+        }
+        let mut best_argb = *argb_start.offset(best_length as isize);
+
+        while pos >= min_pos {
+            iter -= 1;
+            if iter == 0 {
+                break;
+            }
+            c_assert!(base_position > pos as u32);
+
+            if argb_at(pos + best_length) != best_argb {
+                pos = *chain.offset(pos as isize);
+                continue;
+            }
+
+            let curr_length =
+                VP8LVectorMismatch.unwrap()(argb.offset(pos as isize), argb_start, max_len);
+            if best_length < curr_length {
+                best_length = curr_length;
+                best_distance = base_position - pos as u32;
+                best_argb = *argb_start.offset(best_length as isize);
+                // Stop if we have reached a good enough length.
+                if best_length >= length_max {
+                    break;
+                }
+            }
+            pos = *chain.offset(pos as isize);
+        }
+        // We have the best match but in case the two intervals continue matching
+        // to the left, we have the best matches for the left-extended pixels.
+        let mut max_base_position = base_position;
+        loop {
+            c_assert!(best_length <= MAX_LENGTH);
+            c_assert!(best_distance <= WINDOW_SIZE as u32);
+            *p.offset_length_.add(base_position as usize) =
+                (best_distance << MAX_LENGTH_BITS) | best_length as u32;
+            base_position -= 1;
+            // Stop if we don't have a match or if we are out of bounds.
+            if best_distance == 0 || base_position == 0 {
+                break;
+            }
+            // Stop if we cannot extend the matching intervals to the left.
+            if base_position < best_distance
+                || *argb.add((base_position - best_distance) as usize)
+                    != *argb.add(base_position as usize)
+            {
+                break;
+            }
+            // Stop if we are matching at its limit because there could be a closer
+            // matching interval with the same maximum length. Then again, if the
+            // matching interval is as close as possible (best_distance == 1), we will
+            // never find anything better so let's continue.
+            if best_length == MAX_LENGTH
+                && best_distance != 1
+                && base_position + (MAX_LENGTH as u32) < max_base_position
+            {
+                break;
+            }
+            if best_length < MAX_LENGTH {
+                best_length += 1;
+                max_base_position = base_position;
+            }
+        }
+    }
+    1
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn AddSingleLiteral(
+    pixel: u32,
+    use_color_cache: bool,
+    hashers: &VP8LColorCache,
+    refs: *mut VP8LBackwardRefs,
+) {
+    let v = if use_color_cache {
+        let key = VP8LColorCacheGetIndex(hashers, pixel) as u32;
+        if VP8LColorCacheLookup(hashers, key) == pixel {
+            PixOrCopyCreateCacheIdx(key as c_int)
+        } else {
+            VP8LColorCacheSet(hashers, key, pixel);
+            PixOrCopyCreateLiteral(pixel)
+        }
+    } else {
+        PixOrCopyCreateLiteral(pixel)
+    };
+    VP8LBackwardRefsCursorAdd(refs, v);
+}
+
+#[allow(non_snake_case)]
+unsafe fn BackwardReferencesRle(
+    xsize: c_int,
+    ysize: c_int,
+    argb: *const u32,
+    cache_bits: c_int,
+    refs: *mut VP8LBackwardRefs,
+) -> c_int {
+    let pix_count = xsize * ysize;
+    let use_color_cache = cache_bits > 0;
+    let mut hashers: VP8LColorCache = mem::zeroed();
+
+    if use_color_cache && VP8LColorCacheInit(&mut hashers, cache_bits) == 0 {
+        return 0;
+    }
+    VP8LClearBackwardRefs(refs);
+    // Add first pixel as literal.
+    AddSingleLiteral(*argb, use_color_cache, &hashers, refs);
+    let mut i: c_int = 1;
+    while i < pix_count {
+        let max_len = MaxFindCopyLength(pix_count - i);
+        let argb_i = argb.offset(i as isize);
+        let rle_len = FindMatchLength(argb_i, argb_i.offset(-1), 0, max_len);
+        let prev_row_len = if i < xsize {
+            0
+        } else {
+            FindMatchLength(argb_i, argb_i.offset(-(xsize as isize)), 0, max_len)
+        };
+        if rle_len >= prev_row_len && rle_len >= MIN_LENGTH {
+            VP8LBackwardRefsCursorAdd(refs, PixOrCopyCreateCopy(1, rle_len as u16));
+            // We don't need to update the color cache here since it is always the
+            // same pixel being copied, and that does not change the color cache
+            // state.
+            i += rle_len;
+        } else if prev_row_len >= MIN_LENGTH {
+            VP8LBackwardRefsCursorAdd(refs, PixOrCopyCreateCopy(xsize as u32, prev_row_len as u16));
+            if use_color_cache {
+                for k in 0..prev_row_len {
+                    VP8LColorCacheInsert(&hashers, *argb.offset((i + k) as isize));
+                }
+            }
+            i += prev_row_len;
+        } else {
+            AddSingleLiteral(*argb_i, use_color_cache, &hashers, refs);
+            i += 1;
+        }
+    }
+    if use_color_cache {
+        VP8LColorCacheClear(&mut hashers);
+    }
+    ((*refs).error_ == 0) as c_int
+}
+
+#[allow(non_snake_case)]
+unsafe fn BackwardReferencesLz77(
+    xsize: c_int,
+    ysize: c_int,
+    argb: *const u32,
+    cache_bits: c_int,
+    hash_chain: &VP8LHashChain,
+    refs: *mut VP8LBackwardRefs,
+) -> c_int {
+    let mut i_last_check: c_int = -1;
+    let use_color_cache = cache_bits > 0;
+    let pix_count = xsize * ysize;
+    let mut hashers: VP8LColorCache = mem::zeroed();
+
+    if use_color_cache && VP8LColorCacheInit(&mut hashers, cache_bits) == 0 {
+        return 0;
+    }
+    VP8LClearBackwardRefs(refs);
+    let mut i: c_int = 0;
+    while i < pix_count {
+        // Alternative#1: Code the pixels starting at 'i' using backward reference.
+        let mut offset: c_int = 0;
+        let mut len: c_int = 0;
+        VP8LHashChainFindCopy(hash_chain, i, &mut offset, &mut len);
+        if len >= MIN_LENGTH {
+            let len_ini = len;
+            let mut max_reach = 0;
+            let j_max = if i + len_ini >= pix_count {
+                pix_count - 1
+            } else {
+                i + len_ini
+            };
+            // Only start from what we have not checked already.
+            i_last_check = if i > i_last_check { i } else { i_last_check };
+            // We know the best match for the current pixel but we try to find the
+            // best matches for the current pixel AND the next one combined.
+            // The naive method would use the intervals:
+            // [i,i+len) + [i+len, length of best match at i+len)
+            // while we check if we can use:
+            // [i,j) (where j<=i+len) + [j, length of best match at j)
+            for j in i_last_check + 1..=j_max {
+                let len_j = VP8LHashChainFindLength(hash_chain, j);
+                // 1 for single literal.
+                let reach = j + if len_j >= MIN_LENGTH { len_j } else { 1 };
+                if reach > max_reach {
+                    len = j - i;
+                    max_reach = reach;
+                    if max_reach >= pix_count {
+                        break;
+                    }
+                }
+            }
+        } else {
+            len = 1;
+        }
+        // Go with literal or backward reference.
+        c_assert!(len > 0);
+        if len == 1 {
+            AddSingleLiteral(*argb.offset(i as isize), use_color_cache, &hashers, refs);
+        } else {
+            VP8LBackwardRefsCursorAdd(refs, PixOrCopyCreateCopy(offset as u32, len as u16));
+            if use_color_cache {
+                for j in i..i + len {
+                    VP8LColorCacheInsert(&hashers, *argb.offset(j as isize));
+                }
+            }
+        }
+        i += len;
+    }
+
+    let ok = ((*refs).error_ == 0) as c_int;
+    if use_color_cache {
+        VP8LColorCacheClear(&mut hashers);
+    }
+    ok
+}
+
+// Compute an LZ77 by forcing matches to happen within a given distance cost.
+// We therefore limit the algorithm to the lowest 32 values in the PlaneCode
+// definition.
+const WINDOW_OFFSETS_SIZE_MAX: usize = 32;
+#[allow(non_snake_case)]
+unsafe fn BackwardReferencesLz77Box(
+    xsize: c_int,
+    ysize: c_int,
+    argb: *const u32,
+    cache_bits: c_int,
+    hash_chain_best: &VP8LHashChain,
+    hash_chain: &mut VP8LHashChain,
+    refs: *mut VP8LBackwardRefs,
+) -> c_int {
+    let pix_count = xsize * ysize;
+    let mut window_offsets = [0 as c_int; WINDOW_OFFSETS_SIZE_MAX];
+    let mut window_offsets_new = [0 as c_int; WINDOW_OFFSETS_SIZE_MAX];
+    let mut window_offsets_size: usize = 0;
+    let mut window_offsets_new_size: usize = 0;
+    let counts_ini = WebPSafeMalloc((xsize * ysize) as u64, mem::size_of::<u16>()) as *mut u16;
+    let mut best_offset_prev: c_int = -1;
+    let mut best_length_prev: c_int = -1;
+    if counts_ini.is_null() {
+        return 0;
+    }
+
+    // counts[i] counts how many times a pixel is repeated starting at position i.
+    *counts_ini.offset(pix_count as isize - 1) = 1;
+    let mut i = pix_count - 2;
+    while i >= 0 {
+        let counts = counts_ini.offset(i as isize);
+        if *argb.offset(i as isize) == *argb.offset(i as isize + 1) {
+            // Max out the counts to MAX_LENGTH.
+            *counts = *counts.add(1) + (*counts.add(1) != MAX_LENGTH as u16) as u16;
+        } else {
+            *counts = 1;
+        }
+        i -= 1;
+    }
+
+    // Figure out the window offsets around a pixel. They are stored in a
+    // spiraling order around the pixel as defined by VP8LDistanceToPlaneCode.
+    {
+        for y in 0..=6 {
+            for x in -6..=6 {
+                let offset = y * xsize + x;
+                // Ignore offsets that bring us after the pixel.
+                if offset <= 0 {
+                    continue;
+                }
+                let plane_code = VP8LDistanceToPlaneCode(xsize, offset) - 1;
+                if plane_code >= WINDOW_OFFSETS_SIZE_MAX as c_int {
+                    continue;
+                }
+                window_offsets[plane_code as usize] = offset;
+            }
+        }
+        // For narrow images, not all plane codes are reached, so remove those.
+        for i in 0..WINDOW_OFFSETS_SIZE_MAX {
+            if window_offsets[i] == 0 {
+                continue;
+            }
+            window_offsets[window_offsets_size] = window_offsets[i];
+            window_offsets_size += 1;
+        }
+        // Given a pixel P, find the offsets that reach pixels unreachable from P-1
+        // with any of the offsets in window_offsets[].
+        for i in 0..window_offsets_size {
+            let mut is_reachable = false;
+            let mut j = 0;
+            while j < window_offsets_size && !is_reachable {
+                is_reachable |= window_offsets[i] == window_offsets[j] + 1;
+                j += 1;
+            }
+            if !is_reachable {
+                window_offsets_new[window_offsets_new_size] = window_offsets[i];
+                window_offsets_new_size += 1;
+            }
+        }
+    }
+
+    *hash_chain.offset_length_ = 0;
+    for i in 1..pix_count {
+        let mut best_length = VP8LHashChainFindLength(hash_chain_best, i);
+        let mut best_offset: c_int = 0;
+        let mut do_compute = true;
+
+        if best_length >= MAX_LENGTH {
+            // Do not recompute the best match if we already have a maximal one in the
+            // window.
+            best_offset = VP8LHashChainFindOffset(hash_chain_best, i);
+            if window_offsets[..window_offsets_size].contains(&best_offset) {
+                do_compute = false;
+            }
+        }
+        if do_compute {
+            // Figure out if we should use the offset/length from the previous pixel
+            // as an initial guess and therefore only inspect the offsets in
+            // window_offsets_new[].
+            let use_prev = best_length_prev > 1 && best_length_prev < MAX_LENGTH;
+            let offsets: &[c_int] = if use_prev {
+                &window_offsets_new[..window_offsets_new_size]
+            } else {
+                &window_offsets[..window_offsets_size]
+            };
+            best_length = if use_prev { best_length_prev - 1 } else { 0 };
+            best_offset = if use_prev { best_offset_prev } else { 0 };
+            // Find the longest match in a window around the pixel.
+            for &window_offset in offsets {
+                let mut curr_length: c_int = 0;
+                let mut j = i;
+                let mut j_offset = i - window_offset;
+                if j_offset < 0 || *argb.offset(j_offset as isize) != *argb.offset(i as isize) {
+                    continue;
+                }
+                // The longest match is the sum of how many times each pixel is
+                // repeated.
+                loop {
+                    let counts_j_offset = *counts_ini.offset(j_offset as isize) as c_int;
+                    let counts_j = *counts_ini.offset(j as isize) as c_int;
+                    if counts_j_offset != counts_j {
+                        curr_length += if counts_j_offset < counts_j {
+                            counts_j_offset
+                        } else {
+                            counts_j
+                        };
+                        break;
+                    }
+                    // The same color is repeated counts_pos times at j_offset and j.
+                    curr_length += counts_j_offset;
+                    j_offset += counts_j_offset;
+                    j += counts_j_offset;
+                    if !(curr_length <= MAX_LENGTH
+                        && j < pix_count
+                        && *argb.offset(j_offset as isize) == *argb.offset(j as isize))
+                    {
+                        break;
+                    }
+                }
+                if best_length < curr_length {
+                    best_offset = window_offset;
+                    if curr_length >= MAX_LENGTH {
+                        best_length = MAX_LENGTH;
+                        break;
+                    } else {
+                        best_length = curr_length;
+                    }
+                }
+            }
+        }
+
+        c_assert!(i + best_length <= pix_count);
+        c_assert!(best_length <= MAX_LENGTH);
+        if best_length <= MIN_LENGTH {
+            *hash_chain.offset_length_.offset(i as isize) = 0;
+            best_offset_prev = 0;
+            best_length_prev = 0;
+        } else {
+            *hash_chain.offset_length_.offset(i as isize) =
+                ((best_offset as u32) << MAX_LENGTH_BITS) | best_length as u32;
+            best_offset_prev = best_offset;
+            best_length_prev = best_length;
+        }
+    }
+    *hash_chain.offset_length_ = 0;
+    WebPSafeFree(counts_ini as *mut c_void);
+
+    BackwardReferencesLz77(xsize, ysize, argb, cache_bits, hash_chain, refs)
+}
+
+// -----------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn BackwardReferences2DLocality(xsize: c_int, refs: *const VP8LBackwardRefs) {
+    let mut c = VP8LRefsCursorInit(refs);
+    while VP8LRefsCursorOk(&c) {
+        if PixOrCopyIsCopy(&*c.cur_pos) {
+            let dist = (*c.cur_pos).argb_or_distance as c_int;
+            let transformed_dist = VP8LDistanceToPlaneCode(xsize, dist);
+            (*c.cur_pos).argb_or_distance = transformed_dist as u32;
+        }
+        VP8LRefsCursorNext(&mut c);
+    }
+}
+
+// Evaluate optimal cache bits for the local color cache.
+// The input *best_cache_bits sets the maximum cache bits to use (passing 0
+// implies disabling the local color cache). The local color cache is also
+// disabled for the lower (<= 25) quality.
+// Returns 0 in case of memory error.
+#[allow(non_snake_case)]
+unsafe fn CalculateBestCacheSize(
+    mut argb: *const u32,
+    quality: c_int,
+    refs: *const VP8LBackwardRefs,
+    best_cache_bits: &mut c_int,
+) -> c_int {
+    const N: usize = MAX_COLOR_CACHE_BITS as usize + 1;
+    let cache_bits_max = if quality <= 25 { 0 } else { *best_cache_bits };
+    let mut entropy_min = MAX_ENTROPY;
+    let mut cc_init = [0 as c_int; N];
+    let mut hashers: [VP8LColorCache; N] = mem::zeroed();
+    let mut c = VP8LRefsCursorInit(refs);
+    let mut histos: [*mut VP8LHistogram; N] = [ptr::null_mut(); N];
+    let mut ok = 0;
+
+    c_assert!(cache_bits_max >= 0 && cache_bits_max <= MAX_COLOR_CACHE_BITS);
+
+    if cache_bits_max == 0 {
+        *best_cache_bits = 0;
+        // Local color cache is disabled.
+        return 1;
+    }
+
+    'error: {
+        // Allocate data.
+        for i in 0..=cache_bits_max as usize {
+            histos[i] = VP8LAllocateHistogram(i as c_int);
+            if histos[i].is_null() {
+                break 'error;
+            }
+            if i == 0 {
+                continue;
+            }
+            cc_init[i] = VP8LColorCacheInit(&mut hashers[i], i as c_int);
+            if cc_init[i] == 0 {
+                break 'error;
+            }
+        }
+
+        // Find the cache_bits giving the lowest entropy. The search is done in a
+        // brute-force way as the function (entropy w.r.t cache_bits) can be
+        // anything in practice.
+        while VP8LRefsCursorOk(&c) {
+            let v = &*c.cur_pos;
+            if PixOrCopyIsLiteral(v) {
+                let pix = *argb;
+                argb = argb.add(1);
+                let a = ((pix >> 24) & 0xff) as usize;
+                let r = ((pix >> 16) & 0xff) as usize;
+                let g = ((pix >> 8) & 0xff) as usize;
+                let b = (pix & 0xff) as usize;
+                // The keys of the caches can be derived from the longest one.
+                let mut key = VP8LHashPix(pix, 32 - cache_bits_max);
+                // Do not use the color cache for cache_bits = 0.
+                (*histos[0]).blue_[b] += 1;
+                *(*histos[0]).literal_.add(g) += 1;
+                (*histos[0]).red_[r] += 1;
+                (*histos[0]).alpha_[a] += 1;
+                // Deal with cache_bits > 0.
+                for i in (1..=cache_bits_max as usize).rev() {
+                    if VP8LColorCacheLookup(&hashers[i], key as u32) == pix {
+                        *(*histos[i])
+                            .literal_
+                            .add((NUM_LITERAL_CODES + NUM_LENGTH_CODES + key) as usize) += 1;
+                    } else {
+                        VP8LColorCacheSet(&hashers[i], key as u32, pix);
+                        (*histos[i]).blue_[b] += 1;
+                        *(*histos[i]).literal_.add(g) += 1;
+                        (*histos[i]).red_[r] += 1;
+                        (*histos[i]).alpha_[a] += 1;
+                    }
+                    key >>= 1;
+                }
+            } else {
+                // We should compute the contribution of the (distance,length)
+                // histograms but those are the same independently from the cache size.
+                // As those constant contributions are in the end added to the other
+                // histogram contributions, we can safely ignore them.
+                let mut len = PixOrCopyLength(v);
+                let mut argb_prev = *argb ^ 0xffffffff;
+                // Update the color caches.
+                loop {
+                    if *argb != argb_prev {
+                        // Efficiency: insert only if the color changes.
+                        let mut key = VP8LHashPix(*argb, 32 - cache_bits_max);
+                        for i in (1..=cache_bits_max as usize).rev() {
+                            *hashers[i].colors_.offset(key as isize) = *argb;
+                            key >>= 1;
+                        }
+                        argb_prev = *argb;
+                    }
+                    argb = argb.add(1);
+                    len -= 1;
+                    if len == 0 {
+                        break;
+                    }
+                }
+            }
+            VP8LRefsCursorNext(&mut c);
+        }
+
+        for i in 0..=cache_bits_max {
+            let entropy = VP8LHistogramEstimateBits(histos[i as usize]);
+            if i == 0 || entropy < entropy_min {
+                entropy_min = entropy;
+                *best_cache_bits = i;
+            }
+        }
+        ok = 1;
+    }
+    for i in 0..=cache_bits_max as usize {
+        if cc_init[i] != 0 {
+            VP8LColorCacheClear(&mut hashers[i]);
+        }
+        VP8LFreeHistogram(histos[i]);
+    }
+    ok
+}
+
+// Update (in-place) backward references for specified cache_bits.
+#[allow(non_snake_case)]
+unsafe fn BackwardRefsWithLocalCache(
+    argb: *const u32,
+    cache_bits: c_int,
+    refs: *const VP8LBackwardRefs,
+) -> c_int {
+    let mut pixel_index: usize = 0;
+    let mut hashers: VP8LColorCache = mem::zeroed();
+    let mut c = VP8LRefsCursorInit(refs);
+    if VP8LColorCacheInit(&mut hashers, cache_bits) == 0 {
+        return 0;
+    }
+
+    while VP8LRefsCursorOk(&c) {
+        let v = &mut *c.cur_pos;
+        if PixOrCopyIsLiteral(v) {
+            let argb_literal = v.argb_or_distance;
+            let ix = VP8LColorCacheContains(&hashers, argb_literal);
+            if ix >= 0 {
+                // hashers contains argb_literal
+                *v = PixOrCopyCreateCacheIdx(ix);
+            } else {
+                VP8LColorCacheInsert(&hashers, argb_literal);
+            }
+            pixel_index += 1;
+        } else {
+            // refs was created without local cache, so it can not have cache indexes.
+            c_assert!(PixOrCopyIsCopy(v));
+            for _ in 0..v.len {
+                VP8LColorCacheInsert(&hashers, *argb.add(pixel_index));
+                pixel_index += 1;
+            }
+        }
+        VP8LRefsCursorNext(&mut c);
+    }
+    VP8LColorCacheClear(&mut hashers);
+    1
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetBackwardReferencesLowEffort(
+    width: c_int,
+    height: c_int,
+    argb: *const u32,
+    cache_bits: &mut c_int,
+    hash_chain: &VP8LHashChain,
+    refs_lz77: *mut VP8LBackwardRefs,
+) -> *mut VP8LBackwardRefs {
+    *cache_bits = 0;
+    if BackwardReferencesLz77(width, height, argb, 0, hash_chain, refs_lz77) == 0 {
+        return ptr::null_mut();
+    }
+    BackwardReferences2DLocality(width, refs_lz77);
+    refs_lz77
+}
+
+#[allow(non_snake_case, non_upper_case_globals)]
+unsafe fn GetBackwardReferences(
+    width: c_int,
+    height: c_int,
+    argb: *const u32,
+    quality: c_int,
+    mut lz77_types_to_try: c_int,
+    cache_bits: &mut c_int,
+    hash_chain: &VP8LHashChain,
+    mut best: *mut VP8LBackwardRefs,
+    mut worst: *mut VP8LBackwardRefs,
+) -> *mut VP8LBackwardRefs {
+    let cache_bits_initial = *cache_bits;
+    let mut bit_cost_best: c_double = -1.0;
+    let mut lz77_type_best = 0;
+    let mut hash_chain_box: VP8LHashChain = mem::zeroed();
+
+    let histo = VP8LAllocateHistogram(MAX_COLOR_CACHE_BITS);
+    'error: {
+        if histo.is_null() {
+            break 'error;
+        }
+
+        let mut lz77_type = 1;
+        while lz77_types_to_try != 0 {
+            if (lz77_types_to_try & lz77_type) != 0 {
+                let mut cache_bits_tmp = cache_bits_initial;
+                let res = match lz77_type {
+                    kLZ77RLE => BackwardReferencesRle(width, height, argb, 0, worst),
+                    kLZ77Standard => {
+                        // Compute LZ77 with no cache (0 bits), as the ideal LZ77 with a color
+                        // cache is not that different in practice.
+                        BackwardReferencesLz77(width, height, argb, 0, hash_chain, worst)
+                    }
+                    kLZ77Box => {
+                        if VP8LHashChainInit(&mut hash_chain_box, width * height) == 0 {
+                            break 'error;
+                        }
+                        BackwardReferencesLz77Box(
+                            width,
+                            height,
+                            argb,
+                            0,
+                            hash_chain,
+                            &mut hash_chain_box,
+                            worst,
+                        )
+                    }
+                    _ => unreachable!(),
+                };
+                if res == 0 {
+                    break 'error;
+                }
+
+                // Next, try with a color cache and update the references.
+                if CalculateBestCacheSize(argb, quality, worst, &mut cache_bits_tmp) == 0 {
+                    break 'error;
+                }
+                if cache_bits_tmp > 0
+                    && BackwardRefsWithLocalCache(argb, cache_bits_tmp, worst) == 0
+                {
+                    break 'error;
+                }
+
+                // Keep the best backward references.
+                VP8LHistogramCreate(histo, worst, cache_bits_tmp);
+                let bit_cost = VP8LHistogramEstimateBits(histo);
+                if lz77_type_best == 0 || bit_cost < bit_cost_best {
+                    mem::swap(&mut worst, &mut best);
+                    bit_cost_best = bit_cost;
+                    *cache_bits = cache_bits_tmp;
+                    lz77_type_best = lz77_type;
+                }
+            }
+            lz77_types_to_try &= !lz77_type;
+            lz77_type <<= 1;
+        }
+        c_assert!(lz77_type_best > 0);
+
+        // Improve on simple LZ77 but only for high quality (TraceBackwards is
+        // costly).
+        if (lz77_type_best == kLZ77Standard || lz77_type_best == kLZ77Box) && quality >= 25 {
+            let hash_chain_tmp: &VP8LHashChain = if lz77_type_best == kLZ77Standard {
+                hash_chain
+            } else {
+                &hash_chain_box
+            };
+            if VP8LBackwardReferencesTraceBackwards(
+                width,
+                height,
+                argb,
+                *cache_bits,
+                hash_chain_tmp,
+                best,
+                worst,
+            ) != 0
+            {
+                VP8LHistogramCreate(histo, worst, *cache_bits);
+                let bit_cost_trace = VP8LHistogramEstimateBits(histo);
+                if bit_cost_trace < bit_cost_best {
+                    best = worst;
+                }
+            }
+        }
+
+        BackwardReferences2DLocality(width, best);
+    }
+
+    VP8LHashChainClear(&mut hash_chain_box);
+    VP8LFreeHistogram(histo);
+    best
+}
+
+// Evaluates best possible backward references for specified quality.
+// The input cache_bits to 'VP8LGetBackwardReferences' sets the maximum cache
+// bits to use (passing 0 implies disabling the local color cache).
+// The optimal cache bits is evaluated and set for the *cache_bits parameter.
+// The return value is the pointer to the best of the two backward refs viz,
+// refs[0] or refs[1].
+#[no_mangle]
+pub unsafe extern "C" fn VP8LGetBackwardReferences(
+    width: c_int,
+    height: c_int,
+    argb: *const u32,
+    quality: c_int,
+    low_effort: c_int,
+    lz77_types_to_try: c_int,
+    cache_bits: *mut c_int,
+    hash_chain: *const VP8LHashChain,
+    refs_tmp1: *mut VP8LBackwardRefs,
+    refs_tmp2: *mut VP8LBackwardRefs,
+) -> *mut VP8LBackwardRefs {
+    if low_effort != 0 {
+        GetBackwardReferencesLowEffort(
+            width,
+            height,
+            argb,
+            &mut *cache_bits,
+            &*hash_chain,
+            refs_tmp1,
+        )
+    } else {
+        GetBackwardReferences(
+            width,
+            height,
+            argb,
+            quality,
+            lz77_types_to_try,
+            &mut *cache_bits,
+            &*hash_chain,
+            refs_tmp1,
+            refs_tmp2,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use dsp::lossless_enc::VP8LEncDspInit;
+    use test_util::{argb_images, check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LDistanceToPlaneCode(xsize: c_int, dist: c_int) -> c_int;
+        fn WebPRef_VP8LBackwardRefsClear(refs: *mut VP8LBackwardRefs);
+        fn WebPRef_VP8LBackwardRefsInit(refs: *mut VP8LBackwardRefs, block_size: c_int);
+        fn WebPRef_VP8LBackwardRefsCursorAdd(refs: *mut VP8LBackwardRefs, v: PixOrCopy);
+        fn WebPRef_VP8LHashChainInit(p: *mut VP8LHashChain, size: c_int) -> c_int;
+        fn WebPRef_VP8LHashChainClear(p: *mut VP8LHashChain);
+        fn WebPRef_VP8LHashChainFill(
+            p: *mut VP8LHashChain,
+            quality: c_int,
+            argb: *const u32,
+            xsize: c_int,
+            ysize: c_int,
+            low_effort: c_int,
+        ) -> c_int;
+        fn WebPRef_VP8LGetBackwardReferences(
+            width: c_int,
+            height: c_int,
+            argb: *const u32,
+            quality: c_int,
+            low_effort: c_int,
+            lz77_types_to_try: c_int,
+            cache_bits: *mut c_int,
+            hash_chain: *const VP8LHashChain,
+            refs_tmp1: *mut VP8LBackwardRefs,
+            refs_tmp2: *mut VP8LBackwardRefs,
+        ) -> *mut VP8LBackwardRefs;
+    }
+
+    struct Api {
+        refs_init: unsafe extern "C" fn(*mut VP8LBackwardRefs, c_int),
+        refs_add: unsafe extern "C" fn(*mut VP8LBackwardRefs, PixOrCopy),
+        refs_clear: unsafe extern "C" fn(*mut VP8LBackwardRefs),
+        hash_chain_init: unsafe extern "C" fn(*mut VP8LHashChain, c_int) -> c_int,
+        hash_chain_fill: unsafe extern "C" fn(
+            *mut VP8LHashChain,
+            c_int,
+            *const u32,
+            c_int,
+            c_int,
+            c_int,
+        ) -> c_int,
+        hash_chain_clear: unsafe extern "C" fn(*mut VP8LHashChain),
+        get_backward_references: unsafe extern "C" fn(
+            c_int,
+            c_int,
+            *const u32,
+            c_int,
+            c_int,
+            c_int,
+            *mut c_int,
+            *const VP8LHashChain,
+            *mut VP8LBackwardRefs,
+            *mut VP8LBackwardRefs,
+        ) -> *mut VP8LBackwardRefs,
+    }
+
+    const PORT: Api = Api {
+        refs_init: VP8LBackwardRefsInit,
+        refs_add: VP8LBackwardRefsCursorAdd,
+        refs_clear: VP8LBackwardRefsClear,
+        hash_chain_init: VP8LHashChainInit,
+        hash_chain_fill: VP8LHashChainFill,
+        hash_chain_clear: VP8LHashChainClear,
+        get_backward_references: VP8LGetBackwardReferences,
+    };
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = Api {
+        refs_init: WebPRef_VP8LBackwardRefsInit,
+        refs_add: WebPRef_VP8LBackwardRefsCursorAdd,
+        refs_clear: WebPRef_VP8LBackwardRefsClear,
+        hash_chain_init: WebPRef_VP8LHashChainInit,
+        hash_chain_fill: WebPRef_VP8LHashChainFill,
+        hash_chain_clear: WebPRef_VP8LHashChainClear,
+        get_backward_references: WebPRef_VP8LGetBackwardReferences,
+    };
+
+    fn images() -> Vec<(c_int, c_int, Vec<u32>)> {
+        argb_images(
+            0x1234,
+            0x0f,
+            &[
+                (1, 1, 0, false),
+                (5, 3, 0, true),
+                (64, 40, 0, false),
+                (97, 61, 0, true),
+                (33, 17, 1, false),
+                (40, 21, 2, true),
+                (29, 30, 4, false),
+                (50, 31, 16, true),
+                (80, 52, 600, true),
+            ],
+        )
+    }
+
+    // The tokens stored in 'refs', in order, as (mode, len, argb_or_distance).
+    unsafe fn tokens(refs: *const VP8LBackwardRefs) -> Vec<(u8, u16, u32)> {
+        let mut tokens = Vec::new();
+        let mut c = VP8LRefsCursorInit(refs);
+        while VP8LRefsCursorOk(&c) {
+            let v = &*c.cur_pos;
+            tokens.push((v.mode, v.len, v.argb_or_distance));
+            VP8LRefsCursorNext(&mut c);
+        }
+        tokens
+    }
+
+    fn digest_tokens(digest: &mut Digest, tokens: &[(u8, u16, u32)]) {
+        for &(mode, len, argb_or_distance) in tokens {
+            digest.update(&[mode]);
+            digest.update_u16(&[len]);
+            digest.update_u32(&[argb_or_distance]);
+        }
+    }
+
+    unsafe fn hash_chain(
+        api: &Api,
+        argb: &[u32],
+        width: c_int,
+        height: c_int,
+        quality: c_int,
+        low_effort: c_int,
+    ) -> VP8LHashChain {
+        let mut hash_chain: VP8LHashChain = mem::zeroed();
+        assert_ne!((api.hash_chain_init)(&mut hash_chain, width * height), 0);
+        assert_ne!(
+            (api.hash_chain_fill)(
+                &mut hash_chain,
+                quality,
+                argb.as_ptr(),
+                width,
+                height,
+                low_effort
+            ),
+            0
+        );
+        hash_chain
+    }
+
+    // Returns the tokens of the best references, which of the two
+    // temporaries holds them, and the chosen cache bits.
+    fn backward_references(
+        api: &Api,
+        argb: &[u32],
+        width: c_int,
+        height: c_int,
+        (quality, low_effort, lz77_types_to_try, cache_bits): (c_int, c_int, c_int, c_int),
+    ) -> (Vec<(u8, u16, u32)>, usize, c_int) {
+        unsafe {
+            VP8LEncDspInit();
+            let mut hash_chain = hash_chain(api, argb, width, height, quality, low_effort);
+            let mut refs: [VP8LBackwardRefs; 2] = mem::zeroed();
+            let block_size = (width * height - 1) / MAX_REFS_BLOCK_PER_IMAGE + 1;
+            (api.refs_init)(&mut refs[0], block_size);
+            (api.refs_init)(&mut refs[1], block_size);
+            let mut cache_bits = cache_bits;
+            let (refs_0, refs_1) = refs.split_at_mut(1);
+            let best = (api.get_backward_references)(
+                width,
+                height,
+                argb.as_ptr(),
+                quality,
+                low_effort,
+                lz77_types_to_try,
+                &mut cache_bits,
+                &hash_chain,
+                &mut refs_0[0],
+                &mut refs_1[0],
+            );
+            assert!(!best.is_null());
+            let index = if ptr::eq(best, &refs[0]) { 0 } else { 1 };
+            let tokens = tokens(best);
+            (api.refs_clear)(&mut refs[0]);
+            (api.refs_clear)(&mut refs[1]);
+            (api.hash_chain_clear)(&mut hash_chain);
+            (tokens, index, cache_bits)
+        }
+    }
+
+    #[test]
+    fn test_distance_to_plane_code_parity() {
+        let mut digest = Digest::new();
+        for &xsize in &[1, 2, 7, 8, 9, 16, 100, 16383] {
+            for dist in 1..2000 {
+                let code = VP8LDistanceToPlaneCode(xsize, dist);
+                digest.update_i32(&[code]);
+                #[cfg(feature = "c-reference")]
+                assert_eq!(
+                    code,
+                    unsafe { WebPRef_VP8LDistanceToPlaneCode(xsize, dist) },
+                    "xsize {}, dist {}",
+                    xsize,
+                    dist
+                );
+            }
+        }
+        check_parity(
+            "enc::backward_references_enc::test_distance_to_plane_code_parity",
+            digest,
+        );
+    }
+
+    // Random tokens across several blocks, read back with the cursor.
+    #[test]
+    fn test_backward_refs_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(5);
+        for &(block_size, num_tokens) in &[(0, 0), (1, 3), (256, 255), (256, 257), (300, 2000)] {
+            let tokens: Vec<PixOrCopy> = (0..num_tokens)
+                .map(|_| match rng.next() % 3 {
+                    0 => PixOrCopyCreateLiteral(rng.next()),
+                    1 => PixOrCopyCreateCacheIdx((rng.next() % 1024) as c_int),
+                    _ => PixOrCopyCreateCopy(rng.next() % 1000 + 1, (rng.next() % 4095 + 1) as u16),
+                })
+                .collect();
+            let store = |api: &Api| unsafe {
+                let mut refs: VP8LBackwardRefs = mem::zeroed();
+                (api.refs_init)(&mut refs, block_size);
+                // Stored twice, to reuse the blocks after clearing.
+                for _ in 0..2 {
+                    VP8LClearBackwardRefs(&mut refs);
+                    for &v in &tokens {
+                        (api.refs_add)(&mut refs, v);
+                    }
+                }
+                assert_eq!(refs.error_, 0);
+                let stored = self::tokens(&refs);
+                (api.refs_clear)(&mut refs);
+                stored
+            };
+            let stored = store(&PORT);
+            assert_eq!(stored.len(), tokens.len());
+            digest_tokens(&mut digest, &stored);
+            #[cfg(feature = "c-reference")]
+            assert_eq!(stored, store(&REFERENCE));
+        }
+        check_parity(
+            "enc::backward_references_enc::test_backward_refs_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_hash_chain_fill_parity() {
+        let mut digest = Digest::new();
+        for (width, height, argb) in images() {
+            for &quality in &[0, 25, 50, 75, 90, 100] {
+                for &low_effort in &[0, 1] {
+                    let fill = |api: &Api| unsafe {
+                        VP8LEncDspInit();
+                        let mut hash_chain =
+                            hash_chain(api, &argb, width, height, quality, low_effort);
+                        let offset_length = slice::from_raw_parts(
+                            hash_chain.offset_length_,
+                            (width * height) as usize,
+                        )
+                        .to_vec();
+                        (api.hash_chain_clear)(&mut hash_chain);
+                        offset_length
+                    };
+                    let offset_length = fill(&PORT);
+                    digest.update_u32(&offset_length);
+                    #[cfg(feature = "c-reference")]
+                    assert!(
+                        offset_length == fill(&REFERENCE),
+                        "{}x{} image, quality {}, low effort {}",
+                        width,
+                        height,
+                        quality,
+                        low_effort
+                    );
+                }
+            }
+        }
+        check_parity(
+            "enc::backward_references_enc::test_hash_chain_fill_parity",
+            digest,
+        );
+    }
+
+    // Covers the low effort path, the LZ77 variants with and without the
+    // color cache, and the cost-based refinement of
+    // backward_references_cost_enc.rs at the higher qualities.
+    #[test]
+    fn test_get_backward_references_parity() {
+        let mut digest = Digest::new();
+        let params = [
+            (25, 1, kLZ77Standard | kLZ77RLE, 0),
+            (25, 1, kLZ77Standard | kLZ77RLE, MAX_COLOR_CACHE_BITS),
+            (10, 0, kLZ77RLE, 4),
+            (50, 0, kLZ77Standard | kLZ77RLE, 0),
+            (75, 0, kLZ77Standard | kLZ77RLE, MAX_COLOR_CACHE_BITS),
+            (75, 0, kLZ77Box, MAX_COLOR_CACHE_BITS),
+            (90, 0, kLZ77Standard, 6),
+            (100, 0, kLZ77Standard | kLZ77RLE, MAX_COLOR_CACHE_BITS),
+            (100, 0, kLZ77Box, 0),
+        ];
+        for (width, height, argb) in images() {
+            for &param in &params {
+                let (tokens, index, cache_bits) =
+                    backward_references(&PORT, &argb, width, height, param);
+                digest_tokens(&mut digest, &tokens);
+                digest.update_i32(&[index as i32, cache_bits]);
+                #[cfg(feature = "c-reference")]
+                assert!(
+                    (tokens, index, cache_bits)
+                        == backward_references(&REFERENCE, &argb, width, height, param),
+                    "{}x{} image, {:?}",
+                    width,
+                    height,
+                    param
+                );
+            }
+        }
+        check_parity(
+            "enc::backward_references_enc::test_get_backward_references_parity",
+            digest,
+        );
+    }
+}
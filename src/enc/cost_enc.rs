@@ -0,0 +1,627 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Cost tables for level and modes
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+
+use dec::common_dec::*;
+use dsp::enc::{
+    VP8EncBands, VP8EntropyCost, VP8GetResidualCost, VP8LevelFixedCosts, VP8SetResidualCoeffs,
+};
+use enc::iterator_enc::VP8IteratorNzToBytes;
+use enc::vp8i_enc::*;
+
+// On-the-fly info about the current set of residuals. Handy to avoid
+// passing zillions of params.
+#[repr(C)]
+pub struct VP8Residual {
+    pub(crate) first: c_int,
+    pub(crate) last: c_int,
+    pub(crate) coeffs: *const i16,
+
+    pub(crate) coeff_type: c_int,
+    pub(crate) prob: *mut ProbaArray,
+    pub(crate) stats: *mut StatsArray,
+    pub(crate) costs: CostArrayPtr,
+}
+
+// Record proba context used.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8RecordStats(bit: c_int, stats: *mut proba_t) -> c_int {
+    let mut p = *stats;
+    // An overflow is inbound. Note we handle this at 0xfffe0000u instead of
+    // 0xffff0000u to make sure p + 1u does not overflow.
+    if p >= 0xfffe0000 {
+        p = ((p + 1) >> 1) & 0x7fff7fff; // -> divide the stats by 2.
+    }
+    // record bit count (lower 16 bits) and increment total count (upper 16 bits).
+    p += 0x00010000 + bit as u32;
+    *stats = p;
+    bit
+}
+
+// Cost of coding one event with probability 'proba'.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8BitCost(bit: c_int, proba: u8) -> c_int {
+    unsafe {
+        if bit == 0 {
+            VP8EntropyCost[proba as usize] as c_int
+        } else {
+            VP8EntropyCost[255 - proba as usize] as c_int
+        }
+    }
+}
+
+// Level cost calculations
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LevelCost(table: *const u16, level: c_int) -> c_int {
+    VP8LevelFixedCosts[level as usize] as c_int
+        + *table.offset(if level > MAX_VARIABLE_LEVEL {
+            MAX_VARIABLE_LEVEL
+        } else {
+            level
+        } as isize) as c_int
+}
+
+//------------------------------------------------------------------------------
+// Level cost tables
+
+// For each given level, the following table gives the pattern of contexts to
+// use for coding it (in [][0]) as well as the bit value to use for each
+// context (in [][1]).
+#[rustfmt::skip]
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8LevelCodes: [[u16; 2]; MAX_VARIABLE_LEVEL as usize] = [
+  [0x001, 0x000], [0x007, 0x001], [0x00f, 0x005],
+  [0x00f, 0x00d], [0x033, 0x003], [0x033, 0x003], [0x033, 0x023],
+  [0x033, 0x023], [0x033, 0x023], [0x033, 0x023], [0x0d3, 0x013],
+  [0x0d3, 0x013], [0x0d3, 0x013], [0x0d3, 0x013], [0x0d3, 0x013],
+  [0x0d3, 0x013], [0x0d3, 0x013], [0x0d3, 0x013], [0x0d3, 0x093],
+  [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093],
+  [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093],
+  [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093],
+  [0x0d3, 0x093], [0x0d3, 0x093], [0x0d3, 0x093], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x053],
+  [0x153, 0x053], [0x153, 0x053], [0x153, 0x053], [0x153, 0x153]
+];
+
+#[allow(non_snake_case)]
+fn VariableLevelCost(level: c_int, probas: &[u8; NUM_PROBAS as usize]) -> c_int {
+    let mut pattern = VP8LevelCodes[level as usize - 1][0] as c_int;
+    let mut bits = VP8LevelCodes[level as usize - 1][1] as c_int;
+    let mut cost = 0;
+    let mut i = 2;
+    while pattern != 0 {
+        if (pattern & 1) != 0 {
+            cost += VP8BitCost(bits & 1, probas[i]);
+        }
+        bits >>= 1;
+        pattern >>= 1;
+        i += 1;
+    }
+    cost
+}
+
+//------------------------------------------------------------------------------
+// Pre-calc level costs once for all
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8CalculateLevelCosts(proba: *mut VP8EncProba) {
+    let proba = &mut *proba;
+
+    if proba.dirty_ == 0 {
+        return; // nothing to do.
+    }
+
+    for ctype in 0..NUM_TYPES as usize {
+        for band in 0..NUM_BANDS as usize {
+            for ctx in 0..NUM_CTX as usize {
+                let p = &proba.coeffs_[ctype][band][ctx];
+                let table = &mut proba.level_cost_[ctype][band][ctx];
+                let cost0 = if ctx > 0 { VP8BitCost(1, p[0]) } else { 0 };
+                let cost_base = VP8BitCost(1, p[1]) + cost0;
+                table[0] = (VP8BitCost(0, p[1]) + cost0) as u16;
+                for v in 1..=MAX_VARIABLE_LEVEL {
+                    table[v as usize] = (cost_base + VariableLevelCost(v, p)) as u16;
+                }
+                // Starting at level 67 and up, the variable part of the cost is
+                // actually constant.
+            }
+        }
+        for n in 0..16 {
+            // replicate bands. We don't need to sentinel.
+            for ctx in 0..NUM_CTX as usize {
+                proba.remapped_costs_[ctype][n][ctx] =
+                    proba.level_cost_[ctype][VP8EncBands[n] as usize][ctx].as_ptr();
+            }
+        }
+    }
+    proba.dirty_ = 0;
+}
+
+//------------------------------------------------------------------------------
+// Mode cost tables.
+
+// These are the fixed probabilities (in the coding trees) turned into bit-cost
+// by calling VP8BitCost().
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8FixedCostsUV: [u16; 4] = [302, 984, 439, 642];
+// note: these values include the fixed VP8BitCost(1, 145) mode selection cost.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8FixedCostsI16: [u16; 4] = [663, 919, 872, 919];
+#[rustfmt::skip]
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8FixedCostsI4: [[[u16; NUM_BMODES as usize]; NUM_BMODES as usize];
+                             NUM_BMODES as usize] = [
+  [ [   40, 1151, 1723, 1874, 2103, 2019, 1628, 1777, 2226, 2137 ],
+    [  192,  469, 1296, 1308, 1849, 1794, 1781, 1703, 1713, 1522 ],
+    [  142,  910,  762, 1684, 1849, 1576, 1460, 1305, 1801, 1657 ],
+    [  559,  641, 1370,  421, 1182, 1569, 1612, 1725,  863, 1007 ],
+    [  299, 1059, 1256, 1108,  636, 1068, 1581, 1883,  869, 1142 ],
+    [  277, 1111,  707, 1362, 1089,  672, 1603, 1541, 1545, 1291 ],
+    [  214,  781, 1609, 1303, 1632, 2229,  726, 1560, 1713,  918 ],
+    [  152, 1037, 1046, 1759, 1983, 2174, 1358,  742, 1740, 1390 ],
+    [  512, 1046, 1420,  753,  752, 1297, 1486, 1613,  460, 1207 ],
+    [  424,  827, 1362,  719, 1462, 1202, 1199, 1476, 1199,  538 ] ],
+  [ [  240,  402, 1134, 1491, 1659, 1505, 1517, 1555, 1979, 2099 ],
+    [  467,  242,  960, 1232, 1714, 1620, 1834, 1570, 1676, 1391 ],
+    [  500,  455,  463, 1507, 1699, 1282, 1564,  982, 2114, 2114 ],
+    [  672,  643, 1372,  331, 1589, 1667, 1453, 1938,  996,  876 ],
+    [  458,  783, 1037,  911,  738,  968, 1165, 1518,  859, 1033 ],
+    [  504,  815,  504, 1139, 1219,  719, 1506, 1085, 1268, 1268 ],
+    [  333,  630, 1445, 1239, 1883, 3672,  799, 1548, 1865,  598 ],
+    [  399,  644,  746, 1342, 1856, 1350, 1493,  613, 1855, 1015 ],
+    [  622,  749, 1205,  608, 1066, 1408, 1290, 1406,  546,  971 ],
+    [  500,  753, 1041,  668, 1230, 1617, 1297, 1425, 1383,  523 ] ],
+  [ [  394,  553,  523, 1502, 1536,  981, 1608, 1142, 1666, 2181 ],
+    [  655,  430,  375, 1411, 1861, 1220, 1677, 1135, 1978, 1553 ],
+    [  690,  640,  245, 1954, 2070, 1194, 1528,  982, 1972, 2232 ],
+    [  559,  834,  741,  867, 1131,  980, 1225,  852, 1092,  784 ],
+    [  690,  875,  516,  959,  673,  894, 1056, 1190, 1528, 1126 ],
+    [  740,  951,  384, 1277, 1177,  492, 1579, 1155, 1846, 1513 ],
+    [  323,  775, 1062, 1776, 3062, 1274,  813, 1188, 1372,  655 ],
+    [  488,  971,  484, 1767, 1515, 1775, 1115,  503, 1539, 1461 ],
+    [  740, 1006,  998,  709,  851, 1230, 1337,  788,  741,  721 ],
+    [  522, 1073,  573, 1045, 1346,  887, 1046, 1146, 1203,  697 ] ],
+  [ [  105,  864, 1442, 1009, 1934, 1840, 1519, 1920, 1673, 1579 ],
+    [  534,  305, 1193,  683, 1388, 2164, 1802, 1894, 1264, 1170 ],
+    [  305,  518,  877, 1108, 1426, 3215, 1425, 1064, 1320, 1242 ],
+    [  683,  732, 1927,  257, 1493, 2048, 1858, 1552, 1055,  947 ],
+    [  394,  814, 1024,  660,  959, 1556, 1282, 1289,  893, 1047 ],
+    [  528,  615,  996,  940, 1201,  635, 1094, 2515,  803, 1358 ],
+    [  347,  614, 1609, 1187, 3133, 1345, 1007, 1339, 1017,  667 ],
+    [  218,  740,  878, 1605, 3650, 3650, 1345,  758, 1357, 1617 ],
+    [  672,  750, 1541,  558, 1257, 1599, 1870, 2135,  402, 1087 ],
+    [  592,  684, 1161,  430, 1092, 1497, 1475, 1489, 1095,  822 ] ],
+  [ [  228, 1056, 1059, 1368,  752,  982, 1512, 1518,  987, 1782 ],
+    [  494,  514,  818,  942,  965,  892, 1610, 1356, 1048, 1363 ],
+    [  512,  648,  591, 1042,  761,  991, 1196, 1454, 1309, 1463 ],
+    [  683,  749, 1043,  676,  841, 1396, 1133, 1138,  654,  939 ],
+    [  622, 1101, 1126,  994,  361, 1077, 1203, 1318,  877, 1219 ],
+    [  631, 1068,  857, 1650,  651,  477, 1650, 1419,  828, 1170 ],
+    [  555,  727, 1068, 1335, 3127, 1339,  820, 1331, 1077,  429 ],
+    [  504,  879,  624, 1398,  889,  889, 1392,  808,  891, 1406 ],
+    [  683, 1602, 1289,  977,  578,  983, 1280, 1708,  406, 1122 ],
+    [  399,  865, 1433, 1070, 1072,  764,  968, 1477, 1223,  678 ] ],
+  [ [  333,  760,  935, 1638, 1010,  529, 1646, 1410, 1472, 2219 ],
+    [  512,  494,  750, 1160, 1215,  610, 1870, 1868, 1628, 1169 ],
+    [  572,  646,  492, 1934, 1208,  603, 1580, 1099, 1398, 1995 ],
+    [  786,  789,  942,  581, 1018,  951, 1599, 1207,  731,  768 ],
+    [  690, 1015,  672, 1078,  582,  504, 1693, 1438, 1108, 2897 ],
+    [  768, 1267,  571, 2005, 1243,  244, 2881, 1380, 1786, 1453 ],
+    [  452,  899, 1293,  903, 1311, 3100,  465, 1311, 1319,  813 ],
+    [  394,  927,  942, 1103, 1358, 1104,  946,  593, 1363, 1109 ],
+    [  559, 1005, 1007, 1016,  658, 1173, 1021, 1164,  623, 1028 ],
+    [  564,  796,  632, 1005, 1014,  863, 2316, 1268,  938,  764 ] ],
+  [ [  266,  606, 1098, 1228, 1497, 1243,  948, 1030, 1734, 1461 ],
+    [  366,  585,  901, 1060, 1407, 1247,  876, 1134, 1620, 1054 ],
+    [  452,  565,  542, 1729, 1479, 1479, 1016,  886, 2938, 1150 ],
+    [  555, 1088, 1533,  950, 1354,  895,  834, 1019, 1021,  496 ],
+    [  704,  815, 1193,  971,  973,  640, 1217, 2214,  832,  578 ],
+    [  672, 1245,  579,  871,  875,  774,  872, 1273, 1027,  949 ],
+    [  296, 1134, 2050, 1784, 1636, 3425,  442, 1550, 2076,  722 ],
+    [  342,  982, 1259, 1846, 1848, 1848,  622,  568, 1847, 1052 ],
+    [  555, 1064, 1304,  828,  746, 1343, 1075, 1329, 1078,  494 ],
+    [  288, 1167, 1285, 1174, 1639, 1639,  833, 2254, 1304,  509 ] ],
+  [ [  342,  719,  767, 1866, 1757, 1270, 1246,  550, 1746, 2151 ],
+    [  483,  653,  694, 1509, 1459, 1410, 1218,  507, 1914, 1266 ],
+    [  488,  757,  447, 2979, 1813, 1268, 1654,  539, 1849, 2109 ],
+    [  522, 1097, 1085,  851, 1365, 1111,  851,  901,  961,  605 ],
+    [  709,  716,  841,  728,  736,  945,  941,  862, 2845, 1057 ],
+    [  512, 1323,  500, 1336, 1083,  681, 1342,  717, 1604, 1350 ],
+    [  452, 1155, 1372, 1900, 1501, 3290,  311,  944, 1919,  922 ],
+    [  403, 1520,  977, 2132, 1733, 3522, 1076,  276, 3335, 1547 ],
+    [  559, 1374, 1101,  615,  673, 2462,  974,  795,  984,  984 ],
+    [  547, 1122, 1062,  812, 1410,  951, 1140,  622, 1268,  651 ] ],
+  [ [  165,  982, 1235,  938, 1334, 1366, 1659, 1578,  964, 1612 ],
+    [  592,  422,  925,  847, 1139, 1112, 1387, 2036,  861, 1041 ],
+    [  403,  837,  732,  770,  941, 1658, 1250,  809, 1407, 1407 ],
+    [  896,  874, 1071,  381, 1568, 1722, 1437, 2192,  480, 1035 ],
+    [  640, 1098, 1012, 1032,  684, 1382, 1581, 2106,  416,  865 ],
+    [  559, 1005,  819,  914,  710,  770, 1418,  920,  838, 1435 ],
+    [  415, 1258, 1245,  870, 1278, 3067,  770, 1021, 1287,  522 ],
+    [  406,  990,  601, 1009, 1265, 1265, 1267,  759, 1017, 1277 ],
+    [  968, 1182, 1329,  788, 1032, 1292, 1705, 1714,  203, 1403 ],
+    [  732,  877, 1279,  471,  901, 1161, 1545, 1294,  755,  755 ] ],
+  [ [  111,  931, 1378, 1185, 1933, 1648, 1148, 1714, 1873, 1307 ],
+    [  406,  414, 1030, 1023, 1910, 1404, 1313, 1647, 1509,  793 ],
+    [  342,  640,  575, 1088, 1241, 1349, 1161, 1350, 1756, 1502 ],
+    [  559,  766, 1185,  357, 1682, 1428, 1329, 1897, 1219,  802 ],
+    [  473,  909, 1164,  771,  719, 2508, 1427, 1432,  722,  782 ],
+    [  342,  892,  785, 1145, 1150,  794, 1296, 1550,  973, 1057 ],
+    [  208, 1036, 1326, 1343, 1606, 3395,  815, 1455, 1618,  712 ],
+    [  228,  928,  890, 1046, 3499, 1711,  994,  829, 1720, 1318 ],
+    [  768,  724, 1058,  636,  991, 1075, 1319, 1324,  616,  825 ],
+    [  305, 1167, 1358,  899, 1587, 1587,  987, 1988, 1332,  501 ] ]
+];
+
+//------------------------------------------------------------------------------
+// helper functions for residuals struct VP8Residual.
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitResidual(
+    first: c_int,
+    coeff_type: c_int,
+    enc: *mut VP8Encoder,
+    res: *mut VP8Residual,
+) {
+    (*res).coeff_type = coeff_type;
+    (*res).prob = (*enc).proba_.coeffs_[coeff_type as usize].as_mut_ptr();
+    (*res).stats = (*enc).proba_.stats_[coeff_type as usize].as_mut_ptr();
+    (*res).costs = (*enc).proba_.remapped_costs_[coeff_type as usize].as_ptr() as CostArrayPtr;
+    (*res).first = first;
+}
+
+//------------------------------------------------------------------------------
+// Mode costs
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetCostLuma4(it: *mut VP8EncIterator, levels: *const i16) -> c_int {
+    let x = (*it).i4_ & 3;
+    let y = (*it).i4_ >> 2;
+    let mut res: VP8Residual = mem::zeroed();
+    let enc = (*it).enc_;
+    let mut R = 0;
+
+    VP8InitResidual(0, 3, enc, &mut res);
+    let ctx = (*it).top_nz_[x as usize] + (*it).left_nz_[y as usize];
+    VP8SetResidualCoeffs.unwrap()(levels, &mut res);
+    R += VP8GetResidualCost.unwrap()(ctx, &res);
+    R
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetCostLuma16(
+    it: *mut VP8EncIterator,
+    rd: *const VP8ModeScore,
+) -> c_int {
+    let mut res: VP8Residual = mem::zeroed();
+    let enc = (*it).enc_;
+    let mut R = 0;
+
+    VP8IteratorNzToBytes(it); // re-import the non-zero context
+
+    // DC
+    VP8InitResidual(0, 1, enc, &mut res);
+    VP8SetResidualCoeffs.unwrap()((*rd).y_dc_levels.as_ptr(), &mut res);
+    R += VP8GetResidualCost.unwrap()((*it).top_nz_[8] + (*it).left_nz_[8], &res);
+
+    // AC
+    VP8InitResidual(1, 0, enc, &mut res);
+    for y in 0..4 {
+        for x in 0..4 {
+            let ctx = (*it).top_nz_[x] + (*it).left_nz_[y];
+            VP8SetResidualCoeffs.unwrap()((*rd).y_ac_levels[x + y * 4].as_ptr(), &mut res);
+            R += VP8GetResidualCost.unwrap()(ctx, &res);
+            let nz = (res.last >= 0) as c_int;
+            (*it).left_nz_[y] = nz;
+            (*it).top_nz_[x] = nz;
+        }
+    }
+    R
+}
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetCostUV(it: *mut VP8EncIterator, rd: *const VP8ModeScore) -> c_int {
+    let mut res: VP8Residual = mem::zeroed();
+    let enc = (*it).enc_;
+    let mut R = 0;
+
+    VP8IteratorNzToBytes(it); // re-import the non-zero context
+
+    VP8InitResidual(0, 2, enc, &mut res);
+    for ch in (0..=2).step_by(2) {
+        for y in 0..2 {
+            for x in 0..2 {
+                let ctx = (*it).top_nz_[4 + ch + x] + (*it).left_nz_[4 + ch + y];
+                VP8SetResidualCoeffs.unwrap()(
+                    (*rd).uv_levels[ch * 2 + x + y * 2].as_ptr(),
+                    &mut res,
+                );
+                R += VP8GetResidualCost.unwrap()(ctx, &res);
+                let nz = (res.last >= 0) as c_int;
+                (*it).left_nz_[4 + ch + y] = nz;
+                (*it).top_nz_[4 + ch + x] = nz;
+            }
+        }
+    }
+    R
+}
+
+//------------------------------------------------------------------------------
+// Recording of token probabilities.
+
+// Simulate block coding, but only record statistics.
+// Note: no need to record the fixed probas.
+#[no_mangle]
+pub unsafe extern "C" fn VP8RecordCoeffs(ctx: c_int, res: *const VP8Residual) -> c_int {
+    let res = &*res;
+    let mut n = res.first;
+    // should be stats[VP8EncBands[n]], but it's equivalent for n=0 or 1
+    let mut s: *mut proba_t = (*res.stats.offset(n as isize))[ctx as usize].as_mut_ptr();
+    if res.last < 0 {
+        VP8RecordStats(0, s.offset(0));
+        return 0;
+    }
+    while n <= res.last {
+        VP8RecordStats(1, s.offset(0)); // order of record doesn't matter
+        let mut v;
+        loop {
+            v = *res.coeffs.offset(n as isize) as c_int;
+            n += 1;
+            if v != 0 {
+                break;
+            }
+            VP8RecordStats(0, s.offset(1));
+            s = (*res.stats.offset(VP8EncBands[n as usize] as isize))[0].as_mut_ptr();
+        }
+        VP8RecordStats(1, s.offset(1));
+        if VP8RecordStats((2u32 < (v + 1) as c_uint) as c_int, s.offset(2)) == 0 {
+            // v = -1 or 1
+            s = (*res.stats.offset(VP8EncBands[n as usize] as isize))[1].as_mut_ptr();
+        } else {
+            v = v.abs();
+            if v > MAX_VARIABLE_LEVEL {
+                v = MAX_VARIABLE_LEVEL;
+            }
+
+            {
+                let bits = VP8LevelCodes[v as usize - 1][1] as c_int;
+                let mut pattern = VP8LevelCodes[v as usize - 1][0] as c_int;
+                let mut i = 0;
+                loop {
+                    pattern >>= 1;
+                    if pattern == 0 {
+                        break;
+                    }
+                    let mask = 2 << i;
+                    if (pattern & 1) != 0 {
+                        VP8RecordStats(((bits & mask) != 0) as c_int, s.offset(3 + i));
+                    }
+                    i += 1;
+                }
+            }
+            s = (*res.stats.offset(VP8EncBands[n as usize] as isize))[2].as_mut_ptr();
+        }
+    }
+    if n < 16 {
+        VP8RecordStats(0, s.offset(0));
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use encode::EncoderConfig;
+    use test_util::{
+        check_parity, coeff_levels, for_each_macroblock, lossy_images, stage_encoders, Digest, Lcg,
+    };
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8CalculateLevelCosts(proba: *mut VP8EncProba);
+        fn WebPRef_VP8InitResidual(
+            first: c_int,
+            coeff_type: c_int,
+            enc: *mut VP8Encoder,
+            res: *mut VP8Residual,
+        );
+        fn WebPRef_VP8GetCostLuma4(it: *mut VP8EncIterator, levels: *const i16) -> c_int;
+        fn WebPRef_VP8GetCostLuma16(it: *mut VP8EncIterator, rd: *const VP8ModeScore) -> c_int;
+        fn WebPRef_VP8GetCostUV(it: *mut VP8EncIterator, rd: *const VP8ModeScore) -> c_int;
+        fn WebPRef_VP8RecordCoeffs(ctx: c_int, res: *const VP8Residual) -> c_int;
+    }
+
+    struct Api {
+        calculate_level_costs: unsafe extern "C" fn(*mut VP8EncProba),
+        init_residual: unsafe extern "C" fn(c_int, c_int, *mut VP8Encoder, *mut VP8Residual),
+        get_cost_luma4: unsafe extern "C" fn(*mut VP8EncIterator, *const i16) -> c_int,
+        get_cost_luma16: unsafe extern "C" fn(*mut VP8EncIterator, *const VP8ModeScore) -> c_int,
+        get_cost_uv: unsafe extern "C" fn(*mut VP8EncIterator, *const VP8ModeScore) -> c_int,
+        record_coeffs: unsafe extern "C" fn(c_int, *const VP8Residual) -> c_int,
+    }
+
+    const PORT: Api = Api {
+        calculate_level_costs: VP8CalculateLevelCosts,
+        init_residual: VP8InitResidual,
+        get_cost_luma4: VP8GetCostLuma4,
+        get_cost_luma16: VP8GetCostLuma16,
+        get_cost_uv: VP8GetCostUV,
+        record_coeffs: VP8RecordCoeffs,
+    };
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = Api {
+        calculate_level_costs: WebPRef_VP8CalculateLevelCosts,
+        init_residual: WebPRef_VP8InitResidual,
+        get_cost_luma4: WebPRef_VP8GetCostLuma4,
+        get_cost_luma16: WebPRef_VP8GetCostLuma16,
+        get_cost_uv: WebPRef_VP8GetCostUV,
+        record_coeffs: WebPRef_VP8RecordCoeffs,
+    };
+
+    fn random_probas(proba: &mut VP8EncProba, seed: u32) {
+        let mut rng = Lcg(seed);
+        for p in proba.coeffs_.iter_mut().flat_map(|bands| bands.iter_mut()) {
+            for ctx in p.iter_mut() {
+                for v in ctx.iter_mut() {
+                    *v = rng.next() as u8;
+                }
+            }
+        }
+        proba.dirty_ = 1;
+    }
+
+    // The level costs, and the remapped costs as offsets into them.
+    fn level_costs(proba: &VP8EncProba) -> (Vec<u16>, Vec<u32>) {
+        let base = proba.level_cost_.as_ptr() as *const u16;
+        let size = mem::size_of_val(&proba.level_cost_) / mem::size_of::<u16>();
+        let costs = unsafe { slice::from_raw_parts(base, size) }.to_vec();
+        let mut offsets = Vec::new();
+        for map in proba.remapped_costs_.iter().flat_map(|maps| maps.iter()) {
+            offsets.extend(
+                map.iter()
+                    .map(|&p| ((p as usize - base as usize) / mem::size_of::<u16>()) as u32),
+            );
+        }
+        (costs, offsets)
+    }
+
+    fn stats(proba: &VP8EncProba) -> Vec<u32> {
+        let size = mem::size_of_val(&proba.stats_) / mem::size_of::<proba_t>();
+        unsafe { slice::from_raw_parts(proba.stats_.as_ptr() as *const proba_t, size) }.to_vec()
+    }
+
+    // Computes the costs of the levels of 'rd' for the macroblock of 'it',
+    // then records the statistics of each block. Returns the costs and the
+    // non-zero contexts left.
+    unsafe fn residual_costs(
+        api: &Api,
+        it: &mut VP8EncIterator,
+        rd: &VP8ModeScore,
+        nz: (u32, u32),
+    ) -> Vec<c_int> {
+        *it.nz_ = nz.0;
+        *it.nz_.offset(-1) = nz.1;
+        let mut costs = vec![(api.get_cost_luma16)(it, rd), (api.get_cost_uv)(it, rd)];
+        for i4 in 0..16 {
+            it.i4_ = i4;
+            costs.push((api.get_cost_luma4)(
+                it,
+                rd.y_ac_levels[i4 as usize].as_ptr(),
+            ));
+        }
+        costs.extend_from_slice(&it.top_nz_);
+        costs.extend_from_slice(&it.left_nz_);
+        for coeff_type in 0..NUM_TYPES {
+            let levels = match coeff_type {
+                0 => &rd.y_ac_levels[it.i4_ as usize],
+                1 => &rd.y_dc_levels,
+                2 => &rd.uv_levels[it.x_ as usize & 7],
+                _ => &rd.y_ac_levels[it.y_ as usize & 15],
+            };
+            let mut res: VP8Residual = mem::zeroed();
+            (api.init_residual)((coeff_type == 0) as c_int, coeff_type, it.enc_, &mut res);
+            VP8SetResidualCoeffs.unwrap()(levels.as_ptr(), &mut res);
+            for ctx in 0..NUM_CTX {
+                costs.push((api.record_coeffs)(ctx, &res));
+            }
+        }
+        costs
+    }
+
+    #[test]
+    fn test_calculate_level_costs_parity() {
+        let mut digest = Digest::new();
+        for seed in 0..8 {
+            let results: Vec<_> = [
+                &PORT,
+                #[cfg(feature = "c-reference")]
+                &REFERENCE,
+            ]
+            .iter()
+            .map(|api| unsafe {
+                let mut proba: Box<VP8EncProba> = Box::new(mem::zeroed());
+                random_probas(&mut proba, seed);
+                (api.calculate_level_costs)(&mut *proba);
+                assert_eq!(proba.dirty_, 0);
+                level_costs(&proba)
+            })
+            .collect();
+            digest.update_u16(&results[0].0);
+            digest.update_u32(&results[0].1);
+            #[cfg(feature = "c-reference")]
+            assert!(results[1] == results[0], "seed {}", seed);
+        }
+        check_parity(
+            "enc::cost_enc::tests::test_calculate_level_costs_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_residual_costs_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(0x5678);
+        for (seed, (width, height, rgba)) in lossy_images().into_iter().enumerate() {
+            let config = EncoderConfig::default();
+            let encs = stage_encoders(&rgba, width, height, &config, |enc| unsafe {
+                random_probas(&mut (*enc).proba_, seed as u32);
+                VP8CalculateLevelCosts(&mut (*enc).proba_);
+            });
+            for_each_macroblock(&encs, |its| unsafe {
+                let mut rd: VP8ModeScore = mem::zeroed();
+                rd.y_dc_levels = coeff_levels(&mut rng);
+                for levels in rd.y_ac_levels.iter_mut() {
+                    *levels = coeff_levels(&mut rng);
+                    // The DC coefficients go in y_dc_levels.
+                    levels[0] = 0;
+                }
+                for levels in rd.uv_levels.iter_mut() {
+                    *levels = coeff_levels(&mut rng);
+                }
+                let nz = (rng.next(), rng.next());
+                let costs = residual_costs(&PORT, &mut its[0], &rd, nz);
+                digest.update_i32(&costs);
+                #[cfg(feature = "c-reference")]
+                assert_eq!(residual_costs(&REFERENCE, &mut its[1], &rd, nz), costs);
+            });
+            let stats = stats(unsafe { &(*encs[0].enc).proba_ });
+            digest.update_u32(&stats);
+            #[cfg(feature = "c-reference")]
+            assert!(stats == self::stats(unsafe { &(*encs[1].enc).proba_ }));
+        }
+        check_parity("enc::cost_enc::tests::test_residual_costs_parity", digest);
+    }
+}
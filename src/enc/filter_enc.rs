@@ -0,0 +1,440 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Selecting filter level
+//
+// Author: somnath@google.com (Somnath Banerjee)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+use std::ptr;
+
+use dec::common_dec::*;
+use dsp::dec::{
+    VP8HFilter16i, VP8HFilter8i, VP8SimpleHFilter16i, VP8SimpleVFilter16i, VP8VFilter16i,
+    VP8VFilter8i,
+};
+use dsp::dsp::BPS;
+use dsp::enc::{VP8SSIMDspInit, VP8SSIMGetClipped, VP8_SSIM_KERNEL};
+use enc::vp8i_enc::*;
+
+// This table gives, for a given sharpness, the filtering strength to be
+// used (at least) in order to filter a given edge step delta.
+// This is constructed by brute force inspection: for all delta, we iterate
+// over all possible filtering strength / thresh until needs_filter() returns
+// true.
+const MAX_DELTA_SIZE: c_int = 64;
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kLevelsFromDelta: [[u8; MAX_DELTA_SIZE as usize]; 8] = [
+  [ 0,   1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15,
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+    48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63 ],
+  [ 0,  1,  2,  3,  5,  6,  7,  8,  9, 11, 12, 13, 14, 15, 17, 18,
+    20, 21, 23, 24, 26, 27, 29, 30, 32, 33, 35, 36, 38, 39, 41, 42,
+    44, 45, 47, 48, 50, 51, 53, 54, 56, 57, 59, 60, 62, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ],
+  [  0,  1,  2,  3,  5,  6,  7,  8,  9, 11, 12, 13, 14, 16, 17, 19,
+    20, 22, 23, 25, 26, 28, 29, 31, 32, 34, 35, 37, 38, 40, 41, 43,
+    44, 46, 47, 49, 50, 52, 53, 55, 56, 58, 59, 61, 62, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ],
+  [  0,  1,  2,  3,  5,  6,  7,  8,  9, 11, 12, 13, 15, 16, 18, 19,
+    21, 22, 24, 25, 27, 28, 30, 31, 33, 34, 36, 37, 39, 40, 42, 43,
+    45, 46, 48, 49, 51, 52, 54, 55, 57, 58, 60, 61, 63, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ],
+  [  0,  1,  2,  3,  5,  6,  7,  8,  9, 11, 12, 14, 15, 17, 18, 20,
+    21, 23, 24, 26, 27, 29, 30, 32, 33, 35, 36, 38, 39, 41, 42, 44,
+    45, 47, 48, 50, 51, 53, 54, 56, 57, 59, 60, 62, 63, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ],
+  [  0,  1,  2,  4,  5,  7,  8,  9, 11, 12, 13, 15, 16, 17, 19, 20,
+    22, 23, 25, 26, 28, 29, 31, 32, 34, 35, 37, 38, 40, 41, 43, 44,
+    46, 47, 49, 50, 52, 53, 55, 56, 58, 59, 61, 62, 63, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ],
+  [  0,  1,  2,  4,  5,  7,  8,  9, 11, 12, 13, 15, 16, 18, 19, 21,
+    22, 24, 25, 27, 28, 30, 31, 33, 34, 36, 37, 39, 40, 42, 43, 45,
+    46, 48, 49, 51, 52, 54, 55, 57, 58, 60, 61, 63, 63, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ],
+  [  0,  1,  2,  4,  5,  7,  8,  9, 11, 12, 14, 15, 17, 18, 20, 21,
+    23, 24, 26, 27, 29, 30, 32, 33, 35, 36, 38, 39, 41, 42, 44, 45,
+    47, 48, 50, 51, 53, 54, 56, 57, 59, 60, 62, 63, 63, 63, 63, 63,
+    63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63, 63 ]
+];
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8FilterStrengthFromDelta(sharpness: c_int, delta: c_int) -> c_int {
+    let pos = if delta < MAX_DELTA_SIZE {
+        delta
+    } else {
+        MAX_DELTA_SIZE - 1
+    };
+    c_assert!(sharpness >= 0 && sharpness <= 7);
+    kLevelsFromDelta[sharpness as usize][pos as usize] as c_int
+}
+
+//------------------------------------------------------------------------------
+// Paragraph 15.4: compute the inner-edge filtering strength
+
+#[allow(non_snake_case)]
+fn GetILevel(sharpness: c_int, mut level: c_int) -> c_int {
+    if sharpness > 0 {
+        if sharpness > 4 {
+            level >>= 2;
+        } else {
+            level >>= 1;
+        }
+        if level > 9 - sharpness {
+            level = 9 - sharpness;
+        }
+    }
+    if level < 1 {
+        level = 1;
+    }
+    level
+}
+
+#[allow(non_snake_case)]
+unsafe fn DoFilter(it: *const VP8EncIterator, level: c_int) {
+    let enc = (*it).enc_;
+    let ilevel = GetILevel((*(*enc).config_).filter_sharpness, level);
+    let limit = 2 * level + ilevel;
+
+    let y_dst = (*it).yuv_out2_.offset(Y_OFF_ENC as isize);
+    let u_dst = (*it).yuv_out2_.offset(U_OFF_ENC as isize);
+    let v_dst = (*it).yuv_out2_.offset(V_OFF_ENC as isize);
+
+    // copy current block to yuv_out2_
+    ptr::copy_nonoverlapping((*it).yuv_out_, y_dst, YUV_SIZE_ENC as usize);
+
+    if (*enc).filter_hdr_.simple_ == 1 {
+        // simple
+        VP8SimpleHFilter16i.unwrap()(y_dst, BPS, limit);
+        VP8SimpleVFilter16i.unwrap()(y_dst, BPS, limit);
+    } else {
+        // complex
+        let hev_thresh = if level >= 40 {
+            2
+        } else if level >= 15 {
+            1
+        } else {
+            0
+        };
+        VP8HFilter16i.unwrap()(y_dst, BPS, limit, ilevel, hev_thresh);
+        VP8HFilter8i.unwrap()(u_dst, v_dst, BPS, limit, ilevel, hev_thresh);
+        VP8VFilter16i.unwrap()(y_dst, BPS, limit, ilevel, hev_thresh);
+        VP8VFilter8i.unwrap()(u_dst, v_dst, BPS, limit, ilevel, hev_thresh);
+    }
+}
+
+//------------------------------------------------------------------------------
+// SSIM metric for one macroblock
+
+#[allow(non_snake_case)]
+unsafe fn GetMBSSIM(yuv1: *const u8, yuv2: *const u8) -> c_double {
+    let mut sum = 0.0;
+
+    // compute SSIM in a 10 x 10 window
+    for y in VP8_SSIM_KERNEL..16 - VP8_SSIM_KERNEL {
+        for x in VP8_SSIM_KERNEL..16 - VP8_SSIM_KERNEL {
+            sum += VP8SSIMGetClipped.unwrap()(
+                yuv1.offset(Y_OFF_ENC as isize),
+                BPS,
+                yuv2.offset(Y_OFF_ENC as isize),
+                BPS,
+                x,
+                y,
+                16,
+                16,
+            );
+        }
+    }
+    for x in 1..7 {
+        for y in 1..7 {
+            sum += VP8SSIMGetClipped.unwrap()(
+                yuv1.offset(U_OFF_ENC as isize),
+                BPS,
+                yuv2.offset(U_OFF_ENC as isize),
+                BPS,
+                x,
+                y,
+                8,
+                8,
+            );
+            sum += VP8SSIMGetClipped.unwrap()(
+                yuv1.offset(V_OFF_ENC as isize),
+                BPS,
+                yuv2.offset(V_OFF_ENC as isize),
+                BPS,
+                x,
+                y,
+                8,
+                8,
+            );
+        }
+    }
+    sum
+}
+
+//------------------------------------------------------------------------------
+// Exposed APIs: Encoder should call the following 3 functions to adjust
+// loop filter strength
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitFilter(it: *mut VP8EncIterator) {
+    if !(*it).lf_stats_.is_null() {
+        for s in 0..NUM_MB_SEGMENTS as usize {
+            for i in 0..MAX_LF_LEVELS as usize {
+                (*(*it).lf_stats_)[s][i] = 0.0;
+            }
+        }
+        VP8SSIMDspInit();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8StoreFilterStats(it: *mut VP8EncIterator) {
+    let enc = (*it).enc_;
+    let s = (*(*it).mb_).segment_ as usize;
+    let level0 = (*enc).dqm_[s].fstrength_;
+
+    // explore +/-quant range of values around level0
+    let delta_min = -(*enc).dqm_[s].quant_;
+    let delta_max = (*enc).dqm_[s].quant_;
+    let step_size = if delta_max - delta_min >= 4 { 4 } else { 1 };
+
+    if (*it).lf_stats_.is_null() {
+        return;
+    }
+
+    // NOTE: Currently we are applying filter only across the sublock edges
+    // There are two reasons for that.
+    // 1. Applying filter on macro block edges will change the pixels in
+    // the left and top macro blocks. That will be hard to restore
+    // 2. Macro Blocks on the bottom and right are not yet compressed. So we
+    // cannot apply filter on the right and bottom macro block edges.
+    if (*(*it).mb_).type_ == 1 && (*(*it).mb_).skip_ != 0 {
+        return;
+    }
+
+    // Always try filter level  zero
+    (*(*it).lf_stats_)[s][0] += GetMBSSIM((*it).yuv_in_, (*it).yuv_out_);
+
+    let mut d = delta_min;
+    while d <= delta_max {
+        let level = level0 + d;
+        d += step_size;
+        if level <= 0 || level >= MAX_LF_LEVELS {
+            continue;
+        }
+        DoFilter(it, level);
+        (*(*it).lf_stats_)[s][level as usize] += GetMBSSIM((*it).yuv_in_, (*it).yuv_out2_);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8AdjustFilterStrength(it: *mut VP8EncIterator) {
+    let enc = (*it).enc_;
+    if !(*it).lf_stats_.is_null() {
+        for s in 0..NUM_MB_SEGMENTS as usize {
+            let mut best_level = 0;
+            // Improvement over filter level 0 should be at least 1e-5 (relatively)
+            let mut best_v = 1.00001 * (*(*it).lf_stats_)[s][0];
+            for i in 1..MAX_LF_LEVELS {
+                let v = (*(*it).lf_stats_)[s][i as usize];
+                if v > best_v {
+                    best_v = v;
+                    best_level = i;
+                }
+            }
+            (*enc).dqm_[s].fstrength_ = best_level;
+        }
+        return;
+    }
+    if (*(*enc).config_).filter_strength > 0 {
+        let mut max_level = 0;
+        for s in 0..NUM_MB_SEGMENTS as usize {
+            let dqm = &mut (*enc).dqm_[s];
+            // this '>> 3' accounts for some inverse WHT scaling
+            let delta = (dqm.max_edge_ * dqm.y2_.q_[1] as c_int) >> 3;
+            let level = VP8FilterStrengthFromDelta((*enc).filter_hdr_.sharpness_, delta);
+            if level > dqm.fstrength_ {
+                dqm.fstrength_ = level;
+            }
+            if max_level < dqm.fstrength_ {
+                max_level = dqm.fstrength_;
+            }
+        }
+        (*enc).filter_hdr_.level_ = max_level;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem;
+
+    use enc::analysis_enc::VP8EncAnalyze;
+    use enc::cost_enc::VP8CalculateLevelCosts;
+    use enc::quant_enc::{VP8Decimate, VP8SetSegmentParams};
+    use encode::EncoderConfig;
+    use test_util::{
+        check_parity, for_each_macroblock, lossy_images, stage_encoders, Digest, StageEncoder,
+    };
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8FilterStrengthFromDelta(sharpness: c_int, delta: c_int) -> c_int;
+        fn WebPRef_VP8InitFilter(it: *mut VP8EncIterator);
+        fn WebPRef_VP8StoreFilterStats(it: *mut VP8EncIterator);
+        fn WebPRef_VP8AdjustFilterStrength(it: *mut VP8EncIterator);
+        fn WebPRef_VP8Decimate(
+            it: *mut VP8EncIterator,
+            rd: *mut VP8ModeScore,
+            rd_opt: VP8RDLevel,
+        ) -> c_int;
+    }
+
+    struct Api {
+        init_filter: unsafe extern "C" fn(*mut VP8EncIterator),
+        store_filter_stats: unsafe extern "C" fn(*mut VP8EncIterator),
+        adjust_filter_strength: unsafe extern "C" fn(*mut VP8EncIterator),
+        decimate: unsafe extern "C" fn(*mut VP8EncIterator, *mut VP8ModeScore, VP8RDLevel) -> c_int,
+    }
+
+    const PORT: Api = Api {
+        init_filter: VP8InitFilter,
+        store_filter_stats: VP8StoreFilterStats,
+        adjust_filter_strength: VP8AdjustFilterStrength,
+        decimate: VP8Decimate,
+    };
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = Api {
+        init_filter: WebPRef_VP8InitFilter,
+        store_filter_stats: WebPRef_VP8StoreFilterStats,
+        adjust_filter_strength: WebPRef_VP8AdjustFilterStrength,
+        decimate: WebPRef_VP8Decimate,
+    };
+
+    // With and without the filter statistics of autofilter.
+    fn configs() -> Vec<EncoderConfig> {
+        let base = EncoderConfig::default();
+        let mut configs: Vec<_> = (2..=6)
+            .map(|method| EncoderConfig {
+                autofilter: true,
+                method,
+                ..base
+            })
+            .collect();
+        configs.extend_from_slice(&[
+            base,
+            EncoderConfig {
+                autofilter: true,
+                segments: 1,
+                quality: 20.0,
+                ..base
+            },
+            EncoderConfig {
+                filter_strength: 0,
+                ..base
+            },
+            EncoderConfig {
+                filter_strength: 100,
+                filter_sharpness: 7,
+                ..base
+            },
+        ]);
+        configs
+    }
+
+    // The filter statistics, then the filter strengths they lead to.
+    fn filter_strengths(encoder: &StageEncoder) -> (Vec<u64>, Vec<c_int>) {
+        let enc = unsafe { &*encoder.enc };
+        let mut stats = Vec::new();
+        if !enc.lf_stats_.is_null() {
+            for segment in unsafe { &*enc.lf_stats_ } {
+                stats.extend(segment.iter().map(|v| v.to_bits()));
+            }
+        }
+        let mut strengths: Vec<_> = enc.dqm_.iter().map(|dqm| dqm.fstrength_).collect();
+        strengths.push(enc.filter_hdr_.level_);
+        (stats, strengths)
+    }
+
+    #[test]
+    fn test_filter_strength_from_delta_parity() {
+        let mut digest = Digest::new();
+        for sharpness in 0..=7 {
+            let levels: Vec<_> = (0..MAX_DELTA_SIZE + 8)
+                .map(|delta| unsafe { VP8FilterStrengthFromDelta(sharpness, delta) })
+                .collect();
+            digest.update_i32(&levels);
+            #[cfg(feature = "c-reference")]
+            {
+                for (delta, &level) in levels.iter().enumerate() {
+                    let ref_level =
+                        unsafe { WebPRef_VP8FilterStrengthFromDelta(sharpness, delta as c_int) };
+                    assert_eq!(level, ref_level, "sharpness {}, delta {}", sharpness, delta);
+                }
+            }
+        }
+        check_parity(
+            "enc::filter_enc::tests::test_filter_strength_from_delta_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_filter_stats_parity() {
+        let apis = [
+            &PORT,
+            #[cfg(feature = "c-reference")]
+            &REFERENCE,
+        ];
+        let mut digest = Digest::new();
+        for (width, height, rgba) in lossy_images() {
+            for config in &configs() {
+                let encs = stage_encoders(&rgba, width, height, config, |enc| unsafe {
+                    assert_ne!(VP8EncAnalyze(enc), 0);
+                    VP8SetSegmentParams(enc, config.quality);
+                    VP8CalculateLevelCosts(&mut (*enc).proba_);
+                });
+                let rd_opt = unsafe { (*encs[0].enc).rd_opt_level_ };
+                let mut its = for_each_macroblock(&encs, |its| {
+                    for (it, api) in its.iter_mut().zip(&apis) {
+                        unsafe {
+                            if it.x_ == 0 && it.y_ == 0 {
+                                (api.init_filter)(&mut **it);
+                            }
+                            let mut rd: VP8ModeScore = mem::zeroed();
+                            (api.decimate)(&mut **it, &mut rd, rd_opt);
+                            (api.store_filter_stats)(&mut **it);
+                        }
+                    }
+                });
+                for (it, api) in its.iter_mut().zip(&apis) {
+                    unsafe { (api.adjust_filter_strength)(&mut **it) };
+                }
+                let (stats, strengths) = filter_strengths(&encs[0]);
+                digest.update_u64(&stats);
+                digest.update_i32(&strengths);
+                #[cfg(feature = "c-reference")]
+                assert!(
+                    filter_strengths(&encs[1]) == (stats, strengths),
+                    "{}x{} image, {:?}",
+                    width,
+                    height,
+                    config
+                );
+            }
+        }
+        check_parity("enc::filter_enc::tests::test_filter_stats_parity", digest);
+    }
+}
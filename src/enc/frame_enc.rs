@@ -0,0 +1,991 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+//   frame coding and analysis
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dec::common_dec::*;
+use dsp::enc::{VP8EncBands, VP8SSE16x16, VP8SSE8x8, VP8SetResidualCoeffs};
+use enc::cost_enc::{
+    VP8BitCost, VP8CalculateLevelCosts, VP8InitResidual, VP8RecordCoeffs, VP8Residual,
+};
+use enc::filter_enc::{VP8AdjustFilterStrength, VP8InitFilter, VP8StoreFilterStats};
+use enc::iterator_enc::{
+    VP8IteratorBytesToNz, VP8IteratorExport, VP8IteratorImport, VP8IteratorInit, VP8IteratorNext,
+    VP8IteratorNzToBytes, VP8IteratorProgress, VP8IteratorSaveBoundary,
+};
+use enc::quant_enc::{VP8Decimate, VP8SetSegmentParams};
+use enc::syntax_enc::VP8EncFreeBitWriters;
+use enc::token_enc::{VP8EmitTokens, VP8EstimateTokenSize, VP8RecordCoeffTokens, VP8TBufferClear};
+use enc::tree_enc::{VP8CoeffsProba0, VP8CoeffsUpdateProba};
+use enc::vp8i_enc::VP8RDLevel::*;
+use enc::vp8i_enc::*;
+use enc::webp_enc::{WebPEncodingSetError, WebPReportProgress};
+use encode::WebPEncodingError::*;
+use format_constants::{
+    CHUNK_HEADER_SIZE, RIFF_HEADER_SIZE, VP8_FRAME_HEADER_SIZE, VP8_MAX_PARTITION0_SIZE,
+};
+use utils::bit_writer_utils::{
+    VP8BitWriter, VP8BitWriterFinish, VP8BitWriterInit, VP8BitWriterPos, VP8PutBit,
+    VP8PutBitUniform,
+};
+
+//------------------------------------------------------------------------------
+// multi-pass convergence
+
+const HEADER_SIZE_ESTIMATE: u64 =
+    (RIFF_HEADER_SIZE + CHUNK_HEADER_SIZE + VP8_FRAME_HEADER_SIZE) as u64;
+const DQ_LIMIT: c_double = 0.4; // convergence is considered reached if dq < DQ_LIMIT
+                                // we allow 2k of extra head-room in PARTITION0 limit.
+const PARTITION0_SIZE_LIMIT: u64 = (VP8_MAX_PARTITION0_SIZE as u64 - 2048) << 11;
+
+// struct for organizing convergence in either size or PSNR
+struct PassStats {
+    is_first: c_int,
+    dq: c_float,
+    q: c_float,
+    last_q: c_float,
+    value: c_double, // PSNR or size
+    last_value: c_double,
+    target: c_double,
+    do_size_search: c_int,
+}
+
+#[allow(non_snake_case)]
+unsafe fn InitPassStats(enc: *const VP8Encoder, s: &mut PassStats) -> c_int {
+    let target_size = (*(*enc).config_).target_size as u64;
+    let do_size_search = (target_size != 0) as c_int;
+    let target_PSNR = (*(*enc).config_).target_psnr;
+
+    s.is_first = 1;
+    s.dq = 10.0;
+    s.q = (*(*enc).config_).quality;
+    s.last_q = s.q;
+    s.target = if do_size_search != 0 {
+        target_size as c_double
+    } else if target_PSNR as c_double > 0. {
+        target_PSNR as c_double
+    } else {
+        40. // default, just in case
+    };
+    s.value = 0.;
+    s.last_value = 0.;
+    s.do_size_search = do_size_search;
+    do_size_search
+}
+
+#[allow(non_snake_case)]
+fn Clamp(v: c_float, min: c_float, max: c_float) -> c_float {
+    if v < min {
+        min
+    } else if v > max {
+        max
+    } else {
+        v
+    }
+}
+
+#[allow(non_snake_case)]
+fn ComputeNextQ(s: &mut PassStats) -> c_float {
+    let dq;
+    if s.is_first != 0 {
+        dq = if s.value > s.target { -s.dq } else { s.dq };
+        s.is_first = 0;
+    } else if s.value != s.last_value {
+        let slope = (s.target - s.value) / (s.last_value - s.value);
+        dq = (slope * (s.last_q - s.q) as c_double) as c_float;
+    } else {
+        dq = 0.; // we're done?!
+    }
+    // Limit variable to avoid large swings.
+    s.dq = Clamp(dq, -30.0, 30.0);
+    s.last_q = s.q;
+    s.last_value = s.value;
+    s.q = Clamp(s.q + s.dq, 0.0, 100.0);
+    s.q
+}
+
+//------------------------------------------------------------------------------
+// Tables for level coding
+
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8Cat3: [u8; 3] = [173, 148, 140];
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8Cat4: [u8; 4] = [176, 155, 140, 135];
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8Cat5: [u8; 5] = [180, 157, 141, 134, 130];
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8Cat6: [u8; 11] = [254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129];
+
+//------------------------------------------------------------------------------
+// Reset the statistics about: number of skips, token proba, level cost,...
+
+#[allow(non_snake_case)]
+unsafe fn ResetStats(enc: *mut VP8Encoder) {
+    let proba = &mut (*enc).proba_;
+    VP8CalculateLevelCosts(proba);
+    proba.nb_skip_ = 0;
+}
+
+//------------------------------------------------------------------------------
+// Skip decision probability
+
+const SKIP_PROBA_THRESHOLD: c_int = 250; // value below which using skip_proba is OK.
+
+#[allow(non_snake_case)]
+fn CalcSkipProba(nb: u64, total: u64) -> c_int {
+    ((total - nb) * 255).checked_div(total).unwrap_or(255) as c_int
+}
+
+// Returns the bit-cost for coding the skip probability.
+#[allow(non_snake_case)]
+unsafe fn FinalizeSkipProba(enc: *mut VP8Encoder) -> c_int {
+    let proba = &mut (*enc).proba_;
+    let nb_mbs = (*enc).mb_w_ * (*enc).mb_h_;
+    let nb_events = proba.nb_skip_;
+    proba.skip_proba_ = CalcSkipProba(nb_events as u64, nb_mbs as u64) as u8;
+    proba.use_skip_proba_ = ((proba.skip_proba_ as c_int) < SKIP_PROBA_THRESHOLD) as c_int;
+    let mut size = 256; // 'use_skip_proba' bit
+    if proba.use_skip_proba_ != 0 {
+        size += nb_events * VP8BitCost(1, proba.skip_proba_)
+            + (nb_mbs - nb_events) * VP8BitCost(0, proba.skip_proba_);
+        size += 8 * 256; // cost of signaling the skip_proba_ itself.
+    }
+    size
+}
+
+// Collect statistics and deduce probabilities for next coding pass.
+// Return the total bit-cost for coding the probability updates.
+#[allow(non_snake_case)]
+fn CalcTokenProba(nb: c_int, total: c_int) -> c_int {
+    c_assert!(nb <= total);
+    if nb != 0 {
+        255 - nb * 255 / total
+    } else {
+        255
+    }
+}
+
+// Cost of coding 'nb' 1's and 'total-nb' 0's using 'proba' probability.
+#[allow(non_snake_case)]
+fn BranchCost(nb: c_int, total: c_int, proba: c_int) -> c_int {
+    nb * VP8BitCost(1, proba as u8) + (total - nb) * VP8BitCost(0, proba as u8)
+}
+
+#[allow(non_snake_case)]
+unsafe fn ResetTokenStats(enc: *mut VP8Encoder) {
+    let proba = &mut (*enc).proba_;
+    proba.stats_ = mem::zeroed();
+}
+
+#[allow(non_snake_case)]
+fn FinalizeTokenProbas(proba: &mut VP8EncProba) -> c_int {
+    let mut has_changed = 0;
+    let mut size = 0;
+    for t in 0..NUM_TYPES as usize {
+        for b in 0..NUM_BANDS as usize {
+            for c in 0..NUM_CTX as usize {
+                for p in 0..NUM_PROBAS as usize {
+                    let stats = proba.stats_[t][b][c][p];
+                    let nb = ((stats >> 0) & 0xffff) as c_int;
+                    let total = ((stats >> 16) & 0xffff) as c_int;
+                    let update_proba = VP8CoeffsUpdateProba[t][b][c][p];
+                    let old_p = VP8CoeffsProba0[t][b][c][p] as c_int;
+                    let new_p = CalcTokenProba(nb, total);
+                    let old_cost = BranchCost(nb, total, old_p) + VP8BitCost(0, update_proba);
+                    let new_cost =
+                        BranchCost(nb, total, new_p) + VP8BitCost(1, update_proba) + 8 * 256;
+                    let use_new_p = (old_cost > new_cost) as c_int;
+                    size += VP8BitCost(use_new_p, update_proba);
+                    if use_new_p != 0 {
+                        // only use proba that seem meaningful enough.
+                        proba.coeffs_[t][b][c][p] = new_p as u8;
+                        has_changed |= (new_p != old_p) as c_int;
+                        size += 8 * 256;
+                    } else {
+                        proba.coeffs_[t][b][c][p] = old_p as u8;
+                    }
+                }
+            }
+        }
+    }
+    proba.dirty_ = has_changed;
+    size
+}
+
+//------------------------------------------------------------------------------
+// Finalize Segment probability based on the coding tree
+
+#[allow(non_snake_case)]
+fn GetProba(a: c_int, b: c_int) -> c_int {
+    let total = a + b;
+    if total == 0 {
+        255 // that's the default probability.
+    } else {
+        (255 * a + total / 2) / total // rounded proba
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ResetSegments(enc: *mut VP8Encoder) {
+    for n in 0..((*enc).mb_w_ * (*enc).mb_h_) as isize {
+        (*(*enc).mb_info_.offset(n)).segment_ = 0;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn SetSegmentProbas(enc: *mut VP8Encoder) {
+    let mut p = [0; NUM_MB_SEGMENTS as usize];
+
+    for n in 0..((*enc).mb_w_ * (*enc).mb_h_) as isize {
+        let mb = &*(*enc).mb_info_.offset(n);
+        p[mb.segment_ as usize] += 1;
+    }
+    if !(*(*enc).pic_).stats.is_null() {
+        (*(*(*enc).pic_).stats).segment_size = p;
+    }
+    if (*enc).segment_hdr_.num_segments_ > 1 {
+        let probas = &mut (*enc).proba_.segments_;
+        probas[0] = GetProba(p[0] + p[1], p[2] + p[3]) as u8;
+        probas[1] = GetProba(p[0], p[1]) as u8;
+        probas[2] = GetProba(p[2], p[3]) as u8;
+
+        (*enc).segment_hdr_.update_map_ =
+            (probas[0] != 255 || probas[1] != 255 || probas[2] != 255) as c_int;
+        if (*enc).segment_hdr_.update_map_ == 0 {
+            ResetSegments(enc);
+        }
+        (*enc).segment_hdr_.size_ = p[0] * (VP8BitCost(0, probas[0]) + VP8BitCost(0, probas[1]))
+            + p[1] * (VP8BitCost(0, probas[0]) + VP8BitCost(1, probas[1]))
+            + p[2] * (VP8BitCost(1, probas[0]) + VP8BitCost(0, probas[2]))
+            + p[3] * (VP8BitCost(1, probas[0]) + VP8BitCost(1, probas[2]));
+    } else {
+        (*enc).segment_hdr_.update_map_ = 0;
+        (*enc).segment_hdr_.size_ = 0;
+    }
+}
+
+//------------------------------------------------------------------------------
+// Coefficient coding
+
+#[allow(non_snake_case)]
+unsafe fn PutCoeffs(bw: *mut VP8BitWriter, ctx: c_int, res: *const VP8Residual) -> c_int {
+    let mut n = (*res).first;
+    // should be prob[VP8EncBands[n]], but it's equivalent for n=0 or 1
+    let mut p = (*(*res).prob.offset(n as isize))[ctx as usize].as_ptr();
+    if VP8PutBit(bw, ((*res).last >= 0) as c_int, *p.offset(0) as c_int) == 0 {
+        return 0;
+    }
+
+    while n < 16 {
+        let c = *(*res).coeffs.offset(n as isize) as c_int;
+        n += 1;
+        let sign = (c < 0) as c_int;
+        let mut v = if sign != 0 { -c } else { c };
+        if VP8PutBit(bw, (v != 0) as c_int, *p.offset(1) as c_int) == 0 {
+            p = (*(*res).prob.offset(VP8EncBands[n as usize] as isize))[0].as_ptr();
+            continue;
+        }
+        if VP8PutBit(bw, (v > 1) as c_int, *p.offset(2) as c_int) == 0 {
+            p = (*(*res).prob.offset(VP8EncBands[n as usize] as isize))[1].as_ptr();
+        } else {
+            if VP8PutBit(bw, (v > 4) as c_int, *p.offset(3) as c_int) == 0 {
+                if VP8PutBit(bw, (v != 2) as c_int, *p.offset(4) as c_int) != 0 {
+                    VP8PutBit(bw, (v == 4) as c_int, *p.offset(5) as c_int);
+                }
+            } else if VP8PutBit(bw, (v > 10) as c_int, *p.offset(6) as c_int) == 0 {
+                if VP8PutBit(bw, (v > 6) as c_int, *p.offset(7) as c_int) == 0 {
+                    VP8PutBit(bw, (v == 6) as c_int, 159);
+                } else {
+                    VP8PutBit(bw, (v >= 9) as c_int, 165);
+                    VP8PutBit(bw, (v & 1 == 0) as c_int, 145);
+                }
+            } else {
+                let mut mask;
+                let mut tab: *const u8;
+                if v < 3 + (8 << 1) {
+                    // VP8Cat3  (3b)
+                    VP8PutBit(bw, 0, *p.offset(8) as c_int);
+                    VP8PutBit(bw, 0, *p.offset(9) as c_int);
+                    v -= 3 + (8 << 0);
+                    mask = 1 << 2;
+                    tab = VP8Cat3.as_ptr();
+                } else if v < 3 + (8 << 2) {
+                    // VP8Cat4  (4b)
+                    VP8PutBit(bw, 0, *p.offset(8) as c_int);
+                    VP8PutBit(bw, 1, *p.offset(9) as c_int);
+                    v -= 3 + (8 << 1);
+                    mask = 1 << 3;
+                    tab = VP8Cat4.as_ptr();
+                } else if v < 3 + (8 << 3) {
+                    // VP8Cat5  (5b)
+                    VP8PutBit(bw, 1, *p.offset(8) as c_int);
+                    VP8PutBit(bw, 0, *p.offset(10) as c_int);
+                    v -= 3 + (8 << 2);
+                    mask = 1 << 4;
+                    tab = VP8Cat5.as_ptr();
+                } else {
+                    // VP8Cat6 (11b)
+                    VP8PutBit(bw, 1, *p.offset(8) as c_int);
+                    VP8PutBit(bw, 1, *p.offset(10) as c_int);
+                    v -= 3 + (8 << 3);
+                    mask = 1 << 10;
+                    tab = VP8Cat6.as_ptr();
+                }
+                while mask != 0 {
+                    VP8PutBit(bw, (v & mask != 0) as c_int, *tab as c_int);
+                    tab = tab.offset(1);
+                    mask >>= 1;
+                }
+            }
+            p = (*(*res).prob.offset(VP8EncBands[n as usize] as isize))[2].as_ptr();
+        }
+        VP8PutBitUniform(bw, sign);
+        if n == 16 || VP8PutBit(bw, (n <= (*res).last) as c_int, *p.offset(0) as c_int) == 0 {
+            return 1; // EOB
+        }
+    }
+    1
+}
+
+#[allow(non_snake_case)]
+unsafe fn CodeResiduals(bw: *mut VP8BitWriter, it: *mut VP8EncIterator, rd: *const VP8ModeScore) {
+    let mut res: VP8Residual = mem::zeroed();
+    let i16 = ((*(*it).mb_).type_ == 1) as usize;
+    let segment = (*(*it).mb_).segment_ as usize;
+    let enc = (*it).enc_;
+
+    VP8IteratorNzToBytes(it);
+
+    let pos1 = VP8BitWriterPos(&*bw);
+    if i16 != 0 {
+        VP8InitResidual(0, 1, enc, &mut res);
+        VP8SetResidualCoeffs.unwrap()((*rd).y_dc_levels.as_ptr(), &mut res);
+        let nz = PutCoeffs(bw, (*it).top_nz_[8] + (*it).left_nz_[8], &res);
+        (*it).top_nz_[8] = nz;
+        (*it).left_nz_[8] = nz;
+        VP8InitResidual(1, 0, enc, &mut res);
+    } else {
+        VP8InitResidual(0, 3, enc, &mut res);
+    }
+
+    // luma-AC
+    for y in 0..4 {
+        for x in 0..4 {
+            let ctx = (*it).top_nz_[x] + (*it).left_nz_[y];
+            VP8SetResidualCoeffs.unwrap()((*rd).y_ac_levels[x + y * 4].as_ptr(), &mut res);
+            let nz = PutCoeffs(bw, ctx, &res);
+            (*it).top_nz_[x] = nz;
+            (*it).left_nz_[y] = nz;
+        }
+    }
+    let pos2 = VP8BitWriterPos(&*bw);
+
+    // U/V
+    VP8InitResidual(0, 2, enc, &mut res);
+    for ch in (0..=2).step_by(2) {
+        for y in 0..2 {
+            for x in 0..2 {
+                let ctx = (*it).top_nz_[4 + ch + x] + (*it).left_nz_[4 + ch + y];
+                VP8SetResidualCoeffs.unwrap()(
+                    (*rd).uv_levels[ch * 2 + x + y * 2].as_ptr(),
+                    &mut res,
+                );
+                let nz = PutCoeffs(bw, ctx, &res);
+                (*it).top_nz_[4 + ch + x] = nz;
+                (*it).left_nz_[4 + ch + y] = nz;
+            }
+        }
+    }
+    let pos3 = VP8BitWriterPos(&*bw);
+    (*it).luma_bits_ = pos2.wrapping_sub(pos1);
+    (*it).uv_bits_ = pos3.wrapping_sub(pos2);
+    (*it).bit_count_[segment][i16] += (*it).luma_bits_;
+    (*it).bit_count_[segment][2] += (*it).uv_bits_;
+    VP8IteratorBytesToNz(it);
+}
+
+// Same as CodeResiduals, but doesn't actually write anything.
+// Instead, it just records the event distribution.
+#[allow(non_snake_case)]
+unsafe fn RecordResiduals(it: *mut VP8EncIterator, rd: *const VP8ModeScore) {
+    let mut res: VP8Residual = mem::zeroed();
+    let enc = (*it).enc_;
+
+    VP8IteratorNzToBytes(it);
+
+    if (*(*it).mb_).type_ == 1 {
+        // i16x16
+        VP8InitResidual(0, 1, enc, &mut res);
+        VP8SetResidualCoeffs.unwrap()((*rd).y_dc_levels.as_ptr(), &mut res);
+        let nz = VP8RecordCoeffs((*it).top_nz_[8] + (*it).left_nz_[8], &res);
+        (*it).top_nz_[8] = nz;
+        (*it).left_nz_[8] = nz;
+        VP8InitResidual(1, 0, enc, &mut res);
+    } else {
+        VP8InitResidual(0, 3, enc, &mut res);
+    }
+
+    // luma-AC
+    for y in 0..4 {
+        for x in 0..4 {
+            let ctx = (*it).top_nz_[x] + (*it).left_nz_[y];
+            VP8SetResidualCoeffs.unwrap()((*rd).y_ac_levels[x + y * 4].as_ptr(), &mut res);
+            let nz = VP8RecordCoeffs(ctx, &res);
+            (*it).top_nz_[x] = nz;
+            (*it).left_nz_[y] = nz;
+        }
+    }
+
+    // U/V
+    VP8InitResidual(0, 2, enc, &mut res);
+    for ch in (0..=2).step_by(2) {
+        for y in 0..2 {
+            for x in 0..2 {
+                let ctx = (*it).top_nz_[4 + ch + x] + (*it).left_nz_[4 + ch + y];
+                VP8SetResidualCoeffs.unwrap()(
+                    (*rd).uv_levels[ch * 2 + x + y * 2].as_ptr(),
+                    &mut res,
+                );
+                let nz = VP8RecordCoeffs(ctx, &res);
+                (*it).top_nz_[4 + ch + x] = nz;
+                (*it).left_nz_[4 + ch + y] = nz;
+            }
+        }
+    }
+
+    VP8IteratorBytesToNz(it);
+}
+
+//------------------------------------------------------------------------------
+// Token buffer
+
+#[allow(non_snake_case)]
+unsafe fn RecordTokens(
+    it: *mut VP8EncIterator,
+    rd: *const VP8ModeScore,
+    tokens: *mut VP8TBuffer,
+) -> c_int {
+    let mut res: VP8Residual = mem::zeroed();
+    let enc = (*it).enc_;
+
+    VP8IteratorNzToBytes(it);
+    if (*(*it).mb_).type_ == 1 {
+        // i16x16
+        let ctx = (*it).top_nz_[8] + (*it).left_nz_[8];
+        VP8InitResidual(0, 1, enc, &mut res);
+        VP8SetResidualCoeffs.unwrap()((*rd).y_dc_levels.as_ptr(), &mut res);
+        let nz = VP8RecordCoeffTokens(ctx, &res, tokens);
+        (*it).top_nz_[8] = nz;
+        (*it).left_nz_[8] = nz;
+        VP8InitResidual(1, 0, enc, &mut res);
+    } else {
+        VP8InitResidual(0, 3, enc, &mut res);
+    }
+
+    // luma-AC
+    for y in 0..4 {
+        for x in 0..4 {
+            let ctx = (*it).top_nz_[x] + (*it).left_nz_[y];
+            VP8SetResidualCoeffs.unwrap()((*rd).y_ac_levels[x + y * 4].as_ptr(), &mut res);
+            let nz = VP8RecordCoeffTokens(ctx, &res, tokens);
+            (*it).top_nz_[x] = nz;
+            (*it).left_nz_[y] = nz;
+        }
+    }
+
+    // U/V
+    VP8InitResidual(0, 2, enc, &mut res);
+    for ch in (0..=2).step_by(2) {
+        for y in 0..2 {
+            for x in 0..2 {
+                let ctx = (*it).top_nz_[4 + ch + x] + (*it).left_nz_[4 + ch + y];
+                VP8SetResidualCoeffs.unwrap()(
+                    (*rd).uv_levels[ch * 2 + x + y * 2].as_ptr(),
+                    &mut res,
+                );
+                let nz = VP8RecordCoeffTokens(ctx, &res, tokens);
+                (*it).top_nz_[4 + ch + x] = nz;
+                (*it).left_nz_[4 + ch + y] = nz;
+            }
+        }
+    }
+    VP8IteratorBytesToNz(it);
+    ((*tokens).error_ == 0) as c_int
+}
+
+//------------------------------------------------------------------------------
+// ExtraInfo map / Debug function
+
+#[allow(non_snake_case)]
+unsafe fn ResetSSE(enc: *mut VP8Encoder) {
+    (*enc).sse_[0] = 0;
+    (*enc).sse_[1] = 0;
+    (*enc).sse_[2] = 0;
+    // Note: enc->sse_[3] is managed by alpha.c
+    (*enc).sse_count_ = 0;
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreSSE(it: *const VP8EncIterator) {
+    let enc = (*it).enc_;
+    let in_ = (*it).yuv_in_;
+    let out = (*it).yuv_out_;
+    // Note: not totally accurate at boundary. And doesn't include in-loop filter.
+    (*enc).sse_[0] += VP8SSE16x16.unwrap()(
+        in_.offset(Y_OFF_ENC as isize),
+        out.offset(Y_OFF_ENC as isize),
+    ) as u64;
+    (*enc).sse_[1] += VP8SSE8x8.unwrap()(
+        in_.offset(U_OFF_ENC as isize),
+        out.offset(U_OFF_ENC as isize),
+    ) as u64;
+    (*enc).sse_[2] += VP8SSE8x8.unwrap()(
+        in_.offset(V_OFF_ENC as isize),
+        out.offset(V_OFF_ENC as isize),
+    ) as u64;
+    (*enc).sse_count_ += 16 * 16;
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreSideInfo(it: *const VP8EncIterator) {
+    let enc = (*it).enc_;
+    let mb = &*(*it).mb_;
+    let pic = (*enc).pic_;
+
+    if !(*pic).stats.is_null() {
+        StoreSSE(it);
+        (*enc).block_count_[0] += (mb.type_ == 0) as c_int;
+        (*enc).block_count_[1] += (mb.type_ == 1) as c_int;
+        (*enc).block_count_[2] += (mb.skip_ != 0) as c_int;
+    }
+
+    if !(*pic).extra_info.is_null() {
+        let info = (*pic)
+            .extra_info
+            .offset(((*it).x_ + (*it).y_ * (*enc).mb_w_) as isize);
+        *info = match (*pic).extra_info_type {
+            1 => mb.type_,
+            2 => mb.segment_,
+            3 => (*enc).dqm_[mb.segment_ as usize].quant_ as u8,
+            4 => {
+                if mb.type_ == 1 {
+                    *(*it).preds_
+                } else {
+                    0xff
+                }
+            }
+            5 => mb.uv_mode_,
+            6 => {
+                let b = (((*it).luma_bits_ + (*it).uv_bits_ + 7) >> 3) as c_int;
+                if b > 255 {
+                    255
+                } else {
+                    b as u8
+                }
+            }
+            7 => mb.alpha_,
+            _ => 0,
+        };
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ResetSideInfo(it: *const VP8EncIterator) {
+    let enc = (*it).enc_;
+    let pic = (*enc).pic_;
+    if !(*pic).stats.is_null() {
+        (*enc).block_count_ = [0; 3];
+    }
+    ResetSSE(enc);
+}
+
+#[allow(non_snake_case)]
+fn GetPSNR(mse: u64, size: u64) -> c_double {
+    if mse > 0 && size > 0 {
+        10. * (255. * 255. * size as c_double / mse as c_double).log10()
+    } else {
+        99.
+    }
+}
+
+//------------------------------------------------------------------------------
+//  StatLoop(): only collect statistics (number of skips, token usage, ...).
+//  This is used for deciding optimal probabilities. It also modifies the
+//  quantizer value if some target (size, PSNR) was specified.
+
+#[allow(non_snake_case)]
+unsafe fn SetLoopParams(enc: *mut VP8Encoder, mut q: c_float) {
+    // Make sure the quality parameter is inside valid bounds
+    q = Clamp(q, 0.0, 100.0);
+
+    VP8SetSegmentParams(enc, q); // setup segment quantizations and filters
+    SetSegmentProbas(enc); // compute segment probabilities
+
+    ResetStats(enc);
+    ResetSSE(enc);
+}
+
+#[allow(non_snake_case)]
+unsafe fn OneStatPass(
+    enc: *mut VP8Encoder,
+    rd_opt: VP8RDLevel,
+    mut nb_mbs: c_int,
+    percent_delta: c_int,
+    s: &mut PassStats,
+) -> u64 {
+    let mut it: VP8EncIterator = mem::zeroed();
+    let mut size: u64 = 0;
+    let mut size_p0: u64 = 0;
+    let mut distortion: u64 = 0;
+    let pixel_count = (nb_mbs * 384) as u64;
+
+    VP8IteratorInit(enc, &mut it);
+    SetLoopParams(enc, s.q);
+    loop {
+        let mut info: VP8ModeScore = mem::zeroed();
+        VP8IteratorImport(&mut it, ptr::null_mut());
+        if VP8Decimate(&mut it, &mut info, rd_opt) != 0 {
+            // Just record the number of skips and act like skip_proba is not used.
+            (*enc).proba_.nb_skip_ += 1;
+        }
+        RecordResiduals(&mut it, &info);
+        size = size.wrapping_add((info.R + info.H) as u64);
+        size_p0 = size_p0.wrapping_add(info.H as u64);
+        distortion = distortion.wrapping_add(info.D as u64);
+        if percent_delta != 0 && VP8IteratorProgress(&it, percent_delta) == 0 {
+            return 0;
+        }
+        VP8IteratorSaveBoundary(&mut it);
+        if VP8IteratorNext(&mut it) == 0 {
+            break;
+        }
+        nb_mbs -= 1;
+        if nb_mbs <= 0 {
+            break;
+        }
+    }
+
+    size_p0 = size_p0.wrapping_add((*enc).segment_hdr_.size_ as u64);
+    if s.do_size_search != 0 {
+        size = size.wrapping_add(FinalizeSkipProba(enc) as u64);
+        size = size.wrapping_add(FinalizeTokenProbas(&mut (*enc).proba_) as u64);
+        size = ((size + size_p0 + 1024) >> 11) + HEADER_SIZE_ESTIMATE;
+        s.value = size as c_double;
+    } else {
+        s.value = GetPSNR(distortion, pixel_count);
+    }
+    size_p0
+}
+
+#[allow(non_snake_case)]
+unsafe fn StatLoop(enc: *mut VP8Encoder) -> c_int {
+    let method = (*enc).method_;
+    let do_search = (*enc).do_search_;
+    let fast_probe = (method == 0 || method == 3) && do_search == 0;
+    let mut num_pass_left = (*(*enc).config_).pass;
+    let task_percent = 20;
+    let percent_per_pass = (task_percent + num_pass_left / 2) / num_pass_left;
+    let final_percent = (*enc).percent_ + task_percent;
+    let rd_opt = if method >= 3 || do_search != 0 {
+        RD_OPT_BASIC
+    } else {
+        RD_OPT_NONE
+    };
+    let mut nb_mbs = (*enc).mb_w_ * (*enc).mb_h_;
+    let mut stats: PassStats = mem::zeroed();
+
+    InitPassStats(enc, &mut stats);
+    ResetTokenStats(enc);
+
+    // Fast mode: quick analysis pass over few mbs. Better than nothing.
+    if fast_probe {
+        if method == 3 {
+            // we need more stats for method 3 to be reliable.
+            nb_mbs = if nb_mbs > 200 { nb_mbs >> 1 } else { 100 };
+        } else {
+            nb_mbs = if nb_mbs > 200 { nb_mbs >> 2 } else { 50 };
+        }
+    }
+
+    while num_pass_left > 0 {
+        num_pass_left -= 1;
+        let is_last_pass = (stats.dq as c_double).abs() <= DQ_LIMIT
+            || num_pass_left == 0
+            || (*enc).max_i4_header_bits_ == 0;
+        let size_p0 = OneStatPass(enc, rd_opt, nb_mbs, percent_per_pass, &mut stats);
+        if size_p0 == 0 {
+            return 0;
+        }
+        if (*enc).max_i4_header_bits_ > 0 && size_p0 > PARTITION0_SIZE_LIMIT {
+            num_pass_left += 1;
+            (*enc).max_i4_header_bits_ >>= 1; // strengthen header bit limitation...
+            continue; // ...and start over
+        }
+        if is_last_pass {
+            break;
+        }
+        // If no target size: just do several pass without changing 'q'
+        if do_search != 0 {
+            ComputeNextQ(&mut stats);
+            if (stats.dq as c_double).abs() <= DQ_LIMIT {
+                break;
+            }
+        }
+    }
+    if do_search == 0 || stats.do_size_search == 0 {
+        // Need to finalize probas now, since it wasn't done during the search.
+        FinalizeSkipProba(enc);
+        FinalizeTokenProbas(&mut (*enc).proba_);
+    }
+    VP8CalculateLevelCosts(&mut (*enc).proba_); // finalize costs
+    WebPReportProgress((*enc).pic_, final_percent, &mut (*enc).percent_)
+}
+
+//------------------------------------------------------------------------------
+// Main loops
+//
+
+#[allow(non_upper_case_globals)]
+static kAverageBytesPerMB: [u8; 8] = [50, 24, 16, 9, 7, 5, 3, 2];
+
+#[allow(non_snake_case)]
+unsafe fn PreLoopInitialize(enc: *mut VP8Encoder) -> c_int {
+    let mut ok = 1;
+    let average_bytes_per_MB = kAverageBytesPerMB[((*enc).base_quant_ >> 4) as usize] as c_int;
+    let bytes_per_parts = (*enc).mb_w_ * (*enc).mb_h_ * average_bytes_per_MB / (*enc).num_parts_;
+    // Initialize the bit-writers
+    let mut p = 0;
+    while ok != 0 && p < (*enc).num_parts_ {
+        ok = VP8BitWriterInit(&mut (*enc).parts_[p as usize], bytes_per_parts as usize);
+        p += 1;
+    }
+    if ok == 0 {
+        VP8EncFreeBitWriters(enc); // malloc error occurred
+        WebPEncodingSetError((*enc).pic_, VP8_ENC_ERROR_OUT_OF_MEMORY);
+    }
+    ok
+}
+
+#[allow(non_snake_case)]
+unsafe fn PostLoopFinalize(it: *mut VP8EncIterator, mut ok: c_int) -> c_int {
+    let enc = (*it).enc_;
+    if ok != 0 {
+        // Finalize the partitions, check for extra errors.
+        for p in 0..(*enc).num_parts_ as usize {
+            VP8BitWriterFinish(&mut (*enc).parts_[p]);
+            ok &= ((*enc).parts_[p].error_ == 0) as c_int;
+        }
+    }
+
+    if ok != 0 {
+        // All good. Finish up.
+        if !(*(*enc).pic_).stats.is_null() {
+            // finalize byte counters...
+            for i in 0..=2 {
+                for s in 0..NUM_MB_SEGMENTS as usize {
+                    (*enc).residual_bytes_[i][s] = (((*it).bit_count_[s][i] + 7) >> 3) as c_int;
+                }
+            }
+        }
+        VP8AdjustFilterStrength(it); // ...and store filter stats.
+    } else {
+        // Something bad happened -> need to do some memory cleanup.
+        VP8EncFreeBitWriters(enc);
+    }
+    ok
+}
+
+//------------------------------------------------------------------------------
+//  VP8EncLoop(): does the final bitstream coding.
+
+#[allow(non_snake_case)]
+unsafe fn ResetAfterSkip(it: *mut VP8EncIterator) {
+    if (*(*it).mb_).type_ == 1 {
+        *(*it).nz_ = 0; // reset all predictors
+        (*it).left_nz_[8] = 0;
+    } else {
+        *(*it).nz_ &= 1 << 24; // preserve the dc_nz bit
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8EncLoop(enc: *mut VP8Encoder) -> c_int {
+    let mut it: VP8EncIterator = mem::zeroed();
+    let mut ok = PreLoopInitialize(enc);
+    if ok == 0 {
+        return 0;
+    }
+
+    StatLoop(enc); // stats-collection loop
+
+    VP8IteratorInit(enc, &mut it);
+    VP8InitFilter(&mut it);
+    loop {
+        let mut info: VP8ModeScore = mem::zeroed();
+        let dont_use_skip = (*enc).proba_.use_skip_proba_ == 0;
+        let rd_opt = (*enc).rd_opt_level_;
+
+        VP8IteratorImport(&mut it, ptr::null_mut());
+        // Warning! order is important: first call VP8Decimate() and
+        // *then* decide how to code the skip decision if there's one.
+        if VP8Decimate(&mut it, &mut info, rd_opt) == 0 || dont_use_skip {
+            CodeResiduals(it.bw_, &mut it, &info);
+        } else {
+            // reset predictors after a skip
+            ResetAfterSkip(&mut it);
+        }
+        StoreSideInfo(&it);
+        VP8StoreFilterStats(&mut it);
+        VP8IteratorExport(&it);
+        ok = VP8IteratorProgress(&it, 20);
+        VP8IteratorSaveBoundary(&mut it);
+        if !(ok != 0 && VP8IteratorNext(&mut it) != 0) {
+            break;
+        }
+    }
+
+    PostLoopFinalize(&mut it, ok)
+}
+
+//------------------------------------------------------------------------------
+// Single pass using Token Buffer.
+
+const MIN_COUNT: c_int = 96; // minimum number of macroblocks before updating stats
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8EncTokenLoop(enc: *mut VP8Encoder) -> c_int {
+    // Roughly refresh the proba eight times per pass
+    let mut max_count = ((*enc).mb_w_ * (*enc).mb_h_) >> 3;
+    let mut num_pass_left = (*(*enc).config_).pass;
+    let do_search = (*enc).do_search_;
+    let mut it: VP8EncIterator = mem::zeroed();
+    let proba: *mut VP8EncProba = &mut (*enc).proba_;
+    let rd_opt = (*enc).rd_opt_level_;
+    let pixel_count = ((*enc).mb_w_ * (*enc).mb_h_ * 384) as u64;
+    let mut stats: PassStats = mem::zeroed();
+
+    InitPassStats(enc, &mut stats);
+    let mut ok = PreLoopInitialize(enc);
+    if ok == 0 {
+        return 0;
+    }
+
+    if max_count < MIN_COUNT {
+        max_count = MIN_COUNT;
+    }
+
+    c_assert!((*enc).num_parts_ == 1);
+    c_assert!((*enc).use_tokens_ != 0);
+    c_assert!((*proba).use_skip_proba_ == 0);
+    c_assert!(rd_opt >= RD_OPT_BASIC); // otherwise, token-buffer won't be useful
+    c_assert!(num_pass_left > 0);
+
+    while ok != 0 && num_pass_left > 0 {
+        num_pass_left -= 1;
+        let is_last_pass = (stats.dq as c_double).abs() <= DQ_LIMIT
+            || num_pass_left == 0
+            || (*enc).max_i4_header_bits_ == 0;
+        let mut size_p0: u64 = 0;
+        let mut distortion: u64 = 0;
+        let mut cnt = max_count;
+        VP8IteratorInit(enc, &mut it);
+        SetLoopParams(enc, stats.q);
+        if is_last_pass {
+            ResetTokenStats(enc);
+            VP8InitFilter(&mut it); // don't collect stats until last pass (too costly)
+        }
+        VP8TBufferClear(&mut (*enc).tokens_);
+        loop {
+            let mut info: VP8ModeScore = mem::zeroed();
+            VP8IteratorImport(&mut it, ptr::null_mut());
+            cnt -= 1;
+            if cnt < 0 {
+                FinalizeTokenProbas(&mut *proba);
+                VP8CalculateLevelCosts(proba); // refresh cost tables for rd-opt
+                cnt = max_count;
+            }
+            VP8Decimate(&mut it, &mut info, rd_opt);
+            ok = RecordTokens(&mut it, &info, &mut (*enc).tokens_);
+            if ok == 0 {
+                WebPEncodingSetError((*enc).pic_, VP8_ENC_ERROR_OUT_OF_MEMORY);
+                break;
+            }
+            size_p0 = size_p0.wrapping_add(info.H as u64);
+            distortion = distortion.wrapping_add(info.D as u64);
+            if is_last_pass {
+                StoreSideInfo(&it);
+                VP8StoreFilterStats(&mut it);
+                VP8IteratorExport(&it);
+                ok = VP8IteratorProgress(&it, 20);
+            }
+            VP8IteratorSaveBoundary(&mut it);
+            if !(ok != 0 && VP8IteratorNext(&mut it) != 0) {
+                break;
+            }
+        }
+        if ok == 0 {
+            break;
+        }
+
+        size_p0 = size_p0.wrapping_add((*enc).segment_hdr_.size_ as u64);
+        if stats.do_size_search != 0 {
+            let mut size = FinalizeTokenProbas(&mut (*enc).proba_) as u64;
+            size +=
+                VP8EstimateTokenSize(&mut (*enc).tokens_, (*proba).coeffs_.as_ptr() as *const u8)
+                    as u64;
+            size = (size + size_p0 + 1024) >> 11; // -> size in bytes
+            size += HEADER_SIZE_ESTIMATE;
+            stats.value = size as c_double;
+        } else {
+            // compute and store PSNR
+            stats.value = GetPSNR(distortion, pixel_count);
+        }
+
+        if (*enc).max_i4_header_bits_ > 0 && size_p0 > PARTITION0_SIZE_LIMIT {
+            num_pass_left += 1;
+            (*enc).max_i4_header_bits_ >>= 1; // strengthen header bit limitation...
+            if is_last_pass {
+                ResetSideInfo(&it);
+            }
+            continue; // ...and start over
+        }
+        if is_last_pass {
+            break; // done
+        }
+        if do_search != 0 {
+            ComputeNextQ(&mut stats); // Adjust q
+        }
+    }
+    if ok != 0 {
+        if stats.do_size_search == 0 {
+            FinalizeTokenProbas(&mut (*enc).proba_);
+        }
+        ok = VP8EmitTokens(
+            &mut (*enc).tokens_,
+            &mut (*enc).parts_[0],
+            (*proba).coeffs_.as_ptr() as *const u8,
+            1,
+        );
+    }
+    ok = (ok != 0
+        && WebPReportProgress((*enc).pic_, (*enc).percent_ + 20, &mut (*enc).percent_) != 0)
+        as c_int;
+    PostLoopFinalize(&mut it, ok)
+}
@@ -0,0 +1,1708 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Author: Jyrki Alakuijala (jyrki@google.com)
+//
+// Models the histograms of literal and distance codes.
+//
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::lossless_common::VP8LSubSampleSize;
+use dsp::lossless_enc::{
+    VP8LBitEntropy, VP8LBitsEntropyUnrefined, VP8LExtraCost, VP8LExtraCostCombined,
+    VP8LGetCombinedEntropyUnrefined, VP8LGetEntropyUnrefined, VP8LHistogramAdd,
+    VP8LPrefixEncodeBits, VP8LStreaks,
+};
+use enc::backward_references_enc::{
+    PixOrCopy, PixOrCopyCacheIdx, PixOrCopyDistance, PixOrCopyIsCacheIdx, PixOrCopyIsLiteral,
+    PixOrCopyLength, PixOrCopyLiteral, VP8LBackwardRefs, VP8LRefsCursorInit, VP8LRefsCursorNext,
+    VP8LRefsCursorOk,
+};
+use format_constants::{
+    CODE_LENGTH_CODES, NUM_DISTANCE_CODES, NUM_LENGTH_CODES, NUM_LITERAL_CODES,
+};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc, WEBP_ALIGN, WEBP_ALIGN_CST};
+
+// Not a trivial literal symbol.
+pub(crate) const VP8L_NON_TRIVIAL_SYM: u32 = 0xffffffff;
+
+// A simple container for histograms of data.
+#[repr(C)]
+pub struct VP8LHistogram {
+    // literal_ contains green literal, palette-code and
+    // copy-length-prefix histogram
+    pub(crate) literal_: *mut u32, // Pointer to the allocated buffer for literal.
+    pub(crate) red_: [u32; NUM_LITERAL_CODES as usize],
+    pub(crate) blue_: [u32; NUM_LITERAL_CODES as usize],
+    pub(crate) alpha_: [u32; NUM_LITERAL_CODES as usize],
+    // Backward reference prefix-code histogram.
+    pub(crate) distance_: [u32; NUM_DISTANCE_CODES as usize],
+    pub(crate) palette_code_bits_: c_int,
+    pub(crate) trivial_symbol_: u32, // True, if histograms for Red, Blue & Alpha
+    // literal symbols are single valued.
+    pub(crate) bit_cost_: c_double,     // cached value of bit cost.
+    pub(crate) literal_cost_: c_double, // Cached values of dominant entropy costs:
+    pub(crate) red_cost_: c_double,     // literal, red & blue.
+    pub(crate) blue_cost_: c_double,
+}
+
+// Collection of histograms with fixed capacity, allocated as one
+// big memory chunk. Can be destroyed by calling WebPSafeFree().
+#[repr(C)]
+pub struct VP8LHistogramSet {
+    pub(crate) size: c_int,     // number of slots currently in use
+    pub(crate) max_size: c_int, // maximum capacity
+    pub(crate) histograms: *mut *mut VP8LHistogram,
+}
+
+// Return the size of the histogram for a given palette_code_bits.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LHistogramNumCodes(palette_code_bits: c_int) -> c_int {
+    NUM_LITERAL_CODES
+        + NUM_LENGTH_CODES
+        + if palette_code_bits > 0 {
+            1 << palette_code_bits
+        } else {
+            0
+        }
+}
+
+const MAX_COST: c_double = 1.0e38;
+
+// Number of partitions for the three dominant (literal, red and blue) symbol
+// costs.
+const NUM_PARTITIONS: c_int = 4;
+// The size of the bin-hash corresponding to the three dominant costs.
+const BIN_SIZE: c_int = NUM_PARTITIONS * NUM_PARTITIONS * NUM_PARTITIONS;
+// Maximum number of histograms allowed in greedy combining algorithm.
+const MAX_HISTO_GREEDY: c_int = 100;
+
+#[allow(non_snake_case)]
+unsafe fn HistogramClear(p: *mut VP8LHistogram) {
+    let literal = (*p).literal_;
+    let cache_bits = (*p).palette_code_bits_;
+    let histo_size = VP8LGetHistogramSize(cache_bits);
+    ptr::write_bytes(p as *mut u8, 0, histo_size as usize);
+    (*p).palette_code_bits_ = cache_bits;
+    (*p).literal_ = literal;
+}
+
+#[allow(non_snake_case)]
+unsafe fn HistogramCopy(src: *const VP8LHistogram, dst: *mut VP8LHistogram) {
+    let dst_literal = (*dst).literal_;
+    let dst_cache_bits = (*dst).palette_code_bits_;
+    let histo_size = VP8LGetHistogramSize(dst_cache_bits);
+    c_assert!((*src).palette_code_bits_ == dst_cache_bits);
+    ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, histo_size as usize);
+    (*dst).literal_ = dst_literal;
+}
+
+// Return the size of the histogram for a given cache_bits.
+#[no_mangle]
+pub extern "C" fn VP8LGetHistogramSize(cache_bits: c_int) -> c_int {
+    let literal_size = VP8LHistogramNumCodes(cache_bits);
+    let total_size =
+        mem::size_of::<VP8LHistogram>() + mem::size_of::<c_int>() * literal_size as usize;
+    c_assert!(total_size <= 0x7fffffff);
+    total_size as c_int
+}
+
+// Free the memory allocated for the histogram.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LFreeHistogram(histo: *mut VP8LHistogram) {
+    WebPSafeFree(histo as *mut c_void);
+}
+
+// Free the memory allocated for the histogram set.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LFreeHistogramSet(histo: *mut VP8LHistogramSet) {
+    WebPSafeFree(histo as *mut c_void);
+}
+
+// Collect all the references into a histogram (without reset)
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHistogramStoreRefs(
+    refs: *const VP8LBackwardRefs,
+    histo: *mut VP8LHistogram,
+) {
+    let mut c = VP8LRefsCursorInit(refs);
+    while VP8LRefsCursorOk(&c) {
+        VP8LHistogramAddSinglePixOrCopy(histo, c.cur_pos, None, 0);
+        VP8LRefsCursorNext(&mut c);
+    }
+}
+
+// Create the histogram.
+//
+// The input data is the PixOrCopy data, which models the literals, stop
+// codes and backward references (both distances and lengths).  Also: if
+// palette_code_bits is >= 0, initialize the histogram with this value.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHistogramCreate(
+    p: *mut VP8LHistogram,
+    refs: *const VP8LBackwardRefs,
+    palette_code_bits: c_int,
+) {
+    if palette_code_bits >= 0 {
+        (*p).palette_code_bits_ = palette_code_bits;
+    }
+    HistogramClear(p);
+    VP8LHistogramStoreRefs(refs, p);
+}
+
+// Set the palette_code_bits and reset the stats.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHistogramInit(p: *mut VP8LHistogram, palette_code_bits: c_int) {
+    (*p).palette_code_bits_ = palette_code_bits;
+    HistogramClear(p);
+}
+
+// Allocate and initialize histogram object with specified 'cache_bits'.
+// Returns NULL in case of memory error.
+// Special case of VP8LAllocateHistogramSet, with size equals 1.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LAllocateHistogram(cache_bits: c_int) -> *mut VP8LHistogram {
+    let total_size = VP8LGetHistogramSize(cache_bits);
+    let memory = WebPSafeMalloc(total_size as u64, mem::size_of::<u8>()) as *mut u8;
+    if memory.is_null() {
+        return ptr::null_mut();
+    }
+    let histo = memory as *mut VP8LHistogram;
+    // literal_ won't necessary be aligned.
+    (*histo).literal_ = memory.add(mem::size_of::<VP8LHistogram>()) as *mut u32;
+    VP8LHistogramInit(histo, cache_bits);
+    histo
+}
+
+// Allocate an array of pointer to histograms, allocated and initialized
+// using 'cache_bits'. Return NULL in case of memory error.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LAllocateHistogramSet(
+    size: c_int,
+    cache_bits: c_int,
+) -> *mut VP8LHistogramSet {
+    let histo_size = VP8LGetHistogramSize(cache_bits);
+    let total_size = mem::size_of::<VP8LHistogramSet>()
+        + size as usize
+            * (mem::size_of::<*mut VP8LHistogram>() + histo_size as usize + WEBP_ALIGN_CST);
+    let mut memory = WebPSafeMalloc(total_size as u64, mem::size_of::<u8>()) as *mut u8;
+    if memory.is_null() {
+        return ptr::null_mut();
+    }
+
+    let set = memory as *mut VP8LHistogramSet;
+    memory = memory.add(mem::size_of::<VP8LHistogramSet>());
+    (*set).histograms = memory as *mut *mut VP8LHistogram;
+    memory = memory.add(size as usize * mem::size_of::<*mut VP8LHistogram>());
+    (*set).max_size = size;
+    (*set).size = size;
+    for i in 0..size as usize {
+        memory = WEBP_ALIGN(memory);
+        let histo = memory as *mut VP8LHistogram;
+        *(*set).histograms.add(i) = histo;
+        // literal_ won't necessary be aligned.
+        (*histo).literal_ = memory.add(mem::size_of::<VP8LHistogram>()) as *mut u32;
+        VP8LHistogramInit(histo, cache_bits);
+        memory = memory.add(histo_size as usize);
+    }
+    set
+}
+
+// -----------------------------------------------------------------------------
+
+// Accumulate a token 'v' into a histogram.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHistogramAddSinglePixOrCopy(
+    histo: *mut VP8LHistogram,
+    v: *const PixOrCopy,
+    distance_modifier: Option<extern "C" fn(c_int, c_int) -> c_int>,
+    distance_modifier_arg0: c_int,
+) {
+    let histo = &mut *histo;
+    let v = &*v;
+    if PixOrCopyIsLiteral(v) {
+        histo.alpha_[PixOrCopyLiteral(v, 3) as usize] += 1;
+        histo.red_[PixOrCopyLiteral(v, 2) as usize] += 1;
+        *histo.literal_.add(PixOrCopyLiteral(v, 1) as usize) += 1;
+        histo.blue_[PixOrCopyLiteral(v, 0) as usize] += 1;
+    } else if PixOrCopyIsCacheIdx(v) {
+        let literal_ix =
+            (NUM_LITERAL_CODES + NUM_LENGTH_CODES) as usize + PixOrCopyCacheIdx(v) as usize;
+        *histo.literal_.add(literal_ix) += 1;
+    } else {
+        let mut code = 0;
+        let mut extra_bits = 0;
+        VP8LPrefixEncodeBits(PixOrCopyLength(v) as c_int, &mut code, &mut extra_bits);
+        *histo.literal_.add((NUM_LITERAL_CODES + code) as usize) += 1;
+        match distance_modifier {
+            None => {
+                VP8LPrefixEncodeBits(PixOrCopyDistance(v) as c_int, &mut code, &mut extra_bits);
+            }
+            Some(distance_modifier) => {
+                VP8LPrefixEncodeBits(
+                    distance_modifier(distance_modifier_arg0, PixOrCopyDistance(v) as c_int),
+                    &mut code,
+                    &mut extra_bits,
+                );
+            }
+        }
+        histo.distance_[code as usize] += 1;
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Entropy-related functions.
+
+#[allow(non_snake_case)]
+#[inline]
+fn BitsEntropyRefine(entropy: &VP8LBitEntropy) -> c_double {
+    let mix;
+    if entropy.nonzeros < 5 {
+        if entropy.nonzeros <= 1 {
+            return 0.0;
+        }
+        // Two symbols, they will be 0 and 1 in a Huffman code.
+        // Let's mix in a bit of entropy to favor good clustering when
+        // distributions of these are combined.
+        if entropy.nonzeros == 2 {
+            return 0.99 * entropy.sum as c_double + 0.01 * entropy.entropy;
+        }
+        // No matter what the entropy says, we cannot be better than min_limit
+        // with Huffman coding. I am mixing a bit of entropy into the
+        // min_limit since it produces much better (~0.5 %) compression results
+        // perhaps because of better entropy clustering.
+        if entropy.nonzeros == 3 {
+            mix = 0.95;
+        } else {
+            mix = 0.7; // nonzeros == 4.
+        }
+    } else {
+        mix = 0.627;
+    }
+
+    let mut min_limit = (2u32.wrapping_mul(entropy.sum)).wrapping_sub(entropy.max_val) as c_double;
+    min_limit = mix * min_limit + (1.0 - mix) * entropy.entropy;
+    if entropy.entropy < min_limit {
+        min_limit
+    } else {
+        entropy.entropy
+    }
+}
+
+// Returns the entropy for the symbols in the input array.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitsEntropy(array: *const u32, n: c_int) -> c_double {
+    let mut entropy = VP8LBitEntropy::default();
+    VP8LBitsEntropyUnrefined(array, n, &mut entropy);
+
+    BitsEntropyRefine(&entropy)
+}
+
+#[allow(non_snake_case)]
+fn InitialHuffmanCost() -> c_double {
+    // Small bias because Huffman code length is typically not stored in
+    // full length.
+    #[allow(non_upper_case_globals)]
+    const kHuffmanCodeOfHuffmanCodeSize: c_int = CODE_LENGTH_CODES * 3;
+    #[allow(non_upper_case_globals)]
+    const kSmallBias: c_double = 9.1;
+    kHuffmanCodeOfHuffmanCodeSize as c_double - kSmallBias
+}
+
+// Finalize the Huffman cost based on streak numbers and length type (<3 or >=3)
+#[allow(non_snake_case)]
+fn FinalHuffmanCost(stats: &VP8LStreaks) -> c_double {
+    // The constants in this function are experimental and got rounded from
+    // their original values in 1/8 when switched to 1/1024.
+    let mut retval = InitialHuffmanCost();
+    // Second coefficient: Many zeros in the histogram are covered efficiently
+    // by a run-length encode. Originally 2/8.
+    retval += stats.counts[0] as c_double * 1.5625 + 0.234375 * stats.streaks[0][1] as c_double;
+    // Second coefficient: Constant values are encoded less efficiently, but still
+    // RLE'ed. Originally 6/8.
+    retval += stats.counts[1] as c_double * 2.578125 + 0.703125 * stats.streaks[1][1] as c_double;
+    // 0s are usually encoded more efficiently than non-0s.
+    // Originally 15/8.
+    retval += 1.796875 * stats.streaks[0][0] as c_double;
+    // Originally 26/8.
+    retval += 3.28125 * stats.streaks[1][0] as c_double;
+    retval
+}
+
+// Get the symbol entropy for the distribution 'population'.
+// Set 'trivial_sym', if there's only one symbol present in the distribution.
+#[allow(non_snake_case)]
+unsafe fn PopulationCost(
+    population: *const u32,
+    length: c_int,
+    trivial_sym: Option<&mut u32>,
+) -> c_double {
+    let mut bit_entropy = VP8LBitEntropy::default();
+    let mut stats = VP8LStreaks::default();
+    VP8LGetEntropyUnrefined.unwrap()(population, length, &mut bit_entropy, &mut stats);
+    if let Some(trivial_sym) = trivial_sym {
+        *trivial_sym = if bit_entropy.nonzeros == 1 {
+            bit_entropy.nonzero_code
+        } else {
+            VP8L_NON_TRIVIAL_SYM
+        };
+    }
+
+    BitsEntropyRefine(&bit_entropy) + FinalHuffmanCost(&stats)
+}
+
+// trivial_at_end is 1 if the two histograms only have one element that is
+// non-zero: both the zero-th one, or both the last one.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetCombinedEntropy(
+    X: *const u32,
+    Y: *const u32,
+    length: c_int,
+    trivial_at_end: bool,
+) -> c_double {
+    let mut stats = VP8LStreaks::default();
+    if trivial_at_end {
+        // This configuration is due to palettization that transforms an indexed
+        // pixel into 0xff000000 | (pixel << 8) in VP8LBundleColorMap.
+        // BitsEntropyRefine is 0 for histograms with only one non-zero value.
+        // Only FinalHuffmanCost needs to be evaluated.
+        // Deal with the non-zero value at index 0 or length-1.
+        stats.streaks[1][0] += 1;
+        // Deal with the following/previous zero streak.
+        stats.counts[0] += 1;
+        stats.streaks[0][1] += length - 1;
+        FinalHuffmanCost(&stats)
+    } else {
+        let mut bit_entropy = VP8LBitEntropy::default();
+        VP8LGetCombinedEntropyUnrefined.unwrap()(X, Y, length, &mut bit_entropy, &mut stats);
+
+        BitsEntropyRefine(&bit_entropy) + FinalHuffmanCost(&stats)
+    }
+}
+
+// Estimate how many bits the combined entropy of literals and distance
+// approximately maps to.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHistogramEstimateBits(p: *const VP8LHistogram) -> c_double {
+    let p = &*p;
+    PopulationCost(
+        p.literal_,
+        VP8LHistogramNumCodes(p.palette_code_bits_),
+        None,
+    ) + PopulationCost(p.red_.as_ptr(), NUM_LITERAL_CODES, None)
+        + PopulationCost(p.blue_.as_ptr(), NUM_LITERAL_CODES, None)
+        + PopulationCost(p.alpha_.as_ptr(), NUM_LITERAL_CODES, None)
+        + PopulationCost(p.distance_.as_ptr(), NUM_DISTANCE_CODES, None)
+        + VP8LExtraCost.unwrap()(p.literal_.add(NUM_LITERAL_CODES as usize), NUM_LENGTH_CODES)
+        + VP8LExtraCost.unwrap()(p.distance_.as_ptr(), NUM_DISTANCE_CODES)
+}
+
+// -----------------------------------------------------------------------------
+// Various histogram combine/cost-eval functions
+
+#[allow(non_snake_case)]
+unsafe fn GetCombinedHistogramEntropy(
+    a: *const VP8LHistogram,
+    b: *const VP8LHistogram,
+    cost_threshold: c_double,
+    cost: &mut c_double,
+) -> bool {
+    let a = &*a;
+    let b = &*b;
+    let palette_code_bits = a.palette_code_bits_;
+    let mut trivial_at_end = false;
+    c_assert!(a.palette_code_bits_ == b.palette_code_bits_);
+    *cost += GetCombinedEntropy(
+        a.literal_,
+        b.literal_,
+        VP8LHistogramNumCodes(palette_code_bits),
+        false,
+    );
+    *cost += VP8LExtraCostCombined.unwrap()(
+        a.literal_.add(NUM_LITERAL_CODES as usize),
+        b.literal_.add(NUM_LITERAL_CODES as usize),
+        NUM_LENGTH_CODES,
+    );
+    if *cost > cost_threshold {
+        return false;
+    }
+
+    if a.trivial_symbol_ != VP8L_NON_TRIVIAL_SYM && a.trivial_symbol_ == b.trivial_symbol_ {
+        // A, R and B are all 0 or 0xff.
+        let color_a = (a.trivial_symbol_ >> 24) & 0xff;
+        let color_r = (a.trivial_symbol_ >> 16) & 0xff;
+        let color_b = a.trivial_symbol_ & 0xff;
+        if (color_a == 0 || color_a == 0xff)
+            && (color_r == 0 || color_r == 0xff)
+            && (color_b == 0 || color_b == 0xff)
+        {
+            trivial_at_end = true;
+        }
+    }
+
+    *cost += GetCombinedEntropy(
+        a.red_.as_ptr(),
+        b.red_.as_ptr(),
+        NUM_LITERAL_CODES,
+        trivial_at_end,
+    );
+    if *cost > cost_threshold {
+        return false;
+    }
+
+    *cost += GetCombinedEntropy(
+        a.blue_.as_ptr(),
+        b.blue_.as_ptr(),
+        NUM_LITERAL_CODES,
+        trivial_at_end,
+    );
+    if *cost > cost_threshold {
+        return false;
+    }
+
+    *cost += GetCombinedEntropy(
+        a.alpha_.as_ptr(),
+        b.alpha_.as_ptr(),
+        NUM_LITERAL_CODES,
+        trivial_at_end,
+    );
+    if *cost > cost_threshold {
+        return false;
+    }
+
+    *cost += GetCombinedEntropy(
+        a.distance_.as_ptr(),
+        b.distance_.as_ptr(),
+        NUM_DISTANCE_CODES,
+        false,
+    );
+    *cost += VP8LExtraCostCombined.unwrap()(
+        a.distance_.as_ptr(),
+        b.distance_.as_ptr(),
+        NUM_DISTANCE_CODES,
+    );
+    if *cost > cost_threshold {
+        return false;
+    }
+
+    true
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn HistogramAdd(a: *const VP8LHistogram, b: *const VP8LHistogram, out: *mut VP8LHistogram) {
+    VP8LHistogramAdd.unwrap()(a, b, out);
+    (*out).trivial_symbol_ = if (*a).trivial_symbol_ == (*b).trivial_symbol_ {
+        (*a).trivial_symbol_
+    } else {
+        VP8L_NON_TRIVIAL_SYM
+    };
+}
+
+// Performs out = a + b, computing the cost C(a+b) - C(a) - C(b) while comparing
+// to the threshold value 'cost_threshold'. The score returned is
+//  Score = C(a+b) - C(a) - C(b), where C(a) + C(b) is known and fixed.
+// Since the previous score passed is 'cost_threshold', we only need to compare
+// the partial cost against 'cost_threshold + C(a) + C(b)' to possibly bail-out
+// early.
+#[allow(non_snake_case)]
+unsafe fn HistogramAddEval(
+    a: *const VP8LHistogram,
+    b: *const VP8LHistogram,
+    out: *mut VP8LHistogram,
+    mut cost_threshold: c_double,
+) -> c_double {
+    let mut cost = 0.0;
+    let sum_cost = (*a).bit_cost_ + (*b).bit_cost_;
+    cost_threshold += sum_cost;
+
+    if GetCombinedHistogramEntropy(a, b, cost_threshold, &mut cost) {
+        HistogramAdd(a, b, out);
+        (*out).bit_cost_ = cost;
+        (*out).palette_code_bits_ = (*a).palette_code_bits_;
+    }
+
+    cost - sum_cost
+}
+
+// Same as HistogramAddEval(), except that the resulting histogram
+// is not stored. Only the cost C(a+b) - C(a) is evaluated. We omit
+// the term C(b) which is constant over all the evaluations.
+#[allow(non_snake_case)]
+unsafe fn HistogramAddThresh(
+    a: *const VP8LHistogram,
+    b: *const VP8LHistogram,
+    cost_threshold: c_double,
+) -> c_double {
+    let mut cost = -(*a).bit_cost_;
+    GetCombinedHistogramEntropy(a, b, cost_threshold, &mut cost);
+    cost
+}
+
+// -----------------------------------------------------------------------------
+
+// The structure to keep track of cost range for the three dominant entropy
+// symbols.
+// TODO(skal): Evaluate if float can be used here instead of double for
+// representing the entropy costs.
+struct DominantCostRange {
+    literal_max_: c_double,
+    literal_min_: c_double,
+    red_max_: c_double,
+    red_min_: c_double,
+    blue_max_: c_double,
+    blue_min_: c_double,
+}
+
+#[allow(non_snake_case)]
+fn DominantCostRangeInit(c: &mut DominantCostRange) {
+    c.literal_max_ = 0.0;
+    c.literal_min_ = MAX_COST;
+    c.red_max_ = 0.0;
+    c.red_min_ = MAX_COST;
+    c.blue_max_ = 0.0;
+    c.blue_min_ = MAX_COST;
+}
+
+#[allow(non_snake_case)]
+fn UpdateDominantCostRange(h: &VP8LHistogram, c: &mut DominantCostRange) {
+    if c.literal_max_ < h.literal_cost_ {
+        c.literal_max_ = h.literal_cost_;
+    }
+    if c.literal_min_ > h.literal_cost_ {
+        c.literal_min_ = h.literal_cost_;
+    }
+    if c.red_max_ < h.red_cost_ {
+        c.red_max_ = h.red_cost_;
+    }
+    if c.red_min_ > h.red_cost_ {
+        c.red_min_ = h.red_cost_;
+    }
+    if c.blue_max_ < h.blue_cost_ {
+        c.blue_max_ = h.blue_cost_;
+    }
+    if c.blue_min_ > h.blue_cost_ {
+        c.blue_min_ = h.blue_cost_;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn UpdateHistogramCost(h: &mut VP8LHistogram) {
+    let mut alpha_sym = 0;
+    let mut red_sym = 0;
+    let mut blue_sym = 0;
+    let alpha_cost = PopulationCost(h.alpha_.as_ptr(), NUM_LITERAL_CODES, Some(&mut alpha_sym));
+    let distance_cost = PopulationCost(h.distance_.as_ptr(), NUM_DISTANCE_CODES, None)
+        + VP8LExtraCost.unwrap()(h.distance_.as_ptr(), NUM_DISTANCE_CODES);
+    let num_codes = VP8LHistogramNumCodes(h.palette_code_bits_);
+    h.literal_cost_ = PopulationCost(h.literal_, num_codes, None)
+        + VP8LExtraCost.unwrap()(h.literal_.add(NUM_LITERAL_CODES as usize), NUM_LENGTH_CODES);
+    h.red_cost_ = PopulationCost(h.red_.as_ptr(), NUM_LITERAL_CODES, Some(&mut red_sym));
+    h.blue_cost_ = PopulationCost(h.blue_.as_ptr(), NUM_LITERAL_CODES, Some(&mut blue_sym));
+    h.bit_cost_ = h.literal_cost_ + h.red_cost_ + h.blue_cost_ + alpha_cost + distance_cost;
+    if (alpha_sym | red_sym | blue_sym) == VP8L_NON_TRIVIAL_SYM {
+        h.trivial_symbol_ = VP8L_NON_TRIVIAL_SYM;
+    } else {
+        h.trivial_symbol_ = (alpha_sym << 24) | (red_sym << 16) | blue_sym;
+    }
+}
+
+#[allow(non_snake_case)]
+fn GetBinIdForEntropy(min: c_double, max: c_double, val: c_double) -> c_int {
+    let range = max - min;
+    if range > 0.0 {
+        let delta = val - min;
+        ((NUM_PARTITIONS as c_double - 1e-6) * delta / range) as c_int
+    } else {
+        0
+    }
+}
+
+#[allow(non_snake_case)]
+fn GetHistoBinIndex(h: &VP8LHistogram, c: &DominantCostRange, low_effort: c_int) -> c_int {
+    let mut bin_id = GetBinIdForEntropy(c.literal_min_, c.literal_max_, h.literal_cost_);
+    c_assert!(bin_id < NUM_PARTITIONS);
+    if low_effort == 0 {
+        bin_id = bin_id * NUM_PARTITIONS + GetBinIdForEntropy(c.red_min_, c.red_max_, h.red_cost_);
+        bin_id =
+            bin_id * NUM_PARTITIONS + GetBinIdForEntropy(c.blue_min_, c.blue_max_, h.blue_cost_);
+        c_assert!(bin_id < BIN_SIZE);
+    }
+    bin_id
+}
+
+// Construct the histograms from backward references.
+#[allow(non_snake_case)]
+unsafe fn HistogramBuild(
+    xsize: c_int,
+    histo_bits: c_int,
+    backward_refs: *const VP8LBackwardRefs,
+    image_histo: &mut VP8LHistogramSet,
+) {
+    let mut x: c_int = 0;
+    let mut y: c_int = 0;
+    let histo_xsize = VP8LSubSampleSize(xsize as u32, histo_bits as u32) as c_int;
+    let histograms = image_histo.histograms;
+    let mut c = VP8LRefsCursorInit(backward_refs);
+    c_assert!(histo_bits > 0);
+    while VP8LRefsCursorOk(&c) {
+        let v = c.cur_pos;
+        let ix = (y >> histo_bits) * histo_xsize + (x >> histo_bits);
+        VP8LHistogramAddSinglePixOrCopy(*histograms.offset(ix as isize), v, None, 0);
+        x += PixOrCopyLength(&*v) as c_int;
+        while x >= xsize {
+            x -= xsize;
+            y += 1;
+        }
+        VP8LRefsCursorNext(&mut c);
+    }
+}
+
+// Copies the histograms and computes its bit_cost.
+#[allow(non_snake_case)]
+unsafe fn HistogramCopyAndAnalyze(
+    orig_histo: &mut VP8LHistogramSet,
+    image_histo: &mut VP8LHistogramSet,
+) {
+    let histo_size = orig_histo.size as usize;
+    let orig_histograms = orig_histo.histograms;
+    let histograms = image_histo.histograms;
+    for i in 0..histo_size {
+        let histo = *orig_histograms.add(i);
+        UpdateHistogramCost(&mut *histo);
+        // Copy histograms from orig_histo[] to image_histo[].
+        HistogramCopy(histo, *histograms.add(i));
+    }
+}
+
+// Partition histograms to different entropy bins for three dominant (literal,
+// red and blue) symbol costs and compute the histogram aggregate bit_cost.
+#[allow(non_snake_case)]
+unsafe fn HistogramAnalyzeEntropyBin(
+    image_histo: &mut VP8LHistogramSet,
+    bin_map: *mut u16,
+    low_effort: c_int,
+) {
+    let histograms = image_histo.histograms;
+    let histo_size = image_histo.size as usize;
+    let mut cost_range: DominantCostRange = mem::zeroed();
+    DominantCostRangeInit(&mut cost_range);
+
+    // Analyze the dominant (literal, red and blue) entropy costs.
+    for i in 0..histo_size {
+        UpdateDominantCostRange(&**histograms.add(i), &mut cost_range);
+    }
+
+    // bin-hash histograms on three of the dominant (literal, red and blue)
+    // symbol costs and store the resulting bin_id for each histogram.
+    for i in 0..histo_size {
+        *bin_map.add(i) = GetHistoBinIndex(&**histograms.add(i), &cost_range, low_effort) as u16;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct BinInfo {
+    first: i16, // position of the histogram that accumulates all
+    // histograms with the same bin_id
+    num_combine_failures: u16, // number of combine failures per bin_id
+}
+
+// Compact image_histo[] by merging some histograms with same bin_id together if
+// it's advantageous.
+#[allow(non_snake_case)]
+unsafe fn HistogramCombineEntropyBin(
+    image_histo: &mut VP8LHistogramSet,
+    mut cur_combo: *mut VP8LHistogram,
+    bin_map: *const u16,
+    bin_map_size: c_int,
+    num_bins: c_int,
+    combine_cost_factor: c_double,
+    low_effort: c_int,
+) {
+    let histograms = image_histo.histograms;
+    // Work in-place: processed histograms are put at the beginning of
+    // image_histo[]. At the end, we just have to truncate the array.
+    let mut size: usize = 0;
+    let mut bin_info = [BinInfo {
+        first: -1,
+        num_combine_failures: 0,
+    }; BIN_SIZE as usize];
+
+    c_assert!(num_bins <= BIN_SIZE);
+    for info in bin_info.iter_mut().take(num_bins as usize) {
+        info.first = -1;
+        info.num_combine_failures = 0;
+    }
+
+    for idx in 0..bin_map_size as usize {
+        let bin_id = *bin_map.add(idx) as usize;
+        let first = bin_info[bin_id].first;
+        c_assert!(size <= idx);
+        if first == -1 {
+            // just move histogram #idx to its final position
+            *histograms.add(size) = *histograms.add(idx);
+            bin_info[bin_id].first = size as i16;
+            size += 1;
+        } else if low_effort != 0 {
+            let first = *histograms.add(first as usize);
+            HistogramAdd(*histograms.add(idx), first, first);
+        } else {
+            // try to merge #idx into #first (both share the same bin_id)
+            let bit_cost = (**histograms.add(idx)).bit_cost_;
+            let bit_cost_thresh = -bit_cost * combine_cost_factor;
+            let curr_cost_diff = HistogramAddEval(
+                *histograms.add(first as usize),
+                *histograms.add(idx),
+                cur_combo,
+                bit_cost_thresh,
+            );
+            if curr_cost_diff < bit_cost_thresh {
+                // Try to merge two histograms only if the combo is a trivial one or
+                // the two candidate histograms are already non-trivial.
+                // For some images, 'try_combine' turns out to be false for a lot of
+                // histogram pairs. In that case, we fallback to combining
+                // histograms as usual to avoid increasing the header size.
+                let try_combine = (*cur_combo).trivial_symbol_ != VP8L_NON_TRIVIAL_SYM
+                    || ((**histograms.add(idx)).trivial_symbol_ == VP8L_NON_TRIVIAL_SYM
+                        && (**histograms.add(first as usize)).trivial_symbol_
+                            == VP8L_NON_TRIVIAL_SYM);
+                #[allow(non_upper_case_globals)]
+                const max_combine_failures: u16 = 32;
+                if try_combine || bin_info[bin_id].num_combine_failures >= max_combine_failures {
+                    // move the (better) merged histogram to its final slot
+                    mem::swap(&mut cur_combo, &mut *histograms.add(first as usize));
+                } else {
+                    *histograms.add(size) = *histograms.add(idx);
+                    size += 1;
+                    bin_info[bin_id].num_combine_failures += 1;
+                }
+            } else {
+                *histograms.add(size) = *histograms.add(idx);
+                size += 1;
+            }
+        }
+    }
+    image_histo.size = size as c_int;
+    if low_effort != 0 {
+        // for low_effort case, update the final cost when everything is merged
+        for idx in 0..size {
+            UpdateHistogramCost(&mut **histograms.add(idx));
+        }
+    }
+}
+
+// Implement a Lehmer random number generator with a multiplicative constant of
+// 48271 and a modulo constant of 2^31 - 1.
+#[allow(non_snake_case)]
+fn MyRand(seed: &mut u32) -> u32 {
+    *seed = ((*seed as u64 * 48271) % 2147483647) as u32;
+    c_assert!(*seed > 0);
+    *seed
+}
+
+// -----------------------------------------------------------------------------
+// Histogram pairs priority queue
+
+// Pair of histograms. Negative idx1 value means that pair is out-of-date.
+#[derive(Clone, Copy)]
+struct HistogramPair {
+    idx1: c_int,
+    idx2: c_int,
+    cost_diff: c_double,
+    cost_combo: c_double,
+}
+
+struct HistoQueue {
+    queue: *mut HistogramPair,
+    size: c_int,
+    max_size: c_int,
+}
+
+#[allow(non_snake_case)]
+unsafe fn HistoQueueInit(histo_queue: &mut HistoQueue, max_index: c_int) -> bool {
+    histo_queue.size = 0;
+    // max_index^2 for the queue size is safe. If you look at
+    // HistogramCombineGreedy, and imagine that UpdateQueueFront always pushes
+    // data to the queue, you insert at most:
+    // - max_index*(max_index-1)/2 (the first two for loops)
+    // - max_index - 1 in the last for loop at the first iteration of the while
+    //   loop, max_index - 2 at the second iteration ... therefore
+    //   max_index*(max_index-1)/2 overall too
+    histo_queue.max_size = max_index * max_index;
+    // We allocate max_size + 1 because the last element at index "size" is
+    // used as temporary data (and it could be up to max_size).
+    histo_queue.queue = WebPSafeMalloc(
+        histo_queue.max_size as u64 + 1,
+        mem::size_of::<HistogramPair>(),
+    ) as *mut HistogramPair;
+    !histo_queue.queue.is_null()
+}
+
+#[allow(non_snake_case)]
+unsafe fn HistoQueueClear(histo_queue: &mut HistoQueue) {
+    WebPSafeFree(histo_queue.queue as *mut c_void);
+    histo_queue.size = 0;
+    histo_queue.max_size = 0;
+}
+
+// Pop a specific pair in the queue by replacing it with the last one
+// and shrinking the queue.
+#[allow(non_snake_case)]
+unsafe fn HistoQueuePopPair(histo_queue: &mut HistoQueue, pair: *mut HistogramPair) {
+    c_assert!(
+        pair >= histo_queue.queue && pair < histo_queue.queue.offset(histo_queue.size as isize)
+    );
+    c_assert!(histo_queue.size > 0);
+    *pair = *histo_queue.queue.offset(histo_queue.size as isize - 1);
+    histo_queue.size -= 1;
+}
+
+// Check whether a pair in the queue should be updated as head or not.
+#[allow(non_snake_case)]
+unsafe fn HistoQueueUpdateHead(histo_queue: &mut HistoQueue, pair: *mut HistogramPair) {
+    c_assert!((*pair).cost_diff < 0.0);
+    c_assert!(
+        pair >= histo_queue.queue && pair < histo_queue.queue.offset(histo_queue.size as isize)
+    );
+    c_assert!(histo_queue.size > 0);
+    if (*pair).cost_diff < (*histo_queue.queue).cost_diff {
+        // Replace the best pair.
+        ptr::swap(histo_queue.queue, pair);
+    }
+}
+
+// Create a pair from indices "idx1" and "idx2" provided its cost
+// is inferior to "threshold", a negative entropy.
+// It returns the cost of the pair, or 0. if it superior to threshold.
+#[allow(non_snake_case)]
+unsafe fn HistoQueuePush(
+    histo_queue: &mut HistoQueue,
+    histograms: *mut *mut VP8LHistogram,
+    mut idx1: c_int,
+    mut idx2: c_int,
+    threshold: c_double,
+) -> c_double {
+    c_assert!(threshold <= 0.0);
+    if idx1 > idx2 {
+        mem::swap(&mut idx1, &mut idx2);
+    }
+    let h1 = *histograms.offset(idx1 as isize);
+    let h2 = *histograms.offset(idx2 as isize);
+    let sum_cost = (*h1).bit_cost_ + (*h2).bit_cost_;
+    let mut pair = HistogramPair {
+        idx1,
+        idx2,
+        cost_diff: 0.0,
+        cost_combo: 0.0,
+    };
+    GetCombinedHistogramEntropy(h1, h2, sum_cost + threshold, &mut pair.cost_combo);
+    pair.cost_diff = pair.cost_combo - sum_cost;
+
+    // Do not even consider the pair if it does not improve the entropy.
+    if pair.cost_diff >= threshold {
+        return 0.0;
+    }
+
+    // We cannot add more elements than the capacity.
+    c_assert!(histo_queue.size < histo_queue.max_size);
+    *histo_queue.queue.offset(histo_queue.size as isize) = pair;
+    histo_queue.size += 1;
+    let last = histo_queue.queue.offset(histo_queue.size as isize - 1);
+    HistoQueueUpdateHead(histo_queue, last);
+
+    pair.cost_diff
+}
+
+// -----------------------------------------------------------------------------
+
+// Combines histograms by continuously choosing the one with the highest cost
+// reduction.
+#[allow(non_snake_case)]
+unsafe fn HistogramCombineGreedy(image_histo: &mut VP8LHistogramSet) -> bool {
+    let mut ok = false;
+    let mut image_histo_size = image_histo.size;
+    let histograms = image_histo.histograms;
+    // Indexes of remaining histograms.
+    let clusters = WebPSafeMalloc(image_histo_size as u64, mem::size_of::<c_int>()) as *mut c_int;
+    // Priority queue of histogram pairs.
+    let mut histo_queue = HistoQueue {
+        queue: ptr::null_mut(),
+        size: 0,
+        max_size: 0,
+    };
+
+    'end: {
+        if !HistoQueueInit(&mut histo_queue, image_histo_size) || clusters.is_null() {
+            break 'end;
+        }
+
+        for i in 0..image_histo_size {
+            // Initialize clusters indexes.
+            *clusters.offset(i as isize) = i;
+            for j in i + 1..image_histo_size {
+                // Initialize positions array.
+                HistoQueuePush(&mut histo_queue, histograms, i, j, 0.0);
+            }
+        }
+
+        while image_histo_size > 1 && histo_queue.size > 0 {
+            let idx1 = (*histo_queue.queue).idx1;
+            let idx2 = (*histo_queue.queue).idx2;
+            let h1 = *histograms.offset(idx1 as isize);
+            HistogramAdd(*histograms.offset(idx2 as isize), h1, h1);
+            (*h1).bit_cost_ = (*histo_queue.queue).cost_combo;
+            // Remove merged histogram.
+            let mut i = 0;
+            while i + 1 < image_histo_size {
+                if *clusters.offset(i as isize) >= idx2 {
+                    *clusters.offset(i as isize) = *clusters.offset(i as isize + 1);
+                }
+                i += 1;
+            }
+            image_histo_size -= 1;
+
+            // Remove pairs intersecting the just combined best pair.
+            let mut i = 0;
+            while i < histo_queue.size {
+                let p = histo_queue.queue.offset(i as isize);
+                if (*p).idx1 == idx1 || (*p).idx2 == idx1 || (*p).idx1 == idx2 || (*p).idx2 == idx2
+                {
+                    HistoQueuePopPair(&mut histo_queue, p);
+                } else {
+                    HistoQueueUpdateHead(&mut histo_queue, p);
+                    i += 1;
+                }
+            }
+
+            // Push new pairs formed with combined histogram to the queue.
+            for i in 0..image_histo_size {
+                if *clusters.offset(i as isize) != idx1 {
+                    HistoQueuePush(
+                        &mut histo_queue,
+                        histograms,
+                        idx1,
+                        *clusters.offset(i as isize),
+                        0.0,
+                    );
+                }
+            }
+        }
+        // Move remaining histograms to the beginning of the array.
+        for i in 0..image_histo_size as isize {
+            let cluster = *clusters.offset(i) as isize;
+            if i != cluster {
+                // swap the two histograms
+                ptr::swap(histograms.offset(i), histograms.offset(cluster));
+            }
+        }
+
+        image_histo.size = image_histo_size;
+        ok = true;
+    }
+
+    WebPSafeFree(clusters as *mut c_void);
+    HistoQueueClear(&mut histo_queue);
+    ok
+}
+
+// Perform histogram aggregation using a stochastic approach.
+// 'do_greedy' is set to 1 if a greedy approach needs to be performed
+// afterwards, 0 otherwise.
+#[allow(non_snake_case)]
+unsafe fn HistogramCombineStochastic(
+    image_histo: &mut VP8LHistogramSet,
+    mut min_cluster_size: c_int,
+    do_greedy: &mut bool,
+) -> bool {
+    let mut seed: u32 = 1;
+    let mut tries_with_no_success = 0;
+    let mut image_histo_size = image_histo.size;
+    let outer_iters = image_histo_size;
+    let num_tries_no_success = outer_iters / 2;
+    let histograms = image_histo.histograms;
+    // Priority queue of histogram pairs. Its size of "kCostHeapSizeSqrt"^2
+    // impacts the quality of the compression and the speed: the smaller the
+    // faster but the worse for the compression.
+    let mut histo_queue = HistoQueue {
+        queue: ptr::null_mut(),
+        size: 0,
+        max_size: 0,
+    };
+    #[allow(non_upper_case_globals)]
+    const kHistoQueueSizeSqrt: c_int = 3;
+
+    if !HistoQueueInit(&mut histo_queue, kHistoQueueSizeSqrt) {
+        HistoQueueClear(&mut histo_queue);
+        return false;
+    }
+    // Collapse similar histograms in 'image_histo'.
+    min_cluster_size += 1;
+    for _ in 0..outer_iters {
+        if image_histo_size < min_cluster_size {
+            break;
+        }
+        tries_with_no_success += 1;
+        if tries_with_no_success >= num_tries_no_success {
+            break;
+        }
+        let mut best_cost = if histo_queue.size == 0 {
+            0.0
+        } else {
+            (*histo_queue.queue).cost_diff
+        };
+        let rand_range = ((image_histo_size - 1) * image_histo_size) as u32;
+        // image_histo_size / 2 was chosen empirically. Less means faster but worse
+        // compression.
+        let num_tries = image_histo_size / 2;
+
+        for _ in 0..num_tries {
+            // Choose two different histograms at random and try to combine them.
+            let tmp = MyRand(&mut seed) % rand_range;
+            let idx1 = tmp / (image_histo_size - 1) as u32;
+            let mut idx2 = tmp % (image_histo_size - 1) as u32;
+            if idx2 >= idx1 {
+                idx2 += 1;
+            }
+
+            // Calculate cost reduction on combination.
+            let curr_cost = HistoQueuePush(
+                &mut histo_queue,
+                histograms,
+                idx1 as c_int,
+                idx2 as c_int,
+                best_cost,
+            );
+            if curr_cost < 0.0 {
+                // found a better pair?
+                best_cost = curr_cost;
+                // Empty the queue if we reached full capacity.
+                if histo_queue.size == histo_queue.max_size {
+                    break;
+                }
+            }
+        }
+        if histo_queue.size == 0 {
+            continue;
+        }
+
+        // Merge the two best histograms.
+        let best_idx1 = (*histo_queue.queue).idx1;
+        let best_idx2 = (*histo_queue.queue).idx2;
+        c_assert!(best_idx1 < best_idx2);
+        let h1 = *histograms.offset(best_idx1 as isize);
+        HistogramAddEval(h1, *histograms.offset(best_idx2 as isize), h1, 0.0);
+        // Swap the best_idx2 histogram with the last one (which is now unused).
+        image_histo_size -= 1;
+        if best_idx2 != image_histo_size {
+            ptr::swap(
+                histograms.offset(image_histo_size as isize),
+                histograms.offset(best_idx2 as isize),
+            );
+        }
+        *histograms.offset(image_histo_size as isize) = ptr::null_mut();
+        // Parse the queue and update each pair that deals with best_idx1,
+        // best_idx2 or image_histo_size.
+        let mut j = 0;
+        while j < histo_queue.size {
+            let p = histo_queue.queue.offset(j as isize);
+            let is_idx1_best = (*p).idx1 == best_idx1 || (*p).idx1 == best_idx2;
+            let is_idx2_best = (*p).idx2 == best_idx1 || (*p).idx2 == best_idx2;
+            let mut do_eval = false;
+            // The front pair could have been duplicated by a random pick so
+            // check for it all the time nevertheless.
+            if is_idx1_best && is_idx2_best {
+                HistoQueuePopPair(&mut histo_queue, p);
+                continue;
+            }
+            // Any pair containing one of the two best indices should only refer to
+            // best_idx1. Its cost should also be updated.
+            if is_idx1_best {
+                (*p).idx1 = best_idx1;
+                do_eval = true;
+            } else if is_idx2_best {
+                (*p).idx2 = best_idx1;
+                do_eval = true;
+            }
+            if (*p).idx2 == image_histo_size {
+                // No need to re-evaluate here as it does not involve a pair
+                // containing best_idx1 or best_idx2.
+                (*p).idx2 = best_idx2;
+            }
+            c_assert!((*p).idx2 < image_histo_size);
+            // Make sure the index order is respected.
+            if (*p).idx1 > (*p).idx2 {
+                mem::swap(&mut (*p).idx1, &mut (*p).idx2);
+            }
+            if do_eval {
+                // Re-evaluate the cost of an updated pair.
+                GetCombinedHistogramEntropy(
+                    *histograms.offset((*p).idx1 as isize),
+                    *histograms.offset((*p).idx2 as isize),
+                    0.0,
+                    &mut (*p).cost_diff,
+                );
+                if (*p).cost_diff >= 0.0 {
+                    HistoQueuePopPair(&mut histo_queue, p);
+                    continue;
+                }
+            }
+            HistoQueueUpdateHead(&mut histo_queue, p);
+            j += 1;
+        }
+
+        tries_with_no_success = 0;
+    }
+    image_histo.size = image_histo_size;
+    *do_greedy = image_histo.size <= min_cluster_size;
+
+    HistoQueueClear(&mut histo_queue);
+    true
+}
+
+// -----------------------------------------------------------------------------
+// Histogram refinement
+
+// Find the best 'out' histogram for each of the 'in' histograms.
+// Note: we assume that out[]->bit_cost_ is already up-to-date.
+#[allow(non_snake_case)]
+unsafe fn HistogramRemap(in_: &VP8LHistogramSet, out: &VP8LHistogramSet, symbols: *mut u16) {
+    let in_histo = in_.histograms;
+    let out_histo = out.histograms;
+    let in_size = in_.size as usize;
+    let out_size = out.size as usize;
+    if out_size > 1 {
+        for i in 0..in_size {
+            let mut best_out = 0;
+            let mut best_bits = MAX_COST;
+            for k in 0..out_size {
+                let cur_bits = HistogramAddThresh(*out_histo.add(k), *in_histo.add(i), best_bits);
+                if k == 0 || cur_bits < best_bits {
+                    best_bits = cur_bits;
+                    best_out = k;
+                }
+            }
+            *symbols.add(i) = best_out as u16;
+        }
+    } else {
+        c_assert!(out_size == 1);
+        for i in 0..in_size {
+            *symbols.add(i) = 0;
+        }
+    }
+
+    // Recompute each out based on raw and symbols.
+    for i in 0..out_size {
+        HistogramClear(*out_histo.add(i));
+    }
+
+    for i in 0..in_size {
+        let idx = *symbols.add(i) as usize;
+        HistogramAdd(*in_histo.add(i), *out_histo.add(idx), *out_histo.add(idx));
+    }
+}
+
+#[allow(non_snake_case)]
+fn GetCombineCostFactor(histo_size: c_int, quality: c_int) -> c_double {
+    let mut combine_cost_factor = 0.16;
+    if quality < 90 {
+        if histo_size > 256 {
+            combine_cost_factor /= 2.0;
+        }
+        if histo_size > 512 {
+            combine_cost_factor /= 2.0;
+        }
+        if histo_size > 1024 {
+            combine_cost_factor /= 2.0;
+        }
+        if quality <= 50 {
+            combine_cost_factor /= 2.0;
+        }
+    }
+    combine_cost_factor
+}
+
+// Builds the histogram image.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LGetHistoImageSymbols(
+    xsize: c_int,
+    ysize: c_int,
+    refs: *const VP8LBackwardRefs,
+    quality: c_int,
+    low_effort: c_int,
+    histo_bits: c_int,
+    cache_bits: c_int,
+    image_histo: *mut VP8LHistogramSet,
+    tmp_histo: *mut VP8LHistogram,
+    histogram_symbols: *mut u16,
+) -> c_int {
+    let image_histo = &mut *image_histo;
+    let mut ok = 0;
+    let histo_xsize = if histo_bits != 0 {
+        VP8LSubSampleSize(xsize as u32, histo_bits as u32) as c_int
+    } else {
+        1
+    };
+    let histo_ysize = if histo_bits != 0 {
+        VP8LSubSampleSize(ysize as u32, histo_bits as u32) as c_int
+    } else {
+        1
+    };
+    let image_histo_raw_size = histo_xsize * histo_ysize;
+    let orig_histo = VP8LAllocateHistogramSet(image_histo_raw_size, cache_bits);
+    // Don't attempt linear bin-partition heuristic for
+    // histograms of small sizes (as bin_map will be very sparse) and
+    // maximum quality q==100 (to preserve the compression gains at that level).
+    let entropy_combine_num_bins = if low_effort != 0 {
+        NUM_PARTITIONS
+    } else {
+        BIN_SIZE
+    };
+
+    'error: {
+        if orig_histo.is_null() {
+            break 'error;
+        }
+        let orig_histo = &mut *orig_histo;
+        let entropy_combine = orig_histo.size > entropy_combine_num_bins * 2 && quality < 100;
+
+        // Construct the histograms from backward references.
+        HistogramBuild(xsize, histo_bits, refs, orig_histo);
+        // Copies the histograms and computes its bit_cost.
+        HistogramCopyAndAnalyze(orig_histo, image_histo);
+
+        if entropy_combine {
+            let bin_map_size = orig_histo.size;
+            // Reuse histogram_symbols storage. By definition, it's guaranteed to be ok.
+            let bin_map = histogram_symbols;
+            let combine_cost_factor = GetCombineCostFactor(image_histo_raw_size, quality);
+
+            HistogramAnalyzeEntropyBin(orig_histo, bin_map, low_effort);
+            // Collapse histograms with similar entropy.
+            HistogramCombineEntropyBin(
+                image_histo,
+                tmp_histo,
+                bin_map,
+                bin_map_size,
+                entropy_combine_num_bins,
+                combine_cost_factor,
+                low_effort,
+            );
+        }
+
+        // Don't combine the histograms using stochastic and greedy heuristics for
+        // low-effort compression mode.
+        if low_effort == 0 || !entropy_combine {
+            let x = quality as c_float / 100.0;
+            // cubic ramp between 1 and MAX_HISTO_GREEDY:
+            let threshold_size = (1.0 + (x * x * x) * (MAX_HISTO_GREEDY - 1) as c_float) as c_int;
+            let mut do_greedy = false;
+            if !HistogramCombineStochastic(image_histo, threshold_size, &mut do_greedy) {
+                break 'error;
+            }
+            if do_greedy && !HistogramCombineGreedy(image_histo) {
+                break 'error;
+            }
+        }
+
+        // TODO(vrabaud): Optimize HistogramRemap for low-effort compression mode.
+        // Find the optimal map from original histograms to the final ones.
+        HistogramRemap(orig_histo, image_histo, histogram_symbols);
+
+        ok = 1;
+    }
+
+    VP8LFreeHistogramSet(orig_histo);
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use dsp::lossless_enc::VP8LEncDspInit;
+    use enc::backward_references_enc::{
+        kLZ77RLE, kLZ77Standard, VP8LBackwardRefsClear, VP8LBackwardRefsInit,
+        VP8LDistanceToPlaneCode, VP8LGetBackwardReferences, VP8LHashChain, VP8LHashChainClear,
+        VP8LHashChainFill, VP8LHashChainInit, MAX_COLOR_CACHE_BITS, MAX_REFS_BLOCK_PER_IMAGE,
+    };
+    use test_util::{argb_images, check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LFreeHistogram(histo: *mut VP8LHistogram);
+        fn WebPRef_VP8LFreeHistogramSet(histo: *mut VP8LHistogramSet);
+        fn WebPRef_VP8LHistogramCreate(
+            p: *mut VP8LHistogram,
+            refs: *const VP8LBackwardRefs,
+            palette_code_bits: c_int,
+        );
+        fn WebPRef_VP8LAllocateHistogram(cache_bits: c_int) -> *mut VP8LHistogram;
+        fn WebPRef_VP8LAllocateHistogramSet(
+            size: c_int,
+            cache_bits: c_int,
+        ) -> *mut VP8LHistogramSet;
+        fn WebPRef_VP8LHistogramAddSinglePixOrCopy(
+            histo: *mut VP8LHistogram,
+            v: *const PixOrCopy,
+            distance_modifier: Option<extern "C" fn(c_int, c_int) -> c_int>,
+            distance_modifier_arg0: c_int,
+        );
+        fn WebPRef_VP8LBitsEntropy(array: *const u32, n: c_int) -> c_double;
+        fn WebPRef_VP8LHistogramEstimateBits(p: *const VP8LHistogram) -> c_double;
+        fn WebPRef_VP8LGetHistoImageSymbols(
+            xsize: c_int,
+            ysize: c_int,
+            refs: *const VP8LBackwardRefs,
+            quality: c_int,
+            low_effort: c_int,
+            histo_bits: c_int,
+            cache_bits: c_int,
+            image_histo: *mut VP8LHistogramSet,
+            tmp_histo: *mut VP8LHistogram,
+            histogram_symbols: *mut u16,
+        ) -> c_int;
+    }
+
+    struct Api {
+        allocate_histogram: unsafe extern "C" fn(c_int) -> *mut VP8LHistogram,
+        free_histogram: unsafe extern "C" fn(*mut VP8LHistogram),
+        allocate_histogram_set: unsafe extern "C" fn(c_int, c_int) -> *mut VP8LHistogramSet,
+        free_histogram_set: unsafe extern "C" fn(*mut VP8LHistogramSet),
+        histogram_create: unsafe extern "C" fn(*mut VP8LHistogram, *const VP8LBackwardRefs, c_int),
+        add_single_pix_or_copy: unsafe extern "C" fn(
+            *mut VP8LHistogram,
+            *const PixOrCopy,
+            Option<extern "C" fn(c_int, c_int) -> c_int>,
+            c_int,
+        ),
+        estimate_bits: unsafe extern "C" fn(*const VP8LHistogram) -> c_double,
+        get_histo_image_symbols: unsafe extern "C" fn(
+            c_int,
+            c_int,
+            *const VP8LBackwardRefs,
+            c_int,
+            c_int,
+            c_int,
+            c_int,
+            *mut VP8LHistogramSet,
+            *mut VP8LHistogram,
+            *mut u16,
+        ) -> c_int,
+    }
+
+    const PORT: Api = Api {
+        allocate_histogram: VP8LAllocateHistogram,
+        free_histogram: VP8LFreeHistogram,
+        allocate_histogram_set: VP8LAllocateHistogramSet,
+        free_histogram_set: VP8LFreeHistogramSet,
+        histogram_create: VP8LHistogramCreate,
+        add_single_pix_or_copy: VP8LHistogramAddSinglePixOrCopy,
+        estimate_bits: VP8LHistogramEstimateBits,
+        get_histo_image_symbols: VP8LGetHistoImageSymbols,
+    };
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = Api {
+        allocate_histogram: WebPRef_VP8LAllocateHistogram,
+        free_histogram: WebPRef_VP8LFreeHistogram,
+        allocate_histogram_set: WebPRef_VP8LAllocateHistogramSet,
+        free_histogram_set: WebPRef_VP8LFreeHistogramSet,
+        histogram_create: WebPRef_VP8LHistogramCreate,
+        add_single_pix_or_copy: WebPRef_VP8LHistogramAddSinglePixOrCopy,
+        estimate_bits: WebPRef_VP8LHistogramEstimateBits,
+        get_histo_image_symbols: WebPRef_VP8LGetHistoImageSymbols,
+    };
+
+    fn images() -> Vec<(c_int, c_int, Vec<u32>)> {
+        argb_images(
+            0x5678,
+            0x1f,
+            &[
+                (1, 1, 0, false),
+                (64, 40, 0, false),
+                (97, 61, 0, true),
+                (33, 17, 1, false),
+                (40, 21, 2, true),
+                (50, 31, 16, true),
+                (80, 52, 600, true),
+                (130, 70, 0, true),
+            ],
+        )
+    }
+
+    // Everything in the histogram but the pointer to its literals, with the
+    // costs as bits.
+    unsafe fn contents(histo: *const VP8LHistogram) -> Vec<u64> {
+        let histo = &*histo;
+        let num_codes = VP8LHistogramNumCodes(histo.palette_code_bits_) as usize;
+        slice::from_raw_parts(histo.literal_, num_codes)
+            .iter()
+            .chain(&histo.red_[..])
+            .chain(&histo.blue_[..])
+            .chain(&histo.alpha_[..])
+            .chain(&histo.distance_[..])
+            .map(|&count| count as u64)
+            .chain(vec![
+                histo.palette_code_bits_ as u64,
+                histo.trivial_symbol_ as u64,
+                histo.bit_cost_.to_bits(),
+                histo.literal_cost_.to_bits(),
+                histo.red_cost_.to_bits(),
+                histo.blue_cost_.to_bits(),
+            ])
+            .collect()
+    }
+
+    // Runs 'f' on the backward references computed by the port, as the
+    // histograms are built from them, with the chosen cache bits.
+    fn with_refs<F>(
+        argb: &[u32],
+        width: c_int,
+        height: c_int,
+        quality: c_int,
+        low_effort: c_int,
+        cache_bits: c_int,
+        mut f: F,
+    ) where
+        F: FnMut(*const VP8LBackwardRefs, c_int),
+    {
+        unsafe {
+            VP8LEncDspInit();
+            let mut hash_chain: VP8LHashChain = mem::zeroed();
+            assert_ne!(VP8LHashChainInit(&mut hash_chain, width * height), 0);
+            assert_ne!(
+                VP8LHashChainFill(
+                    &mut hash_chain,
+                    quality,
+                    argb.as_ptr(),
+                    width,
+                    height,
+                    low_effort
+                ),
+                0
+            );
+            let mut refs: [VP8LBackwardRefs; 2] = mem::zeroed();
+            let block_size = (width * height - 1) / MAX_REFS_BLOCK_PER_IMAGE + 1;
+            VP8LBackwardRefsInit(&mut refs[0], block_size);
+            VP8LBackwardRefsInit(&mut refs[1], block_size);
+            let mut cache_bits = cache_bits;
+            let (refs_0, refs_1) = refs.split_at_mut(1);
+            let best = VP8LGetBackwardReferences(
+                width,
+                height,
+                argb.as_ptr(),
+                quality,
+                low_effort,
+                kLZ77Standard | kLZ77RLE,
+                &mut cache_bits,
+                &hash_chain,
+                &mut refs_0[0],
+                &mut refs_1[0],
+            );
+            assert!(!best.is_null());
+            f(best, cache_bits);
+            VP8LBackwardRefsClear(&mut refs[0]);
+            VP8LBackwardRefsClear(&mut refs[1]);
+            VP8LHashChainClear(&mut hash_chain);
+        }
+    }
+
+    // Sparse arrays for the special cases of the refinement, and dense ones.
+    #[test]
+    fn test_bits_entropy_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(6);
+        unsafe {
+            VP8LEncDspInit();
+        }
+        for &n in &[0, 1, 2, 5, 16, 40, 256, 280, 1064] {
+            for kind in 0..8 {
+                let mut array = vec![0u32; n];
+                match kind {
+                    0 => {}
+                    1..=5 if n > 0 => {
+                        for _ in 0..kind {
+                            array[rng.next() as usize % n] += rng.next() % 1000 + 1;
+                        }
+                    }
+                    6 => {
+                        for v in &mut array {
+                            *v = rng.next() % 8;
+                        }
+                    }
+                    _ => {
+                        for v in &mut array {
+                            *v = rng.next() % 100000;
+                        }
+                    }
+                }
+                let bits = unsafe { VP8LBitsEntropy(array.as_ptr(), n as c_int) };
+                digest.update(&bits.to_bits().to_le_bytes());
+                #[cfg(feature = "c-reference")]
+                assert_eq!(
+                    bits.to_bits(),
+                    unsafe { WebPRef_VP8LBitsEntropy(array.as_ptr(), n as c_int) }.to_bits(),
+                    "{:?}",
+                    array
+                );
+            }
+        }
+        check_parity("enc::histogram_enc::test_bits_entropy_parity", digest);
+    }
+
+    // A histogram of the references with VP8LHistogramCreate(), and one with
+    // VP8LHistogramAddSinglePixOrCopy() mapping the distances to plane codes,
+    // with their estimated bits.
+    #[test]
+    fn test_histogram_create_parity() {
+        let mut digest = Digest::new();
+        for (width, height, argb) in images() {
+            for &cache_bits in &[0, MAX_COLOR_CACHE_BITS] {
+                with_refs(
+                    &argb,
+                    width,
+                    height,
+                    75,
+                    0,
+                    cache_bits,
+                    |refs, cache_bits| {
+                        let create = |api: &Api| unsafe {
+                            let histos = [
+                                (api.allocate_histogram)(cache_bits),
+                                (api.allocate_histogram)(cache_bits),
+                            ];
+                            (api.histogram_create)(histos[0], refs, cache_bits);
+                            let mut c = VP8LRefsCursorInit(refs);
+                            while VP8LRefsCursorOk(&c) {
+                                (api.add_single_pix_or_copy)(
+                                    histos[1],
+                                    c.cur_pos,
+                                    Some(VP8LDistanceToPlaneCode),
+                                    width,
+                                );
+                                VP8LRefsCursorNext(&mut c);
+                            }
+                            let mut results = Vec::new();
+                            for &histo in &histos {
+                                let mut contents = contents(histo);
+                                contents.push((api.estimate_bits)(histo).to_bits());
+                                results.push(contents);
+                                (api.free_histogram)(histo);
+                            }
+                            results
+                        };
+                        let results = create(&PORT);
+                        for contents in &results {
+                            digest.update_u64(contents);
+                        }
+                        #[cfg(feature = "c-reference")]
+                        assert!(
+                            results == create(&REFERENCE),
+                            "{}x{} image, cache bits {}",
+                            width,
+                            height,
+                            cache_bits
+                        );
+                    },
+                );
+            }
+        }
+        check_parity("enc::histogram_enc::test_histogram_create_parity", digest);
+    }
+
+    // The histogram image, with the entropy binning, the stochastic and the
+    // greedy combining, and the remapping.
+    #[test]
+    fn test_get_histo_image_symbols_parity() {
+        let mut digest = Digest::new();
+        let params = [
+            (25, 1, 5, 0),
+            (50, 0, 2, 0),
+            (50, 0, 3, MAX_COLOR_CACHE_BITS),
+            (75, 0, 2, MAX_COLOR_CACHE_BITS),
+            (90, 1, 3, 4),
+            (100, 0, 2, 0),
+        ];
+        for (width, height, argb) in images() {
+            for &(quality, low_effort, histo_bits, cache_bits) in &params {
+                with_refs(
+                    &argb,
+                    width,
+                    height,
+                    quality,
+                    low_effort,
+                    cache_bits,
+                    |refs, cache_bits| {
+                        let histo_size = (VP8LSubSampleSize(width as u32, histo_bits as u32)
+                            * VP8LSubSampleSize(height as u32, histo_bits as u32))
+                            as usize;
+                        let histo_image = |api: &Api| unsafe {
+                            let image_histo =
+                                (api.allocate_histogram_set)(histo_size as c_int, cache_bits);
+                            let tmp_histo = (api.allocate_histogram)(cache_bits);
+                            let mut symbols = vec![0u16; histo_size];
+                            assert_ne!(
+                                (api.get_histo_image_symbols)(
+                                    width,
+                                    height,
+                                    refs,
+                                    quality,
+                                    low_effort,
+                                    histo_bits,
+                                    cache_bits,
+                                    image_histo,
+                                    tmp_histo,
+                                    symbols.as_mut_ptr(),
+                                ),
+                                0
+                            );
+                            let histograms = slice::from_raw_parts(
+                                (*image_histo).histograms,
+                                (*image_histo).size as usize,
+                            )
+                            .iter()
+                            .map(|&histo| contents(histo))
+                            .collect::<Vec<_>>();
+                            (api.free_histogram)(tmp_histo);
+                            (api.free_histogram_set)(image_histo);
+                            (symbols, histograms)
+                        };
+                        let (symbols, histograms) = histo_image(&PORT);
+                        digest.update_u16(&symbols);
+                        for contents in &histograms {
+                            digest.update_u64(contents);
+                        }
+                        #[cfg(feature = "c-reference")]
+                        assert!(
+                            (symbols, histograms) == histo_image(&REFERENCE),
+                            "{}x{} image, quality {}, low effort {}, histo bits {}",
+                            width,
+                            height,
+                            quality,
+                            low_effort,
+                            histo_bits
+                        );
+                    },
+                );
+            }
+        }
+        check_parity(
+            "enc::histogram_enc::test_get_histo_image_symbols_parity",
+            digest,
+        );
+    }
+}
@@ -0,0 +1,579 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// VP8Iterator: block iterator
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::dsp::BPS;
+use enc::quant_enc::VP8Scan;
+use enc::vp8i_enc::*;
+use enc::webp_enc::WebPReportProgress;
+use utils::utils::WEBP_ALIGN;
+
+//------------------------------------------------------------------------------
+// VP8Iterator
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn InitLeft(it: *mut VP8EncIterator) {
+    let v = if (*it).y_ > 0 { 129 } else { 127 };
+    *(*it).v_left_.offset(-1) = v;
+    *(*it).u_left_.offset(-1) = v;
+    *(*it).y_left_.offset(-1) = v;
+    ptr::write_bytes((*it).y_left_, 129, 16);
+    ptr::write_bytes((*it).u_left_, 129, 8);
+    ptr::write_bytes((*it).v_left_, 129, 8);
+    (*it).left_nz_[8] = 0;
+    if !(*it).top_derr_.is_null() {
+        (*it).left_derr_ = [[0; 2]; 2];
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn InitTop(it: *mut VP8EncIterator) {
+    let enc = (*it).enc_;
+    let top_size = (*enc).mb_w_ as usize * 16;
+    ptr::write_bytes((*enc).y_top_, 127, 2 * top_size);
+    ptr::write_bytes((*enc).nz_, 0, (*enc).mb_w_ as usize);
+    if !(*enc).top_derr_.is_null() {
+        ptr::write_bytes((*enc).top_derr_, 0, (*enc).mb_w_ as usize);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorSetRow(it: *mut VP8EncIterator, y: c_int) {
+    let enc = (*it).enc_;
+    (*it).x_ = 0;
+    (*it).y_ = y;
+    (*it).bw_ = &mut (*enc).parts_[(y & ((*enc).num_parts_ - 1)) as usize];
+    (*it).preds_ = (*enc).preds_.offset((y * 4 * (*enc).preds_w_) as isize);
+    (*it).nz_ = (*enc).nz_;
+    (*it).mb_ = (*enc).mb_info_.offset((y * (*enc).mb_w_) as isize);
+    (*it).y_top_ = (*enc).y_top_;
+    (*it).uv_top_ = (*enc).uv_top_;
+    InitLeft(it);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorReset(it: *mut VP8EncIterator) {
+    let enc = (*it).enc_;
+    VP8IteratorSetRow(it, 0);
+    VP8IteratorSetCountDown(it, (*enc).mb_w_ * (*enc).mb_h_); // default
+    InitTop(it);
+    (*it).bit_count_ = [[0; 3]; 4];
+    (*it).do_trellis_ = 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorSetCountDown(it: *mut VP8EncIterator, count_down: c_int) {
+    (*it).count_down0_ = count_down;
+    (*it).count_down_ = count_down;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorIsDone(it: *const VP8EncIterator) -> c_int {
+    ((*it).count_down_ <= 0) as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorInit(enc: *mut VP8Encoder, it: *mut VP8EncIterator) {
+    (*it).enc_ = enc;
+    (*it).yuv_in_ = WEBP_ALIGN((*it).yuv_mem_.as_mut_ptr());
+    (*it).yuv_out_ = (*it).yuv_in_.offset(YUV_SIZE_ENC as isize);
+    (*it).yuv_out2_ = (*it).yuv_out_.offset(YUV_SIZE_ENC as isize);
+    (*it).yuv_p_ = (*it).yuv_out2_.offset(YUV_SIZE_ENC as isize);
+    (*it).lf_stats_ = (*enc).lf_stats_;
+    (*it).percent0_ = (*enc).percent_;
+    (*it).y_left_ = WEBP_ALIGN((*it).yuv_left_mem_.as_mut_ptr().offset(1));
+    (*it).u_left_ = (*it).y_left_.offset(16 + 16);
+    (*it).v_left_ = (*it).u_left_.offset(16);
+    (*it).top_derr_ = (*enc).top_derr_;
+    VP8IteratorReset(it);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorProgress(it: *const VP8EncIterator, delta: c_int) -> c_int {
+    let enc = (*it).enc_;
+    if delta != 0 && (*(*enc).pic_).progress_hook.is_some() {
+        let done = (*it).count_down0_ - (*it).count_down_;
+        let percent = if (*it).count_down0_ <= 0 {
+            (*it).percent0_
+        } else {
+            (*it).percent0_ + delta * done / (*it).count_down0_
+        };
+        return WebPReportProgress((*enc).pic_, percent, &mut (*enc).percent_);
+    }
+    1
+}
+
+//------------------------------------------------------------------------------
+// Import the source samples into the cache. Takes care of replicating
+// boundary pixels if necessary.
+
+#[allow(non_snake_case)]
+#[inline]
+fn MinSize(a: c_int, b: c_int) -> c_int {
+    if a < b {
+        a
+    } else {
+        b
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ImportBlock(
+    mut src: *const u8,
+    src_stride: c_int,
+    mut dst: *mut u8,
+    w: c_int,
+    h: c_int,
+    size: c_int,
+) {
+    for _ in 0..h {
+        ptr::copy_nonoverlapping(src, dst, w as usize);
+        if w < size {
+            ptr::write_bytes(
+                dst.offset(w as isize),
+                *dst.offset(w as isize - 1),
+                (size - w) as usize,
+            );
+        }
+        dst = dst.offset(BPS as isize);
+        src = src.offset(src_stride as isize);
+    }
+    for _ in h..size {
+        ptr::copy_nonoverlapping(dst.offset(-BPS as isize), dst, size as usize);
+        dst = dst.offset(BPS as isize);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ImportLine(
+    mut src: *const u8,
+    src_stride: c_int,
+    dst: *mut u8,
+    len: c_int,
+    total_len: c_int,
+) {
+    for i in 0..len as isize {
+        *dst.offset(i) = *src;
+        src = src.offset(src_stride as isize);
+    }
+    for i in len as isize..total_len as isize {
+        *dst.offset(i) = *dst.offset(len as isize - 1);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorImport(it: *mut VP8EncIterator, tmp_32: *mut u8) {
+    let enc = (*it).enc_;
+    let x = (*it).x_;
+    let y = (*it).y_;
+    let pic = &*(*enc).pic_;
+    let ysrc: *const u8 = pic.y.offset(((y * pic.y_stride + x) * 16) as isize);
+    let usrc: *const u8 = pic.u.offset(((y * pic.uv_stride + x) * 8) as isize);
+    let vsrc: *const u8 = pic.v.offset(((y * pic.uv_stride + x) * 8) as isize);
+    let w = MinSize(pic.width - x * 16, 16);
+    let h = MinSize(pic.height - y * 16, 16);
+    let uv_w = (w + 1) >> 1;
+    let uv_h = (h + 1) >> 1;
+
+    ImportBlock(
+        ysrc,
+        pic.y_stride,
+        (*it).yuv_in_.offset(Y_OFF_ENC as isize),
+        w,
+        h,
+        16,
+    );
+    ImportBlock(
+        usrc,
+        pic.uv_stride,
+        (*it).yuv_in_.offset(U_OFF_ENC as isize),
+        uv_w,
+        uv_h,
+        8,
+    );
+    ImportBlock(
+        vsrc,
+        pic.uv_stride,
+        (*it).yuv_in_.offset(V_OFF_ENC as isize),
+        uv_w,
+        uv_h,
+        8,
+    );
+
+    if tmp_32.is_null() {
+        return;
+    }
+
+    // Import source (uncompressed) samples into boundary.
+    if x == 0 {
+        InitLeft(it);
+    } else {
+        if y == 0 {
+            *(*it).v_left_.offset(-1) = 127;
+            *(*it).u_left_.offset(-1) = 127;
+            *(*it).y_left_.offset(-1) = 127;
+        } else {
+            *(*it).y_left_.offset(-1) = *ysrc.offset((-1 - pic.y_stride) as isize);
+            *(*it).u_left_.offset(-1) = *usrc.offset((-1 - pic.uv_stride) as isize);
+            *(*it).v_left_.offset(-1) = *vsrc.offset((-1 - pic.uv_stride) as isize);
+        }
+        ImportLine(ysrc.offset(-1), pic.y_stride, (*it).y_left_, h, 16);
+        ImportLine(usrc.offset(-1), pic.uv_stride, (*it).u_left_, uv_h, 8);
+        ImportLine(vsrc.offset(-1), pic.uv_stride, (*it).v_left_, uv_h, 8);
+    }
+
+    (*it).y_top_ = tmp_32.offset(0);
+    (*it).uv_top_ = tmp_32.offset(16);
+    if y == 0 {
+        ptr::write_bytes(tmp_32, 127, 32);
+    } else {
+        ImportLine(ysrc.offset(-pic.y_stride as isize), 1, tmp_32, w, 16);
+        ImportLine(
+            usrc.offset(-pic.uv_stride as isize),
+            1,
+            tmp_32.offset(16),
+            uv_w,
+            8,
+        );
+        ImportLine(
+            vsrc.offset(-pic.uv_stride as isize),
+            1,
+            tmp_32.offset(16 + 8),
+            uv_w,
+            8,
+        );
+    }
+}
+
+//------------------------------------------------------------------------------
+// Copy back the compressed samples into user space if requested.
+
+#[allow(non_snake_case)]
+unsafe fn ExportBlock(mut src: *const u8, mut dst: *mut u8, dst_stride: c_int, w: c_int, h: c_int) {
+    for _ in 0..h {
+        ptr::copy_nonoverlapping(src, dst, w as usize);
+        dst = dst.offset(dst_stride as isize);
+        src = src.offset(BPS as isize);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorExport(it: *const VP8EncIterator) {
+    let enc = (*it).enc_;
+    if (*(*enc).config_).show_compressed != 0 {
+        let x = (*it).x_;
+        let y = (*it).y_;
+        let ysrc = (*it).yuv_out_.offset(Y_OFF_ENC as isize);
+        let usrc = (*it).yuv_out_.offset(U_OFF_ENC as isize);
+        let vsrc = (*it).yuv_out_.offset(V_OFF_ENC as isize);
+        let pic = &*(*enc).pic_;
+        let ydst = pic.y.offset(((y * pic.y_stride + x) * 16) as isize);
+        let udst = pic.u.offset(((y * pic.uv_stride + x) * 8) as isize);
+        let vdst = pic.v.offset(((y * pic.uv_stride + x) * 8) as isize);
+        let mut w = pic.width - x * 16;
+        let mut h = pic.height - y * 16;
+
+        if w > 16 {
+            w = 16;
+        }
+        if h > 16 {
+            h = 16;
+        }
+
+        // Luma plane
+        ExportBlock(ysrc, ydst, pic.y_stride, w, h);
+
+        {
+            // U/V planes
+            let uv_w = (w + 1) >> 1;
+            let uv_h = (h + 1) >> 1;
+            ExportBlock(usrc, udst, pic.uv_stride, uv_w, uv_h);
+            ExportBlock(vsrc, vdst, pic.uv_stride, uv_w, uv_h);
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// Non-zero contexts setup/teardown
+
+// Nz bits:
+//  0  1  2  3  Y
+//  4  5  6  7
+//  8  9 10 11
+// 12 13 14 15
+// 16 17        U
+// 18 19
+// 20 21        V
+// 22 23
+// 24           DC-intra16
+
+// Convert packed context to byte array
+#[allow(non_snake_case)]
+#[inline]
+fn BIT(nz: c_int, n: c_int) -> c_int {
+    ((nz & (1 << n)) != 0) as c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorNzToBytes(it: *mut VP8EncIterator) {
+    let tnz = *(*it).nz_.offset(0) as c_int;
+    let lnz = *(*it).nz_.offset(-1) as c_int;
+    let top_nz = &mut (*it).top_nz_;
+    let left_nz = &mut (*it).left_nz_;
+
+    // Top-Y
+    top_nz[0] = BIT(tnz, 12);
+    top_nz[1] = BIT(tnz, 13);
+    top_nz[2] = BIT(tnz, 14);
+    top_nz[3] = BIT(tnz, 15);
+    // Top-U
+    top_nz[4] = BIT(tnz, 18);
+    top_nz[5] = BIT(tnz, 19);
+    // Top-V
+    top_nz[6] = BIT(tnz, 22);
+    top_nz[7] = BIT(tnz, 23);
+    // DC
+    top_nz[8] = BIT(tnz, 24);
+
+    // left-Y
+    left_nz[0] = BIT(lnz, 3);
+    left_nz[1] = BIT(lnz, 7);
+    left_nz[2] = BIT(lnz, 11);
+    left_nz[3] = BIT(lnz, 15);
+    // left-U
+    left_nz[4] = BIT(lnz, 17);
+    left_nz[5] = BIT(lnz, 19);
+    // left-V
+    left_nz[6] = BIT(lnz, 21);
+    left_nz[7] = BIT(lnz, 23);
+    // left-DC is special, iterated separately
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorBytesToNz(it: *mut VP8EncIterator) {
+    let mut nz: u32 = 0;
+    let top_nz = &(*it).top_nz_;
+    let left_nz = &(*it).left_nz_;
+    // top
+    nz |= ((top_nz[0] << 12) | (top_nz[1] << 13)) as u32;
+    nz |= ((top_nz[2] << 14) | (top_nz[3] << 15)) as u32;
+    nz |= ((top_nz[4] << 18) | (top_nz[5] << 19)) as u32;
+    nz |= ((top_nz[6] << 22) | (top_nz[7] << 23)) as u32;
+    nz |= (top_nz[8] << 24) as u32; // we propagate the _top_ bit, esp. for intra4
+                                    // left
+    nz |= ((left_nz[0] << 3) | (left_nz[1] << 7)) as u32;
+    nz |= (left_nz[2] << 11) as u32;
+    nz |= ((left_nz[4] << 17) | (left_nz[6] << 21)) as u32;
+
+    *(*it).nz_ = nz;
+}
+
+//------------------------------------------------------------------------------
+// Advance to the next position, doing the bookkeeping.
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorSaveBoundary(it: *mut VP8EncIterator) {
+    let enc = (*it).enc_;
+    let x = (*it).x_;
+    let y = (*it).y_;
+    let ysrc = (*it).yuv_out_.offset(Y_OFF_ENC as isize);
+    let uvsrc = (*it).yuv_out_.offset(U_OFF_ENC as isize);
+    if x < (*enc).mb_w_ - 1 {
+        // left
+        for i in 0..16 {
+            *(*it).y_left_.offset(i) = *ysrc.offset(15 + i * BPS as isize);
+        }
+        for i in 0..8 {
+            *(*it).u_left_.offset(i) = *uvsrc.offset(7 + i * BPS as isize);
+            *(*it).v_left_.offset(i) = *uvsrc.offset(15 + i * BPS as isize);
+        }
+        // top-left (before 'top'!)
+        *(*it).y_left_.offset(-1) = *(*it).y_top_.offset(15);
+        *(*it).u_left_.offset(-1) = *(*it).uv_top_.offset(0 + 7);
+        *(*it).v_left_.offset(-1) = *(*it).uv_top_.offset(8 + 7);
+    }
+    if y < (*enc).mb_h_ - 1 {
+        // top
+        ptr::copy_nonoverlapping(ysrc.offset(15 * BPS as isize), (*it).y_top_, 16);
+        ptr::copy_nonoverlapping(uvsrc.offset(7 * BPS as isize), (*it).uv_top_, 8 + 8);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorNext(it: *mut VP8EncIterator) -> c_int {
+    (*it).x_ += 1;
+    if (*it).x_ == (*(*it).enc_).mb_w_ {
+        (*it).y_ += 1;
+        VP8IteratorSetRow(it, (*it).y_);
+    } else {
+        (*it).preds_ = (*it).preds_.offset(4);
+        (*it).mb_ = (*it).mb_.offset(1);
+        (*it).nz_ = (*it).nz_.offset(1);
+        (*it).y_top_ = (*it).y_top_.offset(16);
+        (*it).uv_top_ = (*it).uv_top_.offset(16);
+    }
+    (*it).count_down_ -= 1;
+    (0 < (*it).count_down_) as c_int
+}
+
+//------------------------------------------------------------------------------
+// Helper function to set mode properties
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8SetIntra16Mode(it: *const VP8EncIterator, mode: c_int) {
+    let mut preds = (*it).preds_;
+    for _ in 0..4 {
+        ptr::write_bytes(preds, mode as u8, 4);
+        preds = preds.offset((*(*it).enc_).preds_w_ as isize);
+    }
+    (*(*it).mb_).type_ = 1;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8SetIntra4Mode(it: *const VP8EncIterator, mut modes: *const u8) {
+    let mut preds = (*it).preds_;
+    for _ in 0..4 {
+        ptr::copy_nonoverlapping(modes, preds, 4);
+        preds = preds.offset((*(*it).enc_).preds_w_ as isize);
+        modes = modes.offset(4);
+    }
+    (*(*it).mb_).type_ = 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8SetIntraUVMode(it: *const VP8EncIterator, mode: c_int) {
+    (*(*it).mb_).uv_mode_ = mode as u8;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8SetSkip(it: *const VP8EncIterator, skip: c_int) {
+    (*(*it).mb_).skip_ = skip as u8;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8SetSegment(it: *const VP8EncIterator, segment: c_int) {
+    (*(*it).mb_).segment_ = segment as u8;
+}
+
+//------------------------------------------------------------------------------
+// Intra4x4 sub-blocks iteration
+//
+//  We store and update the boundary samples into an array of 37 pixels. They
+//  are updated as we iterate and reconstructs each intra4x4 blocks in turn.
+//  The position of the samples has the following snake pattern:
+//
+// 16|17 18 19 20|21 22 23 24|25 26 27 28|29 30 31 32|33 34 35 36  <- Top-right
+// --+-----------+-----------+-----------+-----------+
+// 15|         19|         23|         27|         31|
+// 14|         18|         22|         26|         30|
+// 13|         17|         21|         25|         29|
+// 12|13 14 15 16|17 18 19 20|21 22 23 24|25 26 27 28|
+// --+-----------+-----------+-----------+-----------+
+// 11|         15|         19|         23|         27|
+// 10|         14|         18|         22|         26|
+//  9|         13|         17|         21|         25|
+//  8| 9 10 11 12|13 14 15 16|17 18 19 20|21 22 23 24|
+// --+-----------+-----------+-----------+-----------+
+//  7|         11|         15|         19|         23|
+//  6|         10|         14|         18|         22|
+//  5|          9|         13|         17|         21|
+//  4| 5  6  7  8| 9 10 11 12|13 14 15 16|17 18 19 20|
+// --+-----------+-----------+-----------+-----------+
+//  3|          7|         11|         15|         19|
+//  2|          6|         10|         14|         18|
+//  1|          5|          9|         13|         17|
+//  0| 1  2  3  4| 5  6  7  8| 9 10 11 12|13 14 15 16|
+// --+-----------+-----------+-----------+-----------+
+
+// Array to record the position of the top sample to pass to the prediction
+// functions in dsp.c.
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static VP8TopLeftI4: [u8; 16] = [
+  17, 21, 25, 29,
+  13, 17, 21, 25,
+  9,  13, 17, 21,
+  5,   9, 13, 17
+];
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorStartI4(it: *mut VP8EncIterator) {
+    let enc = (*it).enc_;
+
+    (*it).i4_ = 0; // first 4x4 sub-block
+    (*it).i4_top_ = (*it)
+        .i4_boundary_
+        .as_mut_ptr()
+        .offset(VP8TopLeftI4[0] as isize);
+
+    // Import the boundary samples
+    for i in 0..17 {
+        // left
+        (*it).i4_boundary_[i] = *(*it).y_left_.offset(15 - i as isize);
+    }
+    for i in 0..16 {
+        // top
+        (*it).i4_boundary_[17 + i] = *(*it).y_top_.offset(i as isize);
+    }
+    // top-right samples have a special case on the far right of the picture
+    if (*it).x_ < (*enc).mb_w_ - 1 {
+        for i in 16..16 + 4 {
+            (*it).i4_boundary_[17 + i] = *(*it).y_top_.offset(i as isize);
+        }
+    } else {
+        // else, replicate the last valid pixel four times
+        for i in 16..16 + 4 {
+            (*it).i4_boundary_[17 + i] = (*it).i4_boundary_[17 + 15];
+        }
+    }
+    VP8IteratorNzToBytes(it); // import the non-zero context
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8IteratorRotateI4(it: *mut VP8EncIterator, yuv_out: *const u8) -> c_int {
+    let blk = yuv_out.offset(VP8Scan[(*it).i4_ as usize] as isize);
+    let top = (*it).i4_top_;
+
+    // Update the cache with 7 fresh samples
+    for i in 0..=3 {
+        *top.offset(-4 + i) = *blk.offset(i + 3 * BPS as isize); // store future top samples
+    }
+    if ((*it).i4_ & 3) != 3 {
+        // if not on the right sub-blocks #3, #7, #11, #15
+        for i in 0..=2 {
+            // store future left samples
+            *top.offset(i) = *blk.offset(3 + (2 - i) * BPS as isize);
+        }
+    } else {
+        // else replicate top-right samples, as says the specs.
+        for i in 0..=3 {
+            *top.offset(i) = *top.offset(i + 4);
+        }
+    }
+    // move pointers to next sub-block
+    (*it).i4_ += 1;
+    if (*it).i4_ == 16 {
+        // we're done
+        return 0;
+    }
+
+    (*it).i4_top_ = (*it)
+        .i4_boundary_
+        .as_mut_ptr()
+        .offset(VP8TopLeftI4[(*it).i4_ as usize] as isize);
+    1
+}
@@ -0,0 +1,51 @@
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod analysis_enc;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod backward_references_cost_enc;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod backward_references_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod cost_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod filter_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod frame_enc;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod histogram_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod iterator_enc;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod predictor_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod quant_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod syntax_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod token_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod tree_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod vp8i_enc;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod vp8l_enc;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod vp8li_enc;
+#[cfg(feature = "rust-vp8-encoder")]
+#[doc(hidden)]
+pub mod webp_enc;
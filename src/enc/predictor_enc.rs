@@ -0,0 +1,1280 @@
+// Copyright 2016 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Image transform methods for lossless encoder.
+//
+// Authors: Vikas Arora (vikaas.arora@gmail.com)
+//          Jyrki Alakuijala (jyrki@google.com)
+//          Urvang Joshi (urvang@google.com)
+//          Vincent Rabaud (vrabaud@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::cmp;
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::lossless::{VP8LMultipliers, VP8LPredictors};
+use dsp::lossless_common::{VP8LAddPixels, VP8LSubSampleSize};
+use dsp::lossless_enc::{
+    VP8LCollectColorBlueTransforms, VP8LCollectColorRedTransforms, VP8LCombinedShannonEntropy,
+    VP8LNearLosslessBits, VP8LPredictorsSub, VP8LSubPixels, VP8LTransformColor,
+};
+use enc::vp8li_enc::MAX_TRANSFORM_BITS;
+use format_constants::ARGB_BLACK;
+
+const MAX_DIFF_COST: c_float = 1e30;
+
+#[allow(non_upper_case_globals)]
+const kSpatialPredictorBias: c_float = 15.0;
+#[allow(non_upper_case_globals)]
+const kPredLowEffort: c_int = 11;
+#[allow(non_upper_case_globals)]
+const kMaskAlpha: u32 = 0xff000000;
+
+//------------------------------------------------------------------------------
+// Methods to calculate Entropy (Shannon).
+
+#[allow(non_snake_case)]
+fn PredictionCostSpatial(counts: &[c_int; 256], weight_0: c_int, mut exp_val: c_double) -> c_float {
+    let significant_symbols = 256 >> 4;
+    let exp_decay_factor = 0.6;
+    let mut bits = (weight_0 * counts[0]) as c_double;
+    for i in 1..significant_symbols {
+        bits += exp_val * (counts[i] + counts[256 - i]) as c_double;
+        exp_val *= exp_decay_factor;
+    }
+    (-0.1 * bits) as c_float
+}
+
+#[allow(non_snake_case)]
+unsafe fn PredictionCostSpatialHistogram(
+    accumulated: &[[c_int; 256]; 4],
+    tile: &[[c_int; 256]; 4],
+) -> c_float {
+    let mut retval: c_double = 0.0;
+    for i in 0..4 {
+        #[allow(non_upper_case_globals)]
+        const kExpValue: c_double = 0.94;
+        retval += PredictionCostSpatial(&tile[i], 1, kExpValue) as c_double;
+        retval += VP8LCombinedShannonEntropy.unwrap()(tile[i].as_ptr(), accumulated[i].as_ptr())
+            as c_double;
+    }
+    retval as c_float
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn UpdateHisto(histo_argb: &mut [[c_int; 256]; 4], argb: u32) {
+    histo_argb[0][(argb >> 24) as usize] += 1;
+    histo_argb[1][((argb >> 16) & 0xff) as usize] += 1;
+    histo_argb[2][((argb >> 8) & 0xff) as usize] += 1;
+    histo_argb[3][(argb & 0xff) as usize] += 1;
+}
+
+//------------------------------------------------------------------------------
+// Spatial transform functions.
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn PredictBatch(
+    mode: c_int,
+    mut x_start: c_int,
+    y: c_int,
+    mut num_pixels: c_int,
+    current: *const u32,
+    upper: *const u32,
+    mut out: *mut u32,
+) {
+    if x_start == 0 {
+        if y == 0 {
+            // ARGB_BLACK.
+            VP8LPredictorsSub[0].unwrap()(current, ptr::null(), 1, out);
+        } else {
+            // Top one.
+            VP8LPredictorsSub[2].unwrap()(current, upper, 1, out);
+        }
+        x_start += 1;
+        out = out.add(1);
+        num_pixels -= 1;
+    }
+    if y == 0 {
+        // Left one.
+        VP8LPredictorsSub[1].unwrap()(
+            current.offset(x_start as isize),
+            ptr::null(),
+            num_pixels,
+            out,
+        );
+    } else {
+        VP8LPredictorsSub[mode as usize].unwrap()(
+            current.offset(x_start as isize),
+            upper.offset(x_start as isize),
+            num_pixels,
+            out,
+        );
+    }
+}
+
+#[allow(non_snake_case)]
+fn MaxDiffBetweenPixels(p1: u32, p2: u32) -> c_int {
+    let diff_a = ((p1 >> 24) as c_int - (p2 >> 24) as c_int).abs();
+    let diff_r = (((p1 >> 16) & 0xff) as c_int - ((p2 >> 16) & 0xff) as c_int).abs();
+    let diff_g = (((p1 >> 8) & 0xff) as c_int - ((p2 >> 8) & 0xff) as c_int).abs();
+    let diff_b = ((p1 & 0xff) as c_int - (p2 & 0xff) as c_int).abs();
+    cmp::max(cmp::max(diff_a, diff_r), cmp::max(diff_g, diff_b))
+}
+
+#[allow(non_snake_case)]
+fn MaxDiffAroundPixel(current: u32, up: u32, down: u32, left: u32, right: u32) -> c_int {
+    let diff_up = MaxDiffBetweenPixels(current, up);
+    let diff_down = MaxDiffBetweenPixels(current, down);
+    let diff_left = MaxDiffBetweenPixels(current, left);
+    let diff_right = MaxDiffBetweenPixels(current, right);
+    cmp::max(
+        cmp::max(diff_up, diff_down),
+        cmp::max(diff_left, diff_right),
+    )
+}
+
+#[allow(non_snake_case)]
+fn AddGreenToBlueAndRed(argb: u32) -> u32 {
+    let green = (argb >> 8) & 0xff;
+    let mut red_blue = argb & 0x00ff00ff;
+    red_blue += (green << 16) | green;
+    red_blue &= 0x00ff00ff;
+    (argb & 0xff00ff00) | red_blue
+}
+
+#[allow(non_snake_case)]
+unsafe fn MaxDiffsForRow(
+    width: c_int,
+    stride: c_int,
+    argb: *const u32,
+    max_diffs: *mut u8,
+    used_subtract_green: c_int,
+) {
+    if width <= 2 {
+        return;
+    }
+    let mut current = *argb;
+    let mut right = *argb.add(1);
+    if used_subtract_green != 0 {
+        current = AddGreenToBlueAndRed(current);
+        right = AddGreenToBlueAndRed(right);
+    }
+    // max_diffs[0] and max_diffs[width - 1] are never used.
+    for x in 1..(width - 1) as isize {
+        let mut up = *argb.offset(-stride as isize + x);
+        let mut down = *argb.offset(stride as isize + x);
+        let left = current;
+        current = right;
+        right = *argb.offset(x + 1);
+        if used_subtract_green != 0 {
+            up = AddGreenToBlueAndRed(up);
+            down = AddGreenToBlueAndRed(down);
+            right = AddGreenToBlueAndRed(right);
+        }
+        *max_diffs.offset(x) = MaxDiffAroundPixel(current, up, down, left, right) as u8;
+    }
+}
+
+// Quantize the difference between the actual component value and its prediction
+// to a multiple of quantization, working modulo 256, taking care not to cross
+// a boundary (inclusive upper limit).
+#[allow(non_snake_case)]
+fn NearLosslessComponent(value: u8, predict: u8, boundary: u8, quantization: c_int) -> u8 {
+    let residual = (value as c_int - predict as c_int) & 0xff;
+    let boundary_residual = (boundary as c_int - predict as c_int) & 0xff;
+    let lower = residual & !(quantization - 1);
+    let upper = lower + quantization;
+    // Resolve ties towards a value closer to the prediction (i.e. towards lower
+    // if value comes after prediction and towards upper otherwise).
+    let bias = (((boundary as c_int - value as c_int) & 0xff) < boundary_residual) as c_int;
+    if residual - lower < upper - residual + bias {
+        // lower is closer to residual than upper.
+        if residual > boundary_residual && lower <= boundary_residual {
+            // Halve quantization step to avoid crossing boundary. This midpoint is
+            // on the same side of boundary as residual because midpoint >= residual
+            // (since lower is closer than upper) and residual is above the boundary.
+            return (lower + (quantization >> 1)) as u8;
+        }
+        lower as u8
+    } else {
+        // upper is closer to residual than lower.
+        if residual <= boundary_residual && upper > boundary_residual {
+            // Halve quantization step to avoid crossing boundary. This midpoint is
+            // on the same side of boundary as residual because midpoint <= residual
+            // (since upper is closer than lower) and residual is below the boundary.
+            return (lower + (quantization >> 1)) as u8;
+        }
+        (upper & 0xff) as u8
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn NEAR_LOSSLESS_DIFF(a: u32, b: u32) -> u8 {
+    ((a as c_int - b as c_int) & 0xff) as u8
+}
+
+// Quantize every component of the difference between the actual pixel value and
+// its prediction to a multiple of a quantization (a power of 2, not larger than
+// max_quantization which is a power of 2, smaller than max_diff). Take care if
+// value and predict have undergone subtract green, which means that red and
+// blue are represented as offsets from green.
+#[allow(non_snake_case)]
+fn NearLossless(
+    value: u32,
+    predict: u32,
+    max_quantization: c_int,
+    max_diff: c_int,
+    used_subtract_green: c_int,
+) -> u32 {
+    let mut new_green: u8 = 0;
+    let mut green_diff: u8 = 0;
+    if max_diff <= 2 {
+        return VP8LSubPixels(value, predict);
+    }
+    let mut quantization = max_quantization;
+    while quantization >= max_diff {
+        quantization >>= 1;
+    }
+    let a = if (value >> 24) == 0 || (value >> 24) == 0xff {
+        // Preserve transparency of fully transparent or fully opaque pixels.
+        NEAR_LOSSLESS_DIFF(value >> 24, predict >> 24)
+    } else {
+        NearLosslessComponent(
+            (value >> 24) as u8,
+            (predict >> 24) as u8,
+            0xff,
+            quantization,
+        )
+    };
+    let g = NearLosslessComponent(
+        ((value >> 8) & 0xff) as u8,
+        ((predict >> 8) & 0xff) as u8,
+        0xff,
+        quantization,
+    );
+    if used_subtract_green != 0 {
+        // The green offset will be added to red and blue components during decoding
+        // to obtain the actual red and blue values.
+        new_green = (((predict >> 8) + g as u32) & 0xff) as u8;
+        // The amount by which green has been adjusted during quantization. It is
+        // subtracted from red and blue for compensation, to avoid accumulating two
+        // quantization errors in them.
+        green_diff = NEAR_LOSSLESS_DIFF(new_green as u32, value >> 8);
+    }
+    let r = NearLosslessComponent(
+        NEAR_LOSSLESS_DIFF(value >> 16, green_diff as u32),
+        ((predict >> 16) & 0xff) as u8,
+        0xff - new_green,
+        quantization,
+    );
+    let b = NearLosslessComponent(
+        NEAR_LOSSLESS_DIFF(value, green_diff as u32),
+        (predict & 0xff) as u8,
+        0xff - new_green,
+        quantization,
+    );
+    ((a as u32) << 24) | ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+}
+
+// Stores the difference between the pixel and its prediction in "out".
+// In case of a lossy encoding, updates the source image to avoid propagating
+// the deviation further to pixels which depend on the current pixel for their
+// predictions.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn GetResidual(
+    width: c_int,
+    height: c_int,
+    upper_row: *mut u32,
+    current_row: *mut u32,
+    max_diffs: *const u8,
+    mode: c_int,
+    x_start: c_int,
+    x_end: c_int,
+    y: c_int,
+    max_quantization: c_int,
+    exact: c_int,
+    used_subtract_green: c_int,
+    out: *mut u32,
+) {
+    if exact != 0 {
+        PredictBatch(
+            mode,
+            x_start,
+            y,
+            x_end - x_start,
+            current_row,
+            upper_row,
+            out,
+        );
+    } else {
+        let pred_func = VP8LPredictors[mode as usize].unwrap();
+        for x in x_start..x_end {
+            let xi = x as isize;
+            let predict = if y == 0 {
+                if x == 0 {
+                    ARGB_BLACK
+                } else {
+                    *current_row.offset(xi - 1) // Left.
+                }
+            } else if x == 0 {
+                *upper_row.offset(xi) // Top.
+            } else {
+                pred_func(*current_row.offset(xi - 1), upper_row.offset(xi))
+            };
+            let mut residual;
+            if max_quantization == 1
+                || mode == 0
+                || y == 0
+                || y == height - 1
+                || x == 0
+                || x == width - 1
+            {
+                residual = VP8LSubPixels(*current_row.offset(xi), predict);
+            } else {
+                residual = NearLossless(
+                    *current_row.offset(xi),
+                    predict,
+                    max_quantization,
+                    *max_diffs.offset(xi) as c_int,
+                    used_subtract_green,
+                );
+                // Update the source image.
+                *current_row.offset(xi) = VP8LAddPixels(predict, residual);
+                // x is never 0 here so we do not need to update upper_row like below.
+            }
+            if (*current_row.offset(xi) & kMaskAlpha) == 0 {
+                // If alpha is 0, cleanup RGB. We can choose the RGB values of the
+                // residual for best compression. The prediction of alpha itself can be
+                // non-zero and must be kept though. We choose RGB of the residual to be
+                // 0.
+                residual &= kMaskAlpha;
+                // Update the source image.
+                *current_row.offset(xi) = predict & !kMaskAlpha;
+                // The prediction for the rightmost pixel in a row uses the leftmost
+                // pixel
+                // in that row as its top-right context pixel. Hence if we change the
+                // leftmost pixel of current_row, the corresponding change must be
+                // applied
+                // to upper_row as well where top-right context is being read from.
+                if x == 0 && y != 0 {
+                    *upper_row.offset(width as isize) = *current_row;
+                }
+            }
+            *out.offset((x - x_start) as isize) = residual;
+        }
+    }
+}
+
+// Returns best predictor and updates the accumulated histogram.
+// If max_quantization > 1, assumes that near lossless processing will be
+// applied, quantizing residuals to multiples of quantization levels up to
+// max_quantization (the actual quantization level depends on smoothness near
+// the given pixel).
+#[allow(non_snake_case)]
+unsafe fn GetBestPredictorForTile(
+    width: c_int,
+    height: c_int,
+    tile_x: c_int,
+    tile_y: c_int,
+    bits: c_int,
+    accumulated: &mut [[c_int; 256]; 4],
+    argb_scratch: *mut u32,
+    argb: *const u32,
+    max_quantization: c_int,
+    exact: c_int,
+    used_subtract_green: c_int,
+    modes: *const u32,
+) -> c_int {
+    #[allow(non_upper_case_globals)]
+    const kNumPredModes: c_int = 14;
+    let start_x = tile_x << bits;
+    let start_y = tile_y << bits;
+    let tile_size = 1 << bits;
+    let max_y = cmp::min(tile_size, height - start_y);
+    let max_x = cmp::min(tile_size, width - start_x);
+    // Whether there exist columns just outside the tile.
+    let have_left = (start_x > 0) as c_int;
+    // Position and size of the strip covering the tile and adjacent columns if
+    // they exist.
+    let context_start_x = start_x - have_left;
+    let context_width = max_x + have_left + (max_x < width - start_x) as c_int;
+    let tiles_per_row = VP8LSubSampleSize(width as u32, bits as u32) as c_int;
+    // Prediction modes of the left and above neighbor tiles.
+    let left_mode = if tile_x > 0 {
+        ((*modes.offset((tile_y * tiles_per_row + tile_x - 1) as isize) >> 8) & 0xff) as c_int
+    } else {
+        0xff
+    };
+    let above_mode = if tile_y > 0 {
+        ((*modes.offset(((tile_y - 1) * tiles_per_row + tile_x) as isize) >> 8) & 0xff) as c_int
+    } else {
+        0xff
+    };
+    // The width of upper_row and current_row is one pixel larger than image width
+    // to allow the top right pixel to point to the leftmost pixel of the next row
+    // when at the right edge.
+    let mut upper_row = argb_scratch;
+    let mut current_row = upper_row.offset(width as isize + 1);
+    let max_diffs = current_row.offset(width as isize + 1) as *mut u8;
+    let mut best_diff = MAX_DIFF_COST;
+    let mut best_mode = 0;
+    let mut histo_stack_1 = [[0 as c_int; 256]; 4];
+    let mut histo_stack_2 = [[0 as c_int; 256]; 4];
+    // Need references to be able to swap arrays.
+    let mut histo_argb = &mut histo_stack_1;
+    let mut best_histo = &mut histo_stack_2;
+    let mut residuals = [0u32; 1 << MAX_TRANSFORM_BITS];
+    c_assert!(bits <= MAX_TRANSFORM_BITS);
+    c_assert!(max_x <= (1 << MAX_TRANSFORM_BITS));
+
+    for mode in 0..kNumPredModes {
+        *histo_argb = [[0; 256]; 4];
+        if start_y > 0 {
+            // Read the row above the tile which will become the first upper_row.
+            // Include a pixel to the left if it exists; include a pixel to the right
+            // in all cases (wrapping to the leftmost pixel of the next row if it does
+            // not exist).
+            ptr::copy_nonoverlapping(
+                argb.offset(((start_y - 1) * width + context_start_x) as isize),
+                current_row.offset(context_start_x as isize),
+                (max_x + have_left + 1) as usize,
+            );
+        }
+        for relative_y in 0..max_y {
+            let y = start_y + relative_y;
+            mem::swap(&mut upper_row, &mut current_row);
+            // Read current_row. Include a pixel to the left if it exists; include a
+            // pixel to the right in all cases except at the bottom right corner of
+            // the image (wrapping to the leftmost pixel of the next row if it does
+            // not exist in the current row).
+            ptr::copy_nonoverlapping(
+                argb.offset((y * width + context_start_x) as isize),
+                current_row.offset(context_start_x as isize),
+                (max_x + have_left + (y + 1 < height) as c_int) as usize,
+            );
+            if max_quantization > 1 && y >= 1 && y + 1 < height {
+                MaxDiffsForRow(
+                    context_width,
+                    width,
+                    argb.offset((y * width + context_start_x) as isize),
+                    max_diffs.offset(context_start_x as isize),
+                    used_subtract_green,
+                );
+            }
+
+            GetResidual(
+                width,
+                height,
+                upper_row,
+                current_row,
+                max_diffs,
+                mode,
+                start_x,
+                start_x + max_x,
+                y,
+                max_quantization,
+                exact,
+                used_subtract_green,
+                residuals.as_mut_ptr(),
+            );
+            for &residual in &residuals[..max_x as usize] {
+                UpdateHisto(histo_argb, residual);
+            }
+        }
+        let mut cur_diff = PredictionCostSpatialHistogram(accumulated, histo_argb);
+        // Favor keeping the areas locally similar.
+        if mode == left_mode {
+            cur_diff -= kSpatialPredictorBias;
+        }
+        if mode == above_mode {
+            cur_diff -= kSpatialPredictorBias;
+        }
+
+        if cur_diff < best_diff {
+            mem::swap(&mut histo_argb, &mut best_histo);
+            best_diff = cur_diff;
+            best_mode = mode;
+        }
+    }
+
+    for i in 0..4 {
+        for j in 0..256 {
+            accumulated[i][j] += best_histo[i][j];
+        }
+    }
+
+    best_mode
+}
+
+// Converts pixels of the image to residuals with respect to predictions.
+// If max_quantization > 1, applies near lossless processing, quantizing
+// residuals to multiples of quantization levels up to max_quantization
+// (the actual quantization level depends on smoothness near the given pixel).
+#[allow(non_snake_case)]
+unsafe fn CopyImageWithPrediction(
+    width: c_int,
+    height: c_int,
+    bits: c_int,
+    modes: *const u32,
+    argb_scratch: *mut u32,
+    argb: *mut u32,
+    low_effort: c_int,
+    max_quantization: c_int,
+    exact: c_int,
+    used_subtract_green: c_int,
+) {
+    let tiles_per_row = VP8LSubSampleSize(width as u32, bits as u32) as c_int;
+    // The width of upper_row and current_row is one pixel larger than image width
+    // to allow the top right pixel to point to the leftmost pixel of the next row
+    // when at the right edge.
+    let mut upper_row = argb_scratch;
+    let mut current_row = upper_row.offset(width as isize + 1);
+    let mut current_max_diffs = current_row.offset(width as isize + 1) as *mut u8;
+    let mut lower_max_diffs = current_max_diffs.offset(width as isize);
+
+    for y in 0..height {
+        mem::swap(&mut upper_row, &mut current_row);
+        ptr::copy_nonoverlapping(
+            argb.offset((y * width) as isize),
+            current_row,
+            (width + (y + 1 < height) as c_int) as usize,
+        );
+
+        if low_effort != 0 {
+            PredictBatch(
+                kPredLowEffort,
+                0,
+                y,
+                width,
+                current_row,
+                upper_row,
+                argb.offset((y * width) as isize),
+            );
+        } else {
+            if max_quantization > 1 {
+                // Compute max_diffs for the lower row now, because that needs the
+                // contents of argb for the current row, which we will overwrite with
+                // residuals before proceeding with the next row.
+                mem::swap(&mut current_max_diffs, &mut lower_max_diffs);
+                if y + 2 < height {
+                    MaxDiffsForRow(
+                        width,
+                        width,
+                        argb.offset(((y + 1) * width) as isize),
+                        lower_max_diffs,
+                        used_subtract_green,
+                    );
+                }
+            }
+            let mut x = 0;
+            while x < width {
+                let mode = ((*modes.offset(((y >> bits) * tiles_per_row + (x >> bits)) as isize)
+                    >> 8)
+                    & 0xff) as c_int;
+                let x_end = cmp::min(x + (1 << bits), width);
+                GetResidual(
+                    width,
+                    height,
+                    upper_row,
+                    current_row,
+                    current_max_diffs,
+                    mode,
+                    x,
+                    x_end,
+                    y,
+                    max_quantization,
+                    exact,
+                    used_subtract_green,
+                    argb.offset((y * width + x) as isize),
+                );
+                x = x_end;
+            }
+        }
+    }
+}
+
+// Finds the best predictor for each tile, and converts the image to residuals
+// with respect to predictions. If near_lossless_quality < 100, applies
+// near lossless processing, shaving off more bits of residuals for lower
+// qualities.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LResidualImage(
+    width: c_int,
+    height: c_int,
+    bits: c_int,
+    low_effort: c_int,
+    argb: *mut u32,
+    argb_scratch: *mut u32,
+    image: *mut u32,
+    near_lossless_quality: c_int,
+    exact: c_int,
+    used_subtract_green: c_int,
+) {
+    let tiles_per_row = VP8LSubSampleSize(width as u32, bits as u32) as c_int;
+    let tiles_per_col = VP8LSubSampleSize(height as u32, bits as u32) as c_int;
+    let max_quantization = 1 << VP8LNearLosslessBits(near_lossless_quality);
+    if low_effort != 0 {
+        for i in 0..(tiles_per_row * tiles_per_col) as isize {
+            *image.offset(i) = ARGB_BLACK | ((kPredLowEffort as u32) << 8);
+        }
+    } else {
+        let mut histo = [[0 as c_int; 256]; 4];
+        for tile_y in 0..tiles_per_col {
+            for tile_x in 0..tiles_per_row {
+                let pred = GetBestPredictorForTile(
+                    width,
+                    height,
+                    tile_x,
+                    tile_y,
+                    bits,
+                    &mut histo,
+                    argb_scratch,
+                    argb,
+                    max_quantization,
+                    exact,
+                    used_subtract_green,
+                    image,
+                );
+                *image.offset((tile_y * tiles_per_row + tile_x) as isize) =
+                    ARGB_BLACK | ((pred as u32) << 8);
+            }
+        }
+    }
+
+    CopyImageWithPrediction(
+        width,
+        height,
+        bits,
+        image,
+        argb_scratch,
+        argb,
+        low_effort,
+        max_quantization,
+        exact,
+        used_subtract_green,
+    );
+}
+
+//------------------------------------------------------------------------------
+// Color transform functions.
+
+#[allow(non_snake_case)]
+#[inline]
+fn MultipliersClear(m: &mut VP8LMultipliers) {
+    m.green_to_red_ = 0;
+    m.green_to_blue_ = 0;
+    m.red_to_blue_ = 0;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ColorCodeToMultipliers(color_code: u32, m: &mut VP8LMultipliers) {
+    m.green_to_red_ = ((color_code >> 0) & 0xff) as u8;
+    m.green_to_blue_ = ((color_code >> 8) & 0xff) as u8;
+    m.red_to_blue_ = ((color_code >> 16) & 0xff) as u8;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn MultipliersToColorCode(m: &VP8LMultipliers) -> u32 {
+    0xff000000
+        | ((m.red_to_blue_ as u32) << 16)
+        | ((m.green_to_blue_ as u32) << 8)
+        | m.green_to_red_ as u32
+}
+
+#[allow(non_snake_case)]
+unsafe fn PredictionCostCrossColor(accumulated: &[c_int; 256], counts: &[c_int; 256]) -> c_float {
+    // Favor low entropy, locally and globally.
+    // Favor small absolute values for PredictionCostSpatial
+    #[allow(non_upper_case_globals)]
+    const kExpValue: c_double = 2.4;
+    VP8LCombinedShannonEntropy.unwrap()(counts.as_ptr(), accumulated.as_ptr())
+        + PredictionCostSpatial(counts, 3, kExpValue)
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetPredictionCostCrossColorRed(
+    argb: *const u32,
+    stride: c_int,
+    tile_width: c_int,
+    tile_height: c_int,
+    prev_x: VP8LMultipliers,
+    prev_y: VP8LMultipliers,
+    green_to_red: c_int,
+    accumulated_red_histo: &[c_int; 256],
+) -> c_float {
+    let mut histo = [0 as c_int; 256];
+
+    VP8LCollectColorRedTransforms.unwrap()(
+        argb,
+        stride,
+        tile_width,
+        tile_height,
+        green_to_red,
+        histo.as_mut_ptr(),
+    );
+
+    let mut cur_diff = PredictionCostCrossColor(accumulated_red_histo, &histo);
+    if green_to_red as u8 == prev_x.green_to_red_ {
+        cur_diff -= 3.0; // favor keeping the areas locally similar
+    }
+    if green_to_red as u8 == prev_y.green_to_red_ {
+        cur_diff -= 3.0; // favor keeping the areas locally similar
+    }
+    if green_to_red == 0 {
+        cur_diff -= 3.0;
+    }
+    cur_diff
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetBestGreenToRed(
+    argb: *const u32,
+    stride: c_int,
+    tile_width: c_int,
+    tile_height: c_int,
+    prev_x: VP8LMultipliers,
+    prev_y: VP8LMultipliers,
+    quality: c_int,
+    accumulated_red_histo: &[c_int; 256],
+    best_tx: &mut VP8LMultipliers,
+) {
+    let kMaxIters = 4 + ((7 * quality) >> 8); // in range [4..6]
+    let mut green_to_red_best = 0;
+    let mut best_diff = GetPredictionCostCrossColorRed(
+        argb,
+        stride,
+        tile_width,
+        tile_height,
+        prev_x,
+        prev_y,
+        green_to_red_best,
+        accumulated_red_histo,
+    );
+    for iter in 0..kMaxIters {
+        // ColorTransformDelta is a 3.5 bit fixed point, so 32 is equal to
+        // one in color computation. Having initial delta here as 1 is sufficient
+        // to explore the range of (-2, 2).
+        let delta = 32 >> iter;
+        // Try a negative and a positive delta from the best known value.
+        let mut offset = -delta;
+        while offset <= delta {
+            let green_to_red_cur = offset + green_to_red_best;
+            let cur_diff = GetPredictionCostCrossColorRed(
+                argb,
+                stride,
+                tile_width,
+                tile_height,
+                prev_x,
+                prev_y,
+                green_to_red_cur,
+                accumulated_red_histo,
+            );
+            if cur_diff < best_diff {
+                best_diff = cur_diff;
+                green_to_red_best = green_to_red_cur;
+            }
+            offset += 2 * delta;
+        }
+    }
+    best_tx.green_to_red_ = green_to_red_best as u8;
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetPredictionCostCrossColorBlue(
+    argb: *const u32,
+    stride: c_int,
+    tile_width: c_int,
+    tile_height: c_int,
+    prev_x: VP8LMultipliers,
+    prev_y: VP8LMultipliers,
+    green_to_blue: c_int,
+    red_to_blue: c_int,
+    accumulated_blue_histo: &[c_int; 256],
+) -> c_float {
+    let mut histo = [0 as c_int; 256];
+
+    VP8LCollectColorBlueTransforms.unwrap()(
+        argb,
+        stride,
+        tile_width,
+        tile_height,
+        green_to_blue,
+        red_to_blue,
+        histo.as_mut_ptr(),
+    );
+
+    let mut cur_diff = PredictionCostCrossColor(accumulated_blue_histo, &histo);
+    if green_to_blue as u8 == prev_x.green_to_blue_ {
+        cur_diff -= 3.0; // favor keeping the areas locally similar
+    }
+    if green_to_blue as u8 == prev_y.green_to_blue_ {
+        cur_diff -= 3.0; // favor keeping the areas locally similar
+    }
+    if red_to_blue as u8 == prev_x.red_to_blue_ {
+        cur_diff -= 3.0; // favor keeping the areas locally similar
+    }
+    if red_to_blue as u8 == prev_y.red_to_blue_ {
+        cur_diff -= 3.0; // favor keeping the areas locally similar
+    }
+    if green_to_blue == 0 {
+        cur_diff -= 3.0;
+    }
+    if red_to_blue == 0 {
+        cur_diff -= 3.0;
+    }
+    cur_diff
+}
+
+#[allow(non_upper_case_globals)]
+const kGreenRedToBlueNumAxis: usize = 8;
+#[allow(non_upper_case_globals)]
+const kGreenRedToBlueMaxIters: c_int = 7;
+
+#[allow(non_snake_case)]
+unsafe fn GetBestGreenRedToBlue(
+    argb: *const u32,
+    stride: c_int,
+    tile_width: c_int,
+    tile_height: c_int,
+    prev_x: VP8LMultipliers,
+    prev_y: VP8LMultipliers,
+    quality: c_int,
+    accumulated_blue_histo: &[c_int; 256],
+    best_tx: &mut VP8LMultipliers,
+) {
+    let offset: [[i8; 2]; kGreenRedToBlueNumAxis] = [
+        [0, -1],
+        [0, 1],
+        [-1, 0],
+        [1, 0],
+        [-1, -1],
+        [-1, 1],
+        [1, -1],
+        [1, 1],
+    ];
+    let delta_lut: [i8; kGreenRedToBlueMaxIters as usize] = [16, 16, 8, 4, 2, 2, 2];
+    let iters = if quality < 25 {
+        1
+    } else if quality > 50 {
+        kGreenRedToBlueMaxIters
+    } else {
+        4
+    };
+    let mut green_to_blue_best = 0;
+    let mut red_to_blue_best = 0;
+    // Initial value at origin:
+    let mut best_diff = GetPredictionCostCrossColorBlue(
+        argb,
+        stride,
+        tile_width,
+        tile_height,
+        prev_x,
+        prev_y,
+        green_to_blue_best,
+        red_to_blue_best,
+        accumulated_blue_histo,
+    );
+    for iter in 0..iters {
+        let delta = delta_lut[iter as usize] as c_int;
+        for axis in 0..kGreenRedToBlueNumAxis {
+            let green_to_blue_cur = offset[axis][0] as c_int * delta + green_to_blue_best;
+            let red_to_blue_cur = offset[axis][1] as c_int * delta + red_to_blue_best;
+            let cur_diff = GetPredictionCostCrossColorBlue(
+                argb,
+                stride,
+                tile_width,
+                tile_height,
+                prev_x,
+                prev_y,
+                green_to_blue_cur,
+                red_to_blue_cur,
+                accumulated_blue_histo,
+            );
+            if cur_diff < best_diff {
+                best_diff = cur_diff;
+                green_to_blue_best = green_to_blue_cur;
+                red_to_blue_best = red_to_blue_cur;
+            }
+            if quality < 25 && iter == 4 {
+                // Only axis aligned diffs for lower quality.
+                break; // next iter.
+            }
+        }
+        if delta == 2 && green_to_blue_best == 0 && red_to_blue_best == 0 {
+            // Further iterations would not help.
+            break; // out of iter-loop.
+        }
+    }
+    best_tx.green_to_blue_ = green_to_blue_best as u8;
+    best_tx.red_to_blue_ = red_to_blue_best as u8;
+}
+
+#[allow(non_snake_case)]
+unsafe fn GetBestColorTransformForTile(
+    tile_x: c_int,
+    tile_y: c_int,
+    bits: c_int,
+    prev_x: VP8LMultipliers,
+    prev_y: VP8LMultipliers,
+    quality: c_int,
+    xsize: c_int,
+    ysize: c_int,
+    accumulated_red_histo: &[c_int; 256],
+    accumulated_blue_histo: &[c_int; 256],
+    argb: *const u32,
+) -> VP8LMultipliers {
+    let max_tile_size = 1 << bits;
+    let tile_y_offset = tile_y * max_tile_size;
+    let tile_x_offset = tile_x * max_tile_size;
+    let all_x_max = cmp::min(tile_x_offset + max_tile_size, xsize);
+    let all_y_max = cmp::min(tile_y_offset + max_tile_size, ysize);
+    let tile_width = all_x_max - tile_x_offset;
+    let tile_height = all_y_max - tile_y_offset;
+    let tile_argb = argb.offset((tile_y_offset * xsize + tile_x_offset) as isize);
+    let mut best_tx = VP8LMultipliers::default();
+    MultipliersClear(&mut best_tx);
+
+    GetBestGreenToRed(
+        tile_argb,
+        xsize,
+        tile_width,
+        tile_height,
+        prev_x,
+        prev_y,
+        quality,
+        accumulated_red_histo,
+        &mut best_tx,
+    );
+    GetBestGreenRedToBlue(
+        tile_argb,
+        xsize,
+        tile_width,
+        tile_height,
+        prev_x,
+        prev_y,
+        quality,
+        accumulated_blue_histo,
+        &mut best_tx,
+    );
+    best_tx
+}
+
+#[allow(non_snake_case)]
+unsafe fn CopyTileWithColorTransform(
+    xsize: c_int,
+    ysize: c_int,
+    tile_x: c_int,
+    tile_y: c_int,
+    max_tile_size: c_int,
+    color_transform: VP8LMultipliers,
+    mut argb: *mut u32,
+) {
+    let xscan = cmp::min(max_tile_size, xsize - tile_x);
+    let yscan = cmp::min(max_tile_size, ysize - tile_y);
+    argb = argb.offset((tile_y * xsize + tile_x) as isize);
+    for _ in 0..yscan {
+        VP8LTransformColor.unwrap()(&color_transform, argb, xscan);
+        argb = argb.offset(xsize as isize);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LColorSpaceTransform(
+    width: c_int,
+    height: c_int,
+    bits: c_int,
+    quality: c_int,
+    argb: *mut u32,
+    image: *mut u32,
+) {
+    let max_tile_size = 1 << bits;
+    let tile_xsize = VP8LSubSampleSize(width as u32, bits as u32) as c_int;
+    let tile_ysize = VP8LSubSampleSize(height as u32, bits as u32) as c_int;
+    let mut accumulated_red_histo = [0 as c_int; 256];
+    let mut accumulated_blue_histo = [0 as c_int; 256];
+    let mut prev_x = VP8LMultipliers::default();
+    let mut prev_y = VP8LMultipliers::default();
+    MultipliersClear(&mut prev_y);
+    MultipliersClear(&mut prev_x);
+    for tile_y in 0..tile_ysize {
+        for tile_x in 0..tile_xsize {
+            let tile_x_offset = tile_x * max_tile_size;
+            let tile_y_offset = tile_y * max_tile_size;
+            let all_x_max = cmp::min(tile_x_offset + max_tile_size, width);
+            let all_y_max = cmp::min(tile_y_offset + max_tile_size, height);
+            let offset = (tile_y * tile_xsize + tile_x) as isize;
+            if tile_y != 0 {
+                ColorCodeToMultipliers(*image.offset(offset - tile_xsize as isize), &mut prev_y);
+            }
+            prev_x = GetBestColorTransformForTile(
+                tile_x,
+                tile_y,
+                bits,
+                prev_x,
+                prev_y,
+                quality,
+                width,
+                height,
+                &accumulated_red_histo,
+                &accumulated_blue_histo,
+                argb,
+            );
+            *image.offset(offset) = MultipliersToColorCode(&prev_x);
+            CopyTileWithColorTransform(
+                width,
+                height,
+                tile_x_offset,
+                tile_y_offset,
+                max_tile_size,
+                prev_x,
+                argb,
+            );
+
+            // Gather accumulated histogram data.
+            for y in tile_y_offset..all_y_max {
+                let ix_start = y * width + tile_x_offset;
+                let ix_end = ix_start + all_x_max - tile_x_offset;
+                for ix in ix_start..ix_end {
+                    let at = |i: c_int| *argb.offset(i as isize);
+                    let pix = at(ix);
+                    if ix >= 2 && pix == at(ix - 2) && pix == at(ix - 1) {
+                        continue; // repeated pixels are handled by backward references
+                    }
+                    if ix >= width + 2
+                        && at(ix - 2) == at(ix - width - 2)
+                        && at(ix - 1) == at(ix - width - 1)
+                        && pix == at(ix - width)
+                    {
+                        continue; // repeated pixels are handled by backward references
+                    }
+                    accumulated_red_histo[((pix >> 16) & 0xff) as usize] += 1;
+                    accumulated_blue_histo[((pix >> 0) & 0xff) as usize] += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dsp::lossless_enc::VP8LEncDspInit;
+    use test_util::{argb_images, check_parity, Digest};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LDspInit();
+        fn WebPRef_VP8LResidualImage(
+            width: c_int,
+            height: c_int,
+            bits: c_int,
+            low_effort: c_int,
+            argb: *mut u32,
+            argb_scratch: *mut u32,
+            image: *mut u32,
+            near_lossless_quality: c_int,
+            exact: c_int,
+            used_subtract_green: c_int,
+        );
+        fn WebPRef_VP8LColorSpaceTransform(
+            width: c_int,
+            height: c_int,
+            bits: c_int,
+            quality: c_int,
+            argb: *mut u32,
+            image: *mut u32,
+        );
+    }
+
+    type ResidualImageFunc = unsafe extern "C" fn(
+        c_int,
+        c_int,
+        c_int,
+        c_int,
+        *mut u32,
+        *mut u32,
+        *mut u32,
+        c_int,
+        c_int,
+        c_int,
+    );
+    type ColorSpaceTransformFunc =
+        unsafe extern "C" fn(c_int, c_int, c_int, c_int, *mut u32, *mut u32);
+
+    const PORT: (ResidualImageFunc, ColorSpaceTransformFunc) =
+        (VP8LResidualImage, VP8LColorSpaceTransform);
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: (ResidualImageFunc, ColorSpaceTransformFunc) =
+        (WebPRef_VP8LResidualImage, WebPRef_VP8LColorSpaceTransform);
+
+    fn images() -> Vec<(c_int, c_int, Vec<u32>)> {
+        argb_images(
+            0x9abc,
+            0x3f,
+            &[
+                (1, 1, 0, false),
+                (5, 3, 0, true),
+                (64, 40, 0, false),
+                (97, 61, 0, true),
+                (33, 17, 0, true),
+                (40, 21, 2, true),
+                (50, 31, 16, false),
+            ],
+        )
+    }
+
+    fn init_dsp() {
+        unsafe {
+            VP8LEncDspInit();
+            // The reference predictor_enc.c uses the reference predictors.
+            #[cfg(feature = "c-reference")]
+            WebPRef_VP8LDspInit();
+        }
+    }
+
+    // Returns the predictor image and the residuals.
+    fn residual_image(
+        residual_image: ResidualImageFunc,
+        argb: &[u32],
+        width: c_int,
+        height: c_int,
+        (bits, low_effort, near_lossless_quality, exact, used_subtract_green): (
+            c_int,
+            c_int,
+            c_int,
+            c_int,
+            c_int,
+        ),
+    ) -> (Vec<u32>, Vec<u32>) {
+        let mut argb = argb.to_vec();
+        // Two rows of pixels with an extra one each, and two rows of bytes.
+        let mut argb_scratch = vec![0u32; ((width + 1) * 2 + (width * 2 + 3) / 4) as usize];
+        let mut image = vec![
+            0u32;
+            (VP8LSubSampleSize(width as u32, bits as u32)
+                * VP8LSubSampleSize(height as u32, bits as u32))
+                as usize
+        ];
+        unsafe {
+            residual_image(
+                width,
+                height,
+                bits,
+                low_effort,
+                argb.as_mut_ptr(),
+                argb_scratch.as_mut_ptr(),
+                image.as_mut_ptr(),
+                near_lossless_quality,
+                exact,
+                used_subtract_green,
+            );
+        }
+        (image, argb)
+    }
+
+    // Returns the multipliers image and the transformed pixels.
+    fn color_space_transform(
+        color_space_transform: ColorSpaceTransformFunc,
+        argb: &[u32],
+        width: c_int,
+        height: c_int,
+        bits: c_int,
+        quality: c_int,
+    ) -> (Vec<u32>, Vec<u32>) {
+        let mut argb = argb.to_vec();
+        let mut image = vec![
+            0u32;
+            (VP8LSubSampleSize(width as u32, bits as u32)
+                * VP8LSubSampleSize(height as u32, bits as u32))
+                as usize
+        ];
+        unsafe {
+            color_space_transform(
+                width,
+                height,
+                bits,
+                quality,
+                argb.as_mut_ptr(),
+                image.as_mut_ptr(),
+            );
+        }
+        (image, argb)
+    }
+
+    // Covers the predictor search, the low effort mode, near-lossless
+    // quantization and the handling of transparent pixels.
+    #[test]
+    fn test_residual_image_parity() {
+        init_dsp();
+        let mut digest = Digest::new();
+        let params = [
+            (2, 0, 100, 0, 0),
+            (3, 0, 100, 1, 0),
+            (4, 1, 100, 0, 0),
+            (5, 0, 100, 0, 1),
+            (3, 0, 80, 0, 0),
+            (4, 0, 40, 0, 1),
+            (2, 0, 0, 1, 1),
+        ];
+        for (width, height, argb) in images() {
+            for &param in &params {
+                let (image, residuals) = residual_image(PORT.0, &argb, width, height, param);
+                digest.update_u32(&image);
+                digest.update_u32(&residuals);
+                #[cfg(feature = "c-reference")]
+                assert!(
+                    (image, residuals) == residual_image(REFERENCE.0, &argb, width, height, param),
+                    "{}x{} image, {:?}",
+                    width,
+                    height,
+                    param
+                );
+            }
+        }
+        check_parity("enc::predictor_enc::test_residual_image_parity", digest);
+    }
+
+    #[test]
+    fn test_color_space_transform_parity() {
+        init_dsp();
+        let mut digest = Digest::new();
+        for (width, height, argb) in images() {
+            for &(bits, quality) in &[(2, 0), (3, 25), (4, 75), (5, 100), (2, 90)] {
+                let (image, transformed) =
+                    color_space_transform(PORT.1, &argb, width, height, bits, quality);
+                digest.update_u32(&image);
+                digest.update_u32(&transformed);
+                #[cfg(feature = "c-reference")]
+                assert!(
+                    (image, transformed)
+                        == color_space_transform(REFERENCE.1, &argb, width, height, bits, quality),
+                    "{}x{} image, bits {}, quality {}",
+                    width,
+                    height,
+                    bits,
+                    quality
+                );
+            }
+        }
+        check_parity(
+            "enc::predictor_enc::test_color_space_transform_parity",
+            digest,
+        );
+    }
+}
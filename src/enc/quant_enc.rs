@@ -0,0 +1,1850 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+//   Quantization
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dec::common_dec::*;
+use dsp::dec::VP8TransformWHT;
+use dsp::dsp::BPS;
+use dsp::enc::{
+    VP8Copy16x8, VP8Copy4x4, VP8EncBands, VP8EncPredChroma8, VP8EncPredLuma16, VP8EncPredLuma4,
+    VP8EncQuantize2Blocks, VP8EncQuantizeBlock, VP8EncQuantizeBlockWHT, VP8FTransform,
+    VP8FTransform2, VP8FTransformWHT, VP8ITransform, VP8SSE16x16, VP8SSE16x8, VP8SSE4x4,
+    VP8TDisto16x16, VP8TDisto4x4,
+};
+use enc::cost_enc::{
+    VP8BitCost, VP8FixedCostsI16, VP8FixedCostsI4, VP8FixedCostsUV, VP8GetCostLuma16,
+    VP8GetCostLuma4, VP8GetCostUV, VP8LevelCost,
+};
+use enc::filter_enc::VP8FilterStrengthFromDelta;
+use enc::iterator_enc::{
+    VP8IteratorNzToBytes, VP8IteratorRotateI4, VP8IteratorStartI4, VP8SetIntra16Mode,
+    VP8SetIntra4Mode, VP8SetIntraUVMode, VP8SetSkip,
+};
+use enc::vp8i_enc::*;
+
+const DO_TRELLIS_I4: bool = true;
+const DO_TRELLIS_I16: bool = true; // not a huge gain, but ok at low bitrate.
+const DO_TRELLIS_UV: bool = false; // disable trellis for UV. Risky. Not worth.
+
+const MID_ALPHA: c_int = 64; // neutral value for susceptibility
+const MIN_ALPHA: c_int = 30; // lowest usable value for susceptibility
+const MAX_ALPHA: c_int = 100; // higher meaningful value for susceptibility
+
+const SNS_TO_DQ: c_double = 0.9; // Scaling constant between the sns value and the QP
+                                 // power-law modulation. Must be strictly less than 1.
+
+// number of non-zero coeffs below which we consider the block very flat
+// (and apply a penalty to complex predictions)
+const FLATNESS_LIMIT_I16: score_t = 10; // I16 mode
+const FLATNESS_LIMIT_I4: score_t = 3; // I4 mode
+const FLATNESS_LIMIT_UV: score_t = 2; // UV mode
+const FLATNESS_PENALTY: score_t = 140; // roughly ~1bit per block
+
+#[allow(non_snake_case)]
+fn MULT_8B(a: c_int, b: c_int) -> c_int {
+    (a * b + 128) >> 8
+}
+
+const RD_DISTO_MULT: score_t = 256; // distortion multiplier (equivalent of lambda)
+
+//------------------------------------------------------------------------------
+
+#[inline]
+#[allow(non_snake_case)]
+fn clip(v: c_int, m: c_int, M: c_int) -> c_int {
+    if v < m {
+        m
+    } else if v > M {
+        M
+    } else {
+        v
+    }
+}
+
+#[allow(non_upper_case_globals)]
+static kZigzag: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kDcTable: [u8; 128] = [
+    4,     5,   6,   7,   8,   9,  10,  10,
+    11,   12,  13,  14,  15,  16,  17,  17,
+    18,   19,  20,  20,  21,  21,  22,  22,
+    23,   23,  24,  25,  25,  26,  27,  28,
+    29,   30,  31,  32,  33,  34,  35,  36,
+    37,   37,  38,  39,  40,  41,  42,  43,
+    44,   45,  46,  46,  47,  48,  49,  50,
+    51,   52,  53,  54,  55,  56,  57,  58,
+    59,   60,  61,  62,  63,  64,  65,  66,
+    67,   68,  69,  70,  71,  72,  73,  74,
+    75,   76,  76,  77,  78,  79,  80,  81,
+    82,   83,  84,  85,  86,  87,  88,  89,
+    91,   93,  95,  96,  98, 100, 101, 102,
+    104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136,
+    138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kAcTable: [u16; 128] = [
+    4,     5,   6,   7,   8,   9,  10,  11,
+    12,   13,  14,  15,  16,  17,  18,  19,
+    20,   21,  22,  23,  24,  25,  26,  27,
+    28,   29,  30,  31,  32,  33,  34,  35,
+    36,   37,  38,  39,  40,  41,  42,  43,
+    44,   45,  46,  47,  48,  49,  50,  51,
+    52,   53,  54,  55,  56,  57,  58,  60,
+    62,   64,  66,  68,  70,  72,  74,  76,
+    78,   80,  82,  84,  86,  88,  90,  92,
+    94,   96,  98, 100, 102, 104, 106, 108,
+    110, 112, 114, 116, 119, 122, 125, 128,
+    131, 134, 137, 140, 143, 146, 149, 152,
+    155, 158, 161, 164, 167, 170, 173, 177,
+    181, 185, 189, 193, 197, 201, 205, 209,
+    213, 217, 221, 225, 229, 234, 239, 245,
+    249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kAcTable2: [u16; 128] = [
+    8,     8,   9,  10,  12,  13,  15,  17,
+    18,   20,  21,  23,  24,  26,  27,  29,
+    31,   32,  34,  35,  37,  38,  40,  41,
+    43,   44,  46,  48,  49,  51,  52,  54,
+    55,   57,  58,  60,  62,  63,  65,  66,
+    68,   69,  71,  72,  74,  75,  77,  79,
+    80,   82,  83,  85,  86,  88,  89,  93,
+    96,   99, 102, 105, 108, 111, 114, 117,
+    120, 124, 127, 130, 133, 136, 139, 142,
+    145, 148, 151, 155, 158, 161, 164, 167,
+    170, 173, 176, 179, 184, 189, 193, 198,
+    203, 207, 212, 217, 221, 226, 230, 235,
+    240, 244, 249, 254, 258, 263, 268, 274,
+    280, 286, 292, 299, 305, 311, 317, 323,
+    330, 336, 342, 348, 354, 362, 370, 379,
+    385, 393, 401, 409, 416, 424, 432, 440,
+];
+
+#[allow(non_upper_case_globals)]
+static kBiasMatrices: [[u8; 2]; 3] = [
+    // [luma-ac,luma-dc,chroma][dc,ac]
+    [96, 110],
+    [96, 108],
+    [110, 115],
+];
+
+// Sharpening by (slightly) raising the hi-frequency coeffs.
+// Hack-ish but helpful for mid-bitrate range. Use with care.
+const SHARPEN_BITS: c_int = 11; // number of descaling bits for sharpening bias
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kFreqSharpening: [u8; 16] = [
+    0,  30, 60, 90,
+    30, 60, 90, 90,
+    60, 90, 90, 90,
+    90, 90, 90, 90,
+];
+
+//------------------------------------------------------------------------------
+// Initialize quantization parameters in VP8Matrix
+
+// Returns the average quantizer
+#[allow(non_snake_case)]
+fn ExpandMatrix(m: &mut VP8Matrix, type_: c_int) -> c_int {
+    for i in 0..2 {
+        let is_ac_coeff = (i > 0) as usize;
+        let bias = kBiasMatrices[type_ as usize][is_ac_coeff] as u32;
+        m.iq_[i] = ((1 << QFIX) / m.q_[i] as c_int) as u16;
+        m.bias_[i] = BIAS(bias);
+        // zthresh_ is the exact value such that QUANTDIV(coeff, iQ, B) is:
+        //   * zero if coeff <= zthresh
+        //   * non-zero if coeff > zthresh
+        m.zthresh_[i] = ((1u32 << QFIX) - 1 - m.bias_[i]) / m.iq_[i] as u32;
+    }
+    for i in 2..16 {
+        m.q_[i] = m.q_[1];
+        m.iq_[i] = m.iq_[1];
+        m.bias_[i] = m.bias_[1];
+        m.zthresh_[i] = m.zthresh_[1];
+    }
+    let mut sum = 0;
+    for i in 0..16 {
+        if type_ == 0 {
+            // we only use sharpening for AC luma coeffs
+            m.sharpen_[i] =
+                ((kFreqSharpening[i] as c_int * m.q_[i] as c_int) >> SHARPEN_BITS) as u16;
+        } else {
+            m.sharpen_[i] = 0;
+        }
+        sum += m.q_[i] as c_int;
+    }
+    (sum + 8) >> 4
+}
+
+#[allow(non_snake_case)]
+fn CheckLambdaValue(v: &mut c_int) {
+    if *v < 1 {
+        *v = 1;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn SetupMatrices(enc: *mut VP8Encoder) {
+    let tlambda_scale = if (*enc).method_ >= 4 {
+        (*(*enc).config_).sns_strength
+    } else {
+        0
+    };
+    let num_segments = (*enc).segment_hdr_.num_segments_;
+    for i in 0..num_segments as usize {
+        let m = &mut (*enc).dqm_[i];
+        let q = m.quant_;
+        m.y1_.q_[0] = kDcTable[clip(q + (*enc).dq_y1_dc_, 0, 127) as usize] as u16;
+        m.y1_.q_[1] = kAcTable[clip(q, 0, 127) as usize];
+
+        m.y2_.q_[0] = kDcTable[clip(q + (*enc).dq_y2_dc_, 0, 127) as usize] as u16 * 2;
+        m.y2_.q_[1] = kAcTable2[clip(q + (*enc).dq_y2_ac_, 0, 127) as usize];
+
+        m.uv_.q_[0] = kDcTable[clip(q + (*enc).dq_uv_dc_, 0, 117) as usize] as u16;
+        m.uv_.q_[1] = kAcTable[clip(q + (*enc).dq_uv_ac_, 0, 127) as usize];
+
+        let q_i4 = ExpandMatrix(&mut m.y1_, 0);
+        let q_i16 = ExpandMatrix(&mut m.y2_, 1);
+        let q_uv = ExpandMatrix(&mut m.uv_, 2);
+
+        m.lambda_i4_ = (3 * q_i4 * q_i4) >> 7;
+        m.lambda_i16_ = 3 * q_i16 * q_i16;
+        m.lambda_uv_ = (3 * q_uv * q_uv) >> 6;
+        m.lambda_mode_ = (1 * q_i4 * q_i4) >> 7;
+        m.lambda_trellis_i4_ = (7 * q_i4 * q_i4) >> 3;
+        m.lambda_trellis_i16_ = (q_i16 * q_i16) >> 2;
+        m.lambda_trellis_uv_ = (q_uv * q_uv) << 1;
+        m.tlambda_ = (tlambda_scale * q_i4) >> 5;
+
+        // none of these constants should be < 1
+        CheckLambdaValue(&mut m.lambda_i4_);
+        CheckLambdaValue(&mut m.lambda_i16_);
+        CheckLambdaValue(&mut m.lambda_uv_);
+        CheckLambdaValue(&mut m.lambda_mode_);
+        CheckLambdaValue(&mut m.lambda_trellis_i4_);
+        CheckLambdaValue(&mut m.lambda_trellis_i16_);
+        CheckLambdaValue(&mut m.lambda_trellis_uv_);
+        CheckLambdaValue(&mut m.tlambda_);
+
+        m.min_disto_ = 20 * m.y1_.q_[0] as c_int; // quantization-aware min disto
+        m.max_edge_ = 0;
+
+        m.i4_penalty_ = (1000 * q_i4 * q_i4) as score_t;
+    }
+}
+
+//------------------------------------------------------------------------------
+// Initialize filtering parameters
+
+// Very small filter-strength values have close to no visual effect. So we can
+// save a little decoding-CPU by turning filtering off for these.
+const FSTRENGTH_CUTOFF: c_int = 2;
+
+#[allow(non_snake_case)]
+unsafe fn SetupFilterStrength(enc: *mut VP8Encoder) {
+    // level0 is in [0..500]. Using '-f 50' as filter_strength is mid-filtering.
+    let level0 = 5 * (*(*enc).config_).filter_strength;
+    for i in 0..NUM_MB_SEGMENTS as usize {
+        let m = &mut (*enc).dqm_[i];
+        // We focus on the quantization of AC coeffs.
+        let qstep = kAcTable[clip(m.quant_, 0, 127) as usize] as c_int >> 2;
+        let base_strength = VP8FilterStrengthFromDelta((*enc).filter_hdr_.sharpness_, qstep);
+        // Segments with lower complexity ('beta') will be less filtered.
+        let f = base_strength * level0 / (256 + m.beta_);
+        m.fstrength_ = if f < FSTRENGTH_CUTOFF {
+            0
+        } else if f > 63 {
+            63
+        } else {
+            f
+        };
+    }
+    // We record the initial strength (mainly for the case of 1-segment only).
+    (*enc).filter_hdr_.level_ = (*enc).dqm_[0].fstrength_;
+    (*enc).filter_hdr_.simple_ = ((*(*enc).config_).filter_type == 0) as c_int;
+    (*enc).filter_hdr_.sharpness_ = (*(*enc).config_).filter_sharpness;
+}
+
+//------------------------------------------------------------------------------
+
+// Note: if you change the values below, remember that the max range
+// allowed by the syntax for DQ_UV is [-16,16].
+const MAX_DQ_UV: c_int = 6;
+const MIN_DQ_UV: c_int = -4;
+
+// We want to emulate jpeg-like behaviour where the expected "good" quality
+// is around q=75. Internally, our "good" middle is around c=50. So we
+// map accordingly using linear piece-wise function
+#[allow(non_snake_case)]
+fn QualityToCompression(c: c_double) -> c_double {
+    let linear_c = if c < 0.75 { c * (2. / 3.) } else { 2. * c - 1. };
+    // The file size roughly scales as pow(quantizer, 3.). Actually, the
+    // exponent is somewhere between 2.8 and 3.2, but we're mostly interested
+    // in the mid-quant range. So we scale the compressibility inversely to
+    // this power-law: quant ~= compression ^ 1/3. This law holds well for
+    // low quant. Finer modeling for high-quant would make use of kAcTable[]
+    // more explicitly.
+    linear_c.powf(1. / 3.)
+}
+
+#[allow(non_snake_case)]
+fn QualityToJPEGCompression(c: c_double, alpha: c_double) -> c_double {
+    // We map the complexity 'alpha' and quality setting 'c' to a compression
+    // exponent empirically matched to the compression curve of libjpeg6b.
+    // On average, the WebP output size will be roughly similar to that of a
+    // JPEG file compressed with same quality factor.
+    let amin = 0.30;
+    let amax = 0.85;
+    let exp_min = 0.4;
+    let exp_max = 0.9;
+    let slope = (exp_min - exp_max) / (amax - amin);
+    // Linearly interpolate 'expn' from exp_min to exp_max
+    // in the [amin, amax] range.
+    let expn = if alpha > amax {
+        exp_min
+    } else if alpha < amin {
+        exp_max
+    } else {
+        exp_max + slope * (alpha - amin)
+    };
+    c.powf(expn)
+}
+
+#[allow(non_snake_case)]
+fn SegmentsAreEquivalent(S1: &VP8SegmentInfo, S2: &VP8SegmentInfo) -> bool {
+    (S1.quant_ == S2.quant_) && (S1.fstrength_ == S2.fstrength_)
+}
+
+#[allow(non_snake_case)]
+unsafe fn SimplifySegments(enc: *mut VP8Encoder) {
+    let mut map = [0, 1, 2, 3];
+    // 'num_segments_' is previously validated and <= NUM_MB_SEGMENTS, but an
+    // explicit check is needed to avoid a spurious warning about 'i' exceeding
+    // array bounds of 'dqm_' with some compilers (noticed with gcc-4.9).
+    let num_segments = if (*enc).segment_hdr_.num_segments_ < NUM_MB_SEGMENTS {
+        (*enc).segment_hdr_.num_segments_
+    } else {
+        NUM_MB_SEGMENTS
+    } as usize;
+    let mut num_final_segments = 1;
+    for s1 in 1..num_segments {
+        // find similar segments
+        let mut found = false;
+        // check if we already have similar segment
+        let mut s2 = 0;
+        while s2 < num_final_segments {
+            if SegmentsAreEquivalent(&(*enc).dqm_[s1], &(*enc).dqm_[s2]) {
+                found = true;
+                break;
+            }
+            s2 += 1;
+        }
+        map[s1] = s2;
+        if !found {
+            if num_final_segments != s1 {
+                (*enc).dqm_[num_final_segments] = (*enc).dqm_[s1];
+            }
+            num_final_segments += 1;
+        }
+    }
+    if num_final_segments < num_segments {
+        // Remap
+        let mut i = ((*enc).mb_w_ * (*enc).mb_h_) as isize;
+        while i > 0 {
+            i -= 1;
+            let mb = &mut *(*enc).mb_info_.offset(i);
+            mb.segment_ = map[mb.segment_ as usize] as u8;
+        }
+        (*enc).segment_hdr_.num_segments_ = num_final_segments as c_int;
+        // Replicate the trailing segment infos (it's mostly cosmetics)
+        for i in num_final_segments..num_segments {
+            (*enc).dqm_[i] = (*enc).dqm_[num_final_segments - 1];
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub unsafe extern "C" fn VP8SetSegmentParams(enc: *mut VP8Encoder, quality: c_float) {
+    let num_segments = (*enc).segment_hdr_.num_segments_;
+    let amp = SNS_TO_DQ * (*(*enc).config_).sns_strength as c_double / 100. / 128.;
+    let Q = quality as c_double / 100.;
+    let c_base = if (*(*enc).config_).emulate_jpeg_size != 0 {
+        QualityToJPEGCompression(Q, (*enc).alpha_ as c_double / 255.)
+    } else {
+        QualityToCompression(Q)
+    };
+    for i in 0..num_segments as usize {
+        // We modulate the base coefficient to accommodate for the quantization
+        // susceptibility and allow denser segments to be quantized more.
+        let expn = 1. - amp * (*enc).dqm_[i].alpha_ as c_double;
+        let c = c_base.powf(expn);
+        let q = (127. * (1. - c)) as c_int;
+        c_assert!(expn > 0.);
+        (*enc).dqm_[i].quant_ = clip(q, 0, 127);
+    }
+
+    // purely indicative in the bitstream (except for the 1-segment case)
+    (*enc).base_quant_ = (*enc).dqm_[0].quant_;
+
+    // fill-in values for the unused segments (required by the syntax)
+    for i in num_segments as usize..NUM_MB_SEGMENTS as usize {
+        (*enc).dqm_[i].quant_ = (*enc).base_quant_;
+    }
+
+    // uv_alpha_ is normally spread around ~60. The useful range is
+    // typically ~30 (quite bad) to ~100 (ok to decimate UV more).
+    // We map it to the safe maximal range of MAX/MIN_DQ_UV for dq_uv.
+    let mut dq_uv_ac =
+        ((*enc).uv_alpha_ - MID_ALPHA) * (MAX_DQ_UV - MIN_DQ_UV) / (MAX_ALPHA - MIN_ALPHA);
+    // we rescale by the user-defined strength of adaptation
+    dq_uv_ac = dq_uv_ac * (*(*enc).config_).sns_strength / 100;
+    // and make it safe.
+    dq_uv_ac = clip(dq_uv_ac, MIN_DQ_UV, MAX_DQ_UV);
+    // We also boost the dc-uv-quant a little, based on sns-strength, since
+    // U/V channels are quite more reactive to high quants (flat DC-blocks
+    // tend to appear, and are unpleasant).
+    let mut dq_uv_dc = -4 * (*(*enc).config_).sns_strength / 100;
+    dq_uv_dc = clip(dq_uv_dc, -15, 15); // 4bit-signed max allowed
+
+    (*enc).dq_y1_dc_ = 0; // TODO(skal): dq-lum
+    (*enc).dq_y2_dc_ = 0;
+    (*enc).dq_y2_ac_ = 0;
+    (*enc).dq_uv_dc_ = dq_uv_dc;
+    (*enc).dq_uv_ac_ = dq_uv_ac;
+
+    SetupFilterStrength(enc); // initialize segments' filtering, eventually
+
+    if num_segments > 1 {
+        SimplifySegments(enc);
+    }
+
+    SetupMatrices(enc); // finalize quantization matrices
+}
+
+//------------------------------------------------------------------------------
+// Form the predictions in cache
+
+// Must be ordered using {DC_PRED, TM_PRED, V_PRED, H_PRED} as index
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8I16ModeOffsets: [u16; 4] = [
+    I16DC16 as u16,
+    I16TM16 as u16,
+    I16VE16 as u16,
+    I16HE16 as u16,
+];
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8UVModeOffsets: [u16; 4] = [C8DC8 as u16, C8TM8 as u16, C8VE8 as u16, C8HE8 as u16];
+
+// Must be indexed using {B_DC_PRED -> B_HU_PRED} as index
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8I4ModeOffsets: [u16; NUM_BMODES as usize] = [
+    I4DC4 as u16,
+    I4TM4 as u16,
+    I4VE4 as u16,
+    I4HE4 as u16,
+    I4RD4 as u16,
+    I4VR4 as u16,
+    I4LD4 as u16,
+    I4VL4 as u16,
+    I4HD4 as u16,
+    I4HU4 as u16,
+];
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8MakeLuma16Preds(it: *const VP8EncIterator) {
+    let left = if (*it).x_ != 0 {
+        (*it).y_left_ as *const u8
+    } else {
+        ptr::null()
+    };
+    let top = if (*it).y_ != 0 {
+        (*it).y_top_ as *const u8
+    } else {
+        ptr::null()
+    };
+    VP8EncPredLuma16.unwrap()((*it).yuv_p_, left, top);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8MakeChroma8Preds(it: *const VP8EncIterator) {
+    let left = if (*it).x_ != 0 {
+        (*it).u_left_ as *const u8
+    } else {
+        ptr::null()
+    };
+    let top = if (*it).y_ != 0 {
+        (*it).uv_top_ as *const u8
+    } else {
+        ptr::null()
+    };
+    VP8EncPredChroma8.unwrap()((*it).yuv_p_, left, top);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8MakeIntra4Preds(it: *const VP8EncIterator) {
+    VP8EncPredLuma4.unwrap()((*it).yuv_p_, (*it).i4_top_);
+}
+
+//------------------------------------------------------------------------------
+// Quantize
+
+// Layout:
+// +----+----+
+// |YYYY|UUVV| 0
+// |YYYY|UUVV| 4
+// |YYYY|....| 8
+// |YYYY|....| 12
+// +----+----+
+
+#[rustfmt::skip]
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static VP8Scan: [u16; 16] = [  // Luma
+    0 +  0 * BPS as u16,  4 +  0 * BPS as u16, 8 +  0 * BPS as u16, 12 +  0 * BPS as u16,
+    0 +  4 * BPS as u16,  4 +  4 * BPS as u16, 8 +  4 * BPS as u16, 12 +  4 * BPS as u16,
+    0 +  8 * BPS as u16,  4 +  8 * BPS as u16, 8 +  8 * BPS as u16, 12 +  8 * BPS as u16,
+    0 + 12 * BPS as u16,  4 + 12 * BPS as u16, 8 + 12 * BPS as u16, 12 + 12 * BPS as u16,
+];
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static VP8ScanUV: [u16; 4 + 4] = [
+    0 + 0 * BPS as u16,   4 + 0 * BPS as u16, 0 + 4 * BPS as u16,  4 + 4 * BPS as u16,    // U
+    8 + 0 * BPS as u16,  12 + 0 * BPS as u16, 8 + 4 * BPS as u16, 12 + 4 * BPS as u16,    // V
+];
+
+//------------------------------------------------------------------------------
+// Distortion measurement
+
+#[allow(non_upper_case_globals)]
+static kWeightY: [u16; 16] = [38, 32, 20, 9, 32, 28, 17, 7, 20, 17, 10, 4, 9, 7, 4, 2];
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kWeightTrellis: [u16; 16] = [
+    30, 27, 19, 11,
+    27, 24, 17, 10,
+    19, 17, 12,  8,
+    11, 10,  8,  6,
+];
+
+// Init/Copy the common fields in score.
+#[allow(non_snake_case)]
+fn InitScore(rd: &mut VP8ModeScore) {
+    rd.D = 0;
+    rd.SD = 0;
+    rd.R = 0;
+    rd.H = 0;
+    rd.nz = 0;
+    rd.score = MAX_COST;
+}
+
+#[allow(non_snake_case)]
+fn CopyScore(dst: &mut VP8ModeScore, src: &VP8ModeScore) {
+    dst.D = src.D;
+    dst.SD = src.SD;
+    dst.R = src.R;
+    dst.H = src.H;
+    dst.nz = src.nz; // note that nz is not accumulated, but just copied.
+    dst.score = src.score;
+}
+
+#[allow(non_snake_case)]
+fn AddScore(dst: &mut VP8ModeScore, src: &VP8ModeScore) {
+    dst.D += src.D;
+    dst.SD += src.SD;
+    dst.R += src.R;
+    dst.H += src.H;
+    dst.nz |= src.nz; // here, new nz bits are accumulated.
+    dst.score += src.score;
+}
+
+//------------------------------------------------------------------------------
+// Performs trellis-optimized quantization.
+
+// Trellis node
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Node {
+    prev: i8,   // best previous node
+    sign: i8,   // sign of coeff_i
+    level: i16, // level
+}
+
+// Score state
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct ScoreState {
+    score: score_t,    // partial RD score
+    costs: *const u16, // shortcut to cost tables
+}
+
+// If a coefficient was quantized to a value Q (using a neutral bias),
+// we test all alternate possibilities between [Q-MIN_DELTA, Q+MAX_DELTA]
+// We don't test negative values though.
+const MIN_DELTA: c_int = 0; // how much lower level to try
+const MAX_DELTA: c_int = 1; // how much higher
+const NUM_NODES: usize = (MIN_DELTA + 1 + MAX_DELTA) as usize;
+
+#[inline]
+#[allow(non_snake_case)]
+fn SetRDScore(lambda: c_int, rd: &mut VP8ModeScore) {
+    rd.score = (rd.R + rd.H) * lambda as score_t + RD_DISTO_MULT * (rd.D + rd.SD);
+}
+
+#[inline]
+#[allow(non_snake_case)]
+fn RDScoreTrellis(lambda: c_int, rate: score_t, distortion: score_t) -> score_t {
+    rate * lambda as score_t + RD_DISTO_MULT * distortion
+}
+
+#[allow(non_snake_case)]
+unsafe fn TrellisQuantizeBlock(
+    enc: *const VP8Encoder,
+    in_: *mut i16,
+    out: *mut i16,
+    ctx0: c_int,
+    coeff_type: c_int,
+    mtx: *const VP8Matrix,
+    lambda: c_int,
+) -> c_int {
+    let probas = &(*enc).proba_.coeffs_[coeff_type as usize];
+    let costs = (*enc).proba_.remapped_costs_[coeff_type as usize].as_ptr() as CostArrayPtr;
+    let first = if coeff_type == 0 { 1 } else { 0 };
+    let mut nodes: [[Node; NUM_NODES]; 16] = mem::zeroed();
+    let mut score_states: [[ScoreState; NUM_NODES]; 2] = mem::zeroed();
+    let score_states_ptr = score_states.as_mut_ptr() as *mut ScoreState;
+    let mut ss_cur = score_states_ptr.offset(MIN_DELTA as isize);
+    let mut ss_prev = score_states_ptr.offset((NUM_NODES as c_int + MIN_DELTA) as isize);
+    let mut best_path = [-1, -1, -1]; // store best-last/best-level/best-previous
+    let mut best_score;
+    let mut last;
+
+    {
+        let thresh = (*mtx).q_[1] as c_int * (*mtx).q_[1] as c_int / 4;
+        let last_proba = probas[VP8EncBands[first as usize] as usize][ctx0 as usize][0];
+
+        // compute the position of the last interesting coefficient
+        last = first - 1;
+        let mut n = 15;
+        while n >= first {
+            let j = kZigzag[n as usize] as isize;
+            let err = *in_.offset(j) as c_int * *in_.offset(j) as c_int;
+            if err > thresh {
+                last = n;
+                break;
+            }
+            n -= 1;
+        }
+        // we don't need to go inspect up to n = 16 coeffs. We can just go up
+        // to last + 1 (inclusive) without losing much.
+        if last < 15 {
+            last += 1;
+        }
+
+        // compute 'skip' score. This is the max score one can do.
+        let cost = VP8BitCost(0, last_proba) as score_t;
+        best_score = RDScoreTrellis(lambda, cost, 0);
+
+        // initialize source node.
+        for m in -MIN_DELTA..=MAX_DELTA {
+            let rate = if ctx0 == 0 {
+                VP8BitCost(1, last_proba) as score_t
+            } else {
+                0
+            };
+            let ss = &mut *ss_cur.offset(m as isize);
+            ss.score = RDScoreTrellis(lambda, rate, 0);
+            ss.costs = (*costs.offset(first as isize))[ctx0 as usize];
+        }
+    }
+
+    // traverse trellis.
+    for n in first..=last {
+        let j = kZigzag[n as usize] as usize;
+        let Q = (*mtx).q_[j] as u32;
+        let iQ = (*mtx).iq_[j] as u32;
+        let B = BIAS(0x00); // neutral bias
+                            // note: it's important to take sign of the _original_ coeff,
+                            // so we don't have to consider level < 0 afterward.
+        let in_j = *in_.offset(j as isize) as c_int;
+        let sign = (in_j < 0) as i8;
+        let coeff0 = ((if sign != 0 { -in_j } else { in_j }) + (*mtx).sharpen_[j] as c_int) as u32;
+        let mut level0 = QUANTDIV(coeff0, iQ, B);
+        let mut thresh_level = QUANTDIV(coeff0, iQ, BIAS(0x80));
+        if thresh_level > MAX_LEVEL {
+            thresh_level = MAX_LEVEL;
+        }
+        if level0 > MAX_LEVEL {
+            level0 = MAX_LEVEL;
+        }
+
+        // Swap current and previous score states
+        mem::swap(&mut ss_cur, &mut ss_prev);
+
+        // test all alternate level values around level0.
+        for m in -MIN_DELTA..=MAX_DELTA {
+            let cur = &mut nodes[n as usize][(m + MIN_DELTA) as usize];
+            let level = level0 + m;
+            let ctx = if level > 2 { 2 } else { level };
+            let band = VP8EncBands[(n + 1) as usize] as usize;
+            let mut best_cur_score = MAX_COST;
+            let mut best_prev = 0; // default, in case
+
+            let ss = &mut *ss_cur.offset(m as isize);
+            ss.score = MAX_COST;
+            ss.costs = (*costs.offset((n + 1) as isize))[ctx as usize];
+            if level < 0 || level > thresh_level {
+                // Node is dead.
+                continue;
+            }
+
+            let base_score = {
+                // Compute delta_error = how much coding this level will
+                // subtract to max_error as distortion.
+                // Here, distortion = sum of (|coeff_i| - level_i * Q_i)^2
+                let new_error = coeff0.wrapping_sub((level as u32).wrapping_mul(Q)) as c_int;
+                let delta_error = (kWeightTrellis[j] as u32).wrapping_mul(
+                    (new_error.wrapping_mul(new_error) as u32)
+                        .wrapping_sub(coeff0.wrapping_mul(coeff0)),
+                ) as c_int;
+                RDScoreTrellis(lambda, 0, delta_error as score_t)
+            };
+
+            // Inspect all possible non-dead predecessors. Retain only the best one.
+            for p in -MIN_DELTA..=MAX_DELTA {
+                let ss_p = &*ss_prev.offset(p as isize);
+                // Dead nodes (with ss_prev[p].score >= MAX_COST) are automatically
+                // eliminated since their score can't be better than the current best.
+                let cost = VP8LevelCost(ss_p.costs, level) as score_t;
+                // Examine node assuming it's a non-terminal one.
+                let score = base_score + ss_p.score + RDScoreTrellis(lambda, cost, 0);
+                if score < best_cur_score {
+                    best_cur_score = score;
+                    best_prev = p;
+                }
+            }
+            // Store best finding in current node.
+            cur.sign = sign;
+            cur.level = level as i16;
+            cur.prev = best_prev as i8;
+            ss.score = best_cur_score;
+
+            // Now, record best terminal node (and thus best entry in the graph).
+            if level != 0 {
+                let last_pos_cost = if n < 15 {
+                    VP8BitCost(0, probas[band][ctx as usize][0]) as score_t
+                } else {
+                    0
+                };
+                let last_pos_score = RDScoreTrellis(lambda, last_pos_cost, 0);
+                let score = best_cur_score + last_pos_score;
+                if score < best_score {
+                    best_score = score;
+                    best_path[0] = n; // best eob position
+                    best_path[1] = m; // best node index
+                    best_path[2] = best_prev; // best predecessor
+                }
+            }
+        }
+    }
+
+    // Fresh start
+    ptr::write_bytes(in_.offset(first as isize), 0, (16 - first) as usize);
+    ptr::write_bytes(out.offset(first as isize), 0, (16 - first) as usize);
+    if best_path[0] == -1 {
+        return 0; // skip!
+    }
+
+    {
+        // Unwind the best path.
+        // Note: best-prev on terminal node is not necessarily equal to the
+        // best_prev for non-terminal. So we patch best_path[2] in.
+        let mut nz = 0;
+        let mut best_node = best_path[1];
+        let mut n = best_path[0];
+        nodes[n as usize][(best_node + MIN_DELTA) as usize].prev = best_path[2] as i8; // force best-prev for terminal
+
+        while n >= first {
+            let node = &nodes[n as usize][(best_node + MIN_DELTA) as usize];
+            let j = kZigzag[n as usize] as usize;
+            *out.offset(n as isize) = if node.sign != 0 {
+                -node.level
+            } else {
+                node.level
+            };
+            nz |= node.level as c_int;
+            *in_.offset(j as isize) =
+                (*out.offset(n as isize) as c_int * (*mtx).q_[j] as c_int) as i16;
+            best_node = node.prev as c_int;
+            n -= 1;
+        }
+        (nz != 0) as c_int
+    }
+}
+
+//------------------------------------------------------------------------------
+// Performs: difference, transform, quantize, back-transform, add
+// all at once. Output is the reconstructed block in *yuv_out, and the
+// quantized levels in *levels.
+
+#[allow(non_snake_case)]
+unsafe fn ReconstructIntra16(
+    it: *mut VP8EncIterator,
+    rd: *mut VP8ModeScore,
+    yuv_out: *mut u8,
+    mode: c_int,
+) -> c_int {
+    let enc = (*it).enc_;
+    let ref_ = (*it)
+        .yuv_p_
+        .offset(VP8I16ModeOffsets[mode as usize] as isize);
+    let src = (*it).yuv_in_.offset(Y_OFF_ENC as isize);
+    let dqm = &(*enc).dqm_[(*(*it).mb_).segment_ as usize];
+    let mut nz = 0;
+    let mut tmp = [[0i16; 16]; 16];
+    let mut dc_tmp = [0i16; 16];
+
+    for n in (0..16).step_by(2) {
+        VP8FTransform2.unwrap()(
+            src.offset(VP8Scan[n] as isize),
+            ref_.offset(VP8Scan[n] as isize),
+            tmp[n..].as_mut_ptr() as *mut i16,
+        );
+    }
+    VP8FTransformWHT.unwrap()(tmp.as_ptr() as *const i16, dc_tmp.as_mut_ptr());
+    nz |= VP8EncQuantizeBlockWHT.unwrap()(
+        dc_tmp.as_mut_ptr(),
+        (*rd).y_dc_levels.as_mut_ptr(),
+        &dqm.y2_,
+    ) << 24;
+
+    if DO_TRELLIS_I16 && (*it).do_trellis_ != 0 {
+        VP8IteratorNzToBytes(it);
+        let mut n = 0;
+        for y in 0..4 {
+            for x in 0..4 {
+                let ctx = (*it).top_nz_[x] + (*it).left_nz_[y];
+                let non_zero = TrellisQuantizeBlock(
+                    enc,
+                    tmp[n].as_mut_ptr(),
+                    (*rd).y_ac_levels[n].as_mut_ptr(),
+                    ctx,
+                    0,
+                    &dqm.y1_,
+                    dqm.lambda_trellis_i16_,
+                );
+                (*it).top_nz_[x] = non_zero;
+                (*it).left_nz_[y] = non_zero;
+                (*rd).y_ac_levels[n][0] = 0;
+                nz |= non_zero << n;
+                n += 1;
+            }
+        }
+    } else {
+        for n in (0..16).step_by(2) {
+            // Zero-out the first coeff, so that: a) nz is correct below, and
+            // b) finding 'last' non-zero coeffs in SetResidualCoeffs() is simplified.
+            tmp[n][0] = 0;
+            tmp[n + 1][0] = 0;
+            nz |= VP8EncQuantize2Blocks.unwrap()(
+                tmp[n..].as_mut_ptr() as *mut i16,
+                (*rd).y_ac_levels.as_mut_ptr().add(n) as *mut i16,
+                &dqm.y1_,
+            ) << n;
+            c_assert!((*rd).y_ac_levels[n + 0][0] == 0);
+            c_assert!((*rd).y_ac_levels[n + 1][0] == 0);
+        }
+    }
+
+    // Transform back
+    VP8TransformWHT.unwrap()(dc_tmp.as_ptr(), tmp.as_mut_ptr() as *mut i16);
+    for n in (0..16).step_by(2) {
+        VP8ITransform.unwrap()(
+            ref_.offset(VP8Scan[n] as isize),
+            tmp[n..].as_ptr() as *const i16,
+            yuv_out.offset(VP8Scan[n] as isize),
+            1,
+        );
+    }
+
+    nz
+}
+
+#[allow(non_snake_case)]
+unsafe fn ReconstructIntra4(
+    it: *mut VP8EncIterator,
+    levels: *mut i16,
+    src: *const u8,
+    yuv_out: *mut u8,
+    mode: c_int,
+) -> c_int {
+    let enc = (*it).enc_;
+    let ref_ = (*it)
+        .yuv_p_
+        .offset(VP8I4ModeOffsets[mode as usize] as isize);
+    let dqm = &(*enc).dqm_[(*(*it).mb_).segment_ as usize];
+    let mut tmp = [0i16; 16];
+
+    VP8FTransform.unwrap()(src, ref_, tmp.as_mut_ptr());
+    let nz = if DO_TRELLIS_I4 && (*it).do_trellis_ != 0 {
+        let x = ((*it).i4_ & 3) as usize;
+        let y = ((*it).i4_ >> 2) as usize;
+        let ctx = (*it).top_nz_[x] + (*it).left_nz_[y];
+        TrellisQuantizeBlock(
+            enc,
+            tmp.as_mut_ptr(),
+            levels,
+            ctx,
+            3,
+            &dqm.y1_,
+            dqm.lambda_trellis_i4_,
+        )
+    } else {
+        VP8EncQuantizeBlock.unwrap()(tmp.as_mut_ptr(), levels, &dqm.y1_)
+    };
+    VP8ITransform.unwrap()(ref_, tmp.as_ptr(), yuv_out, 0);
+    nz
+}
+
+//------------------------------------------------------------------------------
+// DC-error diffusion
+
+// Diffusion weights. We under-correct a bit (15/16th of the error is actually
+// diffused) to avoid 'rainbow' chessboard pattern of blocks at q~=0.
+const C1: c_int = 7; // fraction of error sent to the 4x4 block below
+const C2: c_int = 8; // fraction of error sent to the 4x4 block on the right
+const DSHIFT: c_int = 4;
+const DSCALE: c_int = 1; // storage descaling, needed to make the error fit int8_t
+
+// Quantize as usual, but also compute and return the quantization error.
+// Error is already divided by DSHIFT.
+#[allow(non_snake_case)]
+fn QuantizeSingle(v: &mut i16, mtx: &VP8Matrix) -> c_int {
+    let mut V = *v as c_int;
+    let sign = V < 0;
+    if sign {
+        V = -V;
+    }
+    if V > mtx.zthresh_[0] as c_int {
+        let qV = QUANTDIV(V as u32, mtx.iq_[0] as u32, mtx.bias_[0]) * mtx.q_[0] as c_int;
+        let err = V - qV;
+        *v = if sign { -qV } else { qV } as i16;
+        return (if sign { -err } else { err }) >> DSCALE;
+    }
+    *v = 0;
+    (if sign { -V } else { V }) >> DSCALE
+}
+
+#[allow(non_snake_case)]
+unsafe fn CorrectDCValues(
+    it: *const VP8EncIterator,
+    mtx: &VP8Matrix,
+    tmp: &mut [[i16; 16]; 8],
+    rd: *mut VP8ModeScore,
+) {
+    //         | top[0] | top[1]
+    // --------+--------+---------
+    // left[0] | tmp[0]   tmp[1]  <->   err0 err1
+    // left[1] | tmp[2]   tmp[3]        err2 err3
+    //
+    // Final errors {err1,err2,err3} are preserved and later restored
+    // as top[]/left[] on the next block.
+    for ch in 0..=1 {
+        let top = &(*(*it).top_derr_.offset((*it).x_ as isize))[ch];
+        let left = &(*it).left_derr_[ch];
+        let c = &mut tmp[ch * 4..];
+        c[0][0] = c[0][0].wrapping_add(
+            ((C1 * top[0] as c_int + C2 * left[0] as c_int) >> (DSHIFT - DSCALE)) as i16,
+        );
+        let err0 = QuantizeSingle(&mut c[0][0], mtx);
+        c[1][0] =
+            c[1][0].wrapping_add(((C1 * top[1] as c_int + C2 * err0) >> (DSHIFT - DSCALE)) as i16);
+        let err1 = QuantizeSingle(&mut c[1][0], mtx);
+        c[2][0] =
+            c[2][0].wrapping_add(((C1 * err0 + C2 * left[1] as c_int) >> (DSHIFT - DSCALE)) as i16);
+        let err2 = QuantizeSingle(&mut c[2][0], mtx);
+        c[3][0] = c[3][0].wrapping_add(((C1 * err1 + C2 * err2) >> (DSHIFT - DSCALE)) as i16);
+        let err3 = QuantizeSingle(&mut c[3][0], mtx);
+        // error 'err' is bounded by mtx->q_[0] which is 132 at max. Hence
+        // err >> DSCALE will fit in an int8_t type if DSCALE>=1.
+        c_assert!(err1.abs() <= 127 && err2.abs() <= 127 && err3.abs() <= 127);
+        (*rd).derr[ch][0] = err1 as i8;
+        (*rd).derr[ch][1] = err2 as i8;
+        (*rd).derr[ch][2] = err3 as i8;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreDiffusionErrors(it: *mut VP8EncIterator, rd: *const VP8ModeScore) {
+    for ch in 0..=1 {
+        let top = &mut (*(*it).top_derr_.offset((*it).x_ as isize))[ch];
+        let left = &mut (*it).left_derr_[ch];
+        left[0] = (*rd).derr[ch][0]; // restore err1
+        left[1] = ((3 * (*rd).derr[ch][2] as c_int) >> 2) as i8; //     ... 3/4th of err3
+        top[0] = (*rd).derr[ch][1]; //     ... err2
+        top[1] = ((*rd).derr[ch][2] as c_int - left[1] as c_int) as i8; //     ... 1/4th of err3.
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn ReconstructUV(
+    it: *mut VP8EncIterator,
+    rd: *mut VP8ModeScore,
+    yuv_out: *mut u8,
+    mode: c_int,
+) -> c_int {
+    let enc = (*it).enc_;
+    let ref_ = (*it)
+        .yuv_p_
+        .offset(VP8UVModeOffsets[mode as usize] as isize);
+    let src = (*it).yuv_in_.offset(U_OFF_ENC as isize);
+    let dqm = &(*enc).dqm_[(*(*it).mb_).segment_ as usize];
+    let mut nz = 0;
+    let mut tmp = [[0i16; 16]; 8];
+
+    for n in (0..8).step_by(2) {
+        VP8FTransform2.unwrap()(
+            src.offset(VP8ScanUV[n] as isize),
+            ref_.offset(VP8ScanUV[n] as isize),
+            tmp[n..].as_mut_ptr() as *mut i16,
+        );
+    }
+    if !(*it).top_derr_.is_null() {
+        CorrectDCValues(it, &dqm.uv_, &mut tmp, rd);
+    }
+
+    if DO_TRELLIS_UV && (*it).do_trellis_ != 0 {
+        let mut n = 0;
+        for ch in (0..=2).step_by(2) {
+            for y in 0..2 {
+                for x in 0..2 {
+                    let ctx = (*it).top_nz_[4 + ch + x] + (*it).left_nz_[4 + ch + y];
+                    let non_zero = TrellisQuantizeBlock(
+                        enc,
+                        tmp[n].as_mut_ptr(),
+                        (*rd).uv_levels[n].as_mut_ptr(),
+                        ctx,
+                        2,
+                        &dqm.uv_,
+                        dqm.lambda_trellis_uv_,
+                    );
+                    (*it).top_nz_[4 + ch + x] = non_zero;
+                    (*it).left_nz_[4 + ch + y] = non_zero;
+                    nz |= non_zero << n;
+                    n += 1;
+                }
+            }
+        }
+    } else {
+        for n in (0..8).step_by(2) {
+            nz |= VP8EncQuantize2Blocks.unwrap()(
+                tmp[n..].as_mut_ptr() as *mut i16,
+                (*rd).uv_levels.as_mut_ptr().add(n) as *mut i16,
+                &dqm.uv_,
+            ) << n;
+        }
+    }
+
+    for n in (0..8).step_by(2) {
+        VP8ITransform.unwrap()(
+            ref_.offset(VP8ScanUV[n] as isize),
+            tmp[n..].as_ptr() as *const i16,
+            yuv_out.offset(VP8ScanUV[n] as isize),
+            1,
+        );
+    }
+    nz << 16
+}
+
+//------------------------------------------------------------------------------
+// RD-opt decision. Reconstruct each modes, evalue distortion and bit-cost.
+// Pick the mode is lower RD-cost = Rate + lambda * Distortion.
+
+#[allow(non_snake_case)]
+fn StoreMaxDelta(dqm: &mut VP8SegmentInfo, DCs: &[i16; 16]) {
+    // We look at the first three AC coefficients to determine what is the average
+    // delta between each sub-4x4 block.
+    let v0 = (DCs[1] as c_int).abs();
+    let v1 = (DCs[2] as c_int).abs();
+    let v2 = (DCs[4] as c_int).abs();
+    let mut max_v = if v1 > v0 { v1 } else { v0 };
+    max_v = if v2 > max_v { v2 } else { max_v };
+    if max_v > dqm.max_edge_ {
+        dqm.max_edge_ = max_v;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn SwapOut(it: *mut VP8EncIterator) {
+    mem::swap(&mut (*it).yuv_out_, &mut (*it).yuv_out2_);
+}
+
+#[allow(non_snake_case)]
+unsafe fn IsFlat(mut levels: *const i16, mut num_blocks: c_int, thresh: score_t) -> score_t {
+    let mut score = 0;
+    while num_blocks > 0 {
+        num_blocks -= 1;
+        // TODO(skal): refine positional scoring?
+        for i in 1..16 {
+            // omit DC, we're only interested in AC
+            score += (*levels.offset(i) != 0) as score_t;
+            if score > thresh {
+                return 0;
+            }
+        }
+        levels = levels.offset(16);
+    }
+    1
+}
+
+#[allow(non_snake_case)]
+unsafe fn PickBestIntra16(it: *mut VP8EncIterator, rd: *mut VP8ModeScore) {
+    let kNumBlocks = 16;
+    let dqm: *mut VP8SegmentInfo = &mut (*(*it).enc_).dqm_[(*(*it).mb_).segment_ as usize];
+    let lambda = (*dqm).lambda_i16_;
+    let tlambda = (*dqm).tlambda_;
+    let src = (*it).yuv_in_.offset(Y_OFF_ENC as isize);
+    let mut rd_tmp: VP8ModeScore = mem::zeroed();
+    let mut rd_cur: *mut VP8ModeScore = &mut rd_tmp;
+    let mut rd_best = rd;
+
+    (*rd).mode_i16 = -1;
+    for mode in 0..NUM_PRED_MODES {
+        let tmp_dst = (*it).yuv_out2_.offset(Y_OFF_ENC as isize); // scratch buffer
+        (*rd_cur).mode_i16 = mode;
+
+        // Reconstruct
+        (*rd_cur).nz = ReconstructIntra16(it, rd_cur, tmp_dst, mode) as u32;
+
+        // Measure RD-score
+        (*rd_cur).D = VP8SSE16x16.unwrap()(src, tmp_dst) as score_t;
+        (*rd_cur).SD = if tlambda != 0 {
+            MULT_8B(
+                tlambda,
+                VP8TDisto16x16.unwrap()(src, tmp_dst, kWeightY.as_ptr()),
+            ) as score_t
+        } else {
+            0
+        };
+        (*rd_cur).H = VP8FixedCostsI16[mode as usize] as score_t;
+        (*rd_cur).R = VP8GetCostLuma16(it, rd_cur) as score_t;
+        if mode > 0
+            && IsFlat(
+                (*rd_cur).y_ac_levels.as_ptr() as *const i16,
+                kNumBlocks,
+                FLATNESS_LIMIT_I16,
+            ) != 0
+        {
+            // penalty to avoid flat area to be mispredicted by complex mode
+            (*rd_cur).R += FLATNESS_PENALTY * kNumBlocks as score_t;
+        }
+
+        // Since we always examine Intra16 first, we can overwrite *rd directly.
+        SetRDScore(lambda, &mut *rd_cur);
+        if mode == 0 || (*rd_cur).score < (*rd_best).score {
+            mem::swap(&mut rd_cur, &mut rd_best);
+            SwapOut(it);
+        }
+    }
+    if rd_best != rd {
+        ptr::copy_nonoverlapping(rd_best, rd, 1);
+    }
+    SetRDScore((*dqm).lambda_mode_, &mut *rd); // finalize score for mode decision.
+    VP8SetIntra16Mode(it, (*rd).mode_i16);
+
+    // we have a blocky macroblock (only DCs are non-zero) with fairly high
+    // distortion, record max delta so we can later adjust the minimal filtering
+    // strength needed to smooth these blocks out.
+    if ((*rd).nz & 0x100ffff) == 0x1000000 && (*rd).D > (*dqm).min_disto_ as score_t {
+        StoreMaxDelta(&mut *dqm, &(*rd).y_dc_levels);
+    }
+}
+
+//------------------------------------------------------------------------------
+
+// return the cost array corresponding to the surrounding prediction modes.
+#[allow(non_snake_case)]
+unsafe fn GetCostModeI4(it: *mut VP8EncIterator, modes: &[u8; 16]) -> *const u16 {
+    let preds_w = (*(*it).enc_).preds_w_;
+    let x = (*it).i4_ & 3;
+    let y = (*it).i4_ >> 2;
+    let left = if x == 0 {
+        *(*it).preds_.offset((y * preds_w - 1) as isize)
+    } else {
+        modes[((*it).i4_ - 1) as usize]
+    };
+    let top = if y == 0 {
+        *(*it).preds_.offset((-preds_w + x) as isize)
+    } else {
+        modes[((*it).i4_ - 4) as usize]
+    };
+    VP8FixedCostsI4[top as usize][left as usize].as_ptr()
+}
+
+#[allow(non_snake_case)]
+unsafe fn PickBestIntra4(it: *mut VP8EncIterator, rd: *mut VP8ModeScore) -> c_int {
+    let enc = (*it).enc_;
+    let dqm = &(*enc).dqm_[(*(*it).mb_).segment_ as usize];
+    let lambda = dqm.lambda_i4_;
+    let tlambda = dqm.tlambda_;
+    let src0 = (*it).yuv_in_.offset(Y_OFF_ENC as isize);
+    let best_blocks = (*it).yuv_out2_.offset(Y_OFF_ENC as isize);
+    let mut total_header_bits = 0;
+    let mut rd_best: VP8ModeScore = mem::zeroed();
+
+    if (*enc).max_i4_header_bits_ == 0 {
+        return 0;
+    }
+
+    InitScore(&mut rd_best);
+    rd_best.H = 211; // '211' is the value of VP8BitCost(0, 145)
+    SetRDScore(dqm.lambda_mode_, &mut rd_best);
+    VP8IteratorStartI4(it);
+    loop {
+        let kNumBlocks = 1;
+        let mut rd_i4: VP8ModeScore = mem::zeroed();
+        let mut best_mode = -1;
+        let i4 = (*it).i4_ as usize;
+        let src = src0.offset(VP8Scan[i4] as isize);
+        let mode_costs = GetCostModeI4(it, &(*rd).modes_i4);
+        let mut best_block = best_blocks.offset(VP8Scan[i4] as isize);
+        let mut tmp_dst = (*it).yuv_p_.offset(I4TMP as isize); // scratch buffer.
+
+        InitScore(&mut rd_i4);
+        VP8MakeIntra4Preds(it);
+        for mode in 0..NUM_BMODES {
+            let mut rd_tmp: VP8ModeScore = mem::zeroed();
+            let mut tmp_levels = [0i16; 16];
+
+            // Reconstruct
+            rd_tmp.nz =
+                (ReconstructIntra4(it, tmp_levels.as_mut_ptr(), src, tmp_dst, mode) << i4) as u32;
+
+            // Compute RD-score
+            rd_tmp.D = VP8SSE4x4.unwrap()(src, tmp_dst) as score_t;
+            rd_tmp.SD = if tlambda != 0 {
+                MULT_8B(
+                    tlambda,
+                    VP8TDisto4x4.unwrap()(src, tmp_dst, kWeightY.as_ptr()),
+                ) as score_t
+            } else {
+                0
+            };
+            rd_tmp.H = *mode_costs.offset(mode as isize) as score_t;
+
+            // Add flatness penalty
+            if mode > 0 && IsFlat(tmp_levels.as_ptr(), kNumBlocks, FLATNESS_LIMIT_I4) != 0 {
+                rd_tmp.R = FLATNESS_PENALTY * kNumBlocks as score_t;
+            } else {
+                rd_tmp.R = 0;
+            }
+
+            // early-out check
+            SetRDScore(lambda, &mut rd_tmp);
+            if best_mode >= 0 && rd_tmp.score >= rd_i4.score {
+                continue;
+            }
+
+            // finish computing score
+            rd_tmp.R += VP8GetCostLuma4(it, tmp_levels.as_ptr()) as score_t;
+            SetRDScore(lambda, &mut rd_tmp);
+
+            if best_mode < 0 || rd_tmp.score < rd_i4.score {
+                CopyScore(&mut rd_i4, &rd_tmp);
+                best_mode = mode;
+                mem::swap(&mut tmp_dst, &mut best_block);
+                rd_best.y_ac_levels[i4] = tmp_levels;
+            }
+        }
+        SetRDScore(dqm.lambda_mode_, &mut rd_i4);
+        AddScore(&mut rd_best, &rd_i4);
+        if rd_best.score >= (*rd).score {
+            return 0;
+        }
+        total_header_bits += rd_i4.H as c_int; // <- equal to mode_costs[best_mode];
+        if total_header_bits > (*enc).max_i4_header_bits_ {
+            return 0;
+        }
+        // Copy selected samples if not in the right place already.
+        if best_block != best_blocks.offset(VP8Scan[i4] as isize) {
+            VP8Copy4x4.unwrap()(best_block, best_blocks.offset(VP8Scan[i4] as isize));
+        }
+        (*rd).modes_i4[i4] = best_mode as u8;
+        let nz = (rd_i4.nz != 0) as c_int;
+        (*it).top_nz_[i4 & 3] = nz;
+        (*it).left_nz_[i4 >> 2] = nz;
+        if VP8IteratorRotateI4(it, best_blocks) == 0 {
+            break;
+        }
+    }
+
+    // finalize state
+    CopyScore(&mut *rd, &rd_best);
+    VP8SetIntra4Mode(it, (*rd).modes_i4.as_ptr());
+    SwapOut(it);
+    (*rd).y_ac_levels = rd_best.y_ac_levels;
+    1 // select intra4x4 over intra16x16
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn PickBestUV(it: *mut VP8EncIterator, rd: *mut VP8ModeScore) {
+    let kNumBlocks = 8;
+    let dqm = &(*(*it).enc_).dqm_[(*(*it).mb_).segment_ as usize];
+    let lambda = dqm.lambda_uv_;
+    let src = (*it).yuv_in_.offset(U_OFF_ENC as isize);
+    let mut tmp_dst = (*it).yuv_out2_.offset(U_OFF_ENC as isize); // scratch buffer
+    let dst0 = (*it).yuv_out_.offset(U_OFF_ENC as isize);
+    let mut dst = dst0;
+    let mut rd_best: VP8ModeScore = mem::zeroed();
+
+    (*rd).mode_uv = -1;
+    InitScore(&mut rd_best);
+    for mode in 0..NUM_PRED_MODES {
+        let mut rd_uv: VP8ModeScore = mem::zeroed();
+
+        // Reconstruct
+        rd_uv.nz = ReconstructUV(it, &mut rd_uv, tmp_dst, mode) as u32;
+
+        // Compute RD-score
+        rd_uv.D = VP8SSE16x8.unwrap()(src, tmp_dst) as score_t;
+        rd_uv.SD = 0; // not calling TDisto here: it tends to flatten areas.
+        rd_uv.H = VP8FixedCostsUV[mode as usize] as score_t;
+        rd_uv.R = VP8GetCostUV(it, &rd_uv) as score_t;
+        if mode > 0
+            && IsFlat(
+                rd_uv.uv_levels.as_ptr() as *const i16,
+                kNumBlocks,
+                FLATNESS_LIMIT_UV,
+            ) != 0
+        {
+            rd_uv.R += FLATNESS_PENALTY * kNumBlocks as score_t;
+        }
+
+        SetRDScore(lambda, &mut rd_uv);
+        if mode == 0 || rd_uv.score < rd_best.score {
+            CopyScore(&mut rd_best, &rd_uv);
+            (*rd).mode_uv = mode;
+            (*rd).uv_levels = rd_uv.uv_levels;
+            if !(*it).top_derr_.is_null() {
+                (*rd).derr = rd_uv.derr;
+            }
+            mem::swap(&mut dst, &mut tmp_dst);
+        }
+    }
+    VP8SetIntraUVMode(it, (*rd).mode_uv);
+    AddScore(&mut *rd, &rd_best);
+    if dst != dst0 {
+        // copy 16x8 block if needed
+        VP8Copy16x8.unwrap()(dst, dst0);
+    }
+    if !(*it).top_derr_.is_null() {
+        // store diffusion errors for next block
+        StoreDiffusionErrors(it, rd);
+    }
+}
+
+//------------------------------------------------------------------------------
+// Final reconstruction and quantization.
+
+#[allow(non_snake_case)]
+unsafe fn SimpleQuantize(it: *mut VP8EncIterator, rd: *mut VP8ModeScore) {
+    let enc = (*it).enc_;
+    let is_i16 = (*(*it).mb_).type_ == 1;
+    let mut nz = 0;
+
+    if is_i16 {
+        nz = ReconstructIntra16(
+            it,
+            rd,
+            (*it).yuv_out_.offset(Y_OFF_ENC as isize),
+            *(*it).preds_ as c_int,
+        );
+    } else {
+        VP8IteratorStartI4(it);
+        loop {
+            let i4 = (*it).i4_;
+            let mode = *(*it)
+                .preds_
+                .offset(((i4 & 3) + (i4 >> 2) * (*enc).preds_w_) as isize);
+            let src = (*it)
+                .yuv_in_
+                .offset((Y_OFF_ENC + VP8Scan[i4 as usize] as c_int) as isize);
+            let dst = (*it)
+                .yuv_out_
+                .offset((Y_OFF_ENC + VP8Scan[i4 as usize] as c_int) as isize);
+            VP8MakeIntra4Preds(it);
+            nz |= ReconstructIntra4(
+                it,
+                (*rd).y_ac_levels[i4 as usize].as_mut_ptr(),
+                src,
+                dst,
+                mode as c_int,
+            ) << i4;
+            if VP8IteratorRotateI4(it, (*it).yuv_out_.offset(Y_OFF_ENC as isize)) == 0 {
+                break;
+            }
+        }
+    }
+
+    nz |= ReconstructUV(
+        it,
+        rd,
+        (*it).yuv_out_.offset(U_OFF_ENC as isize),
+        (*(*it).mb_).uv_mode_ as c_int,
+    );
+    (*rd).nz = nz as u32;
+}
+
+// Refine intra16/intra4 sub-modes based on distortion only (not rate).
+#[allow(non_snake_case)]
+unsafe fn RefineUsingDistortion(
+    it: *mut VP8EncIterator,
+    try_both_modes: bool,
+    refine_uv_mode: bool,
+    rd: *mut VP8ModeScore,
+) {
+    let mut best_score = MAX_COST;
+    let mut nz = 0;
+    let mut is_i16 = try_both_modes || (*(*it).mb_).type_ == 1;
+
+    let dqm = &(*(*it).enc_).dqm_[(*(*it).mb_).segment_ as usize];
+    // Some empiric constants, of approximate order of magnitude.
+    let lambda_d_i16: score_t = 106;
+    let lambda_d_i4: score_t = 11;
+    let lambda_d_uv: score_t = 120;
+    let mut score_i4 = dqm.i4_penalty_;
+    let mut i4_bit_sum: score_t = 0;
+    let bit_limit = if try_both_modes {
+        (*(*it).enc_).mb_header_limit_ as score_t
+    } else {
+        MAX_COST // no early-out allowed
+    };
+
+    if is_i16 {
+        // First, evaluate Intra16 distortion
+        let mut best_mode = -1;
+        let src = (*it).yuv_in_.offset(Y_OFF_ENC as isize);
+        for mode in 0..NUM_PRED_MODES {
+            let ref_ = (*it)
+                .yuv_p_
+                .offset(VP8I16ModeOffsets[mode as usize] as isize);
+            let score = VP8SSE16x16.unwrap()(src, ref_) as score_t * RD_DISTO_MULT
+                + VP8FixedCostsI16[mode as usize] as score_t * lambda_d_i16;
+            if mode > 0 && VP8FixedCostsI16[mode as usize] as score_t > bit_limit {
+                continue;
+            }
+            if score < best_score {
+                best_mode = mode;
+                best_score = score;
+            }
+        }
+        VP8SetIntra16Mode(it, best_mode);
+        // we'll reconstruct later, if i16 mode actually gets selected
+    }
+
+    // Next, evaluate Intra4
+    if try_both_modes || !is_i16 {
+        // We don't evaluate the rate here, but just account for it through a
+        // constant penalty (i4 mode usually needs more bits compared to i16).
+        is_i16 = false;
+        VP8IteratorStartI4(it);
+        loop {
+            let mut best_i4_mode = -1;
+            let mut best_i4_score = MAX_COST;
+            let i4 = (*it).i4_ as usize;
+            let src = (*it)
+                .yuv_in_
+                .offset((Y_OFF_ENC + VP8Scan[i4] as c_int) as isize);
+            let mode_costs = GetCostModeI4(it, &(*rd).modes_i4);
+
+            VP8MakeIntra4Preds(it);
+            for mode in 0..NUM_BMODES {
+                let ref_ = (*it)
+                    .yuv_p_
+                    .offset(VP8I4ModeOffsets[mode as usize] as isize);
+                let score = VP8SSE4x4.unwrap()(src, ref_) as score_t * RD_DISTO_MULT
+                    + *mode_costs.offset(mode as isize) as score_t * lambda_d_i4;
+                if score < best_i4_score {
+                    best_i4_mode = mode;
+                    best_i4_score = score;
+                }
+            }
+            i4_bit_sum += *mode_costs.offset(best_i4_mode as isize) as score_t;
+            (*rd).modes_i4[i4] = best_i4_mode as u8;
+            score_i4 += best_i4_score;
+            if score_i4 >= best_score || i4_bit_sum > bit_limit {
+                // Intra4 won't be better than Intra16. Bail out and pick Intra16.
+                is_i16 = true;
+                break;
+            } else {
+                // reconstruct partial block inside yuv_out2_ buffer
+                let tmp_dst = (*it)
+                    .yuv_out2_
+                    .offset((Y_OFF_ENC + VP8Scan[i4] as c_int) as isize);
+                nz |= ReconstructIntra4(
+                    it,
+                    (*rd).y_ac_levels[i4].as_mut_ptr(),
+                    src,
+                    tmp_dst,
+                    best_i4_mode,
+                ) << i4;
+            }
+            if VP8IteratorRotateI4(it, (*it).yuv_out2_.offset(Y_OFF_ENC as isize)) == 0 {
+                break;
+            }
+        }
+    }
+
+    // Final reconstruction, depending on which mode is selected.
+    if !is_i16 {
+        VP8SetIntra4Mode(it, (*rd).modes_i4.as_ptr());
+        SwapOut(it);
+        best_score = score_i4;
+    } else {
+        nz = ReconstructIntra16(
+            it,
+            rd,
+            (*it).yuv_out_.offset(Y_OFF_ENC as isize),
+            *(*it).preds_ as c_int,
+        );
+    }
+
+    // ... and UV!
+    if refine_uv_mode {
+        let mut best_mode = -1;
+        let mut best_uv_score = MAX_COST;
+        let src = (*it).yuv_in_.offset(U_OFF_ENC as isize);
+        for mode in 0..NUM_PRED_MODES {
+            let ref_ = (*it)
+                .yuv_p_
+                .offset(VP8UVModeOffsets[mode as usize] as isize);
+            let score = VP8SSE16x8.unwrap()(src, ref_) as score_t * RD_DISTO_MULT
+                + VP8FixedCostsUV[mode as usize] as score_t * lambda_d_uv;
+            if score < best_uv_score {
+                best_mode = mode;
+                best_uv_score = score;
+            }
+        }
+        VP8SetIntraUVMode(it, best_mode);
+    }
+    nz |= ReconstructUV(
+        it,
+        rd,
+        (*it).yuv_out_.offset(U_OFF_ENC as isize),
+        (*(*it).mb_).uv_mode_ as c_int,
+    );
+
+    (*rd).nz = nz as u32;
+    (*rd).score = best_score;
+}
+
+//------------------------------------------------------------------------------
+// Entry point
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8Decimate(
+    it: *mut VP8EncIterator,
+    rd: *mut VP8ModeScore,
+    rd_opt: VP8RDLevel,
+) -> c_int {
+    let method = (*(*it).enc_).method_;
+
+    InitScore(&mut *rd);
+
+    // We can perform predictions for Luma16x16 and Chroma8x8 already.
+    // Luma4x4 predictions needs to be done as-we-go.
+    VP8MakeLuma16Preds(it);
+    VP8MakeChroma8Preds(it);
+
+    if rd_opt > VP8RDLevel::RD_OPT_NONE {
+        (*it).do_trellis_ = (rd_opt >= VP8RDLevel::RD_OPT_TRELLIS_ALL) as c_int;
+        PickBestIntra16(it, rd);
+        if method >= 2 {
+            PickBestIntra4(it, rd);
+        }
+        PickBestUV(it, rd);
+        if rd_opt == VP8RDLevel::RD_OPT_TRELLIS {
+            // finish off with trellis-optim now
+            (*it).do_trellis_ = 1;
+            SimpleQuantize(it, rd);
+        }
+    } else {
+        // At this point we have heuristically decided intra16 / intra4.
+        // For method >= 2, pick the best intra4/intra16 based on SSE (~tad slower).
+        // For method <= 1, we don't re-examine the decision but just go ahead with
+        // quantization/reconstruction.
+        RefineUsingDistortion(it, method >= 2, method >= 1, rd);
+    }
+    let is_skipped = ((*rd).nz == 0) as c_int;
+    VP8SetSkip(it, is_skipped);
+    is_skipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use enc::analysis_enc::VP8EncAnalyze;
+    use enc::cost_enc::VP8CalculateLevelCosts;
+    use encode::EncoderConfig;
+    use test_util::{
+        check_parity, for_each_macroblock, lossy_images, stage_encoders, Digest, StageEncoder,
+    };
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8SetSegmentParams(enc: *mut VP8Encoder, quality: c_float);
+        fn WebPRef_VP8Decimate(
+            it: *mut VP8EncIterator,
+            rd: *mut VP8ModeScore,
+            rd_opt: VP8RDLevel,
+        ) -> c_int;
+    }
+
+    // Every method, for each level of rd-optimization, then the settings the
+    // quantizers and the filter strengths are derived from.
+    fn configs() -> Vec<EncoderConfig> {
+        let base = EncoderConfig::default();
+        let mut configs: Vec<_> = (0..=6)
+            .map(|method| EncoderConfig { method, ..base })
+            .collect();
+        configs.extend_from_slice(&[
+            EncoderConfig {
+                segments: 1,
+                quality: 10.0,
+                ..base
+            },
+            EncoderConfig {
+                segments: 2,
+                sns_strength: 100,
+                quality: 95.0,
+                ..base
+            },
+            EncoderConfig {
+                emulate_jpeg_size: true,
+                quality: 50.0,
+                ..base
+            },
+            EncoderConfig {
+                filter_strength: 0,
+                ..base
+            },
+            EncoderConfig {
+                filter_strength: 100,
+                filter_sharpness: 7,
+                filter_type: 0,
+                ..base
+            },
+            EncoderConfig {
+                quality: 100.0,
+                method: 6,
+                ..base
+            },
+        ]);
+        configs
+    }
+
+    fn analyze(enc: *mut VP8Encoder) {
+        assert_ne!(unsafe { VP8EncAnalyze(enc) }, 0);
+    }
+
+    // What VP8SetSegmentParams() sets: the segment quantizers and filter
+    // strengths, the headers and the simplified segment map.
+    fn segment_params(encoder: &StageEncoder) -> (Vec<i64>, Vec<[u8; 5]>) {
+        let enc = unsafe { &*encoder.enc };
+        let mut values = Vec::new();
+        for dqm in &enc.dqm_ {
+            for matrix in &[dqm.y1_, dqm.y2_, dqm.uv_] {
+                values.extend(matrix.q_.iter().map(|&v| i64::from(v)));
+                values.extend(matrix.iq_.iter().map(|&v| i64::from(v)));
+                values.extend(matrix.bias_.iter().map(|&v| i64::from(v)));
+                values.extend(matrix.zthresh_.iter().map(|&v| i64::from(v)));
+                values.extend(matrix.sharpen_.iter().map(|&v| i64::from(v)));
+            }
+            values.extend(
+                [
+                    dqm.alpha_,
+                    dqm.beta_,
+                    dqm.quant_,
+                    dqm.fstrength_,
+                    dqm.max_edge_,
+                    dqm.min_disto_,
+                    dqm.lambda_i16_,
+                    dqm.lambda_i4_,
+                    dqm.lambda_uv_,
+                    dqm.lambda_mode_,
+                    dqm.lambda_trellis_,
+                    dqm.tlambda_,
+                    dqm.lambda_trellis_i16_,
+                    dqm.lambda_trellis_i4_,
+                    dqm.lambda_trellis_uv_,
+                ]
+                .iter()
+                .map(|&v| i64::from(v)),
+            );
+            values.push(dqm.i4_penalty_);
+        }
+        values.extend(
+            [
+                enc.base_quant_,
+                enc.dq_y1_dc_,
+                enc.dq_y2_dc_,
+                enc.dq_y2_ac_,
+                enc.dq_uv_dc_,
+                enc.dq_uv_ac_,
+                enc.filter_hdr_.simple_,
+                enc.filter_hdr_.level_,
+                enc.filter_hdr_.sharpness_,
+                enc.filter_hdr_.i4x4_lf_delta_,
+                enc.segment_hdr_.num_segments_,
+                enc.segment_hdr_.update_map_,
+                enc.segment_hdr_.size_,
+            ]
+            .iter()
+            .map(|&v| i64::from(v)),
+        );
+        values.extend(enc.proba_.segments_.iter().map(|&v| i64::from(v)));
+        (values, encoder.mb_info())
+    }
+
+    // What VP8Decimate() returns and sets for the macroblock of 'it': the
+    // scores, levels and modes, the error diffusion and the reconstruction.
+    // The intra4 modes are left out: the reference copies them uninitialized
+    // for intra16 macroblocks, and the preds_ plane holds those in use. So is
+    // the error diffusion when it is disabled.
+    fn decimation(it: &VP8EncIterator, rd: &VP8ModeScore, skipped: c_int) -> (Vec<i64>, Vec<u8>) {
+        let mut values = vec![
+            i64::from(it.x_),
+            i64::from(it.y_),
+            i64::from(skipped),
+            rd.D,
+            rd.SD,
+            rd.H,
+            rd.R,
+            rd.score,
+            i64::from(rd.mode_i16),
+            i64::from(rd.mode_uv),
+            i64::from(rd.nz),
+        ];
+        values.extend(rd.y_dc_levels.iter().map(|&v| i64::from(v)));
+        for levels in rd.y_ac_levels.iter().chain(&rd.uv_levels) {
+            values.extend(levels.iter().map(|&v| i64::from(v)));
+        }
+        if !it.top_derr_.is_null() {
+            for derr in &rd.derr {
+                values.extend(derr.iter().map(|&v| i64::from(v)));
+            }
+            for derr in &it.left_derr_ {
+                values.extend(derr.iter().map(|&v| i64::from(v)));
+            }
+        }
+        let yuv_out = unsafe { slice::from_raw_parts(it.yuv_out_, YUV_SIZE_ENC as usize) };
+        (values, yuv_out.to_vec())
+    }
+
+    #[test]
+    fn test_set_segment_params_parity() {
+        let mut digest = Digest::new();
+        for (width, height, rgba) in lossy_images() {
+            for config in &configs() {
+                let encs = stage_encoders(&rgba, width, height, config, analyze);
+                unsafe { VP8SetSegmentParams(encs[0].enc, config.quality) };
+                let (values, mb_info) = segment_params(&encs[0]);
+                digest.update_i64(&values);
+                digest.update(&mb_info.concat());
+                #[cfg(feature = "c-reference")]
+                {
+                    unsafe { WebPRef_VP8SetSegmentParams(encs[1].enc, config.quality) };
+                    assert!(
+                        segment_params(&encs[1]) == (values, mb_info),
+                        "{}x{} image, {:?}",
+                        width,
+                        height,
+                        config
+                    );
+                }
+            }
+        }
+        check_parity(
+            "enc::quant_enc::tests::test_set_segment_params_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_decimate_parity() {
+        let mut digest = Digest::new();
+        for (width, height, rgba) in lossy_images() {
+            for config in &configs() {
+                let encs = stage_encoders(&rgba, width, height, config, |enc| unsafe {
+                    analyze(enc);
+                    VP8SetSegmentParams(enc, config.quality);
+                    VP8CalculateLevelCosts(&mut (*enc).proba_);
+                });
+                let rd_opt = unsafe { (*encs[0].enc).rd_opt_level_ };
+                for_each_macroblock(&encs, |its| unsafe {
+                    let mut rd: VP8ModeScore = mem::zeroed();
+                    let skipped = VP8Decimate(&mut *its[0], &mut rd, rd_opt);
+                    let (values, yuv_out) = decimation(&its[0], &rd, skipped);
+                    digest.update_i64(&values);
+                    digest.update(&yuv_out);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let mut ref_rd: VP8ModeScore = mem::zeroed();
+                        let ref_skipped = WebPRef_VP8Decimate(&mut *its[1], &mut ref_rd, rd_opt);
+                        assert!(
+                            decimation(&its[1], &ref_rd, ref_skipped) == (values, yuv_out),
+                            "{}x{} image, {:?}, macroblock {},{}",
+                            width,
+                            height,
+                            config,
+                            its[1].x_,
+                            its[1].y_
+                        );
+                    }
+                });
+                let (mb_info, preds) = (encs[0].mb_info(), encs[0].preds());
+                digest.update(&mb_info.concat());
+                digest.update(&preds);
+                #[cfg(feature = "c-reference")]
+                {
+                    assert!(encs[1].mb_info() == mb_info);
+                    assert!(encs[1].preds() == preds);
+                }
+            }
+        }
+        check_parity("enc::quant_enc::tests::test_decimate_parity", digest);
+    }
+}
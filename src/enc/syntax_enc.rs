@@ -0,0 +1,532 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Header syntax writing
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+
+use dec::common_dec::*;
+use enc::tree_enc::{VP8CodeIntraModes, VP8WriteProbas};
+use enc::vp8i_enc::*;
+use enc::webp_enc::{WebPEncodingSetError, WebPReportProgress};
+use encode::WebPEncodingError::{self, *};
+use format_constants::{
+    ALPHA_FLAG, CHUNK_HEADER_SIZE, MAX_CANVAS_SIZE, RIFF_HEADER_SIZE, TAG_SIZE, VP8X_CHUNK_SIZE,
+    VP8_FRAME_HEADER_SIZE, VP8_MAX_PARTITION0_SIZE, VP8_MAX_PARTITION_SIZE, VP8_SIGNATURE,
+};
+use sys::WebPPicture;
+use utils::bit_writer_utils::{
+    VP8BitWriter, VP8BitWriterBuf, VP8BitWriterFinish, VP8BitWriterInit, VP8BitWriterPos,
+    VP8BitWriterSize, VP8BitWriterWipeOut, VP8PutBitUniform, VP8PutBits, VP8PutSignedBits,
+};
+
+//------------------------------------------------------------------------------
+// Helper functions
+
+#[allow(non_snake_case)]
+unsafe fn IsVP8XNeeded(enc: *const VP8Encoder) -> bool {
+    (*enc).has_alpha_ != 0 // Currently the only case when VP8X is needed.
+                           // This could change in the future.
+}
+
+#[allow(non_snake_case)]
+unsafe fn PutPaddingByte(pic: *const WebPPicture) -> c_int {
+    let pad_byte: [u8; 1] = [0];
+    ((*pic).writer.unwrap()(pad_byte.as_ptr(), 1, pic) != 0) as c_int
+}
+
+//------------------------------------------------------------------------------
+// Writers for header's various pieces (in order of appearance)
+
+#[allow(non_snake_case)]
+unsafe fn PutRIFFHeader(enc: *const VP8Encoder, riff_size: usize) -> WebPEncodingError {
+    let pic = (*enc).pic_;
+    let mut riff: [u8; RIFF_HEADER_SIZE] =
+        [b'R', b'I', b'F', b'F', 0, 0, 0, 0, b'W', b'E', b'B', b'P'];
+    c_assert!(riff_size == riff_size as u32 as usize);
+    riff[TAG_SIZE..TAG_SIZE + 4].copy_from_slice(&(riff_size as u32).to_le_bytes());
+    if (*pic).writer.unwrap()(riff.as_ptr(), riff.len(), pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+    VP8_ENC_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn PutVP8XHeader(enc: *const VP8Encoder) -> WebPEncodingError {
+    let pic = (*enc).pic_;
+    let mut vp8x: [u8; CHUNK_HEADER_SIZE + VP8X_CHUNK_SIZE] =
+        [0; CHUNK_HEADER_SIZE + VP8X_CHUNK_SIZE];
+    vp8x[..TAG_SIZE].copy_from_slice(b"VP8X");
+    let mut flags: u32 = 0;
+
+    c_assert!(IsVP8XNeeded(enc));
+    c_assert!((*pic).width >= 1 && (*pic).height >= 1);
+    c_assert!((*pic).width as u32 <= MAX_CANVAS_SIZE && (*pic).height as u32 <= MAX_CANVAS_SIZE);
+
+    if (*enc).has_alpha_ != 0 {
+        flags |= ALPHA_FLAG;
+    }
+
+    let width = ((*pic).width - 1) as u32;
+    let height = ((*pic).height - 1) as u32;
+    vp8x[TAG_SIZE..TAG_SIZE + 4].copy_from_slice(&(VP8X_CHUNK_SIZE as u32).to_le_bytes());
+    vp8x[CHUNK_HEADER_SIZE..CHUNK_HEADER_SIZE + 4].copy_from_slice(&flags.to_le_bytes());
+    vp8x[CHUNK_HEADER_SIZE + 4..CHUNK_HEADER_SIZE + 7].copy_from_slice(&width.to_le_bytes()[..3]);
+    vp8x[CHUNK_HEADER_SIZE + 7..CHUNK_HEADER_SIZE + 10].copy_from_slice(&height.to_le_bytes()[..3]);
+    if (*pic).writer.unwrap()(vp8x.as_ptr(), vp8x.len(), pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+    VP8_ENC_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn PutAlphaChunk(enc: *const VP8Encoder) -> WebPEncodingError {
+    let pic = (*enc).pic_;
+    let mut alpha_chunk_hdr: [u8; CHUNK_HEADER_SIZE] = [b'A', b'L', b'P', b'H', 0, 0, 0, 0];
+
+    c_assert!((*enc).has_alpha_ != 0);
+
+    // Alpha chunk header.
+    alpha_chunk_hdr[TAG_SIZE..TAG_SIZE + 4].copy_from_slice(&(*enc).alpha_data_size_.to_le_bytes());
+    if (*pic).writer.unwrap()(alpha_chunk_hdr.as_ptr(), alpha_chunk_hdr.len(), pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+
+    // Alpha chunk data.
+    if (*pic).writer.unwrap()((*enc).alpha_data_, (*enc).alpha_data_size_ as usize, pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+
+    // Padding.
+    if (*enc).alpha_data_size_ & 1 != 0 && PutPaddingByte(pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+    VP8_ENC_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn PutVP8Header(pic: *const WebPPicture, vp8_size: usize) -> WebPEncodingError {
+    let mut vp8_chunk_hdr: [u8; CHUNK_HEADER_SIZE] = [b'V', b'P', b'8', b' ', 0, 0, 0, 0];
+    c_assert!(vp8_size == vp8_size as u32 as usize);
+    vp8_chunk_hdr[TAG_SIZE..TAG_SIZE + 4].copy_from_slice(&(vp8_size as u32).to_le_bytes());
+    if (*pic).writer.unwrap()(vp8_chunk_hdr.as_ptr(), vp8_chunk_hdr.len(), pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+    VP8_ENC_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn PutVP8FrameHeader(
+    pic: *const WebPPicture,
+    profile: c_int,
+    size0: usize,
+) -> WebPEncodingError {
+    let mut vp8_frm_hdr: [u8; VP8_FRAME_HEADER_SIZE] = [0; VP8_FRAME_HEADER_SIZE];
+
+    if size0 >= VP8_MAX_PARTITION0_SIZE {
+        // partition #0 is too big to fit
+        return VP8_ENC_ERROR_PARTITION0_OVERFLOW;
+    }
+
+    // Paragraph 9.1.
+    #[rustfmt::skip]
+    let bits: u32 = 0                       // keyframe (1b)
+                  | ((profile as u32) << 1) // profile (3b)
+                  | (1 << 4)                // visible (1b)
+                  | ((size0 as u32) << 5); // partition length (19b)
+    vp8_frm_hdr[0] = ((bits >> 0) & 0xff) as u8;
+    vp8_frm_hdr[1] = ((bits >> 8) & 0xff) as u8;
+    vp8_frm_hdr[2] = ((bits >> 16) & 0xff) as u8;
+    // signature
+    vp8_frm_hdr[3] = ((VP8_SIGNATURE >> 16) & 0xff) as u8;
+    vp8_frm_hdr[4] = ((VP8_SIGNATURE >> 8) & 0xff) as u8;
+    vp8_frm_hdr[5] = ((VP8_SIGNATURE >> 0) & 0xff) as u8;
+    // dimensions
+    vp8_frm_hdr[6] = ((*pic).width & 0xff) as u8;
+    vp8_frm_hdr[7] = ((*pic).width >> 8) as u8;
+    vp8_frm_hdr[8] = ((*pic).height & 0xff) as u8;
+    vp8_frm_hdr[9] = ((*pic).height >> 8) as u8;
+
+    if (*pic).writer.unwrap()(vp8_frm_hdr.as_ptr(), vp8_frm_hdr.len(), pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+    VP8_ENC_OK
+}
+
+// WebP Headers.
+#[allow(non_snake_case)]
+unsafe fn PutWebPHeaders(
+    enc: *const VP8Encoder,
+    size0: usize,
+    vp8_size: usize,
+    riff_size: usize,
+) -> c_int {
+    let pic = (*enc).pic_;
+    let mut err;
+
+    'error: {
+        // RIFF header.
+        err = PutRIFFHeader(enc, riff_size);
+        if err != VP8_ENC_OK {
+            break 'error;
+        }
+
+        // VP8X.
+        if IsVP8XNeeded(enc) {
+            err = PutVP8XHeader(enc);
+            if err != VP8_ENC_OK {
+                break 'error;
+            }
+        }
+
+        // Alpha.
+        if (*enc).has_alpha_ != 0 {
+            err = PutAlphaChunk(enc);
+            if err != VP8_ENC_OK {
+                break 'error;
+            }
+        }
+
+        // VP8 header.
+        err = PutVP8Header(pic, vp8_size);
+        if err != VP8_ENC_OK {
+            break 'error;
+        }
+
+        // VP8 frame header.
+        err = PutVP8FrameHeader(pic, (*enc).profile_, size0);
+        if err != VP8_ENC_OK {
+            break 'error;
+        }
+
+        // All OK.
+        return 1;
+    }
+
+    // Error.
+    WebPEncodingSetError(pic, err)
+}
+
+// Segmentation header
+#[allow(non_snake_case)]
+unsafe fn PutSegmentHeader(bw: *mut VP8BitWriter, enc: *const VP8Encoder) {
+    let hdr = &(*enc).segment_hdr_;
+    let proba = &(*enc).proba_;
+    if VP8PutBitUniform(bw, (hdr.num_segments_ > 1) as c_int) != 0 {
+        // We always 'update' the quant and filter strength values
+        let update_data = 1;
+        VP8PutBitUniform(bw, hdr.update_map_);
+        if VP8PutBitUniform(bw, update_data) != 0 {
+            // we always use absolute values, not relative ones
+            VP8PutBitUniform(bw, 1); // (segment_feature_mode = 1. Paragraph 9.3.)
+            for s in 0..NUM_MB_SEGMENTS as usize {
+                VP8PutSignedBits(bw, (*enc).dqm_[s].quant_, 7);
+            }
+            for s in 0..NUM_MB_SEGMENTS as usize {
+                VP8PutSignedBits(bw, (*enc).dqm_[s].fstrength_, 6);
+            }
+        }
+        if hdr.update_map_ != 0 {
+            for s in 0..3 {
+                if VP8PutBitUniform(bw, (proba.segments_[s] != 255) as c_int) != 0 {
+                    VP8PutBits(bw, proba.segments_[s] as u32, 8);
+                }
+            }
+        }
+    }
+}
+
+// Filtering parameters header
+#[allow(non_snake_case)]
+unsafe fn PutFilterHeader(bw: *mut VP8BitWriter, hdr: &VP8EncFilterHeader) {
+    let use_lf_delta = (hdr.i4x4_lf_delta_ != 0) as c_int;
+    VP8PutBitUniform(bw, hdr.simple_);
+    VP8PutBits(bw, hdr.level_ as u32, 6);
+    VP8PutBits(bw, hdr.sharpness_ as u32, 3);
+    if VP8PutBitUniform(bw, use_lf_delta) != 0 {
+        // '0' is the default value for i4x4_lf_delta_ at frame #0.
+        let need_update = (hdr.i4x4_lf_delta_ != 0) as c_int;
+        if VP8PutBitUniform(bw, need_update) != 0 {
+            // we don't use ref_lf_delta => emit four 0 bits
+            VP8PutBits(bw, 0, 4);
+            // we use mode_lf_delta for i4x4
+            VP8PutSignedBits(bw, hdr.i4x4_lf_delta_, 6);
+            VP8PutBits(bw, 0, 3); // all others unused
+        }
+    }
+}
+
+// Nominal quantization parameters
+#[allow(non_snake_case)]
+unsafe fn PutQuant(bw: *mut VP8BitWriter, enc: *const VP8Encoder) {
+    VP8PutBits(bw, (*enc).base_quant_ as u32, 7);
+    VP8PutSignedBits(bw, (*enc).dq_y1_dc_, 4);
+    VP8PutSignedBits(bw, (*enc).dq_y2_dc_, 4);
+    VP8PutSignedBits(bw, (*enc).dq_y2_ac_, 4);
+    VP8PutSignedBits(bw, (*enc).dq_uv_dc_, 4);
+    VP8PutSignedBits(bw, (*enc).dq_uv_ac_, 4);
+}
+
+// Partition sizes
+#[allow(non_snake_case)]
+unsafe fn EmitPartitionsSize(enc: *const VP8Encoder, pic: *mut WebPPicture) -> c_int {
+    let mut buf: [u8; 3 * (MAX_NUM_PARTITIONS as usize - 1)] =
+        [0; 3 * (MAX_NUM_PARTITIONS as usize - 1)];
+    let mut p = 0;
+    while p < ((*enc).num_parts_ - 1) as usize {
+        let part_size = VP8BitWriterSize(&(*enc).parts_[p]);
+        if part_size >= VP8_MAX_PARTITION_SIZE {
+            return WebPEncodingSetError(pic, VP8_ENC_ERROR_PARTITION_OVERFLOW);
+        }
+        buf[3 * p + 0] = ((part_size >> 0) & 0xff) as u8;
+        buf[3 * p + 1] = ((part_size >> 8) & 0xff) as u8;
+        buf[3 * p + 2] = ((part_size >> 16) & 0xff) as u8;
+        p += 1;
+    }
+    if p != 0 {
+        (*pic).writer.unwrap()(buf.as_ptr(), 3 * p, pic)
+    } else {
+        1
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn GeneratePartition0(enc: *mut VP8Encoder) -> c_int {
+    let bw: *mut VP8BitWriter = &mut (*enc).bw_;
+    let mb_size = (*enc).mb_w_ * (*enc).mb_h_;
+
+    let pos1 = VP8BitWriterPos(&*bw);
+    if VP8BitWriterInit(bw, (mb_size * 7 / 8) as usize) == 0 {
+        // ~7 bits per macroblock
+        return WebPEncodingSetError((*enc).pic_, VP8_ENC_ERROR_OUT_OF_MEMORY);
+    }
+    VP8PutBitUniform(bw, 0); // colorspace
+    VP8PutBitUniform(bw, 0); // clamp type
+
+    PutSegmentHeader(bw, enc);
+    PutFilterHeader(bw, &(*enc).filter_hdr_);
+    VP8PutBits(
+        bw,
+        match (*enc).num_parts_ {
+            8 => 3,
+            4 => 2,
+            2 => 1,
+            _ => 0,
+        },
+        2,
+    );
+    PutQuant(bw, enc);
+    VP8PutBitUniform(bw, 0); // no proba update
+    VP8WriteProbas(bw, &(*enc).proba_);
+    let pos2 = VP8BitWriterPos(&*bw);
+    VP8CodeIntraModes(enc);
+    VP8BitWriterFinish(bw);
+
+    let pos3 = VP8BitWriterPos(&*bw);
+
+    let stats = (*(*enc).pic_).stats;
+    if !stats.is_null() {
+        (*stats).header_bytes[0] = (pos2.wrapping_sub(pos1).wrapping_add(7) >> 3) as c_int;
+        (*stats).header_bytes[1] = (pos3.wrapping_sub(pos2).wrapping_add(7) >> 3) as c_int;
+        (*stats).alpha_data_size = (*enc).alpha_data_size_ as c_int;
+    }
+    if (*bw).error_ != 0 {
+        return WebPEncodingSetError((*enc).pic_, VP8_ENC_ERROR_OUT_OF_MEMORY);
+    }
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8EncFreeBitWriters(enc: *mut VP8Encoder) {
+    VP8BitWriterWipeOut(&mut (*enc).bw_);
+    for p in 0..(*enc).num_parts_ as usize {
+        VP8BitWriterWipeOut(&mut (*enc).parts_[p]);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8EncWrite(enc: *mut VP8Encoder) -> c_int {
+    let pic = (*enc).pic_;
+    let bw: *mut VP8BitWriter = &mut (*enc).bw_;
+    let task_percent = 19;
+    let percent_per_part = task_percent / (*enc).num_parts_;
+    let final_percent = (*enc).percent_ + task_percent;
+
+    // Partition #0 with header and partition sizes
+    let mut ok = GeneratePartition0(enc);
+    if ok == 0 {
+        return 0;
+    }
+
+    // Compute VP8 size
+    let mut vp8_size =
+        VP8_FRAME_HEADER_SIZE + VP8BitWriterSize(&*bw) + 3 * ((*enc).num_parts_ - 1) as usize;
+    for p in 0..(*enc).num_parts_ as usize {
+        vp8_size += VP8BitWriterSize(&(*enc).parts_[p]);
+    }
+    let pad = vp8_size & 1;
+    vp8_size += pad;
+
+    // Compute RIFF size
+    // At the minimum it is: "WEBPVP8 nnnn" + VP8 data size.
+    let mut riff_size = TAG_SIZE + CHUNK_HEADER_SIZE + vp8_size;
+    if IsVP8XNeeded(enc) {
+        // Add size for: VP8X header + data.
+        riff_size += CHUNK_HEADER_SIZE + VP8X_CHUNK_SIZE;
+    }
+    if (*enc).has_alpha_ != 0 {
+        // Add size for: ALPH header + data.
+        let padded_alpha_size = (*enc).alpha_data_size_ + ((*enc).alpha_data_size_ & 1);
+        riff_size += CHUNK_HEADER_SIZE + padded_alpha_size as usize;
+    }
+    // Sanity check.
+    if riff_size > 0xfffffffe {
+        return WebPEncodingSetError(pic, VP8_ENC_ERROR_FILE_TOO_BIG);
+    }
+
+    // Emit headers and partition #0
+    {
+        let part0 = VP8BitWriterBuf(&*bw);
+        let size0 = VP8BitWriterSize(&*bw);
+        ok = (ok != 0
+            && PutWebPHeaders(enc, size0, vp8_size, riff_size) != 0
+            && (*pic).writer.unwrap()(part0, size0, pic) != 0
+            && EmitPartitionsSize(enc, pic) != 0) as c_int;
+        VP8BitWriterWipeOut(bw); // will free the internal buffer.
+    }
+
+    // Token partitions
+    for p in 0..(*enc).num_parts_ as usize {
+        let buf = VP8BitWriterBuf(&(*enc).parts_[p]);
+        let size = VP8BitWriterSize(&(*enc).parts_[p]);
+        if size != 0 {
+            ok = (ok != 0 && (*pic).writer.unwrap()(buf, size, pic) != 0) as c_int;
+        }
+        VP8BitWriterWipeOut(&mut (*enc).parts_[p]); // will free the internal buffer.
+        ok = (ok != 0
+            && WebPReportProgress(
+                pic,
+                (*enc).percent_ + percent_per_part,
+                &mut (*enc).percent_,
+            ) != 0) as c_int;
+    }
+
+    // Padding byte
+    if ok != 0 && pad != 0 {
+        ok = PutPaddingByte(pic);
+    }
+
+    (*enc).coded_size_ = (CHUNK_HEADER_SIZE + riff_size) as c_int;
+    ok = (ok != 0 && WebPReportProgress(pic, final_percent, &mut (*enc).percent_) != 0) as c_int;
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use enc::analysis_enc::VP8EncAnalyze;
+    use enc::frame_enc::{VP8EncLoop, VP8EncTokenLoop};
+    use encode::EncoderConfig;
+    use test_util::{check_parity, lossy_images, stage_encoders, Digest, StageEncoder};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8EncWrite(enc: *mut VP8Encoder) -> c_int;
+    }
+
+    // The settings of the headers and of the partitions.
+    fn configs() -> Vec<EncoderConfig> {
+        let base = EncoderConfig::default();
+        vec![
+            base,
+            EncoderConfig {
+                segments: 1,
+                method: 0,
+                ..base
+            },
+            EncoderConfig {
+                segments: 2,
+                filter_strength: 0,
+                method: 6,
+                ..base
+            },
+            EncoderConfig {
+                filter_sharpness: 7,
+                filter_type: 0,
+                ..base
+            },
+            EncoderConfig {
+                partitions: 3,
+                method: 2,
+                ..base
+            },
+            EncoderConfig {
+                partitions: 2,
+                low_memory: true,
+                ..base
+            },
+            EncoderConfig {
+                target_size: 800,
+                pass: 3,
+                ..base
+            },
+            EncoderConfig {
+                alpha_compression: 0,
+                quality: 10.0,
+                ..base
+            },
+        ]
+    }
+
+    // The bitstream written and the size accounted for.
+    fn written(encoder: &StageEncoder) -> (Vec<u8>, c_int) {
+        (encoder.output(), unsafe { (*encoder.enc).coded_size_ })
+    }
+
+    #[test]
+    fn test_enc_write_parity() {
+        let mut digest = Digest::new();
+        for (width, height, rgba) in lossy_images() {
+            for config in &configs() {
+                let encs = stage_encoders(&rgba, width, height, config, |enc| unsafe {
+                    assert_ne!(VP8EncAnalyze(enc), 0);
+                    assert_ne!(VP8EncStartAlpha(enc), 0);
+                    if (*enc).use_tokens_ == 0 {
+                        assert_ne!(VP8EncLoop(enc), 0);
+                    } else {
+                        assert_ne!(VP8EncTokenLoop(enc), 0);
+                    }
+                    assert_ne!(VP8EncFinishAlpha(enc), 0);
+                });
+                assert_ne!(unsafe { VP8EncWrite(encs[0].enc) }, 0);
+                let (output, coded_size) = written(&encs[0]);
+                digest.update(&output);
+                digest.update_i32(&[coded_size]);
+                #[cfg(feature = "c-reference")]
+                {
+                    assert_ne!(unsafe { WebPRef_VP8EncWrite(encs[1].enc) }, 0);
+                    assert!(
+                        written(&encs[1]) == (output, coded_size),
+                        "{}x{} image, {:?}",
+                        width,
+                        height,
+                        config
+                    );
+                }
+            }
+        }
+        check_parity("enc::syntax_enc::tests::test_enc_write_parity", digest);
+    }
+}
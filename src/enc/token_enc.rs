@@ -0,0 +1,433 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+//   Paginated token buffer
+//
+//  A 'token' is a bit value associated with a probability, either fixed
+// or a later-to-be-determined after statistics have been collected.
+// For dynamic probability, we just record the slot id (idx) for the probability
+// value in the final probability array (uint8_t* probas in VP8EmitTokens).
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dec::common_dec::*;
+use dsp::enc::VP8EncBands;
+use enc::cost_enc::{VP8BitCost, VP8RecordStats, VP8Residual};
+use enc::frame_enc::{VP8Cat3, VP8Cat4, VP8Cat5, VP8Cat6};
+use enc::vp8i_enc::*;
+use utils::bit_writer_utils::{VP8BitWriter, VP8PutBit};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc};
+
+// we use pages to reduce the number of memcpy()
+const MIN_PAGE_SIZE: c_int = 8192; // minimum number of token per page
+const FIXED_PROBA_BIT: u32 = 1 << 14;
+
+#[allow(non_camel_case_types)]
+type token_t = u16; // bit #15: bit value
+                    // bit #14: flags for constant proba or idx
+                    // bits #0..13: slot or constant proba
+#[repr(C)]
+pub struct VP8Tokens {
+    next_: *mut VP8Tokens, // pointer to next page
+}
+// Token data is located in memory just after the next_ field.
+// This function is used to return their address and hide the trick.
+#[allow(non_snake_case)]
+unsafe fn TOKEN_DATA(p: *mut VP8Tokens) -> *mut token_t {
+    p.offset(1) as *mut token_t
+}
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8TBufferInit(b: *mut VP8TBuffer, page_size: c_int) {
+    (*b).tokens_ = ptr::null_mut();
+    (*b).pages_ = ptr::null_mut();
+    (*b).last_page_ = &mut (*b).pages_;
+    (*b).left_ = 0;
+    (*b).page_size_ = if page_size < MIN_PAGE_SIZE {
+        MIN_PAGE_SIZE
+    } else {
+        page_size
+    };
+    (*b).error_ = 0;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8TBufferClear(b: *mut VP8TBuffer) {
+    if !b.is_null() {
+        let mut p = (*b).pages_;
+        while !p.is_null() {
+            let next = (*p).next_;
+            WebPSafeFree(p as *mut c_void);
+            p = next;
+        }
+        VP8TBufferInit(b, (*b).page_size_);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn TBufferNewPage(b: *mut VP8TBuffer) -> c_int {
+    let mut page: *mut VP8Tokens = ptr::null_mut();
+    if (*b).error_ == 0 {
+        let size =
+            mem::size_of::<VP8Tokens>() + (*b).page_size_ as usize * mem::size_of::<token_t>();
+        page = WebPSafeMalloc(1, size) as *mut VP8Tokens;
+    }
+    if page.is_null() {
+        (*b).error_ = 1;
+        return 0;
+    }
+    (*page).next_ = ptr::null_mut();
+
+    *(*b).last_page_ = page;
+    (*b).last_page_ = &mut (*page).next_;
+    (*b).left_ = (*b).page_size_;
+    (*b).tokens_ = TOKEN_DATA(page);
+    1
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+fn TOKEN_ID(t: c_int, b: c_int, ctx: c_int) -> u32 {
+    (NUM_PROBAS * (ctx + NUM_CTX * (b + NUM_BANDS * t))) as u32
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn AddToken(b: *mut VP8TBuffer, bit: u32, proba_idx: u32, stats: *mut proba_t) -> u32 {
+    c_assert!(proba_idx < FIXED_PROBA_BIT);
+    c_assert!(bit <= 1);
+    if (*b).left_ > 0 || TBufferNewPage(b) != 0 {
+        (*b).left_ -= 1;
+        let slot = (*b).left_;
+        *(*b).tokens_.offset(slot as isize) = ((bit << 15) | proba_idx) as token_t;
+    }
+    VP8RecordStats(bit as c_int, stats);
+    bit
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn AddConstantToken(b: *mut VP8TBuffer, bit: u32, proba: u32) {
+    c_assert!(proba < 256);
+    c_assert!(bit <= 1);
+    if (*b).left_ > 0 || TBufferNewPage(b) != 0 {
+        (*b).left_ -= 1;
+        let slot = (*b).left_;
+        *(*b).tokens_.offset(slot as isize) = ((bit << 15) | FIXED_PROBA_BIT | proba) as token_t;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8RecordCoeffTokens(
+    ctx: c_int,
+    res: *const VP8Residual,
+    tokens: *mut VP8TBuffer,
+) -> c_int {
+    let coeffs = (*res).coeffs;
+    let coeff_type = (*res).coeff_type;
+    let last = (*res).last;
+    let mut n = (*res).first;
+    let mut base_id = TOKEN_ID(coeff_type, n, ctx);
+    // should be stats[VP8EncBands[n]], but it's equivalent for n=0 or 1
+    let mut s: *mut proba_t = (*(*res).stats.offset(n as isize))[ctx as usize].as_mut_ptr();
+    if AddToken(tokens, (last >= 0) as u32, base_id + 0, s.offset(0)) == 0 {
+        return 0;
+    }
+
+    while n < 16 {
+        let c = *coeffs.offset(n as isize) as c_int;
+        n += 1;
+        let sign = (c < 0) as u32;
+        let v = (if sign != 0 { -c } else { c }) as u32;
+        if AddToken(tokens, (v != 0) as u32, base_id + 1, s.offset(1)) == 0 {
+            base_id = TOKEN_ID(coeff_type, VP8EncBands[n as usize] as c_int, 0); // ctx=0
+            s = (*(*res).stats.offset(VP8EncBands[n as usize] as isize))[0].as_mut_ptr();
+            continue;
+        }
+        if AddToken(tokens, (v > 1) as u32, base_id + 2, s.offset(2)) == 0 {
+            base_id = TOKEN_ID(coeff_type, VP8EncBands[n as usize] as c_int, 1); // ctx=1
+            s = (*(*res).stats.offset(VP8EncBands[n as usize] as isize))[1].as_mut_ptr();
+        } else {
+            if AddToken(tokens, (v > 4) as u32, base_id + 3, s.offset(3)) == 0 {
+                if AddToken(tokens, (v != 2) as u32, base_id + 4, s.offset(4)) != 0 {
+                    AddToken(tokens, (v == 4) as u32, base_id + 5, s.offset(5));
+                }
+            } else if AddToken(tokens, (v > 10) as u32, base_id + 6, s.offset(6)) == 0 {
+                if AddToken(tokens, (v > 6) as u32, base_id + 7, s.offset(7)) == 0 {
+                    AddConstantToken(tokens, (v == 6) as u32, 159);
+                } else {
+                    AddConstantToken(tokens, (v >= 9) as u32, 165);
+                    AddConstantToken(tokens, ((v & 1) == 0) as u32, 145);
+                }
+            } else {
+                let mut mask: u32;
+                let mut tab: *const u8;
+                let mut residue = v - 3;
+                if residue < (8 << 1) {
+                    // VP8Cat3  (3b)
+                    AddToken(tokens, 0, base_id + 8, s.offset(8));
+                    AddToken(tokens, 0, base_id + 9, s.offset(9));
+                    residue -= 8 << 0;
+                    mask = 1 << 2;
+                    tab = VP8Cat3.as_ptr();
+                } else if residue < (8 << 2) {
+                    // VP8Cat4  (4b)
+                    AddToken(tokens, 0, base_id + 8, s.offset(8));
+                    AddToken(tokens, 1, base_id + 9, s.offset(9));
+                    residue -= 8 << 1;
+                    mask = 1 << 3;
+                    tab = VP8Cat4.as_ptr();
+                } else if residue < (8 << 3) {
+                    // VP8Cat5  (5b)
+                    AddToken(tokens, 1, base_id + 8, s.offset(8));
+                    AddToken(tokens, 0, base_id + 10, s.offset(9));
+                    residue -= 8 << 2;
+                    mask = 1 << 4;
+                    tab = VP8Cat5.as_ptr();
+                } else {
+                    // VP8Cat6 (11b)
+                    AddToken(tokens, 1, base_id + 8, s.offset(8));
+                    AddToken(tokens, 1, base_id + 10, s.offset(9));
+                    residue -= 8 << 3;
+                    mask = 1 << 10;
+                    tab = VP8Cat6.as_ptr();
+                }
+                while mask != 0 {
+                    AddConstantToken(tokens, ((residue & mask) != 0) as u32, *tab as u32);
+                    tab = tab.offset(1);
+                    mask >>= 1;
+                }
+            }
+            base_id = TOKEN_ID(coeff_type, VP8EncBands[n as usize] as c_int, 2); // ctx=2
+            s = (*(*res).stats.offset(VP8EncBands[n as usize] as isize))[2].as_mut_ptr();
+        }
+        AddConstantToken(tokens, sign, 128);
+        if n == 16 || AddToken(tokens, (n <= last) as u32, base_id + 0, s.offset(0)) == 0 {
+            return 1; // EOB
+        }
+    }
+    1
+}
+
+//------------------------------------------------------------------------------
+// Final coding pass, with known probabilities
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8EmitTokens(
+    b: *mut VP8TBuffer,
+    bw: *mut VP8BitWriter,
+    probas: *const u8,
+    final_pass: c_int,
+) -> c_int {
+    let mut p = (*b).pages_;
+    c_assert!((*b).error_ == 0);
+    while !p.is_null() {
+        let next = (*p).next_;
+        let N = if next.is_null() { (*b).left_ } else { 0 };
+        let mut n = (*b).page_size_;
+        let tokens = TOKEN_DATA(p);
+        while n > N {
+            n -= 1;
+            let token = *tokens.offset(n as isize) as u32;
+            let bit = ((token >> 15) & 1) as c_int;
+            if (token & FIXED_PROBA_BIT) != 0 {
+                VP8PutBit(bw, bit, (token & 0xff) as c_int); // constant proba
+            } else {
+                VP8PutBit(bw, bit, *probas.offset((token & 0x3fff) as isize) as c_int);
+            }
+        }
+        if final_pass != 0 {
+            WebPSafeFree(p as *mut c_void);
+        }
+        p = next;
+    }
+    if final_pass != 0 {
+        (*b).pages_ = ptr::null_mut();
+    }
+    1
+}
+
+// Size estimation
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern "C" fn VP8EstimateTokenSize(b: *mut VP8TBuffer, probas: *const u8) -> usize {
+    let mut size: usize = 0;
+    let mut p = (*b).pages_;
+    c_assert!((*b).error_ == 0);
+    while !p.is_null() {
+        let next = (*p).next_;
+        let N = if next.is_null() { (*b).left_ } else { 0 };
+        let mut n = (*b).page_size_;
+        let tokens = TOKEN_DATA(p);
+        while n > N {
+            n -= 1;
+            let token = *tokens.offset(n as isize) as u32;
+            let bit = (token & (1 << 15)) as c_int;
+            if (token & FIXED_PROBA_BIT) != 0 {
+                size += VP8BitCost(bit, (token & 0xff) as u8) as usize;
+            } else {
+                size += VP8BitCost(bit, *probas.offset((token & 0x3fff) as isize)) as usize;
+            }
+        }
+        p = next;
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use dsp::enc::{VP8EncDspCostInit, VP8SetResidualCoeffs};
+    use test_util::{check_parity, coeff_levels, Digest, Lcg};
+    use utils::bit_writer_utils::{
+        VP8BitWriterBuf, VP8BitWriterFinish, VP8BitWriterInit, VP8BitWriterSize,
+        VP8BitWriterWipeOut,
+    };
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8TBufferInit(b: *mut VP8TBuffer, page_size: c_int);
+        fn WebPRef_VP8TBufferClear(b: *mut VP8TBuffer);
+        fn WebPRef_VP8RecordCoeffTokens(
+            ctx: c_int,
+            res: *const VP8Residual,
+            tokens: *mut VP8TBuffer,
+        ) -> c_int;
+        fn WebPRef_VP8EmitTokens(
+            b: *mut VP8TBuffer,
+            bw: *mut VP8BitWriter,
+            probas: *const u8,
+            final_pass: c_int,
+        ) -> c_int;
+        fn WebPRef_VP8EstimateTokenSize(b: *mut VP8TBuffer, probas: *const u8) -> usize;
+    }
+
+    struct Api {
+        init: unsafe extern "C" fn(*mut VP8TBuffer, c_int),
+        clear: unsafe extern "C" fn(*mut VP8TBuffer),
+        record_coeff_tokens:
+            unsafe extern "C" fn(c_int, *const VP8Residual, *mut VP8TBuffer) -> c_int,
+        emit_tokens:
+            unsafe extern "C" fn(*mut VP8TBuffer, *mut VP8BitWriter, *const u8, c_int) -> c_int,
+        estimate_token_size: unsafe extern "C" fn(*mut VP8TBuffer, *const u8) -> usize,
+    }
+
+    const PORT: Api = Api {
+        init: VP8TBufferInit,
+        clear: VP8TBufferClear,
+        record_coeff_tokens: VP8RecordCoeffTokens,
+        emit_tokens: VP8EmitTokens,
+        estimate_token_size: VP8EstimateTokenSize,
+    };
+
+    #[cfg(feature = "c-reference")]
+    const REFERENCE: Api = Api {
+        init: WebPRef_VP8TBufferInit,
+        clear: WebPRef_VP8TBufferClear,
+        record_coeff_tokens: WebPRef_VP8RecordCoeffTokens,
+        emit_tokens: WebPRef_VP8EmitTokens,
+        estimate_token_size: WebPRef_VP8EstimateTokenSize,
+    };
+
+    // A block to code: its context, coefficient type and levels.
+    type Block = (c_int, c_int, [i16; 16]);
+
+    fn blocks(rng: &mut Lcg, count: usize) -> Vec<Block> {
+        (0..count)
+            .map(|_| {
+                let ctx = (rng.next() % NUM_CTX as u32) as c_int;
+                let coeff_type = (rng.next() % NUM_TYPES as u32) as c_int;
+                let mut levels = coeff_levels(rng);
+                if coeff_type == 0 {
+                    // The DC coefficient of i16-AC blocks is coded apart.
+                    levels[0] = 0;
+                }
+                (ctx, coeff_type, levels)
+            })
+            .collect()
+    }
+
+    // Records the tokens of 'blocks' in a buffer, then returns what each call
+    // returned, the statistics recorded, the estimated size and the bits the
+    // tokens are emitted as, with 'probas' as the coefficient probabilities.
+    unsafe fn tokens(
+        api: &Api,
+        blocks: &[Block],
+        probas: &[u8],
+    ) -> (Vec<c_int>, Vec<proba_t>, usize, Vec<u8>) {
+        let mut proba: Box<VP8EncProba> = Box::new(mem::zeroed());
+        let coeffs = proba.coeffs_.as_mut_ptr() as *mut u8;
+        ptr::copy_nonoverlapping(probas.as_ptr(), coeffs, mem::size_of_val(&proba.coeffs_));
+        // Boxed, as last_page_ points into the buffer.
+        let mut b: Box<VP8TBuffer> = Box::new(mem::zeroed());
+        (api.init)(&mut *b, 0);
+        let mut recorded = Vec::new();
+        for &(ctx, coeff_type, ref levels) in blocks {
+            let mut res: VP8Residual = mem::zeroed();
+            res.first = (coeff_type == 0) as c_int;
+            res.coeff_type = coeff_type;
+            res.prob = proba.coeffs_[coeff_type as usize].as_mut_ptr();
+            res.stats = proba.stats_[coeff_type as usize].as_mut_ptr();
+            VP8SetResidualCoeffs.unwrap()(levels.as_ptr(), &mut res);
+            recorded.push((api.record_coeff_tokens)(ctx, &res, &mut *b));
+        }
+        let stats = slice::from_raw_parts(
+            proba.stats_.as_ptr() as *const proba_t,
+            mem::size_of_val(&proba.stats_) / mem::size_of::<proba_t>(),
+        )
+        .to_vec();
+        let size = (api.estimate_token_size)(&mut *b, coeffs);
+        let mut bw: VP8BitWriter = mem::zeroed();
+        assert_ne!(VP8BitWriterInit(&mut bw, 0), 0);
+        assert_ne!((api.emit_tokens)(&mut *b, &mut bw, coeffs, 1), 0);
+        VP8BitWriterFinish(&mut bw);
+        let bits = slice::from_raw_parts(VP8BitWriterBuf(&bw), VP8BitWriterSize(&bw)).to_vec();
+        VP8BitWriterWipeOut(&mut bw);
+        (api.clear)(&mut *b);
+        (recorded, stats, size, bits)
+    }
+
+    #[test]
+    fn test_tokens_parity() {
+        unsafe { VP8EncDspCostInit() };
+        let mut digest = Digest::new();
+        let mut rng = Lcg(0x9abc);
+        // From part of a page to several pages of tokens.
+        for &count in &[1, 10, 300, 3000] {
+            let blocks = blocks(&mut rng, count);
+            let probas = rng.bytes(mem::size_of::<
+                [[ProbaArray; NUM_BANDS as usize]; NUM_TYPES as usize],
+            >());
+            let (recorded, stats, size, bits) = unsafe { tokens(&PORT, &blocks, &probas) };
+            digest.update_i32(&recorded);
+            digest.update_u32(&stats);
+            digest.update_u32(&[size as u32]);
+            digest.update(&bits);
+            #[cfg(feature = "c-reference")]
+            assert!(
+                unsafe { tokens(&REFERENCE, &blocks, &probas) } == (recorded, stats, size, bits),
+                "{} blocks",
+                count
+            );
+        }
+        check_parity("enc::token_enc::tests::test_tokens_parity", digest);
+    }
+}
@@ -0,0 +1,371 @@
+use std::os::raw::*;
+
+use dec::common_dec::*;
+use dsp::dsp::BPS;
+use enc::token_enc::VP8Tokens;
+use sys::{WebPConfig, WebPPicture};
+use utils::bit_writer_utils::VP8BitWriter;
+use utils::thread_utils::WebPWorker;
+use utils::utils::WEBP_ALIGN_CST;
+
+//------------------------------------------------------------------------------
+// Various defines and enums
+
+// version numbers
+pub(crate) const ENC_MAJ_VERSION: c_int = 1;
+pub(crate) const ENC_MIN_VERSION: c_int = 0;
+pub(crate) const ENC_REV_VERSION: c_int = 0;
+
+pub(crate) const MAX_LF_LEVELS: c_int = 64; // Maximum loop filter level
+pub(crate) const MAX_VARIABLE_LEVEL: c_int = 67; // last (inclusive) level with variable cost
+pub(crate) const MAX_LEVEL: c_int = 2047; // max level (note: max codable is 2047 + 67)
+
+// Rate-distortion optimization levels
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(C)]
+pub enum VP8RDLevel {
+    RD_OPT_NONE = 0,        // no rd-opt
+    RD_OPT_BASIC = 1,       // basic scoring (no trellis)
+    RD_OPT_TRELLIS = 2,     // perform trellis-quant on the final decision only
+    RD_OPT_TRELLIS_ALL = 3, // trellis-quant for every scoring (much slower)
+}
+
+// YUV-cache parameters. Cache is 32-bytes wide (= one cacheline).
+// The original or reconstructed samples can be accessed using VP8Scan[].
+// The predicted blocks can be accessed using offsets to yuv_p_ and
+// the arrays VP8*ModeOffsets[].
+// * YUV Samples area (yuv_in_/yuv_out_/yuv_out2_)
+//   (see VP8Scan[] for accessing the blocks, along with
+//   Y_OFF_ENC/U_OFF_ENC/V_OFF_ENC):
+//             +----+----+
+//  Y_OFF_ENC  |YYYY|UUVV|
+//  U_OFF_ENC  |YYYY|UUVV|
+//  V_OFF_ENC  |YYYY|....| <- 25% wasted U/V area
+//             |YYYY|....|
+//             +----+----+
+// * Prediction area ('yuv_p_', size = PRED_SIZE_ENC)
+//   Intra16 predictions (16x16 block each, two per row):
+//         |I16DC16|I16TM16|
+//         |I16VE16|I16HE16|
+//   Chroma U/V predictions (16x8 block each, two per row):
+//         |C8DC8|C8TM8|
+//         |C8VE8|C8HE8|
+//   Intra 4x4 predictions (4x4 block each)
+//         |I4DC4 I4TM4 I4VE4 I4HE4|I4RD4 I4VR4 I4LD4 I4VL4|
+//         |I4HD4 I4HU4 I4TMP .....|.......................| <- ~31% wasted
+pub(crate) const YUV_SIZE_ENC: c_int = BPS * 16;
+pub(crate) const PRED_SIZE_ENC: c_int = 32 * BPS + 16 * BPS + 8 * BPS; // I16+Chroma+I4 preds
+pub(crate) const Y_OFF_ENC: c_int = 0;
+pub(crate) const U_OFF_ENC: c_int = 16;
+pub(crate) const V_OFF_ENC: c_int = 16 + 8;
+
+// Layout of prediction blocks
+// intra 16x16
+pub(crate) const I16DC16: c_int = 0 * 16 * BPS;
+pub(crate) const I16TM16: c_int = I16DC16 + 16;
+pub(crate) const I16VE16: c_int = 1 * 16 * BPS;
+pub(crate) const I16HE16: c_int = I16VE16 + 16;
+// chroma 8x8, two U/V blocks side by side (hence: 16x8 each)
+pub(crate) const C8DC8: c_int = 2 * 16 * BPS;
+pub(crate) const C8TM8: c_int = C8DC8 + 1 * 16;
+pub(crate) const C8VE8: c_int = 2 * 16 * BPS + 8 * BPS;
+pub(crate) const C8HE8: c_int = C8VE8 + 1 * 16;
+// intra 4x4
+pub(crate) const I4DC4: c_int = 3 * 16 * BPS + 0;
+pub(crate) const I4TM4: c_int = I4DC4 + 4;
+pub(crate) const I4VE4: c_int = I4DC4 + 8;
+pub(crate) const I4HE4: c_int = I4DC4 + 12;
+pub(crate) const I4RD4: c_int = I4DC4 + 16;
+pub(crate) const I4VR4: c_int = I4DC4 + 20;
+pub(crate) const I4LD4: c_int = I4DC4 + 24;
+pub(crate) const I4VL4: c_int = I4DC4 + 28;
+pub(crate) const I4HD4: c_int = 3 * 16 * BPS + 4 * BPS;
+pub(crate) const I4HU4: c_int = I4HD4 + 4;
+pub(crate) const I4TMP: c_int = I4HD4 + 8;
+
+#[allow(non_camel_case_types)]
+pub type score_t = i64; // type used for scores, rate, distortion
+                        // Note that MAX_COST is not the maximum allowed by sizeof(score_t),
+                        // in order to allow overflowing computations.
+pub(crate) const MAX_COST: score_t = 0x7fffffffffffff;
+
+pub(crate) const QFIX: c_int = 17;
+#[allow(non_snake_case)]
+pub(crate) const fn BIAS(b: u32) -> u32 {
+    b << (QFIX - 8)
+}
+// Fun fact: this is the _only_ line where we're actually being lossy and
+// discarding bits.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn QUANTDIV(n: u32, iQ: u32, B: u32) -> c_int {
+    (n.wrapping_mul(iQ).wrapping_add(B) >> QFIX) as c_int
+}
+
+// quality below which error-diffusion is enabled
+pub(crate) const ERROR_DIFFUSION_QUALITY: c_float = 98.0;
+
+//------------------------------------------------------------------------------
+// Headers
+
+#[allow(non_camel_case_types)]
+pub type proba_t = u32; // 16b + 16b
+pub type ProbaArray = [[u8; NUM_PROBAS as usize]; NUM_CTX as usize];
+pub type StatsArray = [[proba_t; NUM_PROBAS as usize]; NUM_CTX as usize];
+pub type CostArray = [[u16; MAX_VARIABLE_LEVEL as usize + 1]; NUM_CTX as usize];
+pub type CostArrayPtr = *const [*const u16; NUM_CTX as usize]; // for easy casting
+pub type CostArrayMap = [[*const u16; NUM_CTX as usize]; 16];
+pub type LFStats = [[c_double; MAX_LF_LEVELS as usize]; NUM_MB_SEGMENTS as usize]; // filter stats
+
+// segment features
+#[repr(C)]
+pub struct VP8EncSegmentHeader {
+    pub(crate) num_segments_: c_int, // Actual number of segments. 1 segment only = unused.
+    pub(crate) update_map_: c_int,   // whether to update the segment map or not.
+    // must be 0 if there's only 1 segment.
+    pub(crate) size_: c_int, // bit-cost for transmitting the segment map
+}
+
+// Struct collecting all frame-persistent probabilities.
+#[repr(C)]
+pub struct VP8EncProba {
+    pub(crate) segments_: [u8; 3], // probabilities for segment tree
+    pub(crate) skip_proba_: u8,    // final probability of being skipped.
+    pub(crate) coeffs_: [[ProbaArray; NUM_BANDS as usize]; NUM_TYPES as usize], // 1056 bytes
+    pub(crate) stats_: [[StatsArray; NUM_BANDS as usize]; NUM_TYPES as usize], // 4224 bytes
+    pub(crate) level_cost_: [[CostArray; NUM_BANDS as usize]; NUM_TYPES as usize], // 13056 bytes
+    pub(crate) remapped_costs_: [CostArrayMap; NUM_TYPES as usize], // 1536 bytes
+    pub(crate) dirty_: c_int,      // if true, need to call VP8CalculateLevelCosts()
+    pub(crate) use_skip_proba_: c_int, // Note: we always use skip_proba for now.
+    pub(crate) nb_skip_: c_int,    // number of skipped blocks
+}
+
+// Filter parameters. Not actually used in the code (we don't perform
+// the in-loop filtering), but filled from user's config
+#[repr(C)]
+pub struct VP8EncFilterHeader {
+    pub(crate) simple_: c_int,        // filtering type: 0=complex, 1=simple
+    pub(crate) level_: c_int,         // base filter level [0..63]
+    pub(crate) sharpness_: c_int,     // [0..7]
+    pub(crate) i4x4_lf_delta_: c_int, // delta filter level for i4x4 relative to i16x16
+}
+
+//------------------------------------------------------------------------------
+// Informations about the macroblocks.
+
+// Note: the C struct packs the first four fields into bitfields. The Rust
+// encoder is the only one touching it, so plain bytes are used instead.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct VP8MBInfo {
+    // block type
+    pub(crate) type_: u8, // 0=i4x4, 1=i16x16
+    pub(crate) uv_mode_: u8,
+    pub(crate) skip_: u8,
+    pub(crate) segment_: u8,
+    pub(crate) alpha_: u8, // quantization-susceptibility
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct VP8Matrix {
+    pub(crate) q_: [u16; 16],       // quantizer steps
+    pub(crate) iq_: [u16; 16],      // reciprocals, fixed point.
+    pub(crate) bias_: [u32; 16],    // rounding bias
+    pub(crate) zthresh_: [u32; 16], // value below which a coefficient is zeroed
+    pub(crate) sharpen_: [u16; 16], // frequency boosters for slight sharpening
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct VP8SegmentInfo {
+    pub(crate) y1_: VP8Matrix, // quantization matrices
+    pub(crate) y2_: VP8Matrix,
+    pub(crate) uv_: VP8Matrix,
+    pub(crate) alpha_: c_int, // quant-susceptibility, range [-127,127]. Zero is neutral.
+    // Lower values indicate a lower risk of blurriness.
+    pub(crate) beta_: c_int,      // filter-susceptibility, range [0,255].
+    pub(crate) quant_: c_int,     // final segment quantizer.
+    pub(crate) fstrength_: c_int, // final in-loop filtering strength
+    pub(crate) max_edge_: c_int,  // max edge delta (for filtering strength)
+    pub(crate) min_disto_: c_int, // minimum distortion required to trigger filtering record
+    // reactivities
+    pub(crate) lambda_i16_: c_int,
+    pub(crate) lambda_i4_: c_int,
+    pub(crate) lambda_uv_: c_int,
+    pub(crate) lambda_mode_: c_int,
+    pub(crate) lambda_trellis_: c_int,
+    pub(crate) tlambda_: c_int,
+    pub(crate) lambda_trellis_i16_: c_int,
+    pub(crate) lambda_trellis_i4_: c_int,
+    pub(crate) lambda_trellis_uv_: c_int,
+
+    // lambda values for distortion-based evaluation
+    pub(crate) i4_penalty_: score_t, // penalty for using Intra4
+}
+
+pub type DError = [[i8; 2]; 2];
+
+// Handy transient struct to accumulate score and info during RD-optimization
+// and mode evaluation.
+#[derive(Clone, Copy)]
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct VP8ModeScore {
+    pub(crate) D: score_t, // Distortion, spectral distortion
+    pub(crate) SD: score_t,
+    pub(crate) H: score_t, // header bits, rate, score.
+    pub(crate) R: score_t,
+    pub(crate) score: score_t,
+    pub(crate) y_dc_levels: [i16; 16], // Quantized levels for luma-DC, luma-AC, chroma.
+    pub(crate) y_ac_levels: [[i16; 16]; 16],
+    pub(crate) uv_levels: [[i16; 16]; 4 + 4],
+    pub(crate) mode_i16: c_int,    // mode number for intra16 prediction
+    pub(crate) modes_i4: [u8; 16], // mode numbers for intra4 predictions
+    pub(crate) mode_uv: c_int,     // mode number of chroma prediction
+    pub(crate) nz: u32,            // non-zero blocks
+    pub(crate) derr: [[i8; 3]; 2], // DC diffusion errors for U/V for blocks #1/2/3
+}
+
+// Iterator structure to iterate through macroblocks, pointing to the
+// right neighbouring data (samples, predictions, contexts, ...)
+#[repr(C)]
+pub struct VP8EncIterator {
+    pub(crate) x_: c_int, // current macroblock
+    pub(crate) y_: c_int,
+    pub(crate) yuv_in_: *mut u8,          // input samples
+    pub(crate) yuv_out_: *mut u8,         // output samples
+    pub(crate) yuv_out2_: *mut u8,        // secondary buffer swapped with yuv_out_.
+    pub(crate) yuv_p_: *mut u8,           // scratch buffer for prediction
+    pub(crate) enc_: *mut VP8Encoder,     // back-pointer
+    pub(crate) mb_: *mut VP8MBInfo,       // current macroblock
+    pub(crate) bw_: *mut VP8BitWriter,    // current bit-writer
+    pub(crate) preds_: *mut u8,           // intra mode predictors (4x4 blocks)
+    pub(crate) nz_: *mut u32,             // non-zero pattern
+    pub(crate) i4_boundary_: [u8; 37],    // 32+5 boundary samples needed by intra4x4
+    pub(crate) i4_top_: *mut u8,          // pointer to the current top boundary sample
+    pub(crate) i4_: c_int,                // current intra4x4 mode being tested
+    pub(crate) top_nz_: [c_int; 9],       // top-non-zero context.
+    pub(crate) left_nz_: [c_int; 9],      // left-non-zero. left_nz[8] is independent.
+    pub(crate) bit_count_: [[u64; 3]; 4], // bit counters for coded levels.
+    pub(crate) luma_bits_: u64,           // macroblock bit-cost for luma
+    pub(crate) uv_bits_: u64,             // macroblock bit-cost for chroma
+    pub(crate) lf_stats_: *mut LFStats,   // filter stats (borrowed from enc_)
+    pub(crate) do_trellis_: c_int,        // if true, perform extra level optimisation
+    pub(crate) count_down_: c_int,        // number of mb still to be processed
+    pub(crate) count_down0_: c_int,       // starting counter value (for progress)
+    pub(crate) percent0_: c_int,          // saved initial progress percent
+
+    pub(crate) left_derr_: DError,     // left error diffusion (u/v)
+    pub(crate) top_derr_: *mut DError, // top diffusion error - NULL if disabled
+
+    pub(crate) y_left_: *mut u8, // left luma samples (addressable from index -1 to 15).
+    pub(crate) u_left_: *mut u8, // left u samples (addressable from index -1 to 7)
+    pub(crate) v_left_: *mut u8, // left v samples (addressable from index -1 to 7)
+
+    pub(crate) y_top_: *mut u8,  // top luma samples at position 'x_'
+    pub(crate) uv_top_: *mut u8, // top u/v samples at position 'x_', packed as 16 bytes
+
+    // memory for storing y/u/v_left_
+    pub(crate) yuv_left_mem_: [u8; 17 + 16 + 16 + 8 + WEBP_ALIGN_CST],
+    // memory for yuv_*
+    pub(crate) yuv_mem_: [u8; (3 * YUV_SIZE_ENC + PRED_SIZE_ENC) as usize + WEBP_ALIGN_CST],
+}
+
+//------------------------------------------------------------------------------
+// Paginated token buffer
+
+#[repr(C)]
+pub struct VP8TBuffer {
+    pub(crate) pages_: *mut VP8Tokens,          // first page
+    pub(crate) last_page_: *mut *mut VP8Tokens, // last page
+    pub(crate) tokens_: *mut u16,               // set to (*last_page_)->tokens_
+    pub(crate) left_: c_int,                    // how many free tokens left before the page is full
+    pub(crate) page_size_: c_int,               // number of tokens per page
+    pub(crate) error_: c_int,                   // true in case of malloc error
+}
+
+//------------------------------------------------------------------------------
+// VP8Encoder
+
+#[repr(C)]
+pub struct VP8Encoder {
+    pub(crate) config_: *const WebPConfig, // user configuration and parameters
+    pub(crate) pic_: *mut WebPPicture,     // input / output picture
+
+    // headers
+    pub(crate) filter_hdr_: VP8EncFilterHeader, // filtering information
+    pub(crate) segment_hdr_: VP8EncSegmentHeader, // segment information
+
+    pub(crate) profile_: c_int, // VP8's profile, deduced from Config.
+
+    // dimension, in macroblock units.
+    pub(crate) mb_w_: c_int,
+    pub(crate) mb_h_: c_int,
+    pub(crate) preds_w_: c_int, // stride of the *preds_ prediction plane (=4*mb_w + 1)
+
+    // number of partitions (1, 2, 4 or 8 = MAX_NUM_PARTITIONS)
+    pub(crate) num_parts_: c_int,
+
+    // per-partition boolean decoders.
+    pub(crate) bw_: VP8BitWriter, // part0
+    pub(crate) parts_: [VP8BitWriter; MAX_NUM_PARTITIONS as usize], // token partitions
+    pub(crate) tokens_: VP8TBuffer, // token buffer
+
+    pub(crate) percent_: c_int, // for progress
+
+    // transparency blob
+    pub(crate) has_alpha_: c_int,
+    pub(crate) alpha_data_: *mut u8, // non-NULL if transparency is present
+    pub(crate) alpha_data_size_: u32,
+    pub(crate) alpha_worker_: WebPWorker,
+
+    // quantization info (one set of DC/AC dequant factor per segment)
+    pub(crate) dqm_: [VP8SegmentInfo; NUM_MB_SEGMENTS as usize],
+    pub(crate) base_quant_: c_int, // nominal quantizer value. Only used
+    // for relative coding of segments' quant.
+    pub(crate) alpha_: c_int,    // global susceptibility (<=> complexity)
+    pub(crate) uv_alpha_: c_int, // U/V quantization susceptibility
+    // global offset of quantizers, shared by all segments
+    pub(crate) dq_y1_dc_: c_int,
+    pub(crate) dq_y2_dc_: c_int,
+    pub(crate) dq_y2_ac_: c_int,
+    pub(crate) dq_uv_dc_: c_int,
+    pub(crate) dq_uv_ac_: c_int,
+
+    // probabilities and statistics
+    pub(crate) proba_: VP8EncProba,
+    pub(crate) sse_: [u64; 4], // sum of Y/U/V/A squared errors for all macroblocks
+    pub(crate) sse_count_: u64, // pixel count for the sse_[] stats
+    pub(crate) coded_size_: c_int,
+    pub(crate) residual_bytes_: [[c_int; 4]; 3],
+    pub(crate) block_count_: [c_int; 3],
+
+    // quality/speed settings
+    pub(crate) method_: c_int,             // 0=fastest, 6=best/slowest.
+    pub(crate) rd_opt_level_: VP8RDLevel,  // Deduced from method_.
+    pub(crate) max_i4_header_bits_: c_int, // partition #0 safeness factor
+    pub(crate) mb_header_limit_: c_int,    // rough limit for header bits per MB
+    pub(crate) thread_level_: c_int,       // derived from config->thread_level
+    pub(crate) do_search_: c_int,          // derived from config->target_XXX
+    pub(crate) use_tokens_: c_int,         // if true, use token buffer
+
+    // Memory
+    pub(crate) mb_info_: *mut VP8MBInfo, // contextual macroblock infos (mb_w_ + 1)
+    pub(crate) preds_: *mut u8,          // predictions modes: (4*mb_w+1) * (4*mb_h+1)
+    pub(crate) nz_: *mut u32,            // non-zero bit context: mb_w+1
+    pub(crate) y_top_: *mut u8,          // top luma samples.
+    pub(crate) uv_top_: *mut u8,         // top u/v samples.
+    // U and V are packed into 16 bytes (8 U + 8 V)
+    pub(crate) lf_stats_: *mut LFStats, // autofilter stats (if NULL, autofilter is off)
+    pub(crate) top_derr_: *mut DError,  // diffusion error (NULL if disabled)
+}
+
+extern "C" {
+    // in alpha.c
+    pub(crate) fn VP8EncInitAlpha(enc: *mut VP8Encoder); // initialize alpha compression
+    pub(crate) fn VP8EncStartAlpha(enc: *mut VP8Encoder) -> c_int; // start alpha coding process
+    pub(crate) fn VP8EncFinishAlpha(enc: *mut VP8Encoder) -> c_int; // finalize compressed data
+    pub(crate) fn VP8EncDeleteAlpha(enc: *mut VP8Encoder) -> c_int; // delete compressed data
+}
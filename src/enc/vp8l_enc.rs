@@ -0,0 +1,2639 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// main entry for the lossless encoder.
+//
+// Author: Vikas Arora (vikaas.arora@gmail.com)
+//
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::lossless_common::VP8LSubSampleSize;
+use dsp::lossless_enc::{
+    VP8LBundleColorMap, VP8LEncDspInit, VP8LFastLog2, VP8LPrefixEncode, VP8LSubPixels,
+    VP8LSubtractGreenFromBlueAndRed,
+};
+use enc::backward_references_enc::{
+    kLZ77Box, kLZ77RLE, kLZ77Standard, PixOrCopyCacheIdx, PixOrCopyDistance, PixOrCopyIsCacheIdx,
+    PixOrCopyIsLiteral, PixOrCopyLength, PixOrCopyLiteral, VP8LBackwardRefs, VP8LBackwardRefsClear,
+    VP8LBackwardRefsInit, VP8LGetBackwardReferences, VP8LHashChain, VP8LHashChainClear,
+    VP8LHashChainFill, VP8LHashChainInit, VP8LRefsCursorInit, VP8LRefsCursorNext, VP8LRefsCursorOk,
+    MAX_COLOR_CACHE_BITS, MAX_REFS_BLOCK_PER_IMAGE,
+};
+use enc::histogram_enc::{
+    VP8LAllocateHistogram, VP8LAllocateHistogramSet, VP8LBitsEntropy, VP8LFreeHistogram,
+    VP8LFreeHistogramSet, VP8LGetHistoImageSymbols, VP8LHistogram, VP8LHistogramNumCodes,
+    VP8LHistogramSet, VP8LHistogramStoreRefs,
+};
+use enc::predictor_enc::{VP8LColorSpaceTransform, VP8LResidualImage};
+use enc::vp8li_enc::VP8LEncoderARGBContent::*;
+use enc::vp8li_enc::{VP8ApplyNearLossless, VP8LEncoder, MAX_TRANSFORM_BITS};
+use encode::WebPEncodingError::{self, *};
+use encode::{WebPImageHint, WEBP_MAX_DIMENSION};
+use format_constants::VP8LImageTransformType::*;
+use format_constants::{
+    CHUNK_HEADER_SIZE, CODE_LENGTH_CODES, MAX_HUFFMAN_BITS, MAX_PALETTE_SIZE, MIN_HUFFMAN_BITS,
+    NUM_DISTANCE_CODES, NUM_LENGTH_CODES, RIFF_HEADER_SIZE, TAG_SIZE, TRANSFORM_PRESENT,
+    VP8L_IMAGE_SIZE_BITS, VP8L_MAGIC_BYTE, VP8L_SIGNATURE_SIZE, VP8L_VERSION, VP8L_VERSION_BITS,
+};
+use sys::{WebPAuxStats, WebPConfig, WebPPicture};
+use utils::bit_writer_utils::{
+    VP8LBitWriter, VP8LBitWriterClone, VP8LBitWriterFinish, VP8LBitWriterInit,
+    VP8LBitWriterNumBytes, VP8LBitWriterReset, VP8LBitWriterSwap, VP8LBitWriterWipeOut,
+    VP8LPutBits,
+};
+use utils::huffman_encode_utils::{
+    HuffmanTree, HuffmanTreeCode, HuffmanTreeToken, VP8LCreateCompressedHuffmanTree,
+    VP8LCreateHuffmanTree,
+};
+use utils::thread_utils::{WebPGetWorkerInterface, WebPWorker};
+use utils::utils::{
+    BitsLog2Floor, WebPGetColorPalette, WebPSafeCalloc, WebPSafeFree, WebPSafeMalloc, WEBP_ALIGN,
+    WEBP_ALIGN_CST,
+};
+
+extern "C" {
+    // in webp_enc.c
+    fn WebPEncodingSetError(pic: *const WebPPicture, error: WebPEncodingError) -> c_int;
+    fn WebPReportProgress(
+        pic: *const WebPPicture,
+        percent: c_int,
+        percent_store: *mut c_int,
+    ) -> c_int;
+    // in picture_tools_enc.c
+    fn WebPPictureHasTransparency(picture: *const WebPPicture) -> c_int;
+}
+
+// Maximum number of histogram images (sub-blocks).
+const MAX_HUFF_IMAGE_SIZE: c_int = 2600;
+
+// Palette reordering for smaller sum of deltas (and for smaller storage).
+
+#[allow(non_snake_case)]
+#[inline]
+fn PaletteComponentDistance(v: u32) -> u32 {
+    if v <= 128 {
+        v
+    } else {
+        256 - v
+    }
+}
+
+// Computes a value that is related to the entropy created by the
+// palette entry diff.
+//
+// Note that the last & 0xff is a no-operation in the next statement, but
+// removed by most compilers and is here only for regularity of the code.
+#[allow(non_snake_case)]
+#[inline]
+fn PaletteColorDistance(col1: u32, col2: u32) -> u32 {
+    let diff = VP8LSubPixels(col1, col2);
+    #[allow(non_upper_case_globals)]
+    const kMoreWeightForRGBThanForAlpha: u32 = 9;
+    let mut score = PaletteComponentDistance((diff >> 0) & 0xff);
+    score += PaletteComponentDistance((diff >> 8) & 0xff);
+    score += PaletteComponentDistance((diff >> 16) & 0xff);
+    score *= kMoreWeightForRGBThanForAlpha;
+    score += PaletteComponentDistance((diff >> 24) & 0xff);
+    score
+}
+
+#[allow(non_snake_case)]
+fn GreedyMinimizeDeltas(palette: &mut [u32]) {
+    // Find greedily always the closest color of the predicted color to minimize
+    // deltas in the palette. This reduces storage needs since the
+    // palette is stored with delta encoding.
+    let mut predict = 0x00000000;
+    for i in 0..palette.len() {
+        let mut best_ix = i;
+        let mut best_score = !0u32;
+        for (k, &color) in palette.iter().enumerate().skip(i) {
+            let cur_score = PaletteColorDistance(color, predict);
+            if best_score > cur_score {
+                best_score = cur_score;
+                best_ix = k;
+            }
+        }
+        palette.swap(best_ix, i);
+        predict = palette[i];
+    }
+}
+
+// The palette has been sorted by alpha. This function checks if the other
+// components of the palette have a monotonic development with regards to
+// position in the palette. If all have monotonic development, there is
+// no benefit to re-organize them greedily. A monotonic development
+// would be spotted in green-only situations (like lossy alpha) or gray-scale
+// images.
+#[allow(non_snake_case)]
+fn PaletteHasNonMonotonousDeltas(palette: &[u32]) -> bool {
+    let mut predict = 0x000000;
+    let mut sign_found: u8 = 0x00;
+    for &color in palette {
+        let diff = VP8LSubPixels(color, predict);
+        let rd = ((diff >> 16) & 0xff) as u8;
+        let gd = ((diff >> 8) & 0xff) as u8;
+        let bd = ((diff >> 0) & 0xff) as u8;
+        if rd != 0x00 {
+            sign_found |= if rd < 0x80 { 1 } else { 2 };
+        }
+        if gd != 0x00 {
+            sign_found |= if gd < 0x80 { 8 } else { 16 };
+        }
+        if bd != 0x00 {
+            sign_found |= if bd < 0x80 { 64 } else { 128 };
+        }
+        predict = color;
+    }
+    (sign_found & (sign_found << 1)) != 0 // two consequent signs.
+}
+
+// -----------------------------------------------------------------------------
+// Palette
+
+// If number of colors in the image is less than or equal to MAX_PALETTE_SIZE,
+// creates a palette and returns true, else returns false.
+#[allow(non_snake_case)]
+unsafe fn AnalyzeAndCreatePalette(
+    pic: *const WebPPicture,
+    low_effort: bool,
+    palette: &mut [u32; MAX_PALETTE_SIZE],
+    palette_size: &mut c_int,
+) -> bool {
+    let num_colors = WebPGetColorPalette(pic, palette.as_mut_ptr());
+    if num_colors > MAX_PALETTE_SIZE as c_int {
+        *palette_size = 0;
+        return false;
+    }
+    *palette_size = num_colors;
+    let palette = &mut palette[..num_colors as usize];
+    // The colors are distinct, so any sort gives the same result as qsort.
+    palette.sort_unstable();
+    if !low_effort && PaletteHasNonMonotonousDeltas(palette) {
+        GreedyMinimizeDeltas(palette);
+    }
+    true
+}
+
+// These five modes are evaluated and their respective entropy is computed.
+#[allow(non_upper_case_globals)]
+const kDirect: c_int = 0;
+#[allow(non_upper_case_globals)]
+const kSpatial: c_int = 1;
+#[allow(non_upper_case_globals)]
+const kSubGreen: c_int = 2;
+#[allow(non_upper_case_globals)]
+const kSpatialSubGreen: c_int = 3;
+#[allow(non_upper_case_globals)]
+const kPalette: c_int = 4;
+#[allow(non_upper_case_globals)]
+const kNumEntropyIx: c_int = 5;
+
+#[allow(non_upper_case_globals)]
+const kHistoAlpha: usize = 0;
+#[allow(non_upper_case_globals)]
+const kHistoAlphaPred: usize = 1;
+#[allow(non_upper_case_globals)]
+const kHistoGreen: usize = 2;
+#[allow(non_upper_case_globals)]
+const kHistoGreenPred: usize = 3;
+#[allow(non_upper_case_globals)]
+const kHistoRed: usize = 4;
+#[allow(non_upper_case_globals)]
+const kHistoRedPred: usize = 5;
+#[allow(non_upper_case_globals)]
+const kHistoBlue: usize = 6;
+#[allow(non_upper_case_globals)]
+const kHistoBluePred: usize = 7;
+#[allow(non_upper_case_globals)]
+const kHistoRedSubGreen: usize = 8;
+#[allow(non_upper_case_globals)]
+const kHistoRedPredSubGreen: usize = 9;
+#[allow(non_upper_case_globals)]
+const kHistoBlueSubGreen: usize = 10;
+#[allow(non_upper_case_globals)]
+const kHistoBluePredSubGreen: usize = 11;
+#[allow(non_upper_case_globals)]
+const kHistoPalette: usize = 12;
+#[allow(non_upper_case_globals)]
+const kHistoTotal: usize = 13; // Must be last.
+
+#[allow(non_snake_case)]
+fn AddSingleSubGreen(p: c_int, r: &mut [u32], b: &mut [u32]) {
+    let green = p >> 8; // The upper bits are masked away later.
+    r[(((p >> 16) - green) & 0xff) as usize] += 1;
+    b[(((p >> 0) - green) & 0xff) as usize] += 1;
+}
+
+#[allow(non_snake_case)]
+fn AddSingle(p: u32, histo: &mut [u32], a: usize, r: usize, g: usize, b: usize) {
+    histo[a * 256 + ((p >> 24) & 0xff) as usize] += 1;
+    histo[r * 256 + ((p >> 16) & 0xff) as usize] += 1;
+    histo[g * 256 + ((p >> 8) & 0xff) as usize] += 1;
+    histo[b * 256 + ((p >> 0) & 0xff) as usize] += 1;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn HashPix(pix: u32) -> u32 {
+    // Note that masking with 0xffffffffu is for preventing an
+    // 'unsigned int overflow' warning. Doesn't impact the compiled code.
+    ((((pix as u64) + (pix >> 19) as u64).wrapping_mul(0x39c5fba7) & 0xffffffff) >> 24) as u32
+}
+
+#[allow(non_snake_case)]
+unsafe fn AnalyzeEntropy(
+    argb: *const u32,
+    width: c_int,
+    height: c_int,
+    argb_stride: c_int,
+    use_palette: bool,
+    palette_size: c_int,
+    transform_bits: c_int,
+    min_entropy_ix: &mut c_int,
+    red_and_blue_always_zero: &mut c_int,
+) -> bool {
+    if use_palette && palette_size <= 16 {
+        // In the case of small palettes, we pack 2, 4 or 8 pixels together. In
+        // practice, small palettes are better than any other transform.
+        *min_entropy_ix = kPalette;
+        *red_and_blue_always_zero = 1;
+        return true;
+    }
+    // Allocate histogram set with cache_bits = 0.
+    let histo_ptr = WebPSafeCalloc(kHistoTotal as u64, mem::size_of::<u32>() * 256) as *mut u32;
+    if histo_ptr.is_null() {
+        return false;
+    }
+    {
+        let histo = ::std::slice::from_raw_parts_mut(histo_ptr, kHistoTotal * 256);
+        let mut prev_row: *const u32 = ptr::null();
+        let mut curr_row = argb;
+        let mut pix_prev = *argb; // Skip the first pixel.
+        for _ in 0..height {
+            for x in 0..width as isize {
+                let pix = *curr_row.offset(x);
+                let pix_diff = VP8LSubPixels(pix, pix_prev);
+                pix_prev = pix;
+                if pix_diff == 0 || (!prev_row.is_null() && pix == *prev_row.offset(x)) {
+                    continue;
+                }
+                AddSingle(pix, histo, kHistoAlpha, kHistoRed, kHistoGreen, kHistoBlue);
+                AddSingle(
+                    pix_diff,
+                    histo,
+                    kHistoAlphaPred,
+                    kHistoRedPred,
+                    kHistoGreenPred,
+                    kHistoBluePred,
+                );
+                {
+                    let (r, b) = histo[kHistoRedSubGreen * 256..].split_at_mut(512);
+                    AddSingleSubGreen(pix as c_int, r, b);
+                }
+                {
+                    let (r, b) = histo[kHistoRedPredSubGreen * 256..].split_at_mut(512);
+                    AddSingleSubGreen(pix_diff as c_int, r, b);
+                }
+                {
+                    // Approximate the palette by the entropy of the multiplicative hash.
+                    let hash = HashPix(pix);
+                    histo[kHistoPalette * 256 + hash as usize] += 1;
+                }
+            }
+            prev_row = curr_row;
+            curr_row = curr_row.offset(argb_stride as isize);
+        }
+        {
+            let mut entropy_comp = [0.0 as c_double; kHistoTotal];
+            let mut entropy = [0.0 as c_double; kNumEntropyIx as usize];
+            let last_mode_to_analyze = if use_palette {
+                kPalette
+            } else {
+                kSpatialSubGreen
+            };
+            // Let's add one zero to the predicted histograms. The zeros are removed
+            // too efficiently by the pix_diff == 0 comparison, at least one of the
+            // zeros is likely to exist.
+            histo[kHistoRedPredSubGreen * 256] += 1;
+            histo[kHistoBluePredSubGreen * 256] += 1;
+            histo[kHistoRedPred * 256] += 1;
+            histo[kHistoGreenPred * 256] += 1;
+            histo[kHistoBluePred * 256] += 1;
+            histo[kHistoAlphaPred * 256] += 1;
+
+            for j in 0..kHistoTotal {
+                entropy_comp[j] = VP8LBitsEntropy(histo[j * 256..].as_ptr(), 256);
+            }
+            entropy[kDirect as usize] = entropy_comp[kHistoAlpha]
+                + entropy_comp[kHistoRed]
+                + entropy_comp[kHistoGreen]
+                + entropy_comp[kHistoBlue];
+            entropy[kSpatial as usize] = entropy_comp[kHistoAlphaPred]
+                + entropy_comp[kHistoRedPred]
+                + entropy_comp[kHistoGreenPred]
+                + entropy_comp[kHistoBluePred];
+            entropy[kSubGreen as usize] = entropy_comp[kHistoAlpha]
+                + entropy_comp[kHistoRedSubGreen]
+                + entropy_comp[kHistoGreen]
+                + entropy_comp[kHistoBlueSubGreen];
+            entropy[kSpatialSubGreen as usize] = entropy_comp[kHistoAlphaPred]
+                + entropy_comp[kHistoRedPredSubGreen]
+                + entropy_comp[kHistoGreenPred]
+                + entropy_comp[kHistoBluePredSubGreen];
+            entropy[kPalette as usize] = entropy_comp[kHistoPalette];
+
+            let num_tiles = VP8LSubSampleSize(width as u32, transform_bits as u32)
+                .wrapping_mul(VP8LSubSampleSize(height as u32, transform_bits as u32));
+            // When including transforms, there is an overhead in bits from
+            // storing them. This overhead is small but matters for small images.
+            // For spatial, there are 14 transformations.
+            entropy[kSpatial as usize] += (num_tiles as c_float * VP8LFastLog2(14)) as c_double;
+            // For color transforms: 24 as only 3 channels are considered in a
+            // ColorTransformElement.
+            entropy[kSpatialSubGreen as usize] +=
+                (num_tiles as c_float * VP8LFastLog2(24)) as c_double;
+            // For palettes, add the cost of storing the palette.
+            // We empirically estimate the cost of a compressed entry as 8 bits.
+            // The palette is differential-coded when compressed hence a much
+            // lower cost than sizeof(uint32_t)*8.
+            entropy[kPalette as usize] += (palette_size * 8) as c_double;
+
+            *min_entropy_ix = kDirect;
+            for k in kDirect + 1..=last_mode_to_analyze {
+                if entropy[*min_entropy_ix as usize] > entropy[k as usize] {
+                    *min_entropy_ix = k;
+                }
+            }
+            c_assert!(*min_entropy_ix <= last_mode_to_analyze);
+            *red_and_blue_always_zero = 1;
+            // Let's check if the histogram of the chosen entropy mode has
+            // non-zero red and blue values. If all are zero, we can later skip
+            // the cross color optimization.
+            {
+                #[allow(non_upper_case_globals)]
+                const kHistoPairs: [[usize; 2]; 5] = [
+                    [kHistoRed, kHistoBlue],
+                    [kHistoRedPred, kHistoBluePred],
+                    [kHistoRedSubGreen, kHistoBlueSubGreen],
+                    [kHistoRedPredSubGreen, kHistoBluePredSubGreen],
+                    [kHistoRed, kHistoBlue],
+                ];
+                let red_histo = &histo[256 * kHistoPairs[*min_entropy_ix as usize][0]..];
+                let blue_histo = &histo[256 * kHistoPairs[*min_entropy_ix as usize][1]..];
+                for i in 1..256 {
+                    if (red_histo[i] | blue_histo[i]) != 0 {
+                        *red_and_blue_always_zero = 0;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    WebPSafeFree(histo_ptr as *mut c_void);
+    true
+}
+
+#[allow(non_snake_case)]
+fn GetHistoBits(method: c_int, use_palette: bool, width: c_int, height: c_int) -> c_int {
+    // Make tile size a function of encoding method (Range: 0 to 6).
+    let mut histo_bits = (if use_palette { 9 } else { 7 }) - method;
+    loop {
+        let huff_image_size = (VP8LSubSampleSize(width as u32, histo_bits as u32)
+            * VP8LSubSampleSize(height as u32, histo_bits as u32))
+            as c_int;
+        if huff_image_size <= MAX_HUFF_IMAGE_SIZE {
+            break;
+        }
+        histo_bits += 1;
+    }
+    histo_bits.clamp(MIN_HUFFMAN_BITS, MAX_HUFFMAN_BITS)
+}
+
+#[allow(non_snake_case)]
+fn GetTransformBits(method: c_int, histo_bits: c_int) -> c_int {
+    let max_transform_bits = if method < 4 {
+        6
+    } else if method > 4 {
+        4
+    } else {
+        5
+    };
+    let res = if histo_bits > max_transform_bits {
+        max_transform_bits
+    } else {
+        histo_bits
+    };
+    c_assert!(res <= MAX_TRANSFORM_BITS);
+    res
+}
+
+// Set of parameters to be used in each iteration of the cruncher.
+const CRUNCH_CONFIGS_LZ77_MAX: usize = 2;
+#[derive(Clone, Copy)]
+struct CrunchConfig {
+    entropy_idx_: c_int,
+    lz77s_types_to_try_: [c_int; CRUNCH_CONFIGS_LZ77_MAX],
+    lz77s_types_to_try_size_: c_int,
+}
+
+const CRUNCH_CONFIGS_MAX: usize = kNumEntropyIx as usize;
+
+#[allow(non_snake_case)]
+unsafe fn EncoderAnalyze(
+    enc: &mut VP8LEncoder,
+    crunch_configs: &mut [CrunchConfig; CRUNCH_CONFIGS_MAX],
+    crunch_configs_size: &mut c_int,
+    red_and_blue_always_zero: &mut c_int,
+) -> bool {
+    let pic = &*enc.pic_;
+    let width = pic.width;
+    let height = pic.height;
+    let config = &*enc.config_;
+    let method = config.method;
+    let low_effort = config.method == 0;
+    let n_lz77s;
+    c_assert!(!pic.argb.is_null());
+
+    let use_palette =
+        AnalyzeAndCreatePalette(pic, low_effort, &mut enc.palette_, &mut enc.palette_size_);
+
+    // Empirical bit sizes.
+    enc.histo_bits_ = GetHistoBits(method, use_palette, pic.width, pic.height);
+    enc.transform_bits_ = GetTransformBits(method, enc.histo_bits_);
+
+    if low_effort {
+        // AnalyzeEntropy is somewhat slow.
+        crunch_configs[0].entropy_idx_ = if use_palette {
+            kPalette
+        } else {
+            kSpatialSubGreen
+        };
+        n_lz77s = 1;
+        *crunch_configs_size = 1;
+    } else {
+        let mut min_entropy_ix = kDirect;
+        // Try out multiple LZ77 on images with few colors.
+        n_lz77s = if enc.palette_size_ > 0 && enc.palette_size_ <= 16 {
+            2
+        } else {
+            1
+        };
+        if !AnalyzeEntropy(
+            pic.argb,
+            width,
+            height,
+            pic.argb_stride,
+            use_palette,
+            enc.palette_size_,
+            enc.transform_bits_,
+            &mut min_entropy_ix,
+            red_and_blue_always_zero,
+        ) {
+            return false;
+        }
+        if method == 6 && config.quality == 100.0 {
+            // Go brute force on all transforms.
+            *crunch_configs_size = 0;
+            for i in 0..kNumEntropyIx {
+                if i != kPalette || use_palette {
+                    c_assert!((*crunch_configs_size as usize) < CRUNCH_CONFIGS_MAX);
+                    crunch_configs[*crunch_configs_size as usize].entropy_idx_ = i;
+                    *crunch_configs_size += 1;
+                }
+            }
+        } else {
+            // Only choose the guessed best transform.
+            *crunch_configs_size = 1;
+            crunch_configs[0].entropy_idx_ = min_entropy_ix;
+        }
+    }
+    // Fill in the different LZ77s.
+    c_assert!(n_lz77s <= CRUNCH_CONFIGS_LZ77_MAX);
+    for config in crunch_configs
+        .iter_mut()
+        .take(*crunch_configs_size as usize)
+    {
+        for j in 0..n_lz77s {
+            config.lz77s_types_to_try_[j] = if j == 0 {
+                kLZ77Standard | kLZ77RLE
+            } else {
+                kLZ77Box
+            };
+        }
+        config.lz77s_types_to_try_size_ = n_lz77s as c_int;
+    }
+    true
+}
+
+#[allow(non_snake_case)]
+unsafe fn EncoderInit(enc: &mut VP8LEncoder) -> bool {
+    let pic = &*enc.pic_;
+    let width = pic.width;
+    let height = pic.height;
+    let pix_cnt = width * height;
+    // we round the block size up, so we're guaranteed to have
+    // at most MAX_REFS_BLOCK_PER_IMAGE blocks used:
+    let refs_block_size = (pix_cnt - 1) / MAX_REFS_BLOCK_PER_IMAGE + 1;
+    if VP8LHashChainInit(&mut enc.hash_chain_, pix_cnt) == 0 {
+        return false;
+    }
+
+    for refs in &mut enc.refs_ {
+        VP8LBackwardRefsInit(refs, refs_block_size);
+    }
+
+    true
+}
+
+// Returns false in case of memory error.
+#[allow(non_snake_case)]
+unsafe fn GetHuffBitLengthsAndCodes(
+    histogram_image: &VP8LHistogramSet,
+    huffman_codes: *mut HuffmanTreeCode,
+) -> bool {
+    let mut ok = false;
+    let mut total_length_size: u64 = 0;
+    let mem_buf;
+    let histogram_image_size = histogram_image.size as usize;
+    let mut max_num_symbols = 0;
+    let mut buf_rle: *mut u8 = ptr::null_mut();
+    let mut huff_tree: *mut HuffmanTree = ptr::null_mut();
+
+    'end: {
+        // Iterate over all histograms and get the aggregate number of codes used.
+        for i in 0..histogram_image_size {
+            let histo = &**histogram_image.histograms.add(i);
+            let codes = huffman_codes.add(5 * i);
+            for k in 0..5 {
+                let num_symbols = if k == 0 {
+                    VP8LHistogramNumCodes(histo.palette_code_bits_)
+                } else if k == 4 {
+                    NUM_DISTANCE_CODES
+                } else {
+                    256
+                };
+                (*codes.add(k)).num_symbols = num_symbols;
+                total_length_size += num_symbols as u64;
+            }
+        }
+
+        // Allocate and Set Huffman codes.
+        {
+            mem_buf = WebPSafeCalloc(
+                total_length_size,
+                mem::size_of::<u8>() + mem::size_of::<u16>(),
+            ) as *mut u8;
+            if mem_buf.is_null() {
+                break 'end;
+            }
+
+            let mut codes = mem_buf as *mut u16;
+            let mut lengths = codes.add(total_length_size as usize) as *mut u8;
+            for i in 0..5 * histogram_image_size {
+                let bit_length = (*huffman_codes.add(i)).num_symbols;
+                (*huffman_codes.add(i)).codes = codes;
+                (*huffman_codes.add(i)).code_lengths = lengths;
+                codes = codes.offset(bit_length as isize);
+                lengths = lengths.offset(bit_length as isize);
+                if max_num_symbols < bit_length {
+                    max_num_symbols = bit_length;
+                }
+            }
+        }
+
+        buf_rle = WebPSafeMalloc(1, max_num_symbols as usize) as *mut u8;
+        huff_tree = WebPSafeMalloc(3 * max_num_symbols as u64, mem::size_of::<HuffmanTree>())
+            as *mut HuffmanTree;
+        if buf_rle.is_null() || huff_tree.is_null() {
+            break 'end;
+        }
+
+        // Create Huffman trees.
+        for i in 0..histogram_image_size {
+            let codes = huffman_codes.add(5 * i);
+            let histo = &mut **histogram_image.histograms.add(i);
+            VP8LCreateHuffmanTree(histo.literal_, 15, buf_rle, huff_tree, codes.add(0));
+            VP8LCreateHuffmanTree(
+                histo.red_.as_mut_ptr(),
+                15,
+                buf_rle,
+                huff_tree,
+                codes.add(1),
+            );
+            VP8LCreateHuffmanTree(
+                histo.blue_.as_mut_ptr(),
+                15,
+                buf_rle,
+                huff_tree,
+                codes.add(2),
+            );
+            VP8LCreateHuffmanTree(
+                histo.alpha_.as_mut_ptr(),
+                15,
+                buf_rle,
+                huff_tree,
+                codes.add(3),
+            );
+            VP8LCreateHuffmanTree(
+                histo.distance_.as_mut_ptr(),
+                15,
+                buf_rle,
+                huff_tree,
+                codes.add(4),
+            );
+        }
+        ok = true;
+    }
+    WebPSafeFree(huff_tree as *mut c_void);
+    WebPSafeFree(buf_rle as *mut c_void);
+    if !ok {
+        WebPSafeFree(mem_buf as *mut c_void);
+        ptr::write_bytes(huffman_codes, 0, 5 * histogram_image_size);
+    }
+    ok
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreHuffmanTreeOfHuffmanTreeToBitMask(
+    bw: *mut VP8LBitWriter,
+    code_length_bitdepth: &[u8; CODE_LENGTH_CODES as usize],
+) {
+    // RFC 1951 will calm you down if you are worried about this funny sequence.
+    // This sequence is tuned from that, but more weighted for lower symbol count,
+    // and more spiking histograms.
+    #[allow(non_upper_case_globals)]
+    const kStorageOrder: [u8; CODE_LENGTH_CODES as usize] = [
+        17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+    ];
+    // Throw away trailing zeros:
+    let mut codes_to_store = CODE_LENGTH_CODES as usize;
+    while codes_to_store > 4 {
+        if code_length_bitdepth[kStorageOrder[codes_to_store - 1] as usize] != 0 {
+            break;
+        }
+        codes_to_store -= 1;
+    }
+    VP8LPutBits(bw, codes_to_store as u32 - 4, 4);
+    for &order in &kStorageOrder[..codes_to_store] {
+        VP8LPutBits(bw, code_length_bitdepth[order as usize] as u32, 3);
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn ClearHuffmanTreeIfOnlyOneSymbol(huffman_code: &mut HuffmanTreeCode) {
+    let mut count = 0;
+    for k in 0..huffman_code.num_symbols as usize {
+        if *huffman_code.code_lengths.add(k) != 0 {
+            count += 1;
+            if count > 1 {
+                return;
+            }
+        }
+    }
+    for k in 0..huffman_code.num_symbols as usize {
+        *huffman_code.code_lengths.add(k) = 0;
+        *huffman_code.codes.add(k) = 0;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreHuffmanTreeToBitMask(
+    bw: *mut VP8LBitWriter,
+    tokens: *const HuffmanTreeToken,
+    num_tokens: c_int,
+    huffman_code: &HuffmanTreeCode,
+) {
+    for i in 0..num_tokens as usize {
+        let ix = (*tokens.add(i)).code as usize;
+        let extra_bits = (*tokens.add(i)).extra_bits as u32;
+        VP8LPutBits(
+            bw,
+            *huffman_code.codes.add(ix) as u32,
+            *huffman_code.code_lengths.add(ix) as c_int,
+        );
+        match ix {
+            16 => VP8LPutBits(bw, extra_bits, 2),
+            17 => VP8LPutBits(bw, extra_bits, 3),
+            18 => VP8LPutBits(bw, extra_bits, 7),
+            _ => {}
+        }
+    }
+}
+
+// 'huff_tree' and 'tokens' are pre-alloacted buffers.
+#[allow(non_snake_case)]
+unsafe fn StoreFullHuffmanCode(
+    bw: *mut VP8LBitWriter,
+    huff_tree: *mut HuffmanTree,
+    tokens: *mut HuffmanTreeToken,
+    tree: *const HuffmanTreeCode,
+) {
+    let mut code_length_bitdepth = [0u8; CODE_LENGTH_CODES as usize];
+    let mut code_length_bitdepth_symbols = [0u16; CODE_LENGTH_CODES as usize];
+    let max_tokens = (*tree).num_symbols;
+    let mut huffman_code = HuffmanTreeCode {
+        num_symbols: CODE_LENGTH_CODES,
+        code_lengths: code_length_bitdepth.as_mut_ptr(),
+        codes: code_length_bitdepth_symbols.as_mut_ptr(),
+    };
+
+    VP8LPutBits(bw, 0, 1);
+    let num_tokens = VP8LCreateCompressedHuffmanTree(tree, tokens, max_tokens);
+    {
+        let mut histogram = [0u32; CODE_LENGTH_CODES as usize];
+        let mut buf_rle = [0u8; CODE_LENGTH_CODES as usize];
+        for i in 0..num_tokens as usize {
+            histogram[(*tokens.add(i)).code as usize] += 1;
+        }
+
+        VP8LCreateHuffmanTree(
+            histogram.as_mut_ptr(),
+            7,
+            buf_rle.as_mut_ptr(),
+            huff_tree,
+            &mut huffman_code,
+        );
+    }
+
+    StoreHuffmanTreeOfHuffmanTreeToBitMask(bw, &*huffman_code.code_lengths.cast());
+    ClearHuffmanTreeIfOnlyOneSymbol(&mut huffman_code);
+    {
+        let mut trailing_zero_bits = 0;
+        let mut trimmed_length = num_tokens;
+        let mut i = num_tokens;
+        while i > 0 {
+            i -= 1;
+            let ix = (*tokens.offset(i as isize)).code as usize;
+            if ix == 0 || ix == 17 || ix == 18 {
+                trimmed_length -= 1; // discount trailing zeros
+                trailing_zero_bits += *huffman_code.code_lengths.add(ix) as c_int;
+                if ix == 17 {
+                    trailing_zero_bits += 3;
+                } else if ix == 18 {
+                    trailing_zero_bits += 7;
+                }
+            } else {
+                break;
+            }
+        }
+        let write_trimmed_length = trimmed_length > 1 && trailing_zero_bits > 12;
+        let length = if write_trimmed_length {
+            trimmed_length
+        } else {
+            num_tokens
+        };
+        VP8LPutBits(bw, write_trimmed_length as u32, 1);
+        if write_trimmed_length {
+            if trimmed_length == 2 {
+                VP8LPutBits(bw, 0, 3 + 2); // nbitpairs=1, trimmed_length=2
+            } else {
+                let nbits = BitsLog2Floor((trimmed_length - 2) as u32);
+                let nbitpairs = nbits / 2 + 1;
+                c_assert!(trimmed_length > 2);
+                c_assert!(nbitpairs - 1 < 8);
+                VP8LPutBits(bw, (nbitpairs - 1) as u32, 3);
+                VP8LPutBits(bw, (trimmed_length - 2) as u32, nbitpairs * 2);
+            }
+        }
+        StoreHuffmanTreeToBitMask(bw, tokens, length, &huffman_code);
+    }
+}
+
+// 'huff_tree' and 'tokens' are pre-alloacted buffers.
+#[allow(non_snake_case)]
+unsafe fn StoreHuffmanCode(
+    bw: *mut VP8LBitWriter,
+    huff_tree: *mut HuffmanTree,
+    tokens: *mut HuffmanTreeToken,
+    huffman_code: *const HuffmanTreeCode,
+) {
+    let mut count = 0;
+    let mut symbols = [0 as c_int; 2];
+    #[allow(non_upper_case_globals)]
+    const kMaxBits: c_int = 8;
+    #[allow(non_upper_case_globals)]
+    const kMaxSymbol: c_int = 1 << kMaxBits;
+
+    // Check whether it's a small tree.
+    let mut i = 0;
+    while i < (*huffman_code).num_symbols && count < 3 {
+        if *(*huffman_code).code_lengths.offset(i as isize) != 0 {
+            if count < 2 {
+                symbols[count] = i;
+            }
+            count += 1;
+        }
+        i += 1;
+    }
+
+    if count == 0 {
+        // emit minimal tree for empty cases
+        // bits: small tree marker: 1, count-1: 0, large 8-bit code: 0, code: 0
+        VP8LPutBits(bw, 0x01, 4);
+    } else if count <= 2 && symbols[0] < kMaxSymbol && symbols[1] < kMaxSymbol {
+        VP8LPutBits(bw, 1, 1); // Small tree marker to encode 1 or 2 symbols.
+        VP8LPutBits(bw, count as u32 - 1, 1);
+        if symbols[0] <= 1 {
+            VP8LPutBits(bw, 0, 1); // Code bit for small (1 bit) symbol value.
+            VP8LPutBits(bw, symbols[0] as u32, 1);
+        } else {
+            VP8LPutBits(bw, 1, 1);
+            VP8LPutBits(bw, symbols[0] as u32, 8);
+        }
+        if count == 2 {
+            VP8LPutBits(bw, symbols[1] as u32, 8);
+        }
+    } else {
+        StoreFullHuffmanCode(bw, huff_tree, tokens, huffman_code);
+    }
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn WriteHuffmanCode(
+    bw: *mut VP8LBitWriter,
+    code: *const HuffmanTreeCode,
+    code_index: c_int,
+) {
+    let depth = *(*code).code_lengths.offset(code_index as isize) as c_int;
+    let symbol = *(*code).codes.offset(code_index as isize) as u32;
+    VP8LPutBits(bw, symbol, depth);
+}
+
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn WriteHuffmanCodeWithExtraBits(
+    bw: *mut VP8LBitWriter,
+    code: *const HuffmanTreeCode,
+    code_index: c_int,
+    bits: c_int,
+    n_bits: c_int,
+) {
+    let depth = *(*code).code_lengths.offset(code_index as isize) as c_int;
+    let symbol = *(*code).codes.offset(code_index as isize) as c_int;
+    VP8LPutBits(bw, ((bits << depth) | symbol) as u32, depth + n_bits);
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreImageToBitMask(
+    bw: *mut VP8LBitWriter,
+    width: c_int,
+    histo_bits: c_int,
+    refs: *const VP8LBackwardRefs,
+    histogram_symbols: *const u16,
+    huffman_codes: *const HuffmanTreeCode,
+) -> WebPEncodingError {
+    let histo_xsize = if histo_bits != 0 {
+        VP8LSubSampleSize(width as u32, histo_bits as u32) as c_int
+    } else {
+        1
+    };
+    let tile_mask = if histo_bits == 0 {
+        0
+    } else {
+        -(1 << histo_bits)
+    };
+    // x and y trace the position in the image.
+    let mut x = 0;
+    let mut y = 0;
+    let mut tile_x = x & tile_mask;
+    let mut tile_y = y & tile_mask;
+    let mut histogram_ix = *histogram_symbols as usize;
+    let mut codes = huffman_codes.add(5 * histogram_ix);
+    let mut c = VP8LRefsCursorInit(refs);
+    while VP8LRefsCursorOk(&c) {
+        let v = &*c.cur_pos;
+        if tile_x != (x & tile_mask) || tile_y != (y & tile_mask) {
+            tile_x = x & tile_mask;
+            tile_y = y & tile_mask;
+            histogram_ix = *histogram_symbols
+                .offset(((y >> histo_bits) * histo_xsize + (x >> histo_bits)) as isize)
+                as usize;
+            codes = huffman_codes.add(5 * histogram_ix);
+        }
+        if PixOrCopyIsLiteral(v) {
+            #[allow(non_upper_case_globals)]
+            const order: [c_int; 4] = [1, 2, 0, 3];
+            for (k, &component) in order.iter().enumerate() {
+                let code = PixOrCopyLiteral(v, component) as c_int;
+                WriteHuffmanCode(bw, codes.add(k), code);
+            }
+        } else if PixOrCopyIsCacheIdx(v) {
+            let code = PixOrCopyCacheIdx(v) as c_int;
+            let literal_ix = 256 + NUM_LENGTH_CODES + code;
+            WriteHuffmanCode(bw, codes, literal_ix);
+        } else {
+            let mut bits = 0;
+            let mut n_bits = 0;
+            let mut code = 0;
+
+            let distance = PixOrCopyDistance(v) as c_int;
+            VP8LPrefixEncode(v.len as c_int, &mut code, &mut n_bits, &mut bits);
+            WriteHuffmanCodeWithExtraBits(bw, codes, 256 + code, bits, n_bits);
+
+            // Don't write the distance with the extra bits code since
+            // the distance can be up to 18 bits of extra bits, and the prefix
+            // 15 bits, totaling to 33, and our PutBits only supports up to 32 bits.
+            VP8LPrefixEncode(distance, &mut code, &mut n_bits, &mut bits);
+            WriteHuffmanCode(bw, codes.add(4), code);
+            VP8LPutBits(bw, bits as u32, n_bits);
+        }
+        x += PixOrCopyLength(v) as c_int;
+        while x >= width {
+            x -= width;
+            y += 1;
+        }
+        VP8LRefsCursorNext(&mut c);
+    }
+    if (*bw).error_ != 0 {
+        VP8_ENC_ERROR_OUT_OF_MEMORY
+    } else {
+        VP8_ENC_OK
+    }
+}
+
+// Special case of EncodeImageInternal() for cache-bits=0, histo_bits=31
+#[allow(non_snake_case)]
+unsafe fn EncodeImageNoHuffman(
+    bw: *mut VP8LBitWriter,
+    argb: *const u32,
+    hash_chain: *mut VP8LHashChain,
+    refs_tmp1: *mut VP8LBackwardRefs,
+    refs_tmp2: *mut VP8LBackwardRefs,
+    width: c_int,
+    height: c_int,
+    quality: c_int,
+    low_effort: c_int,
+) -> WebPEncodingError {
+    let mut max_tokens = 0;
+    let err;
+    let mut tokens: *mut HuffmanTreeToken = ptr::null_mut();
+    let mut huffman_codes = [HuffmanTreeCode {
+        num_symbols: 0,
+        code_lengths: ptr::null_mut(),
+        codes: ptr::null_mut(),
+    }; 5];
+    let histogram_symbols: [u16; 1] = [0]; // only one tree, one symbol
+    let mut cache_bits = 0;
+    let mut histogram_image: *mut VP8LHistogramSet = ptr::null_mut();
+    let huff_tree = WebPSafeMalloc(3 * CODE_LENGTH_CODES as u64, mem::size_of::<HuffmanTree>())
+        as *mut HuffmanTree;
+
+    'error: {
+        if huff_tree.is_null() {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        // Calculate backward references from ARGB image.
+        if VP8LHashChainFill(hash_chain, quality, argb, width, height, low_effort) == 0 {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+        let refs = VP8LGetBackwardReferences(
+            width,
+            height,
+            argb,
+            quality,
+            0,
+            kLZ77Standard | kLZ77RLE,
+            &mut cache_bits,
+            hash_chain,
+            refs_tmp1,
+            refs_tmp2,
+        );
+        if refs.is_null() {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+        histogram_image = VP8LAllocateHistogramSet(1, cache_bits);
+        if histogram_image.is_null() {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        // Build histogram image and symbols from backward references.
+        VP8LHistogramStoreRefs(refs, *(*histogram_image).histograms);
+
+        // Create Huffman bit lengths and codes for each histogram image.
+        c_assert!((*histogram_image).size == 1);
+        if !GetHuffBitLengthsAndCodes(&*histogram_image, huffman_codes.as_mut_ptr()) {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        // No color cache, no Huffman image.
+        VP8LPutBits(bw, 0, 1);
+
+        // Find maximum number of symbols for the huffman tree-set.
+        for codes in &huffman_codes {
+            if max_tokens < codes.num_symbols {
+                max_tokens = codes.num_symbols;
+            }
+        }
+
+        tokens = WebPSafeMalloc(max_tokens as u64, mem::size_of::<HuffmanTreeToken>())
+            as *mut HuffmanTreeToken;
+        if tokens.is_null() {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        // Store Huffman codes.
+        for codes in &mut huffman_codes {
+            StoreHuffmanCode(bw, huff_tree, tokens, codes);
+            ClearHuffmanTreeIfOnlyOneSymbol(codes);
+        }
+
+        // Store actual literals.
+        err = StoreImageToBitMask(
+            bw,
+            width,
+            0,
+            refs,
+            histogram_symbols.as_ptr(),
+            huffman_codes.as_ptr(),
+        );
+    }
+
+    WebPSafeFree(tokens as *mut c_void);
+    WebPSafeFree(huff_tree as *mut c_void);
+    VP8LFreeHistogramSet(histogram_image);
+    WebPSafeFree(huffman_codes[0].codes as *mut c_void);
+    err
+}
+
+#[allow(non_snake_case)]
+unsafe fn EncodeImageInternal(
+    bw: *mut VP8LBitWriter,
+    argb: *const u32,
+    hash_chain: *mut VP8LHashChain,
+    refs_array: &mut [VP8LBackwardRefs; 3],
+    width: c_int,
+    height: c_int,
+    quality: c_int,
+    low_effort: c_int,
+    use_cache: c_int,
+    config: &CrunchConfig,
+    cache_bits: &mut c_int,
+    histogram_bits: c_int,
+    init_byte_position: usize,
+    hdr_size: &mut c_int,
+    data_size: &mut c_int,
+) -> WebPEncodingError {
+    let mut err = VP8_ENC_OK;
+    let histogram_image_xysize = VP8LSubSampleSize(width as u32, histogram_bits as u32)
+        * VP8LSubSampleSize(height as u32, histogram_bits as u32);
+    let mut histogram_image: *mut VP8LHistogramSet = ptr::null_mut();
+    let mut tmp_histo: *mut VP8LHistogram = ptr::null_mut();
+    let huff_tree = WebPSafeMalloc(3 * CODE_LENGTH_CODES as u64, mem::size_of::<HuffmanTree>())
+        as *mut HuffmanTree;
+    let mut tokens: *mut HuffmanTreeToken = ptr::null_mut();
+    let mut huffman_codes: *mut HuffmanTreeCode = ptr::null_mut();
+    let histogram_symbols =
+        WebPSafeMalloc(histogram_image_xysize as u64, mem::size_of::<u16>()) as *mut u16;
+    let bw_init = *bw;
+    let mut bw_best: VP8LBitWriter = mem::zeroed();
+    c_assert!(histogram_bits >= MIN_HUFFMAN_BITS);
+    c_assert!(histogram_bits <= MAX_HUFFMAN_BITS);
+
+    'error: {
+        if histogram_symbols.is_null() {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        if use_cache != 0 {
+            // If the value is different from zero, it has been set during the
+            // palette analysis.
+            if *cache_bits == 0 {
+                *cache_bits = MAX_COLOR_CACHE_BITS;
+            }
+        } else {
+            *cache_bits = 0;
+        }
+        // 'best_refs' is the reference to the best backward refs and points to one
+        // of refs_array[0] or refs_array[1].
+        // Calculate backward references from ARGB image.
+        if huff_tree.is_null()
+            || VP8LHashChainFill(hash_chain, quality, argb, width, height, low_effort) == 0
+            || VP8LBitWriterInit(&mut bw_best, 0) == 0
+            || (config.lz77s_types_to_try_size_ > 1 && VP8LBitWriterClone(bw, &mut bw_best) == 0)
+        {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+        for lz77s_idx in 0..config.lz77s_types_to_try_size_ {
+            let refs_best = VP8LGetBackwardReferences(
+                width,
+                height,
+                argb,
+                quality,
+                low_effort,
+                config.lz77s_types_to_try_[lz77s_idx as usize],
+                cache_bits,
+                hash_chain,
+                &mut refs_array[0],
+                &mut refs_array[1],
+            );
+            if refs_best.is_null() {
+                err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                break 'error;
+            }
+            // Keep the best references aside and use the other element from the first
+            // two as a temporary for later usage.
+            let refs_tmp: *mut VP8LBackwardRefs =
+                &mut refs_array[if ptr::eq(refs_best, &refs_array[0]) {
+                    1
+                } else {
+                    0
+                }];
+
+            histogram_image =
+                VP8LAllocateHistogramSet(histogram_image_xysize as c_int, *cache_bits);
+            tmp_histo = VP8LAllocateHistogram(*cache_bits);
+            if histogram_image.is_null() || tmp_histo.is_null() {
+                err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                break 'error;
+            }
+
+            // Build histogram image and symbols from backward references.
+            if VP8LGetHistoImageSymbols(
+                width,
+                height,
+                refs_best,
+                quality,
+                low_effort,
+                histogram_bits,
+                *cache_bits,
+                histogram_image,
+                tmp_histo,
+                histogram_symbols,
+            ) == 0
+            {
+                err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                break 'error;
+            }
+            // Create Huffman bit lengths and codes for each histogram image.
+            let mut histogram_image_size = (*histogram_image).size;
+            let bit_array_size = 5 * histogram_image_size as usize;
+            huffman_codes = WebPSafeCalloc(bit_array_size as u64, mem::size_of::<HuffmanTreeCode>())
+                as *mut HuffmanTreeCode;
+            // Note: some histogram_image entries may point to tmp_histos[], so the
+            // latter need to outlive the following call to GetHuffBitLengthsAndCodes().
+            if huffman_codes.is_null()
+                || !GetHuffBitLengthsAndCodes(&*histogram_image, huffman_codes)
+            {
+                err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                break 'error;
+            }
+            // Free combined histograms.
+            VP8LFreeHistogramSet(histogram_image);
+            histogram_image = ptr::null_mut();
+
+            // Free scratch histograms.
+            VP8LFreeHistogram(tmp_histo);
+            tmp_histo = ptr::null_mut();
+
+            // Color Cache parameters.
+            if *cache_bits > 0 {
+                VP8LPutBits(bw, 1, 1);
+                VP8LPutBits(bw, *cache_bits as u32, 4);
+            } else {
+                VP8LPutBits(bw, 0, 1);
+            }
+
+            // Huffman image + meta huffman.
+            {
+                let write_histogram_image = histogram_image_size > 1;
+                VP8LPutBits(bw, write_histogram_image as u32, 1);
+                if write_histogram_image {
+                    let histogram_argb =
+                        WebPSafeMalloc(histogram_image_xysize as u64, mem::size_of::<u32>())
+                            as *mut u32;
+                    let mut max_index = 0;
+                    if histogram_argb.is_null() {
+                        err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                        break 'error;
+                    }
+                    for i in 0..histogram_image_xysize as usize {
+                        let symbol_index = (*histogram_symbols.add(i) & 0xffff) as c_int;
+                        *histogram_argb.add(i) = (symbol_index << 8) as u32;
+                        if symbol_index >= max_index {
+                            max_index = symbol_index + 1;
+                        }
+                    }
+                    histogram_image_size = max_index;
+
+                    VP8LPutBits(bw, (histogram_bits - 2) as u32, 3);
+                    err = EncodeImageNoHuffman(
+                        bw,
+                        histogram_argb,
+                        hash_chain,
+                        refs_tmp,
+                        &mut refs_array[2],
+                        VP8LSubSampleSize(width as u32, histogram_bits as u32) as c_int,
+                        VP8LSubSampleSize(height as u32, histogram_bits as u32) as c_int,
+                        quality,
+                        low_effort,
+                    );
+                    WebPSafeFree(histogram_argb as *mut c_void);
+                    if err != VP8_ENC_OK {
+                        break 'error;
+                    }
+                }
+            }
+
+            // Store Huffman codes.
+            {
+                let mut max_tokens = 0;
+                // Find maximum number of symbols for the huffman tree-set.
+                for i in 0..5 * histogram_image_size as usize {
+                    let codes = &*huffman_codes.add(i);
+                    if max_tokens < codes.num_symbols {
+                        max_tokens = codes.num_symbols;
+                    }
+                }
+                tokens = WebPSafeMalloc(max_tokens as u64, mem::size_of::<HuffmanTreeToken>())
+                    as *mut HuffmanTreeToken;
+                if tokens.is_null() {
+                    err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                    break 'error;
+                }
+                for i in 0..5 * histogram_image_size as usize {
+                    let codes = &mut *huffman_codes.add(i);
+                    StoreHuffmanCode(bw, huff_tree, tokens, codes);
+                    ClearHuffmanTreeIfOnlyOneSymbol(codes);
+                }
+            }
+            // Store actual literals.
+            let hdr_size_tmp = (VP8LBitWriterNumBytes(&*bw) - init_byte_position) as c_int;
+            err = StoreImageToBitMask(
+                bw,
+                width,
+                histogram_bits,
+                refs_best,
+                histogram_symbols,
+                huffman_codes,
+            );
+            // Keep track of the smallest image so far.
+            if lz77s_idx == 0 || VP8LBitWriterNumBytes(&*bw) < VP8LBitWriterNumBytes(&bw_best) {
+                *hdr_size = hdr_size_tmp;
+                *data_size = (VP8LBitWriterNumBytes(&*bw) - init_byte_position - *hdr_size as usize)
+                    as c_int;
+                VP8LBitWriterSwap(bw, &mut bw_best);
+            }
+            // Reset the bit writer for the following iteration if any.
+            if config.lz77s_types_to_try_size_ > 1 {
+                VP8LBitWriterReset(&bw_init, bw);
+            }
+            WebPSafeFree(tokens as *mut c_void);
+            tokens = ptr::null_mut();
+            if !huffman_codes.is_null() {
+                WebPSafeFree((*huffman_codes).codes as *mut c_void);
+                WebPSafeFree(huffman_codes as *mut c_void);
+                huffman_codes = ptr::null_mut();
+            }
+        }
+        VP8LBitWriterSwap(bw, &mut bw_best);
+    }
+
+    WebPSafeFree(tokens as *mut c_void);
+    WebPSafeFree(huff_tree as *mut c_void);
+    VP8LFreeHistogramSet(histogram_image);
+    VP8LFreeHistogram(tmp_histo);
+    if !huffman_codes.is_null() {
+        WebPSafeFree((*huffman_codes).codes as *mut c_void);
+        WebPSafeFree(huffman_codes as *mut c_void);
+    }
+    WebPSafeFree(histogram_symbols as *mut c_void);
+    VP8LBitWriterWipeOut(&mut bw_best);
+    err
+}
+
+// -----------------------------------------------------------------------------
+// Transforms
+
+#[allow(non_snake_case)]
+unsafe fn ApplySubtractGreen(
+    enc: &mut VP8LEncoder,
+    width: c_int,
+    height: c_int,
+    bw: *mut VP8LBitWriter,
+) {
+    VP8LPutBits(bw, TRANSFORM_PRESENT, 1);
+    VP8LPutBits(bw, SUBTRACT_GREEN as u32, 2);
+    VP8LSubtractGreenFromBlueAndRed.unwrap()(enc.argb_, width * height);
+}
+
+#[allow(non_snake_case)]
+unsafe fn ApplyPredictFilter(
+    enc: &mut VP8LEncoder,
+    width: c_int,
+    height: c_int,
+    quality: c_int,
+    low_effort: c_int,
+    used_subtract_green: c_int,
+    bw: *mut VP8LBitWriter,
+) -> WebPEncodingError {
+    let pred_bits = enc.transform_bits_;
+    let transform_width = VP8LSubSampleSize(width as u32, pred_bits as u32) as c_int;
+    let transform_height = VP8LSubSampleSize(height as u32, pred_bits as u32) as c_int;
+    // we disable near-lossless quantization if palette is used.
+    let near_lossless_strength = if enc.use_palette_ != 0 {
+        100
+    } else {
+        (*enc.config_).near_lossless
+    };
+
+    VP8LResidualImage(
+        width,
+        height,
+        pred_bits,
+        low_effort,
+        enc.argb_,
+        enc.argb_scratch_,
+        enc.transform_data_,
+        near_lossless_strength,
+        (*enc.config_).exact,
+        used_subtract_green,
+    );
+    VP8LPutBits(bw, TRANSFORM_PRESENT, 1);
+    VP8LPutBits(bw, PREDICTOR_TRANSFORM as u32, 2);
+    c_assert!(pred_bits >= 2);
+    VP8LPutBits(bw, (pred_bits - 2) as u32, 3);
+    let (refs_0, refs_1) = enc.refs_.split_at_mut(1);
+    EncodeImageNoHuffman(
+        bw,
+        enc.transform_data_,
+        &mut enc.hash_chain_,
+        &mut refs_0[0],
+        &mut refs_1[0],
+        transform_width,
+        transform_height,
+        quality,
+        low_effort,
+    )
+}
+
+#[allow(non_snake_case)]
+unsafe fn ApplyCrossColorFilter(
+    enc: &mut VP8LEncoder,
+    width: c_int,
+    height: c_int,
+    quality: c_int,
+    low_effort: c_int,
+    bw: *mut VP8LBitWriter,
+) -> WebPEncodingError {
+    let ccolor_transform_bits = enc.transform_bits_;
+    let transform_width = VP8LSubSampleSize(width as u32, ccolor_transform_bits as u32) as c_int;
+    let transform_height = VP8LSubSampleSize(height as u32, ccolor_transform_bits as u32) as c_int;
+
+    VP8LColorSpaceTransform(
+        width,
+        height,
+        ccolor_transform_bits,
+        quality,
+        enc.argb_,
+        enc.transform_data_,
+    );
+    VP8LPutBits(bw, TRANSFORM_PRESENT, 1);
+    VP8LPutBits(bw, CROSS_COLOR_TRANSFORM as u32, 2);
+    c_assert!(ccolor_transform_bits >= 2);
+    VP8LPutBits(bw, (ccolor_transform_bits - 2) as u32, 3);
+    let (refs_0, refs_1) = enc.refs_.split_at_mut(1);
+    EncodeImageNoHuffman(
+        bw,
+        enc.transform_data_,
+        &mut enc.hash_chain_,
+        &mut refs_0[0],
+        &mut refs_1[0],
+        transform_width,
+        transform_height,
+        quality,
+        low_effort,
+    )
+}
+
+// -----------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn WriteRiffHeader(
+    pic: *const WebPPicture,
+    riff_size: usize,
+    vp8l_size: usize,
+) -> WebPEncodingError {
+    let mut riff: [u8; RIFF_HEADER_SIZE + CHUNK_HEADER_SIZE + VP8L_SIGNATURE_SIZE] = [
+        b'R',
+        b'I',
+        b'F',
+        b'F',
+        0,
+        0,
+        0,
+        0,
+        b'W',
+        b'E',
+        b'B',
+        b'P',
+        b'V',
+        b'P',
+        b'8',
+        b'L',
+        0,
+        0,
+        0,
+        0,
+        VP8L_MAGIC_BYTE,
+    ];
+    riff[TAG_SIZE..TAG_SIZE + 4].copy_from_slice(&(riff_size as u32).to_le_bytes());
+    riff[RIFF_HEADER_SIZE + TAG_SIZE..RIFF_HEADER_SIZE + TAG_SIZE + 4]
+        .copy_from_slice(&(vp8l_size as u32).to_le_bytes());
+    if (*pic).writer.unwrap()(riff.as_ptr(), riff.len(), pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+    VP8_ENC_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn WriteImageSize(pic: &WebPPicture, bw: *mut VP8LBitWriter) -> bool {
+    let width = pic.width - 1;
+    let height = pic.height - 1;
+    c_assert!(width < WEBP_MAX_DIMENSION && height < WEBP_MAX_DIMENSION);
+
+    VP8LPutBits(bw, width as u32, VP8L_IMAGE_SIZE_BITS);
+    VP8LPutBits(bw, height as u32, VP8L_IMAGE_SIZE_BITS);
+    (*bw).error_ == 0
+}
+
+#[allow(non_snake_case)]
+unsafe fn WriteRealAlphaAndVersion(bw: *mut VP8LBitWriter, has_alpha: c_int) -> bool {
+    VP8LPutBits(bw, has_alpha as u32, 1);
+    VP8LPutBits(bw, VP8L_VERSION, VP8L_VERSION_BITS);
+    (*bw).error_ == 0
+}
+
+#[allow(non_snake_case)]
+unsafe fn WriteImage(
+    pic: *const WebPPicture,
+    bw: *mut VP8LBitWriter,
+    coded_size: &mut usize,
+) -> WebPEncodingError {
+    let webpll_data = VP8LBitWriterFinish(bw);
+    let webpll_size = VP8LBitWriterNumBytes(&*bw);
+    let vp8l_size = VP8L_SIGNATURE_SIZE + webpll_size;
+    let pad = vp8l_size & 1;
+    let riff_size = TAG_SIZE + CHUNK_HEADER_SIZE + vp8l_size + pad;
+
+    let err = WriteRiffHeader(pic, riff_size, vp8l_size);
+    if err != VP8_ENC_OK {
+        return err;
+    }
+
+    if (*pic).writer.unwrap()(webpll_data, webpll_size, pic) == 0 {
+        return VP8_ENC_ERROR_BAD_WRITE;
+    }
+
+    if pad != 0 {
+        let pad_byte: [u8; 1] = [0];
+        if (*pic).writer.unwrap()(pad_byte.as_ptr(), 1, pic) == 0 {
+            return VP8_ENC_ERROR_BAD_WRITE;
+        }
+    }
+    *coded_size = CHUNK_HEADER_SIZE + riff_size;
+    VP8_ENC_OK
+}
+
+// -----------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn ClearTransformBuffer(enc: &mut VP8LEncoder) {
+    WebPSafeFree(enc.transform_mem_ as *mut c_void);
+    enc.transform_mem_ = ptr::null_mut();
+    enc.transform_mem_size_ = 0;
+}
+
+// Allocates the memory for argb (W x H) buffer, 2 rows of context for
+// prediction and transform data.
+// Flags influencing the memory allocated:
+//  enc->transform_bits_
+//  enc->use_predict_, enc->use_cross_color_
+#[allow(non_snake_case)]
+unsafe fn AllocateTransformBuffer(
+    enc: &mut VP8LEncoder,
+    width: c_int,
+    height: c_int,
+) -> WebPEncodingError {
+    let image_size = (width * height) as u64;
+    // VP8LResidualImage needs room for 2 scanlines of uint32 pixels with an extra
+    // pixel in each, plus 2 regular scanlines of bytes.
+    // TODO(skal): Clean up by using arithmetic in bytes instead of words.
+    let argb_scratch_size: u64 = if enc.use_predict_ != 0 {
+        ((width + 1) * 2) as u64
+            + (width as u64 * 2 + mem::size_of::<u32>() as u64 - 1) / mem::size_of::<u32>() as u64
+    } else {
+        0
+    };
+    let transform_data_size: u64 = if enc.use_predict_ != 0 || enc.use_cross_color_ != 0 {
+        (VP8LSubSampleSize(width as u32, enc.transform_bits_ as u32)
+            * VP8LSubSampleSize(height as u32, enc.transform_bits_ as u32)) as u64
+    } else {
+        0
+    };
+    let max_alignment_in_words =
+        ((WEBP_ALIGN_CST + mem::size_of::<u32>() - 1) / mem::size_of::<u32>()) as u64;
+    let mem_size = image_size
+        + max_alignment_in_words
+        + argb_scratch_size
+        + max_alignment_in_words
+        + transform_data_size;
+    let mut mem = enc.transform_mem_;
+    if mem.is_null() || mem_size > enc.transform_mem_size_ as u64 {
+        ClearTransformBuffer(enc);
+        mem = WebPSafeMalloc(mem_size, mem::size_of::<u32>()) as *mut u32;
+        if mem.is_null() {
+            return VP8_ENC_ERROR_OUT_OF_MEMORY;
+        }
+        enc.transform_mem_ = mem;
+        enc.transform_mem_size_ = mem_size as usize;
+        enc.argb_content_ = kEncoderNone;
+    }
+    enc.argb_ = mem;
+    mem = WEBP_ALIGN(mem.add(image_size as usize));
+    enc.argb_scratch_ = mem;
+    mem = WEBP_ALIGN(mem.add(argb_scratch_size as usize));
+    enc.transform_data_ = mem;
+
+    enc.current_width_ = width;
+    VP8_ENC_OK
+}
+
+#[allow(non_snake_case)]
+unsafe fn MakeInputImageCopy(enc: &mut VP8LEncoder) -> WebPEncodingError {
+    let picture = &*enc.pic_;
+    let width = picture.width;
+    let height = picture.height;
+    let err = AllocateTransformBuffer(enc, width, height);
+    if err != VP8_ENC_OK {
+        return err;
+    }
+    if enc.argb_content_ == kEncoderARGB {
+        return VP8_ENC_OK;
+    }
+    for y in 0..height {
+        ptr::copy_nonoverlapping(
+            picture.argb.offset((y * picture.argb_stride) as isize),
+            enc.argb_.offset((y * width) as isize),
+            width as usize,
+        );
+    }
+    enc.argb_content_ = kEncoderARGB;
+    c_assert!(enc.current_width_ == width);
+    VP8_ENC_OK
+}
+
+// -----------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+#[inline]
+fn SearchColorNoIdx(sorted: &[u32], color: u32, mut hi: usize) -> usize {
+    let mut low = 0;
+    if sorted[low] == color {
+        return low; // loop invariant: sorted[low] != color
+    }
+    loop {
+        let mid = (low + hi) >> 1;
+        if sorted[mid] == color {
+            return mid;
+        } else if sorted[mid] < color {
+            low = mid;
+        } else {
+            hi = mid;
+        }
+    }
+}
+
+const APPLY_PALETTE_GREEDY_MAX: c_int = 4;
+
+#[allow(non_snake_case)]
+#[inline]
+fn SearchColorGreedy(palette: &[u32], palette_size: c_int, color: u32) -> u32 {
+    let _ = palette_size;
+    c_assert!(palette_size < APPLY_PALETTE_GREEDY_MAX);
+    c_assert!(3 == APPLY_PALETTE_GREEDY_MAX - 1);
+    if color == palette[0] {
+        return 0;
+    }
+    if color == palette[1] {
+        return 1;
+    }
+    if color == palette[2] {
+        return 2;
+    }
+    3
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ApplyPaletteHash0(color: u32) -> u32 {
+    // Focus on the green color.
+    (color >> 8) & 0xff
+}
+
+const PALETTE_INV_SIZE_BITS: u32 = 11;
+const PALETTE_INV_SIZE: usize = 1 << PALETTE_INV_SIZE_BITS;
+
+#[allow(non_snake_case)]
+#[inline]
+fn ApplyPaletteHash1(color: u32) -> u32 {
+    // Forget about alpha.
+    (((color & 0x00ffffff) as u64).wrapping_mul(4222244071) as u32) >> (32 - PALETTE_INV_SIZE_BITS)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn ApplyPaletteHash2(color: u32) -> u32 {
+    // Forget about alpha.
+    (((color & 0x00ffffff) as u64).wrapping_mul((1u64 << 31) - 1) as u32)
+        >> (32 - PALETTE_INV_SIZE_BITS)
+}
+
+// Sort palette in increasing order and prepare an inverse mapping array.
+#[allow(non_snake_case)]
+fn PrepareMapToPalette(palette: &[u32], sorted: &mut [u32], idx_map: &mut [u32]) {
+    let num_colors = palette.len();
+    sorted[..num_colors].copy_from_slice(palette);
+    sorted[..num_colors].sort_unstable();
+    for (i, &color) in palette.iter().enumerate() {
+        idx_map[SearchColorNoIdx(sorted, color, num_colors)] = i as u32;
+    }
+}
+
+// Use 1 pixel cache for ARGB pixels.
+#[allow(non_snake_case)]
+unsafe fn ApplyPaletteFor<F: FnMut(u32) -> u32>(
+    mut src: *const u32,
+    src_stride: u32,
+    mut dst: *mut u32,
+    dst_stride: u32,
+    palette: &[u32],
+    width: c_int,
+    height: c_int,
+    xbits: c_int,
+    tmp_row: *mut u8,
+    mut color_index: F,
+) {
+    let mut prev_pix = palette[0];
+    let mut prev_idx = 0;
+    for _ in 0..height {
+        for x in 0..width as isize {
+            let pix = *src.offset(x);
+            if pix != prev_pix {
+                prev_idx = color_index(pix);
+                prev_pix = pix;
+            }
+            *tmp_row.offset(x) = prev_idx as u8;
+        }
+        VP8LBundleColorMap.unwrap()(tmp_row, width, xbits, dst);
+        src = src.add(src_stride as usize);
+        dst = dst.add(dst_stride as usize);
+    }
+}
+
+// Remap argb values in src[] to packed palettes entries in dst[]
+// using 'row' as a temporary buffer of size 'width'.
+// We assume that all src[] values have a corresponding entry in the palette.
+// Note: src[] can be the same as dst[]
+#[allow(non_snake_case)]
+unsafe fn ApplyPalette(
+    src: *const u32,
+    src_stride: u32,
+    dst: *mut u32,
+    dst_stride: u32,
+    palette: &[u32],
+    palette_size: c_int,
+    width: c_int,
+    height: c_int,
+    xbits: c_int,
+) -> WebPEncodingError {
+    // TODO(skal): this tmp buffer is not needed if VP8LBundleColorMap() can be
+    // made to work in-place.
+    let tmp_row = WebPSafeMalloc(width as u64, mem::size_of::<u8>()) as *mut u8;
+
+    if tmp_row.is_null() {
+        return VP8_ENC_ERROR_OUT_OF_MEMORY;
+    }
+
+    if palette_size < APPLY_PALETTE_GREEDY_MAX {
+        ApplyPaletteFor(
+            src,
+            src_stride,
+            dst,
+            dst_stride,
+            palette,
+            width,
+            height,
+            xbits,
+            tmp_row,
+            |pix| SearchColorGreedy(palette, palette_size, pix),
+        );
+    } else {
+        let mut buffer = [0u16; PALETTE_INV_SIZE];
+        let hash_functions: [fn(u32) -> u32; 3] =
+            [ApplyPaletteHash0, ApplyPaletteHash1, ApplyPaletteHash2];
+
+        // Try to find a perfect hash function able to go from a color to an index
+        // within 1 << PALETTE_INV_SIZE_BITS in order to build a hash map to go
+        // from color to index in palette.
+        let mut i = 0;
+        while i < 3 {
+            let mut use_LUT = true;
+            // Set each element in buffer to max uint16_t.
+            buffer = [0xffff; PALETTE_INV_SIZE];
+            for (j, &color) in palette[..palette_size as usize].iter().enumerate() {
+                let ind = hash_functions[i](color) as usize;
+                if buffer[ind] != 0xffff {
+                    use_LUT = false;
+                    break;
+                } else {
+                    buffer[ind] = j as u16;
+                }
+            }
+            if use_LUT {
+                break;
+            }
+            i += 1;
+        }
+
+        if i == 0 {
+            ApplyPaletteFor(
+                src,
+                src_stride,
+                dst,
+                dst_stride,
+                palette,
+                width,
+                height,
+                xbits,
+                tmp_row,
+                |pix| buffer[ApplyPaletteHash0(pix) as usize] as u32,
+            );
+        } else if i == 1 {
+            ApplyPaletteFor(
+                src,
+                src_stride,
+                dst,
+                dst_stride,
+                palette,
+                width,
+                height,
+                xbits,
+                tmp_row,
+                |pix| buffer[ApplyPaletteHash1(pix) as usize] as u32,
+            );
+        } else if i == 2 {
+            ApplyPaletteFor(
+                src,
+                src_stride,
+                dst,
+                dst_stride,
+                palette,
+                width,
+                height,
+                xbits,
+                tmp_row,
+                |pix| buffer[ApplyPaletteHash2(pix) as usize] as u32,
+            );
+        } else {
+            let mut idx_map = [0u32; MAX_PALETTE_SIZE];
+            let mut palette_sorted = [0u32; MAX_PALETTE_SIZE];
+            PrepareMapToPalette(
+                &palette[..palette_size as usize],
+                &mut palette_sorted,
+                &mut idx_map,
+            );
+            ApplyPaletteFor(
+                src,
+                src_stride,
+                dst,
+                dst_stride,
+                palette,
+                width,
+                height,
+                xbits,
+                tmp_row,
+                |pix| idx_map[SearchColorNoIdx(&palette_sorted, pix, palette_size as usize)],
+            );
+        }
+    }
+    WebPSafeFree(tmp_row as *mut c_void);
+    VP8_ENC_OK
+}
+
+// Note: Expects "enc->palette_" to be set properly.
+#[allow(non_snake_case)]
+unsafe fn MapImageFromPalette(enc: &mut VP8LEncoder, in_place: bool) -> WebPEncodingError {
+    let pic = &*enc.pic_;
+    let width = pic.width;
+    let height = pic.height;
+    let src = if in_place { enc.argb_ } else { pic.argb };
+    let src_stride = if in_place {
+        enc.current_width_
+    } else {
+        pic.argb_stride
+    };
+    let palette_size = enc.palette_size_;
+
+    // Replace each input pixel by corresponding palette index.
+    // This is done line by line.
+    let xbits = if palette_size <= 4 {
+        if palette_size <= 2 {
+            3
+        } else {
+            2
+        }
+    } else if palette_size <= 16 {
+        1
+    } else {
+        0
+    };
+
+    let err = AllocateTransformBuffer(
+        enc,
+        VP8LSubSampleSize(width as u32, xbits as u32) as c_int,
+        height,
+    );
+    if err != VP8_ENC_OK {
+        return err;
+    }
+
+    let err = ApplyPalette(
+        src,
+        src_stride as u32,
+        enc.argb_,
+        enc.current_width_ as u32,
+        &enc.palette_,
+        palette_size,
+        width,
+        height,
+        xbits,
+    );
+    enc.argb_content_ = kEncoderPalette;
+    err
+}
+
+// Save palette_[] to bitstream.
+#[allow(non_snake_case)]
+unsafe fn EncodePalette(
+    bw: *mut VP8LBitWriter,
+    low_effort: c_int,
+    enc: &mut VP8LEncoder,
+) -> WebPEncodingError {
+    let mut tmp_palette = [0u32; MAX_PALETTE_SIZE];
+    let palette_size = enc.palette_size_ as usize;
+    let palette = &enc.palette_;
+    VP8LPutBits(bw, TRANSFORM_PRESENT, 1);
+    VP8LPutBits(bw, COLOR_INDEXING_TRANSFORM as u32, 2);
+    c_assert!(palette_size >= 1 && palette_size <= MAX_PALETTE_SIZE);
+    VP8LPutBits(bw, palette_size as u32 - 1, 8);
+    for i in (1..palette_size).rev() {
+        tmp_palette[i] = VP8LSubPixels(palette[i], palette[i - 1]);
+    }
+    tmp_palette[0] = palette[0];
+    let (refs_0, refs_1) = enc.refs_.split_at_mut(1);
+    EncodeImageNoHuffman(
+        bw,
+        tmp_palette.as_ptr(),
+        &mut enc.hash_chain_,
+        &mut refs_0[0],
+        &mut refs_1[0],
+        palette_size as c_int,
+        1,
+        20, // quality
+        low_effort,
+    )
+}
+
+// -----------------------------------------------------------------------------
+// VP8LEncoder
+
+#[allow(non_snake_case)]
+unsafe fn VP8LEncoderNew(
+    config: *const WebPConfig,
+    picture: *const WebPPicture,
+) -> *mut VP8LEncoder {
+    let enc = WebPSafeCalloc(1, mem::size_of::<VP8LEncoder>()) as *mut VP8LEncoder;
+    if enc.is_null() {
+        WebPEncodingSetError(picture, VP8_ENC_ERROR_OUT_OF_MEMORY);
+        return ptr::null_mut();
+    }
+    (*enc).config_ = config;
+    (*enc).pic_ = picture;
+    (*enc).argb_content_ = kEncoderNone;
+
+    VP8LEncDspInit();
+
+    enc
+}
+
+#[allow(non_snake_case)]
+unsafe fn VP8LEncoderDelete(enc: *mut VP8LEncoder) {
+    if !enc.is_null() {
+        VP8LHashChainClear(&mut (*enc).hash_chain_);
+        for refs in &mut (*enc).refs_ {
+            VP8LBackwardRefsClear(refs);
+        }
+        ClearTransformBuffer(&mut *enc);
+        WebPSafeFree(enc as *mut c_void);
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Main call
+
+#[repr(C)]
+struct StreamEncodeContext {
+    config_: *const WebPConfig,
+    picture_: *const WebPPicture,
+    bw_: *mut VP8LBitWriter,
+    enc_: *mut VP8LEncoder,
+    use_cache_: c_int,
+    crunch_configs_: [CrunchConfig; CRUNCH_CONFIGS_MAX],
+    num_crunch_configs_: c_int,
+    red_and_blue_always_zero_: c_int,
+    err_: WebPEncodingError,
+    stats_: *mut WebPAuxStats,
+}
+
+#[allow(non_snake_case)]
+extern "C-unwind" fn EncodeStreamHook(input: *mut c_void, _data2: *mut c_void) -> c_int {
+    unsafe {
+        let params = &mut *(input as *mut StreamEncodeContext);
+        let config = &*params.config_;
+        let picture = params.picture_;
+        let bw = params.bw_;
+        let enc = &mut *params.enc_;
+        let use_cache = params.use_cache_;
+        let crunch_configs = &params.crunch_configs_;
+        let num_crunch_configs = params.num_crunch_configs_;
+        let red_and_blue_always_zero = params.red_and_blue_always_zero_;
+        let stats = params.stats_;
+        let mut err = VP8_ENC_OK;
+        let quality = config.quality as c_int;
+        let low_effort = (config.method == 0) as c_int;
+        let width = (*picture).width;
+        let height = (*picture).height;
+        let byte_position = VP8LBitWriterNumBytes(&*bw);
+        let mut hdr_size = 0;
+        let mut data_size = 0;
+        let use_delta_palette = false;
+        let mut best_size = 0;
+        let bw_init = *bw;
+        let mut bw_best: VP8LBitWriter = mem::zeroed();
+
+        'error: {
+            if VP8LBitWriterInit(&mut bw_best, 0) == 0
+                || (num_crunch_configs > 1 && VP8LBitWriterClone(bw, &mut bw_best) == 0)
+            {
+                err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                break 'error;
+            }
+
+            for (idx, crunch_config) in crunch_configs[..num_crunch_configs as usize]
+                .iter()
+                .enumerate()
+            {
+                let entropy_idx = crunch_config.entropy_idx_;
+                enc.use_palette_ = (entropy_idx == kPalette) as c_int;
+                enc.use_subtract_green_ =
+                    (entropy_idx == kSubGreen || entropy_idx == kSpatialSubGreen) as c_int;
+                enc.use_predict_ =
+                    (entropy_idx == kSpatial || entropy_idx == kSpatialSubGreen) as c_int;
+                if low_effort != 0 {
+                    enc.use_cross_color_ = 0;
+                } else {
+                    enc.use_cross_color_ = if red_and_blue_always_zero != 0 {
+                        0
+                    } else {
+                        enc.use_predict_
+                    };
+                }
+                // Reset any parameter in the encoder that is set in the previous iteration.
+                enc.cache_bits_ = 0;
+                VP8LBackwardRefsClear(&mut enc.refs_[0]);
+                VP8LBackwardRefsClear(&mut enc.refs_[1]);
+
+                // Apply near-lossless preprocessing.
+                let use_near_lossless =
+                    config.near_lossless < 100 && enc.use_palette_ == 0 && enc.use_predict_ == 0;
+                if use_near_lossless {
+                    err = AllocateTransformBuffer(enc, width, height);
+                    if err != VP8_ENC_OK {
+                        break 'error;
+                    }
+                    if enc.argb_content_ != kEncoderNearLossless
+                        && VP8ApplyNearLossless(picture, config.near_lossless, enc.argb_) == 0
+                    {
+                        err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                        break 'error;
+                    }
+                    enc.argb_content_ = kEncoderNearLossless;
+                } else {
+                    enc.argb_content_ = kEncoderNone;
+                }
+
+                // Encode palette
+                if enc.use_palette_ != 0 {
+                    err = EncodePalette(bw, low_effort, enc);
+                    if err != VP8_ENC_OK {
+                        break 'error;
+                    }
+                    err = MapImageFromPalette(enc, use_delta_palette);
+                    if err != VP8_ENC_OK {
+                        break 'error;
+                    }
+                    // If using a color cache, do not have it bigger than the number of
+                    // colors.
+                    if use_cache != 0 && enc.palette_size_ < (1 << MAX_COLOR_CACHE_BITS) {
+                        enc.cache_bits_ = BitsLog2Floor(enc.palette_size_ as u32) + 1;
+                    }
+                }
+                if !use_delta_palette {
+                    // In case image is not packed.
+                    if enc.argb_content_ != kEncoderNearLossless
+                        && enc.argb_content_ != kEncoderPalette
+                    {
+                        err = MakeInputImageCopy(enc);
+                        if err != VP8_ENC_OK {
+                            break 'error;
+                        }
+                    }
+
+                    // -------------------------------------------------------------------
+                    // Apply transforms and write transform data.
+
+                    if enc.use_subtract_green_ != 0 {
+                        let current_width = enc.current_width_;
+                        ApplySubtractGreen(enc, current_width, height, bw);
+                    }
+
+                    if enc.use_predict_ != 0 {
+                        let current_width = enc.current_width_;
+                        let used_subtract_green = enc.use_subtract_green_;
+                        err = ApplyPredictFilter(
+                            enc,
+                            current_width,
+                            height,
+                            quality,
+                            low_effort,
+                            used_subtract_green,
+                            bw,
+                        );
+                        if err != VP8_ENC_OK {
+                            break 'error;
+                        }
+                    }
+
+                    if enc.use_cross_color_ != 0 {
+                        let current_width = enc.current_width_;
+                        err = ApplyCrossColorFilter(
+                            enc,
+                            current_width,
+                            height,
+                            quality,
+                            low_effort,
+                            bw,
+                        );
+                        if err != VP8_ENC_OK {
+                            break 'error;
+                        }
+                    }
+                }
+
+                VP8LPutBits(bw, (TRANSFORM_PRESENT == 0) as u32, 1); // No more transforms.
+
+                // ---------------------------------------------------------------------
+                // Encode and write the transformed image.
+                err = EncodeImageInternal(
+                    bw,
+                    enc.argb_,
+                    &mut enc.hash_chain_,
+                    &mut enc.refs_,
+                    enc.current_width_,
+                    height,
+                    quality,
+                    low_effort,
+                    use_cache,
+                    crunch_config,
+                    &mut enc.cache_bits_,
+                    enc.histo_bits_,
+                    byte_position,
+                    &mut hdr_size,
+                    &mut data_size,
+                );
+                if err != VP8_ENC_OK {
+                    break 'error;
+                }
+
+                // If we are better than what we already have.
+                if idx == 0 || VP8LBitWriterNumBytes(&*bw) < best_size {
+                    best_size = VP8LBitWriterNumBytes(&*bw);
+                    // Store the BitWriter.
+                    VP8LBitWriterSwap(bw, &mut bw_best);
+                    // Update the stats.
+                    if !stats.is_null() {
+                        let stats = &mut *stats;
+                        stats.lossless_features = 0;
+                        if enc.use_predict_ != 0 {
+                            stats.lossless_features |= 1;
+                        }
+                        if enc.use_cross_color_ != 0 {
+                            stats.lossless_features |= 2;
+                        }
+                        if enc.use_subtract_green_ != 0 {
+                            stats.lossless_features |= 4;
+                        }
+                        if enc.use_palette_ != 0 {
+                            stats.lossless_features |= 8;
+                        }
+                        stats.histogram_bits = enc.histo_bits_;
+                        stats.transform_bits = enc.transform_bits_;
+                        stats.cache_bits = enc.cache_bits_;
+                        stats.palette_size = enc.palette_size_;
+                        stats.lossless_size = (best_size - byte_position) as c_int;
+                        stats.lossless_hdr_size = hdr_size;
+                        stats.lossless_data_size = data_size;
+                    }
+                }
+                // Reset the bit writer for the following iteration if any.
+                if num_crunch_configs > 1 {
+                    VP8LBitWriterReset(&bw_init, bw);
+                }
+            }
+            VP8LBitWriterSwap(&mut bw_best, bw);
+        }
+
+        VP8LBitWriterWipeOut(&mut bw_best);
+        params.err_ = err;
+        // The hook should return false in case of error.
+        (err == VP8_ENC_OK) as c_int
+    }
+}
+
+// Encodes the main image stream using the supplied bit writer.
+// If 'use_cache' is false, disables the use of color cache.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LEncodeStream(
+    config: *const WebPConfig,
+    picture: *const WebPPicture,
+    bw_main: *mut VP8LBitWriter,
+    use_cache: c_int,
+) -> WebPEncodingError {
+    let mut err = VP8_ENC_OK;
+    let enc_main = VP8LEncoderNew(config, picture);
+    let mut enc_side: *mut VP8LEncoder = ptr::null_mut();
+    let mut crunch_configs: [CrunchConfig; CRUNCH_CONFIGS_MAX] = mem::zeroed();
+    let mut num_crunch_configs_main = 0;
+    let mut num_crunch_configs_side = 0;
+    let mut red_and_blue_always_zero = 0;
+    let mut worker_main: WebPWorker = mem::zeroed();
+    let mut worker_side: WebPWorker = mem::zeroed();
+    let mut params_main: StreamEncodeContext = mem::zeroed();
+    let mut params_side: StreamEncodeContext = mem::zeroed();
+    // The main thread uses picture->stats, the side thread uses stats_side.
+    let mut stats_side: WebPAuxStats = mem::zeroed();
+    let mut bw_side: VP8LBitWriter = mem::zeroed();
+    let worker_interface = &*WebPGetWorkerInterface();
+
+    'error: {
+        // Analyze image (entropy, num_palettes etc)
+        if enc_main.is_null()
+            || !EncoderAnalyze(
+                &mut *enc_main,
+                &mut crunch_configs,
+                &mut num_crunch_configs_main,
+                &mut red_and_blue_always_zero,
+            )
+            || !EncoderInit(&mut *enc_main)
+            || VP8LBitWriterInit(&mut bw_side, 0) == 0
+        {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        // Split the configs between the main and side threads (if any).
+        if (*config).thread_level > 0 {
+            num_crunch_configs_side = num_crunch_configs_main / 2;
+            for idx in 0..num_crunch_configs_side as usize {
+                params_side.crunch_configs_[idx] = crunch_configs
+                    [(num_crunch_configs_main - num_crunch_configs_side) as usize + idx];
+            }
+            params_side.num_crunch_configs_ = num_crunch_configs_side;
+        }
+        num_crunch_configs_main -= num_crunch_configs_side;
+        params_main.crunch_configs_[..num_crunch_configs_main as usize]
+            .copy_from_slice(&crunch_configs[..num_crunch_configs_main as usize]);
+        params_main.num_crunch_configs_ = num_crunch_configs_main;
+
+        // Fill in the parameters for the thread workers.
+        {
+            let params_size = if num_crunch_configs_side > 0 { 2 } else { 1 };
+            for idx in 0..params_size {
+                // Create the parameters for each worker.
+                let worker = if idx == 0 {
+                    &mut worker_main
+                } else {
+                    &mut worker_side
+                };
+                let param = if idx == 0 {
+                    &mut params_main
+                } else {
+                    &mut params_side
+                };
+                param.config_ = config;
+                param.picture_ = picture;
+                param.use_cache_ = use_cache;
+                param.red_and_blue_always_zero_ = red_and_blue_always_zero;
+                if idx == 0 {
+                    param.stats_ = (*picture).stats;
+                    param.bw_ = bw_main;
+                    param.enc_ = enc_main;
+                } else {
+                    param.stats_ = if (*picture).stats.is_null() {
+                        ptr::null_mut()
+                    } else {
+                        &mut stats_side
+                    };
+                    // Create a side bit writer.
+                    if VP8LBitWriterClone(bw_main, &mut bw_side) == 0 {
+                        err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                        break 'error;
+                    }
+                    param.bw_ = &mut bw_side;
+                    // Create a side encoder.
+                    enc_side = VP8LEncoderNew(config, picture);
+                    if enc_side.is_null() || !EncoderInit(&mut *enc_side) {
+                        err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                        break 'error;
+                    }
+                    // Copy the values that were computed for the main encoder.
+                    (*enc_side).histo_bits_ = (*enc_main).histo_bits_;
+                    (*enc_side).transform_bits_ = (*enc_main).transform_bits_;
+                    (*enc_side).palette_size_ = (*enc_main).palette_size_;
+                    (*enc_side).palette_ = (*enc_main).palette_;
+                    param.enc_ = enc_side;
+                }
+                // Create the workers.
+                worker_interface.Init.unwrap()(worker);
+                worker.data1 = param as *mut StreamEncodeContext as *mut c_void;
+                worker.data2 = ptr::null_mut();
+                worker.hook = Some(EncodeStreamHook);
+            }
+        }
+
+        // Start the second thread if needed.
+        if num_crunch_configs_side != 0 {
+            if worker_interface.Reset.unwrap()(&mut worker_side) == 0 {
+                err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+                break 'error;
+            }
+            // This line is here and not in the param initialization above to remove a
+            // Clang static analyzer warning.
+            if !(*picture).stats.is_null() {
+                stats_side = *(*picture).stats;
+            }
+            // This line is only useful to remove a Clang static analyzer warning.
+            params_side.err_ = VP8_ENC_OK;
+            worker_interface.Launch.unwrap()(&mut worker_side);
+        }
+        // Execute the main thread.
+        worker_interface.Execute.unwrap()(&mut worker_main);
+        let ok_main = worker_interface.Sync.unwrap()(&mut worker_main);
+        worker_interface.End.unwrap()(&mut worker_main);
+        if num_crunch_configs_side != 0 {
+            // Wait for the second thread.
+            let ok_side = worker_interface.Sync.unwrap()(&mut worker_side);
+            worker_interface.End.unwrap()(&mut worker_side);
+            if ok_main == 0 || ok_side == 0 {
+                err = if ok_main != 0 {
+                    params_side.err_
+                } else {
+                    params_main.err_
+                };
+                break 'error;
+            }
+            if VP8LBitWriterNumBytes(&bw_side) < VP8LBitWriterNumBytes(&*bw_main) {
+                VP8LBitWriterSwap(bw_main, &mut bw_side);
+                if !(*picture).stats.is_null() {
+                    *(*picture).stats = stats_side;
+                }
+            }
+        } else if ok_main == 0 {
+            err = params_main.err_;
+            break 'error;
+        }
+    }
+
+    VP8LBitWriterWipeOut(&mut bw_side);
+    VP8LEncoderDelete(enc_main);
+    VP8LEncoderDelete(enc_side);
+    err
+}
+
+// Encodes the picture.
+// Returns 0 if config or picture is NULL or picture doesn't have valid argb
+// input.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LEncodeImage(
+    config: *const WebPConfig,
+    picture: *const WebPPicture,
+) -> c_int {
+    let mut coded_size: usize = 0;
+    let mut percent = 0;
+    let mut err;
+    let mut bw: VP8LBitWriter = mem::zeroed();
+
+    if picture.is_null() {
+        return 0;
+    }
+
+    if config.is_null() || (*picture).argb.is_null() {
+        err = VP8_ENC_ERROR_NULL_PARAMETER;
+        WebPEncodingSetError(picture, err);
+        return 0;
+    }
+
+    let width = (*picture).width;
+    let height = (*picture).height;
+    // Initialize BitWriter with size corresponding to 16 bpp to photo images and
+    // 8 bpp for graphical images.
+    let initial_size = if (*config).image_hint == WebPImageHint::WEBP_HINT_GRAPH as c_int {
+        width * height
+    } else {
+        width * height * 2
+    };
+    'error: {
+        if VP8LBitWriterInit(&mut bw, initial_size as usize) == 0 {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        if WebPReportProgress(picture, 1, &mut percent) == 0 {
+            err = VP8_ENC_ERROR_USER_ABORT;
+            break 'error;
+        }
+        // Reset stats (for pure lossless coding)
+        if !(*picture).stats.is_null() {
+            let stats = &mut *(*picture).stats;
+            *stats = mem::zeroed();
+            stats.PSNR[0] = 99.0;
+            stats.PSNR[1] = 99.0;
+            stats.PSNR[2] = 99.0;
+            stats.PSNR[3] = 99.0;
+            stats.PSNR[4] = 99.0;
+        }
+
+        // Write image size.
+        if !WriteImageSize(&*picture, &mut bw) {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        let has_alpha = WebPPictureHasTransparency(picture);
+        // Write the non-trivial Alpha flag and lossless version.
+        if !WriteRealAlphaAndVersion(&mut bw, has_alpha) {
+            err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+            break 'error;
+        }
+
+        if WebPReportProgress(picture, 5, &mut percent) == 0 {
+            err = VP8_ENC_ERROR_USER_ABORT;
+            break 'error;
+        }
+
+        // Encode main image stream.
+        err = VP8LEncodeStream(config, picture, &mut bw, 1 /*use_cache*/);
+        if err != VP8_ENC_OK {
+            break 'error;
+        }
+
+        if WebPReportProgress(picture, 90, &mut percent) == 0 {
+            err = VP8_ENC_ERROR_USER_ABORT;
+            break 'error;
+        }
+
+        // Finish the RIFF chunk.
+        err = WriteImage(picture, &mut bw, &mut coded_size);
+        if err != VP8_ENC_OK {
+            break 'error;
+        }
+
+        if WebPReportProgress(picture, 100, &mut percent) == 0 {
+            err = VP8_ENC_ERROR_USER_ABORT;
+            break 'error;
+        }
+
+        // Save size.
+        if !(*picture).stats.is_null() {
+            (*(*picture).stats).coded_size += coded_size as c_int;
+            (*(*picture).stats).lossless_size = coded_size as c_int;
+        }
+
+        if !(*picture).extra_info.is_null() {
+            let mb_w = (width + 15) >> 4;
+            let mb_h = (height + 15) >> 4;
+            ptr::write_bytes((*picture).extra_info, 0, (mb_w * mb_h) as usize);
+        }
+    }
+
+    if bw.error_ != 0 {
+        err = VP8_ENC_ERROR_OUT_OF_MEMORY;
+    }
+    VP8LBitWriterWipeOut(&mut bw);
+    if err != VP8_ENC_OK {
+        WebPEncodingSetError(picture, err);
+        return 0;
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use decode::decode_rgba;
+    use encode::{EncoderConfig, WEBP_ENCODER_ABI_VERSION};
+    use sys::{
+        WebPMemoryWrite, WebPMemoryWriter, WebPMemoryWriterClear, WebPMemoryWriterInit,
+        WebPPictureFree, WebPPictureImportRGBA, WebPPictureInitInternal,
+    };
+    use test_util::{check_parity, rgba_images, Digest};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LDspInit();
+        fn WebPRef_VP8LEncodeImage(config: *const WebPConfig, picture: *const WebPPicture)
+            -> c_int;
+    }
+
+    fn images() -> Vec<(u32, u32, Vec<u8>)> {
+        rgba_images(
+            0x4321,
+            0x07,
+            &[
+                (1, 1, 0, false),
+                (3, 17, 0, true),
+                (64, 40, 0, false),
+                (97, 61, 0, true),
+                (33, 17, 1, false),
+                (40, 21, 2, true),
+                (29, 30, 4, false),
+                (50, 31, 16, true),
+                (70, 44, 200, false),
+                (80, 52, 600, true),
+            ],
+        )
+    }
+
+    // Configurations covering all the methods, near-lossless, the threaded
+    // search and the image hint.
+    fn configs() -> Vec<EncoderConfig> {
+        let mut configs = Vec::new();
+        for level in 0..=9 {
+            let config = EncoderConfig::lossless_preset(level).unwrap();
+            configs.push(config);
+            configs.push(EncoderConfig {
+                exact: true,
+                thread_level: 1,
+                ..config
+            });
+        }
+        for &near_lossless in &[0, 40, 80] {
+            configs.push(EncoderConfig {
+                near_lossless,
+                ..EncoderConfig::lossless_preset(6).unwrap()
+            });
+        }
+        configs.push(EncoderConfig {
+            image_hint: WebPImageHint::WEBP_HINT_GRAPH,
+            ..EncoderConfig::lossless_preset(3).unwrap()
+        });
+        configs
+    }
+
+    type EncodeFunc = unsafe extern "C" fn(*const WebPConfig, *const WebPPicture) -> c_int;
+
+    #[cfg(feature = "c-reference")]
+    unsafe extern "C" fn reference_encode_image(
+        config: *const WebPConfig,
+        picture: *const WebPPicture,
+    ) -> c_int {
+        // The reference predictor_enc.c uses the reference predictors.
+        WebPRef_VP8LDspInit();
+        WebPRef_VP8LEncodeImage(config, picture)
+    }
+
+    // Encodes the image with either version, returning the bitstream and
+    // the statistics.
+    fn encode(
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        config: &EncoderConfig,
+        encode_image: EncodeFunc,
+    ) -> (Vec<u8>, WebPAuxStats) {
+        unsafe {
+            let mut picture: WebPPicture = mem::zeroed();
+            assert_ne!(
+                WebPPictureInitInternal(&mut picture, WEBP_ENCODER_ABI_VERSION as c_int),
+                0
+            );
+            let mut writer: WebPMemoryWriter = mem::zeroed();
+            WebPMemoryWriterInit(&mut writer);
+            let mut stats: WebPAuxStats = mem::zeroed();
+            picture.use_argb = 1;
+            picture.width = width as c_int;
+            picture.height = height as c_int;
+            picture.writer = Some(WebPMemoryWrite);
+            picture.custom_ptr = &mut writer as *mut WebPMemoryWriter as *mut c_void;
+            picture.stats = &mut stats;
+            assert_ne!(
+                WebPPictureImportRGBA(&mut picture, rgba.as_ptr(), width as c_int * 4),
+                0
+            );
+            let raw_config = config.to_raw();
+            assert_ne!(encode_image(&raw_config, &picture), 0);
+            let data = slice::from_raw_parts(writer.mem, writer.size).to_vec();
+            WebPMemoryWriterClear(&mut writer);
+            WebPPictureFree(&mut picture);
+            (data, stats)
+        }
+    }
+
+    #[test]
+    fn test_encode_image_matches_reference() {
+        let mut digest = Digest::new();
+        let configs = configs();
+        for (width, height, rgba) in images() {
+            for config in &configs {
+                let (data, stats) = encode(&rgba, width, height, config, VP8LEncodeImage);
+                digest.update(&data);
+                digest.update_u32(&[stats.lossless_features]);
+                digest.update_i32(&[
+                    stats.coded_size,
+                    stats.histogram_bits,
+                    stats.transform_bits,
+                    stats.cache_bits,
+                    stats.palette_size,
+                    stats.lossless_size,
+                    stats.lossless_hdr_size,
+                    stats.lossless_data_size,
+                ]);
+                #[cfg(feature = "c-reference")]
+                {
+                    let (ref_data, ref_stats) =
+                        encode(&rgba, width, height, config, reference_encode_image);
+                    assert!(data == ref_data, "{}x{} image, {:?}", width, height, config);
+                    assert_eq!(stats.coded_size, ref_stats.coded_size);
+                    assert_eq!(stats.lossless_features, ref_stats.lossless_features);
+                    assert_eq!(stats.histogram_bits, ref_stats.histogram_bits);
+                    assert_eq!(stats.transform_bits, ref_stats.transform_bits);
+                    assert_eq!(stats.cache_bits, ref_stats.cache_bits);
+                    assert_eq!(stats.palette_size, ref_stats.palette_size);
+                    assert_eq!(stats.lossless_size, ref_stats.lossless_size);
+                    assert_eq!(stats.lossless_hdr_size, ref_stats.lossless_hdr_size);
+                    assert_eq!(stats.lossless_data_size, ref_stats.lossless_data_size);
+                }
+            }
+        }
+        check_parity("enc::vp8l_enc::test_encode_image_matches_reference", digest);
+    }
+
+    #[test]
+    fn test_encode_image_roundtrip() {
+        for (width, height, rgba) in images() {
+            for level in &[0, 5, 9] {
+                let config = EncoderConfig {
+                    exact: true,
+                    ..EncoderConfig::lossless_preset(*level).unwrap()
+                };
+                let (data, _) = encode(&rgba, width, height, &config, VP8LEncodeImage);
+                let (w, h, decoded) = decode_rgba(&data).unwrap();
+                assert_eq!((w, h), (width, height));
+                assert!(&decoded[..] == &rgba[..]);
+            }
+        }
+    }
+}
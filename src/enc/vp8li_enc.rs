@@ -0,0 +1,62 @@
+use std::os::raw::*;
+
+use enc::backward_references_enc::{VP8LBackwardRefs, VP8LHashChain};
+use format_constants::MAX_PALETTE_SIZE;
+use sys::{WebPConfig, WebPPicture};
+
+// maximum value of transform_bits_ in VP8LEncoder.
+pub(crate) const MAX_TRANSFORM_BITS: c_int = 6;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub(crate) enum VP8LEncoderARGBContent {
+    kEncoderNone = 0,
+    kEncoderARGB,
+    kEncoderNearLossless,
+    kEncoderPalette,
+}
+
+#[repr(C)]
+pub(crate) struct VP8LEncoder {
+    pub(crate) config_: *const WebPConfig, // user configuration and parameters
+    pub(crate) pic_: *const WebPPicture,   // input picture.
+
+    pub(crate) argb_: *mut u32, // Transformed argb image data.
+    pub(crate) argb_content_: VP8LEncoderARGBContent, // Content type of the argb buffer.
+    pub(crate) argb_scratch_: *mut u32, // Scratch memory for argb rows
+    // (used for prediction).
+    pub(crate) transform_data_: *mut u32, // Scratch memory for transform data.
+    pub(crate) transform_mem_: *mut u32,  // Currently allocated memory.
+    pub(crate) transform_mem_size_: usize, // Currently allocated memory size.
+
+    pub(crate) current_width_: c_int, // Corresponds to packed image width.
+
+    // Encoding parameters derived from quality parameter.
+    pub(crate) histo_bits_: c_int,
+    pub(crate) transform_bits_: c_int, // <= MAX_TRANSFORM_BITS.
+    pub(crate) cache_bits_: c_int,     // If equal to 0, don't use color cache.
+
+    // Encoding parameters derived from image characteristics.
+    pub(crate) use_cross_color_: c_int,
+    pub(crate) use_subtract_green_: c_int,
+    pub(crate) use_predict_: c_int,
+    pub(crate) use_palette_: c_int,
+    pub(crate) palette_size_: c_int,
+    pub(crate) palette_: [u32; MAX_PALETTE_SIZE],
+
+    // Some 'scratch' (potentially large) objects.
+    pub(crate) refs_: [VP8LBackwardRefs; 3], // Backward Refs array for temporaries.
+    pub(crate) hash_chain_: VP8LHashChain,   // HashChain data for constructing
+                                             // backward references.
+}
+
+extern "C" {
+    // in near_lossless.c
+    // Near lossless preprocessing in RGB color-space.
+    pub(crate) fn VP8ApplyNearLossless(
+        picture: *const WebPPicture,
+        quality: c_int,
+        argb_dst: *mut u32,
+    ) -> c_int;
+}
@@ -0,0 +1,739 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// WebP encoder: main entry point
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dec::common_dec::*;
+use dsp::enc::{VP8EncDspCostInit, VP8EncDspInit};
+use enc::analysis_enc::VP8EncAnalyze;
+use enc::frame_enc::{VP8EncLoop, VP8EncTokenLoop};
+use enc::syntax_enc::{VP8EncFreeBitWriters, VP8EncWrite};
+use enc::token_enc::{VP8TBufferClear, VP8TBufferInit};
+use enc::tree_enc::VP8DefaultProbas;
+use enc::vp8i_enc::VP8RDLevel::*;
+use enc::vp8i_enc::*;
+#[cfg(feature = "rust-vp8l-encoder")]
+use enc::vp8l_enc::VP8LEncodeImage;
+use encode::WebPEncodingError::{self, *};
+use encode::WEBP_MAX_DIMENSION;
+use sys::{WebPConfig, WebPPicture, WebPValidateConfig};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc, WEBP_ALIGN, WEBP_ALIGN_CST};
+
+extern "C" {
+    // in picture_csp_enc.c
+    fn WebPPictureARGBToYUVADithered(
+        picture: *mut WebPPicture,
+        colorspace: c_int,
+        dithering: c_float,
+    ) -> c_int;
+    fn WebPPictureSharpARGBToYUVA(picture: *mut WebPPicture) -> c_int;
+    fn WebPPictureYUVAToARGB(picture: *mut WebPPicture) -> c_int;
+    // in picture_tools_enc.c
+    fn WebPCleanupTransparentArea(picture: *mut WebPPicture);
+    fn WebPCleanupTransparentAreaLossless(picture: *mut WebPPicture);
+}
+
+#[cfg(not(feature = "rust-vp8l-encoder"))]
+extern "C" {
+    // in vp8l_enc.c
+    fn VP8LEncodeImage(config: *const WebPConfig, picture: *const WebPPicture) -> c_int;
+}
+
+const WEBP_YUV420: c_int = 0; // 4:2:0
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub extern "C" fn WebPGetEncoderVersion() -> c_int {
+    (ENC_MAJ_VERSION << 16) | (ENC_MIN_VERSION << 8) | ENC_REV_VERSION
+}
+
+//------------------------------------------------------------------------------
+// VP8Encoder
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe fn ResetSegmentHeader(enc: *mut VP8Encoder) {
+    let hdr = &mut (*enc).segment_hdr_;
+    hdr.num_segments_ = (*(*enc).config_).segments;
+    hdr.update_map_ = (hdr.num_segments_ > 1) as c_int;
+    hdr.size_ = 0;
+}
+
+#[allow(non_snake_case)]
+unsafe fn ResetFilterHeader(enc: *mut VP8Encoder) {
+    let hdr = &mut (*enc).filter_hdr_;
+    hdr.simple_ = 1;
+    hdr.level_ = 0;
+    hdr.sharpness_ = 0;
+    hdr.i4x4_lf_delta_ = 0;
+}
+
+#[allow(non_snake_case)]
+unsafe fn ResetBoundaryPredictions(enc: *mut VP8Encoder) {
+    // init boundary values once for all
+    // Note: actually, initializing the preds_[] is only needed for intra4.
+    let top = (*enc).preds_.offset(-(*enc).preds_w_ as isize);
+    let left = (*enc).preds_.offset(-1);
+    for i in -1..4 * (*enc).mb_w_ {
+        *top.offset(i as isize) = B_DC_PRED as u8;
+    }
+    for i in 0..4 * (*enc).mb_h_ {
+        *left.offset((i * (*enc).preds_w_) as isize) = B_DC_PRED as u8;
+    }
+    *(*enc).nz_.offset(-1) = 0; // constant
+}
+
+// Mapping from config->method_ to coding tools used.
+//-------------------+---+---+---+---+---+---+---+
+//   Method          | 0 | 1 | 2 | 3 |(4)| 5 | 6 |
+//-------------------+---+---+---+---+---+---+---+
+// fast probe        | x |   |   | x |   |   |   |
+//-------------------+---+---+---+---+---+---+---+
+// dynamic proba     | ~ | x | x | x | x | x | x |
+//-------------------+---+---+---+---+---+---+---+
+// fast mode analysis|[x]|[x]|   |   | x | x | x |
+//-------------------+---+---+---+---+---+---+---+
+// basic rd-opt      |   |   |   | x | x | x | x |
+//-------------------+---+---+---+---+---+---+---+
+// disto-refine i4/16| x | x | x |   |   |   |   |
+//-------------------+---+---+---+---+---+---+---+
+// disto-refine uv   |   | x | x |   |   |   |   |
+//-------------------+---+---+---+---+---+---+---+
+// rd-opt i4/16      |   |   | ~ | x | x | x | x |
+//-------------------+---+---+---+---+---+---+---+
+// token buffer (opt)|   |   |   | x | x | x | x |
+//-------------------+---+---+---+---+---+---+---+
+// Trellis           |   |   |   |   |   | x |Ful|
+//-------------------+---+---+---+---+---+---+---+
+// full-SNS          |   |   |   |   | x | x | x |
+//-------------------+---+---+---+---+---+---+---+
+
+#[allow(non_snake_case)]
+unsafe fn MapConfigToTools(enc: *mut VP8Encoder) {
+    let config = &*(*enc).config_;
+    let method = config.method;
+    let limit = 100 - config.partition_limit;
+    (*enc).method_ = method;
+    (*enc).rd_opt_level_ = if method >= 6 {
+        RD_OPT_TRELLIS_ALL
+    } else if method >= 5 {
+        RD_OPT_TRELLIS
+    } else if method >= 3 {
+        RD_OPT_BASIC
+    } else {
+        RD_OPT_NONE
+    };
+    // upper bound: up to 16bit per 4x4 block, modulated with a quadratic curve.
+    (*enc).max_i4_header_bits_ = 256 * 16 * 16 * (limit * limit) / (100 * 100);
+
+    // partition0 = 512k max.
+    (*enc).mb_header_limit_ =
+        (256 as score_t * 510 * 8 * 1024 / ((*enc).mb_w_ * (*enc).mb_h_) as score_t) as c_int;
+
+    (*enc).thread_level_ = config.thread_level;
+
+    (*enc).do_search_ = (config.target_size > 0 || config.target_psnr > 0.) as c_int;
+    if config.low_memory == 0 {
+        (*enc).use_tokens_ = ((*enc).rd_opt_level_ >= RD_OPT_BASIC) as c_int; // need rd stats
+        if (*enc).use_tokens_ != 0 {
+            (*enc).num_parts_ = 1; // doesn't work with multi-partition
+        }
+    }
+}
+
+// Memory scaling with dimensions:
+//  memory (bytes) ~= 2.25 * w + 0.0625 * w * h
+//
+// Typical memory footprint (614x440 picture)
+//              encoder: 22111
+//                 info: 4368
+//                preds: 17741
+//          top samples: 1263
+//             non-zero: 175
+//             lf-stats: 0
+//                total: 45658
+// Transient object sizes:
+//       VP8EncIterator: 3360
+//         VP8ModeScore: 872
+//       VP8SegmentInfo: 732
+//          VP8EncProba: 18352
+//              LFStats: 2048
+// Picture size (yuv): 419328
+
+#[allow(non_snake_case)]
+pub(crate) unsafe fn InitVP8Encoder(
+    config: *const WebPConfig,
+    picture: *mut WebPPicture,
+) -> *mut VP8Encoder {
+    let use_filter = (*config).filter_strength > 0 || (*config).autofilter > 0;
+    let mb_w = ((*picture).width + 15) >> 4;
+    let mb_h = ((*picture).height + 15) >> 4;
+    let preds_w = 4 * mb_w + 1;
+    let preds_h = 4 * mb_h + 1;
+    let preds_size = (preds_w * preds_h) as usize * mem::size_of::<u8>();
+    let top_stride = (mb_w * 16) as usize;
+    let nz_size = (mb_w + 1) as usize * mem::size_of::<u32>() + WEBP_ALIGN_CST;
+    let info_size = (mb_w * mb_h) as usize * mem::size_of::<VP8MBInfo>();
+    let samples_size = 2 * top_stride * mem::size_of::<u8>() // top-luma/u/v
+        + WEBP_ALIGN_CST; // align all
+    let lf_stats_size = if (*config).autofilter != 0 {
+        mem::size_of::<LFStats>() + WEBP_ALIGN_CST
+    } else {
+        0
+    };
+    let top_derr_size = if (*config).quality <= ERROR_DIFFUSION_QUALITY || (*config).pass > 1 {
+        mb_w as usize * mem::size_of::<DError>()
+    } else {
+        0
+    };
+    #[rustfmt::skip]
+    let size = mem::size_of::<VP8Encoder>() as u64 // main struct
+             + WEBP_ALIGN_CST as u64               // cache alignment
+             + info_size as u64                    // modes info
+             + preds_size as u64                   // prediction modes
+             + samples_size as u64                 // top/left samples
+             + top_derr_size as u64                // top diffusion error
+             + nz_size as u64                      // coeff context bits
+             + lf_stats_size as u64; // autofilter stats
+
+    let mut mem = WebPSafeMalloc(size, mem::size_of::<u8>()) as *mut u8;
+    if mem.is_null() {
+        WebPEncodingSetError(picture, VP8_ENC_ERROR_OUT_OF_MEMORY);
+        return ptr::null_mut();
+    }
+    let enc = mem as *mut VP8Encoder;
+    mem = WEBP_ALIGN(mem.add(mem::size_of::<VP8Encoder>()));
+    ptr::write_bytes(enc, 0, 1);
+    (*enc).num_parts_ = 1 << (*config).partitions;
+    (*enc).mb_w_ = mb_w;
+    (*enc).mb_h_ = mb_h;
+    (*enc).preds_w_ = preds_w;
+    (*enc).mb_info_ = mem as *mut VP8MBInfo;
+    mem = mem.add(info_size);
+    (*enc).preds_ = mem.offset(1 + (*enc).preds_w_ as isize);
+    mem = mem.add(preds_size);
+    (*enc).nz_ = (WEBP_ALIGN(mem) as *mut u32).offset(1);
+    mem = mem.add(nz_size);
+    (*enc).lf_stats_ = if lf_stats_size != 0 {
+        WEBP_ALIGN(mem) as *mut LFStats
+    } else {
+        ptr::null_mut()
+    };
+    mem = mem.add(lf_stats_size);
+
+    // top samples (all 16-aligned)
+    mem = WEBP_ALIGN(mem);
+    (*enc).y_top_ = mem;
+    (*enc).uv_top_ = (*enc).y_top_.add(top_stride);
+    mem = mem.add(2 * top_stride);
+    (*enc).top_derr_ = if top_derr_size != 0 {
+        mem as *mut DError
+    } else {
+        ptr::null_mut()
+    };
+    mem = mem.add(top_derr_size);
+    c_assert!(mem <= (enc as *mut u8).add(size as usize));
+
+    (*enc).config_ = config;
+    (*enc).profile_ = if use_filter {
+        if (*config).filter_type == 1 {
+            0
+        } else {
+            1
+        }
+    } else {
+        2
+    };
+    (*enc).pic_ = picture;
+    (*enc).percent_ = 0;
+
+    MapConfigToTools(enc);
+    VP8EncDspInit();
+    VP8DefaultProbas(enc);
+    ResetSegmentHeader(enc);
+    ResetFilterHeader(enc);
+    ResetBoundaryPredictions(enc);
+    VP8EncDspCostInit();
+    VP8EncInitAlpha(enc);
+
+    // lower quality means smaller output -> we modulate a little the page
+    // size based on quality. This is just a crude 1rst-order prediction.
+    {
+        let scale = 1. + (*config).quality * 5. / 100.; // in [1,6]
+        VP8TBufferInit(
+            &mut (*enc).tokens_,
+            ((mb_w * mb_h * 4) as c_float * scale) as c_int,
+        );
+    }
+    enc
+}
+
+#[allow(non_snake_case)]
+pub(crate) unsafe fn DeleteVP8Encoder(enc: *mut VP8Encoder) -> c_int {
+    let mut ok = 1;
+    if !enc.is_null() {
+        ok = VP8EncDeleteAlpha(enc);
+        VP8TBufferClear(&mut (*enc).tokens_);
+        WebPSafeFree(enc as *mut c_void);
+    }
+    ok
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+fn GetPSNR(err: u64, size: u64) -> c_double {
+    if err > 0 && size > 0 {
+        10. * (255. * 255. * size as c_double / err as c_double).log10()
+    } else {
+        99.
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe fn FinalizePSNR(enc: *const VP8Encoder) {
+    let stats = &mut *(*(*enc).pic_).stats;
+    let size = (*enc).sse_count_;
+    let sse = &(*enc).sse_;
+    stats.PSNR[0] = GetPSNR(sse[0], size) as c_float;
+    stats.PSNR[1] = GetPSNR(sse[1], size / 4) as c_float;
+    stats.PSNR[2] = GetPSNR(sse[2], size / 4) as c_float;
+    stats.PSNR[3] = GetPSNR(sse[0] + sse[1] + sse[2], size * 3 / 2) as c_float;
+    stats.PSNR[4] = GetPSNR(sse[3], size) as c_float;
+}
+
+#[allow(non_snake_case)]
+unsafe fn StoreStats(enc: *mut VP8Encoder) {
+    let stats = (*(*enc).pic_).stats;
+    if !stats.is_null() {
+        for i in 0..NUM_MB_SEGMENTS as usize {
+            (*stats).segment_level[i] = (*enc).dqm_[i].fstrength_;
+            (*stats).segment_quant[i] = (*enc).dqm_[i].quant_;
+            for s in 0..=2 {
+                (*stats).residual_bytes[s][i] = (*enc).residual_bytes_[s][i];
+            }
+        }
+        FinalizePSNR(enc);
+        (*stats).coded_size = (*enc).coded_size_;
+        for i in 0..3 {
+            (*stats).block_count[i] = (*enc).block_count_[i];
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPEncodingSetError(
+    pic: *const WebPPicture,
+    error: WebPEncodingError,
+) -> c_int {
+    (*(pic as *mut WebPPicture)).error_code = error as c_int;
+    0
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPReportProgress(
+    pic: *const WebPPicture,
+    percent: c_int,
+    percent_store: *mut c_int,
+) -> c_int {
+    if !percent_store.is_null() && percent != *percent_store {
+        *percent_store = percent;
+        if let Some(progress_hook) = (*pic).progress_hook {
+            if progress_hook(percent, pic) == 0 {
+                // user abort requested
+                WebPEncodingSetError(pic, VP8_ENC_ERROR_USER_ABORT);
+                return 0;
+            }
+        }
+    }
+    1 // ok
+}
+
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+pub unsafe extern "C" fn WebPEncode(config: *const WebPConfig, pic: *mut WebPPicture) -> c_int {
+    let mut ok;
+    if pic.is_null() {
+        return 0;
+    }
+
+    WebPEncodingSetError(pic, VP8_ENC_OK); // all ok so far
+    if config.is_null() {
+        // bad params
+        return WebPEncodingSetError(pic, VP8_ENC_ERROR_NULL_PARAMETER);
+    }
+    if WebPValidateConfig(config) == 0 {
+        return WebPEncodingSetError(pic, VP8_ENC_ERROR_INVALID_CONFIGURATION);
+    }
+    if (*pic).width <= 0 || (*pic).height <= 0 {
+        return WebPEncodingSetError(pic, VP8_ENC_ERROR_BAD_DIMENSION);
+    }
+    if (*pic).width > WEBP_MAX_DIMENSION || (*pic).height > WEBP_MAX_DIMENSION {
+        return WebPEncodingSetError(pic, VP8_ENC_ERROR_BAD_DIMENSION);
+    }
+
+    if !(*pic).stats.is_null() {
+        *(*pic).stats = mem::zeroed();
+    }
+
+    if (*config).lossless == 0 {
+        if (*pic).use_argb != 0 || (*pic).y.is_null() || (*pic).u.is_null() || (*pic).v.is_null() {
+            // Make sure we have YUVA samples.
+            if (*config).use_sharp_yuv != 0 || ((*config).preprocessing & 4) != 0 {
+                if WebPPictureSharpARGBToYUVA(pic) == 0 {
+                    return 0;
+                }
+            } else {
+                let mut dithering: c_float = 0.;
+                if ((*config).preprocessing & 2) != 0 {
+                    let x = (*config).quality / 100.;
+                    let x2 = x * x;
+                    // slowly decreasing from max dithering at low quality (q->0)
+                    // to 0.5 dithering amplitude at high quality (q->100)
+                    dithering = 1.0 + (0.5 - 1.0) * x2 * x2;
+                }
+                if WebPPictureARGBToYUVADithered(pic, WEBP_YUV420, dithering) == 0 {
+                    return 0;
+                }
+            }
+        }
+
+        if (*config).exact == 0 {
+            WebPCleanupTransparentArea(pic);
+        }
+
+        let enc = InitVP8Encoder(config, pic);
+        if enc.is_null() {
+            return 0; // pic->error is already set.
+        }
+        // Note: each of the tasks below account for 20% in the progress report.
+        ok = VP8EncAnalyze(enc) != 0;
+
+        // Analysis is done, proceed to actual coding.
+        ok = ok && VP8EncStartAlpha(enc) != 0; // possibly done in parallel
+        if (*enc).use_tokens_ == 0 {
+            ok = ok && VP8EncLoop(enc) != 0;
+        } else {
+            ok = ok && VP8EncTokenLoop(enc) != 0;
+        }
+        ok = ok && VP8EncFinishAlpha(enc) != 0;
+
+        ok = ok && VP8EncWrite(enc) != 0;
+        StoreStats(enc);
+        if !ok {
+            VP8EncFreeBitWriters(enc);
+        }
+        ok &= DeleteVP8Encoder(enc) != 0; // must always be called, even if !ok
+    } else {
+        // Make sure we have ARGB samples.
+        if (*pic).argb.is_null() && WebPPictureYUVAToARGB(pic) == 0 {
+            return 0;
+        }
+
+        if (*config).exact == 0 {
+            WebPCleanupTransparentAreaLossless(pic);
+        }
+
+        ok = VP8LEncodeImage(config, pic) != 0; // Sets pic->error in case of problem.
+    }
+
+    ok as c_int
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use decode::{decode_rgba, get_info};
+    use encode::{EncoderConfig, WEBP_ENCODER_ABI_VERSION};
+    use sys::{
+        WebPAuxStats, WebPMemoryWrite, WebPMemoryWriter, WebPMemoryWriterClear,
+        WebPMemoryWriterInit, WebPPictureFree, WebPPictureImportRGBA, WebPPictureInitInternal,
+    };
+    use test_util::{check_parity, lossy_images, Digest};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8DspInit();
+        fn WebPRef_WebPEncode(config: *const WebPConfig, picture: *mut WebPPicture) -> c_int;
+    }
+
+    // Everything but quality and method, which test_encode_matches_reference
+    // goes through on its own.
+    fn configs() -> Vec<EncoderConfig> {
+        let base = EncoderConfig::default();
+        vec![
+            EncoderConfig {
+                segments: 1,
+                sns_strength: 0,
+                ..base
+            },
+            EncoderConfig {
+                segments: 2,
+                sns_strength: 100,
+                ..base
+            },
+            EncoderConfig {
+                filter_strength: 0,
+                ..base
+            },
+            EncoderConfig {
+                filter_strength: 100,
+                filter_sharpness: 7,
+                filter_type: 0,
+                ..base
+            },
+            EncoderConfig {
+                autofilter: true,
+                method: 6,
+                ..base
+            },
+            EncoderConfig {
+                target_size: 800,
+                pass: 6,
+                ..base
+            },
+            EncoderConfig {
+                target_psnr: 42.0,
+                pass: 4,
+                method: 2,
+                ..base
+            },
+            EncoderConfig {
+                partitions: 3,
+                low_memory: true,
+                method: 4,
+                ..base
+            },
+            EncoderConfig {
+                partitions: 2,
+                method: 1,
+                partition_limit: 70,
+                ..base
+            },
+            EncoderConfig {
+                emulate_jpeg_size: true,
+                ..base
+            },
+            EncoderConfig {
+                preprocessing: 2,
+                quality: 10.0,
+                ..base
+            },
+            EncoderConfig {
+                preprocessing: 4,
+                ..base
+            },
+            EncoderConfig {
+                use_sharp_yuv: true,
+                exact: true,
+                ..base
+            },
+            EncoderConfig {
+                thread_level: 1,
+                method: 5,
+                ..base
+            },
+            EncoderConfig {
+                alpha_compression: 0,
+                ..base
+            },
+            EncoderConfig {
+                alpha_filtering: 2,
+                alpha_quality: 50,
+                ..base
+            },
+            EncoderConfig {
+                quality: 99.0,
+                method: 4,
+                ..base
+            },
+        ]
+    }
+
+    type EncodeFunc = unsafe extern "C" fn(*const WebPConfig, *mut WebPPicture) -> c_int;
+
+    #[cfg(feature = "c-reference")]
+    unsafe extern "C" fn reference_encode(
+        config: *const WebPConfig,
+        picture: *mut WebPPicture,
+    ) -> c_int {
+        // The reference quant_enc.c uses the reference VP8TransformWHT.
+        WebPRef_VP8DspInit();
+        WebPRef_WebPEncode(config, picture)
+    }
+
+    // Encodes the image with either version, returning the bitstream, the
+    // statistics and the extra info map.
+    fn encode(
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        config: &EncoderConfig,
+        extra_info_type: c_int,
+        encode: EncodeFunc,
+    ) -> (Vec<u8>, WebPAuxStats, Vec<u8>) {
+        unsafe {
+            let mut picture: WebPPicture = mem::zeroed();
+            assert_ne!(
+                WebPPictureInitInternal(&mut picture, WEBP_ENCODER_ABI_VERSION as c_int),
+                0
+            );
+            let mut writer: WebPMemoryWriter = mem::zeroed();
+            WebPMemoryWriterInit(&mut writer);
+            let mut stats: WebPAuxStats = mem::zeroed();
+            let mut extra_info = vec![0; (((width + 15) >> 4) * ((height + 15) >> 4)) as usize];
+            picture.use_argb = 1;
+            picture.width = width as c_int;
+            picture.height = height as c_int;
+            picture.writer = Some(WebPMemoryWrite);
+            picture.custom_ptr = &mut writer as *mut WebPMemoryWriter as *mut c_void;
+            picture.stats = &mut stats;
+            if extra_info_type > 0 {
+                picture.extra_info_type = extra_info_type;
+                picture.extra_info = extra_info.as_mut_ptr();
+            }
+            assert_ne!(
+                WebPPictureImportRGBA(&mut picture, rgba.as_ptr(), width as c_int * 4),
+                0
+            );
+            let raw_config = config.to_raw();
+            assert_ne!(encode(&raw_config, &mut picture), 0);
+            let data = slice::from_raw_parts(writer.mem, writer.size).to_vec();
+            WebPMemoryWriterClear(&mut writer);
+            WebPPictureFree(&mut picture);
+            (data, stats, extra_info)
+        }
+    }
+
+    // Encodes with the port, adding the output to 'digest', and compares with
+    // the reference.
+    fn check(
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        config: &EncoderConfig,
+        extra_info_type: c_int,
+        digest: &mut Digest,
+    ) {
+        let (data, stats, extra_info) =
+            encode(rgba, width, height, config, extra_info_type, WebPEncode);
+        digest.update(&data);
+        digest.update_i32(&[stats.coded_size, stats.alpha_data_size]);
+        digest.update_u32(&stats.PSNR.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+        digest.update_i32(&stats.block_count);
+        digest.update_i32(&stats.header_bytes);
+        for residual_bytes in &stats.residual_bytes {
+            digest.update_i32(residual_bytes);
+        }
+        digest.update_i32(&stats.segment_size);
+        digest.update_i32(&stats.segment_quant);
+        digest.update_i32(&stats.segment_level);
+        digest.update(&extra_info);
+        #[cfg(feature = "c-reference")]
+        {
+            let (ref_data, ref_stats, ref_extra_info) = encode(
+                rgba,
+                width,
+                height,
+                config,
+                extra_info_type,
+                reference_encode,
+            );
+            assert!(data == ref_data, "{}x{} image, {:?}", width, height, config);
+            assert_eq!(stats.coded_size, ref_stats.coded_size);
+            assert_eq!(stats.PSNR, ref_stats.PSNR);
+            assert_eq!(stats.block_count, ref_stats.block_count);
+            assert_eq!(stats.header_bytes, ref_stats.header_bytes);
+            assert_eq!(stats.residual_bytes, ref_stats.residual_bytes);
+            assert_eq!(stats.segment_size, ref_stats.segment_size);
+            assert_eq!(stats.segment_quant, ref_stats.segment_quant);
+            assert_eq!(stats.segment_level, ref_stats.segment_level);
+            assert_eq!(stats.alpha_data_size, ref_stats.alpha_data_size);
+            assert_eq!(
+                extra_info, ref_extra_info,
+                "extra info type {}",
+                extra_info_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_encode_matches_reference() {
+        let mut digest = Digest::new();
+        for (width, height, rgba) in lossy_images() {
+            for &quality in &[0.0, 25.0, 50.0, 75.0, 90.0, 100.0] {
+                for method in 0..=6 {
+                    let config = EncoderConfig {
+                        quality,
+                        method,
+                        ..EncoderConfig::default()
+                    };
+                    check(&rgba, width, height, &config, 0, &mut digest);
+                }
+            }
+        }
+        check_parity("enc::webp_enc::test_encode_matches_reference", digest);
+    }
+
+    #[test]
+    fn test_encode_options_match_reference() {
+        let mut digest = Digest::new();
+        let configs = configs();
+        for (width, height, rgba) in lossy_images() {
+            for config in &configs {
+                check(&rgba, width, height, config, 0, &mut digest);
+            }
+            // Not with the token buffer (method >= 3): VP8EncTokenLoop() leaves
+            // the bit counts read by type 6 uninitialized in the C version.
+            let config = EncoderConfig {
+                method: 2,
+                ..EncoderConfig::default()
+            };
+            for extra_info_type in 1..=7 {
+                check(&rgba, width, height, &config, extra_info_type, &mut digest);
+            }
+        }
+        check_parity("enc::webp_enc::test_encode_options_match_reference", digest);
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        for (width, height, rgba) in lossy_images() {
+            let config = EncoderConfig {
+                quality: 100.0,
+                exact: true,
+                ..EncoderConfig::default()
+            };
+            let (data, _, _) = encode(&rgba, width, height, &config, 0, WebPEncode);
+            assert_eq!(get_info(&data), Some((width, height)));
+            let (w, h, decoded) = decode_rgba(&data).unwrap();
+            assert_eq!((w, h), (width, height));
+            // Lossy, and the chroma is subsampled, but close at this quality.
+            let total_diff: u64 = rgba
+                .iter()
+                .zip(decoded.iter())
+                .map(|(&a, &b)| (a as i32 - b as i32).abs() as u64)
+                .sum();
+            let mean_diff = total_diff / rgba.len() as u64;
+            assert!(mean_diff < 24, "{}x{} image: {}", width, height, mean_diff);
+        }
+    }
+}
@@ -1,13 +1,216 @@
+use std::mem;
 use std::os::raw::*;
 use std::ptr;
 
 use sys;
 use WebpBox;
 
+pub const WEBP_ENCODER_ABI_VERSION: u16 = 0x020e;
+
 pub fn encoder_version() -> i32 {
     (unsafe { sys::WebPGetEncoderVersion() }) as i32
 }
 
+// Image characteristics hint for the underlying encoder.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum WebPImageHint {
+    WEBP_HINT_DEFAULT = 0, // default preset.
+    WEBP_HINT_PICTURE,     // digital picture, like portrait, inner shot
+    WEBP_HINT_PHOTO,       // outdoor photograph, with natural lighting
+    WEBP_HINT_GRAPH,       // Discrete tone image (graph, map-tile etc).
+}
+
+impl WebPImageHint {
+    fn new(x: c_int) -> Self {
+        match x {
+            1 => WebPImageHint::WEBP_HINT_PICTURE,
+            2 => WebPImageHint::WEBP_HINT_PHOTO,
+            3 => WebPImageHint::WEBP_HINT_GRAPH,
+            _ => WebPImageHint::WEBP_HINT_DEFAULT,
+        }
+    }
+}
+
+// Predefined settings for EncoderConfig, depending on the type of source picture.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum WebPPreset {
+    WEBP_PRESET_DEFAULT = 0, // default preset.
+    WEBP_PRESET_PICTURE,     // digital picture, like portrait, inner shot
+    WEBP_PRESET_PHOTO,       // outdoor photograph, with natural lighting
+    WEBP_PRESET_DRAWING,     // hand or line drawing, with high-contrast details
+    WEBP_PRESET_ICON,        // small-sized colorful images
+    WEBP_PRESET_TEXT,        // text-like
+}
+
+// Encoding error conditions.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum WebPEncodingError {
+    VP8_ENC_OK = 0,
+    VP8_ENC_ERROR_OUT_OF_MEMORY, // memory error allocating objects
+    VP8_ENC_ERROR_BITSTREAM_OUT_OF_MEMORY, // memory error while flushing bits
+    VP8_ENC_ERROR_NULL_PARAMETER, // a pointer parameter is NULL
+    VP8_ENC_ERROR_INVALID_CONFIGURATION, // configuration is invalid
+    VP8_ENC_ERROR_BAD_DIMENSION, // picture has invalid width/height
+    VP8_ENC_ERROR_PARTITION0_OVERFLOW, // partition is bigger than 512k
+    VP8_ENC_ERROR_PARTITION_OVERFLOW, // partition is bigger than 16M
+    VP8_ENC_ERROR_BAD_WRITE,     // error while flushing bytes
+    VP8_ENC_ERROR_FILE_TOO_BIG,  // file is bigger than 4G
+    VP8_ENC_ERROR_USER_ABORT,    // abort request by user
+    VP8_ENC_ERROR_LAST,          // list terminator. always last.
+}
+
+// maximum width/height allowed (inclusive), in pixels
+pub const WEBP_MAX_DIMENSION: c_int = 16383;
+
+/// Compression parameters, mirroring libwebp's `WebPConfig`.
+///
+/// See `webp/encode.h` for the meaning and valid range of each field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderConfig {
+    pub lossless: bool,
+    pub quality: f32,
+    pub method: i32,
+    pub image_hint: WebPImageHint,
+    pub target_size: i32,
+    pub target_psnr: f32,
+    pub segments: i32,
+    pub sns_strength: i32,
+    pub filter_strength: i32,
+    pub filter_sharpness: i32,
+    pub filter_type: i32,
+    pub autofilter: bool,
+    pub alpha_compression: i32,
+    pub alpha_filtering: i32,
+    pub alpha_quality: i32,
+    pub pass: i32,
+    pub show_compressed: bool,
+    pub preprocessing: i32,
+    pub partitions: i32,
+    pub partition_limit: i32,
+    pub emulate_jpeg_size: bool,
+    pub thread_level: i32,
+    pub low_memory: bool,
+    pub near_lossless: i32,
+    pub exact: bool,
+    pub use_delta_palette: bool,
+    pub use_sharp_yuv: bool,
+}
+
+impl EncoderConfig {
+    /// Settings of `WebPConfigPreset(preset, quality)`.
+    /// Returns `None` if `quality` is out of range.
+    pub fn preset(preset: WebPPreset, quality: f32) -> Option<Self> {
+        let mut raw: sys::WebPConfig = unsafe { mem::zeroed() };
+        let ok = unsafe {
+            sys::WebPConfigInitInternal(
+                &mut raw,
+                preset as c_int,
+                quality as c_float,
+                WEBP_ENCODER_ABI_VERSION as c_int,
+            )
+        };
+        if ok != 0 {
+            Some(Self::from_raw(&raw))
+        } else {
+            None
+        }
+    }
+
+    /// Settings of `WebPConfigLosslessPreset(level)` applied to the defaults.
+    /// `level` goes from 0 (fastest) to 9 (best compression).
+    pub fn lossless_preset(level: u32) -> Option<Self> {
+        let mut raw = Self::default().to_raw();
+        if unsafe { sys::WebPConfigLosslessPreset(&mut raw, level as c_int) } != 0 {
+            Some(Self::from_raw(&raw))
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if all parameters are within their valid ranges.
+    pub fn is_valid(&self) -> bool {
+        (unsafe { sys::WebPValidateConfig(&self.to_raw()) }) != 0
+    }
+
+    pub(crate) fn from_raw(raw: &sys::WebPConfig) -> Self {
+        Self {
+            lossless: raw.lossless != 0,
+            quality: raw.quality,
+            method: raw.method,
+            image_hint: WebPImageHint::new(raw.image_hint),
+            target_size: raw.target_size,
+            target_psnr: raw.target_psnr,
+            segments: raw.segments,
+            sns_strength: raw.sns_strength,
+            filter_strength: raw.filter_strength,
+            filter_sharpness: raw.filter_sharpness,
+            filter_type: raw.filter_type,
+            autofilter: raw.autofilter != 0,
+            alpha_compression: raw.alpha_compression,
+            alpha_filtering: raw.alpha_filtering,
+            alpha_quality: raw.alpha_quality,
+            pass: raw.pass,
+            show_compressed: raw.show_compressed != 0,
+            preprocessing: raw.preprocessing,
+            partitions: raw.partitions,
+            partition_limit: raw.partition_limit,
+            emulate_jpeg_size: raw.emulate_jpeg_size != 0,
+            thread_level: raw.thread_level,
+            low_memory: raw.low_memory != 0,
+            near_lossless: raw.near_lossless,
+            exact: raw.exact != 0,
+            use_delta_palette: raw.use_delta_palette != 0,
+            use_sharp_yuv: raw.use_sharp_yuv != 0,
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> sys::WebPConfig {
+        sys::WebPConfig {
+            lossless: self.lossless as c_int,
+            quality: self.quality as c_float,
+            method: self.method as c_int,
+            image_hint: self.image_hint as c_int,
+            target_size: self.target_size as c_int,
+            target_psnr: self.target_psnr as c_float,
+            segments: self.segments as c_int,
+            sns_strength: self.sns_strength as c_int,
+            filter_strength: self.filter_strength as c_int,
+            filter_sharpness: self.filter_sharpness as c_int,
+            filter_type: self.filter_type as c_int,
+            autofilter: self.autofilter as c_int,
+            alpha_compression: self.alpha_compression as c_int,
+            alpha_filtering: self.alpha_filtering as c_int,
+            alpha_quality: self.alpha_quality as c_int,
+            pass: self.pass as c_int,
+            show_compressed: self.show_compressed as c_int,
+            preprocessing: self.preprocessing as c_int,
+            partitions: self.partitions as c_int,
+            partition_limit: self.partition_limit as c_int,
+            emulate_jpeg_size: self.emulate_jpeg_size as c_int,
+            thread_level: self.thread_level as c_int,
+            low_memory: self.low_memory as c_int,
+            near_lossless: self.near_lossless as c_int,
+            exact: self.exact as c_int,
+            use_delta_palette: self.use_delta_palette as c_int,
+            use_sharp_yuv: self.use_sharp_yuv as c_int,
+            pad: [0; 2],
+        }
+    }
+}
+
+impl Default for EncoderConfig {
+    // Same as WebPConfigInit(): lossy, quality 75.
+    fn default() -> Self {
+        Self::preset(WebPPreset::WEBP_PRESET_DEFAULT, 75.0).unwrap()
+    }
+}
+
 macro_rules! wrap_encoder {
     ($encoder:ident, $c_encoder:ident, $elems:expr) => {
         pub fn $encoder(
@@ -85,6 +288,54 @@ wrap_lossless_encoder!(encode_lossless_bgr, WebPEncodeLosslessBGR, 3);
 wrap_lossless_encoder!(encode_lossless_rgba, WebPEncodeLosslessRGBA, 4);
 wrap_lossless_encoder!(encode_lossless_bgra, WebPEncodeLosslessBGRA, 4);
 
+/// Encodes an RGBA image with the given configuration.
+pub fn encode_rgba_with_config(
+    buf: &[u8],
+    width: u32,
+    height: u32,
+    stride: u32,
+    config: &EncoderConfig,
+) -> Option<WebpBox<[u8]>> {
+    let rgba_len = (stride as usize).checked_mul(height as usize).unwrap();
+    assert_eq!(rgba_len, buf.len());
+    assert!(width <= stride / 4);
+    assert_eq!(width as c_int as u32, width);
+    assert_eq!(height as c_int as u32, height);
+    assert_eq!(stride as c_int as u32, stride);
+
+    let mut picture: sys::WebPPicture = unsafe { mem::zeroed() };
+    if unsafe { sys::WebPPictureInitInternal(&mut picture, WEBP_ENCODER_ABI_VERSION as c_int) } == 0
+    {
+        return None;
+    }
+    let mut writer: sys::WebPMemoryWriter = unsafe { mem::zeroed() };
+    unsafe {
+        sys::WebPMemoryWriterInit(&mut writer);
+    }
+    // Lossless encoding works on ARGB; the lossy one converts to YUV itself.
+    picture.use_argb = config.lossless as c_int;
+    picture.width = width as c_int;
+    picture.height = height as c_int;
+    picture.writer = Some(sys::WebPMemoryWrite);
+    picture.custom_ptr = &mut writer as *mut sys::WebPMemoryWriter as *mut c_void;
+    let raw_config = config.to_raw();
+    let result = unsafe {
+        sys::WebPPictureImportRGBA(&mut picture, buf.as_ptr(), stride as c_int) != 0
+            && sys::WebPEncode(&raw_config, &mut picture) != 0
+    };
+    unsafe {
+        sys::WebPPictureFree(&mut picture);
+    }
+    if result {
+        Some(unsafe { WebpBox::from_raw_parts(writer.mem, writer.size) })
+    } else {
+        unsafe {
+            sys::WebPMemoryWriterClear(&mut writer);
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,6 +345,26 @@ mod tests {
         assert_eq!(encoder_version(), 0x10000);
     }
 
+    #[test]
+    fn test_encoder_config() {
+        let config = EncoderConfig::default();
+        assert!(!config.lossless);
+        assert_eq!(config.quality, 75.0);
+        assert!(config.is_valid());
+
+        let config = EncoderConfig::lossless_preset(9).unwrap();
+        assert!(config.lossless);
+        assert!(config.is_valid());
+        assert!(EncoderConfig::lossless_preset(10).is_none());
+
+        assert!(EncoderConfig::preset(WebPPreset::WEBP_PRESET_PHOTO, 101.0).is_none());
+        let mut config = EncoderConfig::preset(WebPPreset::WEBP_PRESET_PHOTO, 50.0).unwrap();
+        assert_eq!(config.quality, 50.0);
+        assert_eq!(config.sns_strength, 80);
+        config.method = 7;
+        assert!(!config.is_valid());
+    }
+
     #[test]
     fn test_encode_rgb() {
         let img = encode_rgb(&[255, 255, 255], 1, 1, 3, 75.0);
@@ -141,4 +412,26 @@ mod tests {
         let img = encode_lossless_bgra(&[255, 255, 255, 255], 1, 1, 4);
         assert!(img.is_some());
     }
+
+    #[test]
+    fn test_encode_rgba_with_config() {
+        let config = EncoderConfig::default();
+        let img = encode_rgba_with_config(&[255, 255, 255, 255], 1, 1, 4, &config).unwrap();
+        assert_eq!(&img[0..4], b"RIFF");
+
+        let config = EncoderConfig {
+            exact: true,
+            ..EncoderConfig::lossless_preset(6).unwrap()
+        };
+        let rgba = [10, 20, 30, 40, 50, 60, 70, 0];
+        let img = encode_rgba_with_config(&rgba, 2, 1, 8, &config).unwrap();
+        let (_, _, decoded) = ::decode::decode_rgba(&img).unwrap();
+        assert_eq!(*decoded, rgba[..]);
+
+        let config = EncoderConfig {
+            method: 7,
+            ..EncoderConfig::default()
+        };
+        assert!(encode_rgba_with_config(&[255, 255, 255, 255], 1, 1, 4, &config).is_none());
+    }
 }
@@ -0,0 +1,185 @@
+// Tag number of the orientation in IFD0.
+pub(crate) const TAG_ORIENTATION: u16 = 0x0112;
+// TIFF field type SHORT (16-bit unsigned integer).
+pub(crate) const TYPE_SHORT: u16 = 3;
+
+/// EXIF orientation, i.e. where the 0th row and 0th column of the stored
+/// image are located in the visual image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    TopLeft = 1,     // As stored.
+    TopRight = 2,    // Mirrored horizontally.
+    BottomRight = 3, // Rotated by 180 degrees.
+    BottomLeft = 4,  // Mirrored vertically.
+    LeftTop = 5,     // Transposed.
+    RightTop = 6,    // Needs a 90 degrees clockwise rotation.
+    RightBottom = 7, // Transversed.
+    LeftBottom = 8,  // Needs a 90 degrees counter-clockwise rotation.
+}
+
+struct TiffReader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    fn read_u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.data.get(pos..pos.checked_add(2)?)?;
+        Some(if self.big_endian {
+            (bytes[0] as u16) << 8 | bytes[1] as u16
+        } else {
+            bytes[0] as u16 | (bytes[1] as u16) << 8
+        })
+    }
+
+    fn read_u32(&self, pos: usize) -> Option<u32> {
+        let hi = self.read_u16(pos)? as u32;
+        let lo = self.read_u16(pos + 2)? as u32;
+        Some(if self.big_endian {
+            hi << 16 | lo
+        } else {
+            lo << 16 | hi
+        })
+    }
+}
+
+impl Orientation {
+    pub fn from_u16(value: u16) -> Option<Self> {
+        match value {
+            1 => Some(Orientation::TopLeft),
+            2 => Some(Orientation::TopRight),
+            3 => Some(Orientation::BottomRight),
+            4 => Some(Orientation::BottomLeft),
+            5 => Some(Orientation::LeftTop),
+            6 => Some(Orientation::RightTop),
+            7 => Some(Orientation::RightBottom),
+            8 => Some(Orientation::LeftBottom),
+            _ => None,
+        }
+    }
+
+    /// Reads the orientation tag from the payload of an `EXIF` chunk (a TIFF
+    /// structure, optionally preceded by the JPEG-style `Exif\0\0` marker).
+    pub fn from_exif(exif: &[u8]) -> Option<Self> {
+        let data = if exif.starts_with(b"Exif\0\0") {
+            &exif[6..]
+        } else {
+            exif
+        };
+        let big_endian = match data.get(0..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        let reader = TiffReader { data, big_endian };
+        let ifd = reader.read_u32(4)? as usize;
+        let num_entries = reader.read_u16(ifd)? as usize;
+        for i in 0..num_entries {
+            let entry = ifd + 2 + i * 12;
+            if reader.read_u16(entry)? == TAG_ORIENTATION {
+                if reader.read_u16(entry + 2)? != TYPE_SHORT || reader.read_u32(entry + 4)? != 1 {
+                    return None;
+                }
+                return Self::from_u16(reader.read_u16(entry + 8)?);
+            }
+        }
+        None
+    }
+
+    /// Returns true if displaying the image swaps its width and height.
+    pub fn swaps_dimensions(self) -> bool {
+        self as u16 >= 5
+    }
+
+    // Position in the stored image of pixel (x, y) of the displayed one.
+    fn source_position(self, x: u32, y: u32, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Orientation::TopLeft => (x, y),
+            Orientation::TopRight => (width - 1 - x, y),
+            Orientation::BottomRight => (width - 1 - x, height - 1 - y),
+            Orientation::BottomLeft => (x, height - 1 - y),
+            Orientation::LeftTop => (y, x),
+            Orientation::RightTop => (y, height - 1 - x),
+            Orientation::RightBottom => (width - 1 - y, height - 1 - x),
+            Orientation::LeftBottom => (width - 1 - y, x),
+        }
+    }
+
+    /// Transforms a tightly packed RGBA image of the given (stored) size into
+    /// its displayed orientation, in place. Returns the displayed size.
+    pub fn apply_rgba(self, width: u32, height: u32, buf: &mut [u8]) -> (u32, u32) {
+        assert_eq!(buf.len(), width as usize * height as usize * 4);
+        if self == Orientation::TopLeft {
+            return (width, height);
+        }
+        let (out_width, out_height) = if self.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let src = buf.to_vec();
+        for y in 0..out_height {
+            for x in 0..out_width {
+                let (sx, sy) = self.source_position(x, y, width, height);
+                let src_pos = (sy as usize * width as usize + sx as usize) * 4;
+                let dst_pos = (y as usize * out_width as usize + x as usize) * 4;
+                buf[dst_pos..dst_pos + 4].copy_from_slice(&src[src_pos..src_pos + 4]);
+            }
+        }
+        (out_width, out_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::exif_with_orientation;
+
+    #[test]
+    fn test_from_exif() {
+        for orientation in 1..9 {
+            for &big_endian in &[false, true] {
+                let exif = exif_with_orientation(orientation, big_endian);
+                let expected = Orientation::from_u16(orientation).unwrap();
+                assert_eq!(Orientation::from_exif(&exif), Some(expected));
+                let mut prefixed = b"Exif\0\0".to_vec();
+                prefixed.extend_from_slice(&exif);
+                assert_eq!(Orientation::from_exif(&prefixed), Some(expected));
+            }
+        }
+        assert_eq!(
+            Orientation::from_exif(&exif_with_orientation(9, false)),
+            None
+        );
+        let exif = exif_with_orientation(6, false);
+        assert_eq!(Orientation::from_exif(&exif[..20]), None);
+        assert_eq!(Orientation::from_exif(b"II*\0\xff\xff\xff\xff"), None);
+        assert_eq!(Orientation::from_exif(b""), None);
+    }
+
+    #[test]
+    fn test_apply_rgba() {
+        // 3x2 image:
+        //   1 2 3
+        //   4 5 6
+        let image: Vec<u8> = (1..7).flat_map(|v| vec![v, v, v, 255]).collect();
+        let expected: [(u32, u32, &[u8]); 8] = [
+            (3, 2, &[1, 2, 3, 4, 5, 6]),
+            (3, 2, &[3, 2, 1, 6, 5, 4]),
+            (3, 2, &[6, 5, 4, 3, 2, 1]),
+            (3, 2, &[4, 5, 6, 1, 2, 3]),
+            (2, 3, &[1, 4, 2, 5, 3, 6]),
+            (2, 3, &[4, 1, 5, 2, 6, 3]),
+            (2, 3, &[6, 3, 5, 2, 4, 1]),
+            (2, 3, &[3, 6, 2, 5, 1, 4]),
+        ];
+        for (i, &(width, height, pixels)) in expected.iter().enumerate() {
+            let orientation = Orientation::from_u16(i as u16 + 1).unwrap();
+            let mut buf = image.clone();
+            assert_eq!(orientation.apply_rgba(3, 2, &mut buf), (width, height));
+            let actual: Vec<u8> = buf.chunks(4).map(|p| p[0]).collect();
+            assert_eq!(actual, pixels, "{:?}", orientation);
+        }
+    }
+}
@@ -2,3 +2,72 @@ pub const ALPHA_HEADER_LEN: usize = 1;
 pub const ALPHA_NO_COMPRESSION: i32 = 0;
 pub const ALPHA_LOSSLESS_COMPRESSION: i32 = 1;
 pub const ALPHA_PREPROCESSED_LEVELS: i32 = 1;
+
+// VP8 related constants.
+pub const VP8_SIGNATURE: u32 = 0x9d012a; // Signature in VP8 data.
+pub const VP8_MAX_PARTITION0_SIZE: usize = 1 << 19; // max size of mode partition
+pub const VP8_MAX_PARTITION_SIZE: usize = 1 << 24; // max size for token partition
+pub const VP8_FRAME_HEADER_SIZE: usize = 10; // Size of the frame header within VP8 data.
+
+// VP8L related constants.
+pub const VP8L_SIGNATURE_SIZE: usize = 1; // VP8L signature size.
+pub const VP8L_MAGIC_BYTE: u8 = 0x2f; // VP8L signature byte.
+pub const VP8L_IMAGE_SIZE_BITS: i32 = 14; // Number of bits used to store width and height.
+pub const VP8L_VERSION_BITS: i32 = 3; // 3 bits reserved for version.
+pub const VP8L_VERSION: u32 = 0; // version 0
+pub const VP8L_FRAME_HEADER_SIZE: usize = 5; // Size of the VP8L frame header.
+
+pub const MAX_PALETTE_SIZE: usize = 256;
+pub const MAX_CACHE_BITS: i32 = 11;
+pub const HUFFMAN_CODES_PER_META_CODE: usize = 5;
+pub const ARGB_BLACK: u32 = 0xff000000;
+
+pub const DEFAULT_CODE_LENGTH: i32 = 8;
+pub const MAX_ALLOWED_CODE_LENGTH: i32 = 15;
+
+pub const NUM_LITERAL_CODES: i32 = 256;
+pub const NUM_LENGTH_CODES: i32 = 24;
+pub const NUM_DISTANCE_CODES: i32 = 40;
+pub const CODE_LENGTH_CODES: i32 = 19;
+
+pub const MIN_HUFFMAN_BITS: i32 = 2; // min number of Huffman bits
+pub const MAX_HUFFMAN_BITS: i32 = 9; // max number of Huffman bits
+
+pub const TRANSFORM_PRESENT: u32 = 1; // The bit to be written when next data to be read is a transform.
+pub const NUM_TRANSFORMS: usize = 4; // Maximum number of allowed transform in a bitstream.
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub enum VP8LImageTransformType {
+    PREDICTOR_TRANSFORM = 0,
+    CROSS_COLOR_TRANSFORM = 1,
+    SUBTRACT_GREEN = 2,
+    COLOR_INDEXING_TRANSFORM = 3,
+}
+
+pub const TAG_SIZE: usize = 4; // Size of a chunk tag (e.g. "VP8L").
+pub const CHUNK_SIZE_BYTES: usize = 4; // Size needed to store chunk's size.
+pub const CHUNK_HEADER_SIZE: usize = 8; // Size of a chunk header.
+pub const RIFF_HEADER_SIZE: usize = 12; // Size of the RIFF header ("RIFFnnnnWEBP").
+pub const ANMF_CHUNK_SIZE: usize = 16; // Size of an ANMF chunk.
+pub const ANIM_CHUNK_SIZE: usize = 6; // Size of an ANIM chunk.
+pub const VP8X_CHUNK_SIZE: usize = 10; // Size of a VP8X chunk.
+
+pub const MAX_CANVAS_SIZE: u32 = 1 << 24; // 24-bit max for VP8X width/height.
+pub const MAX_IMAGE_AREA: u64 = 1 << 32; // 32-bit max for width x height.
+pub const MAX_LOOP_COUNT: u32 = 1 << 16; // maximum value for loop-count
+pub const MAX_DURATION: u32 = 1 << 24; // maximum duration
+pub const MAX_POSITION_OFFSET: u32 = 1 << 24; // maximum frame x/y offset
+
+// Maximum chunk payload is such that adding the header and padding won't
+// overflow a uint32_t.
+pub const MAX_CHUNK_PAYLOAD: u32 = !0 - CHUNK_HEADER_SIZE as u32 - 1;
+
+// VP8X Feature Flags.
+pub const ANIMATION_FLAG: u32 = 0x00000002;
+pub const XMP_FLAG: u32 = 0x00000004;
+pub const EXIF_FLAG: u32 = 0x00000008;
+pub const ALPHA_FLAG: u32 = 0x00000010;
+pub const ICCP_FLAG: u32 = 0x00000020;
+pub const ALL_VALID_FLAGS: u32 = 0x0000003e;
@@ -0,0 +1,365 @@
+use decode::{decode_rgba, get_icc_profile};
+use WebpBox;
+
+// Linear sRGB from the D50 profile connection space (Bradford-adapted).
+const XYZ_D50_TO_SRGB: [[f64; 3]; 3] = [
+    [3.1338561, -1.6168667, -0.4906146],
+    [-0.9787684, 1.9161415, 0.0334540],
+    [0.0719453, -0.2289914, 1.4052427],
+];
+
+// Number of entries of the output (linear -> sRGB) table.
+const OUT_LUT_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+enum ToneCurve {
+    // Sampled curve, evenly spaced over [0, 1].
+    Table(Vec<f32>),
+    // Parametric curve in the most general ICC form (function type 4):
+    // y = (a * x + b)^g + e if x >= d, else c * x + f.
+    Parametric([f32; 7]),
+}
+
+impl ToneCurve {
+    fn gamma(g: f32) -> Self {
+        ToneCurve::Parametric([g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0])
+    }
+
+    fn eval(&self, x: f32) -> f32 {
+        match *self {
+            ToneCurve::Table(ref table) => {
+                let pos = x * (table.len() - 1) as f32;
+                let i = (pos as usize).min(table.len() - 2);
+                let frac = pos - i as f32;
+                table[i] + (table[i + 1] - table[i]) * frac
+            }
+            ToneCurve::Parametric([g, a, b, c, d, e, f]) => {
+                if x >= d {
+                    (a * x + b).max(0.0).powf(g) + e
+                } else {
+                    c * x + f
+                }
+            }
+        }
+    }
+}
+
+/// A matrix/TRC RGB ICC profile, as embedded by phones and cameras
+/// (sRGB, Display P3, Adobe RGB, ...).
+///
+/// LUT-based profiles (`A2B0` only) are not supported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IccProfile {
+    // Columns are the D50-adapted XYZ colorants of red, green and blue.
+    matrix: [[f32; 3]; 3],
+    curves: [ToneCurve; 3],
+}
+
+fn read_be16(data: &[u8], pos: usize) -> Option<u16> {
+    let bytes = data.get(pos..pos + 2)?;
+    Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+}
+
+fn read_be32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(
+        (bytes[0] as u32) << 24
+            | (bytes[1] as u32) << 16
+            | (bytes[2] as u32) << 8
+            | bytes[3] as u32,
+    )
+}
+
+fn read_s15fixed16(data: &[u8], pos: usize) -> Option<f32> {
+    Some(read_be32(data, pos)? as i32 as f32 / 65536.0)
+}
+
+fn find_tag<'a>(data: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+    let count = read_be32(data, 128)? as usize;
+    for i in 0..count {
+        let entry = 132 + i * 12;
+        if data.get(entry..entry + 4)? == signature {
+            let offset = read_be32(data, entry + 4)? as usize;
+            let size = read_be32(data, entry + 8)? as usize;
+            return data.get(offset..offset.checked_add(size)?);
+        }
+    }
+    None
+}
+
+fn parse_xyz(tag: &[u8]) -> Option<[f32; 3]> {
+    if tag.get(0..4)? != b"XYZ " {
+        return None;
+    }
+    Some([
+        read_s15fixed16(tag, 8)?,
+        read_s15fixed16(tag, 12)?,
+        read_s15fixed16(tag, 16)?,
+    ])
+}
+
+fn parse_curve(tag: &[u8]) -> Option<ToneCurve> {
+    match tag.get(0..4)? {
+        b"curv" => {
+            let count = read_be32(tag, 8)? as usize;
+            match count {
+                0 => Some(ToneCurve::gamma(1.0)),
+                1 => Some(ToneCurve::gamma(read_be16(tag, 12)? as f32 / 256.0)),
+                _ => {
+                    // The count is untrusted: check it against the size of
+                    // the tag before allocating the table.
+                    let end = count.checked_mul(2)?.checked_add(12)?;
+                    let table = tag
+                        .get(12..end)?
+                        .chunks(2)
+                        .map(|entry| ((entry[0] as u16) << 8 | entry[1] as u16) as f32 / 65535.0)
+                        .collect();
+                    Some(ToneCurve::Table(table))
+                }
+            }
+        }
+        b"para" => {
+            let function_type = read_be16(tag, 8)?;
+            let num_params = match function_type {
+                0 => 1,
+                1 => 3,
+                2 => 4,
+                3 => 5,
+                4 => 7,
+                _ => return None,
+            };
+            let mut p = [0.0; 7];
+            for (i, param) in p.iter_mut().enumerate().take(num_params) {
+                *param = read_s15fixed16(tag, 12 + i * 4)?;
+            }
+            let [g, a, b, c, d, _, _] = p;
+            let params = match function_type {
+                0 => [g, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                1 if a != 0.0 => [g, a, b, 0.0, -b / a, 0.0, 0.0],
+                2 if a != 0.0 => [g, a, b, 0.0, -b / a, c, c],
+                3 => [g, a, b, c, d, 0.0, 0.0],
+                4 => p,
+                _ => return None,
+            };
+            Some(ToneCurve::Parametric(params))
+        }
+        _ => None,
+    }
+}
+
+impl IccProfile {
+    /// Parses an ICC profile. Returns `None` if it isn't a matrix/TRC RGB
+    /// profile.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 132
+            || &data[36..40] != b"acsp"
+            || &data[16..20] != b"RGB "
+            || &data[20..24] != b"XYZ "
+        {
+            return None;
+        }
+        let r = parse_xyz(find_tag(data, b"rXYZ")?)?;
+        let g = parse_xyz(find_tag(data, b"gXYZ")?)?;
+        let b = parse_xyz(find_tag(data, b"bXYZ")?)?;
+        let matrix = [[r[0], g[0], b[0]], [r[1], g[1], b[1]], [r[2], g[2], b[2]]];
+        let curves = [
+            parse_curve(find_tag(data, b"rTRC")?)?,
+            parse_curve(find_tag(data, b"gTRC")?)?,
+            parse_curve(find_tag(data, b"bTRC")?)?,
+        ];
+        Some(Self { matrix, curves })
+    }
+
+    /// Converts RGBA pixels (non-premultiplied) from this profile to sRGB in
+    /// place. Alpha is left untouched; out-of-gamut colors are clipped.
+    /// Trailing bytes which don't make up a whole pixel are left untouched.
+    pub fn transform_rgba_to_srgb(&self, rgba: &mut [u8]) {
+        let mut in_lut = [[0.0f32; 256]; 3];
+        for (lut, curve) in in_lut.iter_mut().zip(self.curves.iter()) {
+            for (v, entry) in lut.iter_mut().enumerate() {
+                *entry = curve.eval(v as f32 / 255.0);
+            }
+        }
+        let mut m = [[0.0f32; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, entry) in row.iter_mut().enumerate() {
+                *entry = (0..3)
+                    .map(|k| XYZ_D50_TO_SRGB[i][k] * self.matrix[k][j] as f64)
+                    .sum::<f64>() as f32;
+            }
+        }
+        let mut out_lut = vec![0u8; OUT_LUT_SIZE];
+        for (i, entry) in out_lut.iter_mut().enumerate() {
+            let x = i as f32 / (OUT_LUT_SIZE - 1) as f32;
+            let y = if x <= 0.0031308 {
+                x * 12.92
+            } else {
+                1.055 * x.powf(1.0 / 2.4) - 0.055
+            };
+            *entry = (y * 255.0 + 0.5) as u8;
+        }
+
+        for pixel in rgba.chunks_exact_mut(4) {
+            let lin = [
+                in_lut[0][pixel[0] as usize],
+                in_lut[1][pixel[1] as usize],
+                in_lut[2][pixel[2] as usize],
+            ];
+            for (out, row) in pixel.iter_mut().zip(m.iter()) {
+                let v = row[0] * lin[0] + row[1] * lin[1] + row[2] * lin[2];
+                let v = v.clamp(0.0, 1.0);
+                *out = out_lut[(v * (OUT_LUT_SIZE - 1) as f32 + 0.5) as usize];
+            }
+        }
+    }
+}
+
+/// Same as `decode_rgba`, but converts the pixels to sRGB when the file
+/// embeds a supported ICC profile. Pixels are returned as-is otherwise.
+pub fn decode_rgba_srgb(data: &[u8]) -> Option<(u32, u32, WebpBox<[u8]>)> {
+    let (width, height, mut buf) = decode_rgba(data)?;
+    if let Some(profile) = get_icc_profile(data).and_then(IccProfile::parse) {
+        profile.transform_rgba_to_srgb(&mut buf);
+    }
+    Some((width, height, buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::with_chunks;
+
+    // sRGB transfer function as a 'para' function type 3.
+    const SRGB_PARA: [f32; 5] = [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045];
+
+    const SRGB_COLORANTS: [[f32; 3]; 3] = [
+        [0.4361, 0.2225, 0.0139],
+        [0.3851, 0.7169, 0.0971],
+        [0.1431, 0.0606, 0.7141],
+    ];
+
+    const DISPLAY_P3_COLORANTS: [[f32; 3]; 3] = [
+        [0.5151, 0.2412, -0.0011],
+        [0.2920, 0.6922, 0.0419],
+        [0.1571, 0.0666, 0.7841],
+    ];
+
+    fn s15fixed16(x: f32) -> [u8; 4] {
+        ((x * 65536.0).round() as i32).to_be_bytes()
+    }
+
+    // Builds a minimal v4 matrix/TRC profile sharing one TRC tag.
+    fn build_profile(colorants: &[[f32; 3]; 3], trc: &[u8]) -> Vec<u8> {
+        let mut tags: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+        for (signature, xyz) in [b"rXYZ", b"gXYZ", b"bXYZ"].iter().zip(colorants) {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            for &v in xyz {
+                tag.extend_from_slice(&s15fixed16(v));
+            }
+            tags.push((*signature, tag));
+        }
+        for signature in [b"rTRC", b"gTRC", b"bTRC"].iter() {
+            tags.push((*signature, trc.to_vec()));
+        }
+
+        let mut data = vec![0; 128];
+        data[16..20].copy_from_slice(b"RGB ");
+        data[20..24].copy_from_slice(b"XYZ ");
+        data[36..40].copy_from_slice(b"acsp");
+        data.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        let mut offset = 132 + tags.len() * 12;
+        for (signature, tag) in &tags {
+            data.extend_from_slice(*signature);
+            data.extend_from_slice(&(offset as u32).to_be_bytes());
+            data.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            offset += tag.len();
+        }
+        for (_, tag) in &tags {
+            data.extend_from_slice(tag);
+        }
+        let size = data.len() as u32;
+        data[0..4].copy_from_slice(&size.to_be_bytes());
+        data
+    }
+
+    fn srgb_trc() -> Vec<u8> {
+        let mut trc = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for &v in &SRGB_PARA {
+            trc.extend_from_slice(&s15fixed16(v));
+        }
+        trc
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(IccProfile::parse(&build_profile(&SRGB_COLORANTS, &srgb_trc())).is_some());
+        assert!(IccProfile::parse(&build_profile(
+            &SRGB_COLORANTS,
+            b"curv\0\0\0\0\0\0\0\x01\x02\x33"
+        ))
+        .is_some());
+        assert!(IccProfile::parse(&build_profile(&SRGB_COLORANTS, b"mft2")).is_none());
+        assert!(IccProfile::parse(b"fake icc profile").is_none());
+        assert!(IccProfile::parse(&[0; 200]).is_none());
+    }
+
+    #[test]
+    fn test_parse_curve_count_beyond_tag() {
+        let table = b"curv\0\0\0\0\0\0\0\x03\0\0\x80\0\xff\xff";
+        assert!(IccProfile::parse(&build_profile(&SRGB_COLORANTS, table)).is_some());
+        // One entry short.
+        let truncated = &table[..table.len() - 2];
+        assert!(IccProfile::parse(&build_profile(&SRGB_COLORANTS, truncated)).is_none());
+        // Must be rejected without trying to allocate the table.
+        let huge = b"curv\0\0\0\0\xff\xff\xff\xff\0\0\x80\0\xff\xff";
+        assert!(IccProfile::parse(&build_profile(&SRGB_COLORANTS, huge)).is_none());
+    }
+
+    #[test]
+    fn test_srgb_is_identity() {
+        let profile = IccProfile::parse(&build_profile(&SRGB_COLORANTS, &srgb_trc())).unwrap();
+        let orig: Vec<u8> = (0..=255).flat_map(|v| vec![v, 255 - v, v / 2, v]).collect();
+        let mut rgba = orig.clone();
+        profile.transform_rgba_to_srgb(&mut rgba);
+        assert_abs_diff_eq!(*rgba.as_slice(), *orig.as_slice(), epsilon = 1);
+    }
+
+    #[test]
+    fn test_display_p3() {
+        let profile =
+            IccProfile::parse(&build_profile(&DISPLAY_P3_COLORANTS, &srgb_trc())).unwrap();
+        let mut rgba = vec![
+            255, 0, 0, 255, // P3 red is outside of sRGB.
+            128, 128, 128, 200, // Grays are preserved.
+            200, 100, 50, 0, // More saturated in sRGB.
+            255, 0, // Not a whole pixel.
+        ];
+        profile.transform_rgba_to_srgb(&mut rgba);
+        assert_eq!(&rgba[0..4], &[255, 0, 0, 255]);
+        assert_abs_diff_eq!(rgba[4..8], [128, 128, 128, 200][..], epsilon = 1);
+        // More saturated in sRGB.
+        assert!(rgba[8] > 200 && rgba[9] < 100 && rgba[10] < 50);
+        assert_eq!(rgba[11], 0);
+        assert_eq!(&rgba[12..], &[255, 0]);
+    }
+
+    #[test]
+    fn test_decode_rgba_srgb() {
+        let webp = include_bytes!("../libwebp-1.0.0/examples/test.webp");
+        let (_, _, plain) = decode_rgba(webp).unwrap();
+        let (_, _, unchanged) = decode_rgba_srgb(webp).unwrap();
+        assert_eq!(*plain, *unchanged);
+
+        let p3 = build_profile(&DISPLAY_P3_COLORANTS, &srgb_trc());
+        let img = with_chunks(webp, &[(b"ICCP", &p3)]);
+        let (width, height, converted) = decode_rgba_srgb(&img).unwrap();
+        assert_eq!((width, height), (128, 128));
+        let mut expected = plain.to_vec();
+        IccProfile::parse(&p3)
+            .unwrap()
+            .transform_rgba_to_srgb(&mut expected);
+        assert_eq!(*converted, *expected.as_slice());
+        assert_ne!(*converted, *plain);
+    }
+}
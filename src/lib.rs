@@ -15,18 +15,47 @@ macro_rules! c_assert {
     };
 }
 
+// NUL-terminated string literal, for the error messages read from C.
+macro_rules! c_str {
+    ($s:expr) => {
+        concat!($s, "\0").as_ptr() as *const ::std::os::raw::c_char
+    };
+}
+
+mod anim_decode;
+mod anim_encode;
+#[cfg(test)]
+mod conformance;
 #[doc(hidden)]
 pub mod dec;
 mod decode;
+pub mod demux;
 #[doc(hidden)]
 pub mod dsp;
+#[cfg(any(feature = "rust-vp8-encoder", feature = "rust-vp8l-encoder"))]
+#[doc(hidden)]
+pub mod enc;
 mod encode;
+mod exif;
 pub mod format_constants;
+#[cfg(feature = "icc")]
+mod icc;
+mod mux;
+mod rescale;
 pub mod sys;
+#[cfg(test)]
+mod test_util;
 #[doc(hidden)]
 pub mod utils;
 mod webpbox;
 
+pub use anim_decode::*;
+pub use anim_encode::*;
 pub use decode::*;
 pub use encode::*;
+pub use exif::*;
+#[cfg(feature = "icc")]
+pub use icc::*;
+pub use mux::*;
+pub use rescale::*;
 pub use webpbox::WebpBox;
@@ -0,0 +1,315 @@
+use decode::decode_rgba;
+use demux::{Demuxer, WebPMuxAnimBlend, WebPMuxAnimDispose};
+use encode::{encode_rgba_with_config, EncoderConfig};
+use format_constants::*;
+
+fn put_le24(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&[val as u8, (val >> 8) as u8, (val >> 16) as u8]);
+}
+
+fn put_le32(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&[
+        val as u8,
+        (val >> 8) as u8,
+        (val >> 16) as u8,
+        (val >> 24) as u8,
+    ]);
+}
+
+// Appends a chunk with its header and padding.
+fn put_chunk(out: &mut Vec<u8>, fourcc: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(fourcc);
+    put_le32(out, payload.len() as u32);
+    out.extend_from_slice(payload);
+    if payload.len() & 1 != 0 {
+        out.push(0);
+    }
+}
+
+fn get_le32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(
+        bytes[0] as u32
+            | (bytes[1] as u32) << 8
+            | (bytes[2] as u32) << 16
+            | (bytes[3] as u32) << 24,
+    )
+}
+
+// The chunks of a RIFF/WEBP file in file order, as (fourcc, payload) pairs.
+fn riff_chunks(data: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    if data.get(0..4)? != b"RIFF" || data.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let riff_size = get_le32(data, 4)? as usize;
+    let data = data.get(..CHUNK_HEADER_SIZE.checked_add(riff_size)?)?;
+    let mut chunks = Vec::new();
+    let mut pos = RIFF_HEADER_SIZE;
+    while pos < data.len() {
+        let mut fourcc = [0; 4];
+        fourcc.copy_from_slice(data.get(pos..pos + TAG_SIZE)?);
+        let size = get_le32(data, pos + TAG_SIZE)? as usize;
+        let start = pos + CHUNK_HEADER_SIZE;
+        chunks.push((fourcc, data.get(start..start.checked_add(size)?)?));
+        pos = start + size + (size & 1);
+    }
+    Some(chunks)
+}
+
+/// Decodes `input` and encodes it again with `config`.
+///
+/// The other chunks (`ICCP`, `EXIF`, `XMP `, `ANIM` and unknown ones) are
+/// carried over byte-identical and in their original order, as are the
+/// metadata flags. For animations, every frame is re-encoded on its own so
+/// that the frame rectangles, durations, dispose and blend methods are
+/// preserved as well.
+pub fn reencode(input: &[u8], config: &EncoderConfig) -> Option<Vec<u8>> {
+    let demux = Demuxer::new(input)?;
+    let is_animation = demux.feature_flags & ANIMATION_FLAG != 0;
+    let chunks = riff_chunks(input)?;
+
+    let mut encoded_frames = Vec::with_capacity(demux.frames.len());
+    for frame in &demux.frames {
+        let (width, height, rgba) = decode_rgba(frame.payload)?;
+        encoded_frames.push(encode_rgba_with_config(
+            &rgba,
+            width,
+            height,
+            width * 4,
+            config,
+        )?);
+    }
+    let is_image_chunk = |fourcc: &[u8; 4]| matches!(fourcc, b"VP8X" | b"ALPH" | b"VP8 " | b"VP8L");
+    if !is_animation && chunks.iter().all(|(fourcc, _)| is_image_chunk(fourcc)) {
+        return encoded_frames.pop().map(|encoded| encoded.to_vec());
+    }
+
+    let mut flags = demux.feature_flags & (ICCP_FLAG | EXIF_FLAG | XMP_FLAG | ANIMATION_FLAG);
+    let mut frames = demux.frames.iter().zip(&encoded_frames);
+    let mut body = Vec::new();
+    for (fourcc, payload) in chunks {
+        let (frame, encoded) = match &fourcc {
+            b"VP8X" => continue,
+            b"ANMF" | b"ALPH" | b"VP8 " | b"VP8L" => match frames.next() {
+                Some(frame) => frame,
+                // The ALPH and VP8 chunks of a still image are replaced
+                // together, by the first one.
+                None => continue,
+            },
+            _ => {
+                put_chunk(&mut body, &fourcc, payload);
+                continue;
+            }
+        };
+        let encoded_demux = Demuxer::new(encoded)?;
+        let image = encoded_demux.frame(1)?;
+        if image.has_alpha {
+            flags |= ALPHA_FLAG;
+        }
+        if is_animation {
+            let mut anmf = Vec::with_capacity(ANMF_CHUNK_SIZE + image.payload.len());
+            put_le24(&mut anmf, frame.x_offset / 2);
+            put_le24(&mut anmf, frame.y_offset / 2);
+            put_le24(&mut anmf, frame.width - 1);
+            put_le24(&mut anmf, frame.height - 1);
+            put_le24(&mut anmf, frame.duration);
+            let mut bits = 0;
+            if frame.blend_method == WebPMuxAnimBlend::WEBP_MUX_NO_BLEND {
+                bits |= 2;
+            }
+            if frame.dispose_method == WebPMuxAnimDispose::WEBP_MUX_DISPOSE_BACKGROUND {
+                bits |= 1;
+            }
+            anmf.push(bits);
+            anmf.extend_from_slice(image.payload);
+            put_chunk(&mut body, b"ANMF", &anmf);
+        } else {
+            body.extend_from_slice(image.payload);
+        }
+    }
+
+    let mut vp8x = Vec::with_capacity(VP8X_CHUNK_SIZE);
+    put_le32(&mut vp8x, flags);
+    put_le24(&mut vp8x, demux.canvas_width - 1);
+    put_le24(&mut vp8x, demux.canvas_height - 1);
+
+    let mut out =
+        Vec::with_capacity(RIFF_HEADER_SIZE + CHUNK_HEADER_SIZE + vp8x.len() + body.len());
+    out.extend_from_slice(b"RIFF");
+    put_le32(
+        &mut out,
+        (TAG_SIZE + CHUNK_HEADER_SIZE + vp8x.len() + body.len()) as u32,
+    );
+    out.extend_from_slice(b"WEBP");
+    put_chunk(&mut out, b"VP8X", &vp8x);
+    out.extend_from_slice(&body);
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anim_decode::AnimDecoder;
+    use anim_encode::{AnimEncoder, AnimEncoderOptions};
+    use test_util::with_chunks;
+
+    fn example_webp() -> &'static [u8] {
+        include_bytes!("../libwebp-1.0.0/examples/test.webp")
+    }
+
+    fn metadata_chunks(demux: &Demuxer) -> Vec<([u8; 4], Vec<u8>)> {
+        demux
+            .chunks
+            .iter()
+            .map(|c| (c.fourcc, c.payload.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_reencode_still() {
+        // Without metadata, this is a plain re-encode.
+        let config = EncoderConfig::lossless_preset(1).unwrap();
+        let out = reencode(example_webp(), &config).unwrap();
+        let demux = Demuxer::new(&out).unwrap();
+        assert!(!demux.is_ext_format);
+        assert_eq!(&demux.frame(1).unwrap().payload[0..4], b"VP8L");
+
+        let input = with_chunks(
+            example_webp(),
+            &[
+                (b"ICCP", b"icc profile"),
+                (b"EXIF", b"II*\0exif data"),
+                (b"XMP ", b"<x:xmpmeta/>"),
+                (b"ABCD", b"unknown chunk"),
+            ],
+        );
+        let out = reencode(&input, &EncoderConfig::default()).unwrap();
+        let demux = Demuxer::new(&out).unwrap();
+        let orig_demux = Demuxer::new(&input).unwrap();
+        assert_eq!(demux.feature_flags, ICCP_FLAG | EXIF_FLAG | XMP_FLAG);
+        assert_eq!((demux.canvas_width, demux.canvas_height), (128, 128));
+        assert_eq!(metadata_chunks(&demux), metadata_chunks(&orig_demux));
+        assert_eq!(&out[30..34], b"ICCP");
+        let (width, height, _) = decode_rgba(&out).unwrap();
+        assert_eq!((width, height), (128, 128));
+
+        // Alpha.
+        let logo = include_bytes!("../examples/rust-logo-256x256.webp");
+        let input = with_chunks(&logo[..], &[(b"EXIF", b"MM\0*exif")]);
+        let out = reencode(&input, &EncoderConfig::default()).unwrap();
+        let demux = Demuxer::new(&out).unwrap();
+        assert_eq!(demux.feature_flags, ALPHA_FLAG | EXIF_FLAG);
+        assert_eq!(demux.chunk(b"EXIF"), Some(&b"MM\0*exif"[..]));
+        assert!(demux.frame(1).unwrap().has_alpha);
+
+        assert!(reencode(b"", &config).is_none());
+    }
+
+    #[test]
+    fn test_reencode_keeps_chunks_in_order() {
+        // An EXIF chunk with its flag cleared is still carried over, and an
+        // unknown chunk before the image stays there.
+        let mut input = with_chunks(
+            example_webp(),
+            &[(b"ABCD", b"unknown chunk"), (b"EXIF", b"II*\0exif data")],
+        );
+        input[20] &= !EXIF_FLAG as u8;
+        assert_eq!(Demuxer::new(&input).unwrap().chunk(b"EXIF"), None);
+        let out = reencode(&input, &EncoderConfig::default()).unwrap();
+        assert_eq!(out[20], input[20]);
+        let fourccs = |data| -> Vec<[u8; 4]> {
+            riff_chunks(data)
+                .unwrap()
+                .iter()
+                .map(|&(fourcc, _)| fourcc)
+                .collect()
+        };
+        assert_eq!(fourccs(&out), vec![*b"VP8X", *b"ABCD", *b"EXIF", *b"VP8 "]);
+        assert_eq!(fourccs(&out), fourccs(&input));
+        assert_eq!(
+            riff_chunks(&out).unwrap()[2],
+            riff_chunks(&input).unwrap()[2]
+        );
+        let (width, height, _) = decode_rgba(&out).unwrap();
+        assert_eq!((width, height), (128, 128));
+    }
+
+    #[test]
+    fn test_reencode_animation() {
+        let (width, height) = (24, 20);
+        let options = AnimEncoderOptions {
+            loop_count: 5,
+            bgcolor: 0x80402010,
+            ..AnimEncoderOptions::default()
+        };
+        let mut enc = AnimEncoder::new(width, height, &options).unwrap();
+        let config = EncoderConfig::lossless_preset(1).unwrap();
+        for t in 0..5 {
+            let mut canvas = vec![0; (width * height * 4) as usize];
+            for y in 0..height {
+                for x in 0..width {
+                    let pos = ((y * width + x) * 4) as usize;
+                    let inside = x >= t * 3 && x < t * 3 + 8 && y >= t && y < t + 6;
+                    let alpha = if inside { 255 } else { (x * 10) as u8 };
+                    canvas[pos..pos + 4].copy_from_slice(&[
+                        (t * 50) as u8,
+                        y as u8,
+                        x as u8,
+                        alpha,
+                    ]);
+                }
+            }
+            enc.add_rgba(&canvas, width * 4, (t * 70) as i32, &config)
+                .unwrap();
+        }
+        let anim = enc.assemble(400).unwrap();
+
+        // Add metadata after the frames, as allowed by the container format.
+        let mut input = anim.to_vec();
+        input[20] |= EXIF_FLAG as u8 | XMP_FLAG as u8;
+        input.extend_from_slice(b"EXIF\x05\0\0\0II*\0\x01\0");
+        input.extend_from_slice(b"XMP \x04\0\0\0<x/>");
+        let riff_size = (input.len() - CHUNK_HEADER_SIZE) as u32;
+        input[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        let reencode_config = EncoderConfig {
+            exact: true,
+            ..EncoderConfig::lossless_preset(6).unwrap()
+        };
+        let out = reencode(&input, &reencode_config).unwrap();
+        let orig_demux = Demuxer::new(&input).unwrap();
+        let demux = Demuxer::new(&out).unwrap();
+        assert_eq!(demux.feature_flags, orig_demux.feature_flags);
+        assert_eq!(demux.loop_count, 5);
+        assert_eq!(demux.bgcolor, orig_demux.bgcolor);
+        assert_eq!(metadata_chunks(&demux), metadata_chunks(&orig_demux));
+        assert_eq!(demux.frames.len(), orig_demux.frames.len());
+        for (frame, orig_frame) in demux.frames.iter().zip(&orig_demux.frames) {
+            assert_eq!(
+                (frame.x_offset, frame.y_offset, frame.width, frame.height),
+                (
+                    orig_frame.x_offset,
+                    orig_frame.y_offset,
+                    orig_frame.width,
+                    orig_frame.height
+                )
+            );
+            assert_eq!(frame.duration, orig_frame.duration);
+            assert_eq!(frame.dispose_method, orig_frame.dispose_method);
+            assert_eq!(frame.blend_method, orig_frame.blend_method);
+        }
+
+        // Lossless to lossless: the rendered canvases are the same.
+        let mut orig_dec = AnimDecoder::new(&input).unwrap();
+        let mut dec = AnimDecoder::new(&out).unwrap();
+        while let Some((orig_canvas, orig_timestamp)) =
+            orig_dec.next_frame().map(|(c, t)| (c.to_vec(), t))
+        {
+            let (canvas, timestamp) = dec.next_frame().unwrap();
+            assert_eq!(timestamp, orig_timestamp);
+            assert_eq!(canvas, &orig_canvas[..]);
+        }
+        assert!(!dec.has_more_frames());
+    }
+}
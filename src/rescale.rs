@@ -0,0 +1,176 @@
+use std::mem;
+use std::os::raw::*;
+
+use utils::rescaler_utils::{
+    rescaler_t, WebPRescaler, WebPRescalerExport, WebPRescalerImport, WebPRescalerInit,
+};
+
+// Largest width or height of a WebP image, which the fixed-point
+// accumulators of the rescaler are sized for.
+const MAX_DIMENSION: u32 = 16383;
+
+/// Resizes an RGBA image of `width` x `height` pixels to `new_width` x
+/// `new_height`, with the rescaler used for scaled decoding: the pixels are
+/// area-averaged when shrinking and bilinearly interpolated when enlarging.
+/// The four channels are scaled independently, i.e. the colors are not
+/// premultiplied by alpha.
+///
+/// Returns `None` if a dimension is zero or larger than 16383, or if `src`
+/// is shorter than `width * height * 4` bytes.
+pub fn resize_rgba(
+    src: &[u8],
+    width: u32,
+    height: u32,
+    new_width: u32,
+    new_height: u32,
+) -> Option<Vec<u8>> {
+    if [width, height, new_width, new_height]
+        .iter()
+        .any(|&dim| dim == 0 || dim > MAX_DIMENSION)
+    {
+        return None;
+    }
+    let stride = width as c_int * 4;
+    let new_stride = new_width as c_int * 4;
+    if src.len() < stride as usize * height as usize {
+        return None;
+    }
+    let mut dst = vec![0; new_stride as usize * new_height as usize];
+    let mut work: Vec<rescaler_t> = vec![0; 2 * new_stride as usize];
+    unsafe {
+        let mut rescaler: WebPRescaler = mem::zeroed();
+        WebPRescalerInit(
+            &mut rescaler,
+            width as c_int,
+            height as c_int,
+            dst.as_mut_ptr(),
+            new_width as c_int,
+            new_height as c_int,
+            new_stride,
+            4,
+            work.as_mut_ptr(),
+        );
+        let mut y = 0;
+        while y < height as c_int {
+            let row = src.as_ptr().offset(y as isize * stride as isize);
+            y += WebPRescalerImport(&mut rescaler, height as c_int - y, row, stride);
+            WebPRescalerExport(&mut rescaler);
+        }
+    }
+    Some(dst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_WebPRescalerInit(
+            rescaler: *mut WebPRescaler,
+            src_width: c_int,
+            src_height: c_int,
+            dst: *mut u8,
+            dst_width: c_int,
+            dst_height: c_int,
+            dst_stride: c_int,
+            num_channels: c_int,
+            work: *mut rescaler_t,
+        );
+        fn WebPRef_WebPRescalerImport(
+            rescaler: *mut WebPRescaler,
+            num_rows: c_int,
+            src: *const u8,
+            src_stride: c_int,
+        ) -> c_int;
+        fn WebPRef_WebPRescalerExport(rescaler: *mut WebPRescaler) -> c_int;
+    }
+
+    #[cfg(feature = "c-reference")]
+    // The same as resize_rgba(), with the original C rescaler.
+    fn resize_rgba_ref(
+        src: &[u8],
+        width: u32,
+        height: u32,
+        new_width: u32,
+        new_height: u32,
+    ) -> Vec<u8> {
+        let stride = width as c_int * 4;
+        let new_stride = new_width as c_int * 4;
+        let mut dst = vec![0; new_stride as usize * new_height as usize];
+        let mut work: Vec<rescaler_t> = vec![0; 2 * new_stride as usize];
+        unsafe {
+            let mut rescaler: WebPRescaler = mem::zeroed();
+            WebPRef_WebPRescalerInit(
+                &mut rescaler,
+                width as c_int,
+                height as c_int,
+                dst.as_mut_ptr(),
+                new_width as c_int,
+                new_height as c_int,
+                new_stride,
+                4,
+                work.as_mut_ptr(),
+            );
+            let mut y = 0;
+            while y < height as c_int {
+                let row = src.as_ptr().offset(y as isize * stride as isize);
+                y += WebPRef_WebPRescalerImport(&mut rescaler, height as c_int - y, row, stride);
+                WebPRef_WebPRescalerExport(&mut rescaler);
+            }
+        }
+        dst
+    }
+
+    #[test]
+    fn test_resize_rgba_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(0x5ca1e);
+        let sizes = [(1, 1), (1, 7), (2, 2), (13, 5), (64, 48), (100, 1)];
+        for &(width, height) in &sizes {
+            let src = rng.bytes(width as usize * height as usize * 4);
+            for &(new_width, new_height) in &sizes {
+                let dst = resize_rgba(&src, width, height, new_width, new_height).unwrap();
+                digest.update(&dst);
+                #[cfg(feature = "c-reference")]
+                assert_eq!(
+                    dst,
+                    resize_rgba_ref(&src, width, height, new_width, new_height),
+                    "{}x{} -> {}x{}",
+                    width,
+                    height,
+                    new_width,
+                    new_height
+                );
+            }
+        }
+        check_parity("rescale::test_resize_rgba_parity", digest);
+    }
+
+    #[test]
+    fn test_resize_rgba() {
+        // A uniform image stays uniform, whatever the scale.
+        let pixel = [12, 200, 99, 128];
+        let src = pixel.repeat(30 * 20);
+        for &(new_width, new_height) in &[(30, 20), (7, 3), (61, 45), (1, 1)] {
+            let dst = resize_rgba(&src, 30, 20, new_width, new_height).unwrap();
+            assert_eq!(dst, pixel.repeat((new_width * new_height) as usize));
+        }
+
+        // Halving averages each 2x2 block.
+        let src = [
+            0, 0, 0, 0, 100, 40, 8, 255, //
+            200, 80, 16, 255, 100, 40, 8, 255,
+        ];
+        assert_eq!(
+            resize_rgba(&src, 2, 2, 1, 1).unwrap(),
+            vec![100, 40, 8, 191]
+        );
+
+        assert_eq!(resize_rgba(&src, 2, 2, 0, 1), None);
+        assert_eq!(resize_rgba(&src, 2, 3, 1, 1), None);
+        assert_eq!(resize_rgba(&src, 2, 2, 1, MAX_DIMENSION + 1), None);
+    }
+}
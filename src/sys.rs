@@ -1,5 +1,255 @@
 use std::os::raw::*;
 
+#[repr(C)]
+pub struct WebPBitstreamFeatures {
+    pub width: c_int,
+    pub height: c_int,
+    pub has_alpha: c_int,
+    pub has_animation: c_int,
+    pub format: c_int,
+    pub pad: [u32; 5],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WebPRGBABuffer {
+    pub rgba: *mut u8,
+    pub stride: c_int,
+    pub size: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WebPYUVABuffer {
+    pub y: *mut u8,
+    pub u: *mut u8,
+    pub v: *mut u8,
+    pub a: *mut u8,
+    pub y_stride: c_int,
+    pub u_stride: c_int,
+    pub v_stride: c_int,
+    pub a_stride: c_int,
+    pub y_size: usize,
+    pub u_size: usize,
+    pub v_size: usize,
+    pub a_size: usize,
+}
+
+#[allow(non_snake_case)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union WebPDecBufferUnion {
+    pub RGBA: WebPRGBABuffer,
+    pub YUVA: WebPYUVABuffer,
+}
+
+#[repr(C)]
+pub struct WebPDecBuffer {
+    pub colorspace: c_int,
+    pub width: c_int,
+    pub height: c_int,
+    pub is_external_memory: c_int,
+    pub u: WebPDecBufferUnion,
+    pub pad: [u32; 4],
+    pub private_memory: *mut u8,
+}
+
+#[repr(C)]
+pub struct WebPDecoderOptions {
+    pub bypass_filtering: c_int,
+    pub no_fancy_upsampling: c_int,
+    pub use_cropping: c_int,
+    pub crop_left: c_int,
+    pub crop_top: c_int,
+    pub crop_width: c_int,
+    pub crop_height: c_int,
+    pub use_scaling: c_int,
+    pub scaled_width: c_int,
+    pub scaled_height: c_int,
+    pub use_threads: c_int,
+    pub dithering_strength: c_int,
+    pub flip: c_int,
+    pub alpha_dithering_strength: c_int,
+    pub pad: [u32; 5],
+}
+
+#[repr(C)]
+pub struct WebPDecoderConfig {
+    pub input: WebPBitstreamFeatures,
+    pub output: WebPDecBuffer,
+    pub options: WebPDecoderOptions,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WebPConfig {
+    pub lossless: c_int,
+    pub quality: c_float,
+    pub method: c_int,
+    pub image_hint: c_int,
+    pub target_size: c_int,
+    pub target_psnr: c_float,
+    pub segments: c_int,
+    pub sns_strength: c_int,
+    pub filter_strength: c_int,
+    pub filter_sharpness: c_int,
+    pub filter_type: c_int,
+    pub autofilter: c_int,
+    pub alpha_compression: c_int,
+    pub alpha_filtering: c_int,
+    pub alpha_quality: c_int,
+    pub pass: c_int,
+    pub show_compressed: c_int,
+    pub preprocessing: c_int,
+    pub partitions: c_int,
+    pub partition_limit: c_int,
+    pub emulate_jpeg_size: c_int,
+    pub thread_level: c_int,
+    pub low_memory: c_int,
+    pub near_lossless: c_int,
+    pub exact: c_int,
+    pub use_delta_palette: c_int,
+    pub use_sharp_yuv: c_int,
+    pub pad: [u32; 2],
+}
+
+// Structure for storing auxiliary statistics.
+#[allow(non_snake_case)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WebPAuxStats {
+    pub coded_size: c_int, // final size
+
+    pub PSNR: [c_float; 5], // peak-signal-to-noise ratio for Y/U/V/All/Alpha
+    pub block_count: [c_int; 3], // number of intra4/intra16/skipped macroblocks
+    pub header_bytes: [c_int; 2], // approximate number of bytes spent for header
+    // and mode-partition #0
+    pub residual_bytes: [[c_int; 4]; 3], // approximate number of bytes spent for
+    // DC/AC/uv coefficients for each (0..3) segments.
+    pub segment_size: [c_int; 4], // number of macroblocks in each segments
+    pub segment_quant: [c_int; 4], // quantizer values for each segments
+    pub segment_level: [c_int; 4], // filtering strength for each segments [0..63]
+
+    pub alpha_data_size: c_int, // size of the transparency data
+    pub layer_data_size: c_int, // size of the enhancement layer data
+
+    // lossless encoder statistics
+    pub lossless_features: u32, // bit0:predictor bit1:cross-color transform
+    // bit2:subtract-green bit3:color indexing
+    pub histogram_bits: c_int,     // number of precision bits of histogram
+    pub transform_bits: c_int,     // precision bits for transform
+    pub cache_bits: c_int,         // number of bits for color cache lookup
+    pub palette_size: c_int,       // number of color in palette, if used
+    pub lossless_size: c_int,      // final lossless size
+    pub lossless_hdr_size: c_int,  // lossless header (transform, huffman etc) size
+    pub lossless_data_size: c_int, // lossless image data size
+
+    pub pad: [u32; 2], // padding for later use
+}
+
+pub type WebPWriterFunction = Option<
+    unsafe extern "C" fn(data: *const u8, data_size: usize, picture: *const WebPPicture) -> c_int,
+>;
+pub type WebPProgressHook =
+    Option<unsafe extern "C" fn(percent: c_int, picture: *const WebPPicture) -> c_int>;
+
+#[repr(C)]
+pub struct WebPMemoryWriter {
+    pub mem: *mut u8,
+    pub size: usize,
+    pub max_size: usize,
+    pub pad: [u32; 1],
+}
+
+#[repr(C)]
+pub struct WebPPicture {
+    pub use_argb: c_int,
+
+    pub colorspace: c_int,
+    pub width: c_int,
+    pub height: c_int,
+    pub y: *mut u8,
+    pub u: *mut u8,
+    pub v: *mut u8,
+    pub y_stride: c_int,
+    pub uv_stride: c_int,
+    pub a: *mut u8,
+    pub a_stride: c_int,
+    pub pad1: [u32; 2],
+
+    pub argb: *mut u32,
+    pub argb_stride: c_int,
+    pub pad2: [u32; 3],
+
+    pub writer: WebPWriterFunction,
+    pub custom_ptr: *mut c_void,
+
+    pub extra_info_type: c_int,
+    pub extra_info: *mut u8,
+
+    pub stats: *mut WebPAuxStats,
+    pub error_code: c_int,
+    pub progress_hook: WebPProgressHook,
+    pub user_data: *mut c_void,
+
+    pub pad3: [u32; 3],
+    pub pad4: *mut u8,
+    pub pad5: *mut u8,
+    pub pad6: [u32; 8],
+
+    pub memory_: *mut c_void,
+    pub memory_argb_: *mut c_void,
+    pub pad7: [*mut c_void; 2],
+}
+
+#[repr(C)]
+pub struct WebPData {
+    pub bytes: *const u8,
+    pub size: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WebPMuxAnimParams {
+    pub bgcolor: u32,
+    pub loop_count: c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct WebPAnimEncoderOptions {
+    pub anim_params: WebPMuxAnimParams,
+    pub minimize_size: c_int,
+    pub kmin: c_int,
+    pub kmax: c_int,
+    pub allow_mixed: c_int,
+    pub verbose: c_int,
+    pub padding: [u32; 4],
+}
+
+pub type WebPAnimEncoder = c_void;
+
+#[repr(C)]
+pub struct WebPAnimDecoderOptions {
+    pub color_mode: c_int,
+    pub use_threads: c_int,
+    pub padding: [u32; 7],
+}
+
+#[repr(C)]
+pub struct WebPAnimInfo {
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+    pub loop_count: u32,
+    pub bgcolor: u32,
+    pub frame_count: u32,
+    pub pad: [u32; 4],
+}
+
+pub type WebPAnimDecoder = c_void;
+
+pub type WebPIDecoder = c_void;
+
 extern "C" {
     pub fn WebPFree(ptr: *mut c_void);
 
@@ -11,6 +261,17 @@ extern "C" {
         height: *mut c_int,
     ) -> c_int;
 
+    pub fn WebPGetFeaturesInternal(
+        data: *const u8,
+        data_size: usize,
+        features: *mut WebPBitstreamFeatures,
+        version: c_int,
+    ) -> c_int;
+
+    pub fn WebPInitDecoderConfigInternal(config: *mut WebPDecoderConfig, version: c_int) -> c_int;
+    pub fn WebPDecode(data: *const u8, data_size: usize, config: *mut WebPDecoderConfig) -> c_int;
+    pub fn WebPFreeDecBuffer(buffer: *mut WebPDecBuffer);
+
     pub fn WebPDecodeRGBA(
         data: *const u8,
         data_size: usize,
@@ -46,6 +307,29 @@ extern "C" {
         height: *mut c_int,
     ) -> *mut u8;
 
+    pub fn WebPINewDecoder(output_buffer: *mut WebPDecBuffer) -> *mut WebPIDecoder;
+    pub fn WebPINewRGB(
+        csp: c_int,
+        output_buffer: *mut u8,
+        output_buffer_size: usize,
+        output_stride: c_int,
+    ) -> *mut WebPIDecoder;
+    pub fn WebPIDecode(
+        data: *const u8,
+        data_size: usize,
+        config: *mut WebPDecoderConfig,
+    ) -> *mut WebPIDecoder;
+    pub fn WebPIDelete(idec: *mut WebPIDecoder);
+    pub fn WebPIAppend(idec: *mut WebPIDecoder, data: *const u8, data_size: usize) -> c_int;
+    pub fn WebPIUpdate(idec: *mut WebPIDecoder, data: *const u8, data_size: usize) -> c_int;
+    pub fn WebPIDecGetRGB(
+        idec: *const WebPIDecoder,
+        last_y: *mut c_int,
+        width: *mut c_int,
+        height: *mut c_int,
+        stride: *mut c_int,
+    ) -> *mut u8;
+
     pub fn WebPGetEncoderVersion() -> c_int;
 
     pub fn WebPEncodeRGB(
@@ -115,4 +399,66 @@ extern "C" {
         stride: c_int,
         output: *mut *mut u8,
     ) -> usize;
+
+    pub fn WebPConfigInitInternal(
+        config: *mut WebPConfig,
+        preset: c_int,
+        quality: c_float,
+        version: c_int,
+    ) -> c_int;
+    pub fn WebPConfigLosslessPreset(config: *mut WebPConfig, level: c_int) -> c_int;
+    pub fn WebPValidateConfig(config: *const WebPConfig) -> c_int;
+
+    pub fn WebPPictureInitInternal(picture: *mut WebPPicture, version: c_int) -> c_int;
+    pub fn WebPPictureFree(picture: *mut WebPPicture);
+    pub fn WebPPictureImportRGBA(
+        picture: *mut WebPPicture,
+        rgba: *const u8,
+        rgba_stride: c_int,
+    ) -> c_int;
+    pub fn WebPEncode(config: *const WebPConfig, picture: *mut WebPPicture) -> c_int;
+
+    pub fn WebPMemoryWriterInit(writer: *mut WebPMemoryWriter);
+    pub fn WebPMemoryWriterClear(writer: *mut WebPMemoryWriter);
+    pub fn WebPMemoryWrite(data: *const u8, data_size: usize, picture: *const WebPPicture)
+        -> c_int;
+
+    pub fn WebPAnimEncoderOptionsInitInternal(
+        enc_options: *mut WebPAnimEncoderOptions,
+        version: c_int,
+    ) -> c_int;
+    pub fn WebPAnimEncoderNewInternal(
+        width: c_int,
+        height: c_int,
+        enc_options: *const WebPAnimEncoderOptions,
+        version: c_int,
+    ) -> *mut WebPAnimEncoder;
+    pub fn WebPAnimEncoderAdd(
+        enc: *mut WebPAnimEncoder,
+        frame: *mut WebPPicture,
+        timestamp_ms: c_int,
+        config: *const WebPConfig,
+    ) -> c_int;
+    pub fn WebPAnimEncoderAssemble(enc: *mut WebPAnimEncoder, webp_data: *mut WebPData) -> c_int;
+    pub fn WebPAnimEncoderGetError(enc: *mut WebPAnimEncoder) -> *const c_char;
+    pub fn WebPAnimEncoderDelete(enc: *mut WebPAnimEncoder);
+
+    pub fn WebPAnimDecoderOptionsInitInternal(
+        dec_options: *mut WebPAnimDecoderOptions,
+        version: c_int,
+    ) -> c_int;
+    pub fn WebPAnimDecoderNewInternal(
+        webp_data: *const WebPData,
+        dec_options: *const WebPAnimDecoderOptions,
+        version: c_int,
+    ) -> *mut WebPAnimDecoder;
+    pub fn WebPAnimDecoderGetInfo(dec: *const WebPAnimDecoder, info: *mut WebPAnimInfo) -> c_int;
+    pub fn WebPAnimDecoderGetNext(
+        dec: *mut WebPAnimDecoder,
+        buf: *mut *mut u8,
+        timestamp: *mut c_int,
+    ) -> c_int;
+    pub fn WebPAnimDecoderHasMoreFrames(dec: *const WebPAnimDecoder) -> c_int;
+    pub fn WebPAnimDecoderReset(dec: *mut WebPAnimDecoder);
+    pub fn WebPAnimDecoderDelete(dec: *mut WebPAnimDecoder);
 }
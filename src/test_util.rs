@@ -0,0 +1,771 @@
+// Fixtures and helpers shared by the tests of several modules.
+
+use std::env;
+use std::fs;
+use std::mem;
+use std::os::raw::*;
+use std::path::PathBuf;
+#[cfg(feature = "rust-vp8-encoder")]
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use dec::vp8_dec::{VP8InitIo, VP8Io};
+use decode::*;
+use demux::Demuxer;
+#[cfg(feature = "rust-vp8-encoder")]
+use enc::iterator_enc::{
+    VP8IteratorImport, VP8IteratorInit, VP8IteratorNext, VP8IteratorSaveBoundary,
+};
+#[cfg(feature = "rust-vp8-encoder")]
+use enc::vp8i_enc::{VP8EncIterator, VP8Encoder};
+#[cfg(feature = "rust-vp8-encoder")]
+use enc::webp_enc::{DeleteVP8Encoder, InitVP8Encoder};
+#[cfg(feature = "rust-vp8-encoder")]
+use encode::{EncoderConfig, WEBP_ENCODER_ABI_VERSION};
+use exif::{TAG_ORIENTATION, TYPE_SHORT};
+#[cfg(feature = "rust-vp8-encoder")]
+use sys::{
+    WebPConfig, WebPMemoryWrite, WebPMemoryWriter, WebPMemoryWriterClear, WebPMemoryWriterInit,
+    WebPPicture, WebPPictureFree, WebPPictureImportRGBA, WebPPictureInitInternal,
+};
+#[cfg(feature = "c-reference")]
+use utils::bit_reader_utils::VP8BitReader;
+
+#[cfg(all(feature = "rust-vp8-encoder", feature = "c-reference"))]
+extern "C" {
+    fn WebPRef_VP8DspInit();
+    fn WebPRef_VP8IteratorInit(enc: *mut VP8Encoder, it: *mut VP8EncIterator);
+    fn WebPRef_VP8IteratorImport(it: *mut VP8EncIterator, tmp_32: *mut u8);
+    fn WebPRef_VP8IteratorSaveBoundary(it: *mut VP8EncIterator);
+    fn WebPRef_VP8IteratorNext(it: *mut VP8EncIterator) -> c_int;
+}
+
+// Simple LCG, good enough to generate test streams.
+pub(crate) struct Lcg(pub(crate) u32);
+
+impl Lcg {
+    pub(crate) fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1664525).wrapping_add(1013904223);
+        self.0 >> 8
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+// 64-bit FNV-1a hash, to check decoded data against the digests recorded in
+// the fixture indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Digest(u64);
+
+impl Digest {
+    pub(crate) fn new() -> Self {
+        Digest(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub(crate) fn update_i16(&mut self, data: &[i16]) {
+        for &v in data {
+            self.update(&v.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn update_u16(&mut self, data: &[u16]) {
+        for &v in data {
+            self.update(&v.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn update_u32(&mut self, data: &[u32]) {
+        for &v in data {
+            self.update(&v.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn update_i32(&mut self, data: &[i32]) {
+        for &v in data {
+            self.update(&v.to_le_bytes());
+        }
+    }
+
+    pub(crate) fn update_i64(&mut self, data: &[i64]) {
+        for &v in data {
+            self.update(&v.to_le_bytes());
+        }
+    }
+
+    #[cfg(any(feature = "rust-vp8-encoder", feature = "rust-vp8l-encoder"))]
+    pub(crate) fn update_u64(&mut self, data: &[u64]) {
+        for &v in data {
+            self.update(&v.to_le_bytes());
+        }
+    }
+}
+
+// A test file listed in tests/fixtures/<index>.txt, with the digests of the
+// libwebp output recorded for it.
+pub(crate) struct Fixture {
+    pub(crate) path: String, // Relative to the root of the crate.
+    pub(crate) data: Vec<u8>,
+    pub(crate) digests: Vec<Digest>,
+}
+
+fn crate_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn index_path(index: &str) -> PathBuf {
+    crate_path(&format!("tests/fixtures/{}.txt", index))
+}
+
+// The test files listed in tests/fixtures/<index>.txt. Each line holds the
+// path of a file followed by its digests in hexadecimal; lines starting with
+// '#' are comments.
+pub(crate) fn fixtures(index: &str) -> Vec<Fixture> {
+    let text = fs::read_to_string(index_path(index)).unwrap();
+    text.lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split_whitespace();
+            let path = fields.next().unwrap().to_string();
+            let data = fs::read(crate_path(&path)).unwrap();
+            let digests = fields
+                .map(|digest| Digest(u64::from_str_radix(digest, 16).unwrap()))
+                .collect();
+            Fixture {
+                path,
+                data,
+                digests,
+            }
+        })
+        .collect()
+}
+
+// Checks that tests/fixtures/<index>.txt lists the freshly generated 'files'
+// (paths and contents) in order. With WEBP_UPDATE_FIXTURES set, rewrites the
+// index (headed by 'header') and the files under tests/fixtures/<index>/
+// instead, with the digests computed by 'digests'.
+pub(crate) fn check_fixtures<F>(index: &str, header: &str, files: &[(String, Vec<u8>)], digests: F)
+where
+    F: Fn(&[(String, Vec<u8>)]) -> Vec<Vec<Digest>>,
+{
+    if env::var_os("WEBP_UPDATE_FIXTURES").is_some() {
+        if !cfg!(feature = "c-reference") {
+            panic!("the digests to record are libwebp's, build with the c-reference feature");
+        }
+        let dir = format!("tests/fixtures/{}/", index);
+        let _ = fs::remove_dir_all(crate_path(&dir));
+        fs::create_dir_all(crate_path(&dir)).unwrap();
+        let mut text = header.to_string();
+        for ((path, data), digests) in files.iter().zip(digests(files)) {
+            if path.starts_with(&dir) {
+                fs::write(crate_path(path), data).unwrap();
+            }
+            text.push_str(path);
+            for digest in digests {
+                text.push_str(&format!(" {:016x}", digest.0));
+            }
+            text.push('\n');
+        }
+        fs::write(index_path(index), text).unwrap();
+        return;
+    }
+
+    let fixtures = fixtures(index);
+    let out_of_date = format!(
+        "tests/fixtures/{}.txt is out of date, regenerate it with \
+         WEBP_UPDATE_FIXTURES=1 cargo test --features c-reference fixtures",
+        index
+    );
+    assert_eq!(fixtures.len(), files.len(), "{}", out_of_date);
+    for (fixture, (path, data)) in fixtures.iter().zip(files) {
+        assert_eq!(&fixture.path, path, "{}", out_of_date);
+        assert!(fixture.data == *data, "{}: {}", path, out_of_date);
+    }
+}
+
+// Guards the read-modify-write of tests/fixtures/parity.txt, as the parity
+// tests run in parallel.
+static PARITY_LOCK: Mutex<()> = Mutex::new(());
+
+const PARITY_HEADER: &str = "\
+# Digests of the output of the Rust port in each parity test, which the tests
+# check when libwebp isn't built to compare against (without c-reference).
+# Regenerate with WEBP_UPDATE_FIXTURES=1 cargo test --all-features
+";
+
+// Checks 'digest', of everything the parity test 'name' computed with the
+// port, against the one recorded in tests/fixtures/parity.txt. With
+// WEBP_UPDATE_FIXTURES set, records it instead.
+pub(crate) fn check_parity(name: &str, digest: Digest) {
+    let path = index_path("parity");
+    let prefix = format!("{} ", name);
+    if env::var_os("WEBP_UPDATE_FIXTURES").is_some() {
+        // Only what the port computed is recorded, but it has to be checked
+        // against libwebp first.
+        if !cfg!(feature = "c-reference") {
+            panic!(
+                "recording the parity digests needs libwebp, build with the c-reference feature"
+            );
+        }
+        let _lock = PARITY_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        let mut lines: Vec<String> = text
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter(|line| !line.starts_with(&prefix))
+            .map(|line| line.to_string())
+            .collect();
+        lines.push(format!("{}{:016x}", prefix, digest.0));
+        lines.sort();
+        let mut text = PARITY_HEADER.to_string();
+        for line in lines {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        fs::write(&path, text).unwrap();
+        return;
+    }
+
+    let text = fs::read_to_string(&path).unwrap();
+    let recorded = text
+        .lines()
+        .find(|line| line.starts_with(&prefix))
+        .map(|line| Digest(u64::from_str_radix(&line[prefix.len()..], 16).unwrap()));
+    assert_eq!(
+        recorded,
+        Some(digest),
+        "{}: tests/fixtures/parity.txt is out of date, regenerate it with \
+         WEBP_UPDATE_FIXTURES=1 cargo test --all-features",
+        name
+    );
+}
+
+#[cfg(feature = "c-reference")]
+pub(crate) fn assert_same_state(a: &VP8BitReader, b: &VP8BitReader) {
+    assert_eq!(a.value_, b.value_);
+    assert_eq!(a.range_, b.range_);
+    assert_eq!(a.bits_, b.bits_);
+    assert_eq!(a.buf_, b.buf_);
+    assert_eq!(a.buf_end_, b.buf_end_);
+    assert_eq!(a.buf_max_, b.buf_max_);
+    assert_eq!(a.eof_, b.eof_);
+}
+
+// Payload of the first chunk tagged 'fourcc' in a WebP file.
+pub(crate) fn chunk<'a>(data: &'a [u8], fourcc: &[u8]) -> Option<&'a [u8]> {
+    let pos = data.windows(4).position(|w| w == fourcc)?;
+    let size =
+        u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+    Some(&data[pos + 8..pos + 8 + size])
+}
+
+// Raw VP8 bitstreams of the sample images.
+pub(crate) fn corpus() -> Vec<Vec<u8>> {
+    let files: [&[u8]; 2] = [
+        include_bytes!("../libwebp-1.0.0/examples/test.webp"),
+        include_bytes!("../examples/rust-logo-256x256.webp"),
+    ];
+    files
+        .iter()
+        .map(|data| chunk(data, b"VP8 ").unwrap().to_vec())
+        .collect()
+}
+
+pub(crate) fn new_io(data: &[u8]) -> VP8Io {
+    let mut io: VP8Io = unsafe { mem::zeroed() };
+    assert_eq!(VP8InitIo(&mut io), 1);
+    io.data = data.as_ptr();
+    io.data_size = data.len();
+    io
+}
+
+// Collects the emitted Y, U, V (and A) samples into the Vec<u8> in 'opaque'.
+pub(crate) extern "C" fn put(io: *const VP8Io) -> c_int {
+    unsafe {
+        let io: &VP8Io = &*io;
+        let out: &mut Vec<u8> = &mut *(io.opaque as *mut Vec<u8>);
+        let uv_w = ((io.mb_w + 1) / 2) as usize;
+        for y in 0..io.mb_h {
+            let row = io.y.offset((y * io.y_stride) as isize);
+            out.extend_from_slice(slice::from_raw_parts(row, io.mb_w as usize));
+        }
+        for y in 0..(io.mb_h + 1) / 2 {
+            let offset = (y * io.uv_stride) as isize;
+            out.extend_from_slice(slice::from_raw_parts(io.u.offset(offset), uv_w));
+            out.extend_from_slice(slice::from_raw_parts(io.v.offset(offset), uv_w));
+        }
+        if !io.a.is_null() {
+            for y in 0..io.mb_h {
+                let row = io.a.offset((y * io.width) as isize);
+                out.extend_from_slice(slice::from_raw_parts(row, io.mb_w as usize));
+            }
+        }
+    }
+    1
+}
+
+// The sample values used for each of the y, u and v axes: a regular grid
+// over [0, 255] which includes both ends of the range.
+pub(crate) fn grid() -> Vec<u8> {
+    (0..=255).step_by(3).collect()
+}
+
+// RGBA test images: smooth gradients, noise and few-color (paletted)
+// content, with and without transparency. Each of 'specs' gives the width,
+// the height, the number of colors (0 for a gradient with 'noise' masking the
+// random bits added to it) and whether the image has an alpha channel.
+pub(crate) fn rgba_images(
+    seed: u32,
+    noise: u8,
+    specs: &[(u32, u32, u32, bool)],
+) -> Vec<(u32, u32, Vec<u8>)> {
+    let mut rng = Lcg(seed);
+    let mut images = Vec::new();
+    for &(width, height, num_colors, alpha) in specs {
+        let palette: Vec<u32> = (0..num_colors).map(|_| rng.next()).collect();
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = if num_colors > 0 {
+                    // Runs of the same color, for backward references.
+                    palette[((x / 3 + y * 7) % num_colors) as usize].to_le_bytes()
+                } else {
+                    let noise = rng.next() as u8 & noise;
+                    [
+                        (x * 255 / width) as u8 ^ noise,
+                        (y * 255 / height) as u8,
+                        ((x + y) * 4) as u8,
+                        (x * y) as u8,
+                    ]
+                };
+                let a = if alpha { pixel[3] } else { 0xff };
+                rgba.extend_from_slice(&[pixel[0], pixel[1], pixel[2], a]);
+            }
+        }
+        images.push((width, height, rgba));
+    }
+    images
+}
+
+// rgba_images() as ARGB words, the input of the lossless encoder stages.
+#[cfg(feature = "rust-vp8l-encoder")]
+pub(crate) fn argb_images(
+    seed: u32,
+    noise: u8,
+    specs: &[(u32, u32, u32, bool)],
+) -> Vec<(c_int, c_int, Vec<u32>)> {
+    rgba_images(seed, noise, specs)
+        .into_iter()
+        .map(|(width, height, rgba)| {
+            let argb = rgba
+                .chunks(4)
+                .map(|p| u32::from_be_bytes([p[3], p[0], p[1], p[2]]))
+                .collect();
+            (width as c_int, height as c_int, argb)
+        })
+        .collect()
+}
+
+// RGBA test images for the lossy encoder: smooth gradients with some noise
+// and sharp edges, so that the segmentation, the intra4 modes and the skip
+// flags all get used.
+#[cfg(feature = "rust-vp8-encoder")]
+pub(crate) fn lossy_images() -> Vec<(u32, u32, Vec<u8>)> {
+    let mut rng = Lcg(0x1234);
+    let mut images = Vec::new();
+    for &(width, height, noise, alpha) in &[
+        (1, 1, 0x00, false),
+        (5, 3, 0x3f, true),
+        (16, 16, 0x00, false),
+        (33, 17, 0x07, true),
+        (64, 48, 0x1f, false),
+        (100, 75, 0x03, true),
+    ] {
+        let mut rgba = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                let noise = rng.next() as u8 & noise;
+                let edge = if (x / 11 + y / 7) % 3 == 0 { 0x80 } else { 0 };
+                let a = if alpha { (x * 255 / width) as u8 } else { 0xff };
+                rgba.extend_from_slice(&[
+                    (x * 255 / width) as u8 ^ noise,
+                    (y * 255 / height) as u8 ^ edge,
+                    ((x + y) * 4) as u8 ^ noise,
+                    a,
+                ]);
+            }
+        }
+        images.push((width, height, rgba));
+    }
+    images
+}
+
+#[cfg(feature = "rust-vp8-encoder")]
+struct IteratorApi {
+    init: unsafe extern "C" fn(*mut VP8Encoder, *mut VP8EncIterator),
+    import: unsafe extern "C" fn(*mut VP8EncIterator, *mut u8),
+    save_boundary: unsafe extern "C" fn(*mut VP8EncIterator),
+    next: unsafe extern "C" fn(*mut VP8EncIterator) -> c_int,
+}
+
+#[cfg(feature = "rust-vp8-encoder")]
+const PORT_ITERATOR: IteratorApi = IteratorApi {
+    init: VP8IteratorInit,
+    import: VP8IteratorImport,
+    save_boundary: VP8IteratorSaveBoundary,
+    next: VP8IteratorNext,
+};
+
+#[cfg(all(feature = "rust-vp8-encoder", feature = "c-reference"))]
+const REFERENCE_ITERATOR: IteratorApi = IteratorApi {
+    init: WebPRef_VP8IteratorInit,
+    import: WebPRef_VP8IteratorImport,
+    save_boundary: WebPRef_VP8IteratorSaveBoundary,
+    next: WebPRef_VP8IteratorNext,
+};
+
+// A lossy encoder set up the way WebPEncode() does it before the analysis,
+// to run the encoding stages on one by one.
+#[cfg(feature = "rust-vp8-encoder")]
+pub(crate) struct StageEncoder {
+    pub(crate) enc: *mut VP8Encoder,
+    // The macroblock infos in the C layout, see use_reference_layout().
+    reference_mb_info: Option<Vec<u32>>,
+    _config: Box<WebPConfig>,
+    picture: Box<WebPPicture>,
+    writer: Box<WebPMemoryWriter>,
+}
+
+#[cfg(feature = "rust-vp8-encoder")]
+impl StageEncoder {
+    fn new(rgba: &[u8], width: u32, height: u32, config: &EncoderConfig) -> Self {
+        unsafe {
+            let config = Box::new(config.to_raw());
+            let mut picture: Box<WebPPicture> = Box::new(mem::zeroed());
+            assert_ne!(
+                WebPPictureInitInternal(&mut *picture, WEBP_ENCODER_ABI_VERSION as c_int),
+                0
+            );
+            let mut writer: Box<WebPMemoryWriter> = Box::new(mem::zeroed());
+            WebPMemoryWriterInit(&mut *writer);
+            picture.width = width as c_int;
+            picture.height = height as c_int;
+            picture.writer = Some(WebPMemoryWrite);
+            picture.custom_ptr = &mut *writer as *mut WebPMemoryWriter as *mut c_void;
+            // Imported straight to YUV420, as WebPPictureARGBToYUVA() would.
+            assert_ne!(
+                WebPPictureImportRGBA(&mut *picture, rgba.as_ptr(), width as c_int * 4),
+                0
+            );
+            let enc = InitVP8Encoder(&*config, &mut *picture);
+            assert!(!enc.is_null());
+            // The macroblock infos and the prediction modes inside the borders
+            // are left uninitialized until the stages fill them in.
+            let (mb_w, mb_h, preds_w) = ((*enc).mb_w_, (*enc).mb_h_, (*enc).preds_w_);
+            ptr::write_bytes((*enc).mb_info_, 0, (mb_w * mb_h) as usize);
+            for y in 0..4 * mb_h {
+                ptr::write_bytes(
+                    (*enc).preds_.offset((y * preds_w) as isize),
+                    0,
+                    4 * mb_w as usize,
+                );
+            }
+            StageEncoder {
+                enc,
+                reference_mb_info: None,
+                _config: config,
+                picture,
+                writer,
+            }
+        }
+    }
+
+    // Switches the macroblock infos to the layout of the C VP8MBInfo, which
+    // packs the first four fields in the bits of the first byte. The stages
+    // and the iterator of the reference only work on that one.
+    #[cfg(feature = "c-reference")]
+    fn use_reference_layout(&mut self) {
+        let mut packed: Vec<u32> = self
+            .mb_info()
+            .iter()
+            .map(|info| {
+                u32::from_le_bytes([
+                    info[0] | info[1] << 2 | info[2] << 4 | info[3] << 5,
+                    info[4],
+                    0,
+                    0,
+                ])
+            })
+            .collect();
+        unsafe {
+            (*self.enc).mb_info_ = packed.as_mut_ptr() as *mut _;
+        }
+        self.reference_mb_info = Some(packed);
+    }
+
+    // The macroblock infos, as [type_, uv_mode_, skip_, segment_, alpha_].
+    pub(crate) fn mb_info(&self) -> Vec<[u8; 5]> {
+        match self.reference_mb_info {
+            Some(ref packed) => packed
+                .iter()
+                .map(|&bits| {
+                    let bits = bits.to_le_bytes();
+                    [
+                        bits[0] & 3,
+                        bits[0] >> 2 & 3,
+                        bits[0] >> 4 & 1,
+                        bits[0] >> 5 & 3,
+                        bits[1],
+                    ]
+                })
+                .collect(),
+            None => unsafe {
+                let count = (*self.enc).mb_w_ * (*self.enc).mb_h_;
+                slice::from_raw_parts((*self.enc).mb_info_, count as usize)
+                    .iter()
+                    .map(|info| {
+                        [
+                            info.type_,
+                            info.uv_mode_,
+                            info.skip_,
+                            info.segment_,
+                            info.alpha_,
+                        ]
+                    })
+                    .collect()
+            },
+        }
+    }
+
+    // The whole prediction mode plane, borders included.
+    pub(crate) fn preds(&self) -> Vec<u8> {
+        unsafe {
+            let preds_w = (*self.enc).preds_w_;
+            let size = preds_w * (4 * (*self.enc).mb_h_ + 1);
+            slice::from_raw_parts(
+                (*self.enc).preds_.offset(-(preds_w as isize) - 1),
+                size as usize,
+            )
+            .to_vec()
+        }
+    }
+
+    // What VP8EncWrite() wrote.
+    pub(crate) fn output(&self) -> Vec<u8> {
+        if self.writer.size == 0 {
+            return Vec::new();
+        }
+        unsafe { slice::from_raw_parts(self.writer.mem, self.writer.size).to_vec() }
+    }
+
+    fn iterator_api(&self) -> IteratorApi {
+        #[cfg(feature = "c-reference")]
+        {
+            if self.reference_mb_info.is_some() {
+                return REFERENCE_ITERATOR;
+            }
+        }
+        PORT_ITERATOR
+    }
+}
+
+#[cfg(feature = "rust-vp8-encoder")]
+impl Drop for StageEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            DeleteVP8Encoder(self.enc);
+            WebPMemoryWriterClear(&mut *self.writer);
+            WebPPictureFree(&mut *self.picture);
+        }
+    }
+}
+
+// The encoders to run the stage under test of each version on: the one of
+// the port, then with c-reference the one of the reference. 'prepare' runs the
+// stages before it, with the port on both.
+#[cfg(feature = "rust-vp8-encoder")]
+pub(crate) fn stage_encoders<F>(
+    rgba: &[u8],
+    width: u32,
+    height: u32,
+    config: &EncoderConfig,
+    mut prepare: F,
+) -> Vec<StageEncoder>
+where
+    F: FnMut(*mut VP8Encoder),
+{
+    #[cfg(feature = "c-reference")]
+    unsafe {
+        // The reference quant_enc.c uses the reference VP8TransformWHT.
+        WebPRef_VP8DspInit();
+    }
+    let count = if cfg!(feature = "c-reference") { 2 } else { 1 };
+    let encoders: Vec<StageEncoder> = (0..count)
+        .map(|_| {
+            let encoder = StageEncoder::new(rgba, width, height, config);
+            prepare(encoder.enc);
+            encoder
+        })
+        .collect();
+    #[cfg(feature = "c-reference")]
+    let encoders = {
+        let mut encoders = encoders;
+        encoders[1].use_reference_layout();
+        encoders
+    };
+    encoders
+}
+
+// Quantized levels of a random 4x4 block: all zero a quarter of the time,
+// otherwise mostly small, with some beyond MAX_VARIABLE_LEVEL.
+#[cfg(feature = "rust-vp8-encoder")]
+pub(crate) fn coeff_levels(rng: &mut Lcg) -> [i16; 16] {
+    let mut levels = [0; 16];
+    if rng.next() % 4 == 0 {
+        return levels;
+    }
+    for level in levels.iter_mut() {
+        let r = rng.next();
+        let magnitude = match r % 8 {
+            0..=3 => 0,
+            4 | 5 => 1 + (r >> 3) % 3,
+            6 => 1 + (r >> 3) % 67,
+            _ => 1 + (r >> 3) % 2047,
+        } as i16;
+        *level = if r & 0x100 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+    }
+    levels
+}
+
+// Calls 'f' on each macroblock in the order of VP8EncLoop(), with one iterator
+// per encoder moved along in lockstep. Returns the iterators once done.
+#[cfg(feature = "rust-vp8-encoder")]
+pub(crate) fn for_each_macroblock<F>(
+    encoders: &[StageEncoder],
+    mut f: F,
+) -> Vec<Box<VP8EncIterator>>
+where
+    F: FnMut(&mut [Box<VP8EncIterator>]),
+{
+    let apis: Vec<IteratorApi> = encoders.iter().map(StageEncoder::iterator_api).collect();
+    unsafe {
+        let mut its: Vec<Box<VP8EncIterator>> = encoders
+            .iter()
+            .zip(&apis)
+            .map(|(encoder, api)| {
+                let mut it = Box::new(mem::zeroed());
+                (api.init)(encoder.enc, &mut *it);
+                it
+            })
+            .collect();
+        loop {
+            for (it, api) in its.iter_mut().zip(&apis) {
+                (api.import)(&mut **it, ptr::null_mut());
+            }
+            f(&mut its);
+            let mut done = false;
+            for (it, api) in its.iter_mut().zip(&apis) {
+                (api.save_boundary)(&mut **it);
+                done = (api.next)(&mut **it) == 0;
+            }
+            if done {
+                return its;
+            }
+        }
+    }
+}
+
+#[allow(non_upper_case_globals)]
+pub(crate) fn bytes_per_pixel(mode: WEBP_CSP_MODE) -> usize {
+    match mode {
+        MODE_RGB | MODE_BGR => 3,
+        MODE_RGBA_4444 | MODE_rgbA_4444 | MODE_RGB_565 => 2,
+        _ => 4,
+    }
+}
+
+// Rewraps a still image into an extended file carrying the given metadata
+// chunks.
+pub(crate) fn with_chunks(webp: &[u8], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    use format_constants::*;
+
+    let (width, height) = get_info(webp).unwrap();
+    let image = *Demuxer::new(webp).unwrap().frame(1).unwrap();
+    let mut flags = if image.has_alpha { ALPHA_FLAG } else { 0 };
+    let mut body = Vec::new();
+    for &(fourcc, payload) in chunks {
+        flags |= match fourcc {
+            b"ICCP" => ICCP_FLAG,
+            b"EXIF" => EXIF_FLAG,
+            b"XMP " => XMP_FLAG,
+            _ => 0,
+        };
+        body.extend_from_slice(fourcc);
+        body.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        body.extend_from_slice(payload);
+        if payload.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    body.extend_from_slice(image.payload);
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"RIFF");
+    let riff_size = 4 + CHUNK_HEADER_SIZE + VP8X_CHUNK_SIZE + body.len();
+    out.extend_from_slice(&(riff_size as u32).to_le_bytes());
+    out.extend_from_slice(b"WEBPVP8X");
+    out.extend_from_slice(&(VP8X_CHUNK_SIZE as u32).to_le_bytes());
+    out.extend_from_slice(&flags.to_le_bytes());
+    out.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    out.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    out.extend_from_slice(&body);
+    out
+}
+
+// Minimal TIFF structure with an IFD0 holding an orientation tag
+// (preceded by another tag).
+pub(crate) fn exif_with_orientation(orientation: u16, big_endian: bool) -> Vec<u8> {
+    let u16_bytes = |v: u16| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let u32_bytes = |v: u32| {
+        if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        }
+    };
+    let mut data = Vec::new();
+    data.extend_from_slice(if big_endian { b"MM\0*" } else { b"II*\0" });
+    data.extend_from_slice(&u32_bytes(8));
+    data.extend_from_slice(&u16_bytes(2));
+    // ImageDescription, ASCII, stored at offset 38.
+    data.extend_from_slice(&u16_bytes(0x010e));
+    data.extend_from_slice(&u16_bytes(2));
+    data.extend_from_slice(&u32_bytes(6));
+    data.extend_from_slice(&u32_bytes(38));
+    data.extend_from_slice(&u16_bytes(TAG_ORIENTATION));
+    data.extend_from_slice(&u16_bytes(TYPE_SHORT));
+    data.extend_from_slice(&u32_bytes(1));
+    data.extend_from_slice(&u16_bytes(orientation));
+    data.extend_from_slice(&[0, 0]);
+    data.extend_from_slice(&u32_bytes(0));
+    data.extend_from_slice(b"photo\0");
+    data
+}
@@ -0,0 +1,144 @@
+// Copyright 2014 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Specific inlined methods for boolean decoder [VP8GetBit() ...]
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+#![allow(dead_code)]
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use utils::bit_reader_utils::{
+    bit_t, kVP8Log2Range, kVP8NewRange, range_t, VP8BitReader, VP8LoadFinalBytes, BITS,
+};
+use utils::utils::BitsLog2Floor;
+
+//------------------------------------------------------------------------------
+// Derived type lbit_t = natural type for memory I/O
+
+cfg_if! {
+    if #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))] {
+        // BITS > 32
+        #[allow(non_camel_case_types)]
+        pub(crate) type lbit_t = u64;
+    } else {
+        // BITS > 16
+        #[allow(non_camel_case_types)]
+        pub(crate) type lbit_t = u32;
+    }
+}
+
+//------------------------------------------------------------------------------
+// Inlined critical functions
+
+// makes sure br->value_ has at least BITS bits worth of data
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe extern "C" fn VP8LoadNewBytes(br: *mut VP8BitReader) {
+    c_assert!(!br.is_null() && !(*br).buf_.is_null());
+    let br: &mut VP8BitReader = &mut *br;
+
+    // Read 'BITS' bits at a time if possible.
+    if br.buf_ < br.buf_max_ {
+        // convert memory type to register type (with some zero'ing!)
+        let in_bits: lbit_t = ptr::read_unaligned(br.buf_ as *const lbit_t);
+        br.buf_ = br.buf_.add(BITS >> 3);
+        let bits: bit_t =
+            (lbit_t::from_be(in_bits) >> (8 * mem::size_of::<lbit_t>() - BITS)) as bit_t;
+        br.value_ = bits | (br.value_ << BITS);
+        br.bits_ += BITS as c_int;
+    } else {
+        VP8LoadFinalBytes(br); // no need to be inlined
+    }
+}
+
+// Read a bit with proba 'prob'. Speed-critical function!
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe extern "C" fn VP8GetBit(br: *mut VP8BitReader, prob: c_int) -> c_int {
+    // Don't move this declaration! It makes a big speed difference to store
+    // 'range' *before* calling VP8LoadNewBytes(), even if this function doesn't
+    // alter br->range_ value.
+    let mut range: range_t = (*br).range_;
+    if (*br).bits_ < 0 {
+        VP8LoadNewBytes(br);
+    }
+    let br: &mut VP8BitReader = &mut *br;
+    let pos = br.bits_;
+    let split: range_t = (range * prob as range_t) >> 8;
+    let value: range_t = (br.value_ >> pos) as range_t;
+    let bit = (value > split) as c_int;
+    if bit != 0 {
+        range -= split;
+        br.value_ -= ((split + 1) as bit_t) << pos;
+    } else {
+        range = split + 1;
+    }
+    let shift = 7 ^ BitsLog2Floor(range);
+    range <<= shift;
+    br.bits_ -= shift;
+    br.range_ = range - 1;
+    bit
+}
+
+// simplified version of VP8GetBit() for prob=0x80 (note shift is always 1 here)
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe extern "C" fn VP8GetSigned(br: *mut VP8BitReader, v: c_int) -> c_int {
+    if (*br).bits_ < 0 {
+        VP8LoadNewBytes(br);
+    }
+    let br: &mut VP8BitReader = &mut *br;
+    let pos = br.bits_;
+    let split: range_t = br.range_ >> 1;
+    let value: range_t = (br.value_ >> pos) as range_t;
+    let mask: i32 = (split.wrapping_sub(value) as i32) >> 31; // -1 or 0
+    br.bits_ -= 1;
+    br.range_ = br.range_.wrapping_add(mask as range_t);
+    br.range_ |= 1;
+    br.value_ = br
+        .value_
+        .wrapping_sub((((split + 1) & mask as range_t) as bit_t) << pos);
+    (v ^ mask) - mask
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe extern "C" fn VP8GetBitAlt(br: *mut VP8BitReader, prob: c_int) -> c_int {
+    // Don't move this declaration! It makes a big speed difference to store
+    // 'range' *before* calling VP8LoadNewBytes(), even if this function doesn't
+    // alter br->range_ value.
+    let mut range: range_t = (*br).range_;
+    if (*br).bits_ < 0 {
+        VP8LoadNewBytes(br);
+    }
+    let br: &mut VP8BitReader = &mut *br;
+    let pos = br.bits_;
+    let split: range_t = (range * prob as range_t) >> 8;
+    let value: range_t = (br.value_ >> pos) as range_t;
+    let bit = if value > split {
+        range -= split + 1;
+        br.value_ -= ((split + 1) as bit_t) << pos;
+        1
+    } else {
+        range = split;
+        0
+    };
+    if range <= 0x7e {
+        let shift = kVP8Log2Range[range as usize] as c_int;
+        range = kVP8NewRange[range as usize] as range_t;
+        br.bits_ -= shift;
+    }
+    br.range_ = range;
+    bit
+}
@@ -1,7 +1,25 @@
+// Copyright 2010 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Boolean decoder non-inlined methods
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
 #![allow(dead_code)]
 
+use std::mem;
 use std::os::raw::*;
 
+use utils::bit_reader_inl_utils::{lbit_t, VP8GetBit, VP8LoadNewBytes};
+use utils::utils::WebPMemToUint32;
+
 cfg_if! {
     if #[cfg(target_arch = "x86")] {
         // x86 32bit
@@ -40,7 +58,7 @@ cfg_if! {
 pub(crate) type range_t = u32;
 
 #[repr(C)]
-pub(crate) struct VP8BitReader {
+pub struct VP8BitReader {
     // boolean decoder  (keep the field ordering as is!)
     pub(crate) value_: bit_t,   // current value
     pub(crate) range_: range_t, // current range minus 1. In [127, 254] interval.
@@ -52,13 +70,532 @@ pub(crate) struct VP8BitReader {
     pub(crate) eof_: c_int,         // true if input is exhausted
 }
 
+//------------------------------------------------------------------------------
+// VP8BitReader
+
+// Sets the working read buffer.
+#[no_mangle]
+pub unsafe extern "C" fn VP8BitReaderSetBuffer(
+    br: *mut VP8BitReader,
+    start: *const u8,
+    size: usize,
+) {
+    let br: &mut VP8BitReader = &mut *br;
+
+    br.buf_ = start;
+    br.buf_end_ = start.add(size);
+    br.buf_max_ = if size >= mem::size_of::<lbit_t>() {
+        start.add(size - mem::size_of::<lbit_t>() + 1)
+    } else {
+        start
+    };
+}
+
+// Initialize the bit reader and the boolean decoder.
+#[no_mangle]
+pub unsafe extern "C" fn VP8InitBitReader(br: *mut VP8BitReader, start: *const u8, size: usize) {
+    c_assert!(!br.is_null());
+    c_assert!(!start.is_null());
+    c_assert!(size < (1u32 << 31) as usize); // limit ensured by format and upstream checks
+    {
+        let br: &mut VP8BitReader = &mut *br;
+        br.range_ = 255 - 1;
+        br.value_ = 0;
+        br.bits_ = -8; // to load the very first 8bits
+        br.eof_ = 0;
+    }
+    VP8BitReaderSetBuffer(br, start, size);
+    VP8LoadNewBytes(br);
+}
+
+// Update internal pointers to displace the byte buffer by the
+// relative offset 'offset'.
+#[no_mangle]
+pub unsafe extern "C" fn VP8RemapBitReader(br: *mut VP8BitReader, offset: isize) {
+    let br: &mut VP8BitReader = &mut *br;
+
+    if !br.buf_.is_null() {
+        br.buf_ = br.buf_.offset(offset);
+        br.buf_end_ = br.buf_end_.offset(offset);
+        br.buf_max_ = br.buf_max_.offset(offset);
+    }
+}
+
+#[rustfmt::skip]
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static kVP8Log2Range: [u8; 128] = [
+     7, 6, 6, 5, 5, 5, 5, 4, 4, 4, 4, 4, 4, 4, 4,
+  3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+  2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+  2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  0
+];
+
+// range = ((range - 1) << kVP8Log2Range[range]) + 1
+#[rustfmt::skip]
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static kVP8NewRange: [u8; 128] = [
+  127, 127, 191, 127, 159, 191, 223, 127,
+  143, 159, 175, 191, 207, 223, 239, 127,
+  135, 143, 151, 159, 167, 175, 183, 191,
+  199, 207, 215, 223, 231, 239, 247, 127,
+  131, 135, 139, 143, 147, 151, 155, 159,
+  163, 167, 171, 175, 179, 183, 187, 191,
+  195, 199, 203, 207, 211, 215, 219, 223,
+  227, 231, 235, 239, 243, 247, 251, 127,
+  129, 131, 133, 135, 137, 139, 141, 143,
+  145, 147, 149, 151, 153, 155, 157, 159,
+  161, 163, 165, 167, 169, 171, 173, 175,
+  177, 179, 181, 183, 185, 187, 189, 191,
+  193, 195, 197, 199, 201, 203, 205, 207,
+  209, 211, 213, 215, 217, 219, 221, 223,
+  225, 227, 229, 231, 233, 235, 237, 239,
+  241, 243, 245, 247, 249, 251, 253, 127
+];
+
+// special case for the tail byte-reading
+#[no_mangle]
+pub unsafe extern "C" fn VP8LoadFinalBytes(br: *mut VP8BitReader) {
+    c_assert!(!br.is_null() && !(*br).buf_.is_null());
+    let br: &mut VP8BitReader = &mut *br;
+
+    // Only read 8bits at a time
+    if br.buf_ < br.buf_end_ {
+        br.bits_ += 8;
+        br.value_ = *br.buf_ as bit_t | (br.value_ << 8);
+        br.buf_ = br.buf_.offset(1);
+    } else if br.eof_ == 0 {
+        br.value_ <<= 8;
+        br.bits_ += 8;
+        br.eof_ = 1;
+    } else {
+        br.bits_ = 0; // This is to avoid undefined behaviour with shifts.
+    }
+}
+
+//------------------------------------------------------------------------------
+// Higher-level calls
+
+// return the next value made of 'num_bits' bits
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetValue(br: *mut VP8BitReader, mut bits: c_int) -> u32 {
+    let mut v: u32 = 0;
+    while bits > 0 {
+        bits -= 1;
+        v |= (VP8GetBit(br, 0x80) as u32) << bits;
+    }
+    v
+}
+
 #[allow(non_snake_case)]
 #[inline]
 pub(crate) unsafe extern "C" fn VP8Get(br: *mut VP8BitReader) -> u32 {
     VP8GetValue(br, 1)
 }
 
-extern "C" {
-    pub(crate) fn VP8GetValue(br: *mut VP8BitReader, num_bits: c_int) -> u32;
-    pub(crate) fn VP8GetSignedValue(br: *mut VP8BitReader, num_bits: c_int) -> i32;
+// return the next value with sign-extension.
+#[no_mangle]
+pub unsafe extern "C" fn VP8GetSignedValue(br: *mut VP8BitReader, bits: c_int) -> i32 {
+    let value = VP8GetValue(br, bits) as c_int;
+    if VP8Get(br) != 0 {
+        -value
+    } else {
+        value
+    }
+}
+
+//------------------------------------------------------------------------------
+// VP8LBitReader
+
+pub(crate) const VP8L_MAX_NUM_BIT_READ: c_int = 24;
+
+pub(crate) const VP8L_LBITS: c_int = 64; // Number of bits prefetched (= bit-size of vp8l_val_t).
+pub(crate) const VP8L_WBITS: c_int = 32; // Minimum number of bytes ready after VP8LFillBitWindow.
+
+#[allow(non_camel_case_types)]
+pub(crate) type vp8l_val_t = u64; // right now, this bit-reader can only use 64bit.
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VP8LBitReader {
+    pub(crate) val_: vp8l_val_t, // pre-fetched bits
+    pub(crate) buf_: *const u8,  // input byte buffer
+    pub(crate) len_: usize,      // buffer length
+    pub(crate) pos_: usize,      // byte position in buf_
+    pub(crate) bit_pos_: c_int,  // current bit-reading position in val_
+    pub(crate) eos_: c_int,      // true if a bit was read past the end of buffer
+}
+
+const VP8L_LOG8_WBITS: usize = 4; // Number of bytes needed to store VP8L_WBITS bits.
+
+#[cfg(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
+const VP8L_USE_FAST_LOAD: bool = true;
+#[cfg(not(any(
+    target_arch = "arm",
+    target_arch = "aarch64",
+    target_arch = "x86",
+    target_arch = "x86_64"
+)))]
+const VP8L_USE_FAST_LOAD: bool = false;
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+const kBitMask: [u32; VP8L_MAX_NUM_BIT_READ as usize + 1] = [
+  0,
+  0x000001, 0x000003, 0x000007, 0x00000f,
+  0x00001f, 0x00003f, 0x00007f, 0x0000ff,
+  0x0001ff, 0x0003ff, 0x0007ff, 0x000fff,
+  0x001fff, 0x003fff, 0x007fff, 0x00ffff,
+  0x01ffff, 0x03ffff, 0x07ffff, 0x0fffff,
+  0x1fffff, 0x3fffff, 0x7fffff, 0xffffff
+];
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LInitBitReader(
+    br: *mut VP8LBitReader,
+    start: *const u8,
+    length: usize,
+) {
+    c_assert!(!br.is_null());
+    c_assert!(!start.is_null());
+    c_assert!(length < 0xfffffff8); // can't happen with a RIFF chunk.
+    let br: &mut VP8LBitReader = &mut *br;
+
+    let mut value: vp8l_val_t = 0;
+    br.len_ = length;
+    br.val_ = 0;
+    br.bit_pos_ = 0;
+    br.eos_ = 0;
+
+    let length = if length > mem::size_of::<vp8l_val_t>() {
+        mem::size_of::<vp8l_val_t>()
+    } else {
+        length
+    };
+    for i in 0..length {
+        value |= (*start.add(i) as vp8l_val_t) << (8 * i);
+    }
+    br.val_ = value;
+    br.pos_ = length;
+    br.buf_ = start;
+}
+
+//  Sets a new data buffer.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitReaderSetBuffer(
+    br: *mut VP8LBitReader,
+    buf: *const u8,
+    len: usize,
+) {
+    c_assert!(!br.is_null());
+    c_assert!(!buf.is_null());
+    c_assert!(len < 0xfffffff8); // can't happen with a RIFF chunk.
+    let br: &mut VP8LBitReader = &mut *br;
+
+    br.buf_ = buf;
+    br.len_ = len;
+    // pos_ > len_ should be considered a param error.
+    br.eos_ = (br.pos_ > br.len_ || VP8LIsEndOfStream(br) != 0) as c_int;
+}
+
+#[allow(non_snake_case)]
+fn VP8LSetEndOfStream(br: &mut VP8LBitReader) {
+    br.eos_ = 1;
+    br.bit_pos_ = 0; // To avoid undefined behaviour with shifts.
+}
+
+// If not at EOS, reload up to VP8L_LBITS byte-by-byte
+#[allow(non_snake_case)]
+unsafe fn ShiftBytes(br: &mut VP8LBitReader) {
+    while br.bit_pos_ >= 8 && br.pos_ < br.len_ {
+        br.val_ >>= 8;
+        br.val_ |= (*br.buf_.add(br.pos_) as vp8l_val_t) << (VP8L_LBITS - 8);
+        br.pos_ += 1;
+        br.bit_pos_ -= 8;
+    }
+    if VP8LIsEndOfStream(br) != 0 {
+        VP8LSetEndOfStream(br);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LDoFillBitWindow(br: *mut VP8LBitReader) {
+    let br: &mut VP8LBitReader = &mut *br;
+
+    c_assert!(br.bit_pos_ >= VP8L_WBITS);
+    if VP8L_USE_FAST_LOAD && br.pos_ + mem::size_of::<vp8l_val_t>() < br.len_ {
+        br.val_ >>= VP8L_WBITS;
+        br.bit_pos_ -= VP8L_WBITS;
+        br.val_ |= (u32::from_le(WebPMemToUint32(br.buf_.add(br.pos_))) as vp8l_val_t)
+            << (VP8L_LBITS - VP8L_WBITS);
+        br.pos_ += VP8L_LOG8_WBITS;
+    } else {
+        ShiftBytes(br); // Slow path.
+    }
+}
+
+// Reads the specified number of bits from read buffer.
+// Flags an error in case end_of_stream or n_bits is more than the allowed limit
+// of VP8L_MAX_NUM_BIT_READ (inclusive).
+// Flags 'eos_' if this read attempt is going to cross the read buffer.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LReadBits(br: *mut VP8LBitReader, n_bits: c_int) -> u32 {
+    c_assert!(n_bits >= 0);
+    let br: &mut VP8LBitReader = &mut *br;
+
+    // Flag an error if end_of_stream or n_bits is more than allowed limit.
+    if br.eos_ == 0 && n_bits <= VP8L_MAX_NUM_BIT_READ {
+        let val: u32 = VP8LPrefetchBits(br) & kBitMask[n_bits as usize];
+        let new_bits: c_int = br.bit_pos_ + n_bits;
+        br.bit_pos_ = new_bits;
+        ShiftBytes(br);
+        val
+    } else {
+        VP8LSetEndOfStream(br);
+        0
+    }
+}
+
+// Return the prefetched bits, so they can be looked up.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LPrefetchBits(br: &VP8LBitReader) -> u32 {
+    (br.val_ >> (br.bit_pos_ & (VP8L_LBITS - 1))) as u32
+}
+
+// Returns true if there was an attempt at reading bit past the end of
+// the buffer. Doesn't set br->eos_ flag.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LIsEndOfStream(br: &VP8LBitReader) -> c_int {
+    c_assert!(br.pos_ <= br.len_);
+    (br.eos_ != 0 || (br.pos_ == br.len_ && br.bit_pos_ > VP8L_LBITS)) as c_int
+}
+
+// For jumping over a number of bits in the bit stream when accessed with
+// VP8LPrefetchBits and VP8LFillBitWindow.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LSetBitPos(br: &mut VP8LBitReader, val: c_int) {
+    br.bit_pos_ = val;
+}
+
+// Advances the read buffer by 4 bytes to make room for reading next 32 bits.
+// Speed critical, but infrequent part of the code can be non-inlined.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LFillBitWindow(br: *mut VP8LBitReader) {
+    if (*br).bit_pos_ >= VP8L_WBITS {
+        VP8LDoFillBitWindow(br);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ptr;
+
+    #[cfg(feature = "c-reference")]
+    use test_util::assert_same_state;
+    use test_util::{check_parity, Digest, Lcg};
+    use utils::bit_reader_inl_utils::{VP8GetBitAlt, VP8GetSigned};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8InitBitReader(br: *mut VP8BitReader, start: *const u8, size: usize);
+        fn WebPRef_VP8GetValue(br: *mut VP8BitReader, num_bits: c_int) -> u32;
+        fn WebPRef_VP8GetSignedValue(br: *mut VP8BitReader, num_bits: c_int) -> i32;
+        fn WebPRef_VP8GetBit(br: *mut VP8BitReader, prob: c_int) -> c_int;
+        fn WebPRef_VP8GetBitAlt(br: *mut VP8BitReader, prob: c_int) -> c_int;
+        fn WebPRef_VP8GetSigned(br: *mut VP8BitReader, v: c_int) -> c_int;
+        fn WebPRef_VP8LInitBitReader(br: *mut VP8LBitReader, start: *const u8, length: usize);
+        fn WebPRef_VP8LBitReaderSetBuffer(br: *mut VP8LBitReader, buf: *const u8, len: usize);
+        fn WebPRef_VP8LReadBits(br: *mut VP8LBitReader, n_bits: c_int) -> u32;
+        fn WebPRef_VP8LFillBitWindow(br: *mut VP8LBitReader);
+        fn WebPRef_VP8LPrefetchBits(br: *mut VP8LBitReader) -> u32;
+    }
+
+    fn new_br() -> VP8BitReader {
+        VP8BitReader {
+            value_: 0,
+            range_: 0,
+            bits_: 0,
+            buf_: ptr::null(),
+            buf_end_: ptr::null(),
+            buf_max_: ptr::null(),
+            eof_: 0,
+        }
+    }
+
+    fn new_vp8l_br() -> VP8LBitReader {
+        VP8LBitReader {
+            val_: 0,
+            buf_: ptr::null(),
+            len_: 0,
+            pos_: 0,
+            bit_pos_: 0,
+            eos_: 0,
+        }
+    }
+
+    #[cfg(feature = "c-reference")]
+    fn assert_same_vp8l_state(a: &VP8LBitReader, b: &VP8LBitReader) {
+        assert_eq!(a.val_, b.val_);
+        assert_eq!(a.buf_, b.buf_);
+        assert_eq!(a.len_, b.len_);
+        assert_eq!(a.pos_, b.pos_);
+        assert_eq!(a.bit_pos_, b.bit_pos_);
+        assert_eq!(a.eos_, b.eos_);
+    }
+
+    // The read values go into the digests, but not the reader states, which
+    // depend on the size of bit_t.
+    #[test]
+    fn test_vp8_bit_reader_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(1);
+        for &len in &[0, 1, 2, 7, 8, 9, 31, 100, 1000] {
+            let data = rng.bytes(len);
+            let mut br = new_br();
+            #[cfg(feature = "c-reference")]
+            let mut ref_br = new_br();
+            unsafe {
+                VP8InitBitReader(&mut br, data.as_ptr(), data.len());
+                #[cfg(feature = "c-reference")]
+                {
+                    WebPRef_VP8InitBitReader(&mut ref_br, data.as_ptr(), data.len());
+                    assert_same_state(&br, &ref_br);
+                }
+                // Read past the end of the stream too.
+                for _ in 0..(len * 2 + 32) {
+                    let value = match rng.next() % 5 {
+                        0 => {
+                            let bits = (rng.next() % 25) as c_int;
+                            let value = VP8GetValue(&mut br, bits);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8GetValue(&mut ref_br, bits));
+                            value as i32
+                        }
+                        1 => {
+                            let bits = (rng.next() % 8) as c_int;
+                            let value = VP8GetSignedValue(&mut br, bits);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8GetSignedValue(&mut ref_br, bits));
+                            value
+                        }
+                        2 => {
+                            let prob = (rng.next() % 256) as c_int;
+                            let value = VP8GetBit(&mut br, prob);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8GetBit(&mut ref_br, prob));
+                            value
+                        }
+                        3 => {
+                            let prob = (rng.next() % 256) as c_int;
+                            let value = VP8GetBitAlt(&mut br, prob);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8GetBitAlt(&mut ref_br, prob));
+                            value
+                        }
+                        _ => {
+                            let v = (rng.next() % 2048) as c_int;
+                            let value = VP8GetSigned(&mut br, v);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8GetSigned(&mut ref_br, v));
+                            value
+                        }
+                    };
+                    digest.update(&value.to_le_bytes());
+                    #[cfg(feature = "c-reference")]
+                    assert_same_state(&br, &ref_br);
+                }
+            }
+        }
+        check_parity(
+            "utils::bit_reader_utils::test_vp8_bit_reader_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_vp8l_bit_reader_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(2);
+        for &len in &[0, 1, 4, 8, 9, 12, 13, 100, 1000] {
+            let data = rng.bytes(len);
+            let mut br = new_vp8l_br();
+            #[cfg(feature = "c-reference")]
+            let mut ref_br = new_vp8l_br();
+            unsafe {
+                VP8LInitBitReader(&mut br, data.as_ptr(), data.len());
+                #[cfg(feature = "c-reference")]
+                {
+                    WebPRef_VP8LInitBitReader(&mut ref_br, data.as_ptr(), data.len());
+                    assert_same_vp8l_state(&br, &ref_br);
+                }
+                for _ in 0..(len + 32) {
+                    match rng.next() % 4 {
+                        0 | 1 => {
+                            // Also exercises the out-of-range error path.
+                            let bits = (rng.next() % 26) as c_int;
+                            let value = VP8LReadBits(&mut br, bits);
+                            digest.update(&value.to_le_bytes());
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8LReadBits(&mut ref_br, bits));
+                        }
+                        2 => {
+                            let bits = (rng.next() % 16) as c_int;
+                            let new_pos = br.bit_pos_ + bits;
+                            VP8LSetBitPos(&mut br, new_pos);
+                            VP8LFillBitWindow(&mut br);
+                            #[cfg(feature = "c-reference")]
+                            {
+                                ref_br.bit_pos_ = new_pos;
+                                WebPRef_VP8LFillBitWindow(&mut ref_br);
+                            }
+                        }
+                        _ => {
+                            let value = VP8LPrefetchBits(&br);
+                            digest.update(&value.to_le_bytes());
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8LPrefetchBits(&mut ref_br));
+                        }
+                    }
+                    digest.update(&br.val_.to_le_bytes());
+                    digest.update(&[br.pos_ as u8, br.bit_pos_ as u8, br.eos_ as u8]);
+                    #[cfg(feature = "c-reference")]
+                    assert_same_vp8l_state(&br, &ref_br);
+                    if br.eos_ != 0 {
+                        break;
+                    }
+                }
+
+                // Switch to a longer buffer, as the incremental decoder does.
+                let more = rng.bytes(len + 16);
+                VP8LBitReaderSetBuffer(&mut br, more.as_ptr(), more.len());
+                digest.update(&br.val_.to_le_bytes());
+                #[cfg(feature = "c-reference")]
+                {
+                    WebPRef_VP8LBitReaderSetBuffer(&mut ref_br, more.as_ptr(), more.len());
+                    assert_same_vp8l_state(&br, &ref_br);
+                }
+            }
+        }
+        check_parity(
+            "utils::bit_reader_utils::test_vp8l_bit_reader_parity",
+            digest,
+        );
+    }
 }
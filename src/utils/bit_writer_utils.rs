@@ -0,0 +1,807 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Bit writing and boolean coder
+//
+// Author: Skal (pascal.massimino@gmail.com)
+//         Vikas Arora (vikaas.arora@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use utils::utils::{WebPSafeFree, WebPSafeMalloc};
+
+//------------------------------------------------------------------------------
+// Bit-writing
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VP8BitWriter {
+    pub(crate) range_: i32, // range-1
+    pub(crate) value_: i32,
+    pub(crate) run_: c_int,     // number of outstanding bits
+    pub(crate) nb_bits_: c_int, // number of pending bits
+    pub(crate) buf_: *mut u8,   // internal buffer. Re-allocated regularly. Not owned.
+    pub(crate) pos_: usize,
+    pub(crate) max_pos_: usize,
+    pub(crate) error_: c_int, // true in case of error
+}
+
+// return approximate write position (in bits)
+#[cfg(feature = "rust-vp8-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8BitWriterPos(bw: &VP8BitWriter) -> u64 {
+    let nb_bits = (8 + bw.nb_bits_) as u64; // bw->nb_bits_ is <= 0, note
+    (bw.pos_ as u64 + bw.run_ as u64) * 8 + nb_bits
+}
+
+// Returns a pointer to the internal buffer.
+#[cfg(feature = "rust-vp8-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8BitWriterBuf(bw: &VP8BitWriter) -> *mut u8 {
+    bw.buf_
+}
+
+// Returns the size of the internal buffer.
+#[cfg(feature = "rust-vp8-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8BitWriterSize(bw: &VP8BitWriter) -> usize {
+    bw.pos_
+}
+
+#[allow(non_snake_case)]
+unsafe fn BitWriterResize(bw: &mut VP8BitWriter, extra_size: usize) -> bool {
+    let needed_size_64b = bw.pos_ as u64 + extra_size as u64;
+    let needed_size = needed_size_64b as usize;
+    if needed_size_64b != needed_size as u64 {
+        bw.error_ = 1;
+        return false;
+    }
+    if needed_size <= bw.max_pos_ {
+        return true;
+    }
+    // If the following line wraps over 32bit, the test just after will catch it.
+    let mut new_size = bw.max_pos_.wrapping_mul(2);
+    if new_size < needed_size {
+        new_size = needed_size;
+    }
+    if new_size < 1024 {
+        new_size = 1024;
+    }
+    let new_buf = WebPSafeMalloc(1, new_size) as *mut u8;
+    if new_buf.is_null() {
+        bw.error_ = 1;
+        return false;
+    }
+    if bw.pos_ > 0 {
+        c_assert!(!bw.buf_.is_null());
+        ptr::copy_nonoverlapping(bw.buf_, new_buf, bw.pos_);
+    }
+    WebPSafeFree(bw.buf_ as *mut c_void);
+    bw.buf_ = new_buf;
+    bw.max_pos_ = new_size;
+    true
+}
+
+#[allow(non_snake_case)]
+unsafe fn Flush(bw: &mut VP8BitWriter) {
+    let s = 8 + bw.nb_bits_;
+    let bits = bw.value_ >> s;
+    c_assert!(bw.nb_bits_ >= 0);
+    bw.value_ -= bits << s;
+    bw.nb_bits_ -= 8;
+    if (bits & 0xff) != 0xff {
+        let mut pos = bw.pos_;
+        if !BitWriterResize(bw, (bw.run_ + 1) as usize) {
+            return;
+        }
+        if (bits & 0x100) != 0 {
+            // overflow -> propagate carry over pending 0xff's
+            if pos > 0 {
+                *bw.buf_.add(pos - 1) = (*bw.buf_.add(pos - 1)).wrapping_add(1);
+            }
+        }
+        if bw.run_ > 0 {
+            let value = if (bits & 0x100) != 0 { 0x00 } else { 0xff };
+            while bw.run_ > 0 {
+                *bw.buf_.add(pos) = value;
+                pos += 1;
+                bw.run_ -= 1;
+            }
+        }
+        *bw.buf_.add(pos) = bits as u8;
+        pos += 1;
+        bw.pos_ = pos;
+    } else {
+        bw.run_ += 1; // delay writing of bytes 0xff, pending eventual carry.
+    }
+}
+
+//------------------------------------------------------------------------------
+// renormalization
+
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kNorm: [u8; 128] = [ // renorm_sizes[i] = 8 - log2(i)
+     7, 6, 6, 5, 5, 5, 5, 4, 4, 4, 4, 4, 4, 4, 4,
+  3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3, 3,
+  2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+  2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+  0
+];
+
+// range = ((range + 1) << kVP8Log2Range[range]) - 1
+#[rustfmt::skip]
+#[allow(non_upper_case_globals)]
+static kNewRange: [u8; 128] = [
+  127, 127, 191, 127, 159, 191, 223, 127, 143, 159, 175, 191, 207, 223, 239,
+  127, 135, 143, 151, 159, 167, 175, 183, 191, 199, 207, 215, 223, 231, 239,
+  247, 127, 131, 135, 139, 143, 147, 151, 155, 159, 163, 167, 171, 175, 179,
+  183, 187, 191, 195, 199, 203, 207, 211, 215, 219, 223, 227, 231, 235, 239,
+  243, 247, 251, 127, 129, 131, 133, 135, 137, 139, 141, 143, 145, 147, 149,
+  151, 153, 155, 157, 159, 161, 163, 165, 167, 169, 171, 173, 175, 177, 179,
+  181, 183, 185, 187, 189, 191, 193, 195, 197, 199, 201, 203, 205, 207, 209,
+  211, 213, 215, 217, 219, 221, 223, 225, 227, 229, 231, 233, 235, 237, 239,
+  241, 243, 245, 247, 249, 251, 253, 127
+];
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8PutBit(bw: *mut VP8BitWriter, bit: c_int, prob: c_int) -> c_int {
+    let bw = &mut *bw;
+    let split = (bw.range_ * prob) >> 8;
+    if bit != 0 {
+        bw.value_ += split + 1;
+        bw.range_ -= split + 1;
+    } else {
+        bw.range_ = split;
+    }
+    if bw.range_ < 127 {
+        // emit 'shift' bits out and renormalize
+        let shift = kNorm[bw.range_ as usize] as c_int;
+        bw.range_ = kNewRange[bw.range_ as usize] as i32;
+        bw.value_ <<= shift;
+        bw.nb_bits_ += shift;
+        if bw.nb_bits_ > 0 {
+            Flush(bw);
+        }
+    }
+    bit
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8PutBitUniform(bw: *mut VP8BitWriter, bit: c_int) -> c_int {
+    let bw = &mut *bw;
+    let split = bw.range_ >> 1;
+    if bit != 0 {
+        bw.value_ += split + 1;
+        bw.range_ -= split + 1;
+    } else {
+        bw.range_ = split;
+    }
+    if bw.range_ < 127 {
+        bw.range_ = kNewRange[bw.range_ as usize] as i32;
+        bw.value_ <<= 1;
+        bw.nb_bits_ += 1;
+        if bw.nb_bits_ > 0 {
+            Flush(bw);
+        }
+    }
+    bit
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8PutBits(bw: *mut VP8BitWriter, value: u32, nb_bits: c_int) {
+    c_assert!(nb_bits > 0 && nb_bits < 32);
+    let mut mask = 1u32 << (nb_bits - 1);
+    while mask != 0 {
+        VP8PutBitUniform(bw, (value & mask) as c_int);
+        mask >>= 1;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8PutSignedBits(bw: *mut VP8BitWriter, value: c_int, nb_bits: c_int) {
+    if VP8PutBitUniform(bw, (value != 0) as c_int) == 0 {
+        return;
+    }
+    if value < 0 {
+        VP8PutBits(bw, ((-value << 1) | 1) as u32, nb_bits + 1);
+    } else {
+        VP8PutBits(bw, (value << 1) as u32, nb_bits + 1);
+    }
+}
+
+//------------------------------------------------------------------------------
+
+// Initialize the object. Allocates some initial memory based on expected_size.
+#[no_mangle]
+pub unsafe extern "C" fn VP8BitWriterInit(bw: *mut VP8BitWriter, expected_size: usize) -> c_int {
+    let bw = &mut *bw;
+    bw.range_ = 255 - 1;
+    bw.value_ = 0;
+    bw.run_ = 0;
+    bw.nb_bits_ = -8;
+    bw.pos_ = 0;
+    bw.max_pos_ = 0;
+    bw.error_ = 0;
+    bw.buf_ = ptr::null_mut();
+    if expected_size > 0 {
+        BitWriterResize(bw, expected_size) as c_int
+    } else {
+        1
+    }
+}
+
+// Finalize the bitstream coding. Returns a pointer to the internal buffer.
+#[no_mangle]
+pub unsafe extern "C" fn VP8BitWriterFinish(bw: *mut VP8BitWriter) -> *mut u8 {
+    VP8PutBits(bw, 0, 9 - (*bw).nb_bits_);
+    (*bw).nb_bits_ = 0; // pad with zeroes
+    Flush(&mut *bw);
+    (*bw).buf_
+}
+
+// Appends some bytes to the internal buffer. Data is copied.
+#[no_mangle]
+pub unsafe extern "C" fn VP8BitWriterAppend(
+    bw: *mut VP8BitWriter,
+    data: *const u8,
+    size: usize,
+) -> c_int {
+    let bw = &mut *bw;
+    c_assert!(!data.is_null());
+    if bw.nb_bits_ != -8 {
+        return 0; // Flush() must have been called
+    }
+    if !BitWriterResize(bw, size) {
+        return 0;
+    }
+    ptr::copy_nonoverlapping(data, bw.buf_.add(bw.pos_), size);
+    bw.pos_ += size;
+    1
+}
+
+// Release any pending memory and zeroes the object. Not a mandatory call.
+// Only useful in case of error, when the internal buffer hasn't been grabbed!
+#[no_mangle]
+pub unsafe extern "C" fn VP8BitWriterWipeOut(bw: *mut VP8BitWriter) {
+    if !bw.is_null() {
+        WebPSafeFree((*bw).buf_ as *mut c_void);
+        *bw = mem::zeroed();
+    }
+}
+
+//------------------------------------------------------------------------------
+// VP8LBitWriter
+
+cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        // 64bit
+        #[allow(non_camel_case_types)]
+        pub(crate) type vp8l_atype_t = u64; // accumulator type
+        #[allow(non_camel_case_types)]
+        pub(crate) type vp8l_wtype_t = u32; // writing type
+        const VP8L_WRITER_BYTES: usize = 4; // sizeof(vp8l_wtype_t)
+        const VP8L_WRITER_BITS: c_int = 32; // 8 * sizeof(vp8l_wtype_t)
+    } else {
+        #[allow(non_camel_case_types)]
+        pub(crate) type vp8l_atype_t = u32;
+        #[allow(non_camel_case_types)]
+        pub(crate) type vp8l_wtype_t = u16;
+        const VP8L_WRITER_BYTES: usize = 2;
+        const VP8L_WRITER_BITS: c_int = 16;
+        const VP8L_WRITER_MAX_BITS: c_int = 32;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct VP8LBitWriter {
+    pub(crate) bits_: vp8l_atype_t, // bit accumulator
+    pub(crate) used_: c_int,        // number of bits used in accumulator
+    pub(crate) buf_: *mut u8,       // start of buffer
+    pub(crate) cur_: *mut u8,       // current write position
+    pub(crate) end_: *mut u8,       // end of buffer
+
+    // After all bits are written (VP8LBitWriterFinish()), the caller must observe
+    // the state of error_. A value of 1 indicates that a memory allocation
+    // failure has happened during bit writing. A value of 0 indicates successful
+    // writing of bits.
+    pub(crate) error_: c_int,
+}
+
+#[cfg(feature = "rust-vp8l-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LBitWriterNumBytes(bw: &VP8LBitWriter) -> usize {
+    (bw.cur_ as usize - bw.buf_ as usize) + ((bw.used_ + 7) >> 3) as usize
+}
+
+// This is the minimum amount of size the memory buffer is guaranteed to grow
+// when extra space is needed.
+const MIN_EXTRA_SIZE: u64 = 32768;
+
+// Returns 1 on success.
+#[allow(non_snake_case)]
+unsafe fn VP8LBitWriterResize(bw: &mut VP8LBitWriter, extra_size: usize) -> bool {
+    let max_bytes = bw.end_ as usize - bw.buf_ as usize;
+    let current_size = bw.cur_ as usize - bw.buf_ as usize;
+    let size_required_64b = current_size as u64 + extra_size as u64;
+    let size_required = size_required_64b as usize;
+    if size_required as u64 != size_required_64b {
+        bw.error_ = 1;
+        return false;
+    }
+    if max_bytes > 0 && size_required <= max_bytes {
+        return true;
+    }
+    let mut allocated_size = (3 * max_bytes) >> 1;
+    if allocated_size < size_required {
+        allocated_size = size_required;
+    }
+    // make allocated size multiple of 1k
+    allocated_size = ((allocated_size >> 10) + 1) << 10;
+    let allocated_buf = WebPSafeMalloc(1, allocated_size) as *mut u8;
+    if allocated_buf.is_null() {
+        bw.error_ = 1;
+        return false;
+    }
+    if current_size > 0 {
+        ptr::copy_nonoverlapping(bw.buf_, allocated_buf, current_size);
+    }
+    WebPSafeFree(bw.buf_ as *mut c_void);
+    bw.buf_ = allocated_buf;
+    bw.cur_ = bw.buf_.add(current_size);
+    bw.end_ = bw.buf_.add(allocated_size);
+    true
+}
+
+// Returns false in case of memory allocation error.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitWriterInit(bw: *mut VP8LBitWriter, expected_size: usize) -> c_int {
+    *bw = mem::zeroed();
+    VP8LBitWriterResize(&mut *bw, expected_size) as c_int
+}
+
+// Returns false in case of memory allocation error.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitWriterClone(
+    src: *const VP8LBitWriter,
+    dst: *mut VP8LBitWriter,
+) -> c_int {
+    let src = &*src;
+    let dst = &mut *dst;
+    let current_size = src.cur_ as usize - src.buf_ as usize;
+    c_assert!(src.cur_ >= src.buf_ && src.cur_ <= src.end_);
+    if !VP8LBitWriterResize(dst, current_size) {
+        return 0;
+    }
+    ptr::copy_nonoverlapping(src.buf_, dst.buf_, current_size);
+    dst.bits_ = src.bits_;
+    dst.used_ = src.used_;
+    dst.error_ = src.error_;
+    1
+}
+
+// Release any pending memory and zeroes the object.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitWriterWipeOut(bw: *mut VP8LBitWriter) {
+    if !bw.is_null() {
+        WebPSafeFree((*bw).buf_ as *mut c_void);
+        *bw = mem::zeroed();
+    }
+}
+
+// Resets the cursor of the BitWriter bw to when it was like in bw_init.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitWriterReset(bw_init: *const VP8LBitWriter, bw: *mut VP8LBitWriter) {
+    let bw_init = &*bw_init;
+    let bw = &mut *bw;
+    bw.bits_ = bw_init.bits_;
+    bw.used_ = bw_init.used_;
+    bw.cur_ = bw.buf_.add(bw_init.cur_ as usize - bw_init.buf_ as usize);
+    c_assert!(bw.cur_ <= bw.end_);
+    bw.error_ = bw_init.error_;
+}
+
+// Swaps the memory held by two BitWriters.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitWriterSwap(src: *mut VP8LBitWriter, dst: *mut VP8LBitWriter) {
+    ptr::swap(src, dst);
+}
+
+// Makes room for VP8L_WRITER_BYTES more bytes. Returns false (and sets the
+// error flag) in case of memory allocation error.
+#[allow(non_snake_case)]
+unsafe fn VP8LBitWriterReserve(bw: &mut VP8LBitWriter) -> bool {
+    if bw.cur_.add(VP8L_WRITER_BYTES) > bw.end_ {
+        let extra_size = (bw.end_ as usize - bw.buf_ as usize) as u64 + MIN_EXTRA_SIZE;
+        if extra_size != extra_size as usize as u64 || !VP8LBitWriterResize(bw, extra_size as usize)
+        {
+            bw.cur_ = bw.buf_;
+            bw.error_ = 1;
+            return false;
+        }
+    }
+    true
+}
+
+// Internal function for VP8LPutBits flushing 32 bits from the written state.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LPutBitsFlushBits(bw: *mut VP8LBitWriter) {
+    let bw = &mut *bw;
+    // If needed, make some room by flushing some bits out.
+    if !VP8LBitWriterReserve(bw) {
+        return;
+    }
+    ptr::write_unaligned(
+        bw.cur_ as *mut vp8l_wtype_t,
+        (bw.bits_ as vp8l_wtype_t).to_le(),
+    );
+    bw.cur_ = bw.cur_.add(VP8L_WRITER_BYTES);
+    bw.bits_ >>= VP8L_WRITER_BITS;
+    bw.used_ -= VP8L_WRITER_BITS;
+}
+
+// PutBits internal function used in the 16 bit vp8l_wtype_t case.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LPutBitsInternal(bw: *mut VP8LBitWriter, bits: u32, n_bits: c_int) {
+    let bw = &mut *bw;
+    c_assert!(n_bits <= 32);
+    // That's the max we can handle:
+    c_assert!(mem::size_of::<vp8l_wtype_t>() == 2);
+    if n_bits > 0 {
+        let mut lbits = bw.bits_;
+        let mut used = bw.used_;
+        // Special case of overflow handling for 32bit accumulator (2-steps flush).
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            if used + n_bits >= VP8L_WRITER_MAX_BITS {
+                // Fill up all the VP8L_WRITER_MAX_BITS so it can be flushed out below.
+                let shift = VP8L_WRITER_MAX_BITS - used;
+                lbits |= (bits as vp8l_atype_t) << used;
+                used = VP8L_WRITER_MAX_BITS;
+                n_bits -= shift;
+                bits >>= shift;
+                c_assert!(n_bits <= VP8L_WRITER_MAX_BITS);
+            }
+        }
+        // If needed, make some room by flushing some bits out.
+        while used >= VP8L_WRITER_BITS {
+            if !VP8LBitWriterReserve(bw) {
+                return;
+            }
+            ptr::write_unaligned(
+                bw.cur_ as *mut vp8l_wtype_t,
+                (lbits as vp8l_wtype_t).to_le(),
+            );
+            bw.cur_ = bw.cur_.add(VP8L_WRITER_BYTES);
+            lbits >>= VP8L_WRITER_BITS;
+            used -= VP8L_WRITER_BITS;
+        }
+        bw.bits_ = lbits | ((bits as vp8l_atype_t) << used);
+        bw.used_ = used + n_bits;
+    }
+}
+
+// This function writes bits into bytes in increasing addresses (little endian),
+// and within a byte least-significant-bit first.
+// This function can write up to 32 bits in one go, but VP8LBitReader can only
+// read 24 bits max (VP8L_MAX_NUM_BIT_READ).
+// VP8LBitWriter's error_ flag is set in case of  memory allocation error.
+#[cfg(feature = "rust-vp8l-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LPutBits(bw: *mut VP8LBitWriter, bits: u32, n_bits: c_int) {
+    if mem::size_of::<vp8l_wtype_t>() == 4 {
+        if n_bits > 0 {
+            if (*bw).used_ >= 32 {
+                VP8LPutBitsFlushBits(bw);
+            }
+            (*bw).bits_ |= (bits as vp8l_atype_t) << (*bw).used_;
+            (*bw).used_ += n_bits;
+        }
+    } else {
+        VP8LPutBitsInternal(bw, bits, n_bits);
+    }
+}
+
+// Finalize the bitstream coding. Returns a pointer to the internal buffer.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBitWriterFinish(bw: *mut VP8LBitWriter) -> *mut u8 {
+    let bw = &mut *bw;
+    // flush leftover bits
+    if VP8LBitWriterResize(bw, ((bw.used_ + 7) >> 3) as usize) {
+        while bw.used_ > 0 {
+            *bw.cur_ = bw.bits_ as u8;
+            bw.cur_ = bw.cur_.add(1);
+            bw.bits_ >>= 8;
+            bw.used_ -= 8;
+        }
+        bw.used_ = 0;
+    }
+    bw.buf_
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::slice;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8PutBit(bw: *mut VP8BitWriter, bit: c_int, prob: c_int) -> c_int;
+        fn WebPRef_VP8PutBitUniform(bw: *mut VP8BitWriter, bit: c_int) -> c_int;
+        fn WebPRef_VP8PutBits(bw: *mut VP8BitWriter, value: u32, nb_bits: c_int);
+        fn WebPRef_VP8PutSignedBits(bw: *mut VP8BitWriter, value: c_int, nb_bits: c_int);
+        fn WebPRef_VP8BitWriterInit(bw: *mut VP8BitWriter, expected_size: usize) -> c_int;
+        fn WebPRef_VP8BitWriterFinish(bw: *mut VP8BitWriter) -> *mut u8;
+        fn WebPRef_VP8BitWriterAppend(bw: *mut VP8BitWriter, data: *const u8, size: usize)
+            -> c_int;
+        fn WebPRef_VP8BitWriterWipeOut(bw: *mut VP8BitWriter);
+        fn WebPRef_VP8LBitWriterInit(bw: *mut VP8LBitWriter, expected_size: usize) -> c_int;
+        fn WebPRef_VP8LBitWriterClone(src: *const VP8LBitWriter, dst: *mut VP8LBitWriter) -> c_int;
+        fn WebPRef_VP8LBitWriterWipeOut(bw: *mut VP8LBitWriter);
+        fn WebPRef_VP8LBitWriterReset(bw_init: *const VP8LBitWriter, bw: *mut VP8LBitWriter);
+        fn WebPRef_VP8LBitWriterSwap(src: *mut VP8LBitWriter, dst: *mut VP8LBitWriter);
+        fn WebPRef_VP8LPutBitsFlushBits(bw: *mut VP8LBitWriter);
+        fn WebPRef_VP8LPutBitsInternal(bw: *mut VP8LBitWriter, bits: u32, n_bits: c_int);
+        fn WebPRef_VP8LBitWriterFinish(bw: *mut VP8LBitWriter) -> *mut u8;
+    }
+
+    // VP8LPutBits() is inline in bit_writer_utils.h, over the reference
+    // functions here.
+    #[cfg(all(feature = "rust-vp8l-encoder", feature = "c-reference"))]
+    unsafe fn ref_vp8l_put_bits(bw: *mut VP8LBitWriter, bits: u32, n_bits: c_int) {
+        if mem::size_of::<vp8l_wtype_t>() == 4 {
+            if n_bits > 0 {
+                if (*bw).used_ >= 32 {
+                    WebPRef_VP8LPutBitsFlushBits(bw);
+                }
+                (*bw).bits_ |= (bits as vp8l_atype_t) << (*bw).used_;
+                (*bw).used_ += n_bits;
+            }
+        } else {
+            WebPRef_VP8LPutBitsInternal(bw, bits, n_bits);
+        }
+    }
+
+    unsafe fn vp8_output(bw: &mut VP8BitWriter, extra: &[u8]) -> Vec<u8> {
+        VP8BitWriterFinish(bw);
+        assert_ne!(VP8BitWriterAppend(bw, extra.as_ptr(), extra.len()), 0);
+        assert_eq!(bw.error_, 0);
+        let buf = bw.buf_;
+        let output = slice::from_raw_parts(buf, bw.pos_).to_vec();
+        VP8BitWriterWipeOut(bw);
+        output
+    }
+
+    #[cfg(feature = "c-reference")]
+    unsafe fn ref_vp8_output(bw: &mut VP8BitWriter, extra: &[u8]) -> Vec<u8> {
+        WebPRef_VP8BitWriterFinish(bw);
+        assert_ne!(
+            WebPRef_VP8BitWriterAppend(bw, extra.as_ptr(), extra.len()),
+            0
+        );
+        assert_eq!(bw.error_, 0);
+        let buf = bw.buf_;
+        let output = slice::from_raw_parts(buf, bw.pos_).to_vec();
+        WebPRef_VP8BitWriterWipeOut(bw);
+        output
+    }
+
+    #[cfg(feature = "rust-vp8l-encoder")]
+    unsafe fn vp8l_output(bw: &mut VP8LBitWriter) -> Vec<u8> {
+        let buf = VP8LBitWriterFinish(bw);
+        assert_eq!(bw.error_, 0);
+        let output = slice::from_raw_parts(buf, bw.cur_ as usize - buf as usize).to_vec();
+        VP8LBitWriterWipeOut(bw);
+        output
+    }
+
+    #[cfg(all(feature = "rust-vp8l-encoder", feature = "c-reference"))]
+    unsafe fn ref_vp8l_output(bw: &mut VP8LBitWriter) -> Vec<u8> {
+        let buf = WebPRef_VP8LBitWriterFinish(bw);
+        assert_eq!(bw.error_, 0);
+        let output = slice::from_raw_parts(buf, bw.cur_ as usize - buf as usize).to_vec();
+        WebPRef_VP8LBitWriterWipeOut(bw);
+        output
+    }
+
+    // Random sequences of all the kinds of writes, from an empty buffer so
+    // that it has to grow, with carries through runs of 0xff bytes.
+    #[test]
+    fn test_vp8_bit_writer_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(3);
+        for &num_writes in &[0, 1, 7, 100, 5000, 40000] {
+            unsafe {
+                let mut bw: VP8BitWriter = mem::zeroed();
+                assert_ne!(VP8BitWriterInit(&mut bw, 0), 0);
+                #[cfg(feature = "c-reference")]
+                let mut ref_bw: VP8BitWriter = mem::zeroed();
+                #[cfg(feature = "c-reference")]
+                assert_ne!(WebPRef_VP8BitWriterInit(&mut ref_bw, 0), 0);
+                for _ in 0..num_writes {
+                    match rng.next() % 6 {
+                        0 | 1 => {
+                            let prob = (rng.next() % 256) as c_int;
+                            // Mostly likely bits, for long runs.
+                            let bit = (rng.next() % 256 >= prob as u32) as c_int;
+                            let value = VP8PutBit(&mut bw, bit, prob);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8PutBit(&mut ref_bw, bit, prob));
+                            assert_eq!(value, bit);
+                        }
+                        2 => {
+                            let bit = (rng.next() & 1) as c_int;
+                            let value = VP8PutBitUniform(&mut bw, bit);
+                            #[cfg(feature = "c-reference")]
+                            assert_eq!(value, WebPRef_VP8PutBitUniform(&mut ref_bw, bit));
+                            assert_eq!(value, bit);
+                        }
+                        3 | 4 => {
+                            let nb_bits = 1 + (rng.next() % 24) as c_int;
+                            let value = rng.next() & ((1 << nb_bits) - 1);
+                            VP8PutBits(&mut bw, value, nb_bits);
+                            #[cfg(feature = "c-reference")]
+                            WebPRef_VP8PutBits(&mut ref_bw, value, nb_bits);
+                        }
+                        _ => {
+                            let nb_bits = 1 + (rng.next() % 15) as c_int;
+                            let value = (rng.next() % (1 << nb_bits)) as c_int;
+                            let value = if rng.next() & 1 != 0 { -value } else { value };
+                            VP8PutSignedBits(&mut bw, value, nb_bits);
+                            #[cfg(feature = "c-reference")]
+                            WebPRef_VP8PutSignedBits(&mut ref_bw, value, nb_bits);
+                        }
+                    }
+                    #[cfg(feature = "c-reference")]
+                    {
+                        assert_eq!(bw.range_, ref_bw.range_);
+                        assert_eq!(bw.value_, ref_bw.value_);
+                        assert_eq!(bw.run_, ref_bw.run_);
+                        assert_eq!(bw.nb_bits_, ref_bw.nb_bits_);
+                        assert_eq!(bw.pos_, ref_bw.pos_);
+                    }
+                }
+                // The partitions are appended to the flushed first one.
+                let extra = rng.bytes(num_writes % 37);
+                let output = vp8_output(&mut bw, &extra);
+                digest.update(&output);
+                #[cfg(feature = "c-reference")]
+                assert!(
+                    output == ref_vp8_output(&mut ref_bw, &extra),
+                    "{} writes",
+                    num_writes
+                );
+            }
+        }
+        check_parity(
+            "utils::bit_writer_utils::test_vp8_bit_writer_parity",
+            digest,
+        );
+    }
+
+    // Bits of all widths, with the cloning, resetting and swapping that
+    // vp8l_enc.c does to try several encodings of the same image.
+    #[cfg(feature = "rust-vp8l-encoder")]
+    #[test]
+    fn test_vp8l_bit_writer_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(4);
+        for &num_writes in &[0, 1, 3, 64, 1000, 30000] {
+            unsafe {
+                let mut bws: [VP8LBitWriter; 2] = mem::zeroed();
+                assert_ne!(VP8LBitWriterInit(&mut bws[0], 0), 0);
+                assert_ne!(VP8LBitWriterInit(&mut bws[1], 16), 0);
+                #[cfg(feature = "c-reference")]
+                let mut ref_bws: [VP8LBitWriter; 2] = mem::zeroed();
+                #[cfg(feature = "c-reference")]
+                {
+                    assert_ne!(WebPRef_VP8LBitWriterInit(&mut ref_bws[0], 0), 0);
+                    assert_ne!(WebPRef_VP8LBitWriterInit(&mut ref_bws[1], 16), 0);
+                }
+                // Only rewound to, as the writer may not have written the
+                // bytes up to a later position.
+                let mut snapshot: Option<(usize, VP8LBitWriter)> = None;
+                #[cfg(feature = "c-reference")]
+                let mut ref_snapshot: Option<(usize, VP8LBitWriter)> = None;
+                for _ in 0..num_writes {
+                    let i = (rng.next() & 1) as usize;
+                    match rng.next() % 40 {
+                        0 => {
+                            if let Some((j, bw_init)) = snapshot {
+                                VP8LBitWriterReset(&bw_init, &mut bws[j]);
+                            }
+                            #[cfg(feature = "c-reference")]
+                            {
+                                if let Some((j, bw_init)) = ref_snapshot {
+                                    WebPRef_VP8LBitWriterReset(&bw_init, &mut ref_bws[j]);
+                                }
+                            }
+                        }
+                        1 => {
+                            snapshot = Some((i, bws[i]));
+                            #[cfg(feature = "c-reference")]
+                            {
+                                ref_snapshot = Some((i, ref_bws[i]));
+                            }
+                        }
+                        2 => {
+                            let (src, dst) = bws.split_at_mut(1);
+                            assert_ne!(VP8LBitWriterClone(&src[0], &mut dst[0]), 0);
+                            snapshot = None;
+                            #[cfg(feature = "c-reference")]
+                            {
+                                let (src, dst) = ref_bws.split_at_mut(1);
+                                assert_ne!(WebPRef_VP8LBitWriterClone(&src[0], &mut dst[0]), 0);
+                                ref_snapshot = None;
+                            }
+                        }
+                        3 => {
+                            let (a, b) = bws.split_at_mut(1);
+                            VP8LBitWriterSwap(&mut a[0], &mut b[0]);
+                            snapshot = None;
+                            #[cfg(feature = "c-reference")]
+                            {
+                                let (a, b) = ref_bws.split_at_mut(1);
+                                WebPRef_VP8LBitWriterSwap(&mut a[0], &mut b[0]);
+                                ref_snapshot = None;
+                            }
+                        }
+                        _ => {
+                            let n_bits = (rng.next() % 33) as c_int;
+                            let bits = if n_bits == 32 {
+                                rng.next() << 8 | rng.next() & 0xff
+                            } else {
+                                rng.next() & ((1 << n_bits) - 1)
+                            };
+                            VP8LPutBits(&mut bws[i], bits, n_bits);
+                            #[cfg(feature = "c-reference")]
+                            ref_vp8l_put_bits(&mut ref_bws[i], bits, n_bits);
+                        }
+                    }
+                    #[cfg(feature = "c-reference")]
+                    for (bw, ref_bw) in bws.iter().zip(&ref_bws) {
+                        assert_eq!(bw.bits_, ref_bw.bits_);
+                        assert_eq!(bw.used_, ref_bw.used_);
+                        assert_eq!(VP8LBitWriterNumBytes(bw), VP8LBitWriterNumBytes(ref_bw));
+                    }
+                }
+                let outputs: Vec<Vec<u8>> = bws.iter_mut().map(|bw| vp8l_output(bw)).collect();
+                for output in &outputs {
+                    digest.update(output);
+                }
+                #[cfg(feature = "c-reference")]
+                {
+                    let ref_outputs: Vec<Vec<u8>> =
+                        ref_bws.iter_mut().map(|bw| ref_vp8l_output(bw)).collect();
+                    assert!(outputs == ref_outputs, "{} writes", num_writes);
+                }
+            }
+        }
+        check_parity(
+            "utils::bit_writer_utils::test_vp8l_bit_writer_parity",
+            digest,
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Color Cache for WebP Lossless
+//
+// Authors: Jyrki Alakuijala (jyrki@google.com)
+//          Urvang Joshi (urvang@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use utils::utils::{WebPSafeCalloc, WebPSafeFree};
+
+// Main color cache struct.
+#[repr(C)]
+pub struct VP8LColorCache {
+    pub(crate) colors_: *mut u32,  // color entries
+    pub(crate) hash_shift_: c_int, // Hash shift: 32 - hash_bits_.
+    pub(crate) hash_bits_: c_int,
+}
+
+#[allow(non_upper_case_globals)]
+const kHashMul: u64 = 0x1e35a7bd;
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LHashPix(argb: u32, shift: c_int) -> c_int {
+    (((argb as u64 * kHashMul) & 0xffffffff) >> shift) as c_int
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LColorCacheLookup(cc: &VP8LColorCache, key: u32) -> u32 {
+    c_assert!((key >> cc.hash_bits_) == 0);
+    *cc.colors_.add(key as usize)
+}
+
+#[cfg(feature = "rust-vp8l-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LColorCacheSet(cc: &VP8LColorCache, key: u32, argb: u32) {
+    c_assert!((key >> cc.hash_bits_) == 0);
+    *cc.colors_.add(key as usize) = argb;
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LColorCacheInsert(cc: &VP8LColorCache, argb: u32) {
+    let key = VP8LHashPix(argb, cc.hash_shift_);
+    *cc.colors_.add(key as usize) = argb;
+}
+
+#[cfg(feature = "rust-vp8l-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn VP8LColorCacheGetIndex(cc: &VP8LColorCache, argb: u32) -> c_int {
+    VP8LHashPix(argb, cc.hash_shift_)
+}
+
+// Return the key if cc contains argb, and -1 otherwise.
+#[cfg(feature = "rust-vp8l-encoder")]
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn VP8LColorCacheContains(cc: &VP8LColorCache, argb: u32) -> c_int {
+    let key = VP8LHashPix(argb, cc.hash_shift_);
+    if *cc.colors_.add(key as usize) == argb {
+        key
+    } else {
+        -1
+    }
+}
+
+//------------------------------------------------------------------------------
+// VP8LColorCache.
+
+// Initializes the color cache with 'hash_bits' bits for the keys.
+// Returns false in case of memory error.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LColorCacheInit(cc: *mut VP8LColorCache, hash_bits: c_int) -> c_int {
+    let hash_size = 1 << hash_bits;
+    c_assert!(!cc.is_null());
+    c_assert!(hash_bits > 0);
+    let cc: &mut VP8LColorCache = &mut *cc;
+    cc.colors_ = WebPSafeCalloc(hash_size as u64, mem::size_of::<u32>()) as *mut u32;
+    if cc.colors_.is_null() {
+        return 0;
+    }
+    cc.hash_shift_ = 32 - hash_bits;
+    cc.hash_bits_ = hash_bits;
+    1
+}
+
+// Delete the memory associated to color cache.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LColorCacheClear(cc: *mut VP8LColorCache) {
+    if !cc.is_null() {
+        WebPSafeFree((*cc).colors_ as *mut c_void);
+        (*cc).colors_ = ptr::null_mut();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn VP8LColorCacheCopy(src: *const VP8LColorCache, dst: *mut VP8LColorCache) {
+    c_assert!(!src.is_null());
+    c_assert!(!dst.is_null());
+    c_assert!((*src).hash_bits_ == (*dst).hash_bits_);
+    ptr::copy_nonoverlapping((*src).colors_, (*dst).colors_, 1 << (*dst).hash_bits_);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LColorCacheInit(color_cache: *mut VP8LColorCache, hash_bits: c_int) -> c_int;
+        fn WebPRef_VP8LColorCacheClear(color_cache: *mut VP8LColorCache);
+    }
+
+    fn new_cc() -> VP8LColorCache {
+        VP8LColorCache {
+            colors_: ptr::null_mut(),
+            hash_shift_: 0,
+            hash_bits_: 0,
+        }
+    }
+
+    #[test]
+    fn test_color_cache() {
+        for hash_bits in 1..=11 {
+            let mut cc = new_cc();
+            let mut copy = new_cc();
+            unsafe {
+                assert_eq!(VP8LColorCacheInit(&mut cc, hash_bits), 1);
+                assert_eq!(VP8LColorCacheInit(&mut copy, hash_bits), 1);
+                assert_eq!((cc.hash_shift_, cc.hash_bits_), (32 - hash_bits, hash_bits));
+                #[cfg(feature = "c-reference")]
+                {
+                    let mut ref_cc = new_cc();
+                    assert_eq!(WebPRef_VP8LColorCacheInit(&mut ref_cc, hash_bits), 1);
+                    assert_eq!(
+                        (cc.hash_shift_, cc.hash_bits_),
+                        (ref_cc.hash_shift_, ref_cc.hash_bits_)
+                    );
+                    WebPRef_VP8LColorCacheClear(&mut ref_cc);
+                }
+                // Freshly initialized caches are all black.
+                for key in 0..1 << hash_bits {
+                    assert_eq!(VP8LColorCacheLookup(&cc, key), 0);
+                }
+                let colors = [0xff000000, 0x12345678, 0xffffffff, 0x00abcdef];
+                for &argb in &colors {
+                    VP8LColorCacheInsert(&cc, argb);
+                    let key = VP8LHashPix(argb, cc.hash_shift_);
+                    assert!(key >= 0 && key < 1 << hash_bits);
+                    assert_eq!(VP8LColorCacheLookup(&cc, key as u32), argb);
+                }
+                VP8LColorCacheCopy(&cc, &mut copy);
+                for key in 0..1 << hash_bits {
+                    assert_eq!(
+                        VP8LColorCacheLookup(&copy, key),
+                        VP8LColorCacheLookup(&cc, key)
+                    );
+                }
+                VP8LColorCacheClear(&mut cc);
+                assert!(cc.colors_.is_null());
+                VP8LColorCacheClear(&mut copy);
+                VP8LColorCacheClear(ptr::null_mut());
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// filter estimation
+//
+// Author: Urvang (urvang@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::os::raw::*;
+
+use dsp::dsp::WEBP_FILTER_TYPE::{self, *};
+
+const SMAX: usize = 16;
+
+// Scoring diff, in [0..SMAX)
+#[allow(non_snake_case)]
+#[inline]
+fn SDIFF(a: c_int, b: c_int) -> usize {
+    ((a - b).abs() >> 4) as usize
+}
+
+#[allow(non_snake_case)]
+#[inline]
+fn GradientPredictor(a: u8, b: u8, c: u8) -> c_int {
+    let g = a as c_int + b as c_int - c as c_int;
+    if (g & !0xff) == 0 {
+        g
+    } else if g < 0 {
+        0
+    } else {
+        255
+    } // clip to 8bit
+}
+
+// Fast estimate of a potentially good filter.
+#[no_mangle]
+pub unsafe extern "C" fn WebPEstimateBestFilter(
+    data: *const u8,
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+) -> WEBP_FILTER_TYPE {
+    let mut bins = [[0; SMAX]; WEBP_FILTER_LAST as usize];
+
+    // We only sample every other pixels. That's enough.
+    for j in (2..height - 1).step_by(2) {
+        let p = data.offset(j as isize * stride as isize);
+        let mut mean = *p as c_int;
+        for i in (2..width as isize - 1).step_by(2) {
+            let diff0 = SDIFF(*p.offset(i) as c_int, mean);
+            let diff1 = SDIFF(*p.offset(i) as c_int, *p.offset(i - 1) as c_int);
+            let diff2 = SDIFF(
+                *p.offset(i) as c_int,
+                *p.offset(i - width as isize) as c_int,
+            );
+            let grad_pred = GradientPredictor(
+                *p.offset(i - 1),
+                *p.offset(i - width as isize),
+                *p.offset(i - width as isize - 1),
+            );
+            let diff3 = SDIFF(*p.offset(i) as c_int, grad_pred);
+            bins[WEBP_FILTER_NONE as usize][diff0] = 1;
+            bins[WEBP_FILTER_HORIZONTAL as usize][diff1] = 1;
+            bins[WEBP_FILTER_VERTICAL as usize][diff2] = 1;
+            bins[WEBP_FILTER_GRADIENT as usize][diff3] = 1;
+            mean = (3 * mean + *p.offset(i) as c_int + 2) >> 2;
+        }
+    }
+    {
+        let mut best_filter = WEBP_FILTER_NONE;
+        let mut best_score = 0x7fffffff;
+        for filter in WEBP_FILTER_NONE as c_int..WEBP_FILTER_LAST as c_int {
+            let mut score = 0;
+            for (i, &bin) in bins[filter as usize].iter().enumerate() {
+                if bin > 0 {
+                    score += i;
+                }
+            }
+            if score < best_score {
+                best_score = score;
+                best_filter = WEBP_FILTER_TYPE::new(filter);
+            }
+        }
+        best_filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_WebPEstimateBestFilter(
+            data: *const u8,
+            width: c_int,
+            height: c_int,
+            stride: c_int,
+        ) -> WEBP_FILTER_TYPE;
+    }
+
+    #[test]
+    fn test_estimate_best_filter_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(1);
+        let mut seen = Vec::new();
+        for _ in 0..500 {
+            let width = 1 + rng.next() as c_int % 40;
+            let height = 1 + rng.next() as c_int % 40;
+            // Mix of noise and gradients of various slopes, so that every
+            // filter gets picked at some point.
+            let (dx, dy, noise) = (rng.next() % 8, rng.next() % 8, 1 + rng.next() % 64);
+            let data: Vec<u8> = (0..width * height)
+                .map(|i| {
+                    let (x, y) = ((i % width) as u32, (i / width) as u32);
+                    (x * dx + y * dy + rng.next() % noise) as u8
+                })
+                .collect();
+            let filter = unsafe { WebPEstimateBestFilter(data.as_ptr(), width, height, width) };
+            digest.update(&[filter as u8]);
+            #[cfg(feature = "c-reference")]
+            assert_eq!(filter, unsafe {
+                WebPRef_WebPEstimateBestFilter(data.as_ptr(), width, height, width)
+            });
+            if !seen.contains(&filter) {
+                seen.push(filter);
+            }
+        }
+        assert_eq!(seen.len(), WEBP_FILTER_LAST as usize);
+        check_parity(
+            "utils::filters_utils::test_estimate_best_filter_parity",
+            digest,
+        );
+    }
+}
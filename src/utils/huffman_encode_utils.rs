@@ -0,0 +1,66 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Author: Jyrki Alakuijala (jyrki@google.com)
+//
+// Entropy encoding (Huffman) for webp lossless
+//
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+// The implementation is still the C one (huffman_encode_utils.c).
+
+use std::os::raw::*;
+
+// Struct for holding the tree header in coded form.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HuffmanTreeToken {
+    pub(crate) code: u8,       // value (0..15) or escape code (16,17,18)
+    pub(crate) extra_bits: u8, // extra bits for escape codes
+}
+
+// Struct to represent the tree codes (depth and bits array).
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HuffmanTreeCode {
+    pub(crate) num_symbols: c_int,    // Number of symbols.
+    pub(crate) code_lengths: *mut u8, // Code lengths of the symbols.
+    pub(crate) codes: *mut u16,       // Symbol Codes.
+}
+
+// Struct to represent the Huffman tree.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct HuffmanTree {
+    pub(crate) total_count_: u32,        // Symbol frequency.
+    pub(crate) value_: c_int,            // Symbol value.
+    pub(crate) pool_index_left_: c_int,  // Index for the left sub-tree.
+    pub(crate) pool_index_right_: c_int, // Index for the right sub-tree.
+}
+
+extern "C" {
+    // Turn the Huffman tree into a token sequence.
+    // Returns the number of tokens used.
+    pub(crate) fn VP8LCreateCompressedHuffmanTree(
+        tree: *const HuffmanTreeCode,
+        tokens: *mut HuffmanTreeToken,
+        max_tokens: c_int,
+    ) -> c_int;
+
+    // Create an optimized tree, and tokenize it.
+    // 'buf_rle' and 'huff_tree' are pre-allocated and the 'tree' is the constructed
+    // huffman code tree.
+    pub(crate) fn VP8LCreateHuffmanTree(
+        histogram: *mut u32,
+        tree_depth_limit: c_int,
+        buf_rle: *mut u8,
+        huff_tree: *mut HuffmanTree,
+        tree: *mut HuffmanTreeCode,
+    );
+}
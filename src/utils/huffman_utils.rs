@@ -0,0 +1,462 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Utilities for building and looking up Huffman trees.
+//
+// Author: Urvang Joshi (urvang@google.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+
+use format_constants::{
+    HUFFMAN_CODES_PER_META_CODE, MAX_ALLOWED_CODE_LENGTH, MAX_CACHE_BITS, NUM_LENGTH_CODES,
+    NUM_LITERAL_CODES,
+};
+use utils::utils::{WebPSafeFree, WebPSafeMalloc};
+
+pub(crate) const HUFFMAN_TABLE_BITS: c_int = 8;
+pub(crate) const HUFFMAN_TABLE_MASK: u32 = (1 << HUFFMAN_TABLE_BITS) - 1;
+
+pub(crate) const LENGTHS_TABLE_BITS: c_int = 7;
+pub(crate) const LENGTHS_TABLE_MASK: u32 = (1 << LENGTHS_TABLE_BITS) - 1;
+
+// Huffman lookup table entry
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HuffmanCode {
+    pub(crate) bits: u8,   // number of bits used for this symbol
+    pub(crate) value: u16, // symbol value or table offset
+}
+
+// long version for holding 32b values
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HuffmanCode32 {
+    pub(crate) bits: c_int, // number of bits used for this symbol,
+    // or an impossible value if not a literal code.
+    pub(crate) value: u32, // 32b packed ARGB value if literal,
+                           // or non-literal symbol otherwise
+}
+
+pub(crate) const HUFFMAN_PACKED_BITS: c_int = 6;
+pub(crate) const HUFFMAN_PACKED_TABLE_SIZE: u32 = 1 << HUFFMAN_PACKED_BITS;
+
+// Huffman table group.
+// Includes special handling for the following cases:
+//  - is_trivial_literal: one common literal base for RED/BLUE/ALPHA (not GREEN)
+//  - is_trivial_code: only 1 code (no bit is read from bitstream)
+//  - use_packed_table: few enough literal symbols, so all the bit codes
+//    can fit into a small look-up table packed_table[]
+// The common literal base, if applicable, is stored in 'literal_arb'.
+#[repr(C)]
+pub struct HTreeGroup {
+    pub(crate) htrees: [*mut HuffmanCode; HUFFMAN_CODES_PER_META_CODE],
+    pub(crate) is_trivial_literal: c_int, // True, if huffman trees for Red, Blue & Alpha
+    // Symbols are trivial (have a single code).
+    pub(crate) literal_arb: u32, // If is_trivial_literal is true, this is the
+    // ARGB value of the pixel, with Green channel
+    // being set to zero.
+    pub(crate) is_trivial_code: c_int, // true if is_trivial_literal with only one code
+    pub(crate) use_packed_table: c_int, // use packed table below for short literal code
+    // table mapping input bits to a packed values, or escape case to literal code
+    pub(crate) packed_table: [HuffmanCode32; HUFFMAN_PACKED_TABLE_SIZE as usize],
+}
+
+// Huffman data read via DecodeImageStream is represented in two (red and green)
+// bytes.
+const MAX_HTREE_GROUPS: c_int = 0x10000;
+
+// Creates the instance of HTreeGroup with specified number of tree-groups.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHtreeGroupsNew(num_htree_groups: c_int) -> *mut HTreeGroup {
+    let htree_groups: *mut HTreeGroup =
+        WebPSafeMalloc(num_htree_groups as u64, mem::size_of::<HTreeGroup>()) as *mut HTreeGroup;
+    if htree_groups.is_null() {
+        return htree_groups;
+    }
+    c_assert!(num_htree_groups <= MAX_HTREE_GROUPS);
+    htree_groups
+}
+
+// Releases the memory allocated for HTreeGroup.
+#[no_mangle]
+pub unsafe extern "C" fn VP8LHtreeGroupsFree(htree_groups: *mut HTreeGroup) {
+    if !htree_groups.is_null() {
+        WebPSafeFree(htree_groups as *mut c_void);
+    }
+}
+
+// Returns reverse(reverse(key, len) + 1, len), where reverse(key, len) is the
+// bit-wise reversal of the len least significant bits of key.
+#[allow(non_snake_case)]
+#[inline]
+fn GetNextKey(key: u32, len: c_int) -> u32 {
+    let mut step: u32 = 1 << (len - 1);
+    while key & step != 0 {
+        step >>= 1;
+    }
+    if step != 0 {
+        (key & (step - 1)) + step
+    } else {
+        key
+    }
+}
+
+// Stores code in table[0], table[step], table[2*step], ..., table[end].
+// Assumes that end is an integer multiple of step.
+#[allow(non_snake_case)]
+#[inline]
+unsafe fn ReplicateValue(table: *mut HuffmanCode, step: c_int, mut end: c_int, code: HuffmanCode) {
+    c_assert!(end % step == 0);
+    loop {
+        end -= step;
+        *table.offset(end as isize) = code;
+        if end <= 0 {
+            break;
+        }
+    }
+}
+
+// Returns the table width of the next 2nd level table. count is the histogram
+// of bit lengths for the remaining symbols, len is the code length of the next
+// processed symbol
+#[allow(non_snake_case)]
+#[inline]
+fn NextTableBitSize(count: &[c_int], mut len: c_int, root_bits: c_int) -> c_int {
+    let mut left = 1 << (len - root_bits);
+    while len < MAX_ALLOWED_CODE_LENGTH {
+        left -= count[len as usize];
+        if left <= 0 {
+            break;
+        }
+        len += 1;
+        left <<= 1;
+    }
+    len - root_bits
+}
+
+// sorted[code_lengths_size] is a pre-allocated array for sorting symbols
+// by code length.
+#[allow(non_snake_case)]
+unsafe fn BuildHuffmanTable(
+    root_table: *mut HuffmanCode,
+    root_bits: c_int,
+    code_lengths: &[c_int],
+    sorted: &mut [u16],
+) -> c_int {
+    let mut table: *mut HuffmanCode = root_table; // next available space in table
+    let mut total_size: c_int = 1 << root_bits; // total size root table + 2nd level table
+                                                // number of codes of each length:
+    let mut count = [0 as c_int; MAX_ALLOWED_CODE_LENGTH as usize + 1];
+    // offsets in sorted table for each length:
+    let mut offset = [0 as c_int; MAX_ALLOWED_CODE_LENGTH as usize + 1];
+
+    c_assert!(!code_lengths.is_empty());
+    c_assert!(!root_table.is_null());
+    c_assert!(root_bits > 0);
+
+    // Build histogram of code lengths.
+    for &code_length in code_lengths {
+        if code_length > MAX_ALLOWED_CODE_LENGTH {
+            return 0;
+        }
+        count[code_length as usize] += 1;
+    }
+
+    // Error, all code lengths are zeros.
+    if count[0] as usize == code_lengths.len() {
+        return 0;
+    }
+
+    // Generate offsets into sorted symbol table by code length.
+    offset[1] = 0;
+    for len in 1..MAX_ALLOWED_CODE_LENGTH as usize {
+        if count[len] > (1 << len) {
+            return 0;
+        }
+        offset[len + 1] = offset[len] + count[len];
+    }
+
+    // Sort symbols by length, by symbol order within each length.
+    for (symbol, &symbol_code_length) in code_lengths.iter().enumerate() {
+        if symbol_code_length > 0 {
+            sorted[offset[symbol_code_length as usize] as usize] = symbol as u16;
+            offset[symbol_code_length as usize] += 1;
+        }
+    }
+
+    // Special case code with only one value.
+    if offset[MAX_ALLOWED_CODE_LENGTH as usize] == 1 {
+        let code = HuffmanCode {
+            bits: 0,
+            value: sorted[0],
+        };
+        ReplicateValue(table, 1, total_size, code);
+        return total_size;
+    }
+
+    {
+        let mut low: u32 = !0; // low bits for current root entry
+        let mask: u32 = total_size as u32 - 1; // mask for low bits
+        let mut key: u32 = 0; // reversed prefix code
+        let mut num_nodes: c_int = 1; // number of Huffman tree nodes
+        let mut num_open: c_int = 1; // number of open branches in current tree level
+        let mut table_bits: c_int = root_bits; // key length of current table
+        let mut table_size: c_int = 1 << table_bits; // size of current table
+        let mut symbol: usize = 0;
+        // Fill in root table.
+        let mut step: c_int = 2; // step size to replicate values in current table
+        for len in 1..=root_bits {
+            num_open <<= 1;
+            num_nodes += num_open;
+            num_open -= count[len as usize];
+            if num_open < 0 {
+                return 0;
+            }
+            while count[len as usize] > 0 {
+                let code = HuffmanCode {
+                    bits: len as u8,
+                    value: sorted[symbol],
+                };
+                symbol += 1;
+                ReplicateValue(table.add(key as usize), step, table_size, code);
+                key = GetNextKey(key, len);
+                count[len as usize] -= 1;
+            }
+            step <<= 1;
+        }
+
+        // Fill in 2nd level tables and add pointers to root table.
+        step = 2;
+        for len in root_bits + 1..=MAX_ALLOWED_CODE_LENGTH {
+            num_open <<= 1;
+            num_nodes += num_open;
+            num_open -= count[len as usize];
+            if num_open < 0 {
+                return 0;
+            }
+            while count[len as usize] > 0 {
+                if (key & mask) != low {
+                    table = table.offset(table_size as isize);
+                    table_bits = NextTableBitSize(&count, len, root_bits);
+                    table_size = 1 << table_bits;
+                    total_size += table_size;
+                    low = key & mask;
+                    let root: &mut HuffmanCode = &mut *root_table.add(low as usize);
+                    root.bits = (table_bits + root_bits) as u8;
+                    root.value = (table.offset_from(root_table) - low as isize) as u16;
+                }
+                let code = HuffmanCode {
+                    bits: (len - root_bits) as u8,
+                    value: sorted[symbol],
+                };
+                symbol += 1;
+                ReplicateValue(
+                    table.add((key >> root_bits) as usize),
+                    step,
+                    table_size,
+                    code,
+                );
+                key = GetNextKey(key, len);
+                count[len as usize] -= 1;
+            }
+            step <<= 1;
+        }
+
+        // Check if tree is full.
+        if num_nodes != 2 * offset[MAX_ALLOWED_CODE_LENGTH as usize] - 1 {
+            return 0;
+        }
+    }
+
+    total_size
+}
+
+// Maximum code_lengths_size is 2328 (reached for 11-bit color_cache_bits).
+// More commonly, the value is around ~280.
+const MAX_CODE_LENGTHS_SIZE: c_int = (1 << MAX_CACHE_BITS) + NUM_LITERAL_CODES + NUM_LENGTH_CODES;
+// Cut-off value for switching between heap and stack allocation.
+const SORTED_SIZE_CUTOFF: usize = 512;
+
+// Builds Huffman lookup table assuming code lengths are in symbol order.
+// The 'code_lengths' is pre-allocated temporary memory buffer used for creating
+// the huffman table.
+// Returns built table size or 0 in case of error (invalid tree or
+// memory error).
+#[no_mangle]
+pub unsafe extern "C" fn VP8LBuildHuffmanTable(
+    root_table: *mut HuffmanCode,
+    root_bits: c_int,
+    code_lengths: *const c_int,
+    code_lengths_size: c_int,
+) -> c_int {
+    c_assert!(code_lengths_size <= MAX_CODE_LENGTHS_SIZE);
+    c_assert!(!code_lengths.is_null());
+    let code_lengths: &[c_int] =
+        ::std::slice::from_raw_parts(code_lengths, code_lengths_size as usize);
+    if code_lengths.len() <= SORTED_SIZE_CUTOFF {
+        // use local stack-allocated array.
+        let mut sorted = [0u16; SORTED_SIZE_CUTOFF];
+        BuildHuffmanTable(root_table, root_bits, code_lengths, &mut sorted)
+    } else {
+        // rare case. Use heap allocation.
+        let sorted: *mut u16 =
+            WebPSafeMalloc(code_lengths.len() as u64, mem::size_of::<u16>()) as *mut u16;
+        if sorted.is_null() {
+            return 0;
+        }
+        ::std::ptr::write_bytes(sorted, 0, code_lengths.len());
+        let total_size = BuildHuffmanTable(
+            root_table,
+            root_bits,
+            code_lengths,
+            ::std::slice::from_raw_parts_mut(sorted, code_lengths.len()),
+        );
+        WebPSafeFree(sorted as *mut c_void);
+        total_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_VP8LBuildHuffmanTable(
+            root_table: *mut HuffmanCode,
+            root_bits: c_int,
+            code_lengths: *const c_int,
+            code_lengths_size: c_int,
+        ) -> c_int;
+    }
+
+    // Code lengths of a random complete prefix code over 'size' symbols, built
+    // by splitting random leaves of the code tree.
+    fn complete_code_lengths(rng: &mut Lcg, size: usize) -> Vec<c_int> {
+        let mut leaves: Vec<c_int> = vec![1, 1];
+        let num_leaves = 2 + rng.next() as usize % (size - 1);
+        while leaves.len() < num_leaves {
+            let i = rng.next() as usize % leaves.len();
+            if leaves[i] < MAX_ALLOWED_CODE_LENGTH {
+                leaves[i] += 1;
+                let len = leaves[i];
+                leaves.push(len);
+            }
+        }
+        let mut code_lengths = vec![0; size];
+        for len in leaves {
+            // Put each leaf on a random unused symbol.
+            let mut symbol = rng.next() as usize % size;
+            while code_lengths[symbol] != 0 {
+                symbol = (symbol + 1) % size;
+            }
+            code_lengths[symbol] = len;
+        }
+        code_lengths
+    }
+
+    type BuildFunc = unsafe extern "C" fn(*mut HuffmanCode, c_int, *const c_int, c_int) -> c_int;
+
+    fn build(build: BuildFunc, root_bits: c_int, code_lengths: &[c_int]) -> Vec<HuffmanCode> {
+        // Large enough for any 2nd level tables.
+        let mut table = vec![HuffmanCode::default(); 1 << 16];
+        let size = unsafe {
+            build(
+                table.as_mut_ptr(),
+                root_bits,
+                code_lengths.as_ptr(),
+                code_lengths.len() as c_int,
+            )
+        };
+        table.truncate(size as usize);
+        table
+    }
+
+    // The table built by the port, checked against the C reference if built.
+    fn check_build(root_bits: c_int, code_lengths: &[c_int]) -> Vec<HuffmanCode> {
+        let table = build(VP8LBuildHuffmanTable, root_bits, code_lengths);
+        #[cfg(feature = "c-reference")]
+        assert!(table == build(WebPRef_VP8LBuildHuffmanTable, root_bits, code_lengths));
+        table
+    }
+
+    #[test]
+    fn test_build_huffman_table_parity() {
+        let mut digest = Digest::new();
+        let mut add = |table: &[HuffmanCode]| {
+            digest.update(&(table.len() as u32).to_le_bytes());
+            for code in table {
+                digest.update(&[code.bits]);
+                digest.update(&code.value.to_le_bytes());
+            }
+        };
+        let mut rng = Lcg(42);
+        for &(root_bits, size) in &[
+            (LENGTHS_TABLE_BITS, 19),
+            (HUFFMAN_TABLE_BITS, 40),
+            (HUFFMAN_TABLE_BITS, 256),
+            (HUFFMAN_TABLE_BITS, 280),
+            (HUFFMAN_TABLE_BITS, 280 + 256),
+            (HUFFMAN_TABLE_BITS, MAX_CODE_LENGTHS_SIZE as usize),
+        ] {
+            for _ in 0..50 {
+                let code_lengths = complete_code_lengths(&mut rng, size);
+                let table = check_build(root_bits, &code_lengths);
+                assert!(table.len() >= 1 << root_bits);
+                add(&table);
+
+                // Over-subscribed, incomplete and out of range code lengths.
+                if let Some(symbol) = code_lengths.iter().position(|&len| len > 1) {
+                    for &delta in &[-1, 1, MAX_ALLOWED_CODE_LENGTH] {
+                        let mut invalid = code_lengths.clone();
+                        invalid[symbol] += delta;
+                        assert!(check_build(root_bits, &invalid).is_empty());
+                    }
+                }
+            }
+        }
+        check_parity(
+            "utils::huffman_utils::test_build_huffman_table_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_build_huffman_table_special_cases() {
+        // All code lengths are zero.
+        assert!(check_build(HUFFMAN_TABLE_BITS, &[0; 10]).is_empty());
+        // A single symbol takes no bits.
+        let table = check_build(HUFFMAN_TABLE_BITS, &[0, 0, 0, 7, 0]);
+        assert_eq!(table.len(), 1 << HUFFMAN_TABLE_BITS);
+        assert!(table
+            .iter()
+            .all(|&code| code == HuffmanCode { bits: 0, value: 3 }));
+        // More codes of a length than the length allows.
+        assert!(check_build(HUFFMAN_TABLE_BITS, &[1, 1, 1]).is_empty());
+        // Two one-bit codes fill the root table.
+        assert_eq!(
+            check_build(HUFFMAN_TABLE_BITS, &[1, 0, 1]).len(),
+            1 << HUFFMAN_TABLE_BITS
+        );
+    }
+
+    #[test]
+    fn test_htree_groups() {
+        unsafe {
+            let htree_groups = VP8LHtreeGroupsNew(3);
+            assert!(!htree_groups.is_null());
+            VP8LHtreeGroupsFree(htree_groups);
+            VP8LHtreeGroupsFree(::std::ptr::null_mut());
+        }
+    }
+}
@@ -1,10 +1,26 @@
 #[doc(hidden)]
+pub mod bit_reader_inl_utils;
+#[doc(hidden)]
 pub mod bit_reader_utils;
+#[cfg(any(feature = "rust-vp8-encoder", feature = "rust-vp8l-encoder"))]
+#[doc(hidden)]
+pub mod bit_writer_utils;
+#[doc(hidden)]
+pub mod color_cache_utils;
+#[doc(hidden)]
+pub mod filters_utils;
+#[cfg(feature = "rust-vp8l-encoder")]
+#[doc(hidden)]
+pub mod huffman_encode_utils;
+#[doc(hidden)]
+pub mod huffman_utils;
 #[doc(hidden)]
 pub mod quant_levels_dec_utils;
 #[doc(hidden)]
 pub mod random_utils;
 #[doc(hidden)]
+pub mod rescaler_utils;
+#[doc(hidden)]
 pub mod thread_utils;
 #[doc(hidden)]
 pub mod utils;
@@ -1,11 +1,477 @@
+// Copyright 2013 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Implement gradient smoothing: we replace a current alpha value by its
+// surrounding average if it's close enough (that is: the change will be less
+// than the minimum distance between two quantized level).
+// We use sliding window for computing the 2d moving average.
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
 use std::os::raw::*;
+use std::ptr;
+
+use utils::utils::{WebPSafeFree, WebPSafeMalloc};
+
+const FIX: c_int = 16; // fix-point precision for averaging
+const LFIX: c_int = 2; // extra precision for look-up table
+const LUT_SIZE: c_int = (1 << (8 + LFIX)) - 1; // look-up table size
+
+// Ordered dithering (USE_DITHERING in the C version) is not ported.
+const DFIX: c_int = 0;
+
+struct SmoothParams {
+    width_: c_int, // dimension
+    height_: c_int,
+    stride_: c_int, // stride in bytes
+    row_: c_int,    // current input row being processed
+    src_: *mut u8,  // input pointer
+    dst_: *mut u8,  // output pointer
+
+    radius_: c_int, // filter radius (=delay)
+    scale_: c_int,  // normalization factor, in FIX bits precision
+
+    mem_: *mut c_void, // all memory
+
+    // various scratch buffers
+    start_: *mut u16,
+    cur_: *mut u16,
+    end_: *mut u16,
+    top_: *mut u16,
+    average_: *mut u16,
+
+    // input levels distribution
+    num_levels_: c_int, // number of quantized levels
+    min_: c_int,        // min and max level values
+    max_: c_int,
+    min_level_dist_: c_int, // smallest distance between two consecutive levels
+
+    correction_: *mut i16, // size = 1 + 2*LUT_SIZE  -> ~4k memory
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_upper_case_globals)]
+const CLIP_8b_MASK: c_int = (!0u32 << (8 + DFIX)) as c_int;
+#[inline]
+fn clip_8b(v: c_int) -> u8 {
+    if (v & CLIP_8b_MASK) == 0 {
+        (v >> DFIX) as u8
+    } else if v < 0 {
+        0
+    } else {
+        255
+    }
+}
+
+// vertical accumulation
+#[allow(non_snake_case)]
+unsafe fn VFilter(p: &mut SmoothParams) {
+    let src: *const u8 = p.src_;
+    let w = p.width_ as isize;
+    let cur: *mut u16 = p.cur_;
+    let top: *const u16 = p.top_;
+    let out: *mut u16 = p.end_;
+    let mut sum: u16 = 0; // all arithmetic is modulo 16bit
+
+    for x in 0..w {
+        sum = sum.wrapping_add(*src.offset(x) as u16);
+        let new_value = (*top.offset(x)).wrapping_add(sum);
+        *out.offset(x) = new_value.wrapping_sub(*cur.offset(x)); // vertical sum of 'r' pixels.
+        *cur.offset(x) = new_value;
+    }
+    // move input pointers one row down
+    p.top_ = p.cur_;
+    p.cur_ = p.cur_.offset(w);
+    if p.cur_ == p.end_ {
+        p.cur_ = p.start_; // roll-over
+    }
+    // We replicate edges, as it's somewhat easier as a boundary condition.
+    // That's why we don't update the 'src' pointer on top/bottom area:
+    if p.row_ >= 0 && p.row_ < p.height_ - 1 {
+        p.src_ = p.src_.offset(p.stride_ as isize);
+    }
+}
+
+// horizontal accumulation. We use mirror replication of missing pixels, as it's
+// a little easier to implement (surprisingly).
+#[allow(non_snake_case)]
+unsafe fn HFilter(p: &mut SmoothParams) {
+    let in_: *const u16 = p.end_;
+    let out: *mut u16 = p.average_;
+    let scale = p.scale_ as u32;
+    let w = p.width_ as isize;
+    let r = p.radius_ as isize;
+
+    let mut x = 0;
+    while x <= r {
+        // left mirroring
+        let delta = (*in_.offset(x + r - 1)).wrapping_add(*in_.offset(r - x));
+        *out.offset(x) = ((delta as u32).wrapping_mul(scale) >> FIX) as u16;
+        x += 1;
+    }
+    while x < w - r {
+        // bulk middle run
+        let delta = (*in_.offset(x + r)).wrapping_sub(*in_.offset(x - r - 1));
+        *out.offset(x) = ((delta as u32).wrapping_mul(scale) >> FIX) as u16;
+        x += 1;
+    }
+    while x < w {
+        // right mirroring
+        let delta = (2 * *in_.offset(w - 1) as c_int
+            - *in_.offset(2 * w - 2 - r - x) as c_int
+            - *in_.offset(x - r - 1) as c_int) as u16;
+        *out.offset(x) = ((delta as u32).wrapping_mul(scale) >> FIX) as u16;
+        x += 1;
+    }
+}
+
+// emit one filtered output row
+#[allow(non_snake_case)]
+unsafe fn ApplyFilter(p: &mut SmoothParams) {
+    let average: *const u16 = p.average_;
+    let w = p.width_ as isize;
+    let correction: *const i16 = p.correction_;
+    let dst: *mut u8 = p.dst_;
+    for x in 0..w {
+        let v = *dst.offset(x) as c_int;
+        if v < p.max_ && v > p.min_ {
+            let c = (v << DFIX)
+                + *correction.offset((*average.offset(x) as c_int - (v << LFIX)) as isize) as c_int;
+            *dst.offset(x) = clip_8b(c);
+        }
+    }
+    p.dst_ = p.dst_.offset(p.stride_ as isize); // advance output pointer
+}
+
+//------------------------------------------------------------------------------
+// Initialize correction table
+
+#[allow(non_snake_case)]
+unsafe fn InitCorrectionLUT(lut: *mut i16, min_dist: c_int) {
+    // The correction curve is:
+    //   f(x) = x for x <= threshold2
+    //   f(x) = 0 for x >= threshold1
+    // and a linear interpolation for range x=[threshold2, threshold1]
+    // (along with f(-x) = -f(x) symmetry).
+    // Note that: threshold2 = 3/4 * threshold1
+    let threshold1 = min_dist << LFIX;
+    let threshold2 = (3 * threshold1) >> 2;
+    let max_threshold = threshold2 << DFIX;
+    let delta = threshold1 - threshold2;
+    for i in 1..=LUT_SIZE {
+        let mut c = if i <= threshold2 {
+            i << DFIX
+        } else if i < threshold1 {
+            max_threshold * (threshold1 - i) / delta
+        } else {
+            0
+        };
+        c >>= LFIX;
+        *lut.offset(i as isize) = c as i16;
+        *lut.offset(-i as isize) = -c as i16;
+    }
+    *lut = 0;
+}
+
+#[allow(non_snake_case)]
+unsafe fn CountLevels(p: &mut SmoothParams) {
+    let mut used_levels = [0u8; 256];
+    let mut data: *const u8 = p.src_;
+    p.min_ = 255;
+    p.max_ = 0;
+    for _ in 0..p.height_ {
+        for i in 0..p.width_ as isize {
+            let v = *data.offset(i) as c_int;
+            if v < p.min_ {
+                p.min_ = v;
+            }
+            if v > p.max_ {
+                p.max_ = v;
+            }
+            used_levels[v as usize] = 1;
+        }
+        data = data.offset(p.stride_ as isize);
+    }
+    // Compute the mininum distance between two non-zero levels.
+    p.min_level_dist_ = p.max_ - p.min_;
+    let mut last_level = -1;
+    for (i, &used) in used_levels.iter().enumerate() {
+        let i = i as c_int;
+        if used != 0 {
+            p.num_levels_ += 1;
+            if last_level >= 0 {
+                let level_dist = i - last_level;
+                if level_dist < p.min_level_dist_ {
+                    p.min_level_dist_ = level_dist;
+                }
+            }
+            last_level = i;
+        }
+    }
+}
+
+// Initialize all params.
+#[allow(non_snake_case)]
+unsafe fn InitParams(
+    data: *mut u8,
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+    radius: c_int,
+    p: &mut SmoothParams,
+) -> c_int {
+    let R = 2 * radius + 1; // total size of the kernel
+
+    let size_scratch_m = (R + 1) as usize * width as usize * mem::size_of::<u16>();
+    let size_m = width as usize * mem::size_of::<u16>();
+    let size_lut = (1 + 2 * LUT_SIZE) as usize * mem::size_of::<i16>();
+    let total_size = size_scratch_m + size_m + size_lut;
+    let mut mem = WebPSafeMalloc(1, total_size) as *mut u8;
+
+    if mem.is_null() {
+        return 0;
+    }
+    p.mem_ = mem as *mut c_void;
+
+    p.start_ = mem as *mut u16;
+    p.cur_ = p.start_;
+    p.end_ = p.start_.offset(R as isize * width as isize);
+    p.top_ = p.end_.offset(-width as isize);
+    ptr::write_bytes(p.top_, 0, width as usize);
+    mem = mem.add(size_scratch_m);
+
+    p.average_ = mem as *mut u16;
+    mem = mem.add(size_m);
+
+    p.width_ = width;
+    p.height_ = height;
+    p.stride_ = stride;
+    p.src_ = data;
+    p.dst_ = data;
+    p.radius_ = radius;
+    p.scale_ = (1 << (FIX + LFIX)) / (R * R); // normalization constant
+    p.row_ = -radius;
+
+    // analyze the input distribution so we can best-fit the threshold
+    CountLevels(p);
+
+    // correction table
+    p.correction_ = (mem as *mut i16).offset(LUT_SIZE as isize);
+    InitCorrectionLUT(p.correction_, p.min_level_dist_);
+
+    1
+}
+
+#[allow(non_snake_case)]
+unsafe fn CleanupParams(p: &mut SmoothParams) {
+    WebPSafeFree(p.mem_);
+}
+
+// Apply post-processing to input 'data' of size 'width'x'height' assuming that
+// the source was quantized to a reduced number of levels.
+// Strength is in [0..100] and controls the amount of dithering applied.
+// Returns false in case of error (data is NULL, invalid parameters,
+// malloc failure, ...).
+#[no_mangle]
+pub unsafe extern "C" fn WebPDequantizeLevels(
+    data: *mut u8,
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+    strength: c_int,
+) -> c_int {
+    let radius = 4 * strength / 100;
+    if !(0..=100).contains(&strength) {
+        return 0;
+    }
+    if data.is_null() || width <= 0 || height <= 0 {
+        return 0; // bad params
+    }
+    if radius > 0 {
+        let mut p: SmoothParams = mem::zeroed();
+        if InitParams(data, width, height, stride, radius, &mut p) == 0 {
+            return 0;
+        }
+        if p.num_levels_ > 2 {
+            while p.row_ < p.height_ {
+                VFilter(&mut p); // accumulate average of input
+                                 // Need to wait few rows in order to prime the filter,
+                                 // before emitting some output.
+                if p.row_ >= p.radius_ {
+                    HFilter(&mut p);
+                    ApplyFilter(&mut p);
+                }
+                p.row_ += 1;
+            }
+        }
+        CleanupParams(&mut p);
+    }
+    1
+}
+
+// Same as WebPDequantizeLevels(), on the 'width'x'height' area at the start of
+// 'data'. Returns false if 'data' is too short to hold it.
+#[allow(non_snake_case)]
+pub(crate) fn DequantizeLevels(
+    data: &mut [u8],
+    width: c_int,
+    height: c_int,
+    stride: c_int,
+    strength: c_int,
+) -> c_int {
+    if width <= 0 || height <= 0 || stride < width {
+        return 0; // bad params
+    }
+    if data.len() < (height as usize - 1) * stride as usize + width as usize {
+        return 0;
+    }
+    // Every row read and written lies within 'data', as checked above.
+    unsafe { WebPDequantizeLevels(data.as_mut_ptr(), width, height, stride, strength) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use test_util::{check_parity, Digest, Lcg};
+
+    #[cfg(feature = "c-reference")]
+    extern "C" {
+        fn WebPRef_WebPDequantizeLevels(
+            data: *mut u8,
+            width: c_int,
+            height: c_int,
+            stride: c_int,
+            strength: c_int,
+        ) -> c_int;
+    }
+
+    // A radial gradient quantized to 'num_levels' levels, with a bit of noise
+    // in the quantization thresholds.
+    fn quantized_plane(
+        rng: &mut Lcg,
+        width: usize,
+        height: usize,
+        stride: usize,
+        num_levels: u32,
+    ) -> Vec<u8> {
+        let mut plane = rng.bytes(stride * height);
+        let step = 255 / (num_levels - 1);
+        for y in 0..height {
+            for x in 0..width {
+                let (dx, dy) = (
+                    x as f64 - width as f64 / 3.0,
+                    y as f64 - height as f64 / 2.0,
+                );
+                let v = ((dx * dx + dy * dy).sqrt() * 4.0) as u32 + rng.next() % 8;
+                let level = ((v % 256) + step / 2) / step;
+                plane[y * stride + x] = (level.min(num_levels - 1) * step) as u8;
+            }
+        }
+        plane
+    }
+
+    #[test]
+    fn test_dequantize_levels_parity() {
+        let mut digest = Digest::new();
+        let mut rng = Lcg(1);
+        // Note: pictures narrower than 2 * radius + 1 make the mirroring in
+        // HFilter() read uninitialized scratch memory, in C too.
+        for &(width, height) in &[(9, 1), (9, 17), (17, 3), (40, 30), (100, 7)] {
+            for &num_levels in &[2, 3, 4, 8, 16, 64] {
+                for &strength in &[0, 10, 25, 49, 50, 75, 100] {
+                    let stride = width + rng.next() as usize % 3;
+                    let plane = quantized_plane(&mut rng, width, height, stride, num_levels);
+                    let mut data = plane.clone();
+                    let ok = unsafe {
+                        WebPDequantizeLevels(
+                            data.as_mut_ptr(),
+                            width as c_int,
+                            height as c_int,
+                            stride as c_int,
+                            strength,
+                        )
+                    };
+                    assert_eq!(ok, 1);
+                    digest.update(&data);
+                    #[cfg(feature = "c-reference")]
+                    {
+                        let mut ref_data = plane.clone();
+                        let ref_ok = unsafe {
+                            WebPRef_WebPDequantizeLevels(
+                                ref_data.as_mut_ptr(),
+                                width as c_int,
+                                height as c_int,
+                                stride as c_int,
+                                strength,
+                            )
+                        };
+                        assert_eq!(ref_ok, 1);
+                        assert!(
+                            data == ref_data,
+                            "{}x{}, {} levels, strength = {}",
+                            width,
+                            height,
+                            num_levels,
+                            strength
+                        );
+                    }
+                }
+            }
+        }
+        check_parity(
+            "utils::quant_levels_dec_utils::test_dequantize_levels_parity",
+            digest,
+        );
+    }
+
+    #[test]
+    fn test_dequantize_levels_smooths() {
+        let mut rng = Lcg(2);
+        let (width, height) = (64, 64);
+        let plane = quantized_plane(&mut rng, width, height, width, 8);
+        let mut data = plane.clone();
+        unsafe {
+            assert_eq!(
+                WebPDequantizeLevels(
+                    data.as_mut_ptr(),
+                    width as c_int,
+                    height as c_int,
+                    width as c_int,
+                    100
+                ),
+                1
+            );
+        }
+        assert!(data != plane);
+        // Levels are only nudged towards the local average.
+        for (&v, &orig) in data.iter().zip(&plane) {
+            assert!((v as c_int - orig as c_int).abs() <= 255 / 7);
+        }
+    }
 
-extern "C" {
-    pub(crate) fn WebPDequantizeLevels(
-        data: *mut u8,
-        width: c_int,
-        height: c_int,
-        stride: c_int,
-        strength: c_int,
-    ) -> c_int;
+    #[test]
+    fn test_dequantize_levels_bad_params() {
+        let mut data = [0u8; 16];
+        unsafe {
+            assert_eq!(WebPDequantizeLevels(data.as_mut_ptr(), 4, 4, 4, -1), 0);
+            assert_eq!(WebPDequantizeLevels(data.as_mut_ptr(), 4, 4, 4, 101), 0);
+            assert_eq!(WebPDequantizeLevels(ptr::null_mut(), 4, 4, 4, 50), 0);
+            assert_eq!(WebPDequantizeLevels(data.as_mut_ptr(), 0, 4, 4, 50), 0);
+            assert_eq!(WebPDequantizeLevels(data.as_mut_ptr(), 4, 0, 4, 50), 0);
+        }
+        assert_eq!(DequantizeLevels(&mut data, 4, 4, 3, 50), 0);
+        assert_eq!(DequantizeLevels(&mut data, 4, 5, 4, 50), 0);
+        assert_eq!(DequantizeLevels(&mut data[1..], 4, 4, 4, 50), 0);
+        assert_eq!(DequantizeLevels(&mut data[..13], 1, 4, 4, 50), 1);
+    }
 }
@@ -39,7 +39,7 @@ pub unsafe extern "C" fn VP8RandomBits2(rg: *mut VP8Random, num_bits: c_int, amp
     c_assert!(num_bits + (VP8_RANDOM_DITHER_FIX as i32) <= 31);
     diff = rg.tab_[rg.index1_ as usize] as i32 - rg.tab_[rg.index2_ as usize] as i32;
     if diff < 0 {
-        diff += 1 << 31;
+        diff = (diff as u32).wrapping_add(1 << 31) as i32;
     }
     rg.tab_[rg.index1_ as usize] = diff as u32;
     rg.index1_ += 1;
@@ -0,0 +1,246 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Rescaling functions
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
+use std::mem;
+use std::os::raw::*;
+use std::ptr;
+
+use dsp::rescaler::{WebPRescalerDspInit, WebPRescalerExportRow, WebPRescalerImportRow};
+
+pub(crate) const WEBP_RESCALER_RFIX: c_int = 32; // fixed-point precision for multiplies
+pub(crate) const WEBP_RESCALER_ONE: u64 = 1 << WEBP_RESCALER_RFIX;
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WEBP_RESCALER_FRAC(x: u64, y: u64) -> u32 {
+    ((x << WEBP_RESCALER_RFIX) / y) as u32
+}
+
+// Structure used for on-the-fly rescaling
+#[allow(non_camel_case_types)]
+pub(crate) type rescaler_t = u32; // type for side-buffer
+
+#[repr(C)]
+pub struct WebPRescaler {
+    pub(crate) x_expand: c_int, // true if we're expanding in the x direction
+    pub(crate) y_expand: c_int, // true if we're expanding in the y direction
+    pub(crate) num_channels: c_int, // bytes to jump between pixels
+    pub(crate) fx_scale: u32,   // fixed-point scaling factors
+    pub(crate) fy_scale: u32,   // ''
+    pub(crate) fxy_scale: u32,  // ''
+    pub(crate) y_accum: c_int,  // vertical accumulator
+    pub(crate) y_add: c_int,    // vertical increments
+    pub(crate) y_sub: c_int,
+    pub(crate) x_add: c_int, // horizontal increments
+    pub(crate) x_sub: c_int,
+    pub(crate) src_width: c_int, // source dimensions
+    pub(crate) src_height: c_int,
+    pub(crate) dst_width: c_int, // destination dimensions
+    pub(crate) dst_height: c_int,
+    pub(crate) src_y: c_int, // row counters for input and output
+    pub(crate) dst_y: c_int,
+    pub(crate) dst: *mut u8,
+    pub(crate) dst_stride: c_int,
+    pub(crate) irow: *mut rescaler_t, // work buffer
+    pub(crate) frow: *mut rescaler_t,
+}
+
+//------------------------------------------------------------------------------
+
+// Initialize a rescaler given scratch area 'work' and dimensions of src & dst.
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerInit(
+    wrk: *mut WebPRescaler,
+    src_width: c_int,
+    src_height: c_int,
+    dst: *mut u8,
+    dst_width: c_int,
+    dst_height: c_int,
+    dst_stride: c_int,
+    num_channels: c_int,
+    work: *mut rescaler_t,
+) {
+    let wrk = &mut *wrk;
+    let x_add = src_width;
+    let x_sub = dst_width;
+    let y_add = src_height;
+    let y_sub = dst_height;
+    wrk.x_expand = (src_width < dst_width) as c_int;
+    wrk.y_expand = (src_height < dst_height) as c_int;
+    wrk.src_width = src_width;
+    wrk.src_height = src_height;
+    wrk.dst_width = dst_width;
+    wrk.dst_height = dst_height;
+    wrk.src_y = 0;
+    wrk.dst_y = 0;
+    wrk.dst = dst;
+    wrk.dst_stride = dst_stride;
+    wrk.num_channels = num_channels;
+
+    // for 'x_expand', we use bilinear interpolation
+    wrk.x_add = if wrk.x_expand != 0 { x_sub - 1 } else { x_add };
+    wrk.x_sub = if wrk.x_expand != 0 { x_add - 1 } else { x_sub };
+    if wrk.x_expand == 0 {
+        // fx_scale is not used otherwise
+        wrk.fx_scale = WEBP_RESCALER_FRAC(1, wrk.x_sub as u64);
+    }
+    // vertical scaling parameters
+    wrk.y_add = if wrk.y_expand != 0 { y_add - 1 } else { y_add };
+    wrk.y_sub = if wrk.y_expand != 0 { y_sub - 1 } else { y_sub };
+    wrk.y_accum = if wrk.y_expand != 0 {
+        wrk.y_sub
+    } else {
+        wrk.y_add
+    };
+    if wrk.y_expand == 0 {
+        // This is WEBP_RESCALER_FRAC(dst_height, x_add * y_add) without the cast.
+        // Its value is <= WEBP_RESCALER_ONE, because dst_height <= wrk->y_add, and
+        // wrk->x_add >= 1;
+        let ratio = dst_height as u64 * WEBP_RESCALER_ONE / (wrk.x_add * wrk.y_add) as u64;
+        if ratio != ratio as u32 as u64 {
+            // When ratio == WEBP_RESCALER_ONE, we can't represent the ratio with the
+            // current fixed-point precision. This happens when src_height ==
+            // wrk->y_add (which == src_height), and wrk->x_add == 1.
+            // => We special-case fxy_scale = 0, in WebPRescalerExportRow().
+            wrk.fxy_scale = 0;
+        } else {
+            wrk.fxy_scale = ratio as u32;
+        }
+        wrk.fy_scale = WEBP_RESCALER_FRAC(1, wrk.y_sub as u64);
+    } else {
+        wrk.fy_scale = WEBP_RESCALER_FRAC(1, wrk.x_add as u64);
+        // wrk->fxy_scale is unused here.
+    }
+    wrk.irow = work;
+    wrk.frow = work.offset((num_channels * dst_width) as isize);
+    ptr::write_bytes(work, 0, 2 * dst_width as usize * num_channels as usize);
+
+    WebPRescalerDspInit();
+}
+
+// If either 'scaled_width' or 'scaled_height' (but not both) is 0 the value
+// will be calculated preserving the aspect ratio, otherwise the values are
+// left unmodified. Returns true on success, false if either value is 0 after
+// performing the scaling calculation.
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerGetScaledDimensions(
+    src_width: c_int,
+    src_height: c_int,
+    scaled_width: *mut c_int,
+    scaled_height: *mut c_int,
+) -> c_int {
+    c_assert!(!scaled_width.is_null());
+    c_assert!(!scaled_height.is_null());
+    let mut width = *scaled_width;
+    let mut height = *scaled_height;
+
+    // if width is unspecified, scale original proportionally to height ratio.
+    if width == 0 {
+        width = ((src_width as u64 * height as u64 + (src_height / 2) as u64) / src_height as u64)
+            as c_int;
+    }
+    // if height is unspecified, scale original proportionally to width ratio.
+    if height == 0 {
+        height = ((src_height as u64 * width as u64 + (src_width / 2) as u64) / src_width as u64)
+            as c_int;
+    }
+    // Check if the overall dimensions still make sense.
+    if width <= 0 || height <= 0 {
+        return 0;
+    }
+
+    *scaled_width = width;
+    *scaled_height = height;
+    1
+}
+
+//------------------------------------------------------------------------------
+// all-in-one calls
+
+// Returns the number of input lines needed next to produce one output line,
+// considering that the maximum available input lines are 'max_num_lines'.
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescaleNeededLines(
+    wrk: *const WebPRescaler,
+    max_num_lines: c_int,
+) -> c_int {
+    let num_lines = ((*wrk).y_accum + (*wrk).y_sub - 1) / (*wrk).y_sub;
+    if num_lines > max_num_lines {
+        max_num_lines
+    } else {
+        num_lines
+    }
+}
+
+// Import multiple rows over all channels, until at least one row is ready to
+// be exported. Returns the actual number of lines that were imported.
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerImport(
+    wrk: *mut WebPRescaler,
+    num_lines: c_int,
+    mut src: *const u8,
+    src_stride: c_int,
+) -> c_int {
+    let mut total_imported = 0;
+    while total_imported < num_lines && WebPRescalerHasPendingOutput(&*wrk) == 0 {
+        let wrk = &mut *wrk;
+        if wrk.y_expand != 0 {
+            mem::swap(&mut wrk.irow, &mut wrk.frow);
+        }
+        WebPRescalerImportRow(wrk, src);
+        if wrk.y_expand == 0 {
+            // Accumulate the contribution of the new row.
+            for x in 0..(wrk.num_channels * wrk.dst_width) as isize {
+                *wrk.irow.offset(x) = (*wrk.irow.offset(x)).wrapping_add(*wrk.frow.offset(x));
+            }
+        }
+        wrk.src_y += 1;
+        src = src.offset(src_stride as isize);
+        total_imported += 1;
+        wrk.y_accum -= wrk.y_sub;
+    }
+    total_imported
+}
+
+// Export as many rows as possible. Return the numbers of rows written.
+#[no_mangle]
+pub unsafe extern "C" fn WebPRescalerExport(rescaler: *mut WebPRescaler) -> c_int {
+    let mut total_exported = 0;
+    while WebPRescalerHasPendingOutput(&*rescaler) != 0 {
+        WebPRescalerExportRow(rescaler);
+        total_exported += 1;
+    }
+    total_exported
+}
+
+// Return true if input is finished
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WebPRescalerInputDone(rescaler: &WebPRescaler) -> c_int {
+    (rescaler.src_y >= rescaler.src_height) as c_int
+}
+
+// Return true if output is finished
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WebPRescalerOutputDone(rescaler: &WebPRescaler) -> c_int {
+    (rescaler.dst_y >= rescaler.dst_height) as c_int
+}
+
+// Return true if there are pending output rows ready.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WebPRescalerHasPendingOutput(rescaler: &WebPRescaler) -> c_int {
+    (WebPRescalerOutputDone(rescaler) == 0 && rescaler.y_accum <= 0) as c_int
+}
@@ -1,7 +1,26 @@
+// Copyright 2011 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Multi-threaded worker
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
 use std::os::raw::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
 
-#[allow(dead_code)]
+// State of the worker thread object
 #[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[repr(C)]
 pub(crate) enum WebPWorkerStatus {
     NOT_OK = 0, // object is unusable
@@ -9,8 +28,13 @@ pub(crate) enum WebPWorkerStatus {
     WORK,       // busy finishing the current task
 }
 
-pub(crate) type WebPWorkerHook = Option<extern "C" fn(*mut c_void, *mut c_void) -> c_int>;
+// Function to be called by the worker thread. Takes two opaque pointers as
+// arguments (data1 and data2), and should return false in case of error.
+// A panic unwinding out of the hook is reported as an error, too.
+pub(crate) type WebPWorkerHook =
+    Option<unsafe extern "C-unwind" fn(data1: *mut c_void, data2: *mut c_void) -> c_int>;
 
+// Synchronization object used to launch job in the worker thread
 #[repr(C)]
 pub(crate) struct WebPWorker {
     pub(crate) impl_: *mut c_void, // platform-dependent implementation worker details
@@ -20,3 +44,305 @@ pub(crate) struct WebPWorker {
     pub(crate) data2: *mut c_void,   // second argument passed to 'hook'
     pub(crate) had_error: c_int,     // return value of the last call to 'hook'
 }
+
+// The interface for all thread-worker related functions. All these functions
+// must be implemented.
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct WebPWorkerInterface {
+    // Must be called first, before any other method.
+    pub(crate) Init: Option<unsafe extern "C" fn(worker: *mut WebPWorker)>,
+    // Must be called to initialize the object and spawn the thread. Re-entrant.
+    // Will potentially launch the thread. Returns false in case of error.
+    pub(crate) Reset: Option<unsafe extern "C" fn(worker: *mut WebPWorker) -> c_int>,
+    // Makes sure the previous work is finished. Returns true if worker->had_error
+    // was not set and no error condition was triggered by the working thread.
+    pub(crate) Sync: Option<unsafe extern "C" fn(worker: *mut WebPWorker) -> c_int>,
+    // Triggers the thread to call hook() with data1 and data2 arguments. These
+    // hook/data1/data2 values can be changed at any time before calling this
+    // function, but not be changed afterward until the next call to Sync().
+    pub(crate) Launch: Option<unsafe extern "C" fn(worker: *mut WebPWorker)>,
+    // This function is similar to Launch() except that it calls the
+    // hook directly instead of using a thread. Convenient to bypass the thread
+    // mechanism while still using the WebPWorker structs. Sync() must
+    // still be called afterward (for error reporting).
+    pub(crate) Execute: Option<unsafe extern "C" fn(worker: *mut WebPWorker)>,
+    // Kill the thread and terminate the object. To use the object again, one
+    // must call Reset() again.
+    pub(crate) End: Option<unsafe extern "C" fn(worker: *mut WebPWorker)>,
+}
+
+//------------------------------------------------------------------------------
+
+// The worker as seen from the thread. The owning WebPWorker is only touched
+// by the thread between a job request and the matching completion message.
+struct WorkerPtr(*mut WebPWorker);
+
+unsafe impl Send for WorkerPtr {}
+
+// Implementation details of a started worker: the thread, a channel to hand
+// it jobs and a channel on which it acknowledges them. Dropping the job
+// sender makes the thread exit.
+struct WebPWorkerImpl {
+    jobs_: Option<Sender<WorkerPtr>>,
+    done_: Receiver<()>,
+    thread_: Option<JoinHandle<()>>,
+}
+
+#[allow(non_snake_case)]
+fn ThreadLoop(jobs: Receiver<WorkerPtr>, done: Sender<()>) {
+    for worker in jobs {
+        unsafe {
+            (*WebPGetWorkerInterface()).Execute.unwrap()(worker.0);
+        }
+        // signal to the main thread that we're done (for Sync())
+        if done.send(()).is_err() {
+            break;
+        }
+    }
+}
+
+// main thread state control
+#[allow(non_snake_case)]
+unsafe fn ChangeState(worker: &mut WebPWorker, new_status: WebPWorkerStatus) {
+    // No-op when attempting to change state on a thread that didn't come up.
+    let impl_ = worker.impl_ as *mut WebPWorkerImpl;
+    if impl_.is_null() {
+        return;
+    }
+    let impl_ = &mut *impl_;
+
+    if worker.status_ >= WebPWorkerStatus::OK {
+        // wait for the worker to finish
+        if worker.status_ == WebPWorkerStatus::WORK {
+            if impl_.done_.recv().is_err() {
+                // The thread is gone without completing its job.
+                worker.had_error = 1;
+            }
+            worker.status_ = WebPWorkerStatus::OK;
+        }
+        // assign new status and release the working thread if needed
+        if new_status != WebPWorkerStatus::OK {
+            worker.status_ = new_status;
+            match new_status {
+                WebPWorkerStatus::WORK => {
+                    let sent = impl_.jobs_.as_ref().is_some_and(|jobs| {
+                        jobs.send(WorkerPtr(worker as *mut WebPWorker)).is_ok()
+                    });
+                    if !sent {
+                        // Nobody will run the job: report it as failed.
+                        worker.had_error = 1;
+                        worker.status_ = WebPWorkerStatus::OK;
+                    }
+                }
+                _ => {
+                    impl_.jobs_ = None;
+                }
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn Init(worker: *mut WebPWorker) {
+    ptr::write_bytes(worker, 0, 1);
+    (*worker).status_ = WebPWorkerStatus::NOT_OK;
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn Sync(worker: *mut WebPWorker) -> c_int {
+    let worker = &mut *worker;
+    ChangeState(worker, WebPWorkerStatus::OK);
+    c_assert!(worker.status_ <= WebPWorkerStatus::OK);
+    (worker.had_error == 0) as c_int
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn Reset(worker: *mut WebPWorker) -> c_int {
+    let worker = &mut *worker;
+    let mut ok = 1;
+    worker.had_error = 0;
+    if worker.status_ < WebPWorkerStatus::OK {
+        let (jobs_sender, jobs) = mpsc::channel();
+        let (done, done_receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("webp-worker".to_string())
+            .spawn(move || ThreadLoop(jobs, done));
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(_) => return 0,
+        };
+        worker.impl_ = Box::into_raw(Box::new(WebPWorkerImpl {
+            jobs_: Some(jobs_sender),
+            done_: done_receiver,
+            thread_: Some(thread),
+        })) as *mut c_void;
+        worker.status_ = WebPWorkerStatus::OK;
+    } else if worker.status_ > WebPWorkerStatus::OK {
+        ok = Sync(worker);
+    }
+    c_assert!(ok == 0 || worker.status_ == WebPWorkerStatus::OK);
+    ok
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn Execute(worker: *mut WebPWorker) {
+    let worker = &mut *worker;
+    if let Some(hook) = worker.hook {
+        let (data1, data2) = (worker.data1, worker.data2);
+        let ok = panic::catch_unwind(AssertUnwindSafe(|| hook(data1, data2))).unwrap_or(0);
+        worker.had_error |= (ok == 0) as c_int;
+    }
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn Launch(worker: *mut WebPWorker) {
+    ChangeState(&mut *worker, WebPWorkerStatus::WORK);
+}
+
+#[allow(non_snake_case)]
+unsafe extern "C" fn End(worker: *mut WebPWorker) {
+    let worker = &mut *worker;
+    if !worker.impl_.is_null() {
+        ChangeState(worker, WebPWorkerStatus::NOT_OK);
+        let mut impl_ = Box::from_raw(worker.impl_ as *mut WebPWorkerImpl);
+        if let Some(thread) = impl_.thread_.take() {
+            let _ = thread.join();
+        }
+        worker.impl_ = ptr::null_mut();
+    }
+    c_assert!(worker.status_ == WebPWorkerStatus::NOT_OK);
+}
+
+//------------------------------------------------------------------------------
+
+#[allow(non_upper_case_globals)]
+static mut g_worker_interface: WebPWorkerInterface = WebPWorkerInterface {
+    Init: Some(Init),
+    Reset: Some(Reset),
+    Sync: Some(Sync),
+    Launch: Some(Launch),
+    Execute: Some(Execute),
+    End: Some(End),
+};
+
+// Install a new set of threading functions, overriding the defaults. This
+// should be done before any workers are started, i.e., before any encoding or
+// decoding takes place. The contents of the interface struct are copied, it
+// is safe to free the corresponding memory after this call. This function is
+// not thread-safe. Return false in case of invalid pointer or methods.
+#[no_mangle]
+pub unsafe extern "C" fn WebPSetWorkerInterface(winterface: *const WebPWorkerInterface) -> c_int {
+    let winterface = match winterface.as_ref() {
+        Some(winterface) => winterface,
+        None => return 0,
+    };
+    if winterface.Init.is_none()
+        || winterface.Reset.is_none()
+        || winterface.Sync.is_none()
+        || winterface.Launch.is_none()
+        || winterface.Execute.is_none()
+        || winterface.End.is_none()
+    {
+        return 0;
+    }
+    g_worker_interface = ptr::read(winterface);
+    1
+}
+
+// Retrieve the currently set thread worker interface.
+#[no_mangle]
+pub unsafe extern "C" fn WebPGetWorkerInterface() -> *const WebPWorkerInterface {
+    ptr::addr_of!(g_worker_interface)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::mem;
+
+    unsafe extern "C-unwind" fn count_hook(data1: *mut c_void, data2: *mut c_void) -> c_int {
+        *(data1 as *mut c_int) += 1;
+        data2.is_null() as c_int
+    }
+
+    unsafe extern "C-unwind" fn panic_hook(_data1: *mut c_void, _data2: *mut c_void) -> c_int {
+        panic!("hook failure");
+    }
+
+    #[test]
+    fn test_worker_launch_sync() {
+        unsafe {
+            let winterface = &*WebPGetWorkerInterface();
+            let mut worker: WebPWorker = mem::zeroed();
+            let mut count: c_int = 0;
+            winterface.Init.unwrap()(&mut worker);
+            assert_eq!(winterface.Reset.unwrap()(&mut worker), 1);
+            assert_eq!(worker.status_, WebPWorkerStatus::OK);
+            worker.hook = Some(count_hook);
+            worker.data1 = &mut count as *mut c_int as *mut c_void;
+            for _ in 0..10 {
+                winterface.Launch.unwrap()(&mut worker);
+                assert_eq!(winterface.Sync.unwrap()(&mut worker), 1);
+            }
+            assert_eq!(count, 10);
+
+            // A failing hook is reported by Sync() until the next Reset().
+            worker.data2 = &mut count as *mut c_int as *mut c_void;
+            winterface.Launch.unwrap()(&mut worker);
+            assert_eq!(winterface.Sync.unwrap()(&mut worker), 0);
+            worker.data2 = ptr::null_mut();
+            winterface.Execute.unwrap()(&mut worker);
+            assert_eq!(winterface.Sync.unwrap()(&mut worker), 0);
+            assert_eq!(winterface.Reset.unwrap()(&mut worker), 1);
+            assert_eq!(winterface.Sync.unwrap()(&mut worker), 1);
+            assert_eq!(count, 12);
+
+            winterface.End.unwrap()(&mut worker);
+            assert_eq!(worker.status_, WebPWorkerStatus::NOT_OK);
+            assert!(worker.impl_.is_null());
+        }
+    }
+
+    #[test]
+    fn test_worker_hook_panic() {
+        unsafe {
+            let winterface = &*WebPGetWorkerInterface();
+            let mut worker: WebPWorker = mem::zeroed();
+            let mut count: c_int = 0;
+            winterface.Init.unwrap()(&mut worker);
+            assert_eq!(winterface.Reset.unwrap()(&mut worker), 1);
+
+            worker.hook = Some(panic_hook);
+            winterface.Launch.unwrap()(&mut worker);
+            assert_eq!(winterface.Sync.unwrap()(&mut worker), 0);
+            assert_eq!(winterface.Reset.unwrap()(&mut worker), 1);
+            winterface.Execute.unwrap()(&mut worker);
+            assert_eq!(winterface.Sync.unwrap()(&mut worker), 0);
+
+            // The worker thread survives the panic.
+            assert_eq!(winterface.Reset.unwrap()(&mut worker), 1);
+            worker.hook = Some(count_hook);
+            worker.data1 = &mut count as *mut c_int as *mut c_void;
+            winterface.Launch.unwrap()(&mut worker);
+            assert_eq!(winterface.Sync.unwrap()(&mut worker), 1);
+            assert_eq!(count, 1);
+            winterface.End.unwrap()(&mut worker);
+        }
+    }
+
+    #[test]
+    fn test_set_worker_interface() {
+        unsafe {
+            assert_eq!(WebPSetWorkerInterface(ptr::null()), 0);
+            let mut winterface = ptr::read(WebPGetWorkerInterface());
+            winterface.Launch = None;
+            assert_eq!(WebPSetWorkerInterface(&winterface), 0);
+            // The installed interface is left untouched.
+            assert!((*WebPGetWorkerInterface()).Launch.is_some());
+        }
+    }
+}
@@ -1,6 +1,250 @@
+// Copyright 2012 Google Inc. All Rights Reserved.
+//
+// Use of this source code is governed by a BSD-style license
+// that can be found in the COPYING file in the root of the source
+// tree. An additional intellectual property rights grant can be found
+// in the file PATENTS. All contributing project authors may
+// be found in the AUTHORS file in the root of the source tree.
+// -----------------------------------------------------------------------------
+//
+// Misc. common utility functions
+//
+// Author: Skal (pascal.massimino@gmail.com)
+// Port to Rust: Masaki Hara (ackie.h.gmai@gmail.com)
+
 use std::os::raw::*;
+use std::ptr;
+
+use format_constants::MAX_PALETTE_SIZE;
+use sys::WebPPicture;
+use utils::color_cache_utils::VP8LHashPix;
+
+// The allocator of the C library, so that the memory can be released by
+// either side.
 extern "C" {
-    pub(crate) fn WebPSafeMalloc(nmemb: u64, size: usize) -> *mut c_void;
-    pub(crate) fn WebPSafeCalloc(nmemb: u64, size: usize) -> *mut c_void;
-    pub(crate) fn WebPSafeFree(ptr: *mut c_void);
+    fn malloc(size: usize) -> *mut c_void;
+    fn calloc(nmemb: usize, size: usize) -> *mut c_void;
+    fn free(ptr: *mut c_void);
+}
+
+//------------------------------------------------------------------------------
+// Memory allocation
+
+// This is the maximum memory amount that libwebp will ever try to allocate.
+#[cfg(target_pointer_width = "64")]
+const WEBP_MAX_ALLOCABLE_MEMORY: u64 = 1 << 34;
+// For 32-bit targets keep this below INT_MAX to avoid valgrind warnings.
+#[cfg(not(target_pointer_width = "64"))]
+const WEBP_MAX_ALLOCABLE_MEMORY: u64 = (1 << 31) - (1 << 16);
+
+// Returns 0 in case of overflow of nmemb * size.
+#[allow(non_snake_case)]
+fn CheckSizeArgumentsOverflow(nmemb: u64, size: usize) -> c_int {
+    let total_size = nmemb.wrapping_mul(size as u64);
+    if nmemb == 0 {
+        return 1;
+    }
+    if size as u64 > WEBP_MAX_ALLOCABLE_MEMORY / nmemb {
+        return 0;
+    }
+    if total_size != total_size as usize as u64 {
+        return 0;
+    }
+    1
+}
+
+// size-checking safe malloc/calloc: verify that the requested size is not too
+// large, or return NULL. You don't need to call these for constructs like
+// malloc(sizeof(foo)), but only if there's picture-dependent size involved
+// somewhere (like: malloc(num_pixels * sizeof(*something))). That's why this
+// safe malloc() borrows the signature from calloc(), pointing at the dangerous
+// underlying multiply involved.
+#[no_mangle]
+pub unsafe extern "C" fn WebPSafeMalloc(nmemb: u64, size: usize) -> *mut c_void {
+    if CheckSizeArgumentsOverflow(nmemb, size) == 0 {
+        return ptr::null_mut();
+    }
+    c_assert!(nmemb * size as u64 > 0);
+    malloc((nmemb * size as u64) as usize)
+}
+
+// Note that WebPSafeCalloc() expects the second argument type to be 'size_t'
+// in order to favor the "calloc(num_foo, sizeof(foo))" pattern.
+#[no_mangle]
+pub unsafe extern "C" fn WebPSafeCalloc(nmemb: u64, size: usize) -> *mut c_void {
+    if CheckSizeArgumentsOverflow(nmemb, size) == 0 {
+        return ptr::null_mut();
+    }
+    c_assert!(nmemb * size as u64 > 0);
+    calloc(nmemb as usize, size)
+}
+
+// Companion deallocation function to the above allocations.
+#[no_mangle]
+pub unsafe extern "C" fn WebPSafeFree(ptr: *mut c_void) {
+    free(ptr);
+}
+
+// Public API function.
+#[no_mangle]
+pub unsafe extern "C" fn WebPFree(ptr: *mut c_void) {
+    free(ptr);
+}
+
+//------------------------------------------------------------------------------
+// Alignment
+
+pub(crate) const WEBP_ALIGN_CST: usize = 31;
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn WEBP_ALIGN<T>(ptr: *mut T) -> *mut T {
+    ((ptr as usize + WEBP_ALIGN_CST) & !WEBP_ALIGN_CST) as *mut T
+}
+
+// memcpy() is the safe way of moving potentially unaligned 32b memory.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn WebPMemToUint32(ptr: *const u8) -> u32 {
+    ptr::read_unaligned(ptr as *const u32)
+}
+
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) unsafe fn WebPUint32ToMem(ptr: *mut u8, val: u32) {
+    ptr::write_unaligned(ptr as *mut u32, val)
+}
+
+// Returns 31 ^ clz(n) = log2(n), for the C code built without the GNU
+// builtins.
+#[allow(non_upper_case_globals)]
+#[no_mangle]
+#[rustfmt::skip]
+pub static WebPLogTable8bit: [u8; 256] = [ // 31 ^ clz(i)
+    0, 0, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 3,
+    4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4, 4,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
+    6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+    6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+    6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+    6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+];
+
+// Returns (int)floor(log2(n)). n must be > 0.
+#[allow(non_snake_case)]
+#[inline]
+pub(crate) fn BitsLog2Floor(n: u32) -> c_int {
+    31 ^ n.leading_zeros() as c_int
+}
+
+//------------------------------------------------------------------------------
+// Pixel copying.
+
+// Copy width x height pixels from 'src' to 'dst' honoring the strides.
+#[no_mangle]
+pub unsafe extern "C" fn WebPCopyPlane(
+    mut src: *const u8,
+    src_stride: c_int,
+    mut dst: *mut u8,
+    dst_stride: c_int,
+    width: c_int,
+    mut height: c_int,
+) {
+    c_assert!(!src.is_null() && !dst.is_null());
+    c_assert!(src_stride >= width && dst_stride >= width);
+    while height > 0 {
+        ptr::copy_nonoverlapping(src, dst, width as usize);
+        src = src.offset(src_stride as isize);
+        dst = dst.offset(dst_stride as isize);
+        height -= 1;
+    }
+}
+
+// Copy ARGB pixels from 'src' to 'dst' honoring strides. 'src' and 'dst' are
+// assumed to be already allocated and using ARGB data.
+#[no_mangle]
+pub unsafe extern "C" fn WebPCopyPixels(src: *const WebPPicture, dst: *mut WebPPicture) {
+    let (src, dst) = (&*src, &*dst);
+    c_assert!(src.width == dst.width && src.height == dst.height);
+    c_assert!(src.use_argb != 0 && dst.use_argb != 0);
+    WebPCopyPlane(
+        src.argb as *const u8,
+        4 * src.argb_stride,
+        dst.argb as *mut u8,
+        4 * dst.argb_stride,
+        4 * src.width,
+        src.height,
+    );
+}
+
+//------------------------------------------------------------------------------
+// Unique colors.
+
+const COLOR_HASH_SIZE: usize = MAX_PALETTE_SIZE * 4;
+const COLOR_HASH_RIGHT_SHIFT: c_int = 22; // 32 - log2(COLOR_HASH_SIZE).
+
+// Returns count of unique colors in 'pic', assuming pic->use_argb is true.
+// If the unique color count is more than MAX_PALETTE_SIZE, returns
+// MAX_PALETTE_SIZE+1.
+// If 'palette' is not NULL and number of unique colors is less than or equal to
+// MAX_PALETTE_SIZE, also outputs the actual unique colors into 'palette'.
+// Note: 'palette' is assumed to be an array already allocated with at least
+// MAX_PALETTE_SIZE elements.
+#[no_mangle]
+pub unsafe extern "C" fn WebPGetColorPalette(pic: *const WebPPicture, palette: *mut u32) -> c_int {
+    let mut num_colors = 0;
+    let mut in_use = [false; COLOR_HASH_SIZE];
+    let mut colors = [0u32; COLOR_HASH_SIZE];
+    let pic = &*pic;
+    let mut argb = pic.argb as *const u32;
+    let width = pic.width;
+    let height = pic.height;
+    let mut last_pix = !*argb; // so we're sure that last_pix != argb[0]
+    c_assert!(pic.use_argb != 0);
+
+    for _ in 0..height {
+        for x in 0..width as isize {
+            if *argb.offset(x) == last_pix {
+                continue;
+            }
+            last_pix = *argb.offset(x);
+            let mut key = VP8LHashPix(last_pix, COLOR_HASH_RIGHT_SHIFT) as usize;
+            loop {
+                if !in_use[key] {
+                    colors[key] = last_pix;
+                    in_use[key] = true;
+                    num_colors += 1;
+                    if num_colors > MAX_PALETTE_SIZE {
+                        return MAX_PALETTE_SIZE as c_int + 1; // Exact count not needed.
+                    }
+                    break;
+                } else if colors[key] == last_pix {
+                    break; // The color is already there.
+                } else {
+                    // Some other color sits here, so do linear conflict resolution.
+                    key += 1;
+                    key &= COLOR_HASH_SIZE - 1; // Key mask.
+                }
+            }
+        }
+        argb = argb.offset(pic.argb_stride as isize);
+    }
+
+    if !palette.is_null() {
+        // Fill the colors into palette.
+        num_colors = 0;
+        for (&color, _) in colors.iter().zip(&in_use).filter(|&(_, &used)| used) {
+            *palette.add(num_colors) = color;
+            num_colors += 1;
+        }
+    }
+    num_colors as c_int
 }
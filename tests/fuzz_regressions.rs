@@ -0,0 +1,70 @@
+// Replays the seed corpus in fuzz/seeds/<target>/ and the crash inputs stored
+// in fuzz/regressions/<target>/ through the fuzz target of the same name, so
+// that the crashes found by fuzzing stay fixed.
+
+extern crate webp;
+
+#[path = "../fuzz/src/lib.rs"]
+#[allow(dead_code)]
+mod targets;
+
+use std::fs;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+fn inputs(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn replay(target: &str, fuzz: fn(&[u8])) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz");
+    let mut paths = inputs(&root.join("seeds").join(target));
+    assert!(!paths.is_empty());
+    // Only the targets that found crashes have regressions.
+    let regressions = root.join("regressions").join(target);
+    if regressions.exists() {
+        paths.extend(inputs(&regressions));
+    }
+    for path in paths {
+        let data = fs::read(&path).unwrap();
+        if panic::catch_unwind(|| fuzz(&data)).is_err() {
+            panic!("{}: {} failed", target, path.display());
+        }
+    }
+}
+
+#[test]
+fn test_get_info() {
+    replay("get_info", targets::fuzz_get_info);
+}
+
+#[test]
+fn test_decode() {
+    replay("decode", targets::fuzz_decode);
+}
+
+#[test]
+fn test_decode_with_options() {
+    replay("decode_with_options", targets::fuzz_decode_with_options);
+}
+
+#[cfg(feature = "icc")]
+#[test]
+fn test_decode_rgba_srgb() {
+    replay("decode_rgba_srgb", targets::fuzz_decode_rgba_srgb);
+}
+
+#[test]
+fn test_incremental_decode() {
+    replay("incremental_decode", targets::fuzz_incremental_decode);
+}
+
+#[test]
+fn test_demux() {
+    replay("demux", targets::fuzz_demux);
+}